@@ -1,5 +1,5 @@
 use clap::Parser;
-use common::config::{Config, LogConfig};
+use common::config::Config;
 use gemini::ztm::{
     agent::{run_ztm_client, LocalZTMAgent, ZTMAgent},
     hub::LocalZTMHub,
@@ -14,7 +14,6 @@ use std::{
     thread::{self},
     time::{self},
 };
-use tracing_subscriber::fmt::writer::MakeWriterExt;
 
 pub mod service;
 
@@ -22,7 +21,7 @@ pub mod service;
 async fn main() {
     ctrlc::set_handler(move || {
         tracing::info!("Received Ctrl-C signal, exiting...");
-        std::process::exit(0);
+        common::daemon::exit(0);
     })
     .unwrap();
 
@@ -40,7 +39,7 @@ async fn main() {
         Config::default()
     };
 
-    init_log(&config.log);
+    common::log::init(&config, "mega-relay");
 
     tracing::info!("{:?}", option);
 
@@ -96,30 +95,3 @@ async fn main() {
     //Start  relay server
     run_relay_server(config, option).await;
 }
-
-fn init_log(config: &LogConfig) {
-    let log_level = match config.level.as_str() {
-        "trace" => tracing::Level::TRACE,
-        "debug" => tracing::Level::DEBUG,
-        "info" => tracing::Level::INFO,
-        "warn" => tracing::Level::WARN,
-        "error" => tracing::Level::ERROR,
-        _ => tracing::Level::INFO,
-    };
-
-    let file_appender =
-        tracing_appender::rolling::hourly(config.log_path.clone(), "mega-relay-logs");
-
-    if config.print_std {
-        let stdout = std::io::stdout;
-        tracing_subscriber::fmt()
-            .with_writer(stdout.and(file_appender))
-            .with_max_level(log_level)
-            .init();
-    } else {
-        tracing_subscriber::fmt()
-            .with_writer(file_appender)
-            .with_max_level(log_level)
-            .init();
-    }
-}
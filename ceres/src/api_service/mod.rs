@@ -5,9 +5,13 @@ use std::{
 
 use async_trait::async_trait;
 
+use callisto::db_enums::SignatureStatus;
 use callisto::raw_blob;
 use common::errors::MegaError;
-use jupiter::{context::Context, utils::converter::generate_git_keep_with_timestamp};
+use common::utils::repo_path;
+use jupiter::{
+    context::Context, storage::TreeItemRange, utils::converter::generate_git_keep_with_timestamp,
+};
 use mercury::{
     errors::GitError,
     internal::object::{
@@ -21,6 +25,7 @@ use crate::model::{
     create_file::CreateFileInfo,
     tree::{LatestCommitInfo, TreeBriefItem, TreeCommitItem, UserInfo},
 };
+use crate::signature;
 
 pub mod import_api_service;
 pub mod mono_api_service;
@@ -72,6 +77,12 @@ pub trait ApiHandler: Send + Sync {
 
     async fn get_commits_by_hashes(&self, c_hashes: Vec<String>) -> Result<Vec<Commit>, GitError>;
 
+    /// The latest commit which changed each of the files and directories `paths`, by path, for
+    /// the paths whose history is recorded.
+    async fn get_last_commits(&self, _paths: Vec<String>) -> HashMap<String, String> {
+        HashMap::new()
+    }
+
     async fn traverse_commit_history(
         &self,
         path: &Path,
@@ -103,20 +114,39 @@ pub trait ApiHandler: Send + Sync {
                 "can't find target parent tree under latest commit".to_string(),
             ));
         };
-        let commit = self.get_tree_relate_commit(&tree.id.to_string()).await;
-        self.convert_commit_to_info(commit)
+        let recorded = self
+            .get_last_commits(vec![path.to_string_lossy().into_owned()])
+            .await
+            .into_values()
+            .next();
+        let commit = match recorded {
+            Some(commit_id) => self.get_commits_by_hashes(vec![commit_id]).await?.pop(),
+            None => None,
+        };
+        let commit = match commit {
+            Some(commit) => commit,
+            None => self.get_tree_relate_commit(&tree.id.to_string()).await,
+        };
+        let statuses = signature::commit_statuses(&self.get_context(), &[commit.clone()]).await;
+        let mut info = self.convert_commit_to_info(commit)?;
+        if let Some(status) = statuses.get(&info.oid) {
+            info.verification = *status;
+        }
+        Ok(info)
     }
 
-    async fn get_tree_info(&self, path: PathBuf) -> Result<Vec<TreeBriefItem>, GitError> {
+    /// Entries of the directory `path` in `range`.
+    async fn get_tree_info(
+        &self,
+        path: PathBuf,
+        range: &TreeItemRange,
+    ) -> Result<Vec<TreeBriefItem>, GitError> {
         match self.search_tree_by_path(&path).await? {
             Some(tree) => {
                 let mut items = Vec::new();
-                for item in tree.tree_items {
+                for item in range.apply(tree.tree_items).0 {
                     let mut info: TreeBriefItem = item.clone().into();
-                    path.join(item.name)
-                        .to_str()
-                        .unwrap()
-                        .clone_into(&mut info.path);
+                    info.path = repo_path(&path.join(item.name));
                     items.push(info);
                 }
                 Ok(items)
@@ -125,14 +155,21 @@ pub trait ApiHandler: Send + Sync {
         }
     }
 
-    async fn get_tree_commit_info(&self, path: PathBuf) -> Result<Vec<TreeCommitItem>, GitError> {
+    /// Entries of the directory `path` in `range` with the commits which changed them last.
+    async fn get_tree_commit_info(
+        &self,
+        path: PathBuf,
+        range: &TreeItemRange,
+    ) -> Result<Vec<TreeCommitItem>, GitError> {
         match self.search_tree_by_path(&path).await? {
             Some(tree) => {
+                // commits are only looked up for the listed entries
+                let (tree_items, _) = range.apply(tree.tree_items);
                 let mut item_to_commit = HashMap::new();
 
                 self.add_trees_to_map(
                     &mut item_to_commit,
-                    tree.tree_items
+                    tree_items
                         .iter()
                         .filter(|x| x.mode == TreeItemMode::Tree)
                         .map(|x| x.id.to_string())
@@ -142,7 +179,7 @@ pub trait ApiHandler: Send + Sync {
 
                 self.add_blobs_to_map(
                     &mut item_to_commit,
-                    tree.tree_items
+                    tree_items
                         .iter()
                         .filter(|x| x.mode == TreeItemMode::Blob)
                         .map(|x| x.id.to_string())
@@ -150,21 +187,34 @@ pub trait ApiHandler: Send + Sync {
                 )
                 .await;
 
+                // the recorded history knows the commit which changed an entry last, the commit
+                // which added its object may be older
+                let entry_path = |item: &TreeItem| repo_path(&path.join(&item.name));
+                let last_commits = self
+                    .get_last_commits(tree_items.iter().map(entry_path).collect())
+                    .await;
+
                 let mut items = Vec::new();
-                let commit_ids: HashSet<String> = item_to_commit.values().cloned().collect();
+                let commit_ids: HashSet<String> = item_to_commit
+                    .values()
+                    .chain(last_commits.values())
+                    .cloned()
+                    .collect();
                 let commits = self
                     .get_commits_by_hashes(commit_ids.into_iter().collect())
                     .await
                     .unwrap();
-                let commit_map: HashMap<String, Commit> = commits
-                    .into_iter()
-                    .map(|x| (x.id.to_string(), x))
-                    .collect();
+                let statuses = signature::commit_statuses(&self.get_context(), &commits).await;
+                let commit_map: HashMap<String, Commit> =
+                    commits.into_iter().map(|x| (x.id.to_string(), x)).collect();
 
                 let root_commit: Option<Commit> = None;
-                for item in tree.tree_items {
+                for item in tree_items {
                     let mut info: TreeCommitItem = item.clone().into();
-                    if let Some(commit_id) = item_to_commit.get(&item.id.to_string()) {
+                    let commit_id = last_commits
+                        .get(&entry_path(&item))
+                        .or_else(|| item_to_commit.get(&item.id.to_string()));
+                    if let Some(commit_id) = commit_id {
                         let commit = if let Some(commit) = commit_map.get(commit_id) {
                             commit
                         } else {
@@ -181,6 +231,10 @@ pub trait ApiHandler: Send + Sync {
                         info.oid = commit.id.to_string();
                         info.message = commit.format_message();
                         info.date = commit.committer.timestamp.to_string();
+                        info.verification = statuses
+                            .get(&info.oid)
+                            .copied()
+                            .unwrap_or(SignatureStatus::Unsigned);
                     }
                     items.push(info);
                 }
@@ -214,6 +268,7 @@ pub trait ApiHandler: Send + Sync {
             author,
             committer,
             status: "success".to_string(),
+            verification: SignatureStatus::Unsigned,
         };
         Ok(res)
     }
@@ -7,20 +7,31 @@ use async_trait::async_trait;
 use tokio::process::Command;
 
 use callisto::db_enums::ConvType;
-use callisto::{mega_blob, mega_tree, raw_blob};
+use callisto::{mega_blob, mega_refs, mega_tag, mega_tree, raw_blob};
 use common::errors::MegaError;
+use common::utils::{repo_path, MEGA_BRANCH_NAME, TAG_REF_PREFIX};
 use jupiter::context::Context;
-use jupiter::storage::batch_save_model;
+use jupiter::storage::stats_storage::Counter;
+use jupiter::storage::{batch_save_model, TreeItemRange};
 use jupiter::utils::converter::generate_git_keep_with_timestamp;
 use mercury::errors::GitError;
 use mercury::hash::SHA1;
 use mercury::internal::object::blob::Blob;
 use mercury::internal::object::commit::Commit;
+use mercury::internal::object::signature::{self, SignatureType};
+use mercury::internal::object::tag::Tag;
 use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
+use sea_orm::ConnectionTrait;
 
 use crate::api_service::ApiHandler;
+use crate::history;
+use crate::merge;
 use crate::model::create_file::CreateFileInfo;
+use crate::model::tree::{TreeEntries, TreeEntry};
 use crate::protocol::mr::MergeRequest;
+use crate::stats;
+use crate::subtree;
 
 #[derive(Clone)]
 pub struct MonoApiService {
@@ -46,6 +57,7 @@ impl ApiHandler for MonoApiService {
         let storage = self.context.services.mono_storage.clone();
         let path = PathBuf::from(file_info.path);
         let mut save_trees = vec![];
+        let mut new_blob = None;
 
         // Search for the tree to update and get its tree items
         let (update_trees, search_tree) = self.search_tree_for_update(&path).await?;
@@ -75,18 +87,13 @@ impl ApiHandler for MonoApiService {
         } else {
             let content = file_info.content.unwrap();
             let blob = Blob::from_content(&content);
-            let mega_blob: mega_blob::ActiveModel = Into::<mega_blob::Model>::into(&blob).into();
-            let raw_blob: raw_blob::ActiveModel =
-                Into::<raw_blob::Model>::into(blob.clone()).into();
-
-            let conn = storage.get_connection();
-            batch_save_model(conn, vec![mega_blob]).await.unwrap();
-            batch_save_model(conn, vec![raw_blob]).await.unwrap();
-            TreeItem {
+            let item = TreeItem {
                 mode: TreeItemMode::Blob,
                 id: blob.id,
                 name: file_info.name.clone(),
-            }
+            };
+            new_blob = Some(blob);
+            item
         };
         // Add the new item to the tree items and create a new tree
         t_items.push(new_item);
@@ -100,21 +107,39 @@ impl ApiHandler for MonoApiService {
             &format!("\ncreate file {} commit", file_info.name),
         );
 
-        // Update the parent tree with the new commit
-        let commit_id = self.update_parent_tree(path, update_trees, commit).await?;
         save_trees.push(p_tree);
+        let raw_storage = &self.context.services.raw_db_storage;
+        let commit_id = storage
+            .transaction(|txn| async move {
+                let conn = &*txn;
+                if let Some(blob) = new_blob {
+                    let mega_blob: mega_blob::ActiveModel =
+                        Into::<mega_blob::Model>::into(&blob).into();
+                    let raw_blob: raw_blob::Model = blob.clone().into();
+                    batch_save_model(conn, vec![mega_blob]).await?;
+                    raw_storage.save_raw_blobs(conn, vec![raw_blob]).await?;
+                    raw_storage.add_refs(conn, &[blob.id.to_string()]).await?;
+                }
 
-        let save_trees: Vec<mega_tree::ActiveModel> = save_trees
-            .into_iter()
-            .map(|save_t| {
-                let mut tree_model: mega_tree::Model = save_t.into();
-                tree_model.commit_id.clone_from(&commit_id);
-                tree_model.into()
+                // Update the parent tree with the new commit
+                let commit_id = self
+                    .update_parent_tree(conn, path, update_trees, commit)
+                    .await?;
+                let save_trees: Vec<mega_tree::ActiveModel> = save_trees
+                    .into_iter()
+                    .map(|save_t| {
+                        let mut tree_model: mega_tree::Model = save_t.into();
+                        tree_model.commit_id.clone_from(&commit_id);
+                        tree_model.into()
+                    })
+                    .collect();
+                batch_save_model(conn, save_trees).await?;
+                Ok(commit_id)
             })
-            .collect();
-        batch_save_model(storage.get_connection(), save_trees)
             .await
-            .unwrap();
+            .map_err(|err| GitError::CustomError(err.to_string()))?;
+        history::record(&self.context, "/", MEGA_BRANCH_NAME, &commit_id).await;
+        tokio::spawn(merge::refresh_open(self.context.clone()));
         Ok(())
     }
 
@@ -190,6 +215,18 @@ impl ApiHandler for MonoApiService {
         Ok(commits.into_iter().map(|x| x.into()).collect())
     }
 
+    async fn get_last_commits(&self, paths: Vec<String>) -> HashMap<String, String> {
+        self.context
+            .services
+            .mono_storage
+            .get_last_commits("/", MEGA_BRANCH_NAME, paths)
+            .await
+            .unwrap_or_else(|err| {
+                tracing::error!("failed to read the last commits of paths: {}", err);
+                HashMap::new()
+            })
+    }
+
     async fn traverse_commit_history(&self, _: &Path, _: Commit, _: &TreeItem) -> Commit {
         unreachable!()
     }
@@ -201,7 +238,7 @@ impl MonoApiService {
         let refs = storage.get_ref(&mr.path).await.unwrap().unwrap();
 
         if mr.from_hash == refs.ref_commit_hash {
-            let commit: Commit = storage
+            let mut commit: Commit = storage
                 .get_commit_by_hash(&mr.to_hash)
                 .await
                 .unwrap()
@@ -209,17 +246,60 @@ impl MonoApiService {
                 .into();
 
             if mr.path != "/" {
+                // the directory may have been changed by other merge requests since
+                let merged =
+                    merge::merge_tree(&self.context, &mr.path, &mr.from_hash, &mr.to_hash).await?;
+                if !merged.conflicts.is_empty() {
+                    return Err(MegaError::with_message(&format!(
+                        "merge conflict in {}",
+                        merged.conflicts.join(", ")
+                    )));
+                }
+                let mut new_commits = vec![];
+                if merged.tree_id != commit.tree_id {
+                    commit = Commit::from_tree_id(
+                        merged.tree_id,
+                        vec![commit.id],
+                        &format!("\nmerge {} into {}", merged.target_hash, mr.path),
+                    );
+                    new_commits.push(commit.clone());
+                }
                 let path = PathBuf::from(mr.path.clone());
                 // beacuse only parent tree is needed so we skip current directory
                 let (tree_vec, _) = self
                     .search_tree_for_update(path.parent().unwrap())
                     .await
                     .unwrap();
-                self.update_parent_tree(path, tree_vec, commit)
-                    .await
-                    .unwrap();
-                // remove refs start with path
-                storage.remove_refs(&mr.path).await.unwrap();
+                let commit_id = storage
+                    .transaction(|txn| async move {
+                        let conn = &*txn;
+                        storage.save_mega_commits(conn, new_commits).await?;
+                        let commit_id = self
+                            .update_parent_tree(conn, path, tree_vec, commit)
+                            .await?;
+                        let save_trees: Vec<mega_tree::ActiveModel> = merged
+                            .trees
+                            .into_iter()
+                            .map(|tree| {
+                                let mut tree_model: mega_tree::Model = tree.into();
+                                tree_model.commit_id.clone_from(&commit_id);
+                                tree_model.into()
+                            })
+                            .collect();
+                        batch_save_model(conn, save_trees).await?;
+                        Ok(commit_id)
+                    })
+                    .await?;
+                history::record(&self.context, "/", MEGA_BRANCH_NAME, &commit_id).await;
+                tokio::spawn(merge::refresh_open(self.context.clone()));
+                // remove refs start with path, unless the branch settings keep them
+                let delete_on_merge = storage
+                    .get_branch_setting(Path::new(&mr.path))
+                    .await?
+                    .is_none_or(|s| s.delete_on_merge);
+                if delete_on_merge {
+                    storage.remove_refs(&mr.path).await.unwrap();
+                }
                 // TODO: self.clean_dangling_commits().await;
             }
             // update mr
@@ -236,18 +316,119 @@ impl MonoApiService {
                 .update_mr(mr.clone().into())
                 .await
                 .unwrap();
+            stats::count(&self.context, Counter::MrMerged).await;
         } else {
             return Err(MegaError::with_message("ref hash conflict"));
         }
         Ok(())
     }
 
+    /// Rename the file or directory at `path` to `new_name` and move everything stored by path
+    /// (refs, merge requests, import repos, ownership and visibility), the old path keeps
+    /// redirecting to the new one for `monorepo.redirect_grace_days`.
+    ///
+    /// Returns the new path.
+    pub async fn rename_path(&self, path: &Path, new_name: &str) -> Result<PathBuf, GitError> {
+        let storage = &self.context.services.mono_storage;
+        if new_name.is_empty() || new_name.contains('/') {
+            return Err(GitError::CustomError(format!("Invalid name: {}", new_name)));
+        }
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(GitError::CustomError(
+                "Can't rename root directory".to_string(),
+            ));
+        };
+        let name = name.to_str().unwrap();
+        let new_path = parent.join(new_name);
+
+        let (update_trees, parent_tree) = self.search_tree_for_update(parent).await?;
+        let mut t_items = parent_tree.tree_items;
+        if t_items.iter().any(|x| x.name == new_name) {
+            return Err(GitError::CustomError("Duplicate name".to_string()));
+        }
+        let item = t_items
+            .iter_mut()
+            .find(|x| x.name == name)
+            .ok_or(GitError::CustomError("Path not exist".to_string()))?;
+        item.name = new_name.to_owned();
+        let p_tree = Tree::from_tree_items(t_items).unwrap();
+
+        let refs = storage.get_ref("/").await.unwrap().unwrap();
+        let commit = Commit::from_tree_id(
+            p_tree.id,
+            vec![SHA1::from_str(&refs.ref_commit_hash).unwrap()],
+            &format!("\nrename {} to {}", path.display(), new_path.display()),
+        );
+        let (old, new) = (repo_path(path), repo_path(&new_path));
+        let (old, new) = (old.as_str(), new.as_str());
+        let grace_days = self.context.config.monorepo.redirect_grace_days;
+        let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::days(grace_days as i64);
+        let context = &self.context;
+        // the new tree and everything stored by path move together, or not at all
+        let commit_id = storage
+            .transaction(|txn| async move {
+                let conn = &*txn;
+                let commit_id = if parent == Path::new("/") {
+                    // the renamed item is in root tree, update the root ref directly
+                    let mut root_ref = refs;
+                    let old_commit = std::mem::take(&mut root_ref.ref_commit_hash);
+                    root_ref.ref_commit_hash = commit.id.to_string();
+                    root_ref.ref_tree_hash = p_tree.id.to_string();
+                    storage.update_ref(conn, root_ref, &old_commit).await?;
+                    storage
+                        .save_mega_commits(conn, vec![commit.clone()])
+                        .await?;
+                    commit.id.to_string()
+                } else {
+                    // trees found by `search_tree_for_update` end with the parent tree itself
+                    let mut update_trees = update_trees;
+                    update_trees.pop();
+                    self.update_parent_tree(conn, parent.to_path_buf(), update_trees, commit)
+                        .await?
+                };
+                let mut tree_model: mega_tree::Model = p_tree.into();
+                tree_model.commit_id.clone_from(&commit_id);
+                let save_tree: mega_tree::ActiveModel = tree_model.into();
+                batch_save_model(conn, vec![save_tree]).await?;
+
+                storage.rename_paths(conn, old, new).await?;
+                context.mr_stg().rename_mr_paths(conn, old, new).await?;
+                context
+                    .services
+                    .git_db_storage
+                    .rename_git_repos(conn, old, new)
+                    .await?;
+                context
+                    .user_stg()
+                    .rename_owned_paths(conn, old, new)
+                    .await?;
+                context
+                    .services
+                    .quota_storage
+                    .rename_paths(conn, old, new)
+                    .await?;
+                storage.save_redirect(conn, old, new, expires_at).await?;
+                Ok(commit_id)
+            })
+            .await
+            .map_err(|err| GitError::CustomError(err.to_string()))?;
+
+        // the history of the old path moves along, the rename itself is recorded at both
+        history::record(&self.context, "/", MEGA_BRANCH_NAME, &commit_id).await;
+        tokio::spawn(merge::refresh_open(self.context.clone()));
+        Ok(new_path)
+    }
+
+    /// Point the trees above `path` and the monorepo root ref to the tree of `commit`, the
+    /// refs of directories in between are removed. Written with `conn`, which is usually a
+    /// transaction the new trees below are saved with as well.
     async fn update_parent_tree(
         &self,
+        conn: &impl ConnectionTrait,
         mut path: PathBuf,
         mut tree_vec: Vec<Tree>,
         commit: Commit,
-    ) -> Result<String, GitError> {
+    ) -> Result<String, MegaError> {
         let storage = self.context.services.mono_storage.clone();
         let mut save_trees = Vec::new();
         let mut p_commit_id = String::new();
@@ -267,7 +448,7 @@ impl MonoApiService {
             let model: mega_tree::Model = new_tree.into();
             save_trees.push(model);
 
-            let p_ref = storage.get_ref(path.to_str().unwrap()).await.unwrap();
+            let p_ref = storage.get_ref(path.to_str().unwrap()).await?;
             if let Some(mut p_ref) = p_ref {
                 if path == Path::new("/") {
                     let p_commit = Commit::new(
@@ -279,12 +460,13 @@ impl MonoApiService {
                     );
                     p_commit_id = p_commit.id.to_string();
                     // update p_ref
+                    let old_commit = std::mem::take(&mut p_ref.ref_commit_hash);
                     p_ref.ref_commit_hash = p_commit.id.to_string();
                     p_ref.ref_tree_hash = target_hash.to_string();
-                    storage.update_ref(p_ref).await.unwrap();
-                    storage.save_mega_commits(vec![p_commit]).await.unwrap();
+                    storage.update_ref(conn, p_ref, &old_commit).await?;
+                    storage.save_mega_commits(conn, vec![p_commit]).await?;
                 } else {
-                    storage.remove_ref(p_ref).await.unwrap();
+                    storage.remove_ref(conn, p_ref).await?;
                 }
             }
         }
@@ -296,12 +478,193 @@ impl MonoApiService {
             })
             .collect();
 
-        batch_save_model(storage.get_connection(), save_trees)
-            .await
-            .unwrap();
+        batch_save_model(conn, save_trees).await?;
         Ok(p_commit_id)
     }
 
+    /// Entries of the monorepo directory `path` at `refs` in `range`, `None` if the directory
+    /// does not exist.
+    ///
+    /// `refs` is a commit id or the name of a ref of the monorepo root, the latest commit if empty.
+    /// Sizes are only looked up for the listed entries.
+    pub async fn get_tree_entries(
+        &self,
+        path: &Path,
+        refs: &str,
+        range: &TreeItemRange,
+    ) -> Result<Option<TreeEntries>, MegaError> {
+        let storage = self.context.services.mono_storage.clone();
+        let root_refs = storage.get_refs("/").await?;
+        let found = match refs {
+            "" => root_refs
+                .into_iter()
+                .find(|r| r.ref_name == MEGA_BRANCH_NAME),
+            refs => root_refs.into_iter().find(|r| {
+                r.ref_name == refs
+                    || r.ref_name.strip_prefix("refs/heads/") == Some(refs)
+                    || r.ref_name.strip_prefix(TAG_REF_PREFIX) == Some(refs)
+            }),
+        };
+        let commit = match found {
+            // annotated tags point to their tag object
+            Some(r) if r.ref_name.starts_with(TAG_REF_PREFIX) => storage
+                .get_tags_by_hashes(vec![r.ref_commit_hash.clone()])
+                .await?
+                .pop()
+                .map_or(r.ref_commit_hash, |t| t.object_id),
+            Some(r) => r.ref_commit_hash,
+            None => refs.to_owned(),
+        };
+        let Some(commit_model) = storage.get_commit_by_hash(&commit).await? else {
+            return Ok(None);
+        };
+        let Some(oid) = subtree::find_subtree(&storage, &commit_model.tree, path).await? else {
+            return Ok(None);
+        };
+        let Some((tree_items, total)) = storage.get_tree_items(&oid, range).await? else {
+            return Ok(None);
+        };
+        let blob_ids = tree_items
+            .iter()
+            .filter(|item| item.mode != TreeItemMode::Tree && item.mode != TreeItemMode::Commit)
+            .map(|item| item.id.to_string())
+            .collect();
+        let sizes: HashMap<String, u64> = storage
+            .get_mega_blobs_by_hashes(blob_ids)
+            .await?
+            .into_iter()
+            .map(|b| (b.blob_id, b.size as u64))
+            .collect();
+        let entries = tree_items
+            .into_iter()
+            .map(|item| {
+                let oid = item.id.to_string();
+                TreeEntry {
+                    size: sizes.get(&oid).copied().unwrap_or_default(),
+                    name: item.name,
+                    oid,
+                    mode: item.mode,
+                }
+            })
+            .collect();
+        Ok(Some(TreeEntries {
+            commit,
+            oid,
+            total,
+            entries,
+        }))
+    }
+
+    /// The tags of the monorepo path `path` with their annotated tag objects, `None` for
+    /// lightweight tags.
+    pub async fn list_tags(
+        &self,
+        path: &str,
+    ) -> Result<Vec<(mega_refs::Model, Option<mega_tag::Model>)>, MegaError> {
+        let storage = &self.context.services.mono_storage;
+        let refs = storage.get_tag_refs(path).await?;
+        let tags: HashMap<String, mega_tag::Model> = storage
+            .get_tags_by_hashes(refs.iter().map(|r| r.ref_commit_hash.clone()).collect())
+            .await?
+            .into_iter()
+            .map(|t| (t.tag_id.clone(), t))
+            .collect();
+        Ok(refs
+            .into_iter()
+            .map(|r| {
+                let tag = tags.get(&r.ref_commit_hash).cloned();
+                (r, tag)
+            })
+            .collect())
+    }
+
+    /// Tag `commit` of the monorepo path `path`, the commit of its branch if it is `None`.
+    ///
+    /// With a `message` an annotated tag object by `tagger`, its name and email, is stored and
+    /// the tag points to it, without the tag is lightweight.
+    pub async fn create_tag(
+        &self,
+        path: &str,
+        name: &str,
+        commit: Option<String>,
+        message: Option<&str>,
+        tagger: (&str, &str),
+    ) -> Result<(mega_refs::Model, Option<mega_tag::Model>), MegaError> {
+        if !is_valid_tag_name(name) {
+            return Err(MegaError::with_message("invalid tag name"));
+        }
+        let storage = &self.context.services.mono_storage;
+        let ref_name = format!("{}{}", TAG_REF_PREFIX, name);
+        if storage
+            .get_refs(path)
+            .await?
+            .iter()
+            .any(|r| r.ref_name == ref_name)
+        {
+            return Err(MegaError::with_message("tag already exists"));
+        }
+        let commit_id = match commit {
+            Some(commit) => commit,
+            None => storage
+                .get_ref(path)
+                .await?
+                .map(|r| r.ref_commit_hash)
+                .ok_or_else(|| MegaError::with_message("commit is required"))?,
+        };
+        let commit: Commit = storage
+            .get_commit_by_hash(&commit_id)
+            .await?
+            .ok_or_else(|| MegaError::with_message("commit not found"))?
+            .into();
+        let tag = message.map(|message| {
+            let tagger = signature::new(
+                SignatureType::Tagger,
+                tagger.0.to_owned(),
+                tagger.1.to_owned(),
+            );
+            mega_tag::Model::from(Tag::new(
+                commit.id,
+                ObjectType::Commit,
+                name,
+                tagger,
+                message,
+            ))
+        });
+        let target = tag.as_ref().map_or(commit_id, |t| t.tag_id.clone());
+        let tree = commit.tree_id.to_string();
+        storage
+            .transaction(|txn| async move {
+                if let Some(tag) = tag {
+                    storage.save_tag(&*txn, tag).await?;
+                }
+                storage
+                    .save_ref(&*txn, path, Some(ref_name), &target, &tree)
+                    .await
+            })
+            .await?;
+        self.list_tags(path)
+            .await?
+            .into_iter()
+            .find(|(r, _)| r.ref_name.strip_prefix(TAG_REF_PREFIX) == Some(name))
+            .ok_or_else(|| MegaError::with_message("tag not found"))
+    }
+
+    /// Remove the tag `name` of the monorepo path `path`, an annotated tag object is left to
+    /// the object garbage collection.
+    pub async fn delete_tag(&self, path: &str, name: &str) -> Result<(), MegaError> {
+        let storage = &self.context.services.mono_storage;
+        let ref_name = format!("{}{}", TAG_REF_PREFIX, name);
+        let Some(tag) = storage
+            .get_tag_refs(path)
+            .await?
+            .into_iter()
+            .find(|r| r.ref_name == ref_name)
+        else {
+            return Err(MegaError::with_message("tag not found"));
+        };
+        storage.remove_ref(storage.get_connection(), tag).await
+    }
+
     pub async fn content_diff(&self, mr_link: &str) -> Result<String, GitError> {
         let stg = self.context.mr_stg();
         if let Some(mr) = stg.get_mr(mr_link).await.unwrap() {
@@ -379,10 +742,39 @@ impl MonoApiService {
     }
 }
 
+/// Tag names follow the rules of `git check-ref-format` for one or more path components.
+fn is_valid_tag_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with(".lock")
+        && !name.ends_with('.')
+        && name
+            .split('/')
+            .all(|c| !c.is_empty() && !c.starts_with('.'))
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+}
+
 #[cfg(test)]
 mod test {
     use std::path::PathBuf;
 
+    use super::is_valid_tag_name;
+
+    #[test]
+    fn test_is_valid_tag_name() {
+        assert!(is_valid_tag_name("v1.0.0"));
+        assert!(is_valid_tag_name("release/2024-01"));
+        assert!(!is_valid_tag_name(""));
+        assert!(!is_valid_tag_name("v1..0"));
+        assert!(!is_valid_tag_name("v1 0"));
+        assert!(!is_valid_tag_name("release/.hidden"));
+        assert!(!is_valid_tag_name("v1.lock"));
+        assert!(!is_valid_tag_name("v1^"));
+    }
+
     #[test]
     pub fn test() {
         let mut full_path = PathBuf::from("/project/rust/mega");
@@ -0,0 +1,316 @@
+//! Signed entity files.
+//!
+//! The entity files of directories, `.mega_cedar.json`, live in the repository like any other
+//! file, so anyone allowed to push could grant themselves roles by changing one. When
+//! `policy.entity_signing_key` is set, the entity files changed by a merge request are signed
+//! when it is merged, if the user merging it is an admin of their directory, and the
+//! authorization only loads entity files with a valid signature for their directory. Pushes to
+//! the monorepo only update merge requests, so a file is never signed before it reaches the main
+//! ref. Only the last signature of a directory is valid: an unsigned, tampered or superseded file
+//! of the main tree is ignored for the last signed version of the file in its directory, or no
+//! file at all if it never had one.
+//!
+//! A signature is an HMAC of the directory and the hash of the blob, so it is not valid for a
+//! copy of the file in another directory. The entity files the monorepo is initialized with are
+//! trusted without a signature.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use ring::hmac;
+
+use callisto::entity_file_signature;
+use common::errors::MegaError;
+use common::utils::repo_path;
+use jupiter::context::Context;
+use jupiter::storage::mono_storage::MonoStorage;
+use mercury::hash::SHA1;
+use mercury::internal::object::blob::Blob;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use saturn::entitystore::generate_entity;
+
+use crate::merge;
+
+/// Name of the entity files of directories.
+pub const ENTITY_FILE: &str = ".mega_cedar.json";
+
+/// The signature of the entity file `blob_id` in the directory `dir`, hex encoded.
+pub fn sign(key: &str, dir: &str, blob_id: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    hex::encode(hmac::sign(&key, payload(dir, blob_id).as_bytes()))
+}
+
+pub fn verify(key: &str, dir: &str, blob_id: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    hmac::verify(&key, payload(dir, blob_id).as_bytes(), &signature).is_ok()
+}
+
+fn payload(dir: &str, blob_id: &str) -> String {
+    format!("{}\n{}", dir, blob_id)
+}
+
+/// The `(directory, blob id)` of the entity files the commit `to_hash` of the directory `path`
+/// changed since its commit `from_hash`, the ones a merge request brings to the main ref.
+///
+/// Subtrees with the same id on both sides did not change, so only the others are walked.
+pub async fn changed_entity_files(
+    context: &Context,
+    path: &str,
+    from_hash: &str,
+    to_hash: &str,
+) -> Result<Vec<(String, String)>, MegaError> {
+    let storage = &context.services.mono_storage;
+    let base = commit_tree(storage, from_hash).await?;
+    let tree = commit_tree(storage, to_hash)
+        .await?
+        .ok_or_else(|| MegaError::with_message(&format!("commit {} not found", to_hash)))?;
+    let mut pending = vec![(PathBuf::from(path), base, tree)];
+    let mut res = BTreeSet::new();
+    while let Some((dir, base, tree)) = pending.pop() {
+        let base = match base {
+            Some(id) => Some(merge::load_tree(storage, id).await?),
+            None => None,
+        };
+        let tree = merge::load_tree(storage, tree).await?;
+        let (changed, subdirs) = compare_trees(&dir, base.as_ref(), &tree);
+        if let Some(blob_id) = changed {
+            res.insert((repo_path(&dir), blob_id));
+        }
+        pending.extend(subdirs);
+    }
+    Ok(res.into_iter().collect())
+}
+
+async fn commit_tree(storage: &MonoStorage, hash: &str) -> Result<Option<SHA1>, MegaError> {
+    let commit = storage.get_commit_by_hash(hash).await?;
+    Ok(commit.map(|c| Commit::from(c).tree_id))
+}
+
+/// The entity file of the tree `tree` of `dir` if it differs from the one in `base`, and the
+/// subdirectories whose trees differ, with their tree in `base` if it has one.
+#[allow(clippy::type_complexity)]
+fn compare_trees(
+    dir: &Path,
+    base: Option<&Tree>,
+    tree: &Tree,
+) -> (Option<String>, Vec<(PathBuf, Option<SHA1>, SHA1)>) {
+    let in_base = |name: &str, mode: TreeItemMode| {
+        let items = base.map_or(&[][..], |b| &b.tree_items[..]);
+        items
+            .iter()
+            .find(|i| i.name == name && i.mode == mode)
+            .map(|i| i.id)
+    };
+    let mut changed = None;
+    let mut subdirs = vec![];
+    for item in &tree.tree_items {
+        match item.mode {
+            TreeItemMode::Tree => {
+                let old = in_base(&item.name, TreeItemMode::Tree);
+                if old != Some(item.id) {
+                    subdirs.push((dir.join(&item.name), old, item.id));
+                }
+            }
+            TreeItemMode::Blob if item.name == ENTITY_FILE => {
+                if in_base(ENTITY_FILE, TreeItemMode::Blob) != Some(item.id) {
+                    changed = Some(item.id.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    (changed, subdirs)
+}
+
+/// Sign the entity file `blob_id` of the directory `dir` in the name of `signed_by`.
+pub async fn sign_file(
+    context: &Context,
+    dir: &str,
+    blob_id: &str,
+    signed_by: &str,
+) -> Result<(), MegaError> {
+    let key = &context.config.policy.entity_signing_key;
+    if key.is_empty() {
+        return Err(MegaError::with_message("entity files are not signed"));
+    }
+    context
+        .services
+        .policy_storage
+        .save_entity_signature(dir, blob_id, &sign(key, dir, blob_id), signed_by)
+        .await?;
+    Ok(())
+}
+
+/// The entity file of the directory `dir` to load for the `blob_id` of the main tree: the file
+/// itself if it is the last signed version, otherwise the last signed version of the file, which
+/// was on the main ref when it was signed, and `None` if there is none.
+pub async fn trusted_blob(
+    context: &Context,
+    dir: &str,
+    blob_id: &str,
+) -> Result<Option<String>, MegaError> {
+    let key = &context.config.policy.entity_signing_key;
+    if key.is_empty() {
+        return Ok(Some(blob_id.to_owned()));
+    }
+    if is_initial(context, dir, blob_id) {
+        return Ok(Some(blob_id.to_owned()));
+    }
+    let signatures = context
+        .services
+        .policy_storage
+        .list_entity_signatures(dir)
+        .await?;
+    let latest = latest_signed(key, dir, &signatures);
+    match &latest {
+        Some(trusted) if trusted == blob_id => {}
+        Some(trusted) => tracing::warn!(
+            "entity file {} of {} is not its last signed version, using {}",
+            blob_id,
+            dir,
+            trusted
+        ),
+        None => tracing::warn!("entity file {} of {} is not signed, ignored", blob_id, dir),
+    }
+    Ok(latest)
+}
+
+/// The blob of the latest valid signature in `signatures`, which are listed latest first.
+///
+/// Only the latest signature of a directory counts, so restoring an older version of the file,
+/// signed when its pusher was still an admin, doesn't bring back the roles it granted.
+fn latest_signed(
+    key: &str,
+    dir: &str,
+    signatures: &[entity_file_signature::Model],
+) -> Option<String> {
+    signatures.iter().find_map(|model| {
+        if verify(key, dir, &model.blob_id, &model.signature) {
+            Some(model.blob_id.clone())
+        } else {
+            tracing::warn!(
+                "invalid signature of the entity file {} of {}",
+                model.blob_id,
+                dir
+            );
+            None
+        }
+    })
+}
+
+/// Whether `blob_id` is the entity file the root directory `dir` was initialized with.
+fn is_initial(context: &Context, dir: &str, blob_id: &str) -> bool {
+    let monorepo = &context.config.monorepo;
+    let is_root_dir = dir
+        .strip_prefix('/')
+        .is_some_and(|name| monorepo.root_dirs.iter().any(|d| d == name));
+    is_root_dir
+        && generate_entity(&monorepo.admin, dir)
+            .is_ok_and(|content| Blob::from_content(&content).id.to_string() == blob_id)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    use mercury::hash::SHA1;
+    use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+    use callisto::entity_file_signature;
+
+    use super::{compare_trees, latest_signed, sign, verify, ENTITY_FILE};
+
+    fn blob_id(digit: &str) -> SHA1 {
+        SHA1::from_str(&digit.repeat(40)).unwrap()
+    }
+
+    #[test]
+    fn test_sign() {
+        let blob = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        let signature = sign("secret", "/project/a", blob);
+        assert!(verify("secret", "/project/a", blob, &signature));
+        // not valid with another key, in another directory or for another blob
+        assert!(!verify("other", "/project/a", blob, &signature));
+        assert!(!verify("secret", "/project/b", blob, &signature));
+        assert!(!verify("secret", "/project/a", &"0".repeat(40), &signature));
+        assert!(!verify("secret", "/project/a", blob, "not hex"));
+    }
+
+    #[test]
+    fn test_latest_signed() {
+        let dir = "/project/a";
+        let signed = |blob_id: &str, signature: String| entity_file_signature::Model {
+            id: 0,
+            path: dir.to_owned(),
+            blob_id: blob_id.to_owned(),
+            signature,
+            signed_by: "admin".to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        let (old, new) = ("1".repeat(40), "2".repeat(40));
+        // latest first, the old version was signed before the new one superseded it
+        let signatures = vec![
+            signed(&new, sign("secret", dir, &new)),
+            signed(&old, sign("secret", dir, &old)),
+        ];
+        // merging the old version again loads the new one
+        assert_eq!(latest_signed("secret", dir, &signatures), Some(new.clone()));
+        // unless an admin signs it again
+        let mut resigned = vec![signed(&old, sign("secret", dir, &old))];
+        resigned.extend(signatures.clone());
+        assert_eq!(latest_signed("secret", dir, &resigned), Some(old.clone()));
+        // a tampered signature doesn't supersede the valid ones
+        let mut tampered = vec![signed(&old, "0".repeat(64))];
+        tampered.extend(signatures);
+        assert_eq!(latest_signed("secret", dir, &tampered), Some(new));
+        assert_eq!(latest_signed("secret", dir, &[]), None);
+    }
+
+    #[test]
+    fn test_compare_trees() {
+        let (old, new) = (blob_id("1"), blob_id("2"));
+        let unchanged = blob_id("3");
+        let sub = |entity| {
+            Tree::from_tree_items(vec![
+                TreeItem::new(TreeItemMode::Blob, entity, ENTITY_FILE.to_owned()),
+                TreeItem::new(TreeItemMode::Blob, unchanged, "README.md".to_owned()),
+            ])
+            .unwrap()
+        };
+        let (base_sub, sub) = (sub(old), sub(new));
+        let root = |sub: &Tree| {
+            Tree::from_tree_items(vec![
+                TreeItem::new(TreeItemMode::Tree, sub.id, "a".to_owned()),
+                TreeItem::new(TreeItemMode::Tree, unchanged, "b".to_owned()),
+            ])
+            .unwrap()
+        };
+        let (base_root, root) = (root(&base_sub), root(&sub));
+        let dir = Path::new("/project");
+
+        // only the directory which changed is walked
+        assert_eq!(
+            compare_trees(dir, Some(&base_root), &root),
+            (
+                None,
+                vec![(PathBuf::from("/project/a"), Some(base_sub.id), sub.id)]
+            )
+        );
+        let dir = Path::new("/project/a");
+        assert_eq!(
+            compare_trees(dir, Some(&base_sub), &sub),
+            (Some(new.to_string()), vec![])
+        );
+        assert_eq!(compare_trees(dir, Some(&sub), &sub), (None, vec![]));
+        // everything in a new directory changed
+        assert_eq!(
+            compare_trees(dir, None, &sub),
+            (Some(new.to_string()), vec![])
+        );
+    }
+}
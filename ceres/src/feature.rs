@@ -0,0 +1,226 @@
+//! Feature flags, so risky features like the merge queue or p2p sync can be rolled out
+//! gradually on a live instance.
+//!
+//! Flags are configured in `features.flags` or managed through the api, a stored flag replaces
+//! the configured one of the same name. A flag is on for a request if it is enabled, or else if
+//! the request is made by one of its users, to a repository at or below one of its paths, or by
+//! one of the percentage of all users it is rolled out to. Flags neither configured nor stored
+//! are off, unless they are among the features of the tenant of the user or repository.
+
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+
+use callisto::feature_flag;
+use common::config::{FeatureFlag, TenantConfig};
+use common::errors::MegaError;
+use jupiter::context::Context;
+
+use crate::tenant;
+
+/// Merging merge requests through a queue which tests them on top of each other.
+pub const MERGE_QUEUE: &str = "merge_queue";
+/// Providing and forking repositories through the ztm p2p network.
+pub const P2P_SYNC: &str = "p2p_sync";
+
+/// Flags of the features mega knows, others can be managed but are not consulted.
+pub const FLAGS: [&str; 2] = [MERGE_QUEUE, P2P_SYNC];
+
+/// Who makes a request and to which repository, either may be unknown.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Target<'a> {
+    pub user: Option<&'a str>,
+    pub path: Option<&'a str>,
+}
+
+impl<'a> Target<'a> {
+    pub fn new(user: Option<&'a str>, path: Option<&'a str>) -> Self {
+        Self { user, path }
+    }
+}
+
+/// Where the flag in use comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Config,
+    Stored {
+        updated_by: String,
+        updated_at: NaiveDateTime,
+    },
+}
+
+/// Whether `flag` is on for `target`.
+pub fn applies(flag: &FeatureFlag, target: &Target) -> bool {
+    if flag.enabled {
+        return true;
+    }
+    if let Some(path) = target.path {
+        if flag.paths.iter().any(|p| Path::new(path).starts_with(p)) {
+            return true;
+        }
+    }
+    match target.user {
+        Some(user) => {
+            flag.users.iter().any(|u| u == user) || bucket(&flag.name, user) < flag.percentage
+        }
+        None => false,
+    }
+}
+
+/// The bucket from 0 to 99 of `user`, a user in a bucket below the percentage of a flag gets
+/// the feature. Buckets differ by flag, so not the same users get every feature first.
+fn bucket(flag: &str, user: &str) -> u8 {
+    // 64-bit FNV-1a, the same on every instance unlike the std hasher
+    let hash = flag
+        .bytes()
+        .chain([b':'])
+        .chain(user.bytes())
+        .fold(0xcbf29ce484222325_u64, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+    (hash % 100) as u8
+}
+
+fn from_model(model: feature_flag::Model) -> (FeatureFlag, Source) {
+    let flag = FeatureFlag {
+        name: model.name,
+        enabled: model.enabled,
+        users: serde_json::from_str(&model.users).unwrap_or_default(),
+        paths: serde_json::from_str(&model.paths).unwrap_or_default(),
+        percentage: model.percentage.clamp(0, 100) as u8,
+    };
+    let source = Source::Stored {
+        updated_by: model.updated_by,
+        updated_at: model.updated_at,
+    };
+    (flag, source)
+}
+
+/// The flag `name` in use, `None` if it is neither stored nor configured.
+pub async fn find(
+    context: &Context,
+    name: &str,
+) -> Result<Option<(FeatureFlag, Source)>, MegaError> {
+    if let Some(model) = context.services.feature_storage.find_flag(name).await? {
+        return Ok(Some(from_model(model)));
+    }
+    let settings = context.settings();
+    Ok(settings
+        .features
+        .flags
+        .iter()
+        .find(|f| f.name == name)
+        .map(|f| (f.clone(), Source::Config)))
+}
+
+/// All flags in use, ordered by name.
+pub async fn list(context: &Context) -> Result<Vec<(FeatureFlag, Source)>, MegaError> {
+    let mut flags: Vec<_> = context
+        .services
+        .feature_storage
+        .list_flags()
+        .await?
+        .into_iter()
+        .map(from_model)
+        .collect();
+    let settings = context.settings();
+    for flag in &settings.features.flags {
+        if flags.iter().all(|(f, _)| f.name != flag.name) {
+            flags.push((flag.clone(), Source::Config));
+        }
+    }
+    flags.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+    Ok(flags)
+}
+
+/// Whether the feature `name` is on for `target`.
+///
+/// A flag which cannot be read is off, the feature is not risked when the database fails.
+pub async fn is_enabled(context: &Context, name: &str, target: Target<'_>) -> bool {
+    match find(context, name).await {
+        Ok(Some((flag, _))) if applies(&flag, &target) => true,
+        Ok(_) => is_tenant_feature(context, name, &target).await,
+        Err(err) => {
+            tracing::error!("failed to read feature flag {}, it is off: {}", name, err);
+            false
+        }
+    }
+}
+
+/// Whether `name` is one of the features of the tenant of the repository or the user of
+/// `target`.
+async fn is_tenant_feature(context: &Context, name: &str, target: &Target<'_>) -> bool {
+    let settings = context.settings();
+    let config = &settings.tenancy;
+    let has = |t: &TenantConfig| t.features.iter().any(|f| f == name);
+    if !tenant::tenants(config).any(has) {
+        return false;
+    }
+    if let Some(path) = target.path {
+        if tenant::of_path(config, path).is_some_and(has) {
+            return true;
+        }
+    }
+    let Some(user) = target.user else {
+        return false;
+    };
+    match tenant::of_user(context, user).await {
+        Ok(t) => t.and_then(|t| tenant::find(config, &t)).is_some_and(has),
+        Err(err) => {
+            tracing::error!(
+                "failed to read the tenant of {}, {} is off: {}",
+                user,
+                name,
+                err
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use common::config::FeatureFlag;
+
+    use super::{applies, Target};
+
+    #[test]
+    fn test_applies() {
+        let mut flag = FeatureFlag {
+            name: "p2p_sync".to_owned(),
+            users: vec!["alice".to_owned()],
+            paths: vec!["/project/mega".to_owned()],
+            ..Default::default()
+        };
+        assert!(applies(&flag, &Target::new(Some("alice"), None)));
+        assert!(applies(
+            &flag,
+            &Target::new(Some("bob"), Some("/project/mega/moon"))
+        ));
+        assert!(!applies(
+            &flag,
+            &Target::new(Some("bob"), Some("/project/megas"))
+        ));
+        assert!(!applies(&flag, &Target::default()));
+
+        // the users in the lowest buckets get the feature first
+        let users: Vec<String> = (0..1000).map(|i| format!("user{}", i)).collect();
+        let count = |flag: &FeatureFlag| {
+            users
+                .iter()
+                .filter(|u| applies(flag, &Target::new(Some(u), None)))
+                .count()
+        };
+        flag.users.clear();
+        assert_eq!(count(&flag), 0);
+        flag.percentage = 20;
+        let some = count(&flag);
+        assert!((100..300).contains(&some));
+        flag.percentage = 100;
+        assert_eq!(count(&flag), users.len());
+
+        flag.percentage = 0;
+        flag.enabled = true;
+        assert!(applies(&flag, &Target::default()));
+    }
+}
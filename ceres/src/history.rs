@@ -0,0 +1,329 @@
+//! Files and directories changed by the commits of monorepo refs.
+//!
+//! When a commit is added to a ref its tree is compared with the tree of its first parent, every
+//! file and directory which differs is recorded together with all directories above it up to the
+//! root of the ref, with how it changed and the objects it was before and after. The history of
+//! a path is then read page by page from these records, see `MonoStorage::get_path_history`,
+//! and so are the last commit of each entry of a directory and the versions of a file [`blame`]
+//! compares, instead of comparing the trees of every commit of the ref.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use futures::TryStreamExt;
+use similar::{Algorithm, DiffOp};
+
+use callisto::db_enums::ChangeType;
+use common::errors::MegaError;
+use common::utils::repo_path;
+use jupiter::context::Context;
+use jupiter::storage::mono_storage::{MonoStorage, PathChange};
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+use crate::model::blame::BlameLine;
+
+/// Record the paths changed by `commit_id`, which was just added to the ref `ref_name` of
+/// `ref_path`.
+///
+/// The commit is already stored at this point, a failure is only logged.
+pub async fn record(context: &Context, ref_path: &str, ref_name: &str, commit_id: &str) {
+    if let Err(err) = record_commit(context, ref_path, ref_name, commit_id).await {
+        tracing::error!(
+            "failed to record the paths changed by {} on {}: {}",
+            commit_id,
+            ref_path,
+            err
+        );
+    }
+}
+
+async fn record_commit(
+    context: &Context,
+    ref_path: &str,
+    ref_name: &str,
+    commit_id: &str,
+) -> Result<(), MegaError> {
+    let storage = &context.services.mono_storage;
+    let Some(model) = storage.get_commit_by_hash(commit_id).await? else {
+        return Err(MegaError::with_message("commit not found"));
+    };
+    let commit = Commit::from(model);
+    let old_tree = match commit.parent_commit_ids.first() {
+        Some(parent) => storage
+            .get_commit_by_hash(&parent.to_string())
+            .await?
+            .map(|c| c.tree),
+        None => None,
+    };
+    let changes = changed_paths(
+        storage,
+        old_tree.as_deref(),
+        &commit.tree_id.to_string(),
+        Path::new(ref_path),
+    )
+    .await?;
+    let committed_at = chrono::DateTime::from_timestamp(commit.committer.timestamp as i64, 0)
+        .map_or_else(|| chrono::Utc::now().naive_utc(), |t| t.naive_utc());
+    storage
+        .save_commit_paths(ref_path, ref_name, commit_id, committed_at, changes)
+        .await
+}
+
+/// The files and directories which differ between the trees `old` and `new` of the directory
+/// `root`, with all directories above them up to `root`. `root` itself is always included, so
+/// every commit is listed in the history of the whole ref.
+///
+/// Only directories which differ are loaded.
+pub async fn changed_paths(
+    storage: &MonoStorage,
+    old: Option<&str>,
+    new: &str,
+    root: &Path,
+) -> Result<Vec<PathChange>, MegaError> {
+    let mut changes = BTreeMap::new();
+    // directories to compare, with the objects they were and are, and the trees to load
+    let mut pending = vec![(
+        root.to_path_buf(),
+        old.map(str::to_owned),
+        Some(new.to_owned()),
+        old.map(str::to_owned),
+        Some(new.to_owned()),
+    )];
+    while let Some((dir, old_id, new_id, old_tree, new_tree)) = pending.pop() {
+        changes.insert(dir.clone(), path_change(&dir, old_id, new_id));
+        if old_tree == new_tree {
+            continue;
+        }
+        let old_items = load_items(storage, old_tree.as_deref()).await?;
+        let new_items = load_items(storage, new_tree.as_deref()).await?;
+        for (name, old_item, new_item) in diff_items(old_items, new_items) {
+            let path = dir.join(&name);
+            let id = |item: &Option<TreeItem>| item.as_ref().map(|i| i.id.to_string());
+            let subtree = |item: &Option<TreeItem>| {
+                item.as_ref()
+                    .filter(|i| i.mode == TreeItemMode::Tree)
+                    .map(|i| i.id.to_string())
+            };
+            let (old_tree, new_tree) = (subtree(&old_item), subtree(&new_item));
+            if old_tree.is_some() || new_tree.is_some() {
+                // a file replaced by a directory is recorded with both, the files of the
+                // directory as added
+                pending.push((path, id(&old_item), id(&new_item), old_tree, new_tree));
+            } else {
+                changes.insert(
+                    path.clone(),
+                    path_change(&path, id(&old_item), id(&new_item)),
+                );
+            }
+        }
+    }
+    Ok(changes.into_values().collect())
+}
+
+fn path_change(path: &Path, old_id: Option<String>, new_id: Option<String>) -> PathChange {
+    let change_type = match (&old_id, &new_id) {
+        (None, _) => ChangeType::Added,
+        (_, None) => ChangeType::Deleted,
+        _ => ChangeType::Modified,
+    };
+    PathChange {
+        path: repo_path(path),
+        change_type,
+        old_id,
+        new_id,
+    }
+}
+
+async fn load_items(storage: &MonoStorage, id: Option<&str>) -> Result<Vec<TreeItem>, MegaError> {
+    let Some(id) = id else {
+        return Ok(Vec::new());
+    };
+    match storage.get_tree_by_hash(id).await? {
+        Some(model) => Ok(Tree::from(model).tree_items),
+        None => Err(MegaError::with_message(&format!("tree {} not found", id))),
+    }
+}
+
+/// The lines of the file `path` on the ref `ref_name` of `ref_path` with the commit which last
+/// changed each of them, `None` if the file does not exist.
+///
+/// The versions of the file are read from its recorded changes, latest first, and compared with
+/// the version before until every line was attributed. Lines older than the recorded history
+/// are left without a commit.
+pub async fn blame(
+    context: &Context,
+    ref_path: &str,
+    ref_name: &str,
+    path: &str,
+) -> Result<Option<Vec<BlameLine>>, MegaError> {
+    let changes = context
+        .services
+        .mono_storage
+        .get_path_changes(ref_path, ref_name, path)
+        .await?;
+    let Some(latest) = changes.first() else {
+        return Ok(None);
+    };
+    if latest.change_type == ChangeType::Deleted {
+        return Ok(None);
+    }
+    let Some(latest_id) = &latest.new_id else {
+        return Err(MegaError::with_message(
+            "the history of this file was recorded without its versions",
+        ));
+    };
+    let mut current = load_lines(context, latest_id).await?;
+    let mut lines: Vec<BlameLine> = current
+        .iter()
+        .enumerate()
+        .map(|(i, content)| BlameLine {
+            line: i + 1,
+            commit_id: None,
+            content: content.clone(),
+        })
+        .collect();
+    // lines not attributed yet, by their index in `current` and in the latest version
+    let mut pending: Vec<(usize, usize)> = (0..current.len()).map(|i| (i, i)).collect();
+    for change in &changes {
+        if pending.is_empty() || change.new_id.is_none() {
+            break;
+        }
+        let old = match &change.old_id {
+            Some(old_id) => load_lines(context, old_id).await?,
+            None => Vec::new(),
+        };
+        let to_old = unchanged_lines(&old, &current);
+        let mut older = Vec::new();
+        for (at, line) in pending {
+            match to_old[at] {
+                Some(old_at) => older.push((old_at, line)),
+                None => lines[line].commit_id = Some(change.commit_id.clone()),
+            }
+        }
+        pending = older;
+        current = old;
+    }
+    Ok(Some(lines))
+}
+
+/// For each line of `new` the line of `old` it was before, `None` if it was added or changed.
+fn unchanged_lines(old: &[String], new: &[String]) -> Vec<Option<usize>> {
+    let mut to_old = vec![None; new.len()];
+    for op in similar::capture_diff_slices(Algorithm::Myers, old, new) {
+        if let DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } = op
+        {
+            for i in 0..len {
+                to_old[new_index + i] = Some(old_index + i);
+            }
+        }
+    }
+    to_old
+}
+
+/// The lines of the blob `id`, none if it is not a blob.
+async fn load_lines(context: &Context, id: &str) -> Result<Vec<String>, MegaError> {
+    let Some(content) = context
+        .services
+        .raw_db_storage
+        .get_raw_blob_content(id)
+        .await?
+    else {
+        return Ok(Vec::new());
+    };
+    let data: Vec<u8> = content
+        .try_fold(Vec::new(), |mut data, chunk| async move {
+            data.extend_from_slice(&chunk);
+            Ok(data)
+        })
+        .await?;
+    Ok(String::from_utf8_lossy(&data)
+        .lines()
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Names of the entries which differ between `old` and `new`, with what they were before and
+/// are now.
+fn diff_items(
+    old: Vec<TreeItem>,
+    new: Vec<TreeItem>,
+) -> Vec<(String, Option<TreeItem>, Option<TreeItem>)> {
+    let mut old: HashMap<String, TreeItem> = old
+        .into_iter()
+        .map(|item| (item.name.clone(), item))
+        .collect();
+    let mut res = Vec::new();
+    for item in new {
+        match old.remove(&item.name) {
+            Some(o) if o.id == item.id && o.mode == item.mode => {}
+            o => res.push((item.name.clone(), o, Some(item))),
+        }
+    }
+    // removed entries
+    for (name, o) in old {
+        res.push((name, Some(o), None));
+    }
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use mercury::hash::SHA1;
+    use mercury::internal::object::tree::{TreeItem, TreeItemMode};
+
+    use super::{diff_items, unchanged_lines};
+
+    #[test]
+    fn test_diff_items() {
+        let item = |mode, content: &str, name: &str| {
+            TreeItem::new(mode, SHA1::new(content.as_bytes()), name.to_owned())
+        };
+        let old = vec![
+            item(TreeItemMode::Blob, "same", "README.md"),
+            item(TreeItemMode::Blob, "old", "main.rs"),
+            item(TreeItemMode::Tree, "src", "src"),
+            item(TreeItemMode::Tree, "docs", "docs"),
+        ];
+        let new = vec![
+            item(TreeItemMode::Blob, "same", "README.md"),
+            item(TreeItemMode::Blob, "new", "main.rs"),
+            item(TreeItemMode::Tree, "src2", "src"),
+            item(TreeItemMode::Blob, "lib", "lib.rs"),
+        ];
+        let mut diff: Vec<_> = diff_items(old, new)
+            .into_iter()
+            .map(|(name, old, new)| {
+                let id = |item: Option<TreeItem>| item.map(|i| i.id.to_string());
+                (name, id(old), id(new))
+            })
+            .collect();
+        diff.sort();
+        let id = |content: &str| Some(SHA1::new(content.as_bytes()).to_string());
+        assert_eq!(
+            diff,
+            vec![
+                ("docs".to_owned(), id("docs"), None),
+                ("lib.rs".to_owned(), None, id("lib")),
+                ("main.rs".to_owned(), id("old"), id("new")),
+                ("src".to_owned(), id("src"), id("src2")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unchanged_lines() {
+        let lines = |s: &str| s.lines().map(str::to_owned).collect::<Vec<_>>();
+        let old = lines("a\nb\nc\nd");
+        let new = lines("a\nc\nx\nd\ny");
+        assert_eq!(
+            unchanged_lines(&old, &new),
+            vec![Some(0), Some(2), None, Some(3), None]
+        );
+        assert_eq!(unchanged_lines(&[], &new), vec![None; 5]);
+    }
+}
@@ -0,0 +1,412 @@
+//! Import of local bare repositories as import repositories, for moving existing repositories
+//! onto mega without pushing them one by one.
+//!
+//! The objects of a repository are packed by `git pack-objects` and stored like the pack of a
+//! push, its refs are only written once all objects are stored. A repository whose refs are
+//! already stored is skipped, and one imported before is only sent the objects it is missing, so
+//! an interrupted import is resumed by running it again.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::mpsc;
+
+use callisto::{db_enums::RefType, import_refs};
+use common::{errors::MegaError, utils::ZERO_ID};
+use jupiter::context::Context;
+use jupiter::storage::{git_db_storage::GitDbStorage, lock_repo};
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::pack::{entry::Entry, Pack};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+use crate::pack::{import_repo::ImportRepo, PackHandler};
+use crate::protocol::{import_refs::RefCommand, repo::Repo};
+use crate::quota;
+
+/// Objects stored in one transaction.
+const BATCH_SIZE: usize = 10000;
+
+/// How an import of a repository ended.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The stored refs are the ones of the repository
+    UpToDate,
+    /// The repository has no refs
+    Empty,
+    Imported {
+        objects: usize,
+        refs: usize,
+    },
+}
+
+/// Whether `path` is a bare git repository.
+pub fn is_bare_repo(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+/// The bare repositories to import from `source`: itself if it is one, otherwise the ones
+/// below it, ordered by path.
+pub fn find_repos(source: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if is_bare_repo(source) {
+        return Ok(vec![source.to_path_buf()]);
+    }
+    let mut res = Vec::new();
+    let mut pending = vec![source.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if !path.is_dir() {
+                continue;
+            }
+            if is_bare_repo(&path) {
+                res.push(path);
+            } else {
+                pending.push(path);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Path of the import repository for the bare repository `repo` found in `source`, the
+/// directories between them are kept and the `.git` suffix is dropped.
+pub fn target_path(target: &Path, source: &Path, repo: &Path) -> PathBuf {
+    let relative = match repo.strip_prefix(source) {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        _ => Path::new(repo.file_name().unwrap_or_default()),
+    };
+    let path = target.join(relative);
+    match path.to_string_lossy().strip_suffix(".git") {
+        Some(stripped) if !stripped.ends_with('/') => PathBuf::from(stripped),
+        _ => path,
+    }
+}
+
+/// The commands bringing the `stored` refs to the `local` `(name, id)` ones. The branch `head`,
+/// or the first branch without it, comes first and becomes the default branch of a new
+/// repository. Refs missing locally are kept.
+pub fn plan_refs(
+    local: &[(String, String)],
+    stored: &[import_refs::Model],
+    head: Option<&str>,
+) -> Vec<RefCommand> {
+    let stored: HashMap<&str, &str> = stored
+        .iter()
+        .map(|r| (r.ref_name.as_str(), r.ref_git_id.as_str()))
+        .collect();
+    let mut res: Vec<RefCommand> = local
+        .iter()
+        .filter(|(name, id)| stored.get(name.as_str()) != Some(&id.as_str()))
+        .map(|(name, id)| {
+            let old = stored.get(name.as_str()).copied().unwrap_or(ZERO_ID);
+            let mut command = RefCommand::new(old.to_owned(), id.clone(), name.clone());
+            command.default_branch = stored.is_empty() && Some(name.as_str()) == head;
+            command
+        })
+        .collect();
+    if stored.is_empty() && !res.iter().any(|c| c.default_branch) {
+        if let Some(first) = res.iter_mut().find(|c| c.ref_type == RefType::Branch) {
+            first.default_branch = true;
+        }
+    }
+    res.sort_by_key(|c| !c.default_branch);
+    res
+}
+
+/// Import the bare repository `source` as the import repository at `path`, storing `parallel`
+/// batches of objects at a time. `progress` is called with the number of objects stored so far.
+pub async fn import_repo(
+    context: &Context,
+    source: &Path,
+    path: &str,
+    parallel: usize,
+    progress: impl Fn(usize) + Send + 'static,
+) -> Result<Outcome, MegaError> {
+    let import_dir = &context.config.monorepo.import_dir;
+    if !Path::new(path).starts_with(import_dir) {
+        return Err(MegaError::with_message(&format!(
+            "{} is not below the import directory {}",
+            path,
+            import_dir.display()
+        )));
+    }
+    let local = read_refs(source).await?;
+    if local.is_empty() {
+        return Ok(Outcome::Empty);
+    }
+    let storage = &context.services.git_db_storage;
+    if storage.find_deleted_git_repo(path).await?.is_some() {
+        return Err(MegaError::with_message(&format!(
+            "{} is taken by a deleted repository",
+            path
+        )));
+    }
+    let repo: Repo = match storage.find_git_repo_exact_match(path).await? {
+        Some(model) => model.into(),
+        None => {
+            let repo = Repo::new(PathBuf::from(path), false);
+            storage.save_git_repo(repo.clone().into()).await?;
+            repo
+        }
+    };
+    let stored = storage.get_ref(repo.repo_id).await?;
+    let head = git(source, &["symbolic-ref", "-q", "HEAD"]).await.ok();
+    let commands = plan_refs(&local, &stored, head.as_deref().map(str::trim));
+    if commands.is_empty() {
+        return Ok(Outcome::UpToDate);
+    }
+
+    // objects reachable from the stored refs were all stored before them
+    let mut exclude = String::new();
+    for r in &stored {
+        if git(source, &["cat-file", "-e", &r.ref_git_id])
+            .await
+            .is_ok()
+        {
+            exclude.push_str(&format!("^{}\n", r.ref_git_id));
+        }
+    }
+    let mut child = std::process::Command::new("git")
+        .arg("-C")
+        .arg(source)
+        .args(["pack-objects", "--revs", "--all", "--stdout", "-q"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(exclude.as_bytes())?;
+    let stdout = child.stdout.take().unwrap();
+
+    let config = &context.config.pack;
+    let pack = Pack::new(
+        None,
+        Some(1024 * 1024 * 1024 * config.pack_decode_mem_size),
+        Some(config.pack_decode_cache_path.clone()),
+        config.clean_cache_after_decode,
+    );
+    let (sender, receiver) = mpsc::channel();
+    let decoder = pack.decode_async(BufReader::new(stdout), sender);
+    let stored_objects = {
+        let storage = storage.clone();
+        let repo_id = repo.repo_id;
+        tokio::task::spawn_blocking(move || {
+            save_entries(storage, repo_id, receiver, parallel, progress)
+        })
+        .await
+        .map_err(|err| MegaError::with_message(&err.to_string()))?
+    };
+    let (output, decoded) =
+        tokio::task::spawn_blocking(move || (child.wait_with_output(), decoder.join().is_ok()))
+            .await
+            .map_err(|err| MegaError::with_message(&err.to_string()))?;
+    let (objects, size) = stored_objects?;
+    let output = output?;
+    if !output.status.success() || !decoded {
+        return Err(MegaError::with_message(&format!(
+            "failed to pack {}: {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let handler = ImportRepo {
+        context: context.clone(),
+        repo: repo.clone(),
+        command_list: commands.clone(),
+    };
+    handler
+        .attach_to_monorepo_parent()
+        .await
+        .map_err(|err| MegaError::with_message(&err.to_string()))?;
+    quota::record(context, path, size, 0).await;
+
+    let lock = lock_repo(storage.get_connection(), path).await?;
+    for command in &commands {
+        if let Err(err) = handler.update_refs(None, None, command).await {
+            lock.release().await?;
+            return Err(MegaError::with_message(&format!(
+                "failed to update {}: {}",
+                command.ref_name, err
+            )));
+        }
+    }
+    lock.release().await?;
+    Ok(Outcome::Imported {
+        objects,
+        refs: commands.len(),
+    })
+}
+
+/// The `(name, id)` of the branches and tags of the repository.
+async fn read_refs(source: &Path) -> Result<Vec<(String, String)>, MegaError> {
+    let output = git(
+        source,
+        &[
+            "for-each-ref",
+            "--format=%(refname) %(objectname)",
+            "refs/heads",
+            "refs/tags",
+        ],
+    )
+    .await?;
+    Ok(output
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, id)| (name.to_owned(), id.to_owned()))
+        .collect())
+}
+
+async fn git(repo: &Path, args: &[&str]) -> Result<String, MegaError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(MegaError::with_message(&format!(
+            "git {} failed in {}: {}",
+            args.join(" "),
+            repo.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Store the entries of `receiver` in batches, `parallel` of them at a time, returns the number
+/// of objects and the size of the blobs stored.
+///
+/// Blocks until the pack is decoded, it runs off the async runtime.
+fn save_entries(
+    storage: GitDbStorage,
+    repo_id: i64,
+    receiver: mpsc::Receiver<Entry>,
+    parallel: usize,
+    progress: impl Fn(usize),
+) -> Result<(usize, u64), MegaError> {
+    let handle = tokio::runtime::Handle::current();
+    let mut tasks: VecDeque<JoinHandle<Result<usize, MegaError>>> = VecDeque::new();
+    let (mut objects, mut size) = (0, 0);
+    let mut wait = |task: JoinHandle<Result<usize, MegaError>>| -> Result<(), MegaError> {
+        objects += handle
+            .block_on(task)
+            .map_err(|err| MegaError::with_message(&err.to_string()))??;
+        progress(objects);
+        Ok(())
+    };
+    let mut batch = Vec::new();
+    let mut receiver = receiver.into_iter().peekable();
+    while let Some(entry) = receiver.next() {
+        if entry.obj_type == ObjectType::Blob {
+            size += entry.data.len() as u64;
+        }
+        batch.push(entry);
+        if batch.len() < BATCH_SIZE && receiver.peek().is_some() {
+            continue;
+        }
+        if tasks.len() >= parallel.max(1) {
+            wait(tasks.pop_front().unwrap())?;
+        }
+        let storage = storage.clone();
+        let batch = std::mem::take(&mut batch);
+        tasks.push_back(handle.spawn(async move {
+            let len = batch.len();
+            storage.save_entry(repo_id, batch).await?;
+            Ok(len)
+        }));
+    }
+    while let Some(task) = tasks.pop_front() {
+        wait(task)?;
+    }
+    Ok((objects, size))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use callisto::{db_enums::RefType, import_refs};
+    use common::utils::ZERO_ID;
+
+    use super::{plan_refs, target_path};
+    use crate::protocol::import_refs::CommandType;
+
+    fn stored(name: &str, id: &str) -> import_refs::Model {
+        import_refs::Model {
+            id: 0,
+            repo_id: 0,
+            ref_name: name.to_owned(),
+            ref_git_id: id.to_owned(),
+            ref_type: RefType::Branch,
+            default_branch: false,
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    fn local(refs: &[(&str, &str)]) -> Vec<(String, String)> {
+        refs.iter()
+            .map(|(n, i)| (n.to_string(), i.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_refs() {
+        let refs = local(&[
+            ("refs/heads/dev", "b"),
+            ("refs/heads/main", "a"),
+            ("refs/tags/v1", "c"),
+        ]);
+        // a new repository gets every ref, its head first
+        let res = plan_refs(&refs, &[], Some("refs/heads/main"));
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].ref_name, "refs/heads/main");
+        assert!(res[0].default_branch);
+        assert!(res.iter().all(|c| c.command_type == CommandType::Create));
+        assert_eq!(res[2].ref_type, RefType::Tag);
+
+        // an import run again only updates what changed
+        let res = plan_refs(
+            &refs,
+            &[
+                stored("refs/heads/main", "a"),
+                stored("refs/heads/dev", "old"),
+                stored("refs/heads/gone", "d"),
+            ],
+            Some("refs/heads/main"),
+        );
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].command_type, CommandType::Update);
+        assert_eq!(res[0].old_id, "old");
+        assert!(!res[0].default_branch);
+        assert_eq!(res[1].old_id, ZERO_ID);
+        let current = [
+            stored("refs/heads/dev", "b"),
+            stored("refs/heads/main", "a"),
+        ];
+        assert!(plan_refs(&refs[..2], &current, None).is_empty());
+    }
+
+    #[test]
+    fn test_target_path() {
+        let target = Path::new("/third-part");
+        let source = Path::new("/srv/git");
+        assert_eq!(
+            target_path(target, source, Path::new("/srv/git/tools/build.git")),
+            PathBuf::from("/third-part/tools/build")
+        );
+        // a single repository is named after itself
+        assert_eq!(
+            target_path(
+                target,
+                Path::new("/srv/git/app.git"),
+                Path::new("/srv/git/app.git")
+            ),
+            PathBuf::from("/third-part/app")
+        );
+    }
+}
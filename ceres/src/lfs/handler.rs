@@ -1,5 +1,7 @@
 use std::cmp::min;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 use crate::lfs::lfs_structs::ChunkRepresentation;
 use crate::lfs::lfs_structs::{
@@ -7,17 +9,18 @@ use crate::lfs::lfs_structs::{
     VerifiableLockRequest,
 };
 use crate::lfs::lfs_structs::{Link, Lock, LockListQuery, MetaObject, Representation, RequestVars};
+use crate::quota;
 use anyhow::Result;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use callisto::{lfs_locks, lfs_objects, lfs_split_relations};
 use chrono::{prelude::*, Duration};
 use common::errors::{GitLFSError, MegaError};
-use futures::Stream;
+use futures::{future, stream, Stream, StreamExt, TryStreamExt};
 use jupiter::context::Context;
+use jupiter::raw_storage::{once, ByteStream};
 use jupiter::storage::lfs_db_storage::LfsDbStorage;
 use rand::prelude::*;
 use sea_orm::{DatabaseTransaction, EntityTrait, IntoActiveModel, TransactionTrait};
-use tokio_stream::wrappers::ReceiverStream;
 
 pub async fn lfs_retrieve_lock(
     storage: LfsDbStorage,
@@ -209,6 +212,28 @@ pub async fn lfs_process_batch(
         }
         // Not found
         if batch_vars.operation == "upload" {
+            // refuse objects which are declared too large early, the upload counts what is
+            // actually sent
+            let over_quota = if found {
+                None
+            } else {
+                quota::check(context, object_repo(object), object.size as u64)
+                    .await
+                    .map_err(|err| GitLFSError::GeneralError(err.to_string()))?
+            };
+            if let Some(reason) = over_quota {
+                response_objects.push(Representation {
+                    oid: object.oid.to_owned(),
+                    size: object.size,
+                    authenticated: None,
+                    actions: None,
+                    error: Some(ObjectError {
+                        code: 507,
+                        message: reason,
+                    }),
+                });
+                continue;
+            }
             meta = lfs_put_meta(storage.clone(), object, config.enable_split)
                 .await
                 .unwrap();
@@ -285,10 +310,11 @@ pub async fn lfs_fetch_chunk_ids(
 
 /// Upload object to storage.
 /// if server enable split, split the object and upload each part to storage, save the relationship to database.
+/// The body is consumed as a stream, at most one chunk of it is held in memory at a time.
 pub async fn lfs_upload_object(
     context: &Context,
     request_vars: &RequestVars,
-    body_bytes: &[u8],
+    content: ByteStream,
 ) -> Result<(), GitLFSError> {
     let config = context.config.lfs.clone();
     let storage = context.services.lfs_db_storage.clone();
@@ -298,23 +324,56 @@ pub async fn lfs_upload_object(
         .await
         .unwrap();
     tracing::debug!("upload lfs object {} size: {}", meta.oid, meta.size);
+    // objects are shared by all repositories, their size counts for the one announcing them
+    // first, the declared size is not trusted
+    let counted = !lfs_file_exist(context, &meta).await;
+    let allowance = if counted {
+        quota::available(context, &meta.repo)
+            .await
+            .map_err(|err| GitLFSError::GeneralError(err.to_string()))?
+    } else {
+        None
+    };
+    let received = Arc::new(AtomicU64::new(0));
+    let mut content = limit_size(content, allowance, received.clone());
     if config.enable_split && meta.splited {
-        // assert!(request_vars.size == body_bytes.len() as i64, "size didn't match: {} != {}", request_vars.size, body_bytes.len()); // TODO: git client, request_vars.size is `0`!!
-        // split object to blocks
-
+        // TODO: git client, request_vars.size is `0`!! so the size can't be checked before reading the body.
+        // split object to blocks, each block is stored as soon as it is complete.
         let mut sub_ids = vec![];
-        for chunk in body_bytes.chunks(config.split_size) {
-            // sha256
-            let sub_id = hex::encode(ring::digest::digest(&ring::digest::SHA256, chunk));
-            let res = lfs_storage.put_object(&sub_id, chunk).await;
-            if res.is_err() {
-                lfs_delete_meta(&storage, request_vars).await.unwrap();
-                // TODO: whether/how to delete the uploaded blocks.
-                return Err(GitLFSError::GeneralError(String::from(
-                    "Header not acceptable!",
-                )));
+        let mut buffer = BytesMut::with_capacity(config.split_size);
+        loop {
+            let piece = match content.try_next().await {
+                Ok(piece) => piece,
+                Err(err) => {
+                    tracing::error!("lfs object upload failed, failed to read body: {}", err);
+                    lfs_delete_meta(&storage, request_vars).await.unwrap();
+                    return Err(GitLFSError::GeneralError(err.to_string()));
+                }
+            };
+            let finished = piece.is_none();
+            if let Some(piece) = piece {
+                buffer.extend_from_slice(&piece);
+            }
+            while buffer.len() >= config.split_size || (finished && !buffer.is_empty()) {
+                let chunk = buffer
+                    .split_to(min(config.split_size, buffer.len()))
+                    .freeze();
+                // sha256
+                let sub_id = hex::encode(ring::digest::digest(&ring::digest::SHA256, &chunk));
+                let size = chunk.len() as i64;
+                let res = lfs_storage.put_object_stream(&sub_id, once(chunk)).await;
+                if res.is_err() {
+                    lfs_delete_meta(&storage, request_vars).await.unwrap();
+                    // TODO: whether/how to delete the uploaded blocks.
+                    return Err(GitLFSError::GeneralError(String::from(
+                        "Header not acceptable!",
+                    )));
+                }
+                sub_ids.push((sub_id, size));
+            }
+            if finished {
+                break;
             }
-            sub_ids.push(sub_id);
         }
         tracing::debug!(
             "lfs object {} split into {} chunks",
@@ -326,8 +385,7 @@ pub async fn lfs_upload_object(
         let con = storage.get_connection();
         let tx = con.begin().await.unwrap();
         let mut offset = 0;
-        for sub_id in sub_ids {
-            let size = min(config.split_size as i64, body_bytes.len() as i64 - offset);
+        for (sub_id, size) in sub_ids {
             let result = lfs_put_relation(&tx, &meta.oid, &sub_id, offset, size).await;
             if result.is_err() {
                 tx.rollback().await.unwrap();
@@ -343,19 +401,57 @@ pub async fn lfs_upload_object(
         tracing::debug!("lfs object  split relationship saved");
     } else {
         // normal mode
-        let res = lfs_storage.put_object(&meta.oid, body_bytes).await;
-        if res.is_err() {
+        let res = lfs_storage.put_object_stream(&meta.oid, content).await;
+        if let Err(err) = res {
+            tracing::error!("lfs object upload failed: {}", err);
             lfs_delete_meta(&storage, request_vars).await.unwrap();
-            return Err(GitLFSError::GeneralError(String::from(
-                "Header not acceptable!",
-            )));
+            return Err(GitLFSError::GeneralError(err.to_string()));
         }
     }
+    if counted {
+        quota::record(context, &meta.repo, 0, received.load(Ordering::Relaxed)).await;
+    }
     Ok(())
 }
 
+/// Count the bytes read from `content` in `received`, the stream fails once they exceed the
+/// `allowance` left by the quota.
+fn limit_size(
+    content: ByteStream,
+    allowance: Option<(u64, String)>,
+    received: Arc<AtomicU64>,
+) -> ByteStream {
+    content
+        .and_then(move |piece| {
+            let size = piece.len() as u64;
+            let total = received.fetch_add(size, Ordering::Relaxed) + size;
+            let res = match &allowance {
+                Some((available, owner)) if total > *available => {
+                    Err(MegaError::with_message(&format!(
+                        "storage quota of {} exceeded: only {} MB left",
+                        owner,
+                        available / (1024 * 1024)
+                    )))
+                }
+                _ => Ok(piece),
+            };
+            future::ready(res)
+        })
+        .boxed()
+}
+
+/// The repository an object of a batch request counts for, LFS clients usually leave it empty.
+fn object_repo(object: &RequestVars) -> &str {
+    if object.repo.is_empty() {
+        "/"
+    } else {
+        &object.repo
+    }
+}
+
 /// Download object from storage.
 /// when server enable split,  if OID is a complete object, then splice the object and return it.
+/// Chunks are opened one after another while the response is sent, so the object is never loaded as a whole.
 pub async fn lfs_download_object(
     context: Context,
     oid: &String,
@@ -363,7 +459,7 @@ pub async fn lfs_download_object(
     let config = context.config.lfs;
     let stg = context.services.lfs_db_storage.clone();
     let lfs_storage = context.services.lfs_storage.clone();
-    if config.enable_split {
+    let content = if config.enable_split {
         let meta = lfs_get_meta(stg.clone(), oid).await;
         match meta {
             Ok(meta) => {
@@ -374,29 +470,18 @@ pub async fn lfs_download_object(
                         "oid didn't have chunks".to_string(),
                     ));
                 }
-                let (tx, rx) = tokio::sync::mpsc::channel(1);
-                let oid = oid.clone();
-                tokio::spawn(async move {
-                    let chunks_len = relations.len();
-                    for relation in relations {
-                        let sub_bytes = lfs_storage.get_object(&relation.sub_oid).await.unwrap();
-                        // let _ = tx.send(Ok(Bytes::from(sub_bytes))).await;
-                        if let Err(err) = tx.send(Ok(sub_bytes)).await {
-                            tracing::error!(
-                                "lfs object download failed, failed to send chunk [{}], error: {}",
-                                relation.offset,
-                                err
-                            );
-                            break;
-                        }
-                    }
-                    tracing::debug!(
-                        "lfs object download completed for oid: {}, {} chunks",
-                        oid,
-                        chunks_len
-                    );
-                });
-                Ok(ReceiverStream::new(rx))
+                tracing::debug!(
+                    "lfs object download for oid: {}, {} chunks",
+                    oid,
+                    relations.len()
+                );
+                stream::iter(relations)
+                    .then(move |relation| {
+                        let lfs_storage = lfs_storage.clone();
+                        async move { lfs_storage.get_object_stream(&relation.sub_oid).await }
+                    })
+                    .try_flatten()
+                    .boxed()
             }
             Err(_) => {
                 // check if the oid is a part of a split object, if so, return the part.
@@ -405,21 +490,20 @@ pub async fn lfs_download_object(
                         "oid didn't belong to any object".to_string(),
                     ));
                 }
-
-                let bytes = lfs_storage.get_object(oid).await.unwrap();
-                // because return type must be `ReceiverStream`, so we need to wrap the bytes into a stream.
-                let (tx, rx) = tokio::sync::mpsc::channel(1);
-                tx.send(Ok(bytes)).await.unwrap();
-                Ok(ReceiverStream::new(rx))
+                lfs_storage
+                    .get_object_stream(oid)
+                    .await
+                    .map_err(|err| GitLFSError::GeneralError(err.to_string()))?
             }
         }
     } else {
-        let meta = lfs_get_meta(stg, oid).await.unwrap();
-        let bytes = lfs_storage.get_object(&meta.oid).await.unwrap();
-        let (tx, rx) = tokio::sync::mpsc::channel(1);
-        tx.send(Ok(bytes)).await.unwrap();
-        Ok(ReceiverStream::new(rx))
-    }
+        let meta = lfs_get_meta(stg, oid).await?;
+        lfs_storage
+            .get_object_stream(&meta.oid)
+            .await
+            .map_err(|err| GitLFSError::GeneralError(err.to_string()))?
+    };
+    Ok(content.map_err(|err| GitLFSError::GeneralError(err.to_string())))
 }
 
 /// Download a chunk from a large object.
@@ -448,9 +532,14 @@ pub async fn lfs_download_chunk(
         let chunk = lfs_stg.get_object(chunk_oid).await.unwrap();
         Ok(chunk)
     } else {
-        // return part of the original object.
-        let bytes = lfs_stg.get_object(origin_oid).await.unwrap();
-        let chunk_bytes = bytes[offset as usize..(offset + size) as usize].to_vec();
+        // return part of the original object, only the requested range is kept in memory.
+        let content = lfs_stg
+            .get_object_stream(origin_oid)
+            .await
+            .map_err(|err| GitLFSError::GeneralError(err.to_string()))?;
+        let chunk_bytes = read_range(content, offset, size)
+            .await
+            .map_err(|err| GitLFSError::GeneralError(err.to_string()))?;
         // check hash
         let chunk_hash = hex::encode(ring::digest::digest(&ring::digest::SHA256, &chunk_bytes));
         if chunk_hash != *chunk_oid {
@@ -458,10 +547,31 @@ pub async fn lfs_download_chunk(
                 "Chunk hash not match".to_string(),
             ));
         }
-        Ok(Bytes::from(chunk_bytes))
+        Ok(chunk_bytes)
     }
 }
 
+/// Read `size` bytes starting at `offset` from a stream, dropping everything outside the range.
+/// The result is shorter than `size` if the stream ends early.
+async fn read_range(mut content: ByteStream, offset: u64, size: u64) -> Result<Bytes, MegaError> {
+    let (start, end) = (offset as usize, (offset + size) as usize);
+    let mut range = BytesMut::with_capacity(size as usize);
+    let mut position = 0;
+    while position < end {
+        let Some(piece) = content.try_next().await? else {
+            break;
+        };
+        let piece_end = position + piece.len();
+        if piece_end > start {
+            let from = start.saturating_sub(position);
+            let to = min(end, piece_end) - position;
+            range.extend_from_slice(&piece[from..to]);
+        }
+        position = piece_end;
+    }
+    Ok(range.freeze())
+}
+
 pub async fn represent(
     rv: &RequestVars,
     meta: &MetaObject,
@@ -682,6 +792,7 @@ async fn lfs_get_meta(storage: LfsDbStorage, oid: &str) -> Result<MetaObject, Gi
             size: val.size,
             exist: val.exist,
             splited: val.splited,
+            repo: val.repo,
         }),
         None => Err(GitLFSError::GeneralError("".to_string())),
     }
@@ -700,6 +811,7 @@ async fn lfs_put_meta(
             size: result.size,
             exist: true,
             splited: result.splited,
+            repo: result.repo,
         });
     }
 
@@ -709,6 +821,7 @@ async fn lfs_put_meta(
         size: v.size,
         exist: true,
         splited,
+        repo: object_repo(v).to_owned(),
     };
 
     let meta_to = lfs_objects::Model {
@@ -716,6 +829,7 @@ async fn lfs_put_meta(
         size: meta.size.to_owned(),
         exist: true,
         splited,
+        repo: meta.repo.to_owned(),
     };
 
     let res = storage.new_lfs_object(meta_to).await;
@@ -855,3 +969,22 @@ async fn lfs_check_sub_oid_exist(
     let result = storage.get_lfs_relations_ori_oid(sub_oid).await.unwrap();
     Ok(!result.is_empty())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pieces(pieces: &[&'static [u8]]) -> ByteStream {
+        stream::iter(pieces.iter().map(|piece| Ok(Bytes::from_static(piece)))).boxed()
+    }
+
+    #[tokio::test]
+    async fn test_read_range() {
+        let content = || pieces(&[b"abc", b"defg", b"", b"hij"]);
+        assert_eq!(read_range(content(), 0, 3).await.unwrap(), "abc");
+        assert_eq!(read_range(content(), 2, 6).await.unwrap(), "cdefgh");
+        assert_eq!(read_range(content(), 4, 2).await.unwrap(), "ef");
+        assert_eq!(read_range(content(), 8, 10).await.unwrap(), "ij");
+        assert!(read_range(content(), 12, 1).await.unwrap().is_empty());
+    }
+}
@@ -17,6 +17,8 @@ pub struct MetaObject {
     pub size: i64,
     pub exist: bool,
     pub splited: bool,
+    /// The repository the object was announced for, its upload counts towards its quota
+    pub repo: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -1,5 +1,17 @@
 pub mod api_service;
+pub mod entity_file;
+pub mod feature;
+pub mod history;
+pub mod import;
 pub mod lfs;
+pub mod maintenance;
+pub mod merge;
 pub mod pack;
 pub mod protocol;
+pub mod quota;
+pub mod release;
+pub mod signature;
+pub mod stats;
+pub mod subtree;
+pub mod tenant;
 pub mod model;
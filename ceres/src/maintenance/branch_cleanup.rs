@@ -0,0 +1,179 @@
+//! Cleanup of branches which are no longer in use.
+//!
+//! Monorepo directories get their own ref while a merge request is open on them, import
+//! repositories keep the branches pushed by their users. Branches without any update for
+//! `maintenance.stale_branch_days` are stale, unless they are a default branch, still have
+//! an open merge request or match one of the protected patterns of their branch settings.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+
+use callisto::{branch_setting, import_refs, maintenance_job, mega_refs};
+use common::errors::MegaError;
+use common::utils::glob_match;
+use jupiter::context::Context;
+
+enum StaleRef {
+    Mono(mega_refs::Model),
+    Import(import_refs::Model),
+}
+
+pub struct StaleBranch {
+    /// Monorepo directory or path of the import repository
+    pub path: String,
+    pub branch: String,
+    pub commit: String,
+    pub updated_at: NaiveDateTime,
+    stale_ref: StaleRef,
+}
+
+/// The glob patterns of branches which must never be deleted.
+pub fn protected_patterns(setting: &branch_setting::Model) -> Vec<String> {
+    serde_json::from_str(&setting.protected_branches).unwrap_or_default()
+}
+
+/// Whether `branch` matches one of `patterns`, with or without its `refs/heads/` prefix.
+pub fn is_protected(patterns: &[String], branch: &str) -> bool {
+    let short = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+    patterns
+        .iter()
+        .any(|p| glob_match(p, branch) || glob_match(p, short))
+}
+
+/// Find the stale branches at or below `target`, the whole instance if it is `None`.
+///
+/// Monorepo refs are matched against the protected patterns by their directory.
+pub async fn find_stale_branches(
+    context: &Context,
+    target: Option<&str>,
+) -> Result<Vec<StaleBranch>, MegaError> {
+    let days = context.config.maintenance.stale_branch_days as i64;
+    let before = chrono::Utc::now().naive_utc() - chrono::Duration::days(days);
+    let in_target = |path: &str| target.is_none_or(|t| Path::new(path).starts_with(t));
+    let mono_storage = &context.services.mono_storage;
+    let mut patterns: HashMap<String, Vec<String>> = HashMap::new();
+    let mut res = Vec::new();
+
+    for r in mono_storage.get_refs_updated_before(before).await? {
+        if !in_target(&r.path) {
+            continue;
+        }
+        if context
+            .mr_stg()
+            .get_open_mr_by_path(&r.path)
+            .await?
+            .is_some()
+        {
+            continue;
+        }
+        let protected = load_patterns(context, &mut patterns, &r.path).await?;
+        if is_protected(protected, &r.path) {
+            continue;
+        }
+        res.push(StaleBranch {
+            path: r.path.clone(),
+            branch: r.ref_name.clone(),
+            commit: r.ref_commit_hash.clone(),
+            updated_at: r.updated_at,
+            stale_ref: StaleRef::Mono(r),
+        });
+    }
+
+    let git_storage = &context.services.git_db_storage;
+    let branches = git_storage.get_branches_updated_before(before).await?;
+    let repo_ids: HashSet<i64> = branches.iter().map(|b| b.repo_id).collect();
+    let repos: HashMap<i64, String> = git_storage
+        .get_git_repos_by_ids(repo_ids.into_iter().collect())
+        .await?
+        .into_iter()
+        .map(|repo| (repo.id, repo.repo_path))
+        .collect();
+    for b in branches {
+        let Some(path) = repos.get(&b.repo_id) else {
+            continue;
+        };
+        if !in_target(path) {
+            continue;
+        }
+        let protected = load_patterns(context, &mut patterns, path).await?;
+        if is_protected(protected, &b.ref_name) {
+            continue;
+        }
+        res.push(StaleBranch {
+            path: path.clone(),
+            branch: b.ref_name.clone(),
+            commit: b.ref_git_id.clone(),
+            updated_at: b.updated_at,
+            stale_ref: StaleRef::Import(b),
+        });
+    }
+    Ok(res)
+}
+
+async fn load_patterns<'a>(
+    context: &Context,
+    cache: &'a mut HashMap<String, Vec<String>>,
+    path: &str,
+) -> Result<&'a Vec<String>, MegaError> {
+    if !cache.contains_key(path) {
+        let setting = context
+            .services
+            .mono_storage
+            .get_branch_setting(Path::new(path))
+            .await?;
+        let patterns = setting.as_ref().map(protected_patterns).unwrap_or_default();
+        cache.insert(path.to_owned(), patterns);
+    }
+    Ok(&cache[path])
+}
+
+/// Report the stale branches below the job target, and delete them if configured.
+pub async fn run(context: &Context, job: &maintenance_job::Model) -> Result<String, MegaError> {
+    let branches = find_stale_branches(context, job.target.as_deref()).await?;
+    if !context.config.maintenance.delete_stale_branches {
+        for b in &branches {
+            tracing::info!("stale branch {} of {} at {}", b.branch, b.path, b.commit);
+        }
+        return Ok(format!("found {} stale branches", branches.len()));
+    }
+    let count = branches.len();
+    for b in branches {
+        tracing::info!(
+            "delete stale branch {} of {} at {}",
+            b.branch,
+            b.path,
+            b.commit
+        );
+        match b.stale_ref {
+            StaleRef::Mono(r) => {
+                let storage = &context.services.mono_storage;
+                storage.remove_ref(storage.get_connection(), r).await?
+            }
+            StaleRef::Import(r) => {
+                context
+                    .services
+                    .git_db_storage
+                    .remove_ref(r.repo_id, &r.ref_name)
+                    .await?
+            }
+        }
+    }
+    Ok(format!("deleted {} stale branches", count))
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_protected;
+
+    #[test]
+    fn test_is_protected() {
+        let patterns = vec!["release/*".to_owned(), "refs/heads/keep".to_owned()];
+        assert!(is_protected(&patterns, "refs/heads/release/1.0"));
+        assert!(is_protected(&patterns, "release/2.0"));
+        assert!(is_protected(&patterns, "refs/heads/keep"));
+        assert!(!is_protected(&patterns, "refs/heads/feature"));
+        assert!(!is_protected(&[], "refs/heads/release/1.0"));
+    }
+}
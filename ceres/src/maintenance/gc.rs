@@ -0,0 +1,343 @@
+//! Maintenance of the whole instance at once, run by `mega gc`.
+//!
+//! The repack, bitmap, commit-graph, object gc and lfs gc tasks run one after another over all
+//! repositories, or over the repositories at or below some paths, several repositories at a
+//! time. Every task is recorded in the job history like a manual run and is skipped if it is
+//! already running on an instance. A dry run only reports what would be done and records nothing.
+//!
+//! Some work is not done per repository: generation numbers are computed for all commits of the
+//! monorepo, the monorepo is collected as a whole by object gc if any path is in it, and lfs gc
+//! is skipped for some paths since LFS objects are shared by all repositories.
+
+use std::collections::HashSet;
+use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use futures::{stream, StreamExt};
+
+use callisto::db_enums::{JobStatus, JobTrigger, MaintenanceTask};
+use callisto::mega_refs;
+use common::errors::MegaError;
+use jupiter::context::Context;
+
+use crate::maintenance::jobs;
+use crate::maintenance::object_gc::{self, Collected, Source};
+use crate::pack::cache;
+
+/// The tasks run by gc, in the order they run: the reachability indexes are built for the
+/// prebuilt packs.
+pub const TASKS: [MaintenanceTask; 5] = [
+    MaintenanceTask::Repack,
+    MaintenanceTask::Bitmap,
+    MaintenanceTask::CommitGraph,
+    MaintenanceTask::ObjectGc,
+    MaintenanceTask::LfsGc,
+];
+
+/// Operator of the jobs in the history.
+const OPERATOR: &str = "mega gc";
+
+#[derive(Debug, Clone)]
+pub struct GcOptions {
+    /// Tasks to run, all of [`TASKS`] if empty
+    pub tasks: Vec<MaintenanceTask>,
+    /// Only the repositories at or below these paths, all if empty
+    pub paths: Vec<String>,
+    /// How many repositories are worked on at the same time
+    pub jobs: usize,
+    /// Delete unreachable objects even if `maintenance.prune_unreachable_objects` is not set
+    pub prune: bool,
+    /// Only report what would be done
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+/// What one task did.
+#[derive(Debug)]
+pub struct TaskReport {
+    pub task: MaintenanceTask,
+    pub outcome: Outcome,
+    pub summary: String,
+    /// Paths of the repositories the task failed on, with the error
+    pub failed: Vec<(String, String)>,
+    pub elapsed: Duration,
+}
+
+/// Run the tasks of `options` and report how each went, a task which fails does not stop the
+/// others.
+pub async fn run(context: &Context, options: &GcOptions) -> Vec<TaskReport> {
+    let mut reports = Vec::new();
+    for task in TASKS {
+        if !options.tasks.is_empty() && !options.tasks.contains(&task) {
+            continue;
+        }
+        let started = Instant::now();
+        let mut report = run_task(context, options, task).await;
+        report.elapsed = started.elapsed();
+        tracing::info!("gc task {} {:?}: {}", task, report.outcome, report.summary);
+        reports.push(report);
+    }
+    reports
+}
+
+async fn run_task(context: &Context, options: &GcOptions, task: MaintenanceTask) -> TaskReport {
+    let mut report = TaskReport {
+        task,
+        outcome: Outcome::Skipped,
+        summary: String::new(),
+        failed: Vec::new(),
+        elapsed: Duration::ZERO,
+    };
+    if task == MaintenanceTask::LfsGc && !options.paths.is_empty() {
+        report.summary = "lfs objects are shared by all repositories".to_owned();
+        return report;
+    }
+    let storage = &context.services.maintenance_storage;
+    let job = if options.dry_run {
+        None
+    } else {
+        match storage
+            .start_job(task, JobTrigger::Manual, Some(OPERATOR.to_owned()), None)
+            .await
+        {
+            Ok(Some(job)) => Some(job),
+            Ok(None) => {
+                report.summary = "already running".to_owned();
+                return report;
+            }
+            Err(err) => {
+                report.outcome = Outcome::Failed;
+                report.summary = err.to_string();
+                return report;
+            }
+        }
+    };
+
+    let res = execute(context, options, task, &mut report.failed).await;
+    (report.outcome, report.summary) = match res {
+        Ok(summary) if report.failed.is_empty() => (Outcome::Succeeded, summary),
+        Ok(summary) => (
+            Outcome::Failed,
+            format!(
+                "{}, failed for {} repositories",
+                summary,
+                report.failed.len()
+            ),
+        ),
+        Err(err) => (Outcome::Failed, err.to_string()),
+    };
+    if let Some(job) = job {
+        let status = match report.outcome {
+            Outcome::Succeeded => JobStatus::Succeeded,
+            _ => JobStatus::Failed,
+        };
+        if let Err(err) = storage
+            .finish_job(job, status, report.summary.clone())
+            .await
+        {
+            tracing::error!("failed to record maintenance job {}: {}", task, err);
+        }
+    }
+    report
+}
+
+async fn execute(
+    context: &Context,
+    options: &GcOptions,
+    task: MaintenanceTask,
+    failed: &mut Vec<(String, String)>,
+) -> Result<String, MegaError> {
+    let dry_run = options.dry_run;
+    let dir = &context.config.maintenance.pack_cache_path;
+    let services = &context.services;
+    match task {
+        MaintenanceTask::Repack => {
+            let refs = services.mono_storage.get_default_refs().await?;
+            let tips: HashSet<String> = refs.iter().map(|r| r.ref_commit_hash.clone()).collect();
+            let refs = selected_refs(refs, &options.paths);
+            let count = refs.len();
+            if !dry_run {
+                fs::create_dir_all(dir)?;
+            }
+            let built = each(refs, options.jobs, failed, |r| async move {
+                if dry_run {
+                    Ok(!cache::pack_path(dir, &r.ref_commit_hash).exists())
+                } else {
+                    jobs::build_pack(context, &r).await
+                }
+            })
+            .await;
+            // the packs of old tips belong to no repository
+            let stale = match (dir.exists(), dry_run) {
+                (false, _) => 0,
+                (true, true) => cache::stale_files(dir, &tips)?.len(),
+                (true, false) => cache::remove_stale(dir, &tips)?,
+            };
+            Ok(format!(
+                "{} {} packs of {} repositories, {} {} stale files",
+                verb(dry_run, "built", "would build"),
+                built.into_iter().filter(|b| *b).count(),
+                count,
+                verb(dry_run, "removed", "would remove"),
+                stale
+            ))
+        }
+        MaintenanceTask::Bitmap => {
+            let refs = services.mono_storage.get_default_refs().await?;
+            let refs = selected_refs(refs, &options.paths);
+            let count = refs.len();
+            let built = each(refs, options.jobs, failed, |r| async move {
+                if dry_run {
+                    Ok(!cache::reach_path(dir, &r.ref_commit_hash).exists()
+                        && cache::pack_path(dir, &r.ref_commit_hash).exists())
+                } else {
+                    jobs::build_reachable(context, &r).await
+                }
+            })
+            .await;
+            Ok(format!(
+                "{} {} reachability indexes of {} repositories",
+                verb(dry_run, "built", "would build"),
+                built.into_iter().filter(|b| *b).count(),
+                count
+            ))
+        }
+        MaintenanceTask::CommitGraph => {
+            let count = jobs::update_generations(context, dry_run).await?;
+            Ok(format!(
+                "{} generation numbers of {} commits",
+                verb(dry_run, "computed", "would compute"),
+                count
+            ))
+        }
+        MaintenanceTask::ObjectGc => {
+            let before = object_gc::grace_cutoff(context);
+            let prune =
+                !dry_run && (options.prune || context.config.maintenance.prune_unreachable_objects);
+            let import_dir = &context.config.monorepo.import_dir;
+            let mut repos = Vec::new();
+            if options.paths.is_empty()
+                || options
+                    .paths
+                    .iter()
+                    .any(|p| !Path::new(p).starts_with(import_dir))
+            {
+                repos.push(("/".to_owned(), Source::Mono));
+            }
+            for repo in services.git_db_storage.list_git_repos().await? {
+                if is_selected(&options.paths, &repo.repo_path) {
+                    repos.push((repo.repo_path, Source::Import(repo.id)));
+                }
+            }
+            let count = repos.len();
+            let found = each(repos, options.jobs, failed, |(path, source)| async move {
+                object_gc::collect(context, source, &path, before, prune).await
+            })
+            .await;
+            let mut collected = Collected::default();
+            for ids in &found {
+                collected.add(ids);
+            }
+            Ok(format!(
+                "{} {} unreachable commits, {} trees, {} blobs and {} tags in {} of {} repositories",
+                if prune { "removed" } else { "found" },
+                collected.commits,
+                collected.trees,
+                collected.blobs,
+                collected.tags,
+                collected.repos,
+                count
+            ))
+        }
+        MaintenanceTask::LfsGc => {
+            let removed = jobs::remove_lfs_objects(context, dry_run).await?;
+            Ok(format!(
+                "{} {} unreferenced lfs objects",
+                verb(dry_run, "removed", "would remove"),
+                removed
+            ))
+        }
+        _ => Err(MegaError::with_message(&format!(
+            "{} is not run by gc",
+            task
+        ))),
+    }
+}
+
+/// Whether the repository at `path` is at or below one of `paths`, every repository is if
+/// there are none.
+fn is_selected(paths: &[String], path: &str) -> bool {
+    paths.is_empty() || paths.iter().any(|p| Path::new(path).starts_with(p))
+}
+
+fn selected_refs(refs: Vec<mega_refs::Model>, paths: &[String]) -> Vec<(String, mega_refs::Model)> {
+    refs.into_iter()
+        .filter(|r| is_selected(paths, &r.path))
+        .map(|r| (r.path.clone(), r))
+        .collect()
+}
+
+fn verb(dry_run: bool, done: &'static str, would: &'static str) -> &'static str {
+    if dry_run {
+        would
+    } else {
+        done
+    }
+}
+
+/// Run `f` for every repository of `repos`, keyed by path, `jobs` of them at a time. Returns
+/// the results of those it succeeded for and adds the others to `failed`.
+async fn each<T, R, F, Fut>(
+    repos: Vec<(String, T)>,
+    jobs: usize,
+    failed: &mut Vec<(String, String)>,
+    f: F,
+) -> Vec<R>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = Result<R, MegaError>>,
+{
+    let results: Vec<(String, Result<R, MegaError>)> = stream::iter(repos)
+        .map(|(path, repo)| {
+            let fut = f(repo);
+            async move { (path, fut.await) }
+        })
+        .buffer_unordered(jobs.max(1))
+        .collect()
+        .await;
+    let mut done = Vec::new();
+    for (path, res) in results {
+        match res {
+            Ok(r) => done.push(r),
+            Err(err) => {
+                tracing::error!("gc failed for {}: {}", path, err);
+                failed.push((path, err.to_string()));
+            }
+        }
+    }
+    done
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_selected;
+
+    #[test]
+    fn test_is_selected() {
+        assert!(is_selected(&[], "/project/mega"));
+        let paths = vec!["/project".to_owned(), "/third-party/rust".to_owned()];
+        assert!(is_selected(&paths, "/project"));
+        assert!(is_selected(&paths, "/project/mega"));
+        assert!(is_selected(&paths, "/third-party/rust/tokio"));
+        assert!(!is_selected(&paths, "/projects"));
+        assert!(!is_selected(&paths, "/third-party"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Synchronization of team memberships with the groups of an external identity provider.
+//!
+//! The groups and users of the provider are read through its SCIM 2.0 api. Every team mapped in
+//! `idp_sync.mappings` gets the users in its groups as members and loses all others, group
+//! members are matched to users by email and those without an account are skipped. Teams are
+//! part of the entities of every authorization request, so policies granting permissions to
+//! `principal in Team::"org/team"` follow the directory after the next sync.
+//!
+//! A team is left alone if one of its groups is missing from the directory, so that a renamed
+//! group or a broken response never removes every member of a team.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use callisto::team;
+use common::config::{IdpGroupMapping, IdpSyncConfig};
+use common::errors::MegaError;
+use jupiter::context::Context;
+
+/// Resources fetched from the SCIM api with one request.
+const PAGE_SIZE: usize = 100;
+
+/// Members of the groups of the directory by their display name, as lowercase emails.
+pub type Groups = HashMap<String, BTreeSet<String>>;
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// `(team, email)` of the memberships to add
+    pub add: Vec<(String, String)>,
+    /// `(team, email)` of the memberships to remove
+    pub remove: Vec<(String, String)>,
+    /// Mapped teams which are left alone as one of their groups is missing
+    pub skipped: Vec<String>,
+}
+
+/// Compare the members of the mapped teams in `current` with those of their groups.
+pub fn plan(
+    mappings: &[IdpGroupMapping],
+    groups: &Groups,
+    current: &BTreeMap<String, BTreeSet<String>>,
+) -> SyncPlan {
+    let mut desired: BTreeMap<&str, Option<BTreeSet<String>>> = BTreeMap::new();
+    for mapping in mappings {
+        let members = desired
+            .entry(&mapping.team)
+            .or_insert_with(|| Some(BTreeSet::new()));
+        match (groups.get(&mapping.group), members.as_mut()) {
+            (Some(group), Some(members)) => members.extend(group.iter().cloned()),
+            _ => *members = None,
+        }
+    }
+
+    let mut res = SyncPlan::default();
+    let empty = BTreeSet::new();
+    for (team, members) in desired {
+        let Some(members) = members else {
+            res.skipped.push(team.to_owned());
+            continue;
+        };
+        let current = current.get(team).unwrap_or(&empty);
+        for email in members.difference(current) {
+            res.add.push((team.to_owned(), email.clone()));
+        }
+        for email in current.difference(&members) {
+            res.remove.push((team.to_owned(), email.clone()));
+        }
+    }
+    res
+}
+
+/// Bring the members of the mapped teams in line with the groups of the identity provider.
+pub async fn run(context: &Context) -> Result<String, MegaError> {
+    let config = &context.config.idp_sync;
+    if config.scim_url.is_empty() {
+        return Ok("no identity provider configured".to_owned());
+    }
+    let groups = ScimClient::new(config).groups().await?;
+
+    let storage = context.user_stg();
+    let mut teams: BTreeMap<String, team::Model> = BTreeMap::new();
+    for mapping in &config.mappings {
+        if teams.contains_key(&mapping.team) {
+            continue;
+        }
+        match find_team(context, &mapping.team).await? {
+            Some(team) => {
+                teams.insert(mapping.team.clone(), team);
+            }
+            None => tracing::warn!("team {} mapped to an idp group not found", mapping.team),
+        }
+    }
+    let mappings: Vec<IdpGroupMapping> = config
+        .mappings
+        .iter()
+        .filter(|m| teams.contains_key(&m.team))
+        .cloned()
+        .collect();
+
+    let members = storage
+        .list_team_members(teams.values().map(|t| t.id).collect())
+        .await?;
+    let users: HashMap<i64, String> = storage
+        .find_users_by_ids(members.iter().map(|m| m.user_id).collect())
+        .await?
+        .into_iter()
+        .map(|u| (u.id, u.email.to_lowercase()))
+        .collect();
+    let mut current: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for (name, team) in &teams {
+        let emails = members
+            .iter()
+            .filter(|m| m.team_id == team.id)
+            .filter_map(|m| users.get(&m.user_id).cloned())
+            .collect();
+        current.insert(name.clone(), emails);
+    }
+
+    let plan = plan(&mappings, &groups, &current);
+    let (mut added, mut unknown) = (0, 0);
+    for (team, email) in &plan.add {
+        match storage.find_user_by_email(email).await? {
+            Some(user) => {
+                storage.save_team_member(teams[team].id, user.id).await?;
+                added += 1;
+            }
+            None => unknown += 1,
+        }
+    }
+    let user_ids: HashMap<&String, i64> = users.iter().map(|(id, email)| (email, *id)).collect();
+    for (team, email) in &plan.remove {
+        storage
+            .delete_team_member(teams[team].id, user_ids[email])
+            .await?;
+    }
+    for team in &plan.skipped {
+        tracing::warn!(
+            "members of {} not synced, one of its idp groups is missing",
+            team
+        );
+    }
+    Ok(format!(
+        "added {} and removed {} team members, skipped {} teams and {} users without account",
+        added,
+        plan.remove.len(),
+        plan.skipped.len(),
+        unknown
+    ))
+}
+
+/// The team named `org/team`.
+async fn find_team(context: &Context, name: &str) -> Result<Option<team::Model>, MegaError> {
+    let Some((org, team)) = name.split_once('/') else {
+        return Ok(None);
+    };
+    let storage = context.user_stg();
+    let Some(org) = storage.find_org_by_name(org).await? else {
+        return Ok(None);
+    };
+    Ok(storage
+        .list_teams(org.id)
+        .await?
+        .into_iter()
+        .find(|t| t.name == team))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListResponse<T> {
+    total_results: usize,
+    #[serde(default = "Vec::new", rename = "Resources")]
+    resources: Vec<T>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimUser {
+    id: String,
+    user_name: String,
+    #[serde(default)]
+    emails: Vec<ScimEmail>,
+}
+
+impl ScimUser {
+    /// The primary email of the user, its user name if it has none.
+    fn email(&self) -> String {
+        self.emails
+            .iter()
+            .find(|e| e.primary)
+            .or(self.emails.first())
+            .map_or(&self.user_name, |e| &e.value)
+            .to_lowercase()
+    }
+}
+
+#[derive(Deserialize)]
+struct ScimEmail {
+    value: String,
+    #[serde(default)]
+    primary: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ScimGroup {
+    display_name: String,
+    #[serde(default)]
+    members: Vec<ScimMember>,
+}
+
+#[derive(Deserialize)]
+struct ScimMember {
+    /// Id of the user, or of a nested group which is not followed
+    value: String,
+}
+
+struct ScimClient<'a> {
+    client: reqwest::Client,
+    config: &'a IdpSyncConfig,
+}
+
+impl<'a> ScimClient<'a> {
+    fn new(config: &'a IdpSyncConfig) -> Self {
+        ScimClient {
+            client: reqwest::Client::new(),
+            config,
+        }
+    }
+
+    /// The members of every group of the directory.
+    async fn groups(&self) -> Result<Groups, MegaError> {
+        let emails: HashMap<String, String> = self
+            .list::<ScimUser>("Users")
+            .await?
+            .into_iter()
+            .map(|u| (u.id.clone(), u.email()))
+            .collect();
+        Ok(self
+            .list::<ScimGroup>("Groups")
+            .await?
+            .into_iter()
+            .map(|g| {
+                let members = g
+                    .members
+                    .iter()
+                    .filter_map(|m| emails.get(&m.value).cloned())
+                    .collect();
+                (g.display_name, members)
+            })
+            .collect())
+    }
+
+    /// All resources of the type, fetched page by page.
+    async fn list<T: DeserializeOwned>(&self, resource: &str) -> Result<Vec<T>, MegaError> {
+        let url = format!(
+            "{}/{}",
+            self.config.scim_url.trim_end_matches('/'),
+            resource
+        );
+        let mut res = Vec::new();
+        loop {
+            let page: ListResponse<T> = self
+                .client
+                .get(&url)
+                .bearer_auth(&self.config.token)
+                .query(&[("startIndex", res.len() + 1), ("count", PAGE_SIZE)])
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status())
+                .map_err(|err| MegaError::with_message(&format!("scim request failed: {}", err)))?
+                .json()
+                .await
+                .map_err(|err| {
+                    MegaError::with_message(&format!("invalid scim response: {}", err))
+                })?;
+            let done = page.resources.is_empty();
+            res.extend(page.resources);
+            if done || res.len() >= page.total_results {
+                return Ok(res);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use common::config::IdpGroupMapping;
+
+    use super::{plan, Groups};
+
+    fn set(emails: &[&str]) -> BTreeSet<String> {
+        emails.iter().map(|e| e.to_string()).collect()
+    }
+
+    fn mapping(group: &str, team: &str) -> IdpGroupMapping {
+        IdpGroupMapping {
+            group: group.to_owned(),
+            team: team.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let mappings = vec![
+            mapping("Engineers", "mega/core"),
+            mapping("Contractors", "mega/core"),
+            mapping("Security", "mega/security"),
+            mapping("Renamed", "mega/docs"),
+        ];
+        let groups = Groups::from([
+            ("Engineers".to_owned(), set(&["alice@x.org", "bob@x.org"])),
+            ("Contractors".to_owned(), set(&["carol@x.org"])),
+            ("Security".to_owned(), set(&[])),
+        ]);
+        let current = BTreeMap::from([
+            ("mega/core".to_owned(), set(&["alice@x.org", "dave@x.org"])),
+            ("mega/security".to_owned(), set(&["erin@x.org"])),
+            ("mega/docs".to_owned(), set(&["frank@x.org"])),
+            ("mega/other".to_owned(), set(&["gina@x.org"])),
+        ]);
+        let res = plan(&mappings, &groups, &current);
+        let pairs = |v: &[(&str, &str)]| -> Vec<(String, String)> {
+            v.iter()
+                .map(|(t, e)| (t.to_string(), e.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            res.add,
+            pairs(&[("mega/core", "bob@x.org"), ("mega/core", "carol@x.org")])
+        );
+        assert_eq!(
+            res.remove,
+            pairs(&[("mega/core", "dave@x.org"), ("mega/security", "erin@x.org")])
+        );
+        // a missing group never empties its team, unmapped teams are left alone
+        assert_eq!(res.skipped, vec!["mega/docs".to_owned()]);
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use callisto::db_enums::MaintenanceTask;
+use callisto::{maintenance_job, mega_refs};
+use common::errors::MegaError;
+use jupiter::context::Context;
+use mercury::internal::object::tree::Tree;
+
+use crate::maintenance::{branch_cleanup, idp_sync, object_gc, subtree_split, verify};
+use crate::pack::{cache, monorepo::MonoRepo, PackHandler};
+
+/// Files younger than this are left alone, they might still be in use by a running push or upload.
+const GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Run the task of `job` to completion and return a short summary of what it did.
+pub async fn run(context: &Context, job: &maintenance_job::Model) -> Result<String, MegaError> {
+    match job.task {
+        MaintenanceTask::Repack => repack(context).await,
+        MaintenanceTask::CommitGraph => commit_graph(context).await,
+        MaintenanceTask::Bitmap => bitmap(context).await,
+        MaintenanceTask::LfsGc => lfs_gc(context).await,
+        MaintenanceTask::StaleCleanup => stale_cleanup(context).await,
+        MaintenanceTask::Verify => verify::run(context, job).await,
+        MaintenanceTask::BranchCleanup => branch_cleanup::run(context, job).await,
+        MaintenanceTask::SubtreeSplit => subtree_split::run(context, job).await,
+        MaintenanceTask::RepoPurge => repo_purge(context).await,
+        MaintenanceTask::ObjectGc => object_gc::run(context, job).await,
+        MaintenanceTask::GrantExpiry => grant_expiry(context).await,
+        MaintenanceTask::IdpSync => idp_sync::run(context).await,
+    }
+}
+
+fn mono_repo(context: &Context, path: &str) -> MonoRepo {
+    MonoRepo {
+        context: context.clone(),
+        path: PathBuf::from(path),
+        from_hash: String::new(),
+        to_hash: String::new(),
+    }
+}
+
+/// Prebuild the full clone pack of every default branch tip and drop packs of old tips.
+async fn repack(context: &Context) -> Result<String, MegaError> {
+    let dir = &context.config.maintenance.pack_cache_path;
+    fs::create_dir_all(dir)?;
+    let refs = context.services.mono_storage.get_default_refs().await?;
+    let tips: HashSet<String> = refs.iter().map(|r| r.ref_commit_hash.clone()).collect();
+
+    let mut built = 0;
+    for r in refs {
+        if build_pack(context, &r).await? {
+            built += 1;
+        }
+    }
+    let removed = cache::remove_stale(dir, &tips)?;
+    Ok(format!(
+        "built {} packs, removed {} stale files",
+        built, removed
+    ))
+}
+
+/// Prebuild the full clone pack of the tip of `r`, returns whether it was missing.
+pub(super) async fn build_pack(context: &Context, r: &mega_refs::Model) -> Result<bool, MegaError> {
+    let path = cache::pack_path(
+        &context.config.maintenance.pack_cache_path,
+        &r.ref_commit_hash,
+    );
+    if path.exists() {
+        return Ok(false);
+    }
+    let stream = mono_repo(context, &r.path)
+        .full_pack(vec![r.ref_commit_hash.clone()])
+        .await
+        .map_err(|err| MegaError::with_message(&err.to_string()))?;
+    cache::write_pack(&path, stream).await?;
+    Ok(true)
+}
+
+/// Index the objects reachable from every prebuilt pack, used to skip tree walks for `have` commits.
+async fn bitmap(context: &Context) -> Result<String, MegaError> {
+    let refs = context.services.mono_storage.get_default_refs().await?;
+
+    let mut built = 0;
+    for r in refs {
+        if build_reachable(context, &r).await? {
+            built += 1;
+        }
+    }
+    Ok(format!("built {} reachability indexes", built))
+}
+
+/// Index the objects reachable from the prebuilt pack of `r`, returns whether the pack is there
+/// and had no index yet.
+pub(super) async fn build_reachable(
+    context: &Context,
+    r: &mega_refs::Model,
+) -> Result<bool, MegaError> {
+    let dir = &context.config.maintenance.pack_cache_path;
+    let path = cache::reach_path(dir, &r.ref_commit_hash);
+    if path.exists() || !cache::pack_path(dir, &r.ref_commit_hash).exists() {
+        return Ok(false);
+    }
+    let storage = &context.services.mono_storage;
+    let Some(tree) = storage.get_tree_by_hash(&r.ref_tree_hash).await? else {
+        return Ok(false);
+    };
+    let tree: Tree = tree.into();
+    let mut objects = HashSet::new();
+    mono_repo(context, &r.path)
+        .traverse(vec![tree], &mut objects, None)
+        .await;
+    cache::write_reachable(&path, &objects)?;
+    Ok(true)
+}
+
+/// Store the generation number of every commit which does not have one yet.
+async fn commit_graph(context: &Context) -> Result<String, MegaError> {
+    let count = update_generations(context, false).await?;
+    Ok(format!("computed generation numbers of {} commits", count))
+}
+
+/// Compute the generation numbers of the commits which do not have one yet, and store them
+/// unless `dry_run` is set. Returns how many there are.
+pub(super) async fn update_generations(
+    context: &Context,
+    dry_run: bool,
+) -> Result<usize, MegaError> {
+    let storage = &context.services.maintenance_storage;
+    let parents: HashMap<String, Vec<String>> = context
+        .services
+        .mono_storage
+        .get_commit_parents()
+        .await?
+        .into_iter()
+        .collect();
+    let known = storage.get_commit_generations().await?;
+    let generations = compute_generations(&parents, known);
+    let count = generations.len();
+    if !dry_run {
+        storage.save_commit_generations(generations).await?;
+    }
+    Ok(count)
+}
+
+/// Generation numbers as defined by git's commit-graph: root commits are `1`, any other
+/// commit is one more than its highest parent. Parents which are not in `parents` count as `0`.
+///
+/// Only commits missing from `known` are returned.
+fn compute_generations(
+    parents: &HashMap<String, Vec<String>>,
+    mut known: HashMap<String, i64>,
+) -> Vec<(String, i64)> {
+    let mut computed = Vec::new();
+    for commit in parents.keys() {
+        // iterative post-order walk, histories are far too deep for recursion
+        let mut stack = vec![(commit.clone(), false)];
+        while let Some((id, parents_done)) = stack.pop() {
+            if known.contains_key(&id) {
+                continue;
+            }
+            let commit_parents = parents.get(&id).map(Vec::as_slice).unwrap_or_default();
+            if parents_done {
+                let generation = 1 + commit_parents
+                    .iter()
+                    .filter_map(|p| known.get(p))
+                    .max()
+                    .copied()
+                    .unwrap_or(0);
+                known.insert(id.clone(), generation);
+                computed.push((id, generation));
+            } else {
+                stack.push((id, true));
+                for p in commit_parents {
+                    if !known.contains_key(p) && parents.contains_key(p) {
+                        stack.push((p.clone(), false));
+                    }
+                }
+            }
+        }
+    }
+    computed
+}
+
+/// Remove stored LFS objects which are not referenced by the database any more.
+async fn lfs_gc(context: &Context) -> Result<String, MegaError> {
+    let removed = remove_lfs_objects(context, false).await?;
+    Ok(format!("removed {} unreferenced lfs objects", removed))
+}
+
+/// Remove the stored LFS objects which are not referenced by the database, or only count them
+/// if `dry_run` is set.
+///
+/// Release assets share the object storage, their objects are kept as well.
+pub(super) async fn remove_lfs_objects(
+    context: &Context,
+    dry_run: bool,
+) -> Result<usize, MegaError> {
+    let mut known = context.services.lfs_db_storage.list_lfs_oids().await?;
+    known.extend(context.services.release_storage.list_asset_oids().await?);
+    let storage = &context.services.lfs_storage;
+    let threshold = SystemTime::now() - GRACE_PERIOD;
+
+    let mut removed = 0;
+    for (oid, modified) in storage.list_objects()? {
+        // content is stored before its record is saved, keep recent objects of running uploads
+        if modified < threshold && !known.contains(&oid) {
+            if !dry_run {
+                storage.delete_object(&oid)?;
+            }
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Remove expired redirects, old job history and files left behind in the pack decode cache.
+async fn stale_cleanup(context: &Context) -> Result<String, MegaError> {
+    let redirects = context
+        .services
+        .mono_storage
+        .delete_expired_redirects()
+        .await?;
+    let retention =
+        chrono::Duration::days(context.config.maintenance.history_retention_days as i64);
+    let jobs = context
+        .services
+        .maintenance_storage
+        .delete_jobs_before(chrono::Utc::now().naive_utc() - retention)
+        .await?;
+    let files = remove_old_entries(&context.config.pack.pack_decode_cache_path)?;
+    Ok(format!(
+        "removed {} expired redirects, {} old jobs, {} decode cache files",
+        redirects, jobs, files
+    ))
+}
+
+/// Permanently remove the repositories which have been in the trash for longer than the
+/// retention period.
+async fn repo_purge(context: &Context) -> Result<String, MegaError> {
+    let storage = &context.services.git_db_storage;
+    let retention = chrono::Duration::days(context.config.maintenance.repo_retention_days as i64);
+    let repos = storage
+        .get_git_repos_deleted_before(chrono::Utc::now().naive_utc() - retention)
+        .await?;
+    let count = repos.len();
+    for repo in repos {
+        storage.purge_git_repo(repo.id).await?;
+        context
+            .services
+            .quota_storage
+            .delete_usage(&repo.repo_path)
+            .await?;
+        tracing::info!("purged repository {}", repo.repo_path);
+    }
+    Ok(format!("purged {} deleted repositories", count))
+}
+
+/// Remove expired temporary role assignments and put the remaining policies in use. Expired
+/// assignments already stop applying before, their policies check the time of the request.
+async fn grant_expiry(context: &Context) -> Result<String, MegaError> {
+    let storage = &context.services.policy_storage;
+    let removed = storage.delete_expired_role_assignments().await?;
+    if removed > 0 {
+        context
+            .policies
+            .set_namespaces(storage.current_policies().await?)
+            .map_err(|err| MegaError::with_message(&err.to_string()))?;
+    }
+    Ok(format!("removed {} expired role assignments", removed))
+}
+
+fn remove_old_entries(dir: &Path) -> Result<usize, MegaError> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let threshold = SystemTime::now() - GRACE_PERIOD;
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.modified()? >= threshold {
+            continue;
+        }
+        if metadata.is_dir() {
+            fs::remove_dir_all(entry.path())?;
+        } else {
+            fs::remove_file(entry.path())?;
+        }
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::compute_generations;
+
+    #[test]
+    fn test_compute_generations() {
+        // a <- b <- d, a <- c <- d, e is a parent which is not stored
+        let parents: HashMap<String, Vec<String>> = [
+            ("a", vec![]),
+            ("b", vec!["a"]),
+            ("c", vec!["a", "e"]),
+            ("d", vec!["b", "c"]),
+            ("f", vec!["d"]),
+        ]
+        .into_iter()
+        .map(|(id, ps)| (id.to_owned(), ps.into_iter().map(str::to_owned).collect()))
+        .collect();
+
+        let mut res: HashMap<String, i64> = compute_generations(&parents, HashMap::new())
+            .into_iter()
+            .collect();
+        assert_eq!(res.len(), 5);
+        assert_eq!(res["a"], 1);
+        assert_eq!(res["c"], 2);
+        assert_eq!(res["d"], 3);
+        assert_eq!(res["f"], 4);
+
+        // only new commits are computed
+        res.remove("f");
+        let res = compute_generations(&parents, res);
+        assert_eq!(res, vec![("f".to_owned(), 4)]);
+    }
+}
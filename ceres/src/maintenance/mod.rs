@@ -0,0 +1,212 @@
+//! Scheduler for the background maintenance jobs.
+//!
+//! Every [`MaintenanceTask`] has a cron schedule in [`MaintenanceConfig`], runs are recorded
+//! in the job history so that they can be inspected through the api, and the same task never
+//! runs twice at the same time no matter whether it was started by the schedule or manually.
+//! Tasks can also be queued in the background job queue as [`MAINTENANCE_JOB`], they are then
+//! run by the worker pool of any instance.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use sea_orm::Iterable;
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::{JobStatus, JobTrigger, MaintenanceTask};
+use callisto::maintenance_job;
+use common::config::MaintenanceConfig;
+use common::cron::CronSchedule;
+use common::errors::MegaError;
+use jupiter::context::Context;
+use jupiter::worker::JobHandler;
+
+pub mod branch_cleanup;
+pub mod gc;
+pub mod idp_sync;
+pub mod jobs;
+pub mod mode;
+pub mod object_gc;
+pub mod subtree_split;
+pub mod verify;
+
+#[derive(Clone)]
+pub struct Scheduler {
+    pub context: Context,
+}
+
+impl Scheduler {
+    pub fn new(context: Context) -> Self {
+        Scheduler { context }
+    }
+
+    /// The configured cron expression of `task`, empty if the schedule is disabled.
+    pub fn schedule_expr(config: &MaintenanceConfig, task: MaintenanceTask) -> &str {
+        match task {
+            MaintenanceTask::Repack => &config.repack,
+            MaintenanceTask::CommitGraph => &config.commit_graph,
+            MaintenanceTask::Bitmap => &config.bitmap,
+            MaintenanceTask::LfsGc => &config.lfs_gc,
+            MaintenanceTask::StaleCleanup => &config.stale_cleanup,
+            MaintenanceTask::Verify => &config.verify,
+            MaintenanceTask::BranchCleanup => &config.branch_cleanup,
+            MaintenanceTask::SubtreeSplit => &config.subtree_split,
+            MaintenanceTask::RepoPurge => &config.repo_purge,
+            MaintenanceTask::ObjectGc => &config.object_gc,
+            MaintenanceTask::GrantExpiry => &config.grant_expiry,
+            MaintenanceTask::IdpSync => &config.idp_sync,
+        }
+        .trim()
+    }
+
+    /// Parse the schedules of all tasks, disabled and invalid schedules are `None`.
+    pub fn schedules(config: &MaintenanceConfig) -> Vec<(MaintenanceTask, Option<CronSchedule>)> {
+        MaintenanceTask::iter()
+            .map(|task| {
+                let expr = Self::schedule_expr(config, task);
+                if expr.is_empty() {
+                    return (task, None);
+                }
+                match expr.parse() {
+                    Ok(schedule) => (task, Some(schedule)),
+                    Err(err) => {
+                        tracing::error!("invalid schedule for maintenance job {}: {}", task, err);
+                        (task, None)
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Start `task` in the background and return its job record, or `None` if the task is
+    /// already running.
+    ///
+    /// `target` is the repository path for tasks which work on a single repository.
+    pub async fn trigger(
+        &self,
+        task: MaintenanceTask,
+        triggered_by: JobTrigger,
+        operator: Option<String>,
+        target: Option<String>,
+    ) -> Result<Option<maintenance_job::Model>, MegaError> {
+        let storage = self.context.services.maintenance_storage.clone();
+        let Some(job) = storage
+            .start_job(task, triggered_by, operator, target)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let context = self.context.clone();
+        let record = job.clone();
+        tokio::spawn(async move { execute(&context, record).await });
+        Ok(Some(job))
+    }
+
+    /// Run the scheduled jobs until the service stops, schedules are checked once a minute.
+    pub async fn start(self) {
+        let storage = self.context.services.maintenance_storage.clone();
+        if let Err(err) = storage.fail_running_jobs().await {
+            tracing::error!("failed to reset interrupted maintenance jobs: {}", err);
+        }
+        let schedules = Self::schedules(&self.context.config.maintenance);
+        let last_runs = storage.last_runs().await.unwrap_or_default();
+        let now = chrono::Utc::now().naive_utc();
+        // continue from the last run, so a run missed while the service was down happens now
+        let mut next_runs: HashMap<MaintenanceTask, NaiveDateTime> = schedules
+            .iter()
+            .filter_map(|(task, schedule)| {
+                let from = last_runs.get(task).copied().unwrap_or(now);
+                Some((*task, schedule.as_ref()?.next_after(from)?))
+            })
+            .collect();
+
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            let now = chrono::Utc::now().naive_utc();
+            for (task, schedule) in &schedules {
+                let Some(schedule) = schedule else {
+                    continue;
+                };
+                if !next_runs.get(task).is_some_and(|next| *next <= now) {
+                    continue;
+                }
+                match self.trigger(*task, JobTrigger::Schedule, None, None).await {
+                    Ok(Some(_)) => (),
+                    Ok(None) => {
+                        tracing::info!("maintenance job {} is still running, skipped", task)
+                    }
+                    Err(err) => {
+                        tracing::error!("failed to start maintenance job {}: {}", task, err)
+                    }
+                }
+                match schedule.next_after(now) {
+                    Some(next) => next_runs.insert(*task, next),
+                    None => next_runs.remove(task),
+                };
+            }
+        }
+    }
+}
+
+/// Run the task of the started `job` and record how it went.
+async fn execute(context: &Context, job: maintenance_job::Model) -> (JobStatus, String) {
+    let task = job.task;
+    tracing::info!("maintenance job {} started", task);
+    let (status, message) = match jobs::run(context, &job).await {
+        Ok(message) => (JobStatus::Succeeded, message),
+        Err(err) => (JobStatus::Failed, err.to_string()),
+    };
+    tracing::info!("maintenance job {} {}: {}", task, status, message);
+    let storage = &context.services.maintenance_storage;
+    if let Err(err) = storage.finish_job(job, status, message.clone()).await {
+        tracing::error!("failed to record maintenance job {}: {}", task, err);
+    }
+    (status, message)
+}
+
+/// Background job type of maintenance tasks, run by [`MaintenanceJob`].
+pub const MAINTENANCE_JOB: &str = "maintenance";
+
+/// Payload of a queued maintenance task.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MaintenancePayload {
+    pub task: MaintenanceTask,
+    /// Repository path for tasks which work on a single repository
+    pub target: Option<String>,
+    /// User who queued the task, `None` if it was queued by the service itself
+    pub operator: Option<String>,
+}
+
+/// Runs queued maintenance tasks, they are recorded in the job history like tasks started by
+/// the scheduler.
+pub struct MaintenanceJob;
+
+#[async_trait]
+impl JobHandler for MaintenanceJob {
+    async fn run(&self, context: &Context, payload: &str) -> Result<(), MegaError> {
+        let payload: MaintenancePayload = serde_json::from_str(payload)
+            .map_err(|err| MegaError::with_message(&err.to_string()))?;
+        let triggered_by = match payload.operator {
+            Some(_) => JobTrigger::Manual,
+            None => JobTrigger::Schedule,
+        };
+        let Some(job) = context
+            .services
+            .maintenance_storage
+            .start_job(payload.task, triggered_by, payload.operator, payload.target)
+            .await?
+        else {
+            // retried once the running one is done
+            return Err(MegaError::with_message(&format!(
+                "maintenance job {} is already running",
+                payload.task
+            )));
+        };
+        match execute(context, job).await {
+            (JobStatus::Failed, message) => Err(MegaError::with_message(&message)),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+//! Maintenance mode, which keeps the instance readable but rejects writes while it is migrated
+//! or backed up.
+//!
+//! The mode is stored in the database, so it applies to every instance at once and survives
+//! restarts. While it is on, pushes, uploads of LFS objects and every change made through the
+//! api are rejected with its message, clones, fetches and reads keep working. The maintenance
+//! api stays available, so admins can end the mode.
+
+use chrono::NaiveDateTime;
+
+use common::errors::MegaError;
+use jupiter::context::Context;
+
+#[derive(Debug, Clone)]
+pub struct MaintenanceMode {
+    /// Told to clients whose writes are rejected
+    pub message: String,
+    pub enabled_by: String,
+    pub enabled_at: NaiveDateTime,
+}
+
+/// The maintenance mode of the instance, `None` if writes are allowed.
+///
+/// Writes are allowed if the mode cannot be read, a database which fails rejects them anyway.
+pub async fn current(context: &Context) -> Option<MaintenanceMode> {
+    match context.services.maintenance_storage.get_mode().await {
+        Ok(mode) => mode.map(|m| MaintenanceMode {
+            message: m
+                .message
+                .filter(|m| !m.is_empty())
+                .unwrap_or_else(|| context.config.maintenance.mode_message.clone()),
+            enabled_by: m.enabled_by,
+            enabled_at: m.enabled_at,
+        }),
+        Err(err) => {
+            tracing::error!("failed to read the maintenance mode: {}", err);
+            None
+        }
+    }
+}
+
+/// Reject writes with `message`, the configured message if it is `None`.
+pub async fn enable(
+    context: &Context,
+    message: Option<String>,
+    operator: &str,
+) -> Result<(), MegaError> {
+    context
+        .services
+        .maintenance_storage
+        .enable_mode(message, operator)
+        .await?;
+    tracing::warn!(
+        "maintenance mode enabled by {}, writes are rejected",
+        operator
+    );
+    Ok(())
+}
+
+/// Allow writes again, returns whether the instance was in maintenance mode.
+pub async fn disable(context: &Context, operator: &str) -> Result<bool, MegaError> {
+    let disabled = context.services.maintenance_storage.disable_mode().await?;
+    if disabled {
+        tracing::warn!("maintenance mode disabled by {}", operator);
+    }
+    Ok(disabled)
+}
@@ -0,0 +1,440 @@
+//! Garbage collection of objects no ref can reach.
+//!
+//! Force pushes, deleted branches and abandoned merge requests leave commits, trees and blobs
+//! behind which nothing points to anymore. Everything reachable from the refs of the monorepo
+//! and of the import repositories is marked, together with the commits kept by merge requests
+//! which are not merged, releases, subtree splits and pushes quarantined by secret scanning.
+//! Objects which are not marked and were saved more than `maintenance.object_gc_grace_days` ago
+//! are unreachable, the grace period protects the objects of pushes whose refs are not updated
+//! yet.
+//!
+//! Unreachable objects are only reported unless `maintenance.prune_unreachable_objects` is set.
+//! Repositories in the trash are left alone, they are removed as a whole when purged.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use callisto::maintenance_job;
+use common::errors::MegaError;
+use common::utils::ZERO_ID;
+use jupiter::context::Context;
+use jupiter::storage::ObjectIds;
+use mercury::internal::object::tree::TreeItemMode;
+use mercury::internal::object::types::ObjectType;
+use sea_orm::prelude::Json;
+
+use crate::maintenance::verify::decode_tree;
+
+/// Objects loaded from the database in one query.
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Clone, Copy)]
+pub(super) enum Source {
+    Mono,
+    Import(i64),
+}
+
+/// The objects of one repository reachable from its roots.
+struct Marker<'a> {
+    context: &'a Context,
+    source: Source,
+    reachable: HashSet<String>,
+}
+
+/// Unreachable objects of all repositories checked by a run.
+#[derive(Default)]
+pub(super) struct Collected {
+    pub repos: usize,
+    pub commits: usize,
+    pub trees: usize,
+    pub blobs: usize,
+    pub tags: usize,
+}
+
+impl Collected {
+    pub fn add(&mut self, ids: &ObjectIds) {
+        self.repos += usize::from(!ids.is_empty());
+        self.commits += ids.commits.len();
+        self.trees += ids.trees.len();
+        self.blobs += ids.blobs.len();
+        self.tags += ids.tags.len();
+    }
+}
+
+/// Collect the unreachable objects of the job target, the monorepo and every import repository
+/// if it has none.
+pub async fn run(context: &Context, job: &maintenance_job::Model) -> Result<String, MegaError> {
+    let before = grace_cutoff(context);
+    let prune = context.config.maintenance.prune_unreachable_objects;
+    let import_dir = &context.config.monorepo.import_dir;
+    let target = job.target.as_deref();
+
+    let mut collected = Collected::default();
+    let mut checked = 0;
+    if target.is_none_or(|t| !Path::new(t).starts_with(import_dir)) {
+        let ids = collect(context, Source::Mono, "/", before, prune).await?;
+        collected.add(&ids);
+        checked += 1;
+    }
+    let storage = &context.services.git_db_storage;
+    let repos = match target {
+        Some(t) if Path::new(t).starts_with(import_dir) => storage
+            .find_git_repo_exact_match(t)
+            .await?
+            .map(|repo| vec![repo])
+            .ok_or_else(|| MegaError::with_message(&format!("repository {} not found", t)))?,
+        Some(_) => Vec::new(),
+        None => storage.list_git_repos().await?,
+    };
+    for repo in repos {
+        let source = Source::Import(repo.id);
+        let ids = collect(context, source, &repo.repo_path, before, prune).await?;
+        collected.add(&ids);
+        checked += 1;
+    }
+
+    Ok(format!(
+        "{} {} unreachable commits, {} trees, {} blobs and {} tags in {} of {} repositories",
+        if prune { "removed" } else { "found" },
+        collected.commits,
+        collected.trees,
+        collected.blobs,
+        collected.tags,
+        collected.repos,
+        checked
+    ))
+}
+
+/// Objects saved before this time are old enough to be collected.
+pub(super) fn grace_cutoff(context: &Context) -> chrono::NaiveDateTime {
+    let days = context.config.maintenance.object_gc_grace_days as i64;
+    chrono::Utc::now().naive_utc() - chrono::Duration::days(days)
+}
+
+/// Find the unreachable objects of one repository and delete them if `prune` is set.
+pub(super) async fn collect(
+    context: &Context,
+    source: Source,
+    path: &str,
+    before: chrono::NaiveDateTime,
+    prune: bool,
+) -> Result<ObjectIds, MegaError> {
+    let (commits, trees) = roots(context, source, path).await?;
+    let mut marker = Marker {
+        context,
+        source,
+        reachable: HashSet::new(),
+    };
+    marker.mark(commits, trees).await?;
+
+    let services = &context.services;
+    let mut ids = match source {
+        Source::Mono => services.mono_storage.get_object_ids_before(before).await?,
+        Source::Import(repo_id) => {
+            services
+                .git_db_storage
+                .get_object_ids_before(repo_id, before)
+                .await?
+        }
+    };
+    ids.retain(|id| !marker.reachable.contains(id));
+    if ids.is_empty() {
+        return Ok(ids);
+    }
+    tracing::info!(
+        "{} {} unreachable objects of {}",
+        if prune { "remove" } else { "found" },
+        ids.len(),
+        path
+    );
+    if prune {
+        match source {
+            Source::Mono => services.mono_storage.delete_objects(&ids, before).await?,
+            Source::Import(repo_id) => {
+                services
+                    .git_db_storage
+                    .delete_objects(repo_id, &ids)
+                    .await?
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// The commits or tags and the trees everything reachable is found from.
+async fn roots(
+    context: &Context,
+    source: Source,
+    path: &str,
+) -> Result<(Vec<String>, Vec<String>), MegaError> {
+    let services = &context.services;
+    let mut commits = Vec::new();
+    let mut trees = Vec::new();
+    match source {
+        Source::Mono => {
+            for r in services.mono_storage.get_all_refs().await? {
+                commits.push(r.ref_commit_hash);
+                trees.push(r.ref_tree_hash);
+            }
+            for mr in context.mr_stg().get_unmerged_mrs().await? {
+                commits.push(mr.from_hash);
+                commits.push(mr.to_hash);
+            }
+            for split in services.mono_storage.list_splits().await? {
+                commits.extend(split.last_commit);
+            }
+        }
+        Source::Import(repo_id) => {
+            for r in services.git_db_storage.get_ref(repo_id).await? {
+                commits.push(r.ref_git_id);
+            }
+            for split in services.mono_storage.list_splits().await? {
+                if split.repo_path == path {
+                    commits.extend(split.last_split);
+                }
+            }
+        }
+    }
+    let import_paths = match source {
+        Source::Mono => services
+            .git_db_storage
+            .list_git_repos()
+            .await?
+            .into_iter()
+            .map(|repo| repo.repo_path)
+            .collect(),
+        Source::Import(_) => HashSet::new(),
+    };
+    for release in services.release_storage.list_all_releases().await? {
+        if owns_release(source, path, &release.path, &import_paths) {
+            commits.push(release.commit_id);
+        }
+    }
+    // a quarantined push is stored until it is released
+    for push in services.secret_storage.list_held_pushes().await? {
+        if owns_release(source, path, &push.path, &import_paths) {
+            let updates: Vec<[String; 3]> =
+                serde_json::from_str(&push.ref_updates).unwrap_or_default();
+            commits.extend(
+                updates
+                    .into_iter()
+                    .map(|[_, new_id, _]| new_id)
+                    .filter(|id| id != ZERO_ID),
+            );
+        }
+    }
+    Ok((commits, trees))
+}
+
+/// Whether a release or a held push at `release_path` is of the repository at `path`, they are
+/// of the monorepo unless an import repository is at their path.
+fn owns_release(
+    source: Source,
+    path: &str,
+    release_path: &str,
+    import_paths: &HashSet<String>,
+) -> bool {
+    match source {
+        Source::Mono => !import_paths.contains(release_path),
+        Source::Import(_) => release_path == path,
+    }
+}
+
+impl Marker<'_> {
+    async fn mark(&mut self, roots: Vec<String>, mut trees: Vec<String>) -> Result<(), MegaError> {
+        let (commits, tagged_trees) = self.mark_tags(roots).await?;
+        trees.extend(tagged_trees);
+        trees.extend(self.mark_commits(commits).await?);
+        self.mark_trees(trees).await
+    }
+
+    /// Mark the annotated tags among `ids`, returns the commits and trees they point to
+    /// together with the ids which are no tags.
+    async fn mark_tags(
+        &mut self,
+        ids: Vec<String>,
+    ) -> Result<(Vec<String>, Vec<String>), MegaError> {
+        let mut commits = Vec::new();
+        let mut trees = Vec::new();
+        let mut pending = ids;
+        // tags may point to other tags
+        while !pending.is_empty() {
+            let mut tags = self.load_tags(&pending).await?;
+            for id in std::mem::take(&mut pending) {
+                let Some((object_id, object_type)) = tags.remove(&id) else {
+                    commits.push(id);
+                    continue;
+                };
+                self.reachable.insert(id);
+                match ObjectType::from_string(&object_type) {
+                    Ok(ObjectType::Tag) => pending.push(object_id),
+                    Ok(ObjectType::Tree) => trees.push(object_id),
+                    Ok(ObjectType::Blob) => {
+                        self.reachable.insert(object_id);
+                    }
+                    _ => commits.push(object_id),
+                }
+            }
+        }
+        Ok((commits, trees))
+    }
+
+    /// Object ids and types pointed to by the tags among `ids`.
+    async fn load_tags(
+        &self,
+        ids: &[String],
+    ) -> Result<HashMap<String, (String, String)>, MegaError> {
+        let services = &self.context.services;
+        Ok(match self.source {
+            Source::Mono => services
+                .mono_storage
+                .get_tags_by_hashes(ids.to_vec())
+                .await?
+                .into_iter()
+                .map(|t| (t.tag_id, (t.object_id, t.object_type)))
+                .collect(),
+            Source::Import(repo_id) => services
+                .git_db_storage
+                .get_tags_by_repo_id(repo_id)
+                .await?
+                .into_iter()
+                .filter(|t| ids.contains(&t.tag_id))
+                .map(|t| (t.tag_id, (t.object_id, t.object_type)))
+                .collect(),
+        })
+    }
+
+    /// Mark the whole history of `tips`, returns the root trees of all commits.
+    async fn mark_commits(&mut self, tips: Vec<String>) -> Result<Vec<String>, MegaError> {
+        let mut trees = Vec::new();
+        let mut pending = tips;
+        while !pending.is_empty() {
+            let batch: Vec<String> = pending
+                .drain(..pending.len().min(BATCH_SIZE))
+                .filter(|id| self.reachable.insert(id.clone()))
+                .collect();
+            for (tree, parents) in self.load_commits(&batch).await? {
+                trees.push(tree);
+                pending.extend(parents);
+            }
+        }
+        Ok(trees)
+    }
+
+    /// Tree and parent ids of the stored commits among `ids`.
+    async fn load_commits(
+        &self,
+        ids: &Vec<String>,
+    ) -> Result<Vec<(String, Vec<String>)>, MegaError> {
+        let services = &self.context.services;
+        Ok(match self.source {
+            Source::Mono => services
+                .mono_storage
+                .get_commits_by_hashes(ids)
+                .await?
+                .into_iter()
+                .map(|c| (c.tree, parent_ids(&c.parents_id)))
+                .collect(),
+            Source::Import(repo_id) => services
+                .git_db_storage
+                .get_commits_by_hashes(repo_id, ids)
+                .await?
+                .into_iter()
+                .map(|c| (c.tree, parent_ids(&c.parents_id)))
+                .collect(),
+        })
+    }
+
+    async fn mark_trees(&mut self, roots: Vec<String>) -> Result<(), MegaError> {
+        let mut pending = roots;
+        while !pending.is_empty() {
+            let batch: Vec<String> = pending
+                .drain(..pending.len().min(BATCH_SIZE))
+                .filter(|id| self.reachable.insert(id.clone()))
+                .collect();
+            for (id, data) in self.load_trees(batch).await? {
+                // the content of a broken tree is unknown, nothing below it may be removed
+                let tree = decode_tree(&data, &id).ok_or_else(|| {
+                    MegaError::with_message(&format!(
+                        "tree {} can not be decoded, run the verify task",
+                        id
+                    ))
+                })?;
+                for item in tree.tree_items {
+                    match item.mode {
+                        TreeItemMode::Tree => pending.push(item.id.to_string()),
+                        // submodules point to commits of other repositories
+                        TreeItemMode::Commit => (),
+                        _ => {
+                            self.reachable.insert(item.id.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Encoded content of the stored trees among `ids`.
+    async fn load_trees(&self, ids: Vec<String>) -> Result<Vec<(String, Vec<u8>)>, MegaError> {
+        let services = &self.context.services;
+        Ok(match self.source {
+            Source::Mono => services
+                .mono_storage
+                .get_trees_by_hashes(ids)
+                .await?
+                .into_iter()
+                .map(|t| (t.tree_id, t.sub_trees))
+                .collect(),
+            Source::Import(repo_id) => services
+                .git_db_storage
+                .get_trees_by_hashes(repo_id, ids)
+                .await?
+                .into_iter()
+                .map(|t| (t.tree_id, t.sub_trees))
+                .collect(),
+        })
+    }
+}
+
+fn parent_ids(parents: &Json) -> Vec<String> {
+    parents
+        .as_array()
+        .map(|ids| {
+            ids.iter()
+                .filter_map(|id| id.as_str().map(str::to_owned))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use std::collections::HashSet;
+
+    use super::{owns_release, parent_ids, Source};
+
+    #[test]
+    fn test_parent_ids() {
+        assert_eq!(
+            parent_ids(&json!(["a", "b"])),
+            vec!["a".to_owned(), "b".to_owned()]
+        );
+        assert!(parent_ids(&json!([])).is_empty());
+        assert!(parent_ids(&json!(null)).is_empty());
+    }
+
+    #[test]
+    fn test_owns_release() {
+        let lib = "/third-party/lib";
+        let imports = HashSet::from([lib.to_owned()]);
+        assert!(owns_release(Source::Mono, "/", "/project/app", &imports));
+        assert!(!owns_release(Source::Mono, "/", lib, &imports));
+
+        let none = HashSet::new();
+        assert!(owns_release(Source::Import(1), lib, lib, &none));
+        assert!(!owns_release(Source::Import(1), lib, "/project/app", &none));
+    }
+}
@@ -0,0 +1,277 @@
+//! Split of monorepo directories into standalone repositories.
+//!
+//! Like `git subtree split`, the history of the directory is translated by [`crate::subtree`]
+//! into the history of the split repository. The split repository is an import repository below
+//! `monorepo.import_dir`, it can be cloned and fetched like any other repository but refuses
+//! pushes.
+//!
+//! Splits are incremental, the last processed monorepo commit is recorded and later runs only
+//! rewrite the commits made since. The monorepo history is followed along first parents.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use callisto::db_enums::RefType;
+use callisto::{git_blob, git_commit, git_tree, import_refs, maintenance_job, subtree_split};
+use common::errors::MegaError;
+use common::utils::{generate_id, MEGA_BRANCH_NAME};
+use jupiter::context::Context;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use sea_orm::ConnectionTrait;
+
+use crate::protocol::repo::Repo;
+use crate::subtree;
+
+/// Register the split of the monorepo directory `path` into a new repository at `repo_path`.
+///
+/// The split repository starts with the visibility of the directory, its history is written by
+/// the next run of the split.
+pub async fn create_split(
+    context: &Context,
+    path: &str,
+    repo_path: &str,
+    created_by: &str,
+) -> Result<subtree_split::Model, MegaError> {
+    let import_dir = &context.config.monorepo.import_dir;
+    if path == "/" || Path::new(path).starts_with(import_dir) {
+        return Err(MegaError::with_message(
+            "only directories of the monorepo can be split",
+        ));
+    }
+    if !Path::new(repo_path).starts_with(import_dir) || Path::new(repo_path) == import_dir {
+        return Err(MegaError::with_message(&format!(
+            "split repository must be below {}",
+            import_dir.display()
+        )));
+    }
+    let git_storage = &context.services.git_db_storage;
+    if git_storage
+        .find_git_repo_exact_match(repo_path)
+        .await?
+        .is_some()
+    {
+        return Err(MegaError::with_message("repository already exists"));
+    }
+    if git_storage
+        .find_deleted_git_repo(repo_path)
+        .await?
+        .is_some()
+    {
+        return Err(MegaError::with_message(
+            "a deleted repository is still in the trash at this path",
+        ));
+    }
+    let mono_storage = &context.services.mono_storage;
+    let split = mono_storage.save_split(path, repo_path, created_by).await?;
+    let repo = Repo::new(PathBuf::from(repo_path), false);
+    git_storage.save_git_repo(repo.into()).await?;
+    let visibility = mono_storage.get_visibility(Path::new(path)).await?;
+    mono_storage.save_visibility(repo_path, visibility).await?;
+    Ok(split)
+}
+
+/// Rewrite the monorepo commits made since the last run, returns the number of new commits.
+pub async fn run_split(context: &Context, split: subtree_split::Model) -> Result<usize, MegaError> {
+    let mono_storage = &context.services.mono_storage;
+    let git_storage = &context.services.git_db_storage;
+    let repo = git_storage
+        .find_git_repo_exact_match(&split.repo_path)
+        .await?
+        .ok_or_else(|| MegaError::with_message("split repository not found"))?;
+    let Some(head) = mono_storage.get_ref("/").await? else {
+        return Ok(0);
+    };
+    if split.last_commit.as_deref() == Some(head.ref_commit_hash.as_str()) {
+        return Ok(0);
+    }
+
+    let base = match &split.last_split {
+        Some(id) => git_storage
+            .get_commit_by_hash(repo.id, id)
+            .await?
+            .map(|c| (id.clone(), c.tree)),
+        None => None,
+    };
+    let commits = subtree::translate_history(
+        mono_storage,
+        Path::new(&split.path),
+        &head.ref_commit_hash,
+        split.last_commit.as_deref(),
+        base,
+        0,
+    )
+    .await?;
+
+    let mut last_split = split.last_split.clone();
+    let count = commits.len();
+    for commit in commits {
+        let commit_id = commit.id.to_string();
+        let tree = commit.tree_id.to_string();
+        let (trees, blobs) = collect_objects(context, repo.id, &tree, &commit_id).await?;
+        let commit_model: git_commit::Model = commit.into();
+        git_storage
+            .save_objects(
+                git_storage.get_connection(),
+                repo.id,
+                vec![commit_model],
+                trees,
+                blobs,
+            )
+            .await?;
+        last_split = Some(commit_id);
+    }
+
+    // objects saved above stay unreferenced if this fails, the next run writes them again
+    git_storage
+        .transaction(|txn| async move {
+            if let Some(id) = &last_split {
+                if split.last_split.as_ref() != Some(id) {
+                    update_branch(context, &*txn, repo.id, id).await?;
+                }
+            }
+            mono_storage
+                .update_split(&*txn, split, &head.ref_commit_hash, last_split)
+                .await
+        })
+        .await?;
+    Ok(count)
+}
+
+/// Records of the trees and blobs below `tree` which the split repository does not have yet.
+///
+/// The content is shared with the monorepo, only the object records are created.
+async fn collect_objects(
+    context: &Context,
+    repo_id: i64,
+    tree: &str,
+    commit_id: &str,
+) -> Result<(Vec<git_tree::Model>, Vec<git_blob::Model>), MegaError> {
+    let mono_storage = &context.services.mono_storage;
+    let git_storage = &context.services.git_db_storage;
+    let now = chrono::Utc::now().naive_utc();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut trees = Vec::new();
+    let mut blobs = Vec::new();
+    let mut pending = vec![tree.to_owned()];
+    while !pending.is_empty() {
+        let existing: HashSet<String> = git_storage
+            .get_trees_by_hashes(repo_id, pending.clone())
+            .await?
+            .into_iter()
+            .map(|t| t.tree_id)
+            .collect();
+        pending.retain(|id| !existing.contains(id) && seen.insert(id.clone()));
+        if pending.is_empty() {
+            break;
+        }
+        let mut next = Vec::new();
+        let mut blob_ids = HashSet::new();
+        let mut loaded = HashSet::new();
+        for model in mono_storage.get_trees_by_hashes(pending).await? {
+            if !loaded.insert(model.tree_id.clone()) {
+                continue;
+            }
+            for item in &Tree::from(model.clone()).tree_items {
+                match item.mode {
+                    TreeItemMode::Tree => next.push(item.id.to_string()),
+                    // submodules point to commits of other repositories
+                    TreeItemMode::Commit => {}
+                    _ => {
+                        blob_ids.insert(item.id.to_string());
+                    }
+                }
+            }
+            trees.push(git_tree::Model {
+                id: generate_id(),
+                repo_id,
+                tree_id: model.tree_id,
+                sub_trees: model.sub_trees,
+                size: model.size,
+                commit_id: commit_id.to_owned(),
+                created_at: now,
+            });
+        }
+        let existing: HashSet<String> = git_storage
+            .get_blobs_by_hashes(repo_id, blob_ids.iter().cloned().collect())
+            .await?
+            .into_iter()
+            .map(|b| b.blob_id)
+            .collect();
+        blobs.extend(
+            blob_ids
+                .into_iter()
+                .filter(|id| !existing.contains(id))
+                .map(|blob_id| git_blob::Model {
+                    id: generate_id(),
+                    repo_id,
+                    blob_id,
+                    name: None,
+                    size: 0,
+                    commit_id: commit_id.to_owned(),
+                    created_at: now,
+                }),
+        );
+        pending = next;
+    }
+    Ok((trees, blobs))
+}
+
+async fn update_branch(
+    context: &Context,
+    conn: &impl ConnectionTrait,
+    repo_id: i64,
+    commit_id: &str,
+) -> Result<(), MegaError> {
+    let git_storage = &context.services.git_db_storage;
+    let refs = git_storage.get_ref(repo_id).await?;
+    if let Some(r) = refs.iter().find(|r| r.ref_name == MEGA_BRANCH_NAME) {
+        return git_storage
+            .update_ref(conn, repo_id, MEGA_BRANCH_NAME, &r.ref_git_id, commit_id)
+            .await;
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let branch = import_refs::Model {
+        id: generate_id(),
+        repo_id,
+        ref_name: MEGA_BRANCH_NAME.to_owned(),
+        ref_git_id: commit_id.to_owned(),
+        ref_type: RefType::Branch,
+        default_branch: true,
+        created_at: now,
+        updated_at: now,
+    };
+    git_storage.save_ref(conn, repo_id, branch).await
+}
+
+/// Bring all splits of directories at or below the job target up to date.
+pub async fn run(context: &Context, job: &maintenance_job::Model) -> Result<String, MegaError> {
+    let splits = context.services.mono_storage.list_splits().await?;
+    let mut updated = 0;
+    let mut commits = 0;
+    let mut failed = 0;
+    for split in splits {
+        if !job
+            .target
+            .as_deref()
+            .is_none_or(|t| Path::new(&split.path).starts_with(t))
+        {
+            continue;
+        }
+        let path = split.path.clone();
+        match run_split(context, split).await {
+            Ok(0) => {}
+            Ok(count) => {
+                updated += 1;
+                commits += count;
+            }
+            Err(err) => {
+                tracing::error!("failed to split {}: {}", path, err);
+                failed += 1;
+            }
+        }
+    }
+    Ok(format!(
+        "wrote {} commits to {} split repositories, {} failed",
+        commits, updated, failed
+    ))
+}
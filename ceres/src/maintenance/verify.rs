@@ -0,0 +1,454 @@
+//! Integrity verification of a single repository.
+//!
+//! Starting from the refs of the repository every reachable commit, tree and blob is loaded,
+//! re-hashed and compared with its id. Blobs are also checked against the object store they
+//! live in, and blobs which are LFS pointers must have their content in the LFS storage.
+//! The result is stored as an integrity report of the job.
+
+use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::{RefType, StorageType};
+use callisto::maintenance_job;
+use common::errors::MegaError;
+use common::utils;
+use jupiter::context::Context;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+
+/// Objects loaded from the database in one query.
+const BATCH_SIZE: usize = 1000;
+/// Reports keep the first problems only, a broken store would otherwise produce huge reports.
+const MAX_PROBLEMS: usize = 1000;
+const LFS_POINTER_VERSION: &str = "version https://git-lfs.github.com/spec/v1";
+/// Pointer files are tiny, anything larger is regular content.
+const LFS_POINTER_MAX_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// A ref or another object points to an object which is not stored
+    MissingObject,
+    /// The stored content does not hash to the object id
+    HashMismatch,
+    /// The stored object can not be decoded
+    CorruptObject,
+    /// The object is recorded but its content is missing from the object store
+    MissingContent,
+    /// A blob is an LFS pointer but the LFS object is not stored
+    MissingLfsObject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    pub object_id: String,
+    pub detail: String,
+}
+
+enum Source {
+    Mono,
+    Import(i64),
+}
+
+struct Verifier<'a> {
+    context: &'a Context,
+    source: Source,
+    checked: i64,
+    problem_count: usize,
+    problems: Vec<Problem>,
+    seen: HashSet<String>,
+}
+
+/// Verify the repository at the target path of `job`, the whole monorepo if the job has none.
+pub async fn run(context: &Context, job: &maintenance_job::Model) -> Result<String, MegaError> {
+    let path = job.target.clone().unwrap_or_else(|| String::from("/"));
+    let mut verifier = Verifier::new(context, &path).await?;
+    verifier.verify(&path).await?;
+
+    let problems = serde_json::to_string(&verifier.problems)
+        .map_err(|err| MegaError::with_message(&err.to_string()))?;
+    context
+        .services
+        .maintenance_storage
+        .save_report(
+            job.id,
+            &path,
+            verifier.problem_count == 0,
+            verifier.checked,
+            problems,
+        )
+        .await?;
+    Ok(format!(
+        "checked {} objects of {}, found {} problems",
+        verifier.checked, path, verifier.problem_count
+    ))
+}
+
+impl<'a> Verifier<'a> {
+    async fn new(context: &'a Context, path: &str) -> Result<Verifier<'a>, MegaError> {
+        let import_dir = &context.config.monorepo.import_dir;
+        let source = if Path::new(path).starts_with(import_dir) {
+            let repo = context
+                .services
+                .git_db_storage
+                .find_git_repo_exact_match(path)
+                .await?
+                .ok_or_else(|| {
+                    MegaError::with_message(&format!("repository {} not found", path))
+                })?;
+            Source::Import(repo.id)
+        } else {
+            Source::Mono
+        };
+        Ok(Verifier {
+            context,
+            source,
+            checked: 0,
+            problem_count: 0,
+            problems: Vec::new(),
+            seen: HashSet::new(),
+        })
+    }
+
+    fn problem(&mut self, kind: ProblemKind, object_id: &str, detail: impl Into<String>) {
+        self.problem_count += 1;
+        if self.problems.len() < MAX_PROBLEMS {
+            self.problems.push(Problem {
+                kind,
+                object_id: object_id.to_owned(),
+                detail: detail.into(),
+            });
+        }
+    }
+
+    async fn verify(&mut self, path: &str) -> Result<(), MegaError> {
+        let storage = self.context.services.mono_storage.clone();
+        let commits = match self.source {
+            Source::Import(repo_id) => self
+                .context
+                .services
+                .git_db_storage
+                .get_ref(repo_id)
+                .await?
+                .into_iter()
+                // annotated tags point to tag objects, only branches are walked
+                .filter(|r| r.ref_type == RefType::Branch)
+                .map(|r| r.ref_git_id)
+                .collect(),
+            Source::Mono => {
+                let refs = storage.get_refs(path).await?;
+                if refs.is_empty() {
+                    // a directory of the monorepo without refs of its own, check its tree only
+                    let tree = self.find_mono_tree(path).await?;
+                    return self.verify_trees(vec![tree]).await;
+                }
+                refs.into_iter()
+                    // annotated tags point to tag objects, only branches are walked
+                    .filter(|r| !r.ref_name.starts_with(utils::TAG_REF_PREFIX))
+                    .map(|r| r.ref_commit_hash)
+                    .collect()
+            }
+        };
+        let trees = self.verify_commits(commits).await?;
+        self.verify_trees(trees).await
+    }
+
+    /// Walk down from the root tree to the tree of `path`.
+    async fn find_mono_tree(&self, path: &str) -> Result<String, MegaError> {
+        let storage = &self.context.services.mono_storage;
+        let not_found = || MegaError::with_message(&format!("path {} not found", path));
+        let root = storage.get_ref("/").await?.ok_or_else(not_found)?;
+        let mut tree_id = root.ref_tree_hash;
+        for component in Path::new(path).components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            let model = storage
+                .get_tree_by_hash(&tree_id)
+                .await?
+                .ok_or_else(not_found)?;
+            let tree = decode_tree(&model.sub_trees, &tree_id).ok_or_else(not_found)?;
+            tree_id = tree
+                .tree_items
+                .into_iter()
+                .find(|item| item.mode == TreeItemMode::Tree && item.name.as_str() == name)
+                .ok_or_else(not_found)?
+                .id
+                .to_string();
+        }
+        Ok(tree_id)
+    }
+
+    /// Check the whole history of `tips`, returns the root trees of all commits.
+    async fn verify_commits(&mut self, tips: Vec<String>) -> Result<Vec<String>, MegaError> {
+        let mut trees = Vec::new();
+        let mut pending = tips;
+        while !pending.is_empty() {
+            let batch: Vec<String> = pending
+                .drain(..pending.len().min(BATCH_SIZE))
+                .filter(|id| self.seen.insert(id.clone()))
+                .collect();
+            let mut loaded = self.load_commits(&batch).await?;
+            for id in batch {
+                self.checked += 1;
+                let Some(commit) = loaded.remove(&id) else {
+                    self.problem(ProblemKind::MissingObject, &id, "commit is not stored");
+                    continue;
+                };
+                let Some(commit) = commit else {
+                    self.problem(ProblemKind::CorruptObject, &id, "commit can not be decoded");
+                    continue;
+                };
+                match commit.to_data() {
+                    Ok(data) => {
+                        let hash = SHA1::from_type_and_data(ObjectType::Commit, &data);
+                        if hash.to_string() != id {
+                            self.problem(
+                                ProblemKind::HashMismatch,
+                                &id,
+                                format!("commit content hashes to {}", hash),
+                            );
+                        }
+                    }
+                    Err(err) => self.problem(ProblemKind::CorruptObject, &id, err.to_string()),
+                }
+                trees.push(commit.tree_id.to_string());
+                pending.extend(commit.parent_commit_ids.iter().map(SHA1::to_string));
+            }
+        }
+        Ok(trees)
+    }
+
+    /// Decoded commits by id, `None` for commits which are stored but can not be decoded.
+    async fn load_commits(
+        &self,
+        ids: &Vec<String>,
+    ) -> Result<HashMap<String, Option<Commit>>, MegaError> {
+        let services = &self.context.services;
+        // the model conversions unwrap every field, a corrupted row must not abort the job
+        Ok(match self.source {
+            Source::Mono => services
+                .mono_storage
+                .get_commits_by_hashes(ids)
+                .await?
+                .into_iter()
+                .map(|m| (m.commit_id.clone(), catch_decode(|| Commit::from(m))))
+                .collect(),
+            Source::Import(repo_id) => services
+                .git_db_storage
+                .get_commits_by_hashes(repo_id, ids)
+                .await?
+                .into_iter()
+                .map(|m| (m.commit_id.clone(), catch_decode(|| Commit::from(m))))
+                .collect(),
+        })
+    }
+
+    /// Encoded content of the stored trees by id.
+    async fn load_trees(&self, ids: Vec<String>) -> Result<HashMap<String, Vec<u8>>, MegaError> {
+        let services = &self.context.services;
+        Ok(match self.source {
+            Source::Mono => services
+                .mono_storage
+                .get_trees_by_hashes(ids)
+                .await?
+                .into_iter()
+                .map(|m| (m.tree_id, m.sub_trees))
+                .collect(),
+            Source::Import(repo_id) => services
+                .git_db_storage
+                .get_trees_by_hashes(repo_id, ids)
+                .await?
+                .into_iter()
+                .map(|m| (m.tree_id, m.sub_trees))
+                .collect(),
+        })
+    }
+
+    async fn verify_trees(&mut self, roots: Vec<String>) -> Result<(), MegaError> {
+        let mut blobs = Vec::new();
+        let mut pending = roots;
+        while !pending.is_empty() {
+            let batch: Vec<String> = pending
+                .drain(..pending.len().min(BATCH_SIZE))
+                .filter(|id| self.seen.insert(id.clone()))
+                .collect();
+            let mut loaded = self.load_trees(batch.clone()).await?;
+            for id in batch {
+                self.checked += 1;
+                let Some(data) = loaded.remove(&id) else {
+                    self.problem(ProblemKind::MissingObject, &id, "tree is not stored");
+                    continue;
+                };
+                let hash = SHA1::from_type_and_data(ObjectType::Tree, &data);
+                if hash.to_string() != id {
+                    self.problem(
+                        ProblemKind::HashMismatch,
+                        &id,
+                        format!("tree content hashes to {}", hash),
+                    );
+                }
+                let Some(tree) = decode_tree(&data, &id) else {
+                    self.problem(ProblemKind::CorruptObject, &id, "tree can not be decoded");
+                    continue;
+                };
+                for item in tree.tree_items {
+                    match item.mode {
+                        TreeItemMode::Tree => pending.push(item.id.to_string()),
+                        // submodules point to commits of other repositories
+                        TreeItemMode::Commit => (),
+                        _ => blobs.push(item.id.to_string()),
+                    }
+                }
+            }
+            // blobs are checked as they are found to keep memory bounded on large repositories
+            if blobs.len() >= BATCH_SIZE || pending.is_empty() {
+                self.verify_blobs(std::mem::take(&mut blobs)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn verify_blobs(&mut self, ids: Vec<String>) -> Result<(), MegaError> {
+        let ids: Vec<String> = ids
+            .into_iter()
+            .filter(|id| self.seen.insert(id.clone()))
+            .collect();
+        for batch in ids.chunks(BATCH_SIZE) {
+            let mut loaded: HashMap<String, _> = self
+                .context
+                .services
+                .raw_db_storage
+                .get_raw_blobs_by_hashes(batch.to_vec())
+                .await?
+                .into_iter()
+                .map(|m| (m.sha1.clone(), m))
+                .collect();
+            for id in batch {
+                self.checked += 1;
+                let Some(blob) = loaded.remove(id) else {
+                    self.problem(ProblemKind::MissingObject, id, "blob is not stored");
+                    continue;
+                };
+                match blob.storage_type {
+                    // chunks are put together when the blob is loaded
+                    StorageType::Database | StorageType::Chunked => {
+                        let Some(data) = blob.data else {
+                            self.problem(ProblemKind::MissingContent, id, "blob has no data");
+                            continue;
+                        };
+                        let hash = SHA1::from_type_and_data(ObjectType::Blob, &data);
+                        if hash.to_string() != *id {
+                            self.problem(
+                                ProblemKind::HashMismatch,
+                                id,
+                                format!("blob content hashes to {}", hash),
+                            );
+                        }
+                        if let Some(oid) = parse_lfs_pointer(&data) {
+                            self.verify_lfs_object(id, &oid).await?;
+                        }
+                    }
+                    StorageType::LocalFs => {
+                        let exists = blob
+                            .local_path
+                            .as_deref()
+                            .is_some_and(|p| Path::new(p).exists());
+                        if !exists {
+                            self.problem(
+                                ProblemKind::MissingContent,
+                                id,
+                                format!("local file {:?} does not exist", blob.local_path),
+                            );
+                        }
+                    }
+                    // remote content is not fetched, it may be arbitrarily large or slow
+                    StorageType::RemoteUrl => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn verify_lfs_object(&mut self, blob_id: &str, oid: &str) -> Result<(), MegaError> {
+        let db = &self.context.services.lfs_db_storage;
+        let storage = &self.context.services.lfs_storage;
+        let Some(object) = db.get_lfs_object(oid.to_owned()).await? else {
+            self.problem(
+                ProblemKind::MissingLfsObject,
+                blob_id,
+                format!("lfs object {} is not recorded", oid),
+            );
+            return Ok(());
+        };
+        let parts = if object.splited {
+            db.get_lfs_relations(oid.to_owned())
+                .await?
+                .into_iter()
+                .map(|r| r.sub_oid)
+                .collect()
+        } else {
+            vec![object.oid]
+        };
+        for part in parts {
+            if !storage.exist_object(&part) {
+                self.problem(
+                    ProblemKind::MissingLfsObject,
+                    blob_id,
+                    format!("content of lfs object {} is missing", part),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn catch_decode<T>(decode: impl FnOnce() -> T) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(decode)).ok()
+}
+
+pub(crate) fn decode_tree(data: &[u8], id: &str) -> Option<Tree> {
+    let id = SHA1::from_str(id).ok()?;
+    catch_decode(|| Tree::from_bytes(data, id).ok()).flatten()
+}
+
+/// The sha256 oid of an LFS pointer file, `None` if `data` is regular content.
+fn parse_lfs_pointer(data: &[u8]) -> Option<String> {
+    if data.len() > LFS_POINTER_MAX_SIZE || !data.starts_with(LFS_POINTER_VERSION.as_bytes()) {
+        return None;
+    }
+    std::str::from_utf8(data)
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("oid sha256:"))
+        .map(|oid| oid.trim().to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_lfs_pointer;
+
+    #[test]
+    fn test_parse_lfs_pointer() {
+        let pointer = "version https://git-lfs.github.com/spec/v1\n\
+            oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+            size 12345\n";
+        assert_eq!(
+            parse_lfs_pointer(pointer.as_bytes()).as_deref(),
+            Some("4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393")
+        );
+        assert_eq!(parse_lfs_pointer(b"fn main() {}\n"), None);
+        assert_eq!(
+            parse_lfs_pointer(b"version https://git-lfs.github.com/spec/v1\nsize 1\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! Mergeability of merge requests.
+//!
+//! A merge request records the commit of its directory it started from and its latest commit.
+//! Merges of other merge requests may have changed the directory in the monorepo since, so the
+//! tree of the directory at the monorepo head is merged in memory with the tree of the merge
+//! request, taking the tree it started from as base. Entries changed differently on both sides
+//! are conflicts. The result is stored per merge request and refreshed when a push updates the
+//! merge request and whenever the monorepo head moves, the API serves it without merging.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use callisto::db_enums::{MergeStatus, Mergeability};
+use callisto::{mega_mr, mega_mr_mergeability};
+use common::errors::MegaError;
+use common::utils::repo_path;
+use jupiter::context::Context;
+use jupiter::storage::mono_storage::MonoStorage;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+
+/// Result of merging a merge request into the monorepo head.
+pub struct TreeMerge {
+    /// Monorepo commit the merge request was merged into
+    pub target_hash: String,
+    /// Merged tree of the directory, the tree of the merge request if the directory did not
+    /// change in the monorepo
+    pub tree_id: SHA1,
+    /// Trees created by the merge, they are not stored yet
+    pub trees: Vec<Tree>,
+    /// Paths changed differently by the merge request and the monorepo
+    pub conflicts: Vec<String>,
+}
+
+/// Check the merge request `link` again and store the result, a failure is only logged.
+pub async fn refresh(context: &Context, link: &str) {
+    let res = match context.mr_stg().get_mr(link).await {
+        Ok(Some(mr)) if mr.status == MergeStatus::Open => check(context, &mr).await.map(|_| ()),
+        Ok(_) => Ok(()),
+        Err(err) => Err(err),
+    };
+    if let Err(err) = res {
+        tracing::error!(
+            "failed to check whether merge request {} merges: {}",
+            link,
+            err
+        );
+    }
+}
+
+/// Check every open merge request again, run after the monorepo head moved.
+pub async fn refresh_open(context: Context) {
+    let mrs = match context.mr_stg().get_open_mrs().await {
+        Ok(mrs) => mrs,
+        Err(err) => {
+            tracing::error!("failed to load the open merge requests: {}", err);
+            return;
+        }
+    };
+    for mr in mrs {
+        if let Err(err) = check(&context, &mr).await {
+            tracing::error!(
+                "failed to check whether merge request {} merges: {}",
+                mr.link,
+                err
+            );
+        }
+    }
+}
+
+/// The stored mergeability of the open merge request `mr`, it is checked first if either
+/// side moved since it was stored.
+pub async fn current(
+    context: &Context,
+    mr: &mega_mr::Model,
+) -> Result<Option<mega_mr_mergeability::Model>, MegaError> {
+    let storage = context.mr_stg();
+    let Some(root) = context.services.mono_storage.get_ref("/").await? else {
+        return Ok(None);
+    };
+    let stored = storage.get_mergeability(&mr.link).await?;
+    if stored
+        .as_ref()
+        .is_some_and(|m| m.source_hash == mr.to_hash && m.target_hash == root.ref_commit_hash)
+    {
+        return Ok(stored);
+    }
+    check(context, mr).await?;
+    storage.get_mergeability(&mr.link).await
+}
+
+/// Merge `mr` in memory and store whether it merges cleanly.
+async fn check(context: &Context, mr: &mega_mr::Model) -> Result<TreeMerge, MegaError> {
+    let merge = merge_tree(context, &mr.path, &mr.from_hash, &mr.to_hash).await?;
+    let status = if merge.conflicts.is_empty() {
+        Mergeability::Mergeable
+    } else {
+        Mergeability::Conflicting
+    };
+    context
+        .mr_stg()
+        .save_mergeability(
+            &mr.link,
+            status,
+            &merge.conflicts,
+            &mr.to_hash,
+            &merge.target_hash,
+        )
+        .await?;
+    Ok(merge)
+}
+
+/// Merge the changes from `from_hash` to `to_hash` of the directory `path` into the directory
+/// at the monorepo head.
+pub async fn merge_tree(
+    context: &Context,
+    path: &str,
+    from_hash: &str,
+    to_hash: &str,
+) -> Result<TreeMerge, MegaError> {
+    let storage = &context.services.mono_storage;
+    let root = storage
+        .get_ref("/")
+        .await?
+        .ok_or_else(|| MegaError::with_message("monorepo is not initialized"))?;
+    let commit_tree = |id: &str| {
+        let id = id.to_owned();
+        async move {
+            storage
+                .get_commit_by_hash(&id)
+                .await?
+                .map(|c| Commit::from(c).tree_id)
+                .ok_or_else(|| MegaError::with_message(&format!("commit {} not found", id)))
+        }
+    };
+    let base = commit_tree(from_hash).await?;
+    let theirs = commit_tree(to_hash).await?;
+    let mut merge = TreeMerge {
+        target_hash: root.ref_commit_hash,
+        tree_id: theirs,
+        trees: vec![],
+        conflicts: vec![],
+    };
+    let root_tree = SHA1::from_str(&root.ref_tree_hash).unwrap();
+    let Some(ours) = find_tree(storage, root_tree, path).await? else {
+        // the directory was removed from the monorepo
+        merge.conflicts.push(path.to_owned());
+        return Ok(merge);
+    };
+    if ours != base && ours != theirs {
+        let mut trees = HashMap::new();
+        let res = merge_trees(
+            storage,
+            path.to_owned(),
+            Some(base),
+            ours,
+            theirs,
+            &mut trees,
+            &mut merge.conflicts,
+        )
+        .await?;
+        match res {
+            Some(tree_id) => merge.tree_id = tree_id,
+            None => merge.conflicts.push(path.to_owned()),
+        }
+        merge.trees = trees.into_values().collect();
+    }
+    Ok(merge)
+}
+
+/// The tree of `path` below the tree `root`.
+async fn find_tree(
+    storage: &MonoStorage,
+    root: SHA1,
+    path: &str,
+) -> Result<Option<SHA1>, MegaError> {
+    let mut tree_id = root;
+    for component in Path::new(path).components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        let tree = load_tree(storage, tree_id).await?;
+        match tree
+            .tree_items
+            .into_iter()
+            .find(|x| x.mode == TreeItemMode::Tree && name.to_str() == Some(x.name.as_str()))
+        {
+            Some(item) => tree_id = item.id,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(tree_id))
+}
+
+pub(crate) async fn load_tree(storage: &MonoStorage, id: SHA1) -> Result<Tree, MegaError> {
+    storage
+        .get_tree_by_hash(&id.to_string())
+        .await?
+        .map(Tree::from)
+        .ok_or_else(|| MegaError::with_message(&format!("tree {} not found", id)))
+}
+
+/// Merge the trees `ours` and `theirs` of `path` with `base`, returns the merged tree or `None`
+/// if it ends up empty. New trees are added to `trees` and conflicting paths to `conflicts`.
+fn merge_trees<'a>(
+    storage: &'a MonoStorage,
+    path: String,
+    base: Option<SHA1>,
+    ours: SHA1,
+    theirs: SHA1,
+    trees: &'a mut HashMap<SHA1, Tree>,
+    conflicts: &'a mut Vec<String>,
+) -> BoxFuture<'a, Result<Option<SHA1>, MegaError>> {
+    async move {
+        let base = match base {
+            Some(id) => load_tree(storage, id).await?.tree_items,
+            None => vec![],
+        };
+        let ours = load_tree(storage, ours).await?.tree_items;
+        let theirs = load_tree(storage, theirs).await?.tree_items;
+        let mut items = Vec::new();
+        for (name, resolution) in merge_entries(&base, &ours, &theirs) {
+            let item_path = repo_path(&Path::new(&path).join(&name));
+            match resolution {
+                Resolution::Take(item) => items.extend(item),
+                Resolution::Merge(base, ours, theirs) => {
+                    let merged = merge_trees(
+                        storage,
+                        item_path,
+                        base,
+                        ours,
+                        theirs,
+                        &mut *trees,
+                        &mut *conflicts,
+                    )
+                    .await?;
+                    if let Some(id) = merged {
+                        items.push(TreeItem::new(TreeItemMode::Tree, id, name));
+                    }
+                }
+                Resolution::Conflict(item) => {
+                    conflicts.push(item_path);
+                    items.extend(item);
+                }
+            }
+        }
+        if items.is_empty() {
+            return Ok(None);
+        }
+        items.sort_by_key(sort_key);
+        let tree = Tree::from_tree_items(items).unwrap();
+        let id = tree.id;
+        trees.insert(id, tree);
+        Ok(Some(id))
+    }
+    .boxed()
+}
+
+/// How an entry of a directory is merged.
+#[derive(Debug, PartialEq)]
+enum Resolution {
+    /// The entry as changed by one side, `None` if it was removed
+    Take(Option<TreeItem>),
+    /// Both sides changed the directory, it is merged entry by entry
+    Merge(Option<SHA1>, SHA1, SHA1),
+    /// Both sides changed the entry differently, our entry is kept in its place
+    Conflict(Option<TreeItem>),
+}
+
+/// Merge the entries of a directory by name, `ours` wins where both sides agree.
+fn merge_entries(
+    base: &[TreeItem],
+    ours: &[TreeItem],
+    theirs: &[TreeItem],
+) -> Vec<(String, Resolution)> {
+    let find = |items: &[TreeItem], name: &str| items.iter().find(|x| x.name == name).cloned();
+    let names: BTreeSet<&str> = base
+        .iter()
+        .chain(ours)
+        .chain(theirs)
+        .map(|x| x.name.as_str())
+        .collect();
+    names
+        .into_iter()
+        .map(|name| {
+            let (b, o, t) = (find(base, name), find(ours, name), find(theirs, name));
+            let is_tree =
+                |x: &Option<TreeItem>| x.as_ref().is_some_and(|x| x.mode == TreeItemMode::Tree);
+            let resolution = if o == t || b == t {
+                Resolution::Take(o)
+            } else if b == o {
+                Resolution::Take(t)
+            } else if is_tree(&o) && is_tree(&t) && (b.is_none() || is_tree(&b)) {
+                Resolution::Merge(b.map(|x| x.id), o.unwrap().id, t.unwrap().id)
+            } else {
+                Resolution::Conflict(o)
+            };
+            (name.to_owned(), resolution)
+        })
+        .collect()
+}
+
+/// Git orders the entries of a tree by name, directories as if their name ended with `/`.
+fn sort_key(item: &TreeItem) -> Vec<u8> {
+    let mut key = item.name.as_bytes().to_vec();
+    if item.mode == TreeItemMode::Tree {
+        key.push(b'/');
+    }
+    key
+}
+
+#[cfg(test)]
+mod test {
+    use mercury::hash::SHA1;
+    use mercury::internal::object::tree::{TreeItem, TreeItemMode};
+
+    use super::{merge_entries, sort_key, Resolution};
+
+    fn blob(name: &str, content: &str) -> TreeItem {
+        TreeItem::new(
+            TreeItemMode::Blob,
+            SHA1::new(content.as_bytes()),
+            name.to_owned(),
+        )
+    }
+
+    fn tree(name: &str, content: &str) -> TreeItem {
+        TreeItem::new(
+            TreeItemMode::Tree,
+            SHA1::new(content.as_bytes()),
+            name.to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_merge_entries() {
+        let base = vec![
+            blob("a.txt", "a"),
+            blob("b.txt", "b"),
+            blob("c.txt", "c"),
+            tree("src", "src"),
+        ];
+        let ours = vec![
+            blob("a.txt", "a2"),
+            blob("b.txt", "b"),
+            blob("c.txt", "c2"),
+            tree("src", "src2"),
+        ];
+        let theirs = vec![
+            blob("a.txt", "a"),
+            blob("c.txt", "c3"),
+            blob("d.txt", "d"),
+            tree("src", "src3"),
+        ];
+        let res = merge_entries(&base, &ours, &theirs);
+        assert_eq!(
+            res,
+            vec![
+                (
+                    "a.txt".to_owned(),
+                    Resolution::Take(Some(blob("a.txt", "a2")))
+                ),
+                ("b.txt".to_owned(), Resolution::Take(None)),
+                (
+                    "c.txt".to_owned(),
+                    Resolution::Conflict(Some(blob("c.txt", "c2")))
+                ),
+                (
+                    "d.txt".to_owned(),
+                    Resolution::Take(Some(blob("d.txt", "d")))
+                ),
+                (
+                    "src".to_owned(),
+                    Resolution::Merge(
+                        Some(SHA1::new("src".as_bytes())),
+                        SHA1::new("src2".as_bytes()),
+                        SHA1::new("src3".as_bytes())
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_entries_removed_and_changed() {
+        let base = vec![blob("a.txt", "a")];
+        let theirs = vec![blob("a.txt", "a2")];
+        assert_eq!(
+            merge_entries(&base, &[], &theirs),
+            vec![("a.txt".to_owned(), Resolution::Conflict(None))]
+        );
+        // added on both sides the same way
+        assert_eq!(
+            merge_entries(&[], &theirs, &theirs),
+            vec![(
+                "a.txt".to_owned(),
+                Resolution::Take(Some(blob("a.txt", "a2")))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_sort_key() {
+        let mut items = vec![tree("a", "a"), blob("a.txt", "b"), blob("a-b", "c")];
+        items.sort_by_key(sort_key);
+        let names: Vec<&str> = items.iter().map(|x| x.name.as_str()).collect();
+        assert_eq!(names, vec!["a-b", "a.txt", "a"]);
+    }
+}
@@ -0,0 +1,11 @@
+use serde::{Deserialize, Serialize};
+
+/// A line of a file with the commit which last changed it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlameLine {
+    /// Starting at 1
+    pub line: usize,
+    /// `None` if the line is older than the recorded history of the file
+    pub commit_id: Option<String>,
+    pub content: String,
+}
@@ -1,3 +1,4 @@
+pub mod blame;
 pub mod create_file;
 pub mod query;
 pub mod tree;
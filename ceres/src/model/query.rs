@@ -1,6 +1,10 @@
 use serde::Deserialize;
 
-#[allow(dead_code)]
+use jupiter::storage::TreeItemRange;
+
+/// Most entries of a directory listed in one page.
+const MAX_TREE_PAGE_SIZE: u64 = 1000;
+
 #[derive(Debug, Deserialize)]
 pub struct CodePreviewQuery {
     #[serde(default)]
@@ -9,12 +13,54 @@ pub struct CodePreviewQuery {
     pub path: String,
 }
 
+/// Listing of a directory, all entries unless a page is requested.
+#[derive(Debug, Deserialize)]
+pub struct TreeQuery {
+    #[serde(default)]
+    pub refs: String,
+    #[serde(default = "default_path")]
+    pub path: String,
+    /// Only entries whose name starts with it
+    #[serde(default)]
+    pub prefix: String,
+    /// Page of the entries sorted by name, starting at 1
+    pub page: Option<u64>,
+    #[serde(default = "default_per_page")]
+    pub per_page: u64,
+}
+
+impl TreeQuery {
+    pub fn range(&self) -> TreeItemRange {
+        match self.page {
+            Some(page) => TreeItemRange::page(
+                &self.prefix,
+                page,
+                self.per_page.clamp(1, MAX_TREE_PAGE_SIZE),
+            ),
+            None => TreeItemRange {
+                prefix: self.prefix.clone(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct BlobContentQuery {
     #[serde(default = "default_path")]
     pub path: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PathHistoryQuery {
+    #[serde(default = "default_path")]
+    pub path: String,
+}
+
 fn default_path() -> String {
     "/".to_string()
 }
+
+fn default_per_page() -> u64 {
+    100
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+use callisto::db_enums::SignatureStatus;
 use mercury::internal::object::tree::{TreeItem, TreeItemMode};
 
 #[derive(Serialize, Deserialize)]
@@ -10,6 +11,8 @@ pub struct LatestCommitInfo {
     pub author: UserInfo,
     pub committer: UserInfo,
     pub status: String,
+    /// Whether the signature of the commit is verified
+    pub verification: SignatureStatus,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -34,6 +37,7 @@ pub struct TreeCommitItem {
     pub content_type: String,
     pub message: String,
     pub date: String,
+    pub verification: SignatureStatus,
 }
 
 impl From<TreeItem> for TreeCommitItem {
@@ -48,6 +52,7 @@ impl From<TreeItem> for TreeCommitItem {
             oid: String::new(),
             message: String::new(),
             date: String::new(),
+            verification: SignatureStatus::Unsigned,
         }
     }
 }
@@ -73,6 +78,27 @@ impl From<TreeItem> for TreeBriefItem {
     }
 }
 
+/// Listing of a monorepo directory at a commit, used by mounts of the monorepo.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TreeEntries {
+    /// Commit the listing was resolved from
+    pub commit: String,
+    pub oid: String,
+    /// Number of entries matching the prefix of the listing, `entries` may be a page of them
+    #[serde(default)]
+    pub total: u64,
+    pub entries: Vec<TreeEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TreeEntry {
+    pub name: String,
+    pub oid: String,
+    pub mode: TreeItemMode,
+    /// Size of a blob in bytes, 0 for other objects
+    pub size: u64,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct MRFileTree {
     pub title: String,
@@ -0,0 +1,134 @@
+//! Prebuilt packs and reachability indexes created by the maintenance jobs.
+//!
+//! Both are keyed by commit id and therefore never change once written: `<commit>.pack`
+//! holds the full clone pack of a ref tip and `<commit>.reach` lists every tree and blob
+//! reachable from the tip, one id per line, which plays the role of git's reachability
+//! bitmaps when negotiating fetches.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use common::errors::MegaError;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+pub fn pack_path(dir: &Path, commit: &str) -> PathBuf {
+    dir.join(format!("{}.pack", commit))
+}
+
+pub fn reach_path(dir: &Path, commit: &str) -> PathBuf {
+    dir.join(format!("{}.reach", commit))
+}
+
+/// Stream the prebuilt pack of `commit`, returns `None` if the repack job has not built it.
+pub fn open_pack(dir: &Path, commit: &str, channel_size: usize) -> Option<ReceiverStream<Vec<u8>>> {
+    let mut file = File::open(pack_path(dir, commit)).ok()?;
+    let (tx, rx) = mpsc::channel(channel_size);
+    tokio::task::spawn_blocking(move || {
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        loop {
+            match file.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.blocking_send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("read prebuilt pack failed: {}", err);
+                    break;
+                }
+            }
+        }
+    });
+    Some(ReceiverStream::new(rx))
+}
+
+/// Write an encoded pack stream to `path`, readers never see a partial file.
+pub async fn write_pack(path: &Path, stream: ReceiverStream<Vec<u8>>) -> Result<(), MegaError> {
+    let tmp = path.with_extension("pack.tmp");
+    let mut file = File::create(&tmp)?;
+    let mut rx = stream.into_inner();
+    while let Some(chunk) = rx.recv().await {
+        file.write_all(&chunk)?;
+    }
+    file.sync_all()?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+pub fn load_reachable(dir: &Path, commit: &str) -> Option<HashSet<String>> {
+    let content = fs::read_to_string(reach_path(dir, commit)).ok()?;
+    Some(content.lines().map(str::to_owned).collect())
+}
+
+pub fn write_reachable(path: &Path, objects: &HashSet<String>) -> Result<(), MegaError> {
+    let mut objects: Vec<&String> = objects.iter().collect();
+    objects.sort_unstable();
+    let mut content = String::with_capacity(objects.len() * 41);
+    for id in objects {
+        content.push_str(id);
+        content.push('\n');
+    }
+    let tmp = path.with_extension("reach.tmp");
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// The packs and indexes of commits which are no longer a ref tip.
+pub fn stale_files(dir: &Path, tips: &HashSet<String>) -> Result<Vec<PathBuf>, MegaError> {
+    let mut stale = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let commit = name.split('.').next().unwrap_or_default();
+        if !tips.contains(commit) {
+            stale.push(entry.path());
+        }
+    }
+    Ok(stale)
+}
+
+/// Remove the packs and indexes of commits which are no longer a ref tip.
+pub fn remove_stale(dir: &Path, tips: &HashSet<String>) -> Result<usize, MegaError> {
+    let stale = stale_files(dir, tips)?;
+    for path in &stale {
+        fs::remove_file(path)?;
+    }
+    Ok(stale.len())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_reachable_and_remove_stale() {
+        let dir = std::env::temp_dir().join("mega_pack_cache_test");
+        fs::create_dir_all(&dir).unwrap();
+        let objects: HashSet<String> = ["b".to_owned(), "a".to_owned()].into();
+        write_reachable(&reach_path(&dir, "tip"), &objects).unwrap();
+        write_reachable(&reach_path(&dir, "old"), &objects).unwrap();
+        assert_eq!(load_reachable(&dir, "tip"), Some(objects));
+        assert_eq!(load_reachable(&dir, "missing"), None);
+
+        let tips: HashSet<String> = ["tip".to_owned()].into();
+        assert_eq!(
+            stale_files(&dir, &tips).unwrap(),
+            vec![reach_path(&dir, "old")]
+        );
+        assert_eq!(remove_stale(&dir, &tips).unwrap(), 1);
+        assert!(reach_path(&dir, "tip").exists());
+        assert!(!reach_path(&dir, "old").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
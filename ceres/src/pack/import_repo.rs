@@ -1,5 +1,5 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashSet,
     path::PathBuf,
     str::FromStr,
     sync::{
@@ -14,7 +14,7 @@ use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
 use callisto::{db_enums::RefType, mega_tree, raw_blob};
-use common::errors::MegaError;
+use common::{errors::MegaError, utils::MEGA_BRANCH_NAME};
 use jupiter::{context::Context, storage::batch_save_model};
 use mercury::{
     errors::GitError,
@@ -27,6 +27,7 @@ use mercury::{hash::SHA1, internal::pack::encode::PackEncoder};
 
 use crate::{
     api_service::{mono_api_service::MonoApiService, ApiHandler},
+    history, merge,
     pack::PackHandler,
     protocol::{
         import_refs::{CommandType, RefCommand, Refs},
@@ -169,49 +170,16 @@ impl PackHandler for ImportRepo {
         want: Vec<String>,
         have: Vec<String>,
     ) -> Result<ReceiverStream<Vec<u8>>, GitError> {
-        let mut want_clone = want.clone();
         let pack_config = &self.context.config.pack;
         let storage = self.context.services.git_db_storage.clone();
         let obj_num = AtomicUsize::new(0);
 
         let mut exist_objs = HashSet::new();
 
-        let mut want_commits: Vec<Commit> = storage
-            .get_commits_by_hashes(self.repo.repo_id, &want_clone)
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|x| x.into())
-            .collect();
-        let mut traversal_list: Vec<Commit> = want_commits.clone();
-
-        // traverse commit's all parents to find the commit that client does not have
-        while let Some(temp) = traversal_list.pop() {
-            for p_commit_id in temp.parent_commit_ids {
-                let p_commit_id = p_commit_id.to_string();
-
-                if !have.contains(&p_commit_id) && !want_clone.contains(&p_commit_id) {
-                    let parent: Commit = storage
-                        .get_commit_by_hash(self.repo.repo_id, &p_commit_id)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .into();
-                    want_commits.push(parent.clone());
-                    want_clone.push(p_commit_id);
-                    traversal_list.push(parent);
-                }
-            }
-        }
-
+        // the commits the client does not have
+        let want_commits = self.missing_commits(want, &have).await.unwrap();
         let want_tree_ids = want_commits.iter().map(|c| c.tree_id.to_string()).collect();
-        let want_trees: HashMap<SHA1, Tree> = storage
-            .get_trees_by_hashes(self.repo.repo_id, want_tree_ids)
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|m| (SHA1::from_str(&m.tree_id).unwrap(), m.into()))
-            .collect();
+        let want_trees = self.get_trees_by_hashes(want_tree_ids).await.unwrap();
 
         obj_num.fetch_add(want_commits.len(), Ordering::SeqCst);
 
@@ -219,41 +187,25 @@ impl PackHandler for ImportRepo {
             .get_commits_by_hashes(self.repo.repo_id, &have)
             .await
             .unwrap();
-        let have_trees = storage
-            .get_trees_by_hashes(
-                self.repo.repo_id,
-                have_commits.iter().map(|x| x.tree.clone()).collect(),
-            )
+        let have_trees = self
+            .get_trees_by_hashes(have_commits.into_iter().map(|x| x.tree).collect())
             .await
             .unwrap();
         // traverse to get exist_objs
-        for have_tree in have_trees {
-            self.traverse(have_tree.into(), &mut exist_objs, None).await;
-        }
+        self.traverse(have_trees, &mut exist_objs, None).await;
 
         let mut counted_obj = HashSet::new();
         // traverse for get obj nums
-        for c in want_commits.clone() {
-            self.traverse_for_count(
-                want_trees.get(&c.tree_id).unwrap().clone(),
-                &exist_objs,
-                &mut counted_obj,
-                &obj_num,
-            )
+        self.traverse_for_count(want_trees.clone(), &exist_objs, &mut counted_obj, &obj_num)
             .await;
-        }
         let (entry_tx, entry_rx) = mpsc::channel(pack_config.channel_message_size);
         let (stream_tx, stream_rx) = mpsc::channel(pack_config.channel_message_size);
         let encoder = PackEncoder::new(obj_num.into_inner(), 0, stream_tx);
         encoder.encode_async(entry_rx).await.unwrap();
 
-        for c in want_commits {
-            self.traverse(
-                want_trees.get(&c.tree_id).unwrap().clone(),
-                &mut exist_objs,
-                Some(&entry_tx),
-            )
+        self.traverse(want_trees, &mut exist_objs, Some(&entry_tx))
             .await;
+        for c in want_commits {
             entry_tx.send(c.into()).await.unwrap();
         }
         drop(entry_tx);
@@ -261,6 +213,16 @@ impl PackHandler for ImportRepo {
         Ok(ReceiverStream::new(stream_rx))
     }
 
+    async fn get_commits_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Commit>, MegaError> {
+        let commits = self
+            .context
+            .services
+            .git_db_storage
+            .get_commits_by_hashes(self.repo.repo_id, &hashes)
+            .await?;
+        Ok(commits.into_iter().map(|x| x.into()).collect())
+    }
+
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError> {
         Ok(self
             .context
@@ -296,25 +258,31 @@ impl PackHandler for ImportRepo {
         refs: &RefCommand,
     ) -> Result<(), GitError> {
         let storage = self.context.services.git_db_storage.clone();
-        match refs.command_type {
+        let conn = storage.get_connection();
+        let res = match refs.command_type {
             CommandType::Create => {
                 storage
-                    .save_ref(self.repo.repo_id, refs.clone().into())
+                    .save_ref(conn, self.repo.repo_id, refs.clone().into())
+                    .await
+            }
+            CommandType::Delete => {
+                storage
+                    .remove_ref_if(conn, self.repo.repo_id, &refs.ref_name, &refs.old_id)
                     .await
-                    .unwrap();
             }
-            CommandType::Delete => storage
-                .remove_ref(self.repo.repo_id, &refs.ref_name)
-                .await
-                .unwrap(),
             CommandType::Update => {
                 storage
-                    .update_ref(self.repo.repo_id, &refs.ref_name, &refs.new_id)
+                    .update_ref(
+                        conn,
+                        self.repo.repo_id,
+                        &refs.ref_name,
+                        &refs.old_id,
+                        &refs.new_id,
+                    )
                     .await
-                    .unwrap();
             }
-        }
-        Ok(())
+        };
+        res.map_err(|e| GitError::CustomError(e.to_string()))
     }
 
     async fn check_commit_exist(&self, hash: &str) -> bool {
@@ -338,7 +306,7 @@ impl PackHandler for ImportRepo {
 
 impl ImportRepo {
     // attach import repo to monorepo parent tree
-    async fn attach_to_monorepo_parent(&self) -> Result<(), GitError> {
+    pub(crate) async fn attach_to_monorepo_parent(&self) -> Result<(), GitError> {
         let iter = self
             .command_list
             .clone()
@@ -353,7 +321,7 @@ impl ImportRepo {
         let mono_api_service = MonoApiService {
             context: self.context.clone(),
         };
-        let storage = self.context.services.mono_storage.clone();
+        let storage = &self.context.services.mono_storage;
         let save_trees = mono_api_service.search_and_create_tree(&path).await?;
 
         let mut root_ref = storage.get_ref("/").await.unwrap().unwrap();
@@ -382,14 +350,21 @@ impl ImportRepo {
             })
             .collect();
 
-        batch_save_model(storage.get_connection(), save_trees)
-            .await
-            .unwrap();
-
-        root_ref.ref_commit_hash = new_commit.id.to_string();
+        let old_commit =
+            std::mem::replace(&mut root_ref.ref_commit_hash, new_commit.id.to_string());
         root_ref.ref_tree_hash = new_commit.tree_id.to_string();
-        storage.update_ref(root_ref).await.unwrap();
-        storage.save_mega_commits(vec![new_commit]).await.unwrap();
+        let new_commit_id = new_commit.id.to_string();
+        storage
+            .transaction(|txn| async move {
+                let conn = &*txn;
+                batch_save_model(conn, save_trees).await?;
+                storage.save_mega_commits(conn, vec![new_commit]).await?;
+                storage.update_ref(conn, root_ref, &old_commit).await
+            })
+            .await
+            .map_err(|err| GitError::CustomError(err.to_string()))?;
+        history::record(&self.context, "/", MEGA_BRANCH_NAME, &new_commit_id).await;
+        tokio::spawn(merge::refresh_open(self.context.clone()));
         Ok(())
     }
 }
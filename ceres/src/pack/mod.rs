@@ -31,8 +31,10 @@ use mercury::{
     },
 };
 
+pub mod cache;
 pub mod import_repo;
 pub mod monorepo;
+pub mod secret_scan;
 
 #[async_trait]
 pub trait PackHandler: Send + Sync {
@@ -56,6 +58,8 @@ pub trait PackHandler: Send + Sync {
         have: Vec<String>,
     ) -> Result<ReceiverStream<Vec<u8>>, GitError>;
 
+    async fn get_commits_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Commit>, MegaError>;
+
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError>;
 
     async fn get_blobs_by_hashes(
@@ -86,9 +90,12 @@ pub trait PackHandler: Send + Sync {
         (head_hash, refs)
     }
 
+    /// Decode a pushed pack, fails with [`ProtocolError::TooLarge`] once more than
+    /// `pack_limit` bytes were received.
     async fn unpack_stream(
         &self,
         pack_config: &PackConfig,
+        pack_limit: usize,
         stream: Pin<Box<dyn Stream<Item = Result<Bytes, axum::Error>> + Send>>,
     ) -> Result<Receiver<Entry>, ProtocolError> {
         let (sender, receiver) = std::sync::mpsc::channel();
@@ -98,13 +105,7 @@ pub trait PackHandler: Send + Sync {
             Some(pack_config.pack_decode_cache_path.clone()),
             pack_config.clean_cache_after_decode,
         );
-        let (unpack_handle, convert) = p
-            .decode_stream(
-                stream,
-                1024 * 1024 * 1024 * pack_config.maximum_pack_size,
-                sender,
-            )
-            .await;
+        let (unpack_handle, convert) = p.decode_stream(stream, pack_limit, sender).await;
         match convert.await.unwrap() {
             Ok(_) => (),
             Err(err) => {
@@ -116,43 +117,70 @@ pub trait PackHandler: Send + Sync {
         Ok(receiver)
     }
 
+    /// The commits of `want` and all their ancestors which are not in `have`, loaded a
+    /// generation at a time.
+    async fn missing_commits(
+        &self,
+        want: Vec<String>,
+        have: &[String],
+    ) -> Result<Vec<Commit>, MegaError> {
+        let mut seen: HashSet<String> = have.iter().chain(&want).cloned().collect();
+        let mut commits = Vec::new();
+        let mut pending = want;
+        while !pending.is_empty() {
+            let generation = self.get_commits_by_hashes(pending).await?;
+            pending = Vec::new();
+            for commit in &generation {
+                for id in &commit.parent_commit_ids {
+                    let id = id.to_string();
+                    if seen.insert(id.clone()) {
+                        pending.push(id);
+                    }
+                }
+            }
+            commits.extend(generation);
+        }
+        Ok(commits)
+    }
+
+    /// Count the trees `trees`, and the objects below them which are neither in `exist_objs`
+    /// nor already in `counted_obj`. Subtrees are loaded a level at a time.
     async fn traverse_for_count(
         &self,
-        tree: Tree,
+        trees: Vec<Tree>,
         exist_objs: &HashSet<String>,
         counted_obj: &mut HashSet<String>,
         obj_num: &AtomicUsize,
     ) {
-        let mut search_tree_ids = vec![];
-        let mut search_blob_ids = vec![];
-        for item in &tree.tree_items {
-            let hash = item.id.to_string();
-            if !exist_objs.contains(&hash) && counted_obj.insert(hash.clone()) {
-                if item.mode == TreeItemMode::Tree {
-                    search_tree_ids.push(hash.clone())
-                } else {
-                    search_blob_ids.push(hash.clone());
+        let mut level = trees;
+        while !level.is_empty() {
+            let mut search_tree_ids = vec![];
+            let mut blob_num = 0;
+            for tree in &level {
+                for item in &tree.tree_items {
+                    let hash = item.id.to_string();
+                    if !exist_objs.contains(&hash) && counted_obj.insert(hash.clone()) {
+                        if item.mode == TreeItemMode::Tree {
+                            search_tree_ids.push(hash)
+                        } else {
+                            blob_num += 1;
+                        }
+                    }
                 }
             }
+            obj_num.fetch_add(level.len() + blob_num, Ordering::SeqCst);
+            level = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
         }
-        obj_num.fetch_add(search_blob_ids.len(), Ordering::SeqCst);
-        let trees = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
-        for t in trees {
-            self.traverse_for_count(t, exist_objs, counted_obj, obj_num)
-                .await;
-        }
-        obj_num.fetch_add(1, Ordering::SeqCst);
     }
 
-    /// Traverse a tree structure asynchronously.
+    /// Traverse the trees `trees` asynchronously.
     ///
-    /// This function traverses a given tree, keeps track of processed objects, and optionally sends
-    /// traversal data to a provided sender. The function will:
-    /// 1. Traverse the tree and calculate the quantities of tree and blob items.
-    /// 2. If a sender is provided, send blob and tree data via the sender.
+    /// This function walks the given trees a level at a time, keeps track of processed objects,
+    /// and optionally sends the objects to a provided sender. The trees and blobs of a level
+    /// are loaded with one batched lookup each, instead of one per tree.
     ///
     /// # Parameters
-    /// - `tree`: The tree structure to traverse.
+    /// - `trees`: The tree structures to traverse.
     /// - `exist_objs`: A mutable reference to a set containing already processed object IDs.
     /// - `sender`: An optional sender for sending traversal data.
     ///
@@ -160,43 +188,41 @@ pub trait PackHandler: Send + Sync {
     /// - The function processes tree items, distinguishing between tree and blob items.
     /// - It collects IDs of items that have not been processed yet.
     /// - It retrieves and sends blob data if a sender is provided.
-    /// - It recursively traverses sub-trees.
-    /// - It sends the entire tree data if a sender is provided.
+    /// - It sends the trees of every level, `trees` included, if a sender is provided.
     async fn traverse(
         &self,
-        tree: Tree,
+        trees: Vec<Tree>,
         exist_objs: &mut HashSet<String>,
         sender: Option<&tokio::sync::mpsc::Sender<Entry>>,
     ) {
-        let mut search_tree_ids = vec![];
-        let mut search_blob_ids = vec![];
-
-        for item in &tree.tree_items {
-            let hash = item.id.to_string();
-            if exist_objs.insert(hash.clone()) {
-                if item.mode == TreeItemMode::Tree {
-                    search_tree_ids.push(hash);
-                } else {
-                    search_blob_ids.push(hash);
+        let mut level = trees;
+        while !level.is_empty() {
+            let mut search_tree_ids = vec![];
+            let mut search_blob_ids = vec![];
+            for tree in &level {
+                for item in &tree.tree_items {
+                    let hash = item.id.to_string();
+                    if exist_objs.insert(hash.clone()) {
+                        if item.mode == TreeItemMode::Tree {
+                            search_tree_ids.push(hash);
+                        } else {
+                            search_blob_ids.push(hash);
+                        }
+                    }
                 }
             }
-        }
 
-        if let Some(sender) = sender {
-            let blobs = self.get_blobs_by_hashes(search_blob_ids).await.unwrap();
-            for b in blobs {
-                let blob: Blob = b.into();
-                sender.send(blob.into()).await.unwrap();
+            if let Some(sender) = sender {
+                let blobs = self.get_blobs_by_hashes(search_blob_ids).await.unwrap();
+                for b in blobs {
+                    let blob: Blob = b.into();
+                    sender.send(blob.into()).await.unwrap();
+                }
+                for tree in level {
+                    sender.send(tree.into()).await.unwrap();
+                }
             }
-        }
-
-        let trees = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
-        for t in trees {
-            self.traverse(t, exist_objs, sender).await;
-        }
-
-        if let Some(sender) = sender {
-            sender.send(tree.into()).await.unwrap();
+            level = self.get_trees_by_hashes(search_tree_ids).await.unwrap();
         }
     }
 }
@@ -1,7 +1,6 @@
 use std::{
-    collections::{HashMap, HashSet},
-    path::{Component, PathBuf},
-    str::FromStr,
+    collections::HashSet,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicUsize, Ordering},
         mpsc::Receiver,
@@ -15,27 +14,29 @@ use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 
 use callisto::{db_enums::ConvType, raw_blob};
-use common::{
-    errors::MegaError,
-    utils::{self, MEGA_BRANCH_NAME},
+use common::{errors::MegaError, utils};
+use jupiter::{
+    context::Context,
+    storage::{mr_storage::MrStorage, stats_storage::Counter},
 };
-use jupiter::{context::Context, storage::mr_storage::MrStorage};
 use mercury::internal::{object::ObjectTrait, pack::encode::PackEncoder};
 use mercury::{
     errors::GitError,
-    hash::SHA1,
     internal::{
         object::{commit::Commit, tree::Tree, types::ObjectType},
         pack::entry::Entry,
     },
 };
+use taurus::event::mr_updated::{MrAction, MrUpdatedEvent};
 
 use crate::{
-    pack::PackHandler,
+    history, merge,
+    pack::{cache, PackHandler},
     protocol::{
         import_refs::{RefCommand, Refs},
         mr::MergeRequest,
     },
+    stats, subtree,
 };
 
 pub struct MonoRepo {
@@ -49,76 +50,18 @@ pub struct MonoRepo {
 impl PackHandler for MonoRepo {
     async fn head_hash(&self) -> (String, Vec<Refs>) {
         let storage = self.context.services.mono_storage.clone();
+        let path = self.path.to_str().unwrap();
+        if path != "/" {
+            self.sync_virtual_repo().await.unwrap();
+        }
 
-        let result = storage.get_refs(self.path.to_str().unwrap()).await.unwrap();
-        let refs = if !result.is_empty() {
-            let refs: Vec<Refs> = result.into_iter().map(|x| x.into()).collect();
-            refs
-        } else {
-            let target_path = self.path.clone();
-            let refs = storage.get_ref("/").await.unwrap().unwrap();
-            let tree_hash = refs.ref_tree_hash.clone();
-
-            let mut tree: Tree = storage
-                .get_tree_by_hash(&tree_hash)
-                .await
-                .unwrap()
-                .unwrap()
-                .into();
-
-            let commit: Commit = storage
-                .get_commit_by_hash(&refs.ref_commit_hash)
-                .await
-                .unwrap()
-                .unwrap()
-                .into();
-
-            for component in target_path.components() {
-                if component != Component::RootDir {
-                    let path_name = component.as_os_str().to_str().unwrap();
-                    let sha1 = tree
-                        .tree_items
-                        .iter()
-                        .find(|x| x.name == path_name)
-                        .map(|x| x.id);
-                    if let Some(sha1) = sha1 {
-                        tree = storage
-                            .get_trees_by_hashes(vec![sha1.to_string()])
-                            .await
-                            .unwrap()[0]
-                            .clone()
-                            .into();
-                    } else {
-                        return self.find_head_hash(vec![]);
-                    }
-                }
-            }
-
-            let c = Commit::new(
-                commit.author,
-                commit.committer,
-                tree.id,
-                vec![],
-                &commit.message,
-            );
-            storage
-                .save_ref(
-                    self.path.to_str().unwrap(),
-                    None,
-                    &c.id.to_string(),
-                    &c.tree_id.to_string(),
-                )
-                .await
-                .unwrap();
-            storage.save_mega_commits(vec![c.clone()]).await.unwrap();
-
-            vec![Refs {
-                ref_name: MEGA_BRANCH_NAME.to_string(),
-                ref_hash: c.id.to_string(),
-                default_branch: true,
-                ..Default::default()
-            }]
-        };
+        let result = storage.get_refs(path).await.unwrap();
+        // tags are not advertised, the pack does not carry tag objects yet
+        let refs: Vec<Refs> = result
+            .into_iter()
+            .filter(|x| !x.ref_name.starts_with(utils::TAG_REF_PREFIX))
+            .map(|x| x.into())
+            .collect();
         self.find_head_hash(refs)
     }
 
@@ -173,6 +116,17 @@ impl PackHandler for MonoRepo {
             .await
             .unwrap()
             .unwrap();
+        let want_c = want.first().unwrap();
+        if want.len() == 1 && refs.ref_commit_hash == *want_c {
+            // prebuilt by the repack maintenance job
+            if let Some(stream) = cache::open_pack(
+                &self.context.config.maintenance.pack_cache_path,
+                want_c,
+                pack_config.channel_message_size,
+            ) {
+                return Ok(stream);
+            }
+        }
         let commit: Commit = storage
             .get_commit_by_hash(&refs.ref_commit_hash)
             .await
@@ -188,16 +142,29 @@ impl PackHandler for MonoRepo {
         trees.push(tree.clone());
         let mut exist_objs = HashSet::new();
         let mut counted_obj = HashSet::new();
-        self.traverse_for_count(tree.clone(), &exist_objs, &mut counted_obj, &obj_num)
+        // directories are served with the history translated from the monorepo, trees shared
+        // by several commits are sent once
+        let history = self.load_history(&commit).await;
+        let mut top_trees = HashSet::new();
+        if !history.is_empty() {
+            top_trees.insert(tree.id.to_string());
+            let tree_ids = history
+                .iter()
+                .map(|c| c.tree_id.to_string())
+                .filter(|id| top_trees.insert(id.clone()))
+                .collect();
+            trees.extend(self.get_trees_by_hashes(tree_ids).await.unwrap());
+            counted_obj.extend(top_trees.clone());
+        }
+        self.traverse_for_count(trees.clone(), &exist_objs, &mut counted_obj, &obj_num)
             .await;
-        obj_num.fetch_add(1, Ordering::SeqCst);
+        obj_num.fetch_add(1 + history.len(), Ordering::SeqCst);
 
         exist_objs.extend(counted_obj.clone());
 
         let (entry_tx, entry_rx) = mpsc::channel(pack_config.channel_message_size);
         let (stream_tx, stream_rx) = mpsc::channel(pack_config.channel_message_size);
 
-        let want_c = want.first().unwrap();
         if refs.ref_commit_hash != *want_c {
             let refs = storage
                 .get_ref_by_commit(self.path.to_str().unwrap(), want_c)
@@ -217,7 +184,7 @@ impl PackHandler for MonoRepo {
                 .unwrap()
                 .into();
             trees.push(tree.clone());
-            self.traverse_for_count(tree, &exist_objs, &mut counted_obj, &obj_num)
+            self.traverse_for_count(vec![tree], &exist_objs, &mut counted_obj, &obj_num)
                 .await;
             obj_num.fetch_add(1, Ordering::SeqCst);
             entry_tx.send(commit.into()).await.unwrap();
@@ -225,12 +192,13 @@ impl PackHandler for MonoRepo {
 
         let encoder = PackEncoder::new(obj_num.into_inner(), 0, stream_tx);
         encoder.encode_async(entry_rx).await.unwrap();
-        let mut send_exist = HashSet::new();
-        for tree in trees {
-            self.traverse(tree, &mut send_exist, Some(&entry_tx))
-                .await;
-        }
+        let mut send_exist = top_trees;
+        self.traverse(trees, &mut send_exist, Some(&entry_tx))
+            .await;
         entry_tx.send(commit.into()).await.unwrap();
+        for c in history {
+            entry_tx.send(c.into()).await.unwrap();
+        }
         drop(entry_tx);
         Ok(ReceiverStream::new(stream_rx))
     }
@@ -240,87 +208,49 @@ impl PackHandler for MonoRepo {
         want: Vec<String>,
         have: Vec<String>,
     ) -> Result<ReceiverStream<Vec<u8>>, GitError> {
-        let mut want_clone = want.clone();
         let pack_config = &self.context.config.pack;
         let storage = self.context.services.mono_storage.clone();
         let obj_num = AtomicUsize::new(0);
 
         let mut exist_objs = HashSet::new();
 
-        let mut want_commits: Vec<Commit> = storage
-            .get_commits_by_hashes(&want_clone)
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|x| x.into())
-            .collect();
-        let mut traversal_list: Vec<Commit> = want_commits.clone();
-
-        // traverse commit's all parents to find the commit that client does not have
-        while let Some(temp) = traversal_list.pop() {
-            for p_commit_id in temp.parent_commit_ids {
-                let p_commit_id = p_commit_id.to_string();
-
-                if !have.contains(&p_commit_id) && !want_clone.contains(&p_commit_id) {
-                    let parent: Commit = storage
-                        .get_commit_by_hash(&p_commit_id)
-                        .await
-                        .unwrap()
-                        .unwrap()
-                        .into();
-                    want_commits.push(parent.clone());
-                    want_clone.push(p_commit_id);
-                    traversal_list.push(parent);
-                }
-            }
-        }
-
+        // the commits the client does not have
+        let want_commits = self.missing_commits(want, &have).await.unwrap();
         let want_tree_ids = want_commits
             .iter()
             .map(|c| c.tree_id.to_string())
             .collect();
-        let want_trees: HashMap<SHA1, Tree> = storage
-            .get_trees_by_hashes(want_tree_ids)
-            .await
-            .unwrap()
-            .into_iter()
-            .map(|m| (SHA1::from_str(&m.tree_id).unwrap(), m.into()))
-            .collect();
+        let want_trees = self.get_trees_by_hashes(want_tree_ids).await.unwrap();
 
         obj_num.fetch_add(want_commits.len(), Ordering::SeqCst);
 
         let have_commits = storage.get_commits_by_hashes(&have).await.unwrap();
-        let have_trees = storage
-            .get_trees_by_hashes(have_commits.iter().map(|x| x.tree.clone()).collect())
-            .await
-            .unwrap();
-        for have_tree in have_trees {
-            self.traverse(have_tree.into(), &mut exist_objs, None).await;
+        let mut have_tree_ids = vec![];
+        for have_commit in have_commits {
+            // use the reachability index built by the bitmap maintenance job if there is one
+            match cache::load_reachable(
+                &self.context.config.maintenance.pack_cache_path,
+                &have_commit.commit_id,
+            ) {
+                Some(objs) => exist_objs.extend(objs),
+                None => have_tree_ids.push(have_commit.tree),
+            }
         }
+        let have_trees = self.get_trees_by_hashes(have_tree_ids).await.unwrap();
+        self.traverse(have_trees, &mut exist_objs, None).await;
 
         let mut counted_obj = HashSet::new();
         // traverse for get obj nums
-        for c in want_commits.clone() {
-            self.traverse_for_count(
-                want_trees.get(&c.tree_id).unwrap().clone(),
-                &exist_objs,
-                &mut counted_obj,
-                &obj_num,
-            )
+        self.traverse_for_count(want_trees.clone(), &exist_objs, &mut counted_obj, &obj_num)
             .await;
-        }
         let (entry_tx, entry_rx) = mpsc::channel(pack_config.channel_message_size);
         let (stream_tx, stream_rx) = mpsc::channel(pack_config.channel_message_size);
         let encoder = PackEncoder::new(obj_num.into_inner(), 0, stream_tx);
         encoder.encode_async(entry_rx).await.unwrap();
 
-        for c in want_commits {
-            self.traverse(
-                want_trees.get(&c.tree_id).unwrap().clone(),
-                &mut exist_objs,
-                Some(&entry_tx),
-            )
+        self.traverse(want_trees, &mut exist_objs, Some(&entry_tx))
             .await;
+        for c in want_commits {
             entry_tx.send(c.into()).await.unwrap();
         }
         drop(entry_tx);
@@ -328,15 +258,28 @@ impl PackHandler for MonoRepo {
         Ok(ReceiverStream::new(stream_rx))
     }
 
+    async fn get_commits_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Commit>, MegaError> {
+        let commits = self
+            .context
+            .services
+            .mono_storage
+            .get_commits_by_hashes(&hashes)
+            .await?;
+        Ok(commits.into_iter().map(|x| x.into()).collect())
+    }
+
     async fn get_trees_by_hashes(&self, hashes: Vec<String>) -> Result<Vec<Tree>, MegaError> {
-        Ok(self
+        let trees = self
             .context
             .services
             .mono_storage
             .get_trees_by_hashes(hashes)
-            .await
-            .unwrap()
+            .await?;
+        // a tree is recorded once for every commit which added it
+        let mut seen = HashSet::new();
+        Ok(trees
             .into_iter()
+            .filter(|x| seen.insert(x.tree_id.clone()))
             .map(|x| x.into())
             .collect())
     }
@@ -356,10 +299,10 @@ impl PackHandler for MonoRepo {
         let storage = self.context.mr_stg();
         let path_str = self.path.to_str().unwrap();
 
-        match storage.get_open_mr_by_path(path_str).await.unwrap() {
+        let link = match storage.get_open_mr_by_path(path_str).await.unwrap() {
             Some(mr) => {
                 let mut mr = mr.into();
-                self.handle_existing_mr(&mut mr, &storage).await
+                self.handle_existing_mr(&mut mr, &storage).await?
             }
             None => {
                 if self.from_hash == "0".repeat(40) {
@@ -377,9 +320,13 @@ impl PackHandler for MonoRepo {
                     ..Default::default()
                 };
                 storage.save_mr(mr.clone().into()).await.unwrap();
-                Ok(link)
+                stats::count(&self.context, Counter::MrOpened).await;
+                MrUpdatedEvent::notify(&link, path_str, MrAction::Opened, None).await;
+                link
             }
-        }
+        };
+        merge::refresh(&self.context, &link).await;
+        Ok(link)
     }
 
     async fn update_refs(
@@ -391,13 +338,22 @@ impl PackHandler for MonoRepo {
         let ref_name = utils::mr_ref_name(&mr_link.unwrap());
 
         let storage = self.context.services.mono_storage.clone();
-        if let Some(mut mr_ref) = storage.get_mr_ref(&ref_name).await.unwrap() {
+        if let Some(mut mr_ref) = storage
+            .get_mr_ref(&ref_name)
+            .await
+            .map_err(|e| GitError::CustomError(e.to_string()))?
+        {
+            let old_commit = std::mem::take(&mut mr_ref.ref_commit_hash);
             mr_ref.ref_commit_hash = refs.new_id.clone();
             mr_ref.ref_tree_hash = commit.unwrap().tree_id.to_string();
-            storage.update_ref(mr_ref).await.unwrap();
+            storage
+                .update_ref(storage.get_connection(), mr_ref, &old_commit)
+                .await
+                .map_err(|e| GitError::CustomError(e.to_string()))?;
         } else {
             storage
                 .save_ref(
+                    storage.get_connection(),
                     self.path.to_str().unwrap(),
                     Some(ref_name),
                     &refs.new_id,
@@ -406,6 +362,13 @@ impl PackHandler for MonoRepo {
                 .await
                 .unwrap();
         }
+        history::record(
+            &self.context,
+            self.path.to_str().unwrap(),
+            &ref_name,
+            &refs.new_id,
+        )
+        .await;
         Ok(())
     }
 
@@ -425,6 +388,114 @@ impl PackHandler for MonoRepo {
 }
 
 impl MonoRepo {
+    /// Bring the main ref of a cloned directory up to the monorepo head.
+    ///
+    /// The monorepo commits made since the last sync are translated into commits of the
+    /// directory on top of the ref, at most `monorepo.virtual_history_depth` of them on the first
+    /// clone. A ref not written by the sync, like the snapshot of an earlier clone, is adopted and
+    /// only the latest state is added on top of it. The ref is left alone while a merge request
+    /// is open on the directory, merging expects it unchanged.
+    async fn sync_virtual_repo(&self) -> Result<(), MegaError> {
+        let storage = &self.context.services.mono_storage;
+        let path = self.path.to_str().unwrap();
+        let Some(root) = storage.get_ref("/").await? else {
+            return Ok(());
+        };
+        let main_ref = storage.get_ref(path).await?;
+        let virtual_repo = storage.get_virtual_repo(path).await?;
+        let mr_storage = self.context.mr_stg();
+        if main_ref.is_some() && mr_storage.get_open_mr_by_path(path).await?.is_some() {
+            return Ok(());
+        }
+        let synced = virtual_repo.as_ref().filter(|v| {
+            main_ref
+                .as_ref()
+                .is_none_or(|r| r.ref_commit_hash == v.commit_id)
+        });
+        if synced.is_some_and(|v| v.root_commit == root.ref_commit_hash) {
+            return Ok(());
+        }
+
+        let base = match (&main_ref, synced) {
+            (Some(r), _) => Some((r.ref_commit_hash.clone(), r.ref_tree_hash.clone())),
+            (None, Some(v)) => storage
+                .get_commit_by_hash(&v.commit_id)
+                .await?
+                .map(|c| (v.commit_id.clone(), c.tree)),
+            (None, None) => None,
+        };
+        let (since, limit) = match (synced, &base) {
+            (Some(v), _) => (Some(v.root_commit.as_str()), 0),
+            (None, Some(_)) => (None, 1),
+            (None, None) => (None, self.context.config.monorepo.virtual_history_depth),
+        };
+        let commits = subtree::translate_history(
+            storage,
+            &self.path,
+            &root.ref_commit_hash,
+            since,
+            base.clone(),
+            limit,
+        )
+        .await?;
+
+        let head = match commits.last() {
+            Some(c) => (c.id.to_string(), c.tree_id.to_string()),
+            None => match base {
+                Some(base) => base,
+                // the directory does not exist
+                None => return Ok(()),
+            },
+        };
+        // the ref never points to commits which are not saved
+        storage
+            .transaction(|txn| async move {
+                let conn = &*txn;
+                storage.save_mega_commits(conn, commits).await?;
+                match main_ref {
+                    Some(mut r) if r.ref_commit_hash != head.0 => {
+                        let old_commit = std::mem::replace(&mut r.ref_commit_hash, head.0.clone());
+                        r.ref_tree_hash = head.1;
+                        r.updated_at = chrono::Utc::now().naive_utc();
+                        storage.update_ref(conn, r, &old_commit).await?;
+                    }
+                    Some(_) => {}
+                    None => storage.save_ref(conn, path, None, &head.0, &head.1).await?,
+                }
+                storage
+                    .save_virtual_repo(conn, path, &root.ref_commit_hash, &head.0)
+                    .await?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Ancestors of `commit` stored in the monorepo, the translated history of a directory.
+    async fn load_history(&self, commit: &Commit) -> Vec<Commit> {
+        if self.path == Path::new("/") {
+            return vec![];
+        }
+        let storage = &self.context.services.mono_storage;
+        let mut visited = HashSet::new();
+        let mut pending: Vec<String> = commit
+            .parent_commit_ids
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let mut res = Vec::new();
+        while !pending.is_empty() {
+            pending.retain(|id| visited.insert(id.clone()));
+            let mut next = Vec::new();
+            for model in storage.get_commits_by_hashes(&pending).await.unwrap() {
+                let c: Commit = model.into();
+                next.extend(c.parent_commit_ids.iter().map(|p| p.to_string()));
+                res.push(c);
+            }
+            pending = next;
+        }
+        res
+    }
+
     async fn handle_existing_mr(
         &self,
         mr: &mut MergeRequest,
@@ -107,6 +107,10 @@ impl RefCommand {
         RefCommand::FAILED_STATUS.clone_into(&mut self.status);
         self.error_msg = msg;
     }
+
+    pub fn is_failed(&self) -> bool {
+        RefCommand::FAILED_STATUS == self.status
+    }
 }
 
 impl From<RefCommand> for import_refs::Model {
@@ -25,6 +25,8 @@ pub struct SmartProtocol {
     pub command_list: Vec<RefCommand>,
     pub service_type: Option<ServiceType>,
     pub context: Context,
+    /// Name of the authenticated user, recorded with the findings of secret scanning
+    pub username: Option<String>,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy, Default)]
@@ -133,6 +135,7 @@ impl SmartProtocol {
             command_list: Vec::new(),
             service_type: None,
             context,
+            username: None,
         }
     }
 
@@ -145,6 +148,7 @@ impl SmartProtocol {
             command_list: Vec::new(),
             service_type: None,
             context,
+            username: None,
         }
     }
 
@@ -153,9 +157,31 @@ impl SmartProtocol {
         if self.path.starts_with(import_dir.clone()) {
             let storage = self.context.services.git_db_storage.clone();
             let path_str = self.path.to_str().unwrap();
+            if self.service_type == Some(ServiceType::ReceivePack)
+                && self
+                    .context
+                    .services
+                    .mono_storage
+                    .get_split_by_repo_path(path_str)
+                    .await
+                    .unwrap()
+                    .is_some()
+            {
+                return Err(ProtocolError::Forbidden(
+                    "split repositories are read-only".to_owned(),
+                ));
+            }
             let model = storage.find_git_repo_exact_match(path_str).await.unwrap();
             let repo = if let Some(repo) = model {
                 repo.into()
+            } else if storage
+                .find_deleted_git_repo(path_str)
+                .await
+                .unwrap()
+                .is_some()
+            {
+                // the path stays taken by the deleted repository until it is purged
+                return Err(ProtocolError::NotFound("Repository not found.".to_owned()));
             } else {
                 match self.service_type.unwrap() {
                     ServiceType::UploadPack => {
@@ -42,6 +42,8 @@ impl From<Repo> for git_repo::Model {
             repo_name: value.repo_name,
             created_at: chrono::Utc::now().naive_utc(),
             updated_at: chrono::Utc::now().naive_utc(),
+            deleted_at: None,
+            deleted_by: None,
         }
     }
 }
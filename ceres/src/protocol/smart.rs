@@ -1,16 +1,39 @@
+use std::collections::HashMap;
 use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::Stream;
 use tokio_stream::wrappers::ReceiverStream;
 
-use callisto::db_enums::RefType;
+use callisto::db_enums::{FindingStatus, RefType, SecretAction};
+use callisto::{quarantined_push, secret_finding};
+use common::config::SecretScanPolicy;
 use common::errors::ProtocolError;
+use common::plugin::{Push, RefUpdate};
+use common::utils::{generate_id, glob_match, repo_path};
+use jupiter::storage::lock_repo;
+use jupiter::storage::stats_storage::Counter;
+use mercury::errors::GitError;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::Tree;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+use mercury::internal::pack::entry::Entry;
+use taurus::event::push::{PushEvent, PushedRef};
 
+use crate::maintenance;
+use crate::pack::secret_scan::{SecretMatch, SecretScanner};
+use crate::pack::PackHandler;
 use crate::protocol::import_refs::RefCommand;
 use crate::protocol::ZERO_ID;
 use crate::protocol::{Capability, ServiceType, SideBind, SmartProtocol, TransportProtocol};
+use crate::quota;
+use crate::signature;
+use crate::stats;
 
 const LF: char = '\n';
 
@@ -20,6 +43,8 @@ const NUL: char = '\0';
 
 pub const PKT_LINE_END_MARKER: &[u8; 4] = b"0000";
 
+const MB: usize = 1024 * 1024;
+
 // see https://git-scm.com/docs/protocol-capabilities
 // The atomic, report-status, report-status-v2, delete-refs, quiet,
 // and push-cert capabilities are sent and recognized by the receive-pack (push to server) process.
@@ -32,6 +57,18 @@ const COMMON_CAP_LIST: &str = "side-band-64k ofs-delta agent=mega/0.1.0";
 // All other capabilities are only recognized by the upload-pack (fetch from server) process.
 const UPLOAD_CAP_LIST: &str = "multi_ack_detailed no-done include-tag ";
 
+/// What was found in the entries of a push while they were passed on to be stored.
+#[derive(Default)]
+struct Inspection {
+    /// Blobs above the size limit with their size
+    oversized: Vec<(SHA1, usize)>,
+    /// Names of the tree entries, to describe the oversized blobs
+    names: HashMap<SHA1, String>,
+    findings: Vec<(String, SecretMatch)>,
+    /// Size of the blobs in bytes
+    size: u64,
+}
+
 impl SmartProtocol {
     /// # Retrieves the information about Git references (refs) for the specified service type.
     ///
@@ -57,6 +94,7 @@ impl SmartProtocol {
     /// Tracing information is logged regarding the response packet line stream.
     ///
     /// Finally, the constructed packet line stream is returned.
+    #[tracing::instrument(skip_all, fields(path = %self.path.display()))]
     pub async fn git_info_refs(&self) -> Result<BytesMut, ProtocolError> {
         let pack_handler = self.pack_handler().await?;
 
@@ -85,6 +123,7 @@ impl SmartProtocol {
         Ok(pkt_line_stream)
     }
 
+    #[tracing::instrument(skip_all, fields(path = %self.path.display()))]
     pub async fn git_upload_pack(
         &mut self,
         upload_request: &mut Bytes,
@@ -204,17 +243,72 @@ impl SmartProtocol {
         }
     }
 
+    #[tracing::instrument(skip_all, fields(path = %self.path.display()))]
     pub async fn git_receive_pack_stream(
         &mut self,
         data_stream: Pin<Box<dyn Stream<Item = Result<Bytes, axum::Error>> + Send>>,
     ) -> Result<Bytes, ProtocolError> {
+        // nothing is unpacked while the instance is in maintenance
+        if let Some(mode) = maintenance::mode::current(&self.context).await {
+            return Ok(self.reject_push("ok", &mode.message, &[]));
+        }
         // After receiving the pack data from the sender, the receiver sends a report
         let mut report_status = BytesMut::new();
         let pack_handler = self.pack_handler().await?;
+        let settings = self.context.settings();
+        let limits = &settings.push_limit;
+        if limits.max_ref_updates > 0 && self.command_list.len() > limits.max_ref_updates {
+            let reason = format!(
+                "push updates {} refs, at most {} are allowed per push",
+                self.command_list.len(),
+                limits.max_ref_updates
+            );
+            return Ok(self.reject_push("ok", &reason, &[]));
+        }
+        // push hooks of plugins refuse ref updates before the pack is received
+        let push = self.plugin_push();
+        if !push.refs.is_empty() {
+            for denial in self.context.plugins.pre_receive(&push).await {
+                self.deny_refs(&[denial.ref_name], &denial.reason);
+            }
+        }
+
         //1. unpack progress
-        let receiver = pack_handler
-            .unpack_stream(&self.context.config.pack, data_stream)
-            .await?;
+        let pack_limit = limits.pack_size_limit(&self.context.config.pack);
+        let receiver = match pack_handler
+            .unpack_stream(&self.context.config.pack, pack_limit, data_stream)
+            .await
+        {
+            Ok(receiver) => receiver,
+            Err(ProtocolError::TooLarge(_)) => {
+                let reason = format!(
+                    "pack exceeds the maximum push size of {} MB, use Git LFS for large files",
+                    pack_limit / MB
+                );
+                return Ok(self.reject_push("pack too large", &reason, &[]));
+            }
+            Err(err) => return Err(err),
+        };
+
+        // the entries are checked while they are stored, without holding the pack in memory;
+        // oversized files, pushes over quota and secrets refuse the ref updates, the objects
+        // of a refused push are not referenced and left to the object gc
+        let secret_scan = &settings.secret_scan;
+        let path = self.path.to_string_lossy().to_string();
+        let scanner = self.secret_scanner()?;
+        let inspection = Arc::new(Mutex::new(Inspection::default()));
+        let receiver = inspect_entries(
+            receiver,
+            inspection.clone(),
+            limits.max_file_size * MB,
+            scanner,
+        );
+        let signed = Arc::new(Mutex::new(Vec::new()));
+        let receiver = if settings.signature.verify_on_push {
+            collect_signed(receiver, signed.clone())
+        } else {
+            receiver
+        };
 
         // do not block main thread here.
         let handler_clone = pack_handler.clone();
@@ -225,30 +319,117 @@ impl SmartProtocol {
         .await
         .unwrap();
 
+        let inspection = std::mem::take(&mut *inspection.lock().unwrap());
+        let oversized = inspection.oversized_files();
+        if !oversized.is_empty() {
+            let reason = format!(
+                "{} files exceed the maximum file size of {} MB",
+                oversized.len(),
+                limits.max_file_size
+            );
+            return Ok(self.reject_push("ok", &reason, &oversized));
+        }
+        let over_quota = quota::check(&self.context, &path, inspection.size)
+            .await
+            .map_err(|err| {
+                ProtocolError::Forbidden(format!("storage quota check failed: {}", err))
+            })?;
+        if let Some(reason) = over_quota {
+            return Ok(self.reject_push("ok", &reason, &[]));
+        }
+        let findings = self.unreviewed_findings(inspection.findings).await;
+        let commit_id = match &unpack_result {
+            Ok(Some(commit)) => Some(commit.id.to_string()),
+            _ => None,
+        };
+        let blocked = self.record_findings(findings, commit_id).await;
+
+        let rejected = blocked.is_some() && secret_scan.policy == SecretScanPolicy::Reject;
+        if !rejected && unpack_result.is_ok() {
+            // usage is tracked even while quotas are disabled, so they are right once enabled
+            quota::record(&self.context, &path, inspection.size, 0).await;
+            stats::count(&self.context, Counter::Push).await;
+            if let Some(username) = &self.username {
+                stats::record_activity(&self.context, username).await;
+            }
+            // the push does not wait for signatures, commits not verified yet are verified
+            // when they are first shown
+            let entries = std::mem::take(&mut *signed.lock().unwrap());
+            if !entries.is_empty() {
+                let context = self.context.clone();
+                tokio::spawn(async move { signature::verify_entries(&context, entries).await });
+            }
+        }
+
         // write "unpack ok\n to report"
         add_pkt_line_string(&mut report_status, "unpack ok\n".to_owned());
 
+        //2. update each refs and build report
+        self.apply_ref_commands(&pack_handler, &unpack_result, blocked)
+            .await;
+        for command in &self.command_list {
+            add_pkt_line_string(&mut report_status, command.get_status());
+        }
+        report_status.put(&PKT_LINE_END_MARKER[..]);
+        let length = report_status.len();
+        let mut buf = self.build_side_band_format(report_status, length);
+        buf.put(&PKT_LINE_END_MARKER[..]);
+        Ok(buf.into())
+    }
+
+    /// Update the refs of the ref commands which did not fail, all of them fail with `blocked`
+    /// if it is set. `unpack_result` is the commit stored by the push to the monorepo.
+    async fn apply_ref_commands(
+        &mut self,
+        pack_handler: &Arc<dyn PackHandler>,
+        unpack_result: &Result<Option<Commit>, GitError>,
+        blocked: Option<String>,
+    ) {
+        let path = self.path.to_string_lossy().to_string();
         let mut default_exist = pack_handler.check_default_branch().await;
 
-        //2. update each refs and build report
+        // refs are updated with compare-and-swap, a ref changed since it was advertised is
+        // rejected with "fetch first"; the lock keeps pushes to the repository from interleaving
+        let lock = match lock_repo(self.context.services.mono_storage.get_connection(), &path).await
+        {
+            Ok(lock) => Some(lock),
+            Err(err) => {
+                tracing::error!("failed to lock {} for updating its refs: {}", path, err);
+                None
+            }
+        };
+        let blocked = if lock.is_some() {
+            blocked
+        } else {
+            blocked.or_else(|| Some("failed to lock the repository".to_owned()))
+        };
+
         for command in &mut self.command_list {
-            if command.ref_type == RefType::Tag {
+            if command.is_failed() {
+                // already refused before the pack was received, e.g. a protected tag
+            } else if let Some(reason) = &blocked {
+                command.failed(reason.clone());
+            } else if command.ref_type == RefType::Tag {
                 // just update if refs type is tag
-                pack_handler.update_refs(None, None, command).await.unwrap();
+                if let Err(e) = pack_handler.update_refs(None, None, command).await {
+                    command.failed(e.to_string());
+                }
             } else {
                 // Updates can be unsuccessful for a number of reasons.
                 // a.The reference can have changed since the reference discovery phase was originally sent, meaning someone pushed in the meantime.
                 // b.The reference being pushed could be a non-fast-forward reference and the update hooks or configuration could be set to not allow that, etc.
                 // c.Also, some references can be updated while others can be rejected.
-                match unpack_result {
+                match *unpack_result {
                     Ok(ref commit) => {
                         if let Some(c) = commit {
                             let mr_title = c.format_message();
                             if let Ok(mr_link) = pack_handler.handle_mr(&mr_title).await {
-                                pack_handler
+                                if let Err(e) = pack_handler
                                     .update_refs(Some(mr_link), Some(c.clone()), command)
                                     .await
-                                    .unwrap();
+                                {
+                                    command.failed(e.to_string());
+                                }
                             } else if let Err(e) = pack_handler.handle_mr(&mr_title).await {
                                 command.failed(e.to_string());
                             }
@@ -257,7 +438,9 @@ impl SmartProtocol {
                                 command.default_branch = true;
                                 default_exist = true;
                             }
-                            pack_handler.update_refs(None, None, command).await.unwrap();
+                            if let Err(e) = pack_handler.update_refs(None, None, command).await {
+                                command.failed(e.to_string());
+                            }
                         }
                     }
                     Err(ref err) => {
@@ -265,13 +448,265 @@ impl SmartProtocol {
                     }
                 }
             }
+        }
+        if let Some(lock) = lock {
+            if let Err(err) = lock.release().await {
+                tracing::error!("failed to unlock {}: {}", path, err);
+            }
+        }
+        let push = self.plugin_push();
+        if !push.refs.is_empty() {
+            push_event(&push).notify().await;
+            let context = self.context.clone();
+            tokio::spawn(async move { context.plugins.post_receive(&push).await });
+        }
+    }
+
+    /// Apply the ref updates held back by the quarantined `push`, as if they were pushed now.
+    /// Returns the updates with their status, a ref changed since the push is not updated.
+    pub async fn release_push(
+        &mut self,
+        push: &quarantined_push::Model,
+    ) -> Result<Vec<RefCommand>, ProtocolError> {
+        let updates: Vec<[String; 3]> = serde_json::from_str(&push.ref_updates)
+            .map_err(|err| ProtocolError::InvalidInput(err.to_string()))?;
+        self.service_type = Some(ServiceType::ReceivePack);
+        self.username.clone_from(&push.pusher);
+        self.command_list = updates
+            .into_iter()
+            .map(|[old_id, new_id, ref_name]| RefCommand::new(old_id, new_id, ref_name))
+            .collect();
+        let pack_handler = self.pack_handler().await?;
+        let commit = match &push.commit_id {
+            Some(id) => {
+                let commits = pack_handler
+                    .get_commits_by_hashes(vec![id.clone()])
+                    .await
+                    .map_err(|err| ProtocolError::NotFound(err.to_string()))?;
+                let commit = commits.into_iter().next();
+                Some(commit.ok_or_else(|| ProtocolError::NotFound(format!("commit {}", id)))?)
+            }
+            None => None,
+        };
+        self.apply_ref_commands(&pack_handler, &Ok(commit), None)
+            .await;
+        Ok(self.command_list.clone())
+    }
+
+    /// The push as the push hooks of plugins see it, with the ref updates which did not fail.
+    fn plugin_push(&self) -> Push {
+        Push {
+            path: repo_path(&self.path),
+            username: self.username.clone(),
+            refs: self
+                .command_list
+                .iter()
+                .filter(|c| !c.is_failed())
+                .map(|c| RefUpdate {
+                    name: c.ref_name.clone(),
+                    old_id: c.old_id.clone(),
+                    new_id: c.new_id.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Names of the pushed tags which match a tag protection rule of the repository.
+    ///
+    /// If the rules can't be loaded every pushed tag is treated as protected.
+    pub async fn protected_tags(&self) -> Vec<String> {
+        let tags: Vec<&RefCommand> = self
+            .command_list
+            .iter()
+            .filter(|c| c.ref_type == RefType::Tag)
+            .collect();
+        if tags.is_empty() {
+            return Vec::new();
+        }
+        let patterns: Vec<String> = match self
+            .context
+            .services
+            .mono_storage
+            .get_tag_rules(&self.path)
+            .await
+        {
+            Ok(rules) => rules.into_iter().map(|r| r.pattern).collect(),
+            Err(err) => {
+                tracing::error!("failed to load tag rules of {:?}: {}", self.path, err);
+                return tags.into_iter().map(|c| c.ref_name.clone()).collect();
+            }
+        };
+        tags.into_iter()
+            .filter(|c| is_protected_tag(&patterns, &c.ref_name))
+            .map(|c| c.ref_name.clone())
+            .collect()
+    }
+
+    /// Refuse the updates of `refs`, the remaining refs of the push are still processed.
+    pub fn deny_refs(&mut self, refs: &[String], reason: &str) {
+        for command in &mut self.command_list {
+            if refs.contains(&command.ref_name) {
+                command.failed(reason.to_owned());
+            }
+        }
+    }
+
+    /// Report a push refused before any ref was touched.
+    ///
+    /// Every ref update fails with `reason`, the reason and `details` are also shown to
+    /// the user through the progress sideband.
+    fn reject_push(&mut self, unpack_status: &str, reason: &str, details: &[String]) -> Bytes {
+        tracing::info!("push to {:?} rejected: {}", self.path, reason);
+        let mut buf = BytesMut::new();
+        for line in std::iter::once(reason).chain(details.iter().map(String::as_str)) {
+            buf.put(
+                self.build_side_band_message(SideBind::ProgressInfo, &format!("error: {}\n", line)),
+            );
+        }
+
+        let mut report_status = BytesMut::new();
+        add_pkt_line_string(&mut report_status, format!("unpack {}\n", unpack_status));
+        for command in &mut self.command_list {
+            command.failed(reason.to_owned());
             add_pkt_line_string(&mut report_status, command.get_status());
         }
         report_status.put(&PKT_LINE_END_MARKER[..]);
         let length = report_status.len();
-        let mut buf = self.build_side_band_format(report_status, length);
+        buf.put(self.build_side_band_format(report_status, length));
         buf.put(&PKT_LINE_END_MARKER[..]);
-        Ok(buf.into())
+        buf.into()
+    }
+
+    /// The scanner of pushed blobs, if secret scanning is enabled.
+    fn secret_scanner(&self) -> Result<Option<SecretScanner>, ProtocolError> {
+        let config = &self.context.settings().secret_scan;
+        if !config.enable {
+            return Ok(None);
+        }
+        let scanner = SecretScanner::new(config).map_err(|err| {
+            // fail closed, a broken rule must not let secrets through
+            ProtocolError::Forbidden(format!("secret scanning is misconfigured: {}", err))
+        })?;
+        Ok(Some(scanner))
+    }
+
+    /// Drop the findings of blobs which a reviewer marked as false positive before.
+    async fn unreviewed_findings(
+        &self,
+        mut findings: Vec<(String, SecretMatch)>,
+    ) -> Vec<(String, SecretMatch)> {
+        if !findings.is_empty() {
+            let blob_ids = findings.iter().map(|(id, _)| id.clone()).collect();
+            let allowed = self
+                .context
+                .services
+                .secret_storage
+                .get_allowed_blobs(blob_ids)
+                .await
+                .unwrap_or_default();
+            findings.retain(|(id, _)| !allowed.contains(id));
+        }
+        findings
+    }
+
+    /// Record the findings for review, returns why the push is refused if the policy blocks it.
+    /// The ref updates of a quarantined push are held back until a reviewer releases the push,
+    /// `commit_id` is the commit stored by a push to the monorepo.
+    async fn record_findings(
+        &self,
+        findings: Vec<(String, SecretMatch)>,
+        commit_id: Option<String>,
+    ) -> Option<String> {
+        let (first_blob, first) = findings.first()?;
+        let policy = self.context.settings().secret_scan.policy;
+        let action = match policy {
+            SecretScanPolicy::Reject => SecretAction::Rejected,
+            SecretScanPolicy::Quarantine => SecretAction::Quarantined,
+            SecretScanPolicy::Audit => SecretAction::Audited,
+        };
+        let summary = format!(
+            "{} in blob {} line {}{}",
+            first.rule,
+            first_blob,
+            first.line,
+            if findings.len() > 1 {
+                format!(" and {} more", findings.len() - 1)
+            } else {
+                String::new()
+            }
+        );
+        tracing::warn!(
+            "secret scan of push to {:?} by {:?}: {}",
+            self.path,
+            self.username,
+            summary
+        );
+
+        let now = chrono::Utc::now().naive_utc();
+        let push_id = (policy == SecretScanPolicy::Quarantine).then(generate_id);
+        let models = findings
+            .into_iter()
+            .map(|(blob_id, m)| secret_finding::Model {
+                id: generate_id(),
+                path: self.path.to_string_lossy().to_string(),
+                blob_id,
+                rule: m.rule,
+                line: m.line as i32,
+                preview: m.preview,
+                action,
+                pusher: self.username.clone(),
+                push_id,
+                status: FindingStatus::Open,
+                reviewer: None,
+                created_at: now,
+                reviewed_at: None,
+            })
+            .collect();
+        if let Err(err) = self
+            .context
+            .services
+            .secret_storage
+            .save_findings(models)
+            .await
+        {
+            tracing::error!("failed to record secret scan findings: {}", err);
+        }
+        if let Some(id) = push_id {
+            let updates: Vec<[&str; 3]> = self
+                .command_list
+                .iter()
+                .filter(|c| !c.is_failed())
+                .map(|c| [c.old_id.as_str(), c.new_id.as_str(), c.ref_name.as_str()])
+                .collect();
+            let push = quarantined_push::Model {
+                id,
+                path: self.path.to_string_lossy().to_string(),
+                pusher: self.username.clone(),
+                ref_updates: serde_json::to_string(&updates).unwrap(),
+                commit_id,
+                created_at: now,
+                released_at: None,
+                released_by: None,
+            };
+            if let Err(err) = self
+                .context
+                .services
+                .secret_storage
+                .save_quarantined_push(push)
+                .await
+            {
+                tracing::error!("failed to hold back the ref updates of the push: {}", err);
+            }
+        }
+
+        match policy {
+            SecretScanPolicy::Reject => Some(format!("secret detected: {}", summary)),
+            SecretScanPolicy::Quarantine => Some(format!(
+                "push quarantined for security review, secret detected: {}",
+                summary
+            )),
+            SecretScanPolicy::Audit => None,
+        }
     }
 
     /// # Builds the packet data in the sideband format if the SideBand/64k capability is enabled.
@@ -306,6 +741,20 @@ impl SmartProtocol {
         from_bytes
     }
 
+    /// Build a message for the progress or error sideband, the client prints it prefixed with
+    /// `remote:`. Returns nothing if the client did not ask for a sideband.
+    pub fn build_side_band_message(&self, band: SideBind, message: &str) -> BytesMut {
+        let mut to_bytes = BytesMut::new();
+        if self.capabilities.contains(&Capability::SideBand)
+            || self.capabilities.contains(&Capability::SideBand64k)
+        {
+            to_bytes.put(Bytes::from(format!("{:04x}", message.len() + 5)));
+            to_bytes.put_u8(band.value());
+            to_bytes.put(message.as_bytes());
+        }
+        to_bytes
+    }
+
     pub fn build_smart_reply(&self, ref_list: &Vec<String>, service: String) -> BytesMut {
         let mut pkt_line_stream = BytesMut::new();
         if self.transport_protocol == TransportProtocol::Http {
@@ -340,6 +789,130 @@ impl SmartProtocol {
     }
 }
 
+/// Whether `tag` matches one of the glob `patterns`, with or without its `refs/tags/` prefix.
+pub fn is_protected_tag(patterns: &[String], tag: &str) -> bool {
+    let short = tag.strip_prefix("refs/tags/").unwrap_or(tag);
+    patterns
+        .iter()
+        .any(|p| glob_match(p, tag) || glob_match(p, short))
+}
+
+impl Inspection {
+    /// Check the content of `entry`, only what is found is kept. Returns whether `entry` is a
+    /// blob above the size limit.
+    fn inspect(
+        &mut self,
+        entry: &Entry,
+        max_file_size: usize,
+        scanner: Option<&SecretScanner>,
+    ) -> bool {
+        match entry.obj_type {
+            ObjectType::Blob => {
+                self.size += entry.data.len() as u64;
+                if max_file_size > 0 && entry.data.len() > max_file_size {
+                    self.oversized.push((entry.hash, entry.data.len()));
+                    return true;
+                }
+                if let Some(scanner) = scanner {
+                    for m in scanner.scan(&entry.data) {
+                        self.findings.push((entry.hash.to_string(), m));
+                    }
+                }
+            }
+            // a tree may come before or after the blobs it points to
+            ObjectType::Tree if max_file_size > 0 => {
+                if let Ok(tree) = Tree::from_bytes(&entry.data, entry.hash) {
+                    for item in tree.tree_items {
+                        self.names.insert(item.id, item.name);
+                    }
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Describe the blobs above the size limit, named after the tree entries of the push which
+    /// point to them.
+    fn oversized_files(&self) -> Vec<String> {
+        self.oversized
+            .iter()
+            .map(|(hash, size)| {
+                let name = self
+                    .names
+                    .get(hash)
+                    .map(String::as_str)
+                    .unwrap_or("<unknown>");
+                format!(
+                    "{} ({}) is {:.1} MB, track it with Git LFS instead",
+                    name,
+                    hash,
+                    *size as f64 / MB as f64
+                )
+            })
+            .collect()
+    }
+}
+
+/// Pass the entries of `receiver` on while `inspection` checks their content, blobs above the
+/// size limit are not passed on.
+fn inspect_entries(
+    receiver: Receiver<Entry>,
+    inspection: Arc<Mutex<Inspection>>,
+    max_file_size: usize,
+    scanner: Option<SecretScanner>,
+) -> Receiver<Entry> {
+    let (sender, inspected) = mpsc::channel();
+    std::thread::spawn(move || {
+        for entry in receiver {
+            let mut found = inspection.lock().unwrap();
+            let oversized = found.inspect(&entry, max_file_size, scanner.as_ref());
+            drop(found);
+            // the push is refused once it is unpacked, an oversized file is never stored
+            if oversized {
+                continue;
+            }
+            if sender.send(entry).is_err() {
+                break;
+            }
+        }
+    });
+    inspected
+}
+
+/// Keep the commits and tags of a push which may be signed, to verify them once stored.
+fn collect_signed(receiver: Receiver<Entry>, signed: Arc<Mutex<Vec<Entry>>>) -> Receiver<Entry> {
+    let (sender, collected) = mpsc::channel();
+    std::thread::spawn(move || {
+        for entry in receiver {
+            if signature::maybe_signed(&entry) {
+                signed.lock().unwrap().push(entry.clone());
+            }
+            if sender.send(entry).is_err() {
+                break;
+            }
+        }
+    });
+    collected
+}
+
+/// The push as it is published to the message queue.
+fn push_event(push: &Push) -> PushEvent {
+    PushEvent {
+        path: push.path.clone(),
+        username: push.username.clone(),
+        refs: push
+            .refs
+            .iter()
+            .map(|r| PushedRef {
+                name: r.name.clone(),
+                old_id: r.old_id.clone(),
+                new_id: r.new_id.clone(),
+            })
+            .collect(),
+    }
+}
+
 fn read_until_white_space(bytes: &mut Bytes) -> String {
     let mut buf = Vec::new();
     while bytes.has_remaining() {
@@ -410,10 +983,15 @@ pub fn read_pkt_line(bytes: &mut Bytes) -> (usize, Bytes) {
 pub mod test {
     use bytes::{Bytes, BytesMut};
     use callisto::db_enums::RefType;
+    use mercury::internal::object::blob::Blob;
+    use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+    use mercury::internal::pack::entry::Entry;
 
     use crate::protocol::import_refs::{CommandType, RefCommand};
-    use crate::protocol::smart::{add_pkt_line_string, read_pkt_line, read_until_white_space};
-    use crate::protocol::{Capability, SmartProtocol};
+    use crate::protocol::smart::{
+        add_pkt_line_string, is_protected_tag, read_pkt_line, read_until_white_space, Inspection,
+    };
+    use crate::protocol::{Capability, SideBind, SmartProtocol};
 
     #[test]
     pub fn test_read_pkt_line() {
@@ -491,4 +1069,55 @@ pub mod test {
             vec![Capability::ReportStatusv2, Capability::SideBand64k]
         );
     }
+
+    #[test]
+    pub fn test_build_side_band_message() {
+        let mut mock = SmartProtocol::mock();
+        let msg = mock.build_side_band_message(SideBind::ProgressInfo, "error: denied\n");
+        assert!(msg.is_empty());
+
+        mock.capabilities.push(Capability::SideBand64k);
+        let msg = mock.build_side_band_message(SideBind::ProgressInfo, "error: denied\n");
+        assert_eq!(&msg[..], b"0013\x02error: denied\n");
+    }
+
+    #[test]
+    pub fn test_inspection_oversized_files() {
+        let small = Blob::from_content("small");
+        let large = Blob::from_content_bytes(vec![b'x'; 2048]);
+        let tree = Tree::from_tree_items(vec![
+            TreeItem::new(TreeItemMode::Blob, large.id, String::from("model.bin")),
+            TreeItem::new(TreeItemMode::Blob, small.id, String::from("README.md")),
+        ])
+        .unwrap();
+        let large_id = large.id;
+        let entries: Vec<Entry> = vec![small.into(), large.into(), tree.into()];
+        let inspect = |max_file_size| {
+            let mut inspection = Inspection::default();
+            let oversized: Vec<bool> = entries
+                .iter()
+                .map(|entry| inspection.inspect(entry, max_file_size, None))
+                .collect();
+            assert_eq!(oversized, [false, max_file_size == 1024, false]);
+            inspection
+        };
+
+        let inspection = inspect(1024);
+        let res = inspection.oversized_files();
+        assert_eq!(res.len(), 1);
+        assert!(res[0].starts_with(&format!("model.bin ({})", large_id)));
+        assert_eq!(inspection.size, 2048 + 5);
+        assert!(inspect(4096).oversized_files().is_empty());
+        assert!(inspect(0).oversized_files().is_empty());
+    }
+
+    #[test]
+    pub fn test_is_protected_tag() {
+        let patterns = vec![String::from("v*"), String::from("refs/tags/release-?")];
+        assert!(is_protected_tag(&patterns, "refs/tags/v1.0.0"));
+        assert!(is_protected_tag(&patterns, "refs/tags/release-1"));
+        assert!(!is_protected_tag(&patterns, "refs/tags/release-10"));
+        assert!(!is_protected_tag(&patterns, "refs/tags/nightly"));
+        assert!(!is_protected_tag(&[], "refs/tags/v1.0.0"));
+    }
 }
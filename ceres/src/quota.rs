@@ -0,0 +1,277 @@
+//! Storage quotas of repositories and namespaces.
+//!
+//! Every push adds the size of its objects to the usage of the repository it was made to, every
+//! LFS upload the size of its new objects to the repository named in the batch request, or to the
+//! monorepo root as LFS objects are shared by the whole instance. Writes which would take a
+//! repository or the namespace owning it over the limits of `quota` are refused, but not always
+//! before their objects are stored: a pack is stored while it is received and checked once it is
+//! complete, a push over quota has its ref updates refused and leaves its objects unreferenced
+//! for the object gc. LFS uploads are counted while they are streamed and aborted as soon as they
+//! go over, the chunks stored until then are left behind as well.
+//!
+//! A namespace is an organization or user, it uses the storage of every repository below the
+//! directories it owns. The repositories of a tenant also count towards the quota of the tenant.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use serde::Serialize;
+
+use common::config::QuotaConfig;
+use common::errors::MegaError;
+use jupiter::context::Context;
+
+use crate::tenant;
+
+const MB: u64 = 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NamespaceKind {
+    Org,
+    User,
+    Tenant,
+}
+
+/// The storage used by an organization, user or tenant.
+pub struct NamespaceUsage {
+    pub kind: NamespaceKind,
+    pub name: String,
+    /// Directories owned by the namespace
+    pub paths: Vec<String>,
+    pub git_size: i64,
+    pub lfs_size: i64,
+    /// In bytes, 0 is unlimited
+    pub limit: u64,
+}
+
+/// The size limit of the repository at `path` in bytes, 0 is unlimited.
+pub fn repo_limit(config: &QuotaConfig, path: &str) -> u64 {
+    config
+        .rules
+        .iter()
+        .filter(|r| Path::new(path).starts_with(&r.path))
+        .max_by_key(|r| r.path.len())
+        .map_or(config.max_repo_size, |r| r.max_size)
+        * MB
+}
+
+/// Why adding `size` bytes to the repository at `path` is refused, `None` if it stays within
+/// the quotas of the repository and its namespace. Pushes are checked after their pack is stored,
+/// with the size of the objects it contained.
+pub async fn check(context: &Context, path: &str, size: u64) -> Result<Option<String>, MegaError> {
+    let reason = limits(context, path)
+        .await?
+        .into_iter()
+        .find(|(_, used, limit)| used + size > *limit)
+        .map(|(owner, used, limit)| over_quota(&owner, used, size, limit));
+    Ok(reason)
+}
+
+/// How many more bytes the repository at `path` may store and whose quota limits them, `None`
+/// if it is unlimited.
+pub async fn available(context: &Context, path: &str) -> Result<Option<(u64, String)>, MegaError> {
+    let available = limits(context, path)
+        .await?
+        .into_iter()
+        .map(|(owner, used, limit)| (limit.saturating_sub(used), owner))
+        .min_by_key(|(available, _)| *available);
+    Ok(available)
+}
+
+/// The owner, used bytes and limit of every quota the repository at `path` counts towards.
+async fn limits(context: &Context, path: &str) -> Result<Vec<(String, u64, u64)>, MegaError> {
+    let settings = context.settings();
+    let config = &settings.quota;
+    let mut res = Vec::new();
+    if !config.enable {
+        return Ok(res);
+    }
+    let storage = &context.services.quota_storage;
+    let limit = repo_limit(config, path);
+    if limit > 0 {
+        let (git_size, lfs_size) = storage.get_usage_below(path).await?;
+        res.push((
+            format!("repository {}", path),
+            (git_size + lfs_size) as u64,
+            limit,
+        ));
+    }
+    let limit = config.max_namespace_size * MB;
+    if limit > 0 {
+        if let Some((name, paths)) = find_namespace(context, path).await? {
+            let mut used = 0;
+            for p in &paths {
+                let (git_size, lfs_size) = storage.get_usage_below(p).await?;
+                used += (git_size + lfs_size) as u64;
+            }
+            res.push((format!("namespace {}", name), used, limit));
+        }
+    }
+    if let Some(tenant) = tenant::of_path(&settings.tenancy, path) {
+        let limit = tenant.max_size * MB;
+        if limit > 0 {
+            let (git_size, lfs_size) = storage.get_usage_below(&tenant.path).await?;
+            res.push((
+                format!("tenant {}", tenant.name),
+                (git_size + lfs_size) as u64,
+                limit,
+            ));
+        }
+    }
+    Ok(res)
+}
+
+fn over_quota(owner: &str, used: u64, size: u64, limit: u64) -> String {
+    format!(
+        "storage quota of {} exceeded: {} MB of {} MB used, {} MB more requested",
+        owner,
+        used / MB,
+        limit / MB,
+        size.div_ceil(MB)
+    )
+}
+
+/// The name of the namespace owning `path` and all directories it owns, organizations take
+/// precedence over users.
+async fn find_namespace(
+    context: &Context,
+    path: &str,
+) -> Result<Option<(String, Vec<String>)>, MegaError> {
+    let storage = context.user_stg();
+    if let Some((_, org)) = storage.find_owner_org(Path::new(path)).await? {
+        let paths = storage.list_org_repos(org.id).await?;
+        return Ok(Some((
+            org.name,
+            paths.into_iter().map(|r| r.path).collect(),
+        )));
+    }
+    if let Some((_, user)) = storage.find_owner_user(Path::new(path)).await? {
+        let paths = storage.list_user_repos(user.id).await?;
+        return Ok(Some((
+            user.name,
+            paths.into_iter().map(|r| r.path).collect(),
+        )));
+    }
+    Ok(None)
+}
+
+/// Count `git_size` and `lfs_size` bytes stored for the repository at `path`, once the write
+/// was accepted.
+///
+/// The objects are already stored at this point, a failure is only logged.
+pub async fn record(context: &Context, path: &str, git_size: u64, lfs_size: u64) {
+    if git_size == 0 && lfs_size == 0 {
+        return;
+    }
+    let res = context
+        .services
+        .quota_storage
+        .add_usage(path, git_size as i64, lfs_size as i64)
+        .await;
+    if let Err(err) = res {
+        tracing::error!("failed to record storage usage of {}: {}", path, err);
+    }
+}
+
+/// The usage of every organization and user owning directories and of every tenant, largest
+/// first.
+pub async fn namespace_usage(context: &Context) -> Result<Vec<NamespaceUsage>, MegaError> {
+    let users = context.user_stg();
+    let mut owned: BTreeMap<(NamespaceKind, i64), Vec<String>> = BTreeMap::new();
+    for r in users.list_all_org_repos().await? {
+        owned
+            .entry((NamespaceKind::Org, r.org_id))
+            .or_default()
+            .push(r.path);
+    }
+    for r in users.list_all_user_repos().await? {
+        owned
+            .entry((NamespaceKind::User, r.user_id))
+            .or_default()
+            .push(r.path);
+    }
+    let ids = |kind| {
+        owned
+            .keys()
+            .filter(|(k, _)| *k == kind)
+            .map(|(_, id)| *id)
+            .collect()
+    };
+    let mut names: HashMap<(NamespaceKind, i64), String> = HashMap::new();
+    for org in users.find_orgs_by_ids(ids(NamespaceKind::Org)).await? {
+        names.insert((NamespaceKind::Org, org.id), org.name);
+    }
+    for user in users.find_users_by_ids(ids(NamespaceKind::User)).await? {
+        names.insert((NamespaceKind::User, user.id), user.name);
+    }
+
+    let storage = &context.services.quota_storage;
+    let limit = context.settings().quota.max_namespace_size * MB;
+    let mut res = Vec::new();
+    for (key, paths) in owned {
+        let Some(name) = names.remove(&key) else {
+            continue;
+        };
+        let (mut git_size, mut lfs_size) = (0, 0);
+        for p in &paths {
+            let (git, lfs) = storage.get_usage_below(p).await?;
+            git_size += git;
+            lfs_size += lfs;
+        }
+        res.push(NamespaceUsage {
+            kind: key.0,
+            name,
+            paths,
+            git_size,
+            lfs_size,
+            limit,
+        });
+    }
+    let settings = context.settings();
+    for tenant in tenant::tenants(&settings.tenancy) {
+        let (git_size, lfs_size) = storage.get_usage_below(&tenant.path).await?;
+        res.push(NamespaceUsage {
+            kind: NamespaceKind::Tenant,
+            name: tenant.name.clone(),
+            paths: vec![tenant.path.clone()],
+            git_size,
+            lfs_size,
+            limit: tenant.max_size * MB,
+        });
+    }
+    res.sort_by_key(|n| Reverse(n.git_size + n.lfs_size));
+    Ok(res)
+}
+
+#[cfg(test)]
+mod test {
+    use common::config::{QuotaConfig, QuotaRule};
+
+    use super::{repo_limit, MB};
+
+    #[test]
+    fn test_repo_limit() {
+        let config = QuotaConfig {
+            enable: true,
+            max_repo_size: 100,
+            max_namespace_size: 0,
+            rules: vec![
+                QuotaRule {
+                    path: "/third-part".to_owned(),
+                    max_size: 1000,
+                },
+                QuotaRule {
+                    path: "/third-part/unlimited".to_owned(),
+                    max_size: 0,
+                },
+            ],
+        };
+        assert_eq!(repo_limit(&config, "/project/mega"), 100 * MB);
+        assert_eq!(repo_limit(&config, "/third-part/crates"), 1000 * MB);
+        assert_eq!(repo_limit(&config, "/third-part/unlimited/repo"), 0);
+        // rules apply to whole path components
+        assert_eq!(repo_limit(&config, "/third-party"), 100 * MB);
+    }
+}
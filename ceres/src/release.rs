@@ -0,0 +1,383 @@
+//! Releases of import repositories and monorepo paths.
+//!
+//! A release is created from a tag of the repository, in the monorepo from a tag of the path. Its changelog lists the commits reachable
+//! from the tag but not from the tag of the previous release, followed by the merge requests
+//! merged below the repository path since the previous release. Assets attached to a release are
+//! kept in the object storage shared with LFS, addressed by the SHA-256 of their content.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use futures::TryStreamExt;
+use tokio::io::AsyncWriteExt;
+
+use callisto::db_enums::RefType;
+use callisto::{release, release_asset};
+use common::errors::MegaError;
+use common::utils::{generate_id, TAG_REF_PREFIX};
+use jupiter::context::Context;
+use jupiter::raw_storage::{file_stream, ByteStream};
+use mercury::internal::object::commit::Commit;
+
+/// Where the tags and commits of a release are stored.
+#[derive(Clone, Copy)]
+enum Repo {
+    Mono,
+    Import(i64),
+}
+
+/// Create a release of the repository at `path` from the tag `tag_name`, paths without an
+/// import repository are released from the monorepo.
+pub async fn create_release(
+    context: &Context,
+    path: &str,
+    tag_name: &str,
+    name: &str,
+    notes: &str,
+    created_by: &str,
+) -> Result<release::Model, MegaError> {
+    let release_storage = &context.services.release_storage;
+    let repo = match context
+        .services
+        .git_db_storage
+        .find_git_repo_exact_match(path)
+        .await?
+    {
+        Some(repo) => Repo::Import(repo.id),
+        None => Repo::Mono,
+    };
+    if release_storage
+        .get_release_by_tag(path, tag_name)
+        .await?
+        .is_some()
+    {
+        return Err(MegaError::with_message(
+            "release of this tag already exists",
+        ));
+    }
+    let commit_id = resolve_tag(context, repo, path, tag_name).await?;
+
+    let previous = release_storage.list_releases(path, 1).await?.pop();
+    let limit = context.settings().release.changelog_limit;
+    let exclude = match &previous {
+        Some(p) => walk_commits(context, repo, &p.commit_id, &HashSet::new(), None).await?,
+        None => vec![],
+    };
+    let exclude: HashSet<String> = exclude.iter().map(|c| c.id.to_string()).collect();
+    let mut commits = walk_commits(context, repo, &commit_id, &exclude, Some(limit + 1)).await?;
+    let truncated = commits.len() > limit;
+    commits.truncate(limit);
+    commits.sort_by(|a, b| b.committer.timestamp.cmp(&a.committer.timestamp));
+    let commits: Vec<(String, String)> = commits
+        .iter()
+        .map(|c| (c.id.to_string(), c.format_message()))
+        .collect();
+    let mrs: Vec<(String, String)> = context
+        .mr_stg()
+        .get_merged_mrs(path, previous.map(|p| p.created_at))
+        .await?
+        .into_iter()
+        .map(|mr| (mr.link, mr.title))
+        .collect();
+
+    let now = chrono::Utc::now().naive_utc();
+    let model = release::Model {
+        id: generate_id(),
+        path: path.to_owned(),
+        tag_name: tag_name.to_owned(),
+        commit_id,
+        name: match name.trim() {
+            "" => tag_name.to_owned(),
+            name => name.to_owned(),
+        },
+        notes: notes.to_owned(),
+        changelog: format_changelog(&commits, &mrs, truncated),
+        created_by: created_by.to_owned(),
+        created_at: now,
+        updated_at: now,
+    };
+    release_storage.save_release(model).await
+}
+
+/// The commit pointed to by a tag, annotated tags are peeled to their target.
+async fn resolve_tag(
+    context: &Context,
+    repo: Repo,
+    path: &str,
+    tag_name: &str,
+) -> Result<String, MegaError> {
+    let ref_name = format!("{}{}", TAG_REF_PREFIX, tag_name);
+    let not_found = || MegaError::with_message("tag not found");
+    let (target, tags) = match repo {
+        Repo::Mono => {
+            let storage = &context.services.mono_storage;
+            let tag = storage
+                .get_tag_refs(path)
+                .await?
+                .into_iter()
+                .find(|r| r.ref_name == ref_name)
+                .ok_or_else(not_found)?;
+            if storage
+                .get_commit_by_hash(&tag.ref_commit_hash)
+                .await?
+                .is_some()
+            {
+                return Ok(tag.ref_commit_hash);
+            }
+            let tags = storage
+                .get_tags_by_hashes(vec![tag.ref_commit_hash.clone()])
+                .await?
+                .into_iter()
+                .map(|t| (t.tag_id, t.object_type, t.object_id))
+                .collect::<Vec<_>>();
+            (tag.ref_commit_hash, tags)
+        }
+        Repo::Import(repo_id) => {
+            let storage = &context.services.git_db_storage;
+            let tag = storage
+                .get_ref(repo_id)
+                .await?
+                .into_iter()
+                .find(|r| r.ref_type == RefType::Tag && r.ref_name == ref_name)
+                .ok_or_else(not_found)?;
+            if storage
+                .get_commit_by_hash(repo_id, &tag.ref_git_id)
+                .await?
+                .is_some()
+            {
+                return Ok(tag.ref_git_id);
+            }
+            let tags = storage
+                .get_tags_by_repo_id(repo_id)
+                .await?
+                .into_iter()
+                .map(|t| (t.tag_id, t.object_type, t.object_id))
+                .collect::<Vec<_>>();
+            (tag.ref_git_id, tags)
+        }
+    };
+    tags.into_iter()
+        .find(|(id, object_type, _)| *id == target && object_type == "commit")
+        .map(|(_, _, object_id)| object_id)
+        .ok_or_else(|| MegaError::with_message("tag does not point to a commit"))
+}
+
+/// Commits reachable from `start` which are not in `exclude`, stopping after `limit` commits.
+async fn walk_commits(
+    context: &Context,
+    repo: Repo,
+    start: &str,
+    exclude: &HashSet<String>,
+    limit: Option<usize>,
+) -> Result<Vec<Commit>, MegaError> {
+    let mut visited: HashSet<String> = HashSet::new();
+    let mut pending = vec![start.to_owned()];
+    let mut res = Vec::new();
+    while !pending.is_empty() {
+        pending.retain(|id| !exclude.contains(id) && visited.insert(id.clone()));
+        let commits: Vec<Commit> = match repo {
+            Repo::Mono => context
+                .services
+                .mono_storage
+                .get_commits_by_hashes(&pending)
+                .await?
+                .into_iter()
+                .map(Commit::from)
+                .collect(),
+            Repo::Import(repo_id) => context
+                .services
+                .git_db_storage
+                .get_commits_by_hashes(repo_id, &pending)
+                .await?
+                .into_iter()
+                .map(Commit::from)
+                .collect(),
+        };
+        let mut next = Vec::new();
+        for commit in commits {
+            next.extend(commit.parent_commit_ids.iter().map(|p| p.to_string()));
+            res.push(commit);
+            if limit.is_some_and(|l| res.len() >= l) {
+                return Ok(res);
+            }
+        }
+        pending = next;
+    }
+    Ok(res)
+}
+
+/// Render the changelog from `(commit id, summary)` and `(merge request link, title)` pairs.
+pub fn format_changelog(
+    commits: &[(String, String)],
+    merge_requests: &[(String, String)],
+    truncated: bool,
+) -> String {
+    if commits.is_empty() && merge_requests.is_empty() {
+        return "No changes since the previous release.\n".to_owned();
+    }
+    let mut res = String::new();
+    if !commits.is_empty() {
+        res.push_str("## Commits\n\n");
+        for (id, summary) in commits {
+            res.push_str(&format!("- {} {}\n", &id[..id.len().min(7)], summary));
+        }
+        if truncated {
+            res.push_str("- ...\n");
+        }
+    }
+    if !merge_requests.is_empty() {
+        if !res.is_empty() {
+            res.push('\n');
+        }
+        res.push_str("## Merge Requests\n\n");
+        for (link, title) in merge_requests {
+            res.push_str(&format!("- {} {}\n", link, title));
+        }
+    }
+    res
+}
+
+/// Delete a release with its assets, objects still used by other assets or by LFS are kept.
+pub async fn delete_release(context: &Context, id: i64) -> Result<(), MegaError> {
+    let oids = context.services.release_storage.delete_release(id).await?;
+    remove_unused_objects(context, oids).await
+}
+
+/// Store `content` and attach it to the release as asset `name`.
+///
+/// The content is spooled to a file below `base_dir/tmp` while it is hashed, the object id is only
+/// known once the stream has ended.
+pub async fn add_asset(
+    context: &Context,
+    release_id: i64,
+    name: &str,
+    content_type: &str,
+    content: ByteStream,
+    uploaded_by: &str,
+) -> Result<release_asset::Model, MegaError> {
+    let release_storage = &context.services.release_storage;
+    if release_storage
+        .list_assets(release_id)
+        .await?
+        .iter()
+        .any(|a| a.name == name)
+    {
+        return Err(MegaError::with_message("asset already exists"));
+    }
+    let tmp_dir = context.config.base_dir.join("tmp");
+    tokio::fs::create_dir_all(&tmp_dir).await?;
+    let tmp_path = tmp_dir.join(format!("asset-{}", generate_id()));
+    let spooled = spool(content, &tmp_path).await;
+    let stored = match spooled {
+        Ok((oid, size)) => store_spooled(context, &tmp_path, &oid)
+            .await
+            .map(|_| (oid, size)),
+        Err(err) => Err(err),
+    };
+    let _ = tokio::fs::remove_file(&tmp_path).await;
+    let (oid, size) = stored?;
+    let model = release_asset::Model {
+        id: generate_id(),
+        release_id,
+        name: name.to_owned(),
+        content_type: content_type.to_owned(),
+        size,
+        oid,
+        download_count: 0,
+        uploaded_by: uploaded_by.to_owned(),
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    release_storage.save_asset(model).await
+}
+
+/// Write `content` to `path`, returns the SHA-256 and the size of the content.
+async fn spool(mut content: ByteStream, path: &Path) -> Result<(String, i64), MegaError> {
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut digest = ring::digest::Context::new(&ring::digest::SHA256);
+    let mut size = 0;
+    while let Some(chunk) = content.try_next().await? {
+        digest.update(&chunk);
+        size += chunk.len() as i64;
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok((hex::encode(digest.finish()), size))
+}
+
+async fn store_spooled(context: &Context, path: &Path, oid: &str) -> Result<(), MegaError> {
+    let storage = &context.services.lfs_storage;
+    if !storage.exist_object(oid) {
+        let file = tokio::fs::File::open(path).await?;
+        storage.put_object_stream(oid, file_stream(file)).await?;
+    }
+    Ok(())
+}
+
+/// Content of an asset, every call counts as a download.
+pub async fn download_asset(
+    context: &Context,
+    asset: &release_asset::Model,
+) -> Result<ByteStream, MegaError> {
+    let content = context
+        .services
+        .lfs_storage
+        .get_object_stream(&asset.oid)
+        .await?;
+    context
+        .services
+        .release_storage
+        .increase_download_count(asset.id)
+        .await?;
+    Ok(content)
+}
+
+pub async fn delete_asset(context: &Context, asset: release_asset::Model) -> Result<(), MegaError> {
+    context
+        .services
+        .release_storage
+        .delete_asset(asset.id)
+        .await?;
+    remove_unused_objects(context, vec![asset.oid]).await
+}
+
+async fn remove_unused_objects(context: &Context, oids: Vec<String>) -> Result<(), MegaError> {
+    let mut used = context.services.release_storage.list_asset_oids().await?;
+    used.extend(context.services.lfs_db_storage.list_lfs_oids().await?);
+    let storage = &context.services.lfs_storage;
+    for oid in oids {
+        if !used.contains(&oid) && storage.exist_object(&oid) {
+            storage.delete_object(&oid)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::format_changelog;
+
+    #[test]
+    fn test_format_changelog() {
+        let commits = vec![
+            (
+                "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_owned(),
+                "fix pack decode".to_owned(),
+            ),
+            ("1234567".to_owned(), "add releases".to_owned()),
+        ];
+        let mrs = vec![("KD3HF1".to_owned(), "Update docs".to_owned())];
+        assert_eq!(
+            format_changelog(&commits, &mrs, false),
+            "## Commits\n\n- 4b825dc fix pack decode\n- 1234567 add releases\n\n\
+             ## Merge Requests\n\n- KD3HF1 Update docs\n"
+        );
+        assert_eq!(
+            format_changelog(&commits[..1], &[], true),
+            "## Commits\n\n- 4b825dc fix pack decode\n- ...\n"
+        );
+        assert_eq!(
+            format_changelog(&[], &[], false),
+            "No changes since the previous release.\n"
+        );
+    }
+}
@@ -0,0 +1,404 @@
+//! Verification of signed commits and tags.
+//!
+//! Users register the GPG and SSH keys they sign with. A signature is verified when it was made
+//! by a signing key of the user whose email is the one of the committer or tagger, like git
+//! hosts usually do. Results are cached per object, they are dropped when the user adds a key or
+//! removes the key which verified them and computed again on the next request.
+
+use std::collections::HashMap;
+
+use callisto::db_enums::{SignatureStatus, SigningKeyType};
+use callisto::{object_signature, signing_key};
+use common::errors::MegaError;
+use common::utils::generate_id;
+use jupiter::context::Context;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tag::Tag;
+use mercury::internal::object::types::ObjectType;
+use mercury::internal::object::ObjectTrait;
+use mercury::internal::pack::entry::Entry;
+use pgp::composed::{Deserializable, SignedPublicKey, StandaloneSignature};
+use pgp::types::KeyTrait;
+use ssh_key::{HashAlg, PublicKey, SshSig};
+
+/// Namespace git signs commits and tags in with ssh keys.
+const SSH_NAMESPACE: &str = "git";
+const PGP_BEGIN: &[u8] = b"-----BEGIN PGP SIGNATURE-----";
+const SSH_BEGIN: &[u8] = b"-----BEGIN SSH SIGNATURE-----";
+
+/// Check a public key registered by a user, returns its type and fingerprint.
+pub fn parse_key(key: &str) -> Result<(SigningKeyType, String), MegaError> {
+    let key = key.trim();
+    if key.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+        let (public, _) = SignedPublicKey::from_string(key)
+            .map_err(|e| MegaError::with_message(&format!("invalid GPG key: {}", e)))?;
+        public
+            .verify()
+            .map_err(|e| MegaError::with_message(&format!("invalid GPG key: {}", e)))?;
+        Ok((SigningKeyType::Gpg, hex::encode_upper(public.fingerprint())))
+    } else {
+        let public = PublicKey::from_openssh(key)
+            .map_err(|e| MegaError::with_message(&format!("invalid SSH key: {}", e)))?;
+        Ok((
+            SigningKeyType::Ssh,
+            public.fingerprint(HashAlg::Sha256).to_string(),
+        ))
+    }
+}
+
+/// Split the encoded data of a commit into its signature and the data it signs, `None` if the
+/// commit is not signed.
+pub fn split_commit(data: &[u8]) -> Option<(String, Vec<u8>)> {
+    let mut payload = Vec::with_capacity(data.len());
+    let mut signature: Vec<u8> = Vec::new();
+    let mut in_header = true;
+    let mut in_signature = false;
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        if in_header {
+            if line == b"\n" {
+                in_header = false;
+            } else if in_signature && line.starts_with(b" ") {
+                signature.extend_from_slice(&line[1..]);
+                continue;
+            } else {
+                in_signature = false;
+                let value = line
+                    .strip_prefix(b"gpgsig ")
+                    .or_else(|| line.strip_prefix(b"gpgsig-sha256 "));
+                if let (Some(value), true) = (value, signature.is_empty()) {
+                    signature.extend_from_slice(value);
+                    in_signature = true;
+                    continue;
+                }
+            }
+        }
+        payload.extend_from_slice(line);
+    }
+    if signature.is_empty() {
+        return None;
+    }
+    Some((String::from_utf8_lossy(&signature).into_owned(), payload))
+}
+
+/// Split the encoded data of a tag into its signature and the data it signs, `None` if the tag
+/// is not signed.
+pub fn split_tag(data: &[u8]) -> Option<(String, Vec<u8>)> {
+    let start = [PGP_BEGIN, SSH_BEGIN]
+        .iter()
+        .filter_map(|begin| {
+            data.windows(begin.len())
+                .enumerate()
+                .filter(|(i, w)| w == begin && (*i == 0 || data[i - 1] == b'\n'))
+                .map(|(i, _)| i)
+                .last()
+        })
+        .max()?;
+    Some((
+        String::from_utf8_lossy(&data[start..]).into_owned(),
+        data[..start].to_vec(),
+    ))
+}
+
+/// Verify the signature of a commit and cache the result, `None` if it is not signed.
+pub async fn verify_commit(
+    context: &Context,
+    commit: &Commit,
+) -> Result<Option<object_signature::Model>, MegaError> {
+    let data = commit
+        .to_data()
+        .map_err(|e| MegaError::with_message(&e.to_string()))?;
+    let Some((signature, payload)) = split_commit(&data) else {
+        return Ok(None);
+    };
+    let id = commit.id.to_string();
+    let res = verify(context, &id, &commit.committer.email, &signature, &payload).await?;
+    Ok(Some(res))
+}
+
+/// Verify the signature of a tag and cache the result, `None` if it is not signed.
+pub async fn verify_tag(
+    context: &Context,
+    tag: &Tag,
+) -> Result<Option<object_signature::Model>, MegaError> {
+    let data = tag
+        .to_data()
+        .map_err(|e| MegaError::with_message(&e.to_string()))?;
+    let Some((signature, payload)) = split_tag(&data) else {
+        return Ok(None);
+    };
+    let id = tag.id.to_string();
+    let res = verify(context, &id, &tag.tagger.email, &signature, &payload).await?;
+    Ok(Some(res))
+}
+
+/// Verify the signed commits and tags among the objects of a push.
+pub async fn verify_entries(context: &Context, entries: Vec<Entry>) {
+    for entry in entries {
+        let res = match entry.obj_type {
+            ObjectType::Commit => match Commit::from_bytes(&entry.data, entry.hash) {
+                Ok(commit) => verify_commit(context, &commit).await,
+                Err(e) => Err(MegaError::with_message(&e.to_string())),
+            },
+            ObjectType::Tag => match Tag::from_bytes(&entry.data, entry.hash) {
+                Ok(tag) => verify_tag(context, &tag).await,
+                Err(e) => Err(MegaError::with_message(&e.to_string())),
+            },
+            _ => continue,
+        };
+        if let Err(err) = res {
+            tracing::error!("failed to verify the signature of {}: {}", entry.hash, err);
+        }
+    }
+}
+
+/// Whether the encoded commit or tag may be signed, checked before objects are decoded.
+pub fn maybe_signed(entry: &Entry) -> bool {
+    matches!(entry.obj_type, ObjectType::Commit | ObjectType::Tag)
+        && [PGP_BEGIN, SSH_BEGIN]
+            .iter()
+            .any(|begin| entry.data.windows(begin.len()).any(|w| w == *begin))
+}
+
+/// The signature status of each of `commits` for the commit badges, signed commits which were
+/// not verified yet are verified now.
+pub async fn commit_statuses(
+    context: &Context,
+    commits: &[Commit],
+) -> HashMap<String, SignatureStatus> {
+    let mut res = HashMap::new();
+    let mut signed = Vec::new();
+    for commit in commits {
+        let id = commit.id.to_string();
+        let is_signed = commit
+            .to_data()
+            .is_ok_and(|data| split_commit(&data).is_some());
+        if is_signed {
+            signed.push(commit);
+        } else {
+            res.insert(id, SignatureStatus::Unsigned);
+        }
+    }
+    if signed.is_empty() {
+        return res;
+    }
+    let ids = signed.iter().map(|c| c.id.to_string()).collect();
+    match context.services.signature_storage.get_signatures(ids).await {
+        Ok(cached) => res.extend(cached.into_iter().map(|s| (s.object_id, s.status))),
+        Err(err) => tracing::error!("failed to load signatures: {}", err),
+    }
+    for commit in signed {
+        let id = commit.id.to_string();
+        if res.contains_key(&id) {
+            continue;
+        }
+        let status = match verify_commit(context, commit).await {
+            Ok(Some(model)) => model.status,
+            Ok(None) => SignatureStatus::Unsigned,
+            Err(err) => {
+                tracing::error!("failed to verify the signature of {}: {}", id, err);
+                SignatureStatus::Unverified
+            }
+        };
+        res.insert(id, status);
+    }
+    res
+}
+
+/// Find the commit or tag `object_id` in the monorepo or the import repositories and verify its
+/// signature, the cached result is returned unless `refresh` is set.
+///
+/// Returns `None` if there is no such object, a result with the status `Unsigned` if it is not
+/// signed.
+pub async fn verify_object(
+    context: &Context,
+    object_id: &str,
+    refresh: bool,
+) -> Result<Option<object_signature::Model>, MegaError> {
+    let services = &context.services;
+    if !refresh {
+        let cached = services
+            .signature_storage
+            .get_signatures(vec![object_id.to_owned()])
+            .await?;
+        if let Some(model) = cached.into_iter().next() {
+            return Ok(Some(model));
+        }
+    }
+    let (res, email) = if let Some(commit) = find_commit(context, object_id).await? {
+        let email = commit.committer.email.clone();
+        (verify_commit(context, &commit).await?, email)
+    } else if let Some(tag) = services
+        .mono_storage
+        .get_tags_by_hashes(vec![object_id.to_owned()])
+        .await?
+        .into_iter()
+        .next()
+    {
+        let tag = Tag::from(tag);
+        let email = tag.tagger.email.clone();
+        (verify_tag(context, &tag).await?, email)
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(res.unwrap_or_else(|| object_signature::Model {
+        id: 0,
+        object_id: object_id.to_owned(),
+        status: SignatureStatus::Unsigned,
+        signer_email: email,
+        user_id: None,
+        key_id: None,
+        reason: None,
+        verified_at: chrono::Utc::now().naive_utc(),
+    })))
+}
+
+async fn find_commit(context: &Context, id: &str) -> Result<Option<Commit>, MegaError> {
+    let services = &context.services;
+    if let Some(model) = services.mono_storage.get_commit_by_hash(id).await? {
+        return Ok(Some(model.into()));
+    }
+    for repo in services.git_db_storage.find_git_repos_by_commit(id).await? {
+        if let Some(model) = services
+            .git_db_storage
+            .get_commit_by_hash(repo.id, id)
+            .await?
+        {
+            return Ok(Some(model.into()));
+        }
+    }
+    Ok(None)
+}
+
+/// Check `signature` against the signing keys of the user with the email `email` and cache
+/// the result for `object_id`.
+async fn verify(
+    context: &Context,
+    object_id: &str,
+    email: &str,
+    signature: &str,
+    payload: &[u8],
+) -> Result<object_signature::Model, MegaError> {
+    let storage = &context.services.signature_storage;
+    let mut model = object_signature::Model {
+        id: generate_id(),
+        object_id: object_id.to_owned(),
+        status: SignatureStatus::Unverified,
+        signer_email: email.to_owned(),
+        user_id: None,
+        key_id: None,
+        reason: None,
+        verified_at: chrono::Utc::now().naive_utc(),
+    };
+    match context.user_stg().find_user_by_email(email).await? {
+        Some(user) => {
+            model.user_id = Some(user.id);
+            let key_type = if signature.as_bytes().starts_with(SSH_BEGIN) {
+                SigningKeyType::Ssh
+            } else {
+                SigningKeyType::Gpg
+            };
+            let keys = storage.list_signing_keys(user.id).await?;
+            match keys
+                .iter()
+                .filter(|k| k.key_type == key_type)
+                .find(|k| check_signature(k, signature, payload))
+            {
+                Some(key) => {
+                    model.status = SignatureStatus::Verified;
+                    model.key_id = Some(key.id);
+                }
+                None => {
+                    model.reason = Some(format!(
+                        "signed by no {} signing key of {}",
+                        key_type, user.name
+                    ))
+                }
+            }
+        }
+        None => model.reason = Some(format!("no user has the email {}", email)),
+    }
+    storage.save_signature(model.clone()).await?;
+    Ok(model)
+}
+
+fn check_signature(key: &signing_key::Model, signature: &str, payload: &[u8]) -> bool {
+    let res = match key.key_type {
+        SigningKeyType::Gpg => verify_gpg(&key.key, signature, payload),
+        SigningKeyType::Ssh => verify_ssh(&key.key, signature, payload),
+    };
+    if let Err(err) = &res {
+        tracing::debug!("signing key {} does not match: {}", key.fingerprint, err);
+    }
+    res.is_ok()
+}
+
+fn verify_gpg(key: &str, signature: &str, payload: &[u8]) -> Result<(), MegaError> {
+    let err = |e: pgp::errors::Error| MegaError::with_message(&e.to_string());
+    let (key, _) = SignedPublicKey::from_string(key).map_err(err)?;
+    let (signature, _) = StandaloneSignature::from_string(signature).map_err(err)?;
+    // commits are usually signed with a signing subkey
+    if signature.verify(&key, payload).is_ok()
+        || key
+            .public_subkeys
+            .iter()
+            .any(|sub| signature.verify(sub, payload).is_ok())
+    {
+        return Ok(());
+    }
+    Err(MegaError::with_message("bad signature"))
+}
+
+fn verify_ssh(key: &str, signature: &str, payload: &[u8]) -> Result<(), MegaError> {
+    let err = |e: ssh_key::Error| MegaError::with_message(&e.to_string());
+    let key = PublicKey::from_openssh(key).map_err(err)?;
+    let signature = SshSig::from_pem(signature).map_err(err)?;
+    key.verify(SSH_NAMESPACE, payload, &signature).map_err(err)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_commit, split_tag};
+
+    #[test]
+    fn test_split_commit() {
+        let data = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author a <a@mega.org> 1700000000 +0800\n\
+            committer a <a@mega.org> 1700000000 +0800\n\
+            gpgsig -----BEGIN SSH SIGNATURE-----\n \
+            U1NIU0lH\n \
+            -----END SSH SIGNATURE-----\n\
+            \n\
+            message\n";
+        let (signature, payload) = split_commit(data).unwrap();
+        assert_eq!(
+            signature,
+            "-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----END SSH SIGNATURE-----\n"
+        );
+        assert_eq!(
+            payload,
+            b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author a <a@mega.org> 1700000000 +0800\n\
+            committer a <a@mega.org> 1700000000 +0800\n\
+            \n\
+            message\n"
+        );
+        assert!(split_commit(&payload).is_none());
+    }
+
+    #[test]
+    fn test_split_tag() {
+        let data = b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            type commit\n\
+            tag v1.0\n\
+            tagger a <a@mega.org> 1700000000 +0800\n\
+            \n\
+            release\n\
+            -----BEGIN PGP SIGNATURE-----\n\
+            iQ\n\
+            -----END PGP SIGNATURE-----\n";
+        let (signature, payload) = split_tag(data).unwrap();
+        assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----"));
+        assert!(payload.ends_with(b"release\n"));
+        assert!(split_tag(&payload).is_none());
+    }
+}
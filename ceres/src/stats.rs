@@ -0,0 +1,173 @@
+//! Statistics of the whole instance for capacity planning, reported by `mega stats` and the
+//! stats api.
+//!
+//! Pushes and merge requests are counted in rollups per day as they happen, and a user is
+//! recorded as active on a day the first time they push or use the api on it, so the statistics
+//! never scan the history. The storage is the usage tracked for the quotas, the depths of the
+//! queues are counted when asked for.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Mutex;
+
+use chrono::{Days, NaiveDate, Utc};
+use serde::Serialize;
+
+use callisto::db_enums::MergeStatus;
+use common::errors::MegaError;
+use jupiter::context::Context;
+use jupiter::storage::stats_storage::Counter;
+use jupiter::storage::TenantScope;
+
+/// Days users stay in the active users after they were last active.
+pub const ACTIVE_DAYS: u64 = 30;
+
+/// Days the activity of users is kept.
+const KEEP_ACTIVITY_DAYS: u64 = 400;
+
+/// The users already recorded as active today, so the api does not write on every request.
+static ACTIVE_TODAY: Mutex<Option<(NaiveDate, HashSet<String>)>> = Mutex::new(None);
+
+#[derive(Debug, Serialize)]
+pub struct InstanceStats {
+    /// Directories of the monorepo with a branch
+    pub monorepo_repos: u64,
+    pub import_repos: u64,
+    /// Bytes of git objects of all repositories
+    pub git_size: i64,
+    /// Bytes of LFS objects
+    pub lfs_size: i64,
+    pub users: u64,
+    pub deactivated_users: u64,
+    /// Users active in the last [`ACTIVE_DAYS`] days
+    pub active_users: u64,
+    pub open_mrs: u64,
+    /// Every day of the period, oldest first
+    pub days: Vec<DayStats>,
+    /// Messages of the queue by state
+    pub messages: BTreeMap<String, i64>,
+    /// Background jobs by state
+    pub jobs: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DayStats {
+    pub day: NaiveDate,
+    pub pushes: i64,
+    pub mrs_opened: i64,
+    pub mrs_merged: i64,
+    pub active_users: i64,
+}
+
+/// The statistics of the instance with the activity of the last `days` days, today included.
+pub async fn collect(context: &Context, days: u64) -> Result<InstanceStats, MegaError> {
+    let storage = &context.services.stats_storage;
+    let today = Utc::now().date_naive();
+    let since = today - Days::new(days.max(1) - 1);
+
+    let (monorepo_repos, import_repos) = storage.count_repos().await?;
+    let (git_size, lfs_size) = context.services.quota_storage.get_usage_below("/").await?;
+    let (users, deactivated_users) = storage.count_users().await?;
+    let active_users = storage
+        .count_active_users(today - Days::new(ACTIVE_DAYS - 1))
+        .await?;
+    let (_, open_mrs) = context
+        .mr_stg()
+        .get_mr_by_status(vec![MergeStatus::Open], &TenantScope::All, 1, 1)
+        .await?;
+
+    let mut by_day: HashMap<NaiveDate, DayStats> = storage
+        .list_days(since)
+        .await?
+        .into_iter()
+        .map(|d| {
+            let stats = DayStats {
+                day: d.day,
+                pushes: d.pushes,
+                mrs_opened: d.mrs_opened,
+                mrs_merged: d.mrs_merged,
+                active_users: 0,
+            };
+            (d.day, stats)
+        })
+        .collect();
+    for (day, count) in storage.active_users_by_day(since).await? {
+        by_day.entry(day).or_default().active_users = count;
+    }
+    let days = since
+        .iter_days()
+        .take_while(|day| *day <= today)
+        .map(|day| {
+            let mut stats = by_day.remove(&day).unwrap_or_default();
+            stats.day = day;
+            stats
+        })
+        .collect();
+
+    let messages = storage
+        .count_messages()
+        .await?
+        .into_iter()
+        .map(|(state, count)| (state.to_string(), count))
+        .collect();
+    let jobs = storage
+        .count_jobs()
+        .await?
+        .into_iter()
+        .map(|(state, count)| (state.to_string(), count))
+        .collect();
+
+    Ok(InstanceStats {
+        monorepo_repos,
+        import_repos,
+        git_size,
+        lfs_size,
+        users,
+        deactivated_users,
+        active_users,
+        open_mrs,
+        days,
+        messages,
+        jobs,
+    })
+}
+
+/// Count `counter` for today, a failure is only logged.
+pub async fn count(context: &Context, counter: Counter) {
+    let today = Utc::now().date_naive();
+    if let Err(err) = context.services.stats_storage.count(today, counter).await {
+        tracing::error!("failed to count {:?}: {}", counter, err);
+    }
+}
+
+/// Record `user_name` as active today, a failure is only logged.
+///
+/// Each user is written once a day per process. The activity older than [`KEEP_ACTIVITY_DAYS`]
+/// days is pruned when the first user is recorded on a day.
+pub async fn record_activity(context: &Context, user_name: &str) {
+    let today = Utc::now().date_naive();
+    let new_day = {
+        let mut active = ACTIVE_TODAY.lock().unwrap();
+        match active.as_mut() {
+            Some((day, users)) if *day == today => {
+                if !users.insert(user_name.to_owned()) {
+                    return;
+                }
+                false
+            }
+            _ => {
+                *active = Some((today, HashSet::from([user_name.to_owned()])));
+                true
+            }
+        }
+    };
+    let storage = &context.services.stats_storage;
+    if let Err(err) = storage.record_activity(today, user_name).await {
+        tracing::error!("failed to record the activity of {}: {}", user_name, err);
+    }
+    if new_day {
+        let before = today - Days::new(KEEP_ACTIVITY_DAYS);
+        if let Err(err) = storage.prune_activity(before).await {
+            tracing::error!("failed to prune the activity of users: {}", err);
+        }
+    }
+}
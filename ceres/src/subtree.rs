@@ -0,0 +1,100 @@
+//! Translation of monorepo history into the history of a single directory.
+//!
+//! Every monorepo commit which changes the directory becomes a commit whose tree is the
+//! directory, keeping author, committer and message. Commits which leave the directory untouched
+//! are dropped. The translation is deterministic, so split repositories and cloned directories
+//! of the same path end up with the same commit ids.
+
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use common::errors::MegaError;
+use jupiter::storage::mono_storage::MonoStorage;
+use mercury::hash::SHA1;
+use mercury::internal::object::commit::Commit;
+use mercury::internal::object::tree::{Tree, TreeItemMode};
+
+/// Id of the tree at `path` below the root tree `root`, `None` if the directory does not exist.
+pub async fn find_subtree(
+    storage: &MonoStorage,
+    root: &str,
+    path: &Path,
+) -> Result<Option<String>, MegaError> {
+    let mut tree_id = root.to_owned();
+    for component in path.components() {
+        let Component::Normal(name) = component else {
+            continue;
+        };
+        let Some(model) = storage.get_tree_by_hash(&tree_id).await? else {
+            return Ok(None);
+        };
+        let tree = Tree::from(model);
+        let Some(item) = tree.tree_items.iter().find(|item| {
+            item.mode == TreeItemMode::Tree && name.to_str() == Some(item.name.as_str())
+        }) else {
+            return Ok(None);
+        };
+        tree_id = item.id.to_string();
+    }
+    Ok(Some(tree_id))
+}
+
+/// Translate the monorepo commits from `head` back to `since` along first parents into commits
+/// of the directory `path`, oldest first.
+///
+/// `base` is the latest translated commit and its tree, the new commits build on top of it.
+/// At most `limit` monorepo commits are read, 0 reads the whole history.
+pub async fn translate_history(
+    storage: &MonoStorage,
+    path: &Path,
+    head: &str,
+    since: Option<&str>,
+    base: Option<(String, String)>,
+    limit: usize,
+) -> Result<Vec<Commit>, MegaError> {
+    let mut commits = Vec::new();
+    let mut next = Some(head.to_owned());
+    while let Some(id) = next {
+        if since == Some(id.as_str()) || (limit > 0 && commits.len() >= limit) {
+            break;
+        }
+        let Some(model) = storage.get_commit_by_hash(&id).await? else {
+            break;
+        };
+        let commit = Commit::from(model);
+        next = commit.parent_commit_ids.first().map(|p| p.to_string());
+        commits.push(commit);
+    }
+
+    let (mut parent, mut last_tree) = match base {
+        Some((commit, tree)) => (Some(commit), Some(tree)),
+        None => (None, None),
+    };
+    let mut res: Vec<Commit> = Vec::new();
+    for commit in commits.into_iter().rev() {
+        let root = commit.tree_id.to_string();
+        let Some(subtree) = find_subtree(storage, &root, path).await? else {
+            continue;
+        };
+        if last_tree.as_deref() == Some(subtree.as_str()) {
+            continue;
+        }
+        let parents = parent
+            .iter()
+            .map(|id| SHA1::from_str(id))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| MegaError::with_message(&err))?;
+        let tree_id = SHA1::from_str(&subtree).map_err(|err| MegaError::with_message(&err))?;
+        let translated = Commit::new(
+            commit.author,
+            commit.committer,
+            tree_id,
+            parents,
+            &commit.message,
+        );
+        parent = Some(translated.id.to_string());
+        last_tree = Some(subtree);
+        res.push(translated);
+    }
+    Ok(res)
+}
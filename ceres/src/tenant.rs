@@ -0,0 +1,131 @@
+//! Tenants, isolated top-level namespaces of one deployment, e.g. the organizations of a hosted
+//! instance, see `tenancy` of the config.
+//!
+//! Every tenant owns a top-level directory and has its own directory of users: a user belongs to
+//! the tenant stored with it, which new users get by the domain of their email, or else to the
+//! instance. Users of a tenant only reach the repositories below its directory and users of the
+//! instance none of them, saturn denies every request crossing tenants whatever the policies
+//! allow. Lists spanning repositories, like those of merge requests and issues, only return the
+//! entries of the tenant of the user.
+//!
+//! Tenants have their own storage quota and feature flags on top of those of the instance.
+
+use std::path::Path;
+
+use common::config::{TenancyConfig, TenantConfig};
+use common::errors::MegaError;
+use jupiter::context::Context;
+use jupiter::storage::TenantScope;
+
+/// The configured tenant `name`, `None` if it is not configured or tenancy is disabled.
+pub fn find<'a>(config: &'a TenancyConfig, name: &str) -> Option<&'a TenantConfig> {
+    tenants(config).find(|t| t.name == name)
+}
+
+/// The tenant owning the repository at `path`.
+pub fn of_path<'a>(config: &'a TenancyConfig, path: &str) -> Option<&'a TenantConfig> {
+    tenants(config).find(|t| Path::new(path).starts_with(&t.path))
+}
+
+/// The tenant a new user with `email` joins.
+pub fn of_email<'a>(config: &'a TenancyConfig, email: &str) -> Option<&'a TenantConfig> {
+    let (_, domain) = email.rsplit_once('@')?;
+    tenants(config).find(|t| {
+        t.email_domains
+            .iter()
+            .any(|d| d.eq_ignore_ascii_case(domain))
+    })
+}
+
+/// The tenant of the user `username`, `None` for users of the instance and when tenancy is
+/// disabled.
+pub async fn of_user(context: &Context, username: &str) -> Result<Option<String>, MegaError> {
+    if !context.settings().tenancy.enable {
+        return Ok(None);
+    }
+    let user = context.user_stg().find_user_by_name(username).await?;
+    Ok(user.and_then(|u| u.tenant))
+}
+
+/// What the queries for a user of `tenant`, `None` for users of the instance, may return.
+///
+/// A user of a tenant which is no longer configured gets nothing, its directory is unknown.
+pub fn scope(config: &TenancyConfig, tenant: Option<&str>) -> TenantScope {
+    if !config.enable {
+        return TenantScope::All;
+    }
+    match tenant {
+        Some(name) => match find(config, name) {
+            Some(tenant) => TenantScope::Tenant {
+                name: tenant.name.clone(),
+                path: tenant.path.clone(),
+            },
+            None => TenantScope::Nothing,
+        },
+        None => TenantScope::Instance {
+            paths: config.tenants.iter().map(|t| t.path.clone()).collect(),
+        },
+    }
+}
+
+/// The scope of the queries made for `username`, anonymous users get those of the instance,
+/// see [`scope`].
+pub async fn user_scope(
+    context: &Context,
+    username: Option<&str>,
+) -> Result<TenantScope, MegaError> {
+    let tenant = match username {
+        Some(username) => of_user(context, username).await?,
+        None => None,
+    };
+    Ok(scope(&context.settings().tenancy, tenant.as_deref()))
+}
+
+/// The configured tenants, none if tenancy is disabled.
+pub fn tenants(config: &TenancyConfig) -> impl Iterator<Item = &TenantConfig> {
+    config.tenants.iter().filter(|_| config.enable)
+}
+
+#[cfg(test)]
+mod test {
+    use common::config::{TenancyConfig, TenantConfig};
+    use jupiter::storage::TenantScope;
+
+    use super::{of_email, of_path, scope};
+
+    #[test]
+    fn test_tenants() {
+        let mut config = TenancyConfig {
+            enable: true,
+            tenants: vec![TenantConfig {
+                name: "acme".to_owned(),
+                path: "/acme".to_owned(),
+                email_domains: vec!["acme.com".to_owned()],
+                ..Default::default()
+            }],
+        };
+        assert_eq!(of_path(&config, "/acme/mega").unwrap().name, "acme");
+        assert!(of_path(&config, "/acme-labs").is_none());
+        assert_eq!(of_email(&config, "alice@ACME.com").unwrap().name, "acme");
+        assert!(of_email(&config, "bob@acme.com.evil").is_none());
+
+        assert_eq!(
+            scope(&config, Some("acme")),
+            TenantScope::Tenant {
+                name: "acme".to_owned(),
+                path: "/acme".to_owned()
+            }
+        );
+        assert_eq!(scope(&config, Some("globex")), TenantScope::Nothing);
+        assert_eq!(
+            scope(&config, None),
+            TenantScope::Instance {
+                paths: vec!["/acme".to_owned()]
+            }
+        );
+
+        config.enable = false;
+        assert!(of_path(&config, "/acme/mega").is_none());
+        assert_eq!(scope(&config, Some("acme")), TenantScope::All);
+    }
+}
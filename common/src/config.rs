@@ -1,12 +1,21 @@
-use c::{ConfigError, FileFormat};
+use arc_swap::ArcSwap;
+pub use c::ConfigError;
+use c::FileFormat;
 use config as c;
 use config::builder::DefaultState;
 use config::{Source, ValueKind};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use crate::cron::CronSchedule;
+use crate::network::{IpCidr, NetworkPolicy};
+use crate::secrets::Secrets;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
@@ -21,23 +30,600 @@ pub struct Config {
     // Not used in mega app
     #[serde(default)]
     pub oauth: Option<OauthConfig>,
+    #[serde(default)]
+    pub network_policy: NetworkPolicyConfig,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+    #[serde(default)]
+    pub secret_scan: SecretScanConfig,
+    #[serde(default)]
+    pub push_limit: PushLimitConfig,
+    #[serde(default)]
+    pub release: ReleaseConfig,
+    #[serde(default)]
+    pub ssh: SshConfig,
+    #[serde(default)]
+    pub gateway: GatewayConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    #[serde(default)]
+    pub mq: MqConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub signature: SignatureConfig,
+    #[serde(default)]
+    pub jobs: JobConfig,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub idp_sync: IdpSyncConfig,
+    #[serde(default)]
+    pub features: FeatureConfig,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub tenancy: TenancyConfig,
+    #[serde(default)]
+    pub plugins: PluginConfig,
+    #[serde(default)]
+    pub p2p: P2pConfig,
+    /// File the config was read from, it is read again when the config is reloaded
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
 }
 
 impl Config {
+    /// Read the config file at `path`, the `MEGA_*` environment variables override its keys.
+    ///
+    /// Keys of the file mega does not know are ignored with a warning.
     pub fn new(path: &str) -> Result<Self, ConfigError> {
+        let (config, unknown) = Self::load(path, None)?;
+        for key in unknown {
+            eprintln!("unknown key `{}` in {} is ignored", key, path);
+        }
+        Ok(config)
+    }
+
+    /// Read the config file with the variables of `env` instead of the process environment if
+    /// it is `Some`, with the keys of the file which are not known.
+    fn load(
+        path: &str,
+        env: Option<c::Map<String, String>>,
+    ) -> Result<(Self, Vec<String>), ConfigError> {
+        let file = c::File::new(path, FileFormat::Toml);
         let builder = c::Config::builder()
-            .add_source(c::File::new(path, FileFormat::Toml))
-            .add_source(c::Environment::with_prefix("mega").prefix_separator("_").separator("__")); // e.g. MEGA_BASE_DIR == base_dir
-                                                              // support ${} variable substitution
-        let config = variable_placeholder_substitute(builder);
+            .add_source(file.clone())
+            .add_source(environment().source(env));
+        // support ${} variable substitution
+        let config = variable_placeholder_substitute(builder)?;
+        let mut config = Config::from_config(resolve_secrets(config, &Secrets::default())?)?;
+        config.path = Some(PathBuf::from(path));
 
-        Config::from_config(config)
+        let file = c::Config::builder().add_source(file).build()?;
+        let unknown = unknown_keys(&file, &config);
+        Ok((config, unknown))
     }
 
     pub fn from_config(config: c::Config) -> Result<Self, c::ConfigError> {
         // config.get::<Self>(env!("CARGO_PKG_NAME"))
         config.try_deserialize::<Config>()
     }
+
+    /// Read the config file at `path` like [`Config::new`] and check its settings.
+    pub fn check(path: &str) -> Result<ConfigCheck, ConfigError> {
+        let (config, unknown_keys) = Self::load(path, None)?;
+        let errors = config.validate();
+        Ok(ConfigCheck {
+            config,
+            unknown_keys,
+            errors,
+        })
+    }
+
+    /// Settings which are invalid or contradict each other.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let db = &self.database;
+        match db.db_type.as_str() {
+            "sqlite" if db.db_path.is_empty() => {
+                errors.push("`database.db_path` is required for sqlite".to_owned())
+            }
+            "postgres" | "mysql" if db.db_url.is_empty() => {
+                errors.push(format!("`database.db_url` is required for {}", db.db_type))
+            }
+            "sqlite" | "postgres" | "mysql" => {}
+            other => errors.push(format!(
+                "`database.db_type` {} is not one of sqlite, postgres and mysql",
+                other
+            )),
+        }
+        if db.min_connection > db.max_connection {
+            errors.push(format!(
+                "`database.min_connection` {} is above `database.max_connection` {}",
+                db.min_connection, db.max_connection
+            ));
+        }
+        if !matches!(
+            self.log.level.as_str(),
+            "trace" | "debug" | "info" | "warn" | "error"
+        ) {
+            errors.push(format!(
+                "`log.level` {} is not one of trace, debug, info, warn and error",
+                self.log.level
+            ));
+        }
+        let telemetry = &self.telemetry;
+        if telemetry.enable && telemetry.endpoint.is_empty() {
+            errors.push("`telemetry.endpoint` is required to export traces".to_owned());
+        }
+        if !(0.0..=1.0).contains(&telemetry.sample_ratio) {
+            errors.push(format!(
+                "`telemetry.sample_ratio` {} is not between 0 and 1",
+                telemetry.sample_ratio
+            ));
+        }
+
+        let storage = &self.storage;
+        match storage.raw_obj_storage_type {
+            RawStorageType::Local if storage.raw_obj_local_path.as_os_str().is_empty() => {
+                errors.push("`storage.raw_obj_local_path` is required for local storage".to_owned())
+            }
+            RawStorageType::S3 | RawStorageType::Azure | RawStorageType::Gcs
+                if storage.obs_bucket.is_empty() =>
+            {
+                errors.push(format!(
+                    "`storage.obs_bucket` is required for {:?} storage",
+                    storage.raw_obj_storage_type
+                ))
+            }
+            RawStorageType::Plugin if storage.plugin.is_empty() => {
+                errors.push("`storage.plugin` is required for plugin storage".to_owned())
+            }
+            _ => {}
+        }
+        let encryption = &storage.encryption;
+        if encryption.enable && encryption.key_id.is_empty() {
+            errors.push("`storage.encryption.key_id` is required to encrypt objects".to_owned());
+        } else if encryption.enable
+            && !encryption.keys.contains_key(&encryption.key_id)
+            && encryption.key_command.is_empty()
+        {
+            errors.push(format!(
+                "master key {} is not in `storage.encryption.keys` and there is no `key_command`",
+                encryption.key_id
+            ));
+        }
+
+        // a path in the monorepo, which has no drive on Windows
+        if !self.monorepo.import_dir.has_root() {
+            errors.push("`monorepo.import_dir` must be absolute, like /third-part".to_owned());
+        }
+        let auth = &self.authentication;
+        if auth.enable_test_user && auth.test_user_token.is_empty() {
+            errors.push(
+                "`authentication.test_user_token` is required to enable the test user".to_owned(),
+            );
+        }
+        if self.ssh.auth_methods.is_empty() {
+            errors.push("`ssh.auth_methods` is empty, nobody can sign in with ssh".to_owned());
+        }
+        for method in &self.ssh.auth_methods {
+            if !matches!(
+                method.as_str(),
+                "publickey" | "password" | "keyboard-interactive"
+            ) {
+                errors.push(format!(
+                    "`ssh.auth_methods` {} is not publickey, password or keyboard-interactive",
+                    method
+                ));
+            }
+        }
+        if let Err(err) = NetworkPolicy::new(&self.network_policy) {
+            errors.push(format!("`network_policy` {}", err));
+        }
+
+        let maintenance = &self.maintenance;
+        let schedules = [
+            ("repack", &maintenance.repack),
+            ("commit_graph", &maintenance.commit_graph),
+            ("bitmap", &maintenance.bitmap),
+            ("lfs_gc", &maintenance.lfs_gc),
+            ("stale_cleanup", &maintenance.stale_cleanup),
+            ("verify", &maintenance.verify),
+            ("branch_cleanup", &maintenance.branch_cleanup),
+            ("subtree_split", &maintenance.subtree_split),
+            ("repo_purge", &maintenance.repo_purge),
+            ("object_gc", &maintenance.object_gc),
+            ("grant_expiry", &maintenance.grant_expiry),
+            ("idp_sync", &maintenance.idp_sync),
+        ];
+        for (name, schedule) in schedules {
+            // an empty schedule disables the job
+            if schedule.is_empty() {
+                continue;
+            }
+            if let Err(err) = schedule.parse::<CronSchedule>() {
+                errors.push(format!("`maintenance.{}` {}", name, err));
+            }
+        }
+        for rule in &self.secret_scan.rules {
+            if let Err(err) = Regex::new(&rule.pattern) {
+                errors.push(format!("`secret_scan.rules` {}: {}", rule.name, err));
+            }
+        }
+        if !self.idp_sync.mappings.is_empty() && self.idp_sync.scim_url.is_empty() {
+            errors.push("`idp_sync.scim_url` is required for `idp_sync.mappings`".to_owned());
+        }
+        if self.lfs.enable_split && self.lfs.split_size == 0 {
+            errors.push("`lfs.split_size` must be above 0 to split lfs objects".to_owned());
+        }
+        if self.jobs.enable && self.jobs.workers == 0 {
+            errors.push("`jobs.workers` must be above 0 to run jobs".to_owned());
+        }
+        if self.mq.broker == MqBroker::Database {
+            let concurrency = &self.mq.concurrency;
+            for (lane, value) in [
+                ("interactive", concurrency.interactive),
+                ("background", concurrency.background),
+            ] {
+                if value == 0 {
+                    errors.push(format!(
+                        "`mq.concurrency.{}` must be above 0 for the database broker",
+                        lane
+                    ));
+                }
+            }
+        }
+        if self.mq.batch.max_messages == 0 {
+            errors.push("`mq.batch.max_messages` must be above 0".to_owned());
+        }
+        if self.mq.broker == MqBroker::Kafka && self.mq.kafka.brokers.is_empty() {
+            errors.push("`mq.kafka.brokers` is required for the kafka broker".to_owned());
+        }
+        if self.mq.broker == MqBroker::Nats && self.mq.nats.url.is_empty() {
+            errors.push("`mq.nats.url` is required for the nats broker".to_owned());
+        }
+        if self.p2p.sync_interval == 0 {
+            errors.push("`p2p.sync_interval` must be above 0".to_owned());
+        }
+        if self.p2p.nat.enable && self.p2p.nat.punch_timeout == 0 {
+            errors.push("`p2p.nat.punch_timeout` must be above 0".to_owned());
+        }
+        if self.p2p.noise.enable && self.p2p.noise.rekey_interval == 0 {
+            errors.push("`p2p.noise.rekey_interval` must be above 0".to_owned());
+        }
+        for network in &self.p2p.lfs.networks {
+            if let Err(err) = network.parse::<IpCidr>() {
+                errors.push(format!("`p2p.lfs.networks` {}", err));
+            }
+        }
+        if self.p2p.relay.max_peers == 0 {
+            errors.push("`p2p.relay.max_peers` must be above 0".to_owned());
+        }
+        let dht = &self.p2p.dht;
+        if dht.k == 0 {
+            errors.push("`p2p.dht.k` must be above 0".to_owned());
+        }
+        if dht.alpha == 0 {
+            errors.push("`p2p.dht.alpha` must be above 0".to_owned());
+        }
+        if dht.republish_interval >= dht.record_ttl {
+            errors.push(format!(
+                "`p2p.dht.republish_interval` {} must be below `p2p.dht.record_ttl` {}",
+                dht.republish_interval, dht.record_ttl
+            ));
+        }
+        if self.webhook.max_attempts == 0 {
+            errors.push("`webhook.max_attempts` must be above 0".to_owned());
+        }
+        let mut names = BTreeSet::new();
+        for flag in &self.features.flags {
+            if !is_valid_flag_name(&flag.name) {
+                errors.push(format!(
+                    "`features.flags` name {:?} is not lowercase letters, digits and '_'",
+                    flag.name
+                ));
+            } else if !names.insert(flag.name.as_str()) {
+                errors.push(format!(
+                    "`features.flags` {} is configured twice",
+                    flag.name
+                ));
+            }
+            if flag.percentage > 100 {
+                errors.push(format!(
+                    "`features.flags` {} percentage {} is above 100",
+                    flag.name, flag.percentage
+                ));
+            }
+        }
+        let mut names = BTreeSet::new();
+        let mut paths = BTreeSet::new();
+        for tenant in &self.tenancy.tenants {
+            if !is_valid_tenant_name(&tenant.name) {
+                errors.push(format!(
+                    "`tenancy.tenants` name {:?} is not letters, digits, '-' and '_'",
+                    tenant.name
+                ));
+            } else if !names.insert(tenant.name.as_str()) {
+                errors.push(format!(
+                    "`tenancy.tenants` {} is configured twice",
+                    tenant.name
+                ));
+            }
+            let path = Path::new(&tenant.path);
+            if !path.has_root() || path.components().count() != 2 {
+                errors.push(format!(
+                    "`tenancy.tenants` {} path {:?} is not a top-level directory, like /acme",
+                    tenant.name, tenant.path
+                ));
+            } else if !paths.insert(tenant.path.as_str()) {
+                errors.push(format!(
+                    "`tenancy.tenants` {} path {} belongs to another tenant",
+                    tenant.name, tenant.path
+                ));
+            }
+            for feature in &tenant.features {
+                if !is_valid_flag_name(feature) {
+                    errors.push(format!(
+                        "`tenancy.tenants` {} feature {:?} is not lowercase letters, digits and '_'",
+                        tenant.name, feature
+                    ));
+                }
+            }
+        }
+        let mut names = BTreeSet::new();
+        for module in &self.plugins.modules {
+            if !is_valid_tenant_name(&module.name) {
+                errors.push(format!(
+                    "`plugins.modules` name {:?} is not letters, digits, '-' and '_'",
+                    module.name
+                ));
+            } else if !names.insert(module.name.as_str()) {
+                errors.push(format!(
+                    "`plugins.modules` {} is configured twice",
+                    module.name
+                ));
+            }
+        }
+        errors
+    }
+
+    /// A copy with the passwords, keys and tokens replaced, so it can be shown.
+    pub fn redacted(&self) -> Config {
+        let mut config = self.clone();
+        let secrets = [
+            &mut config.storage.obs_access_key,
+            &mut config.storage.obs_secret_key,
+            &mut config.authentication.test_user_token,
+            &mut config.idp_sync.token,
+            &mut config.policy.entity_signing_key,
+        ];
+        for secret in secrets
+            .into_iter()
+            .chain(config.storage.encryption.keys.values_mut())
+            .chain(config.oauth.iter_mut().map(|o| &mut o.github_client_secret))
+            .chain(
+                config
+                    .mq
+                    .kafka
+                    .properties
+                    .iter_mut()
+                    .filter(|(key, _)| key.contains("password"))
+                    .map(|(_, value)| value),
+            )
+        {
+            if !secret.is_empty() {
+                *secret = REDACTED.to_owned();
+            }
+        }
+        config.database.db_url = redact_url(&config.database.db_url);
+        config.cache.redis_url = redact_url(&config.cache.redis_url);
+        config
+    }
+}
+
+/// Keys which are read on every use, changing them takes effect when the config is reloaded.
+/// Changes of the other keys need a restart.
+pub const RELOADABLE: [&str; 15] = [
+    "log.level",
+    "gateway.rate_limit",
+    "gateway.rate_limit_burst",
+    "quota",
+    "push_limit",
+    "secret_scan",
+    "signature",
+    "release",
+    "policy.schema_path",
+    "policy.policy_path",
+    "policy.action_schemas",
+    "policy.audit",
+    "features",
+    "tenancy",
+    "p2p.bandwidth",
+];
+
+/// The config of a running service, its [`RELOADABLE`] keys are replaced when the config file
+/// is read again.
+pub struct LiveConfig {
+    current: ArcSwap<Config>,
+    /// Called with the new config once it is in use
+    hooks: Mutex<Vec<Box<dyn Fn(&Config) + Send + Sync>>>,
+}
+
+/// Keys changed by [`LiveConfig::reload`].
+#[derive(Debug, Default, Serialize)]
+pub struct Reload {
+    /// Reloadable keys which were changed
+    pub changed: Vec<String>,
+    /// Keys which were changed but keep their value until a restart
+    pub restart: Vec<String>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(config),
+            hooks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The config in use, a snapshot which is not changed by reloads.
+    pub fn current(&self) -> Arc<Config> {
+        self.current.load_full()
+    }
+
+    /// Call `hook` with the new config after every reload.
+    pub fn on_reload(&self, hook: impl Fn(&Config) + Send + Sync + 'static) {
+        self.hooks.lock().unwrap().push(Box::new(hook));
+    }
+
+    /// Read the config file again and put its reloadable keys in use if it is valid and
+    /// `prepare` accepts it, the config in use is kept otherwise.
+    pub fn reload<E: Display>(
+        &self,
+        prepare: impl FnOnce(&Config) -> Result<(), E>,
+    ) -> Result<Reload, ConfigError> {
+        // one reload at a time, so none is lost
+        let hooks = self.hooks.lock().unwrap();
+        let current = self.current();
+        let path = current.path.as_ref().ok_or_else(|| {
+            ConfigError::Message("the config was not read from a file".to_owned())
+        })?;
+        let (new, _) = Config::load(&path.to_string_lossy(), None)?;
+        let errors = new.validate();
+        if !errors.is_empty() {
+            return Err(ConfigError::Message(errors.join(", ")));
+        }
+        let (config, reload) = merge(&current, &new)?;
+        prepare(&config).map_err(|err| ConfigError::Message(err.to_string()))?;
+
+        self.current.store(Arc::new(config));
+        let config = self.current();
+        for hook in hooks.iter() {
+            hook(&config);
+        }
+        Ok(reload)
+    }
+}
+
+/// `current` with the reloadable keys of `new`.
+fn merge(current: &Config, new: &Config) -> Result<(Config, Reload), ConfigError> {
+    let to_value =
+        |config| serde_json::to_value(config).map_err(|err| ConfigError::Foreign(Box::new(err)));
+    let mut merged = to_value(current)?;
+    let new = to_value(new)?;
+    let mut reload = Reload::default();
+    for key in RELOADABLE {
+        let pointer = format!("/{}", key.replace('.', "/"));
+        let (Some(value), Some(target)) = (new.pointer(&pointer), merged.pointer_mut(&pointer))
+        else {
+            continue;
+        };
+        if value != target {
+            *target = value.clone();
+            reload.changed.push(key.to_owned());
+        }
+    }
+    changed_keys("", &merged, &new, &mut reload.restart);
+
+    let mut config: Config =
+        serde_json::from_value(merged).map_err(|err| ConfigError::Foreign(Box::new(err)))?;
+    config.path = current.path.clone();
+    Ok((config, reload))
+}
+
+/// Keys whose values differ between `a` and `b`.
+fn changed_keys(
+    key: &str,
+    a: &serde_json::Value,
+    b: &serde_json::Value,
+    changed: &mut Vec<String>,
+) {
+    match (a, b) {
+        (serde_json::Value::Object(a), serde_json::Value::Object(b)) => {
+            let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for k in keys {
+                let sub = if key.is_empty() {
+                    k.clone()
+                } else {
+                    format!("{}.{}", key, k)
+                };
+                let null = serde_json::Value::Null;
+                changed_keys(
+                    &sub,
+                    a.get(k).unwrap_or(&null),
+                    b.get(k).unwrap_or(&null),
+                    changed,
+                );
+            }
+        }
+        (a, b) if a != b => changed.push(key.to_owned()),
+        _ => {}
+    }
+}
+
+/// Result of [`Config::check`].
+#[derive(Debug)]
+pub struct ConfigCheck {
+    pub config: Config,
+    /// Keys of the file mega does not know, they are ignored
+    pub unknown_keys: Vec<String>,
+    /// See [`Config::validate`]
+    pub errors: Vec<String>,
+}
+
+const REDACTED: &str = "******";
+
+/// The url with the password of its user replaced.
+fn redact_url(url: &str) -> String {
+    let Some(start) = url.find("://").map(|i| i + 3) else {
+        return url.to_owned();
+    };
+    let end = url[start..].find('/').map_or(url.len(), |i| start + i);
+    let Some(at) = url[start..end].rfind('@').map(|i| start + i) else {
+        return url.to_owned();
+    };
+    match url[start..at].find(':').map(|i| start + i) {
+        Some(colon) => format!("{}{}{}", &url[..=colon], REDACTED, &url[at..]),
+        None => url.to_owned(),
+    }
+}
+
+/// Keys of the config `file` which are no key of `config`, they are ignored when it is
+/// deserialized.
+fn unknown_keys(file: &c::Config, config: &Config) -> Vec<String> {
+    let mut known = HashSet::new();
+    if let Ok(value) = serde_json::to_value(config) {
+        known_keys("", &value, &mut known);
+    }
+    let unknown = RefCell::new(Vec::new());
+    for (k, v) in file.collect().unwrap_or_default() {
+        traverse_config(&k, &v, &|key: &str, _: &c::Value| {
+            if !known.contains(key) {
+                unknown.borrow_mut().push(key.to_owned());
+            }
+        });
+    }
+    let mut unknown = unknown.into_inner();
+    unknown.sort();
+    unknown
+}
+
+fn known_keys(key: &str, value: &serde_json::Value, keys: &mut HashSet<String>) {
+    if let serde_json::Value::Object(map) = value {
+        for (k, v) in map {
+            let key = if key.is_empty() {
+                k.clone()
+            } else {
+                format!("{}.{}", key, k)
+            };
+            known_keys(&key, v, keys);
+            keys.insert(key);
+        }
+    }
 }
 
 impl Default for Config {
@@ -72,6 +658,36 @@ impl Default for Config {
     }
 }
 
+/// Keys taking a list, given comma separated in the environment.
+const LIST_KEYS: [&str; 4] = [
+    "monorepo.root_dirs",
+    "ssh.auth_methods",
+    "policy.action_schemas",
+    "policy.break_glass_admins",
+];
+
+/// Overrides of config keys by environment variables, so a deployment can be configured without
+/// templating the config file.
+///
+/// The variable of a key is `MEGA_` followed by its path with sections joined by `__`, case
+/// does not matter:
+/// - `MEGA_BASE_DIR` overrides `base_dir`
+/// - `MEGA_DATABASE__DB_URL` overrides `db_url` of `[database]`
+/// - `MEGA_OAUTH__GITHUB_CLIENT_SECRET` overrides `github_client_secret` of `[oauth]`
+///
+/// Lists of strings like `MEGA_SSH__AUTH_METHODS=publickey,password` are comma separated, lists
+/// of tables like `quota.rules` can only be set in the file. Values may use `${}` variables of
+/// the file, like its own values.
+fn environment() -> c::Environment {
+    LIST_KEYS.iter().fold(
+        c::Environment::with_prefix("mega")
+            .prefix_separator("_")
+            .separator("__")
+            .list_separator(","),
+        |env, key| env.with_list_parse_key(key),
+    )
+}
+
 /// supports braces-delimited variables (i.e. ${foo}) in config.
 /// ### Example:
 /// ```toml
@@ -82,18 +698,20 @@ impl Default for Config {
 /// ### Limitations:
 /// - only support `String` type.
 /// - vars apply from up to down
-fn variable_placeholder_substitute(mut builder: c::ConfigBuilder<DefaultState>) -> c::Config {
+fn variable_placeholder_substitute(
+    mut builder: c::ConfigBuilder<DefaultState>,
+) -> Result<c::Config, ConfigError> {
     // `Config::set` is deprecated, use `ConfigBuilder::set_override` instead
-    let config = builder.clone().build().unwrap(); // initial config
+    let config = builder.clone().build()?; // initial config
     let mut vars = HashMap::new();
     // top-level variables
-    for (k, mut v) in config.collect().unwrap() {
+    for (k, mut v) in config.collect()? {
         // a copy
         if let ValueKind::String(str) = &v.kind {
             if envsubst::is_templated(str) {
-                let new_str = envsubst::substitute(str, &vars).unwrap();
+                let new_str = substitute(&k, str, &vars)?;
                 v.kind = ValueKind::String(new_str.clone());
-                builder = builder.set_override(&k, v).unwrap();
+                builder = builder.set_override(&k, v)?;
                 vars.insert(k, new_str);
             } else {
                 vars.insert(k, str.clone());
@@ -103,7 +721,7 @@ fn variable_placeholder_substitute(mut builder: c::ConfigBuilder<DefaultState>)
     // second-level or nested variables
     // extract all config k-v
     let map = Rc::new(RefCell::new(HashMap::new()));
-    for (k, v) in config.collect().unwrap() {
+    for (k, v) in config.collect()? {
         if let ValueKind::Table(_) = v.kind {
             let map_c = map.clone();
             traverse_config(&k, &v, &move |key: &str, value: &c::Value| {
@@ -116,18 +734,51 @@ fn variable_placeholder_substitute(mut builder: c::ConfigBuilder<DefaultState>)
 
     // do substitution: ${} -> real value
     for (k, mut v) in Rc::try_unwrap(map).unwrap().into_inner() {
-        let mut str = v.clone().into_string().unwrap();
+        let mut str = v.clone().into_string()?;
         if envsubst::is_templated(&str) {
-            let new_str = envsubst::substitute(&str, &vars).unwrap();
+            let new_str = substitute(&k, &str, &vars)?;
             println!("{}: {} -> {}", k, str, &new_str);
             v.kind = ValueKind::String(new_str.clone());
-            builder = builder.set_override(&k, v).unwrap();
+            builder = builder.set_override(&k, v)?;
             str = new_str;
         }
         vars.insert(k, str);
     }
 
-    builder.build().unwrap()
+    builder.build()
+}
+
+fn substitute(
+    key: &str,
+    value: &str,
+    vars: &HashMap<String, String>,
+) -> Result<String, ConfigError> {
+    envsubst::substitute(value, vars)
+        .map_err(|err| ConfigError::Message(format!("`{}` {}: {}", key, value, err)))
+}
+
+/// Replace the values of `config` which are secret references by their secrets, see
+/// [`crate::secrets`].
+fn resolve_secrets(config: c::Config, secrets: &Secrets) -> Result<c::Config, ConfigError> {
+    let resolved = RefCell::new(Vec::new());
+    for (k, v) in config.collect()? {
+        traverse_config(&k, &v, &|key: &str, value: &c::Value| {
+            if let ValueKind::String(str) = &value.kind {
+                let secret = secrets
+                    .resolve(str)
+                    .map_err(|err| ConfigError::Message(format!("`{}` {}", key, err)));
+                resolved.borrow_mut().push((key.to_owned(), secret));
+            }
+        });
+    }
+
+    let mut builder = c::Config::builder().add_source(config);
+    for (key, secret) in resolved.into_inner() {
+        if let Some(secret) = secret? {
+            builder = builder.set_override(key, secret)?;
+        }
+    }
+    builder.build()
 }
 
 /// visitor pattern: traverse each config & execute the closure `f`
@@ -149,10 +800,19 @@ fn traverse_config(key: &str, value: &c::Value, f: &impl Fn(&str, &c::Value)) {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct LogConfig {
     pub log_path: PathBuf,
     pub level: String,
     pub print_std: bool,
+    pub format: LogFormat,
+    /// When a new log file is started
+    pub rotation: LogRotation,
+    /// A new log file is started as well when the current one would grow beyond this size in
+    /// MB, 0 is unlimited
+    pub max_file_size: u64,
+    /// Log files kept, the oldest are deleted when a new one is started, 0 keeps all
+    pub max_files: usize,
 }
 
 impl Default for LogConfig {
@@ -161,11 +821,36 @@ impl Default for LogConfig {
             log_path: PathBuf::from("/tmp/.mega/logs"),
             level: String::from("info"),
             print_std: true,
+            format: LogFormat::Text,
+            rotation: LogRotation::Hourly,
+            max_file_size: 0,
+            max_files: 0,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Lines for people to read
+    #[default]
+    Text,
+    /// A JSON object per line for log collectors, with the fields of the spans it is in
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    #[default]
+    Hourly,
+    Daily,
+    /// Only when the file is too large
+    Never,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct DbConfig {
     pub db_type: String,
     pub db_path: String,
@@ -173,6 +858,21 @@ pub struct DbConfig {
     pub max_connection: u32,
     pub min_connection: u32,
     pub sqlx_logging: bool,
+    pub migration: MigrationMode,
+    /// Queries taking at least this many milliseconds are logged, 0 disables the log
+    pub slow_query_threshold: u64,
+    /// Seconds to wait for a connection of the pool before a query fails
+    pub acquire_timeout: u64,
+    /// Seconds a statement may run before the database cancels it, 0 for no limit
+    pub statement_timeout: u64,
+    /// Seconds an unused connection is kept open above `min_connection`
+    pub idle_timeout: u64,
+    /// Seconds after which a connection is closed and opened again
+    pub max_lifetime: u64,
+    /// Attempts to connect on start before giving up
+    pub connect_retries: u32,
+    /// Longest delay in seconds between attempts to reach the database while it is down
+    pub reconnect_max_delay: u64,
 }
 
 impl Default for DbConfig {
@@ -184,34 +884,118 @@ impl Default for DbConfig {
             max_connection: 32,
             min_connection: 16,
             sqlx_logging: false,
+            migration: MigrationMode::default(),
+            slow_query_threshold: 1000,
+            acquire_timeout: 30,
+            statement_timeout: 0,
+            idle_timeout: 600,
+            max_lifetime: 1800,
+            connect_retries: 5,
+            reconnect_max_delay: 60,
         }
     }
 }
 
+/// What a service does on start when the database schema is behind its migrations.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MigrationMode {
+    /// Apply the pending migrations
+    #[default]
+    Auto,
+    /// Log the pending migrations and start anyway
+    Warn,
+    /// Refuse to start until `mega migrate up` was run
+    Refuse,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct StorageConfig {
+    /// Where the content of blobs above `big_obj_threshold` is kept
+    pub raw_obj_storage_type: RawStorageType,
+    /// Directory of the `local` storage
+    pub raw_obj_local_path: PathBuf,
+    /// Size in KB from which blob content is moved out of the database
+    pub big_obj_threshold: usize,
+    /// Size in KB from which blob content is split into content defined chunks, which are
+    /// shared by similar blobs. 0 never splits.
+    pub big_obj_chunk_threshold: usize,
+    /// Bucket of the `s3` and `gcs` storage, container of the `azure` storage
+    pub obs_bucket: String,
     pub obs_access_key: String,
     pub obs_secret_key: String,
     pub obs_region: String,
     pub obs_endpoint: String,
+    /// Name of the backend of the `plugin` storage
+    pub plugin: String,
+    /// Encryption of blob content kept outside the database and of LFS objects
+    pub encryption: EncryptionConfig,
 }
 
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
+            raw_obj_storage_type: RawStorageType::default(),
+            raw_obj_local_path: PathBuf::from("/tmp/.mega/objects"),
+            big_obj_threshold: 1024,
+            big_obj_chunk_threshold: 16384,
+            obs_bucket: String::from("mega"),
             obs_access_key: String::new(),
             obs_secret_key: String::new(),
             obs_region: String::from("cn-east-3"),
             obs_endpoint: String::from("https://obs.cn-east-3.myhuaweicloud.com"),
+            plugin: String::new(),
+            encryption: EncryptionConfig::default(),
         }
     }
 }
 
+/// Envelope encryption of stored objects, every object is encrypted with a data key of its own
+/// which is wrapped by a master key.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    /// Encrypt new objects with the master key `key_id`, objects encrypted before are read as
+    /// long as their master key is configured
+    pub enable: bool,
+    /// Id of the master key new objects are encrypted with
+    pub key_id: String,
+    /// Master keys by id, 32 bytes in base64
+    pub keys: HashMap<String, String>,
+    /// Command printing the master key `key_id` in base64 if `keys` has none, e.g. fetching
+    /// it from a KMS
+    pub key_command: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RawStorageType {
+    /// Keep all content in the `raw_blob` table
+    #[default]
+    Database,
+    /// Files below `raw_obj_local_path`
+    Local,
+    /// Amazon S3 or any S3 compatible store, like Huawei OBS or MinIO
+    S3,
+    /// Azure Blob Storage
+    Azure,
+    /// Google Cloud Storage
+    Gcs,
+    /// The compiled-in backend registered as `plugin`
+    Plugin,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct MonoConfig {
     pub import_dir: PathBuf,
     pub admin: String,
     pub root_dirs: Vec<String>,
+    /// Days the old path of a renamed repository keeps redirecting to the new one
+    pub redirect_grace_days: u32,
+    /// Monorepo commits translated into the history of a cloned directory, 0 is unlimited
+    pub virtual_history_depth: usize,
 }
 
 impl Default for MonoConfig {
@@ -225,6 +1009,8 @@ impl Default for MonoConfig {
                 "doc".to_string(),
                 "release".to_string(),
             ],
+            redirect_grace_days: 30,
+            virtual_history_depth: 1000,
         }
     }
 }
@@ -295,3 +1081,1082 @@ pub struct OauthConfig {
     pub ui_domain: String,
     pub cookie_domain: String,
 }
+
+/// Restricts API and git access by client address.
+///
+/// Rules are evaluated separately for read (clone, fetch, browse) and write
+/// (push, create, merge) operations. Within a rule a matching `deny` entry always
+/// wins, and a non-empty `allow` list rejects any address it does not contain.
+/// Repository rules apply to the configured path and everything below it, on top
+/// of the instance-wide rules. They only cover requests whose repository is known before
+/// the body is read: git requests, and api requests with a `path` query parameter. Api
+/// requests carrying the path in their body are only subject to the instance-wide rules.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct NetworkPolicyConfig {
+    pub enable: bool,
+    /// Use the last address of `X-Forwarded-For`, added by the reverse proxy, as client
+    /// address, only enable it when mega is deployed behind a trusted reverse proxy.
+    pub trust_forwarded_for: bool,
+    pub read: NetworkRule,
+    pub write: NetworkRule,
+    pub repos: Vec<RepoNetworkRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct NetworkRule {
+    /// CIDR ranges, e.g. `10.0.0.0/8`, a bare address is treated as a single host
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RepoNetworkRule {
+    pub path: String,
+    pub read: NetworkRule,
+    pub write: NetworkRule,
+}
+
+/// Background maintenance jobs run by the service.
+///
+/// Every job takes a five field cron expression (`minute hour day month weekday`, in UTC),
+/// an empty string disables the schedule, the job can still be triggered through the api.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    pub enable: bool,
+    /// Where prebuilt packs and reachability indexes for clones are kept
+    pub pack_cache_path: PathBuf,
+    /// Days finished jobs are kept in the job history
+    pub history_retention_days: u32,
+    pub repack: String,
+    pub commit_graph: String,
+    pub bitmap: String,
+    pub lfs_gc: String,
+    pub stale_cleanup: String,
+    /// Scheduled verification checks the whole monorepo, use the api to verify a single repository
+    pub verify: String,
+    pub branch_cleanup: String,
+    /// Branches without any update for this many days are stale
+    pub stale_branch_days: u32,
+    /// Delete stale branches, otherwise the cleanup job only reports them
+    pub delete_stale_branches: bool,
+    /// Bring the split repositories of monorepo directories up to date
+    pub subtree_split: String,
+    /// Permanently remove repositories which have been in the trash for longer than
+    /// `repo_retention_days`
+    pub repo_purge: String,
+    /// Days deleted repositories can be restored before they are purged
+    pub repo_retention_days: u32,
+    /// Find objects which are no longer reachable
+    pub object_gc: String,
+    /// Unreachable objects younger than this many days are kept
+    pub object_gc_grace_days: u32,
+    /// Delete unreachable objects, otherwise the gc job only reports them
+    pub prune_unreachable_objects: bool,
+    /// Remove temporary role assignments once they expired
+    pub grant_expiry: String,
+    /// Pull the team memberships of `idp_sync` from the identity provider
+    pub idp_sync: String,
+    /// Told to clients whose writes are rejected in maintenance mode, unless the mode was
+    /// enabled with a message of its own
+    pub mode_message: String,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            pack_cache_path: PathBuf::from("/tmp/.mega/packs"),
+            history_retention_days: 30,
+            repack: String::from("0 3 * * 0"),
+            commit_graph: String::from("30 3 * * *"),
+            bitmap: String::from("0 4 * * 0"),
+            lfs_gc: String::from("0 5 * * 0"),
+            stale_cleanup: String::from("0 * * * *"),
+            verify: String::new(),
+            branch_cleanup: String::from("0 2 * * *"),
+            stale_branch_days: 90,
+            delete_stale_branches: false,
+            subtree_split: String::from("*/30 * * * *"),
+            repo_purge: String::from("0 1 * * *"),
+            repo_retention_days: 30,
+            object_gc: String::from("0 6 * * 0"),
+            object_gc_grace_days: 14,
+            prune_unreachable_objects: false,
+            grant_expiry: String::from("*/5 * * * *"),
+            idp_sync: String::from("15 * * * *"),
+            mode_message: String::from(
+                "Mega is under maintenance, pushes and changes are not possible until it is done",
+            ),
+        }
+    }
+}
+
+/// Scan the blobs of every push for leaked credentials.
+///
+/// The built-in rules cover AWS keys, private keys and common api tokens, `rules` adds
+/// instance specific patterns. Findings are recorded for security review in any case.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SecretScanConfig {
+    pub enable: bool,
+    pub policy: SecretScanPolicy,
+    /// Blobs larger than this are not scanned, in bytes
+    pub max_blob_size: usize,
+    pub rules: Vec<SecretRule>,
+}
+
+impl Default for SecretScanConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            policy: SecretScanPolicy::Reject,
+            max_blob_size: 1024 * 1024,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretScanPolicy {
+    /// Refuse the push, no ref is updated
+    Reject,
+    /// Hold back the ref updates until a reviewer releases the push
+    Quarantine,
+    /// Accept the push and only record the findings
+    Audit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SecretRule {
+    pub name: String,
+    /// Regular expression matched against every line of a blob
+    pub pattern: String,
+}
+
+/// Limits enforced on every push, pushes breaking them are refused before any ref is updated.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PushLimitConfig {
+    /// Maximum size of the pushed pack in MB, 0 only applies `pack.maximum_pack_size`
+    pub max_pack_size: usize,
+    /// Maximum size of a single file in MB, 0 is unlimited
+    pub max_file_size: usize,
+    /// Maximum number of refs updated by one push, 0 is unlimited
+    pub max_ref_updates: usize,
+}
+
+impl Default for PushLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_pack_size: 0,
+            max_file_size: 100,
+            max_ref_updates: 0,
+        }
+    }
+}
+
+impl PushLimitConfig {
+    /// The effective pack size limit of a push in bytes.
+    pub fn pack_size_limit(&self, pack: &PackConfig) -> usize {
+        let limit = 1024 * 1024 * 1024 * pack.maximum_pack_size;
+        if self.max_pack_size > 0 {
+            limit.min(1024 * 1024 * self.max_pack_size)
+        } else {
+            limit
+        }
+    }
+}
+
+/// Storage quotas of repositories and namespaces, checked on push and LFS upload.
+///
+/// The usage of a repository is the size of the git objects pushed to it and of the LFS objects
+/// uploaded for it. A namespace is an organization or user, it uses the storage of all
+/// repositories below the directories it owns.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct QuotaConfig {
+    pub enable: bool,
+    /// Maximum size of a repository in MB, 0 is unlimited
+    pub max_repo_size: u64,
+    /// Maximum size of all repositories of a namespace in MB, 0 is unlimited
+    pub max_namespace_size: u64,
+    /// Limits replacing `max_repo_size` for the repositories below some directories
+    pub rules: Vec<QuotaRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct QuotaRule {
+    /// Repositories at or below this path use the rule, the most specific rule wins
+    pub path: String,
+    /// Maximum size of each repository in MB, 0 is unlimited
+    pub max_size: u64,
+}
+
+/// Releases created from the tags of import repositories.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ReleaseConfig {
+    /// Maximum size of a release asset in MB, 0 is unlimited
+    pub max_asset_size: usize,
+    /// Maximum number of commits listed in a generated changelog
+    pub changelog_limit: usize,
+}
+
+impl Default for ReleaseConfig {
+    fn default() -> Self {
+        Self {
+            max_asset_size: 1024,
+            changelog_limit: 500,
+        }
+    }
+}
+
+/// Authentication of the ssh server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SshConfig {
+    /// Methods offered to clients: `publickey`, `password` and `keyboard-interactive`,
+    /// the latter two take the user name and an access token
+    pub auth_methods: Vec<String>,
+    /// Failed authentications from one address within `failure_window` before it is banned,
+    /// 0 never bans
+    pub max_auth_failures: u32,
+    /// Seconds failed authentications are counted over
+    pub failure_window: u64,
+    /// Seconds a banned address is refused
+    pub ban_time: u64,
+    /// Shown to clients before they authenticate, nothing if empty
+    pub banner: String,
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            auth_methods: vec!["publickey".to_string()],
+            max_auth_failures: 10,
+            failure_window: 600,
+            ban_time: 1800,
+            banner: String::new(),
+        }
+    }
+}
+
+/// Caches of the ref and commit lookups of git operations.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Entries kept in process by each cache, 0 disables the in-process caches
+    pub capacity: u64,
+    /// Seconds refs are cached, 0 disables caching them
+    pub ref_ttl: u64,
+    /// Seconds commits are cached, 0 disables caching them
+    pub commit_ttl: u64,
+    /// Redis shared by all instances like "redis://127.0.0.1:6379/0", not used if empty.
+    /// Refs are only cached in redis when it is set, so all instances see their updates
+    pub redis_url: String,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10000,
+            ref_ttl: 60,
+            commit_ttl: 3600,
+            redis_url: String::new(),
+        }
+    }
+}
+
+/// Delivery and retries of event messages.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MqConfig {
+    /// Where messages are published and consumed from
+    pub broker: MqBroker,
+    /// Attempts of processing a message before it is dead-lettered
+    pub max_attempts: u32,
+    /// Seconds a message being processed is hidden from other consumers, it is delivered again
+    /// once they are over unless it was acknowledged, in case its consumer stopped
+    pub visibility_timeout: u64,
+    /// Seconds before the first retry of a failed message, doubled with every further attempt
+    pub retry_delay: u64,
+    /// Messages of the database broker an instance processes at the same time in each lane,
+    /// further ones are left to the other instances
+    pub concurrency: LaneConcurrency,
+    pub batch: MqBatchConfig,
+    pub kafka: KafkaConfig,
+    pub nats: NatsConfig,
+}
+
+impl Default for MqConfig {
+    fn default() -> Self {
+        Self {
+            broker: MqBroker::default(),
+            max_attempts: 5,
+            visibility_timeout: 300,
+            retry_delay: 30,
+            concurrency: LaneConcurrency::default(),
+            batch: MqBatchConfig::default(),
+            kafka: KafkaConfig::default(),
+            nats: NatsConfig::default(),
+        }
+    }
+}
+
+/// Messages processed at the same time in each lane, so bulk events do not hold up the
+/// user-facing ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LaneConcurrency {
+    /// Events of user-facing operations, like merge request updates and pushes
+    pub interactive: usize,
+    /// Bulk events, like the records of api requests and GitHub webhooks
+    pub background: usize,
+}
+
+impl Default for LaneConcurrency {
+    fn default() -> Self {
+        Self {
+            interactive: 12,
+            background: 4,
+        }
+    }
+}
+
+/// Publishing messages in batches, which takes a round trip to the broker per batch instead of
+/// one per message when many are published at once, like the push events of a bulk import.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MqBatchConfig {
+    /// Messages published together at most, 1 publishes every message on its own
+    pub max_messages: usize,
+    /// Milliseconds a message waits for others to be published with
+    pub linger_ms: u64,
+    /// Compress the batches of the kafka and nats brokers with zstd
+    pub compress: bool,
+}
+
+impl Default for MqBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_messages: 100,
+            linger_ms: 5,
+            compress: true,
+        }
+    }
+}
+
+/// Delivery of the typed events to the webhooks subscribed to them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Deliver webhooks from this instance, deliveries are still recorded if disabled
+    pub enable: bool,
+    /// Seconds a webhook has to respond to a delivery
+    pub timeout: u64,
+    /// Attempts of a delivery before it is dead-lettered
+    pub max_attempts: u32,
+    /// Seconds before the first retry of a failed delivery, doubled with every further attempt
+    pub retry_delay: u64,
+    /// Longest delay between two attempts in seconds
+    pub max_retry_delay: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            timeout: 10,
+            max_attempts: 8,
+            retry_delay: 30,
+            max_retry_delay: 3600,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MqBroker {
+    /// The `mq_storage` table of the database
+    #[default]
+    Database,
+    /// A Kafka cluster, every type of event has a topic of its own
+    Kafka,
+    /// A NATS server with JetStream, every type of event has a subject of its own
+    Nats,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KafkaConfig {
+    /// Bootstrap servers like "kafka-1:9092,kafka-2:9092"
+    pub brokers: String,
+    /// Prefix of the topics, the topic of repository events is `{prefix}repo`
+    pub topic_prefix: String,
+    /// Consumer group of the instances, each message is processed by one instance of the group
+    pub group_id: String,
+    /// Further librdkafka properties, like `security.protocol` or `sasl.username`
+    pub properties: HashMap<String, String>,
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: String::new(),
+            topic_prefix: "mega.".to_owned(),
+            group_id: "mega".to_owned(),
+            properties: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NatsConfig {
+    /// Server like "nats://127.0.0.1:4222"
+    pub url: String,
+    /// JetStream stream of the messages, created if it does not exist
+    pub stream: String,
+    /// Prefix of the subjects, the subject of repository events is `{prefix}repo`
+    pub subject_prefix: String,
+    /// Durable consumer shared by the instances, each message is processed by one of them
+    pub consumer: String,
+    /// Credentials file of the user, not used if empty
+    pub credentials_file: String,
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            stream: "MEGA".to_owned(),
+            subject_prefix: "mega.".to_owned(),
+            consumer: "mega".to_owned(),
+            credentials_file: String::new(),
+        }
+    }
+}
+
+/// Verification of commit and tag signatures against the signing keys of users.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SignatureConfig {
+    /// Verify the signed commits and tags of a push once it is stored, others are verified
+    /// when they are first shown
+    pub verify_on_push: bool,
+}
+
+impl Default for SignatureConfig {
+    fn default() -> Self {
+        Self {
+            verify_on_push: true,
+        }
+    }
+}
+
+/// Cedar schema and policies deciding what users may do, read from files so they can be
+/// changed while the server runs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PolicyConfig {
+    /// Cedar schema file, the built-in schema if empty
+    pub schema_path: String,
+    /// Cedar policy file, the built-in policies if empty
+    pub policy_path: String,
+    /// Cedar schema files of extensions declaring their actions, like `triggerPipeline`, merged
+    /// into the schema
+    pub action_schemas: Vec<String>,
+    /// Seconds between checks whether the files changed, 0 only reloads them through the api
+    pub reload_interval: u64,
+    /// Authorization decisions recorded in the audit trail
+    pub audit: AuthAudit,
+    /// Users who may grant themselves temporary access to anything in an emergency, every
+    /// grant and every request it allows is recorded in the audit trail
+    pub break_glass_admins: Vec<String>,
+    /// Secret the entity files of directories are signed with when merged by an admin of the
+    /// directory, unsigned or changed files are ignored for the last signed version. Entity
+    /// files are trusted as they are if empty
+    pub entity_signing_key: String,
+}
+
+/// Team memberships following the groups of an external identity provider.
+///
+/// Groups are read through the SCIM 2.0 api of the provider, which directories backed by OIDC
+/// or LDAP offer as well, and their members are matched to users by email. The members of a
+/// mapped team are replaced by those of its groups on every run of the `idp_sync` maintenance
+/// job, teams without a mapping are left alone.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct IdpSyncConfig {
+    /// Base url of the SCIM api, e.g. `https://idp.example.com/scim/v2`, nothing is synced if
+    /// empty
+    pub scim_url: String,
+    /// Bearer token of the SCIM api
+    pub token: String,
+    pub mappings: Vec<IdpGroupMapping>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct IdpGroupMapping {
+    /// Display name of the group in the identity provider
+    pub group: String,
+    /// Team as `org/team`, several groups may be mapped to the same team
+    pub team: String,
+}
+
+/// Feature flags rolling out risky features gradually, e.g. to some users or repositories first.
+///
+/// Flags stored through the api replace the flags of the same name configured here, a flag
+/// which is neither configured nor stored is off.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FeatureConfig {
+    pub flags: Vec<FeatureFlag>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct FeatureFlag {
+    /// Lowercase letters, digits and '_', e.g. `merge_queue`
+    pub name: String,
+    /// On for everyone, the targets below are ignored then
+    pub enabled: bool,
+    /// Names of the users the feature is on for
+    pub users: Vec<String>,
+    /// The feature is on for the repositories at or below these paths
+    pub paths: Vec<String>,
+    /// Percentage of all users the feature is on for, the same users as long as it is not
+    /// lowered
+    pub percentage: u8,
+}
+
+pub fn is_valid_flag_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Tenants, isolated top-level namespaces of one deployment, e.g. the organizations of a hosted
+/// instance.
+///
+/// Every tenant owns a top-level directory and has its own users, which only reach the
+/// repositories below that directory, while the users of the instance do not reach those of
+/// any tenant.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct TenancyConfig {
+    pub enable: bool,
+    pub tenants: Vec<TenantConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct TenantConfig {
+    /// Letters, digits, '-' and '_', e.g. `acme`
+    pub name: String,
+    /// Top-level directory of the tenant, e.g. `/acme`
+    pub path: String,
+    /// Users signing up with an email of these domains join the tenant
+    pub email_domains: Vec<String>,
+    /// Maximum size of all repositories of the tenant in MB, 0 is unlimited
+    pub max_size: u64,
+    /// Feature flags which are on for the users and repositories of the tenant
+    pub features: Vec<String>,
+}
+
+pub fn is_valid_tenant_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Plugins customizing the services without changing mega, see [`crate::plugin`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// Loaded on start in this order, which is the order they are asked in
+    pub modules: Vec<PluginModule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PluginModule {
+    /// Name the compiled-in plugin is registered under, any name for a WASM module
+    pub name: String,
+    /// WebAssembly module of the plugin, the compiled-in plugin `name` is used if empty
+    pub wasm: String,
+    /// Passed to the plugin as it is
+    pub settings: serde_json::Value,
+}
+
+/// Export of the spans of requests to an OpenTelemetry collector, like Jaeger or Tempo, so slow
+/// requests can be traced through the services.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TelemetryConfig {
+    pub enable: bool,
+    /// OTLP gRPC endpoint of the collector
+    pub endpoint: String,
+    /// Name of the service in the traces, the name of the binary if empty
+    pub service_name: String,
+    /// Share of the traces which are exported, from 0 to 1. A trace started by a caller which
+    /// sends a `traceparent` header is exported if the caller samples it.
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            endpoint: String::from("http://localhost:4317"),
+            service_name: String::new(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthAudit {
+    /// Nothing is recorded
+    Off,
+    /// Denied requests, and allowed ones of actions only admins should be able to do
+    #[default]
+    Denied,
+    /// Every decision
+    All,
+}
+
+/// Workers running the jobs of the background job queue.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct JobConfig {
+    /// Run queued jobs in this instance, jobs are still queued if disabled
+    pub enable: bool,
+    /// Jobs run at the same time by this instance
+    pub workers: usize,
+    /// Seconds between looking for due jobs when none is waiting
+    pub poll_interval: u64,
+    /// Seconds a running job is held by its worker, the worker extends it while the job runs
+    /// and the job is run again by another worker once it is over
+    pub lease_timeout: u64,
+    /// Attempts of running a job before it is given up
+    pub max_attempts: u32,
+    /// Seconds before the first retry of a failed job, doubled with every further attempt
+    pub retry_delay: u64,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            workers: 4,
+            poll_interval: 5,
+            lease_timeout: 300,
+            max_attempts: 3,
+            retry_delay: 60,
+        }
+    }
+}
+
+/// Routing and shared middleware of the gateway, which serves the api, git http and lfs of
+/// all services on one port.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GatewayConfig {
+    /// Checked in order before the default layout, the first matching route wins
+    pub routes: Vec<GatewayRoute>,
+    /// Requests per second allowed from one client address, 0 disables rate limiting
+    pub rate_limit: u32,
+    /// Requests a client can send at once before `rate_limit` applies
+    pub rate_limit_burst: u32,
+    /// Serve request counters, database timings and message queue depths in the prometheus text
+    /// format at `/metrics`
+    pub metrics: bool,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            routes: Vec::new(),
+            rate_limit: 0,
+            rate_limit_burst: 100,
+            metrics: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct GatewayRoute {
+    /// Host name the request is sent to, any host if empty
+    pub host: String,
+    /// Path prefix of the route, removed before the request is handed to the service
+    pub prefix: String,
+    pub service: GatewayService,
+    /// Only let requests with valid basic credentials through
+    pub auth: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GatewayService {
+    /// The mono api
+    Mono,
+    /// The mega api of ztm, nostr and github
+    Mega,
+    /// The git smart http protocol and git lfs
+    #[default]
+    Git,
+}
+
+/// Sharing of repositories with other mega instances over the ztm network.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct P2pConfig {
+    /// Seconds between checking the subscribed repositories of other peers for new refs
+    pub sync_interval: u64,
+    pub dht: DhtConfig,
+    pub nat: NatConfig,
+    /// Relay nodes, as `host:port`, the messages of the peers which can't connect directly go
+    /// through before the ztm relay
+    pub relay_nodes: Vec<String>,
+    pub relay: RelayConfig,
+    pub noise: NoiseConfig,
+    pub bandwidth: BandwidthConfig,
+    pub lfs: P2pLfsConfig,
+}
+
+impl Default for P2pConfig {
+    fn default() -> Self {
+        Self {
+            sync_interval: 300,
+            dht: DhtConfig::default(),
+            nat: NatConfig::default(),
+            relay_nodes: Vec::new(),
+            relay: RelayConfig::default(),
+            noise: NoiseConfig::default(),
+            bandwidth: BandwidthConfig::default(),
+            lfs: P2pLfsConfig::default(),
+        }
+    }
+}
+
+/// The distributed hash table the instances find the peers providing a repository with, instead
+/// of asking the relay of the bootstrap node.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DhtConfig {
+    /// Peers kept in each bucket of the routing table, a record is stored by the `k` peers
+    /// closest to its key
+    pub k: usize,
+    /// Peers asked at the same time by a lookup
+    pub alpha: usize,
+    /// Seconds a record of a peer providing a repository is valid
+    pub record_ttl: u64,
+    /// Seconds between publishing the records of the provided repositories again
+    pub republish_interval: u64,
+    /// Peer ids joined through besides the endpoints of the ztm mesh
+    pub seeds: Vec<String>,
+}
+
+impl Default for DhtConfig {
+    fn default() -> Self {
+        Self {
+            k: 20,
+            alpha: 3,
+            record_ttl: 3600,
+            republish_interval: 1200,
+            seeds: Vec::new(),
+        }
+    }
+}
+
+/// Direct connections between the peers through their NATs, the messages between peers which
+/// can't reach each other go through the ztm relay.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NatConfig {
+    pub enable: bool,
+    /// UDP and TCP port the peers connect to directly, any free one if 0
+    pub port: u16,
+    /// STUN servers, as `host:port`, telling the public address of the peer and the type of its
+    /// NAT. At least two are needed to tell a symmetric NAT apart
+    pub stun_servers: Vec<String>,
+    /// Seconds spent punching a hole to a peer before using the relay
+    pub punch_timeout: u64,
+    /// Seconds before trying again to connect directly to a peer which is reached by the relay
+    pub retry_interval: u64,
+}
+
+impl Default for NatConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            port: 0,
+            stun_servers: vec![
+                "stun.l.google.com:19302".to_owned(),
+                "stun1.l.google.com:19302".to_owned(),
+            ],
+            punch_timeout: 5,
+            retry_interval: 600,
+        }
+    }
+}
+
+/// The relay node run by `mega service relay`, forwarding the messages between the peers which
+/// can't connect directly.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// Peer ids allowed to use the relay, any peer proving its id if empty
+    pub allowed_peers: Vec<String>,
+    /// Peers connected at the same time
+    pub max_peers: usize,
+    /// Bytes per second forwarded from each peer, unlimited if 0
+    pub peer_rate: u64,
+    /// Bytes per second forwarded from all the peers, unlimited if 0
+    pub max_rate: u64,
+}
+
+/// Noise encryption of the ztm tunnels between the peers, a peer with it enabled only talks to
+/// the peers which have it enabled too.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NoiseConfig {
+    pub enable: bool,
+    /// Port the ztm tunnels of the other peers end at, the same on every peer
+    pub port: u16,
+    /// Seconds before a channel changes its keys
+    pub rekey_interval: u64,
+}
+
+impl Default for NoiseConfig {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            port: 8010,
+            rekey_interval: 3600,
+        }
+    }
+}
+
+/// Rates of the transfers with the other peers through the Noise channels, in bytes per second
+/// and unlimited if 0. The transfers running at the same time share them equally.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct BandwidthConfig {
+    /// Sent to all the peers together
+    pub upload_rate: u64,
+    /// Received from all the peers together
+    pub download_rate: u64,
+    /// Sent to each peer
+    pub peer_upload_rate: u64,
+    /// Received from each peer
+    pub peer_download_rate: u64,
+    /// Rates of single peers by peer id, replacing the rates of each peer
+    pub peers: HashMap<String, PeerBandwidth>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+pub struct PeerBandwidth {
+    pub upload_rate: u64,
+    pub download_rate: u64,
+}
+
+/// LFS objects exchanged with the other peers, and the clients sent to download them from the
+/// peers near them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct P2pLfsConfig {
+    pub enable: bool,
+    /// Url of the LFS server of this instance the clients of `networks` download from, the
+    /// clients of the other peers are not sent to it if empty
+    pub public_url: String,
+    /// Networks of the clients near this instance, as `10.1.0.0/16` or single addresses
+    pub networks: Vec<String>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            allowed_peers: Vec::new(),
+            max_peers: 1024,
+            peer_rate: 1024 * 1024,
+            max_rate: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::{redact_url, Config, LiveConfig, PluginModule, RawStorageType};
+
+    #[test]
+    fn test_environment_overrides() {
+        let path = std::env::temp_dir().join("mega-test-env-config.toml");
+        std::fs::write(&path, include_str!("../../mega/config.toml")).unwrap();
+        let secret = std::env::temp_dir().join("mega-test-env-secret");
+        std::fs::write(&secret, "s3cret\n").unwrap();
+        let secret_ref = format!("file:{}", secret.display());
+        let env = [
+            ("MEGA_BASE_DIR", "/data/mega"),
+            ("MEGA_DATABASE__DB_TYPE", "postgres"),
+            ("MEGA_DATABASE__DB_URL", "postgres://mega:mega@db:5432/mega"),
+            ("mega_database__max_connection", "64"),
+            ("MEGA_AUTHENTICATION__ENABLE_HTTP_AUTH", "true"),
+            ("MEGA_SSH__AUTH_METHODS", "publickey,password"),
+            ("MEGA_LOG__LOG_PATH", "${base_dir}/var/log"),
+            ("MEGA_STORAGE__OBS_SECRET_KEY", secret_ref.as_str()),
+        ];
+        let env = env
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        let (config, _) = Config::load(path.to_str().unwrap(), Some(env)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&secret).unwrap();
+
+        assert_eq!(config.base_dir, PathBuf::from("/data/mega"));
+        assert_eq!(config.database.db_type, "postgres");
+        assert_eq!(config.database.db_url, "postgres://mega:mega@db:5432/mega");
+        assert_eq!(config.database.max_connection, 64);
+        assert!(config.authentication.enable_http_auth);
+        assert_eq!(config.ssh.auth_methods, ["publickey", "password"]);
+        assert_eq!(config.log.log_path, PathBuf::from("/data/mega/var/log"));
+        assert_eq!(config.storage.obs_secret_key, "s3cret");
+        // keys without a variable keep the value of the file
+        assert_eq!(config.log.level, "debug");
+    }
+
+    #[test]
+    fn test_check() {
+        let path = std::env::temp_dir().join("mega-test-check-config.toml");
+        let file = include_str!("../../mega/config.toml")
+            .replace("db_type = \"sqlite\"", "db_type = \"oracle\"\ndb_pool = 4")
+            + "\n[notify]\nurl = \"http://localhost\"\n";
+        std::fs::write(&path, file).unwrap();
+        let check = Config::check(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(check.unknown_keys, ["database.db_pool", "notify.url"]);
+        assert_eq!(
+            check.errors,
+            ["`database.db_type` oracle is not one of sqlite, postgres and mysql"]
+        );
+    }
+
+    #[test]
+    fn test_validate_plugins() {
+        let mut config = Config::default();
+        let module = PluginModule {
+            name: "ldap".to_owned(),
+            ..Default::default()
+        };
+        config.plugins.modules = vec![module.clone(), module];
+        config.storage.raw_obj_storage_type = RawStorageType::Plugin;
+        let errors = config.validate();
+        assert!(errors.contains(&"`storage.plugin` is required for plugin storage".to_owned()));
+        assert!(errors.contains(&"`plugins.modules` ldap is configured twice".to_owned()));
+    }
+
+    #[test]
+    fn test_validate_brokers() {
+        let mut config = Config::default();
+        config.mq.concurrency.background = 0;
+        let errors = config.validate();
+        assert!(errors.contains(
+            &"`mq.concurrency.background` must be above 0 for the database broker".to_owned()
+        ));
+        config.mq.broker = MqBroker::Kafka;
+        let errors = config.validate();
+        assert!(errors.contains(&"`mq.kafka.brokers` is required for the kafka broker".to_owned()));
+        config.mq.kafka.brokers = "localhost:9092".to_owned();
+        assert!(!config
+            .validate()
+            .iter()
+            .any(|error| error.starts_with("`mq.")));
+        config.mq.broker = MqBroker::Nats;
+        let errors = config.validate();
+        assert!(errors.contains(&"`mq.nats.url` is required for the nats broker".to_owned()));
+    }
+
+    #[test]
+    fn test_redacted() {
+        let mut config = Config::default();
+        config.database.db_url = "postgres://mega:secret@db:5432/mega".to_owned();
+        config.cache.redis_url = "redis://:secret@redis:6379/0".to_owned();
+        config.storage.obs_secret_key = "secret".to_owned();
+        config.storage.obs_access_key = String::new();
+        let kafka = &mut config.mq.kafka.properties;
+        kafka.insert("sasl.username".to_owned(), "mega".to_owned());
+        kafka.insert("sasl.password".to_owned(), "secret".to_owned());
+
+        let redacted = config.redacted();
+        assert_eq!(
+            redacted.database.db_url,
+            "postgres://mega:******@db:5432/mega"
+        );
+        assert_eq!(redacted.cache.redis_url, "redis://:******@redis:6379/0");
+        assert_eq!(redacted.storage.obs_secret_key, "******");
+        assert_eq!(redacted.mq.kafka.properties["sasl.password"], "******");
+        assert_eq!(redacted.mq.kafka.properties["sasl.username"], "mega");
+        // unset secrets stay empty
+        assert!(redacted.storage.obs_access_key.is_empty());
+        assert_eq!(redact_url("sqlite:///tmp/mega.db"), "sqlite:///tmp/mega.db");
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = std::env::temp_dir().join("mega-test-reload-config.toml");
+        let file = include_str!("../../mega/config.toml");
+        std::fs::write(&path, file).unwrap();
+        let (config, _) = Config::load(path.to_str().unwrap(), None).unwrap();
+        let live = LiveConfig::new(config);
+        let reloads = Arc::new(AtomicUsize::new(0));
+        let counter = reloads.clone();
+        live.on_reload(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let file = file
+            .replace("level = \"debug\"", "level = \"warn\"")
+            .replace("max_connection = 32", "max_connection = 64");
+        std::fs::write(&path, &file).unwrap();
+        let reload = live.reload(|_| Ok::<_, String>(())).unwrap();
+        assert_eq!(reload.changed, ["log.level"]);
+        assert_eq!(reload.restart, ["database.max_connection"]);
+        assert_eq!(live.current().log.level, "warn");
+        assert_eq!(live.current().database.max_connection, 32);
+
+        // a rejected or invalid config is not put in use
+        std::fs::write(&path, file.replace("level = \"warn\"", "level = \"info\"")).unwrap();
+        assert!(live.reload(|_| Err("rejected")).is_err());
+        std::fs::write(
+            &path,
+            file.replace("db_type = \"sqlite\"", "db_type = \"oracle\""),
+        )
+        .unwrap();
+        assert!(live.reload(|_| Ok::<_, String>(())).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(live.current().log.level, "warn");
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,186 @@
+//! A small parser for the classic five field cron expressions used to schedule background jobs.
+//!
+//! Fields are `minute hour day-of-month month day-of-week`, each accepting `*`, single values,
+//! ranges (`1-5`), lists (`1,15`) and steps (`*/10`, `0-30/5`). Day of week counts from
+//! sunday (`0`, `7` is accepted as sunday too).
+
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike};
+
+use crate::errors::MegaError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    // cron matches day-of-month OR day-of-week when both are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    /// Returns the first minute strictly after `time` matching the schedule, searching at most
+    /// four years ahead so that impossible dates like `0 0 31 2 *` terminate.
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut next = time.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let limit = time + Duration::days(366 * 4);
+        while next <= limit {
+            if !self.months.contains(&next.month()) || !self.matches_day(&next) {
+                next = next.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.hours.contains(&next.hour()) {
+                next = next.with_minute(0)? + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes.contains(&next.minute()) {
+                next += Duration::minutes(1);
+                continue;
+            }
+            return Some(next);
+        }
+        None
+    }
+
+    fn matches_day(&self, time: &NaiveDateTime) -> bool {
+        let day = self.days.contains(&time.day());
+        let weekday = self
+            .weekdays
+            .contains(&time.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = MegaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(MegaError::with_message(&format!(
+                "cron expression '{}' should have 5 fields",
+                s
+            )));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        for d in weekdays.iter_mut() {
+            *d %= 7;
+        }
+        weekdays.sort_unstable();
+        weekdays.dedup();
+        Ok(CronSchedule {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, MegaError> {
+    let invalid = || MegaError::with_message(&format!("invalid cron field '{}'", field));
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(invalid());
+        }
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (
+                    start.parse().map_err(|_| invalid())?,
+                    end.parse().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let v = range.parse().map_err(|_| invalid())?;
+                    // `5/15` means starting at 5 until the end of the range
+                    if part.contains('/') {
+                        (v, max)
+                    } else {
+                        (v, v)
+                    }
+                }
+            },
+        };
+        if start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_parse_cron() {
+        assert!("* * * * *".parse::<CronSchedule>().is_ok());
+        assert!("*/15 0-6 1,15 * 1-5".parse::<CronSchedule>().is_ok());
+        assert!("* * * *".parse::<CronSchedule>().is_err());
+        assert!("60 * * * *".parse::<CronSchedule>().is_err());
+        assert!("*/0 * * * *".parse::<CronSchedule>().is_err());
+        assert!("5-1 * * * *".parse::<CronSchedule>().is_err());
+    }
+
+    #[test]
+    fn test_next_after() {
+        let every_minute: CronSchedule = "* * * * *".parse().unwrap();
+        assert_eq!(
+            every_minute.next_after(time("2024-01-01 10:00")),
+            Some(time("2024-01-01 10:01"))
+        );
+
+        let nightly: CronSchedule = "30 3 * * *".parse().unwrap();
+        assert_eq!(
+            nightly.next_after(time("2024-01-01 10:00")),
+            Some(time("2024-01-02 03:30"))
+        );
+
+        // 2024-01-01 is a monday
+        let sunday: CronSchedule = "0 4 * * 0".parse().unwrap();
+        assert_eq!(
+            sunday.next_after(time("2024-01-01 10:00")),
+            Some(time("2024-01-07 04:00"))
+        );
+        let sunday: CronSchedule = "0 4 * * 7".parse().unwrap();
+        assert_eq!(
+            sunday.next_after(time("2024-01-01 10:00")),
+            Some(time("2024-01-07 04:00"))
+        );
+
+        let quarter: CronSchedule = "*/15 * * * *".parse().unwrap();
+        assert_eq!(
+            quarter.next_after(time("2024-01-01 10:50")),
+            Some(time("2024-01-01 11:00"))
+        );
+
+        let never: CronSchedule = "0 0 31 2 *".parse().unwrap();
+        assert_eq!(never.next_after(time("2024-01-01 10:00")), None);
+    }
+}
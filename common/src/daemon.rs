@@ -0,0 +1,502 @@
+//! Running the services as a long-running daemon: detaching from the terminal, the pid file,
+//! the listeners passed by systemd socket activation, exiting on `SIGTERM` and upgrading on
+//! `SIGUSR2` without dropping connections.
+//!
+//! Socket activation follows `sd_listen_fds(3)`: the sockets of a `.socket` unit are passed from
+//! fd 3 on with `LISTEN_FDS` and `LISTEN_PID`. A service takes the socket named after it by
+//! `FileDescriptorName=` in `LISTEN_FDNAMES`, or else the one on its port, and binds its own
+//! listener if there is none.
+//!
+//! On `SIGUSR2` the executable is started again with the same arguments and the listening
+//! sockets passed in [`UPGRADE_FDS`]. Once the servers of the new process have started, the old
+//! one stops accepting connections and exits when the transfers in flight are done, or after the
+//! drain timeout. If the new process fails to start, the old one keeps serving.
+use std::fs::File;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+#[cfg(unix)]
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::errors::MegaError;
+
+/// Listening sockets handed over to the new process on upgrade, as `name=fd` separated by `,`.
+pub const UPGRADE_FDS: &str = "MEGA_UPGRADE_FDS";
+/// Pipe the new process tells the old one through that its servers have started.
+pub const UPGRADE_READY: &str = "MEGA_UPGRADE_READY";
+
+/// The pid file written on start, removed again on exit.
+static PID_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// The sockets passed to the process which are not taken yet.
+static PASSED: Mutex<Option<Passed>> = Mutex::new(None);
+
+/// The listening sockets of the servers by name, handed over on upgrade.
+#[cfg(unix)]
+static LISTENERS: Mutex<Vec<(String, i32)>> = Mutex::new(Vec::new());
+
+/// Set once the servers stop accepting connections to drain.
+static DRAIN: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+#[derive(Default)]
+struct Passed {
+    sockets: Vec<(String, TcpListener)>,
+    /// The old process waits on this until the servers of this one have started
+    ready: Option<File>,
+    /// The servers which have not started yet, unknown until [`expect_servers`] is called
+    starting: Option<usize>,
+}
+
+impl Passed {
+    /// Tell the old process to drain once the servers have started, or without
+    /// [`expect_servers`] once all sockets are taken. The sockets no server took are closed.
+    fn notify_started(&mut self) {
+        let started = match self.starting {
+            Some(starting) => starting == 0,
+            None => self.sockets.is_empty(),
+        };
+        if !started {
+            return;
+        }
+        self.sockets.clear();
+        if let Some(mut ready) = self.ready.take() {
+            use std::io::Write;
+            let _ = ready.write_all(b"1");
+        }
+    }
+}
+
+/// Detach from the terminal as a daemon and write the pid file, if `daemon` is set, or else only
+/// write the pid file.
+///
+/// Has to be called before any thread is started, the daemon continues without them. The
+/// process started from the terminal exits once the daemon has written the pid file, so a
+/// service manager finds the pid file when it sees the process exit.
+#[cfg(unix)]
+pub fn start(daemon: bool, pid_file: Option<&Path>) -> Result<(), MegaError> {
+    // the pid file of an upgrade belongs to the old process, which exits after the new one took
+    // over
+    let upgrade = std::env::var_os(UPGRADE_FDS).is_some();
+    if let Some(path) = pid_file.filter(|_| !upgrade) {
+        check_pid_file(path)?;
+    }
+    if daemon {
+        daemonize(pid_file)
+    } else if let Some(path) = pid_file {
+        write_pid_file(path)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn daemonize(pid_file: Option<&Path>) -> Result<(), MegaError> {
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two ends of the pipe
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: the fds were just opened and are owned by nothing else
+    let (mut ready_rx, mut ready_tx) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    if fork()? {
+        // exit with the daemon's verdict, which is nothing if it failed before writing it
+        drop(ready_tx);
+        let mut ready = [0];
+        let code = match ready_rx.read(&mut ready) {
+            Ok(1) if ready[0] == b'1' => 0,
+            _ => 1,
+        };
+        std::process::exit(code);
+    }
+    drop(ready_rx);
+    // a session of its own without a terminal, and no session leader so it never gets one
+    // SAFETY: setsid has no preconditions
+    if unsafe { libc::setsid() } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    if fork()? {
+        std::process::exit(0);
+    }
+
+    if let Some(path) = pid_file {
+        if let Err(err) = write_pid_file(path) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for fd in 0..3 {
+        // SAFETY: both fds are open
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    let _ = ready_tx.write_all(b"1");
+    Ok(())
+}
+
+/// Whether this is the parent.
+#[cfg(unix)]
+fn fork() -> Result<bool, MegaError> {
+    // SAFETY: no other thread runs yet, see `start`
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error().into()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+/// Fail if the pid file belongs to a process which still runs, a stale one is replaced.
+#[cfg(unix)]
+fn check_pid_file(path: &Path) -> Result<(), MegaError> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return Ok(());
+    };
+    match content.trim().parse::<i32>() {
+        // SAFETY: signal 0 only checks whether the process exists
+        Ok(pid) if pid > 0 && unsafe { libc::kill(pid, 0) } == 0 => Err(MegaError::with_message(
+            &format!("already running with pid {}, see {}", pid, path.display()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+fn write_pid_file(path: &Path) -> Result<(), MegaError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, format!("{}\n", std::process::id())).map_err(|err| {
+        MegaError::with_message(&format!("failed to write {}: {}", path.display(), err))
+    })?;
+    let _ = PID_FILE.set(path.to_owned());
+    Ok(())
+}
+
+/// Exit once `SIGTERM` arrives, the way a service manager stops the service.
+#[cfg(unix)]
+pub fn exit_on_terminate() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+                tracing::info!("Received SIGTERM signal, exiting...");
+                exit(0);
+            }
+            Err(err) => tracing::error!("failed to listen for SIGTERM: {}", err),
+        }
+    });
+}
+
+/// Exit after removing the pid file and exporting the spans which are not exported yet. The pid
+/// file is left alone once the process of an upgrade replaced it.
+pub fn exit(code: i32) -> ! {
+    if let Some(path) = PID_FILE.get() {
+        let own = std::fs::read_to_string(path)
+            .is_ok_and(|pid| pid.trim() == std::process::id().to_string());
+        if own {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    crate::log::shutdown();
+    std::process::exit(code);
+}
+
+/// The listener of the server `name` on `addr`: the socket passed by the old process of an
+/// upgrade or by socket activation, or else a socket bound to `addr`. Each passed socket is
+/// taken once.
+pub fn listen(name: &str, addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let listener = match take_passed(name, addr.port()) {
+        Some(listener) => {
+            tracing::info!("{} listens on the socket passed to the process", name);
+            listener
+        }
+        None => TcpListener::bind(addr)?,
+    };
+    // tokio expects its sockets to be non-blocking
+    listener.set_nonblocking(true)?;
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsRawFd;
+        let mut listeners = LISTENERS.lock().unwrap();
+        listeners.push((name.to_owned(), listener.as_raw_fd()));
+    }
+    let mut passed = PASSED.lock().unwrap();
+    let passed = passed.get_or_insert_with(passed_sockets);
+    if let Some(starting) = &mut passed.starting {
+        *starting = starting.saturating_sub(1);
+    }
+    passed.notify_started();
+    Ok(listener)
+}
+
+/// Announce that the process starts `count` servers, each listening with [`listen`]. Once they
+/// all listen, the old process of an upgrade is told to drain and the passed sockets none of them
+/// took are closed, e.g. the one of a server which is not started any more.
+pub fn expect_servers(count: usize) {
+    let mut passed = PASSED.lock().unwrap();
+    let passed = passed.get_or_insert_with(passed_sockets);
+    passed.starting = Some(count);
+    passed.notify_started();
+}
+
+fn take_passed(name: &str, port: u16) -> Option<TcpListener> {
+    let mut passed = PASSED.lock().unwrap();
+    let passed = passed.get_or_insert_with(passed_sockets);
+    let pos = passed
+        .sockets
+        .iter()
+        .position(|(n, _)| n == name)
+        .or_else(|| {
+            passed
+                .sockets
+                .iter()
+                .position(|(_, l)| l.local_addr().is_ok_and(|addr| addr.port() == port))
+        })?;
+    let (_, listener) = passed.sockets.remove(pos);
+    Some(listener)
+}
+
+/// Resolves once the servers should stop accepting connections, the ones in flight are served
+/// to the end.
+pub async fn draining() {
+    let mut drain = drain().subscribe();
+    let _ = drain.wait_for(|drain| *drain).await;
+}
+
+fn drain() -> &'static watch::Sender<bool> {
+    DRAIN.get_or_init(|| watch::channel(false).0)
+}
+
+/// Upgrade to the executable at the path the process was started from once `SIGUSR2` arrives,
+/// and exit `drain_timeout` after the servers of the new process started at the latest.
+#[cfg(unix)]
+pub fn upgrade_on_user_signal(drain_timeout: Duration) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    tokio::spawn(async move {
+        let mut user2 = match signal(SignalKind::user_defined2()) {
+            Ok(user2) => user2,
+            Err(err) => {
+                tracing::error!("failed to listen for SIGUSR2: {}", err);
+                return;
+            }
+        };
+        loop {
+            if user2.recv().await.is_none() {
+                return;
+            }
+            tracing::info!("Received SIGUSR2 signal, starting the new process...");
+            match tokio::task::spawn_blocking(spawn_successor).await {
+                Ok(Ok(())) => break,
+                Ok(Err(err)) => tracing::error!("upgrade failed, serving on: {}", err),
+                Err(err) => tracing::error!("upgrade failed, serving on: {}", err),
+            }
+        }
+        tracing::info!("the servers of the new process started, draining connections");
+        drain().send_replace(true);
+        tokio::time::sleep(drain_timeout).await;
+        tracing::warn!("connections still open after the drain timeout, exiting");
+        exit(0);
+    });
+}
+
+/// Start the new process with the listening sockets, returns once its servers have started.
+#[cfg(unix)]
+fn spawn_successor() -> Result<(), MegaError> {
+    use std::io::Read;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    let listeners = LISTENERS.lock().unwrap().clone();
+    if listeners.is_empty() {
+        return Err(MegaError::with_message("no listening sockets to hand over"));
+    }
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two ends of the pipe
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: the fds were just opened and are owned by nothing else
+    let (mut ready_rx, ready_tx) =
+        unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    set_cloexec(ready_rx.as_raw_fd(), true)?;
+    set_cloexec(ready_tx.as_raw_fd(), true)?;
+
+    let passed: Vec<i32> = listeners
+        .iter()
+        .map(|(_, fd)| *fd)
+        .chain([ready_tx.as_raw_fd()])
+        .collect();
+    let upgrade_fds = listeners
+        .iter()
+        .map(|(name, fd)| format!("{}={}", name, fd))
+        .collect::<Vec<_>>()
+        .join(",");
+    let mut args = std::env::args_os();
+    let program = args
+        .next()
+        .ok_or_else(|| MegaError::with_message("the path of the executable is unknown"))?;
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env(UPGRADE_FDS, upgrade_fds)
+        .env(UPGRADE_READY, ready_tx.as_raw_fd().to_string())
+        .env_remove("LISTEN_PID")
+        .env_remove("LISTEN_FDS")
+        .env_remove("LISTEN_FDNAMES");
+    // SAFETY: only fcntl runs between fork and exec, which is async-signal-safe
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &passed {
+                set_cloexec(*fd, false)?;
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+    drop(ready_tx);
+
+    let mut ready = [0];
+    let res = match ready_rx.read(&mut ready) {
+        Ok(1) if ready[0] == b'1' => Ok(()),
+        _ => Err(MegaError::with_message(
+            "the new process exited before its servers started",
+        )),
+    };
+    // reap the new process if it exits before the old one, e.g. when it daemonizes
+    std::thread::spawn(move || child.wait());
+    res
+}
+
+#[cfg(unix)]
+fn set_cloexec(fd: i32, cloexec: bool) -> std::io::Result<()> {
+    // SAFETY: fcntl on an fd has no other preconditions
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let flags = if cloexec {
+            flags | libc::FD_CLOEXEC
+        } else {
+            flags & !libc::FD_CLOEXEC
+        };
+        if libc::fcntl(fd, libc::F_SETFD, flags) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The sockets passed by the old process of an upgrade, or else by socket activation.
+#[cfg(unix)]
+fn passed_sockets() -> Passed {
+    use std::os::fd::FromRawFd;
+
+    /// The first fd passed by socket activation, after stdin, stdout and stderr
+    const LISTEN_FDS_START: i32 = 3;
+
+    if let Ok(fds) = std::env::var(UPGRADE_FDS) {
+        let sockets = fds
+            .split(',')
+            .filter_map(|socket| socket.split_once('='))
+            .filter_map(|(name, fd)| Some((name, fd.parse::<i32>().ok()?)))
+            .map(|(name, fd)| {
+                let _ = set_cloexec(fd, true);
+                // SAFETY: the old process passed its listening socket at this fd
+                (name.to_owned(), unsafe { TcpListener::from_raw_fd(fd) })
+            })
+            .collect();
+        let ready = std::env::var(UPGRADE_READY)
+            .ok()
+            .and_then(|fd| fd.parse::<i32>().ok())
+            .map(|fd| {
+                // not passed on to the processes this one starts, the old process would not
+                // notice this one fails
+                let _ = set_cloexec(fd, true);
+                // SAFETY: the old process passed the write end of its pipe at this fd
+                unsafe { File::from_raw_fd(fd) }
+            });
+        return Passed {
+            sockets,
+            ready,
+            starting: None,
+        };
+    }
+
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    if !for_us {
+        return Passed::default();
+    }
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(0);
+    let names: Vec<String> = std::env::var("LISTEN_FDNAMES")
+        .map(|names| names.split(':').map(str::to_owned).collect())
+        .unwrap_or_default();
+    let sockets = (0..count)
+        .map(|i| {
+            // SAFETY: the fds from 3 on are the sockets passed to this process, owned by nothing
+            // else
+            let listener = unsafe { TcpListener::from_raw_fd(LISTEN_FDS_START + i) };
+            let name = names.get(i as usize).cloned().unwrap_or_default();
+            (name, listener)
+        })
+        .collect();
+    Passed {
+        sockets,
+        ..Default::default()
+    }
+}
+
+#[cfg(not(unix))]
+fn passed_sockets() -> Passed {
+    Passed::default()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{check_pid_file, listen};
+
+    #[test]
+    fn test_check_pid_file() {
+        let path = std::env::temp_dir().join(format!("mega-test-{}.pid", std::process::id()));
+        assert!(check_pid_file(&path).is_ok());
+        // this process runs
+        std::fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        assert!(check_pid_file(&path).is_err());
+        // no process has the largest pid
+        std::fs::write(&path, format!("{}\n", i32::MAX)).unwrap();
+        assert!(check_pid_file(&path).is_ok());
+        std::fs::write(&path, "mega").unwrap();
+        assert!(check_pid_file(&path).is_ok());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_listen() {
+        // nothing passed, the socket is bound
+        let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let listener = listen("http", addr).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert!(listen("http", addr).is_err());
+    }
+}
@@ -41,14 +41,33 @@ impl MegaError {
             code: 0,
         }
     }
+
+    /// A ref was changed by someone else since it was read, git clients are told to fetch
+    /// first like git does.
+    pub fn ref_conflict() -> MegaError {
+        MegaError {
+            error: anyhow::anyhow!("fetch first").into(),
+            code: REF_CONFLICT,
+        }
+    }
+
+    pub fn is_ref_conflict(&self) -> bool {
+        self.code == REF_CONFLICT
+    }
 }
 
+/// Code of [`MegaError::ref_conflict`].
+const REF_CONFLICT: i32 = 409;
+
 impl std::fmt::Display for MegaError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "{}", self.error.as_ref().unwrap())
     }
 }
 
+// lets api handlers return storage errors with `?`
+impl std::error::Error for MegaError {}
+
 impl From<anyhow::Error> for MegaError {
     fn from(err: anyhow::Error) -> MegaError {
         MegaError::new(err, 101)
@@ -86,6 +105,8 @@ pub enum ProtocolError {
     IO(#[from] std::io::Error),
     #[error("Authentication failed: {0}")]
     Deny(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
     #[error("Repository not found: {0}")]
     NotFound(String),
     #[error("PackFile too large: {0}")]
@@ -94,6 +115,8 @@ pub enum ProtocolError {
     InvalidInput(String),
     #[error("HTTP Push Has Been Disabled")]
     Disabled,
+    #[error("Service Unavailable: {0}")]
+    Unavailable(String),
 }
 
 impl IntoResponse for ProtocolError {
@@ -103,6 +126,7 @@ impl IntoResponse for ProtocolError {
                 // This error is caused by bad user input so don't log it
                 (StatusCode::UNAUTHORIZED, err)
             }
+            ProtocolError::Forbidden(err) => (StatusCode::FORBIDDEN, err),
             ProtocolError::TooLarge(err) => {
                 (StatusCode::PAYLOAD_TOO_LARGE, err)
             }
@@ -115,6 +139,7 @@ impl IntoResponse for ProtocolError {
                 (StatusCode::NOT_FOUND, err)
             }
             ProtocolError::InvalidInput(err) => (StatusCode::BAD_REQUEST, err),
+            ProtocolError::Unavailable(err) => (StatusCode::SERVICE_UNAVAILABLE, err),
             _ => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Something went wrong".to_owned(),
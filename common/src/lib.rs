@@ -1,5 +1,13 @@
 pub mod config;
+pub mod cron;
+pub mod daemon;
 pub mod enums;
 pub mod errors;
+pub mod log;
 pub mod model;
+pub mod network;
+pub mod plugin;
+pub mod secrets;
 pub mod utils;
+#[cfg(windows)]
+pub mod winservice;
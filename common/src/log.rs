@@ -0,0 +1,406 @@
+//! Logging of the services: the formatter and level of `[log]`, the rotated log files and the
+//! request ids which tie the lines of a request together.
+//!
+//! Every HTTP request and ssh connection runs in a `request` span with its id, so the lines it
+//! logs, down to the storage, carry the id. The id of an HTTP request is taken from its
+//! `X-Request-Id` header if the client or a proxy sent one, and returned in the same header.
+//!
+//! With `[telemetry]` enabled the spans are exported to an OpenTelemetry collector as well, an
+//! HTTP request with a `traceparent` header continues the trace of its caller.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use axum::http::HeaderMap;
+use chrono::Utc;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::Resource;
+use tokio::runtime::Runtime;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::{
+    self,
+    writer::{BoxMakeWriter, MakeWriter, MakeWriterExt},
+};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{reload, Registry};
+
+use crate::config::{Config, LogConfig, LogFormat, LogRotation, TelemetryConfig};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+const MB: u64 = 1024 * 1024;
+
+/// Changes the level of the log while the services run, set up by [`init`].
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Runs the export of the spans, which is set up before the runtime of the service.
+static TELEMETRY: OnceLock<Runtime> = OnceLock::new();
+
+/// Log as configured to the files `<service>-logs.<period>` in `log_path`, and to stdout if
+/// `print_std` is set. The spans are exported as `service` if telemetry is enabled. Warnings and
+/// errors go to the event log as well when running as a Windows service.
+pub fn init(config: &Config, service: &str) {
+    let log = &config.log;
+    let (level, handle) = reload::Layer::new(level_filter(&log.level));
+    let _ = LEVEL.set(handle);
+
+    let file = RollingFile::new(&log.log_path, &format!("{}-logs", service), log);
+    let writer = if log.print_std {
+        BoxMakeWriter::new(std::io::stdout.and(file))
+    } else {
+        BoxMakeWriter::new(file)
+    };
+    let layer = match log.format {
+        LogFormat::Text => fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    };
+    let registry = tracing_subscriber::registry()
+        .with(level)
+        .with(layer)
+        .with(telemetry_layer(&config.telemetry, service));
+    #[cfg(windows)]
+    let registry = registry.with(crate::winservice::EventLog);
+    registry.init();
+}
+
+fn telemetry_layer<S>(
+    config: &TelemetryConfig,
+    service: &str,
+) -> Option<OpenTelemetryLayer<S, Tracer>>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    if !config.enable {
+        return None;
+    }
+    let name = if config.service_name.is_empty() {
+        service.to_owned()
+    } else {
+        config.service_name.clone()
+    };
+    let runtime = TELEMETRY.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("telemetry")
+            .enable_all()
+            .build()
+            .unwrap()
+    });
+    let _guard = runtime.enter();
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.endpoint)
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(err) => {
+            // there is no log yet
+            eprintln!("failed to export traces to {}: {}", config.endpoint, err);
+            return None;
+        }
+    };
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new("service.name", name.clone())]))
+        .build();
+    let tracer = provider.tracer(name);
+    opentelemetry::global::set_tracer_provider(provider);
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Export the spans which are not exported yet, before the service exits.
+pub fn shutdown() {
+    if TELEMETRY.get().is_some() {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// Change the level of the log, e.g. when the config is reloaded.
+pub fn set_level(level: &str) {
+    if let Some(handle) = LEVEL.get() {
+        if let Err(err) = handle.modify(|filter| *filter = level_filter(level)) {
+            tracing::error!("failed to change the log level: {}", err);
+        }
+    }
+}
+
+fn level_filter(level: &str) -> LevelFilter {
+    match level {
+        "trace" => LevelFilter::TRACE,
+        "debug" => LevelFilter::DEBUG,
+        "info" => LevelFilter::INFO,
+        "warn" => LevelFilter::WARN,
+        "error" => LevelFilter::ERROR,
+        _ => LevelFilter::INFO,
+    }
+}
+
+/// A new request id, `received` if it is a usable id a client or proxy sent.
+pub fn request_id(received: Option<&str>) -> String {
+    match received {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= 64
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) =>
+        {
+            id.to_owned()
+        }
+        _ => uuid::Uuid::new_v4().simple().to_string(),
+    }
+}
+
+/// The span the work of a request runs in, `kind` is `http` or `ssh`.
+pub fn request_span(kind: &'static str, request_id: &str) -> tracing::Span {
+    tracing::info_span!("request", kind, request_id)
+}
+
+/// Continue the trace of the caller in `span` if `headers` carry its `traceparent`.
+pub fn continue_trace(span: &tracing::Span, headers: &HeaderMap) {
+    let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    span.set_parent(parent);
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Log file started anew when the period of its rotation ends or when it would grow beyond its
+/// maximum size. Files of the same period are named `prefix.<period>.<n>` once they are full.
+pub struct RollingFile {
+    dir: PathBuf,
+    prefix: String,
+    rotation: LogRotation,
+    max_size: u64,
+    max_files: usize,
+    current: Mutex<Option<Current>>,
+}
+
+struct Current {
+    file: File,
+    period: String,
+    size: u64,
+}
+
+impl RollingFile {
+    pub fn new(dir: &Path, prefix: &str, config: &LogConfig) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            prefix: prefix.to_owned(),
+            rotation: config.rotation,
+            max_size: config.max_file_size * MB,
+            max_files: config.max_files,
+            current: Mutex::new(None),
+        }
+    }
+
+    fn period(&self) -> String {
+        let now = Utc::now();
+        match self.rotation {
+            LogRotation::Hourly => now.format("%Y-%m-%d-%H").to_string(),
+            LogRotation::Daily => now.format("%Y-%m-%d").to_string(),
+            LogRotation::Never => String::new(),
+        }
+    }
+
+    fn path(&self, period: &str) -> PathBuf {
+        if period.is_empty() {
+            self.dir.join(&self.prefix)
+        } else {
+            self.dir.join(format!("{}.{}", self.prefix, period))
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut current = self.current.lock().unwrap();
+        let period = self.period();
+        let len = buf.len() as u64;
+        let rotate = match current.as_ref() {
+            None => true,
+            Some(c) if c.period != period => true,
+            Some(c) => self.max_size > 0 && c.size > 0 && c.size + len > self.max_size,
+        };
+        if rotate {
+            if let Some(c) = current.take() {
+                if c.period == period {
+                    self.archive(&period)?;
+                }
+            }
+            *current = Some(self.open(period)?);
+            self.prune();
+        }
+        let c = current.as_mut().unwrap();
+        c.file.write_all(buf)?;
+        c.size += len;
+        Ok(buf.len())
+    }
+
+    fn open(&self, period: String) -> io::Result<Current> {
+        fs::create_dir_all(&self.dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(&period))?;
+        let size = file.metadata()?.len();
+        if self.max_size > 0 && size >= self.max_size {
+            // full from an earlier run
+            drop(file);
+            self.archive(&period)?;
+            return self.open(period);
+        }
+        Ok(Current { file, period, size })
+    }
+
+    /// Rename the full file of `period` to the next free `prefix.<period>.<n>`.
+    fn archive(&self, period: &str) -> io::Result<()> {
+        let path = self.path(period);
+        let mut n = 1;
+        loop {
+            let archived = PathBuf::from(format!("{}.{}", path.display(), n));
+            if !archived.exists() {
+                return fs::rename(&path, archived);
+            }
+            n += 1;
+        }
+    }
+
+    /// Delete the oldest log files beyond `max_files`.
+    fn prune(&self) {
+        if self.max_files == 0 {
+            return;
+        }
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let dotted = format!("{}.", self.prefix);
+        let mut files: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                let name = e.file_name().to_string_lossy().into_owned();
+                name == self.prefix || name.starts_with(&dotted)
+            })
+            .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+            .collect();
+        if files.len() <= self.max_files {
+            return;
+        }
+        files.sort();
+        for (_, path) in &files[..files.len() - self.max_files] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+pub struct RollingWriter<'a>(&'a RollingFile);
+
+impl Write for RollingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.0.current.lock().unwrap().as_mut() {
+            Some(c) => c.file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for RollingFile {
+    type Writer = RollingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RollingWriter(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use axum::http::HeaderMap;
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::TraceContextExt;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::{request_id, HeaderExtractor, RollingFile, MB};
+    use crate::config::{LogConfig, LogRotation};
+
+    #[test]
+    fn test_rolling_file() {
+        let dir = std::env::temp_dir().join(format!("mega-test-logs-{}", request_id(None)));
+        let config = LogConfig {
+            rotation: LogRotation::Never,
+            max_file_size: 1,
+            max_files: 2,
+            ..Default::default()
+        };
+        let file = RollingFile::new(&dir, "mega-logs", &config);
+        let line = vec![b'x'; (MB / 2) as usize];
+        for _ in 0..5 {
+            file.make_writer().write_all(&line).unwrap();
+        }
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // the oldest full file was deleted
+        assert_eq!(names, ["mega-logs", "mega-logs.2"]);
+    }
+
+    #[test]
+    fn test_request_id() {
+        assert_eq!(request_id(Some("req-42.a_b")), "req-42.a_b");
+        assert_eq!(request_id(None).len(), 32);
+        assert_ne!(request_id(Some("")), "");
+        assert_ne!(request_id(Some("a b")), "a b");
+        assert_eq!(request_id(Some(&"x".repeat(65))).len(), 32);
+    }
+
+    #[test]
+    fn test_header_extractor() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        let parent = TraceContextPropagator::new().extract(&HeaderExtractor(&headers));
+        let span = parent.span();
+        let span_context = span.span_context();
+        assert!(span_context.is_remote());
+        assert_eq!(
+            span_context.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Client address based access control shared by the http and ssh servers.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{NetworkPolicyConfig, NetworkRule, SshConfig};
+use crate::errors::{MegaError, ProtocolError};
+
+/// The kind of operation a request performs, read and write are restricted separately.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccessMode {
+    Read,
+    Write,
+}
+
+/// An IPv4 or IPv6 network in CIDR notation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // compare ipv4-mapped ipv6 addresses as plain ipv4
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            IpAddr::V4(_) => *ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = MegaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| MegaError::with_message(&format!("invalid ip address in '{}'", s)))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix =
+            match prefix {
+                Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(|| {
+                    MegaError::with_message(&format!("invalid prefix in '{}'", s))
+                })?,
+                None => max,
+            };
+        Ok(IpCidr { addr, prefix })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct CompiledRule {
+    allow: Vec<IpCidr>,
+    deny: Vec<IpCidr>,
+}
+
+impl CompiledRule {
+    fn compile(rule: &NetworkRule) -> Result<Self, MegaError> {
+        Ok(CompiledRule {
+            allow: parse_cidrs(&rule.allow)?,
+            deny: parse_cidrs(&rule.deny)?,
+        })
+    }
+
+    fn permits(&self, ip: &IpAddr) -> bool {
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip))
+    }
+}
+
+fn parse_cidrs(list: &[String]) -> Result<Vec<IpCidr>, MegaError> {
+    list.iter().map(|s| s.parse()).collect()
+}
+
+#[derive(Debug, Clone)]
+struct RepoRule {
+    path: PathBuf,
+    read: CompiledRule,
+    write: CompiledRule,
+}
+
+/// Parsed form of [`NetworkPolicyConfig`], build it once when the server starts.
+#[derive(Debug, Clone, Default)]
+pub struct NetworkPolicy {
+    pub enable: bool,
+    pub trust_forwarded_for: bool,
+    read: CompiledRule,
+    write: CompiledRule,
+    repos: Vec<RepoRule>,
+}
+
+impl NetworkPolicy {
+    pub fn new(config: &NetworkPolicyConfig) -> Result<Self, MegaError> {
+        let repos = config
+            .repos
+            .iter()
+            .map(|r| {
+                Ok(RepoRule {
+                    path: PathBuf::from(&r.path),
+                    read: CompiledRule::compile(&r.read)?,
+                    write: CompiledRule::compile(&r.write)?,
+                })
+            })
+            .collect::<Result<Vec<_>, MegaError>>()?;
+        Ok(NetworkPolicy {
+            enable: config.enable,
+            trust_forwarded_for: config.trust_forwarded_for,
+            read: CompiledRule::compile(&config.read)?,
+            write: CompiledRule::compile(&config.write)?,
+            repos,
+        })
+    }
+
+    /// Check whether `ip` may perform an operation of `mode` on `path`.
+    ///
+    /// Instance rules are always applied, repository rules are applied for every
+    /// configured path which is an ancestor of (or equal to) `path`.
+    pub fn check(
+        &self,
+        ip: IpAddr,
+        path: Option<&Path>,
+        mode: AccessMode,
+    ) -> Result<(), ProtocolError> {
+        if !self.enable {
+            return Ok(());
+        }
+        let select = |read: &CompiledRule, write: &CompiledRule| match mode {
+            AccessMode::Read => read.permits(&ip),
+            AccessMode::Write => write.permits(&ip),
+        };
+        let mut allowed = select(&self.read, &self.write);
+        if let Some(path) = path {
+            allowed = allowed
+                && self
+                    .repos
+                    .iter()
+                    .filter(|r| path.starts_with(&r.path))
+                    .all(|r| select(&r.read, &r.write));
+        }
+        if allowed {
+            Ok(())
+        } else {
+            tracing::warn!(
+                "network policy rejected {:?} access from {} to {:?}",
+                mode,
+                ip,
+                path
+            );
+            Err(ProtocolError::Forbidden(format!(
+                "Access from {} is not allowed by network policy",
+                ip
+            )))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AuthFailures {
+    count: u32,
+    since: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// Counts failed authentications per client address, an address failing `max_auth_failures`
+/// times within the failure window is banned for the ban time.
+#[derive(Debug, Default)]
+pub struct AuthLimiter {
+    max_failures: u32,
+    window: Duration,
+    ban_time: Duration,
+    clients: Mutex<HashMap<IpAddr, AuthFailures>>,
+}
+
+impl AuthLimiter {
+    pub fn new(config: &SshConfig) -> Self {
+        AuthLimiter {
+            max_failures: config.max_auth_failures,
+            window: Duration::from_secs(config.failure_window),
+            ban_time: Duration::from_secs(config.ban_time),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_banned(&self, ip: IpAddr, now: Instant) -> bool {
+        let clients = self.clients.lock().unwrap();
+        clients
+            .get(&ip)
+            .and_then(|f| f.banned_until)
+            .is_some_and(|until| now < until)
+    }
+
+    /// Count a failed authentication, returns whether it got the address banned.
+    pub fn record_failure(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.max_failures == 0 {
+            return false;
+        }
+        let mut clients = self.clients.lock().unwrap();
+        // forget addresses which neither failed recently nor are banned
+        clients.retain(|_, f| {
+            now.duration_since(f.since) < self.window
+                || f.banned_until.is_some_and(|until| now < until)
+        });
+        let failures = clients.entry(ip).or_insert(AuthFailures {
+            count: 0,
+            since: now,
+            banned_until: None,
+        });
+        if failures.banned_until.is_some_and(|until| now >= until)
+            || now.duration_since(failures.since) >= self.window
+        {
+            *failures = AuthFailures {
+                count: 0,
+                since: now,
+                banned_until: None,
+            };
+        }
+        failures.count += 1;
+        if failures.count >= self.max_failures && failures.banned_until.is_none() {
+            failures.banned_until = Some(now + self.ban_time);
+            return true;
+        }
+        false
+    }
+
+    /// Reset the failures of an address after it authenticated.
+    pub fn record_success(&self, ip: IpAddr) {
+        self.clients.lock().unwrap().remove(&ip);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::path::Path;
+
+    use super::*;
+    use crate::config::RepoNetworkRule;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let net: IpCidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(&ip("10.1.2.3")));
+        assert!(!net.contains(&ip("10.2.0.1")));
+        assert!(net.contains(&ip("::ffff:10.1.9.9")));
+
+        let host: IpCidr = "192.168.1.5".parse().unwrap();
+        assert!(host.contains(&ip("192.168.1.5")));
+        assert!(!host.contains(&ip("192.168.1.6")));
+
+        let any: IpCidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&ip("8.8.8.8")));
+
+        let v6: IpCidr = "fd00::/8".parse().unwrap();
+        assert!(v6.contains(&ip("fd12::1")));
+        assert!(!v6.contains(&ip("10.0.0.1")));
+
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("not-an-ip".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_network_policy_check() {
+        let config = NetworkPolicyConfig {
+            enable: true,
+            trust_forwarded_for: false,
+            read: NetworkRule {
+                allow: vec![],
+                deny: vec!["203.0.113.0/24".to_owned()],
+            },
+            write: NetworkRule {
+                allow: vec!["10.0.0.0/8".to_owned()],
+                deny: vec![],
+            },
+            repos: vec![RepoNetworkRule {
+                path: "/project/secret".to_owned(),
+                read: NetworkRule {
+                    allow: vec!["10.1.0.0/16".to_owned()],
+                    deny: vec![],
+                },
+                write: NetworkRule::default(),
+            }],
+        };
+        let policy = NetworkPolicy::new(&config).unwrap();
+        let public = Path::new("/project/mega");
+        let secret = Path::new("/project/secret/src");
+
+        assert!(policy
+            .check(ip("8.8.8.8"), Some(public), AccessMode::Read)
+            .is_ok());
+        assert!(policy
+            .check(ip("203.0.113.7"), Some(public), AccessMode::Read)
+            .is_err());
+        assert!(policy
+            .check(ip("8.8.8.8"), Some(public), AccessMode::Write)
+            .is_err());
+        assert!(policy
+            .check(ip("10.2.0.1"), Some(public), AccessMode::Write)
+            .is_ok());
+
+        assert!(policy
+            .check(ip("10.2.0.1"), Some(secret), AccessMode::Read)
+            .is_err());
+        assert!(policy
+            .check(ip("10.1.0.1"), Some(secret), AccessMode::Read)
+            .is_ok());
+        assert!(policy.check(ip("10.2.0.1"), None, AccessMode::Read).is_ok());
+
+        let disabled = NetworkPolicy::default();
+        assert!(disabled
+            .check(ip("203.0.113.7"), None, AccessMode::Write)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_auth_limiter() {
+        let config = SshConfig {
+            max_auth_failures: 3,
+            failure_window: 60,
+            ban_time: 300,
+            ..Default::default()
+        };
+        let limiter = AuthLimiter::new(&config);
+        let client = ip("198.51.100.7");
+        let other = ip("198.51.100.8");
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert!(!limiter.record_failure(client, at(0)));
+        assert!(!limiter.record_failure(client, at(10)));
+        assert!(!limiter.is_banned(client, at(10)));
+        assert!(limiter.record_failure(client, at(20)));
+        assert!(limiter.is_banned(client, at(21)));
+        assert!(!limiter.is_banned(other, at(21)));
+        assert!(limiter.is_banned(client, at(319)));
+        assert!(!limiter.is_banned(client, at(320)));
+
+        // failures outside of the window do not add up
+        assert!(!limiter.record_failure(other, at(400)));
+        assert!(!limiter.record_failure(other, at(430)));
+        assert!(!limiter.record_failure(other, at(500)));
+        assert!(!limiter.is_banned(other, at(500)));
+
+        limiter.record_success(other);
+        assert!(!limiter.record_failure(other, at(501)));
+        assert!(!limiter.record_failure(other, at(502)));
+
+        let disabled = AuthLimiter::default();
+        for secs in 0..20 {
+            assert!(!disabled.record_failure(client, at(secs)));
+        }
+        assert!(!disabled.is_banned(client, at(20)));
+    }
+}
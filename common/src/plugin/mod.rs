@@ -0,0 +1,396 @@
+//! Extension points deployments can customize mega with, without forking it.
+//!
+//! A plugin is one of
+//! - compiled in: a crate of the deployment registers its plugins with [`register_push_hook`],
+//!   [`register_auth_provider`] and [`register_webhook_transform`] and then runs the cli of
+//!   mega, the object storage backends of `jupiter::raw_storage` are registered the same way
+//! - a WebAssembly module: it implements the extension points it exports, see [`wasm`]
+//!
+//! Only the plugins listed in `[[plugins.modules]]` are loaded, on start and in that order,
+//! they are asked in the same order:
+//! - push hooks see the ref updates of every push before its pack is received, and may refuse
+//!   any of them; they are told about the refs which were updated afterwards
+//! - auth providers are asked about the credentials of git and ssh clients which are no access
+//!   token, the first one which knows the user decides
+//! - webhook transforms may change or drop the payloads of received webhooks before they are
+//!   processed
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::PluginConfig;
+use crate::errors::MegaError;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// A push as push hooks see it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Push {
+    /// Path of the repository in the monorepo
+    pub path: String,
+    /// The pusher, `None` for anonymous pushes
+    pub username: Option<String>,
+    pub refs: Vec<RefUpdate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RefUpdate {
+    pub name: String,
+    /// All zeros for a created ref
+    pub old_id: String,
+    /// All zeros for a deleted ref
+    pub new_id: String,
+}
+
+/// A ref update a push hook refuses.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Denial {
+    #[serde(rename = "ref")]
+    pub ref_name: String,
+    /// Shown to the pusher
+    pub reason: String,
+}
+
+#[async_trait]
+pub trait PushHook: Send + Sync {
+    /// The ref updates of `push` which are refused, nothing is refused by default.
+    async fn pre_receive(&self, _push: &Push) -> Result<Vec<Denial>, MegaError> {
+        Ok(Vec::new())
+    }
+
+    /// Called with the refs of `push` which were updated.
+    async fn post_receive(&self, _push: &Push) -> Result<(), MegaError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+pub trait AuthProvider: Send + Sync {
+    /// Whether `secret` is a valid credential of `username`, `None` if the provider does not
+    /// know the user.
+    async fn authenticate(&self, username: &str, secret: &str) -> Result<Option<bool>, MegaError>;
+}
+
+#[async_trait]
+pub trait WebhookTransform: Send + Sync {
+    /// The payload of the webhook event `event` to process instead, `None` drops the event.
+    async fn transform(&self, event: &str, payload: Value) -> Result<Option<Value>, MegaError>;
+}
+
+/// Creates a compiled-in plugin from its `settings`.
+pub type Factory<T> = fn(&Value) -> Result<Arc<T>, MegaError>;
+
+struct Registry {
+    push_hooks: BTreeMap<String, Factory<dyn PushHook>>,
+    auth_providers: BTreeMap<String, Factory<dyn AuthProvider>>,
+    webhook_transforms: BTreeMap<String, Factory<dyn WebhookTransform>>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    push_hooks: BTreeMap::new(),
+    auth_providers: BTreeMap::new(),
+    webhook_transforms: BTreeMap::new(),
+});
+
+/// Make the push hook created by `factory` available as the plugin `name`.
+pub fn register_push_hook(name: &str, factory: Factory<dyn PushHook>) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.push_hooks.insert(name.to_owned(), factory);
+}
+
+/// Make the auth provider created by `factory` available as the plugin `name`.
+pub fn register_auth_provider(name: &str, factory: Factory<dyn AuthProvider>) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.auth_providers.insert(name.to_owned(), factory);
+}
+
+/// Make the webhook transform created by `factory` available as the plugin `name`.
+pub fn register_webhook_transform(name: &str, factory: Factory<dyn WebhookTransform>) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.webhook_transforms.insert(name.to_owned(), factory);
+}
+
+/// The plugins in use, in the order they are asked.
+#[derive(Default)]
+pub struct Plugins {
+    push_hooks: Vec<(String, Arc<dyn PushHook>)>,
+    auth_providers: Vec<(String, Arc<dyn AuthProvider>)>,
+    webhook_transforms: Vec<(String, Arc<dyn WebhookTransform>)>,
+}
+
+impl Plugins {
+    /// Load the plugins of `config`, fails if one of them can't be loaded or does nothing.
+    pub fn load(config: &PluginConfig) -> Result<Self, MegaError> {
+        let mut plugins = Plugins::default();
+        for module in &config.modules {
+            let name = module.name.clone();
+            let before = plugins.len();
+            if module.wasm.is_empty() {
+                let (push_hook, auth_provider, webhook_transform) = {
+                    let registry = REGISTRY.lock().unwrap();
+                    (
+                        registry.push_hooks.get(&name).copied(),
+                        registry.auth_providers.get(&name).copied(),
+                        registry.webhook_transforms.get(&name).copied(),
+                    )
+                };
+                let settings = &module.settings;
+                if let Some(factory) = push_hook {
+                    plugins.push_hooks.push((name.clone(), factory(settings)?));
+                }
+                if let Some(factory) = auth_provider {
+                    plugins
+                        .auth_providers
+                        .push((name.clone(), factory(settings)?));
+                }
+                if let Some(factory) = webhook_transform {
+                    plugins
+                        .webhook_transforms
+                        .push((name.clone(), factory(settings)?));
+                }
+            } else {
+                plugins.load_wasm(module)?;
+            }
+            if plugins.len() == before {
+                return Err(MegaError::with_message(&format!(
+                    "plugin {} provides no extension point",
+                    name
+                )));
+            }
+            tracing::info!("plugin {} loaded", name);
+        }
+        Ok(plugins)
+    }
+
+    #[cfg(feature = "wasm")]
+    fn load_wasm(&mut self, module: &crate::config::PluginModule) -> Result<(), MegaError> {
+        let plugin = wasm::WasmPlugin::load(&module.wasm, module.settings.clone())?;
+        let name = module.name.clone();
+        let plugin = Arc::new(plugin);
+        if plugin.is_push_hook() {
+            self.push_hooks.push((name.clone(), plugin.clone()));
+        }
+        if plugin.is_auth_provider() {
+            self.auth_providers.push((name.clone(), plugin.clone()));
+        }
+        if plugin.is_webhook_transform() {
+            self.webhook_transforms.push((name, plugin));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn load_wasm(&mut self, module: &crate::config::PluginModule) -> Result<(), MegaError> {
+        Err(MegaError::with_message(&format!(
+            "plugin {} is a WASM module, mega is built without WASM plugins",
+            module.name
+        )))
+    }
+
+    fn len(&self) -> usize {
+        self.push_hooks.len() + self.auth_providers.len() + self.webhook_transforms.len()
+    }
+
+    pub fn with_push_hook(mut self, name: &str, hook: Arc<dyn PushHook>) -> Self {
+        self.push_hooks.push((name.to_owned(), hook));
+        self
+    }
+
+    pub fn with_auth_provider(mut self, name: &str, provider: Arc<dyn AuthProvider>) -> Self {
+        self.auth_providers.push((name.to_owned(), provider));
+        self
+    }
+
+    pub fn with_webhook_transform(
+        mut self,
+        name: &str,
+        transform: Arc<dyn WebhookTransform>,
+    ) -> Self {
+        self.webhook_transforms.push((name.to_owned(), transform));
+        self
+    }
+
+    /// The ref updates of `push` refused by any push hook. A hook which fails refuses the whole
+    /// push, so a broken hook does not let through what it should have refused.
+    pub async fn pre_receive(&self, push: &Push) -> Vec<Denial> {
+        let mut denials = Vec::new();
+        for (name, hook) in &self.push_hooks {
+            match hook.pre_receive(push).await {
+                Ok(denied) => denials.extend(denied),
+                Err(err) => {
+                    tracing::error!("push hook {} failed: {}", name, err);
+                    let reason = format!("push hook {} failed", name);
+                    denials.extend(push.refs.iter().map(|r| Denial {
+                        ref_name: r.name.clone(),
+                        reason: reason.clone(),
+                    }));
+                }
+            }
+        }
+        denials
+    }
+
+    /// Tell the push hooks about the updated refs of `push`, failures are only logged.
+    pub async fn post_receive(&self, push: &Push) {
+        for (name, hook) in &self.push_hooks {
+            if let Err(err) = hook.post_receive(push).await {
+                tracing::error!("push hook {} failed after the push: {}", name, err);
+            }
+        }
+    }
+
+    /// Whether an auth provider accepts `secret` of `username`, `false` if none knows the
+    /// user. A provider which fails is skipped.
+    pub async fn authenticate(&self, username: &str, secret: &str) -> bool {
+        for (name, provider) in &self.auth_providers {
+            match provider.authenticate(username, secret).await {
+                Ok(Some(valid)) => return valid,
+                Ok(None) => {}
+                Err(err) => tracing::error!("auth provider {} failed: {}", name, err),
+            }
+        }
+        false
+    }
+
+    /// `payload` of the webhook event `event` passed through every transform, `None` if one of
+    /// them dropped it. A transform which fails leaves the payload as it is.
+    pub async fn transform_webhook(&self, event: &str, mut payload: Value) -> Option<Value> {
+        for (name, transform) in &self.webhook_transforms {
+            match transform.transform(event, payload.clone()).await {
+                Ok(Some(transformed)) => payload = transformed,
+                Ok(None) => {
+                    tracing::info!("webhook transform {} dropped a {} event", name, event);
+                    return None;
+                }
+                Err(err) => tracing::error!("webhook transform {} failed: {}", name, err),
+            }
+        }
+        Some(payload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+
+    use super::{
+        register_push_hook, register_webhook_transform, Denial, Plugins, Push, PushHook, RefUpdate,
+        WebhookTransform,
+    };
+    use crate::config::{PluginConfig, PluginModule};
+    use crate::errors::MegaError;
+
+    /// Refuses the refs named in its settings.
+    struct DenyRefs(Vec<String>);
+
+    #[async_trait]
+    impl PushHook for DenyRefs {
+        async fn pre_receive(&self, push: &Push) -> Result<Vec<Denial>, MegaError> {
+            Ok(push
+                .refs
+                .iter()
+                .filter(|r| self.0.contains(&r.name))
+                .map(|r| Denial {
+                    ref_name: r.name.clone(),
+                    reason: "frozen".to_owned(),
+                })
+                .collect())
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait]
+    impl PushHook for Failing {
+        async fn pre_receive(&self, _push: &Push) -> Result<Vec<Denial>, MegaError> {
+            Err(MegaError::with_message("unreachable"))
+        }
+    }
+
+    struct DropIssues;
+
+    #[async_trait]
+    impl WebhookTransform for DropIssues {
+        async fn transform(&self, event: &str, payload: Value) -> Result<Option<Value>, MegaError> {
+            Ok((event != "issues").then_some(payload))
+        }
+    }
+
+    fn push() -> Push {
+        Push {
+            path: "/project".to_owned(),
+            username: Some("alice".to_owned()),
+            refs: ["refs/heads/main", "refs/heads/dev"]
+                .into_iter()
+                .map(|name| RefUpdate {
+                    name: name.to_owned(),
+                    old_id: "0".repeat(40),
+                    new_id: "1".repeat(40),
+                })
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_registered() {
+        register_push_hook("test_deny_refs", |settings| {
+            let refs = serde_json::from_value(settings["refs"].clone())
+                .map_err(|err| MegaError::with_message(&err.to_string()))?;
+            Ok(Arc::new(DenyRefs(refs)))
+        });
+        register_webhook_transform("test_drop_issues", |_| Ok(Arc::new(DropIssues)));
+        let config = PluginConfig {
+            modules: vec![
+                PluginModule {
+                    name: "test_deny_refs".to_owned(),
+                    settings: json!({"refs": ["refs/heads/main"]}),
+                    ..Default::default()
+                },
+                PluginModule {
+                    name: "test_drop_issues".to_owned(),
+                    ..Default::default()
+                },
+            ],
+        };
+        let plugins = Plugins::load(&config).unwrap();
+        let denials = plugins.pre_receive(&push()).await;
+        assert_eq!(denials.len(), 1);
+        assert_eq!(denials[0].ref_name, "refs/heads/main");
+        assert!(plugins
+            .transform_webhook("issues", json!({}))
+            .await
+            .is_none());
+        assert_eq!(
+            plugins
+                .transform_webhook("pull_request", json!({"a": 1}))
+                .await,
+            Some(json!({"a": 1}))
+        );
+        // unknown by every auth provider
+        assert!(!plugins.authenticate("alice", "secret").await);
+
+        let unknown = PluginConfig {
+            modules: vec![PluginModule {
+                name: "test_unknown".to_owned(),
+                ..Default::default()
+            }],
+        };
+        assert!(Plugins::load(&unknown).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failing_hook_refuses() {
+        let plugins = Plugins::default().with_push_hook("failing", Arc::new(Failing));
+        let denials = plugins.pre_receive(&push()).await;
+        assert_eq!(denials.len(), 2);
+        assert_eq!(denials[0].reason, "push hook failing failed");
+    }
+}
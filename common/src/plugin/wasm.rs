@@ -0,0 +1,266 @@
+//! Plugins in WebAssembly modules, run with wasmtime.
+//!
+//! A module exports its `memory` and `alloc(len: i32) -> i32`, which returns where `len` bytes
+//! can be written. The input of every call is JSON written to memory of `alloc`, an output is
+//! JSON in the memory of the module, returned as an `i64` with its address in the high and its
+//! length in the low 32 bits, 0 is no output. The module implements the extension points it
+//! exports:
+//!
+//! | Export | Input | Output |
+//! | --- | --- | --- |
+//! | `pre_receive(ptr, len) -> i64` | `{"settings", "push"}` | the refused refs, `[{"ref", "reason"}]` |
+//! | `post_receive(ptr, len) -> i64` | `{"settings", "push"}` | none |
+//! | `authenticate(ptr, len) -> i32` | `{"settings", "username", "secret"}` | 1 valid, 0 invalid, -1 unknown user |
+//! | `transform_webhook(ptr, len) -> i64` | `{"settings", "event", "payload"}` | the payload, `null` drops the event, none keeps it |
+//!
+//! Modules import nothing. Every call runs in a new instance with at most [`MAX_MEMORY`] bytes
+//! of memory and [`FUEL`] units of fuel, so a plugin keeps no state between calls and can't
+//! hang a push.
+
+use std::fmt::Display;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use wasmtime::{Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::errors::MegaError;
+use crate::plugin::{AuthProvider, Denial, Push, PushHook, WebhookTransform};
+
+/// Memory an instance may grow to.
+pub const MAX_MEMORY: usize = 64 * 1024 * 1024;
+
+/// Fuel of a call, roughly the number of instructions it may run.
+pub const FUEL: u64 = 1_000_000_000;
+
+#[derive(Clone)]
+pub struct WasmPlugin {
+    engine: Engine,
+    module: Module,
+    settings: Value,
+}
+
+/// An instance with the input of a call written to its memory.
+struct Call {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    input: (i32, i32),
+}
+
+impl WasmPlugin {
+    /// Compile the module at `path`, which is passed `settings` on every call.
+    pub fn load(path: &str, settings: Value) -> Result<Self, MegaError> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config).map_err(wasm_error)?;
+        let module = Module::from_file(&engine, path).map_err(|err| {
+            MegaError::with_message(&format!("failed to load the WASM module {}: {}", path, err))
+        })?;
+        for export in ["memory", "alloc"] {
+            if module.get_export(export).is_none() {
+                return Err(MegaError::with_message(&format!(
+                    "the WASM module {} does not export {}",
+                    path, export
+                )));
+            }
+        }
+        Ok(WasmPlugin {
+            engine,
+            module,
+            settings,
+        })
+    }
+
+    fn exports(&self, name: &str) -> bool {
+        self.module.get_export(name).is_some()
+    }
+
+    pub fn is_push_hook(&self) -> bool {
+        self.exports("pre_receive") || self.exports("post_receive")
+    }
+
+    pub fn is_auth_provider(&self) -> bool {
+        self.exports("authenticate")
+    }
+
+    pub fn is_webhook_transform(&self) -> bool {
+        self.exports("transform_webhook")
+    }
+
+    fn start(&self, input: &Value) -> Result<Call, MegaError> {
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL).map_err(wasm_error)?;
+        let instance = Linker::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .map_err(wasm_error)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasm_error("the export memory is not a memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(wasm_error)?;
+        let input = serde_json::to_vec(input).map_err(wasm_error)?;
+        let len = i32::try_from(input.len()).map_err(wasm_error)?;
+        let ptr = alloc.call(&mut store, len).map_err(wasm_error)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, &input)
+            .map_err(wasm_error)?;
+        Ok(Call {
+            store,
+            instance,
+            memory,
+            input: (ptr, len),
+        })
+    }
+
+    /// Call `func` with `input`, returns its output.
+    fn call(&self, func: &str, input: &Value) -> Result<Option<Value>, MegaError> {
+        let mut call = self.start(input)?;
+        let func = call
+            .instance
+            .get_typed_func::<(i32, i32), i64>(&mut call.store, func)
+            .map_err(wasm_error)?;
+        let output = func.call(&mut call.store, call.input).map_err(wasm_error)?;
+        if output == 0 {
+            return Ok(None);
+        }
+        let ptr = (output >> 32) as u32 as usize;
+        let len = output as u32 as usize;
+        let output = ptr
+            .checked_add(len)
+            .and_then(|end| call.memory.data(&call.store).get(ptr..end))
+            .ok_or_else(|| wasm_error("the output is outside of the memory"))?;
+        serde_json::from_slice(output).map(Some).map_err(wasm_error)
+    }
+
+    /// Call `func` with `input`, returns the number it returned.
+    fn call_i32(&self, func: &str, input: &Value) -> Result<i32, MegaError> {
+        let mut call = self.start(input)?;
+        let func = call
+            .instance
+            .get_typed_func::<(i32, i32), i32>(&mut call.store, func)
+            .map_err(wasm_error)?;
+        func.call(&mut call.store, call.input).map_err(wasm_error)
+    }
+
+    /// Run [`WasmPlugin::call`] off the async threads.
+    async fn run(&self, func: &'static str, input: Value) -> Result<Option<Value>, MegaError> {
+        let plugin = self.clone();
+        tokio::task::spawn_blocking(move || plugin.call(func, &input))
+            .await
+            .map_err(wasm_error)?
+    }
+}
+
+fn wasm_error(err: impl Display) -> MegaError {
+    MegaError::with_message(&format!("WASM plugin: {}", err))
+}
+
+#[async_trait]
+impl PushHook for WasmPlugin {
+    async fn pre_receive(&self, push: &Push) -> Result<Vec<Denial>, MegaError> {
+        if !self.exports("pre_receive") {
+            return Ok(Vec::new());
+        }
+        let input = json!({"settings": self.settings, "push": push});
+        match self.run("pre_receive", input).await? {
+            Some(denials) => serde_json::from_value(denials).map_err(wasm_error),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn post_receive(&self, push: &Push) -> Result<(), MegaError> {
+        if !self.exports("post_receive") {
+            return Ok(());
+        }
+        let input = json!({"settings": self.settings, "push": push});
+        self.run("post_receive", input).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuthProvider for WasmPlugin {
+    async fn authenticate(&self, username: &str, secret: &str) -> Result<Option<bool>, MegaError> {
+        let input = json!({"settings": self.settings, "username": username, "secret": secret});
+        let plugin = self.clone();
+        let res = tokio::task::spawn_blocking(move || plugin.call_i32("authenticate", &input))
+            .await
+            .map_err(wasm_error)??;
+        Ok(match res {
+            1 => Some(true),
+            0 => Some(false),
+            _ => None,
+        })
+    }
+}
+
+#[async_trait]
+impl WebhookTransform for WasmPlugin {
+    async fn transform(&self, event: &str, payload: Value) -> Result<Option<Value>, MegaError> {
+        let input = json!({"settings": self.settings, "event": event, "payload": payload});
+        match self.run("transform_webhook", input).await? {
+            Some(Value::Null) => Ok(None),
+            Some(transformed) => Ok(Some(transformed)),
+            None => Ok(Some(payload)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::WasmPlugin;
+    use crate::plugin::{AuthProvider, Push, PushHook, RefUpdate, WebhookTransform};
+
+    /// Refuses the main branch, knows no user and drops every webhook event, the outputs are
+    /// data of the module.
+    const MODULE: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (data (i32.const 0) "[{\"ref\":\"refs/heads/main\",\"reason\":\"frozen\"}]")
+          (data (i32.const 64) "null")
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "pre_receive") (param i32 i32) (result i64)
+            (i64.const 45))
+          (func (export "authenticate") (param $ptr i32) (param $len i32) (result i32)
+            (i32.const -1))
+          (func (export "transform_webhook") (param i32 i32) (result i64)
+            (i64.or (i64.shl (i64.const 64) (i64.const 32)) (i64.const 4))))
+    "#;
+
+    #[tokio::test]
+    async fn test_wasm_plugin() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("plugin.wat");
+        std::fs::write(&path, MODULE).unwrap();
+        let plugin = WasmPlugin::load(path.to_str().unwrap(), json!({})).unwrap();
+        assert!(plugin.is_push_hook() && plugin.is_auth_provider());
+        assert!(plugin.is_webhook_transform());
+
+        let push = Push {
+            path: "/project".to_owned(),
+            username: None,
+            refs: vec![RefUpdate {
+                name: "refs/heads/main".to_owned(),
+                old_id: "0".repeat(40),
+                new_id: "1".repeat(40),
+            }],
+        };
+        let denials = plugin.pre_receive(&push).await.unwrap();
+        assert_eq!(denials.len(), 1);
+        assert_eq!(denials[0].reason, "frozen");
+        plugin.post_receive(&push).await.unwrap();
+        assert_eq!(plugin.authenticate("alice", "secret").await.unwrap(), None);
+        let transformed = plugin.transform("issues", json!({})).await.unwrap();
+        assert!(transformed.is_none());
+    }
+}
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use idgenerator::IdInstance;
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
 use regex::Regex;
@@ -23,6 +25,8 @@ pub fn generate_link() -> String {
 }
 
 pub const MEGA_BRANCH_NAME: &str = "refs/heads/main";
+/// Refs of monorepo paths whose name starts with it are tags, the others branches
+pub const TAG_REF_PREFIX: &str = "refs/tags/";
 
 pub fn generate_rich_text(content: &str) -> String {
     let json_str = r#"
@@ -125,10 +129,100 @@ pub fn check_conventional_commits_message(msg: &str) -> bool {
     false
 }
 
+/// Move `path` from below `old` to below `new`, returns `None` if `path` is not `old` or inside it.
+///
+/// e.g. `/project/a/src` with `old` = `/project/a` and `new` = `/project/b` becomes `/project/b/src`
+pub fn replace_path_prefix(path: &str, old: &str, new: &str) -> Option<String> {
+    let rest = path.strip_prefix(old)?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(format!("{}{}", new, rest))
+    } else {
+        None
+    }
+}
+
+/// The path of a repository or file in the monorepo built with [`Path`], separated by `/` as
+/// stored and shown on every platform, `Path::join` separates with `\` on Windows.
+pub fn repo_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if std::path::MAIN_SEPARATOR == '\\' {
+        path.replace('\\', "/")
+    } else {
+        path.into_owned()
+    }
+}
+
+/// Match `name` against a glob `pattern`, `*` matches any sequence of characters and `?`
+/// matches a single character, e.g. `release/*` matches `release/1.0`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the name position it was tried at
+    let mut backtrack = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, start)) = backtrack {
+            // let the last `*` consume one more character
+            p = star + 1;
+            n = start + 1;
+            backtrack = Some((star, n));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_replace_path_prefix() {
+        assert_eq!(
+            replace_path_prefix("/project/a/src", "/project/a", "/project/b"),
+            Some("/project/b/src".to_owned())
+        );
+        assert_eq!(
+            replace_path_prefix("/project/a", "/project/a", "/project/b"),
+            Some("/project/b".to_owned())
+        );
+        assert_eq!(
+            replace_path_prefix("/project/ab", "/project/a", "/project/b"),
+            None
+        );
+        assert_eq!(
+            replace_path_prefix("/doc", "/project/a", "/project/b"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_repo_path() {
+        let path = Path::new("/project").join("mega").join("src");
+        assert_eq!(repo_path(&path), "/project/mega/src");
+        assert_eq!(repo_path(Path::new("/")), "/");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("v*", "v1.0.0"));
+        assert!(glob_match("release/*", "release/2024/q1"));
+        assert!(glob_match("main", "main"));
+        assert!(glob_match("v?.*", "v1.2"));
+        assert!(glob_match("*-rc*", "v1-rc2"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("v*", "release"));
+        assert!(!glob_match("main", "main2"));
+        assert!(!glob_match("v?.*", "v10.1"));
+    }
+
     #[test]
     fn test_check_conventional_commits() {
         // successfull cases
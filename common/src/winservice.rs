@@ -0,0 +1,340 @@
+//! Running the services as a Windows service: installing them with the service control manager,
+//! reporting to it how the service is doing and stopping when it asks to, and writing the
+//! warnings and errors of the log to the Windows event log.
+//!
+//! A service is installed with the arguments it runs with, `--windows-service <name>` tells the
+//! process the control manager started it. The control manager stops the service on `Stop` and
+//! on shutdown, `ParamChange`, e.g. by `sc control <name> paramchange`, reloads the config like
+//! `SIGHUP` does on unix.
+//!
+//! The event log source is named after the service and registered on install. Its messages are
+//! those of `EventCreate.exe`, which shows the text of an event as it is.
+use std::ffi::{OsStr, OsString};
+use std::os::windows::ffi::OsStrExt;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{
+    self, ServiceControlHandlerResult, ServiceStatusHandle,
+};
+use windows_service::service_dispatcher;
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+use windows_sys::Win32::System::EventLog::{
+    RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_WARNING_TYPE,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteKeyW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE,
+    KEY_SET_VALUE, REG_DWORD, REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+};
+
+use crate::errors::{MegaError, MegaResult};
+
+/// Key of the event log sources of the Application log.
+const EVENT_SOURCES: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application";
+/// Has a message `%1` for the ids 1 to 1000, so the text of an event is shown as it is.
+const EVENT_MESSAGE_FILE: &str = r"%SystemRoot%\System32\EventCreate.exe";
+const EVENT_ID: u32 = 1;
+
+type Serve = Box<dyn FnOnce() -> MegaResult + Send>;
+
+/// The name of the service and what it runs, taken by [`service_main`].
+static SERVE: Mutex<Option<(String, Serve)>> = Mutex::new(None);
+
+/// The handle the state of the service is reported with.
+static STATUS: Mutex<Option<ServiceStatusHandle>> = Mutex::new(None);
+
+/// The event log source, as an address since handles are not `Sync`.
+static EVENT_SOURCE: OnceLock<usize> = OnceLock::new();
+
+/// Changed whenever the control manager sends `ParamChange`.
+static PARAM_CHANGE: OnceLock<watch::Sender<()>> = OnceLock::new();
+
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+/// Install the service `name` running the current executable with `arguments`, started when
+/// Windows starts, and register its event log source.
+pub fn install(name: &str, display_name: &str, arguments: Vec<OsString>) -> MegaResult {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .map_err(|err| service_error(name, err))?;
+    let info = ServiceInfo {
+        name: OsString::from(name),
+        display_name: OsString::from(display_name),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(|err| service_error(name, err))?;
+    service
+        .set_description("Mega monorepo engine")
+        .map_err(|err| service_error(name, err))?;
+    register_event_source(name)
+}
+
+/// Stop the service `name` if it runs, and remove it with its event log source.
+pub fn uninstall(name: &str) -> MegaResult {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(|err| service_error(name, err))?;
+    let service = manager
+        .open_service(
+            name,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .map_err(|err| service_error(name, err))?;
+    let status = service
+        .query_status()
+        .map_err(|err| service_error(name, err))?;
+    if status.current_state != ServiceState::Stopped {
+        service.stop().map_err(|err| service_error(name, err))?;
+    }
+    // removed once the service stopped and every handle to it is closed
+    service.delete().map_err(|err| service_error(name, err))?;
+    let key = wide(&format!(r"{}\{}", EVENT_SOURCES, name));
+    // SAFETY: the key is a nul terminated string
+    let res = unsafe { RegDeleteKeyW(HKEY_LOCAL_MACHINE, key.as_ptr()) };
+    if res != ERROR_SUCCESS {
+        tracing::warn!("failed to remove the event log source {}: {}", name, res);
+    }
+    Ok(())
+}
+
+/// Run `serve` as the service `name` once the control manager started it, returns when the
+/// service stopped. Fails if the process was not started by the control manager.
+pub fn run(name: &str, serve: impl FnOnce() -> MegaResult + Send + 'static) -> MegaResult {
+    *SERVE.lock().unwrap() = Some((name.to_owned(), Box::new(serve)));
+    service_dispatcher::start(name, ffi_service_main).map_err(|err| {
+        MegaError::with_message(&format!(
+            "failed to run as the windows service {}, it is started by the service control \
+             manager: {}",
+            name, err
+        ))
+    })
+}
+
+/// Changed whenever the control manager asks the service to reload its config.
+pub fn param_changes() -> watch::Receiver<()> {
+    param_change().subscribe()
+}
+
+fn param_change() -> &'static watch::Sender<()> {
+    PARAM_CHANGE.get_or_init(|| watch::channel(()).0)
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    let Some((name, serve)) = SERVE.lock().unwrap().take() else {
+        return;
+    };
+    let handle = match service_control_handler::register(&name, on_control) {
+        Ok(handle) => handle,
+        Err(err) => {
+            tracing::error!("failed to register the windows service {}: {}", name, err);
+            return;
+        }
+    };
+    *STATUS.lock().unwrap() = Some(handle);
+    // SAFETY: the name is a nul terminated string
+    let source = unsafe { RegisterEventSourceW(std::ptr::null(), wide(&name).as_ptr()) };
+    if !source.is_null() {
+        let _ = EVENT_SOURCE.set(source as usize);
+    }
+    set_state(ServiceState::Running, 0);
+    tracing::info!("running as the windows service {}", name);
+
+    let code = match serve() {
+        Ok(()) => 0,
+        Err(err) => {
+            tracing::error!("the service failed: {}", err);
+            1
+        }
+    };
+    crate::log::shutdown();
+    set_state(ServiceState::Stopped, code);
+}
+
+fn on_control(control: ServiceControl) -> ServiceControlHandlerResult {
+    match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            tracing::info!("Stopped by the service control manager, exiting...");
+            set_state(ServiceState::StopPending, 0);
+            crate::log::shutdown();
+            set_state(ServiceState::Stopped, 0);
+            std::process::exit(0);
+        }
+        ServiceControl::ParamChange => {
+            param_change().send_replace(());
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    }
+}
+
+/// Report `state` to the control manager, the service fails with `code` if it stops with one.
+fn set_state(state: ServiceState, code: u32) {
+    let Some(handle) = *STATUS.lock().unwrap() else {
+        return;
+    };
+    let controls_accepted = match state {
+        ServiceState::Running => {
+            ServiceControlAccept::STOP
+                | ServiceControlAccept::SHUTDOWN
+                | ServiceControlAccept::PARAM_CHANGE
+        }
+        _ => ServiceControlAccept::empty(),
+    };
+    let exit_code = match code {
+        0 => ServiceExitCode::Win32(0),
+        code => ServiceExitCode::ServiceSpecific(code),
+    };
+    let status = ServiceStatus {
+        service_type: ServiceType::OWN_PROCESS,
+        current_state: state,
+        controls_accepted,
+        exit_code,
+        checkpoint: 0,
+        wait_hint: Duration::from_secs(10),
+        process_id: None,
+    };
+    if let Err(err) = handle.set_service_status(status) {
+        tracing::error!("failed to report the service state {:?}: {}", state, err);
+    }
+}
+
+/// Register `name` as a source of the Application log, it needs administrator rights like
+/// installing the service.
+fn register_event_source(name: &str) -> MegaResult {
+    let path = wide(&format!(r"{}\{}", EVENT_SOURCES, name));
+    let mut key: HKEY = std::ptr::null_mut();
+    // SAFETY: the path is a nul terminated string and `key` is written on success
+    let res = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            path.as_ptr(),
+            0,
+            std::ptr::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            std::ptr::null(),
+            &mut key,
+            std::ptr::null_mut(),
+        )
+    };
+    if res != ERROR_SUCCESS {
+        return Err(MegaError::with_message(&format!(
+            "failed to register the event log source {}: error {}",
+            name, res
+        )));
+    }
+    let message_file = wide(EVENT_MESSAGE_FILE);
+    // error, warning and information
+    let types: u32 = 7;
+    // SAFETY: `key` is open and the values are as long as given
+    let res = unsafe {
+        let res = RegSetValueExW(
+            key,
+            wide("EventMessageFile").as_ptr(),
+            0,
+            REG_EXPAND_SZ,
+            message_file.as_ptr().cast(),
+            (message_file.len() * 2) as u32,
+        );
+        let res = if res == ERROR_SUCCESS {
+            RegSetValueExW(
+                key,
+                wide("TypesSupported").as_ptr(),
+                0,
+                REG_DWORD,
+                (&types as *const u32).cast(),
+                4,
+            )
+        } else {
+            res
+        };
+        RegCloseKey(key);
+        res
+    };
+    if res != ERROR_SUCCESS {
+        return Err(MegaError::with_message(&format!(
+            "failed to register the event log source {}: error {}",
+            name, res
+        )));
+    }
+    Ok(())
+}
+
+/// Writes the warnings and errors of the log to the event log while the process runs as a
+/// service.
+pub struct EventLog;
+
+impl<S: Subscriber> Layer<S> for EventLog {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let Some(source) = EVENT_SOURCE.get() else {
+            return;
+        };
+        let kind = match *event.metadata().level() {
+            Level::ERROR => EVENTLOG_ERROR_TYPE,
+            Level::WARN => EVENTLOG_WARNING_TYPE,
+            _ => return,
+        };
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        let text = wide(&message.0);
+        let strings = [text.as_ptr()];
+        // SAFETY: the source is registered and the string is nul terminated
+        unsafe {
+            ReportEventW(
+                *source as _,
+                kind,
+                0,
+                EVENT_ID,
+                std::ptr::null_mut(),
+                1,
+                0,
+                strings.as_ptr(),
+                std::ptr::null(),
+            );
+        }
+    }
+}
+
+/// The message of an event followed by its other fields.
+struct Message(String);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            self.0.push_str(&format!("{:?}", value));
+        } else {
+            self.0.push_str(&format!("{}={:?}", field.name(), value));
+        }
+    }
+}
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain([0]).collect()
+}
+
+fn service_error(name: &str, err: windows_service::Error) -> MegaError {
+    MegaError::with_message(&format!("windows service {}: {}", name, err))
+}
@@ -1,3 +1,4 @@
+use axum::extract::State;
 use axum::{Json, Router};
 use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
@@ -22,18 +23,21 @@ pub fn routers() -> Router<MegaApiServiceState> {
 
 /// Handle the GitHub webhook event. <br>
 /// For more details, see https://docs.github.com/zh/webhooks/webhook-events-and-payloads.
+/// The webhook transforms of plugins may change or drop the event before it is processed.
 async fn webhook(
+    State(state): State<MegaApiServiceState>,
     headers: HeaderMap,
     Json(mut payload): Json<Value>
 ) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let event_type = headers
+    let event_name = headers
         .get("X-GitHub-Event")
         .and_then(|v| v.to_str().ok())
-        .expect("Missing X-GitHub-Event header");
-    payload["event_type"] = event_type.into();
+        .expect("Missing X-GitHub-Event header")
+        .to_owned();
+    payload["event_type"] = event_name.as_str().into();
 
-    let event_type = WebhookType::from(event_type);
-    match event_type {
+    let event_type = WebhookType::from(event_name.as_str());
+    match &event_type {
         WebhookType::PullRequest => {
             let action = payload["action"].as_str().unwrap();
             tracing::debug!("PR action: {}", action);
@@ -49,18 +53,18 @@ async fn webhook(
                 let _ = payload["pull_request"]["title"].as_str().unwrap();
                 let _ = payload["pull_request"]["body"].as_str().unwrap();
             }
-
-            GithubWebhookEvent::notify(WebhookType::PullRequest, payload);
-        }
-        WebhookType::Issues => {
-            GithubWebhookEvent::notify(WebhookType::Issues, payload);
         }
+        WebhookType::Issues => {}
         WebhookType::Unknown(_type) => {
             tracing::warn!("Unknown event type: {}", _type);
-            GithubWebhookEvent::notify(WebhookType::Unknown(_type), payload);
         }
     }
 
+    let plugins = &state.inner.context.plugins;
+    if let Some(payload) = plugins.transform_webhook(&event_name, payload).await {
+        GithubWebhookEvent::notify(event_type, payload).await;
+    }
+
     Ok("WebHook OK")
 }
 
@@ -1,4 +1,6 @@
 use common::model::ZtmOptions;
+use gemini::dht::Dht;
+use gemini::nat::Nat;
 use mono::api::MonoApiServiceState;
 
 pub mod github_router;
@@ -11,4 +13,8 @@ pub struct MegaApiServiceState {
     pub inner: MonoApiServiceState,
     pub port: u16,
     pub ztm: ZtmOptions,
+    /// The DHT of the ztm network, if the server joined one
+    pub dht: Option<Dht>,
+    /// The direct connections to the peers of the DHT, if enabled
+    pub nat: Option<Nat>,
 }
@@ -9,6 +9,18 @@ pub struct RepoProvideQuery {
     pub path: String,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct SubscribeQuery {
+    pub identifier: String,
+    /// Where the repository is mirrored, below `monorepo.import_dir`
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct UnsubscribeQuery {
+    pub identifier: String,
+}
+
 impl From<RepoProvideQuery> for ztm_path_mapping::Model {
     fn from(value: RepoProvideQuery) -> Self {
         Self {
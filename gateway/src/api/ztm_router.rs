@@ -1,18 +1,27 @@
 use std::collections::HashMap;
+use std::path::Path;
 
 use axum::{
-    extract::{Query, State},
+    extract::{Extension, Query, State},
     http::StatusCode,
     routing::{get, post},
     Json, Router,
 };
 
 use callisto::ztm_path_mapping;
+use ceres::feature::{self, Target, P2P_SYNC};
 use common::model::CommonResult;
+use gemini::dht::{DhtMessage, DhtResponse, PeerRecord};
+use gemini::lfs::LfsEndpoint;
+use gemini::nat::{NatStatus, PunchRequest, PunchResponse};
 use gemini::nostr::subscribe_git_event;
+use gemini::sync::{self, RefsQuery, RefsResponse, Subscription};
+use gemini::util::repo_alias_to_identifier;
+use mono::api::util;
+use mono::server::middleware::PeerIdentity;
 use vault::get_peerid;
 
-use crate::api::model::RepoProvideQuery;
+use crate::api::model::{RepoProvideQuery, SubscribeQuery, UnsubscribeQuery};
 use crate::api::MegaApiServiceState;
 
 pub fn routers() -> Router<MegaApiServiceState> {
@@ -21,6 +30,33 @@ pub fn routers() -> Router<MegaApiServiceState> {
         .route("/ztm/repo_fork", get(repo_fork))
         .route("/ztm/peer_id", get(peer_id))
         .route("/ztm/alias_to_path", get(alias_to_path))
+        .route("/ztm/dht", post(dht))
+        .route("/ztm/providers", get(providers))
+        .route("/ztm/punch", post(punch))
+        .route("/ztm/nat", get(nat))
+        .route("/ztm/publish", post(publish))
+        .route("/ztm/refs", post(refs))
+        .route("/ztm/subscribe", post(subscribe))
+        .route("/ztm/unsubscribe", post(unsubscribe))
+        .route("/ztm/subscriptions", get(subscriptions))
+        .route("/ztm/lfs", get(lfs_endpoint))
+}
+
+/// Providing and forking repositories is rolled out with the `p2p_sync` feature flag, to the
+/// repositories at or below its paths first.
+async fn check_p2p_sync(
+    state: &State<MegaApiServiceState>,
+    path: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    let target = Target::new(None, path);
+    if feature::is_enabled(&state.inner.context, P2P_SYNC, target).await {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::FORBIDDEN,
+            String::from("p2p sync is not enabled by the p2p_sync feature flag\n"),
+        ))
+    }
 }
 
 async fn repo_provide(
@@ -37,6 +73,8 @@ async fn repo_provide(
         }
     };
     let RepoProvideQuery { path, alias } = json.clone();
+    check_p2p_sync(&state, Some(&path)).await?;
+    let identifier = repo_alias_to_identifier(alias.clone());
     let context = state.inner.context.clone();
     let model: ztm_path_mapping::Model = json.into();
     context
@@ -57,6 +95,13 @@ async fn repo_provide(
         Ok(s) => CommonResult::success(Some(s)),
         Err(err) => CommonResult::failed(err.as_str()),
     };
+    if let (true, Some(dht)) = (res.req_result, state.dht.clone()) {
+        // the lookup of the peers to store the record at takes a while
+        tokio::spawn(async move {
+            let stored = dht.provide(&identifier).await;
+            tracing::info!("Published {} to {} peers of the DHT", identifier, stored);
+        });
+    }
     Ok(Json(res))
 }
 
@@ -73,6 +118,7 @@ async fn repo_fork(
             ));
         }
     };
+    check_p2p_sync(&state, None).await?;
 
     let res = gemini::http::handler::repo_folk_alias(
         state.ztm.ztm_agent_port,
@@ -123,3 +169,176 @@ async fn alias_to_path(
         Err((StatusCode::BAD_REQUEST, String::from("Alias not found\n")))
     }
 }
+
+/// The DHT requests of other peers, sent through the ztm tunnels.
+async fn dht(
+    state: State<MegaApiServiceState>,
+    Json(msg): Json<DhtMessage>,
+) -> Result<Json<DhtResponse>, (StatusCode, String)> {
+    match &state.dht {
+        Some(dht) => Ok(Json(dht.handle(msg).await)),
+        None => Err((StatusCode::NOT_FOUND, String::from("Not joined to a DHT\n"))),
+    }
+}
+
+/// A peer asking through the relay to punch a hole for a direct connection.
+async fn punch(
+    state: State<MegaApiServiceState>,
+    Json(req): Json<PunchRequest>,
+) -> Result<Json<PunchResponse>, (StatusCode, String)> {
+    match &state.nat {
+        Some(nat) => Ok(Json(nat.accept(req))),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            String::from("Direct connections are disabled\n"),
+        )),
+    }
+}
+
+/// The type of the NAT of this peer and how it reaches the other peers.
+async fn nat(
+    state: State<MegaApiServiceState>,
+) -> Result<Json<CommonResult<NatStatus>>, (StatusCode, String)> {
+    let res = match &state.nat {
+        Some(nat) => CommonResult::success(Some(nat.status())),
+        None => CommonResult::failed("Direct connections are disabled"),
+    };
+    Ok(Json(res))
+}
+
+/// The peers providing the repository of `identifier`, found in the DHT.
+async fn providers(
+    Query(query): Query<HashMap<String, String>>,
+    state: State<MegaApiServiceState>,
+) -> Result<Json<CommonResult<Vec<PeerRecord>>>, (StatusCode, String)> {
+    let identifier = match query.get("identifier") {
+        Some(i) => i,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                String::from("Identifier not provide\n"),
+            ));
+        }
+    };
+    let res = match &state.dht {
+        Some(dht) => CommonResult::success(Some(dht.find_providers(identifier).await)),
+        None => CommonResult::failed("Not joined to a DHT, the bootstrap node is not set"),
+    };
+    Ok(Json(res))
+}
+
+/// Publish a repository or monorepo directory to the peers through the DHT only, unlike
+/// `repo_provide` which shares it with the relay of the bootstrap node.
+async fn publish(
+    state: State<MegaApiServiceState>,
+    Json(json): Json<RepoProvideQuery>,
+) -> Result<Json<CommonResult<String>>, (StatusCode, String)> {
+    check_p2p_sync(&state, Some(&json.path)).await?;
+    let res = match sync::publish(&state.inner.context, &json.path, &json.alias).await {
+        Ok(identifier) => {
+            if let Some(dht) = state.dht.clone() {
+                let identifier = identifier.clone();
+                tokio::spawn(async move {
+                    let stored = dht.provide(&identifier).await;
+                    tracing::info!("Published {} to {} peers of the DHT", identifier, stored);
+                });
+            }
+            CommonResult::success(Some(identifier))
+        }
+        Err(err) => CommonResult::failed(&err),
+    };
+    Ok(Json(res))
+}
+
+/// The signed refs of a repository provided by this peer, asked for by the subscribed peers.
+/// Peers may only see the refs of the repositories their principal can read.
+async fn refs(
+    state: State<MegaApiServiceState>,
+    peer: Option<Extension<PeerIdentity>>,
+    Json(query): Json<RefsQuery>,
+) -> Result<Json<RefsResponse>, (StatusCode, String)> {
+    let context = &state.inner.context;
+    let res = sync::serve_refs(context, &query.identifier)
+        .await
+        .map_err(|err| (StatusCode::NOT_FOUND, format!("{}\n", err)))?;
+    let peer = peer.as_ref().map(|Extension(peer)| peer);
+    if util::check_peer_read_access(peer, Path::new(&res.path), context)
+        .await
+        .is_err()
+    {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("{} is not published\n", query.identifier),
+        ));
+    }
+    Ok(Json(res))
+}
+
+async fn subscribe(
+    state: State<MegaApiServiceState>,
+    Json(json): Json<SubscribeQuery>,
+) -> Result<Json<CommonResult<Subscription>>, (StatusCode, String)> {
+    check_p2p_sync(&state, json.path.as_deref()).await?;
+    let context = state.inner.context.clone();
+    let res = match sync::subscribe(&context, &json.identifier, json.path).await {
+        Ok(model) => {
+            // the first sync fetches the repository right away instead of at the next interval
+            if let Some(dht) = state.dht.clone() {
+                let (agent_port, http_port) = (state.ztm.ztm_agent_port, state.port);
+                let model = model.clone();
+                tokio::spawn(async move {
+                    sync::sync(&context, agent_port, http_port, Some(&dht), model).await;
+                });
+            }
+            CommonResult::success(Some(Subscription::from(model)))
+        }
+        Err(err) => CommonResult::failed(&err),
+    };
+    Ok(Json(res))
+}
+
+async fn unsubscribe(
+    state: State<MegaApiServiceState>,
+    Json(json): Json<UnsubscribeQuery>,
+) -> Result<Json<CommonResult<String>>, (StatusCode, String)> {
+    let res =
+        match sync::unsubscribe(&state.inner.context, state.dht.as_ref(), &json.identifier).await {
+            Ok(()) => CommonResult::success(None),
+            Err(err) => CommonResult::failed(&err),
+        };
+    Ok(Json(res))
+}
+
+/// The repositories of other peers this peer is subscribed to, and the state of their mirror.
+async fn subscriptions(
+    state: State<MegaApiServiceState>,
+) -> Result<Json<CommonResult<Vec<Subscription>>>, (StatusCode, String)> {
+    let res = match state
+        .inner
+        .context
+        .services
+        .ztm_storage
+        .get_all_subscriptions()
+        .await
+    {
+        Ok(subscriptions) => CommonResult::success(Some(
+            subscriptions.into_iter().map(Subscription::from).collect(),
+        )),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// The LFS server the clients near this peer are sent to, asked for by the other peers.
+async fn lfs_endpoint(
+    state: State<MegaApiServiceState>,
+) -> Result<Json<LfsEndpoint>, (StatusCode, String)> {
+    let config = &state.inner.context.config.p2p.lfs;
+    if !config.enable {
+        return Err((
+            StatusCode::NOT_FOUND,
+            String::from("LFS objects are not exchanged with peers\n"),
+        ));
+    }
+    Ok(Json(LfsEndpoint::new(config)))
+}
@@ -1,27 +1,46 @@
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::{thread, time};
 
+use axum::middleware;
 use axum::routing::get;
 use axum::{http, Router};
 use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
 use clap::Args;
 
+use gemini::bandwidth::Bandwidth;
 use gemini::cache::cache_public_repo_and_lfs;
+use gemini::dht::{Dht, DhtTransport, ZtmTransport};
+use gemini::lfs::LfsPeers;
+use gemini::nat::{Nat, NatTransport};
+use gemini::noise::{self, Identity, SecureChannels};
+use gemini::relay::{RelayClient, RelayTransport};
+use gemini::sync;
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::decompression::RequestDecompressionLayer;
 use tower_http::trace::TraceLayer;
 
+use common::config::GatewayService;
 use common::model::{CommonOptions, ZtmOptions};
+use common::network::NetworkPolicy;
 use gemini::ztm::agent::{run_ztm_client, LocalZTMAgent};
 use jupiter::context::Context;
+use jupiter::storage::metrics as storage_metrics;
 use mono::api::lfs::lfs_router;
 use mono::api::MonoApiServiceState;
 use mono::server::https_server::{get_method_router, post_method_router, AppState};
+use mono::server::middleware::{maintenance_mode, network_policy, path_redirect, request_id};
+use taurus::metrics as mq_metrics;
 
 use crate::api::{github_router, nostr_router, ztm_router, MegaApiServiceState};
+use crate::middleware::{
+    lfs_offload, peer_identity, rate_limit, record_metrics, Metrics, RateLimiter,
+};
+use crate::routing::{self, Gateway, ServiceInfo, SERVICES};
 
 #[derive(Args, Clone, Debug)]
 pub struct HttpOptions {
@@ -62,7 +81,7 @@ pub async fn https_server(context: Context, options: HttpsOptions) {
         ztm,
     } = options.clone();
 
-    check_run_with_ztm(context.clone(), options.ztm.clone(), https_port);
+    let (dht, nat, relay) = check_run_with_ztm(context.clone(), options.ztm.clone(), https_port);
 
     let app = app(
         context,
@@ -70,6 +89,9 @@ pub async fn https_server(context: Context, options: HttpsOptions) {
         https_port,
         options.common.clone(),
         ztm.clone(),
+        dht,
+        nat,
+        relay,
     )
     .await;
 
@@ -78,8 +100,19 @@ pub async fn https_server(context: Context, options: HttpsOptions) {
     let config = RustlsConfig::from_pem_file(https_cert_path.to_owned(), https_key_path.to_owned())
         .await
         .unwrap();
-    axum_server::bind_rustls(addr, config)
-        .serve(app.into_make_service())
+    let listener = common::daemon::listen("https", addr).unwrap();
+    // stop accepting when a new process takes over, the connections in flight are served
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            common::daemon::draining().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    axum_server::from_tcp_rustls(listener, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
@@ -91,7 +124,7 @@ pub async fn http_server(context: Context, options: HttpOptions) {
         ztm,
     } = options.clone();
 
-    check_run_with_ztm(context.clone(), options.ztm.clone(), http_port);
+    let (dht, nat, relay) = check_run_with_ztm(context.clone(), options.ztm.clone(), http_port);
 
     let app = app(
         context,
@@ -99,16 +132,24 @@ pub async fn http_server(context: Context, options: HttpOptions) {
         http_port,
         options.common.clone(),
         ztm.clone(),
+        dht,
+        nat,
+        relay,
     )
     .await;
 
     let server_url = format!("{}:{}", host, http_port);
 
     let addr = SocketAddr::from_str(&server_url).unwrap();
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    let listener = common::daemon::listen("http", addr).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(common::daemon::draining())
+    .await
+    .unwrap();
 }
 
 pub async fn app(
@@ -117,14 +158,23 @@ pub async fn app(
     port: u16,
     common: CommonOptions,
     ztm: ZtmOptions,
+    dht: Option<Dht>,
+    nat: Option<Nat>,
+    relay: Option<RelayClient>,
 ) -> Router {
     let state = AppState {
-        host,
+        host: host.clone(),
         port,
         context: context.clone(),
         common: common.clone(),
     };
 
+    // exchange the LFS objects with the other peers once joined to the DHT
+    let lfs_peers = dht
+        .clone()
+        .filter(|_| context.config.p2p.lfs.enable)
+        .map(|dht| LfsPeers::new(context.clone(), dht, ztm.ztm_agent_port));
+
     let mega_api_state = MegaApiServiceState {
         inner: MonoApiServiceState {
             context: context.clone(),
@@ -133,6 +183,8 @@ pub async fn app(
             store: None,
         },
         ztm,
+        dht,
+        nat: nat.clone(),
         port,
     };
 
@@ -143,6 +195,10 @@ pub async fn app(
         store: None,
     };
 
+    let policy = Arc::new(
+        NetworkPolicy::new(&context.config.network_policy).expect("Invalid network policy"),
+    );
+
     pub fn mega_routers() -> Router<MegaApiServiceState> {
         Router::new()
             .merge(ztm_router::routers())
@@ -150,36 +206,114 @@ pub async fn app(
             .merge(github_router::routers())
     }
 
+    // every service sees the path below its route, so the path based middleware runs inside
+    let service = |router: Router| {
+        router
+            .layer(middleware::from_fn_with_state(
+                context.clone(),
+                maintenance_mode,
+            ))
+            .layer(middleware::from_fn_with_state(
+                context.clone(),
+                path_redirect,
+            ))
+            .layer(middleware::from_fn_with_state(
+                policy.clone(),
+                network_policy,
+            ))
+    };
+    let mut lfs = lfs_router::routers().with_state(mono_api_state.clone());
+    if let Some(peers) = lfs_peers {
+        peers.start();
+        lfs = lfs.route_layer(middleware::from_fn_with_state(peers, lfs_offload));
+    }
+    let git = Router::new()
+        .merge(lfs)
+        // Using Regular Expressions for Path Matching in Protocol
+        .route("/{*path}", get(get_method_router).post(post_method_router))
+        .with_state(state);
+    let gateway = Gateway::new(
+        context.clone(),
+        service(mono::api::api_router::routers().with_state(mono_api_state)),
+        service(mega_routers().with_state(mega_api_state)),
+        service(git),
+    );
+    for kind in [
+        GatewayService::Mono,
+        GatewayService::Mega,
+        GatewayService::Git,
+    ] {
+        SERVICES.register(ServiceInfo {
+            name: routing::service_name(kind).to_owned(),
+            protocol: "http".to_owned(),
+            address: format!("{}:{}", host, port),
+            routes: gateway.describe_routes(kind),
+        });
+    }
+
+    let mut router = Router::new().route("/api/v1/gateway/services", get(routing::list_services));
+    let metrics = Arc::new(Metrics::default());
+    if context.config.gateway.metrics {
+        let metrics = metrics.clone();
+        let context = context.clone();
+        let nat = nat.clone();
+        router = router.route(
+            "/metrics",
+            get(move || async move {
+                metrics.render()
+                    + &storage_metrics::render()
+                    + &mq_metrics::render(&context).await
+                    + &nat.as_ref().map(Nat::render_metrics).unwrap_or_default()
+                    + &relay
+                        .as_ref()
+                        .map(RelayClient::render_metrics)
+                        .unwrap_or_default()
+                    + &noise::channels()
+                        .map(SecureChannels::render_metrics)
+                        .unwrap_or_default()
+            }),
+        );
+    }
+    let limiter = Arc::new(RateLimiter::new(&context.config));
+    context.live.on_reload({
+        let limiter = limiter.clone();
+        move |config| limiter.set_limits(&config.gateway)
+    });
+
     // add RequestDecompressionLayer for handle gzip encode
     // add TraceLayer for log record
+    // add request_id to run the request and its log records in the span of its id
     // add CorsLayer to add cors header
-    Router::new()
-        .merge(lfs_router::routers().with_state(mono_api_state.clone()))
-        .merge(
-            Router::new()
-                .nest(
-                    "/api/v1/mono",
-                    mono::api::api_router::routers().with_state(mono_api_state.clone()),
-                )
-                .nest(
-                    "/api/v1/mega",
-                    mega_routers().with_state(mega_api_state.clone()),
-                ),
-        )
-        // Using Regular Expressions for Path Matching in Protocol
-        .route("/{*path}", get(get_method_router).post(post_method_router))
+    // add peer_identity to authorize the requests of ztm peers as their principal
+    router
+        .route_layer(middleware::from_fn_with_state(policy, network_policy))
+        .fallback(routing::dispatch)
+        .with_state(Arc::new(gateway))
+        .layer(middleware::from_fn_with_state(
+            context.clone(),
+            peer_identity,
+        ))
         .layer(
             ServiceBuilder::new().layer(CorsLayer::new().allow_origin(Any).allow_headers(vec![
                 http::header::AUTHORIZATION,
                 http::header::CONTENT_TYPE,
             ])),
         )
+        .layer(middleware::from_fn_with_state(limiter, rate_limit))
+        .layer(middleware::from_fn_with_state(metrics, record_metrics))
         .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(request_id))
         .layer(RequestDecompressionLayer::new())
-        .with_state(state)
 }
 
-pub fn check_run_with_ztm(context: Context, ztm: ZtmOptions, http_port: u16) {
+/// Join the ztm mesh of the bootstrap node and the DHT of its peers, returns the DHT, the
+/// direct connections to its peers and the connections to the relay nodes if the bootstrap node
+/// is set.
+pub fn check_run_with_ztm(
+    context: Context,
+    ztm: ZtmOptions,
+    http_port: u16,
+) -> (Option<Dht>, Option<Nat>, Option<RelayClient>) {
     //Mega server join a ztm mesh
     match ztm.bootstrap_node {
         Some(bootstrap_node) => {
@@ -194,6 +328,55 @@ pub fn check_run_with_ztm(context: Context, ztm: ZtmOptions, http_port: u16) {
             ztm_agent.clone().start_ztm_agent();
             thread::sleep(time::Duration::from_secs(3));
 
+            let keypair = vault::get_keypair();
+            if context.config.p2p.noise.enable {
+                start_noise(&context, &peer_id, http_port);
+            }
+            let mut relay: Box<dyn DhtTransport> = Box::new(ZtmTransport {
+                agent_port: ztm.ztm_agent_port,
+            });
+            let relay_nodes = context.config.p2p.relay_nodes.clone();
+            let relay_client = if relay_nodes.is_empty() {
+                None
+            } else {
+                let client = RelayClient::new(peer_id.clone(), keypair, relay_nodes);
+                relay = Box::new(RelayTransport {
+                    client: client.clone(),
+                    fallback: relay,
+                });
+                Some(client)
+            };
+            let nat_config = context.config.p2p.nat.clone();
+            let nat = if nat_config.enable {
+                match Nat::bind(peer_id.clone(), nat_config, ztm.ztm_agent_port) {
+                    Ok(nat) => Some(nat),
+                    Err(e) => {
+                        tracing::error!("Failed to bind the sockets of direct connections: {}", e);
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+            let dht_config = context.config.p2p.dht.clone();
+            let dht = match nat.clone() {
+                Some(nat) => Dht::new(
+                    peer_id.clone(),
+                    keypair,
+                    dht_config,
+                    NatTransport { nat, relay },
+                ),
+                None => Dht::new(peer_id.clone(), keypair, dht_config, relay),
+            };
+            if let Some(nat) = &nat {
+                nat.start(dht.clone());
+            }
+            if let Some(client) = &relay_client {
+                client.start(dht.clone());
+            }
+            dht.start(context.clone(), ztm_agent.clone());
+            sync::start(context.clone(), ztm.ztm_agent_port, http_port, dht.clone());
+
             let bootstrap_node_clone = bootstrap_node.clone();
             let config_clone = context.config.clone();
             let ztm_agent_clone = ztm_agent.clone();
@@ -214,11 +397,34 @@ pub fn check_run_with_ztm(context: Context, ztm: ZtmOptions, http_port: u16) {
                     cache_public_repo_and_lfs(bootstrap_node, context, ztm_agent, http_port).await
                 });
             }
+            (Some(dht), nat, relay_client)
         }
         None => {
             tracing::info!("The bootstrap node is not set, prepare to start mega server locally");
+            (None, None, None)
         }
-    };
+    }
+}
+
+// Encrypt the ztm tunnels to the other peers, and accept the channels of their tunnels. The
+// transfers through them are limited to the rates of `p2p.bandwidth`.
+fn start_noise(context: &Context, peer_id: &str, http_port: u16) {
+    let config = context.config.p2p.noise.clone();
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, config.port));
+    let listener = std::net::TcpListener::bind(addr)
+        .and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            tokio::net::TcpListener::from_std(listener)
+        })
+        .unwrap_or_else(|e| panic!("Failed to listen for Noise channels on {}: {}", addr, e));
+    let identity = Identity::new(peer_id.to_owned(), &vault::get_keypair());
+    let bandwidth = Bandwidth::new(context.config.p2p.bandwidth.clone());
+    context.live.on_reload({
+        let bandwidth = bandwidth.clone();
+        move |config| bandwidth.set_config(config.p2p.bandwidth.clone())
+    });
+    let channels = noise::init(identity, config, bandwidth);
+    tokio::spawn(channels.serve(listener, http_port));
 }
 
 #[cfg(test)]
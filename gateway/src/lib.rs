@@ -1,5 +1,8 @@
 pub mod api;
 pub mod https_server;
+pub mod middleware;
+pub mod relay_server;
+pub mod routing;
 
 #[cfg(test)]
 mod tests {}
@@ -0,0 +1,293 @@
+//! Middleware shared by all services behind the gateway.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use axum::body::{to_bytes, Body};
+use axum::extract::{ConnectInfo, State};
+use axum::http::{header, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use ceres::lfs::lfs_structs::{BatchRequest, BatchResponse};
+use common::config::{Config, GatewayConfig};
+use gemini::lfs::LfsPeers;
+use gemini::noise;
+use jupiter::context::Context;
+use mono::git_protocol::peer_principal;
+use mono::server::middleware::{client_ip, ClientIp, PeerIdentity};
+
+use crate::routing::ServiceName;
+
+/// Clients tracked before idle ones are forgotten.
+const MAX_TRACKED_CLIENTS: usize = 4096;
+/// Bytes of an LFS batch request read to offload its downloads.
+const MAX_BATCH_SIZE: usize = 16 * 1024 * 1024;
+
+/// Token bucket per client address, every client may send `burst` requests at once and
+/// `rate` requests per second after.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// `(rate, burst)`, replaced when the config is reloaded
+    limits: RwLock<(f64, f64)>,
+    trust_forwarded_for: bool,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        let limiter = Self {
+            limits: RwLock::new((0.0, 1.0)),
+            trust_forwarded_for: config.network_policy.trust_forwarded_for,
+            buckets: Mutex::new(HashMap::new()),
+        };
+        limiter.set_limits(&config.gateway);
+        limiter
+    }
+
+    /// Apply the rate limits of `config`, the buckets of the clients are kept.
+    pub fn set_limits(&self, config: &GatewayConfig) {
+        *self.limits.write().unwrap() = (
+            config.rate_limit as f64,
+            config.rate_limit_burst.max(1) as f64,
+        );
+    }
+
+    /// Take a token of `ip`, returns how long the client has to wait if none is left.
+    fn acquire(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let (rate, burst) = *self.limits.read().unwrap();
+        if rate == 0.0 {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst
+            });
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Answer `429 Too Many Requests` to clients above the configured rate.
+pub async fn rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if limiter.limits.read().unwrap().0 == 0.0 {
+        return next.run(req).await;
+    }
+    let Some(ip) = client_ip(&req, limiter.trust_forwarded_for) else {
+        return next.run(req).await;
+    };
+    match limiter.acquire(ip, Instant::now()) {
+        Ok(()) => next.run(req).await,
+        Err(wait) => {
+            tracing::debug!("rate limit exceeded by {}", ip);
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, wait.as_secs().max(1).to_string())],
+                "rate limit exceeded",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Request counters by service and status.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(&'static str, u16), RequestStats>>,
+}
+
+#[derive(Debug, Default)]
+struct RequestStats {
+    count: u64,
+    seconds: f64,
+}
+
+impl Metrics {
+    fn record(&self, service: &'static str, status: u16, elapsed: Duration) {
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry((service, status)).or_default();
+        stats.count += 1;
+        stats.seconds += elapsed.as_secs_f64();
+    }
+
+    /// The counters in the prometheus text format.
+    pub fn render(&self) -> String {
+        let requests = self.requests.lock().unwrap();
+        let mut res = String::new();
+        res.push_str("# HELP mega_http_requests_total Requests handled by the gateway.\n");
+        res.push_str("# TYPE mega_http_requests_total counter\n");
+        for ((service, status), stats) in requests.iter() {
+            let _ = writeln!(
+                res,
+                "mega_http_requests_total{{service=\"{}\",status=\"{}\"}} {}",
+                service, status, stats.count
+            );
+        }
+        res.push_str("# HELP mega_http_request_seconds_total Time spent handling requests.\n");
+        res.push_str("# TYPE mega_http_request_seconds_total counter\n");
+        for ((service, status), stats) in requests.iter() {
+            let _ = writeln!(
+                res,
+                "mega_http_request_seconds_total{{service=\"{}\",status=\"{}\"}} {}",
+                service, status, stats.seconds
+            );
+        }
+        res
+    }
+}
+
+/// Count every request by the service which handled it, requests answered by the gateway
+/// itself count as `gateway`.
+pub async fn record_metrics(
+    State(metrics): State<Arc<Metrics>>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let res = next.run(req).await;
+    let service = res
+        .extensions()
+        .get::<ServiceName>()
+        .map_or("gateway", |name| name.0);
+    metrics.record(service, res.status().as_u16(), start.elapsed());
+    res
+}
+
+/// Add the [`PeerIdentity`] of the requests which came through the encrypted channel of a ztm
+/// peer, the handlers authorize them as the principal of the peer.
+pub async fn peer_identity(
+    State(context): State<Context>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Response {
+    let peer_id = noise::channels()
+        .zip(req.extensions().get::<ConnectInfo<SocketAddr>>())
+        .and_then(|(channels, ConnectInfo(addr))| channels.peer_of(addr));
+    if let Some(peer_id) = peer_id {
+        let principal = peer_principal(&context, &peer_id).await;
+        tracing::debug!("request of peer {} as {:?}", peer_id, principal);
+        req.extensions_mut()
+            .insert(PeerIdentity { peer_id, principal });
+    }
+    next.run(req).await
+}
+
+/// Offload the LFS downloads to the peers, see [`LfsPeers`]. The objects missing from a download
+/// batch are fetched from the peers first, and the clients near a peer providing an object
+/// download it from that peer. The objects uploaded here are provided to the peers.
+pub async fn lfs_offload(
+    State(peers): State<LfsPeers>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = req.uri().path().to_owned();
+    if req.method() == Method::PUT {
+        let oid = path.strip_prefix("/objects/").map(str::to_owned);
+        let res = next.run(req).await;
+        if let (true, Some(oid)) = (res.status().is_success(), oid) {
+            tokio::spawn(async move { peers.announce(&oid).await });
+        }
+        return res;
+    }
+    if req.method() != Method::POST || path != "/objects/batch" {
+        return next.run(req).await;
+    }
+
+    let ip = req.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip);
+    let (parts, body) = req.into_parts();
+    let body = match to_bytes(body, MAX_BATCH_SIZE).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::PAYLOAD_TOO_LARGE.into_response(),
+    };
+    let download = serde_json::from_slice::<BatchRequest>(&body)
+        .is_ok_and(|batch| batch.operation == "download");
+    let res = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if !download || !res.status().is_success() {
+        return res;
+    }
+    let (mut parts, body) = res.into_parts();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let mut batch = match serde_json::from_slice::<BatchResponse>(&body) {
+        Ok(batch) => batch,
+        Err(_) => return Response::from_parts(parts, Body::from(body)),
+    };
+    peers.offload(&mut batch, ip).await;
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(serde_json::to_vec(&batch).unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Mutex, RwLock};
+    use std::time::{Duration, Instant};
+
+    use common::config::GatewayConfig;
+
+    use super::RateLimiter;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter {
+            limits: RwLock::new((2.0, 3.0)),
+            trust_forwarded_for: false,
+            buckets: Mutex::new(HashMap::new()),
+        };
+        let ip = "10.0.0.1".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.acquire(ip, now).is_ok());
+        }
+        assert_eq!(limiter.acquire(ip, now), Err(Duration::from_millis(500)));
+        // other clients have their own bucket
+        assert!(limiter.acquire("10.0.0.2".parse().unwrap(), now).is_ok());
+
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.acquire(ip, later).is_ok());
+        assert!(limiter.acquire(ip, later).is_err());
+        // the bucket never holds more than the burst
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.acquire(ip, much_later).is_ok());
+        }
+        assert!(limiter.acquire(ip, much_later).is_err());
+
+        // reloaded limits apply to the clients seen before
+        limiter.set_limits(&GatewayConfig {
+            rate_limit: 10,
+            ..Default::default()
+        });
+        assert!(limiter
+            .acquire(ip, much_later + Duration::from_millis(100))
+            .is_ok());
+    }
+}
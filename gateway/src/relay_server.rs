@@ -0,0 +1,78 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use axum::routing::get;
+use axum::{Json, Router};
+use clap::Args;
+use tower_http::trace::TraceLayer;
+
+use common::config::Config;
+use common::model::CommonOptions;
+use gemini::relay::{RelayServer, RelayStatus};
+
+#[derive(Args, Clone, Debug)]
+pub struct RelayOptions {
+    #[clap(flatten)]
+    pub common: CommonOptions,
+
+    /// Port the peers connect to
+    #[arg(long, default_value_t = 8002)]
+    pub relay_port: u16,
+
+    /// Port of the status of the relay node and its metrics
+    #[arg(long, default_value_t = 8003)]
+    pub http_port: u16,
+}
+
+/// Run a relay node forwarding the messages between the peers which can't connect directly,
+/// with its status at `/api/v1/relay/status` and its metrics at `/metrics` of the http port.
+pub async fn relay_server(config: Config, options: RelayOptions) {
+    let RelayOptions {
+        common: CommonOptions { host, .. },
+        relay_port,
+        http_port,
+    } = options;
+    let relay = RelayServer::new(config.p2p.relay.clone());
+
+    let addr = SocketAddr::from_str(&format!("{}:{}", host, relay_port)).unwrap();
+    let listener = common::daemon::listen("relay", addr).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    tracing::info!("Relay node listening on {}", addr);
+    tokio::spawn({
+        let relay = relay.clone();
+        async move {
+            tokio::select! {
+                _ = relay.serve(listener) => {}
+                _ = common::daemon::draining() => {}
+            }
+        }
+    });
+
+    let addr = SocketAddr::from_str(&format!("{}:{}", host, http_port)).unwrap();
+    let listener = common::daemon::listen("http", addr).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    axum::serve(listener, app(&config, relay))
+        .with_graceful_shutdown(common::daemon::draining())
+        .await
+        .unwrap();
+}
+
+pub fn app(config: &Config, relay: RelayServer) -> Router {
+    let mut router = Router::new().route(
+        "/api/v1/relay/status",
+        get({
+            let relay = relay.clone();
+            move || async move { Json::<RelayStatus>(relay.status()) }
+        }),
+    );
+    if config.gateway.metrics {
+        router = router.route(
+            "/metrics",
+            get(move || async move { relay.render_metrics() }),
+        );
+    }
+    router.layer(TraceLayer::new_for_http())
+}
+
+#[cfg(test)]
+mod tests {}
@@ -0,0 +1,250 @@
+//! Host and path based routing of the gateway.
+//!
+//! Every service is a router of its own. A request is matched against the configured routes
+//! first and the default layout after, the matched prefix is removed from the path before the
+//! request is handed to the service, so services don't need to know where they are mounted.
+
+use std::sync::{Arc, RwLock};
+
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::uri::Authority;
+use axum::http::{header, HeaderValue, Request, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::{Json, Router};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tower::ServiceExt;
+
+use common::config::{GatewayRoute, GatewayService};
+use common::model::CommonResult;
+use jupiter::context::Context;
+use mono::git_protocol::http::http_auth_user;
+
+lazy_static! {
+    /// Services running in this process, listed at `/api/v1/gateway/services` for discovery.
+    pub static ref SERVICES: ServiceRegistry = ServiceRegistry::default();
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ServiceInfo {
+    pub name: String,
+    pub protocol: String,
+    /// Address the service listens on
+    pub address: String,
+    /// Hosts and path prefixes the service is reachable at through the gateway
+    pub routes: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct ServiceRegistry {
+    services: RwLock<Vec<ServiceInfo>>,
+}
+
+impl ServiceRegistry {
+    /// Add a service, replacing an earlier registration with the same name.
+    pub fn register(&self, info: ServiceInfo) {
+        let mut services = self.services.write().unwrap();
+        services.retain(|s| s.name != info.name);
+        services.push(info);
+    }
+
+    pub fn list(&self) -> Vec<ServiceInfo> {
+        self.services.read().unwrap().clone()
+    }
+}
+
+/// Service which handled a request, attached to its response for the metrics.
+#[derive(Clone, Copy, Debug)]
+pub struct ServiceName(pub &'static str);
+
+pub fn service_name(service: GatewayService) -> &'static str {
+    match service {
+        GatewayService::Mono => "mono",
+        GatewayService::Mega => "mega",
+        GatewayService::Git => "git",
+    }
+}
+
+/// The layout of a gateway without configured routes.
+fn default_routes() -> Vec<GatewayRoute> {
+    [
+        ("/api/v1/mono", GatewayService::Mono),
+        ("/api/v1/mega", GatewayService::Mega),
+        ("/", GatewayService::Git),
+    ]
+    .into_iter()
+    .map(|(prefix, service)| GatewayRoute {
+        prefix: prefix.to_owned(),
+        service,
+        ..Default::default()
+    })
+    .collect()
+}
+
+pub struct Gateway {
+    context: Context,
+    routes: Vec<GatewayRoute>,
+    mono: Router,
+    mega: Router,
+    git: Router,
+}
+
+impl Gateway {
+    pub fn new(context: Context, mono: Router, mega: Router, git: Router) -> Self {
+        let mut routes = context.config.gateway.routes.clone();
+        routes.extend(default_routes());
+        Self {
+            context,
+            routes,
+            mono,
+            mega,
+            git,
+        }
+    }
+
+    /// Where `service` is reachable, like `lfs.example.com/` or `/api/v1/mono`.
+    pub fn describe_routes(&self, service: GatewayService) -> Vec<String> {
+        self.routes
+            .iter()
+            .filter(|route| route.service == service)
+            .map(|route| format!("{}{}", route.host, route.prefix))
+            .collect()
+    }
+
+    fn router(&self, service: GatewayService) -> &Router {
+        match service {
+            GatewayService::Mono => &self.mono,
+            GatewayService::Mega => &self.mega,
+            GatewayService::Git => &self.git,
+        }
+    }
+}
+
+/// Hand the request to the service of the first matching route.
+pub async fn dispatch(State(gateway): State<Arc<Gateway>>, mut req: Request<Body>) -> Response {
+    let host = request_host(&req);
+    let Some((route, path)) = find_route(&gateway.routes, host.as_deref(), req.uri().path()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let name = ServiceName(service_name(route.service));
+    if route.auth
+        && http_auth_user(req.headers(), &gateway.context)
+            .await
+            .is_none()
+    {
+        let mut res = (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Basic realm=\"Mega\"")],
+            "authentication required",
+        )
+            .into_response();
+        res.extensions_mut().insert(name);
+        return res;
+    }
+
+    *req.uri_mut() = replace_path(req.uri(), &path);
+    let mut res = gateway
+        .router(route.service)
+        .clone()
+        .oneshot(req)
+        .await
+        .unwrap_or_else(|err| match err {});
+    // redirects of the service are relative to where it is mounted
+    let prefix = route.prefix.trim_end_matches('/');
+    if !prefix.is_empty() {
+        let location = res
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| v.starts_with('/'))
+            .and_then(|v| HeaderValue::from_str(&format!("{}{}", prefix, v)).ok());
+        if let Some(location) = location {
+            res.headers_mut().insert(header::LOCATION, location);
+        }
+    }
+    res.extensions_mut().insert(name);
+    res
+}
+
+pub async fn list_services() -> Json<CommonResult<Vec<ServiceInfo>>> {
+    Json(CommonResult::success(Some(SERVICES.list())))
+}
+
+/// Host name the request is sent to, without the port.
+fn request_host(req: &Request<Body>) -> Option<String> {
+    let authority = match req.headers().get(header::HOST) {
+        Some(host) => host.to_str().ok()?.parse::<Authority>().ok()?,
+        None => req.uri().authority()?.clone(),
+    };
+    Some(authority.host().to_owned())
+}
+
+/// The first route matching `host` and `path`, with the path left for the service.
+fn find_route<'a>(
+    routes: &'a [GatewayRoute],
+    host: Option<&str>,
+    path: &str,
+) -> Option<(&'a GatewayRoute, String)> {
+    routes.iter().find_map(|route| {
+        if !route.host.is_empty() && !host.is_some_and(|h| h.eq_ignore_ascii_case(&route.host)) {
+            return None;
+        }
+        let rest = path.strip_prefix(route.prefix.trim_end_matches('/'))?;
+        match rest {
+            "" => Some((route, "/".to_owned())),
+            rest if rest.starts_with('/') => Some((route, rest.to_owned())),
+            _ => None,
+        }
+    })
+}
+
+fn replace_path(uri: &Uri, path: &str) -> Uri {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_owned(),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().unwrap());
+    Uri::from_parts(parts).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use common::config::{GatewayRoute, GatewayService};
+
+    use super::{default_routes, find_route};
+
+    #[test]
+    fn test_find_route() {
+        let mut routes = vec![GatewayRoute {
+            host: "lfs.example.com".to_owned(),
+            prefix: "/lfs/".to_owned(),
+            service: GatewayService::Git,
+            auth: true,
+        }];
+        routes.extend(default_routes());
+
+        let (route, path) =
+            find_route(&routes, Some("LFS.example.com"), "/lfs/objects/batch").unwrap();
+        assert!(route.auth);
+        assert_eq!(path, "/objects/batch");
+
+        // other hosts and paths only sharing the prefix use the default layout
+        let (route, path) = find_route(&routes, Some("example.com"), "/lfs/objects").unwrap();
+        assert!(!route.auth);
+        assert_eq!(path, "/lfs/objects");
+        let (route, _) = find_route(&routes, Some("lfs.example.com"), "/lfsx").unwrap();
+        assert!(!route.auth);
+
+        let (route, path) = find_route(&routes, None, "/api/v1/mono").unwrap();
+        assert_eq!(route.service, GatewayService::Mono);
+        assert_eq!(path, "/");
+        let (route, path) = find_route(&routes, None, "/api/v1/mega/ztm/peer_id").unwrap();
+        assert_eq!(route.service, GatewayService::Mega);
+        assert_eq!(path, "/ztm/peer_id");
+        let (route, path) = find_route(&routes, None, "/project/mega.git/info/refs").unwrap();
+        assert_eq!(route.service, GatewayService::Git);
+        assert_eq!(path, "/project/mega.git/info/refs");
+    }
+}
@@ -0,0 +1,343 @@
+//! Rate limits of the transfers with the other peers, so seeding repositories to the network
+//! doesn't saturate the uplink of the instance.
+//!
+//! Every byte sent to or received from a peer through a Noise channel takes tokens of the
+//! bucket of its peer, then of the bucket of all the peers, each refilled at its rate of
+//! `p2p.bandwidth`. A transfer waiting for a bucket queues behind the other transfers waiting
+//! for it, and takes at most [`QUANTUM`] bytes at its turn before queueing again, so the
+//! transfers running at the same time share a rate equally whatever the size of their chunks.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use common::config::{BandwidthConfig, PeerBandwidth};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::{sleep, Instant};
+
+/// Bytes a transfer takes from a bucket at its turn.
+pub const QUANTUM: usize = 16 * 1024;
+
+/// Token bucket of bytes, `rate` bytes may be sent every second and a second of them at once.
+/// A larger chunk takes the tokens in advance, and the next ones wait until they are back.
+pub(crate) struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub(crate) fn new(rate: u64, now: Instant) -> Self {
+        Bucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            updated: now,
+        }
+    }
+
+    /// Take `len` bytes, returns how long to wait before sending them.
+    pub(crate) fn take(&mut self, len: usize, now: Instant) -> Duration {
+        if self.rate == 0.0 {
+            return Duration::ZERO;
+        }
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.updated = now;
+        self.tokens -= len as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+
+    fn set_rate(&mut self, rate: u64) {
+        let rate = rate as f64;
+        if rate != self.rate {
+            self.tokens = self.tokens.min(rate);
+            self.rate = rate;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+const DIRECTIONS: [&str; 2] = ["upload", "download"];
+
+// A bucket and the transfers queueing for it. The rate is kept aside, so a reload doesn't wait
+// for the transfers.
+struct Lane {
+    rate: AtomicU64,
+    bucket: AsyncMutex<Bucket>,
+}
+
+impl Lane {
+    fn new(rate: u64) -> Self {
+        Lane {
+            rate: AtomicU64::new(rate),
+            bucket: AsyncMutex::new(Bucket::new(rate, Instant::now())),
+        }
+    }
+
+    // Wait until `len` bytes may pass, returns how long. The lock is held while waiting, it
+    // serves the transfers in the order they queued.
+    async fn take(&self, len: usize) -> Duration {
+        let mut bucket = self.bucket.lock().await;
+        bucket.set_rate(self.rate.load(Ordering::Relaxed));
+        let wait = bucket.take(len, Instant::now());
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+        wait
+    }
+}
+
+struct PeerLanes {
+    lanes: Arc<[Lane; 2]>,
+    transfers: usize,
+}
+
+/// The rate limits of the transfers with the other peers, see the module docs.
+#[derive(Clone)]
+pub struct Bandwidth {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: RwLock<BandwidthConfig>,
+    total: [Lane; 2],
+    // the buckets of the peers with running transfers
+    peers: Mutex<HashMap<String, PeerLanes>>,
+    bytes: [AtomicU64; 2],
+    throttled_ms: [AtomicU64; 2],
+    transfers: AtomicU64,
+}
+
+impl Bandwidth {
+    pub fn new(config: BandwidthConfig) -> Self {
+        Bandwidth {
+            inner: Arc::new(Inner {
+                total: [
+                    Lane::new(config.upload_rate),
+                    Lane::new(config.download_rate),
+                ],
+                config: RwLock::new(config),
+                peers: Mutex::new(HashMap::new()),
+                bytes: Default::default(),
+                throttled_ms: Default::default(),
+                transfers: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Put the rates of `config` in use, for the running transfers too.
+    pub fn set_config(&self, config: BandwidthConfig) {
+        let inner = &self.inner;
+        inner.total[0]
+            .rate
+            .store(config.upload_rate, Ordering::Relaxed);
+        inner.total[1]
+            .rate
+            .store(config.download_rate, Ordering::Relaxed);
+        for (peer, PeerLanes { lanes, .. }) in inner.peers.lock().unwrap().iter() {
+            let rates = peer_rates(&config, peer);
+            lanes[0].rate.store(rates.upload_rate, Ordering::Relaxed);
+            lanes[1].rate.store(rates.download_rate, Ordering::Relaxed);
+        }
+        *inner.config.write().unwrap() = config;
+    }
+
+    /// Start a transfer with `peer`, limited until it is dropped.
+    pub fn transfer(&self, peer: &str) -> Transfer {
+        let rates = peer_rates(&self.inner.config.read().unwrap(), peer);
+        let mut peers = self.inner.peers.lock().unwrap();
+        let entry = peers.entry(peer.to_owned()).or_insert_with(|| PeerLanes {
+            lanes: Arc::new([Lane::new(rates.upload_rate), Lane::new(rates.download_rate)]),
+            transfers: 0,
+        });
+        entry.transfers += 1;
+        self.inner.transfers.fetch_add(1, Ordering::Relaxed);
+        Transfer {
+            bandwidth: self.clone(),
+            peer: peer.to_owned(),
+            lanes: entry.lanes.clone(),
+        }
+    }
+
+    /// The metrics of the transfers in the prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let inner = &self.inner;
+        let mut res = String::new();
+        res.push_str(
+            "# HELP mega_p2p_bandwidth_bytes_total Bytes sent to and received from the peers.\n",
+        );
+        res.push_str("# TYPE mega_p2p_bandwidth_bytes_total counter\n");
+        for (direction, bytes) in DIRECTIONS.iter().zip(&inner.bytes) {
+            let _ = writeln!(
+                res,
+                "mega_p2p_bandwidth_bytes_total{{direction=\"{}\"}} {}",
+                direction,
+                bytes.load(Ordering::Relaxed)
+            );
+        }
+        res.push_str(
+            "# HELP mega_p2p_bandwidth_throttled_seconds_total Time the transfers waited for their rate limits.\n",
+        );
+        res.push_str("# TYPE mega_p2p_bandwidth_throttled_seconds_total counter\n");
+        for (direction, ms) in DIRECTIONS.iter().zip(&inner.throttled_ms) {
+            let _ = writeln!(
+                res,
+                "mega_p2p_bandwidth_throttled_seconds_total{{direction=\"{}\"}} {}",
+                direction,
+                ms.load(Ordering::Relaxed) as f64 / 1000.0
+            );
+        }
+        res.push_str("# HELP mega_p2p_transfers Transfers with the peers running.\n");
+        res.push_str("# TYPE mega_p2p_transfers gauge\n");
+        let _ = writeln!(
+            res,
+            "mega_p2p_transfers {}",
+            inner.transfers.load(Ordering::Relaxed)
+        );
+        res
+    }
+}
+
+impl Default for Bandwidth {
+    fn default() -> Self {
+        Bandwidth::new(BandwidthConfig::default())
+    }
+}
+
+// The rates of `peer`, its own if it has some.
+fn peer_rates(config: &BandwidthConfig, peer: &str) -> PeerBandwidth {
+    config.peers.get(peer).copied().unwrap_or(PeerBandwidth {
+        upload_rate: config.peer_upload_rate,
+        download_rate: config.peer_download_rate,
+    })
+}
+
+/// A transfer with a peer, like a Noise channel, see [`Bandwidth::transfer`].
+pub struct Transfer {
+    bandwidth: Bandwidth,
+    peer: String,
+    lanes: Arc<[Lane; 2]>,
+}
+
+impl Transfer {
+    /// Wait until `len` more bytes may be sent to or received from the peer.
+    pub async fn take(&self, direction: Direction, len: usize) {
+        let d = direction as usize;
+        let inner = &self.bandwidth.inner;
+        inner.bytes[d].fetch_add(len as u64, Ordering::Relaxed);
+        let mut left = len;
+        while left > 0 {
+            let quantum = left.min(QUANTUM);
+            let waited = self.lanes[d].take(quantum).await + inner.total[d].take(quantum).await;
+            if !waited.is_zero() {
+                inner.throttled_ms[d].fetch_add(waited.as_millis() as u64, Ordering::Relaxed);
+            }
+            left -= quantum;
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        let inner = &self.bandwidth.inner;
+        inner.transfers.fetch_sub(1, Ordering::Relaxed);
+        let mut peers = inner.peers.lock().unwrap();
+        if let Some(entry) = peers.get_mut(&self.peer) {
+            entry.transfers -= 1;
+            if entry.transfers == 0 {
+                peers.remove(&self.peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::config::{BandwidthConfig, PeerBandwidth};
+    use tokio::time::Instant;
+
+    use super::{peer_rates, Bandwidth, Bucket, Direction, QUANTUM};
+
+    #[test]
+    fn test_bucket() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(1000, now);
+        assert_eq!(bucket.take(600, now), Duration::ZERO);
+        assert_eq!(bucket.take(900, now), Duration::from_millis(500));
+        // the tokens taken in advance are back after waiting
+        let later = now + Duration::from_millis(500);
+        assert_eq!(bucket.take(0, later), Duration::ZERO);
+        assert_eq!(bucket.take(100, later), Duration::from_millis(100));
+
+        let mut unlimited = Bucket::new(0, now);
+        assert_eq!(unlimited.take(1 << 30, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_peer_rates() {
+        let mut config = BandwidthConfig {
+            peer_upload_rate: 100,
+            peer_download_rate: 200,
+            ..Default::default()
+        };
+        config.peers.insert(
+            "seed".to_owned(),
+            PeerBandwidth {
+                upload_rate: 0,
+                download_rate: 50,
+            },
+        );
+        let rates = peer_rates(&config, "other");
+        assert_eq!((rates.upload_rate, rates.download_rate), (100, 200));
+        let rates = peer_rates(&config, "seed");
+        assert_eq!((rates.upload_rate, rates.download_rate), (0, 50));
+    }
+
+    #[tokio::test]
+    async fn test_fair_share() {
+        let rate = 8 * QUANTUM as u64;
+        let bandwidth = Bandwidth::new(BandwidthConfig {
+            upload_rate: rate,
+            ..Default::default()
+        });
+        let (a, b) = (bandwidth.transfer("a"), bandwidth.transfer("b"));
+        // the tokens of the first second are taken, each quantum then waits 1/8 s
+        a.take(Direction::Upload, rate as usize).await;
+        let start = Instant::now();
+        let (a_done, b_done) = tokio::join!(
+            async {
+                a.take(Direction::Upload, 2 * QUANTUM).await;
+                start.elapsed()
+            },
+            async {
+                b.take(Direction::Upload, 2 * QUANTUM).await;
+                start.elapsed()
+            }
+        );
+        // the transfers took turns instead of the first one sending all of its bytes first
+        assert!(a_done >= Duration::from_millis(330), "{:?}", a_done);
+        assert!(b_done >= Duration::from_millis(450), "{:?}", b_done);
+        assert!(b_done < Duration::from_secs(2), "{:?}", b_done);
+        // downloads are not limited
+        let start = Instant::now();
+        a.take(Direction::Download, rate as usize).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        drop(a);
+        drop(b);
+        assert!(bandwidth.inner.peers.lock().unwrap().is_empty());
+    }
+}
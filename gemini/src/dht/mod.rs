@@ -0,0 +1,487 @@
+//! Kademlia distributed hash table of the mega instances on the ztm network, which finds the
+//! peers providing a repository without asking the relay of the bootstrap node.
+//!
+//! Every peer has a [`Key`], the sha256 of its peer id, and keeps the peers it heard from in a
+//! [`RoutingTable`]. A repository has the key of its identifier, and the peers providing it
+//! publish a [`PeerRecord`] to the `k` peers closest to that key, which they find by asking the
+//! closest peers they know for closer ones until none is left. The records are signed by their
+//! peer and expire after `p2p.dht.record_ttl` seconds, so each peer publishes the records of its
+//! repositories again every `p2p.dht.republish_interval` seconds.
+//!
+//! The peers talk over the ztm tunnels to each other, by posting a [`DhtMessage`] to
+//! `/api/v1/mega/ztm/dht`. A peer joins the table through the endpoints of the ztm mesh and the
+//! `p2p.dht.seeds`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::config::DhtConfig;
+use jupiter::context::Context;
+use secp256k1::Keypair;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+
+pub use record::{PeerRecord, RecordStore};
+pub use routing::{Inserted, Key, RoutingTable};
+
+use crate::util::{get_utc_timestamp, repo_alias_to_identifier};
+use crate::ztm::agent::{LocalZTMAgent, ZTMAgent};
+use crate::ztm::send_post_request_to_peer_by_tunnel;
+
+mod record;
+mod routing;
+
+/// Path of the mega api the peers post their [`DhtMessage`]s to.
+pub const DHT_PATH: &str = "api/v1/mega/ztm/dht";
+// Time between joining the table while the routing table is empty.
+const JOIN_RETRY: Duration = Duration::from_secs(30);
+
+/// A request of the peer `sender`, which is added to the routing table of the peer answering.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DhtMessage {
+    pub sender: String,
+    pub request: DhtRequest,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DhtRequest {
+    /// Check the peer is still there
+    Ping,
+    /// The peers closest to `target` known to the peer
+    FindNode { target: String },
+    /// The records the peer stores for `key`, and the peers closest to it
+    FindValue { key: String },
+    /// Store the record
+    Store { record: PeerRecord },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DhtResponse {
+    Pong,
+    Nodes {
+        peers: Vec<String>,
+    },
+    Value {
+        records: Vec<PeerRecord>,
+        peers: Vec<String>,
+    },
+    Stored,
+    Error {
+        message: String,
+    },
+}
+
+/// How a [`DhtMessage`] reaches another peer.
+#[async_trait]
+pub trait DhtTransport: Send + Sync {
+    async fn send(&self, peer_id: &str, msg: &DhtMessage) -> Result<DhtResponse, String>;
+}
+
+#[async_trait]
+impl<T: DhtTransport + ?Sized> DhtTransport for Box<T> {
+    async fn send(&self, peer_id: &str, msg: &DhtMessage) -> Result<DhtResponse, String> {
+        (**self).send(peer_id, msg).await
+    }
+}
+
+/// Sends the messages through the ztm tunnel to the peer, created on first use.
+pub struct ZtmTransport {
+    pub agent_port: u16,
+}
+
+#[async_trait]
+impl DhtTransport for ZtmTransport {
+    async fn send(&self, peer_id: &str, msg: &DhtMessage) -> Result<DhtResponse, String> {
+        let body = serde_json::to_string(msg).unwrap();
+        let res = send_post_request_to_peer_by_tunnel(
+            self.agent_port,
+            peer_id.to_owned(),
+            DHT_PATH.to_owned(),
+            body,
+        )
+        .await?;
+        serde_json::from_str(&res).map_err(|e| e.to_string())
+    }
+}
+
+/// The table of the local peer, cheap to clone.
+#[derive(Clone)]
+pub struct Dht {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    peer_id: String,
+    keypair: Keypair,
+    config: DhtConfig,
+    transport: Box<dyn DhtTransport>,
+    table: Mutex<RoutingTable>,
+    records: Mutex<RecordStore>,
+    // identifiers of the repositories provided by the local peer, published again until restart
+    provided: Mutex<HashSet<String>>,
+}
+
+impl Dht {
+    pub fn new(
+        peer_id: String,
+        keypair: Keypair,
+        config: DhtConfig,
+        transport: impl DhtTransport + 'static,
+    ) -> Self {
+        let table = RoutingTable::new(&peer_id, config.k);
+        Dht {
+            inner: Arc::new(Inner {
+                peer_id,
+                keypair,
+                config,
+                transport: Box::new(transport),
+                table: Mutex::new(table),
+                records: Mutex::new(RecordStore::default()),
+                provided: Mutex::new(HashSet::new()),
+            }),
+        }
+    }
+
+    pub fn peer_id(&self) -> &str {
+        &self.inner.peer_id
+    }
+
+    /// Peers in the routing table.
+    pub fn peers(&self) -> usize {
+        self.inner.table.lock().unwrap().len()
+    }
+
+    /// Answer the request of another peer.
+    pub async fn handle(&self, msg: DhtMessage) -> DhtResponse {
+        let k = self.inner.config.k;
+        let res = match msg.request {
+            DhtRequest::Ping => DhtResponse::Pong,
+            DhtRequest::FindNode { target } => match target.parse::<Key>() {
+                Ok(target) => DhtResponse::Nodes {
+                    peers: self.closest(&target, k, &msg.sender),
+                },
+                Err(message) => DhtResponse::Error { message },
+            },
+            DhtRequest::FindValue { key } => match key.parse::<Key>() {
+                Ok(key) => DhtResponse::Value {
+                    records: self.inner.records.lock().unwrap().get(&key),
+                    peers: self.closest(&key, k, &msg.sender),
+                },
+                Err(message) => DhtResponse::Error { message },
+            },
+            DhtRequest::Store { record } => match self.inner.records.lock().unwrap().put(record) {
+                Ok(()) => DhtResponse::Stored,
+                Err(message) => DhtResponse::Error { message },
+            },
+        };
+        self.observe(&msg.sender);
+        res
+    }
+
+    /// Join the table through `peers`, by looking up the local peer in it, which fills the
+    /// routing table with the peers close to it and tells them about it.
+    pub async fn bootstrap(&self, peers: &[String]) {
+        for peer in peers {
+            self.inner.table.lock().unwrap().insert(peer);
+        }
+        let local = Key::of(&self.inner.peer_id);
+        self.lookup(&local, false).await;
+    }
+
+    /// Publish that the local peer provides the repository of `identifier` to the peers closest
+    /// to its key, and again every `p2p.dht.republish_interval` seconds. Returns the number of
+    /// peers which stored the record.
+    pub async fn provide(&self, identifier: &str) -> usize {
+        self.inner
+            .provided
+            .lock()
+            .unwrap()
+            .insert(identifier.to_owned());
+        let key = Key::of(identifier);
+        let record = PeerRecord::new(
+            &key,
+            &self.inner.peer_id,
+            &self.inner.keypair,
+            self.inner.config.record_ttl,
+        );
+        let (closest, _) = self.lookup(&key, false).await;
+        let mut stored = 0;
+        for peer in closest {
+            let request = DhtRequest::Store {
+                record: record.clone(),
+            };
+            if let Ok(DhtResponse::Stored) = self.request(&peer, request).await {
+                stored += 1;
+            }
+        }
+        // the local peer is one of the providers it knows about
+        let _ = self.inner.records.lock().unwrap().put(record);
+        stored
+    }
+
+    /// Stop publishing the repository of `identifier`, its records expire with time.
+    pub fn forget(&self, identifier: &str) {
+        self.inner.provided.lock().unwrap().remove(identifier);
+    }
+
+    /// The valid records of the peers providing the repository of `identifier`.
+    pub async fn find_providers(&self, identifier: &str) -> Vec<PeerRecord> {
+        let key = Key::of(identifier);
+        let (_, records) = self.lookup(&key, true).await;
+        records
+    }
+
+    // Iterative lookup of the `k` peers closest to `target`: the `alpha` closest ones which were
+    // not asked yet are asked at the same time for closer ones, until the `k` closest ones known
+    // were all asked. A lookup of a value also collects the records of `target` of the peers.
+    async fn lookup(&self, target: &Key, value: bool) -> (Vec<String>, Vec<PeerRecord>) {
+        let DhtConfig { k, alpha, .. } = self.inner.config;
+        let mut known: Vec<String> = self.closest(target, k, "");
+        let mut asked = HashSet::new();
+        let mut failed = HashSet::new();
+        let mut records = HashMap::new();
+        if value {
+            for record in self.inner.records.lock().unwrap().get(target) {
+                records.insert(record.peer_id.clone(), record);
+            }
+        }
+        loop {
+            let next: Vec<String> = known
+                .iter()
+                .filter(|peer| !asked.contains(*peer))
+                .take(alpha)
+                .cloned()
+                .collect();
+            if next.is_empty() {
+                break;
+            }
+            let mut requests = JoinSet::new();
+            for peer in next {
+                asked.insert(peer.clone());
+                let request = if value {
+                    DhtRequest::FindValue {
+                        key: target.to_string(),
+                    }
+                } else {
+                    DhtRequest::FindNode {
+                        target: target.to_string(),
+                    }
+                };
+                let dht = self.clone();
+                requests.spawn(async move { (dht.request(&peer, request).await, peer) });
+            }
+            while let Some(Ok((res, peer))) = requests.join_next().await {
+                let peers = match res {
+                    Ok(DhtResponse::Nodes { peers }) => peers,
+                    Ok(DhtResponse::Value {
+                        records: found,
+                        peers,
+                    }) => {
+                        for record in found {
+                            if record.key == target.to_string() && record.verify().is_ok() {
+                                records.insert(record.peer_id.clone(), record);
+                            }
+                        }
+                        peers
+                    }
+                    _ => {
+                        failed.insert(peer);
+                        continue;
+                    }
+                };
+                for peer in peers {
+                    if peer != self.inner.peer_id && !known.contains(&peer) {
+                        known.push(peer);
+                    }
+                }
+            }
+            known.retain(|peer| !failed.contains(peer));
+            known.sort_by_key(|peer| Key::of(peer).distance(target));
+            known.truncate(k);
+        }
+        let now = get_utc_timestamp();
+        let records = records
+            .into_values()
+            .filter(|record| !record.is_expired(now))
+            .collect();
+        (known, records)
+    }
+
+    // Send a request to `peer`, which is added to the routing table if it answers and removed
+    // if it does not.
+    async fn request(&self, peer: &str, request: DhtRequest) -> Result<DhtResponse, String> {
+        let msg = DhtMessage {
+            sender: self.inner.peer_id.clone(),
+            request,
+        };
+        match self.inner.transport.send(peer, &msg).await {
+            Ok(res) => {
+                self.observe(peer);
+                Ok(res)
+            }
+            Err(e) => {
+                tracing::debug!("DHT request to {} failed: {}", peer, e);
+                self.inner.table.lock().unwrap().remove(peer);
+                Err(e)
+            }
+        }
+    }
+
+    // Add a peer which was heard from to the routing table. If its bucket is full the least
+    // recently seen peer of the bucket is pinged in the background, and replaced if it does not
+    // answer.
+    fn observe(&self, peer: &str) {
+        let inserted = self.inner.table.lock().unwrap().insert(peer);
+        if let Inserted::Full(oldest) = inserted {
+            let dht = self.clone();
+            let peer = peer.to_owned();
+            tokio::spawn(async move {
+                let msg = DhtMessage {
+                    sender: dht.inner.peer_id.clone(),
+                    request: DhtRequest::Ping,
+                };
+                let mut table = match dht.inner.transport.send(&oldest, &msg).await {
+                    Ok(_) => return,
+                    Err(_) => dht.inner.table.lock().unwrap(),
+                };
+                table.evict(&oldest, &peer);
+            });
+        }
+    }
+
+    fn closest(&self, target: &Key, count: usize, except: &str) -> Vec<String> {
+        let table = self.inner.table.lock().unwrap();
+        let mut peers = table.closest(target, count + 1);
+        peers.retain(|peer| peer != except);
+        peers.truncate(count);
+        peers
+    }
+
+    /// Join the table once the ztm agent is connected to the mesh, publish the repositories
+    /// published by the local peer and the ones it mirrors, and keep them published.
+    pub fn start(&self, context: Context, agent: LocalZTMAgent) {
+        let dht = self.clone();
+        tokio::spawn(async move {
+            let interval = Duration::from_secs(dht.inner.config.republish_interval);
+            loop {
+                let mut peers = dht.inner.config.seeds.clone();
+                match agent.get_ztm_endpoints().await {
+                    Ok(endpoints) => peers.extend(
+                        endpoints
+                            .into_iter()
+                            .filter(|ep| ep.online && !ep.is_local)
+                            .map(|ep| ep.name),
+                    ),
+                    Err(e) => tracing::warn!("Failed to list the ztm endpoints: {}", e),
+                }
+                dht.bootstrap(&peers).await;
+                tracing::info!("{} peers in the DHT routing table", dht.peers());
+
+                match context.services.ztm_storage.get_all_alias_mapping().await {
+                    Ok(mappings) => {
+                        let mut provided = dht.inner.provided.lock().unwrap();
+                        for mapping in mappings {
+                            provided.insert(repo_alias_to_identifier(mapping.alias));
+                        }
+                    }
+                    Err(e) => tracing::error!("Failed to list the provided repositories: {}", e),
+                }
+                // the subscribed repositories are provided once their mirror was synced
+                match context.services.ztm_storage.get_all_subscriptions().await {
+                    Ok(subscriptions) => {
+                        let mut provided = dht.inner.provided.lock().unwrap();
+                        for subscription in subscriptions {
+                            if subscription.synced_at.is_some() {
+                                provided.insert(subscription.identifier);
+                            }
+                        }
+                    }
+                    Err(e) => tracing::error!("Failed to list the subscriptions: {}", e),
+                }
+                let provided: Vec<String> =
+                    dht.inner.provided.lock().unwrap().iter().cloned().collect();
+                for identifier in provided {
+                    let stored = dht.provide(&identifier).await;
+                    tracing::debug!("Published {} to {} peers", identifier, stored);
+                }
+                dht.inner.records.lock().unwrap().prune(get_utc_timestamp());
+                // try again soon while no other peer is around
+                if dht.peers() == 0 {
+                    tokio::time::sleep(JOIN_RETRY).await;
+                } else {
+                    tokio::time::sleep(interval).await;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, OnceLock};
+
+    use async_trait::async_trait;
+    use common::config::DhtConfig;
+    use secp256k1::{rand, Keypair, Secp256k1};
+
+    use super::{Dht, DhtMessage, DhtResponse, DhtTransport};
+
+    // Delivers the messages to the peers of the network in memory.
+    struct Network(Arc<OnceLock<HashMap<String, Dht>>>);
+
+    #[async_trait]
+    impl DhtTransport for Network {
+        async fn send(&self, peer_id: &str, msg: &DhtMessage) -> Result<DhtResponse, String> {
+            let peer = self.0.get().unwrap().get(peer_id).ok_or("offline")?;
+            Ok(peer.handle(msg.clone()).await)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dht() {
+        let network = Arc::new(OnceLock::new());
+        let config = DhtConfig {
+            k: 4,
+            ..Default::default()
+        };
+        let peers: Vec<Dht> = (0..30)
+            .map(|_| {
+                let keypair = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+                let peer_id = bs58::encode(keypair.public_key().serialize()).into_string();
+                Dht::new(peer_id, keypair, config.clone(), Network(network.clone()))
+            })
+            .collect();
+        let ids: Vec<String> = peers.iter().map(|dht| dht.peer_id().to_owned()).collect();
+        network
+            .set(ids.iter().cloned().zip(peers.iter().cloned()).collect())
+            .ok();
+        // every peer joins through the first one, and looks itself up again like it does
+        // before publishing its records
+        for dht in peers[1..].iter().chain(&peers) {
+            dht.bootstrap(&ids[..1]).await;
+        }
+        assert!(peers.iter().all(|dht| dht.peers() > 0));
+
+        let identifier = "p2p://peer/repo";
+        assert!(peers[7].provide(identifier).await > 0);
+        assert!(peers[19].provide(identifier).await > 0);
+        for dht in [&peers[0], &peers[13], &peers[29]] {
+            let mut providers: Vec<_> = dht
+                .find_providers(identifier)
+                .await
+                .into_iter()
+                .map(|record| record.peer_id)
+                .collect();
+            providers.sort();
+            let mut expected = vec![ids[7].clone(), ids[19].clone()];
+            expected.sort();
+            assert_eq!(providers, expected);
+        }
+        assert!(peers[3].find_providers("p2p://peer/other").await.is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::schnorr::Signature;
+use secp256k1::Keypair;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use super::routing::Key;
+use crate::nostr::event::sign_without_rng;
+use crate::util::{get_utc_timestamp, verify_peer_signature};
+
+/// Peers kept as providers of one key, the ones whose records expire first are dropped.
+const MAX_PROVIDERS: usize = 64;
+
+/// Record of a peer providing the repository of `key`, signed with the key of the peer id so
+/// it can be stored by any peer without them being able to forge or extend it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    /// [`Key`] of the repository identifier in hex
+    pub key: String,
+    pub peer_id: String,
+    /// Milliseconds since the epoch the record is valid until
+    pub expires_at: i64,
+    pub sig: Signature,
+}
+
+impl PeerRecord {
+    /// A record of `peer_id` providing `key` for `ttl` seconds, `keypair` is the one of the peer.
+    pub fn new(key: &Key, peer_id: &str, keypair: &Keypair, ttl: u64) -> Self {
+        let key = key.to_string();
+        let expires_at = get_utc_timestamp() + ttl as i64 * 1000;
+        let sig = sign_without_rng(digest(&key, peer_id, expires_at).to_string(), keypair);
+        PeerRecord {
+            key,
+            peer_id: peer_id.to_owned(),
+            expires_at,
+            sig,
+        }
+    }
+
+    /// Check the record was signed by the key of its peer id.
+    pub fn verify(&self) -> Result<(), String> {
+        let hash = digest(&self.key, &self.peer_id, self.expires_at);
+        verify_peer_signature(&self.peer_id, hash, &self.sig)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+// The sha256 of the signed fields of a record.
+fn digest(key: &str, peer_id: &str, expires_at: i64) -> sha256::Hash {
+    let data = json!([key, peer_id, expires_at]).to_string();
+    sha256::Hash::hash(data.as_bytes())
+}
+
+/// Records stored by the local peer for the keys it is one of the closest peers of, in memory,
+/// as their peers publish them again before they expire.
+#[derive(Default)]
+pub struct RecordStore {
+    records: HashMap<Key, HashMap<String, PeerRecord>>,
+}
+
+impl RecordStore {
+    /// Store a valid record, it replaces the record of its peer for its key unless that expires
+    /// later.
+    pub fn put(&mut self, record: PeerRecord) -> Result<(), String> {
+        let key: Key = record.key.parse()?;
+        if record.is_expired(get_utc_timestamp()) {
+            return Err(format!("the record of {} is expired", record.peer_id));
+        }
+        record.verify()?;
+        let providers = self.records.entry(key).or_default();
+        match providers.get(&record.peer_id) {
+            Some(stored) if stored.expires_at >= record.expires_at => {}
+            _ => {
+                providers.insert(record.peer_id.clone(), record);
+            }
+        }
+        if providers.len() > MAX_PROVIDERS {
+            let first = providers
+                .values()
+                .min_by_key(|record| record.expires_at)
+                .map(|record| record.peer_id.clone())
+                .unwrap();
+            providers.remove(&first);
+        }
+        Ok(())
+    }
+
+    /// The records of the peers providing `key` which did not expire.
+    pub fn get(&self, key: &Key) -> Vec<PeerRecord> {
+        let now = get_utc_timestamp();
+        self.records
+            .get(key)
+            .map(|providers| {
+                providers
+                    .values()
+                    .filter(|record| !record.is_expired(now))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Drop the expired records.
+    pub fn prune(&mut self, now: i64) {
+        for providers in self.records.values_mut() {
+            providers.retain(|_, record| !record.is_expired(now));
+        }
+        self.records.retain(|_, providers| !providers.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{rand, Keypair, Secp256k1};
+
+    use super::{PeerRecord, RecordStore};
+    use crate::dht::routing::Key;
+
+    fn peer() -> (String, Keypair) {
+        let keypair = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+        let peer_id = bs58::encode(keypair.public_key().serialize()).into_string();
+        (peer_id, keypair)
+    }
+
+    #[test]
+    fn test_record() {
+        let (peer_id, keypair) = peer();
+        let key = Key::of("p2p://peer/repo");
+        let record = PeerRecord::new(&key, &peer_id, &keypair, 60);
+        assert!(record.verify().is_ok());
+
+        let mut extended = record.clone();
+        extended.expires_at += 1000;
+        assert!(extended.verify().is_err());
+        let (other, _) = peer();
+        let mut forged = record.clone();
+        forged.peer_id = other;
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn test_record_store() {
+        let key = Key::of("p2p://peer/repo");
+        let (peer_id, keypair) = peer();
+        let mut store = RecordStore::default();
+        let record = PeerRecord::new(&key, &peer_id, &keypair, 60);
+        store.put(record.clone()).unwrap();
+        // an older record of the same peer does not replace the newer one
+        let older = PeerRecord::new(&key, &peer_id, &keypair, 30);
+        store.put(older).unwrap();
+        assert_eq!(store.get(&key), [record.clone()]);
+
+        let expired = PeerRecord::new(&key, &peer_id, &keypair, 0);
+        assert!(store.put(expired).is_err());
+        let mut forged = record.clone();
+        forged.expires_at += 1000;
+        assert!(store.put(forged).is_err());
+
+        store.prune(record.expires_at);
+        assert!(store.get(&key).is_empty());
+    }
+}
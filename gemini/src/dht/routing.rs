@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use secp256k1::hashes::{sha256, Hash};
+
+/// Bits of a [`Key`], one bucket of the routing table for each.
+const BITS: usize = 256;
+
+/// Position of a peer or a record in the key space of the table, the sha256 of the peer id or
+/// of the repository identifier.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    pub fn of(data: &str) -> Self {
+        Key(sha256::Hash::hash(data.as_bytes()).to_byte_array())
+    }
+
+    /// XOR distance to `other`, compared as a big-endian number.
+    pub fn distance(&self, other: &Key) -> Key {
+        let mut res = [0; 32];
+        for (i, byte) in res.iter_mut().enumerate() {
+            *byte = self.0[i] ^ other.0[i];
+        }
+        Key(res)
+    }
+
+    // The bucket `other` belongs in from this key, the index of the highest bit of their
+    // distance, `None` for the key itself.
+    fn bucket(&self, other: &Key) -> Option<usize> {
+        let distance = self.distance(other);
+        let zeros = distance
+            .0
+            .iter()
+            .position(|&byte| byte != 0)
+            .map(|i| i * 8 + distance.0[i].leading_zeros() as usize)?;
+        Some(BITS - 1 - zeros)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key({})", self)
+    }
+}
+
+impl FromStr for Key {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut res = [0; 32];
+        hex::decode_to_slice(s, &mut res).map_err(|e| format!("invalid key {}: {}", s, e))?;
+        Ok(Key(res))
+    }
+}
+
+/// Result of [`RoutingTable::insert`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Inserted {
+    /// The peer is in the table, as the most recently seen one of its bucket
+    Added,
+    /// The bucket of the peer is full. Its least recently seen peer is returned, which should be
+    /// replaced with [`RoutingTable::evict`] if it no longer answers
+    Full(String),
+    /// The peer is the local one
+    Local,
+}
+
+/// Kademlia routing table of the peers known to the local one: a bucket of at most `k` peers
+/// for each distance range `[2^i, 2^(i+1))`, so it knows many peers close to it and a few far
+/// away. Each bucket is ordered from the least to the most recently seen peer, and peers which
+/// have been around for long are kept over new ones, as they are likely to stay.
+pub struct RoutingTable {
+    local: Key,
+    k: usize,
+    buckets: Vec<VecDeque<(Key, String)>>,
+}
+
+impl RoutingTable {
+    pub fn new(local_peer_id: &str, k: usize) -> Self {
+        RoutingTable {
+            local: Key::of(local_peer_id),
+            k,
+            buckets: (0..BITS).map(|_| VecDeque::new()).collect(),
+        }
+    }
+
+    /// Note that `peer_id` was heard from.
+    pub fn insert(&mut self, peer_id: &str) -> Inserted {
+        let key = Key::of(peer_id);
+        let Some(index) = self.local.bucket(&key) else {
+            return Inserted::Local;
+        };
+        let bucket = &mut self.buckets[index];
+        if let Some(pos) = bucket.iter().position(|(k, _)| *k == key) {
+            let entry = bucket.remove(pos).unwrap();
+            bucket.push_back(entry);
+            Inserted::Added
+        } else if bucket.len() < self.k {
+            bucket.push_back((key, peer_id.to_owned()));
+            Inserted::Added
+        } else {
+            Inserted::Full(bucket.front().unwrap().1.clone())
+        }
+    }
+
+    /// Replace `stale`, which did not answer, with `peer_id` in its bucket.
+    pub fn evict(&mut self, stale: &str, peer_id: &str) {
+        self.remove(stale);
+        self.insert(peer_id);
+    }
+
+    pub fn remove(&mut self, peer_id: &str) {
+        let key = Key::of(peer_id);
+        if let Some(index) = self.local.bucket(&key) {
+            self.buckets[index].retain(|(k, _)| *k != key);
+        }
+    }
+
+    /// The `count` known peers closest to `target`, the closest first.
+    pub fn closest(&self, target: &Key, count: usize) -> Vec<String> {
+        let mut peers: Vec<_> = self.buckets.iter().flatten().collect();
+        peers.sort_by_key(|(key, _)| key.distance(target));
+        peers
+            .into_iter()
+            .take(count)
+            .map(|(_, peer_id)| peer_id.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Inserted, Key, RoutingTable};
+
+    #[test]
+    fn test_key() {
+        let key = Key::of("p2p://peer/repo");
+        assert_eq!(key.to_string().parse::<Key>().unwrap(), key);
+        assert_eq!(key.distance(&key), Key([0; 32]));
+        assert_eq!(key.bucket(&key), None);
+        let mut other = key;
+        other.0[31] ^= 1;
+        assert_eq!(key.bucket(&other), Some(0));
+        other.0[0] ^= 0x80;
+        assert_eq!(key.bucket(&other), Some(255));
+        assert!("not hex".parse::<Key>().is_err());
+    }
+
+    #[test]
+    fn test_routing_table() {
+        let mut table = RoutingTable::new("local", 2);
+        assert_eq!(table.insert("local"), Inserted::Local);
+        let peers: Vec<String> = (0..200).map(|i| format!("peer-{}", i)).collect();
+        let mut full = None;
+        for peer in &peers {
+            if let Inserted::Full(oldest) = table.insert(peer) {
+                full = Some((oldest, peer.clone()));
+            }
+        }
+        // about half of the peers fall in the farthest bucket, which holds 2
+        let (oldest, newest) = full.unwrap();
+        assert!(table.len() < peers.len());
+        table.evict(&oldest, &newest);
+        let target = Key::of(&newest);
+        assert_eq!(table.closest(&target, 1), [newest.clone()]);
+        assert!(!table.closest(&target, table.len()).contains(&oldest));
+
+        // the closest peers come first
+        let closest = table.closest(&target, 3);
+        let distances: Vec<_> = closest
+            .iter()
+            .map(|peer| Key::of(peer).distance(&target))
+            .collect();
+        assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        table.remove(&newest);
+        assert!(!table.closest(&target, table.len()).contains(&newest));
+    }
+}
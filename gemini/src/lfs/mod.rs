@@ -1,7 +1,25 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 
+use bytes::Bytes;
+use ceres::lfs::handler;
+use ceres::lfs::lfs_structs::{BatchRequest, BatchResponse, RequestVars};
+use common::config::P2pLfsConfig;
+use common::errors::MegaError;
+use common::network::IpCidr;
+use futures::stream::{self, StreamExt};
+use jupiter::context::Context;
 use reqwest::{get, Client};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::dht::Dht;
+use crate::util::get_utc_timestamp;
+use crate::ztm::send_get_request_to_peer_by_tunnel;
 use crate::{
     util::handle_response, ztm::get_or_create_remote_mega_tunnel, LFSInfo, LFSInfoPostBody,
     LFSInfoRes,
@@ -178,8 +196,380 @@ pub fn get_file_hash_from_origin(origin: String) -> Result<String, String> {
     Ok(words.get(4).unwrap().to_string())
 }
 
+/// Path of the mega api a peer serves its [`LfsEndpoint`] at.
+pub const LFS_ENDPOINT_PATH: &str = "api/v1/mega/ztm/lfs";
+// Milliseconds the endpoints of the other peers are cached.
+const ENDPOINT_TTL: i64 = 10 * 60 * 1000;
+// Bytes of a fetched object read at once when it is stored.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Identifier of the LFS object `oid` in the DHT, provided by the peers storing the object.
+pub fn lfs_identifier(oid: &str) -> String {
+    format!("lfs://sha256/{}", oid)
+}
+
+/// The LFS server of a peer the clients near it download the objects it stores from.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LfsEndpoint {
+    pub url: String,
+    /// Networks of the clients near the peer, in CIDR notation
+    pub networks: Vec<String>,
+}
+
+impl LfsEndpoint {
+    pub fn new(config: &P2pLfsConfig) -> Self {
+        LfsEndpoint {
+            url: config.public_url.clone(),
+            networks: config.networks.clone(),
+        }
+    }
+
+    /// Whether the client at `ip` is near the peer, the networks which don't parse are skipped.
+    pub fn serves(&self, ip: &IpAddr) -> bool {
+        !self.url.is_empty()
+            && self
+                .networks
+                .iter()
+                .filter_map(|network| network.parse::<IpCidr>().ok())
+                .any(|network| network.contains(ip))
+    }
+
+    /// Url the object `oid` is downloaded from.
+    pub fn object_url(&self, oid: &str) -> String {
+        format!("{}/objects/{}", self.url.trim_end_matches('/'), oid)
+    }
+}
+
+// The sha256 and the size of the bytes of an object as they come, checked against its oid.
+struct ObjectCheck {
+    digest: digest::Context,
+    size: i64,
+}
+
+impl ObjectCheck {
+    fn new() -> Self {
+        ObjectCheck {
+            digest: digest::Context::new(&digest::SHA256),
+            size: 0,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.digest.update(data);
+        self.size += data.len() as i64;
+    }
+
+    fn finish(self, oid: &str, size: i64) -> Result<(), String> {
+        if self.size != size {
+            return Err(format!("{} bytes received, {} expected", self.size, size));
+        }
+        let hash = hex::encode(self.digest.finish().as_ref());
+        if hash != oid {
+            return Err(format!("sha256 of the object is {}", hash));
+        }
+        Ok(())
+    }
+}
+
+/// The LFS objects exchanged with the other peers through the ztm tunnels.
+///
+/// The peers provide the objects they store in the DHT under their [`lfs_identifier`]. An object
+/// missing here is fetched from one of its providers at `/objects/{oid}`, and stored only if its
+/// sha256 is its oid. A peer with a `p2p.lfs.public_url` serves the clients of its
+/// `p2p.lfs.networks`, which are sent to it to download the objects it provides.
+#[derive(Clone)]
+pub struct LfsPeers {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    context: Context,
+    dht: Dht,
+    agent_port: u16,
+    // the endpoints of the peers by peer id, none for the peers without one, and when fetched
+    endpoints: Mutex<HashMap<String, (Option<LfsEndpoint>, i64)>>,
+}
+
+impl LfsPeers {
+    pub fn new(context: Context, dht: Dht, agent_port: u16) -> Self {
+        LfsPeers {
+            inner: Arc::new(Inner {
+                context,
+                dht,
+                agent_port,
+                endpoints: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Provide the objects stored here in the DHT, they are published again with the
+    /// repositories.
+    pub fn start(&self) {
+        let peers = self.clone();
+        tokio::spawn(async move {
+            let storage = &peers.inner.context.services.lfs_db_storage;
+            match storage.list_lfs_oids().await {
+                Ok(oids) => {
+                    for oid in oids {
+                        peers.announce(&oid).await;
+                    }
+                }
+                Err(e) => tracing::error!("Failed to list the lfs objects: {}", e),
+            }
+        });
+    }
+
+    /// Provide the object `oid` stored here, returns the number of peers which stored the record.
+    pub async fn announce(&self, oid: &str) -> usize {
+        let stored = self.inner.dht.provide(&lfs_identifier(oid)).await;
+        tracing::debug!("Published lfs object {} to {} peers", oid, stored);
+        stored
+    }
+
+    // The other peers providing the object `oid`.
+    async fn providers(&self, oid: &str) -> Vec<String> {
+        let local_peer_id = self.inner.dht.peer_id();
+        let mut peers: Vec<String> = Vec::new();
+        for record in self.inner.dht.find_providers(&lfs_identifier(oid)).await {
+            if record.peer_id != local_peer_id && !peers.contains(&record.peer_id) {
+                peers.push(record.peer_id);
+            }
+        }
+        peers
+    }
+
+    // The endpoint of `peer`, asked for through its tunnel unless it was lately.
+    async fn endpoint(&self, peer: &str) -> Option<LfsEndpoint> {
+        let now = get_utc_timestamp();
+        if let Some((endpoint, fetched_at)) = self.inner.endpoints.lock().unwrap().get(peer) {
+            if now - fetched_at < ENDPOINT_TTL {
+                return endpoint.clone();
+            }
+        }
+        let endpoint = send_get_request_to_peer_by_tunnel(
+            self.inner.agent_port,
+            peer.to_owned(),
+            LFS_ENDPOINT_PATH.to_owned(),
+        )
+        .await
+        .and_then(|res| serde_json::from_str::<LfsEndpoint>(&res).map_err(|e| e.to_string()));
+        let endpoint = match endpoint {
+            Ok(endpoint) => Some(endpoint),
+            Err(e) => {
+                tracing::debug!("Failed to get the lfs endpoint of {}: {}", peer, e);
+                None
+            }
+        };
+        self.inner
+            .endpoints
+            .lock()
+            .unwrap()
+            .insert(peer.to_owned(), (endpoint.clone(), now));
+        endpoint
+    }
+
+    /// Url of a peer near the client at `ip` to download the object `oid` from, if one of the
+    /// peers providing it serves the network of the client.
+    pub async fn nearby(&self, oid: &str, ip: &IpAddr) -> Option<String> {
+        for peer in self.providers(oid).await {
+            match self.endpoint(&peer).await {
+                Some(endpoint) if endpoint.serves(ip) => return Some(endpoint.object_url(oid)),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Fetch the object `oid` of `size` bytes from a peer providing it, and store it once its
+    /// sha256 and size match. The object is provided by this peer as well after.
+    pub async fn fetch(&self, oid: &str, size: i64) -> Result<(), String> {
+        // the oid names the file the object is downloaded to
+        if oid.len() != 64 || !oid.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("{} is not a sha256", oid));
+        }
+        let mut errors = Vec::new();
+        for peer in self.providers(oid).await {
+            match self.fetch_from(&peer, oid, size).await {
+                Ok(()) => {
+                    tracing::info!("Fetched lfs object {} from {}", oid, peer);
+                    self.announce(oid).await;
+                    return Ok(());
+                }
+                Err(e) => errors.push(format!("{}: {}", peer, e)),
+            }
+        }
+        if errors.is_empty() {
+            Err(format!("no peer provides {}", oid))
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    // Download the object into a file of `p2p/lfs` while checking it, then store it.
+    async fn fetch_from(&self, peer: &str, oid: &str, size: i64) -> Result<(), String> {
+        let local_port =
+            get_or_create_remote_mega_tunnel(self.inner.agent_port, peer.to_owned()).await?;
+        let url = format!("http://127.0.0.1:{}/objects/{}", local_port, oid);
+        let mut response = get(&url).await.map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("{} answered {}", url, response.status()));
+        }
+
+        let dir = self.inner.context.config.base_dir.join("p2p").join("lfs");
+        fs::create_dir_all(&dir).await.map_err(|e| e.to_string())?;
+        let path = dir.join(format!("{}.part", oid));
+        let res = async {
+            let mut file = fs::File::create(&path).await.map_err(|e| e.to_string())?;
+            let mut check = ObjectCheck::new();
+            while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+                check.update(&chunk);
+                // a peer sending more than the object is not read to the end
+                if check.size > size {
+                    return Err(format!("more than {} bytes received", size));
+                }
+                file.write_all(&chunk).await.map_err(|e| e.to_string())?;
+            }
+            file.flush().await.map_err(|e| e.to_string())?;
+            check.finish(oid, size)?;
+            self.store(oid, size, &path).await
+        }
+        .await;
+        let _ = fs::remove_file(&path).await;
+        res
+    }
+
+    // Store the object in the file at `path` like an upload of a client.
+    async fn store(&self, oid: &str, size: i64, path: &Path) -> Result<(), String> {
+        let context = &self.inner.context;
+        let batch = BatchRequest {
+            operation: "upload".to_owned(),
+            objects: vec![RequestVars {
+                oid: oid.to_owned(),
+                size,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let objects = handler::lfs_process_batch(context, batch)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(error) = objects.into_iter().find_map(|object| object.error) {
+            return Err(error.message);
+        }
+        let file = fs::File::open(path).await.map_err(|e| e.to_string())?;
+        let content = stream::try_unfold(file, |mut file| async move {
+            let mut buf = vec![0; READ_SIZE];
+            let len = match file.read(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => return Err(MegaError::with_message(&e.to_string())),
+            };
+            if len == 0 {
+                return Ok(None);
+            }
+            buf.truncate(len);
+            Ok(Some((Bytes::from(buf), file)))
+        })
+        .boxed();
+        let request_vars = RequestVars {
+            oid: oid.to_owned(),
+            size,
+            ..Default::default()
+        };
+        handler::lfs_upload_object(context, &request_vars, content)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Offload the downloads of a batch answered to the client at `ip`: the objects missing
+    /// here are fetched from the peers first, and the objects a peer near the client provides
+    /// are downloaded from that peer.
+    pub async fn offload(&self, batch: &mut BatchResponse, ip: Option<IpAddr>) {
+        for object in batch.objects.iter_mut() {
+            let missing = matches!(&object.error, Some(error) if error.code == 404);
+            if missing {
+                if let Err(e) = self.fetch(&object.oid, object.size).await {
+                    tracing::debug!("Failed to fetch lfs object {}: {}", object.oid, e);
+                    continue;
+                }
+                let request = BatchRequest {
+                    operation: "download".to_owned(),
+                    objects: vec![RequestVars {
+                        oid: object.oid.clone(),
+                        size: object.size,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                };
+                match handler::lfs_process_batch(&self.inner.context, request).await {
+                    Ok(mut objects) if !objects.is_empty() => *object = objects.remove(0),
+                    Ok(_) => continue,
+                    Err(e) => {
+                        tracing::error!("Failed to represent lfs object {}: {}", object.oid, e);
+                        continue;
+                    }
+                }
+            }
+            let Some(ip) = ip else { continue };
+            let Some(link) = object
+                .actions
+                .as_mut()
+                .and_then(|actions| actions.get_mut("download"))
+            else {
+                continue;
+            };
+            if let Some(url) = self.nearby(&object.oid, &ip).await {
+                tracing::debug!("Sending {} to {} for lfs object {}", ip, url, object.oid);
+                link.href = url;
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{LfsEndpoint, ObjectCheck};
+
+    #[test]
+    fn test_endpoint_serves() {
+        let endpoint = LfsEndpoint {
+            url: "https://lfs.branch.example/".to_owned(),
+            networks: vec![
+                "10.1.0.0/16".to_owned(),
+                "not a network".to_owned(),
+                "fd00::/8".to_owned(),
+            ],
+        };
+        assert!(endpoint.serves(&"10.1.2.3".parse().unwrap()));
+        assert!(endpoint.serves(&"fd00::1".parse().unwrap()));
+        assert!(!endpoint.serves(&"10.2.0.1".parse().unwrap()));
+        assert_eq!(
+            endpoint.object_url("abc"),
+            "https://lfs.branch.example/objects/abc"
+        );
+        // a peer without a public url serves nobody
+        let hidden = LfsEndpoint {
+            url: String::new(),
+            ..endpoint
+        };
+        assert!(!hidden.serves(&"10.1.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_object_check() {
+        // sha256 of "hello world"
+        let oid = "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9";
+        let check = |parts: &[&[u8]], size| {
+            let mut check = ObjectCheck::new();
+            for part in parts {
+                check.update(part);
+            }
+            check.finish(oid, size)
+        };
+        assert!(check(&[b"hello ", b"world"], 11).is_ok());
+        assert!(check(&[b"hello world"], 12).is_err());
+        assert!(check(&[b"hello there"], 11).is_err());
+    }
+
     // use reqwest::get;
     // use std::path::PathBuf;
     // use tokio::fs::OpenOptions;
@@ -6,11 +6,17 @@ use common::utils::generate_id;
 use serde::{Deserialize, Serialize};
 use util::get_utc_timestamp;
 
+pub mod bandwidth;
 pub mod ca;
 pub mod cache;
+pub mod dht;
 pub mod http;
 pub mod lfs;
+pub mod nat;
+pub mod noise;
 pub mod nostr;
+pub mod relay;
+pub mod sync;
 pub mod util;
 pub mod ztm;
 
@@ -0,0 +1,1007 @@
+//! Direct connections between the peers of the ztm network through their NATs, so the messages
+//! of the DHT do not all go through the ztm relay.
+//!
+//! Each peer learns its public address and the type of its NAT from the `p2p.nat.stun_servers`.
+//! Before talking to another peer directly, the peers exchange their candidate addresses and a
+//! nonce through the relay, by posting a [`PunchRequest`] to `/api/v1/mega/ztm/punch`. Both then
+//! send UDP probes to the candidates of the other one at the same time, which opens a hole in
+//! their NATs for the other's probes, and try a simultaneous TCP open from their TCP port. The
+//! first probe carrying the nonce which comes through establishes the connection. If none does
+//! within `p2p.nat.punch_timeout` seconds, or a direct connection stops answering, the peer is
+//! reached through the relay again, and connected to directly again after
+//! `p2p.nat.retry_interval` seconds.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::config::NatConfig;
+use secp256k1::rand;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UdpSocket};
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout, Instant};
+
+pub use stun::NatType;
+
+use crate::dht::{Dht, DhtMessage, DhtResponse, DhtTransport};
+use crate::ztm::send_post_request_to_peer_by_tunnel;
+use stun::TransactionId;
+
+pub mod stun;
+
+/// Path of the mega api the peers post their [`PunchRequest`]s to, through the relay.
+pub const PUNCH_PATH: &str = "api/v1/mega/ztm/punch";
+// Time between the probes sent while punching a hole.
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+// Time a peer has to answer a request sent directly.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Time between asking the STUN servers again, as the NAT may have changed its mapping.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(300);
+// Largest datagram sent, larger messages over UDP go through the relay.
+const MAX_DATAGRAM: usize = 8192;
+// Largest frame of a TCP connection.
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// How the local peer reaches another one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionType {
+    DirectUdp,
+    DirectTcp,
+    Relay,
+}
+
+impl ConnectionType {
+    const ALL: [ConnectionType; 3] = [
+        ConnectionType::DirectUdp,
+        ConnectionType::DirectTcp,
+        ConnectionType::Relay,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionType::DirectUdp => "direct_udp",
+            ConnectionType::DirectTcp => "direct_tcp",
+            ConnectionType::Relay => "relay",
+        }
+    }
+}
+
+/// Sent through the relay to start punching a hole between the sender and the peer receiving
+/// it, which answers with a [`PunchResponse`].
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PunchRequest {
+    pub sender: String,
+    /// Carried by the probes, only the peers which got it through the relay know it
+    pub nonce: u64,
+    pub candidates: Vec<SocketAddr>,
+    pub tcp_port: u16,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PunchResponse {
+    pub candidates: Vec<SocketAddr>,
+    pub tcp_port: u16,
+}
+
+/// What the local peer knows about its NAT and how it reaches the other peers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NatStatus {
+    pub nat_type: NatType,
+    /// Addresses the other peers are told to probe, the public ones first
+    pub candidates: Vec<SocketAddr>,
+    pub tcp_port: u16,
+    pub peers: BTreeMap<String, ConnectionType>,
+}
+
+// What the peers send each other directly, as a datagram or a frame of a TCP connection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Packet {
+    Probe {
+        sender: String,
+        nonce: u64,
+        ack: bool,
+    },
+    Request {
+        id: u64,
+        msg: DhtMessage,
+    },
+    Response {
+        id: u64,
+        res: DhtResponse,
+    },
+    // the response did not fit in a datagram
+    TooLarge {
+        id: u64,
+    },
+}
+
+// How a peer is reached.
+#[derive(Clone)]
+enum Path {
+    Udp {
+        addr: SocketAddr,
+        nonce: u64,
+    },
+    Tcp {
+        addr: SocketAddr,
+        nonce: u64,
+        writer: Arc<AsyncMutex<OwnedWriteHalf>>,
+    },
+    Relay {
+        since: Instant,
+    },
+    Connecting,
+}
+
+impl Path {
+    fn connection_type(&self) -> Option<ConnectionType> {
+        match self {
+            Path::Udp { .. } => Some(ConnectionType::DirectUdp),
+            Path::Tcp { .. } => Some(ConnectionType::DirectTcp),
+            Path::Relay { .. } => Some(ConnectionType::Relay),
+            Path::Connecting => None,
+        }
+    }
+
+    fn nonce(&self) -> Option<u64> {
+        match self {
+            Path::Udp { nonce, .. } | Path::Tcp { nonce, .. } => Some(*nonce),
+            _ => None,
+        }
+    }
+}
+
+// Where a packet came from, and where its answer goes.
+enum Link {
+    Udp(SocketAddr),
+    // the peer the connection was established with
+    Tcp(String, Arc<AsyncMutex<OwnedWriteHalf>>),
+}
+
+// Why a request was not answered directly.
+enum DirectError {
+    // the message is sent through the relay instead, the connection works
+    TooLarge,
+    Failed(String),
+}
+
+struct Punch {
+    nonce: u64,
+    done: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Default)]
+struct Status {
+    nat_type: Option<NatType>,
+    local: Option<SocketAddr>,
+    public: Vec<SocketAddr>,
+}
+
+/// The direct connections of the local peer, cheap to clone.
+#[derive(Clone)]
+pub struct Nat {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    peer_id: String,
+    config: NatConfig,
+    agent_port: u16,
+    socket: UdpSocket,
+    udp_port: u16,
+    listener: Mutex<Option<TcpListener>>,
+    tcp_port: u16,
+    dht: OnceLock<Dht>,
+    status: Mutex<Status>,
+    paths: Mutex<HashMap<String, Path>>,
+    // the peer each UDP address sent a valid probe for, the only one answered at that address
+    verified: Mutex<HashMap<SocketAddr, String>>,
+    punches: Mutex<HashMap<String, Punch>>,
+    stun: Mutex<HashMap<TransactionId, oneshot::Sender<SocketAddr>>>,
+    requests: Mutex<HashMap<u64, oneshot::Sender<Result<DhtResponse, DirectError>>>>,
+    next_id: AtomicU64,
+    // attempts to connect by the type of connection they ended with
+    connects: [AtomicU64; 3],
+    // messages sent by type of connection
+    messages: [AtomicU64; 3],
+}
+
+impl Nat {
+    /// Bind the UDP and TCP sockets of the direct connections on `p2p.nat.port`, the holes are
+    /// negotiated through the ztm agent at `agent_port`.
+    pub fn bind(peer_id: String, config: NatConfig, agent_port: u16) -> io::Result<Self> {
+        let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port))?;
+        socket.set_nonblocking(true)?;
+        let socket = UdpSocket::from_std(socket)?;
+        let udp_port = socket.local_addr()?.port();
+        // the same port as UDP if it is free, NATs preserving ports map both alike
+        let listener = tcp_socket(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), udp_port))
+            .or_else(|_| tcp_socket(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)))?
+            .listen(64)?;
+        let tcp_port = listener.local_addr()?.port();
+        Ok(Nat {
+            inner: Arc::new(Inner {
+                peer_id,
+                config,
+                agent_port,
+                socket,
+                udp_port,
+                listener: Mutex::new(Some(listener)),
+                tcp_port,
+                dht: OnceLock::new(),
+                status: Mutex::new(Status::default()),
+                paths: Mutex::new(HashMap::new()),
+                verified: Mutex::new(HashMap::new()),
+                punches: Mutex::new(HashMap::new()),
+                stun: Mutex::new(HashMap::new()),
+                requests: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+                connects: Default::default(),
+                messages: Default::default(),
+            }),
+        })
+    }
+
+    /// Receive the packets and connections of the other peers, answering their requests with
+    /// `dht`, and ask the STUN servers for the public address now and then.
+    pub fn start(&self, dht: Dht) {
+        if self.inner.dht.set(dht).is_err() {
+            return;
+        }
+        tokio::spawn(self.clone().receive());
+        if let Some(listener) = self.inner.listener.lock().unwrap().take() {
+            tokio::spawn(self.clone().accept_tcp(listener));
+        }
+        let nat = self.clone();
+        tokio::spawn(async move {
+            loop {
+                nat.discover().await;
+                sleep(DISCOVERY_INTERVAL).await;
+            }
+        });
+    }
+
+    pub fn status(&self) -> NatStatus {
+        let nat_type = self.inner.status.lock().unwrap().nat_type;
+        NatStatus {
+            nat_type: nat_type.unwrap_or(NatType::Unknown),
+            candidates: self.candidates(),
+            tcp_port: self.inner.tcp_port,
+            peers: self
+                .inner
+                .paths
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(peer, path)| Some((peer.clone(), path.connection_type()?)))
+                .collect(),
+        }
+    }
+
+    /// Answer the [`PunchRequest`] of another peer, and punch a hole to it in the background.
+    pub fn accept(&self, req: PunchRequest) -> PunchResponse {
+        // a direct connection the other peer asks for again is no longer working
+        self.inner
+            .paths
+            .lock()
+            .unwrap()
+            .insert(req.sender.clone(), Path::Connecting);
+        let nat = self.clone();
+        tokio::spawn(async move {
+            nat.punch(&req.sender, req.nonce, &req.candidates, req.tcp_port)
+                .await;
+        });
+        PunchResponse {
+            candidates: self.candidates(),
+            tcp_port: self.inner.tcp_port,
+        }
+    }
+
+    /// The metrics of the connections in the prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let nat_type = self.status().nat_type;
+        let mut res = String::new();
+        res.push_str(
+            "# HELP mega_p2p_nat_type Type of the NAT of this instance seen by the STUN servers.\n",
+        );
+        res.push_str("# TYPE mega_p2p_nat_type gauge\n");
+        for t in [
+            NatType::Unknown,
+            NatType::Open,
+            NatType::Cone,
+            NatType::Symmetric,
+        ] {
+            let _ = writeln!(
+                res,
+                "mega_p2p_nat_type{{type=\"{}\"}} {}",
+                t.as_str(),
+                (t == nat_type) as u8
+            );
+        }
+        let mut connected = [0; 3];
+        for path in self.inner.paths.lock().unwrap().values() {
+            if let Some(t) = path.connection_type() {
+                connected[t as usize] += 1;
+            }
+        }
+        res.push_str("# HELP mega_p2p_connections Peers reached by each type of connection.\n");
+        res.push_str("# TYPE mega_p2p_connections gauge\n");
+        for t in ConnectionType::ALL {
+            let _ = writeln!(
+                res,
+                "mega_p2p_connections{{type=\"{}\"}} {}",
+                t.as_str(),
+                connected[t as usize]
+            );
+        }
+        res.push_str(
+            "# HELP mega_p2p_connects_total Attempts to connect to a peer by the type of connection they ended with.\n",
+        );
+        res.push_str("# TYPE mega_p2p_connects_total counter\n");
+        for t in ConnectionType::ALL {
+            let _ = writeln!(
+                res,
+                "mega_p2p_connects_total{{type=\"{}\"}} {}",
+                t.as_str(),
+                self.inner.connects[t as usize].load(Ordering::Relaxed)
+            );
+        }
+        res.push_str(
+            "# HELP mega_p2p_messages_total Messages sent to peers by type of connection.\n",
+        );
+        res.push_str("# TYPE mega_p2p_messages_total counter\n");
+        for t in ConnectionType::ALL {
+            let _ = writeln!(
+                res,
+                "mega_p2p_messages_total{{type=\"{}\"}} {}",
+                t.as_str(),
+                self.inner.messages[t as usize].load(Ordering::Relaxed)
+            );
+        }
+        res
+    }
+
+    // The addresses the other peers may reach the local one at: the public ones seen by the
+    // STUN servers, the one of the local network, and the loopback for peers on the same host.
+    fn candidates(&self) -> Vec<SocketAddr> {
+        let status = self.inner.status.lock().unwrap();
+        let loopback = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), self.inner.udp_port);
+        let mut res: Vec<SocketAddr> = Vec::new();
+        for addr in status.public.iter().chain(&status.local).chain([&loopback]) {
+            if !res.contains(addr) {
+                res.push(*addr);
+            }
+        }
+        res
+    }
+
+    // Ask the STUN servers for the public address of the UDP socket, and classify the NAT.
+    async fn discover(&self) {
+        let local = local_ip().map(|ip| SocketAddr::new(ip, self.inner.udp_port));
+        let mut public = Vec::new();
+        for server in &self.inner.config.stun_servers {
+            match self.binding(server).await {
+                Ok(addr) => public.push(addr),
+                Err(e) => tracing::debug!("STUN binding with {} failed: {}", server, e),
+            }
+        }
+        let nat_type = NatType::classify(
+            local.unwrap_or_else(|| self.inner.socket.local_addr().unwrap()),
+            &public,
+        );
+        public.dedup();
+        tracing::info!(
+            "NAT type {}, public addresses {:?}",
+            nat_type.as_str(),
+            public
+        );
+        *self.inner.status.lock().unwrap() = Status {
+            nat_type: Some(nat_type),
+            local,
+            public,
+        };
+    }
+
+    // The address `server` sees the UDP socket from.
+    async fn binding(&self, server: &str) -> Result<SocketAddr, String> {
+        let addr = tokio::net::lookup_host(server)
+            .await
+            .map_err(|e| e.to_string())?
+            .find(SocketAddr::is_ipv4)
+            .ok_or_else(|| format!("no IPv4 address of {}", server))?;
+        let id: TransactionId = rand::random();
+        let (tx, mut rx) = oneshot::channel();
+        self.inner.stun.lock().unwrap().insert(id, tx);
+        let request = stun::binding_request(&id);
+        // sent again as the datagrams may be lost
+        for _ in 0..3 {
+            if let Err(e) = self.inner.socket.send_to(&request, addr).await {
+                self.inner.stun.lock().unwrap().remove(&id);
+                return Err(e.to_string());
+            }
+            if let Ok(res) = timeout(Duration::from_secs(1), &mut rx).await {
+                return res.map_err(|e| e.to_string());
+            }
+        }
+        self.inner.stun.lock().unwrap().remove(&id);
+        Err(String::from("no answer"))
+    }
+
+    // How `peer` is reached directly, `None` if it is reached through the relay. Connecting
+    // to it directly is started in the background if it was not tried recently.
+    fn direct(&self, peer: &str) -> Option<Path> {
+        let mut paths = self.inner.paths.lock().unwrap();
+        let retry = Duration::from_secs(self.inner.config.retry_interval);
+        match paths.get(peer) {
+            Some(path @ (Path::Udp { .. } | Path::Tcp { .. })) => return Some(path.clone()),
+            Some(Path::Connecting) => return None,
+            Some(Path::Relay { since }) if since.elapsed() < retry => return None,
+            _ => {}
+        }
+        paths.insert(peer.to_owned(), Path::Connecting);
+        let nat = self.clone();
+        let peer = peer.to_owned();
+        tokio::spawn(async move { nat.connect(peer).await });
+        None
+    }
+
+    // Negotiate a hole to `peer` through the relay and punch it.
+    async fn connect(&self, peer: String) {
+        let nonce: u64 = rand::random();
+        let req = PunchRequest {
+            sender: self.inner.peer_id.clone(),
+            nonce,
+            candidates: self.candidates(),
+            tcp_port: self.inner.tcp_port,
+        };
+        let res = send_post_request_to_peer_by_tunnel(
+            self.inner.agent_port,
+            peer.clone(),
+            PUNCH_PATH.to_owned(),
+            serde_json::to_string(&req).unwrap(),
+        )
+        .await
+        .and_then(|res| serde_json::from_str::<PunchResponse>(&res).map_err(|e| e.to_string()));
+        match res {
+            Ok(res) => {
+                self.punch(&peer, nonce, &res.candidates, res.tcp_port)
+                    .await;
+            }
+            Err(e) => {
+                tracing::debug!("Failed to negotiate a hole to {}: {}", peer, e);
+                self.fall_back(&peer);
+            }
+        }
+    }
+
+    // Send probes carrying `nonce` to the `candidates` of `peer` and try to open a TCP
+    // connection to them, until one of them comes through or the punch times out.
+    async fn punch(
+        &self,
+        peer: &str,
+        nonce: u64,
+        candidates: &[SocketAddr],
+        tcp_port: u16,
+    ) -> ConnectionType {
+        let (tx, mut done) = oneshot::channel();
+        self.inner.punches.lock().unwrap().insert(
+            peer.to_owned(),
+            Punch {
+                nonce,
+                done: Some(tx),
+            },
+        );
+        let deadline = Instant::now() + Duration::from_secs(self.inner.config.punch_timeout);
+
+        let mut tcp = JoinSet::new();
+        let mut ips: Vec<IpAddr> = candidates.iter().map(SocketAddr::ip).collect();
+        ips.dedup();
+        if tcp_port != 0 {
+            for ip in ips {
+                let nat = self.clone();
+                let peer = peer.to_owned();
+                tcp.spawn(async move {
+                    nat.punch_tcp(peer, nonce, SocketAddr::new(ip, tcp_port), deadline)
+                        .await
+                });
+            }
+        }
+        let probe = serde_json::to_vec(&Packet::Probe {
+            sender: self.inner.peer_id.clone(),
+            nonce,
+            ack: false,
+        })
+        .unwrap();
+        while Instant::now() < deadline {
+            for addr in candidates {
+                let _ = self.inner.socket.send_to(&probe, addr).await;
+            }
+            tokio::select! {
+                _ = &mut done => break,
+                _ = sleep(PROBE_INTERVAL) => {}
+            }
+        }
+        tcp.abort_all();
+
+        {
+            let mut punches = self.inner.punches.lock().unwrap();
+            if punches.get(peer).is_some_and(|punch| punch.nonce == nonce) {
+                punches.remove(peer);
+            }
+        }
+        let connected = self
+            .inner
+            .paths
+            .lock()
+            .unwrap()
+            .get(peer)
+            .and_then(Path::connection_type);
+        match connected {
+            Some(t @ (ConnectionType::DirectUdp | ConnectionType::DirectTcp)) => {
+                tracing::info!("Connected to {} by {}", peer, t.as_str());
+                self.inner.connects[t as usize].fetch_add(1, Ordering::Relaxed);
+                t
+            }
+            _ => {
+                tracing::info!("No direct connection to {}, using the relay", peer);
+                self.fall_back(peer);
+                ConnectionType::Relay
+            }
+        }
+    }
+
+    // Reach `peer` through the relay until `p2p.nat.retry_interval` passed.
+    fn fall_back(&self, peer: &str) {
+        self.inner.paths.lock().unwrap().insert(
+            peer.to_owned(),
+            Path::Relay {
+                since: Instant::now(),
+            },
+        );
+        self.inner.connects[ConnectionType::Relay as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Open a TCP connection to `addr` from the TCP port, at the same time as the peer opens one
+    // the other way, so both NATs let it through.
+    async fn punch_tcp(&self, peer: String, nonce: u64, addr: SocketAddr, deadline: Instant) {
+        let local = SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), self.inner.tcp_port);
+        while Instant::now() < deadline && addr.is_ipv4() {
+            let Ok(socket) = tcp_socket(local) else {
+                return;
+            };
+            if let Ok(Ok(stream)) = timeout(Duration::from_secs(1), socket.connect(addr)).await {
+                if let Err(e) = self.handshake(stream, Some((peer.as_str(), nonce))).await {
+                    tracing::debug!("TCP handshake with {} failed: {}", addr, e);
+                }
+                return;
+            }
+            sleep(PROBE_INTERVAL).await;
+        }
+    }
+
+    async fn accept_tcp(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let nat = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = nat.handshake(stream, None).await {
+                            tracing::debug!("TCP handshake with {} failed: {}", addr, e);
+                        }
+                    });
+                }
+                Err(e) => tracing::debug!("Failed to accept a TCP connection: {}", e),
+            }
+        }
+    }
+
+    // Exchange probes over a new TCP connection, the one which opened it sends the first probe
+    // with the nonce of `expected`. Both send one when they opened it at the same time.
+    async fn handshake(
+        &self,
+        stream: TcpStream,
+        expected: Option<(&str, u64)>,
+    ) -> Result<(), String> {
+        let addr = stream.peer_addr().map_err(|e| e.to_string())?;
+        let (mut reader, writer) = stream.into_split();
+        let writer = Arc::new(AsyncMutex::new(writer));
+        if let Some((_, nonce)) = expected {
+            let probe = Packet::Probe {
+                sender: self.inner.peer_id.clone(),
+                nonce,
+                ack: false,
+            };
+            write_frame(&writer, &probe)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        let packet = timeout(REQUEST_TIMEOUT, read_frame(&mut reader))
+            .await
+            .map_err(|_| String::from("no probe"))??;
+        let Packet::Probe { sender, nonce, ack } = packet else {
+            return Err(String::from("no probe"));
+        };
+        let unexpected = expected.is_some_and(|expected| expected != (sender.as_str(), nonce));
+        if unexpected || !self.valid_nonce(&sender, nonce) {
+            return Err(format!("unexpected probe of {}", sender));
+        }
+        if expected.is_none() && !ack {
+            let probe = Packet::Probe {
+                sender: self.inner.peer_id.clone(),
+                nonce,
+                ack: true,
+            };
+            write_frame(&writer, &probe)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        self.established(
+            &sender,
+            Path::Tcp {
+                addr,
+                nonce,
+                writer: writer.clone(),
+            },
+        );
+        tokio::spawn(self.clone().read_tcp(sender, addr, reader, writer));
+        Ok(())
+    }
+
+    async fn read_tcp(
+        self,
+        peer: String,
+        addr: SocketAddr,
+        mut reader: OwnedReadHalf,
+        writer: Arc<AsyncMutex<OwnedWriteHalf>>,
+    ) {
+        loop {
+            match read_frame(&mut reader).await {
+                Ok(packet) => {
+                    self.on_packet(packet, Link::Tcp(peer.clone(), writer.clone()))
+                        .await
+                }
+                Err(e) => {
+                    tracing::debug!("TCP connection to {} closed: {}", peer, e);
+                    let mut paths = self.inner.paths.lock().unwrap();
+                    if let Some(Path::Tcp { addr: current, .. }) = paths.get(&peer) {
+                        if *current == addr {
+                            paths.remove(&peer);
+                        }
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn receive(self) {
+        let mut buf = vec![0; 65536];
+        loop {
+            let (len, src) = match self.inner.socket.recv_from(&mut buf).await {
+                Ok(res) => res,
+                Err(e) => {
+                    tracing::debug!("Failed to receive a datagram: {}", e);
+                    continue;
+                }
+            };
+            let data = &buf[..len];
+            if stun::is_stun(data) {
+                if let Ok((id, addr)) = stun::parse_binding_response(data) {
+                    if let Some(tx) = self.inner.stun.lock().unwrap().remove(&id) {
+                        let _ = tx.send(addr);
+                    }
+                }
+                continue;
+            }
+            match serde_json::from_slice::<Packet>(data) {
+                Ok(packet) => self.on_packet(packet, Link::Udp(src)).await,
+                Err(_) => tracing::debug!("Dropped an invalid datagram of {}", src),
+            }
+        }
+    }
+
+    async fn on_packet(&self, packet: Packet, link: Link) {
+        match packet {
+            Packet::Probe { sender, nonce, ack } => {
+                let Link::Udp(addr) = link else {
+                    return;
+                };
+                if sender == self.inner.peer_id || !self.valid_nonce(&sender, nonce) {
+                    return;
+                }
+                if !ack {
+                    let probe = Packet::Probe {
+                        sender: self.inner.peer_id.clone(),
+                        nonce,
+                        ack: true,
+                    };
+                    let _ = self
+                        .inner
+                        .socket
+                        .send_to(&serde_json::to_vec(&probe).unwrap(), addr)
+                        .await;
+                }
+                self.inner
+                    .verified
+                    .lock()
+                    .unwrap()
+                    .insert(addr, sender.clone());
+                self.established(&sender, Path::Udp { addr, nonce });
+            }
+            Packet::Request { id, msg } => {
+                // only the peers connected to directly are answered
+                let known = match &link {
+                    Link::Udp(src) => {
+                        self.inner.verified.lock().unwrap().get(src) == Some(&msg.sender)
+                    }
+                    Link::Tcp(peer, _) => *peer == msg.sender,
+                };
+                let Some(dht) = self.inner.dht.get().filter(|_| known).cloned() else {
+                    return;
+                };
+                let nat = self.clone();
+                tokio::spawn(async move {
+                    let res = Packet::Response {
+                        id,
+                        res: dht.handle(msg).await,
+                    };
+                    match link {
+                        Link::Udp(addr) => {
+                            let mut data = serde_json::to_vec(&res).unwrap();
+                            if data.len() > MAX_DATAGRAM {
+                                data = serde_json::to_vec(&Packet::TooLarge { id }).unwrap();
+                            }
+                            let _ = nat.inner.socket.send_to(&data, addr).await;
+                        }
+                        Link::Tcp(_, writer) => {
+                            let _ = write_frame(&writer, &res).await;
+                        }
+                    }
+                });
+            }
+            Packet::Response { id, res } => {
+                if let Some(tx) = self.inner.requests.lock().unwrap().remove(&id) {
+                    let _ = tx.send(Ok(res));
+                }
+            }
+            Packet::TooLarge { id } => {
+                if let Some(tx) = self.inner.requests.lock().unwrap().remove(&id) {
+                    let _ = tx.send(Err(DirectError::TooLarge));
+                }
+            }
+        }
+    }
+
+    // Whether `nonce` was exchanged with `peer` through the relay, for a hole being punched or
+    // for the connection established.
+    fn valid_nonce(&self, peer: &str, nonce: u64) -> bool {
+        let punching = self
+            .inner
+            .punches
+            .lock()
+            .unwrap()
+            .get(peer)
+            .is_some_and(|punch| punch.nonce == nonce);
+        punching
+            || self
+                .inner
+                .paths
+                .lock()
+                .unwrap()
+                .get(peer)
+                .and_then(Path::nonce)
+                == Some(nonce)
+    }
+
+    // Record the first direct connection to `peer`, and end punching.
+    fn established(&self, peer: &str, path: Path) {
+        {
+            let mut paths = self.inner.paths.lock().unwrap();
+            if matches!(paths.get(peer), Some(Path::Udp { .. } | Path::Tcp { .. })) {
+                return;
+            }
+            paths.insert(peer.to_owned(), path);
+        }
+        if let Some(punch) = self.inner.punches.lock().unwrap().get_mut(peer) {
+            if let Some(done) = punch.done.take() {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    // Send `msg` over the direct connection `path` to a peer.
+    async fn request(&self, path: &Path, msg: &DhtMessage) -> Result<DhtResponse, DirectError> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let packet = Packet::Request {
+            id,
+            msg: msg.clone(),
+        };
+        let (tx, rx) = oneshot::channel();
+        self.inner.requests.lock().unwrap().insert(id, tx);
+        let sent = match path {
+            Path::Udp { addr, .. } => {
+                let data = serde_json::to_vec(&packet).unwrap();
+                if data.len() > MAX_DATAGRAM {
+                    Err(DirectError::TooLarge)
+                } else {
+                    self.inner
+                        .socket
+                        .send_to(&data, addr)
+                        .await
+                        .map(|_| ())
+                        .map_err(|e| DirectError::Failed(e.to_string()))
+                }
+            }
+            Path::Tcp { writer, .. } => write_frame(writer, &packet)
+                .await
+                .map_err(|e| DirectError::Failed(e.to_string())),
+            _ => Err(DirectError::Failed(String::from("not connected"))),
+        };
+        let res = match sent {
+            Ok(()) => match timeout(REQUEST_TIMEOUT, rx).await {
+                Ok(Ok(res)) => res,
+                _ => Err(DirectError::Failed(String::from("no answer"))),
+            },
+            Err(e) => Err(e),
+        };
+        self.inner.requests.lock().unwrap().remove(&id);
+        res
+    }
+}
+
+/// Sends the messages of the DHT over the direct connection to the peer if there is one, and
+/// through `relay` otherwise, the relay nodes or the ztm relay.
+pub struct NatTransport {
+    pub nat: Nat,
+    pub relay: Box<dyn DhtTransport>,
+}
+
+#[async_trait]
+impl DhtTransport for NatTransport {
+    async fn send(&self, peer_id: &str, msg: &DhtMessage) -> Result<DhtResponse, String> {
+        let nat = &self.nat;
+        if let Some(path) = nat.direct(peer_id) {
+            let t = path.connection_type().unwrap();
+            match nat.request(&path, msg).await {
+                Ok(res) => {
+                    nat.inner.messages[t as usize].fetch_add(1, Ordering::Relaxed);
+                    return Ok(res);
+                }
+                Err(DirectError::TooLarge) => {}
+                Err(DirectError::Failed(e)) => {
+                    tracing::debug!("Direct connection to {} failed: {}", peer_id, e);
+                    nat.fall_back(peer_id);
+                }
+            }
+        }
+        nat.inner.messages[ConnectionType::Relay as usize].fetch_add(1, Ordering::Relaxed);
+        self.relay.send(peer_id, msg).await
+    }
+}
+
+// A TCP socket bound to `addr`, which may be bound again while the listener uses it.
+fn tcp_socket(addr: SocketAddr) -> io::Result<TcpSocket> {
+    let socket = TcpSocket::new_v4()?;
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    socket.bind(addr)?;
+    Ok(socket)
+}
+
+// The address of the interface of the default route.
+fn local_ip() -> Option<IpAddr> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    // nothing is sent, connecting only picks the interface
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+async fn write_frame(writer: &AsyncMutex<OwnedWriteHalf>, packet: &Packet) -> io::Result<()> {
+    let data = serde_json::to_vec(packet).unwrap();
+    let mut writer = writer.lock().await;
+    writer.write_all(&(data.len() as u32).to_be_bytes()).await?;
+    writer.write_all(&data).await
+}
+
+async fn read_frame(reader: &mut OwnedReadHalf) -> Result<Packet, String> {
+    let len = reader.read_u32().await.map_err(|e| e.to_string())? as usize;
+    if len > MAX_FRAME {
+        return Err(format!("frame of {} bytes is too large", len));
+    }
+    let mut data = vec![0; len];
+    reader
+        .read_exact(&mut data)
+        .await
+        .map_err(|e| e.to_string())?;
+    serde_json::from_slice(&data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use async_trait::async_trait;
+    use common::config::{DhtConfig, NatConfig};
+    use secp256k1::{rand, Keypair, Secp256k1};
+
+    use super::{ConnectionType, Nat};
+    use crate::dht::{Dht, DhtMessage, DhtRequest, DhtResponse, DhtTransport};
+
+    struct Offline;
+
+    #[async_trait]
+    impl DhtTransport for Offline {
+        async fn send(&self, _: &str, _: &DhtMessage) -> Result<DhtResponse, String> {
+            Err(String::from("offline"))
+        }
+    }
+
+    async fn peer() -> (String, Nat) {
+        let keypair = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+        let peer_id = bs58::encode(keypair.public_key().serialize()).into_string();
+        let config = NatConfig {
+            stun_servers: Vec::new(),
+            punch_timeout: 2,
+            ..Default::default()
+        };
+        let nat = Nat::bind(peer_id.clone(), config, 0).unwrap();
+        let dht = Dht::new(peer_id.clone(), keypair, DhtConfig::default(), Offline);
+        nat.start(dht);
+        nat.discover().await;
+        (peer_id, nat)
+    }
+
+    #[tokio::test]
+    async fn test_punch() {
+        let (a_id, a) = peer().await;
+        let (b_id, b) = peer().await;
+        let nonce = 42;
+        let (a_res, b_res) = tokio::join!(
+            a.punch(&b_id, nonce, &b.candidates(), b.inner.tcp_port),
+            b.punch(&a_id, nonce, &a.candidates(), a.inner.tcp_port),
+        );
+        assert_ne!(a_res, ConnectionType::Relay);
+        assert_ne!(b_res, ConnectionType::Relay);
+        assert!(a.status().peers.contains_key(&b_id));
+
+        let path = a.direct(&b_id).unwrap();
+        let msg = DhtMessage {
+            sender: a_id.clone(),
+            request: DhtRequest::Ping,
+        };
+        assert!(matches!(
+            a.request(&path, &msg).await,
+            Ok(DhtResponse::Pong)
+        ));
+        // requests of peers not connected to directly are not answered
+        let (c_id, _) = peer().await;
+        let forged = DhtMessage {
+            sender: c_id,
+            request: DhtRequest::Ping,
+        };
+        assert!(a.request(&path, &forged).await.is_err());
+
+        // a peer which does not punch back ignores the probes
+        let (d_id, d) = peer().await;
+        let candidates = d.candidates();
+        assert_eq!(
+            a.punch(&d_id, 7, &candidates, 0).await,
+            ConnectionType::Relay
+        );
+        assert_eq!(a.status().peers[&d_id], ConnectionType::Relay);
+        assert!(a.direct(&d_id).is_none());
+        assert!(a
+            .render_metrics()
+            .contains("mega_p2p_connects_total{type=\"relay\"} 1"));
+    }
+}
@@ -0,0 +1,184 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use serde::{Deserialize, Serialize};
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_RESPONSE: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const MAPPED_ADDRESS: u16 = 0x0001;
+const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+/// Id of a STUN transaction, matching a response to its request.
+pub type TransactionId = [u8; 12];
+
+/// Kind of NAT in front of the local peer, as seen by the STUN servers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NatType {
+    /// No STUN server answered, UDP may be blocked
+    Unknown,
+    /// The peer has a public address
+    Open,
+    /// The same public address is used towards every host, holes can be punched
+    Cone,
+    /// Another public address is used towards each host, holes can only be punched if the other
+    /// peer is not behind a symmetric NAT too
+    Symmetric,
+}
+
+impl NatType {
+    /// Classify the NAT from the local address of the socket and the addresses the STUN servers
+    /// saw it from.
+    pub fn classify(local: SocketAddr, mapped: &[SocketAddr]) -> Self {
+        match mapped {
+            [] => NatType::Unknown,
+            [first, ..] if *first == local => NatType::Open,
+            [first, rest @ ..] if rest.iter().all(|addr| addr == first) => NatType::Cone,
+            _ => NatType::Symmetric,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NatType::Unknown => "unknown",
+            NatType::Open => "open",
+            NatType::Cone => "cone",
+            NatType::Symmetric => "symmetric",
+        }
+    }
+}
+
+/// A binding request of RFC 5389, without attributes.
+pub fn binding_request(id: &TransactionId) -> Vec<u8> {
+    let mut res = Vec::with_capacity(HEADER_LEN);
+    res.extend_from_slice(&BINDING_REQUEST.to_be_bytes());
+    res.extend_from_slice(&0u16.to_be_bytes());
+    res.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    res.extend_from_slice(id);
+    res
+}
+
+/// Whether `packet` is a STUN message rather than one of the peers.
+pub fn is_stun(packet: &[u8]) -> bool {
+    packet.len() >= HEADER_LEN
+        && packet[0] & 0xC0 == 0
+        && packet[4..8] == MAGIC_COOKIE.to_be_bytes()
+}
+
+/// The transaction id and the address seen by the server of a binding response.
+pub fn parse_binding_response(packet: &[u8]) -> Result<(TransactionId, SocketAddr), String> {
+    if !is_stun(packet) {
+        return Err(String::from("not a STUN message"));
+    }
+    let kind = u16::from_be_bytes([packet[0], packet[1]]);
+    if kind != BINDING_RESPONSE {
+        return Err(format!("unexpected STUN message type {:#06x}", kind));
+    }
+    let len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    let body = packet
+        .get(HEADER_LEN..HEADER_LEN + len)
+        .ok_or("truncated STUN message")?;
+    let id: TransactionId = packet[8..HEADER_LEN].try_into().unwrap();
+
+    let (mut pos, mut mapped) = (0, None);
+    while pos + 4 <= body.len() {
+        let kind = u16::from_be_bytes([body[pos], body[pos + 1]]);
+        let len = u16::from_be_bytes([body[pos + 2], body[pos + 3]]) as usize;
+        let value = body
+            .get(pos + 4..pos + 4 + len)
+            .ok_or("truncated STUN attribute")?;
+        match kind {
+            XOR_MAPPED_ADDRESS => return Ok((id, parse_address(value, Some(&id))?)),
+            MAPPED_ADDRESS => mapped = Some(parse_address(value, None)?),
+            _ => {}
+        }
+        // attributes are padded to 4 bytes
+        pos += 4 + len.div_ceil(4) * 4;
+    }
+    mapped
+        .map(|addr| (id, addr))
+        .ok_or_else(|| String::from("no mapped address in the STUN response"))
+}
+
+// An address attribute, xored with the magic cookie and the transaction id if `xor` is given.
+fn parse_address(value: &[u8], xor: Option<&TransactionId>) -> Result<SocketAddr, String> {
+    if value.len() < 8 {
+        return Err(String::from("invalid STUN address"));
+    }
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let mut port = u16::from_be_bytes([value[2], value[3]]);
+    if xor.is_some() {
+        port ^= u16::from_be_bytes([cookie[0], cookie[1]]);
+    }
+    let ip = match value[1] {
+        0x01 => {
+            let mut octets: [u8; 4] = value[4..8].try_into().unwrap();
+            if xor.is_some() {
+                for (octet, key) in octets.iter_mut().zip(cookie) {
+                    *octet ^= key;
+                }
+            }
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        0x02 => {
+            let mut octets: [u8; 16] = value
+                .get(4..20)
+                .ok_or("invalid STUN address")?
+                .try_into()
+                .unwrap();
+            if let Some(id) = xor {
+                let key = cookie.iter().chain(id.iter());
+                for (octet, key) in octets.iter_mut().zip(key) {
+                    *octet ^= key;
+                }
+            }
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        family => return Err(format!("unknown address family {}", family)),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{binding_request, is_stun, parse_binding_response, NatType};
+
+    #[test]
+    fn test_binding() {
+        let id = [7; 12];
+        let request = binding_request(&id);
+        assert_eq!(request.len(), 20);
+        assert!(is_stun(&request));
+        assert!(!is_stun(b"{\"type\":\"probe\",\"sender\":\"peer\"}"));
+
+        // a response of a server seeing 192.0.2.1:32853, with an unknown attribute before it
+        let mut response = vec![0x01, 0x01, 0x00, 0x14, 0x21, 0x12, 0xA4, 0x42];
+        response.extend_from_slice(&id);
+        response.extend_from_slice(&[0x80, 0x22, 0x00, 0x03, b'm', b'e', b'g', 0x00]);
+        response.extend_from_slice(&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01]);
+        response.extend_from_slice(&(32853u16 ^ 0x2112).to_be_bytes());
+        response.extend_from_slice(&[192 ^ 0x21, 0x12, 2 ^ 0xA4, 1 ^ 0x42]);
+        let (res_id, addr) = parse_binding_response(&response).unwrap();
+        assert_eq!(res_id, id);
+        assert_eq!(addr, "192.0.2.1:32853".parse::<SocketAddr>().unwrap());
+        assert!(parse_binding_response(&request).is_err());
+        assert!(parse_binding_response(&response[..30]).is_err());
+    }
+
+    #[test]
+    fn test_classify() {
+        let local: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let public: SocketAddr = "198.51.100.7:4000".parse().unwrap();
+        let other: SocketAddr = "198.51.100.7:4001".parse().unwrap();
+        assert_eq!(NatType::classify(local, &[]), NatType::Unknown);
+        assert_eq!(NatType::classify(local, &[local, local]), NatType::Open);
+        assert_eq!(NatType::classify(local, &[public, public]), NatType::Cone);
+        assert_eq!(
+            NatType::classify(local, &[public, other]),
+            NatType::Symmetric
+        );
+    }
+}
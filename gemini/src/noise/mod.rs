@@ -0,0 +1,685 @@
+//! End-to-end encryption of the ztm tunnels between the peers with the Noise protocol, so the
+//! repositories and the messages they exchange through the ztm hub can't be read or tampered
+//! with on the way.
+//!
+//! The ztm tunnel to another peer ends at its `p2p.noise.port`, where the channels are
+//! decrypted and passed on to its mega http server. Locally, the requests to the peer go to a
+//! proxy encrypting them before they enter the tunnel. A channel starts with an XX handshake in
+//! which both sides send their static X25519 key signed with the key of their peer id, so the
+//! initiator knows it talks to the peer it meant to and the responder knows which peer talks
+//! to it.
+//!
+//! Before the handshake the initiator offers the ciphers it supports and the responder picks
+//! one. Both go into the prologue of the handshake, which fails if the offer was tampered with
+//! to make them agree on a weaker cipher, and there is no fallback to plaintext. Each side
+//! changes the key it sends with every `p2p.noise.rekey_interval` seconds or 2^20 messages,
+//! flagging the last message sent with the old key.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use common::config::NoiseConfig;
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::schnorr::Signature;
+use secp256k1::Keypair;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use snow::{Builder, HandshakeState, TransportState};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::{timeout, Instant};
+
+use crate::bandwidth::{Bandwidth, Direction};
+use crate::nostr::event::sign_without_rng;
+use crate::util::{get_ztm_app_tunnel_bound_name, verify_peer_signature};
+use crate::ztm::get_or_create_tunnel;
+
+/// Noise protocols a channel may use, the preferred one first.
+pub const PROTOCOLS: [&str; 2] = [
+    "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+    "Noise_XX_25519_AESGCM_SHA256",
+];
+// Time the other side has to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Largest Noise message.
+const MAX_MESSAGE: usize = 65535;
+// Largest plaintext of a message, after its authentication tag and its flags.
+const MAX_CHUNK: usize = MAX_MESSAGE - 16 - 1;
+// Messages sent with a key at most.
+const REKEY_MESSAGES: u64 = 1 << 20;
+// Flag of the last message sent with a key.
+const FLAG_REKEY: u8 = 1;
+
+/// The static key of the local peer in the channels, signed with the key of its peer id.
+pub struct Identity {
+    private: Vec<u8>,
+    proof: Proof,
+}
+
+// Sent in the handshake, binds the static key of a side to its peer id.
+#[derive(Serialize, Deserialize)]
+struct Proof {
+    peer_id: String,
+    sig: Signature,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Offer {
+    protocols: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Choice {
+    protocol: String,
+}
+
+impl Identity {
+    /// A new static key for `peer_id`, signed with its `keypair`.
+    pub fn new(peer_id: String, keypair: &Keypair) -> Self {
+        let key = Builder::new(PROTOCOLS[0].parse().unwrap())
+            .generate_keypair()
+            .unwrap();
+        let sig = sign_without_rng(key_digest(&key.public).to_string(), keypair);
+        Identity {
+            private: key.private,
+            proof: Proof { peer_id, sig },
+        }
+    }
+}
+
+/// A channel after its handshake, which encrypts the data of a stream.
+pub struct Channel {
+    /// Peer id of the other side
+    pub remote: String,
+    pub protocol: &'static str,
+    state: TransportState,
+    rekey_interval: Duration,
+    rekey_messages: u64,
+}
+
+impl Channel {
+    /// Pass the data of `plain` encrypted to `secure`, and the data of `secure` decrypted to
+    /// `plain`, until both are closed, at the rates `bandwidth` allows for the other peer.
+    /// `rekeys` counts the keys changed.
+    pub async fn pipe<S, P>(
+        self,
+        secure: S,
+        plain: P,
+        rekeys: &AtomicU64,
+        bandwidth: &Bandwidth,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite,
+        P: AsyncRead + AsyncWrite,
+    {
+        let Channel {
+            remote,
+            state,
+            rekey_interval,
+            rekey_messages,
+            ..
+        } = self;
+        let transfer = bandwidth.transfer(&remote);
+        let state = Mutex::new(state);
+        let (mut secure_reader, mut secure_writer) = tokio::io::split(secure);
+        let (mut plain_reader, mut plain_writer) = tokio::io::split(plain);
+
+        let encrypt = async {
+            let mut chunk = vec![0; MAX_CHUNK];
+            let mut plaintext = Vec::with_capacity(MAX_CHUNK + 1);
+            let mut message = vec![0; MAX_MESSAGE];
+            let (mut sent, mut since) = (0, Instant::now());
+            loop {
+                let len = plain_reader.read(&mut chunk).await?;
+                if len == 0 {
+                    return secure_writer.shutdown().await;
+                }
+                transfer.take(Direction::Upload, len).await;
+                sent += 1;
+                let rekey = sent >= rekey_messages || since.elapsed() >= rekey_interval;
+                plaintext.clear();
+                plaintext.push(if rekey { FLAG_REKEY } else { 0 });
+                plaintext.extend_from_slice(&chunk[..len]);
+                let len = {
+                    let mut state = state.lock().unwrap();
+                    let len = state
+                        .write_message(&plaintext, &mut message)
+                        .map_err(invalid_data)?;
+                    if rekey {
+                        state.rekey_outgoing();
+                    }
+                    len
+                };
+                write_message(&mut secure_writer, &message[..len]).await?;
+                if rekey {
+                    rekeys.fetch_add(1, Ordering::Relaxed);
+                    (sent, since) = (0, Instant::now());
+                }
+            }
+        };
+        let decrypt = async {
+            let mut message = Vec::new();
+            let mut plaintext = vec![0; MAX_MESSAGE];
+            loop {
+                match read_message(&mut secure_reader, &mut message).await {
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                        return plain_writer.shutdown().await;
+                    }
+                    res => res?,
+                }
+                let len = {
+                    let mut state = state.lock().unwrap();
+                    let len = state
+                        .read_message(&message, &mut plaintext)
+                        .map_err(invalid_data)?;
+                    if len > 0 && plaintext[0] & FLAG_REKEY != 0 {
+                        state.rekey_incoming();
+                    }
+                    len
+                };
+                if len == 0 {
+                    return Err(invalid_data("message without flags"));
+                }
+                transfer.take(Direction::Download, len - 1).await;
+                plain_writer.write_all(&plaintext[1..len]).await?;
+            }
+        };
+        tokio::try_join!(encrypt, decrypt).map(|_| ())
+    }
+}
+
+/// Start a channel on `stream` to the peer `expected`, failing if another peer answers.
+pub async fn initiate<S>(
+    stream: &mut S,
+    identity: &Identity,
+    expected: &str,
+    rekey_interval: Duration,
+) -> Result<Channel, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let offer = serde_json::to_vec(&Offer {
+        protocols: PROTOCOLS.iter().map(|p| p.to_string()).collect(),
+    })
+    .unwrap();
+    write_message(stream, &offer)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut choice = Vec::new();
+    read_message(stream, &mut choice)
+        .await
+        .map_err(|e| e.to_string())?;
+    let Choice { protocol } = serde_json::from_slice(&choice).map_err(|e| e.to_string())?;
+    let protocol = PROTOCOLS
+        .into_iter()
+        .find(|p| *p == protocol)
+        .ok_or_else(|| format!("unknown protocol {}", protocol))?;
+    let prologue = prologue(&offer, &choice);
+    let mut handshake = builder(identity, protocol, &prologue)
+        .build_initiator()
+        .map_err(|e| e.to_string())?;
+
+    // -> e
+    send_handshake(stream, &mut handshake, &[]).await?;
+    // <- e, ee, s, es
+    let remote = verify_proof(
+        &handshake,
+        &receive_handshake(stream, &mut handshake).await?,
+    )?;
+    if remote != expected {
+        return Err(format!("expected {} but {} answered", expected, remote));
+    }
+    // -> s, se
+    let proof = serde_json::to_vec(&identity.proof).unwrap();
+    send_handshake(stream, &mut handshake, &proof).await?;
+    channel(handshake, remote, protocol, rekey_interval)
+}
+
+/// Accept a channel on `stream` from another peer.
+pub async fn respond<S>(
+    stream: &mut S,
+    identity: &Identity,
+    rekey_interval: Duration,
+) -> Result<Channel, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut offer = Vec::new();
+    read_message(stream, &mut offer)
+        .await
+        .map_err(|e| e.to_string())?;
+    let Offer { protocols } = serde_json::from_slice(&offer).map_err(|e| e.to_string())?;
+    let protocol = PROTOCOLS
+        .into_iter()
+        .find(|p| protocols.iter().any(|offered| offered.as_str() == *p))
+        .ok_or_else(|| format!("no supported protocol in {:?}", protocols))?;
+    let choice = serde_json::to_vec(&Choice {
+        protocol: protocol.to_owned(),
+    })
+    .unwrap();
+    write_message(stream, &choice)
+        .await
+        .map_err(|e| e.to_string())?;
+    let prologue = prologue(&offer, &choice);
+    let mut handshake = builder(identity, protocol, &prologue)
+        .build_responder()
+        .map_err(|e| e.to_string())?;
+
+    // -> e
+    receive_handshake(stream, &mut handshake).await?;
+    // <- e, ee, s, es
+    let proof = serde_json::to_vec(&identity.proof).unwrap();
+    send_handshake(stream, &mut handshake, &proof).await?;
+    // -> s, se
+    let remote = verify_proof(
+        &handshake,
+        &receive_handshake(stream, &mut handshake).await?,
+    )?;
+    channel(handshake, remote, protocol, rekey_interval)
+}
+
+/// The encrypted channels of the local peer to the other ones, see [`init`].
+pub struct SecureChannels {
+    identity: Identity,
+    config: NoiseConfig,
+    // the port of the local proxy to each peer
+    proxies: AsyncMutex<HashMap<String, u16>>,
+    // the peer of each connection passed on to the mega http server, by its local address
+    peers: Mutex<HashMap<SocketAddr, String>>,
+    // handshakes initiated and responded to, by whether they succeeded
+    handshakes: [[AtomicU64; 2]; 2],
+    rekeys: AtomicU64,
+    bandwidth: Bandwidth,
+}
+
+static CHANNELS: OnceLock<SecureChannels> = OnceLock::new();
+
+/// Encrypt the tunnels to the other peers with `identity` from now on, limiting the transfers
+/// through them to the rates of `bandwidth`.
+pub fn init(
+    identity: Identity,
+    config: NoiseConfig,
+    bandwidth: Bandwidth,
+) -> &'static SecureChannels {
+    CHANNELS.get_or_init(|| SecureChannels {
+        identity,
+        config,
+        proxies: AsyncMutex::new(HashMap::new()),
+        peers: Mutex::new(HashMap::new()),
+        handshakes: Default::default(),
+        rekeys: AtomicU64::new(0),
+        bandwidth,
+    })
+}
+
+/// The encrypted channels, `None` if the tunnels are not encrypted.
+pub fn channels() -> Option<&'static SecureChannels> {
+    CHANNELS.get()
+}
+
+impl SecureChannels {
+    /// Accept the channels of the other peers on `listener`, the end of their ztm tunnels, and
+    /// pass them on to the mega http server at `http_port`.
+    pub async fn serve(&'static self, listener: TcpListener, http_port: u16) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = self.accept(stream, http_port).await {
+                            tracing::debug!("Noise channel from {} closed: {}", addr, e);
+                        }
+                    });
+                }
+                Err(e) => tracing::debug!("Failed to accept a Noise channel: {}", e),
+            }
+        }
+    }
+
+    /// The peer which sent the requests the mega http server receives from `addr`, if they
+    /// came through a channel.
+    pub fn peer_of(&self, addr: &SocketAddr) -> Option<String> {
+        self.peers.lock().unwrap().get(addr).cloned()
+    }
+
+    /// The port of the local proxy to `peer`: the requests sent to it reach the mega http
+    /// server of the peer through an encrypted channel in its ztm tunnel. Both are created on
+    /// first use.
+    pub async fn local_port(&'static self, agent_port: u16, peer: &str) -> Result<u16, String> {
+        let mut proxies = self.proxies.lock().await;
+        if let Some(port) = proxies.get(peer) {
+            return Ok(*port);
+        }
+        let bound_name = format!("{}_noise", get_ztm_app_tunnel_bound_name(peer.to_owned()));
+        let tunnel_port =
+            get_or_create_tunnel(agent_port, peer.to_owned(), bound_name, self.config.port).await?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(|e| e.to_string())?;
+        let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+        tokio::spawn(self.proxy(listener, peer.to_owned(), tunnel_port));
+        proxies.insert(peer.to_owned(), port);
+        Ok(port)
+    }
+
+    /// The metrics of the channels in the prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let mut res = String::new();
+        res.push_str(
+            "# HELP mega_p2p_noise_handshakes_total Noise handshakes with other peers by direction and result.\n",
+        );
+        res.push_str("# TYPE mega_p2p_noise_handshakes_total counter\n");
+        for (direction, counts) in ["initiated", "responded"].iter().zip(&self.handshakes) {
+            for (result, count) in ["ok", "failed"].iter().zip(counts) {
+                let _ = writeln!(
+                    res,
+                    "mega_p2p_noise_handshakes_total{{direction=\"{}\",result=\"{}\"}} {}",
+                    direction,
+                    result,
+                    count.load(Ordering::Relaxed)
+                );
+            }
+        }
+        res.push_str("# HELP mega_p2p_noise_rekeys_total Keys changed by the Noise channels.\n");
+        res.push_str("# TYPE mega_p2p_noise_rekeys_total counter\n");
+        let _ = writeln!(
+            res,
+            "mega_p2p_noise_rekeys_total {}",
+            self.rekeys.load(Ordering::Relaxed)
+        );
+        res + &self.bandwidth.render_metrics()
+    }
+
+    async fn accept(&'static self, mut stream: TcpStream, http_port: u16) -> Result<(), String> {
+        let rekey_interval = Duration::from_secs(self.config.rekey_interval);
+        let channel = timeout(
+            HANDSHAKE_TIMEOUT,
+            respond(&mut stream, &self.identity, rekey_interval),
+        )
+        .await
+        .unwrap_or_else(|_| Err(String::from("handshake timed out")));
+        self.handshakes[1][channel.is_err() as usize].fetch_add(1, Ordering::Relaxed);
+        let channel = channel?;
+        let http = TcpStream::connect((Ipv4Addr::LOCALHOST, http_port))
+            .await
+            .map_err(|e| e.to_string())?;
+        let local = http.local_addr().map_err(|e| e.to_string())?;
+        self.peers
+            .lock()
+            .unwrap()
+            .insert(local, channel.remote.clone());
+        let res = channel
+            .pipe(stream, http, &self.rekeys, &self.bandwidth)
+            .await;
+        self.peers.lock().unwrap().remove(&local);
+        res.map_err(|e| e.to_string())
+    }
+
+    // Encrypt the connections to `listener` into channels to `peer` through the ztm tunnel at
+    // `tunnel_port`.
+    async fn proxy(&'static self, listener: TcpListener, peer: String, tunnel_port: u16) {
+        let rekey_interval = Duration::from_secs(self.config.rekey_interval);
+        loop {
+            let plain = match listener.accept().await {
+                Ok((plain, _)) => plain,
+                Err(e) => {
+                    tracing::debug!("Failed to accept a connection to {}: {}", peer, e);
+                    continue;
+                }
+            };
+            let peer = peer.clone();
+            tokio::spawn(async move {
+                let res = async {
+                    let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, tunnel_port))
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    let channel = timeout(
+                        HANDSHAKE_TIMEOUT,
+                        initiate(&mut stream, &self.identity, &peer, rekey_interval),
+                    )
+                    .await
+                    .unwrap_or_else(|_| Err(String::from("handshake timed out")));
+                    self.handshakes[0][channel.is_err() as usize].fetch_add(1, Ordering::Relaxed);
+                    channel?
+                        .pipe(stream, plain, &self.rekeys, &self.bandwidth)
+                        .await
+                        .map_err(|e| e.to_string())
+                }
+                .await;
+                if let Err(e) = res {
+                    tracing::warn!("Noise channel to {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+// The prologue of a handshake, the messages which negotiated its protocol.
+fn prologue(offer: &[u8], choice: &[u8]) -> Vec<u8> {
+    let mut res = b"mega-noise".to_vec();
+    for message in [offer, choice] {
+        res.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        res.extend_from_slice(message);
+    }
+    res
+}
+
+fn builder<'a>(identity: &'a Identity, protocol: &str, prologue: &'a [u8]) -> Builder<'a> {
+    Builder::new(protocol.parse().unwrap())
+        .local_private_key(&identity.private)
+        .prologue(prologue)
+}
+
+fn channel(
+    handshake: HandshakeState,
+    remote: String,
+    protocol: &'static str,
+    rekey_interval: Duration,
+) -> Result<Channel, String> {
+    Ok(Channel {
+        remote,
+        protocol,
+        state: handshake.into_transport_mode().map_err(|e| e.to_string())?,
+        rekey_interval,
+        rekey_messages: REKEY_MESSAGES,
+    })
+}
+
+async fn send_handshake<S>(
+    stream: &mut S,
+    handshake: &mut HandshakeState,
+    payload: &[u8],
+) -> Result<(), String>
+where
+    S: AsyncWrite + Unpin,
+{
+    let mut message = vec![0; MAX_MESSAGE];
+    let len = handshake
+        .write_message(payload, &mut message)
+        .map_err(|e| e.to_string())?;
+    write_message(stream, &message[..len])
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// The payload of the next handshake message.
+async fn receive_handshake<S>(
+    stream: &mut S,
+    handshake: &mut HandshakeState,
+) -> Result<Vec<u8>, String>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut message = Vec::new();
+    read_message(stream, &mut message)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut payload = vec![0; MAX_MESSAGE];
+    let len = handshake
+        .read_message(&message, &mut payload)
+        .map_err(|e| e.to_string())?;
+    payload.truncate(len);
+    Ok(payload)
+}
+
+// The peer id of the other side, if it signed the static key it sent in the handshake.
+fn verify_proof(handshake: &HandshakeState, payload: &[u8]) -> Result<String, String> {
+    let proof: Proof = serde_json::from_slice(payload).map_err(|e| e.to_string())?;
+    let key = handshake
+        .get_remote_static()
+        .ok_or("no static key in the handshake")?;
+    verify_peer_signature(&proof.peer_id, key_digest(key), &proof.sig)?;
+    Ok(proof.peer_id)
+}
+
+// The sha256 a peer signs to bind its static key to its peer id.
+fn key_digest(key: &[u8]) -> sha256::Hash {
+    let data = json!(["mega-noise", hex::encode(key)]).to_string();
+    sha256::Hash::hash(data.as_bytes())
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(data.len() + 2);
+    frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    frame.extend_from_slice(data);
+    writer.write_all(&frame).await
+}
+
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R, data: &mut Vec<u8>) -> io::Result<()> {
+    let len = reader.read_u16().await? as usize;
+    data.resize(len, 0);
+    reader.read_exact(data).await.map(|_| ())
+}
+
+fn invalid_data(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    use secp256k1::{rand, Keypair, Secp256k1};
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::{initiate, read_message, respond, write_message, Identity, PROTOCOLS};
+    use crate::bandwidth::Bandwidth;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn identity() -> (String, Identity) {
+        let keypair = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+        let peer_id = bs58::encode(keypair.public_key().serialize()).into_string();
+        (peer_id.clone(), Identity::new(peer_id, &keypair))
+    }
+
+    #[tokio::test]
+    async fn test_channel() {
+        let (a_id, a) = identity();
+        let (b_id, b) = identity();
+        let (mut a_stream, mut b_stream) = duplex(1 << 20);
+        let (a_channel, b_channel) = tokio::join!(
+            initiate(&mut a_stream, &a, &b_id, HOUR),
+            respond(&mut b_stream, &b, HOUR),
+        );
+        let mut a_channel = a_channel.unwrap();
+        let b_channel = b_channel.unwrap();
+        assert_eq!(a_channel.remote, b_id);
+        assert_eq!(b_channel.remote, a_id);
+        assert_eq!(a_channel.protocol, PROTOCOLS[0]);
+
+        // the keys change every 3 messages of a
+        a_channel.rekey_messages = 3;
+        let (mut a_plain, a_inner) = duplex(1 << 20);
+        let (mut b_plain, b_inner) = duplex(1 << 20);
+        let rekeys = AtomicU64::new(0);
+        let bandwidth = Bandwidth::default();
+        let pipes = async {
+            let (a_res, b_res) = tokio::join!(
+                a_channel.pipe(a_stream, a_inner, &rekeys, &bandwidth),
+                b_channel.pipe(b_stream, b_inner, &rekeys, &bandwidth),
+            );
+            a_res.unwrap();
+            b_res.unwrap();
+        };
+        let exchange = async {
+            let mut received = Vec::new();
+            for i in 0..10u8 {
+                a_plain.write_all(&[i; 100]).await.unwrap();
+                let mut buf = [0; 100];
+                b_plain.read_exact(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf);
+            }
+            b_plain.write_all(b"done").await.unwrap();
+            let mut buf = [0; 4];
+            a_plain.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"done");
+            drop((a_plain, b_plain));
+            received
+        };
+        let ((), received) = tokio::join!(pipes, exchange);
+        let expected: Vec<u8> = (0..10u8).flat_map(|i| [i; 100]).collect();
+        assert_eq!(received, expected);
+        assert_eq!(rekeys.load(Ordering::Relaxed), 3);
+        // the plaintext is counted by each side, 1000 bytes of a and 4 of b
+        let metrics = bandwidth.render_metrics();
+        assert!(metrics.contains("mega_p2p_bandwidth_bytes_total{direction=\"upload\"} 1004\n"));
+        assert!(metrics.contains("mega_p2p_transfers 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_peer() {
+        let (_, a) = identity();
+        let (_, b) = identity();
+        let (c_id, _) = identity();
+        let (mut a_stream, mut b_stream) = duplex(1 << 20);
+        let (a_channel, b_channel) = tokio::join!(
+            async {
+                let res = initiate(&mut a_stream, &a, &c_id, HOUR).await;
+                drop(a_stream);
+                res
+            },
+            async {
+                let res = respond(&mut b_stream, &b, HOUR).await;
+                drop(b_stream);
+                res
+            },
+        );
+        assert!(a_channel.is_err());
+        assert!(b_channel.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_downgrade() {
+        let (_, a) = identity();
+        let (b_id, b) = identity();
+        let (mut a_stream, mut a_mitm) = duplex(1 << 20);
+        let (mut b_mitm, mut b_stream) = duplex(1 << 20);
+        // an attacker on the way only offers the second protocol to the responder
+        tokio::spawn(async move {
+            let mut offer = Vec::new();
+            read_message(&mut a_mitm, &mut offer).await.unwrap();
+            let tampered = format!("{{\"protocols\":[\"{}\"]}}", PROTOCOLS[1]);
+            write_message(&mut b_mitm, tampered.as_bytes())
+                .await
+                .unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut a_mitm, &mut b_mitm).await;
+        });
+        let (a_channel, b_channel) = tokio::join!(
+            async {
+                let res = initiate(&mut a_stream, &a, &b_id, HOUR).await;
+                drop(a_stream);
+                res
+            },
+            respond(&mut b_stream, &b, HOUR),
+        );
+        assert!(a_channel.is_err());
+        assert!(b_channel.is_err());
+    }
+}
@@ -0,0 +1,827 @@
+//! Relay nodes forwarding the messages of the DHT between peers which can't connect directly,
+//! so communities can run their own network infrastructure instead of depending on the ztm
+//! relay of the bootstrap node.
+//!
+//! A peer connects to a relay node over TCP and proves its peer id by signing the challenge the
+//! relay node sends first. It then sends messages addressed to the other peers connected to the
+//! same relay node, which delivers them with the id of the sender. The relay node admits the
+//! peers of `p2p.relay.allowed_peers`, or any peer if it is empty, and forwards at most
+//! `p2p.relay.peer_rate` bytes per second of each peer and `p2p.relay.max_rate` of all of them,
+//! reading more slowly from the peers above them.
+//!
+//! Peers connect to the relay nodes of `p2p.relay_nodes`, and send the messages to the peers
+//! they can't connect to directly through the first relay node the other peer is connected to
+//! as well, before falling back to the ztm relay.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use common::config::RelayConfig;
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::schnorr::Signature;
+use secp256k1::{rand, Keypair};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep, timeout, Instant};
+
+use crate::bandwidth::Bucket;
+use crate::dht::{Dht, DhtMessage, DhtResponse, DhtTransport};
+use crate::nostr::event::sign_without_rng;
+use crate::util::{get_utc_timestamp, verify_peer_signature};
+
+// Time a peer has to prove its id, and a relay node to answer.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// Time the other peer has to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// Time between connecting again to a relay node which closed the connection.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+// Time between measuring the bytes forwarded per second.
+const RATE_INTERVAL: Duration = Duration::from_secs(10);
+// Largest frame of a connection.
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+// Frames waiting to be written to a peer, the ones above are dropped.
+const QUEUE_LEN: usize = 256;
+
+/// What the peers send each other through a relay node.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayMessage {
+    Request { id: u64, msg: DhtMessage },
+    Response { id: u64, res: DhtResponse },
+}
+
+// A frame of the connection of a peer to a relay node.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    // the first frame of the relay node, signed by the peer to prove its id
+    Challenge { nonce: String },
+    Hello { peer_id: String, sig: Signature },
+    Welcome,
+    Refused { reason: String },
+    Send { to: String, data: RelayMessage },
+    Deliver { from: String, data: RelayMessage },
+    // the peer a request was sent to is not connected to the relay node
+    Unreachable { id: u64 },
+}
+
+/// How much a relay node is used.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RelayStatus {
+    pub max_peers: usize,
+    pub peer_rate: u64,
+    pub max_rate: u64,
+    /// Bytes forwarded per second, measured every 10 seconds
+    pub rate: u64,
+    pub bytes_forwarded: u64,
+    pub peers: Vec<RelayPeer>,
+}
+
+/// A peer connected to a relay node.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RelayPeer {
+    pub peer_id: String,
+    pub addr: SocketAddr,
+    pub connected_at: i64,
+    /// Bytes the peer sent to other peers
+    pub bytes_sent: u64,
+    /// Bytes other peers sent to the peer
+    pub bytes_received: u64,
+    /// Milliseconds the messages of the peer waited for its bandwidth caps
+    pub throttled_ms: u64,
+}
+
+#[derive(Default)]
+struct PeerStats {
+    sent: AtomicU64,
+    received: AtomicU64,
+    throttled_ms: AtomicU64,
+}
+
+struct Connected {
+    // tells the connection apart from a later one of the same peer
+    conn: u64,
+    addr: SocketAddr,
+    connected_at: i64,
+    tx: mpsc::Sender<Frame>,
+    stats: Arc<PeerStats>,
+}
+
+/// The relay node run by `mega service relay`, cheap to clone.
+#[derive(Clone)]
+pub struct RelayServer {
+    inner: Arc<ServerInner>,
+}
+
+struct ServerInner {
+    config: RelayConfig,
+    peers: Mutex<HashMap<String, Connected>>,
+    total: Mutex<Bucket>,
+    next_conn: AtomicU64,
+    bytes: AtomicU64,
+    rate: AtomicU64,
+    // frames forwarded, sent to a peer not connected, and dropped as the peer was too slow
+    frames: [AtomicU64; 3],
+    refused: AtomicU64,
+    throttled_ms: AtomicU64,
+}
+
+const FRAME_RESULTS: [&str; 3] = ["forwarded", "unreachable", "dropped"];
+
+impl RelayServer {
+    pub fn new(config: RelayConfig) -> Self {
+        let total = Bucket::new(config.max_rate, Instant::now());
+        RelayServer {
+            inner: Arc::new(ServerInner {
+                config,
+                peers: Mutex::new(HashMap::new()),
+                total: Mutex::new(total),
+                next_conn: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+                rate: AtomicU64::new(0),
+                frames: Default::default(),
+                refused: AtomicU64::new(0),
+                throttled_ms: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Accept the connections of the peers on `listener` and forward their messages.
+    pub async fn serve(self, listener: TcpListener) {
+        let relay = self.clone();
+        tokio::spawn(async move {
+            let mut last = 0;
+            loop {
+                sleep(RATE_INTERVAL).await;
+                let bytes = relay.inner.bytes.load(Ordering::Relaxed);
+                relay
+                    .inner
+                    .rate
+                    .store((bytes - last) / RATE_INTERVAL.as_secs(), Ordering::Relaxed);
+                last = bytes;
+            }
+        });
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    let relay = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = relay.handle(stream, addr).await {
+                            tracing::debug!("Relay connection of {} closed: {}", addr, e);
+                        }
+                    });
+                }
+                Err(e) => tracing::debug!("Failed to accept a relay connection: {}", e),
+            }
+        }
+    }
+
+    pub fn status(&self) -> RelayStatus {
+        let config = &self.inner.config;
+        let mut peers: Vec<RelayPeer> = self
+            .inner
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(peer_id, c)| RelayPeer {
+                peer_id: peer_id.clone(),
+                addr: c.addr,
+                connected_at: c.connected_at,
+                bytes_sent: c.stats.sent.load(Ordering::Relaxed),
+                bytes_received: c.stats.received.load(Ordering::Relaxed),
+                throttled_ms: c.stats.throttled_ms.load(Ordering::Relaxed),
+            })
+            .collect();
+        peers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        RelayStatus {
+            max_peers: config.max_peers,
+            peer_rate: config.peer_rate,
+            max_rate: config.max_rate,
+            rate: self.inner.rate.load(Ordering::Relaxed),
+            bytes_forwarded: self.inner.bytes.load(Ordering::Relaxed),
+            peers,
+        }
+    }
+
+    /// The metrics of the relay node in the prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let inner = &self.inner;
+        let mut res = String::new();
+        res.push_str("# HELP mega_relay_peers Peers connected to the relay node.\n");
+        res.push_str("# TYPE mega_relay_peers gauge\n");
+        let _ = writeln!(
+            res,
+            "mega_relay_peers {}",
+            inner.peers.lock().unwrap().len()
+        );
+        res.push_str("# HELP mega_relay_max_peers Peers which may connect at the same time.\n");
+        res.push_str("# TYPE mega_relay_max_peers gauge\n");
+        let _ = writeln!(res, "mega_relay_max_peers {}", inner.config.max_peers);
+        res.push_str("# HELP mega_relay_bytes_total Bytes forwarded between peers.\n");
+        res.push_str("# TYPE mega_relay_bytes_total counter\n");
+        let _ = writeln!(
+            res,
+            "mega_relay_bytes_total {}",
+            inner.bytes.load(Ordering::Relaxed)
+        );
+        res.push_str("# HELP mega_relay_rate_bytes Bytes forwarded per second.\n");
+        res.push_str("# TYPE mega_relay_rate_bytes gauge\n");
+        let _ = writeln!(
+            res,
+            "mega_relay_rate_bytes {}",
+            inner.rate.load(Ordering::Relaxed)
+        );
+        res.push_str(
+            "# HELP mega_relay_max_rate_bytes Bytes per second forwarded from all the peers at most, 0 if unlimited.\n",
+        );
+        res.push_str("# TYPE mega_relay_max_rate_bytes gauge\n");
+        let _ = writeln!(res, "mega_relay_max_rate_bytes {}", inner.config.max_rate);
+        res.push_str(
+            "# HELP mega_relay_frames_total Messages sent through the relay node by result.\n",
+        );
+        res.push_str("# TYPE mega_relay_frames_total counter\n");
+        for (result, count) in FRAME_RESULTS.iter().zip(&inner.frames) {
+            let _ = writeln!(
+                res,
+                "mega_relay_frames_total{{result=\"{}\"}} {}",
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+        res.push_str(
+            "# HELP mega_relay_refused_total Connections refused, as the peer could not prove its id, was not allowed or the relay node was full.\n",
+        );
+        res.push_str("# TYPE mega_relay_refused_total counter\n");
+        let _ = writeln!(
+            res,
+            "mega_relay_refused_total {}",
+            inner.refused.load(Ordering::Relaxed)
+        );
+        res.push_str(
+            "# HELP mega_relay_throttled_seconds_total Time the messages waited for the bandwidth caps.\n",
+        );
+        res.push_str("# TYPE mega_relay_throttled_seconds_total counter\n");
+        let _ = writeln!(
+            res,
+            "mega_relay_throttled_seconds_total {}",
+            inner.throttled_ms.load(Ordering::Relaxed) as f64 / 1000.0
+        );
+        res
+    }
+
+    async fn handle(self, stream: TcpStream, addr: SocketAddr) -> Result<(), String> {
+        let (mut reader, mut writer) = stream.into_split();
+        let nonce = hex::encode(rand::random::<[u8; 32]>());
+        write_frame(
+            &mut writer,
+            &Frame::Challenge {
+                nonce: nonce.clone(),
+            },
+        )
+        .await?;
+        let hello = timeout(HANDSHAKE_TIMEOUT, read_frame(&mut reader))
+            .await
+            .map_err(|_| String::from("no hello"))??;
+        let Frame::Hello { peer_id, sig } = hello.0 else {
+            return Err(String::from("no hello"));
+        };
+        let (conn, tx, mut rx, stats) = match self.admit(&peer_id, &nonce, &sig, addr) {
+            Ok(res) => res,
+            Err(reason) => {
+                self.inner.refused.fetch_add(1, Ordering::Relaxed);
+                let _ = write_frame(
+                    &mut writer,
+                    &Frame::Refused {
+                        reason: reason.clone(),
+                    },
+                )
+                .await;
+                return Err(reason);
+            }
+        };
+        tracing::info!("Peer {} connected to the relay from {}", peer_id, addr);
+        let res: Result<(), String> = async {
+            write_frame(&mut writer, &Frame::Welcome).await?;
+            let write = async {
+                while let Some(frame) = rx.recv().await {
+                    write_frame(&mut writer, &frame).await?;
+                }
+                Ok::<(), String>(())
+            };
+            let read = async {
+                let mut bucket = Bucket::new(self.inner.config.peer_rate, Instant::now());
+                loop {
+                    match read_frame(&mut reader).await {
+                        Ok((Frame::Send { to, data }, len)) => {
+                            self.forward(&peer_id, to, data, len, &mut bucket, &tx, &stats)
+                                .await
+                        }
+                        Ok(_) => {}
+                        Err(e) => break Err::<(), String>(e),
+                    }
+                }
+            };
+            tokio::select! {
+                res = write => res,
+                res = read => res,
+            }
+        }
+        .await;
+
+        let mut peers = self.inner.peers.lock().unwrap();
+        if peers.get(&peer_id).is_some_and(|c| c.conn == conn) {
+            peers.remove(&peer_id);
+        }
+        res
+    }
+
+    // Check `peer_id` signed the challenge and may connect, and register its connection.
+    #[allow(clippy::type_complexity)]
+    fn admit(
+        &self,
+        peer_id: &str,
+        nonce: &str,
+        sig: &Signature,
+        addr: SocketAddr,
+    ) -> Result<
+        (
+            u64,
+            mpsc::Sender<Frame>,
+            mpsc::Receiver<Frame>,
+            Arc<PeerStats>,
+        ),
+        String,
+    > {
+        verify_peer_signature(peer_id, challenge_digest(nonce), sig)?;
+        let allowed = &self.inner.config.allowed_peers;
+        if !allowed.is_empty() && !allowed.iter().any(|p| p == peer_id) {
+            return Err(format!("{} is not allowed to use the relay node", peer_id));
+        }
+        let mut peers = self.inner.peers.lock().unwrap();
+        // a peer connecting again replaces its previous connection
+        if peers.len() >= self.inner.config.max_peers && !peers.contains_key(peer_id) {
+            return Err(String::from("the relay node is full"));
+        }
+        let conn = self.inner.next_conn.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(QUEUE_LEN);
+        let stats = Arc::new(PeerStats::default());
+        peers.insert(
+            peer_id.to_owned(),
+            Connected {
+                conn,
+                addr,
+                connected_at: get_utc_timestamp(),
+                tx: tx.clone(),
+                stats: stats.clone(),
+            },
+        );
+        Ok((conn, tx, rx, stats))
+    }
+
+    // Deliver `data` of `from` to the peer `to` once the bandwidth caps allow it, telling the
+    // sender of a request if `to` is not connected.
+    #[allow(clippy::too_many_arguments)]
+    async fn forward(
+        &self,
+        from: &str,
+        to: String,
+        data: RelayMessage,
+        len: usize,
+        bucket: &mut Bucket,
+        sender: &mpsc::Sender<Frame>,
+        stats: &PeerStats,
+    ) {
+        let now = Instant::now();
+        let wait = bucket
+            .take(len, now)
+            .max(self.inner.total.lock().unwrap().take(len, now));
+        if !wait.is_zero() {
+            let ms = wait.as_millis() as u64;
+            stats.throttled_ms.fetch_add(ms, Ordering::Relaxed);
+            self.inner.throttled_ms.fetch_add(ms, Ordering::Relaxed);
+            sleep(wait).await;
+        }
+
+        let request = match &data {
+            RelayMessage::Request { id, .. } => Some(*id),
+            RelayMessage::Response { .. } => None,
+        };
+        let target = self
+            .inner
+            .peers
+            .lock()
+            .unwrap()
+            .get(&to)
+            .map(|c| (c.tx.clone(), c.stats.clone()));
+        let result = match target {
+            Some((tx, target_stats)) => {
+                let frame = Frame::Deliver {
+                    from: from.to_owned(),
+                    data,
+                };
+                match tx.try_send(frame) {
+                    Ok(()) => {
+                        stats.sent.fetch_add(len as u64, Ordering::Relaxed);
+                        target_stats
+                            .received
+                            .fetch_add(len as u64, Ordering::Relaxed);
+                        self.inner.bytes.fetch_add(len as u64, Ordering::Relaxed);
+                        0
+                    }
+                    Err(_) => 2,
+                }
+            }
+            None => 1,
+        };
+        self.inner.frames[result].fetch_add(1, Ordering::Relaxed);
+        if let (1 | 2, Some(id)) = (result, request) {
+            let _ = sender.try_send(Frame::Unreachable { id });
+        }
+    }
+}
+
+/// The connections of the local peer to the relay nodes of `p2p.relay_nodes`, cheap to clone.
+#[derive(Clone)]
+pub struct RelayClient {
+    inner: Arc<ClientInner>,
+}
+
+struct ClientInner {
+    peer_id: String,
+    keypair: Keypair,
+    nodes: Vec<String>,
+    dht: OnceLock<Dht>,
+    // the relay nodes connected to, in the order of `nodes`
+    connections: Mutex<Vec<(usize, mpsc::Sender<Frame>)>>,
+    // the requests sent, with the peer they were sent to
+    requests: Mutex<HashMap<u64, (String, oneshot::Sender<Option<DhtResponse>>)>>,
+    next_id: AtomicU64,
+    // messages answered through a relay node, and sent to peers none of them reached
+    messages: [AtomicU64; 2],
+}
+
+impl RelayClient {
+    pub fn new(peer_id: String, keypair: Keypair, nodes: Vec<String>) -> Self {
+        RelayClient {
+            inner: Arc::new(ClientInner {
+                peer_id,
+                keypair,
+                nodes,
+                dht: OnceLock::new(),
+                connections: Mutex::new(Vec::new()),
+                requests: Mutex::new(HashMap::new()),
+                next_id: AtomicU64::new(0),
+                messages: Default::default(),
+            }),
+        }
+    }
+
+    /// Connect to the relay nodes, answering the requests of the other peers with `dht`, and
+    /// connect again to the ones closing the connection.
+    pub fn start(&self, dht: Dht) {
+        if self.inner.dht.set(dht).is_err() {
+            return;
+        }
+        for index in 0..self.inner.nodes.len() {
+            let client = self.clone();
+            tokio::spawn(async move {
+                let node = &client.inner.nodes[index];
+                loop {
+                    if let Err(e) = client.connect(index).await {
+                        tracing::warn!("Connection to the relay node {} failed: {}", node, e);
+                    }
+                    client
+                        .inner
+                        .connections
+                        .lock()
+                        .unwrap()
+                        .retain(|(i, _)| *i != index);
+                    sleep(RECONNECT_INTERVAL).await;
+                }
+            });
+        }
+    }
+
+    /// The relay nodes connected to.
+    pub fn connected(&self) -> Vec<String> {
+        self.inner
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(i, _)| self.inner.nodes[*i].clone())
+            .collect()
+    }
+
+    /// Send `msg` to `peer` through the first relay node it is connected to as well.
+    pub async fn request(&self, peer: &str, msg: &DhtMessage) -> Result<DhtResponse, String> {
+        let connections = self.inner.connections.lock().unwrap().clone();
+        for (index, tx) in connections {
+            let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+            let (res_tx, res_rx) = oneshot::channel();
+            self.inner
+                .requests
+                .lock()
+                .unwrap()
+                .insert(id, (peer.to_owned(), res_tx));
+            let frame = Frame::Send {
+                to: peer.to_owned(),
+                data: RelayMessage::Request {
+                    id,
+                    msg: msg.clone(),
+                },
+            };
+            let res = match tx.send(frame).await {
+                Ok(()) => timeout(REQUEST_TIMEOUT, res_rx)
+                    .await
+                    .ok()
+                    .and_then(Result::ok),
+                Err(_) => None,
+            };
+            self.inner.requests.lock().unwrap().remove(&id);
+            match res {
+                Some(Some(res)) => {
+                    self.inner.messages[0].fetch_add(1, Ordering::Relaxed);
+                    return Ok(res);
+                }
+                Some(None) => {}
+                None => tracing::debug!(
+                    "{} did not answer through the relay node {}",
+                    peer,
+                    self.inner.nodes[index]
+                ),
+            }
+        }
+        self.inner.messages[1].fetch_add(1, Ordering::Relaxed);
+        Err(format!("{} is not reached through the relay nodes", peer))
+    }
+
+    /// The metrics of the connections to the relay nodes in the prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let mut res = String::new();
+        res.push_str(
+            "# HELP mega_p2p_relay_nodes_connected Relay nodes this instance is connected to.\n",
+        );
+        res.push_str("# TYPE mega_p2p_relay_nodes_connected gauge\n");
+        let _ = writeln!(
+            res,
+            "mega_p2p_relay_nodes_connected {}",
+            self.inner.connections.lock().unwrap().len()
+        );
+        res.push_str(
+            "# HELP mega_p2p_relay_messages_total Messages sent through the relay nodes, by whether a relay node reached the peer.\n",
+        );
+        res.push_str("# TYPE mega_p2p_relay_messages_total counter\n");
+        for (result, count) in ["delivered", "unreachable"]
+            .iter()
+            .zip(&self.inner.messages)
+        {
+            let _ = writeln!(
+                res,
+                "mega_p2p_relay_messages_total{{result=\"{}\"}} {}",
+                result,
+                count.load(Ordering::Relaxed)
+            );
+        }
+        res
+    }
+
+    // Connect to the relay node at `index` of the nodes and prove the peer id, then receive the
+    // messages of the other peers until the connection closes.
+    async fn connect(&self, index: usize) -> Result<(), String> {
+        let node = &self.inner.nodes[index];
+        let stream = timeout(HANDSHAKE_TIMEOUT, TcpStream::connect(node))
+            .await
+            .map_err(|_| String::from("connecting timed out"))?
+            .map_err(|e| e.to_string())?;
+        let (mut reader, mut writer) = stream.into_split();
+        let handshake = async {
+            let Frame::Challenge { nonce } = read_frame(&mut reader).await?.0 else {
+                return Err(String::from("no challenge"));
+            };
+            let sig = sign_without_rng(challenge_digest(&nonce).to_string(), &self.inner.keypair);
+            let hello = Frame::Hello {
+                peer_id: self.inner.peer_id.clone(),
+                sig,
+            };
+            write_frame(&mut writer, &hello).await?;
+            match read_frame(&mut reader).await?.0 {
+                Frame::Welcome => Ok(()),
+                Frame::Refused { reason } => Err(format!("refused: {}", reason)),
+                _ => Err(String::from("unexpected answer")),
+            }
+        };
+        timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .map_err(|_| String::from("handshake timed out"))??;
+        tracing::info!("Connected to the relay node {}", node);
+
+        let (tx, mut rx) = mpsc::channel(QUEUE_LEN);
+        {
+            let mut connections = self.inner.connections.lock().unwrap();
+            connections.push((index, tx.clone()));
+            connections.sort_by_key(|(i, _)| *i);
+        }
+        let write = async {
+            while let Some(frame) = rx.recv().await {
+                write_frame(&mut writer, &frame).await?;
+            }
+            Ok::<(), String>(())
+        };
+        let read = async {
+            loop {
+                match read_frame(&mut reader).await {
+                    Ok((frame, _)) => self.on_frame(frame, &tx),
+                    Err(e) => break Err::<(), String>(e),
+                }
+            }
+        };
+        tokio::select! {
+            res = write => res,
+            res = read => res,
+        }
+    }
+
+    fn on_frame(&self, frame: Frame, tx: &mpsc::Sender<Frame>) {
+        match frame {
+            Frame::Deliver {
+                from,
+                data: RelayMessage::Request { id, msg },
+            } => {
+                // the relay node checked the id of the sender
+                let Some(dht) = self.inner.dht.get().filter(|_| msg.sender == from).cloned() else {
+                    return;
+                };
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let res = dht.handle(msg).await;
+                    let frame = Frame::Send {
+                        to: from,
+                        data: RelayMessage::Response { id, res },
+                    };
+                    let _ = tx.send(frame).await;
+                });
+            }
+            Frame::Deliver {
+                from,
+                data: RelayMessage::Response { id, res },
+            } => {
+                let mut requests = self.inner.requests.lock().unwrap();
+                if requests.get(&id).is_some_and(|(peer, _)| *peer == from) {
+                    let (_, res_tx) = requests.remove(&id).unwrap();
+                    let _ = res_tx.send(Some(res));
+                }
+            }
+            Frame::Unreachable { id } => {
+                if let Some((_, res_tx)) = self.inner.requests.lock().unwrap().remove(&id) {
+                    let _ = res_tx.send(None);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Sends the messages of the DHT through a relay node the peer is connected to, and through
+/// `fallback` otherwise.
+pub struct RelayTransport {
+    pub client: RelayClient,
+    pub fallback: Box<dyn DhtTransport>,
+}
+
+#[async_trait]
+impl DhtTransport for RelayTransport {
+    async fn send(&self, peer_id: &str, msg: &DhtMessage) -> Result<DhtResponse, String> {
+        match self.client.request(peer_id, msg).await {
+            Ok(res) => Ok(res),
+            Err(_) => self.fallback.send(peer_id, msg).await,
+        }
+    }
+}
+
+// The sha256 a peer signs to prove its id to a relay node.
+fn challenge_digest(nonce: &str) -> sha256::Hash {
+    let data = json!(["mega-relay", nonce]).to_string();
+    sha256::Hash::hash(data.as_bytes())
+}
+
+async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), frame: &Frame) -> Result<(), String> {
+    let data = serde_json::to_vec(frame).unwrap();
+    writer
+        .write_all(&(data.len() as u32).to_be_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    writer.write_all(&data).await.map_err(|e| e.to_string())
+}
+
+// A frame and its length.
+async fn read_frame(reader: &mut (impl AsyncRead + Unpin)) -> Result<(Frame, usize), String> {
+    let len = reader.read_u32().await.map_err(|e| e.to_string())? as usize;
+    if len > MAX_FRAME {
+        return Err(format!("frame of {} bytes is too large", len));
+    }
+    let mut data = vec![0; len];
+    reader
+        .read_exact(&mut data)
+        .await
+        .map_err(|e| e.to_string())?;
+    let frame = serde_json::from_slice(&data).map_err(|e| e.to_string())?;
+    Ok((frame, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use common::config::{DhtConfig, RelayConfig};
+    use secp256k1::{rand, Keypair, Secp256k1};
+    use tokio::net::TcpListener;
+    use tokio::time::{sleep, Instant};
+
+    use super::{RelayClient, RelayServer};
+    use crate::dht::{Dht, DhtMessage, DhtRequest, DhtResponse, DhtTransport};
+
+    struct Offline;
+
+    #[async_trait]
+    impl DhtTransport for Offline {
+        async fn send(&self, _: &str, _: &DhtMessage) -> Result<DhtResponse, String> {
+            Err(String::from("offline"))
+        }
+    }
+
+    fn peer(node: &str) -> (String, RelayClient) {
+        let keypair = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+        let peer_id = bs58::encode(keypair.public_key().serialize()).into_string();
+        let client = RelayClient::new(peer_id.clone(), keypair, vec![node.to_owned()]);
+        let dht = Dht::new(peer_id.clone(), keypair, DhtConfig::default(), Offline);
+        client.start(dht);
+        (peer_id, client)
+    }
+
+    async fn wait_connected(client: &RelayClient) -> bool {
+        for _ in 0..50 {
+            if !client.connected().is_empty() {
+                return true;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn test_relay() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let node = listener.local_addr().unwrap().to_string();
+        let (a_id, a) = peer(&node);
+        let (b_id, b) = peer(&node);
+        let config = RelayConfig {
+            allowed_peers: vec![a_id.clone(), b_id.clone()],
+            ..Default::default()
+        };
+        let relay = RelayServer::new(config);
+        tokio::spawn(relay.clone().serve(listener));
+        assert!(wait_connected(&a).await);
+        assert!(wait_connected(&b).await);
+
+        let msg = DhtMessage {
+            sender: a_id.clone(),
+            request: DhtRequest::Ping,
+        };
+        assert!(matches!(
+            a.request(&b_id, &msg).await,
+            Ok(DhtResponse::Pong)
+        ));
+        let status = relay.status();
+        assert_eq!(status.peers.len(), 2);
+        assert!(status.bytes_forwarded > 0);
+
+        // a peer not connected to the relay node is unreachable right away
+        let start = Instant::now();
+        assert!(a.request("unknown", &msg).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        // requests of another sender than the connected peer are not answered
+        let forged = DhtMessage {
+            sender: b_id.clone(),
+            request: DhtRequest::Ping,
+        };
+        assert!(a.request(&b_id, &forged).await.is_err());
+
+        // peers not allowed are refused
+        let (_, c) = peer(&node);
+        assert!(!wait_connected(&c).await);
+        assert!(relay
+            .render_metrics()
+            .contains("mega_relay_refused_total 1\n"));
+    }
+}
@@ -0,0 +1,548 @@
+//! Publishing repositories, or directories of the monorepo, to the peers of the ztm network and
+//! subscribing to the ones of other peers.
+//!
+//! The peer publishing a repository is its origin, and signs a [`RefsAnnouncement`] of the refs
+//! of the repository whenever another peer asks for them at `/api/v1/mega/ztm/refs`. A peer
+//! subscribed to the repository mirrors it below `monorepo.import_dir`, and every
+//! `p2p.sync_interval` seconds asks the origin and the peers providing the repository in the
+//! DHT for their announcement. When the newest one has other refs, the packfiles are fetched
+//! over the ztm tunnel from a peer serving these refs, and the mirror is only updated if every
+//! ref fetched points to the hash announced by the origin. Once synced, the subscribed peer
+//! provides the repository as well, serving the announcement it was synced to, so the origin
+//! does not have to be online for the repository to spread.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use callisto::{ztm_path_mapping, ztm_subscription};
+use ceres::protocol::{ServiceType, SmartProtocol, TransportProtocol};
+use common::utils::{generate_id, ZERO_ID};
+use jupiter::context::Context;
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::schnorr::Signature;
+use secp256k1::Keypair;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::process::Command;
+
+use crate::dht::Dht;
+use crate::http::handler::{get_alias_from_identifier, get_peer_id_from_identifier};
+use crate::nostr::event::sign_without_rng;
+use crate::util::{
+    get_git_model_by_path, get_utc_timestamp, repo_alias_to_identifier, verify_peer_signature,
+};
+use crate::ztm::{get_or_create_remote_mega_tunnel, send_post_request_to_peer_by_tunnel};
+
+/// Path of the mega api the peers post their [`RefsQuery`]s to.
+pub const REFS_PATH: &str = "api/v1/mega/ztm/refs";
+
+/// The refs of a repository at a point in time, signed by the peer which published it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RefsAnnouncement {
+    pub identifier: String,
+    /// Hashes of the refs by their name
+    pub refs: BTreeMap<String, String>,
+    /// Milliseconds since the epoch the refs were read at
+    pub created_at: i64,
+    pub sig: Signature,
+}
+
+impl RefsAnnouncement {
+    /// An announcement of the refs of the repository of `identifier`, `keypair` is the one of its
+    /// origin peer.
+    pub fn new(identifier: &str, refs: BTreeMap<String, String>, keypair: &Keypair) -> Self {
+        let created_at = get_utc_timestamp();
+        let sig = sign_without_rng(digest(identifier, &refs, created_at).to_string(), keypair);
+        RefsAnnouncement {
+            identifier: identifier.to_owned(),
+            refs,
+            created_at,
+            sig,
+        }
+    }
+
+    /// Check the announcement was signed by the origin peer of its repository.
+    pub fn verify(&self) -> Result<(), String> {
+        let origin = get_peer_id_from_identifier(self.identifier.clone())?;
+        let hash = digest(&self.identifier, &self.refs, self.created_at);
+        verify_peer_signature(&origin, hash, &self.sig)
+    }
+}
+
+// The sha256 of the signed fields of an announcement.
+fn digest(identifier: &str, refs: &BTreeMap<String, String>, created_at: i64) -> sha256::Hash {
+    let data = json!([identifier, refs, created_at]).to_string();
+    sha256::Hash::hash(data.as_bytes())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefsQuery {
+    pub identifier: String,
+}
+
+/// The answer of a peer providing a repository: the announcement of the origin it serves and
+/// the path the repository can be fetched from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RefsResponse {
+    pub announcement: RefsAnnouncement,
+    pub path: String,
+}
+
+/// A repository of another peer the local one is subscribed to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Subscription {
+    pub identifier: String,
+    pub local_path: String,
+    /// The announcement the mirror was last synced to
+    pub announcement: Option<RefsAnnouncement>,
+    /// Milliseconds since the epoch of the last sync which succeeded
+    pub synced_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+impl From<ztm_subscription::Model> for Subscription {
+    fn from(s: ztm_subscription::Model) -> Self {
+        Subscription {
+            identifier: s.identifier,
+            local_path: s.local_path,
+            announcement: s
+                .announcement
+                .and_then(|announcement| serde_json::from_str(&announcement).ok()),
+            synced_at: s.synced_at.map(|t| t.and_utc().timestamp_millis()),
+            last_error: s.last_error,
+        }
+    }
+}
+
+/// Publish the repository or monorepo directory at `path` as `alias`, returns its identifier.
+pub async fn publish(context: &Context, path: &str, alias: &str) -> Result<String, String> {
+    if alias.is_empty() || alias.contains('/') {
+        return Err(format!("invalid alias {}", alias));
+    }
+    if local_refs(context, path).await?.is_empty() {
+        return Err(format!("{} has no refs to publish", path));
+    }
+    let storage = context.services.ztm_storage.clone();
+    match storage
+        .get_path_from_alias(alias)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        Some(mapping) if mapping.repo_path == path => {}
+        Some(mapping) => {
+            return Err(format!(
+                "alias {} is used by {} already",
+                alias, mapping.repo_path
+            ))
+        }
+        None => {
+            let now = chrono::Utc::now().naive_utc();
+            storage
+                .save_alias_mapping(ztm_path_mapping::Model {
+                    id: generate_id(),
+                    alias: alias.to_owned(),
+                    repo_path: path.to_owned(),
+                    created_at: now,
+                    updated_at: now,
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(repo_alias_to_identifier(alias.to_owned()))
+}
+
+/// Subscribe to the repository of `identifier`, mirrored at `local_path`, or at its alias below
+/// `monorepo.import_dir` if not given. It is fetched by the next sync.
+pub async fn subscribe(
+    context: &Context,
+    identifier: &str,
+    local_path: Option<String>,
+) -> Result<ztm_subscription::Model, String> {
+    if !identifier.starts_with("p2p://") {
+        return Err(format!("invalid identifier {}", identifier));
+    }
+    let origin = get_peer_id_from_identifier(identifier.to_owned())?;
+    let alias = get_alias_from_identifier(identifier.to_owned())?;
+    if origin == vault::get_peerid() {
+        return Err(String::from("the repository is published by this peer"));
+    }
+    let import_dir = context.config.monorepo.import_dir.clone();
+    let local_path = match local_path {
+        Some(path) => PathBuf::from(path),
+        None => import_dir.join(alias),
+    };
+    if !local_path.starts_with(&import_dir) {
+        return Err(format!(
+            "{} is not below {}",
+            local_path.display(),
+            import_dir.display()
+        ));
+    }
+    let local_path = local_path.to_str().unwrap().to_owned();
+    let storage = context.services.ztm_storage.clone();
+    let subscriptions = storage
+        .get_all_subscriptions()
+        .await
+        .map_err(|e| e.to_string())?;
+    if subscriptions.iter().any(|s| s.identifier == identifier) {
+        return Err(format!("already subscribed to {}", identifier));
+    }
+    if subscriptions.iter().any(|s| s.local_path == local_path)
+        || get_git_model_by_path(context.clone(), local_path.clone())
+            .await
+            .is_some()
+    {
+        return Err(format!("{} exists already", local_path));
+    }
+    let model = ztm_subscription::Model {
+        id: generate_id(),
+        identifier: identifier.to_owned(),
+        local_path,
+        announcement: None,
+        synced_at: None,
+        last_error: None,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    storage
+        .save_subscription(model.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(model)
+}
+
+/// Stop syncing and providing the repository of `identifier`, its mirror is kept.
+pub async fn unsubscribe(
+    context: &Context,
+    dht: Option<&Dht>,
+    identifier: &str,
+) -> Result<(), String> {
+    let storage = context.services.ztm_storage.clone();
+    let subscription = storage
+        .get_subscription(identifier)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("not subscribed to {}", identifier))?;
+    storage
+        .delete_subscription(identifier)
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(dht) = dht {
+        dht.forget(identifier);
+    }
+    let _ = tokio::fs::remove_dir_all(fetch_dir(context, &subscription)).await;
+    Ok(())
+}
+
+/// The announcement and path of the repository of `identifier` served to other peers: the
+/// current refs signed by the local peer if it is the origin, otherwise the announcement its
+/// mirror was synced to.
+pub async fn serve_refs(context: &Context, identifier: &str) -> Result<RefsResponse, String> {
+    let origin = get_peer_id_from_identifier(identifier.to_owned())?;
+    let storage = context.services.ztm_storage.clone();
+    if origin == vault::get_peerid() {
+        let alias = get_alias_from_identifier(identifier.to_owned())?;
+        let mapping = storage
+            .get_path_from_alias(&alias)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("{} is not published", identifier))?;
+        let refs = local_refs(context, &mapping.repo_path).await?;
+        Ok(RefsResponse {
+            announcement: RefsAnnouncement::new(identifier, refs, &vault::get_keypair()),
+            path: mapping.repo_path,
+        })
+    } else {
+        let subscription = storage
+            .get_subscription(identifier)
+            .await
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("not subscribed to {}", identifier))?;
+        let announcement = subscription
+            .announcement
+            .ok_or_else(|| format!("{} is not synced yet", identifier))?;
+        Ok(RefsResponse {
+            announcement: serde_json::from_str(&announcement).map_err(|e| e.to_string())?,
+            path: subscription.local_path,
+        })
+    }
+}
+
+// The refs of the repository or monorepo directory at `path` the peers can fetch.
+async fn local_refs(context: &Context, path: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut protocol =
+        SmartProtocol::new(PathBuf::from(path), context.clone(), TransportProtocol::P2p);
+    protocol.service_type = Some(ServiceType::UploadPack);
+    let handler = protocol.pack_handler().await.map_err(|e| e.to_string())?;
+    let (_, refs) = handler.head_hash().await;
+    Ok(refs
+        .into_iter()
+        .filter(|r| r.ref_hash != ZERO_ID)
+        .map(|r| (r.ref_name, r.ref_hash))
+        .collect())
+}
+
+/// Sync the mirrors of the subscribed repositories every `p2p.sync_interval` seconds.
+pub fn start(context: Context, agent_port: u16, http_port: u16, dht: Dht) {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(context.config.p2p.sync_interval);
+        loop {
+            tokio::time::sleep(interval).await;
+            let subscriptions = match context.services.ztm_storage.get_all_subscriptions().await {
+                Ok(subscriptions) => subscriptions,
+                Err(e) => {
+                    tracing::error!("Failed to list the subscriptions: {}", e);
+                    continue;
+                }
+            };
+            for subscription in subscriptions {
+                sync(&context, agent_port, http_port, Some(&dht), subscription).await;
+            }
+        }
+    });
+}
+
+/// Sync the mirror of `subscription` to the newest announcement of its origin, and record the
+/// result. The repository is provided in the DHT once it was synced.
+pub async fn sync(
+    context: &Context,
+    agent_port: u16,
+    http_port: u16,
+    dht: Option<&Dht>,
+    subscription: ztm_subscription::Model,
+) {
+    let identifier = subscription.identifier.clone();
+    let res = sync_mirror(context, agent_port, http_port, dht, &subscription).await;
+    match &res {
+        Ok(announcement) => {
+            tracing::info!("Synced {} to {}", identifier, announcement.created_at)
+        }
+        Err(e) => tracing::warn!("Failed to sync {}: {}", identifier, e),
+    }
+    let synced = res.is_ok();
+    let res = res.map(|announcement| serde_json::to_string(&announcement).unwrap());
+    if let Err(e) = context
+        .services
+        .ztm_storage
+        .update_subscription_sync(subscription.id, res)
+        .await
+    {
+        tracing::error!("Failed to record the sync of {}: {}", identifier, e);
+    }
+    if let (true, None, Some(dht)) = (synced, subscription.synced_at, dht) {
+        let stored = dht.provide(&identifier).await;
+        tracing::info!("Published {} to {} peers of the DHT", identifier, stored);
+    }
+}
+
+// Fetch the newest announced refs into the mirror, returns the announcement it is at.
+async fn sync_mirror(
+    context: &Context,
+    agent_port: u16,
+    http_port: u16,
+    dht: Option<&Dht>,
+    subscription: &ztm_subscription::Model,
+) -> Result<RefsAnnouncement, String> {
+    let identifier = &subscription.identifier;
+    let local_peer_id = vault::get_peerid();
+    let mut peers = vec![get_peer_id_from_identifier(identifier.clone())?];
+    if let Some(dht) = dht {
+        for record in dht.find_providers(identifier).await {
+            if record.peer_id != local_peer_id && !peers.contains(&record.peer_id) {
+                peers.push(record.peer_id);
+            }
+        }
+    }
+
+    let query = serde_json::to_string(&RefsQuery {
+        identifier: identifier.clone(),
+    })
+    .unwrap();
+    let mut served = Vec::new();
+    for peer in peers {
+        let res = send_post_request_to_peer_by_tunnel(
+            agent_port,
+            peer.clone(),
+            REFS_PATH.to_owned(),
+            query.clone(),
+        )
+        .await
+        .and_then(|res| serde_json::from_str::<RefsResponse>(&res).map_err(|e| e.to_string()));
+        match res {
+            Ok(res) if res.announcement.identifier != *identifier => {
+                tracing::warn!("{} announced another repository", peer)
+            }
+            Ok(res) => match res.announcement.verify() {
+                Ok(()) => served.push((peer, res)),
+                Err(e) => tracing::warn!("Announcement of {} rejected: {}", peer, e),
+            },
+            Err(e) => tracing::debug!("Failed to get the refs of {}: {}", peer, e),
+        }
+    }
+    let newest = served
+        .iter()
+        .map(|(_, res)| &res.announcement)
+        .max_by_key(|announcement| announcement.created_at)
+        .cloned()
+        .ok_or_else(|| format!("no peer providing {} answered", identifier))?;
+
+    let current: Option<RefsAnnouncement> = subscription
+        .announcement
+        .as_ref()
+        .and_then(|announcement| serde_json::from_str(announcement).ok());
+    match current {
+        Some(current) if current.created_at >= newest.created_at => return Ok(current),
+        Some(current) if current.refs == newest.refs => return Ok(newest),
+        _ => {}
+    }
+
+    // any peer serving the same refs has the objects, the origin is asked first
+    let dir = fetch_dir(context, subscription);
+    let mut errors = Vec::new();
+    for (peer, res) in served
+        .iter()
+        .filter(|(_, res)| res.announcement.refs == newest.refs)
+    {
+        match fetch(agent_port, peer, &res.path, &newest.refs, &dir).await {
+            Ok(()) => {
+                let url = format!("http://localhost:{}{}", http_port, subscription.local_path);
+                git(
+                    &dir,
+                    &[
+                        "push",
+                        "--prune",
+                        &url,
+                        "+refs/heads/*:refs/heads/*",
+                        "+refs/tags/*:refs/tags/*",
+                    ],
+                )
+                .await?;
+                return Ok(newest);
+            }
+            Err(e) => errors.push(format!("{}: {}", peer, e)),
+        }
+    }
+    Err(errors.join("; "))
+}
+
+// The bare repository the refs of a subscription are fetched into before being pushed to its
+// mirror.
+fn fetch_dir(context: &Context, subscription: &ztm_subscription::Model) -> PathBuf {
+    context
+        .config
+        .base_dir
+        .join("p2p")
+        .join(format!("{}.git", subscription.id))
+}
+
+// Fetch `refs` from the repository at `path` of `peer`, and check each of them points to the
+// announced hash. The objects are checked while fetched, so a ref can only point to its hash if
+// the objects it reaches are the ones of the origin.
+async fn fetch(
+    agent_port: u16,
+    peer: &str,
+    path: &str,
+    refs: &BTreeMap<String, String>,
+    dir: &Path,
+) -> Result<(), String> {
+    if !dir.exists() {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| e.to_string())?;
+        git(dir, &["init", "--bare"]).await?;
+    }
+    let local_port = get_or_create_remote_mega_tunnel(agent_port, peer.to_owned()).await?;
+    let url = format!("http://localhost:{local_port}{path}.git");
+    let refspecs: Vec<String> = refs.keys().map(|name| format!("+{name}:{name}")).collect();
+    let mut args = vec![
+        "-c",
+        "transfer.fsckObjects=true",
+        "fetch",
+        "--no-tags",
+        &url,
+    ];
+    args.extend(refspecs.iter().map(String::as_str));
+    git(dir, &args).await?;
+
+    for (name, hash) in refs {
+        let fetched = git(dir, &["rev-parse", "--verify", name]).await?;
+        if fetched != *hash {
+            return Err(format!(
+                "{} is at {}, {} was announced",
+                name, fetched, hash
+            ));
+        }
+    }
+    // the refs deleted by the origin are deleted from the mirror by the push
+    for name in git(dir, &["for-each-ref", "--format=%(refname)"])
+        .await?
+        .lines()
+    {
+        if !refs.contains_key(name) {
+            git(dir, &["update-ref", "-d", name]).await?;
+        }
+    }
+    Ok(())
+}
+
+// Run git in `dir`, returns its trimmed output.
+async fn git(dir: &Path, args: &[&str]) -> Result<String, String> {
+    tracing::debug!("Exec: git {}", args.join(" "));
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute process: {}", e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use secp256k1::{rand, Keypair, Secp256k1};
+
+    use super::RefsAnnouncement;
+
+    #[test]
+    fn test_refs_announcement() {
+        let keypair = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+        let peer_id = bs58::encode(keypair.public_key().serialize()).into_string();
+        let identifier = format!("p2p://{}/repo", peer_id);
+        let refs = BTreeMap::from([(
+            String::from("refs/heads/main"),
+            String::from("8ab686eafeb1f44702738c8b0f24f2567c36da6d"),
+        )]);
+        let announcement = RefsAnnouncement::new(&identifier, refs, &keypair);
+        assert!(announcement.verify().is_ok());
+        let json = serde_json::to_string(&announcement).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RefsAnnouncement>(&json).unwrap(),
+            announcement
+        );
+
+        let mut moved = announcement.clone();
+        moved.refs.insert(
+            String::from("refs/heads/main"),
+            String::from("0b3b6b36a8e1c5d3b1d8c1e6f3a7b1c0d9e8f7a6"),
+        );
+        assert!(moved.verify().is_err());
+        // only the origin of the repository can announce its refs
+        let other = Keypair::new(&Secp256k1::new(), &mut rand::thread_rng());
+        let forged = RefsAnnouncement::new(&identifier, announcement.refs.clone(), &other);
+        assert!(forged.verify().is_err());
+        let mut replayed = announcement.clone();
+        replayed.identifier = String::from("p2p://other/repo");
+        assert!(replayed.verify().is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use callisto::git_repo;
 use jupiter::context::Context;
+use secp256k1::hashes::{sha256, Hash};
+use secp256k1::schnorr::Signature;
+use secp256k1::{Message, PublicKey, Secp256k1};
 use std::{
     net::TcpListener,
     time::{SystemTime, UNIX_EPOCH},
@@ -23,6 +26,24 @@ pub fn get_available_port() -> Result<u16, String> {
     }
 }
 
+/// Check `sig` is a signature of `hash` by the key of `peer_id`, the bs58 of its public key.
+pub fn verify_peer_signature(
+    peer_id: &str,
+    hash: sha256::Hash,
+    sig: &Signature,
+) -> Result<(), String> {
+    let public_key = bs58::decode(peer_id)
+        .into_vec()
+        .ok()
+        .and_then(|bytes| PublicKey::from_slice(&bytes).ok())
+        .ok_or_else(|| format!("invalid peer id {}", peer_id))?;
+    let (public_key, _) = public_key.x_only_public_key();
+    let message = Message::from_digest(hash.to_byte_array());
+    Secp256k1::verification_only()
+        .verify_schnorr(sig, message.as_ref(), &public_key)
+        .map_err(|_| format!("invalid signature of {}", peer_id))
+}
+
 pub fn get_utc_timestamp() -> i64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
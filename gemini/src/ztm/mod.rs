@@ -1,6 +1,7 @@
 use agent::{LocalZTMAgent, ZTMAgent};
 use reqwest::{header::CONTENT_TYPE, Client};
 
+use crate::noise;
 use crate::util::{get_available_port, get_ztm_app_tunnel_bound_name, handle_response};
 
 pub mod agent;
@@ -74,12 +75,27 @@ async fn create_tunnel(
     Ok(())
 }
 
+/// The local port the mega http server of `remote_peer_id` is reached at, through an encrypted
+/// channel if the tunnels are encrypted, see [`crate::noise`].
 pub async fn get_or_create_remote_mega_tunnel(
     ztm_agent_port: u16,
     remote_peer_id: String,
 ) -> Result<u16, String> {
+    if let Some(channels) = noise::channels() {
+        return channels.local_port(ztm_agent_port, &remote_peer_id).await;
+    }
     let bound_name = get_ztm_app_tunnel_bound_name(remote_peer_id.clone());
+    get_or_create_tunnel(ztm_agent_port, remote_peer_id, bound_name, 8000).await
+}
 
+/// The local port of the tunnel `bound_name` to `remote_port` of `remote_peer_id`, created if
+/// it does not exist.
+pub(crate) async fn get_or_create_tunnel(
+    ztm_agent_port: u16,
+    remote_peer_id: String,
+    bound_name: String,
+    remote_port: u16,
+) -> Result<u16, String> {
     //Check if the tunnel exists
     let local_port = search_tunnel_inbound_port(ztm_agent_port, bound_name.clone()).await;
 
@@ -98,7 +114,6 @@ pub async fn get_or_create_remote_mega_tunnel(
                     return Err(e);
                 }
             };
-            let remote_port = 8000;
             match create_tunnel(
                 ztm_agent_port,
                 remote_peer_id.clone(),
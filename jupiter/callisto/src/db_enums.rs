@@ -3,8 +3,9 @@ use std::fmt::Display;
 
 use sea_orm::prelude::StringLen;
 use sea_orm::{DeriveActiveEnum, EnumIter};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
 #[sea_orm(
     rs_type = "String",
     db_type = "String(StringLen::None)",
@@ -14,6 +15,8 @@ pub enum StorageType {
     Database,
     LocalFs,
     RemoteUrl,
+    /// Split into the chunks listed in `raw_blob_chunk`
+    Chunked,
 }
 
 impl fmt::Display for StorageType {
@@ -22,6 +25,7 @@ impl fmt::Display for StorageType {
             StorageType::Database => write!(f, "database"),
             StorageType::LocalFs => write!(f, "local_fs"),
             StorageType::RemoteUrl => write!(f, "remote_url"),
+            StorageType::Chunked => write!(f, "chunked"),
         }
     }
 }
@@ -49,7 +53,7 @@ impl Display for MergeStatus {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(
     rs_type = "String",
     db_type = "String(StringLen::None)",
@@ -98,3 +102,499 @@ impl Display for ConvType {
         write!(f, "{}", s)
     }
 }
+
+#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+pub enum OrgRole {
+    Owner,
+    Member,
+}
+
+impl Display for OrgRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            OrgRole::Owner => "owner",
+            OrgRole::Member => "member",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+pub enum TeamPermission {
+    Admin,
+    Maintainer,
+    Reader,
+}
+
+impl Display for TeamPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TeamPermission::Admin => "admin",
+            TeamPermission::Maintainer => "maintainer",
+            TeamPermission::Reader => "reader",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, EnumIter, DeriveActiveEnum, Copy, Default, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// Readable by anyone, including anonymous users
+    #[default]
+    Public,
+    /// Readable by any signed in user
+    Internal,
+    /// Readable only by users granted access through saturn policies
+    Private,
+}
+
+impl Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Visibility::Public => "public",
+            Visibility::Internal => "internal",
+            Visibility::Private => "private",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Background jobs run by the maintenance scheduler
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceTask {
+    /// Prebuild the packs served for full clones
+    Repack,
+    /// Compute generation numbers of commits
+    CommitGraph,
+    /// Index the objects reachable from each prebuilt pack
+    Bitmap,
+    /// Remove LFS objects which are no longer known to the database
+    LfsGc,
+    /// Remove expired redirects, old job history and leftover decode cache
+    StaleCleanup,
+    /// Verify object hashes, ref reachability and object store consistency of a repository
+    Verify,
+    /// Report or delete branches without activity
+    BranchCleanup,
+    /// Rewrite the history of monorepo directories into their split repositories
+    SubtreeSplit,
+    /// Permanently remove repositories whose retention period in the trash is over
+    RepoPurge,
+    /// Report or delete objects no ref can reach
+    ObjectGc,
+    /// Remove temporary role assignments which expired
+    GrantExpiry,
+    /// Update the members of teams mapped to groups of the identity provider
+    IdpSync,
+}
+
+impl Display for MaintenanceTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MaintenanceTask::Repack => "repack",
+            MaintenanceTask::CommitGraph => "commit_graph",
+            MaintenanceTask::Bitmap => "bitmap",
+            MaintenanceTask::LfsGc => "lfs_gc",
+            MaintenanceTask::StaleCleanup => "stale_cleanup",
+            MaintenanceTask::Verify => "verify",
+            MaintenanceTask::BranchCleanup => "branch_cleanup",
+            MaintenanceTask::SubtreeSplit => "subtree_split",
+            MaintenanceTask::RepoPurge => "repo_purge",
+            MaintenanceTask::ObjectGc => "object_gc",
+            MaintenanceTask::GrantExpiry => "grant_expiry",
+            MaintenanceTask::IdpSync => "idp_sync",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum JobTrigger {
+    Schedule,
+    Manual,
+}
+
+impl Display for JobTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            JobTrigger::Schedule => "schedule",
+            JobTrigger::Manual => "manual",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretAction {
+    /// The push was refused
+    Rejected,
+    /// The ref updates were held back for review
+    Quarantined,
+    /// The push was accepted
+    Audited,
+}
+
+impl Display for SecretAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SecretAction::Rejected => "rejected",
+            SecretAction::Quarantined => "quarantined",
+            SecretAction::Audited => "audited",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingStatus {
+    Open,
+    /// Not a secret, the blob is no longer reported by later scans
+    FalsePositive,
+    /// The secret was revoked or removed
+    Resolved,
+}
+
+impl Display for FindingStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            FindingStatus::Open => "open",
+            FindingStatus::FalsePositive => "false_positive",
+            FindingStatus::Resolved => "resolved",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageState {
+    /// Waiting to be processed
+    Pending,
+    /// Being processed, retried once its visibility timeout is over
+    Inflight,
+    /// Processing failed, retried at `visible_at`
+    Failed,
+    /// Processing failed too often, only retried when requeued
+    Dead,
+    Done,
+}
+
+impl Display for MessageState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MessageState::Pending => "pending",
+            MessageState::Inflight => "inflight",
+            MessageState::Failed => "failed",
+            MessageState::Dead => "dead",
+            MessageState::Done => "done",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Lane of a queued message, each lane is processed with a concurrency of its own so
+/// user-facing events are not held up behind bulk ones.
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageLane {
+    /// Events of user-facing operations, like merge request updates
+    Interactive,
+    /// Bulk events, like the records of api requests
+    Background,
+}
+
+impl Display for MessageLane {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MessageLane::Interactive => "interactive",
+            MessageLane::Background => "background",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SigningKeyType {
+    /// An armored OpenPGP public key
+    Gpg,
+    /// An OpenSSH public key
+    Ssh,
+}
+
+impl Display for SigningKeyType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SigningKeyType::Gpg => "gpg",
+            SigningKeyType::Ssh => "ssh",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// Signed by a signing key of the user with the email of the committer or tagger
+    Verified,
+    /// Signed, but by no signing key of that user or with a broken signature
+    Unverified,
+    Unsigned,
+}
+
+impl Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SignatureStatus::Verified => "verified",
+            SignatureStatus::Unverified => "unverified",
+            SignatureStatus::Unsigned => "unsigned",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundJobState {
+    /// Waiting to be run at `scheduled_at`
+    Queued,
+    /// Claimed by a worker, claimed again once its lease is over in case the worker stopped
+    Running,
+    Done,
+    /// Failed too often, not run again
+    Failed,
+}
+
+impl Display for BackgroundJobState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BackgroundJobState::Queued => "queued",
+            BackgroundJobState::Running => "running",
+            BackgroundJobState::Done => "done",
+            BackgroundJobState::Failed => "failed",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeType {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl Display for ChangeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ChangeType::Added => "added",
+            ChangeType::Modified => "modified",
+            ChangeType::Deleted => "deleted",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Mergeability {
+    Mergeable,
+    /// The merge request and its target changed the same paths differently
+    Conflicting,
+}
+
+impl Display for Mergeability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Mergeability::Mergeable => "mergeable",
+            Mergeability::Conflicting => "conflicting",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RoleSubject {
+    User,
+    /// Named `org/team`
+    Team,
+    Organization,
+    /// Service account, named without the `[bot]` suffix
+    Bot,
+}
+
+impl Display for RoleSubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RoleSubject::User => "user",
+            RoleSubject::Team => "team",
+            RoleSubject::Organization => "organization",
+            RoleSubject::Bot => "bot",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(
+    Clone, Debug, PartialEq, Eq, Hash, EnumIter, DeriveActiveEnum, Copy, Serialize, Deserialize,
+)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(StringLen::None)",
+    rename_all = "snake_case"
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RepoRole {
+    Guest,
+    Reporter,
+    Developer,
+    Maintainer,
+    Owner,
+}
+
+impl Display for RepoRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RepoRole::Guest => "guest",
+            RepoRole::Reporter => "reporter",
+            RepoRole::Developer => "developer",
+            RepoRole::Maintainer => "maintainer",
+            RepoRole::Owner => "owner",
+        };
+        write!(f, "{}", s)
+    }
+}
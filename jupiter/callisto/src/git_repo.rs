@@ -13,9 +13,54 @@ pub struct Model {
     pub repo_name: String,
     pub created_at: DateTime,
     pub updated_at: DateTime,
+    /// Set while the repository is in the trash
+    pub deleted_at: Option<DateTime>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub deleted_by: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::git_blob::Entity")]
+    GitBlob,
+    #[sea_orm(has_many = "super::git_commit::Entity")]
+    GitCommit,
+    #[sea_orm(has_many = "super::git_tag::Entity")]
+    GitTag,
+    #[sea_orm(has_many = "super::git_tree::Entity")]
+    GitTree,
+    #[sea_orm(has_many = "super::import_refs::Entity")]
+    ImportRefs,
+}
+
+impl Related<super::git_blob::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::GitBlob.def()
+    }
+}
+
+impl Related<super::git_commit::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::GitCommit.def()
+    }
+}
+
+impl Related<super::git_tag::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::GitTag.def()
+    }
+}
+
+impl Related<super::git_tree::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::GitTree.def()
+    }
+}
+
+impl Related<super::import_refs::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ImportRefs.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
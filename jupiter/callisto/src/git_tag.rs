@@ -22,6 +22,19 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::git_repo::Entity",
+        from = "Column::RepoId",
+        to = "super::git_repo::Column::Id"
+    )]
+    GitRepo,
+}
+
+impl Related<super::git_repo::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::GitRepo.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
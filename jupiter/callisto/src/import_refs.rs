@@ -1,10 +1,11 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.0
 
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::db_enums::RefType;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
 #[sea_orm(table_name = "import_refs")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -20,6 +21,19 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::git_repo::Entity",
+        from = "Column::RepoId",
+        to = "super::git_repo::Column::Id"
+    )]
+    GitRepo,
+}
+
+impl Related<super::git_repo::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::GitRepo.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -3,7 +3,16 @@
 pub mod prelude;
 
 pub mod access_token;
+pub mod auth_decision;
+pub mod background_job;
+pub mod break_glass_grant;
+pub mod branch_setting;
+pub mod commit_graph;
+pub mod daily_stats;
 pub mod db_enums;
+pub mod entity_file_signature;
+pub mod event_outbox;
+pub mod feature_flag;
 pub mod git_blob;
 pub mod git_commit;
 pub mod git_issue;
@@ -12,24 +21,58 @@ pub mod git_repo;
 pub mod git_tag;
 pub mod git_tree;
 pub mod import_refs;
+pub mod integrity_report;
 pub mod lfs_locks;
 pub mod lfs_objects;
 pub mod lfs_split_relations;
+pub mod maintenance_job;
+pub mod maintenance_mode;
 pub mod mega_blob;
 pub mod mega_commit;
+pub mod mega_commit_path;
 pub mod mega_issue;
 pub mod mega_mr;
+pub mod mega_mr_mergeability;
 pub mod mega_conversation;
 pub mod mega_refs;
 pub mod mega_tag;
 pub mod mega_tree;
 pub mod mq_storage;
+pub mod object_signature;
+pub mod org_member;
+pub mod org_repo;
+pub mod organization;
+pub mod policy_version;
+pub mod quarantined_push;
 pub mod raw_blob;
+pub mod raw_blob_chunk;
+pub mod release;
+pub mod release_asset;
+pub mod repo_redirect;
+pub mod repo_usage;
+pub mod repo_visibility;
+pub mod role_assignment;
+pub mod scheduled_message;
+pub mod secret_finding;
+pub mod service_account;
+pub mod service_account_token;
+pub mod signing_key;
 pub mod ssh_keys;
+pub mod subtree_split;
+pub mod tag_protection;
+pub mod team;
+pub mod team_member;
 pub mod user;
+pub mod user_activity;
+pub mod user_repo;
+pub mod virtual_repo;
+pub mod webhook;
+pub mod webhook_delivery;
 pub mod ztm_lfs_info;
 pub mod ztm_node;
 pub mod ztm_nostr_event;
 pub mod ztm_nostr_req;
 pub mod ztm_path_mapping;
+pub mod ztm_peer_principal;
 pub mod ztm_repo_info;
+pub mod ztm_subscription;
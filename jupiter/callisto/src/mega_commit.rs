@@ -1,8 +1,9 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.0
 
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, Deserialize)]
 #[sea_orm(table_name = "mega_commit")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -21,6 +22,23 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::mega_blob::Entity")]
+    MegaBlob,
+    #[sea_orm(has_many = "super::mega_tree::Entity")]
+    MegaTree,
+}
+
+impl Related<super::mega_blob::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MegaBlob.def()
+    }
+}
+
+impl Related<super::mega_tree::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MegaTree.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
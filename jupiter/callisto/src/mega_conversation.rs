@@ -19,6 +19,31 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::mega_issue::Entity",
+        from = "Column::Link",
+        to = "super::mega_issue::Column::Link"
+    )]
+    MegaIssue,
+    #[sea_orm(
+        belongs_to = "super::mega_mr::Entity",
+        from = "Column::Link",
+        to = "super::mega_mr::Column::Link"
+    )]
+    MegaMr,
+}
+
+impl Related<super::mega_issue::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MegaIssue.def()
+    }
+}
+
+impl Related<super::mega_mr::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MegaMr.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
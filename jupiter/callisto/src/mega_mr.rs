@@ -22,6 +22,15 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::mega_conversation::Entity")]
+    MegaConversation,
+}
+
+impl Related<super::mega_conversation::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MegaConversation.def()
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
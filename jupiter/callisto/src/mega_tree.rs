@@ -16,6 +16,30 @@ pub struct Model {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::mega_commit::Entity",
+        from = "Column::CommitId",
+        to = "super::mega_commit::Column::CommitId"
+    )]
+    MegaCommit,
+}
+
+impl Related<super::mega_commit::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MegaCommit.def()
+    }
+}
+
+/// Blobs saved for the same commit
+impl Related<super::mega_blob::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::mega_commit::Relation::MegaBlob.def()
+    }
+
+    fn via() -> Option<RelationDef> {
+        Some(Relation::MegaCommit.def())
+    }
+}
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -2,6 +2,8 @@
 
 use sea_orm::entity::prelude::*;
 
+use crate::db_enums::{MessageLane, MessageState};
+
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
 #[sea_orm(table_name = "mq_storage")]
 pub struct Model {
@@ -11,6 +13,17 @@ pub struct Model {
     pub create_time: DateTime,
     #[sea_orm(column_type = "Text", nullable)]
     pub content: Option<String>,
+    pub state: MessageState,
+    /// Times processing the message was started
+    pub attempts: i32,
+    /// When a failed or inflight message can be claimed again
+    pub visible_at: Option<DateTime>,
+    /// Why the last attempt failed
+    #[sea_orm(column_type = "Text", nullable)]
+    pub last_error: Option<String>,
+    /// Consumer processing the message while it is inflight
+    pub claimed_by: Option<String>,
+    pub lane: MessageLane,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
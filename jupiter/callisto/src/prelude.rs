@@ -1,6 +1,15 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.0.0
 
 pub use crate::access_token::Entity as AccessToken;
+pub use crate::auth_decision::Entity as AuthDecision;
+pub use crate::background_job::Entity as BackgroundJob;
+pub use crate::break_glass_grant::Entity as BreakGlassGrant;
+pub use crate::branch_setting::Entity as BranchSetting;
+pub use crate::commit_graph::Entity as CommitGraph;
+pub use crate::daily_stats::Entity as DailyStats;
+pub use crate::entity_file_signature::Entity as EntityFileSignature;
+pub use crate::event_outbox::Entity as EventOutbox;
+pub use crate::feature_flag::Entity as FeatureFlag;
 pub use crate::git_blob::Entity as GitBlob;
 pub use crate::git_commit::Entity as GitCommit;
 pub use crate::git_issue::Entity as GitIssue;
@@ -9,21 +18,55 @@ pub use crate::git_repo::Entity as GitRepo;
 pub use crate::git_tag::Entity as GitTag;
 pub use crate::git_tree::Entity as GitTree;
 pub use crate::import_refs::Entity as ImportRefs;
+pub use crate::integrity_report::Entity as IntegrityReport;
 pub use crate::lfs_locks::Entity as LfsLocks;
 pub use crate::lfs_objects::Entity as LfsObjects;
 pub use crate::lfs_split_relations::Entity as LfsSplitRelations;
+pub use crate::maintenance_job::Entity as MaintenanceJob;
+pub use crate::maintenance_mode::Entity as MaintenanceMode;
 pub use crate::mega_blob::Entity as MegaBlob;
 pub use crate::mega_commit::Entity as MegaCommit;
+pub use crate::mega_commit_path::Entity as MegaCommitPath;
 pub use crate::mega_issue::Entity as MegaIssue;
 pub use crate::mega_mr::Entity as MegaMr;
+pub use crate::mega_mr_mergeability::Entity as MegaMrMergeability;
 pub use crate::mega_conversation::Entity as MegaMrConv;
 pub use crate::mega_refs::Entity as MegaRefs;
 pub use crate::mega_tag::Entity as MegaTag;
 pub use crate::mega_tree::Entity as MegaTree;
+pub use crate::object_signature::Entity as ObjectSignature;
+pub use crate::org_member::Entity as OrgMember;
+pub use crate::org_repo::Entity as OrgRepo;
+pub use crate::organization::Entity as Organization;
+pub use crate::policy_version::Entity as PolicyVersion;
+pub use crate::quarantined_push::Entity as QuarantinedPush;
 pub use crate::raw_blob::Entity as RawBlob;
+pub use crate::raw_blob_chunk::Entity as RawBlobChunk;
+pub use crate::release::Entity as Release;
+pub use crate::release_asset::Entity as ReleaseAsset;
+pub use crate::repo_redirect::Entity as RepoRedirect;
+pub use crate::repo_usage::Entity as RepoUsage;
+pub use crate::repo_visibility::Entity as RepoVisibility;
+pub use crate::role_assignment::Entity as RoleAssignment;
+pub use crate::scheduled_message::Entity as ScheduledMessage;
+pub use crate::secret_finding::Entity as SecretFinding;
+pub use crate::service_account::Entity as ServiceAccount;
+pub use crate::service_account_token::Entity as ServiceAccountToken;
+pub use crate::signing_key::Entity as SigningKey;
 pub use crate::ssh_keys::Entity as SshKeys;
+pub use crate::subtree_split::Entity as SubtreeSplit;
+pub use crate::tag_protection::Entity as TagProtection;
+pub use crate::team::Entity as Team;
+pub use crate::team_member::Entity as TeamMember;
 pub use crate::user::Entity as User;
+pub use crate::user_activity::Entity as UserActivity;
+pub use crate::user_repo::Entity as UserRepo;
+pub use crate::virtual_repo::Entity as VirtualRepo;
+pub use crate::webhook::Entity as Webhook;
+pub use crate::webhook_delivery::Entity as WebhookDelivery;
 pub use crate::ztm_lfs_info::Entity as ZtmLFSInfo;
 pub use crate::ztm_node::Entity as ZtmNode;
 pub use crate::ztm_path_mapping::Entity as ZtmPathMapping;
+pub use crate::ztm_peer_principal::Entity as ZtmPeerPrincipal;
 pub use crate::ztm_repo_info::Entity as ZtmRepoInfo;
+pub use crate::ztm_subscription::Entity as ZtmSubscription;
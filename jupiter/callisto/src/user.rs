@@ -17,6 +17,9 @@ pub struct Model {
     pub is_github: bool,
     pub created_at: DateTime,
     pub updated_at: Option<DateTime>,
+    pub deactivated_at: Option<DateTime>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub tenant: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,762 @@
+//! Backups of an instance, its database together with the objects kept in local files.
+//!
+//! A backup is a directory below the backup directory named by its id. It holds a dump of the
+//! database, copies of the object files and a `manifest.json` listing them with their checksums,
+//! the manifest is written last so a backup without one is incomplete and ignored.
+//!
+//! The database is dumped first, with `VACUUM INTO` for sqlite, `pg_dump` for postgres and
+//! `mysqldump --single-transaction` for mysql. Each of them reads one consistent snapshot while
+//! the instance keeps running. The object files are copied afterwards: objects are addressed by
+//! their content and written before the rows referring to them, so every object the snapshot
+//! refers to exists when the copy starts. Only garbage collection removes objects, a backup
+//! during which an object disappears fails and has to be created again.
+//!
+//! An incremental backup only copies the object files its parent does not have, the manifest
+//! names the backup every file is kept in. The database is always dumped as a whole. A backup
+//! stays usable as long as the backups it takes files from are kept, [`verify`] checks that
+//! every file is there with its checksum and that the dump can be read.
+//!
+//! [`restore`] puts the database and the object files of a backup back in place, the instance
+//! has to be stopped. Object files which are not part of the backup are left alone, nothing in
+//! the restored database refers to them. Objects in a remote object storage are not backed up,
+//! the versioning of the bucket has to cover them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use chrono::NaiveDateTime;
+use ring::digest::{Context as Digest, SHA256};
+use sea_orm::{ConnectionTrait, Database, DbBackend, Statement};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use common::config::{Config, DbConfig, RawStorageType};
+use common::errors::MegaError;
+
+use crate::storage::init::connect;
+
+const MANIFEST: &str = "manifest.json";
+/// Size of the pieces files are copied in.
+const COPY_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Manifest {
+    pub id: String,
+    pub created_at: NaiveDateTime,
+    /// Backup the unchanged object files are taken from
+    pub parent: Option<String>,
+    pub database: DatabaseDump,
+    /// Directory of the raw objects when the backup was created, their locations in the
+    /// database are absolute paths below it
+    pub raw_dir: PathBuf,
+    pub lfs_dir: PathBuf,
+    pub objects: Vec<ObjectFile>,
+    /// Object storage whose objects are not part of the backup
+    pub remote_storage: Option<RawStorageType>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DatabaseDump {
+    pub db_type: String,
+    /// Name of the dump in the backup directory
+    pub file: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ObjectKind {
+    Raw,
+    Lfs,
+}
+
+impl ObjectKind {
+    fn dir_name(self) -> &'static str {
+        match self {
+            ObjectKind::Raw => "raw",
+            ObjectKind::Lfs => "lfs",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ObjectFile {
+    pub kind: ObjectKind,
+    /// Path relative to the directory of its kind
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Backup the copy of the file is kept in
+    pub backup: String,
+}
+
+/// What [`verify`] has checked.
+#[derive(Debug, Default)]
+pub struct Verified {
+    pub objects: usize,
+    pub bytes: u64,
+}
+
+/// What the backups of an instance cover, taken from its configuration.
+#[derive(Clone, Debug)]
+pub struct BackupSource {
+    pub database: DbConfig,
+    pub raw_storage: RawStorageType,
+    pub raw_dir: PathBuf,
+    pub lfs_dir: PathBuf,
+}
+
+impl From<&Config> for BackupSource {
+    fn from(config: &Config) -> Self {
+        BackupSource {
+            database: config.database.clone(),
+            raw_storage: config.storage.raw_obj_storage_type,
+            raw_dir: config.storage.raw_obj_local_path.clone(),
+            lfs_dir: config.lfs.lfs_obj_local_path.clone(),
+        }
+    }
+}
+
+impl BackupSource {
+    fn dir(&self, kind: ObjectKind) -> &Path {
+        match kind {
+            ObjectKind::Raw => &self.raw_dir,
+            ObjectKind::Lfs => &self.lfs_dir,
+        }
+    }
+}
+
+/// Create a backup in `dir`, only with the object files the latest backup there does not have
+/// if `incremental`.
+pub async fn create(
+    source: &BackupSource,
+    dir: &Path,
+    incremental: bool,
+) -> Result<Manifest, MegaError> {
+    let parent = if incremental {
+        let latest = list(dir)?.pop();
+        Some(latest.ok_or_else(|| MegaError::with_message("no backup to continue from"))?)
+    } else {
+        None
+    };
+    // sorts by time
+    let id = chrono::Utc::now().format("%Y%m%dT%H%M%S%3f").to_string();
+    let target = dir.join(&id);
+    if target.exists() {
+        return Err(MegaError::with_message(&format!(
+            "backup {} exists already",
+            id
+        )));
+    }
+    fs::create_dir_all(&target)?;
+    let res = create_in(source, dir, &id, parent).await;
+    if res.is_err() {
+        let _ = fs::remove_dir_all(&target);
+    }
+    res
+}
+
+async fn create_in(
+    source: &BackupSource,
+    dir: &Path,
+    id: &str,
+    parent: Option<Manifest>,
+) -> Result<Manifest, MegaError> {
+    let target = dir.join(id);
+    let database = dump_database(&source.database, &target).await?;
+
+    let objects = {
+        let (source, dir, id) = (source.clone(), dir.to_owned(), id.to_owned());
+        let parent = parent.clone();
+        tokio::task::spawn_blocking(move || copy_objects(&source, &dir, &id, parent.as_ref()))
+            .await
+            .map_err(|err| MegaError::with_message(&err.to_string()))??
+    };
+    let remote_storage = match source.raw_storage {
+        RawStorageType::Database | RawStorageType::Local => None,
+        remote => Some(remote),
+    };
+    let manifest = Manifest {
+        id: id.to_owned(),
+        created_at: chrono::Utc::now().naive_utc(),
+        parent: parent.map(|p| p.id),
+        database,
+        raw_dir: source.raw_dir.clone(),
+        lfs_dir: source.lfs_dir.clone(),
+        objects,
+        remote_storage,
+    };
+    let content = serde_json::to_vec_pretty(&manifest)
+        .map_err(|err| MegaError::with_message(&err.to_string()))?;
+    let tmp = target.join(format!("{}.tmp", MANIFEST));
+    fs::write(&tmp, content)?;
+    fs::rename(tmp, target.join(MANIFEST))?;
+    Ok(manifest)
+}
+
+/// The complete backups in `dir`, oldest first.
+pub fn list(dir: &Path) -> Result<Vec<Manifest>, MegaError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut manifests = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.path().join(MANIFEST).is_file() {
+            manifests.push(read_manifest(dir, &entry.file_name().to_string_lossy())?);
+        }
+    }
+    manifests.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(manifests)
+}
+
+pub fn read_manifest(dir: &Path, id: &str) -> Result<Manifest, MegaError> {
+    let content = match fs::read(dir.join(id).join(MANIFEST)) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Err(MegaError::with_message(&format!("backup {} not found", id)));
+        }
+        Err(err) => return Err(err.into()),
+    };
+    serde_json::from_slice(&content).map_err(|err| MegaError::with_message(&err.to_string()))
+}
+
+/// Check that every file of backup `id` is there with its checksum and that its database dump
+/// can be read.
+pub async fn verify(dir: &Path, id: &str) -> Result<Verified, MegaError> {
+    let manifest = read_manifest(dir, id)?;
+    let dump = dir.join(id).join(&manifest.database.file);
+    let (size, sha256) = hash_file(&dump)?;
+    if size != manifest.database.size || sha256 != manifest.database.sha256 {
+        return Err(MegaError::with_message(&format!(
+            "database dump of backup {} is damaged",
+            id
+        )));
+    }
+    check_dump(&manifest.database.db_type, &dump).await?;
+
+    let dir = dir.to_owned();
+    tokio::task::spawn_blocking(move || {
+        let mut verified = Verified::default();
+        for object in &manifest.objects {
+            let path = backup_path(&dir, object);
+            match hash_file(&path) {
+                Ok((size, sha256)) if size == object.size && sha256 == object.sha256 => {
+                    verified.objects += 1;
+                    verified.bytes += size;
+                }
+                Ok(_) => {
+                    return Err(MegaError::with_message(&format!(
+                        "{} of backup {} is damaged",
+                        object.path, object.backup
+                    )));
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    return Err(MegaError::with_message(&format!(
+                        "{} is missing, backup {} must be kept",
+                        object.path, object.backup
+                    )));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(verified)
+    })
+    .await
+    .map_err(|err| MegaError::with_message(&err.to_string()))?
+}
+
+/// Put the database and the object files of backup `id` back in place, after it was verified.
+/// The instance must not be running.
+pub async fn restore(source: &BackupSource, dir: &Path, id: &str) -> Result<Manifest, MegaError> {
+    verify(dir, id).await?;
+    let manifest = read_manifest(dir, id)?;
+    if manifest.database.db_type != source.database.db_type {
+        return Err(MegaError::with_message(&format!(
+            "backup {} is of a {} database, not {}",
+            id, manifest.database.db_type, source.database.db_type
+        )));
+    }
+    let has_raw = manifest.objects.iter().any(|o| o.kind == ObjectKind::Raw);
+    if has_raw && source.raw_dir != manifest.raw_dir {
+        return Err(MegaError::with_message(&format!(
+            "raw objects of backup {} were kept in {}, set storage.raw_obj_local_path to it",
+            id,
+            manifest.raw_dir.display()
+        )));
+    }
+    restore_database(
+        &source.database,
+        &dir.join(id).join(&manifest.database.file),
+    )
+    .await?;
+
+    let (source, dir, objects) = (source.clone(), dir.to_owned(), manifest.objects.clone());
+    tokio::task::spawn_blocking(move || {
+        for object in &objects {
+            let path = source.dir(object.kind).join(&object.path);
+            // objects never change, one of the same size is the same
+            if fs::metadata(&path).is_ok_and(|m| m.len() == object.size) {
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(backup_path(&dir, object), &path)?;
+        }
+        Ok::<_, MegaError>(())
+    })
+    .await
+    .map_err(|err| MegaError::with_message(&err.to_string()))??;
+    Ok(manifest)
+}
+
+async fn dump_database(db: &DbConfig, target: &Path) -> Result<DatabaseDump, MegaError> {
+    let file = match db.db_type.as_str() {
+        "sqlite" => {
+            let file = target.join("database.sqlite");
+            let conn = connect(db).await;
+            conn.execute(Statement::from_sql_and_values(
+                DbBackend::Sqlite,
+                "VACUUM INTO ?",
+                [file.to_string_lossy().into_owned().into()],
+            ))
+            .await?;
+            conn.close().await?;
+            file
+        }
+        "postgres" => {
+            let file = target.join("database.pgdump");
+            let mut command = Command::new("pg_dump");
+            command
+                .arg("--format=custom")
+                .arg("--file")
+                .arg(&file)
+                .arg(&db.db_url);
+            run(command).await?;
+            file
+        }
+        "mysql" => {
+            let file = target.join("database.sql");
+            let (mut command, database) = mysql_command("mysqldump", &db.db_url)?;
+            command
+                .arg("--single-transaction")
+                .arg(format!("--result-file={}", file.display()))
+                .arg(database);
+            run(command).await?;
+            file
+        }
+        db_type => {
+            return Err(MegaError::with_message(&format!(
+                "unsupported database type: {}",
+                db_type
+            )));
+        }
+    };
+    let (size, sha256) = hash_file(&file)?;
+    Ok(DatabaseDump {
+        db_type: db.db_type.clone(),
+        file: file.file_name().unwrap().to_string_lossy().into_owned(),
+        size,
+        sha256,
+    })
+}
+
+/// Check that `dump` can be read back.
+async fn check_dump(db_type: &str, dump: &Path) -> Result<(), MegaError> {
+    match db_type {
+        "sqlite" => {
+            let url = format!("sqlite://{}?mode=ro", dump.display());
+            let conn = Database::connect(url).await?;
+            let row = conn
+                .query_one(Statement::from_string(
+                    DbBackend::Sqlite,
+                    "PRAGMA integrity_check",
+                ))
+                .await?;
+            let result: Option<String> = match row {
+                Some(row) => row.try_get_by_index(0)?,
+                None => None,
+            };
+            // a database without migrations is not one of an instance
+            let migrations = conn
+                .query_one(Statement::from_string(
+                    DbBackend::Sqlite,
+                    "SELECT COUNT(*) FROM seaql_migrations",
+                ))
+                .await;
+            conn.close().await?;
+            if result.as_deref() != Some("ok") || migrations.is_err() {
+                return Err(MegaError::with_message("database dump is not readable"));
+            }
+        }
+        "postgres" => {
+            let mut command = Command::new("pg_restore");
+            command.arg("--list").arg(dump);
+            run(command).await?;
+        }
+        "mysql" => {
+            let content = fs::read_to_string(dump)?;
+            let last = content.lines().rev().find(|l| !l.trim().is_empty());
+            if !last.is_some_and(|l| l.starts_with("-- Dump completed")) {
+                return Err(MegaError::with_message("database dump is incomplete"));
+            }
+        }
+        db_type => {
+            return Err(MegaError::with_message(&format!(
+                "unsupported database type: {}",
+                db_type
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn restore_database(db: &DbConfig, dump: &Path) -> Result<(), MegaError> {
+    match db.db_type.as_str() {
+        "sqlite" => {
+            let path = PathBuf::from(&db.db_path);
+            let tmp = path.with_extension("restore");
+            fs::copy(dump, &tmp)?;
+            // a write-ahead log left behind would be applied to the restored database
+            for suffix in ["-wal", "-shm"] {
+                let file = PathBuf::from(format!("{}{}", db.db_path, suffix));
+                match fs::remove_file(file) {
+                    Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                    _ => (),
+                }
+            }
+            fs::rename(tmp, path)?;
+        }
+        "postgres" => {
+            let mut command = Command::new("pg_restore");
+            command
+                .arg("--clean")
+                .arg("--if-exists")
+                .arg("--no-owner")
+                .arg("--single-transaction")
+                .arg(format!("--dbname={}", db.db_url))
+                .arg(dump);
+            run(command).await?;
+        }
+        "mysql" => {
+            let (mut command, database) = mysql_command("mysql", &db.db_url)?;
+            command
+                .arg(database)
+                .stdin(Stdio::from(fs::File::open(dump)?));
+            run(command).await?;
+        }
+        db_type => {
+            return Err(MegaError::with_message(&format!(
+                "unsupported database type: {}",
+                db_type
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// The mysql command line tool `program` connecting to the server of `db_url`, and the name of
+/// the database, which has to be the last argument.
+fn mysql_command(program: &str, db_url: &str) -> Result<(Command, String), MegaError> {
+    let invalid = || MegaError::with_message(&format!("invalid mysql url: {}", db_url));
+    let (_, rest) = db_url.split_once("://").ok_or_else(invalid)?;
+    let (auth, rest) = rest.rsplit_once('@').unwrap_or(("", rest));
+    let (host, database) = rest.split_once('/').ok_or_else(invalid)?;
+    let database = database.split('?').next().unwrap_or_default();
+    if host.is_empty() || database.is_empty() {
+        return Err(invalid());
+    }
+    let mut command = Command::new(program);
+    match host.rsplit_once(':') {
+        Some((host, port)) => command
+            .arg(format!("--host={}", host))
+            .arg(format!("--port={}", port)),
+        None => command.arg(format!("--host={}", host)),
+    };
+    if !auth.is_empty() {
+        let (user, password) = auth.split_once(':').unwrap_or((auth, ""));
+        command.arg(format!("--user={}", user));
+        // kept out of the arguments, which other users can see
+        if !password.is_empty() {
+            command.env("MYSQL_PWD", password);
+        }
+    }
+    Ok((command, database.to_owned()))
+}
+
+async fn run(mut command: Command) -> Result<(), MegaError> {
+    let program = command
+        .as_std()
+        .get_program()
+        .to_string_lossy()
+        .into_owned();
+    let output = command
+        .stdout(Stdio::null())
+        .output()
+        .await
+        .map_err(|err| MegaError::with_message(&format!("failed to run {}: {}", program, err)))?;
+    if !output.status.success() {
+        return Err(MegaError::with_message(&format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Copy the object files to backup `id`, those of `parent` are only referred to.
+fn copy_objects(
+    source: &BackupSource,
+    dir: &Path,
+    id: &str,
+    parent: Option<&Manifest>,
+) -> Result<Vec<ObjectFile>, MegaError> {
+    let known: HashMap<(ObjectKind, &str), &ObjectFile> = parent
+        .map(|p| {
+            p.objects
+                .iter()
+                .map(|o| ((o.kind, o.path.as_str()), o))
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut objects = Vec::new();
+    for kind in [ObjectKind::Raw, ObjectKind::Lfs] {
+        let from = source.dir(kind);
+        for path in list_files(from)? {
+            let relative = path
+                .strip_prefix(from)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            let size = match fs::metadata(&path) {
+                Ok(metadata) => metadata.len(),
+                Err(err) => return Err(removed(&relative, err)),
+            };
+            // objects never change, one of the same size is the same
+            if let Some(file) = known.get(&(kind, relative.as_str())) {
+                if file.size == size {
+                    objects.push((*file).clone());
+                    continue;
+                }
+            }
+            let copy = dir
+                .join(id)
+                .join("objects")
+                .join(kind.dir_name())
+                .join(&relative);
+            fs::create_dir_all(copy.parent().unwrap())?;
+            let (size, sha256) = copy_file(&path, &copy).map_err(|err| removed(&relative, err))?;
+            objects.push(ObjectFile {
+                kind,
+                path: relative,
+                size,
+                sha256,
+                backup: id.to_owned(),
+            });
+        }
+    }
+    Ok(objects)
+}
+
+fn removed(path: &str, err: io::Error) -> MegaError {
+    if err.kind() == ErrorKind::NotFound {
+        MegaError::with_message(&format!(
+            "{} was removed while the backup ran, create the backup again",
+            path
+        ))
+    } else {
+        err.into()
+    }
+}
+
+/// Files below `dir`, without those still being written.
+fn list_files(dir: &Path) -> Result<Vec<PathBuf>, MegaError> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_none_or(|e| e != "tmp") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn backup_path(dir: &Path, object: &ObjectFile) -> PathBuf {
+    dir.join(&object.backup)
+        .join("objects")
+        .join(object.kind.dir_name())
+        .join(&object.path)
+}
+
+/// Copy `from` to `to`, returns the size and the SHA-256 of the content.
+fn copy_file(from: &Path, to: &Path) -> io::Result<(u64, String)> {
+    let mut source = fs::File::open(from)?;
+    let mut target = fs::File::create(to)?;
+    let res = copy_hashed(&mut source, Some(&mut target))?;
+    target.sync_all()?;
+    Ok(res)
+}
+
+fn hash_file(path: &Path) -> io::Result<(u64, String)> {
+    copy_hashed(&mut fs::File::open(path)?, None)
+}
+
+fn copy_hashed(
+    source: &mut impl Read,
+    mut target: Option<&mut fs::File>,
+) -> io::Result<(u64, String)> {
+    let mut digest = Digest::new(&SHA256);
+    let mut buf = vec![0; COPY_SIZE];
+    let mut size = 0;
+    loop {
+        let len = source.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        digest.update(&buf[..len]);
+        if let Some(target) = target.as_mut() {
+            target.write_all(&buf[..len])?;
+        }
+        size += len as u64;
+    }
+    Ok((size, hex::encode(digest.finish())))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use sea_orm::{ConnectionTrait, DbBackend, Statement};
+    use sea_orm_migration::MigratorTrait;
+    use tempfile::TempDir;
+
+    use common::config::{DbConfig, RawStorageType};
+
+    use super::{create, list, mysql_command, restore, verify, BackupSource, ObjectKind};
+    use crate::migration::Migrator;
+    use crate::storage::init::connect;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    async fn count_rows(source: &BackupSource) -> i64 {
+        let conn = connect(&source.database).await;
+        let row = conn
+            .query_one(Statement::from_string(
+                DbBackend::Sqlite,
+                "SELECT COUNT(*) FROM backup_test",
+            ))
+            .await
+            .unwrap()
+            .unwrap();
+        row.try_get_by_index(0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_backup_and_restore() {
+        let dir = TempDir::new().unwrap();
+        let backups = dir.path().join("backups");
+        let source = BackupSource {
+            database: DbConfig {
+                db_type: "sqlite".to_owned(),
+                db_path: dir.path().join("mega.db").to_str().unwrap().to_owned(),
+                max_connection: 1,
+                min_connection: 1,
+                ..Default::default()
+            },
+            raw_storage: RawStorageType::Local,
+            raw_dir: dir.path().join("objects"),
+            lfs_dir: dir.path().join("lfs"),
+        };
+        let conn = connect(&source.database).await;
+        Migrator::up(&conn, None).await.unwrap();
+        conn.execute_unprepared("CREATE TABLE backup_test (id INTEGER)")
+            .await
+            .unwrap();
+        conn.execute_unprepared("INSERT INTO backup_test VALUES (1)")
+            .await
+            .unwrap();
+        conn.close().await.unwrap();
+        write(&source.raw_dir.join("ab/cd/ef"), "raw");
+        write(&source.lfs_dir.join("objects/12/34/5678"), "lfs");
+        write(&source.lfs_dir.join("objects/12/34/9999.tmp"), "partial");
+
+        assert!(create(&source, &backups, true).await.is_err());
+        let full = create(&source, &backups, false).await.unwrap();
+        assert_eq!(full.objects.len(), 2);
+        assert!(full.remote_storage.is_none());
+
+        // the next backup only copies the new object
+        write(&source.raw_dir.join("ab/cd/gh"), "new");
+        let incremental = create(&source, &backups, true).await.unwrap();
+        assert_eq!(incremental.parent.as_deref(), Some(full.id.as_str()));
+        let copied: Vec<_> = incremental
+            .objects
+            .iter()
+            .filter(|o| o.backup == incremental.id)
+            .map(|o| (o.kind, o.path.as_str()))
+            .collect();
+        assert_eq!(copied, [(ObjectKind::Raw, "ab/cd/gh")]);
+        assert_eq!(list(&backups).unwrap().len(), 2);
+        let verified = verify(&backups, &incremental.id).await.unwrap();
+        assert_eq!((verified.objects, verified.bytes), (3, 9));
+
+        // restoring brings back the database and the objects of that point
+        let conn = connect(&source.database).await;
+        conn.execute_unprepared("INSERT INTO backup_test VALUES (2)")
+            .await
+            .unwrap();
+        conn.close().await.unwrap();
+        fs::remove_file(source.raw_dir.join("ab/cd/ef")).unwrap();
+        restore(&source, &backups, &full.id).await.unwrap();
+        assert_eq!(count_rows(&source).await, 1);
+        assert_eq!(
+            fs::read_to_string(source.raw_dir.join("ab/cd/ef")).unwrap(),
+            "raw"
+        );
+
+        // a backup depends on the files of its parent
+        let parent_copy = backups
+            .join(&full.id)
+            .join("objects/lfs/objects/12/34/5678");
+        fs::write(&parent_copy, "damaged").unwrap();
+        assert!(verify(&backups, &incremental.id).await.is_err());
+    }
+
+    #[test]
+    fn test_mysql_command() {
+        let (command, database) = mysql_command(
+            "mysqldump",
+            "mysql://mega:secret@db:3307/mega?ssl-mode=disabled",
+        )
+        .unwrap();
+        assert_eq!(database, "mega");
+        let args: Vec<_> = command.as_std().get_args().collect();
+        assert_eq!(args, ["--host=db", "--port=3307", "--user=mega"]);
+        let envs: Vec<_> = command.as_std().get_envs().collect();
+        assert_eq!(envs.len(), 1);
+        assert!(mysql_command("mysql", "mysql://db").is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Caches of the lookups git operations repeat on every request, like the refs advertised to
+//! clients and the commits checked for existence during a push.
+//!
+//! Entries are kept in process and, if `cache.redis_url` is set, in redis shared by all
+//! instances. Writes invalidate what they change in both, but the process caches of other
+//! instances only expire, so refs skip the process cache when redis is used.
+
+use std::future::Future;
+use std::time::Duration;
+
+use futures::StreamExt;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use common::config::CacheConfig;
+use common::errors::MegaError;
+
+use crate::storage::metrics;
+
+/// Prefix of all keys in redis.
+const REDIS_PREFIX: &str = "mega:cache";
+
+/// The configuration and redis connection all caches are made from.
+#[derive(Clone, Default)]
+pub struct CacheBackend {
+    config: CacheConfig,
+    redis: Option<ConnectionManager>,
+}
+
+impl CacheBackend {
+    /// Connect to redis if configured, caches are only kept in process if that fails.
+    pub async fn new(config: &CacheConfig) -> Self {
+        let redis = if config.redis_url.is_empty() {
+            None
+        } else {
+            let client = redis::Client::open(config.redis_url.as_str());
+            match client {
+                Ok(client) => match ConnectionManager::new(client).await {
+                    Ok(conn) => Some(conn),
+                    Err(err) => {
+                        tracing::error!("connect to redis cache failed: {}", err);
+                        None
+                    }
+                },
+                Err(err) => {
+                    tracing::error!("invalid redis url {}: {}", config.redis_url, err);
+                    None
+                }
+            }
+        };
+        CacheBackend {
+            config: config.clone(),
+            redis,
+        }
+    }
+
+    /// A cache of refs, which change on every push.
+    pub fn refs<V: Clone + Send + Sync + 'static>(&self, name: &'static str) -> Cache<V> {
+        // other instances would keep serving refs from their process caches
+        let capacity = if self.redis.is_some() {
+            0
+        } else {
+            self.config.capacity
+        };
+        Cache::new(name, capacity, self.config.ref_ttl, self.redis.clone())
+    }
+
+    /// A cache of commits, which never change once saved.
+    pub fn commits<V: Clone + Send + Sync + 'static>(&self, name: &'static str) -> Cache<V> {
+        Cache::new(
+            name,
+            self.config.capacity,
+            self.config.commit_ttl,
+            self.redis.clone(),
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Cache<V> {
+    name: &'static str,
+    local: Option<moka::future::Cache<String, V>>,
+    redis: Option<ConnectionManager>,
+    ttl: Duration,
+}
+
+impl<V: Clone + Send + Sync + 'static> Cache<V> {
+    fn new(name: &'static str, capacity: u64, ttl: u64, redis: Option<ConnectionManager>) -> Self {
+        let ttl = Duration::from_secs(ttl);
+        let local = (capacity > 0 && !ttl.is_zero()).then(|| {
+            moka::future::Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(ttl)
+                .support_invalidation_closures()
+                .build()
+        });
+        Cache {
+            name,
+            local,
+            redis: redis.filter(|_| !ttl.is_zero()),
+            ttl,
+        }
+    }
+
+    /// Caches nothing.
+    pub fn disabled(name: &'static str) -> Self {
+        Cache::new(name, 0, 0, None)
+    }
+
+    fn redis_key(&self, key: &str) -> String {
+        format!("{}:{}:{}", REDIS_PREFIX, self.name, key)
+    }
+}
+
+impl<V> Cache<V>
+where
+    V: Clone + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// The value of `key`, loaded by `load` if it is not cached. `None` is not cached, as
+    /// the value may be saved later.
+    pub async fn get_or_load<F, Fut>(&self, key: &str, load: F) -> Result<Option<V>, MegaError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<V>, MegaError>>,
+    {
+        if self.local.is_none() && self.redis.is_none() {
+            return load().await;
+        }
+        if let Some(value) = self.get(key).await {
+            metrics::record_cache(self.name, true);
+            return Ok(Some(value));
+        }
+        metrics::record_cache(self.name, false);
+        let value = load().await?;
+        if let Some(value) = &value {
+            self.insert(key, value).await;
+        }
+        Ok(value)
+    }
+
+    async fn get(&self, key: &str) -> Option<V> {
+        if let Some(local) = &self.local {
+            if let Some(value) = local.get(key).await {
+                return Some(value);
+            }
+        }
+        let mut redis = self.redis.clone()?;
+        let json = match redis.get::<_, Option<String>>(self.redis_key(key)).await {
+            Ok(json) => json,
+            Err(err) => {
+                tracing::warn!("read of redis cache {} failed: {}", self.name, err);
+                return None;
+            }
+        };
+        let value: V = serde_json::from_str(&json?).ok()?;
+        if let Some(local) = &self.local {
+            local.insert(key.to_owned(), value.clone()).await;
+        }
+        Some(value)
+    }
+
+    async fn insert(&self, key: &str, value: &V) {
+        if let Some(local) = &self.local {
+            local.insert(key.to_owned(), value.clone()).await;
+        }
+        if let Some(mut redis) = self.redis.clone() {
+            let Ok(json) = serde_json::to_string(value) else {
+                return;
+            };
+            let res: Result<(), _> = redis
+                .set_ex(self.redis_key(key), json, self.ttl.as_secs())
+                .await;
+            if let Err(err) = res {
+                tracing::warn!("write of redis cache {} failed: {}", self.name, err);
+            }
+        }
+    }
+
+    /// Forget the value of `key`, to be called whenever it changes.
+    pub async fn invalidate(&self, key: &str) {
+        if let Some(local) = &self.local {
+            local.invalidate(key).await;
+        }
+        if let Some(mut redis) = self.redis.clone() {
+            let res: Result<(), _> = redis.del(self.redis_key(key)).await;
+            if let Err(err) = res {
+                tracing::warn!("invalidate of redis cache {} failed: {}", self.name, err);
+            }
+        }
+    }
+
+    /// Forget the values of all keys starting with `prefix`.
+    pub async fn invalidate_prefix(&self, prefix: &str) {
+        if let Some(local) = &self.local {
+            let prefix = prefix.to_owned();
+            // only fails if invalidation closures are not supported, which they are
+            let _ = local.invalidate_entries_if(move |key, _| key.starts_with(&prefix));
+        }
+        if let Some(mut redis) = self.redis.clone() {
+            let pattern = format!("{}*", self.redis_key(&escape_pattern(prefix)));
+            let keys: Vec<String> = match redis.scan_match::<_, String>(pattern).await {
+                Ok(iter) => iter.collect().await,
+                Err(err) => {
+                    tracing::warn!("invalidate of redis cache {} failed: {}", self.name, err);
+                    return;
+                }
+            };
+            if keys.is_empty() {
+                return;
+            }
+            let res: Result<(), _> = redis.del(keys).await;
+            if let Err(err) = res {
+                tracing::warn!("invalidate of redis cache {} failed: {}", self.name, err);
+            }
+        }
+    }
+}
+
+/// Escape the glob characters of redis patterns.
+fn escape_pattern(key: &str) -> String {
+    let mut res = String::with_capacity(key.len());
+    for c in key.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use common::config::CacheConfig;
+
+    use super::{escape_pattern, Cache, CacheBackend};
+
+    #[tokio::test]
+    async fn test_cache() {
+        let backend = CacheBackend::new(&CacheConfig::default()).await;
+        let cache: Cache<Vec<String>> = backend.refs("test_refs");
+        let load = |value: &str| {
+            let value = vec![value.to_owned()];
+            move || async move { Ok(Some(value)) }
+        };
+        let value = cache.get_or_load("/a/b", load("old")).await.unwrap();
+        assert_eq!(value, Some(vec!["old".to_owned()]));
+        // cached values are not loaded again
+        let value = cache.get_or_load("/a/b", load("new")).await.unwrap();
+        assert_eq!(value, Some(vec!["old".to_owned()]));
+
+        cache.invalidate_prefix("/a").await;
+        let value = cache.get_or_load("/a/b", load("new")).await.unwrap();
+        assert_eq!(value, Some(vec!["new".to_owned()]));
+        cache.invalidate("/a/b").await;
+        let value = cache.get_or_load("/a/b", || async { Ok(None) }).await;
+        assert_eq!(value.unwrap(), None);
+
+        let cache: Cache<Vec<String>> = Cache::disabled("test_disabled");
+        cache.get_or_load("/a", load("old")).await.unwrap();
+        let value = cache.get_or_load("/a", load("new")).await.unwrap();
+        assert_eq!(value, Some(vec!["new".to_owned()]));
+    }
+
+    #[test]
+    fn test_escape_pattern() {
+        assert_eq!(escape_pattern("/a/b"), "/a/b");
+        assert_eq!(escape_pattern("/a*[x]"), "/a\\*\\[x\\]");
+    }
+}
@@ -1,29 +1,125 @@
-use std::{env, path::PathBuf, sync::Arc};
+use std::{env, path::PathBuf, sync::Arc, time::Duration};
 
-use common::config::Config;
+use common::config::{Config, ConfigError, LiveConfig, Reload};
+use common::plugin::Plugins;
+use saturn::{hierarchy::Hierarchy, policy::PolicyStore, resolver::EntityResolver};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 
 use crate::{
-    lfs_storage::{local_storage::LocalStorage, LfsStorage},
+    cache::CacheBackend,
+    lfs_storage::{self, local_storage::LocalStorage, LfsStorage},
     storage::{
-        git_db_storage::GitDbStorage, init::database_connection, issue_storage::IssueStorage,
-        lfs_db_storage::LfsDbStorage, mono_storage::MonoStorage, mq_storage::MQStorage,
-        mr_storage::MrStorage, raw_db_storage::RawDbStorage, user_storage::UserStorage,
-        ztm_storage::ZTMStorage,
+        feature_storage::FeatureStorage, git_db_storage::GitDbStorage, health,
+        init::database_connection, issue_storage::IssueStorage, job_storage::JobStorage,
+        lfs_db_storage::LfsDbStorage, maintenance_storage::MaintenanceStorage,
+        mono_storage::MonoStorage, mq_storage::MQStorage, mr_storage::MrStorage,
+        outbox_storage::OutboxStorage, policy_storage::PolicyStorage, quota_storage::QuotaStorage,
+        raw_db_storage::RawDbStorage, release_storage::ReleaseStorage,
+        schedule_storage::ScheduleStorage, secret_storage::SecretStorage,
+        signature_storage::SignatureStorage, stats_storage::StatsStorage,
+        user_storage::UserStorage, webhook_storage::WebhookStorage, ztm_storage::ZTMStorage,
     },
 };
 
 #[derive(Clone)]
 pub struct Context {
     pub services: Arc<Service>,
+    /// The config the services were started with, see [`Context::settings`] for the settings
+    /// which can be reloaded
     pub config: Config,
+    pub live: Arc<LiveConfig>,
+    /// Cedar policies in use, reloaded when their files change
+    pub policies: Arc<PolicyStore>,
+    /// Entities of the monorepo directories, merged from their entity files
+    pub entities: Arc<EntityResolver>,
+    /// Directories of the monorepo the repositories are in, rebuilt when its tree changes
+    pub hierarchy: Arc<Hierarchy>,
+    /// Plugins of `[plugins]`, loaded on start
+    pub plugins: Arc<Plugins>,
 }
 
 impl Context {
     pub async fn new(config: Config) -> Self {
+        let services = Service::shared(&config).await;
+        let policies = Arc::new(PolicyStore::new(&config.policy).expect("Invalid policies"));
+        match services.policy_storage.current_policies().await {
+            Ok(namespaces) => {
+                if let Err(err) = policies.set_namespaces(namespaces) {
+                    tracing::error!("policies of the namespaces are not used: {}", err);
+                }
+            }
+            Err(err) => tracing::error!("failed to load the policies of the namespaces: {}", err),
+        }
+        if config.policy.reload_interval > 0 {
+            policies.watch(Duration::from_secs(config.policy.reload_interval));
+        }
+        let plugins = Arc::new(Plugins::load(&config.plugins).expect("Invalid plugins"));
         Context {
-            services: Service::shared(&config).await,
+            services,
+            live: Arc::new(LiveConfig::new(config.clone())),
             config,
+            policies,
+            entities: Arc::new(EntityResolver::new()),
+            hierarchy: Arc::new(Hierarchy::new()),
+            plugins,
+        }
+    }
+
+    /// The config with the reloadable settings as they are now.
+    pub fn settings(&self) -> Arc<Config> {
+        self.live.current()
+    }
+
+    /// Read the config file again and put its reloadable settings in use, the files of the
+    /// policies are read again as well.
+    pub fn reload_config(&self) -> Result<Reload, ConfigError> {
+        let reload = self
+            .live
+            .reload(|config| self.policies.set_files(&config.policy))?;
+        tracing::info!("config reloaded, changed: {:?}", reload.changed);
+        if !reload.restart.is_empty() {
+            tracing::warn!("changes which need a restart: {:?}", reload.restart);
         }
+        Ok(reload)
+    }
+
+    /// Reload the config whenever the process receives SIGHUP.
+    #[cfg(unix)]
+    pub fn reload_on_hangup(&self) {
+        let context = self.clone();
+        tokio::spawn(async move {
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(err) => {
+                    tracing::error!("failed to listen for SIGHUP: {}", err);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                let context = context.clone();
+                let res = tokio::task::spawn_blocking(move || context.reload_config()).await;
+                if let Ok(Err(err)) = res {
+                    tracing::error!("keeping the previous config, reload failed: {}", err);
+                }
+            }
+        });
+    }
+
+    /// Reload the config whenever the Windows service control manager sends `ParamChange`.
+    #[cfg(windows)]
+    pub fn reload_on_param_change(&self) {
+        let context = self.clone();
+        let mut changes = common::winservice::param_changes();
+        tokio::spawn(async move {
+            while changes.changed().await.is_ok() {
+                let context = context.clone();
+                let res = tokio::task::spawn_blocking(move || context.reload_config()).await;
+                if let Ok(Err(err)) = res {
+                    tracing::error!("keeping the previous config, reload failed: {}", err);
+                }
+            }
+        });
     }
 
     pub fn user_stg(&self) -> UserStorage {
@@ -39,9 +135,15 @@ impl Context {
     }
 
     pub fn mock() -> Self {
+        let config = Config::default();
         Context {
             services: Service::mock(),
-            config: Config::default(),
+            live: Arc::new(LiveConfig::new(config.clone())),
+            config,
+            policies: Arc::new(PolicyStore::builtin()),
+            entities: Arc::new(EntityResolver::new()),
+            hierarchy: Arc::new(Hierarchy::new()),
+            plugins: Arc::new(Plugins::default()),
         }
     }
 }
@@ -54,6 +156,18 @@ pub struct Service {
     pub lfs_db_storage: LfsDbStorage,
     pub ztm_storage: ZTMStorage,
     pub mq_storage: MQStorage,
+    pub maintenance_storage: MaintenanceStorage,
+    pub secret_storage: SecretStorage,
+    pub release_storage: ReleaseStorage,
+    pub quota_storage: QuotaStorage,
+    pub signature_storage: SignatureStorage,
+    pub job_storage: JobStorage,
+    pub policy_storage: PolicyStorage,
+    pub feature_storage: FeatureStorage,
+    pub stats_storage: StatsStorage,
+    pub webhook_storage: WebhookStorage,
+    pub outbox_storage: OutboxStorage,
+    pub schedule_storage: ScheduleStorage,
     user_storage: UserStorage,
     mr_storage: MrStorage,
     issue_storage: IssueStorage,
@@ -63,17 +177,41 @@ pub struct Service {
 impl Service {
     async fn new(config: &Config) -> Service {
         let connection = Arc::new(database_connection(&config.database).await);
+        tokio::spawn(health::watch(
+            connection.clone(),
+            config.database.reconnect_max_delay,
+        ));
+        let raw_db_storage = RawDbStorage::new(connection.clone(), &config.storage).await;
+        let cache = CacheBackend::new(&config.cache).await;
+        let lfs_storage = lfs_storage::encrypted(
+            Arc::new(LocalStorage::init(config.lfs.lfs_obj_local_path.clone())),
+            raw_db_storage.keyring(),
+        );
         Service {
-            mono_storage: MonoStorage::new(connection.clone()).await,
-            git_db_storage: GitDbStorage::new(connection.clone()).await,
-            raw_db_storage: RawDbStorage::new(connection.clone()).await,
+            mono_storage: MonoStorage::new(connection.clone(), raw_db_storage.clone(), &cache)
+                .await,
+            git_db_storage: GitDbStorage::new(connection.clone(), raw_db_storage.clone(), &cache)
+                .await,
+            raw_db_storage,
             lfs_db_storage: LfsDbStorage::new(connection.clone()).await,
             ztm_storage: ZTMStorage::new(connection.clone()).await,
             mq_storage: MQStorage::new(connection.clone()).await,
+            maintenance_storage: MaintenanceStorage::new(connection.clone()).await,
+            secret_storage: SecretStorage::new(connection.clone()).await,
+            release_storage: ReleaseStorage::new(connection.clone()).await,
+            quota_storage: QuotaStorage::new(connection.clone()).await,
+            signature_storage: SignatureStorage::new(connection.clone()).await,
+            job_storage: JobStorage::new(connection.clone()).await,
+            policy_storage: PolicyStorage::new(connection.clone()).await,
+            feature_storage: FeatureStorage::new(connection.clone()).await,
+            stats_storage: StatsStorage::new(connection.clone()).await,
+            webhook_storage: WebhookStorage::new(connection.clone()).await,
+            outbox_storage: OutboxStorage::new(connection.clone()).await,
+            schedule_storage: ScheduleStorage::new(connection.clone()).await,
             user_storage: UserStorage::new(connection.clone()).await,
             mr_storage: MrStorage::new(connection.clone()).await,
             issue_storage: IssueStorage::new(connection.clone()).await,
-            lfs_storage: Arc::new(LocalStorage::init(config.lfs.lfs_obj_local_path.clone())),
+            lfs_storage,
         }
     }
 
@@ -101,6 +239,18 @@ impl Service {
             lfs_db_storage: LfsDbStorage::mock(),
             ztm_storage: ZTMStorage::mock(),
             mq_storage: MQStorage::mock(),
+            maintenance_storage: MaintenanceStorage::mock(),
+            secret_storage: SecretStorage::mock(),
+            release_storage: ReleaseStorage::mock(),
+            quota_storage: QuotaStorage::mock(),
+            signature_storage: SignatureStorage::mock(),
+            job_storage: JobStorage::mock(),
+            policy_storage: PolicyStorage::mock(),
+            feature_storage: FeatureStorage::mock(),
+            stats_storage: StatsStorage::mock(),
+            webhook_storage: WebhookStorage::mock(),
+            outbox_storage: OutboxStorage::mock(),
+            schedule_storage: ScheduleStorage::mock(),
             user_storage: UserStorage::mock(),
             lfs_storage: Arc::new(LocalStorage::init(
                 PathBuf::from(env::current_dir().unwrap().parent().unwrap()).join("tests"),
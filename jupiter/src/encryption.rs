@@ -0,0 +1,503 @@
+//! Envelope encryption of stored objects.
+//!
+//! Every object is encrypted with a data key of its own, which is stored in the header of the
+//! encrypted object wrapped by a master key from `storage.encryption`. Changing the master key
+//! therefore only rewrites these headers, see [`Keyring::rotate_stream`], the content stays as
+//! it is. Objects stored before encryption was enabled are read as they are.
+//!
+//! Plain objects are told apart from encrypted ones by their first bytes, so a plain object
+//! starting with [`MAGIC`] is stored behind the escape [`PLAIN`] while objects are only
+//! decrypted, and so is one starting with the escape itself. Objects stored before a master key
+//! was configured at all are not escaped.
+//!
+//! An encrypted object is laid out as
+//!
+//! ```text
+//! MAGIC | key id length (1) | key id | wrapped data key (60) | nonce prefix (8) | segments
+//! ```
+//!
+//! The content is sealed with AES-256-GCM in segments of [`SEGMENT_SIZE`] bytes so it can be
+//! streamed, the nonce of a segment is the nonce prefix followed by its index and the last
+//! segment is marked, so reordered or truncated content is detected.
+
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+use common::config::EncryptionConfig;
+use common::errors::MegaError;
+
+use crate::raw_storage::{collect, once, ByteStream};
+
+const MAGIC: &[u8; 8] = b"MEGAENC1";
+/// Put in front of plain objects which would otherwise be read as encrypted.
+const PLAIN: &[u8; 8] = b"MEGAPLN1";
+const KEY_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+const PREFIX_LEN: usize = 8;
+/// A data key sealed by a master key, with the nonce it was sealed with.
+const WRAPPED_KEY_LEN: usize = NONCE_LEN + KEY_LEN + TAG_LEN;
+/// Bytes of content sealed together.
+pub const SEGMENT_SIZE: usize = 64 * 1024;
+const SEALED_SEGMENT_SIZE: usize = SEGMENT_SIZE + TAG_LEN;
+
+/// The master keys objects are encrypted with.
+pub struct Keyring {
+    /// Id of the key new objects are encrypted with, `None` if objects are only decrypted
+    current: Option<String>,
+    keys: HashMap<String, LessSafeKey>,
+    rng: SystemRandom,
+}
+
+/// The parsed header of an encrypted object.
+struct Header {
+    key_id: String,
+    data_key: [u8; KEY_LEN],
+    prefix: [u8; PREFIX_LEN],
+}
+
+impl Keyring {
+    /// The keyring configured by `config`, `None` if no master key is configured.
+    pub fn from_config(config: &EncryptionConfig) -> Result<Option<Arc<Keyring>>, MegaError> {
+        let mut encoded = config.keys.clone();
+        if !config.key_command.is_empty() && !encoded.contains_key(&config.key_id) {
+            encoded.insert(config.key_id.clone(), run_key_command(&config.key_command)?);
+        }
+        if config.enable && !encoded.contains_key(&config.key_id) {
+            return Err(MegaError::with_message(&format!(
+                "master key {:?} is not configured",
+                config.key_id
+            )));
+        }
+        if encoded.is_empty() {
+            return Ok(None);
+        }
+        let mut keys = HashMap::new();
+        for (id, key) in encoded {
+            if id.is_empty() || id.len() > u8::MAX as usize {
+                return Err(MegaError::with_message(&format!(
+                    "invalid master key id {:?}",
+                    id
+                )));
+            }
+            let key = STANDARD
+                .decode(key.trim())
+                .ok()
+                .filter(|key| key.len() == KEY_LEN)
+                .ok_or_else(|| {
+                    MegaError::with_message(&format!(
+                        "master key {} is not {} bytes in base64",
+                        id, KEY_LEN
+                    ))
+                })?;
+            keys.insert(id, aead_key(&key)?);
+        }
+        Ok(Some(Arc::new(Keyring {
+            current: config.enable.then(|| config.key_id.clone()),
+            keys,
+            rng: SystemRandom::new(),
+        })))
+    }
+
+    /// Whether new objects are encrypted.
+    pub fn encrypts(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Encrypt `content` with a new data key, it is passed through if encryption is disabled,
+    /// escaped if it starts like an encrypted object.
+    pub fn encrypt_stream(&self, content: ByteStream) -> Result<ByteStream, MegaError> {
+        let Some(key_id) = &self.current else {
+            return Ok(escape_plain(content));
+        };
+        let mut data_key = [0; KEY_LEN];
+        let mut prefix = [0; PREFIX_LEN];
+        self.fill(&mut data_key)?;
+        self.fill(&mut prefix)?;
+        let header = Header {
+            key_id: key_id.clone(),
+            data_key,
+            prefix,
+        };
+        let head = Bytes::from(self.write_header(&header)?);
+        let segments = seal_stream(aead_key(&header.data_key)?, header.prefix, content);
+        Ok(stream::once(async { Ok(head) }).chain(segments).boxed())
+    }
+
+    /// Decrypt `content`, content which is not encrypted is passed through.
+    pub async fn decrypt_stream(&self, content: ByteStream) -> Result<ByteStream, MegaError> {
+        let mut reader = Reader::new(content);
+        let Some(header) = self.read_header(&mut reader).await? else {
+            return Ok(reader.into_stream());
+        };
+        Ok(open_stream(
+            aead_key(&header.data_key)?,
+            header.prefix,
+            reader,
+        ))
+    }
+
+    /// `content` encrypted for the current master key, `None` if it is already.
+    ///
+    /// Content encrypted with another master key keeps its data key, only the header is
+    /// written again. Content which is not encrypted is encrypted.
+    pub async fn rotate_stream(
+        &self,
+        content: ByteStream,
+    ) -> Result<Option<ByteStream>, MegaError> {
+        let Some(key_id) = &self.current else {
+            return Ok(None);
+        };
+        let mut reader = Reader::new(content);
+        match self.read_header(&mut reader).await? {
+            Some(header) if header.key_id == *key_id => Ok(None),
+            Some(mut header) => {
+                header.key_id = key_id.clone();
+                let head = Bytes::from(self.write_header(&header)?);
+                Ok(Some(
+                    stream::once(async { Ok(head) })
+                        .chain(reader.into_stream())
+                        .boxed(),
+                ))
+            }
+            None => Ok(Some(self.encrypt_stream(reader.into_stream())?)),
+        }
+    }
+
+    /// Encrypt `content` held in memory, see [`Self::encrypt_stream`].
+    pub async fn encrypt(&self, content: Bytes) -> Result<Bytes, MegaError> {
+        collect(self.encrypt_stream(once(content))?).await
+    }
+
+    /// Decrypt `content` held in memory, see [`Self::decrypt_stream`].
+    pub async fn decrypt(&self, content: Bytes) -> Result<Bytes, MegaError> {
+        collect(self.decrypt_stream(once(content)).await?).await
+    }
+
+    /// Rotate `content` held in memory, see [`Self::rotate_stream`].
+    pub async fn rotate(&self, content: Bytes) -> Result<Option<Bytes>, MegaError> {
+        match self.rotate_stream(once(content)).await? {
+            Some(rotated) => Ok(Some(collect(rotated).await?)),
+            None => Ok(None),
+        }
+    }
+
+    fn fill(&self, dest: &mut [u8]) -> Result<(), MegaError> {
+        self.rng
+            .fill(dest)
+            .map_err(|_| MegaError::with_message("failed to generate a data key"))
+    }
+
+    fn master_key(&self, id: &str) -> Result<&LessSafeKey, MegaError> {
+        self.keys
+            .get(id)
+            .ok_or_else(|| MegaError::with_message(&format!("master key {} is not configured", id)))
+    }
+
+    fn write_header(&self, header: &Header) -> Result<Vec<u8>, MegaError> {
+        let mut nonce = [0; NONCE_LEN];
+        self.fill(&mut nonce)?;
+        let mut wrapped = header.data_key.to_vec();
+        self.master_key(&header.key_id)?
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(header.key_id.as_bytes()),
+                &mut wrapped,
+            )
+            .map_err(|_| MegaError::with_message("failed to wrap a data key"))?;
+        let mut head = Vec::with_capacity(
+            MAGIC.len() + 1 + header.key_id.len() + WRAPPED_KEY_LEN + PREFIX_LEN,
+        );
+        head.extend_from_slice(MAGIC);
+        head.push(header.key_id.len() as u8);
+        head.extend_from_slice(header.key_id.as_bytes());
+        head.extend_from_slice(&nonce);
+        head.extend_from_slice(&wrapped);
+        head.extend_from_slice(&header.prefix);
+        Ok(head)
+    }
+
+    /// Read the header of an encrypted object from `reader`, `None` if the content is not
+    /// encrypted, in which case only the escape of plain content is consumed.
+    async fn read_header(&self, reader: &mut Reader) -> Result<Option<Header>, MegaError> {
+        reader.fill(MAGIC.len() + 1).await?;
+        if reader.buf.starts_with(PLAIN) {
+            reader.buf.advance(PLAIN.len());
+            return Ok(None);
+        }
+        if !reader.buf.starts_with(MAGIC) || reader.buf.len() <= MAGIC.len() {
+            return Ok(None);
+        }
+        let id_len = reader.buf[MAGIC.len()] as usize;
+        let len = MAGIC.len() + 1 + id_len + WRAPPED_KEY_LEN + PREFIX_LEN;
+        reader.fill(len).await?;
+        if reader.buf.len() < len {
+            return Err(MegaError::with_message("encrypted object is truncated"));
+        }
+        let head = reader.buf.split_to(len);
+        let (id, rest) = head[MAGIC.len() + 1..].split_at(id_len);
+        let key_id = String::from_utf8_lossy(id).into_owned();
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (wrapped, prefix) = rest.split_at(KEY_LEN + TAG_LEN);
+        let mut data_key = wrapped.to_vec();
+        self.master_key(&key_id)?
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).unwrap(),
+                Aad::from(key_id.as_bytes()),
+                &mut data_key,
+            )
+            .map_err(|_| {
+                MegaError::with_message(&format!(
+                    "data key of an object can't be unwrapped with master key {}",
+                    key_id
+                ))
+            })?;
+        Ok(Some(Header {
+            key_id,
+            data_key: data_key[..KEY_LEN].try_into().unwrap(),
+            prefix: prefix.try_into().unwrap(),
+        }))
+    }
+}
+
+/// `content` behind [`PLAIN`] if it starts with [`MAGIC`] or [`PLAIN`], as it is otherwise.
+fn escape_plain(content: ByteStream) -> ByteStream {
+    stream::once(async move {
+        let mut reader = Reader::new(content);
+        reader.fill(MAGIC.len()).await?;
+        let escaped = reader.buf.starts_with(MAGIC) || reader.buf.starts_with(PLAIN);
+        let escape = stream::iter(escaped.then(|| Ok(Bytes::from_static(PLAIN))));
+        Ok::<_, MegaError>(escape.chain(reader.into_stream()))
+    })
+    .try_flatten()
+    .boxed()
+}
+
+/// Run `command` printing a master key, e.g. fetching it from a KMS.
+fn run_key_command(command: &str) -> Result<String, MegaError> {
+    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    if !output.status.success() {
+        return Err(MegaError::with_message(&format!(
+            "master key command failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn aead_key(key: &[u8]) -> Result<LessSafeKey, MegaError> {
+    let key = UnboundKey::new(&AES_256_GCM, key)
+        .map_err(|_| MegaError::with_message("invalid encryption key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+fn segment_nonce(prefix: &[u8; PREFIX_LEN], index: u32) -> Nonce {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..].copy_from_slice(&index.to_be_bytes());
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// The segment marker, so the content can't be cut after a full segment.
+fn segment_aad(last: bool) -> Aad<[u8; 1]> {
+    Aad::from([last as u8])
+}
+
+/// Seal `content` segment by segment, the last one is sealed once `content` ended.
+fn seal_stream(key: LessSafeKey, prefix: [u8; PREFIX_LEN], content: ByteStream) -> ByteStream {
+    let state = (Reader::new(content), key, 0_u32, false);
+    stream::try_unfold(state, move |(mut reader, key, index, done)| async move {
+        if done {
+            return Ok(None);
+        }
+        // a full segment is only the last one if nothing follows
+        reader.fill(SEGMENT_SIZE + 1).await?;
+        let last = reader.buf.len() <= SEGMENT_SIZE;
+        let len = reader.buf.len().min(SEGMENT_SIZE);
+        let mut segment = reader.buf.split_to(len).to_vec();
+        key.seal_in_place_append_tag(
+            segment_nonce(&prefix, index),
+            segment_aad(last),
+            &mut segment,
+        )
+        .map_err(|_| MegaError::with_message("failed to encrypt an object"))?;
+        let next = index
+            .checked_add(1)
+            .ok_or_else(|| MegaError::with_message("object is too large to be encrypted"))?;
+        Ok(Some((Bytes::from(segment), (reader, key, next, last))))
+    })
+    .boxed()
+}
+
+/// Open the segments read by `reader`, sealed by [`seal_stream`].
+fn open_stream(key: LessSafeKey, prefix: [u8; PREFIX_LEN], reader: Reader) -> ByteStream {
+    let state = (reader, key, 0_u32, false);
+    stream::try_unfold(state, move |(mut reader, key, index, done)| async move {
+        if done {
+            return Ok(None);
+        }
+        reader.fill(SEALED_SEGMENT_SIZE + 1).await?;
+        let last = reader.buf.len() <= SEALED_SEGMENT_SIZE;
+        let len = reader.buf.len().min(SEALED_SEGMENT_SIZE);
+        let mut segment = reader.buf.split_to(len).to_vec();
+        let plain_len = key
+            .open_in_place(
+                segment_nonce(&prefix, index),
+                segment_aad(last),
+                &mut segment,
+            )
+            .map_err(|_| MegaError::with_message("encrypted object is corrupted"))?
+            .len();
+        segment.truncate(plain_len);
+        Ok(Some((
+            Bytes::from(segment),
+            (reader, key, index.wrapping_add(1), last),
+        )))
+    })
+    .boxed()
+}
+
+/// Buffers the start of a stream.
+struct Reader {
+    inner: ByteStream,
+    buf: BytesMut,
+    eof: bool,
+}
+
+impl Reader {
+    fn new(inner: ByteStream) -> Self {
+        Reader {
+            inner,
+            buf: BytesMut::new(),
+            eof: false,
+        }
+    }
+
+    /// Read until `len` bytes are buffered or the stream ended.
+    async fn fill(&mut self, len: usize) -> Result<(), MegaError> {
+        while self.buf.len() < len && !self.eof {
+            match self.inner.try_next().await? {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None => self.eof = true,
+            }
+        }
+        Ok(())
+    }
+
+    /// The buffered bytes followed by the rest of the stream.
+    fn into_stream(mut self) -> ByteStream {
+        let head = self.buf.copy_to_bytes(self.buf.len());
+        let head = stream::iter((!head.is_empty()).then_some(Ok(head)));
+        head.chain(self.inner).boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use bytes::Bytes;
+
+    use common::config::EncryptionConfig;
+
+    use super::{Keyring, MAGIC, PLAIN, SEGMENT_SIZE};
+
+    fn keyring(current: &str) -> std::sync::Arc<Keyring> {
+        let config = EncryptionConfig {
+            enable: true,
+            key_id: current.to_owned(),
+            keys: HashMap::from([
+                ("k1".to_owned(), STANDARD.encode([1; 32])),
+                ("k2".to_owned(), STANDARD.encode([2; 32])),
+            ]),
+            key_command: String::new(),
+        };
+        Keyring::from_config(&config).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_and_decrypt() {
+        let keyring = keyring("k1");
+        for len in [0, 10, SEGMENT_SIZE, SEGMENT_SIZE * 2 + 7] {
+            let content = Bytes::from((0..len).map(|i| i as u8).collect::<Vec<_>>());
+            let encrypted = keyring.encrypt(content.clone()).await.unwrap();
+            assert!(encrypted.starts_with(MAGIC));
+            assert_eq!(keyring.decrypt(encrypted).await.unwrap(), content);
+        }
+        // content stored before encryption was enabled is read as it is
+        let plain = Bytes::from_static(b"plain");
+        assert_eq!(keyring.decrypt(plain.clone()).await.unwrap(), plain);
+    }
+
+    #[tokio::test]
+    async fn test_plain_content_like_encrypted() {
+        let config = EncryptionConfig {
+            enable: false,
+            key_id: "k1".to_owned(),
+            keys: HashMap::from([("k1".to_owned(), STANDARD.encode([1; 32]))]),
+            key_command: String::new(),
+        };
+        let decrypting = Keyring::from_config(&config).unwrap().unwrap();
+        let mut forged = MAGIC.to_vec();
+        forged.extend_from_slice(&[2; 100]);
+        let mut escape = PLAIN.to_vec();
+        escape.extend_from_slice(b"content");
+        let contents = [forged, escape, PLAIN.to_vec()];
+        for content in contents.map(Bytes::from) {
+            let stored = decrypting.encrypt(content.clone()).await.unwrap();
+            assert!(stored.starts_with(PLAIN));
+            assert_eq!(decrypting.decrypt(stored.clone()).await.unwrap(), content);
+            // and once encryption is enabled, also after rotating it
+            let keyring = keyring("k1");
+            assert_eq!(keyring.decrypt(stored.clone()).await.unwrap(), content);
+            let rotated = keyring.rotate(stored).await.unwrap().unwrap();
+            assert!(rotated.starts_with(MAGIC));
+            assert_eq!(keyring.decrypt(rotated).await.unwrap(), content);
+        }
+        // other plain content is stored as it is
+        let plain = Bytes::from_static(b"plain");
+        assert_eq!(decrypting.encrypt(plain.clone()).await.unwrap(), plain);
+    }
+
+    #[tokio::test]
+    async fn test_tampered_content() {
+        let keyring = keyring("k1");
+        let content = Bytes::from(vec![7; SEGMENT_SIZE * 2]);
+        let encrypted = keyring.encrypt(content).await.unwrap();
+        let mut flipped = encrypted.to_vec();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        assert!(keyring.decrypt(Bytes::from(flipped)).await.is_err());
+        // dropping the last segment is noticed as well
+        let cut = encrypted.slice(..encrypted.len() - 16);
+        assert!(keyring.decrypt(cut).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rotate() {
+        let content = Bytes::from(vec![3; 1000]);
+        let encrypted = keyring("k1").encrypt(content.clone()).await.unwrap();
+        let keyring = keyring("k2");
+        let rotated = keyring.rotate(encrypted.clone()).await.unwrap().unwrap();
+        // only the header is written again
+        assert_eq!(rotated.len(), encrypted.len());
+        assert_eq!(
+            rotated[rotated.len() - 1000..],
+            encrypted[encrypted.len() - 1000..]
+        );
+        assert!(keyring.rotate(rotated.clone()).await.unwrap().is_none());
+        assert_eq!(keyring.decrypt(rotated).await.unwrap(), content);
+        // plain content is encrypted
+        let rotated = keyring.rotate(content.clone()).await.unwrap().unwrap();
+        assert_eq!(keyring.decrypt(rotated).await.unwrap(), content);
+    }
+}
@@ -1,13 +1,16 @@
 use std::fs::{self, OpenOptions};
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 use async_trait::async_trait;
-use bytes::Bytes;
+use futures::TryStreamExt;
+use tokio::io::AsyncWriteExt;
 
 use common::errors::MegaError;
 
 use crate::lfs_storage::LfsStorage;
+use crate::raw_storage::{file_stream, ByteStream};
 
 #[derive(Default)]
 pub struct LocalStorage {
@@ -63,30 +66,30 @@ impl LfsStorage for LocalStorage {
         Ok(())
     }
 
-    async fn get_object(&self, object_id: &str) -> Result<Bytes, MegaError> {
+    async fn get_object_stream(&self, object_id: &str) -> Result<ByteStream, MegaError> {
         let path = Path::new(&self.base_path)
             .join("objects")
             .join(self.transform_path(object_id));
-        let mut file =
-            fs::File::open(&path).unwrap_or_else(|_| panic!("Open file:{:?} failed!", path));
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).unwrap();
-        Ok(Bytes::from(buffer))
+        Ok(file_stream(tokio::fs::File::open(&path).await?))
     }
 
-    async fn put_object(
+    async fn put_object_stream(
         &self,
         object_id: &str,
-        body_content: &[u8],
+        mut content: ByteStream,
     ) -> Result<String, MegaError> {
         let path = Path::new(&self.base_path)
             .join("objects")
             .join(self.transform_path(object_id));
-        let dir = path.parent().unwrap();
-        fs::create_dir_all(dir).expect("Create directory failed!");
-
-        let mut file = fs::File::create(&path).expect("Open file failed");
-        file.write_all(body_content).expect("Write file failed");
+        tokio::fs::create_dir_all(path.parent().unwrap()).await?;
+        // readers never see a partly written object
+        let tmp = path.with_extension("tmp");
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        while let Some(chunk) = content.try_next().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, &path).await?;
         Ok(path.to_str().unwrap().to_string())
     }
 
@@ -96,6 +99,49 @@ impl LfsStorage for LocalStorage {
             .join(self.transform_path(object_id));
         Path::exists(&path)
     }
+
+    fn list_objects(&self) -> Result<Vec<(String, SystemTime)>, MegaError> {
+        let root = Path::new(&self.base_path).join("objects");
+        let mut objects = Vec::new();
+        if !root.exists() {
+            return Ok(objects);
+        }
+        // objects are stored as `objects/ab/cd/ef...`, see `transform_path`
+        for first in fs::read_dir(&root)? {
+            let first = first?;
+            if !first.file_type()?.is_dir() {
+                continue;
+            }
+            for second in fs::read_dir(first.path())? {
+                let second = second?;
+                if !second.file_type()?.is_dir() {
+                    continue;
+                }
+                for file in fs::read_dir(second.path())? {
+                    let file = file?;
+                    // still being written, see `put_object_stream`
+                    if file.path().extension().is_some_and(|e| e == "tmp") {
+                        continue;
+                    }
+                    let oid = format!(
+                        "{}{}{}",
+                        first.file_name().to_string_lossy(),
+                        second.file_name().to_string_lossy(),
+                        file.file_name().to_string_lossy()
+                    );
+                    objects.push((oid, file.metadata()?.modified()?));
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    fn delete_object(&self, object_id: &str) -> Result<(), MegaError> {
+        let path = Path::new(&self.base_path)
+            .join("objects")
+            .join(self.transform_path(object_id));
+        Ok(fs::remove_file(path)?)
+    }
 }
 
 #[cfg(test)]
@@ -104,7 +150,11 @@ mod tests {
     use std::path::Path;
     use std::{env, path::PathBuf};
 
+    use bytes::Bytes;
+    use futures::{stream, StreamExt};
+
     use crate::lfs_storage::{local_storage::LocalStorage, LfsStorage};
+    use crate::raw_storage::collect;
 
     #[tokio::test]
     async fn test_content_store() {
@@ -120,6 +170,38 @@ mod tests {
         assert!(local_storage.exist_object(&oid));
     }
 
+    #[tokio::test]
+    async fn test_list_and_delete_object() {
+        let oid = "1e0f4b4c8e3b0a7d6f2c9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f1a0b9c8d".to_owned();
+        let test_path = env::temp_dir().join("mega_lfs_list_test");
+        let storage = LocalStorage::init(test_path.clone());
+        storage.put_object(&oid, b"content").await.unwrap();
+
+        let objects = storage.list_objects().unwrap();
+        assert!(objects.iter().any(|(id, _)| *id == oid));
+
+        storage.delete_object(&oid).unwrap();
+        assert!(!storage.exist_object(&oid));
+        fs::remove_dir_all(test_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_object_stream() {
+        let oid = "2d0f4b4c8e3b0a7d6f2c9a8b7c6d5e4f3a2b1c0d9e8f7a6b5c4d3e2f1a0b9c8d".to_owned();
+        let test_path = env::temp_dir().join("mega_lfs_stream_test");
+        let storage = LocalStorage::init(test_path.clone());
+        let pieces = vec![Ok(Bytes::from("first ")), Ok(Bytes::from("second"))];
+        storage
+            .put_object_stream(&oid, stream::iter(pieces).boxed())
+            .await
+            .unwrap();
+        assert_eq!(storage.list_objects().unwrap().len(), 1);
+
+        let content = collect(storage.get_object_stream(&oid).await.unwrap()).await;
+        assert_eq!(content.unwrap(), "first second");
+        fs::remove_dir_all(test_path).unwrap();
+    }
+
     #[tokio::test]
     async fn test_put_ref() {
         let test_path = PathBuf::from(env::current_dir().unwrap().parent().unwrap()).join("test");
@@ -1,6 +1,7 @@
 use std::{
     path::{self, PathBuf},
     sync::Arc,
+    time::SystemTime,
 };
 
 use async_trait::async_trait;
@@ -8,7 +9,9 @@ use bytes::Bytes;
 
 use common::errors::MegaError;
 
+use crate::encryption::Keyring;
 use crate::lfs_storage::local_storage::LocalStorage;
+use crate::raw_storage::{self, ByteStream};
 
 pub mod local_storage;
 
@@ -27,12 +30,39 @@ pub trait LfsStorage: Sync + Send {
         ref_hash: &str,
     ) -> Result<(), MegaError>;
 
-    async fn get_object(&self, object_id: &str) -> Result<Bytes, MegaError>;
+    /// The content of an object, read piece by piece.
+    async fn get_object_stream(&self, object_id: &str) -> Result<ByteStream, MegaError>;
 
-    async fn put_object(&self, object_id: &str, body_content: &[u8]) -> Result<String, MegaError>;
+    /// Store an object as its content arrives, it is not visible before it is complete.
+    async fn put_object_stream(
+        &self,
+        object_id: &str,
+        content: ByteStream,
+    ) -> Result<String, MegaError>;
+
+    /// The content of a small object held in memory, see [`Self::get_object_stream`].
+    async fn get_object(&self, object_id: &str) -> Result<Bytes, MegaError> {
+        raw_storage::collect(self.get_object_stream(object_id).await?).await
+    }
+
+    async fn put_object(&self, object_id: &str, body_content: &[u8]) -> Result<String, MegaError> {
+        let content = raw_storage::once(Bytes::copy_from_slice(body_content));
+        self.put_object_stream(object_id, content).await
+    }
 
     fn exist_object(&self, object_id: &str) -> bool;
 
+    /// List ids of all stored objects together with their last modification time.
+    fn list_objects(&self) -> Result<Vec<(String, SystemTime)>, MegaError>;
+
+    fn delete_object(&self, object_id: &str) -> Result<(), MegaError>;
+
+    /// Encrypt the object again for the current master key, returns whether it was rewritten.
+    /// Storages without encryption have nothing to do.
+    async fn rotate_object(&self, _object_id: &str) -> Result<bool, MegaError> {
+        Ok(false)
+    }
+
     fn transform_path(&self, sha1: &str) -> String {
         if sha1.len() < 5 {
             sha1.to_string()
@@ -57,6 +87,89 @@ pub async fn init(storage_type: String, base_path: PathBuf) -> Arc<dyn LfsStorag
     }
 }
 
+/// `storage` encrypting objects with `keyring`, if there is one.
+pub fn encrypted(
+    storage: Arc<dyn LfsStorage>,
+    keyring: Option<Arc<Keyring>>,
+) -> Arc<dyn LfsStorage> {
+    match keyring {
+        Some(keyring) => Arc::new(EncryptedStorage {
+            inner: storage,
+            keyring,
+        }),
+        None => storage,
+    }
+}
+
+/// Encrypts the objects put into another storage and decrypts them when they are read, see
+/// [`crate::encryption`]. Refs are kept as they are.
+pub struct EncryptedStorage {
+    inner: Arc<dyn LfsStorage>,
+    keyring: Arc<Keyring>,
+}
+
+#[async_trait]
+impl LfsStorage for EncryptedStorage {
+    async fn get_ref(&self, repo_id: i64, ref_name: &str) -> Result<String, MegaError> {
+        self.inner.get_ref(repo_id, ref_name).await
+    }
+
+    async fn put_ref(&self, repo_id: i64, ref_name: &str, ref_hash: &str) -> Result<(), MegaError> {
+        self.inner.put_ref(repo_id, ref_name, ref_hash).await
+    }
+
+    async fn delete_ref(&self, repo_id: i64, ref_name: &str) -> Result<(), MegaError> {
+        self.inner.delete_ref(repo_id, ref_name).await
+    }
+
+    async fn update_ref(
+        &self,
+        repo_id: i64,
+        ref_name: &str,
+        ref_hash: &str,
+    ) -> Result<(), MegaError> {
+        self.inner.update_ref(repo_id, ref_name, ref_hash).await
+    }
+
+    async fn get_object_stream(&self, object_id: &str) -> Result<ByteStream, MegaError> {
+        let content = self.inner.get_object_stream(object_id).await?;
+        self.keyring.decrypt_stream(content).await
+    }
+
+    async fn put_object_stream(
+        &self,
+        object_id: &str,
+        content: ByteStream,
+    ) -> Result<String, MegaError> {
+        let content = self.keyring.encrypt_stream(content)?;
+        self.inner.put_object_stream(object_id, content).await
+    }
+
+    fn exist_object(&self, object_id: &str) -> bool {
+        self.inner.exist_object(object_id)
+    }
+
+    fn list_objects(&self) -> Result<Vec<(String, SystemTime)>, MegaError> {
+        self.inner.list_objects()
+    }
+
+    fn delete_object(&self, object_id: &str) -> Result<(), MegaError> {
+        self.inner.delete_object(object_id)
+    }
+
+    async fn rotate_object(&self, object_id: &str) -> Result<bool, MegaError> {
+        let content = self.inner.get_object_stream(object_id).await?;
+        match self.keyring.rotate_stream(content).await? {
+            // the object is replaced once it is written completely
+            Some(rotated) => {
+                self.inner.put_object_stream(object_id, rotated).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
 pub fn mock() -> Arc<dyn LfsStorage> {
     Arc::new(LocalStorage::init(PathBuf::from("/")))
 }
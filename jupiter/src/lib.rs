@@ -1,4 +1,10 @@
+pub mod backup;
+pub mod cache;
 pub mod context;
+pub mod encryption;
 pub mod lfs_storage;
+pub mod migration;
+pub mod raw_storage;
 pub mod storage;
 pub mod utils;
+pub mod worker;
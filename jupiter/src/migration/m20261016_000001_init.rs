@@ -0,0 +1,87 @@
+use sea_orm::DbBackend;
+use sea_orm_migration::prelude::*;
+
+const SQLITE_SQL: &str = include_str!("../../../sql/sqlite/sqlite_20261016_init.sql");
+const POSTGRES_SQL: &str = include_str!("../../../sql/postgres/pg_20261016__init.sql");
+const MYSQL_SQL: &str = include_str!("../../../sql/mysql/mysql_20261016_init.sql");
+
+/// The schema of the init sql files.
+///
+/// Databases set up from the init sql files before migrations existed already have it, the
+/// migration is only recorded for them.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.has_table("mega_commit").await? {
+            return Ok(());
+        }
+        let backend = manager.get_database_backend();
+        let sql = init_sql(backend);
+        if backend == DbBackend::MySql {
+            // mysql runs one statement at a time
+            for statement in sql.split(';').filter(|s| !s.trim().is_empty()) {
+                manager
+                    .get_connection()
+                    .execute_unprepared(statement)
+                    .await?;
+            }
+        } else {
+            manager.get_connection().execute_unprepared(sql).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let sql = init_sql(manager.get_database_backend());
+        for table in created_tables(sql) {
+            manager
+                .drop_table(
+                    Table::drop()
+                        .table(Alias::new(table))
+                        .if_exists()
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn init_sql(backend: DbBackend) -> &'static str {
+    match backend {
+        DbBackend::Sqlite => SQLITE_SQL,
+        DbBackend::Postgres => POSTGRES_SQL,
+        DbBackend::MySql => MYSQL_SQL,
+    }
+}
+
+/// Names of the tables created by `sql`, in reverse order of creation.
+fn created_tables(sql: &str) -> Vec<&str> {
+    let mut tables: Vec<&str> = sql
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("CREATE TABLE IF NOT EXISTS "))
+        .filter_map(|rest| rest.split_whitespace().next())
+        .map(|name| name.trim_matches(['"', '`']))
+        .collect();
+    tables.reverse();
+    tables
+}
+
+#[cfg(test)]
+mod test {
+    use super::{created_tables, MYSQL_SQL, POSTGRES_SQL, SQLITE_SQL};
+
+    #[test]
+    fn test_created_tables() {
+        let tables = created_tables(SQLITE_SQL);
+        assert!(tables.contains(&"mega_commit"));
+        assert!(tables.contains(&"virtual_repo"));
+        assert!(tables.iter().all(|t| !t.contains('"')));
+        let postgres = created_tables(POSTGRES_SQL);
+        assert!(tables.iter().all(|t| postgres.contains(t)));
+        assert_eq!(created_tables(MYSQL_SQL), tables);
+    }
+}
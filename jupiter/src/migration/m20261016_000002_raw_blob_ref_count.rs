@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+/// Count the blob records of the monorepo and the import repositories which share the content
+/// of each raw blob.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RawBlob::Table)
+                    .add_column(
+                        ColumnDef::new(RawBlob::RefCount)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // content stored before is counted once for every record referring to it
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "UPDATE raw_blob SET ref_count = \
+                 (SELECT COUNT(*) FROM git_blob WHERE git_blob.blob_id = raw_blob.sha1) + \
+                 (SELECT COUNT(*) FROM mega_blob WHERE mega_blob.blob_id = raw_blob.sha1)",
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RawBlob::Table)
+                    .drop_column(RawBlob::RefCount)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RawBlob {
+    Table,
+    RefCount,
+}
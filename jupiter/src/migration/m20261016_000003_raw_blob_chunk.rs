@@ -0,0 +1,72 @@
+use sea_orm_migration::prelude::*;
+
+/// The chunks large blobs are split into, in order. Every chunk is a raw blob of its own.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RawBlobChunk::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RawBlobChunk::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RawBlobChunk::BlobId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RawBlobChunk::ChunkIndex)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RawBlobChunk::ChunkId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(RawBlobChunk::Size).big_integer().not_null())
+                    .col(
+                        ColumnDef::new(RawBlobChunk::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_rbc_blob_id")
+                    .table(RawBlobChunk::Table)
+                    .col(RawBlobChunk::BlobId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RawBlobChunk::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RawBlobChunk {
+    Table,
+    Id,
+    BlobId,
+    ChunkIndex,
+    ChunkId,
+    Size,
+    CreatedAt,
+}
@@ -0,0 +1,54 @@
+use sea_orm_migration::prelude::*;
+
+/// Deleted import repositories stay in the trash until they are restored or purged.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // sqlite alters one column at a time
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .add_column(ColumnDef::new(GitRepo::DeletedAt).date_time().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .add_column(ColumnDef::new(GitRepo::DeletedBy).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .drop_column(GitRepo::DeletedBy)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(GitRepo::Table)
+                    .drop_column(GitRepo::DeletedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum GitRepo {
+    Table,
+    DeletedAt,
+    DeletedBy,
+}
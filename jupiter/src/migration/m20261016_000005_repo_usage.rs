@@ -0,0 +1,127 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend, Statement};
+
+use common::utils::generate_id;
+
+/// The storage used by each repository, checked against the quotas on push and LFS upload.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RepoUsage::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RepoUsage::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoUsage::Path)
+                            .string_len(512)
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RepoUsage::GitSize)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(RepoUsage::LfsSize)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(RepoUsage::UpdatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        // blobs stored before are counted by their import repository, the blobs of the
+        // monorepo and all LFS objects by its root
+        let backend = manager.get_database_backend();
+        let int = match backend {
+            DbBackend::MySql => "SIGNED",
+            _ => "BIGINT",
+        };
+        let conn = manager.get_connection();
+        let mut usage: Vec<(String, i64, i64)> = Vec::new();
+        let rows = conn
+            .query_all(Statement::from_string(
+                backend,
+                format!(
+                    "SELECT git_repo.repo_path, CAST(SUM(git_blob.size) AS {}) \
+                     FROM git_repo JOIN git_blob ON git_blob.repo_id = git_repo.id \
+                     GROUP BY git_repo.repo_path",
+                    int
+                ),
+            ))
+            .await?;
+        for row in rows {
+            usage.push((row.try_get_by_index(0)?, row.try_get_by_index(1)?, 0));
+        }
+        let total = |table: &str| {
+            let sql = format!(
+                "SELECT CAST(COALESCE(SUM(size), 0) AS {}) FROM {}",
+                int, table
+            );
+            conn.query_one(Statement::from_string(backend, sql))
+        };
+        let mono: i64 = match total("mega_blob").await? {
+            Some(row) => row.try_get_by_index(0)?,
+            None => 0,
+        };
+        let lfs: i64 = match total("lfs_objects").await? {
+            Some(row) => row.try_get_by_index(0)?,
+            None => 0,
+        };
+        if mono > 0 || lfs > 0 {
+            usage.push(("/".to_owned(), mono, lfs));
+        }
+        let now = chrono::Utc::now().naive_utc();
+        for (path, git_size, lfs_size) in usage {
+            let insert = Query::insert()
+                .into_table(RepoUsage::Table)
+                .columns([
+                    RepoUsage::Id,
+                    RepoUsage::Path,
+                    RepoUsage::GitSize,
+                    RepoUsage::LfsSize,
+                    RepoUsage::UpdatedAt,
+                ])
+                .values_panic([
+                    generate_id().into(),
+                    path.into(),
+                    git_size.into(),
+                    lfs_size.into(),
+                    now.into(),
+                ])
+                .to_owned();
+            manager.exec_stmt(insert).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RepoUsage::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RepoUsage {
+    Table,
+    Id,
+    Path,
+    GitSize,
+    LfsSize,
+    UpdatedAt,
+}
@@ -0,0 +1,90 @@
+use sea_orm_migration::prelude::*;
+
+/// The files and directories changed by each commit of the monorepo, history filtered by path
+/// is looked up by them instead of comparing the trees of every commit.
+///
+/// Commits saved before are not recorded, their history starts with the next commit.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MegaCommitPath::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MegaCommitPath::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaCommitPath::RefPath)
+                            .string_len(512)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaCommitPath::RefName)
+                            .string_len(255)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaCommitPath::CommitId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaCommitPath::Path)
+                            .string_len(512)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaCommitPath::CommittedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // pages of the history of a path are read in commit order
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_mcp_path")
+                    .table(MegaCommitPath::Table)
+                    .col(MegaCommitPath::Path)
+                    .col(MegaCommitPath::CommittedAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_mcp_commit_id")
+                    .table(MegaCommitPath::Table)
+                    .col(MegaCommitPath::CommitId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MegaCommitPath::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MegaCommitPath {
+    Table,
+    Id,
+    RefPath,
+    RefName,
+    CommitId,
+    Path,
+    CommittedAt,
+}
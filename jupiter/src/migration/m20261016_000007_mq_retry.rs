@@ -0,0 +1,102 @@
+use sea_orm_migration::prelude::*;
+
+/// Processing state, attempts and retry time of queued messages, failed messages are retried
+/// and dead-lettered after too many attempts.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // messages stored before were processed when they were sent, sqlite alters one column
+        // at a time
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .add_column(
+                        ColumnDef::new(MqStorage::State)
+                            .string()
+                            .not_null()
+                            .default("done"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .add_column(
+                        ColumnDef::new(MqStorage::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .add_column(ColumnDef::new(MqStorage::VisibleAt).date_time().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .add_column(ColumnDef::new(MqStorage::LastError).text().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_mq_state")
+                    .table(MqStorage::Table)
+                    .col(MqStorage::State)
+                    .col(MqStorage::VisibleAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_mq_state")
+                    .table(MqStorage::Table)
+                    .to_owned(),
+            )
+            .await?;
+        for column in [
+            MqStorage::LastError,
+            MqStorage::VisibleAt,
+            MqStorage::Attempts,
+            MqStorage::State,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MqStorage::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum MqStorage {
+    Table,
+    State,
+    Attempts,
+    VisibleAt,
+    LastError,
+}
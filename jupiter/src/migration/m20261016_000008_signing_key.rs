@@ -0,0 +1,123 @@
+use sea_orm_migration::prelude::*;
+
+/// GPG and SSH keys users sign commits and tags with, and the cached results of verifying the
+/// signatures of commits and tags against them.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SigningKey::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SigningKey::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SigningKey::UserId).big_integer().not_null())
+                    .col(ColumnDef::new(SigningKey::KeyType).string().not_null())
+                    .col(ColumnDef::new(SigningKey::Title).text().not_null())
+                    .col(ColumnDef::new(SigningKey::Key).text().not_null())
+                    .col(
+                        ColumnDef::new(SigningKey::Fingerprint)
+                            .string_len(128)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(SigningKey::CreatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_sk_user_id")
+                    .table(SigningKey::Table)
+                    .col(SigningKey::UserId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(ObjectSignature::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ObjectSignature::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ObjectSignature::ObjectId)
+                            .string_len(40)
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(ObjectSignature::Status).string().not_null())
+                    .col(
+                        ColumnDef::new(ObjectSignature::SignerEmail)
+                            .string_len(255)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ObjectSignature::UserId).big_integer())
+                    .col(ColumnDef::new(ObjectSignature::KeyId).big_integer())
+                    .col(ColumnDef::new(ObjectSignature::Reason).text())
+                    .col(
+                        ColumnDef::new(ObjectSignature::VerifiedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // results are dropped when the keys of a user change
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_os_signer_email")
+                    .table(ObjectSignature::Table)
+                    .col(ObjectSignature::SignerEmail)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ObjectSignature::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(SigningKey::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SigningKey {
+    Table,
+    Id,
+    UserId,
+    KeyType,
+    Title,
+    Key,
+    Fingerprint,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ObjectSignature {
+    Table,
+    Id,
+    ObjectId,
+    Status,
+    SignerEmail,
+    UserId,
+    KeyId,
+    Reason,
+    VerifiedAt,
+}
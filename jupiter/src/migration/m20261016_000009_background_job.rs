@@ -0,0 +1,90 @@
+use sea_orm_migration::prelude::*;
+
+/// Jobs run in the background by the worker pool of the services, e.g. imports, garbage
+/// collection, mirroring and indexing.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(BackgroundJob::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BackgroundJob::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(BackgroundJob::JobType)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(BackgroundJob::Payload).text().not_null())
+                    .col(ColumnDef::new(BackgroundJob::State).string().not_null())
+                    .col(
+                        ColumnDef::new(BackgroundJob::ScheduledAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BackgroundJob::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(BackgroundJob::LockedBy).string_len(64))
+                    .col(ColumnDef::new(BackgroundJob::LockedUntil).date_time())
+                    .col(ColumnDef::new(BackgroundJob::LastError).text())
+                    .col(
+                        ColumnDef::new(BackgroundJob::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BackgroundJob::UpdatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // workers look for due jobs by their state and time
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_bj_state")
+                    .table(BackgroundJob::Table)
+                    .col(BackgroundJob::State)
+                    .col(BackgroundJob::ScheduledAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(BackgroundJob::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BackgroundJob {
+    Table,
+    Id,
+    JobType,
+    Payload,
+    State,
+    ScheduledAt,
+    Attempts,
+    LockedBy,
+    LockedUntil,
+    LastError,
+    CreatedAt,
+    UpdatedAt,
+}
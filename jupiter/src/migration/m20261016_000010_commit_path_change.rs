@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+/// How each recorded path was changed and its object ids before and after the commit, so blame
+/// reads the versions of a file from the index instead of walking every commit.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // paths recorded before have no object ids, sqlite alters one column at a time
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MegaCommitPath::Table)
+                    .add_column(
+                        ColumnDef::new(MegaCommitPath::ChangeType)
+                            .string()
+                            .not_null()
+                            .default("modified"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MegaCommitPath::Table)
+                    .add_column(ColumnDef::new(MegaCommitPath::OldId).string_len(40).null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MegaCommitPath::Table)
+                    .add_column(ColumnDef::new(MegaCommitPath::NewId).string_len(40).null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            MegaCommitPath::NewId,
+            MegaCommitPath::OldId,
+            MegaCommitPath::ChangeType,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(MegaCommitPath::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum MegaCommitPath {
+    Table,
+    ChangeType,
+    OldId,
+    NewId,
+}
@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+/// Whether each open merge request merges cleanly into the current monorepo, and the paths
+/// in conflict if it does not, kept up to date as either side moves.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MegaMrMergeability::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MegaMrMergeability::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaMrMergeability::MrLink)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaMrMergeability::Status)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaMrMergeability::ConflictPaths)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaMrMergeability::SourceHash)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaMrMergeability::TargetHash)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MegaMrMergeability::CheckedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MegaMrMergeability::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MegaMrMergeability {
+    Table,
+    Id,
+    MrLink,
+    Status,
+    ConflictPaths,
+    SourceHash,
+    TargetHash,
+    CheckedAt,
+}
@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+/// Versions of the Cedar policies managed through the API, one row for every change of the
+/// policies of a namespace.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PolicyVersion::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PolicyVersion::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PolicyVersion::Namespace)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PolicyVersion::Version).integer().not_null())
+                    .col(ColumnDef::new(PolicyVersion::Content).text())
+                    .col(ColumnDef::new(PolicyVersion::Operator).string().not_null())
+                    .col(
+                        ColumnDef::new(PolicyVersion::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_pv_namespace_version")
+                    .table(PolicyVersion::Table)
+                    .col(PolicyVersion::Namespace)
+                    .col(PolicyVersion::Version)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PolicyVersion::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PolicyVersion {
+    Table,
+    Id,
+    Namespace,
+    Version,
+    Content,
+    Operator,
+    CreatedAt,
+}
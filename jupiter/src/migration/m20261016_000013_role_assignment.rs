@@ -0,0 +1,78 @@
+use sea_orm_migration::prelude::*;
+
+/// Roles assigned to users, teams and organizations on directories.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RoleAssignment::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RoleAssignment::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RoleAssignment::Path)
+                            .string_len(512)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RoleAssignment::SubjectType)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(RoleAssignment::Subject).string().not_null())
+                    .col(ColumnDef::new(RoleAssignment::Role).string().not_null())
+                    .col(
+                        ColumnDef::new(RoleAssignment::CreatedBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(RoleAssignment::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // a subject has one role on a path, assigning another replaces it
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ra_path_subject")
+                    .table(RoleAssignment::Table)
+                    .col(RoleAssignment::Path)
+                    .col(RoleAssignment::SubjectType)
+                    .col(RoleAssignment::Subject)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RoleAssignment::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RoleAssignment {
+    Table,
+    Id,
+    Path,
+    SubjectType,
+    Subject,
+    Role,
+    CreatedBy,
+    CreatedAt,
+}
@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+/// Audit trail of authorization decisions with the policies which made them.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuthDecision::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(AuthDecision::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(AuthDecision::Principal).string().not_null())
+                    .col(ColumnDef::new(AuthDecision::Action).string().not_null())
+                    .col(ColumnDef::new(AuthDecision::Resource).text().not_null())
+                    .col(ColumnDef::new(AuthDecision::Allowed).boolean().not_null())
+                    .col(ColumnDef::new(AuthDecision::Policies).text().not_null())
+                    .col(ColumnDef::new(AuthDecision::Errors).text())
+                    .col(
+                        ColumnDef::new(AuthDecision::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ad_principal")
+                    .table(AuthDecision::Table)
+                    .col(AuthDecision::Principal)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ad_created_at")
+                    .table(AuthDecision::Table)
+                    .col(AuthDecision::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AuthDecision::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuthDecision {
+    Table,
+    Id,
+    Principal,
+    Action,
+    Resource,
+    Allowed,
+    Policies,
+    Errors,
+    CreatedAt,
+}
@@ -0,0 +1,104 @@
+use sea_orm_migration::prelude::*;
+
+/// Temporary access admins grant themselves in an emergency, with the decisions made by it
+/// marked in the audit trail.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(BreakGlassGrant::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(BreakGlassGrant::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(BreakGlassGrant::User).string().not_null())
+                    .col(
+                        ColumnDef::new(BreakGlassGrant::Path)
+                            .string_len(512)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(BreakGlassGrant::Reason).text().not_null())
+                    .col(
+                        ColumnDef::new(BreakGlassGrant::ExpiresAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(BreakGlassGrant::RevokedAt)
+                            .date_time()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(BreakGlassGrant::RevokedBy).string().null())
+                    .col(
+                        ColumnDef::new(BreakGlassGrant::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_bgg_expires_at")
+                    .table(BreakGlassGrant::Table)
+                    .col(BreakGlassGrant::ExpiresAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthDecision::Table)
+                    .add_column(
+                        ColumnDef::new(AuthDecision::BreakGlass)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(AuthDecision::Table)
+                    .drop_column(AuthDecision::BreakGlass)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(BreakGlassGrant::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum BreakGlassGrant {
+    Table,
+    Id,
+    User,
+    Path,
+    Reason,
+    ExpiresAt,
+    RevokedAt,
+    RevokedBy,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum AuthDecision {
+    Table,
+    BreakGlass,
+}
@@ -0,0 +1,114 @@
+use sea_orm_migration::prelude::*;
+
+/// Service accounts of CI systems and automation with their access tokens.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ServiceAccount::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ServiceAccount::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ServiceAccount::Name)
+                            .string_len(64)
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ServiceAccount::Description)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ServiceAccount::CreatedBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ServiceAccount::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(ServiceAccountToken::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ServiceAccountToken::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ServiceAccountToken::AccountId)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ServiceAccountToken::Token).text().not_null())
+                    .col(
+                        ColumnDef::new(ServiceAccountToken::CreatedBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ServiceAccountToken::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_sat_account_id")
+                    .table(ServiceAccountToken::Table)
+                    .col(ServiceAccountToken::AccountId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ServiceAccountToken::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(ServiceAccount::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ServiceAccount {
+    Table,
+    Id,
+    Name,
+    Description,
+    CreatedBy,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ServiceAccountToken {
+    Table,
+    Id,
+    AccountId,
+    Token,
+    CreatedBy,
+    CreatedAt,
+}
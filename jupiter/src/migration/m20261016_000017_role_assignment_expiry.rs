@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+/// Role assignments can be temporary, they are removed once they expired.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RoleAssignment::Table)
+                    .add_column(ColumnDef::new(RoleAssignment::ExpiresAt).date_time().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(RoleAssignment::Table)
+                    .drop_column(RoleAssignment::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RoleAssignment {
+    Table,
+    ExpiresAt,
+}
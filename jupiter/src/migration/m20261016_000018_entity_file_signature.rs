@@ -0,0 +1,76 @@
+use sea_orm_migration::prelude::*;
+
+/// Signatures of the entity files pushed by the admins of their directories.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EntityFileSignature::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(EntityFileSignature::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(EntityFileSignature::Path)
+                            .string_len(512)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(EntityFileSignature::BlobId)
+                            .string_len(40)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(EntityFileSignature::Signature)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(EntityFileSignature::SignedBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(EntityFileSignature::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_efs_path")
+                    .table(EntityFileSignature::Table)
+                    .col(EntityFileSignature::Path)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EntityFileSignature::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum EntityFileSignature {
+    Table,
+    Id,
+    Path,
+    BlobId,
+    Signature,
+    SignedBy,
+    CreatedAt,
+}
@@ -0,0 +1,60 @@
+use sea_orm_migration::prelude::*;
+
+/// Feature flags managed through the api, replacing those of the config.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(FeatureFlag::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(FeatureFlag::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(FeatureFlag::Name)
+                            .string_len(64)
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(FeatureFlag::Enabled).boolean().not_null())
+                    .col(ColumnDef::new(FeatureFlag::Users).text().not_null())
+                    .col(ColumnDef::new(FeatureFlag::Paths).text().not_null())
+                    .col(ColumnDef::new(FeatureFlag::Percentage).integer().not_null())
+                    .col(ColumnDef::new(FeatureFlag::UpdatedBy).string().not_null())
+                    .col(
+                        ColumnDef::new(FeatureFlag::UpdatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(FeatureFlag::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum FeatureFlag {
+    Table,
+    Id,
+    Name,
+    Enabled,
+    Users,
+    Paths,
+    Percentage,
+    UpdatedBy,
+    UpdatedAt,
+}
@@ -0,0 +1,51 @@
+use sea_orm_migration::prelude::*;
+
+/// Maintenance mode of the instance, writes are rejected while its row exists.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MaintenanceMode::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(MaintenanceMode::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(MaintenanceMode::Message).text())
+                    .col(
+                        ColumnDef::new(MaintenanceMode::EnabledBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(MaintenanceMode::EnabledAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MaintenanceMode::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MaintenanceMode {
+    Table,
+    Id,
+    Message,
+    EnabledBy,
+    EnabledAt,
+}
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::prelude::{Date, DateTime};
+use sea_orm_migration::sea_orm::Statement;
+
+use common::utils::generate_id;
+
+/// Daily activity of the instance for the statistics: counters of pushes and merge requests per
+/// day, and the users active on each day.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(DailyStats::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(DailyStats::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(DailyStats::Day)
+                            .date()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(DailyStats::Pushes)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(DailyStats::MrsOpened)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(
+                        ColumnDef::new(DailyStats::MrsMerged)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(DailyStats::UpdatedAt).date_time().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserActivity::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserActivity::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserActivity::UserName).string().not_null())
+                    .col(ColumnDef::new(UserActivity::Day).date().not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_ua_day_user")
+                    .table(UserActivity::Table)
+                    .col(UserActivity::Day)
+                    .col(UserActivity::UserName)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        // the merge requests opened and merged before are counted on their days, pushes and
+        // active users were not recorded
+        let backend = manager.get_database_backend();
+        let rows = manager
+            .get_connection()
+            .query_all(Statement::from_string(
+                backend,
+                "SELECT created_at, merge_date FROM mega_mr",
+            ))
+            .await?;
+        let mut days: BTreeMap<Date, (i64, i64)> = BTreeMap::new();
+        for row in rows {
+            let created_at: DateTime = row.try_get_by_index(0)?;
+            days.entry(created_at.date()).or_default().0 += 1;
+            if let Some(merged_at) = row.try_get_by_index::<Option<DateTime>>(1)? {
+                days.entry(merged_at.date()).or_default().1 += 1;
+            }
+        }
+        let now = chrono::Utc::now().naive_utc();
+        for (day, (opened, merged)) in days {
+            let insert = Query::insert()
+                .into_table(DailyStats::Table)
+                .columns([
+                    DailyStats::Id,
+                    DailyStats::Day,
+                    DailyStats::Pushes,
+                    DailyStats::MrsOpened,
+                    DailyStats::MrsMerged,
+                    DailyStats::UpdatedAt,
+                ])
+                .values_panic([
+                    generate_id().into(),
+                    day.into(),
+                    0.into(),
+                    opened.into(),
+                    merged.into(),
+                    now.into(),
+                ])
+                .to_owned();
+            manager.exec_stmt(insert).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserActivity::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(DailyStats::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum DailyStats {
+    Table,
+    Id,
+    Day,
+    Pushes,
+    MrsOpened,
+    MrsMerged,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum UserActivity {
+    Table,
+    Id,
+    UserName,
+    Day,
+}
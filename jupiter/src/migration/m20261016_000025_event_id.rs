@@ -0,0 +1,89 @@
+use sea_orm_migration::prelude::*;
+
+/// The event of each webhook delivery, which is delivered once to a webhook however often it is
+/// replayed, and an index for replaying the messages since a time.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(WebhookDelivery::Table)
+                    .add_column(
+                        ColumnDef::new(WebhookDelivery::EventId)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        // the events of deliveries stored before are not known, they are told apart by their ids
+        manager
+            .get_connection()
+            .execute_unprepared("UPDATE webhook_delivery SET event_id = id")
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_webhook_delivery_event")
+                    .table(WebhookDelivery::Table)
+                    .col(WebhookDelivery::WebhookId)
+                    .col(WebhookDelivery::EventId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_mq_create_time")
+                    .table(MqStorage::Table)
+                    .col(MqStorage::CreateTime)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_mq_create_time")
+                    .table(MqStorage::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_webhook_delivery_event")
+                    .table(WebhookDelivery::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(WebhookDelivery::Table)
+                    .drop_column(WebhookDelivery::EventId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WebhookDelivery {
+    Table,
+    EventId,
+}
+
+#[derive(DeriveIden)]
+enum MqStorage {
+    Table,
+    CreateTime,
+}
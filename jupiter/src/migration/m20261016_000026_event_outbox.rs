@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+/// Events written in the transaction of the change they are about, until they are published.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EventOutbox::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(EventOutbox::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(EventOutbox::Category).string().not_null())
+                    .col(ColumnDef::new(EventOutbox::Content).text().not_null())
+                    .col(
+                        ColumnDef::new(EventOutbox::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(EventOutbox::NextAttemptAt).date_time())
+                    .col(
+                        ColumnDef::new(EventOutbox::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(EventOutbox::SentAt).date_time())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_event_outbox_sent")
+                    .table(EventOutbox::Table)
+                    .col(EventOutbox::SentAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EventOutbox::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum EventOutbox {
+    Table,
+    Id,
+    Category,
+    Content,
+    Attempts,
+    NextAttemptAt,
+    CreatedAt,
+    SentAt,
+}
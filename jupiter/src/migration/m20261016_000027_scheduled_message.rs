@@ -0,0 +1,74 @@
+use sea_orm_migration::prelude::*;
+
+/// Messages published at a later time unless they are canceled before.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ScheduledMessage::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ScheduledMessage::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduledMessage::CancelKey)
+                            .string()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduledMessage::Category)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ScheduledMessage::Content).text().not_null())
+                    .col(
+                        ColumnDef::new(ScheduledMessage::DeliverAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ScheduledMessage::ClaimedUntil).date_time())
+                    .col(
+                        ColumnDef::new(ScheduledMessage::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_scheduled_message_deliver_at")
+                    .table(ScheduledMessage::Table)
+                    .col(ScheduledMessage::DeliverAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ScheduledMessage::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ScheduledMessage {
+    Table,
+    Id,
+    CancelKey,
+    Category,
+    Content,
+    DeliverAt,
+    ClaimedUntil,
+    CreatedAt,
+}
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+/// The consumer which claimed each queued message, only it records the outcome of processing
+/// the message and extends its claim while it is processed.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .add_column(ColumnDef::new(MqStorage::ClaimedBy).string().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .drop_column(MqStorage::ClaimedBy)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MqStorage {
+    Table,
+    ClaimedBy,
+}
@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+/// The lane of each queued message, the lanes are claimed and processed separately so
+/// user-facing events are not held up behind bulk ones.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .add_column(
+                        ColumnDef::new(MqStorage::Lane)
+                            .string()
+                            .not_null()
+                            .default("interactive"),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_mq_lane_state")
+                    .table(MqStorage::Table)
+                    .col(MqStorage::Lane)
+                    .col(MqStorage::State)
+                    .col(MqStorage::VisibleAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_mq_lane_state")
+                    .table(MqStorage::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MqStorage::Table)
+                    .drop_column(MqStorage::Lane)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MqStorage {
+    Table,
+    Lane,
+    State,
+    VisibleAt,
+}
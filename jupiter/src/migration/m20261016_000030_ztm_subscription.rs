@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+/// Repositories of other peers of the ztm network mirrored by this instance.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ZtmSubscription::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ZtmSubscription::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ZtmSubscription::Identifier)
+                            .text()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(ZtmSubscription::LocalPath).text().not_null())
+                    .col(ColumnDef::new(ZtmSubscription::Announcement).text())
+                    .col(ColumnDef::new(ZtmSubscription::SyncedAt).date_time())
+                    .col(ColumnDef::new(ZtmSubscription::LastError).text())
+                    .col(
+                        ColumnDef::new(ZtmSubscription::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ZtmSubscription::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ZtmSubscription {
+    Table,
+    Id,
+    Identifier,
+    LocalPath,
+    Announcement,
+    SyncedAt,
+    LastError,
+    CreatedAt,
+}
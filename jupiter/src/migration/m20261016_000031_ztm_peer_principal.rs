@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+/// The principals the peers of the ztm network are authorized as.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ZtmPeerPrincipal::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ZtmPeerPrincipal::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ZtmPeerPrincipal::PeerId)
+                            .text()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ZtmPeerPrincipal::Principal)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ZtmPeerPrincipal::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ZtmPeerPrincipal::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ZtmPeerPrincipal {
+    Table,
+    Id,
+    PeerId,
+    Principal,
+    CreatedAt,
+}
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+/// Find the paths a blob was committed at, to check whether its content may be read.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_mcp_new_id")
+                    .table(MegaCommitPath::Table)
+                    .col(MegaCommitPath::NewId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_mcp_new_id")
+                    .table(MegaCommitPath::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MegaCommitPath {
+    Table,
+    NewId,
+}
@@ -0,0 +1,103 @@
+use sea_orm_migration::prelude::*;
+
+/// The ref updates of pushes quarantined by secret scanning, applied once a reviewer releases
+/// the push, and the push each finding belongs to.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(QuarantinedPush::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(QuarantinedPush::Id)
+                            .big_integer()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(QuarantinedPush::Path).text().not_null())
+                    .col(ColumnDef::new(QuarantinedPush::Pusher).string().null())
+                    .col(
+                        ColumnDef::new(QuarantinedPush::RefUpdates)
+                            .text()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(QuarantinedPush::CommitId).string().null())
+                    .col(
+                        ColumnDef::new(QuarantinedPush::CreatedAt)
+                            .date_time()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(QuarantinedPush::ReleasedAt)
+                            .date_time()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(QuarantinedPush::ReleasedBy).string().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SecretFinding::Table)
+                    .add_column(ColumnDef::new(SecretFinding::PushId).big_integer().null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_secret_finding_push")
+                    .table(SecretFinding::Table)
+                    .col(SecretFinding::PushId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_secret_finding_push")
+                    .table(SecretFinding::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SecretFinding::Table)
+                    .drop_column(SecretFinding::PushId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(QuarantinedPush::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum QuarantinedPush {
+    Table,
+    Id,
+    Path,
+    Pusher,
+    RefUpdates,
+    CommitId,
+    CreatedAt,
+    ReleasedAt,
+    ReleasedBy,
+}
+
+#[derive(DeriveIden)]
+enum SecretFinding {
+    Table,
+    PushId,
+}
@@ -0,0 +1,42 @@
+use sea_orm_migration::prelude::*;
+
+/// The repository an LFS object was announced for in its batch request, its upload counts
+/// towards the storage quota of that repository.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(LfsObjects::Table)
+                    .add_column(
+                        ColumnDef::new(LfsObjects::Repo)
+                            .text()
+                            .not_null()
+                            .default("/"),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(LfsObjects::Table)
+                    .drop_column(LfsObjects::Repo)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum LfsObjects {
+    Table,
+    Repo,
+}
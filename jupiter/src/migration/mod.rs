@@ -0,0 +1,87 @@
+//! Versioned migrations of the database schema.
+//!
+//! The schema of a new database is created by the first migration from the init sql files,
+//! every later change of the schema is a migration of its own. Applied migrations are recorded
+//! in the `seaql_migrations` table, services check for pending ones on start as configured by
+//! `database.migration` and `mega migrate` applies or reverts them.
+
+use sea_orm_migration::prelude::*;
+
+mod m20261016_000001_init;
+mod m20261016_000002_raw_blob_ref_count;
+mod m20261016_000003_raw_blob_chunk;
+mod m20261016_000004_git_repo_deleted_at;
+mod m20261016_000005_repo_usage;
+mod m20261016_000006_commit_path;
+mod m20261016_000007_mq_retry;
+mod m20261016_000008_signing_key;
+mod m20261016_000009_background_job;
+mod m20261016_000010_commit_path_change;
+mod m20261016_000011_mr_mergeability;
+mod m20261016_000012_policy_version;
+mod m20261016_000013_role_assignment;
+mod m20261016_000014_auth_decision;
+mod m20261016_000015_break_glass;
+mod m20261016_000016_service_account;
+mod m20261016_000017_role_assignment_expiry;
+mod m20261016_000018_entity_file_signature;
+mod m20261016_000019_user_deactivated_at;
+mod m20261016_000020_feature_flag;
+mod m20261016_000021_maintenance_mode;
+mod m20261016_000022_user_tenant;
+mod m20261016_000023_daily_stats;
+mod m20261016_000024_webhook;
+mod m20261016_000025_event_id;
+mod m20261016_000026_event_outbox;
+mod m20261016_000027_scheduled_message;
+mod m20261016_000028_mq_claimed_by;
+mod m20261016_000029_mq_lane;
+mod m20261016_000030_ztm_subscription;
+mod m20261016_000031_ztm_peer_principal;
+mod m20261016_000032_commit_path_new_id;
+mod m20261016_000033_quarantined_push;
+mod m20261016_000034_lfs_object_repo;
+
+pub struct Migrator;
+
+#[async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn MigrationTrait>> {
+        vec![
+            Box::new(m20261016_000001_init::Migration),
+            Box::new(m20261016_000002_raw_blob_ref_count::Migration),
+            Box::new(m20261016_000003_raw_blob_chunk::Migration),
+            Box::new(m20261016_000004_git_repo_deleted_at::Migration),
+            Box::new(m20261016_000005_repo_usage::Migration),
+            Box::new(m20261016_000006_commit_path::Migration),
+            Box::new(m20261016_000007_mq_retry::Migration),
+            Box::new(m20261016_000008_signing_key::Migration),
+            Box::new(m20261016_000009_background_job::Migration),
+            Box::new(m20261016_000010_commit_path_change::Migration),
+            Box::new(m20261016_000011_mr_mergeability::Migration),
+            Box::new(m20261016_000012_policy_version::Migration),
+            Box::new(m20261016_000013_role_assignment::Migration),
+            Box::new(m20261016_000014_auth_decision::Migration),
+            Box::new(m20261016_000015_break_glass::Migration),
+            Box::new(m20261016_000016_service_account::Migration),
+            Box::new(m20261016_000017_role_assignment_expiry::Migration),
+            Box::new(m20261016_000018_entity_file_signature::Migration),
+            Box::new(m20261016_000019_user_deactivated_at::Migration),
+            Box::new(m20261016_000020_feature_flag::Migration),
+            Box::new(m20261016_000021_maintenance_mode::Migration),
+            Box::new(m20261016_000022_user_tenant::Migration),
+            Box::new(m20261016_000023_daily_stats::Migration),
+            Box::new(m20261016_000024_webhook::Migration),
+            Box::new(m20261016_000025_event_id::Migration),
+            Box::new(m20261016_000026_event_outbox::Migration),
+            Box::new(m20261016_000027_scheduled_message::Migration),
+            Box::new(m20261016_000028_mq_claimed_by::Migration),
+            Box::new(m20261016_000029_mq_lane::Migration),
+            Box::new(m20261016_000030_ztm_subscription::Migration),
+            Box::new(m20261016_000031_ztm_peer_principal::Migration),
+            Box::new(m20261016_000032_commit_path_new_id::Migration),
+            Box::new(m20261016_000033_quarantined_push::Migration),
+            Box::new(m20261016_000034_lfs_object_repo::Migration),
+        ]
+    }
+}
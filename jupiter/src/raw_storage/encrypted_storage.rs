@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use callisto::db_enums::StorageType;
+use common::errors::MegaError;
+
+use crate::encryption::Keyring;
+use crate::raw_storage::{ByteStream, ObjectBackend};
+
+/// Encrypts the objects put into another backend and decrypts them when they are read, see
+/// [`crate::encryption`].
+pub struct EncryptedBackend {
+    inner: Arc<dyn ObjectBackend>,
+    keyring: Arc<Keyring>,
+}
+
+impl EncryptedBackend {
+    pub fn new(inner: Arc<dyn ObjectBackend>, keyring: Arc<Keyring>) -> Self {
+        EncryptedBackend { inner, keyring }
+    }
+}
+
+#[async_trait]
+impl ObjectBackend for EncryptedBackend {
+    fn storage_type(&self) -> StorageType {
+        self.inner.storage_type()
+    }
+
+    fn contains(&self, location: &str) -> bool {
+        self.inner.contains(location)
+    }
+
+    async fn put_stream(&self, id: &str, content: ByteStream) -> Result<String, MegaError> {
+        let content = self.keyring.encrypt_stream(content)?;
+        self.inner.put_stream(id, content).await
+    }
+
+    async fn get_stream(&self, location: &str) -> Result<ByteStream, MegaError> {
+        let content = self.inner.get_stream(location).await?;
+        self.keyring.decrypt_stream(content).await
+    }
+
+    async fn delete(&self, location: &str) -> Result<(), MegaError> {
+        self.inner.delete(location).await
+    }
+
+    async fn rotate(&self, id: &str, location: &str) -> Result<bool, MegaError> {
+        let content = self.inner.get_stream(location).await?;
+        let Some(rotated) = self.keyring.rotate_stream(content).await? else {
+            return Ok(false);
+        };
+        // the object is replaced once it is written completely
+        let new_location = self.inner.put_stream(id, rotated).await?;
+        if new_location != location {
+            return Err(MegaError::with_message(&format!(
+                "{} was written to {}",
+                location, new_location
+            )));
+        }
+        Ok(true)
+    }
+}
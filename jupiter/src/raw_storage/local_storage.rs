@@ -0,0 +1,88 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use futures::stream::TryStreamExt;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+use callisto::db_enums::StorageType;
+use common::errors::MegaError;
+
+use crate::raw_storage::{file_stream, object_path, ByteStream, ObjectBackend};
+
+/// Objects in files below a directory, their location is the absolute path of the file.
+pub struct LocalBackend {
+    base_path: PathBuf,
+}
+
+impl LocalBackend {
+    pub fn init(base_path: PathBuf) -> Result<Self, MegaError> {
+        std::fs::create_dir_all(&base_path)?;
+        Ok(LocalBackend {
+            base_path: std::path::absolute(base_path)?,
+        })
+    }
+}
+
+#[async_trait]
+impl ObjectBackend for LocalBackend {
+    fn storage_type(&self) -> StorageType {
+        StorageType::LocalFs
+    }
+
+    fn contains(&self, location: &str) -> bool {
+        Path::new(location).starts_with(&self.base_path)
+    }
+
+    async fn put_stream(&self, id: &str, mut content: ByteStream) -> Result<String, MegaError> {
+        let path = self.base_path.join(object_path(id));
+        fs::create_dir_all(path.parent().unwrap()).await?;
+        // readers never see a partly written object
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp).await?;
+        while let Some(chunk) = content.try_next().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.sync_all().await?;
+        fs::rename(&tmp, &path).await?;
+        Ok(path.to_string_lossy().into_owned())
+    }
+
+    async fn get_stream(&self, location: &str) -> Result<ByteStream, MegaError> {
+        Ok(file_stream(fs::File::open(location).await?))
+    }
+
+    async fn delete(&self, location: &str) -> Result<(), MegaError> {
+        match fs::remove_file(location).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::LocalBackend;
+    use crate::raw_storage::ObjectBackend;
+
+    #[tokio::test]
+    async fn test_local_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = LocalBackend::init(dir.path().to_owned()).unwrap();
+        let id = "8ab686eafeb1f44702738c8b0f24f2567c36da6d";
+        let content = Bytes::from(vec![7; 200 * 1024]);
+
+        let location = backend.put(id, content.clone()).await.unwrap();
+        assert!(location.ends_with("8a/b6/86eafeb1f44702738c8b0f24f2567c36da6d"));
+        assert!(backend.contains(&location));
+        assert_eq!(backend.get(&location).await.unwrap(), content);
+
+        backend.delete(&location).await.unwrap();
+        assert!(backend.get(&location).await.is_err());
+        // deleting twice is fine
+        backend.delete(&location).await.unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Backends for the content of raw blobs.
+//!
+//! Small blobs are kept in the `raw_blob` table, the content of blobs above
+//! `storage.big_obj_threshold` is put into the backend of `storage.raw_obj_storage_type` and
+//! the table only records where it is, in `local_path` or `remote_url`. Blobs above
+//! `storage.big_obj_chunk_threshold` are split into content defined chunks listed in
+//! `raw_blob_chunk`, every chunk is stored like a blob of its own. Content put into a backend
+//! is encrypted if `storage.encryption` is enabled.
+//!
+//! Deployments can bring a backend of their own, compiled in and registered with
+//! [`register_backend`] before the services start, and select it with the `plugin` storage.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::stream::{self, BoxStream};
+use futures::{StreamExt, TryStreamExt};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+use callisto::db_enums::StorageType;
+use common::config::{RawStorageType, StorageConfig};
+use common::errors::MegaError;
+
+use crate::encryption::Keyring;
+use crate::raw_storage::encrypted_storage::EncryptedBackend;
+use crate::raw_storage::local_storage::LocalBackend;
+use crate::raw_storage::remote_storage::RemoteBackend;
+
+pub mod encrypted_storage;
+pub mod local_storage;
+pub mod remote_storage;
+
+pub type ByteStream = BoxStream<'static, Result<Bytes, MegaError>>;
+
+#[async_trait]
+pub trait ObjectBackend: Sync + Send {
+    /// How locations of this backend are recorded in `raw_blob`.
+    fn storage_type(&self) -> StorageType;
+
+    /// Whether `location` is in this backend.
+    fn contains(&self, location: &str) -> bool;
+
+    /// Store the content of object `id`, returns its location.
+    async fn put_stream(&self, id: &str, content: ByteStream) -> Result<String, MegaError>;
+
+    async fn get_stream(&self, location: &str) -> Result<ByteStream, MegaError>;
+
+    async fn delete(&self, location: &str) -> Result<(), MegaError>;
+
+    /// Encrypt the object `id` at `location` for the current master key again, returns
+    /// whether it was rewritten. Backends without encryption have nothing to do.
+    async fn rotate(&self, _id: &str, _location: &str) -> Result<bool, MegaError> {
+        Ok(false)
+    }
+
+    async fn put(&self, id: &str, content: Bytes) -> Result<String, MegaError> {
+        self.put_stream(id, once(content)).await
+    }
+
+    async fn get(&self, location: &str) -> Result<Bytes, MegaError> {
+        collect(self.get_stream(location).await?).await
+    }
+}
+
+/// Size of the pieces files are read in.
+const READ_SIZE: usize = 64 * 1024;
+
+/// `content` held in memory as a stream.
+pub fn once(content: Bytes) -> ByteStream {
+    stream::once(async { Ok(content) }).boxed()
+}
+
+/// Read all of `content` into memory, only for content known to be small.
+pub async fn collect(content: ByteStream) -> Result<Bytes, MegaError> {
+    let content = content
+        .try_fold(BytesMut::new(), |mut buf, chunk| async move {
+            buf.extend_from_slice(&chunk);
+            Ok(buf)
+        })
+        .await?;
+    Ok(content.freeze())
+}
+
+/// The content of `file` from its current position, read piece by piece.
+pub fn file_stream(file: File) -> ByteStream {
+    stream::try_unfold(file, |mut file| async move {
+        let mut buf = vec![0; READ_SIZE];
+        let len = file.read(&mut buf).await?;
+        if len == 0 {
+            return Ok(None);
+        }
+        buf.truncate(len);
+        Ok::<_, MegaError>(Some((Bytes::from(buf), file)))
+    })
+    .boxed()
+}
+
+/// Creates a backend of the `plugin` storage from the storage config.
+pub type BackendFactory = fn(&StorageConfig) -> Result<Arc<dyn ObjectBackend>, MegaError>;
+
+/// Backends of the `plugin` storage by name.
+static BACKENDS: Mutex<BTreeMap<String, BackendFactory>> = Mutex::new(BTreeMap::new());
+
+/// Make the backend created by `factory` available as `storage.plugin = "name"`, a backend
+/// registered before under the same name is replaced.
+pub fn register_backend(name: &str, factory: BackendFactory) {
+    BACKENDS.lock().unwrap().insert(name.to_owned(), factory);
+}
+
+fn plugin_backend(config: &StorageConfig) -> Result<Arc<dyn ObjectBackend>, MegaError> {
+    let factory = BACKENDS.lock().unwrap().get(&config.plugin).copied();
+    match factory {
+        Some(factory) => factory(config),
+        None => Err(MegaError::with_message(&format!(
+            "no storage backend is registered as {}",
+            config.plugin
+        ))),
+    }
+}
+
+/// The backend of `kind`, `None` for the database.
+///
+/// With a `keyring` the backend encrypts what it stores and decrypts what it reads.
+pub fn init(
+    kind: RawStorageType,
+    config: &StorageConfig,
+    keyring: Option<Arc<Keyring>>,
+) -> Result<Option<Arc<dyn ObjectBackend>>, MegaError> {
+    let backend: Arc<dyn ObjectBackend> = match kind {
+        RawStorageType::Database => return Ok(None),
+        RawStorageType::Local => Arc::new(LocalBackend::init(config.raw_obj_local_path.clone())?),
+        RawStorageType::S3 | RawStorageType::Azure | RawStorageType::Gcs => {
+            Arc::new(RemoteBackend::init(kind, config)?)
+        }
+        RawStorageType::Plugin => plugin_backend(config)?,
+    };
+    Ok(Some(encrypted(backend, keyring)))
+}
+
+/// `backend` encrypting with `keyring`, if there is one.
+pub fn encrypted(
+    backend: Arc<dyn ObjectBackend>,
+    keyring: Option<Arc<Keyring>>,
+) -> Arc<dyn ObjectBackend> {
+    match keyring {
+        Some(keyring) => Arc::new(EncryptedBackend::new(backend, keyring)),
+        None => backend,
+    }
+}
+
+/// Objects are stored as `ab/cd/ef...` to keep directories and listings small.
+pub fn object_path(id: &str) -> String {
+    if id.len() < 5 {
+        id.to_owned()
+    } else {
+        format!("{}/{}/{}", &id[0..2], &id[2..4], &id[4..])
+    }
+}
+
+/// Id of the object written by [`probe`], it is not the hash of any blob.
+const PROBE_ID: &str = "mega-doctor-probe";
+
+/// Check that `backend` stores, returns and deletes objects.
+pub async fn probe(backend: &dyn ObjectBackend) -> Result<(), MegaError> {
+    let content = Bytes::from_static(b"mega");
+    let location = backend.put(PROBE_ID, content.clone()).await?;
+    let read = backend.get(&location).await;
+    backend.delete(&location).await?;
+    if read? != content {
+        return Err(MegaError::with_message(
+            "the object read back differs from the one written",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use common::config::{RawStorageType, StorageConfig};
+
+    use super::local_storage::LocalBackend;
+    use super::{init, object_path, probe, register_backend, ObjectBackend};
+
+    #[test]
+    fn test_object_path() {
+        assert_eq!(
+            object_path("8ab686eafeb1f44702738c8b0f24f2567c36da6d"),
+            "8a/b6/86eafeb1f44702738c8b0f24f2567c36da6d"
+        );
+        assert_eq!(object_path("8ab6"), "8ab6");
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let backend = LocalBackend::init(dir.path().to_path_buf()).unwrap();
+        probe(&backend).await.unwrap();
+        assert!(!dir.path().join(object_path(super::PROBE_ID)).exists());
+    }
+    #[tokio::test]
+    async fn test_plugin_backend() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut config = StorageConfig {
+            raw_obj_storage_type: RawStorageType::Plugin,
+            raw_obj_local_path: dir.path().to_path_buf(),
+            plugin: "test-local".to_owned(),
+            ..Default::default()
+        };
+        register_backend("test-local", |config| {
+            let backend = LocalBackend::init(config.raw_obj_local_path.clone())?;
+            Ok(Arc::new(backend) as Arc<dyn ObjectBackend>)
+        });
+        let backend = init(config.raw_obj_storage_type, &config, None)
+            .unwrap()
+            .unwrap();
+        probe(backend.as_ref()).await.unwrap();
+        config.plugin = "missing".to_owned();
+        assert!(init(config.raw_obj_storage_type, &config, None).is_err());
+    }
+}
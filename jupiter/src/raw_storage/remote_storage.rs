@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, PutPayload, WriteMultipart};
+
+use callisto::db_enums::StorageType;
+use common::config::{RawStorageType, StorageConfig};
+use common::errors::MegaError;
+
+use crate::raw_storage::{object_path, ByteStream, ObjectBackend};
+
+/// Parts uploaded at the same time by a streaming put.
+const MAX_CONCURRENT_PARTS: usize = 4;
+
+/// Objects in a bucket of a cloud store, their location is an url like
+/// `s3://bucket/ab/cd/ef...`.
+pub struct RemoteBackend {
+    store: Arc<dyn ObjectStore>,
+    /// Prefix of all locations, like `s3://bucket/`
+    url: String,
+}
+
+impl RemoteBackend {
+    pub fn init(kind: RawStorageType, config: &StorageConfig) -> Result<Self, MegaError> {
+        let (scheme, store): (&str, Arc<dyn ObjectStore>) = match kind {
+            RawStorageType::S3 => {
+                let mut builder = AmazonS3Builder::new()
+                    .with_bucket_name(&config.obs_bucket)
+                    .with_region(&config.obs_region)
+                    .with_access_key_id(&config.obs_access_key)
+                    .with_secret_access_key(&config.obs_secret_key);
+                if !config.obs_endpoint.is_empty() {
+                    builder = builder
+                        .with_endpoint(&config.obs_endpoint)
+                        .with_allow_http(config.obs_endpoint.starts_with("http://"));
+                }
+                ("s3", Arc::new(builder.build().map_err(store_error)?))
+            }
+            RawStorageType::Azure => {
+                let store = MicrosoftAzureBuilder::new()
+                    .with_container_name(&config.obs_bucket)
+                    .with_account(&config.obs_access_key)
+                    .with_access_key(&config.obs_secret_key)
+                    .build()
+                    .map_err(store_error)?;
+                ("az", Arc::new(store))
+            }
+            RawStorageType::Gcs => {
+                let store = GoogleCloudStorageBuilder::new()
+                    .with_bucket_name(&config.obs_bucket)
+                    .with_service_account_key(&config.obs_secret_key)
+                    .build()
+                    .map_err(store_error)?;
+                ("gs", Arc::new(store))
+            }
+            RawStorageType::Database | RawStorageType::Local | RawStorageType::Plugin => {
+                unreachable!("{:?} is not a remote storage", kind)
+            }
+        };
+        Ok(RemoteBackend {
+            store,
+            url: format!("{}://{}/", scheme, config.obs_bucket),
+        })
+    }
+
+    fn key(&self, location: &str) -> Result<ObjectPath, MegaError> {
+        let key = location.strip_prefix(&self.url).ok_or_else(|| {
+            MegaError::with_message(&format!("{} is not in {}", location, self.url))
+        })?;
+        Ok(ObjectPath::from(key))
+    }
+}
+
+fn store_error(err: object_store::Error) -> MegaError {
+    MegaError::with_message(&err.to_string())
+}
+
+#[async_trait]
+impl ObjectBackend for RemoteBackend {
+    fn storage_type(&self) -> StorageType {
+        StorageType::RemoteUrl
+    }
+
+    fn contains(&self, location: &str) -> bool {
+        location.starts_with(&self.url)
+    }
+
+    async fn put_stream(&self, id: &str, mut content: ByteStream) -> Result<String, MegaError> {
+        let key = object_path(id);
+        let upload = self
+            .store
+            .put_multipart(&ObjectPath::from(key.as_str()))
+            .await
+            .map_err(store_error)?;
+        let mut writer = WriteMultipart::new(upload);
+        loop {
+            let chunk = match content.try_next().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(err) => {
+                    let _ = writer.abort().await;
+                    return Err(err);
+                }
+            };
+            writer
+                .wait_for_capacity(MAX_CONCURRENT_PARTS)
+                .await
+                .map_err(store_error)?;
+            writer.write(&chunk);
+        }
+        writer.finish().await.map_err(store_error)?;
+        Ok(format!("{}{}", self.url, key))
+    }
+
+    async fn put(&self, id: &str, content: Bytes) -> Result<String, MegaError> {
+        // a single request is enough for content already in memory
+        let key = object_path(id);
+        self.store
+            .put(&ObjectPath::from(key.as_str()), PutPayload::from(content))
+            .await
+            .map_err(store_error)?;
+        Ok(format!("{}{}", self.url, key))
+    }
+
+    async fn get_stream(&self, location: &str) -> Result<ByteStream, MegaError> {
+        let res = self
+            .store
+            .get(&self.key(location)?)
+            .await
+            .map_err(store_error)?;
+        Ok(res.into_stream().map_err(store_error).boxed())
+    }
+
+    async fn delete(&self, location: &str) -> Result<(), MegaError> {
+        match self.store.delete(&self.key(location)?).await {
+            Err(err) if !matches!(err, object_store::Error::NotFound { .. }) => {
+                Err(store_error(err))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder,
+};
+
+use callisto::feature_flag;
+use common::config::FeatureFlag;
+use common::errors::MegaError;
+use common::utils::generate_id;
+
+#[derive(Clone)]
+pub struct FeatureStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl FeatureStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        FeatureStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        FeatureStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    pub async fn list_flags(&self) -> Result<Vec<feature_flag::Model>, MegaError> {
+        let res = feature_flag::Entity::find()
+            .order_by_asc(feature_flag::Column::Name)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_flag(&self, name: &str) -> Result<Option<feature_flag::Model>, MegaError> {
+        let res = feature_flag::Entity::find()
+            .filter(feature_flag::Column::Name.eq(name))
+            .one(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Store `flag`, replacing the stored flag of the same name.
+    pub async fn save_flag(&self, flag: &FeatureFlag, operator: &str) -> Result<(), MegaError> {
+        let model = feature_flag::Model {
+            id: generate_id(),
+            name: flag.name.clone(),
+            enabled: flag.enabled,
+            users: serde_json::to_string(&flag.users).unwrap(),
+            paths: serde_json::to_string(&flag.paths).unwrap(),
+            percentage: flag.percentage as i32,
+            updated_by: operator.to_owned(),
+            updated_at: chrono::Utc::now().naive_utc(),
+        };
+        feature_flag::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::column(feature_flag::Column::Name)
+                    .update_columns([
+                        feature_flag::Column::Enabled,
+                        feature_flag::Column::Users,
+                        feature_flag::Column::Paths,
+                        feature_flag::Column::Percentage,
+                        feature_flag::Column::UpdatedBy,
+                        feature_flag::Column::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Delete the stored flag `name`, the flag of the config is used again. Returns whether
+    /// there was one.
+    pub async fn delete_flag(&self, name: &str) -> Result<bool, MegaError> {
+        let res = feature_flag::Entity::delete_many()
+            .filter(feature_flag::Column::Name.eq(name))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+}
@@ -1,32 +1,44 @@
+use std::collections::HashSet;
+use std::future::Future;
 use std::sync::Arc;
 
 use futures::{stream, Stream, StreamExt};
 use sea_orm::sea_query::Expr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait,
-    IntoActiveModel, QueryFilter, QueryTrait, Set,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction,
+    DbBackend, DbErr, EntityTrait, IntoActiveModel, QueryFilter, QueryTrait, Set, SqlErr,
 };
-use sea_orm::{PaginatorTrait, QueryOrder};
+use sea_orm::{PaginatorTrait, QueryOrder, QuerySelect};
 use tokio::sync::Mutex;
 
+use callisto::db_enums::RefType;
 use callisto::{git_blob, git_commit, git_repo, git_tag, git_tree, import_refs, raw_blob};
 use common::errors::MegaError;
+use common::utils::replace_path_prefix;
 use mercury::internal::object::GitObjectModel;
 use mercury::internal::pack::entry::Entry;
 
-use crate::storage::batch_save_model;
+use crate::cache::{Cache, CacheBackend};
+use crate::storage::raw_db_storage::RawDbStorage;
+use crate::storage::{self, batch_save_model, delete_by_ids, metrics, query_by_ids, ObjectIds};
 
 #[derive(Clone)]
 pub struct GitDbStorage {
     pub connection: Arc<DatabaseConnection>,
+    /// Saves the content of new blobs
+    raw_storage: RawDbStorage,
+    /// Refs by repo id
+    ref_cache: Cache<Vec<import_refs::Model>>,
+    /// Commits by `<repo id>:<commit id>`
+    commit_cache: Cache<git_commit::Model>,
 }
 
 #[derive(Debug)]
 struct GitObjects {
     commits: Vec<git_commit::ActiveModel>,
     trees: Vec<git_tree::ActiveModel>,
-    blobs: Vec<git_blob::ActiveModel>,
-    raw_blobs: Vec<raw_blob::ActiveModel>,
+    blobs: Vec<git_blob::Model>,
+    raw_blobs: Vec<raw_blob::Model>,
     tags: Vec<git_tag::ActiveModel>,
 }
 
@@ -35,27 +47,64 @@ impl GitDbStorage {
         &self.connection
     }
 
-    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
-        GitDbStorage { connection }
+    pub async fn new(
+        connection: Arc<DatabaseConnection>,
+        raw_storage: RawDbStorage,
+        cache: &CacheBackend,
+    ) -> Self {
+        GitDbStorage {
+            connection,
+            raw_storage,
+            ref_cache: cache.refs("import_refs"),
+            commit_cache: cache.commits("git_commit"),
+        }
     }
 
     pub fn mock() -> Self {
         GitDbStorage {
             connection: Arc::new(DatabaseConnection::default()),
+            raw_storage: RawDbStorage::mock(),
+            ref_cache: Cache::disabled("import_refs"),
+            commit_cache: Cache::disabled("git_commit"),
         }
     }
 
+    /// Run `f` in a transaction, see [`storage::transaction`].
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T, MegaError>
+    where
+        F: FnOnce(Arc<DatabaseTransaction>) -> Fut,
+        Fut: Future<Output = Result<T, MegaError>>,
+    {
+        storage::transaction(self.get_connection(), f).await
+    }
+
+    /// Create a ref, fails with [`MegaError::ref_conflict`] if the repository has a ref of that
+    /// name already.
     pub async fn save_ref(
         &self,
+        conn: &impl ConnectionTrait,
         repo_id: i64,
         mut refs: import_refs::Model,
     ) -> Result<(), MegaError> {
         refs.repo_id = repo_id;
+        let exists = import_refs::Entity::find()
+            .filter(import_refs::Column::RepoId.eq(repo_id))
+            .filter(import_refs::Column::RefName.eq(&refs.ref_name))
+            .count(conn)
+            .await?;
+        if exists > 0 {
+            return Err(MegaError::ref_conflict());
+        }
         let a_model = refs.into_active_model();
         import_refs::Entity::insert(a_model)
-            .exec(self.get_connection())
+            .exec(conn)
             .await
-            .unwrap();
+            .map_err(|err| match err.sql_err() {
+                // created concurrently, the ref names of a repository are unique
+                Some(SqlErr::UniqueConstraintViolation(_)) => MegaError::ref_conflict(),
+                _ => err.into(),
+            })?;
+        self.ref_cache.invalidate(&repo_id.to_string()).await;
         Ok(())
     }
 
@@ -65,35 +114,79 @@ impl GitDbStorage {
             .filter(import_refs::Column::RefName.eq(ref_name))
             .exec(self.get_connection())
             .await?;
+        self.ref_cache.invalidate(&repo_id.to_string()).await;
         Ok(())
     }
 
-    pub async fn get_ref(&self, repo_id: i64) -> Result<Vec<import_refs::Model>, MegaError> {
-        let result = import_refs::Entity::find()
+    /// Remove a ref pushed to be deleted, if it still points to `old_id`.
+    ///
+    /// Fails with [`MegaError::ref_conflict`] if the ref was changed since it was read.
+    pub async fn remove_ref_if(
+        &self,
+        conn: &impl ConnectionTrait,
+        repo_id: i64,
+        ref_name: &str,
+        old_id: &str,
+    ) -> Result<(), MegaError> {
+        let res = import_refs::Entity::delete_many()
             .filter(import_refs::Column::RepoId.eq(repo_id))
-            .order_by_asc(import_refs::Column::RefName)
-            .all(self.get_connection())
+            .filter(import_refs::Column::RefName.eq(ref_name))
+            .filter(import_refs::Column::RefGitId.eq(old_id))
+            .exec(conn)
             .await?;
+        self.ref_cache.invalidate(&repo_id.to_string()).await;
+        if res.rows_affected == 0 {
+            return Err(MegaError::ref_conflict());
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    pub async fn get_ref(&self, repo_id: i64) -> Result<Vec<import_refs::Model>, MegaError> {
+        let mut timer = metrics::timer("git_db_storage", "get_ref");
+        let result = self
+            .ref_cache
+            .get_or_load(&repo_id.to_string(), || async {
+                let refs = import_refs::Entity::find()
+                    .filter(import_refs::Column::RepoId.eq(repo_id))
+                    .order_by_asc(import_refs::Column::RefName)
+                    .all(self.get_connection())
+                    .await?;
+                Ok(Some(refs))
+            })
+            .await?
+            .unwrap_or_default();
+        timer.rows(result.len());
         Ok(result)
     }
 
+    /// Point the ref `ref_name` to `new_id`, if it still points to `old_id`.
+    ///
+    /// Fails with [`MegaError::ref_conflict`] if the ref was changed or removed since it was
+    /// read, so concurrent updates never overwrite each other.
     pub async fn update_ref(
         &self,
+        conn: &impl ConnectionTrait,
         repo_id: i64,
         ref_name: &str,
+        old_id: &str,
         new_id: &str,
     ) -> Result<(), MegaError> {
-        let ref_data: import_refs::Model = import_refs::Entity::find()
+        let res = import_refs::Entity::update_many()
+            .col_expr(import_refs::Column::RefGitId, Expr::value(new_id))
+            .col_expr(
+                import_refs::Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
             .filter(import_refs::Column::RepoId.eq(repo_id))
             .filter(import_refs::Column::RefName.eq(ref_name))
-            .one(self.get_connection())
-            .await
-            .unwrap()
-            .unwrap();
-        let mut ref_data: import_refs::ActiveModel = ref_data.into();
-        ref_data.ref_git_id = Set(new_id.to_string());
-        ref_data.updated_at = Set(chrono::Utc::now().naive_utc());
-        ref_data.update(self.get_connection()).await.unwrap();
+            .filter(import_refs::Column::RefGitId.eq(old_id))
+            .exec(conn)
+            .await?;
+        self.ref_cache.invalidate(&repo_id.to_string()).await;
+        if res.rows_affected == 0 {
+            return Err(MegaError::ref_conflict());
+        }
         Ok(())
     }
 
@@ -118,7 +211,23 @@ impl GitDbStorage {
         Ok(result > 0)
     }
 
+    /// Branches other than the default branch which were not updated since `before`.
+    pub async fn get_branches_updated_before(
+        &self,
+        before: chrono::NaiveDateTime,
+    ) -> Result<Vec<import_refs::Model>, MegaError> {
+        Ok(import_refs::Entity::find()
+            .filter(import_refs::Column::RefType.eq(RefType::Branch))
+            .filter(import_refs::Column::DefaultBranch.eq(false))
+            .filter(import_refs::Column::UpdatedAt.lt(before))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn save_entry(&self, repo_id: i64, entry_list: Vec<Entry>) -> Result<(), MegaError> {
+        let mut timer = metrics::timer("git_db_storage", "save_entry");
+        timer.rows(entry_list.len());
         let git_objects = Arc::new(Mutex::new(GitObjects {
             commits: Vec::new(),
             trees: Vec::new(),
@@ -147,8 +256,8 @@ impl GitDbStorage {
                         }
                         GitObjectModel::Blob(mut blob, raw) => {
                             blob.repo_id = repo_id;
-                            git_objects.blobs.push(blob.clone().into_active_model());
-                            git_objects.raw_blobs.push(raw.into_active_model());
+                            git_objects.blobs.push(blob);
+                            git_objects.raw_blobs.push(raw);
                         }
                         GitObjectModel::Tag(mut tag) => {
                             tag.repo_id = repo_id;
@@ -162,24 +271,85 @@ impl GitDbStorage {
         let git_objects = Arc::try_unwrap(git_objects)
             .expect("Failed to unwrap Arc")
             .into_inner();
-        batch_save_model(self.get_connection(), git_objects.commits)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.trees)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.blobs)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.raw_blobs)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.tags)
-            .await
-            .unwrap();
+        // the objects of a batch are saved completely or not at all
+        self.transaction(|txn| async move {
+            let conn = &*txn;
+            batch_save_model(conn, git_objects.commits).await?;
+            batch_save_model(conn, git_objects.trees).await?;
+            self.raw_storage
+                .save_raw_blobs(conn, git_objects.raw_blobs)
+                .await?;
+            self.save_blobs(conn, repo_id, git_objects.blobs).await?;
+            batch_save_model(conn, git_objects.tags).await
+        })
+        .await
+    }
+
+    /// Save object records which reference content already kept in the raw blob storage.
+    pub async fn save_objects(
+        &self,
+        conn: &impl ConnectionTrait,
+        repo_id: i64,
+        commits: Vec<git_commit::Model>,
+        trees: Vec<git_tree::Model>,
+        blobs: Vec<git_blob::Model>,
+    ) -> Result<(), MegaError> {
+        let commits: Vec<git_commit::ActiveModel> = commits
+            .into_iter()
+            .map(|mut c| {
+                c.repo_id = repo_id;
+                c.into_active_model()
+            })
+            .collect();
+        let trees: Vec<git_tree::ActiveModel> = trees
+            .into_iter()
+            .map(|mut t| {
+                t.repo_id = repo_id;
+                t.into_active_model()
+            })
+            .collect();
+        let blobs: Vec<git_blob::Model> = blobs
+            .into_iter()
+            .map(|mut b| {
+                b.repo_id = repo_id;
+                b
+            })
+            .collect();
+        batch_save_model(conn, trees).await?;
+        self.save_blobs(conn, repo_id, blobs).await?;
+        batch_save_model(conn, commits).await?;
         Ok(())
     }
 
+    /// Save the blobs new to the repo and count them as references to their raw content.
+    async fn save_blobs(
+        &self,
+        conn: &impl ConnectionTrait,
+        repo_id: i64,
+        blobs: Vec<git_blob::Model>,
+    ) -> Result<(), MegaError> {
+        let mut stored = HashSet::new();
+        for chunk in blobs.chunks(1000) {
+            let existing = git_blob::Entity::find()
+                .filter(git_blob::Column::RepoId.eq(repo_id))
+                .filter(git_blob::Column::BlobId.is_in(chunk.iter().map(|b| b.blob_id.clone())))
+                .all(conn)
+                .await?;
+            stored.extend(existing.into_iter().map(|b| b.blob_id));
+        }
+        let mut hashes = Vec::new();
+        let blobs: Vec<git_blob::ActiveModel> = blobs
+            .into_iter()
+            .filter(|b| stored.insert(b.blob_id.clone()))
+            .map(|b| {
+                hashes.push(b.blob_id.clone());
+                b.into_active_model()
+            })
+            .collect();
+        batch_save_model(conn, blobs).await?;
+        self.raw_storage.add_refs(conn, &hashes).await
+    }
+
     /// Finds a Git repository with an exact match on the repository path.
     ///
     /// # Arguments
@@ -196,6 +366,7 @@ impl GitDbStorage {
     ) -> Result<Option<git_repo::Model>, MegaError> {
         let result = git_repo::Entity::find()
             .filter(git_repo::Column::RepoPath.eq(repo_path))
+            .filter(git_repo::Column::DeletedAt.is_null())
             .one(self.get_connection())
             .await?;
         Ok(result)
@@ -215,14 +386,78 @@ impl GitDbStorage {
         &self,
         repo_path: &str,
     ) -> Result<Option<git_repo::Model>, MegaError> {
+        let backend = self.get_connection().get_database_backend();
+        // `||` is a logical or in mysql
+        let condition = match backend {
+            DbBackend::MySql => "? LIKE CONCAT(repo_path, '%')",
+            _ => "? LIKE repo_path || '%'",
+        };
         let query = git_repo::Entity::find()
-            .filter(Expr::cust(format!("'{}' LIKE repo_path || '%'", repo_path)))
+            .filter(Expr::cust_with_values(condition, [repo_path]))
+            .filter(git_repo::Column::DeletedAt.is_null())
             .order_by_desc(Expr::cust("LENGTH(repo_path)"));
-        tracing::debug!("{}", query.build(DbBackend::Postgres).to_string());
+        tracing::debug!("{}", query.build(backend).to_string());
         let result = query.one(self.get_connection()).await?;
         Ok(result)
     }
 
+    /// Update the path of import repos at or below `old` after the directory has been renamed.
+    pub async fn rename_git_repos(
+        &self,
+        conn: &impl ConnectionTrait,
+        old: &str,
+        new: &str,
+    ) -> Result<(), MegaError> {
+        let repos = git_repo::Entity::find()
+            .filter(git_repo::Column::RepoPath.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in repos {
+            if let Some(path) = replace_path_prefix(&model.repo_path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.repo_name = Set(path.rsplit('/').next().unwrap_or_default().to_owned());
+                a_model.repo_path = Set(path);
+                a_model.updated_at = Set(chrono::Utc::now().naive_utc());
+                a_model.update(conn).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn list_git_repos(&self) -> Result<Vec<git_repo::Model>, MegaError> {
+        Ok(git_repo::Entity::find()
+            .filter(git_repo::Column::DeletedAt.is_null())
+            .order_by_asc(git_repo::Column::RepoPath)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_git_repos_by_ids(
+        &self,
+        ids: Vec<i64>,
+    ) -> Result<Vec<git_repo::Model>, MegaError> {
+        Ok(git_repo::Entity::find()
+            .filter(git_repo::Column::Id.is_in(ids))
+            .filter(git_repo::Column::DeletedAt.is_null())
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The import repositories containing the commit `commit_id`.
+    pub async fn find_git_repos_by_commit(
+        &self,
+        commit_id: &str,
+    ) -> Result<Vec<git_repo::Model>, MegaError> {
+        Ok(git_repo::Entity::find()
+            .inner_join(git_commit::Entity)
+            .filter(git_commit::Column::CommitId.eq(commit_id))
+            .filter(git_repo::Column::DeletedAt.is_null())
+            .distinct()
+            .order_by_asc(git_repo::Column::RepoPath)
+            .all(self.get_connection())
+            .await?)
+    }
+
     pub async fn save_git_repo(&self, repo: git_repo::Model) -> Result<(), MegaError> {
         let a_model = repo.into_active_model();
         git_repo::Entity::insert(a_model)
@@ -232,30 +467,243 @@ impl GitDbStorage {
         Ok(())
     }
 
+    /// Move the repository to the trash, it is hidden from all other lookups until restored.
+    pub async fn delete_git_repo(
+        &self,
+        repo: git_repo::Model,
+        deleted_by: &str,
+    ) -> Result<(), MegaError> {
+        let mut a_model = repo.into_active_model();
+        a_model.deleted_at = Set(Some(chrono::Utc::now().naive_utc()));
+        a_model.deleted_by = Set(Some(deleted_by.to_owned()));
+        a_model.update(self.get_connection()).await?;
+        Ok(())
+    }
+
+    pub async fn restore_git_repo(&self, repo: git_repo::Model) -> Result<(), MegaError> {
+        let mut a_model = repo.into_active_model();
+        a_model.deleted_at = Set(None);
+        a_model.deleted_by = Set(None);
+        a_model.updated_at = Set(chrono::Utc::now().naive_utc());
+        a_model.update(self.get_connection()).await?;
+        Ok(())
+    }
+
+    /// Repositories in the trash, most recently deleted first.
+    pub async fn list_deleted_git_repos(&self) -> Result<Vec<git_repo::Model>, MegaError> {
+        Ok(git_repo::Entity::find()
+            .filter(git_repo::Column::DeletedAt.is_not_null())
+            .order_by_desc(git_repo::Column::DeletedAt)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_deleted_git_repo(
+        &self,
+        id: i64,
+    ) -> Result<Option<git_repo::Model>, MegaError> {
+        Ok(git_repo::Entity::find_by_id(id)
+            .filter(git_repo::Column::DeletedAt.is_not_null())
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// The repository in the trash which still holds `repo_path`, no other repository can be
+    /// created there until it is purged.
+    pub async fn find_deleted_git_repo(
+        &self,
+        repo_path: &str,
+    ) -> Result<Option<git_repo::Model>, MegaError> {
+        Ok(git_repo::Entity::find()
+            .filter(git_repo::Column::RepoPath.eq(repo_path))
+            .filter(git_repo::Column::DeletedAt.is_not_null())
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_git_repos_deleted_before(
+        &self,
+        before: chrono::NaiveDateTime,
+    ) -> Result<Vec<git_repo::Model>, MegaError> {
+        Ok(git_repo::Entity::find()
+            .filter(git_repo::Column::DeletedAt.lt(before))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Permanently remove the repository with its refs and objects. Raw content no other
+    /// repository refers to is deleted as well.
+    pub async fn purge_git_repo(&self, repo_id: i64) -> Result<(), MegaError> {
+        let hashes: Vec<String> = git_blob::Entity::find()
+            .select_only()
+            .column(git_blob::Column::BlobId)
+            .filter(git_blob::Column::RepoId.eq(repo_id))
+            .into_tuple()
+            .all(self.get_connection())
+            .await?;
+        self.transaction(|txn| async move {
+            let conn = &*txn;
+            import_refs::Entity::delete_many()
+                .filter(import_refs::Column::RepoId.eq(repo_id))
+                .exec(conn)
+                .await?;
+            git_tag::Entity::delete_many()
+                .filter(git_tag::Column::RepoId.eq(repo_id))
+                .exec(conn)
+                .await?;
+            git_commit::Entity::delete_many()
+                .filter(git_commit::Column::RepoId.eq(repo_id))
+                .exec(conn)
+                .await?;
+            git_tree::Entity::delete_many()
+                .filter(git_tree::Column::RepoId.eq(repo_id))
+                .exec(conn)
+                .await?;
+            git_blob::Entity::delete_many()
+                .filter(git_blob::Column::RepoId.eq(repo_id))
+                .exec(conn)
+                .await?;
+            git_repo::Entity::delete_by_id(repo_id).exec(conn).await?;
+            Ok(())
+        })
+        .await?;
+        self.ref_cache.invalidate(&repo_id.to_string()).await;
+        self.commit_cache
+            .invalidate_prefix(&format!("{}:", repo_id))
+            .await;
+        // if this fails the content is only kept longer than needed, never lost
+        self.raw_storage.release_refs(&hashes).await
+    }
+
+    /// Ids of the objects of the repository saved before `before`, reachable or not.
+    pub async fn get_object_ids_before(
+        &self,
+        repo_id: i64,
+        before: chrono::NaiveDateTime,
+    ) -> Result<ObjectIds, MegaError> {
+        let conn = self.get_connection();
+        Ok(ObjectIds {
+            commits: git_commit::Entity::find()
+                .select_only()
+                .column(git_commit::Column::CommitId)
+                .filter(git_commit::Column::RepoId.eq(repo_id))
+                .filter(git_commit::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+            trees: git_tree::Entity::find()
+                .select_only()
+                .column(git_tree::Column::TreeId)
+                .filter(git_tree::Column::RepoId.eq(repo_id))
+                .filter(git_tree::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+            blobs: git_blob::Entity::find()
+                .select_only()
+                .column(git_blob::Column::BlobId)
+                .filter(git_blob::Column::RepoId.eq(repo_id))
+                .filter(git_blob::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+            tags: git_tag::Entity::find()
+                .select_only()
+                .column(git_tag::Column::TagId)
+                .filter(git_tag::Column::RepoId.eq(repo_id))
+                .filter(git_tag::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+        })
+    }
+
+    /// Delete the objects of `ids` from the repository. Raw content no other record refers to
+    /// is deleted as well.
+    #[tracing::instrument(skip_all)]
+    pub async fn delete_objects(&self, repo_id: i64, ids: &ObjectIds) -> Result<(), MegaError> {
+        let mut timer = metrics::timer("git_db_storage", "delete_objects");
+        timer.rows(ids.len());
+        self.transaction(|txn| async move {
+            let conn = &*txn;
+            delete_by_ids(&ids.commits, |ids| {
+                git_commit::Entity::delete_many()
+                    .filter(git_commit::Column::RepoId.eq(repo_id))
+                    .filter(git_commit::Column::CommitId.is_in(ids))
+                    .exec(conn)
+            })
+            .await?;
+            delete_by_ids(&ids.trees, |ids| {
+                git_tree::Entity::delete_many()
+                    .filter(git_tree::Column::RepoId.eq(repo_id))
+                    .filter(git_tree::Column::TreeId.is_in(ids))
+                    .exec(conn)
+            })
+            .await?;
+            delete_by_ids(&ids.blobs, |ids| {
+                git_blob::Entity::delete_many()
+                    .filter(git_blob::Column::RepoId.eq(repo_id))
+                    .filter(git_blob::Column::BlobId.is_in(ids))
+                    .exec(conn)
+            })
+            .await?;
+            delete_by_ids(&ids.tags, |ids| {
+                git_tag::Entity::delete_many()
+                    .filter(git_tag::Column::RepoId.eq(repo_id))
+                    .filter(git_tag::Column::TagId.is_in(ids))
+                    .exec(conn)
+            })
+            .await?;
+            Ok(())
+        })
+        .await?;
+        for id in &ids.commits {
+            self.commit_cache
+                .invalidate(&format!("{}:{}", repo_id, id))
+                .await;
+        }
+        // blobs are saved once per repository, each deleted record held one reference
+        self.raw_storage.release_refs(&ids.blobs).await
+    }
+
     pub async fn get_commit_by_hash(
         &self,
         repo_id: i64,
         hash: &str,
     ) -> Result<Option<git_commit::Model>, MegaError> {
-        Ok(git_commit::Entity::find()
-            .filter(git_commit::Column::RepoId.eq(repo_id))
-            .filter(git_commit::Column::CommitId.eq(hash))
-            .one(self.get_connection())
+        let key = format!("{}:{}", repo_id, hash);
+        self.commit_cache
+            .get_or_load(&key, || async {
+                let commit = git_commit::Entity::find()
+                    .filter(git_commit::Column::RepoId.eq(repo_id))
+                    .filter(git_commit::Column::CommitId.eq(hash))
+                    .one(self.get_connection())
+                    .await?;
+                Ok(commit)
+            })
             .await
-            .unwrap())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_commits_by_hashes(
         &self,
         repo_id: i64,
         hashes: &Vec<String>,
     ) -> Result<Vec<git_commit::Model>, MegaError> {
-        Ok(git_commit::Entity::find()
-            .filter(git_commit::Column::RepoId.eq(repo_id))
-            .filter(git_commit::Column::CommitId.is_in(hashes))
-            .all(self.get_connection())
-            .await
-            .unwrap())
+        let mut timer = metrics::timer("git_db_storage", "get_commits_by_hashes");
+        let commits = query_by_ids(hashes.clone(), |ids| {
+            git_commit::Entity::find()
+                .filter(git_commit::Column::RepoId.eq(repo_id))
+                .filter(git_commit::Column::CommitId.is_in(ids))
+                .all(self.get_connection())
+        })
+        .await?;
+        timer.rows(commits.len());
+        Ok(commits)
     }
 
     pub async fn get_commits_by_repo_id(
@@ -281,17 +729,22 @@ impl GitDbStorage {
             .unwrap())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_trees_by_hashes(
         &self,
         repo_id: i64,
         hashes: Vec<String>,
     ) -> Result<Vec<git_tree::Model>, MegaError> {
-        Ok(git_tree::Entity::find()
-            .filter(git_tree::Column::RepoId.eq(repo_id))
-            .filter(git_tree::Column::TreeId.is_in(hashes))
-            .all(self.get_connection())
-            .await
-            .unwrap())
+        let mut timer = metrics::timer("git_db_storage", "get_trees_by_hashes");
+        let trees = query_by_ids(hashes, |ids| {
+            git_tree::Entity::find()
+                .filter(git_tree::Column::RepoId.eq(repo_id))
+                .filter(git_tree::Column::TreeId.is_in(ids))
+                .all(self.get_connection())
+        })
+        .await?;
+        timer.rows(trees.len());
+        Ok(trees)
     }
 
     pub async fn get_tree_by_hash(
@@ -318,17 +771,22 @@ impl GitDbStorage {
             .unwrap())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_blobs_by_hashes(
         &self,
         repo_id: i64,
         hashes: Vec<String>,
     ) -> Result<Vec<git_blob::Model>, MegaError> {
-        Ok(git_blob::Entity::find()
-            .filter(git_blob::Column::RepoId.eq(repo_id))
-            .filter(git_blob::Column::BlobId.is_in(hashes))
-            .all(self.get_connection())
-            .await
-            .unwrap())
+        let mut timer = metrics::timer("git_db_storage", "get_blobs_by_hashes");
+        let blobs = query_by_ids(hashes, |ids| {
+            git_blob::Entity::find()
+                .filter(git_blob::Column::RepoId.eq(repo_id))
+                .filter(git_blob::Column::BlobId.is_in(ids))
+                .all(self.get_connection())
+        })
+        .await?;
+        timer.rows(blobs.len());
+        Ok(blobs)
     }
 
     pub async fn get_tags_by_repo_id(
@@ -0,0 +1,117 @@
+//! Health of the database and its connection pool, served at `/api/v1/health`.
+//!
+//! The pool opens new connections on its own once the database is back after an outage, and
+//! requests waiting for a connection fail after `database.acquire_timeout`. [`watch`] pings the
+//! database in the background, retrying with a growing delay while it is down, so [`check`]
+//! answers from what it saw last and from the pool usage without ever waiting on the database.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use tracing::log;
+
+use crate::storage::init::reconnect_delay;
+
+/// Time between pings while the database is reachable.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+static AVAILABLE: AtomicBool = AtomicBool::new(true);
+static LAST_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DbStatus {
+    Ok,
+    /// Every connection of the pool is in use, new requests wait for one
+    Saturated,
+    /// The last ping of the database failed
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DbHealth {
+    pub status: DbStatus,
+    /// Connections open, including the idle ones
+    pub connections: u32,
+    pub idle: u32,
+    pub max_connections: u32,
+    pub error: Option<String>,
+}
+
+/// Ping the database until the service stops, after a failed ping it is tried again after
+/// 1, 2, 4... seconds up to `max_delay`.
+pub async fn watch(connection: Arc<DatabaseConnection>, max_delay: u64) {
+    let mut failures = 0;
+    loop {
+        match connection.ping().await {
+            Ok(()) => {
+                if failures > 0 {
+                    log::info!(
+                        "Database is reachable again after {} failed pings",
+                        failures
+                    );
+                }
+                failures = 0;
+                AVAILABLE.store(true, Ordering::Relaxed);
+                *LAST_ERROR.lock().unwrap() = None;
+            }
+            Err(err) => {
+                failures += 1;
+                if failures == 1 {
+                    log::error!("Database is unreachable: {}", err);
+                }
+                AVAILABLE.store(false, Ordering::Relaxed);
+                *LAST_ERROR.lock().unwrap() = Some(err.to_string());
+            }
+        }
+        let delay = match failures {
+            0 => CHECK_INTERVAL,
+            _ => reconnect_delay(failures, max_delay),
+        };
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// The health of the database behind `connection`.
+pub fn check(connection: &DatabaseConnection) -> DbHealth {
+    let (connections, idle, max_connections) = pool_usage(connection);
+    let status = if !AVAILABLE.load(Ordering::Relaxed) {
+        DbStatus::Unavailable
+    } else if max_connections > 0 && connections >= max_connections && idle == 0 {
+        DbStatus::Saturated
+    } else {
+        DbStatus::Ok
+    };
+    DbHealth {
+        status,
+        connections,
+        idle,
+        max_connections,
+        error: LAST_ERROR.lock().unwrap().clone(),
+    }
+}
+
+/// Open, idle and most connections of the pool, all 0 for connections without a pool.
+pub fn pool_usage(connection: &DatabaseConnection) -> (u32, u32, u32) {
+    match connection {
+        DatabaseConnection::SqlxPostgresPoolConnection(_) => {
+            let pool = connection.get_postgres_connection_pool();
+            let max = pool.options().get_max_connections();
+            (pool.size(), pool.num_idle() as u32, max)
+        }
+        DatabaseConnection::SqlxMySqlPoolConnection(_) => {
+            let pool = connection.get_mysql_connection_pool();
+            let max = pool.options().get_max_connections();
+            (pool.size(), pool.num_idle() as u32, max)
+        }
+        DatabaseConnection::SqlxSqlitePoolConnection(_) => {
+            let pool = connection.get_sqlite_connection_pool();
+            let max = pool.options().get_max_connections();
+            (pool.size(), pool.num_idle() as u32, max)
+        }
+        _ => (0, 0, 0),
+    }
+}
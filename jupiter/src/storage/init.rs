@@ -1,65 +1,162 @@
 use std::{path::Path, time::Duration};
 
-use sea_orm::{ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DbErr, Statement, TransactionError, TransactionTrait};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection};
+use sea_orm_migration::MigratorTrait;
 use tracing::log;
 
-use common::config::DbConfig;
+use common::config::{DbConfig, MigrationMode};
+use common::errors::MegaError;
 
+use crate::migration::Migrator;
+use crate::storage::metrics;
 use crate::utils::id_generator;
 
+/// Connect to the database and bring its schema up to date as configured by
+/// `database.migration`.
 pub async fn database_connection(db_config: &DbConfig) -> DatabaseConnection {
+    let conn = connect(db_config).await;
+    check_migrations(&conn, db_config.migration).await;
+    conn
+}
+
+/// Connect to the database without looking at its schema.
+pub async fn connect(db_config: &DbConfig) -> DatabaseConnection {
     id_generator::set_up_options().unwrap();
 
-    let is_sqlite = db_config.db_type == "sqlite";
-    let db_path = &db_config.db_path;
-    let db_url = if is_sqlite {
-        if !Path::new(db_path).exists() {
-            log::info!("Creating new sqlite database: {}", db_path);
-            std::fs::File::create(db_path).expect("Failed to create sqlite database");
+    let opt = connect_options(db_config).unwrap_or_else(|err| panic!("{}", err));
+    let mut attempt = 1;
+    let mut conn = loop {
+        match Database::connect(opt.clone()).await {
+            Ok(conn) => break conn,
+            Err(err) if attempt < db_config.connect_retries => {
+                let delay = reconnect_delay(attempt, db_config.reconnect_max_delay);
+                log::warn!(
+                    "Database connection failed, attempt {} of {}, retrying in {}s: {}",
+                    attempt,
+                    db_config.connect_retries,
+                    delay.as_secs(),
+                    err
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(err) => panic!("Database connection failed: {}", err),
         }
-        &format!("sqlite://{}", db_path)
-    } else {
-        &db_config.db_url
     };
+    let slow_threshold = Duration::from_millis(db_config.slow_query_threshold);
+    conn.set_metric_callback(move |info| metrics::record_query(info, slow_threshold));
+    conn
+}
+
+/// Connect to the database once, failing instead of retrying like [`connect`].
+pub async fn try_connect(db_config: &DbConfig) -> Result<DatabaseConnection, MegaError> {
+    Ok(Database::connect(connect_options(db_config)?).await?)
+}
+
+fn connect_options(db_config: &DbConfig) -> Result<ConnectOptions, MegaError> {
+    let db_url = with_statement_timeout(database_url(db_config)?, db_config);
     log::info!("Connecting to database: {}", db_url);
 
-    let mut opt = ConnectOptions::new(db_url.to_owned());
+    let mut opt = ConnectOptions::new(db_url);
     opt.max_connections(db_config.max_connection)
         .min_connections(db_config.min_connection)
-        .acquire_timeout(Duration::from_secs(30))
+        // requests fail once the pool stays exhausted instead of waiting forever
+        .acquire_timeout(Duration::from_secs(db_config.acquire_timeout.max(1)))
         .connect_timeout(Duration::from_secs(20))
-        .idle_timeout(Duration::from_secs(8))
-        .max_lifetime(Duration::from_secs(8))
+        .idle_timeout(Duration::from_secs(db_config.idle_timeout))
+        .max_lifetime(Duration::from_secs(db_config.max_lifetime))
         .sqlx_logging(db_config.sqlx_logging)
         .sqlx_logging_level(log::LevelFilter::Debug);
-    let conn = Database::connect(opt)
-        .await
-        .expect("Database connection failed");
+    Ok(opt)
+}
 
-    // setup sqlite database (execute .sql)
-    if is_sqlite && is_file_empty(db_path) {
-        log::info!("Setting up sqlite database");
-        setup_sql(&conn).await.expect("Failed to setup sqlite database");
-    }
-    conn
+/// The url to connect to for `database.db_type`, sqlite databases are created if missing.
+fn database_url(db_config: &DbConfig) -> Result<String, MegaError> {
+    let db_url = &db_config.db_url;
+    let url = match db_config.db_type.as_str() {
+        "sqlite" => {
+            let db_path = &db_config.db_path;
+            if !Path::new(db_path).exists() {
+                log::info!("Creating new sqlite database: {}", db_path);
+                std::fs::File::create(db_path).map_err(|err| {
+                    MegaError::with_message(&format!("Failed to create sqlite database: {}", err))
+                })?;
+            }
+            format!("sqlite://{}", db_path)
+        }
+        "postgres" if db_url.starts_with("postgres://") || db_url.starts_with("postgresql://") => {
+            db_url.to_owned()
+        }
+        "mysql" if db_url.starts_with("mysql://") => db_url.to_owned(),
+        // sqlx only knows the mysql scheme, which works for mariadb as well
+        "mysql" if db_url.starts_with("mariadb://") => db_url.replacen("mariadb", "mysql", 1),
+        "postgres" | "mysql" => {
+            return Err(MegaError::with_message(&format!(
+                "Database url {} does not match database type {}",
+                db_url, db_config.db_type
+            )))
+        }
+        db_type => {
+            return Err(MegaError::with_message(&format!(
+                "Unsupported database type: {}",
+                db_type
+            )))
+        }
+    };
+    Ok(url)
 }
 
-/// create table from .sql file
-async fn setup_sql(conn: &DatabaseConnection) -> Result<(), TransactionError<DbErr>> {
-    conn.transaction::<_, _, DbErr>(|txn| {
-        Box::pin(async move {
-            let backend = txn.get_database_backend();
+/// Postgres takes the statement timeout as an option of its connections, mysql and sqlite have
+/// no such option.
+fn with_statement_timeout(db_url: String, db_config: &DbConfig) -> String {
+    if db_config.statement_timeout == 0 {
+        return db_url;
+    }
+    if db_config.db_type != "postgres" {
+        log::warn!(
+            "database.statement_timeout is only supported by postgres, ignored for {}",
+            db_config.db_type
+        );
+        return db_url;
+    }
+    let separator = if db_url.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}options=-c%20statement_timeout%3D{}",
+        db_url,
+        separator,
+        db_config.statement_timeout.saturating_mul(1000)
+    )
+}
 
-            // `include_str!` will expand the file while compiling, so `.sql` is not needed after that
-            const SETUP_SQL: &str = include_str!("../../../sql/sqlite/sqlite_20241204_init.sql");
-            txn.execute(Statement::from_string(backend, SETUP_SQL)).await?;
-            Ok(())
-        })
-    })
-    .await
+/// How long to wait after the `attempt`-th failed attempt to reach the database, the delay
+/// doubles with every attempt up to `max_delay` seconds.
+pub fn reconnect_delay(attempt: u32, max_delay: u64) -> Duration {
+    let exp = attempt.clamp(1, 16) - 1;
+    Duration::from_secs((1u64 << exp).min(max_delay.max(1)))
 }
 
-fn is_file_empty(path: &str) -> bool {
-    let metadata = std::fs::metadata(path).unwrap();
-    metadata.len() == 0
-}
\ No newline at end of file
+async fn check_migrations(conn: &DatabaseConnection, mode: MigrationMode) {
+    let pending = Migrator::get_pending_migrations(conn)
+        .await
+        .expect("Failed to read database migrations");
+    if pending.is_empty() {
+        return;
+    }
+    let names: Vec<&str> = pending.iter().map(|m| m.name()).collect();
+    match mode {
+        MigrationMode::Auto => {
+            log::info!("Applying database migrations: {}", names.join(", "));
+            Migrator::up(conn, None)
+                .await
+                .expect("Failed to apply database migrations");
+        }
+        MigrationMode::Warn => log::warn!(
+            "Database schema is behind, run `mega migrate up` to apply: {}",
+            names.join(", ")
+        ),
+        MigrationMode::Refuse => panic!(
+            "Database schema is behind, run `mega migrate up` to apply: {}",
+            names.join(", ")
+        ),
+    }
+}
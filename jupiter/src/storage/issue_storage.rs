@@ -1,8 +1,9 @@
+use std::future::Future;
 use std::sync::Arc;
 
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel,
-    PaginatorTrait, QueryFilter, QueryOrder, Set,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction,
+    EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, Set,
 };
 
 use callisto::db_enums::ConvType;
@@ -11,6 +12,8 @@ use common::errors::MegaError;
 use common::model::Pagination;
 use common::utils::{generate_id, generate_link};
 
+use crate::storage::{self, TenantScope};
+
 #[derive(Clone)]
 pub struct IssueStorage {
     pub connection: Arc<DatabaseConnection>,
@@ -31,13 +34,25 @@ impl IssueStorage {
         }
     }
 
+    /// Run `f` in a transaction, see [`storage::transaction`].
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T, MegaError>
+    where
+        F: FnOnce(Arc<DatabaseTransaction>) -> Fut,
+        Fut: Future<Output = Result<T, MegaError>>,
+    {
+        storage::transaction(self.get_connection(), f).await
+    }
+
+    /// A page of the issues with `status` opened by the users of `scope`, the newest first.
     pub async fn get_issue_by_status(
         &self,
         status: &str,
+        scope: &TenantScope,
         page: Pagination,
     ) -> Result<(Vec<mega_issue::Model>, u64), MegaError> {
         let paginator = mega_issue::Entity::find()
             .filter(mega_issue::Column::Status.eq(status))
+            .filter(scope.users(mega_issue::Column::Owner))
             .order_by_desc(mega_issue::Column::CreatedAt)
             .paginate(self.get_connection(), page.per_page);
         let num_pages = paginator.num_items().await?;
@@ -58,6 +73,7 @@ impl IssueStorage {
 
     pub async fn save_issue(
         &self,
+        conn: &impl ConnectionTrait,
         user_id: i64,
         title: &str,
     ) -> Result<mega_issue::Model, MegaError> {
@@ -71,31 +87,44 @@ impl IssueStorage {
             updated_at: chrono::Utc::now().naive_utc(),
             closed_at: None,
         };
-        model
-            .clone()
-            .into_active_model()
-            .insert(self.get_connection())
-            .await
-            .unwrap();
+        model.clone().into_active_model().insert(conn).await?;
         Ok(model)
     }
 
-    pub async fn close_issue(&self, link: &str) -> Result<(), MegaError> {
-        if let Some(model) = self.get_issue(link).await.unwrap() {
-            let mut issue = model.into_active_model();
-            issue.status = Set("closed".to_owned());
-            issue.update(self.get_connection()).await.unwrap();
-        };
-        Ok(())
+    /// Close the issue `link`, returns it if there is one.
+    pub async fn close_issue(
+        &self,
+        conn: &impl ConnectionTrait,
+        link: &str,
+    ) -> Result<Option<mega_issue::Model>, MegaError> {
+        self.set_status(conn, link, "closed").await
+    }
+
+    /// Reopen the issue `link`, returns it if there is one.
+    pub async fn reopen_issue(
+        &self,
+        conn: &impl ConnectionTrait,
+        link: &str,
+    ) -> Result<Option<mega_issue::Model>, MegaError> {
+        self.set_status(conn, link, "open").await
     }
 
-    pub async fn reopen_issue(&self, link: &str) -> Result<(), MegaError> {
-        if let Some(model) = self.get_issue(link).await.unwrap() {
-            let mut issue = model.into_active_model();
-            issue.status = Set("open".to_owned());
-            issue.update(self.get_connection()).await.unwrap();
+    async fn set_status(
+        &self,
+        conn: &impl ConnectionTrait,
+        link: &str,
+        status: &str,
+    ) -> Result<Option<mega_issue::Model>, MegaError> {
+        let Some(model) = mega_issue::Entity::find()
+            .filter(mega_issue::Column::Link.eq(link))
+            .one(conn)
+            .await?
+        else {
+            return Ok(None);
         };
-        Ok(())
+        let mut issue = model.into_active_model();
+        issue.status = Set(status.to_owned());
+        Ok(Some(issue.update(conn).await?))
     }
 
     pub async fn get_issue_conversations(
@@ -109,6 +138,21 @@ impl IssueStorage {
         Ok(model?)
     }
 
+    /// The issue `link` with its conversations in the order they were added, loaded with one
+    /// query.
+    pub async fn get_issue_with_conversations(
+        &self,
+        link: &str,
+    ) -> Result<Option<(mega_issue::Model, Vec<mega_conversation::Model>)>, MegaError> {
+        let mut res = mega_issue::Entity::find()
+            .filter(mega_issue::Column::Link.eq(link))
+            .find_with_related(mega_conversation::Entity)
+            .order_by_asc(mega_conversation::Column::CreatedAt)
+            .all(self.get_connection())
+            .await?;
+        Ok(res.pop())
+    }
+
     pub async fn remove_issue_conversation(&self, id: i64) -> Result<(), MegaError> {
         mega_conversation::Entity::delete_by_id(id)
             .exec(self.get_connection())
@@ -119,6 +163,7 @@ impl IssueStorage {
 
     pub async fn add_issue_conversation(
         &self,
+        conn: &impl ConnectionTrait,
         link: &str,
         user_id: i64,
         comment: Option<String>,
@@ -133,7 +178,7 @@ impl IssueStorage {
             updated_at: chrono::Utc::now().naive_utc(),
         };
         let conversation = conversation.into_active_model();
-        let res = conversation.insert(self.get_connection()).await.unwrap();
+        let res = conversation.insert(conn).await?;
         Ok(res.id)
     }
 }
@@ -0,0 +1,242 @@
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use sea_orm::sea_query::{Expr, LockBehavior, LockType};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbBackend, EntityTrait,
+    IntoActiveModel, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+use serde::Serialize;
+
+use callisto::background_job::*;
+use callisto::db_enums::BackgroundJobState;
+use common::config::JobConfig;
+use common::errors::MegaError;
+use common::utils::generate_id;
+
+#[derive(Clone)]
+pub struct JobStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl JobStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        JobStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        JobStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Queue a job of `job_type` run with `payload` at `scheduled_at`, or as soon as possible
+    /// if it is `None`.
+    pub async fn enqueue(
+        &self,
+        conn: &impl ConnectionTrait,
+        job_type: &str,
+        payload: &impl Serialize,
+        scheduled_at: Option<NaiveDateTime>,
+    ) -> Result<Model, MegaError> {
+        let payload = serde_json::to_string(payload)
+            .map_err(|err| MegaError::with_message(&err.to_string()))?;
+        let now = chrono::Utc::now().naive_utc();
+        let model = Model {
+            id: generate_id(),
+            job_type: job_type.to_owned(),
+            payload,
+            state: BackgroundJobState::Queued,
+            scheduled_at: scheduled_at.unwrap_or(now),
+            attempts: 0,
+            locked_by: None,
+            locked_until: None,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        };
+        Ok(model.into_active_model().insert(conn).await?)
+    }
+
+    pub async fn get_job(&self, id: i64) -> Result<Option<Model>, MegaError> {
+        Ok(Entity::find_by_id(id).one(self.get_connection()).await?)
+    }
+
+    /// Claim up to `limit` due jobs for `worker`, they are held by it for `lease` seconds.
+    ///
+    /// Jobs whose lease is over are claimed again. Rows locked by another worker claiming at
+    /// the same time are skipped, sqlite has no row locks but allows one writer only.
+    pub async fn claim_jobs(
+        &self,
+        worker: &str,
+        limit: u64,
+        lease: u64,
+    ) -> Result<Vec<Model>, MegaError> {
+        let txn = self.get_connection().begin().await?;
+        let now = chrono::Utc::now().naive_utc();
+        let mut query = Entity::find()
+            .filter(
+                Column::State
+                    .eq(BackgroundJobState::Queued)
+                    .and(Column::ScheduledAt.lte(now))
+                    .or(Column::State
+                        .eq(BackgroundJobState::Running)
+                        .and(Column::LockedUntil.lte(now))),
+            )
+            .order_by_asc(Column::ScheduledAt)
+            .limit(limit);
+        if txn.get_database_backend() != DbBackend::Sqlite {
+            query = query.lock_with_behavior(LockType::Update, LockBehavior::SkipLocked);
+        }
+        let due = query.all(&txn).await?;
+        let locked_until = now + chrono::Duration::seconds(lease as i64);
+        let mut claimed = Vec::new();
+        for mut job in due {
+            // the attempt count only matches if nobody claimed the job since it was read
+            let res = Entity::update_many()
+                .col_expr(Column::State, Expr::value(BackgroundJobState::Running))
+                .col_expr(Column::Attempts, Expr::value(job.attempts + 1))
+                .col_expr(Column::LockedBy, Expr::value(worker))
+                .col_expr(Column::LockedUntil, Expr::value(locked_until))
+                .col_expr(Column::UpdatedAt, Expr::value(now))
+                .filter(Column::Id.eq(job.id))
+                .filter(Column::Attempts.eq(job.attempts))
+                .exec(&txn)
+                .await?;
+            if res.rows_affected == 1 {
+                job.state = BackgroundJobState::Running;
+                job.attempts += 1;
+                job.locked_by = Some(worker.to_owned());
+                job.locked_until = Some(locked_until);
+                job.updated_at = now;
+                claimed.push(job);
+            }
+        }
+        txn.commit().await?;
+        Ok(claimed)
+    }
+
+    /// Hold the job `id` for another `lease` seconds, returns `false` if `worker` lost it in
+    /// the meantime.
+    pub async fn extend_lease(&self, id: i64, worker: &str, lease: u64) -> Result<bool, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let res = Self::held_by(id, worker)
+            .col_expr(
+                Column::LockedUntil,
+                Expr::value(now + chrono::Duration::seconds(lease as i64)),
+            )
+            .col_expr(Column::UpdatedAt, Expr::value(now))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Record that `worker` finished the job `id`.
+    pub async fn complete_job(&self, id: i64, worker: &str) -> Result<(), MegaError> {
+        Self::held_by(id, worker)
+            .col_expr(Column::State, Expr::value(BackgroundJobState::Done))
+            .col_expr(Column::LockedBy, Expr::value(Option::<String>::None))
+            .col_expr(
+                Column::LockedUntil,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .col_expr(Column::LastError, Expr::value(Option::<String>::None))
+            .col_expr(
+                Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Record that the `attempts`-th attempt of `worker` to run the job `id` failed with
+    /// `error`, returns the state it is in now.
+    pub async fn fail_job(
+        &self,
+        id: i64,
+        worker: &str,
+        attempts: i32,
+        error: &str,
+        config: &JobConfig,
+    ) -> Result<BackgroundJobState, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let (state, scheduled_at) = after_failure(attempts, config, now);
+        Self::held_by(id, worker)
+            .col_expr(Column::State, Expr::value(state))
+            .col_expr(Column::ScheduledAt, Expr::value(scheduled_at))
+            .col_expr(Column::LockedBy, Expr::value(Option::<String>::None))
+            .col_expr(
+                Column::LockedUntil,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .col_expr(Column::LastError, Expr::value(error))
+            .col_expr(Column::UpdatedAt, Expr::value(now))
+            .exec(self.get_connection())
+            .await?;
+        Ok(state)
+    }
+
+    /// Jobs being run, with the worker holding them.
+    pub async fn list_running_jobs(&self) -> Result<Vec<Model>, MegaError> {
+        Ok(Entity::find()
+            .filter(Column::State.eq(BackgroundJobState::Running))
+            .order_by_asc(Column::LockedUntil)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Take the running job `id` away from its worker and queue it to run again right away,
+    /// returns `false` if it is not running.
+    ///
+    /// The worker can no longer record the outcome of the job, it is meant for workers which
+    /// hang without their lease running out.
+    pub async fn release_job(&self, id: i64) -> Result<bool, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let res = Entity::update_many()
+            .col_expr(Column::State, Expr::value(BackgroundJobState::Queued))
+            .col_expr(Column::ScheduledAt, Expr::value(now))
+            .col_expr(Column::LockedBy, Expr::value(Option::<String>::None))
+            .col_expr(
+                Column::LockedUntil,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .col_expr(Column::UpdatedAt, Expr::value(now))
+            .filter(Column::Id.eq(id))
+            .filter(Column::State.eq(BackgroundJobState::Running))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Updates the job `id` if it is still run by `worker`, a worker whose lease ran out must
+    /// not overwrite the outcome of the worker which took the job over.
+    fn held_by(id: i64, worker: &str) -> sea_orm::UpdateMany<Entity> {
+        Entity::update_many()
+            .filter(Column::Id.eq(id))
+            .filter(Column::State.eq(BackgroundJobState::Running))
+            .filter(Column::LockedBy.eq(worker))
+    }
+}
+
+/// The state of a job after its `attempts`-th attempt failed at `now` and when it is run
+/// again, the delay doubles with every attempt.
+pub fn after_failure(
+    attempts: i32,
+    config: &JobConfig,
+    now: NaiveDateTime,
+) -> (BackgroundJobState, NaiveDateTime) {
+    if attempts >= config.max_attempts as i32 {
+        return (BackgroundJobState::Failed, now);
+    }
+    let exp = attempts.clamp(1, 16) as u32 - 1;
+    let delay = config.retry_delay.saturating_mul(1 << exp);
+    (
+        BackgroundJobState::Queued,
+        now + chrono::Duration::seconds(delay as i64),
+    )
+}
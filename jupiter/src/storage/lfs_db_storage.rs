@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use sea_orm::{
@@ -78,6 +79,21 @@ impl LfsDbStorage {
         Ok(result.iter().map(|r| r.ori_oid.clone()).collect())
     }
 
+    /// Ids of all objects referenced by the database, including the parts of split objects.
+    pub async fn list_lfs_oids(&self) -> Result<HashSet<String>, MegaError> {
+        let mut oids: HashSet<String> = lfs_objects::Entity::find()
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .map(|o| o.oid)
+            .collect();
+        let relations = lfs_split_relations::Entity::find()
+            .all(self.get_connection())
+            .await?;
+        oids.extend(relations.into_iter().map(|r| r.sub_oid));
+        Ok(oids)
+    }
+
     pub async fn delete_lfs_object(&self, oid: String) -> Result<(), MegaError> {
         lfs_objects::Entity::delete_by_id(oid)
             .exec(self.get_connection())
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ActiveEnum, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel,
+    QueryFilter, QueryOrder, QuerySelect, Set,
+};
+
+use callisto::db_enums::{JobStatus, JobTrigger, MaintenanceTask};
+use callisto::{commit_graph, integrity_report, maintenance_job, maintenance_mode};
+use common::errors::MegaError;
+use common::utils::generate_id;
+
+use crate::storage::batch_save_model_with_conflict;
+
+/// Id of the single row of the maintenance mode.
+const MODE_ID: i64 = 1;
+
+#[derive(Clone)]
+pub struct MaintenanceStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl MaintenanceStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        MaintenanceStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        MaintenanceStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Record a new running job, returns `None` if the same task is still running.
+    pub async fn start_job(
+        &self,
+        task: MaintenanceTask,
+        triggered_by: JobTrigger,
+        operator: Option<String>,
+        target: Option<String>,
+    ) -> Result<Option<maintenance_job::Model>, MegaError> {
+        let running = maintenance_job::Entity::find()
+            .filter(maintenance_job::Column::Task.eq(task))
+            .filter(maintenance_job::Column::Status.eq(JobStatus::Running))
+            .one(self.get_connection())
+            .await?;
+        if running.is_some() {
+            return Ok(None);
+        }
+        let model = maintenance_job::Model {
+            id: generate_id(),
+            task,
+            triggered_by,
+            target,
+            status: JobStatus::Running,
+            operator,
+            message: None,
+            started_at: chrono::Utc::now().naive_utc(),
+            finished_at: None,
+        };
+        Ok(Some(
+            model
+                .into_active_model()
+                .insert(self.get_connection())
+                .await?,
+        ))
+    }
+
+    pub async fn finish_job(
+        &self,
+        job: maintenance_job::Model,
+        status: JobStatus,
+        message: String,
+    ) -> Result<maintenance_job::Model, MegaError> {
+        let mut a_model = job.into_active_model();
+        a_model.status = Set(status);
+        a_model.message = Set(Some(message));
+        a_model.finished_at = Set(Some(chrono::Utc::now().naive_utc()));
+        Ok(a_model.update(self.get_connection()).await?)
+    }
+
+    /// Jobs still marked as running were interrupted by a restart, mark them as failed.
+    pub async fn fail_running_jobs(&self) -> Result<(), MegaError> {
+        maintenance_job::Entity::update_many()
+            .col_expr(
+                maintenance_job::Column::Status,
+                Expr::value(JobStatus::Failed.to_value()),
+            )
+            .col_expr(
+                maintenance_job::Column::Message,
+                Expr::value("interrupted by service restart"),
+            )
+            .filter(maintenance_job::Column::Status.eq(JobStatus::Running))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Mark the job `id` as failed with `message` if it is still running, returns `false` if
+    /// it is not.
+    pub async fn fail_running_job(&self, id: i64, message: &str) -> Result<bool, MegaError> {
+        let res = maintenance_job::Entity::update_many()
+            .col_expr(
+                maintenance_job::Column::Status,
+                Expr::value(JobStatus::Failed.to_value()),
+            )
+            .col_expr(maintenance_job::Column::Message, Expr::value(message))
+            .col_expr(
+                maintenance_job::Column::FinishedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .filter(maintenance_job::Column::Id.eq(id))
+            .filter(maintenance_job::Column::Status.eq(JobStatus::Running))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    pub async fn list_jobs(
+        &self,
+        task: Option<MaintenanceTask>,
+        limit: u64,
+    ) -> Result<Vec<maintenance_job::Model>, MegaError> {
+        let mut query = maintenance_job::Entity::find();
+        if let Some(task) = task {
+            query = query.filter(maintenance_job::Column::Task.eq(task));
+        }
+        Ok(query
+            .order_by_desc(maintenance_job::Column::StartedAt)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_job(&self, id: i64) -> Result<Option<maintenance_job::Model>, MegaError> {
+        Ok(maintenance_job::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// The start time of the latest run of each task, used to resume schedules after a restart.
+    pub async fn last_runs(
+        &self,
+    ) -> Result<HashMap<MaintenanceTask, chrono::NaiveDateTime>, MegaError> {
+        let mut res = HashMap::new();
+        let jobs = maintenance_job::Entity::find()
+            .filter(maintenance_job::Column::TriggeredBy.eq(JobTrigger::Schedule))
+            .all(self.get_connection())
+            .await?;
+        for job in jobs {
+            let last = res.entry(job.task).or_insert(job.started_at);
+            if *last < job.started_at {
+                *last = job.started_at;
+            }
+        }
+        Ok(res)
+    }
+
+    /// Delete finished jobs started before `before`, returns the number of removed rows.
+    pub async fn delete_jobs_before(
+        &self,
+        before: chrono::NaiveDateTime,
+    ) -> Result<u64, MegaError> {
+        let res = maintenance_job::Entity::delete_many()
+            .filter(maintenance_job::Column::StartedAt.lt(before))
+            .filter(maintenance_job::Column::Status.ne(JobStatus::Running))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    pub async fn get_commit_generations(&self) -> Result<HashMap<String, i64>, MegaError> {
+        Ok(commit_graph::Entity::find()
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .map(|m| (m.commit_id, m.generation))
+            .collect())
+    }
+
+    pub async fn save_commit_generations(
+        &self,
+        generations: Vec<(String, i64)>,
+    ) -> Result<(), MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let models: Vec<commit_graph::ActiveModel> = generations
+            .into_iter()
+            .map(|(commit_id, generation)| {
+                commit_graph::Model {
+                    commit_id,
+                    generation,
+                    created_at: now,
+                }
+                .into_active_model()
+            })
+            .collect();
+        batch_save_model_with_conflict(
+            self.get_connection(),
+            models,
+            OnConflict::column(commit_graph::Column::CommitId)
+                .update_column(commit_graph::Column::Generation)
+                .to_owned(),
+        )
+        .await
+    }
+
+    pub async fn save_report(
+        &self,
+        job_id: i64,
+        path: &str,
+        healthy: bool,
+        checked_objects: i64,
+        problems: String,
+    ) -> Result<integrity_report::Model, MegaError> {
+        let model = integrity_report::Model {
+            id: generate_id(),
+            job_id,
+            path: path.to_owned(),
+            healthy,
+            checked_objects,
+            problems,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    pub async fn list_reports(
+        &self,
+        path: Option<String>,
+        limit: u64,
+    ) -> Result<Vec<integrity_report::Model>, MegaError> {
+        let mut query = integrity_report::Entity::find();
+        if let Some(path) = path {
+            query = query.filter(integrity_report::Column::Path.eq(path));
+        }
+        Ok(query
+            .order_by_desc(integrity_report::Column::CreatedAt)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_report(
+        &self,
+        job_id: i64,
+    ) -> Result<Option<integrity_report::Model>, MegaError> {
+        Ok(integrity_report::Entity::find()
+            .filter(integrity_report::Column::JobId.eq(job_id))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// The maintenance mode of the instance, `None` if it is not in maintenance.
+    pub async fn get_mode(&self) -> Result<Option<maintenance_mode::Model>, MegaError> {
+        Ok(maintenance_mode::Entity::find_by_id(MODE_ID)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Put the instance in maintenance mode, or change the message if it already is.
+    pub async fn enable_mode(
+        &self,
+        message: Option<String>,
+        operator: &str,
+    ) -> Result<(), MegaError> {
+        let model = maintenance_mode::Model {
+            id: MODE_ID,
+            message,
+            enabled_by: operator.to_owned(),
+            enabled_at: chrono::Utc::now().naive_utc(),
+        };
+        maintenance_mode::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::column(maintenance_mode::Column::Id)
+                    .update_columns([
+                        maintenance_mode::Column::Message,
+                        maintenance_mode::Column::EnabledBy,
+                        maintenance_mode::Column::EnabledAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// End the maintenance mode, returns whether the instance was in maintenance.
+    pub async fn disable_mode(&self) -> Result<bool, MegaError> {
+        let res = maintenance_mode::Entity::delete_by_id(MODE_ID)
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+}
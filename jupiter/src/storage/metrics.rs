@@ -0,0 +1,276 @@
+//! Timing of database queries and storage methods, served by the gateway at `/metrics` in the
+//! prometheus text format.
+//!
+//! Every query is timed by its kind and table through the metric callback of the connection,
+//! queries slower than `database.slow_query_threshold` are logged with their statement. The
+//! storage methods git operations go through record their time and the rows they read or wrote
+//! with a [`MethodTimer`] and run in a span of their name, which is exported with the traces of
+//! the requests. The caches in front of them count their hits and misses.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sea_orm::metric::Info;
+
+/// Upper bounds in seconds of the histogram buckets.
+const BUCKETS: [f64; 10] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0];
+
+static QUERIES: Mutex<BTreeMap<(&'static str, String), QueryStats>> = Mutex::new(BTreeMap::new());
+static METHODS: Mutex<BTreeMap<(&'static str, &'static str), MethodStats>> =
+    Mutex::new(BTreeMap::new());
+/// Hits and misses by cache
+static CACHES: Mutex<BTreeMap<&'static str, (u64, u64)>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Cumulative, like the buckets of the text format
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, le) in self.buckets.iter_mut().zip(BUCKETS) {
+            if secs <= le {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    fn render(&self, res: &mut String, name: &str, labels: &str) {
+        for (bucket, le) in self.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                res,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, le, bucket
+            );
+        }
+        let _ = writeln!(
+            res,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let _ = writeln!(res, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(res, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueryStats {
+    time: Histogram,
+    failed: u64,
+    slow: u64,
+}
+
+#[derive(Debug, Default)]
+struct MethodStats {
+    time: Histogram,
+    rows: u64,
+}
+
+/// Record a query run by the connection, set up as its metric callback.
+pub fn record_query(info: &Info<'_>, slow_threshold: Duration) {
+    let (kind, table) = statement_table(&info.statement.sql);
+    let slow = !slow_threshold.is_zero() && info.elapsed >= slow_threshold;
+    if slow {
+        tracing::warn!(
+            "slow query on {} took {} ms: {}",
+            table,
+            info.elapsed.as_millis(),
+            info.statement.sql
+        );
+    }
+    let mut queries = QUERIES.lock().unwrap();
+    let stats = queries.entry((kind, table.to_owned())).or_default();
+    stats.time.observe(info.elapsed);
+    stats.failed += u64::from(info.failed);
+    stats.slow += u64::from(slow);
+}
+
+/// The kind of a statement and the table it reads or writes first.
+fn statement_table(sql: &str) -> (&'static str, &str) {
+    let sql = sql.trim_start();
+    let keyword = sql.split_whitespace().next().unwrap_or_default();
+    let (kind, marker) = match keyword.to_ascii_uppercase().as_str() {
+        "SELECT" => ("select", " FROM "),
+        "INSERT" => ("insert", " INTO "),
+        "UPDATE" => ("update", "UPDATE "),
+        "DELETE" => ("delete", " FROM "),
+        _ => return ("other", ""),
+    };
+    let table = sql
+        .find(marker)
+        .and_then(|pos| sql[pos + marker.len()..].split_whitespace().next())
+        .unwrap_or_default()
+        .trim_matches(|c| c == '"' || c == '`');
+    (kind, table)
+}
+
+/// Time of a storage method, recorded when dropped.
+pub struct MethodTimer {
+    storage: &'static str,
+    method: &'static str,
+    start: Instant,
+    rows: usize,
+}
+
+impl MethodTimer {
+    /// Rows read or written by the method.
+    pub fn rows(&mut self, rows: usize) {
+        self.rows = rows;
+    }
+}
+
+impl Drop for MethodTimer {
+    fn drop(&mut self) {
+        let mut methods = METHODS.lock().unwrap();
+        let stats = methods.entry((self.storage, self.method)).or_default();
+        stats.time.observe(self.start.elapsed());
+        stats.rows += self.rows as u64;
+    }
+}
+
+pub fn timer(storage: &'static str, method: &'static str) -> MethodTimer {
+    MethodTimer {
+        storage,
+        method,
+        start: Instant::now(),
+        rows: 0,
+    }
+}
+
+/// Count a lookup of [`crate::cache::Cache`] `name`.
+pub fn record_cache(name: &'static str, hit: bool) {
+    let mut caches = CACHES.lock().unwrap();
+    let (hits, misses) = caches.entry(name).or_default();
+    if hit {
+        *hits += 1;
+    } else {
+        *misses += 1;
+    }
+}
+
+/// All storage metrics in the prometheus text format.
+pub fn render() -> String {
+    let mut res = String::new();
+    {
+        let queries = QUERIES.lock().unwrap();
+        res.push_str("# HELP mega_db_query_seconds Time of database queries.\n");
+        res.push_str("# TYPE mega_db_query_seconds histogram\n");
+        for ((kind, table), stats) in queries.iter() {
+            let labels = format!("kind=\"{}\",table=\"{}\"", kind, table);
+            stats
+                .time
+                .render(&mut res, "mega_db_query_seconds", &labels);
+        }
+        res.push_str("# HELP mega_db_query_failures_total Database queries which failed.\n");
+        res.push_str("# TYPE mega_db_query_failures_total counter\n");
+        for ((kind, table), stats) in queries.iter() {
+            let _ = writeln!(
+                res,
+                "mega_db_query_failures_total{{kind=\"{}\",table=\"{}\"}} {}",
+                kind, table, stats.failed
+            );
+        }
+        res.push_str("# HELP mega_db_slow_queries_total Queries above the slow query threshold.\n");
+        res.push_str("# TYPE mega_db_slow_queries_total counter\n");
+        for ((kind, table), stats) in queries.iter() {
+            let _ = writeln!(
+                res,
+                "mega_db_slow_queries_total{{kind=\"{}\",table=\"{}\"}} {}",
+                kind, table, stats.slow
+            );
+        }
+    }
+    let methods = METHODS.lock().unwrap();
+    res.push_str("# HELP mega_storage_call_seconds Time of storage methods.\n");
+    res.push_str("# TYPE mega_storage_call_seconds histogram\n");
+    for ((storage, method), stats) in methods.iter() {
+        let labels = format!("storage=\"{}\",method=\"{}\"", storage, method);
+        stats
+            .time
+            .render(&mut res, "mega_storage_call_seconds", &labels);
+    }
+    res.push_str("# HELP mega_storage_rows_total Rows read or written by storage methods.\n");
+    res.push_str("# TYPE mega_storage_rows_total counter\n");
+    for ((storage, method), stats) in methods.iter() {
+        let _ = writeln!(
+            res,
+            "mega_storage_rows_total{{storage=\"{}\",method=\"{}\"}} {}",
+            storage, method, stats.rows
+        );
+    }
+    let caches = CACHES.lock().unwrap();
+    res.push_str("# HELP mega_cache_requests_total Lookups of ref and commit caches.\n");
+    res.push_str("# TYPE mega_cache_requests_total counter\n");
+    for (name, (hits, misses)) in caches.iter() {
+        for (result, count) in [("hit", hits), ("miss", misses)] {
+            let _ = writeln!(
+                res,
+                "mega_cache_requests_total{{cache=\"{}\",result=\"{}\"}} {}",
+                name, result, count
+            );
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{render, statement_table, timer, Histogram};
+
+    #[test]
+    fn test_statement_table() {
+        assert_eq!(
+            statement_table(r#"SELECT "git_commit"."id" FROM "git_commit" WHERE "repo_id" = $1"#),
+            ("select", "git_commit")
+        );
+        assert_eq!(
+            statement_table("INSERT INTO `raw_blob` (`id`) VALUES (?)"),
+            ("insert", "raw_blob")
+        );
+        assert_eq!(
+            statement_table(r#"UPDATE "mega_refs" SET "ref_commit_hash" = $1"#),
+            ("update", "mega_refs")
+        );
+        assert_eq!(
+            statement_table(r#"DELETE FROM "mega_refs" WHERE "id" = $1"#),
+            ("delete", "mega_refs")
+        );
+        assert_eq!(statement_table("PRAGMA foreign_keys = ON"), ("other", ""));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_secs(10));
+        let mut res = String::new();
+        histogram.render(&mut res, "query_seconds", "table=\"t\"");
+        assert!(res.contains("query_seconds_bucket{table=\"t\",le=\"0.001\"} 0\n"));
+        assert!(res.contains("query_seconds_bucket{table=\"t\",le=\"0.005\"} 1\n"));
+        assert!(res.contains("query_seconds_bucket{table=\"t\",le=\"+Inf\"} 2\n"));
+        assert!(res.contains("query_seconds_count{table=\"t\"} 2\n"));
+    }
+
+    #[test]
+    fn test_method_timer() {
+        let mut t = timer("test_storage", "get_rows");
+        t.rows(3);
+        drop(t);
+        let res = render();
+        assert!(res
+            .contains("mega_storage_rows_total{storage=\"test_storage\",method=\"get_rows\"} 3\n"));
+        assert!(res.contains(
+            "mega_storage_call_seconds_count{storage=\"test_storage\",method=\"get_rows\"} 1\n"
+        ));
+    }
+}
@@ -1,17 +1,159 @@
+pub mod feature_storage;
 pub mod git_db_storage;
+pub mod health;
 pub mod init;
 pub mod issue_storage;
+pub mod job_storage;
 pub mod lfs_db_storage;
+pub mod maintenance_storage;
+pub mod metrics;
 pub mod mono_storage;
 pub mod mq_storage;
 pub mod mr_storage;
+pub mod outbox_storage;
+pub mod policy_storage;
+pub mod quota_storage;
 pub mod raw_db_storage;
+pub mod release_storage;
+pub mod schedule_storage;
+pub mod secret_storage;
+pub mod signature_storage;
+pub mod stats_storage;
 pub mod user_storage;
+pub mod webhook_storage;
 pub mod ztm_storage;
 
-use sea_orm::{sea_query::OnConflict, ActiveModelTrait, ConnectionTrait, EntityTrait};
+use std::future::Future;
+use std::sync::Arc;
 
+use futures::{stream, StreamExt, TryStreamExt};
+use sea_orm::{
+    sea_query::{Expr, OnConflict, Query},
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DatabaseTransaction, DbBackend, DbErr, DeleteResult, EntityTrait, Iterable, PrimaryKeyToColumn,
+    Statement, TransactionTrait,
+};
+
+use callisto::user;
 use common::errors::MegaError;
+use mercury::internal::object::tree::TreeItem;
+
+/// Ids bound in one `IN` clause, sqlite allows 32766 parameters and postgres 65535.
+const IN_CLAUSE_CHUNK: usize = 1000;
+/// Chunks of one lookup queried at the same time.
+const CONCURRENT_CHUNKS: usize = 4;
+
+/// Runs `f` in a transaction of `connection`.
+///
+/// Everything `f` writes through the transaction it is given is committed when it returns `Ok`,
+/// and rolled back when it returns an error. Storages expose this as their `transaction` method,
+/// their write methods take the connection to write with so they can be combined in one
+/// transaction.
+///
+/// The transaction can be cloned into tasks spawned by `f`, but they must have finished when
+/// `f` returns.
+pub async fn transaction<T, F, Fut>(connection: &DatabaseConnection, f: F) -> Result<T, MegaError>
+where
+    F: FnOnce(Arc<DatabaseTransaction>) -> Fut,
+    Fut: Future<Output = Result<T, MegaError>>,
+{
+    let txn = Arc::new(connection.begin().await?);
+    // a transaction dropped before it is committed is rolled back
+    let value = f(txn.clone()).await?;
+    let txn = Arc::try_unwrap(txn)
+        .map_err(|_| MegaError::with_message("transaction is still in use after it finished"))?;
+    txn.commit().await?;
+    Ok(value)
+}
+
+/// Seconds mysql waits for the lock of a repository before the push fails.
+const REPO_LOCK_TIMEOUT: i64 = 60;
+
+/// Lock of a repository held while a push updates its refs, see [`lock_repo`].
+pub struct RepoLock {
+    // the transaction the lock is held by, sqlite has none
+    txn: Option<DatabaseTransaction>,
+    key: i64,
+}
+
+impl RepoLock {
+    /// Release the lock. It must always be released, mysql keeps the lock of a dropped
+    /// `RepoLock` until its connection is closed.
+    pub async fn release(self) -> Result<(), MegaError> {
+        let Some(txn) = self.txn else {
+            return Ok(());
+        };
+        if txn.get_database_backend() == DbBackend::MySql {
+            txn.execute(Statement::from_sql_and_values(
+                DbBackend::MySql,
+                "SELECT RELEASE_LOCK(?)",
+                [mysql_lock_name(self.key).into()],
+            ))
+            .await?;
+        }
+        // postgres releases transaction level advisory locks on commit
+        txn.commit().await?;
+        Ok(())
+    }
+}
+
+/// Lock the repository at `path` so pushes updating several of its refs are not interleaved
+/// with other pushes to it, across all instances using the database.
+///
+/// Refs are still updated with compare-and-swap, the lock only keeps a push from updating some
+/// of its refs while another one updates the others. Sqlite allows a single writer at a time,
+/// nothing is locked there.
+pub async fn lock_repo(connection: &DatabaseConnection, path: &str) -> Result<RepoLock, MegaError> {
+    let key = lock_key(path);
+    let backend = connection.get_database_backend();
+    if backend == DbBackend::Sqlite {
+        return Ok(RepoLock { txn: None, key });
+    }
+    // advisory locks belong to a session, the transaction keeps one connection of the pool
+    let txn = connection.begin().await?;
+    if backend == DbBackend::MySql {
+        let row = txn
+            .query_one(Statement::from_sql_and_values(
+                backend,
+                "SELECT GET_LOCK(?, ?) AS locked",
+                [mysql_lock_name(key).into(), REPO_LOCK_TIMEOUT.into()],
+            ))
+            .await?;
+        let locked: Option<i64> = match row {
+            Some(row) => row.try_get("", "locked")?,
+            None => None,
+        };
+        if locked != Some(1) {
+            return Err(MegaError::with_message(&format!(
+                "timed out waiting for the lock of {}",
+                path
+            )));
+        }
+    } else {
+        txn.execute(Statement::from_sql_and_values(
+            backend,
+            "SELECT pg_advisory_xact_lock($1)",
+            [key.into()],
+        ))
+        .await?;
+    }
+    Ok(RepoLock {
+        txn: Some(txn),
+        key,
+    })
+}
+
+fn mysql_lock_name(key: i64) -> String {
+    format!("mega_repo_{}", key)
+}
+
+/// 64-bit FNV-1a hash of `path`, the same on every instance unlike the std hasher.
+fn lock_key(path: &str) -> i64 {
+    let hash = path.bytes().fold(0xcbf29ce484222325_u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    hash as i64
+}
 
 /// Performs batch saving of models in the database.
 ///
@@ -19,7 +161,8 @@ use common::errors::MegaError;
 /// The models should implement the `ActiveModelTrait` trait, which provides the necessary functionality for saving and inserting the models.
 ///
 /// The method splits the models into smaller chunks, each containing models configured by chunk_size, and inserts them into the database using the `E::insert_many` function.
-/// The results of each insertion are collected into a vector of futures.
+/// The results of each insertion are collected into a vector of futures, models which already
+/// exist are skipped.
 ///
 /// Note: Currently, SQLx does not support packets larger than 16MB.
 /// # Arguments
@@ -45,7 +188,10 @@ where
     batch_save_model_with_conflict(
         connection,
         save_models,
-        OnConflict::new().do_nothing().to_owned(),
+        // mysql has no `DO NOTHING`, the primary key is updated to itself there instead
+        OnConflict::new()
+            .do_nothing_on(E::PrimaryKey::iter().map(|pk| pk.into_column()))
+            .to_owned(),
     )
     .await
 }
@@ -67,6 +213,168 @@ where
             .exec(connection);
         results.push(res);
     }
-    futures::future::join_all(results).await;
+    for res in futures::future::join_all(results).await {
+        match res {
+            // every model of the chunk exists already
+            Ok(_) | Err(DbErr::RecordNotInserted) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
     Ok(())
 }
+
+/// Look up rows by many ids, `query` is run for chunks of at most [`IN_CLAUSE_CHUNK`] distinct
+/// ids, several chunks at the same time.
+///
+/// Rows come in no particular order.
+pub async fn query_by_ids<T, F, Fut>(ids: Vec<String>, query: F) -> Result<Vec<T>, MegaError>
+where
+    F: Fn(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<Vec<T>, DbErr>>,
+{
+    let mut ids = ids;
+    ids.sort_unstable();
+    ids.dedup();
+    let rows = stream::iter(
+        ids.chunks(IN_CLAUSE_CHUNK)
+            .map(|chunk| query(chunk.to_vec())),
+    )
+    .buffer_unordered(CONCURRENT_CHUNKS)
+    .try_concat()
+    .await?;
+    Ok(rows)
+}
+
+/// Delete rows by many ids, `delete` is run for chunks of at most [`IN_CLAUSE_CHUNK`] ids one
+/// after another. Returns the number of deleted rows.
+pub async fn delete_by_ids<F, Fut>(ids: &[String], delete: F) -> Result<u64, MegaError>
+where
+    F: Fn(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<DeleteResult, DbErr>>,
+{
+    let mut deleted = 0;
+    for chunk in ids.chunks(IN_CLAUSE_CHUNK) {
+        deleted += delete(chunk.to_vec()).await?.rows_affected;
+    }
+    Ok(deleted)
+}
+
+/// Ids of stored git objects by their kind.
+#[derive(Debug, Default)]
+pub struct ObjectIds {
+    pub commits: Vec<String>,
+    pub trees: Vec<String>,
+    pub blobs: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl ObjectIds {
+    pub fn len(&self) -> usize {
+        self.commits.len() + self.trees.len() + self.blobs.len() + self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Keep only the ids for which `keep` returns `true`.
+    pub fn retain(&mut self, keep: impl Fn(&str) -> bool) {
+        for ids in [
+            &mut self.commits,
+            &mut self.trees,
+            &mut self.blobs,
+            &mut self.tags,
+        ] {
+            ids.retain(|id| keep(id));
+        }
+    }
+}
+
+/// Which entries of a tree are listed: those whose name starts with `prefix`, sorted by name,
+/// skipping the first `offset` of them and at most `limit`, all of them without a limit.
+#[derive(Clone, Debug, Default)]
+pub struct TreeItemRange {
+    pub prefix: String,
+    pub offset: u64,
+    pub limit: Option<u64>,
+}
+
+impl TreeItemRange {
+    /// Page `page` of `per_page` entries, pages start at 1.
+    pub fn page(prefix: &str, page: u64, per_page: u64) -> Self {
+        TreeItemRange {
+            prefix: prefix.to_owned(),
+            offset: page.saturating_sub(1).saturating_mul(per_page),
+            limit: Some(per_page),
+        }
+    }
+
+    /// The entries of `items` in the range, and how many entries match the prefix.
+    pub fn apply(&self, mut items: Vec<TreeItem>) -> (Vec<TreeItem>, u64) {
+        items.retain(|item| item.name.starts_with(&self.prefix));
+        let total = items.len() as u64;
+        items.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let start = (self.offset as usize).min(items.len());
+        let end = match self.limit {
+            Some(limit) => start.saturating_add(limit as usize).min(items.len()),
+            None => items.len(),
+        };
+        items.truncate(end);
+        items.drain(..start);
+        (items, total)
+    }
+}
+
+/// The data of which tenant a query returns, see `common::config::TenancyConfig`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TenantScope {
+    /// Everything, tenancy is disabled
+    #[default]
+    All,
+    /// Only the data of the tenant `name`, below its directory `path`
+    Tenant { name: String, path: String },
+    /// Only the data of the instance, outside of the directories of all tenants
+    Instance { paths: Vec<String> },
+    /// No data at all
+    Nothing,
+}
+
+impl TenantScope {
+    /// Condition on a column of paths in the monorepo.
+    pub fn paths<C: ColumnTrait + Copy>(&self, column: C) -> Condition {
+        match self {
+            TenantScope::All => Condition::all(),
+            TenantScope::Tenant { path, .. } => below(column, path),
+            TenantScope::Instance { paths } => paths.iter().fold(Condition::all(), |cond, path| {
+                cond.add(below(column, path).not())
+            }),
+            TenantScope::Nothing => Condition::all().add(Expr::val(1).eq(0)),
+        }
+    }
+
+    /// Condition on a column of user ids, by the tenant of the users.
+    pub fn users<C: ColumnTrait>(&self, column: C) -> Condition {
+        let tenant = match self {
+            TenantScope::All => return Condition::all(),
+            TenantScope::Tenant { name, .. } => user::Column::Tenant.eq(name.as_str()),
+            TenantScope::Instance { .. } => user::Column::Tenant.is_null(),
+            TenantScope::Nothing => return Condition::all().add(Expr::val(1).eq(0)),
+        };
+        Condition::all().add(
+            column.in_subquery(
+                Query::select()
+                    .column(user::Column::Id)
+                    .from(user::Entity)
+                    .and_where(tenant)
+                    .to_owned(),
+            ),
+        )
+    }
+}
+
+/// `path` and everything below it, but not its siblings starting with the same name.
+fn below<C: ColumnTrait>(column: C, path: &str) -> Condition {
+    Condition::any()
+        .add(column.eq(path))
+        .add(column.starts_with(format!("{}/", path)))
+}
@@ -1,23 +1,45 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 
 use futures::{stream, StreamExt};
+use sea_orm::sea_query::{Expr, OnConflict};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter, QueryOrder, QuerySelect
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction,
+    EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set,
 };
 
-use callisto::{mega_blob, mega_commit, mega_refs, mega_tag, mega_tree, raw_blob};
+use callisto::db_enums::{ChangeType, Visibility};
+use callisto::{
+    branch_setting, mega_blob, mega_commit, mega_commit_path, mega_refs, mega_tag, mega_tree,
+    raw_blob, repo_redirect, repo_visibility, subtree_split, tag_protection, virtual_repo,
+};
 use common::config::MonoConfig;
 use common::errors::MegaError;
-use common::utils::{generate_id, MEGA_BRANCH_NAME};
+use common::model::Pagination;
+use common::utils::{generate_id, replace_path_prefix, MEGA_BRANCH_NAME, TAG_REF_PREFIX};
+use mercury::internal::object::tree::{Tree, TreeItem};
 use mercury::internal::object::MegaObjectModel;
 use mercury::internal::{object::commit::Commit, pack::entry::Entry};
 
-use crate::storage::batch_save_model;
+use crate::cache::{Cache, CacheBackend};
+use crate::storage::raw_db_storage::RawDbStorage;
+use crate::storage::{
+    self, batch_save_model, batch_save_model_with_conflict, delete_by_ids, metrics, query_by_ids,
+    ObjectIds, TreeItemRange,
+};
 use crate::utils::converter::MegaModelConverter;
 
 #[derive(Clone)]
 pub struct MonoStorage {
     pub connection: Arc<DatabaseConnection>,
+    /// Saves the content of new blobs
+    raw_storage: RawDbStorage,
+    /// Refs by path
+    ref_cache: Cache<Vec<mega_refs::Model>>,
+    /// Commits by id
+    commit_cache: Cache<mega_commit::Model>,
 }
 
 #[derive(Debug)]
@@ -25,27 +47,59 @@ struct GitObjects {
     pub commits: Vec<mega_commit::ActiveModel>,
     trees: Vec<mega_tree::ActiveModel>,
     blobs: Vec<mega_blob::ActiveModel>,
-    raw_blobs: Vec<raw_blob::ActiveModel>,
+    raw_blobs: Vec<raw_blob::Model>,
     tags: Vec<mega_tag::ActiveModel>,
 }
 
+/// A file or directory changed by a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathChange {
+    /// From the monorepo root
+    pub path: String,
+    pub change_type: ChangeType,
+    pub old_id: Option<String>,
+    pub new_id: Option<String>,
+}
+
 impl MonoStorage {
     pub fn get_connection(&self) -> &DatabaseConnection {
         &self.connection
     }
 
-    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
-        MonoStorage { connection }
+    pub async fn new(
+        connection: Arc<DatabaseConnection>,
+        raw_storage: RawDbStorage,
+        cache: &CacheBackend,
+    ) -> Self {
+        MonoStorage {
+            connection,
+            raw_storage,
+            ref_cache: cache.refs("mega_refs"),
+            commit_cache: cache.commits("mega_commit"),
+        }
     }
 
     pub fn mock() -> Self {
         MonoStorage {
             connection: Arc::new(DatabaseConnection::default()),
+            raw_storage: RawDbStorage::mock(),
+            ref_cache: Cache::disabled("mega_refs"),
+            commit_cache: Cache::disabled("mega_commit"),
         }
     }
 
+    /// Run `f` in a transaction, see [`storage::transaction`].
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T, MegaError>
+    where
+        F: FnOnce(Arc<DatabaseTransaction>) -> Fut,
+        Fut: Future<Output = Result<T, MegaError>>,
+    {
+        storage::transaction(self.get_connection(), f).await
+    }
+
     pub async fn save_ref(
         &self,
+        conn: &impl ConnectionTrait,
         path: &str,
         ref_name: Option<String>,
         ref_commit_hash: &str,
@@ -60,48 +114,54 @@ impl MonoStorage {
             created_at: chrono::Utc::now().naive_utc(),
             updated_at: chrono::Utc::now().naive_utc(),
         };
-        model
-            .into_active_model()
-            .insert(self.get_connection())
-            .await
-            .unwrap();
+        model.into_active_model().insert(conn).await?;
+        self.ref_cache.invalidate(path).await;
         Ok(())
     }
 
+    /// Remove the branches of `path` and the paths below it, their tags are kept.
     pub async fn remove_refs(&self, path: &str) -> Result<(), MegaError> {
         mega_refs::Entity::delete_many()
             .filter(mega_refs::Column::Path.starts_with(path))
+            .filter(mega_refs::Column::RefName.not_like(format!("{}%", TAG_REF_PREFIX)))
             .exec(self.get_connection())
             .await?;
+        self.ref_cache.invalidate_prefix(path).await;
         Ok(())
     }
 
-    pub async fn remove_ref(&self, refs: mega_refs::Model) -> Result<(), MegaError> {
-        mega_refs::Entity::delete_by_id(refs.id)
-            .exec(self.get_connection())
-            .await?;
+    pub async fn remove_ref(
+        &self,
+        conn: &impl ConnectionTrait,
+        refs: mega_refs::Model,
+    ) -> Result<(), MegaError> {
+        mega_refs::Entity::delete_by_id(refs.id).exec(conn).await?;
+        self.ref_cache.invalidate(&refs.path).await;
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_refs(&self, path: &str) -> Result<Vec<mega_refs::Model>, MegaError> {
-        let result = mega_refs::Entity::find()
-            .filter(mega_refs::Column::Path.eq(path))
-            .order_by_asc(mega_refs::Column::RefName)
-            .all(self.get_connection())
-            .await?;
+        let mut timer = metrics::timer("mono_storage", "get_refs");
+        let result = self
+            .ref_cache
+            .get_or_load(path, || async {
+                let refs = mega_refs::Entity::find()
+                    .filter(mega_refs::Column::Path.eq(path))
+                    .order_by_asc(mega_refs::Column::RefName)
+                    .all(self.get_connection())
+                    .await?;
+                Ok(Some(refs))
+            })
+            .await?
+            .unwrap_or_default();
+        timer.rows(result.len());
         Ok(result)
     }
 
-    pub async fn get_ref(
-        &self,
-        path: &str,
-    ) -> Result<Option<mega_refs::Model>, MegaError> {
-        let result = mega_refs::Entity::find()
-            .filter(mega_refs::Column::Path.eq(path))
-            .filter(mega_refs::Column::RefName.eq(MEGA_BRANCH_NAME.to_owned()))
-            .one(self.get_connection())
-            .await?;
-        Ok(result)
+    pub async fn get_ref(&self, path: &str) -> Result<Option<mega_refs::Model>, MegaError> {
+        let refs = self.get_refs(path).await?;
+        Ok(refs.into_iter().find(|r| r.ref_name == MEGA_BRANCH_NAME))
     }
 
     pub async fn get_ref_by_commit(
@@ -117,6 +177,32 @@ impl MonoStorage {
         Ok(result)
     }
 
+    /// The tags of `path`, they point to an annotated tag object or to a commit.
+    pub async fn get_tag_refs(&self, path: &str) -> Result<Vec<mega_refs::Model>, MegaError> {
+        let refs = self.get_refs(path).await?;
+        Ok(refs
+            .into_iter()
+            .filter(|r| r.ref_name.starts_with(TAG_REF_PREFIX))
+            .collect())
+    }
+
+    /// Save the annotated tag object `tag` unless it exists, the ref pointing to it is saved
+    /// with `save_ref`.
+    pub async fn save_tag(
+        &self,
+        conn: &impl ConnectionTrait,
+        tag: mega_tag::Model,
+    ) -> Result<(), MegaError> {
+        batch_save_model_with_conflict(
+            conn,
+            vec![tag.into_active_model()],
+            OnConflict::new()
+                .do_nothing_on([mega_tag::Column::TagId])
+                .to_owned(),
+        )
+        .await
+    }
+
     pub async fn get_mr_ref(&self, ref_name: &str) -> Result<Option<mega_refs::Model>, MegaError> {
         let res = mega_refs::Entity::find()
             .filter(mega_refs::Column::RefName.eq(ref_name))
@@ -125,20 +211,48 @@ impl MonoStorage {
         Ok(res)
     }
 
-    pub async fn update_ref(&self, refs: mega_refs::Model) -> Result<(), MegaError> {
-        let mut ref_data: mega_refs::ActiveModel = refs.into();
-        ref_data.reset(mega_refs::Column::RefCommitHash);
-        ref_data.reset(mega_refs::Column::RefTreeHash);
-        ref_data.reset(mega_refs::Column::UpdatedAt);
-        ref_data.update(self.get_connection()).await.unwrap();
+    /// Point `refs` to its new commit and tree, if it still points to `old_commit`.
+    ///
+    /// Fails with [`MegaError::ref_conflict`] if the ref was changed since it was read, so
+    /// concurrent updates never overwrite each other.
+    pub async fn update_ref(
+        &self,
+        conn: &impl ConnectionTrait,
+        refs: mega_refs::Model,
+        old_commit: &str,
+    ) -> Result<(), MegaError> {
+        let res = mega_refs::Entity::update_many()
+            .col_expr(
+                mega_refs::Column::RefCommitHash,
+                Expr::value(refs.ref_commit_hash),
+            )
+            .col_expr(
+                mega_refs::Column::RefTreeHash,
+                Expr::value(refs.ref_tree_hash),
+            )
+            .col_expr(
+                mega_refs::Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .filter(mega_refs::Column::Id.eq(refs.id))
+            .filter(mega_refs::Column::RefCommitHash.eq(old_commit))
+            .exec(conn)
+            .await?;
+        self.ref_cache.invalidate(&refs.path).await;
+        if res.rows_affected == 0 {
+            return Err(MegaError::ref_conflict());
+        }
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn save_entry(
         &self,
         commit_id: &str,
         entry_list: Vec<Entry>,
     ) -> Result<(), MegaError> {
+        let mut timer = metrics::timer("mono_storage", "save_entry");
+        timer.rows(entry_list.len());
         let git_objects = Arc::new(Mutex::new(GitObjects {
             commits: Vec::new(),
             trees: Vec::new(),
@@ -165,7 +279,7 @@ impl MonoStorage {
                         MegaObjectModel::Blob(mut blob, raw) => {
                             commit_id.clone_into(&mut blob.commit_id);
                             git_objects.blobs.push(blob.clone().into_active_model());
-                            git_objects.raw_blobs.push(raw.into_active_model());
+                            git_objects.raw_blobs.push(raw);
                         }
                         MegaObjectModel::Tag(tag) => git_objects.tags.push(tag.into_active_model()),
                     }
@@ -178,23 +292,25 @@ impl MonoStorage {
             .into_inner()
             .unwrap();
 
-        batch_save_model(self.get_connection(), git_objects.commits)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.trees)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.blobs)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.raw_blobs)
-            .await
-            .unwrap();
-        batch_save_model(self.get_connection(), git_objects.tags)
-            .await
-            .unwrap();
-
-        Ok(())
+        // the objects of a batch are saved completely or not at all
+        self.transaction(|txn| async move {
+            let conn = &*txn;
+            batch_save_model(conn, git_objects.commits).await?;
+            batch_save_model(conn, git_objects.trees).await?;
+            batch_save_model(conn, git_objects.blobs).await?;
+            // every blob record refers to the content of its raw blob
+            let hashes: Vec<String> = git_objects
+                .raw_blobs
+                .iter()
+                .map(|b| b.sha1.clone())
+                .collect();
+            self.raw_storage
+                .save_raw_blobs(conn, git_objects.raw_blobs)
+                .await?;
+            self.raw_storage.add_refs(conn, &hashes).await?;
+            batch_save_model(conn, git_objects.tags).await
+        })
+        .await
     }
 
     pub async fn init_monorepo(&self, mono_config: &MonoConfig) {
@@ -204,62 +320,78 @@ impl MonoStorage {
         }
         let converter = MegaModelConverter::init(mono_config);
         let commit: mega_commit::Model = converter.commit.into();
-        mega_commit::Entity::insert(commit.into_active_model())
-            .exec(self.get_connection())
-            .await
-            .unwrap();
-        mega_refs::Entity::insert(converter.refs)
-            .exec(self.get_connection())
-            .await
-            .unwrap();
-
-        let mega_trees = converter.mega_trees.borrow().values().cloned().collect();
-        batch_save_model(self.get_connection(), mega_trees)
-            .await
-            .unwrap();
-        let mega_blobs = converter.mega_blobs.borrow().values().cloned().collect();
-        batch_save_model(self.get_connection(), mega_blobs)
-            .await
-            .unwrap();
+        let mega_trees: Vec<mega_tree::ActiveModel> =
+            converter.mega_trees.borrow().values().cloned().collect();
+        let mega_blobs: Vec<mega_blob::ActiveModel> =
+            converter.mega_blobs.borrow().values().cloned().collect();
         let raw_blobs = converter.raw_blobs.borrow().values().cloned().collect();
-        batch_save_model(self.get_connection(), raw_blobs)
-            .await
-            .unwrap();
+        let hashes: Vec<String> = converter
+            .mega_blobs
+            .borrow()
+            .keys()
+            .map(|id| id.to_string())
+            .collect();
+        // a partly saved monorepo would be taken as initialized by the next start
+        self.transaction(|txn| async move {
+            let conn = &*txn;
+            mega_commit::Entity::insert(commit.into_active_model())
+                .exec(conn)
+                .await?;
+            batch_save_model(conn, mega_trees).await?;
+            batch_save_model(conn, mega_blobs).await?;
+            self.raw_storage.save_raw_blobs(conn, raw_blobs).await?;
+            self.raw_storage.add_refs(conn, &hashes).await?;
+            mega_refs::Entity::insert(converter.refs).exec(conn).await?;
+            self.ref_cache.invalidate("/").await;
+            Ok(())
+        })
+        .await
+        .unwrap();
     }
 
-    pub async fn save_mega_commits(&self, commits: Vec<Commit>) -> Result<(), MegaError> {
+    pub async fn save_mega_commits(
+        &self,
+        conn: &impl ConnectionTrait,
+        commits: Vec<Commit>,
+    ) -> Result<(), MegaError> {
         let mega_commits: Vec<mega_commit::Model> =
             commits.into_iter().map(mega_commit::Model::from).collect();
         let mut save_models = Vec::new();
         for mega_commit in mega_commits {
             save_models.push(mega_commit.into_active_model());
         }
-        batch_save_model(self.get_connection(), save_models)
-            .await
-            .unwrap();
-        Ok(())
+        batch_save_model(conn, save_models).await
     }
 
     pub async fn get_commit_by_hash(
         &self,
         hash: &str,
     ) -> Result<Option<mega_commit::Model>, MegaError> {
-        Ok(mega_commit::Entity::find()
-            .filter(mega_commit::Column::CommitId.eq(hash))
-            .one(self.get_connection())
+        self.commit_cache
+            .get_or_load(hash, || async {
+                let commit = mega_commit::Entity::find()
+                    .filter(mega_commit::Column::CommitId.eq(hash))
+                    .one(self.get_connection())
+                    .await?;
+                Ok(commit)
+            })
             .await
-            .unwrap())
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_commits_by_hashes(
         &self,
         hashes: &Vec<String>,
     ) -> Result<Vec<mega_commit::Model>, MegaError> {
-        Ok(mega_commit::Entity::find()
-            .filter(mega_commit::Column::CommitId.is_in(hashes))
-            .all(self.get_connection())
-            .await
-            .unwrap())
+        let mut timer = metrics::timer("mono_storage", "get_commits_by_hashes");
+        let commits = query_by_ids(hashes.clone(), |ids| {
+            mega_commit::Entity::find()
+                .filter(mega_commit::Column::CommitId.is_in(ids))
+                .all(self.get_connection())
+        })
+        .await?;
+        timer.rows(commits.len());
+        Ok(commits)
     }
 
     pub async fn get_tree_by_hash(
@@ -273,27 +405,824 @@ impl MonoStorage {
             .unwrap())
     }
 
+    /// The entries of tree `hash` in `range` and how many entries match its prefix, `None` if
+    /// the tree does not exist.
+    ///
+    /// Only the requested entries are handed out, so large directories are listed in pages.
+    pub async fn get_tree_items(
+        &self,
+        hash: &str,
+        range: &TreeItemRange,
+    ) -> Result<Option<(Vec<TreeItem>, u64)>, MegaError> {
+        let tree = self.get_tree_by_hash(hash).await?.map(Tree::from);
+        Ok(tree.map(|tree| range.apply(tree.tree_items)))
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn get_trees_by_hashes(
         &self,
         hashes: Vec<String>,
     ) -> Result<Vec<mega_tree::Model>, MegaError> {
-        Ok(mega_tree::Entity::find()
-            .filter(mega_tree::Column::TreeId.is_in(hashes))
-            .distinct()
-            .all(self.get_connection())
-            .await
-            .unwrap())
+        let mut timer = metrics::timer("mono_storage", "get_trees_by_hashes");
+        let trees = query_by_ids(hashes, |ids| {
+            mega_tree::Entity::find()
+                .filter(mega_tree::Column::TreeId.is_in(ids))
+                .distinct()
+                .all(self.get_connection())
+        })
+        .await?;
+        timer.rows(trees.len());
+        Ok(trees)
     }
 
+    #[tracing::instrument(skip_all)]
     pub async fn get_mega_blobs_by_hashes(
         &self,
         hashes: Vec<String>,
     ) -> Result<Vec<mega_blob::Model>, MegaError> {
-        Ok(mega_blob::Entity::find()
-            .filter(mega_blob::Column::BlobId.is_in(hashes))
+        let mut timer = metrics::timer("mono_storage", "get_mega_blobs_by_hashes");
+        let blobs = query_by_ids(hashes, |ids| {
+            mega_blob::Entity::find()
+                .filter(mega_blob::Column::BlobId.is_in(ids))
+                .all(self.get_connection())
+        })
+        .await?;
+        timer.rows(blobs.len());
+        Ok(blobs)
+    }
+
+    pub async fn save_visibility(
+        &self,
+        path: &str,
+        visibility: Visibility,
+    ) -> Result<(), MegaError> {
+        let exist = repo_visibility::Entity::find()
+            .filter(repo_visibility::Column::Path.eq(path))
+            .one(self.get_connection())
+            .await?;
+        let now = chrono::Utc::now().naive_utc();
+        match exist {
+            Some(model) => {
+                let mut a_model = model.into_active_model();
+                a_model.visibility = Set(visibility);
+                a_model.updated_at = Set(now);
+                a_model.update(self.get_connection()).await?;
+            }
+            None => {
+                let model = repo_visibility::Model {
+                    id: generate_id(),
+                    path: path.to_owned(),
+                    visibility,
+                    created_at: now,
+                    updated_at: now,
+                };
+                model
+                    .into_active_model()
+                    .insert(self.get_connection())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Visibility of `path`, inherited from the nearest ancestor which has one set.
+    ///
+    /// Paths without any configured visibility are public.
+    pub async fn get_visibility(&self, path: &Path) -> Result<Visibility, MegaError> {
+        let ancestors: Vec<String> = path
+            .ancestors()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect();
+        let res = repo_visibility::Entity::find()
+            .filter(repo_visibility::Column::Path.is_in(ancestors))
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .max_by_key(|m| m.path.len())
+            .map(|m| m.visibility)
+            .unwrap_or_default();
+        Ok(res)
+    }
+
+    /// Create or replace the branch settings of `path`, `protected_branches` is stored as json.
+    pub async fn save_branch_setting(
+        &self,
+        path: &str,
+        delete_on_merge: bool,
+        protected_branches: String,
+    ) -> Result<branch_setting::Model, MegaError> {
+        let exist = branch_setting::Entity::find()
+            .filter(branch_setting::Column::Path.eq(path))
+            .one(self.get_connection())
+            .await?;
+        let now = chrono::Utc::now().naive_utc();
+        let res = match exist {
+            Some(model) => {
+                let mut a_model = model.into_active_model();
+                a_model.delete_on_merge = Set(delete_on_merge);
+                a_model.protected_branches = Set(protected_branches);
+                a_model.updated_at = Set(now);
+                a_model.update(self.get_connection()).await?
+            }
+            None => {
+                let model = branch_setting::Model {
+                    id: generate_id(),
+                    path: path.to_owned(),
+                    delete_on_merge,
+                    protected_branches,
+                    created_at: now,
+                    updated_at: now,
+                };
+                model
+                    .into_active_model()
+                    .insert(self.get_connection())
+                    .await?
+            }
+        };
+        Ok(res)
+    }
+
+    /// Branch settings of `path`, inherited from the nearest ancestor which has them set.
+    pub async fn get_branch_setting(
+        &self,
+        path: &Path,
+    ) -> Result<Option<branch_setting::Model>, MegaError> {
+        let ancestors: Vec<String> = path
+            .ancestors()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect();
+        let res = branch_setting::Entity::find()
+            .filter(branch_setting::Column::Path.is_in(ancestors))
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .max_by_key(|m| m.path.len());
+        Ok(res)
+    }
+
+    /// Refs of directories below the root which were not updated since `before`.
+    pub async fn get_refs_updated_before(
+        &self,
+        before: chrono::NaiveDateTime,
+    ) -> Result<Vec<mega_refs::Model>, MegaError> {
+        Ok(mega_refs::Entity::find()
+            .filter(mega_refs::Column::Path.ne("/"))
+            .filter(mega_refs::Column::RefName.not_like(format!("{}%", TAG_REF_PREFIX)))
+            .filter(mega_refs::Column::UpdatedAt.lt(before))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn save_tag_rule(
+        &self,
+        path: &str,
+        pattern: &str,
+        created_by: &str,
+    ) -> Result<tag_protection::Model, MegaError> {
+        let model = tag_protection::Model {
+            id: generate_id(),
+            path: path.to_owned(),
+            pattern: pattern.to_owned(),
+            created_by: created_by.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_tag_rule(&self, id: i64) -> Result<Option<tag_protection::Model>, MegaError> {
+        Ok(tag_protection::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn delete_tag_rule(&self, id: i64) -> Result<(), MegaError> {
+        tag_protection::Entity::delete_by_id(id)
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Tag rules which apply to `path`, the rules of all its ancestors are inherited.
+    pub async fn get_tag_rules(
+        &self,
+        path: &Path,
+    ) -> Result<Vec<tag_protection::Model>, MegaError> {
+        let ancestors: Vec<String> = path
+            .ancestors()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect();
+        Ok(tag_protection::Entity::find()
+            .filter(tag_protection::Column::Path.is_in(ancestors))
+            .order_by_asc(tag_protection::Column::Path)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn save_split(
+        &self,
+        path: &str,
+        repo_path: &str,
+        created_by: &str,
+    ) -> Result<subtree_split::Model, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let model = subtree_split::Model {
+            id: generate_id(),
+            path: path.to_owned(),
+            repo_path: repo_path.to_owned(),
+            last_commit: None,
+            last_split: None,
+            created_by: created_by.to_owned(),
+            created_at: now,
+            updated_at: now,
+        };
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_split(&self, id: i64) -> Result<Option<subtree_split::Model>, MegaError> {
+        Ok(subtree_split::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_split_by_repo_path(
+        &self,
+        repo_path: &str,
+    ) -> Result<Option<subtree_split::Model>, MegaError> {
+        Ok(subtree_split::Entity::find()
+            .filter(subtree_split::Column::RepoPath.eq(repo_path))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn list_splits(&self) -> Result<Vec<subtree_split::Model>, MegaError> {
+        Ok(subtree_split::Entity::find()
+            .order_by_asc(subtree_split::Column::Path)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Record the monorepo commit a split has processed and the resulting head of the split.
+    pub async fn update_split(
+        &self,
+        conn: &impl ConnectionTrait,
+        model: subtree_split::Model,
+        last_commit: &str,
+        last_split: Option<String>,
+    ) -> Result<subtree_split::Model, MegaError> {
+        let mut a_model = model.into_active_model();
+        a_model.last_commit = Set(Some(last_commit.to_owned()));
+        a_model.last_split = Set(last_split);
+        a_model.updated_at = Set(chrono::Utc::now().naive_utc());
+        Ok(a_model.update(conn).await?)
+    }
+
+    pub async fn delete_split(&self, id: i64) -> Result<(), MegaError> {
+        subtree_split::Entity::delete_by_id(id)
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_virtual_repo(
+        &self,
+        path: &str,
+    ) -> Result<Option<virtual_repo::Model>, MegaError> {
+        Ok(virtual_repo::Entity::find()
+            .filter(virtual_repo::Column::Path.eq(path))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Record the monorepo commit the history of a cloned directory is translated up to and
+    /// the translated commit its ref points to.
+    pub async fn save_virtual_repo(
+        &self,
+        conn: &impl ConnectionTrait,
+        path: &str,
+        root_commit: &str,
+        commit_id: &str,
+    ) -> Result<virtual_repo::Model, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let existing = virtual_repo::Entity::find()
+            .filter(virtual_repo::Column::Path.eq(path))
+            .one(conn)
+            .await?;
+        match existing {
+            Some(model) => {
+                let mut a_model = model.into_active_model();
+                a_model.root_commit = Set(root_commit.to_owned());
+                a_model.commit_id = Set(commit_id.to_owned());
+                a_model.updated_at = Set(now);
+                Ok(a_model.update(conn).await?)
+            }
+            None => {
+                let model = virtual_repo::Model {
+                    id: generate_id(),
+                    path: path.to_owned(),
+                    root_commit: root_commit.to_owned(),
+                    commit_id: commit_id.to_owned(),
+                    created_at: now,
+                    updated_at: now,
+                };
+                Ok(model.into_active_model().insert(conn).await?)
+            }
+        }
+    }
+
+    /// Move refs, visibility, branch settings, tag rules, subtree splits, virtual repositories,
+    /// changed paths of commits and redirects stored for `old` and its children to `new`.
+    pub async fn rename_paths(
+        &self,
+        conn: &impl ConnectionTrait,
+        old: &str,
+        new: &str,
+    ) -> Result<(), MegaError> {
+        let refs = mega_refs::Entity::find()
+            .filter(mega_refs::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in refs {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        self.ref_cache.invalidate_prefix(old).await;
+        self.ref_cache.invalidate_prefix(new).await;
+        let visibilities = repo_visibility::Entity::find()
+            .filter(repo_visibility::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in visibilities {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        let branch_settings = branch_setting::Entity::find()
+            .filter(branch_setting::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in branch_settings {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        let tag_rules = tag_protection::Entity::find()
+            .filter(tag_protection::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in tag_rules {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        let splits = subtree_split::Entity::find()
+            .filter(
+                subtree_split::Column::Path
+                    .starts_with(old)
+                    .or(subtree_split::Column::RepoPath.starts_with(old)),
+            )
+            .all(conn)
+            .await?;
+        for model in splits {
+            let path = replace_path_prefix(&model.path, old, new);
+            let repo_path = replace_path_prefix(&model.repo_path, old, new);
+            if path.is_none() && repo_path.is_none() {
+                continue;
+            }
+            let mut a_model = model.into_active_model();
+            if let Some(path) = path {
+                a_model.path = Set(path);
+            }
+            if let Some(repo_path) = repo_path {
+                a_model.repo_path = Set(repo_path);
+            }
+            a_model.update(conn).await?;
+        }
+        let virtual_repos = virtual_repo::Entity::find()
+            .filter(virtual_repo::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in virtual_repos {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        let commit_paths = mega_commit_path::Entity::find()
+            .filter(mega_commit_path::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in commit_paths {
+            let path = replace_path_prefix(&model.path, old, new);
+            let ref_path = replace_path_prefix(&model.ref_path, old, new);
+            if path.is_none() && ref_path.is_none() {
+                continue;
+            }
+            let mut a_model = model.into_active_model();
+            if let Some(path) = path {
+                a_model.path = Set(path);
+            }
+            if let Some(ref_path) = ref_path {
+                a_model.ref_path = Set(ref_path);
+            }
+            a_model.update(conn).await?;
+        }
+        // keep earlier redirects pointing to the latest location
+        let redirects = repo_redirect::Entity::find()
+            .filter(repo_redirect::Column::NewPath.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in redirects {
+            if let Some(path) = replace_path_prefix(&model.new_path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.new_path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Redirect requests for `old_path` to `new_path` until `expires_at`.
+    pub async fn save_redirect(
+        &self,
+        conn: &impl ConnectionTrait,
+        old_path: &str,
+        new_path: &str,
+        expires_at: chrono::NaiveDateTime,
+    ) -> Result<(), MegaError> {
+        // the new path is in use again, e.g. renamed back
+        repo_redirect::Entity::delete_many()
+            .filter(
+                repo_redirect::Column::OldPath
+                    .eq(old_path)
+                    .or(repo_redirect::Column::OldPath.eq(new_path)),
+            )
+            .exec(conn)
+            .await?;
+        let model = repo_redirect::Model {
+            id: generate_id(),
+            old_path: old_path.to_owned(),
+            new_path: new_path.to_owned(),
+            expires_at,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        model.into_active_model().insert(conn).await?;
+        Ok(())
+    }
+
+    /// Returns the current location of `path` if it (or one of its ancestors) has been renamed
+    /// and the redirect has not expired yet.
+    pub async fn find_redirect(&self, path: &Path) -> Result<Option<String>, MegaError> {
+        let ancestors: Vec<String> = path
+            .ancestors()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect();
+        let res = repo_redirect::Entity::find()
+            .filter(repo_redirect::Column::OldPath.is_in(ancestors))
+            .filter(repo_redirect::Column::ExpiresAt.gt(chrono::Utc::now().naive_utc()))
             .all(self.get_connection())
+            .await?
+            .into_iter()
+            .max_by_key(|m| m.old_path.len())
+            .and_then(|m| replace_path_prefix(path.to_str().unwrap(), &m.old_path, &m.new_path));
+        Ok(res)
+    }
+
+    pub async fn delete_expired_redirects(&self) -> Result<u64, MegaError> {
+        let res = repo_redirect::Entity::delete_many()
+            .filter(repo_redirect::Column::ExpiresAt.lte(chrono::Utc::now().naive_utc()))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    /// The default branch ref of every path.
+    pub async fn get_default_refs(&self) -> Result<Vec<mega_refs::Model>, MegaError> {
+        Ok(mega_refs::Entity::find()
+            .filter(mega_refs::Column::RefName.eq(MEGA_BRANCH_NAME))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Every commit id with the ids of its parents, without loading the commit content.
+    pub async fn get_commit_parents(&self) -> Result<Vec<(String, Vec<String>)>, MegaError> {
+        let rows: Vec<(String, sea_orm::prelude::Json)> = mega_commit::Entity::find()
+            .select_only()
+            .column(mega_commit::Column::CommitId)
+            .column(mega_commit::Column::ParentsId)
+            .into_tuple()
+            .all(self.get_connection())
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(commit_id, parents)| {
+                let parents = parents
+                    .as_array()
+                    .map(|ids| {
+                        ids.iter()
+                            .filter_map(|id| id.as_str().map(str::to_owned))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                (commit_id, parents)
+            })
+            .collect())
+    }
+
+    /// Record the files and directories `changes` made by `commit_id` when it was added to the
+    /// ref `ref_name` of `ref_path`, replacing what was recorded for it before.
+    #[tracing::instrument(skip_all)]
+    pub async fn save_commit_paths(
+        &self,
+        ref_path: &str,
+        ref_name: &str,
+        commit_id: &str,
+        committed_at: chrono::NaiveDateTime,
+        changes: Vec<PathChange>,
+    ) -> Result<(), MegaError> {
+        let mut timer = metrics::timer("mono_storage", "save_commit_paths");
+        timer.rows(changes.len());
+        let models: Vec<mega_commit_path::ActiveModel> = changes
+            .into_iter()
+            .map(|change| {
+                mega_commit_path::Model {
+                    id: generate_id(),
+                    ref_path: ref_path.to_owned(),
+                    ref_name: ref_name.to_owned(),
+                    commit_id: commit_id.to_owned(),
+                    path: change.path,
+                    committed_at,
+                    change_type: change.change_type,
+                    old_id: change.old_id,
+                    new_id: change.new_id,
+                }
+                .into_active_model()
+            })
+            .collect();
+        self.transaction(|txn| async move {
+            let conn = &*txn;
+            mega_commit_path::Entity::delete_many()
+                .filter(mega_commit_path::Column::RefPath.eq(ref_path))
+                .filter(mega_commit_path::Column::RefName.eq(ref_name))
+                .filter(mega_commit_path::Column::CommitId.eq(commit_id))
+                .exec(conn)
+                .await?;
+            batch_save_model(conn, models).await
+        })
+        .await
+    }
+
+    /// A page of the commits of the ref `ref_name` of `ref_path` which changed the file or
+    /// directory `path`, latest first, and the number of all of them. The root of the ref lists
+    /// every recorded commit.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_path_history(
+        &self,
+        ref_path: &str,
+        ref_name: &str,
+        path: &str,
+        page: Pagination,
+    ) -> Result<(Vec<mega_commit::Model>, u64), MegaError> {
+        let mut timer = metrics::timer("mono_storage", "get_path_history");
+        let paginator = mega_commit_path::Entity::find()
+            .select_only()
+            .column(mega_commit_path::Column::CommitId)
+            .filter(mega_commit_path::Column::Path.eq(path))
+            .filter(mega_commit_path::Column::RefPath.eq(ref_path))
+            .filter(mega_commit_path::Column::RefName.eq(ref_name))
+            .order_by_desc(mega_commit_path::Column::CommittedAt)
+            .order_by_desc(mega_commit_path::Column::Id)
+            .into_tuple::<String>()
+            .paginate(self.get_connection(), page.per_page);
+        let total = paginator.num_items().await?;
+        let ids = paginator.fetch_page(page.page.saturating_sub(1)).await?;
+        let mut commits: HashMap<String, mega_commit::Model> = self
+            .get_commits_by_hashes(&ids)
+            .await?
+            .into_iter()
+            .map(|c| (c.commit_id.clone(), c))
+            .collect();
+        let commits: Vec<mega_commit::Model> =
+            ids.iter().filter_map(|id| commits.remove(id)).collect();
+        timer.rows(commits.len());
+        Ok((commits, total))
+    }
+
+    /// The latest commit of the ref `ref_name` of `ref_path` which changed each of `paths`,
+    /// by path. Paths without a recorded change are left out.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_last_commits(
+        &self,
+        ref_path: &str,
+        ref_name: &str,
+        paths: Vec<String>,
+    ) -> Result<HashMap<String, String>, MegaError> {
+        let mut timer = metrics::timer("mono_storage", "get_last_commits");
+        let conn = self.get_connection();
+        // one indexed lookup of the latest row for each path, instead of reading their history
+        let found: Vec<Option<(String, String)>> = stream::iter(paths)
+            .map(|path| async move {
+                let commit_id = mega_commit_path::Entity::find()
+                    .select_only()
+                    .column(mega_commit_path::Column::CommitId)
+                    .filter(mega_commit_path::Column::Path.eq(path.as_str()))
+                    .filter(mega_commit_path::Column::RefPath.eq(ref_path))
+                    .filter(mega_commit_path::Column::RefName.eq(ref_name))
+                    .order_by_desc(mega_commit_path::Column::CommittedAt)
+                    .order_by_desc(mega_commit_path::Column::Id)
+                    .into_tuple::<String>()
+                    .one(conn)
+                    .await?;
+                Ok::<_, MegaError>(commit_id.map(|id| (path, id)))
+            })
+            .buffered(storage::CONCURRENT_CHUNKS)
+            .collect::<Vec<_>>()
             .await
-            .unwrap())
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        let res: HashMap<String, String> = found.into_iter().flatten().collect();
+        timer.rows(res.len());
+        Ok(res)
+    }
+
+    /// The recorded changes of the file or directory `path` on the ref `ref_name` of `ref_path`,
+    /// latest first, with the object ids it had before and after each of them.
+    #[tracing::instrument(skip_all)]
+    pub async fn get_path_changes(
+        &self,
+        ref_path: &str,
+        ref_name: &str,
+        path: &str,
+    ) -> Result<Vec<mega_commit_path::Model>, MegaError> {
+        let mut timer = metrics::timer("mono_storage", "get_path_changes");
+        let changes = mega_commit_path::Entity::find()
+            .filter(mega_commit_path::Column::Path.eq(path))
+            .filter(mega_commit_path::Column::RefPath.eq(ref_path))
+            .filter(mega_commit_path::Column::RefName.eq(ref_name))
+            .order_by_desc(mega_commit_path::Column::CommittedAt)
+            .order_by_desc(mega_commit_path::Column::Id)
+            .all(self.get_connection())
+            .await?;
+        timer.rows(changes.len());
+        Ok(changes)
+    }
+
+    /// The paths the blob `blob_id` was committed at, at most `limit` of them.
+    pub async fn get_blob_paths(
+        &self,
+        blob_id: &str,
+        limit: u64,
+    ) -> Result<Vec<String>, MegaError> {
+        Ok(mega_commit_path::Entity::find()
+            .select_only()
+            .column(mega_commit_path::Column::Path)
+            .filter(mega_commit_path::Column::NewId.eq(blob_id))
+            .distinct()
+            .limit(limit)
+            .into_tuple::<String>()
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The refs of all paths, merge request refs included.
+    pub async fn get_all_refs(&self) -> Result<Vec<mega_refs::Model>, MegaError> {
+        Ok(mega_refs::Entity::find().all(self.get_connection()).await?)
+    }
+
+    pub async fn get_tags_by_hashes(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<mega_tag::Model>, MegaError> {
+        query_by_ids(hashes, |ids| {
+            mega_tag::Entity::find()
+                .filter(mega_tag::Column::TagId.is_in(ids))
+                .all(self.get_connection())
+        })
+        .await
+    }
+
+    /// Ids of the objects saved before `before`, reachable or not.
+    pub async fn get_object_ids_before(
+        &self,
+        before: chrono::NaiveDateTime,
+    ) -> Result<ObjectIds, MegaError> {
+        let conn = self.get_connection();
+        Ok(ObjectIds {
+            commits: mega_commit::Entity::find()
+                .select_only()
+                .column(mega_commit::Column::CommitId)
+                .filter(mega_commit::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+            trees: mega_tree::Entity::find()
+                .select_only()
+                .column(mega_tree::Column::TreeId)
+                .filter(mega_tree::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+            blobs: mega_blob::Entity::find()
+                .select_only()
+                .column(mega_blob::Column::BlobId)
+                .filter(mega_blob::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+            tags: mega_tag::Entity::find()
+                .select_only()
+                .column(mega_tag::Column::TagId)
+                .filter(mega_tag::Column::CreatedAt.lt(before))
+                .distinct()
+                .into_tuple()
+                .all(conn)
+                .await?,
+        })
+    }
+
+    /// Delete the records of `ids` saved before `before`, the same objects saved again since
+    /// by a running push are kept. Raw content no record refers to anymore is deleted as well.
+    #[tracing::instrument(skip_all)]
+    pub async fn delete_objects(
+        &self,
+        ids: &ObjectIds,
+        before: chrono::NaiveDateTime,
+    ) -> Result<(), MegaError> {
+        let mut timer = metrics::timer("mono_storage", "delete_objects");
+        timer.rows(ids.len());
+        // every blob record holds a reference to its content
+        let hashes: Vec<String> = query_by_ids(ids.blobs.clone(), |ids| {
+            mega_blob::Entity::find()
+                .select_only()
+                .column(mega_blob::Column::BlobId)
+                .filter(mega_blob::Column::BlobId.is_in(ids))
+                .filter(mega_blob::Column::CreatedAt.lt(before))
+                .into_tuple()
+                .all(self.get_connection())
+        })
+        .await?;
+        self.transaction(|txn| async move {
+            let conn = &*txn;
+            delete_by_ids(&ids.commits, |ids| {
+                mega_commit::Entity::delete_many()
+                    .filter(mega_commit::Column::CommitId.is_in(ids))
+                    .filter(mega_commit::Column::CreatedAt.lt(before))
+                    .exec(conn)
+            })
+            .await?;
+            delete_by_ids(&ids.commits, |ids| {
+                mega_commit_path::Entity::delete_many()
+                    .filter(mega_commit_path::Column::CommitId.is_in(ids))
+                    .exec(conn)
+            })
+            .await?;
+            delete_by_ids(&ids.trees, |ids| {
+                mega_tree::Entity::delete_many()
+                    .filter(mega_tree::Column::TreeId.is_in(ids))
+                    .filter(mega_tree::Column::CreatedAt.lt(before))
+                    .exec(conn)
+            })
+            .await?;
+            delete_by_ids(&ids.blobs, |ids| {
+                mega_blob::Entity::delete_many()
+                    .filter(mega_blob::Column::BlobId.is_in(ids))
+                    .filter(mega_blob::Column::CreatedAt.lt(before))
+                    .exec(conn)
+            })
+            .await?;
+            delete_by_ids(&ids.tags, |ids| {
+                mega_tag::Entity::delete_many()
+                    .filter(mega_tag::Column::TagId.is_in(ids))
+                    .filter(mega_tag::Column::CreatedAt.lt(before))
+                    .exec(conn)
+            })
+            .await?;
+            Ok(())
+        })
+        .await?;
+        for id in &ids.commits {
+            self.commit_cache.invalidate(id).await;
+        }
+        // if this fails the content is only kept longer than needed, never lost
+        self.raw_storage.release_refs(&hashes).await
     }
 }
 
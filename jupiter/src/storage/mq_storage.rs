@@ -1,7 +1,17 @@
 use std::sync::Arc;
 
+use chrono::NaiveDateTime;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+
+use callisto::db_enums::{MessageLane, MessageState};
 use callisto::mq_storage::*;
-use sea_orm::{DatabaseConnection, EntityTrait, QueryOrder, QuerySelect};
+use common::config::MqConfig;
+use common::errors::MegaError;
+use common::model::Pagination;
 
 use super::batch_save_model;
 
@@ -35,6 +45,25 @@ impl MQStorage {
         batch_save_model(self.get_connection(), msgs).await.unwrap();
     }
 
+    /// Store a message before it is delivered, so it survives a restart of its instance.
+    pub async fn save_message(&self, msg: Model) -> Result<(), MegaError> {
+        Entity::insert(msg.into_active_model())
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Store the messages of a batch in one statement.
+    pub async fn insert_messages(&self, msgs: Vec<Model>) -> Result<(), MegaError> {
+        if msgs.is_empty() {
+            return Ok(());
+        }
+        Entity::insert_many(msgs.into_iter().map(IntoActiveModel::into_active_model))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_latest_message(&self) -> Option<Model> {
         Entity::find()
             .order_by_desc(Column::Id)
@@ -43,4 +72,244 @@ impl MQStorage {
             .await
             .unwrap()
     }
+
+    /// Claim up to `limit` messages of `lane` which are due for processing for `consumer`, they
+    /// stay hidden from other consumers for `visibility_timeout` seconds. A message claimed by
+    /// another consumer at the same time is skipped.
+    pub async fn claim_messages(
+        &self,
+        consumer: &str,
+        lane: MessageLane,
+        limit: u64,
+        visibility_timeout: u64,
+    ) -> Result<Vec<Model>, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let due = Entity::find()
+            .filter(Column::Lane.eq(lane))
+            .filter(Column::State.is_in([
+                MessageState::Pending,
+                MessageState::Inflight,
+                MessageState::Failed,
+            ]))
+            .filter(Column::VisibleAt.is_null().or(Column::VisibleAt.lte(now)))
+            .order_by_asc(Column::Id)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?;
+        let visible_at = now + chrono::Duration::seconds(visibility_timeout as i64);
+        let mut claimed = Vec::new();
+        for mut msg in due {
+            // the attempt count only matches if nobody claimed the message since it was read
+            let res = Entity::update_many()
+                .col_expr(Column::State, Expr::value(MessageState::Inflight))
+                .col_expr(Column::Attempts, Expr::value(msg.attempts + 1))
+                .col_expr(Column::VisibleAt, Expr::value(visible_at))
+                .col_expr(Column::ClaimedBy, Expr::value(consumer))
+                .filter(Column::Id.eq(msg.id))
+                .filter(Column::Attempts.eq(msg.attempts))
+                .exec(self.get_connection())
+                .await?;
+            if res.rows_affected == 1 {
+                msg.state = MessageState::Inflight;
+                msg.attempts += 1;
+                msg.visible_at = Some(visible_at);
+                msg.claimed_by = Some(consumer.to_owned());
+                claimed.push(msg);
+            }
+        }
+        Ok(claimed)
+    }
+
+    /// Hide the message `id` from other consumers for another `visibility_timeout` seconds,
+    /// returns `false` if `consumer` lost it in the meantime.
+    pub async fn extend_claim(
+        &self,
+        id: i64,
+        consumer: &str,
+        visibility_timeout: u64,
+    ) -> Result<bool, MegaError> {
+        let visible_at =
+            chrono::Utc::now().naive_utc() + chrono::Duration::seconds(visibility_timeout as i64);
+        let res = Self::claimed_by(id, consumer)
+            .col_expr(Column::VisibleAt, Expr::value(visible_at))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Record that `consumer` processed the message `id`.
+    pub async fn complete_message(&self, id: i64, consumer: &str) -> Result<(), MegaError> {
+        Self::claimed_by(id, consumer)
+            .col_expr(Column::State, Expr::value(MessageState::Done))
+            .col_expr(
+                Column::VisibleAt,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .col_expr(Column::LastError, Expr::value(Option::<String>::None))
+            .col_expr(Column::ClaimedBy, Expr::value(Option::<String>::None))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Record that the `attempts`-th attempt of `consumer` to process the message `id` failed
+    /// with `error`, returns the state it is in now.
+    pub async fn fail_message(
+        &self,
+        id: i64,
+        consumer: &str,
+        attempts: i32,
+        error: &str,
+        config: &MqConfig,
+    ) -> Result<MessageState, MegaError> {
+        let (state, visible_at) = after_failure(attempts, config);
+        Self::claimed_by(id, consumer)
+            .col_expr(Column::State, Expr::value(state))
+            .col_expr(Column::VisibleAt, Expr::value(visible_at))
+            .col_expr(Column::LastError, Expr::value(error))
+            .col_expr(Column::ClaimedBy, Expr::value(Option::<String>::None))
+            .exec(self.get_connection())
+            .await?;
+        Ok(state)
+    }
+
+    /// A page of the messages in `state`, latest first, and the number of all of them.
+    pub async fn list_messages(
+        &self,
+        state: MessageState,
+        page: Pagination,
+    ) -> Result<(Vec<Model>, u64), MegaError> {
+        let paginator = Entity::find()
+            .filter(Column::State.eq(state))
+            .order_by_desc(Column::Id)
+            .paginate(self.get_connection(), page.per_page);
+        let total = paginator.num_items().await?;
+        Ok((
+            paginator.fetch_page(page.page.saturating_sub(1)).await?,
+            total,
+        ))
+    }
+
+    pub async fn get_message(&self, id: i64) -> Result<Option<Model>, MegaError> {
+        Ok(Entity::find_by_id(id).one(self.get_connection()).await?)
+    }
+
+    /// The number of unprocessed messages by category and state, with the creation time of the
+    /// oldest message of each.
+    pub async fn queue_depth(
+        &self,
+    ) -> Result<Vec<(Option<String>, MessageState, i64, Option<NaiveDateTime>)>, MegaError> {
+        Ok(Entity::find()
+            .select_only()
+            .column(Column::Category)
+            .column(Column::State)
+            .column_as(Column::Id.count(), "count")
+            .column_as(Column::CreateTime.min(), "oldest")
+            .filter(Column::State.ne(MessageState::Done))
+            .group_by(Column::Category)
+            .group_by(Column::State)
+            .into_tuple()
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Delete the message `id` unless a consumer is processing it, returns `false` if there is
+    /// no such message.
+    pub async fn delete_message(&self, id: i64) -> Result<bool, MegaError> {
+        let res = Entity::delete_many()
+            .filter(Column::Id.eq(id))
+            .filter(Column::State.ne(MessageState::Inflight))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Delete the messages in `state`, only those of `category` if given, returns their number.
+    pub async fn purge_messages(
+        &self,
+        state: MessageState,
+        category: Option<&str>,
+    ) -> Result<u64, MegaError> {
+        let mut query = Entity::delete_many().filter(Column::State.eq(state));
+        if let Some(category) = category {
+            query = query.filter(Column::Category.eq(category));
+        }
+        let res = query.exec(self.get_connection()).await?;
+        Ok(res.rows_affected)
+    }
+
+    /// Up to `limit` processed messages after the message `after`, created at `since` or later
+    /// if given, oldest first. Ids grow with time, so the id of the last one is the offset to
+    /// continue after.
+    pub async fn list_history(
+        &self,
+        after: i64,
+        since: Option<NaiveDateTime>,
+        limit: u64,
+    ) -> Result<Vec<Model>, MegaError> {
+        let mut query = Entity::find()
+            .filter(Column::State.eq(MessageState::Done))
+            .filter(Column::Id.gt(after));
+        if let Some(since) = since {
+            query = query.filter(Column::CreateTime.gte(since));
+        }
+        let res = query
+            .order_by_asc(Column::Id)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Process the dead-lettered message `id` again with a fresh attempt count, returns `false`
+    /// if there is no such message.
+    pub async fn requeue_message(&self, id: i64) -> Result<bool, MegaError> {
+        let res = Self::requeue()
+            .filter(Column::Id.eq(id))
+            .filter(Column::State.eq(MessageState::Dead))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Process all dead-lettered messages again, returns their number.
+    pub async fn requeue_dead_messages(&self) -> Result<u64, MegaError> {
+        let res = Self::requeue()
+            .filter(Column::State.eq(MessageState::Dead))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    fn requeue() -> sea_orm::UpdateMany<Entity> {
+        Entity::update_many()
+            .col_expr(Column::State, Expr::value(MessageState::Pending))
+            .col_expr(Column::Attempts, Expr::value(0))
+            .col_expr(
+                Column::VisibleAt,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .col_expr(Column::ClaimedBy, Expr::value(Option::<String>::None))
+    }
+
+    /// Updates the message `id` if it is still processed by `consumer`, a consumer whose claim
+    /// ran out must not overwrite the outcome of the consumer which took the message over.
+    fn claimed_by(id: i64, consumer: &str) -> sea_orm::UpdateMany<Entity> {
+        Entity::update_many()
+            .filter(Column::Id.eq(id))
+            .filter(Column::State.eq(MessageState::Inflight))
+            .filter(Column::ClaimedBy.eq(consumer))
+    }
+}
+
+/// The state of a message after its `attempts`-th attempt failed and when it is retried, the
+/// delay doubles with every attempt.
+pub fn after_failure(attempts: i32, config: &MqConfig) -> (MessageState, Option<NaiveDateTime>) {
+    if attempts >= config.max_attempts as i32 {
+        return (MessageState::Dead, None);
+    }
+    let exp = attempts.clamp(1, 16) as u32 - 1;
+    let delay = config.retry_delay.saturating_mul(1 << exp);
+    let visible_at = chrono::Utc::now().naive_utc() + chrono::Duration::seconds(delay as i64);
+    (MessageState::Failed, Some(visible_at))
 }
@@ -1,14 +1,20 @@
+use std::path::Path;
 use std::sync::Arc;
 
+use chrono::NaiveDateTime;
+
+use sea_orm::sea_query::OnConflict;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel,
-    PaginatorTrait, QueryFilter, QueryOrder, Set,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set,
 };
 
-use callisto::db_enums::{ConvType, MergeStatus};
-use callisto::{mega_conversation, mega_mr};
+use callisto::db_enums::{ConvType, MergeStatus, Mergeability};
+use callisto::{mega_conversation, mega_mr, mega_mr_mergeability};
 use common::errors::MegaError;
-use common::utils::generate_id;
+use common::utils::{generate_id, replace_path_prefix};
+
+use crate::storage::TenantScope;
 
 #[derive(Clone)]
 pub struct MrStorage {
@@ -43,14 +49,17 @@ impl MrStorage {
         Ok(model)
     }
 
+    /// A page of the merge requests with one of `status` in `scope`, the newest first.
     pub async fn get_mr_by_status(
         &self,
         status: Vec<MergeStatus>,
+        scope: &TenantScope,
         page: u64,
         per_page: u64,
     ) -> Result<(Vec<mega_mr::Model>, u64), MegaError> {
         let paginator = mega_mr::Entity::find()
             .filter(mega_mr::Column::Status.is_in(status))
+            .filter(scope.paths(mega_mr::Column::Path))
             .order_by_desc(mega_mr::Column::CreatedAt)
             .paginate(self.get_connection(), per_page);
         let num_pages = paginator.num_items().await?;
@@ -60,6 +69,79 @@ impl MrStorage {
             .map(|m| (m, num_pages))?)
     }
 
+    /// The paths with merge requests in one of `status`, to find the ones a user may read.
+    pub async fn get_mr_paths(
+        &self,
+        status: Vec<MergeStatus>,
+        scope: &TenantScope,
+    ) -> Result<Vec<String>, MegaError> {
+        Ok(mega_mr::Entity::find()
+            .select_only()
+            .column(mega_mr::Column::Path)
+            .filter(mega_mr::Column::Status.is_in(status))
+            .filter(scope.paths(mega_mr::Column::Path))
+            .distinct()
+            .into_tuple::<String>()
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Like [`Self::get_mr_by_status`] for the merge requests of `paths` only.
+    pub async fn get_mr_by_paths(
+        &self,
+        status: Vec<MergeStatus>,
+        paths: Vec<String>,
+        page: u64,
+        per_page: u64,
+    ) -> Result<(Vec<mega_mr::Model>, u64), MegaError> {
+        let paginator = mega_mr::Entity::find()
+            .filter(mega_mr::Column::Status.is_in(status))
+            .filter(mega_mr::Column::Path.is_in(paths))
+            .order_by_desc(mega_mr::Column::CreatedAt)
+            .paginate(self.get_connection(), per_page);
+        let total = paginator.num_items().await?;
+        let mrs = paginator.fetch_page(page.saturating_sub(1)).await?;
+        Ok((mrs, total))
+    }
+
+    /// Merge requests merged at or below `path`, after `since` if it is set.
+    pub async fn get_merged_mrs(
+        &self,
+        path: &str,
+        since: Option<NaiveDateTime>,
+    ) -> Result<Vec<mega_mr::Model>, MegaError> {
+        let mut query = mega_mr::Entity::find()
+            .filter(mega_mr::Column::Path.starts_with(path))
+            .filter(mega_mr::Column::Status.eq(MergeStatus::Merged));
+        if let Some(since) = since {
+            query = query.filter(mega_mr::Column::MergeDate.gt(since));
+        }
+        let mrs = query
+            .order_by_desc(mega_mr::Column::MergeDate)
+            .all(self.get_connection())
+            .await?;
+        Ok(mrs
+            .into_iter()
+            .filter(|mr| Path::new(&mr.path).starts_with(path))
+            .collect())
+    }
+
+    /// Merge requests which are open or were closed without merging, they may still be
+    /// reopened.
+    pub async fn get_unmerged_mrs(&self) -> Result<Vec<mega_mr::Model>, MegaError> {
+        Ok(mega_mr::Entity::find()
+            .filter(mega_mr::Column::Status.ne(MergeStatus::Merged))
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_open_mrs(&self) -> Result<Vec<mega_mr::Model>, MegaError> {
+        Ok(mega_mr::Entity::find()
+            .filter(mega_mr::Column::Status.eq(MergeStatus::Open))
+            .all(self.get_connection())
+            .await?)
+    }
+
     pub async fn get_mr(&self, link: &str) -> Result<Option<mega_mr::Model>, MegaError> {
         let model = mega_mr::Entity::find()
             .filter(mega_mr::Column::Link.eq(link))
@@ -130,6 +212,21 @@ impl MrStorage {
         Ok(model?)
     }
 
+    /// The merge request `link` with its conversations in the order they were added, loaded
+    /// with one query.
+    pub async fn get_mr_with_conversations(
+        &self,
+        link: &str,
+    ) -> Result<Option<(mega_mr::Model, Vec<mega_conversation::Model>)>, MegaError> {
+        let mut res = mega_mr::Entity::find()
+            .filter(mega_mr::Column::Link.eq(link))
+            .find_with_related(mega_conversation::Entity)
+            .order_by_asc(mega_conversation::Column::CreatedAt)
+            .all(self.get_connection())
+            .await?;
+        Ok(res.pop())
+    }
+
     pub async fn remove_mr_conversation(&self, id: i64) -> Result<(), MegaError> {
         mega_conversation::Entity::delete_by_id(id)
             .exec(self.get_connection())
@@ -158,4 +255,70 @@ impl MrStorage {
         let res = conversation.insert(self.get_connection()).await.unwrap();
         Ok(res.id)
     }
+
+    pub async fn rename_mr_paths(
+        &self,
+        conn: &impl ConnectionTrait,
+        old: &str,
+        new: &str,
+    ) -> Result<(), MegaError> {
+        let mrs = mega_mr::Entity::find()
+            .filter(mega_mr::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in mrs {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record whether the merge request `link` at `source_hash` merges cleanly into the
+    /// monorepo at `target_hash`, replacing the previous result.
+    pub async fn save_mergeability(
+        &self,
+        link: &str,
+        status: Mergeability,
+        conflict_paths: &[String],
+        source_hash: &str,
+        target_hash: &str,
+    ) -> Result<(), MegaError> {
+        let model = mega_mr_mergeability::Model {
+            id: generate_id(),
+            mr_link: link.to_owned(),
+            status,
+            conflict_paths: serde_json::to_string(conflict_paths).unwrap(),
+            source_hash: source_hash.to_owned(),
+            target_hash: target_hash.to_owned(),
+            checked_at: chrono::Utc::now().naive_utc(),
+        };
+        mega_mr_mergeability::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::column(mega_mr_mergeability::Column::MrLink)
+                    .update_columns([
+                        mega_mr_mergeability::Column::Status,
+                        mega_mr_mergeability::Column::ConflictPaths,
+                        mega_mr_mergeability::Column::SourceHash,
+                        mega_mr_mergeability::Column::TargetHash,
+                        mega_mr_mergeability::Column::CheckedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_mergeability(
+        &self,
+        link: &str,
+    ) -> Result<Option<mega_mr_mergeability::Model>, MegaError> {
+        Ok(mega_mr_mergeability::Entity::find()
+            .filter(mega_mr_mergeability::Column::MrLink.eq(link))
+            .one(self.get_connection())
+            .await?)
+    }
 }
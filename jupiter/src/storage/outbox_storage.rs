@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait, IntoActiveModel, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect,
+};
+
+use callisto::event_outbox::*;
+use common::errors::MegaError;
+
+#[derive(Clone)]
+pub struct OutboxStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl OutboxStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        OutboxStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        OutboxStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Write the event `id` through `conn`, usually the transaction of the change it is about,
+    /// so it is published if and only if the change is committed.
+    pub async fn add_event(
+        &self,
+        conn: &impl ConnectionTrait,
+        id: i64,
+        category: &str,
+        content: &str,
+    ) -> Result<(), MegaError> {
+        let model = Model {
+            id,
+            category: category.to_owned(),
+            content: content.to_owned(),
+            attempts: 0,
+            next_attempt_at: None,
+            created_at: chrono::Utc::now().naive_utc(),
+            sent_at: None,
+        };
+        Entity::insert(model.into_active_model()).exec(conn).await?;
+        Ok(())
+    }
+
+    /// Claim up to `limit` unsent events, oldest first, they stay hidden from the relays of
+    /// other instances for `hidden_for` seconds. An event claimed by another relay at the same
+    /// time is skipped.
+    pub async fn claim_events(&self, limit: u64, hidden_for: u64) -> Result<Vec<Model>, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let unsent = Entity::find()
+            .filter(Column::SentAt.is_null())
+            .filter(
+                Column::NextAttemptAt
+                    .is_null()
+                    .or(Column::NextAttemptAt.lte(now)),
+            )
+            .order_by_asc(Column::Id)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?;
+        let next_attempt_at = now + chrono::Duration::seconds(hidden_for as i64);
+        let mut claimed = Vec::new();
+        for mut event in unsent {
+            // the attempt count only matches if nobody claimed the event since it was read
+            let res = Entity::update_many()
+                .col_expr(Column::Attempts, Expr::value(event.attempts + 1))
+                .col_expr(Column::NextAttemptAt, Expr::value(next_attempt_at))
+                .filter(Column::Id.eq(event.id))
+                .filter(Column::Attempts.eq(event.attempts))
+                .exec(self.get_connection())
+                .await?;
+            if res.rows_affected == 1 {
+                event.attempts += 1;
+                event.next_attempt_at = Some(next_attempt_at);
+                claimed.push(event);
+            }
+        }
+        Ok(claimed)
+    }
+
+    /// Record that the event `id` was published.
+    pub async fn mark_sent(&self, id: i64) -> Result<(), MegaError> {
+        Entity::update_many()
+            .col_expr(
+                Column::NextAttemptAt,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .col_expr(Column::SentAt, Expr::value(chrono::Utc::now().naive_utc()))
+            .filter(Column::Id.eq(id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// The number of unsent events and the time the oldest of them was staged.
+    pub async fn backlog(&self) -> Result<(u64, Option<NaiveDateTime>), MegaError> {
+        let unsent = || Entity::find().filter(Column::SentAt.is_null());
+        let count = unsent().count(self.get_connection()).await?;
+        let oldest = unsent()
+            .order_by_asc(Column::Id)
+            .one(self.get_connection())
+            .await?
+            .map(|event| event.created_at);
+        Ok((count, oldest))
+    }
+
+    /// Delete the events published before `before`, returns their number.
+    pub async fn purge_sent(&self, before: NaiveDateTime) -> Result<u64, MegaError> {
+        let res = Entity::delete_many()
+            .filter(Column::SentAt.lt(before))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected)
+    }
+}
@@ -0,0 +1,446 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, IntoActiveModel,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, TransactionTrait,
+};
+
+use callisto::db_enums::{RepoRole, RoleSubject};
+use callisto::{
+    auth_decision, break_glass_grant, entity_file_signature, policy_version, role_assignment,
+};
+use common::errors::MegaError;
+use common::model::Pagination;
+use common::utils::generate_id;
+use saturn::break_glass::{self, BreakGlassGrant, BREAK_GLASS_NAMESPACE};
+use saturn::role::{self, Role, RoleAssignment, Subject, ROLE_NAMESPACE};
+
+#[derive(Clone)]
+pub struct PolicyStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl PolicyStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        PolicyStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        PolicyStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Record the next version of the policies of `namespace`, `None` deletes them.
+    pub async fn save_version(
+        &self,
+        namespace: &str,
+        content: Option<String>,
+        operator: &str,
+    ) -> Result<policy_version::Model, MegaError> {
+        let txn = self.get_connection().begin().await?;
+        let latest = policy_version::Entity::find()
+            .filter(policy_version::Column::Namespace.eq(namespace))
+            .order_by_desc(policy_version::Column::Version)
+            .one(&txn)
+            .await?;
+        let model = policy_version::Model {
+            id: generate_id(),
+            namespace: namespace.to_owned(),
+            version: latest.map_or(1, |m| m.version + 1),
+            content,
+            operator: operator.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        // concurrent changes of a namespace collide on its unique version
+        let res = model.into_active_model().insert(&txn).await?;
+        txn.commit().await?;
+        Ok(res)
+    }
+
+    pub async fn get_version(
+        &self,
+        namespace: &str,
+        version: i32,
+    ) -> Result<Option<policy_version::Model>, MegaError> {
+        Ok(policy_version::Entity::find()
+            .filter(policy_version::Column::Namespace.eq(namespace))
+            .filter(policy_version::Column::Version.eq(version))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_latest_version(
+        &self,
+        namespace: &str,
+    ) -> Result<Option<policy_version::Model>, MegaError> {
+        Ok(policy_version::Entity::find()
+            .filter(policy_version::Column::Namespace.eq(namespace))
+            .order_by_desc(policy_version::Column::Version)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Versions of the policies of `namespace`, latest first.
+    pub async fn list_versions(
+        &self,
+        namespace: &str,
+    ) -> Result<Vec<policy_version::Model>, MegaError> {
+        Ok(policy_version::Entity::find()
+            .filter(policy_version::Column::Namespace.eq(namespace))
+            .order_by_desc(policy_version::Column::Version)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The latest version of every namespace which was not deleted, ordered by namespace.
+    pub async fn list_current(&self) -> Result<Vec<policy_version::Model>, MegaError> {
+        let latest: Vec<(String, i32)> = policy_version::Entity::find()
+            .select_only()
+            .column(policy_version::Column::Namespace)
+            .column_as(policy_version::Column::Version.max(), "version")
+            .group_by(policy_version::Column::Namespace)
+            .order_by_asc(policy_version::Column::Namespace)
+            .into_tuple()
+            .all(self.get_connection())
+            .await?;
+        let mut res = Vec::with_capacity(latest.len());
+        for (namespace, version) in latest {
+            if let Some(model) = self.get_version(&namespace, version).await? {
+                if model.content.is_some() {
+                    res.push(model);
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// The policies of every namespace which was not deleted, by namespace. The policies
+    /// generated from the role assignments are the namespace [`ROLE_NAMESPACE`], those of
+    /// the active break-glass grants [`BREAK_GLASS_NAMESPACE`].
+    pub async fn current_policies(&self) -> Result<BTreeMap<String, String>, MegaError> {
+        let mut policies: BTreeMap<String, String> = self
+            .list_current()
+            .await?
+            .into_iter()
+            .filter_map(|m| Some((m.namespace, m.content?)))
+            .collect();
+        let assignments = self.role_assignments().await?;
+        if !assignments.is_empty() {
+            policies.insert(ROLE_NAMESPACE.to_owned(), role::policies(&assignments));
+        }
+        let grants = self.break_glass_grants().await?;
+        if !grants.is_empty() {
+            policies.insert(
+                BREAK_GLASS_NAMESPACE.to_owned(),
+                break_glass::policies(&grants, Utc::now()),
+            );
+        }
+        Ok(policies)
+    }
+
+    /// Assign `role` on `path` to a user, team or organization, replacing the role it had.
+    /// The assignment is temporary if it `expires_at` some time.
+    pub async fn save_role_assignment(
+        &self,
+        path: &str,
+        subject_type: RoleSubject,
+        subject: &str,
+        role: RepoRole,
+        expires_at: Option<NaiveDateTime>,
+        created_by: &str,
+    ) -> Result<role_assignment::Model, MegaError> {
+        let model = role_assignment::Model {
+            id: generate_id(),
+            path: path.to_owned(),
+            subject_type,
+            subject: subject.to_owned(),
+            role,
+            expires_at,
+            created_by: created_by.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        role_assignment::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::columns([
+                    role_assignment::Column::Path,
+                    role_assignment::Column::SubjectType,
+                    role_assignment::Column::Subject,
+                ])
+                .update_columns([
+                    role_assignment::Column::Role,
+                    role_assignment::Column::ExpiresAt,
+                    role_assignment::Column::CreatedBy,
+                    role_assignment::Column::CreatedAt,
+                ])
+                .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        let res = role_assignment::Entity::find()
+            .filter(role_assignment::Column::Path.eq(path))
+            .filter(role_assignment::Column::SubjectType.eq(subject_type))
+            .filter(role_assignment::Column::Subject.eq(subject))
+            .one(self.get_connection())
+            .await?;
+        res.ok_or_else(|| MegaError::with_message("role assignment not saved"))
+    }
+
+    pub async fn get_role_assignment(
+        &self,
+        id: i64,
+    ) -> Result<Option<role_assignment::Model>, MegaError> {
+        Ok(role_assignment::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn delete_role_assignment(&self, id: i64) -> Result<bool, MegaError> {
+        let res = role_assignment::Entity::delete_by_id(id)
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Remove the temporary role assignments which expired, returns how many.
+    pub async fn delete_expired_role_assignments(&self) -> Result<u64, MegaError> {
+        let res = role_assignment::Entity::delete_many()
+            .filter(role_assignment::Column::ExpiresAt.lte(Utc::now().naive_utc()))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    /// Role assignments on `path` and the paths below it, ordered by path.
+    pub async fn list_role_assignments(
+        &self,
+        path: &str,
+    ) -> Result<Vec<role_assignment::Model>, MegaError> {
+        let mut query = role_assignment::Entity::find();
+        if path != "/" {
+            let path = path.trim_end_matches('/');
+            query = query.filter(
+                Condition::any()
+                    .add(role_assignment::Column::Path.eq(path))
+                    .add(role_assignment::Column::Path.starts_with(format!("{path}/"))),
+            );
+        }
+        Ok(query
+            .order_by_asc(role_assignment::Column::Path)
+            .order_by_asc(role_assignment::Column::Subject)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Every role assignment, as saturn turns them into policies.
+    pub async fn role_assignments(&self) -> Result<Vec<RoleAssignment>, MegaError> {
+        Ok(self
+            .list_role_assignments("/")
+            .await?
+            .into_iter()
+            .map(|m| RoleAssignment {
+                subject: match m.subject_type {
+                    RoleSubject::User => Subject::User(m.subject),
+                    RoleSubject::Team => Subject::Team(m.subject),
+                    RoleSubject::Organization => Subject::Organization(m.subject),
+                    RoleSubject::Bot => Subject::Bot(m.subject),
+                },
+                role: match m.role {
+                    RepoRole::Guest => Role::Guest,
+                    RepoRole::Reporter => Role::Reporter,
+                    RepoRole::Developer => Role::Developer,
+                    RepoRole::Maintainer => Role::Maintainer,
+                    RepoRole::Owner => Role::Owner,
+                },
+                path: m.path,
+                expires_at: m.expires_at.map(|t| t.and_utc()),
+            })
+            .collect())
+    }
+
+    pub async fn save_break_glass_grant(
+        &self,
+        user: &str,
+        path: &str,
+        reason: &str,
+        expires_at: NaiveDateTime,
+    ) -> Result<break_glass_grant::Model, MegaError> {
+        let model = break_glass_grant::Model {
+            id: generate_id(),
+            user: user.to_owned(),
+            path: path.to_owned(),
+            reason: reason.to_owned(),
+            expires_at,
+            revoked_at: None,
+            revoked_by: None,
+            created_at: Utc::now().naive_utc(),
+        };
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_break_glass_grant(
+        &self,
+        id: i64,
+    ) -> Result<Option<break_glass_grant::Model>, MegaError> {
+        Ok(break_glass_grant::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Revoke a grant which is still active, false if it already expired or was revoked.
+    pub async fn revoke_break_glass_grant(
+        &self,
+        id: i64,
+        revoked_by: &str,
+    ) -> Result<bool, MegaError> {
+        let now = Utc::now().naive_utc();
+        let res = break_glass_grant::Entity::update_many()
+            .col_expr(break_glass_grant::Column::RevokedAt, Expr::value(now))
+            .col_expr(
+                break_glass_grant::Column::RevokedBy,
+                Expr::value(revoked_by),
+            )
+            .filter(break_glass_grant::Column::Id.eq(id))
+            .filter(break_glass_grant::Column::RevokedAt.is_null())
+            .filter(break_glass_grant::Column::ExpiresAt.gt(now))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Break-glass grants latest first, only those neither expired nor revoked if `active`.
+    pub async fn list_break_glass_grants(
+        &self,
+        active: bool,
+    ) -> Result<Vec<break_glass_grant::Model>, MegaError> {
+        let mut query = break_glass_grant::Entity::find();
+        if active {
+            query = query
+                .filter(break_glass_grant::Column::RevokedAt.is_null())
+                .filter(break_glass_grant::Column::ExpiresAt.gt(Utc::now().naive_utc()));
+        }
+        Ok(query
+            .order_by_desc(break_glass_grant::Column::CreatedAt)
+            .order_by_desc(break_glass_grant::Column::Id)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The active break-glass grants, as saturn turns them into policies.
+    pub async fn break_glass_grants(&self) -> Result<Vec<BreakGlassGrant>, MegaError> {
+        Ok(self
+            .list_break_glass_grants(true)
+            .await?
+            .into_iter()
+            .map(|m| BreakGlassGrant {
+                user: m.user,
+                path: m.path,
+                expires_at: m.expires_at.and_utc(),
+            })
+            .collect())
+    }
+
+    pub async fn save_entity_signature(
+        &self,
+        path: &str,
+        blob_id: &str,
+        signature: &str,
+        signed_by: &str,
+    ) -> Result<entity_file_signature::Model, MegaError> {
+        let model = entity_file_signature::Model {
+            id: generate_id(),
+            path: path.to_owned(),
+            blob_id: blob_id.to_owned(),
+            signature: signature.to_owned(),
+            signed_by: signed_by.to_owned(),
+            created_at: Utc::now().naive_utc(),
+        };
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    /// Signatures of the entity file of the directory `path`, latest first.
+    pub async fn list_entity_signatures(
+        &self,
+        path: &str,
+    ) -> Result<Vec<entity_file_signature::Model>, MegaError> {
+        Ok(entity_file_signature::Entity::find()
+            .filter(entity_file_signature::Column::Path.eq(path))
+            .order_by_desc(entity_file_signature::Column::CreatedAt)
+            .order_by_desc(entity_file_signature::Column::Id)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn save_auth_decision(&self, model: auth_decision::Model) -> Result<(), MegaError> {
+        model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Recorded authorization decisions matching `filter`, latest first, with their total
+    /// number.
+    pub async fn list_auth_decisions(
+        &self,
+        filter: &AuthDecisionFilter,
+        page: Pagination,
+    ) -> Result<(Vec<auth_decision::Model>, u64), MegaError> {
+        let mut query = auth_decision::Entity::find();
+        if let Some(principal) = &filter.principal {
+            query = query.filter(auth_decision::Column::Principal.eq(principal));
+        }
+        if let Some(action) = &filter.action {
+            query = query.filter(auth_decision::Column::Action.eq(action));
+        }
+        if let Some(resource) = &filter.resource {
+            query = query.filter(auth_decision::Column::Resource.eq(resource));
+        }
+        if let Some(allowed) = filter.allowed {
+            query = query.filter(auth_decision::Column::Allowed.eq(allowed));
+        }
+        if let Some(break_glass) = filter.break_glass {
+            query = query.filter(auth_decision::Column::BreakGlass.eq(break_glass));
+        }
+        if let Some(since) = filter.since {
+            query = query.filter(auth_decision::Column::CreatedAt.gte(since));
+        }
+        if let Some(until) = filter.until {
+            query = query.filter(auth_decision::Column::CreatedAt.lt(until));
+        }
+        let paginator = query
+            .order_by_desc(auth_decision::Column::CreatedAt)
+            .order_by_desc(auth_decision::Column::Id)
+            .paginate(self.get_connection(), page.per_page);
+        let total = paginator.num_items().await?;
+        Ok((
+            paginator.fetch_page(page.page.saturating_sub(1)).await?,
+            total,
+        ))
+    }
+}
+
+/// Which recorded authorization decisions to list, every one matching all given fields.
+#[derive(Debug, Clone, Default)]
+pub struct AuthDecisionFilter {
+    pub principal: Option<String>,
+    pub action: Option<String>,
+    pub resource: Option<String>,
+    pub allowed: Option<bool>,
+    pub break_glass: Option<bool>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
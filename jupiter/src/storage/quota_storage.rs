@@ -0,0 +1,121 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    IntoActiveModel, QueryFilter, QueryOrder, Set,
+};
+
+use callisto::repo_usage;
+use common::errors::MegaError;
+use common::utils::{generate_id, replace_path_prefix};
+
+#[derive(Clone)]
+pub struct QuotaStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl QuotaStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        QuotaStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        QuotaStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Add `git_size` and `lfs_size` bytes to the usage of the repository at `path`.
+    pub async fn add_usage(
+        &self,
+        path: &str,
+        git_size: i64,
+        lfs_size: i64,
+    ) -> Result<(), MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let res = repo_usage::Entity::update_many()
+            .col_expr(
+                repo_usage::Column::GitSize,
+                Expr::col(repo_usage::Column::GitSize).add(git_size),
+            )
+            .col_expr(
+                repo_usage::Column::LfsSize,
+                Expr::col(repo_usage::Column::LfsSize).add(lfs_size),
+            )
+            .col_expr(repo_usage::Column::UpdatedAt, Expr::value(now))
+            .filter(repo_usage::Column::Path.eq(path))
+            .exec(self.get_connection())
+            .await?;
+        if res.rows_affected == 0 {
+            let model = repo_usage::Model {
+                id: generate_id(),
+                path: path.to_owned(),
+                git_size,
+                lfs_size,
+                updated_at: now,
+            };
+            model
+                .into_active_model()
+                .insert(self.get_connection())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Usage of the repositories at or below `path`, ordered by path.
+    pub async fn list_usage(&self, path: &str) -> Result<Vec<repo_usage::Model>, MegaError> {
+        let res = repo_usage::Entity::find()
+            .filter(repo_usage::Column::Path.starts_with(path))
+            .order_by_asc(repo_usage::Column::Path)
+            .all(self.get_connection())
+            .await?;
+        // `/a` is a prefix of `/ab` as well
+        Ok(res
+            .into_iter()
+            .filter(|u| Path::new(&u.path).starts_with(path))
+            .collect())
+    }
+
+    /// Total bytes of git and LFS objects of the repositories at or below `path`.
+    pub async fn get_usage_below(&self, path: &str) -> Result<(i64, i64), MegaError> {
+        let usage = self.list_usage(path).await?;
+        Ok(usage
+            .iter()
+            .fold((0, 0), |(git, lfs), u| (git + u.git_size, lfs + u.lfs_size)))
+    }
+
+    /// Move the usage of `old` and its children to `new` after a rename.
+    pub async fn rename_paths(
+        &self,
+        conn: &impl ConnectionTrait,
+        old: &str,
+        new: &str,
+    ) -> Result<(), MegaError> {
+        let usage = repo_usage::Entity::find()
+            .filter(repo_usage::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in usage {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn delete_usage(&self, path: &str) -> Result<(), MegaError> {
+        repo_usage::Entity::delete_many()
+            .filter(repo_usage::Column::Path.eq(path))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+}
@@ -1,14 +1,66 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
 use std::sync::Arc;
 
-use futures::Stream;
-use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use bytes::Bytes;
+use fastcdc::v2020::FastCDC;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbErr,
+    EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+};
 
-use callisto::raw_blob;
+use callisto::db_enums::StorageType;
+use callisto::{raw_blob, raw_blob_chunk};
+use common::config::StorageConfig;
 use common::errors::MegaError;
+use common::utils::generate_id;
+use mercury::hash::SHA1;
+use mercury::internal::object::types::ObjectType;
+
+use crate::encryption::Keyring;
+use crate::raw_storage::local_storage::LocalBackend;
+use crate::raw_storage::{self, ByteStream, ObjectBackend};
+use crate::storage::{self, batch_save_model, metrics, query_by_ids};
+
+/// Objects transferred from and to the backend at the same time.
+const CONCURRENT_TRANSFERS: usize = 8;
+
+/// Sizes of the chunks large blobs are split into.
+const CHUNK_MIN_SIZE: u32 = 256 * 1024;
+const CHUNK_AVG_SIZE: u32 = 1024 * 1024;
+const CHUNK_MAX_SIZE: u32 = 4 * 1024 * 1024;
 
 #[derive(Clone)]
 pub struct RawDbStorage {
     pub connection: Arc<DatabaseConnection>,
+    /// Where the content of large blobs is put, `None` keeps it in the database
+    backend: Option<Arc<dyn ObjectBackend>>,
+    /// Reads content recorded as local files, wherever they are
+    local: Arc<dyn ObjectBackend>,
+    /// Master keys content in `backend` and `local` is encrypted with
+    keyring: Option<Arc<Keyring>>,
+    /// Size in bytes from which content is put into `backend`
+    threshold: usize,
+    /// Size in bytes from which content is split into chunks
+    chunk_threshold: usize,
+}
+
+/// Result of [`RawDbStorage::migrate_blobs`].
+#[derive(Debug, Default)]
+pub struct BlobMigration {
+    pub checked: u64,
+    pub moved: u64,
+    pub failed: Vec<(String, String)>,
+}
+
+/// Result of [`RawDbStorage::rotate_keys`].
+#[derive(Debug, Default)]
+pub struct KeyRotation {
+    pub checked: u64,
+    pub rotated: u64,
+    pub failed: Vec<(String, String)>,
 }
 
 impl RawDbStorage {
@@ -16,46 +68,567 @@ impl RawDbStorage {
         &self.connection
     }
 
-    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
-        RawDbStorage { connection }
+    pub async fn new(connection: Arc<DatabaseConnection>, config: &StorageConfig) -> Self {
+        let keyring = Keyring::from_config(&config.encryption)
+            .expect("Failed to set up the encryption of stored objects");
+        let backend = raw_storage::init(config.raw_obj_storage_type, config, keyring.clone())
+            .expect("Failed to set up raw object storage");
+        let local = LocalBackend::init(config.raw_obj_local_path.clone())
+            .expect("Failed to set up raw object storage");
+        RawDbStorage {
+            connection,
+            backend,
+            local: raw_storage::encrypted(Arc::new(local), keyring.clone()),
+            keyring,
+            threshold: config.big_obj_threshold * 1024,
+            chunk_threshold: match config.big_obj_chunk_threshold {
+                0 => usize::MAX,
+                size => size * 1024,
+            },
+        }
     }
 
     pub fn mock() -> Self {
         RawDbStorage {
             connection: Arc::new(DatabaseConnection::default()),
+            backend: None,
+            local: Arc::new(LocalBackend::init(std::env::temp_dir()).unwrap()),
+            keyring: None,
+            threshold: usize::MAX,
+            chunk_threshold: usize::MAX,
+        }
+    }
+
+    /// The master keys stored content is encrypted with, `None` if none are configured.
+    pub fn keyring(&self) -> Option<Arc<Keyring>> {
+        self.keyring.clone()
+    }
+
+    /// Run `f` in a transaction, see [`storage::transaction`].
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T, MegaError>
+    where
+        F: FnOnce(Arc<DatabaseTransaction>) -> Fut,
+        Fut: Future<Output = Result<T, MegaError>>,
+    {
+        storage::transaction(self.get_connection(), f).await
+    }
+
+    /// Save new blobs, the content of large ones is put into the configured backend first and
+    /// the content of very large ones is split into chunks. Blobs which are stored already are
+    /// skipped.
+    ///
+    /// Content put into a backend stays there when the records are rolled back, it is written
+    /// again by the next save.
+    #[tracing::instrument(skip_all)]
+    pub async fn save_raw_blobs(
+        &self,
+        conn: &impl ConnectionTrait,
+        blobs: Vec<raw_blob::Model>,
+    ) -> Result<(), MegaError> {
+        let mut timer = metrics::timer("raw_db_storage", "save_raw_blobs");
+        timer.rows(blobs.len());
+        for batch in blobs.chunks(1000) {
+            let stored = self
+                .stored_hashes(conn, batch.iter().map(|b| b.sha1.clone()))
+                .await?;
+            let (chunked, plain): (Vec<_>, Vec<_>) = batch
+                .iter()
+                .filter(|b| !stored.contains(&b.sha1))
+                .cloned()
+                .partition(|b| {
+                    b.data
+                        .as_ref()
+                        .is_some_and(|data| data.len() >= self.chunk_threshold)
+                });
+            self.insert(conn, plain).await?;
+            for blob in chunked {
+                self.save_chunked(conn, blob).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn stored_hashes(
+        &self,
+        conn: &impl ConnectionTrait,
+        hashes: impl IntoIterator<Item = String>,
+    ) -> Result<HashSet<String>, MegaError> {
+        let stored: Vec<String> = raw_blob::Entity::find()
+            .select_only()
+            .column(raw_blob::Column::Sha1)
+            .filter(raw_blob::Column::Sha1.is_in(hashes))
+            .into_tuple()
+            .all(conn)
+            .await?;
+        Ok(stored.into_iter().collect())
+    }
+
+    async fn insert(
+        &self,
+        conn: &impl ConnectionTrait,
+        blobs: Vec<raw_blob::Model>,
+    ) -> Result<(), MegaError> {
+        let models: Vec<raw_blob::ActiveModel> = stream::iter(blobs)
+            .map(|blob| self.place(blob))
+            .buffer_unordered(CONCURRENT_TRANSFERS)
+            .map_ok(|blob| blob.into_active_model())
+            .try_collect()
+            .await?;
+        batch_save_model(conn, models).await
+    }
+
+    /// Save `blob` as content defined chunks, which are raw blobs of their own. Chunks which
+    /// other versions of the blob have in common with it are stored once.
+    async fn save_chunked(
+        &self,
+        conn: &impl ConnectionTrait,
+        mut blob: raw_blob::Model,
+    ) -> Result<(), MegaError> {
+        let data = blob.data.take().unwrap_or_default();
+        let now = chrono::Utc::now().naive_utc();
+        let mut chunks = Vec::new();
+        let mut entries = Vec::new();
+        let cuts = FastCDC::new(&data, CHUNK_MIN_SIZE, CHUNK_AVG_SIZE, CHUNK_MAX_SIZE);
+        for (index, cut) in cuts.enumerate() {
+            let content = &data[cut.offset..cut.offset + cut.length];
+            let chunk_id = SHA1::from_type_and_data(ObjectType::Blob, content).to_string();
+            let entry = raw_blob_chunk::Model {
+                id: generate_id(),
+                blob_id: blob.sha1.clone(),
+                chunk_index: index as i32,
+                chunk_id: chunk_id.clone(),
+                size: cut.length as i64,
+                created_at: now,
+            };
+            entries.push(entry.into_active_model());
+            chunks.push(raw_blob::Model {
+                id: generate_id(),
+                sha1: chunk_id,
+                content: None,
+                file_type: None,
+                storage_type: StorageType::Database,
+                data: Some(content.to_vec()),
+                local_path: None,
+                remote_url: None,
+                created_at: now,
+                ref_count: 0,
+            });
+        }
+        let chunk_ids: Vec<String> = chunks.iter().map(|c| c.sha1.clone()).collect();
+        let mut stored = self.stored_hashes(conn, chunk_ids.clone()).await?;
+        chunks.retain(|c| stored.insert(c.sha1.clone()));
+        self.insert(conn, chunks).await?;
+        self.add_refs(conn, &chunk_ids).await?;
+        batch_save_model(conn, entries).await?;
+        // the blob is only found once all of its chunks are there
+        blob.storage_type = StorageType::Chunked;
+        batch_save_model(conn, vec![blob.into_active_model()]).await
+    }
+
+    /// Count the blob records referring to the content of `hashes`, a hash appearing several
+    /// times is counted as often.
+    pub async fn add_refs(
+        &self,
+        conn: &impl ConnectionTrait,
+        hashes: &[String],
+    ) -> Result<(), MegaError> {
+        self.change_refs(conn, hashes, 1).await
+    }
+
+    /// Remove references counted by [`Self::add_refs`] after the blob records were deleted,
+    /// content no record refers to anymore is deleted as well.
+    pub async fn release_refs(&self, hashes: &[String]) -> Result<(), MegaError> {
+        let mut pending = hashes.to_vec();
+        // the chunks of deleted blobs lose a reference in turn
+        while !pending.is_empty() {
+            self.change_refs(self.get_connection(), &pending, -1)
+                .await?;
+            let mut chunk_ids = Vec::new();
+            for batch in pending.chunks(1000) {
+                let unused = raw_blob::Entity::find()
+                    .filter(raw_blob::Column::Sha1.is_in(batch.iter().cloned()))
+                    .filter(raw_blob::Column::RefCount.lte(0))
+                    .all(self.get_connection())
+                    .await?;
+                for blob in unused {
+                    raw_blob::Entity::delete_by_id(blob.id)
+                        .exec(self.get_connection())
+                        .await?;
+                    match blob.storage_type {
+                        StorageType::Database => (),
+                        StorageType::Chunked => {
+                            chunk_ids.extend(self.delete_chunk_entries(&blob.sha1).await?)
+                        }
+                        storage_type => {
+                            let location = location(&blob)?;
+                            self.source(storage_type, location)?
+                                .delete(location)
+                                .await?;
+                        }
+                    }
+                }
+            }
+            pending = chunk_ids;
+        }
+        Ok(())
+    }
+
+    async fn change_refs(
+        &self,
+        conn: &impl ConnectionTrait,
+        hashes: &[String],
+        sign: i64,
+    ) -> Result<(), MegaError> {
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for hash in hashes {
+            *counts.entry(hash.as_str()).or_default() += 1;
+        }
+        // one update for all hashes changed by the same amount
+        let mut by_count: BTreeMap<i64, Vec<&str>> = BTreeMap::new();
+        for (hash, count) in counts {
+            by_count.entry(count).or_default().push(hash);
+        }
+        for (count, hashes) in by_count {
+            for chunk in hashes.chunks(1000) {
+                raw_blob::Entity::update_many()
+                    .col_expr(
+                        raw_blob::Column::RefCount,
+                        Expr::col(raw_blob::Column::RefCount).add(sign * count),
+                    )
+                    .filter(raw_blob::Column::Sha1.is_in(chunk.iter().copied()))
+                    .exec(conn)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the content of `blob` where it belongs by its size.
+    async fn place(&self, mut blob: raw_blob::Model) -> Result<raw_blob::Model, MegaError> {
+        let Some(backend) = &self.backend else {
+            return Ok(blob);
+        };
+        let data = match blob.data.take() {
+            Some(data) if data.len() >= self.threshold => data,
+            data => {
+                blob.data = data;
+                return Ok(blob);
+            }
+        };
+        let location = backend.put(&blob.sha1, Bytes::from(data)).await?;
+        set_location(&mut blob, backend.storage_type(), location);
+        Ok(blob)
+    }
+
+    /// Fill in the `data` of a blob whose content is kept outside the database.
+    async fn load(&self, mut blob: raw_blob::Model) -> Result<raw_blob::Model, MegaError> {
+        if blob.storage_type != StorageType::Database {
+            let content = self
+                .content_stream(&blob)
+                .await?
+                .try_fold(Vec::new(), |mut buf, chunk| async move {
+                    buf.extend_from_slice(&chunk);
+                    Ok(buf)
+                })
+                .await?;
+            blob.data = Some(content);
+        }
+        Ok(blob)
+    }
+
+    /// The content of `blob`, chunked content is streamed one chunk after another.
+    async fn content_stream(&self, blob: &raw_blob::Model) -> Result<ByteStream, MegaError> {
+        if blob.storage_type != StorageType::Chunked {
+            return self.plain_stream(blob).await;
         }
+        let chunk_ids = self.chunk_ids(&blob.sha1).await?;
+        let storage = self.clone();
+        let content = stream::iter(chunk_ids)
+            .map(move |id| {
+                let storage = storage.clone();
+                async move {
+                    let chunk = raw_blob::Entity::find()
+                        .filter(raw_blob::Column::Sha1.eq(id.as_str()))
+                        .one(storage.get_connection())
+                        .await?
+                        .ok_or_else(|| {
+                            MegaError::with_message(&format!("chunk {} is not stored", id))
+                        })?;
+                    storage.plain_stream(&chunk).await
+                }
+            })
+            // the next chunks are requested while one is read
+            .buffered(CONCURRENT_TRANSFERS)
+            .try_flatten();
+        Ok(content.boxed())
     }
 
+    /// The content of a blob which is not split into chunks.
+    async fn plain_stream(&self, blob: &raw_blob::Model) -> Result<ByteStream, MegaError> {
+        match blob.storage_type {
+            StorageType::Database => {
+                let data = Bytes::from(blob.data.clone().unwrap_or_default());
+                Ok(stream::once(async { Ok(data) }).boxed())
+            }
+            StorageType::Chunked => Err(MegaError::with_message(&format!(
+                "blob {} is split into chunks",
+                blob.sha1
+            ))),
+            storage_type => {
+                let location = location(blob)?;
+                self.source(storage_type, location)?
+                    .get_stream(location)
+                    .await
+            }
+        }
+    }
+
+    /// The chunks of blob `hash`, in order.
+    async fn chunk_ids(&self, hash: &str) -> Result<Vec<String>, MegaError> {
+        let entries = raw_blob_chunk::Entity::find()
+            .filter(raw_blob_chunk::Column::BlobId.eq(hash))
+            .order_by_asc(raw_blob_chunk::Column::ChunkIndex)
+            .all(self.get_connection())
+            .await?;
+        let mut chunk_ids = Vec::new();
+        let mut last_index = None;
+        for entry in entries {
+            // a blob saved by two pushes at the same time lists its chunks twice
+            if last_index != Some(entry.chunk_index) {
+                last_index = Some(entry.chunk_index);
+                chunk_ids.push(entry.chunk_id);
+            }
+        }
+        Ok(chunk_ids)
+    }
+
+    /// Delete the chunk list of blob `hash`, returns the chunks it referred to.
+    async fn delete_chunk_entries(&self, hash: &str) -> Result<Vec<String>, MegaError> {
+        let chunk_ids: Vec<String> = raw_blob_chunk::Entity::find()
+            .select_only()
+            .column(raw_blob_chunk::Column::ChunkId)
+            .filter(raw_blob_chunk::Column::BlobId.eq(hash))
+            .into_tuple()
+            .all(self.get_connection())
+            .await?;
+        raw_blob_chunk::Entity::delete_many()
+            .filter(raw_blob_chunk::Column::BlobId.eq(hash))
+            .exec(self.get_connection())
+            .await?;
+        Ok(chunk_ids)
+    }
+
+    /// The backend holding the content at `location`.
+    fn source(
+        &self,
+        storage_type: StorageType,
+        location: &str,
+    ) -> Result<&dyn ObjectBackend, MegaError> {
+        match storage_type {
+            StorageType::LocalFs => Ok(self.local.as_ref()),
+            _ => self
+                .backend
+                .as_deref()
+                .filter(|b| b.storage_type() == storage_type && b.contains(location))
+                .ok_or_else(|| {
+                    MegaError::with_message(&format!("no storage configured for {}", location))
+                }),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
     pub async fn get_raw_blobs_by_hashes(
         &self,
         hashes: Vec<String>,
     ) -> Result<Vec<raw_blob::Model>, MegaError> {
-        Ok(raw_blob::Entity::find()
-            .filter(raw_blob::Column::Sha1.is_in(hashes))
-            .all(self.get_connection())
+        let mut timer = metrics::timer("raw_db_storage", "get_raw_blobs_by_hashes");
+        let blobs = query_by_ids(hashes, |ids| {
+            raw_blob::Entity::find()
+                .filter(raw_blob::Column::Sha1.is_in(ids))
+                .all(self.get_connection())
+        })
+        .await?;
+        timer.rows(blobs.len());
+        stream::iter(blobs)
+            .map(|blob| self.load(blob))
+            .buffered(CONCURRENT_TRANSFERS)
+            .try_collect()
             .await
-            .unwrap())
     }
 
     pub async fn get_raw_blob_by_hash(
         &self,
         hash: &str,
     ) -> Result<Option<raw_blob::Model>, MegaError> {
-        Ok(raw_blob::Entity::find()
+        let blob = raw_blob::Entity::find()
             .filter(raw_blob::Column::Sha1.eq(hash))
             .one(self.get_connection())
-            .await
-            .unwrap())
+            .await?;
+        match blob {
+            Some(blob) => Ok(Some(self.load(blob).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The content of blob `hash`, streamed from where it is kept.
+    pub async fn get_raw_blob_content(&self, hash: &str) -> Result<Option<ByteStream>, MegaError> {
+        let blob = raw_blob::Entity::find()
+            .filter(raw_blob::Column::Sha1.eq(hash))
+            .one(self.get_connection())
+            .await?;
+        match blob {
+            Some(blob) => Ok(Some(self.content_stream(&blob).await?)),
+            None => Ok(None),
+        }
     }
 
     pub async fn get_raw_blobs_stream(
         &self,
         hashes: Vec<String>,
     ) -> Result<impl Stream<Item = Result<raw_blob::Model, DbErr>> + '_ + Send, MegaError> {
-        Ok(raw_blob::Entity::find()
+        let blobs = raw_blob::Entity::find()
             .filter(raw_blob::Column::Sha1.is_in(hashes))
             .stream(self.get_connection())
-            .await
-            .unwrap())
+            .await?;
+        Ok(blobs.and_then(move |blob| async move {
+            self.load(blob)
+                .await
+                .map_err(|err| DbErr::Custom(err.to_string()))
+        }))
+    }
+
+    /// Move the content of every blob to where the current configuration puts it, the old
+    /// copy is deleted after the blob was updated.
+    pub async fn migrate_blobs(&self, dry_run: bool) -> Result<BlobMigration, MegaError> {
+        let mut res = BlobMigration::default();
+        let mut pages = raw_blob::Entity::find()
+            .order_by_asc(raw_blob::Column::Id)
+            .paginate(self.get_connection(), 100);
+        // updated blobs stay in the result, so pages are fetched by number
+        while let Some(blobs) = pages.fetch_and_next().await? {
+            for blob in blobs {
+                res.checked += 1;
+                let sha1 = blob.sha1.clone();
+                match self.migrate_blob(blob, dry_run).await {
+                    Ok(true) => res.moved += 1,
+                    Ok(false) => (),
+                    Err(err) => {
+                        tracing::warn!("failed to move blob {}: {}", sha1, err);
+                        res.failed.push((sha1, err.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    /// Encrypt the content of every blob kept outside the database for the current master
+    /// key, after `storage.encryption.key_id` was changed or encryption was enabled.
+    ///
+    /// Content encrypted with an older master key keeps its data key, only the wrapped data
+    /// key in front of it is written again.
+    pub async fn rotate_keys(&self) -> Result<KeyRotation, MegaError> {
+        let mut res = KeyRotation::default();
+        if !self.keyring.as_ref().is_some_and(|k| k.encrypts()) {
+            return Err(MegaError::with_message("storage encryption is not enabled"));
+        }
+        let mut pages = raw_blob::Entity::find()
+            .filter(
+                raw_blob::Column::StorageType.is_in([StorageType::LocalFs, StorageType::RemoteUrl]),
+            )
+            .order_by_asc(raw_blob::Column::Id)
+            .paginate(self.get_connection(), 100);
+        while let Some(blobs) = pages.fetch_and_next().await? {
+            for blob in blobs {
+                res.checked += 1;
+                match self.rotate_blob(&blob).await {
+                    Ok(true) => res.rotated += 1,
+                    Ok(false) => (),
+                    Err(err) => {
+                        tracing::warn!("failed to encrypt blob {} again: {}", blob.sha1, err);
+                        res.failed.push((blob.sha1, err.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(res)
+    }
+
+    async fn rotate_blob(&self, blob: &raw_blob::Model) -> Result<bool, MegaError> {
+        let location = location(blob)?;
+        let source = self.source(blob.storage_type, location)?;
+        // local files outside the configured directory would be written to another place
+        if !source.contains(location) {
+            return Err(MegaError::with_message(&format!(
+                "{} is not in the configured storage, move it with `mega storage migrate` first",
+                location
+            )));
+        }
+        source.rotate(&blob.sha1, location).await
+    }
+
+    async fn migrate_blob(&self, blob: raw_blob::Model, dry_run: bool) -> Result<bool, MegaError> {
+        let old = match blob.storage_type {
+            // its chunks are moved as blobs of their own
+            StorageType::Chunked => return Ok(false),
+            StorageType::Database => None,
+            storage_type => Some((storage_type, location(&blob)?.to_owned())),
+        };
+        let loaded = self.load(blob).await?;
+        let size = loaded.data.as_ref().map_or(0, |data| data.len());
+        let in_place = match (&self.backend, &old) {
+            (Some(backend), Some((storage_type, location))) if size >= self.threshold => {
+                backend.storage_type() == *storage_type && backend.contains(location)
+            }
+            (Some(_), None) => size < self.threshold,
+            (None, None) => true,
+            _ => false,
+        };
+        if in_place {
+            return Ok(false);
+        }
+        if dry_run {
+            return Ok(true);
+        }
+
+        let mut moved = loaded.clone();
+        moved.local_path = None;
+        moved.remote_url = None;
+        moved.storage_type = StorageType::Database;
+        let moved = self.place(moved).await?;
+        let new_location = location(&moved).ok().map(str::to_owned);
+        let mut a_model = loaded.into_active_model();
+        a_model.storage_type = Set(moved.storage_type);
+        a_model.data = Set(moved.data);
+        a_model.local_path = Set(moved.local_path);
+        a_model.remote_url = Set(moved.remote_url);
+        a_model.update(self.get_connection()).await?;
+
+        if let Some((storage_type, location)) =
+            old.filter(|(_, l)| Some(l) != new_location.as_ref())
+        {
+            self.source(storage_type, &location)?
+                .delete(&location)
+                .await?;
+        }
+        Ok(true)
+    }
+}
+
+fn location(blob: &raw_blob::Model) -> Result<&str, MegaError> {
+    match blob.storage_type {
+        StorageType::Database | StorageType::Chunked => None,
+        StorageType::LocalFs => blob.local_path.as_deref(),
+        StorageType::RemoteUrl => blob.remote_url.as_deref(),
+    }
+    .ok_or_else(|| MegaError::with_message(&format!("blob {} has no location", blob.sha1)))
+}
+
+fn set_location(blob: &mut raw_blob::Model, storage_type: StorageType, location: String) {
+    blob.storage_type = storage_type;
+    match storage_type {
+        StorageType::Database | StorageType::Chunked => (),
+        StorageType::LocalFs => blob.local_path = Some(location),
+        StorageType::RemoteUrl => blob.remote_url = Some(location),
     }
 }
@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+
+use callisto::{release, release_asset};
+use common::errors::MegaError;
+
+#[derive(Clone)]
+pub struct ReleaseStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl ReleaseStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        ReleaseStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        ReleaseStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    pub async fn save_release(&self, model: release::Model) -> Result<release::Model, MegaError> {
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_release(&self, id: i64) -> Result<Option<release::Model>, MegaError> {
+        Ok(release::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_release_by_tag(
+        &self,
+        path: &str,
+        tag_name: &str,
+    ) -> Result<Option<release::Model>, MegaError> {
+        Ok(release::Entity::find()
+            .filter(release::Column::Path.eq(path))
+            .filter(release::Column::TagName.eq(tag_name))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Releases of `path`, latest first.
+    pub async fn list_releases(
+        &self,
+        path: &str,
+        limit: u64,
+    ) -> Result<Vec<release::Model>, MegaError> {
+        Ok(release::Entity::find()
+            .filter(release::Column::Path.eq(path))
+            .order_by_desc(release::Column::CreatedAt)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Releases of all paths.
+    pub async fn list_all_releases(&self) -> Result<Vec<release::Model>, MegaError> {
+        Ok(release::Entity::find().all(self.get_connection()).await?)
+    }
+
+    /// Delete the release with its asset records, returns the object ids of the assets.
+    pub async fn delete_release(&self, id: i64) -> Result<Vec<String>, MegaError> {
+        let assets = self.list_assets(id).await?;
+        release_asset::Entity::delete_many()
+            .filter(release_asset::Column::ReleaseId.eq(id))
+            .exec(self.get_connection())
+            .await?;
+        release::Entity::delete_by_id(id)
+            .exec(self.get_connection())
+            .await?;
+        Ok(assets.into_iter().map(|a| a.oid).collect())
+    }
+
+    pub async fn save_asset(
+        &self,
+        model: release_asset::Model,
+    ) -> Result<release_asset::Model, MegaError> {
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    pub async fn list_assets(
+        &self,
+        release_id: i64,
+    ) -> Result<Vec<release_asset::Model>, MegaError> {
+        Ok(release_asset::Entity::find()
+            .filter(release_asset::Column::ReleaseId.eq(release_id))
+            .order_by_asc(release_asset::Column::Name)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_asset(&self, id: i64) -> Result<Option<release_asset::Model>, MegaError> {
+        Ok(release_asset::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn delete_asset(&self, id: i64) -> Result<(), MegaError> {
+        release_asset::Entity::delete_by_id(id)
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Count a download, done in the database so concurrent downloads are not lost.
+    pub async fn increase_download_count(&self, id: i64) -> Result<(), MegaError> {
+        release_asset::Entity::update_many()
+            .col_expr(
+                release_asset::Column::DownloadCount,
+                Expr::col(release_asset::Column::DownloadCount).add(1),
+            )
+            .filter(release_asset::Column::Id.eq(id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Object ids of all stored assets, they share the object store with LFS.
+    pub async fn list_asset_oids(&self) -> Result<HashSet<String>, MegaError> {
+        let oids: Vec<String> = release_asset::Entity::find()
+            .select_only()
+            .column(release_asset::Column::Oid)
+            .into_tuple()
+            .all(self.get_connection())
+            .await?;
+        Ok(oids.into_iter().collect())
+    }
+}
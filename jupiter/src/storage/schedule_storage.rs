@@ -0,0 +1,123 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction, EntityTrait,
+    IntoActiveModel, QueryFilter, QueryOrder, QuerySelect,
+};
+
+use callisto::scheduled_message::*;
+use common::errors::MegaError;
+
+use crate::storage;
+
+#[derive(Clone)]
+pub struct ScheduleStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl ScheduleStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        ScheduleStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        ScheduleStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Run `f` in a transaction, see [`storage::transaction`].
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T, MegaError>
+    where
+        F: FnOnce(Arc<DatabaseTransaction>) -> Fut,
+        Fut: Future<Output = Result<T, MegaError>>,
+    {
+        storage::transaction(self.get_connection(), f).await
+    }
+
+    /// Store the message to release at its `deliver_at`, it replaces the one with the same
+    /// `cancel_key`.
+    pub async fn schedule(&self, msg: Model) -> Result<(), MegaError> {
+        self.transaction(|txn| async move {
+            if let Some(key) = &msg.cancel_key {
+                Entity::delete_many()
+                    .filter(Column::CancelKey.eq(key.as_str()))
+                    .exec(&*txn)
+                    .await?;
+            }
+            Entity::insert(msg.into_active_model()).exec(&*txn).await?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Cancel the message with `cancel_key`, returns `false` if there is none left to release.
+    pub async fn cancel(&self, cancel_key: &str) -> Result<bool, MegaError> {
+        let res = Entity::delete_many()
+            .filter(Column::CancelKey.eq(cancel_key))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    pub async fn find_scheduled(&self, cancel_key: &str) -> Result<Option<Model>, MegaError> {
+        let res = Entity::find()
+            .filter(Column::CancelKey.eq(cancel_key))
+            .one(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Claim up to `limit` messages to release before `before`, earliest first. Each is held
+    /// until `margin` seconds after it is due, a message claimed by another instance at the
+    /// same time is skipped.
+    pub async fn claim_due(
+        &self,
+        before: NaiveDateTime,
+        margin: u64,
+        limit: u64,
+    ) -> Result<Vec<Model>, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let free = || {
+            Column::ClaimedUntil
+                .is_null()
+                .or(Column::ClaimedUntil.lt(now))
+        };
+        let due = Entity::find()
+            .filter(Column::DeliverAt.lte(before))
+            .filter(free())
+            .order_by_asc(Column::DeliverAt)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?;
+        let mut claimed = Vec::new();
+        for mut msg in due {
+            let claimed_until = msg.deliver_at.max(now) + chrono::Duration::seconds(margin as i64);
+            let res = Entity::update_many()
+                .col_expr(Column::ClaimedUntil, Expr::value(claimed_until))
+                .filter(Column::Id.eq(msg.id))
+                .filter(free())
+                .exec(self.get_connection())
+                .await?;
+            if res.rows_affected == 1 {
+                msg.claimed_until = Some(claimed_until);
+                claimed.push(msg);
+            }
+        }
+        Ok(claimed)
+    }
+
+    /// Remove the message `id` to release it through `conn`, returns `false` if it was
+    /// canceled or released before.
+    pub async fn take(&self, conn: &impl ConnectionTrait, id: i64) -> Result<bool, MegaError> {
+        let res = Entity::delete_by_id(id).exec(conn).await?;
+        Ok(res.rows_affected > 0)
+    }
+}
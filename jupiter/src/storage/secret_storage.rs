@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, Set,
+};
+
+use callisto::db_enums::FindingStatus;
+use callisto::{quarantined_push, secret_finding};
+use common::errors::MegaError;
+
+use crate::storage::batch_save_model;
+
+#[derive(Clone)]
+pub struct SecretStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl SecretStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        SecretStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        SecretStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    pub async fn save_findings(
+        &self,
+        findings: Vec<secret_finding::Model>,
+    ) -> Result<(), MegaError> {
+        let models: Vec<secret_finding::ActiveModel> = findings
+            .into_iter()
+            .map(|f| f.into_active_model())
+            .collect();
+        batch_save_model(self.get_connection(), models).await
+    }
+
+    pub async fn list_findings(
+        &self,
+        status: Option<FindingStatus>,
+        path: Option<String>,
+        limit: u64,
+    ) -> Result<Vec<secret_finding::Model>, MegaError> {
+        let mut query = secret_finding::Entity::find();
+        if let Some(status) = status {
+            query = query.filter(secret_finding::Column::Status.eq(status));
+        }
+        if let Some(path) = path {
+            query = query.filter(secret_finding::Column::Path.eq(path));
+        }
+        Ok(query
+            .order_by_desc(secret_finding::Column::CreatedAt)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_finding(&self, id: i64) -> Result<Option<secret_finding::Model>, MegaError> {
+        Ok(secret_finding::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn review_finding(
+        &self,
+        finding: secret_finding::Model,
+        status: FindingStatus,
+        reviewer: &str,
+    ) -> Result<secret_finding::Model, MegaError> {
+        let mut a_model = finding.into_active_model();
+        a_model.status = Set(status);
+        a_model.reviewer = Set(Some(reviewer.to_owned()));
+        a_model.reviewed_at = Set(Some(chrono::Utc::now().naive_utc()));
+        Ok(a_model.update(self.get_connection()).await?)
+    }
+
+    /// Blobs among `blob_ids` which a reviewer marked as false positive.
+    pub async fn get_allowed_blobs(
+        &self,
+        blob_ids: Vec<String>,
+    ) -> Result<HashSet<String>, MegaError> {
+        Ok(secret_finding::Entity::find()
+            .filter(secret_finding::Column::BlobId.is_in(blob_ids))
+            .filter(secret_finding::Column::Status.eq(FindingStatus::FalsePositive))
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .map(|f| f.blob_id)
+            .collect())
+    }
+
+    pub async fn save_quarantined_push(
+        &self,
+        push: quarantined_push::Model,
+    ) -> Result<(), MegaError> {
+        push.into_active_model()
+            .insert(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_quarantined_push(
+        &self,
+        id: i64,
+    ) -> Result<Option<quarantined_push::Model>, MegaError> {
+        Ok(quarantined_push::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Quarantined pushes which were not released yet, the oldest first.
+    pub async fn list_held_pushes(&self) -> Result<Vec<quarantined_push::Model>, MegaError> {
+        Ok(quarantined_push::Entity::find()
+            .filter(quarantined_push::Column::ReleasedAt.is_null())
+            .order_by_asc(quarantined_push::Column::CreatedAt)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Number of the findings of the push `push_id` which were not reviewed yet.
+    pub async fn count_open_findings(&self, push_id: i64) -> Result<u64, MegaError> {
+        Ok(secret_finding::Entity::find()
+            .filter(secret_finding::Column::PushId.eq(push_id))
+            .filter(secret_finding::Column::Status.eq(FindingStatus::Open))
+            .count(self.get_connection())
+            .await?)
+    }
+
+    pub async fn mark_push_released(
+        &self,
+        push: quarantined_push::Model,
+        reviewer: &str,
+    ) -> Result<quarantined_push::Model, MegaError> {
+        let mut a_model = push.into_active_model();
+        a_model.released_at = Set(Some(chrono::Utc::now().naive_utc()));
+        a_model.released_by = Set(Some(reviewer.to_owned()));
+        Ok(a_model.update(self.get_connection()).await?)
+    }
+}
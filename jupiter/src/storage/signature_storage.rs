@@ -0,0 +1,124 @@
+use std::sync::Arc;
+
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    QueryOrder, TransactionTrait,
+};
+
+use callisto::db_enums::{SignatureStatus, SigningKeyType};
+use callisto::{object_signature, signing_key};
+use common::errors::MegaError;
+use common::utils::generate_id;
+
+#[derive(Clone)]
+pub struct SignatureStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl SignatureStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        SignatureStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        SignatureStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Register a signing key of the user with the email `email`, signatures of the user which
+    /// were unverified so far are verified again.
+    pub async fn save_signing_key(
+        &self,
+        user_id: i64,
+        email: &str,
+        key_type: SigningKeyType,
+        title: &str,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<signing_key::Model, MegaError> {
+        let model = signing_key::Model {
+            id: generate_id(),
+            user_id,
+            key_type,
+            title: title.to_owned(),
+            key: key.to_owned(),
+            fingerprint: fingerprint.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        let txn = self.get_connection().begin().await?;
+        let res = model.into_active_model().insert(&txn).await?;
+        object_signature::Entity::delete_many()
+            .filter(object_signature::Column::SignerEmail.eq(email))
+            .filter(object_signature::Column::Status.eq(SignatureStatus::Unverified))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(res)
+    }
+
+    pub async fn list_signing_keys(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<signing_key::Model>, MegaError> {
+        let res = signing_key::Entity::find()
+            .filter(signing_key::Column::UserId.eq(user_id))
+            .order_by_asc(signing_key::Column::CreatedAt)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Remove a signing key of the user, signatures it verified are verified again.
+    pub async fn delete_signing_key(&self, user_id: i64, id: i64) -> Result<bool, MegaError> {
+        let txn = self.get_connection().begin().await?;
+        let res = signing_key::Entity::delete_many()
+            .filter(signing_key::Column::Id.eq(id))
+            .filter(signing_key::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await?;
+        object_signature::Entity::delete_many()
+            .filter(object_signature::Column::KeyId.eq(id))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// The cached verification results of the commits or tags among `object_ids`.
+    pub async fn get_signatures(
+        &self,
+        object_ids: Vec<String>,
+    ) -> Result<Vec<object_signature::Model>, MegaError> {
+        let res = object_signature::Entity::find()
+            .filter(object_signature::Column::ObjectId.is_in(object_ids))
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Cache the verification result of a commit or tag, replacing an earlier one.
+    pub async fn save_signature(&self, model: object_signature::Model) -> Result<(), MegaError> {
+        object_signature::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::column(object_signature::Column::ObjectId)
+                    .update_columns([
+                        object_signature::Column::Status,
+                        object_signature::Column::SignerEmail,
+                        object_signature::Column::UserId,
+                        object_signature::Column::KeyId,
+                        object_signature::Column::Reason,
+                        object_signature::Column::VerifiedAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+}
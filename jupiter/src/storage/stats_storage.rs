@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+
+use callisto::db_enums::{BackgroundJobState, MessageState};
+use callisto::{background_job, daily_stats, git_repo, mega_refs, mq_storage, user, user_activity};
+use common::errors::MegaError;
+use common::utils::{generate_id, MEGA_BRANCH_NAME};
+
+/// Counters of [`daily_stats`], counted on the day they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    Push,
+    MrOpened,
+    MrMerged,
+}
+
+impl Counter {
+    fn column(self) -> daily_stats::Column {
+        match self {
+            Counter::Push => daily_stats::Column::Pushes,
+            Counter::MrOpened => daily_stats::Column::MrsOpened,
+            Counter::MrMerged => daily_stats::Column::MrsMerged,
+        }
+    }
+}
+
+/// The rollups of the activity of the instance per day and the counts the statistics are
+/// assembled from.
+#[derive(Clone)]
+pub struct StatsStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+impl StatsStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        StatsStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        StatsStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Add one to `counter` of `day`.
+    pub async fn count(&self, day: NaiveDate, counter: Counter) -> Result<(), MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let mut model = daily_stats::Model {
+            id: generate_id(),
+            day,
+            pushes: 0,
+            mrs_opened: 0,
+            mrs_merged: 0,
+            updated_at: now,
+        };
+        match counter {
+            Counter::Push => model.pushes = 1,
+            Counter::MrOpened => model.mrs_opened = 1,
+            Counter::MrMerged => model.mrs_merged = 1,
+        }
+        let column = counter.column();
+        // one statement, so counts of the same day at the same time are not lost
+        daily_stats::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::column(daily_stats::Column::Day)
+                    .value(column, Expr::col((daily_stats::Entity, column)).add(1))
+                    .value(daily_stats::Column::UpdatedAt, now)
+                    .to_owned(),
+            )
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Record that `user_name` was active on `day`, returns `false` if that is already known.
+    pub async fn record_activity(
+        &self,
+        day: NaiveDate,
+        user_name: &str,
+    ) -> Result<bool, MegaError> {
+        let model = user_activity::Model {
+            id: generate_id(),
+            user_name: user_name.to_owned(),
+            day,
+        };
+        let inserted = user_activity::Entity::insert(model.into_active_model())
+            .on_conflict(
+                OnConflict::columns([user_activity::Column::Day, user_activity::Column::UserName])
+                    .do_nothing()
+                    .to_owned(),
+            )
+            .exec_without_returning(self.get_connection())
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    /// The counters of the days since `since`, oldest first, days without activity are missing.
+    pub async fn list_days(&self, since: NaiveDate) -> Result<Vec<daily_stats::Model>, MegaError> {
+        Ok(daily_stats::Entity::find()
+            .filter(daily_stats::Column::Day.gte(since))
+            .order_by_asc(daily_stats::Column::Day)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The number of users active on each day since `since`, oldest first.
+    pub async fn active_users_by_day(
+        &self,
+        since: NaiveDate,
+    ) -> Result<Vec<(NaiveDate, i64)>, MegaError> {
+        Ok(user_activity::Entity::find()
+            .select_only()
+            .column(user_activity::Column::Day)
+            .column_as(user_activity::Column::Id.count(), "count")
+            .filter(user_activity::Column::Day.gte(since))
+            .group_by(user_activity::Column::Day)
+            .order_by_asc(user_activity::Column::Day)
+            .into_tuple()
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The number of users active on any day since `since`.
+    pub async fn count_active_users(&self, since: NaiveDate) -> Result<u64, MegaError> {
+        Ok(user_activity::Entity::find()
+            .select_only()
+            .column(user_activity::Column::UserName)
+            .distinct()
+            .filter(user_activity::Column::Day.gte(since))
+            .count(self.get_connection())
+            .await?)
+    }
+
+    /// Forget the activity of the days before `before`, returns how many records were removed.
+    pub async fn prune_activity(&self, before: NaiveDate) -> Result<u64, MegaError> {
+        let res = user_activity::Entity::delete_many()
+            .filter(user_activity::Column::Day.lt(before))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected)
+    }
+
+    /// The numbers of directories of the monorepo with a branch and of import repositories.
+    pub async fn count_repos(&self) -> Result<(u64, u64), MegaError> {
+        let mono = mega_refs::Entity::find()
+            .filter(mega_refs::Column::RefName.eq(MEGA_BRANCH_NAME))
+            .count(self.get_connection())
+            .await?;
+        let import = git_repo::Entity::find()
+            .filter(git_repo::Column::DeletedAt.is_null())
+            .count(self.get_connection())
+            .await?;
+        Ok((mono, import))
+    }
+
+    /// The numbers of users and of the deactivated ones among them.
+    pub async fn count_users(&self) -> Result<(u64, u64), MegaError> {
+        let total = user::Entity::find().count(self.get_connection()).await?;
+        let deactivated = user::Entity::find()
+            .filter(user::Column::DeactivatedAt.is_not_null())
+            .count(self.get_connection())
+            .await?;
+        Ok((total, deactivated))
+    }
+
+    /// The number of messages of the queue in each state, states without messages are missing.
+    pub async fn count_messages(&self) -> Result<Vec<(MessageState, i64)>, MegaError> {
+        Ok(mq_storage::Entity::find()
+            .select_only()
+            .column(mq_storage::Column::State)
+            .column_as(mq_storage::Column::Id.count(), "count")
+            .group_by(mq_storage::Column::State)
+            .into_tuple()
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// The number of background jobs in each state, states without jobs are missing.
+    pub async fn count_jobs(&self) -> Result<Vec<(BackgroundJobState, i64)>, MegaError> {
+        Ok(background_job::Entity::find()
+            .select_only()
+            .column(background_job::Column::State)
+            .column_as(background_job::Column::Id.count(), "count")
+            .group_by(background_job::Column::State)
+            .into_tuple()
+            .all(self.get_connection())
+            .await?)
+    }
+}
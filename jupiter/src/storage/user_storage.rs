@@ -1,13 +1,25 @@
+use std::future::Future;
+use std::path::Path;
 use std::sync::Arc;
 
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, ModelTrait,
-    QueryFilter,
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection,
+    DatabaseTransaction, EntityTrait, IntoActiveModel, ModelTrait, QueryFilter, QueryOrder, Set,
+    TransactionTrait,
 };
 use uuid::Uuid;
 
-use callisto::{access_token, ssh_keys, user};
-use common::{errors::MegaError, utils::generate_id};
+use callisto::db_enums::{OrgRole, TeamPermission};
+use callisto::{
+    access_token, org_member, org_repo, organization, service_account, service_account_token,
+    ssh_keys, team, team_member, user, user_repo,
+};
+use common::{
+    errors::MegaError,
+    utils::{generate_id, replace_path_prefix},
+};
+
+use crate::storage;
 
 #[derive(Clone)]
 pub struct UserStorage {
@@ -29,6 +41,15 @@ impl UserStorage {
         }
     }
 
+    /// Run `f` in a transaction, see [`storage::transaction`].
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T, MegaError>
+    where
+        F: FnOnce(Arc<DatabaseTransaction>) -> Fut,
+        Fut: Future<Output = Result<T, MegaError>>,
+    {
+        storage::transaction(self.get_connection(), f).await
+    }
+
     pub async fn find_user_by_email(&self, email: &str) -> Result<Option<user::Model>, MegaError> {
         let res = user::Entity::find()
             .filter(user::Column::Email.eq(email))
@@ -51,6 +72,104 @@ impl UserStorage {
         Ok(())
     }
 
+    /// Create a user signing in without GitHub.
+    pub async fn create_user(&self, name: &str, email: &str) -> Result<user::Model, MegaError> {
+        let model = user::Model {
+            id: generate_id(),
+            name: name.to_owned(),
+            email: email.to_owned(),
+            avatar_url: String::new(),
+            is_github: false,
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: None,
+            deactivated_at: None,
+            tenant: None,
+        };
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    /// Deactivate the user, or activate it again with `false`, returns `false` if it does not
+    /// exist.
+    pub async fn set_user_deactivated(
+        &self,
+        user_id: i64,
+        deactivated: bool,
+    ) -> Result<bool, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let res = user::Entity::update_many()
+            .col_expr(
+                user::Column::DeactivatedAt,
+                Expr::value(deactivated.then_some(now)),
+            )
+            .col_expr(user::Column::UpdatedAt, Expr::value(now))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Put the user in `tenant`, or in the instance with `None`, returns `false` if it does not
+    /// exist.
+    pub async fn set_user_tenant(
+        &self,
+        user_id: i64,
+        tenant: Option<&str>,
+    ) -> Result<bool, MegaError> {
+        let res = user::Entity::update_many()
+            .col_expr(user::Column::Tenant, Expr::value(tenant.map(str::to_owned)))
+            .col_expr(
+                user::Column::UpdatedAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .filter(user::Column::Id.eq(user_id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// The users of `tenant`, or those of the instance with `None`, ordered by name.
+    pub async fn list_tenant_users(
+        &self,
+        tenant: Option<&str>,
+    ) -> Result<Vec<user::Model>, MegaError> {
+        let column = user::Column::Tenant;
+        let res = user::Entity::find()
+            .filter(match tenant {
+                Some(tenant) => column.eq(tenant),
+                None => column.is_null(),
+            })
+            .order_by_asc(user::Column::Name)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Whether the user exists and is not deactivated.
+    pub async fn is_user_active(&self, user_id: i64) -> Result<bool, MegaError> {
+        let res = user::Entity::find_by_id(user_id)
+            .one(self.get_connection())
+            .await?;
+        Ok(res.is_some_and(|user| user.deactivated_at.is_none()))
+    }
+
+    /// Delete the access tokens and ssh keys of the user, returns how many of each were removed.
+    pub async fn delete_user_credentials(&self, user_id: i64) -> Result<(u64, u64), MegaError> {
+        let txn = self.get_connection().begin().await?;
+        let tokens = access_token::Entity::delete_many()
+            .filter(access_token::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await?;
+        let keys = ssh_keys::Entity::delete_many()
+            .filter(ssh_keys::Column::UserId.eq(user_id))
+            .exec(&txn)
+            .await?;
+        txn.commit().await?;
+        Ok((tokens.rows_affected, keys.rows_affected))
+    }
+
     pub async fn save_ssh_key(
         &self,
         user_id: i64,
@@ -146,6 +265,488 @@ impl UserStorage {
             None => Ok(false),
         }
     }
+
+    pub async fn save_service_account(
+        &self,
+        name: &str,
+        description: &str,
+        created_by: &str,
+    ) -> Result<service_account::Model, MegaError> {
+        let model = service_account::Model {
+            id: generate_id(),
+            name: name.to_owned(),
+            description: description.to_owned(),
+            created_by: created_by.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        Ok(model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?)
+    }
+
+    pub async fn find_service_account(
+        &self,
+        name: &str,
+    ) -> Result<Option<service_account::Model>, MegaError> {
+        Ok(service_account::Entity::find()
+            .filter(service_account::Column::Name.eq(name))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn list_service_accounts(&self) -> Result<Vec<service_account::Model>, MegaError> {
+        Ok(service_account::Entity::find()
+            .order_by_asc(service_account::Column::Name)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Delete a service account with its tokens.
+    pub async fn delete_service_account(&self, id: i64) -> Result<(), MegaError> {
+        let txn = self.get_connection().begin().await?;
+        service_account_token::Entity::delete_many()
+            .filter(service_account_token::Column::AccountId.eq(id))
+            .exec(&txn)
+            .await?;
+        service_account::Entity::delete_by_id(id).exec(&txn).await?;
+        txn.commit().await?;
+        Ok(())
+    }
+
+    pub async fn generate_service_account_token(
+        &self,
+        account_id: i64,
+        created_by: &str,
+    ) -> Result<String, MegaError> {
+        let token_str = Uuid::new_v4().to_string();
+        let model = service_account_token::Model {
+            id: generate_id(),
+            account_id,
+            token: token_str.clone(),
+            created_by: created_by.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?;
+        Ok(token_str)
+    }
+
+    pub async fn list_service_account_tokens(
+        &self,
+        account_id: i64,
+    ) -> Result<Vec<service_account_token::Model>, MegaError> {
+        Ok(service_account_token::Entity::find()
+            .filter(service_account_token::Column::AccountId.eq(account_id))
+            .order_by_asc(service_account_token::Column::CreatedAt)
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn delete_service_account_token(
+        &self,
+        account_id: i64,
+        id: i64,
+    ) -> Result<bool, MegaError> {
+        let res = service_account_token::Entity::delete_many()
+            .filter(service_account_token::Column::Id.eq(id))
+            .filter(service_account_token::Column::AccountId.eq(account_id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    pub async fn check_service_account_token(
+        &self,
+        account_id: i64,
+        token: &str,
+    ) -> Result<bool, MegaError> {
+        let res = service_account_token::Entity::find()
+            .filter(service_account_token::Column::AccountId.eq(account_id))
+            .filter(service_account_token::Column::Token.eq(token))
+            .one(self.get_connection())
+            .await?;
+        Ok(res.is_some())
+    }
+
+    pub async fn find_users_by_ids(&self, ids: Vec<i64>) -> Result<Vec<user::Model>, MegaError> {
+        let res = user::Entity::find()
+            .filter(user::Column::Id.is_in(ids))
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Create an organization and make `owner_id` its first owner.
+    pub async fn save_organization(
+        &self,
+        name: &str,
+        description: &str,
+        owner_id: i64,
+    ) -> Result<organization::Model, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let model = organization::Model {
+            id: generate_id(),
+            name: name.to_owned(),
+            description: description.to_owned(),
+            created_at: now,
+            updated_at: now,
+        };
+        let res = model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?;
+        self.save_org_member(res.id, owner_id, OrgRole::Owner)
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_org_by_name(
+        &self,
+        name: &str,
+    ) -> Result<Option<organization::Model>, MegaError> {
+        let res = organization::Entity::find()
+            .filter(organization::Column::Name.eq(name))
+            .one(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_orgs_by_ids(
+        &self,
+        ids: Vec<i64>,
+    ) -> Result<Vec<organization::Model>, MegaError> {
+        let res = organization::Entity::find()
+            .filter(organization::Column::Id.is_in(ids))
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn list_user_orgs(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<organization::Model>, MegaError> {
+        let org_ids: Vec<i64> = org_member::Entity::find()
+            .filter(org_member::Column::UserId.eq(user_id))
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .map(|m| m.org_id)
+            .collect();
+        let res = organization::Entity::find()
+            .filter(organization::Column::Id.is_in(org_ids))
+            .order_by_asc(organization::Column::Name)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn save_org_member(
+        &self,
+        org_id: i64,
+        user_id: i64,
+        role: OrgRole,
+    ) -> Result<(), MegaError> {
+        let model = org_member::Model {
+            id: generate_id(),
+            org_id,
+            user_id,
+            role,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_org_members(&self, org_id: i64) -> Result<Vec<org_member::Model>, MegaError> {
+        let res = org_member::Entity::find()
+            .filter(org_member::Column::OrgId.eq(org_id))
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Remove a user from the organization and from all of its teams.
+    pub async fn delete_org_member(&self, org_id: i64, user_id: i64) -> Result<(), MegaError> {
+        org_member::Entity::delete_many()
+            .filter(org_member::Column::OrgId.eq(org_id))
+            .filter(org_member::Column::UserId.eq(user_id))
+            .exec(self.get_connection())
+            .await?;
+        let team_ids: Vec<i64> = self
+            .list_teams(org_id)
+            .await?
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        team_member::Entity::delete_many()
+            .filter(team_member::Column::TeamId.is_in(team_ids))
+            .filter(team_member::Column::UserId.eq(user_id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn save_team(
+        &self,
+        org_id: i64,
+        parent_id: Option<i64>,
+        name: &str,
+        permission: TeamPermission,
+    ) -> Result<team::Model, MegaError> {
+        let now = chrono::Utc::now().naive_utc();
+        let model = team::Model {
+            id: generate_id(),
+            org_id,
+            parent_id,
+            name: name.to_owned(),
+            permission,
+            created_at: now,
+            updated_at: now,
+        };
+        let res = model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn list_teams(&self, org_id: i64) -> Result<Vec<team::Model>, MegaError> {
+        let res = team::Entity::find()
+            .filter(team::Column::OrgId.eq(org_id))
+            .order_by_asc(team::Column::Name)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Delete a team, child teams are moved up to the parent of the deleted team.
+    pub async fn delete_team(&self, team: team::Model) -> Result<(), MegaError> {
+        team::Entity::update_many()
+            .col_expr(team::Column::ParentId, Expr::value(team.parent_id))
+            .filter(team::Column::ParentId.eq(team.id))
+            .exec(self.get_connection())
+            .await?;
+        team_member::Entity::delete_many()
+            .filter(team_member::Column::TeamId.eq(team.id))
+            .exec(self.get_connection())
+            .await?;
+        team.delete(self.get_connection()).await?;
+        Ok(())
+    }
+
+    pub async fn save_team_member(&self, team_id: i64, user_id: i64) -> Result<(), MegaError> {
+        let model = team_member::Model {
+            id: generate_id(),
+            team_id,
+            user_id,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        model
+            .into_active_model()
+            .insert(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_team_members(
+        &self,
+        team_ids: Vec<i64>,
+    ) -> Result<Vec<team_member::Model>, MegaError> {
+        let res = team_member::Entity::find()
+            .filter(team_member::Column::TeamId.is_in(team_ids))
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn delete_team_member(&self, team_id: i64, user_id: i64) -> Result<(), MegaError> {
+        team_member::Entity::delete_many()
+            .filter(team_member::Column::TeamId.eq(team_id))
+            .filter(team_member::Column::UserId.eq(user_id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Mark the repository or directory at `path` (and everything below it) as owned by the organization.
+    pub async fn save_org_repo(
+        &self,
+        conn: &impl ConnectionTrait,
+        org_id: i64,
+        path: &str,
+    ) -> Result<(), MegaError> {
+        let model = org_repo::Model {
+            id: generate_id(),
+            org_id,
+            path: path.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        model.into_active_model().insert(conn).await?;
+        Ok(())
+    }
+
+    pub async fn delete_org_repo(
+        &self,
+        conn: &impl ConnectionTrait,
+        path: &str,
+    ) -> Result<(), MegaError> {
+        org_repo::Entity::delete_many()
+            .filter(org_repo::Column::Path.eq(path))
+            .exec(conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_org_repos(&self, org_id: i64) -> Result<Vec<org_repo::Model>, MegaError> {
+        let res = org_repo::Entity::find()
+            .filter(org_repo::Column::OrgId.eq(org_id))
+            .order_by_asc(org_repo::Column::Path)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// The directories owned by any organization.
+    pub async fn list_all_org_repos(&self) -> Result<Vec<org_repo::Model>, MegaError> {
+        let res = org_repo::Entity::find()
+            .order_by_asc(org_repo::Column::Path)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Find the organization owning `path`, the nearest owned ancestor directory wins.
+    pub async fn find_owner_org(
+        &self,
+        path: &Path,
+    ) -> Result<Option<(org_repo::Model, organization::Model)>, MegaError> {
+        let ancestors: Vec<String> = path
+            .ancestors()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect();
+        let owned = org_repo::Entity::find()
+            .filter(org_repo::Column::Path.is_in(ancestors))
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .max_by_key(|r| r.path.len());
+        if let Some(owned) = owned {
+            let org = organization::Entity::find_by_id(owned.org_id)
+                .one(self.get_connection())
+                .await?;
+            return Ok(org.map(|org| (owned, org)));
+        }
+        Ok(None)
+    }
+
+    pub async fn save_user_repo(
+        &self,
+        conn: &impl ConnectionTrait,
+        user_id: i64,
+        path: &str,
+    ) -> Result<(), MegaError> {
+        let model = user_repo::Model {
+            id: generate_id(),
+            user_id,
+            path: path.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        model.into_active_model().insert(conn).await?;
+        Ok(())
+    }
+
+    pub async fn delete_user_repo(
+        &self,
+        conn: &impl ConnectionTrait,
+        path: &str,
+    ) -> Result<(), MegaError> {
+        user_repo::Entity::delete_many()
+            .filter(user_repo::Column::Path.eq(path))
+            .exec(conn)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list_user_repos(&self, user_id: i64) -> Result<Vec<user_repo::Model>, MegaError> {
+        let res = user_repo::Entity::find()
+            .filter(user_repo::Column::UserId.eq(user_id))
+            .order_by_asc(user_repo::Column::Path)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// The directories owned by any user.
+    pub async fn list_all_user_repos(&self) -> Result<Vec<user_repo::Model>, MegaError> {
+        let res = user_repo::Entity::find()
+            .order_by_asc(user_repo::Column::Path)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Find the user owning `path`, the nearest owned ancestor directory wins.
+    pub async fn find_owner_user(
+        &self,
+        path: &Path,
+    ) -> Result<Option<(user_repo::Model, user::Model)>, MegaError> {
+        let ancestors: Vec<String> = path
+            .ancestors()
+            .map(|p| p.to_str().unwrap().to_owned())
+            .collect();
+        let owned = user_repo::Entity::find()
+            .filter(user_repo::Column::Path.is_in(ancestors))
+            .all(self.get_connection())
+            .await?
+            .into_iter()
+            .max_by_key(|r| r.path.len());
+        if let Some(owned) = owned {
+            let user = user::Entity::find_by_id(owned.user_id)
+                .one(self.get_connection())
+                .await?;
+            return Ok(user.map(|user| (owned, user)));
+        }
+        Ok(None)
+    }
+
+    /// Move ownership records of `old` and its children to `new` after a rename.
+    pub async fn rename_owned_paths(
+        &self,
+        conn: &impl ConnectionTrait,
+        old: &str,
+        new: &str,
+    ) -> Result<(), MegaError> {
+        let org_repos = org_repo::Entity::find()
+            .filter(org_repo::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in org_repos {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        let user_repos = user_repo::Entity::find()
+            .filter(user_repo::Column::Path.starts_with(old))
+            .all(conn)
+            .await?;
+        for model in user_repos {
+            if let Some(path) = replace_path_prefix(&model.path, old, new) {
+                let mut a_model = model.into_active_model();
+                a_model.path = Set(path);
+                a_model.update(conn).await?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+
+use callisto::db_enums::MessageState;
+use callisto::{webhook, webhook_delivery};
+use common::config::WebhookConfig;
+use common::errors::MegaError;
+use common::model::Pagination;
+use common::utils::{generate_id, replace_path_prefix};
+
+#[derive(Clone)]
+pub struct WebhookStorage {
+    pub connection: Arc<DatabaseConnection>,
+}
+
+/// The response to an attempt of a delivery, if the webhook responded.
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryResponse {
+    pub status: Option<i32>,
+    pub body: Option<String>,
+}
+
+impl WebhookStorage {
+    pub fn get_connection(&self) -> &DatabaseConnection {
+        &self.connection
+    }
+
+    pub async fn new(connection: Arc<DatabaseConnection>) -> Self {
+        WebhookStorage { connection }
+    }
+
+    pub fn mock() -> Self {
+        WebhookStorage {
+            connection: Arc::new(DatabaseConnection::default()),
+        }
+    }
+
+    /// Subscribe the webhook at `url` to the events of the schemas `events`, all if empty, of
+    /// `path` and below it.
+    pub async fn create_webhook(
+        &self,
+        url: &str,
+        secret: &str,
+        events: &[String],
+        path: &str,
+        operator: &str,
+    ) -> Result<webhook::Model, MegaError> {
+        let model = webhook::Model {
+            id: generate_id(),
+            url: url.to_owned(),
+            secret: secret.to_owned(),
+            events: events.join(","),
+            path: path.to_owned(),
+            created_by: operator.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        webhook::Entity::insert(model.clone().into_active_model())
+            .exec(self.get_connection())
+            .await?;
+        Ok(model)
+    }
+
+    pub async fn list_webhooks(&self) -> Result<Vec<webhook::Model>, MegaError> {
+        let res = webhook::Entity::find()
+            .order_by_asc(webhook::Column::Id)
+            .all(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    pub async fn find_webhook(&self, id: i64) -> Result<Option<webhook::Model>, MegaError> {
+        let res = webhook::Entity::find_by_id(id)
+            .one(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Delete the webhook `id` with its deliveries, returns whether there was one.
+    pub async fn delete_webhook(&self, id: i64) -> Result<bool, MegaError> {
+        webhook_delivery::Entity::delete_many()
+            .filter(webhook_delivery::Column::WebhookId.eq(id))
+            .exec(self.get_connection())
+            .await?;
+        let res = webhook::Entity::delete_by_id(id)
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// The webhooks to which events of the schema `event` at `path` are delivered.
+    pub async fn subscribed_webhooks(
+        &self,
+        event: &str,
+        path: Option<&str>,
+    ) -> Result<Vec<webhook::Model>, MegaError> {
+        let mut res = self.list_webhooks().await?;
+        res.retain(|webhook| is_subscribed(webhook, event, path));
+        Ok(res)
+    }
+
+    /// Store deliveries to attempt as soon as a worker claims them, a delivery of an event to a
+    /// webhook which already has one is skipped. Returns the number stored.
+    pub async fn save_deliveries(
+        &self,
+        deliveries: Vec<webhook_delivery::Model>,
+    ) -> Result<u64, MegaError> {
+        if deliveries.is_empty() {
+            return Ok(0);
+        }
+        let deliveries: Vec<webhook_delivery::ActiveModel> = deliveries
+            .into_iter()
+            .map(|d| d.into_active_model())
+            .collect();
+        let res = webhook_delivery::Entity::insert_many(deliveries)
+            .on_conflict(
+                OnConflict::columns([
+                    webhook_delivery::Column::WebhookId,
+                    webhook_delivery::Column::EventId,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec_without_returning(self.get_connection())
+            .await?;
+        Ok(res)
+    }
+
+    /// Claim up to `limit` deliveries which are due, they stay hidden from other workers for
+    /// `hidden_for` seconds. A delivery claimed by another worker at the same time is skipped.
+    pub async fn claim_deliveries(
+        &self,
+        limit: u64,
+        hidden_for: u64,
+    ) -> Result<Vec<webhook_delivery::Model>, MegaError> {
+        use webhook_delivery::{Column, Entity};
+
+        let now = chrono::Utc::now().naive_utc();
+        let due = Entity::find()
+            .filter(Column::State.is_in([
+                MessageState::Pending,
+                MessageState::Inflight,
+                MessageState::Failed,
+            ]))
+            .filter(
+                Column::NextAttemptAt
+                    .is_null()
+                    .or(Column::NextAttemptAt.lte(now)),
+            )
+            .order_by_asc(Column::Id)
+            .limit(limit)
+            .all(self.get_connection())
+            .await?;
+        let next_attempt_at = now + chrono::Duration::seconds(hidden_for as i64);
+        let mut claimed = Vec::new();
+        for mut delivery in due {
+            // the attempt count only matches if nobody claimed the delivery since it was read
+            let res = Entity::update_many()
+                .col_expr(Column::State, Expr::value(MessageState::Inflight))
+                .col_expr(Column::Attempts, Expr::value(delivery.attempts + 1))
+                .col_expr(Column::NextAttemptAt, Expr::value(next_attempt_at))
+                .filter(Column::Id.eq(delivery.id))
+                .filter(Column::Attempts.eq(delivery.attempts))
+                .exec(self.get_connection())
+                .await?;
+            if res.rows_affected == 1 {
+                delivery.state = MessageState::Inflight;
+                delivery.attempts += 1;
+                delivery.next_attempt_at = Some(next_attempt_at);
+                claimed.push(delivery);
+            }
+        }
+        Ok(claimed)
+    }
+
+    /// Record that the webhook accepted the delivery `id`.
+    pub async fn complete_delivery(
+        &self,
+        id: i64,
+        response: DeliveryResponse,
+    ) -> Result<(), MegaError> {
+        use webhook_delivery::{Column, Entity};
+
+        Entity::update_many()
+            .col_expr(Column::State, Expr::value(MessageState::Done))
+            .col_expr(
+                Column::NextAttemptAt,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .col_expr(Column::ResponseStatus, Expr::value(response.status))
+            .col_expr(Column::ResponseBody, Expr::value(response.body))
+            .col_expr(Column::LastError, Expr::value(Option::<String>::None))
+            .col_expr(
+                Column::DeliveredAt,
+                Expr::value(chrono::Utc::now().naive_utc()),
+            )
+            .filter(Column::Id.eq(id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Record that the `attempts`-th attempt of the delivery `id` failed with `error`, returns
+    /// the state it is in now.
+    pub async fn fail_delivery(
+        &self,
+        id: i64,
+        attempts: i32,
+        response: DeliveryResponse,
+        error: &str,
+        config: &WebhookConfig,
+    ) -> Result<MessageState, MegaError> {
+        use webhook_delivery::{Column, Entity};
+
+        let (state, next_attempt_at) = retry_at(attempts, config);
+        Entity::update_many()
+            .col_expr(Column::State, Expr::value(state))
+            .col_expr(Column::NextAttemptAt, Expr::value(next_attempt_at))
+            .col_expr(Column::ResponseStatus, Expr::value(response.status))
+            .col_expr(Column::ResponseBody, Expr::value(response.body))
+            .col_expr(Column::LastError, Expr::value(error))
+            .filter(Column::Id.eq(id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(state)
+    }
+
+    /// A page of the deliveries to the webhook `webhook_id`, of those in `state` if given,
+    /// latest first, and the number of all of them.
+    pub async fn list_deliveries(
+        &self,
+        webhook_id: i64,
+        state: Option<MessageState>,
+        page: Pagination,
+    ) -> Result<(Vec<webhook_delivery::Model>, u64), MegaError> {
+        use webhook_delivery::{Column, Entity};
+
+        let mut query = Entity::find().filter(Column::WebhookId.eq(webhook_id));
+        if let Some(state) = state {
+            query = query.filter(Column::State.eq(state));
+        }
+        let paginator = query
+            .order_by_desc(Column::Id)
+            .paginate(self.get_connection(), page.per_page);
+        let total = paginator.num_items().await?;
+        Ok((
+            paginator.fetch_page(page.page.saturating_sub(1)).await?,
+            total,
+        ))
+    }
+
+    /// Attempt the dead-lettered delivery `id` of the webhook `webhook_id` again with a fresh
+    /// attempt count, returns `false` if there is no such delivery.
+    pub async fn requeue_delivery(&self, webhook_id: i64, id: i64) -> Result<bool, MegaError> {
+        use webhook_delivery::{Column, Entity};
+
+        let res = Entity::update_many()
+            .col_expr(Column::State, Expr::value(MessageState::Pending))
+            .col_expr(Column::Attempts, Expr::value(0))
+            .col_expr(
+                Column::NextAttemptAt,
+                Expr::value(Option::<NaiveDateTime>::None),
+            )
+            .filter(Column::Id.eq(id))
+            .filter(Column::WebhookId.eq(webhook_id))
+            .filter(Column::State.eq(MessageState::Dead))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+}
+
+/// Whether events of the schema `event` at `path` are delivered to `webhook`. Events without a
+/// path, like those of issues, are only delivered to webhooks of the whole monorepo.
+pub fn is_subscribed(webhook: &webhook::Model, event: &str, path: Option<&str>) -> bool {
+    let mut events = webhook
+        .events
+        .split(',')
+        .filter(|e| !e.is_empty())
+        .peekable();
+    if events.peek().is_some() && !events.any(|e| e == event) {
+        return false;
+    }
+    let root = webhook.path.trim_end_matches('/');
+    match path {
+        _ if root.is_empty() => true,
+        Some(path) => replace_path_prefix(path, root, "").is_some(),
+        None => false,
+    }
+}
+
+/// The state of a delivery after its `attempts`-th attempt failed and when it is retried, the
+/// delay doubles with every attempt up to `max_retry_delay`.
+pub fn retry_at(attempts: i32, config: &WebhookConfig) -> (MessageState, Option<NaiveDateTime>) {
+    if attempts >= config.max_attempts as i32 {
+        return (MessageState::Dead, None);
+    }
+    let exp = attempts.clamp(1, 16) as u32 - 1;
+    let delay = config
+        .retry_delay
+        .saturating_mul(1 << exp)
+        .min(config.max_retry_delay);
+    let next_attempt_at = chrono::Utc::now().naive_utc() + chrono::Duration::seconds(delay as i64);
+    (MessageState::Failed, Some(next_attempt_at))
+}
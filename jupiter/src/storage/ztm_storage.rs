@@ -1,9 +1,11 @@
 use std::sync::Arc;
 
 use callisto::{
-    ztm_lfs_info, ztm_node, ztm_nostr_event, ztm_nostr_req, ztm_path_mapping, ztm_repo_info,
+    ztm_lfs_info, ztm_node, ztm_nostr_event, ztm_nostr_req, ztm_path_mapping, ztm_peer_principal,
+    ztm_repo_info, ztm_subscription,
 };
 use common::errors::MegaError;
+use common::utils::generate_id;
 use sea_orm::InsertResult;
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter, Set};
 
@@ -293,6 +295,12 @@ impl ZTMStorage {
         Ok(())
     }
 
+    pub async fn get_all_alias_mapping(&self) -> Result<Vec<ztm_path_mapping::Model>, MegaError> {
+        Ok(ztm_path_mapping::Entity::find()
+            .all(self.get_connection())
+            .await?)
+    }
+
     pub async fn get_path_from_alias(
         &self,
         alias: &str,
@@ -303,4 +311,108 @@ impl ZTMStorage {
             .await
             .unwrap())
     }
+
+    /// Subscribe to the repository of the subscription, fails if it is subscribed to already.
+    pub async fn save_subscription(&self, model: ztm_subscription::Model) -> Result<(), MegaError> {
+        ztm_subscription::Entity::insert(model.into_active_model())
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_all_subscriptions(&self) -> Result<Vec<ztm_subscription::Model>, MegaError> {
+        Ok(ztm_subscription::Entity::find()
+            .all(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_subscription(
+        &self,
+        identifier: &str,
+    ) -> Result<Option<ztm_subscription::Model>, MegaError> {
+        Ok(ztm_subscription::Entity::find()
+            .filter(ztm_subscription::Column::Identifier.eq(identifier))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    /// Record the result of a sync of the subscription, the announcement it was synced to if it
+    /// succeeded and the error otherwise.
+    pub async fn update_subscription_sync(
+        &self,
+        id: i64,
+        res: Result<String, String>,
+    ) -> Result<(), MegaError> {
+        let mut model = ztm_subscription::ActiveModel {
+            id: Set(id),
+            ..Default::default()
+        };
+        match res {
+            Ok(announcement) => {
+                model.announcement = Set(Some(announcement));
+                model.synced_at = Set(Some(chrono::Utc::now().naive_utc()));
+                model.last_error = Set(None);
+            }
+            Err(err) => model.last_error = Set(Some(err)),
+        }
+        ztm_subscription::Entity::update(model)
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    /// Returns `false` if the repository was not subscribed to.
+    pub async fn delete_subscription(&self, identifier: &str) -> Result<bool, MegaError> {
+        let res = ztm_subscription::Entity::delete_many()
+            .filter(ztm_subscription::Column::Identifier.eq(identifier))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
+
+    /// Authorize the peer as the principal, replacing the one it was mapped to before.
+    pub async fn save_peer_principal(
+        &self,
+        peer_id: &str,
+        principal: &str,
+    ) -> Result<(), MegaError> {
+        self.delete_peer_principal(peer_id).await?;
+        let model = ztm_peer_principal::Model {
+            id: generate_id(),
+            peer_id: peer_id.to_owned(),
+            principal: principal.to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        ztm_peer_principal::Entity::insert(model.into_active_model())
+            .exec(self.get_connection())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_peer_principal(
+        &self,
+        peer_id: &str,
+    ) -> Result<Option<ztm_peer_principal::Model>, MegaError> {
+        Ok(ztm_peer_principal::Entity::find()
+            .filter(ztm_peer_principal::Column::PeerId.eq(peer_id))
+            .one(self.get_connection())
+            .await?)
+    }
+
+    pub async fn get_all_peer_principals(
+        &self,
+    ) -> Result<Vec<ztm_peer_principal::Model>, MegaError> {
+        Ok(ztm_peer_principal::Entity::find()
+            .all(self.get_connection())
+            .await?)
+    }
+
+    /// Returns `false` if the peer was not mapped to a principal.
+    pub async fn delete_peer_principal(&self, peer_id: &str) -> Result<bool, MegaError> {
+        let res = ztm_peer_principal::Entity::delete_many()
+            .filter(ztm_peer_principal::Column::PeerId.eq(peer_id))
+            .exec(self.get_connection())
+            .await?;
+        Ok(res.rows_affected > 0)
+    }
 }
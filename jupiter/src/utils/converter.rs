@@ -61,7 +61,7 @@ pub struct MegaModelConverter {
     pub blob_maps: HashMap<SHA1, Blob>,
     pub mega_trees: RefCell<HashMap<SHA1, mega_tree::ActiveModel>>,
     pub mega_blobs: RefCell<HashMap<SHA1, mega_blob::ActiveModel>>,
-    pub raw_blobs: RefCell<HashMap<SHA1, raw_blob::ActiveModel>>,
+    pub raw_blobs: RefCell<HashMap<SHA1, raw_blob::Model>>,
     pub refs: mega_refs::ActiveModel,
 }
 
@@ -94,7 +94,7 @@ impl MegaModelConverter {
                     .borrow_mut()
                     .insert(blob.id, mega_blob.clone().into());
                 let raw_blob: raw_blob::Model = blob.to_owned().into();
-                self.raw_blobs.borrow_mut().insert(blob.id, raw_blob.into());
+                self.raw_blobs.borrow_mut().insert(blob.id, raw_blob);
             }
         }
     }
@@ -0,0 +1,153 @@
+//! Worker pool running the jobs of the background job queue.
+//!
+//! Long running work like imports, garbage collection, mirroring and indexing is queued with
+//! [`JobStorage::enqueue`](crate::storage::job_storage::JobStorage::enqueue) as a job of some
+//! type, and run by the [`JobHandler`] registered for that type. Every service instance runs a
+//! [`WorkerPool`] claiming due jobs from the shared table, so a job runs on one instance only.
+//! A job whose worker stopped is run again once its lease is over, failed jobs are retried
+//! with a growing delay until they failed too often.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use callisto::background_job;
+use callisto::db_enums::BackgroundJobState;
+use common::errors::MegaError;
+
+use crate::context::Context;
+
+/// Runs the jobs of one type.
+#[async_trait]
+pub trait JobHandler: Send + Sync {
+    /// Run the job with `payload` as it was queued, an error fails the attempt.
+    ///
+    /// A job may run more than once, e.g. when the instance stopped after it finished but
+    /// before that was recorded, so running it again must not do any harm.
+    async fn run(&self, context: &Context, payload: &str) -> Result<(), MegaError>;
+}
+
+pub struct WorkerPool {
+    context: Context,
+    handlers: HashMap<String, Arc<dyn JobHandler>>,
+}
+
+impl WorkerPool {
+    pub fn new(context: Context) -> Self {
+        WorkerPool {
+            context,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Run the jobs of `job_type` with `handler`.
+    pub fn register(mut self, job_type: &str, handler: impl JobHandler + 'static) -> Self {
+        self.handlers.insert(job_type.to_owned(), Arc::new(handler));
+        self
+    }
+
+    /// Run queued jobs until the service stops, at most `jobs.workers` at the same time.
+    pub async fn start(self) {
+        let config = self.context.config.jobs.clone();
+        let worker = format!("worker-{}", Uuid::new_v4());
+        let slots = Arc::new(Semaphore::new(config.workers.max(1)));
+        let poll_interval = Duration::from_secs(config.poll_interval.max(1));
+        let pool = Arc::new(self);
+        tracing::info!("background job worker {} started", worker);
+        loop {
+            // the semaphore is never closed
+            let slot = slots.clone().acquire_owned().await.unwrap();
+            let storage = &pool.context.services.job_storage;
+            let job = match storage.claim_jobs(&worker, 1, config.lease_timeout).await {
+                Ok(mut jobs) => jobs.pop(),
+                Err(err) => {
+                    tracing::error!("failed to claim background jobs: {}", err);
+                    None
+                }
+            };
+            let Some(job) = job else {
+                drop(slot);
+                tokio::time::sleep(poll_interval).await;
+                continue;
+            };
+            let pool = pool.clone();
+            let worker = worker.clone();
+            tokio::spawn(async move {
+                pool.run_job(&worker, job).await;
+                drop(slot);
+            });
+        }
+    }
+
+    async fn run_job(&self, worker: &str, job: background_job::Model) {
+        let storage = &self.context.services.job_storage;
+        let config = &self.context.config.jobs;
+        tracing::info!(
+            "background job {} of type {} started, attempt {}",
+            job.id,
+            job.job_type,
+            job.attempts
+        );
+        let result = match self.handlers.get(&job.job_type) {
+            Some(handler) => {
+                let run = handler.run(&self.context, &job.payload);
+                tokio::pin!(run);
+                // the lease is extended long before it is over
+                let mut heartbeat =
+                    tokio::time::interval(Duration::from_secs((config.lease_timeout / 3).max(1)));
+                heartbeat.tick().await;
+                loop {
+                    tokio::select! {
+                        res = &mut run => break res,
+                        _ = heartbeat.tick() => {
+                            match storage.extend_lease(job.id, worker, config.lease_timeout).await {
+                                Ok(true) => (),
+                                Ok(false) => tracing::warn!(
+                                    "background job {} was taken over by another worker",
+                                    job.id
+                                ),
+                                Err(err) => tracing::error!(
+                                    "failed to extend the lease of background job {}: {}",
+                                    job.id,
+                                    err
+                                ),
+                            }
+                        }
+                    }
+                }
+            }
+            None => Err(MegaError::with_message(&format!(
+                "no handler for background jobs of type {}",
+                job.job_type
+            ))),
+        };
+        let recorded = match result {
+            Ok(()) => {
+                tracing::info!("background job {} done", job.id);
+                storage.complete_job(job.id, worker).await
+            }
+            Err(err) => {
+                let error = err.to_string();
+                storage
+                    .fail_job(job.id, worker, job.attempts, &error, config)
+                    .await
+                    .map(|state| match state {
+                        BackgroundJobState::Failed => tracing::error!(
+                            "background job {} failed {} times, giving up: {}",
+                            job.id,
+                            job.attempts,
+                            error
+                        ),
+                        _ => tracing::warn!("background job {} failed: {}", job.id, error),
+                    })
+            }
+        };
+        if let Err(err) = recorded {
+            tracing::error!("failed to record background job {}: {}", job.id, err);
+        }
+    }
+}
@@ -0,0 +1,1864 @@
+//! Storage tests run against every supported database.
+//!
+//! sqlite is always tested, postgres and mysql only when a url of a scratch database is given
+//! in `MEGA_TEST_POSTGRES_URL` or `MEGA_TEST_MYSQL_URL`. All tables of these databases are
+//! dropped and created again.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter};
+use sea_orm_migration::MigratorTrait;
+use tempfile::TempDir;
+use tokio::sync::Mutex;
+
+use callisto::db_enums::{
+    BackgroundJobState, ChangeType, ConvType, MergeStatus, Mergeability, MessageLane, MessageState,
+    RepoRole, RoleSubject, SignatureStatus, SigningKeyType, StorageType,
+};
+use callisto::{
+    auth_decision, git_blob, git_commit, git_repo, mega_commit, mega_mr, mega_tag, mega_tree,
+    mq_storage, object_signature, raw_blob, raw_blob_chunk, scheduled_message, webhook_delivery,
+    ztm_subscription,
+};
+use common::config::{
+    DbConfig, EncryptionConfig, FeatureFlag, JobConfig, MqConfig, RawStorageType, StorageConfig,
+    WebhookConfig,
+};
+use common::errors::MegaError;
+use common::model::Pagination;
+use common::utils::generate_id;
+use jupiter::cache::CacheBackend;
+use jupiter::migration::Migrator;
+use jupiter::storage::feature_storage::FeatureStorage;
+use jupiter::storage::git_db_storage::GitDbStorage;
+use jupiter::storage::health::{self, DbStatus};
+use jupiter::storage::init::connect;
+use jupiter::storage::issue_storage::IssueStorage;
+use jupiter::storage::job_storage::JobStorage;
+use jupiter::storage::maintenance_storage::MaintenanceStorage;
+use jupiter::storage::mono_storage::{MonoStorage, PathChange};
+use jupiter::storage::mq_storage::MQStorage;
+use jupiter::storage::mr_storage::MrStorage;
+use jupiter::storage::outbox_storage::OutboxStorage;
+use jupiter::storage::policy_storage::{AuthDecisionFilter, PolicyStorage};
+use jupiter::storage::quota_storage::QuotaStorage;
+use jupiter::storage::raw_db_storage::RawDbStorage;
+use jupiter::storage::schedule_storage::ScheduleStorage;
+use jupiter::storage::signature_storage::SignatureStorage;
+use jupiter::storage::stats_storage::{Counter, StatsStorage};
+use jupiter::storage::user_storage::UserStorage;
+use jupiter::storage::webhook_storage::{DeliveryResponse, WebhookStorage};
+use jupiter::storage::ztm_storage::ZTMStorage;
+use jupiter::storage::{batch_save_model, TenantScope, TreeItemRange};
+use mercury::hash::SHA1;
+use mercury::internal::object::tree::{Tree, TreeItem, TreeItemMode};
+use saturn::break_glass::BREAK_GLASS_NAMESPACE;
+use saturn::role::ROLE_NAMESPACE;
+
+/// The tests take turns, all of them reset the same scratch databases.
+static DATABASES: Mutex<()> = Mutex::const_new(());
+
+fn db_configs(dir: &TempDir) -> Vec<DbConfig> {
+    let mut configs = vec![DbConfig {
+        db_type: "sqlite".to_owned(),
+        db_path: dir.path().join("mega.db").to_str().unwrap().to_owned(),
+        max_connection: 4,
+        min_connection: 1,
+        ..Default::default()
+    }];
+    for (db_type, var) in [
+        ("postgres", "MEGA_TEST_POSTGRES_URL"),
+        ("mysql", "MEGA_TEST_MYSQL_URL"),
+    ] {
+        if let Ok(db_url) = std::env::var(var) {
+            configs.push(DbConfig {
+                db_type: db_type.to_owned(),
+                db_url,
+                max_connection: 4,
+                min_connection: 1,
+                ..Default::default()
+            });
+        }
+    }
+    configs
+}
+
+async fn fresh_connection(config: &DbConfig) -> Arc<DatabaseConnection> {
+    let conn = connect(config).await;
+    Migrator::reset(&conn).await.unwrap();
+    Migrator::up(&conn, None).await.unwrap();
+    assert!(Migrator::get_pending_migrations(&conn)
+        .await
+        .unwrap()
+        .is_empty());
+    Arc::new(conn)
+}
+
+fn git_repo(path: &str) -> git_repo::Model {
+    git_repo::Model {
+        id: generate_id(),
+        repo_path: path.to_owned(),
+        repo_name: path.rsplit('/').next().unwrap().to_owned(),
+        created_at: chrono::Utc::now().naive_utc(),
+        updated_at: chrono::Utc::now().naive_utc(),
+        deleted_at: None,
+        deleted_by: None,
+    }
+}
+
+fn raw_blob(sha1: &str) -> raw_blob::Model {
+    raw_blob::Model {
+        id: generate_id(),
+        sha1: sha1.to_owned(),
+        content: None,
+        file_type: None,
+        storage_type: StorageType::Database,
+        data: Some(b"content".to_vec()),
+        local_path: None,
+        remote_url: None,
+        created_at: chrono::Utc::now().naive_utc(),
+        ref_count: 0,
+    }
+}
+
+fn modified(path: &str) -> PathChange {
+    PathChange {
+        path: path.to_owned(),
+        change_type: ChangeType::Modified,
+        old_id: Some("old".to_owned()),
+        new_id: Some("new".to_owned()),
+    }
+}
+
+/// Content without repetitions, as chunk boundaries depend on it.
+fn random_content(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+async fn read_content(raw_storage: &RawDbStorage, hash: &str) -> Vec<u8> {
+    raw_storage
+        .get_raw_blob_content(hash)
+        .await
+        .unwrap()
+        .unwrap()
+        .try_fold(Vec::new(), |mut buf, chunk| async move {
+            buf.extend_from_slice(&chunk);
+            Ok(buf)
+        })
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_health_and_reset() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        let health = health::check(&conn);
+        assert_eq!(health.status, DbStatus::Ok);
+        assert_eq!(health.max_connections, config.max_connection);
+        assert!(health.connections >= 1 && health.idle <= health.connections);
+
+        Migrator::reset(conn.as_ref()).await.unwrap();
+        assert!(!Migrator::get_pending_migrations(conn.as_ref())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_git_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        let database = StorageConfig {
+            raw_obj_local_path: dir.path().join("objects"),
+            ..Default::default()
+        };
+        let raw_storage = RawDbStorage::new(conn.clone(), &database).await;
+        let cache = CacheBackend::default();
+        let git_storage = GitDbStorage::new(conn.clone(), raw_storage.clone(), &cache).await;
+
+        let repo = git_repo("/third-part/crates/mega");
+        git_storage.save_git_repo(repo.clone()).await.unwrap();
+        git_storage
+            .save_git_repo(git_repo("/third-part/crates"))
+            .await
+            .unwrap();
+        let found = git_storage
+            .find_git_repo_like_path("/third-part/crates/mega/src")
+            .await
+            .unwrap();
+        assert_eq!(found.map(|r| r.id), Some(repo.id));
+        let found = git_storage
+            .find_git_repo_like_path("/third-part/other")
+            .await
+            .unwrap();
+        assert!(found.is_none());
+        // quotes in paths are values, not sql
+        let found = git_storage
+            .find_git_repo_like_path("/third-part/it's")
+            .await
+            .unwrap();
+        assert!(found.is_none());
+
+        // conflicting rows are skipped
+        batch_save_model(
+            conn.as_ref(),
+            vec![
+                git_repo::ActiveModel::from(repo.clone()),
+                git_repo::ActiveModel::from(git_repo("/third-part/crates/jupiter")),
+            ],
+        )
+        .await
+        .unwrap();
+        let count = git_repo::Entity::find().count(conn.as_ref()).await.unwrap();
+        assert_eq!(count, 3);
+
+        // deleted repos are hidden until they are restored, and gone once purged
+        git_storage
+            .delete_git_repo(repo.clone(), "admin")
+            .await
+            .unwrap();
+        let found = git_storage
+            .find_git_repo_like_path("/third-part/crates/mega/src")
+            .await
+            .unwrap();
+        assert_eq!(
+            found.map(|r| r.repo_path).as_deref(),
+            Some("/third-part/crates")
+        );
+        let deleted = git_storage.list_deleted_git_repos().await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].deleted_by.as_deref(), Some("admin"));
+        git_storage
+            .restore_git_repo(deleted[0].clone())
+            .await
+            .unwrap();
+        assert!(git_storage
+            .find_git_repo_exact_match(&repo.repo_path)
+            .await
+            .unwrap()
+            .is_some());
+        let jupiter = git_storage
+            .find_git_repo_exact_match("/third-part/crates/jupiter")
+            .await
+            .unwrap()
+            .unwrap();
+        git_storage.delete_git_repo(jupiter, "admin").await.unwrap();
+        let tomorrow = chrono::Utc::now().naive_utc() + chrono::Duration::days(1);
+        let expired = git_storage
+            .get_git_repos_deleted_before(tomorrow)
+            .await
+            .unwrap();
+        assert_eq!(expired.len(), 1);
+        git_storage.purge_git_repo(expired[0].id).await.unwrap();
+        assert!(git_storage
+            .find_deleted_git_repo("/third-part/crates/jupiter")
+            .await
+            .unwrap()
+            .is_none());
+        let count = git_repo::Entity::find().count(conn.as_ref()).await.unwrap();
+        assert_eq!(count, 2);
+
+        // unreachable objects are deleted with the content only they refer to
+        let raw_storage = RawDbStorage::new(conn.clone(), &database).await;
+        let hash = "89abcdef0123456789abcdef0123456789abcdef".to_owned();
+        raw_storage
+            .save_raw_blobs(conn.as_ref(), vec![raw_blob(&hash)])
+            .await
+            .unwrap();
+        let blob = git_blob::Model {
+            id: generate_id(),
+            repo_id: repo.id,
+            blob_id: hash.clone(),
+            name: None,
+            size: 7,
+            commit_id: String::new(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        git_storage
+            .save_objects(conn.as_ref(), repo.id, vec![], vec![], vec![blob])
+            .await
+            .unwrap();
+        let ids = git_storage
+            .get_object_ids_before(repo.id, tomorrow)
+            .await
+            .unwrap();
+        assert_eq!(ids.blobs, vec![hash.clone()]);
+        git_storage.delete_objects(repo.id, &ids).await.unwrap();
+        let ids = git_storage
+            .get_object_ids_before(repo.id, tomorrow)
+            .await
+            .unwrap();
+        assert!(ids.is_empty());
+        assert!(raw_storage
+            .get_raw_blob_by_hash(&hash)
+            .await
+            .unwrap()
+            .is_none());
+
+        // repositories are found through the commits they contain
+        let commit = git_commit::Model {
+            id: generate_id(),
+            repo_id: repo.id,
+            commit_id: hash.clone(),
+            tree: String::new(),
+            parents_id: serde_json::json!([]),
+            author: None,
+            committer: None,
+            content: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        git_storage
+            .save_objects(conn.as_ref(), repo.id, vec![commit], vec![], vec![])
+            .await
+            .unwrap();
+        let repos = git_storage.find_git_repos_by_commit(&hash).await.unwrap();
+        assert_eq!(
+            repos.into_iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![repo.id]
+        );
+        assert!(git_storage
+            .find_git_repos_by_commit("missing")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_mr_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // a merge request is loaded together with its conversations
+        let mr_storage = MrStorage::new(conn.clone()).await;
+        let mr = mega_mr::Model {
+            id: generate_id(),
+            link: "mr-link".to_owned(),
+            title: "title".to_owned(),
+            merge_date: None,
+            status: MergeStatus::Open,
+            path: "/project".to_owned(),
+            from_hash: "from".to_owned(),
+            to_hash: "to".to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+            updated_at: chrono::Utc::now().naive_utc(),
+        };
+        mr_storage.save_mr(mr).await.unwrap();
+        for comment in ["first", "second"] {
+            mr_storage
+                .add_mr_conversation("mr-link", 0, ConvType::Comment, Some(comment.to_owned()))
+                .await
+                .unwrap();
+        }
+        let (mr, conversations) = mr_storage
+            .get_mr_with_conversations("mr-link")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(mr.link, "mr-link");
+        assert_eq!(conversations.len(), 2);
+        assert!(mr_storage
+            .get_mr_with_conversations("missing")
+            .await
+            .unwrap()
+            .is_none());
+
+        // a new check replaces the stored mergeability
+        assert_eq!(mr_storage.get_open_mrs().await.unwrap().len(), 1);
+        let conflicts = vec!["/project/a.txt".to_owned()];
+        mr_storage
+            .save_mergeability(
+                "mr-link",
+                Mergeability::Conflicting,
+                &conflicts,
+                "to",
+                "root",
+            )
+            .await
+            .unwrap();
+        mr_storage
+            .save_mergeability("mr-link", Mergeability::Mergeable, &[], "to", "root2")
+            .await
+            .unwrap();
+        let mergeability = mr_storage
+            .get_mergeability("mr-link")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(mergeability.status, Mergeability::Mergeable);
+        assert_eq!(mergeability.conflict_paths, "[]");
+        assert_eq!(mergeability.target_hash, "root2");
+
+        // lists are limited to the directory of a tenant, or to the directories of no tenant
+        let count = |scope: TenantScope| {
+            let mr_storage = mr_storage.clone();
+            async move {
+                mr_storage
+                    .get_mr_by_status(vec![MergeStatus::Open], &scope, 1, 10)
+                    .await
+                    .unwrap()
+                    .1
+            }
+        };
+        let tenant = |path: &str| TenantScope::Tenant {
+            name: "acme".to_owned(),
+            path: path.to_owned(),
+        };
+        let instance = |path: &str| TenantScope::Instance {
+            paths: vec![path.to_owned()],
+        };
+        assert_eq!(count(TenantScope::All).await, 1);
+        assert_eq!(count(tenant("/project")).await, 1);
+        assert_eq!(count(tenant("/proj")).await, 0);
+        assert_eq!(count(instance("/project")).await, 0);
+        assert_eq!(count(instance("/proj")).await, 1);
+        assert_eq!(count(TenantScope::Nothing).await, 0);
+    }
+}
+
+#[tokio::test]
+async fn test_quota_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // usage adds up per repository and below directories
+        let quota_storage = QuotaStorage::new(conn.clone()).await;
+        quota_storage.add_usage("/project/a", 100, 0).await.unwrap();
+        quota_storage.add_usage("/project/a", 50, 10).await.unwrap();
+        quota_storage.add_usage("/project/ab", 7, 0).await.unwrap();
+        let usage = quota_storage.get_usage_below("/project/a").await.unwrap();
+        assert_eq!(usage, (150, 10));
+        quota_storage
+            .rename_paths(conn.as_ref(), "/project", "/projects")
+            .await
+            .unwrap();
+        let usage = quota_storage.get_usage_below("/projects").await.unwrap();
+        assert_eq!(usage, (157, 10));
+        quota_storage.delete_usage("/projects/ab").await.unwrap();
+        assert_eq!(quota_storage.list_usage("/").await.unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_feature_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // saving a flag again replaces it
+        let feature_storage = FeatureStorage::new(conn.clone()).await;
+        let mut flag = FeatureFlag {
+            name: "p2p_sync".to_owned(),
+            users: vec!["alice".to_owned()],
+            percentage: 10,
+            ..Default::default()
+        };
+        feature_storage.save_flag(&flag, "admin").await.unwrap();
+        flag.paths = vec!["/project".to_owned()];
+        feature_storage.save_flag(&flag, "root").await.unwrap();
+        let flags = feature_storage.list_flags().await.unwrap();
+        assert_eq!(flags.len(), 1);
+        assert_eq!(flags[0].paths, r#"["/project"]"#);
+        assert_eq!(flags[0].updated_by, "root");
+        assert!(feature_storage.delete_flag("p2p_sync").await.unwrap());
+        assert!(feature_storage
+            .find_flag("p2p_sync")
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_maintenance_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // enabling the maintenance mode again replaces its message
+        let maintenance_storage = MaintenanceStorage::new(conn.clone()).await;
+        assert!(maintenance_storage.get_mode().await.unwrap().is_none());
+        maintenance_storage
+            .enable_mode(None, "admin")
+            .await
+            .unwrap();
+        maintenance_storage
+            .enable_mode(Some("backup running".to_owned()), "root")
+            .await
+            .unwrap();
+        let mode = maintenance_storage.get_mode().await.unwrap().unwrap();
+        assert_eq!(mode.message.as_deref(), Some("backup running"));
+        assert_eq!(mode.enabled_by, "root");
+        assert!(maintenance_storage.disable_mode().await.unwrap());
+        assert!(!maintenance_storage.disable_mode().await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_stats_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // counters of a day add up, a user is active once a day
+        let stats_storage = StatsStorage::new(conn.clone()).await;
+        let day = NaiveDate::from_ymd_opt(2026, 10, 15).unwrap();
+        let next_day = day.succ_opt().unwrap();
+        for counter in [Counter::Push, Counter::Push, Counter::MrOpened] {
+            stats_storage.count(day, counter).await.unwrap();
+        }
+        stats_storage
+            .count(next_day, Counter::MrMerged)
+            .await
+            .unwrap();
+        let days = stats_storage.list_days(day).await.unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(
+            (days[0].pushes, days[0].mrs_opened, days[0].mrs_merged),
+            (2, 1, 0)
+        );
+        assert_eq!((days[1].day, days[1].mrs_merged), (next_day, 1));
+        assert!(stats_storage.record_activity(day, "alice").await.unwrap());
+        assert!(!stats_storage.record_activity(day, "alice").await.unwrap());
+        stats_storage.record_activity(day, "bob").await.unwrap();
+        stats_storage
+            .record_activity(next_day, "alice")
+            .await
+            .unwrap();
+        assert_eq!(
+            stats_storage.active_users_by_day(day).await.unwrap(),
+            vec![(day, 2), (next_day, 1)]
+        );
+        assert_eq!(stats_storage.count_active_users(day).await.unwrap(), 2);
+        assert_eq!(stats_storage.prune_activity(next_day).await.unwrap(), 2);
+        assert_eq!(stats_storage.count_active_users(day).await.unwrap(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_mono_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        let database = StorageConfig {
+            raw_obj_local_path: dir.path().join("objects"),
+            ..Default::default()
+        };
+        let raw_storage = RawDbStorage::new(conn.clone(), &database).await;
+        let cache = CacheBackend::default();
+        let mono_storage = MonoStorage::new(conn.clone(), raw_storage, &cache).await;
+
+        // history of a path is read from the recorded changes, latest first
+        let commits: Vec<mega_commit::ActiveModel> = ["c1", "c2", "c3"]
+            .into_iter()
+            .map(|id| {
+                mega_commit::Model {
+                    id: generate_id(),
+                    commit_id: id.to_owned(),
+                    tree: "tree".to_owned(),
+                    parents_id: serde_json::json!([]),
+                    author: None,
+                    committer: None,
+                    content: None,
+                    created_at: chrono::Utc::now().naive_utc(),
+                }
+                .into()
+            })
+            .collect();
+        batch_save_model(conn.as_ref(), commits).await.unwrap();
+        let changes = [
+            ("c1", 1, vec!["/", "/project", "/project/a"]),
+            ("c2", 2, vec!["/", "/doc"]),
+            ("c3", 3, vec!["/", "/project", "/project/a", "/project/a/b"]),
+        ];
+        for (id, time, paths) in changes {
+            mono_storage
+                .save_commit_paths(
+                    "/",
+                    "main",
+                    id,
+                    chrono::DateTime::from_timestamp(time, 0)
+                        .unwrap()
+                        .naive_utc(),
+                    paths.into_iter().map(modified).collect(),
+                )
+                .await
+                .unwrap();
+        }
+        let page = |page, per_page| Pagination { page, per_page };
+        let history = |path: &'static str, p| {
+            let storage = &mono_storage;
+            async move {
+                let (commits, total) = storage
+                    .get_path_history("/", "main", path, p)
+                    .await
+                    .unwrap();
+                let ids: Vec<String> = commits.into_iter().map(|c| c.commit_id).collect();
+                (ids, total)
+            }
+        };
+        assert_eq!(
+            history("/project/a", page(1, 20)).await,
+            (vec!["c3".to_owned(), "c1".to_owned()], 2)
+        );
+        assert_eq!(history("/", page(2, 2)).await, (vec!["c1".to_owned()], 3));
+        assert_eq!(history("/project/b", page(1, 20)).await, (vec![], 0));
+        let last = mono_storage
+            .get_last_commits(
+                "/",
+                "main",
+                vec![
+                    "/project".to_owned(),
+                    "/doc".to_owned(),
+                    "/other".to_owned(),
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(last.len(), 2);
+        assert_eq!(last["/project"], "c3");
+        assert_eq!(last["/doc"], "c2");
+        let changes = mono_storage
+            .get_path_changes("/", "main", "/project/a")
+            .await
+            .unwrap();
+        let changes: Vec<_> = changes
+            .into_iter()
+            .map(|c| (c.commit_id, c.change_type, c.new_id))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "c3".to_owned(),
+                    ChangeType::Modified,
+                    Some("new".to_owned())
+                ),
+                (
+                    "c1".to_owned(),
+                    ChangeType::Modified,
+                    Some("new".to_owned())
+                ),
+            ]
+        );
+        // recording a commit again replaces its changes
+        mono_storage
+            .save_commit_paths(
+                "/",
+                "main",
+                "c1",
+                chrono::DateTime::from_timestamp(1, 0).unwrap().naive_utc(),
+                vec![modified("/"), modified("/project")],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            history("/project/a", page(1, 20)).await,
+            (vec!["c3".to_owned()], 1)
+        );
+        mono_storage
+            .rename_paths(conn.as_ref(), "/project", "/projects")
+            .await
+            .unwrap();
+        assert_eq!(
+            history("/projects/a", page(1, 20)).await,
+            (vec!["c3".to_owned()], 1)
+        );
+
+        mono_storage
+            .save_ref(conn.as_ref(), "/project", None, "commit", "tree")
+            .await
+            .unwrap();
+        let refs = mono_storage.get_refs("/project").await.unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].ref_commit_hash, "commit");
+
+        // writes of a failed transaction are rolled back together
+        let storage = &mono_storage;
+        let res = storage
+            .transaction(|txn| async move {
+                let mut head = refs[0].clone();
+                head.ref_commit_hash = "other".to_owned();
+                storage.update_ref(&*txn, head, "commit").await?;
+                storage
+                    .save_ref(&*txn, "/other", None, "commit", "tree")
+                    .await?;
+                Err::<(), _>(MegaError::with_message("push rejected"))
+            })
+            .await;
+        assert!(res.is_err());
+        let refs = mono_storage.get_refs("/project").await.unwrap();
+        assert_eq!(refs[0].ref_commit_hash, "commit");
+        assert!(mono_storage.get_refs("/other").await.unwrap().is_empty());
+        storage
+            .transaction(|txn| async move {
+                storage
+                    .save_ref(&*txn, "/other", None, "commit", "tree")
+                    .await
+            })
+            .await
+            .unwrap();
+        assert_eq!(mono_storage.get_refs("/other").await.unwrap().len(), 1);
+
+        // a ref changed since it was read is not overwritten
+        let mut head = mono_storage.get_refs("/other").await.unwrap().remove(0);
+        head.ref_commit_hash = "second".to_owned();
+        mono_storage
+            .update_ref(conn.as_ref(), head.clone(), "commit")
+            .await
+            .unwrap();
+        head.ref_commit_hash = "third".to_owned();
+        let err = mono_storage
+            .update_ref(conn.as_ref(), head, "commit")
+            .await
+            .unwrap_err();
+        assert!(err.is_ref_conflict());
+        assert_eq!(err.to_string(), "fetch first");
+        let refs = mono_storage.get_refs("/other").await.unwrap();
+        assert_eq!(refs[0].ref_commit_hash, "second");
+
+        // tags are kept when the branches of a path are removed
+        let tag = mega_tag::Model {
+            id: generate_id(),
+            tag_id: "tag".to_owned(),
+            object_id: "second".to_owned(),
+            object_type: "commit".to_owned(),
+            tag_name: "v1".to_owned(),
+            tagger: "tagger <tagger@example.com> 0 +0000".to_owned(),
+            message: "\nfirst release\n".to_owned(),
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        for _ in 0..2 {
+            mono_storage
+                .save_tag(conn.as_ref(), tag.clone())
+                .await
+                .unwrap();
+        }
+        mono_storage
+            .save_ref(
+                conn.as_ref(),
+                "/other",
+                Some("refs/tags/v1".to_owned()),
+                "tag",
+                "tree",
+            )
+            .await
+            .unwrap();
+        let tags = mono_storage.get_tag_refs("/other").await.unwrap();
+        assert_eq!(tags.len(), 1);
+        let peeled = mono_storage
+            .get_tags_by_hashes(vec![tags[0].ref_commit_hash.clone()])
+            .await
+            .unwrap();
+        assert_eq!(peeled.len(), 1);
+        assert_eq!(peeled[0].object_id, "second");
+        mono_storage.remove_refs("/other").await.unwrap();
+        let refs = mono_storage.get_refs("/other").await.unwrap();
+        assert_eq!(refs.len(), 1);
+        assert_eq!(refs[0].ref_name, "refs/tags/v1");
+
+        // entries of a tree are listed by name, filtered by prefix and in pages
+        let items = ["src", "node_modules", "nm-2", "README", "nm-1"]
+            .into_iter()
+            .map(|name| {
+                let id = SHA1::from_str("bd4a28f2d8b2efc371f557c3b80d320466ed83f3").unwrap();
+                TreeItem::new(TreeItemMode::Blob, id, name.to_owned())
+            })
+            .collect();
+        let tree = Tree::from_tree_items(items).unwrap();
+        let tree_id = tree.id.to_string();
+        let model: mega_tree::ActiveModel = mega_tree::Model::from(tree).into();
+        batch_save_model(conn.as_ref(), vec![model]).await.unwrap();
+        let names = |items: Vec<TreeItem>| items.into_iter().map(|i| i.name).collect::<Vec<_>>();
+        let (all, total) = mono_storage
+            .get_tree_items(&tree_id, &TreeItemRange::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(total, 5);
+        assert_eq!(
+            names(all),
+            ["README", "nm-1", "nm-2", "node_modules", "src"]
+        );
+        let (page, total) = mono_storage
+            .get_tree_items(&tree_id, &TreeItemRange::page("n", 2, 2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(names(page), ["node_modules"]);
+        let (page, _) = mono_storage
+            .get_tree_items(&tree_id, &TreeItemRange::page("n", 3, 2))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(page.is_empty());
+        assert!(mono_storage
+            .get_tree_items("missing", &TreeItemRange::default())
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_mq_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // failed messages are retried until they are dead-lettered, and again once requeued
+        let mq = MQStorage::new(conn.clone()).await;
+        let mq_config = MqConfig {
+            max_attempts: 2,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        mq.save_messages(vec![mq_storage::Model {
+            id: 1,
+            category: Some("RepoEvent".to_owned()),
+            create_time: chrono::Utc::now().naive_utc(),
+            content: None,
+            state: MessageState::Pending,
+            attempts: 0,
+            visible_at: None,
+            last_error: None,
+            claimed_by: None,
+            lane: MessageLane::Interactive,
+        }])
+        .await;
+        // messages are only claimed in their lane
+        assert!(mq
+            .claim_messages("consumer-a", MessageLane::Background, 10, 300)
+            .await
+            .unwrap()
+            .is_empty());
+        for attempt in 1..=2 {
+            let claimed = mq
+                .claim_messages("consumer-a", MessageLane::Interactive, 10, 300)
+                .await
+                .unwrap();
+            assert_eq!(claimed.len(), 1);
+            assert_eq!(claimed[0].attempts, attempt);
+            // hidden while inflight
+            assert!(mq
+                .claim_messages("consumer-b", MessageLane::Interactive, 10, 300)
+                .await
+                .unwrap()
+                .is_empty());
+            let state = mq
+                .fail_message(1, "consumer-a", attempt, "broken", &mq_config)
+                .await
+                .unwrap();
+            let expected = if attempt < 2 {
+                MessageState::Failed
+            } else {
+                MessageState::Dead
+            };
+            assert_eq!(state, expected);
+        }
+        assert!(mq
+            .claim_messages("consumer-a", MessageLane::Interactive, 10, 300)
+            .await
+            .unwrap()
+            .is_empty());
+        let (dead, total) = mq
+            .list_messages(MessageState::Dead, Pagination::default())
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(dead[0].last_error.as_deref(), Some("broken"));
+        assert!(!mq.requeue_message(2).await.unwrap());
+        assert!(mq.requeue_message(1).await.unwrap());
+        assert_eq!(mq.requeue_dead_messages().await.unwrap(), 0);
+        let claimed = mq
+            .claim_messages("consumer-a", MessageLane::Interactive, 10, 300)
+            .await
+            .unwrap();
+        assert_eq!(claimed[0].attempts, 1);
+        mq.complete_message(1, "consumer-a").await.unwrap();
+        assert!(mq
+            .claim_messages("consumer-a", MessageLane::Interactive, 10, 0)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // a delivered message which was never acknowledged is delivered again
+        let now = chrono::Utc::now().naive_utc();
+        let inflight = |id, visible_at| mq_storage::Model {
+            id,
+            category: Some("RepoEvent".to_owned()),
+            create_time: now,
+            content: None,
+            state: MessageState::Inflight,
+            attempts: 1,
+            visible_at: Some(visible_at),
+            last_error: None,
+            claimed_by: Some("consumer-b".to_owned()),
+            lane: MessageLane::Interactive,
+        };
+        mq.save_message(inflight(2, now - chrono::Duration::seconds(1)))
+            .await
+            .unwrap();
+        mq.save_message(inflight(3, now + chrono::Duration::seconds(300)))
+            .await
+            .unwrap();
+        let claimed = mq
+            .claim_messages("consumer-a", MessageLane::Interactive, 10, 300)
+            .await
+            .unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!((claimed[0].id, claimed[0].attempts), (2, 2));
+        assert_eq!(claimed[0].claimed_by.as_deref(), Some("consumer-a"));
+
+        // only the consumer which holds a message extends its claim and records its outcome
+        assert!(mq.extend_claim(3, "consumer-b", 300).await.unwrap());
+        assert!(!mq.extend_claim(2, "consumer-b", 300).await.unwrap());
+        assert!(mq.extend_claim(2, "consumer-a", 300).await.unwrap());
+        mq.complete_message(2, "consumer-b").await.unwrap();
+        assert_eq!(mq.list_history(0, None, 10).await.unwrap().len(), 1);
+
+        // processed messages are replayed in order from an offset or a time
+        mq.complete_message(2, "consumer-a").await.unwrap();
+        let ids = |msgs: Vec<mq_storage::Model>| msgs.iter().map(|m| m.id).collect::<Vec<_>>();
+        assert_eq!(ids(mq.list_history(0, None, 10).await.unwrap()), vec![1, 2]);
+        assert_eq!(ids(mq.list_history(1, None, 10).await.unwrap()), vec![2]);
+        assert_eq!(ids(mq.list_history(0, None, 1).await.unwrap()), vec![1]);
+        let later = now + chrono::Duration::seconds(1);
+        assert!(mq
+            .list_history(0, Some(later), 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // the depth of the queue leaves out processed messages, ones being processed are kept
+        let depth = mq.queue_depth().await.unwrap();
+        assert_eq!(depth.len(), 1);
+        assert_eq!(
+            (depth[0].0.as_deref(), depth[0].1, depth[0].2),
+            (Some("RepoEvent"), MessageState::Inflight, 1)
+        );
+        assert!(!mq.delete_message(3).await.unwrap());
+        assert!(mq.get_message(3).await.unwrap().is_some());
+        assert!(mq.delete_message(1).await.unwrap());
+        assert!(mq.get_message(1).await.unwrap().is_none());
+        assert_eq!(
+            mq.purge_messages(MessageState::Done, Some("PushEvent"))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            mq.purge_messages(MessageState::Done, None).await.unwrap(),
+            1
+        );
+        // a batch of messages is stored at once
+        mq.insert_messages(vec![inflight(4, now), inflight(5, now)])
+            .await
+            .unwrap();
+        assert!(mq.get_message(5).await.unwrap().is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_webhook_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // webhooks get the events of their schemas and paths, failed deliveries are retried
+        // until they are dead-lettered, and again once requeued
+        let webhooks = WebhookStorage::new(conn.clone()).await;
+        let all = webhooks
+            .create_webhook("http://a.test/hook", "s1", &[], "/", "admin")
+            .await
+            .unwrap();
+        let pushes = webhooks
+            .create_webhook(
+                "http://b.test/hook",
+                "s2",
+                &["push".to_owned()],
+                "/project/",
+                "admin",
+            )
+            .await
+            .unwrap();
+        for (event, path, expected) in [
+            ("push", Some("/project/a"), vec![all.id, pushes.id]),
+            ("push", Some("/projects"), vec![all.id]),
+            ("issue", Some("/project"), vec![all.id]),
+            ("issue", None, vec![all.id]),
+        ] {
+            let subscribed = webhooks.subscribed_webhooks(event, path).await.unwrap();
+            let ids: Vec<i64> = subscribed.iter().map(|w| w.id).collect();
+            assert_eq!(ids, expected, "{} at {:?}", event, path);
+        }
+        let delivery = |id, webhook_id| webhook_delivery::Model {
+            id,
+            webhook_id,
+            event_id: 100 + id,
+            event: "push".to_owned(),
+            payload: "{}".to_owned(),
+            state: MessageState::Pending,
+            attempts: 0,
+            next_attempt_at: None,
+            response_status: None,
+            response_body: None,
+            last_error: None,
+            created_at: chrono::Utc::now().naive_utc(),
+            delivered_at: None,
+        };
+        let saved = webhooks
+            .save_deliveries(vec![delivery(1, pushes.id), delivery(2, all.id)])
+            .await
+            .unwrap();
+        assert_eq!(saved, 2);
+        // an event is delivered to a webhook once, however often it is replayed
+        let replayed = webhook_delivery::Model {
+            id: 3,
+            ..delivery(1, pushes.id)
+        };
+        assert_eq!(webhooks.save_deliveries(vec![replayed]).await.unwrap(), 0);
+        let webhook_config = WebhookConfig {
+            max_attempts: 2,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let response = DeliveryResponse {
+            status: Some(500),
+            body: Some("broken".to_owned()),
+        };
+        for attempt in 1..=2 {
+            let claimed = webhooks.claim_deliveries(10, 60).await.unwrap();
+            assert_eq!(claimed.len(), 3 - attempt as usize);
+            assert_eq!(claimed[0].attempts, attempt);
+            assert!(webhooks.claim_deliveries(10, 60).await.unwrap().is_empty());
+            if attempt == 1 {
+                webhooks
+                    .complete_delivery(2, DeliveryResponse::default())
+                    .await
+                    .unwrap();
+            }
+            let state = webhooks
+                .fail_delivery(1, attempt, response.clone(), "status 500", &webhook_config)
+                .await
+                .unwrap();
+            let expected = if attempt < 2 {
+                MessageState::Failed
+            } else {
+                MessageState::Dead
+            };
+            assert_eq!(state, expected);
+        }
+        let (dead, total) = webhooks
+            .list_deliveries(pushes.id, Some(MessageState::Dead), Pagination::default())
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(dead[0].response_status, Some(500));
+        assert_eq!(dead[0].last_error.as_deref(), Some("status 500"));
+        let (delivered, _) = webhooks
+            .list_deliveries(all.id, None, Pagination::default())
+            .await
+            .unwrap();
+        assert!(delivered[0].delivered_at.is_some());
+        assert!(!webhooks.requeue_delivery(all.id, 1).await.unwrap());
+        assert!(webhooks.requeue_delivery(pushes.id, 1).await.unwrap());
+        assert_eq!(
+            webhooks.claim_deliveries(10, 60).await.unwrap()[0].attempts,
+            1
+        );
+        assert!(webhooks.delete_webhook(pushes.id).await.unwrap());
+        assert!(webhooks
+            .list_deliveries(pushes.id, None, Pagination::default())
+            .await
+            .unwrap()
+            .0
+            .is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_outbox_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // events staged by a rolled back transaction are never published, the others are
+        // claimed by one relay until they are sent
+        let outbox = &OutboxStorage::new(conn.clone()).await;
+        let issues = &IssueStorage::new(conn.clone()).await;
+        let res = issues
+            .transaction(|txn| async move {
+                let issue = issues.save_issue(&*txn, 1, "rolled back").await?;
+                outbox
+                    .add_event(&*txn, 1, "IssueEvent", &issue.link)
+                    .await?;
+                Err::<(), _>(MegaError::with_message("rejected"))
+            })
+            .await;
+        assert!(res.is_err());
+        assert!(outbox.claim_events(10, 60).await.unwrap().is_empty());
+        issues
+            .transaction(|txn| async move {
+                let issue = issues.save_issue(&*txn, 1, "committed").await?;
+                outbox.add_event(&*txn, 2, "IssueEvent", &issue.link).await
+            })
+            .await
+            .unwrap();
+        let claimed = outbox.claim_events(10, 60).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!((claimed[0].id, claimed[0].attempts), (2, 1));
+        assert!(outbox.claim_events(10, 60).await.unwrap().is_empty());
+        assert_eq!(outbox.backlog().await.unwrap().0, 1);
+        outbox.mark_sent(2).await.unwrap();
+        assert_eq!(outbox.backlog().await.unwrap(), (0, None));
+        assert!(outbox.claim_events(10, 0).await.unwrap().is_empty());
+        let later = chrono::Utc::now().naive_utc() + chrono::Duration::seconds(1);
+        assert_eq!(outbox.purge_sent(later).await.unwrap(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_schedule_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // scheduled messages are replaced and canceled by key, and claimed once they are due
+        let schedule = &ScheduleStorage::new(conn.clone()).await;
+        let now = chrono::Utc::now().naive_utc();
+        let scheduled = |id, key: Option<&str>, secs| scheduled_message::Model {
+            id,
+            cancel_key: key.map(str::to_owned),
+            category: "IssueEvent".to_owned(),
+            content: "{}".to_owned(),
+            deliver_at: now + chrono::Duration::seconds(secs),
+            claimed_until: None,
+            created_at: now,
+        };
+        schedule
+            .schedule(scheduled(1, Some("mr-1"), 600))
+            .await
+            .unwrap();
+        schedule
+            .schedule(scheduled(2, Some("mr-1"), 10))
+            .await
+            .unwrap();
+        schedule.schedule(scheduled(3, None, 20)).await.unwrap();
+        schedule
+            .schedule(scheduled(4, Some("mr-2"), 5))
+            .await
+            .unwrap();
+        assert_eq!(
+            schedule.find_scheduled("mr-1").await.unwrap().unwrap().id,
+            2
+        );
+        assert!(schedule.cancel("mr-2").await.unwrap());
+        assert!(!schedule.cancel("mr-2").await.unwrap());
+        let before = now + chrono::Duration::seconds(30);
+        let claimed = schedule.claim_due(before, 60, 10).await.unwrap();
+        assert_eq!(claimed.iter().map(|m| m.id).collect::<Vec<_>>(), [2, 3]);
+        assert!(schedule.claim_due(before, 60, 10).await.unwrap().is_empty());
+        assert!(schedule.take(conn.as_ref(), 2).await.unwrap());
+        assert!(!schedule.take(conn.as_ref(), 2).await.unwrap());
+        assert!(!schedule.cancel("mr-1").await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_ztm_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // subscriptions to repositories of other peers record the result of their last sync
+        let ztm = ZTMStorage::new(conn.clone()).await;
+        let identifier = "p2p://peer/mega";
+        let subscription = ztm_subscription::Model {
+            id: generate_id(),
+            identifier: identifier.to_owned(),
+            local_path: "/third-part/mega".to_owned(),
+            announcement: None,
+            synced_at: None,
+            last_error: None,
+            created_at: chrono::Utc::now().naive_utc(),
+        };
+        ztm.save_subscription(subscription.clone()).await.unwrap();
+        assert!(ztm.save_subscription(subscription.clone()).await.is_err());
+        ztm.update_subscription_sync(subscription.id, Err("offline".to_owned()))
+            .await
+            .unwrap();
+        let failed = ztm.get_subscription(identifier).await.unwrap().unwrap();
+        assert_eq!(failed.last_error.as_deref(), Some("offline"));
+        assert!(failed.synced_at.is_none());
+        ztm.update_subscription_sync(subscription.id, Ok("{}".to_owned()))
+            .await
+            .unwrap();
+        let synced = ztm.get_subscription(identifier).await.unwrap().unwrap();
+        assert_eq!(synced.announcement.as_deref(), Some("{}"));
+        assert!(synced.last_error.is_none() && synced.synced_at.is_some());
+        assert_eq!(ztm.get_all_subscriptions().await.unwrap().len(), 1);
+        assert!(ztm.delete_subscription(identifier).await.unwrap());
+        assert!(!ztm.delete_subscription(identifier).await.unwrap());
+
+        // a peer is authorized as one principal at a time
+        ztm.save_peer_principal("peer", "alice").await.unwrap();
+        ztm.save_peer_principal("peer", "ci[bot]").await.unwrap();
+        let mapped = ztm.get_peer_principal("peer").await.unwrap().unwrap();
+        assert_eq!(mapped.principal, "ci[bot]");
+        assert_eq!(ztm.get_all_peer_principals().await.unwrap().len(), 1);
+        assert!(ztm.get_peer_principal("other").await.unwrap().is_none());
+        assert!(ztm.delete_peer_principal("peer").await.unwrap());
+        assert!(!ztm.delete_peer_principal("peer").await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_signature_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // cached signatures are dropped when the keys of their signer change
+        let signatures = SignatureStorage::new(conn.clone()).await;
+        let key = signatures
+            .save_signing_key(
+                1,
+                "a@mega.org",
+                SigningKeyType::Ssh,
+                "laptop",
+                "ssh-ed25519 AAAA",
+                "SHA256:a",
+            )
+            .await
+            .unwrap();
+        let signature = |object_id: &str, status, key_id| object_signature::Model {
+            id: generate_id(),
+            object_id: object_id.to_owned(),
+            status,
+            signer_email: "a@mega.org".to_owned(),
+            user_id: Some(1),
+            key_id,
+            reason: None,
+            verified_at: chrono::Utc::now().naive_utc(),
+        };
+        signatures
+            .save_signature(signature("c1", SignatureStatus::Unverified, None))
+            .await
+            .unwrap();
+        // verified again, the cached result is replaced
+        signatures
+            .save_signature(signature("c1", SignatureStatus::Verified, Some(key.id)))
+            .await
+            .unwrap();
+        signatures
+            .save_signature(signature("c2", SignatureStatus::Unverified, None))
+            .await
+            .unwrap();
+        let cached = signatures
+            .get_signatures(vec!["c1".to_owned(), "c2".to_owned()])
+            .await
+            .unwrap();
+        assert_eq!(cached.len(), 2);
+        signatures
+            .save_signing_key(
+                1,
+                "a@mega.org",
+                SigningKeyType::Gpg,
+                "gpg",
+                "-----BEGIN",
+                "ABCD",
+            )
+            .await
+            .unwrap();
+        let cached = signatures
+            .get_signatures(vec!["c1".to_owned(), "c2".to_owned()])
+            .await
+            .unwrap();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].status, SignatureStatus::Verified);
+        assert!(!signatures.delete_signing_key(2, key.id).await.unwrap());
+        assert!(signatures.delete_signing_key(1, key.id).await.unwrap());
+        assert!(signatures
+            .get_signatures(vec!["c1".to_owned()])
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(signatures.list_signing_keys(1).await.unwrap().len(), 1);
+    }
+}
+
+#[tokio::test]
+async fn test_policy_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // every change of the policies of a namespace is a new version
+        let policies = PolicyStorage::new(conn.clone()).await;
+        let v1 = policies
+            .save_version(
+                "team-a",
+                Some("permit(principal, action, resource);".to_owned()),
+                "admin",
+            )
+            .await
+            .unwrap();
+        assert_eq!(v1.version, 1);
+        policies
+            .save_version(
+                "team-b",
+                Some("forbid(principal, action, resource);".to_owned()),
+                "admin",
+            )
+            .await
+            .unwrap();
+        let v2 = policies
+            .save_version("team-a", None, "admin")
+            .await
+            .unwrap();
+        assert_eq!(v2.version, 2);
+        let current = policies.current_policies().await.unwrap();
+        assert_eq!(current.keys().collect::<Vec<_>>(), vec!["team-b"]);
+        let versions = policies.list_versions("team-a").await.unwrap();
+        assert_eq!(
+            versions.iter().map(|v| v.version).collect::<Vec<_>>(),
+            vec![2, 1]
+        );
+        let old = policies.get_version("team-a", 1).await.unwrap().unwrap();
+        policies
+            .save_version("team-a", old.content, "admin")
+            .await
+            .unwrap();
+        assert_eq!(policies.list_current().await.unwrap().len(), 2);
+        assert_eq!(
+            policies
+                .get_latest_version("team-a")
+                .await
+                .unwrap()
+                .unwrap()
+                .version,
+            3
+        );
+
+        // a subject has one role on a path
+        let first = policies
+            .save_role_assignment(
+                "/project",
+                RoleSubject::Team,
+                "mega/core",
+                RepoRole::Guest,
+                None,
+                "admin",
+            )
+            .await
+            .unwrap();
+        let second = policies
+            .save_role_assignment(
+                "/project",
+                RoleSubject::Team,
+                "mega/core",
+                RepoRole::Developer,
+                None,
+                "admin",
+            )
+            .await
+            .unwrap();
+        assert_eq!((first.id, second.role), (second.id, RepoRole::Developer));
+        policies
+            .save_role_assignment(
+                "/projects",
+                RoleSubject::User,
+                "alice",
+                RepoRole::Owner,
+                None,
+                "admin",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            policies
+                .list_role_assignments("/project")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(policies.list_role_assignments("/").await.unwrap().len(), 2);
+        let current = policies.current_policies().await.unwrap();
+        assert!(current[ROLE_NAMESPACE].contains(r#"Team::"mega/core""#));
+        assert!(policies.delete_role_assignment(second.id).await.unwrap());
+        assert_eq!(policies.role_assignments().await.unwrap().len(), 1);
+        // temporary assignments are removed once they expired
+        policies
+            .save_role_assignment(
+                "/project",
+                RoleSubject::User,
+                "contractor",
+                RepoRole::Developer,
+                Some(chrono::Utc::now().naive_utc() - chrono::Duration::minutes(1)),
+                "admin",
+            )
+            .await
+            .unwrap();
+        assert_eq!(policies.role_assignments().await.unwrap().len(), 2);
+        assert_eq!(policies.delete_expired_role_assignments().await.unwrap(), 1);
+        assert_eq!(policies.role_assignments().await.unwrap().len(), 1);
+
+        // the signatures of the entity file of a directory are listed latest first
+        for blob_id in ["1".repeat(40), "2".repeat(40)] {
+            policies
+                .save_entity_signature("/project/mega", &blob_id, &"0".repeat(64), "admin")
+                .await
+                .unwrap();
+        }
+        let signatures = policies
+            .list_entity_signatures("/project/mega")
+            .await
+            .unwrap();
+        assert_eq!(signatures.len(), 2);
+        assert_eq!(signatures[0].blob_id, "2".repeat(40));
+        assert!(policies
+            .list_entity_signatures("/project")
+            .await
+            .unwrap()
+            .is_empty());
+
+        // authorization decisions are listed latest first, filtered by what was asked for
+        for (i, allowed) in [true, false, false].into_iter().enumerate() {
+            policies
+                .save_auth_decision(auth_decision::Model {
+                    id: generate_id(),
+                    principal: "alice".to_owned(),
+                    action: "pushRepo".to_owned(),
+                    resource: "/project/mega".to_owned(),
+                    allowed,
+                    policies: if allowed {
+                        "policy0".to_owned()
+                    } else {
+                        String::new()
+                    },
+                    errors: None,
+                    break_glass: false,
+                    created_at: chrono::Utc::now().naive_utc()
+                        + chrono::Duration::seconds(i as i64),
+                })
+                .await
+                .unwrap();
+        }
+        let filter = AuthDecisionFilter {
+            principal: Some("alice".to_owned()),
+            allowed: Some(false),
+            ..Default::default()
+        };
+        let (denied, total) = policies
+            .list_auth_decisions(
+                &filter,
+                Pagination {
+                    page: 1,
+                    per_page: 1,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(denied.len(), 1);
+        assert!(!denied[0].allowed);
+        let filter = AuthDecisionFilter {
+            principal: Some("bob".to_owned()),
+            ..Default::default()
+        };
+        let (_, total) = policies
+            .list_auth_decisions(&filter, Pagination::default())
+            .await
+            .unwrap();
+        assert_eq!(total, 0);
+
+        // break-glass grants apply until they expire or are revoked
+        let now = chrono::Utc::now().naive_utc();
+        let grant = policies
+            .save_break_glass_grant(
+                "alice",
+                "/project",
+                "incident",
+                now + chrono::Duration::hours(1),
+            )
+            .await
+            .unwrap();
+        policies
+            .save_break_glass_grant(
+                "bob",
+                "/project",
+                "incident",
+                now - chrono::Duration::hours(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            policies.list_break_glass_grants(false).await.unwrap().len(),
+            2
+        );
+        let active = policies.break_glass_grants().await.unwrap();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].user, "alice");
+        let current = policies.current_policies().await.unwrap();
+        assert!(current[BREAK_GLASS_NAMESPACE].contains(r#"User::"alice""#));
+        assert!(policies
+            .revoke_break_glass_grant(grant.id, "admin")
+            .await
+            .unwrap());
+        assert!(!policies
+            .revoke_break_glass_grant(grant.id, "admin")
+            .await
+            .unwrap());
+        assert!(policies
+            .list_break_glass_grants(true)
+            .await
+            .unwrap()
+            .is_empty());
+        let current = policies.current_policies().await.unwrap();
+        assert!(!current.contains_key(BREAK_GLASS_NAMESPACE));
+    }
+}
+
+#[tokio::test]
+async fn test_user_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // service accounts sign in with tokens of their own
+        let users = UserStorage::new(conn.clone()).await;
+        let account = users
+            .save_service_account("ci", "builds", "admin")
+            .await
+            .unwrap();
+        assert!(users.save_service_account("ci", "", "admin").await.is_err());
+        let token = users
+            .generate_service_account_token(account.id, "admin")
+            .await
+            .unwrap();
+        assert!(users
+            .check_service_account_token(account.id, &token)
+            .await
+            .unwrap());
+        assert!(!users
+            .check_service_account_token(account.id, "wrong")
+            .await
+            .unwrap());
+        let tokens = users.list_service_account_tokens(account.id).await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        users.delete_service_account(account.id).await.unwrap();
+        assert!(users.find_service_account("ci").await.unwrap().is_none());
+        assert!(users
+            .list_service_account_tokens(account.id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // deactivated users keep their account, their credentials can be reset
+        let user = users.create_user("alice", "alice@x.org").await.unwrap();
+        assert!(users.is_user_active(user.id).await.unwrap());
+        users.generate_token(user.id).await.unwrap();
+        users
+            .save_ssh_key(user.id, "laptop", "ssh-ed25519 AAAA", "SHA256:x")
+            .await
+            .unwrap();
+        assert!(users.set_user_deactivated(user.id, true).await.unwrap());
+        assert!(!users.is_user_active(user.id).await.unwrap());
+        assert!(users.set_user_deactivated(user.id, false).await.unwrap());
+        assert!(users.is_user_active(user.id).await.unwrap());
+        assert!(!users.set_user_deactivated(0, true).await.unwrap());
+        assert_eq!(
+            users.delete_user_credentials(user.id).await.unwrap(),
+            (1, 1)
+        );
+        assert!(users.list_token(user.id).await.unwrap().is_empty());
+
+        // users of a tenant are listed apart, and so are the issues they open
+        assert!(users.set_user_tenant(user.id, Some("acme")).await.unwrap());
+        let names = |list: Vec<callisto::user::Model>| -> Vec<String> {
+            list.into_iter().map(|u| u.name).collect()
+        };
+        assert_eq!(
+            names(users.list_tenant_users(Some("acme")).await.unwrap()),
+            ["alice"]
+        );
+        assert!(!names(users.list_tenant_users(None).await.unwrap()).contains(&"alice".to_owned()));
+        let issues = IssueStorage::new(conn.clone()).await;
+        issues
+            .save_issue(conn.as_ref(), user.id, "tenant issue")
+            .await
+            .unwrap();
+        let acme = TenantScope::Tenant {
+            name: "acme".to_owned(),
+            path: "/acme".to_owned(),
+        };
+        let instance = TenantScope::Instance {
+            paths: vec!["/acme".to_owned()],
+        };
+        let (list, _) = issues
+            .get_issue_by_status("open", &acme, Pagination::default())
+            .await
+            .unwrap();
+        assert_eq!(list.len(), 1);
+        let (list, _) = issues
+            .get_issue_by_status("open", &instance, Pagination::default())
+            .await
+            .unwrap();
+        assert!(list.iter().all(|i| i.owner != user.id));
+        assert!(users.set_user_tenant(user.id, None).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_job_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        // queued jobs are claimed by one worker, and retried until they failed too often
+        let jobs = JobStorage::new(conn.clone()).await;
+        let job_config = JobConfig {
+            max_attempts: 2,
+            retry_delay: 0,
+            ..Default::default()
+        };
+        let later = chrono::Utc::now().naive_utc() + chrono::Duration::hours(1);
+        jobs.enqueue(conn.as_ref(), "index", &"later", Some(later))
+            .await
+            .unwrap();
+        let job = jobs
+            .enqueue(conn.as_ref(), "index", &vec!["/project"], None)
+            .await
+            .unwrap();
+        let claimed = jobs.claim_jobs("w1", 10, 300).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].id, job.id);
+        assert_eq!(claimed[0].payload, r#"["/project"]"#);
+        assert_eq!(claimed[0].attempts, 1);
+        assert!(jobs.claim_jobs("w2", 10, 300).await.unwrap().is_empty());
+        assert!(!jobs.extend_lease(job.id, "w2", 300).await.unwrap());
+        assert!(jobs.extend_lease(job.id, "w1", 300).await.unwrap());
+        let state = jobs
+            .fail_job(job.id, "w1", 1, "index broken", &job_config)
+            .await
+            .unwrap();
+        assert_eq!(state, BackgroundJobState::Queued);
+        // a worker whose lease ran out does not overwrite the outcome
+        let claimed = jobs.claim_jobs("w2", 10, 0).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert_eq!(claimed[0].attempts, 2);
+        jobs.complete_job(job.id, "w1").await.unwrap();
+        assert_eq!(
+            jobs.get_job(job.id).await.unwrap().unwrap().state,
+            BackgroundJobState::Running
+        );
+        // the lease of w2 is over, the job is claimed again
+        let claimed = jobs.claim_jobs("w3", 10, 300).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        let state = jobs
+            .fail_job(
+                job.id,
+                "w3",
+                claimed[0].attempts,
+                "index broken",
+                &job_config,
+            )
+            .await
+            .unwrap();
+        assert_eq!(state, BackgroundJobState::Failed);
+        let failed = jobs.get_job(job.id).await.unwrap().unwrap();
+        assert_eq!(failed.last_error.as_deref(), Some("index broken"));
+        assert!(jobs.claim_jobs("w1", 10, 300).await.unwrap().is_empty());
+        // a job stuck with its worker is released to be claimed again
+        let job = jobs
+            .enqueue(conn.as_ref(), "index", &"stuck", None)
+            .await
+            .unwrap();
+        assert_eq!(jobs.claim_jobs("w1", 10, 300).await.unwrap().len(), 1);
+        assert_eq!(jobs.list_running_jobs().await.unwrap().len(), 1);
+        assert!(jobs.release_job(job.id).await.unwrap());
+        assert!(!jobs.release_job(job.id).await.unwrap());
+        let claimed = jobs.claim_jobs("w2", 10, 300).await.unwrap();
+        assert_eq!(claimed.len(), 1);
+        assert!(!jobs.extend_lease(job.id, "w1", 300).await.unwrap());
+    }
+}
+
+#[tokio::test]
+async fn test_raw_storage() {
+    let _lock = DATABASES.lock().await;
+    let dir = TempDir::new().unwrap();
+    for config in db_configs(&dir) {
+        let conn = fresh_connection(&config).await;
+        let database = StorageConfig {
+            raw_obj_local_path: dir.path().join("objects"),
+            ..Default::default()
+        };
+
+        // all content goes to local files, and back into the database
+        let local = StorageConfig {
+            raw_obj_storage_type: RawStorageType::Local,
+            big_obj_threshold: 0,
+            ..database.clone()
+        };
+        let raw_storage = RawDbStorage::new(conn.clone(), &local).await;
+        raw_storage
+            .save_raw_blobs(
+                conn.as_ref(),
+                vec![raw_blob("0123456789abcdef0123456789abcdef01234567")],
+            )
+            .await
+            .unwrap();
+        let blob = raw_storage
+            .get_raw_blob_by_hash("0123456789abcdef0123456789abcdef01234567")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(blob.storage_type, StorageType::LocalFs);
+        assert_eq!(blob.data.as_deref(), Some(&b"content"[..]));
+        let raw_storage = RawDbStorage::new(conn.clone(), &database).await;
+        let res = raw_storage.migrate_blobs(false).await.unwrap();
+        assert_eq!((res.checked, res.moved), (1, 1));
+        assert!(res.failed.is_empty());
+        let blob = raw_storage
+            .get_raw_blob_by_hash("0123456789abcdef0123456789abcdef01234567")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(blob.storage_type, StorageType::Database);
+        assert_eq!(blob.data.as_deref(), Some(&b"content"[..]));
+
+        // content put into files is encrypted, and encrypted again for a new master key
+        let keys = HashMap::from([
+            ("k1".to_owned(), STANDARD.encode([1; 32])),
+            ("k2".to_owned(), STANDARD.encode([2; 32])),
+        ]);
+        let encrypted = |key_id: &str| StorageConfig {
+            encryption: EncryptionConfig {
+                enable: true,
+                key_id: key_id.to_owned(),
+                keys: keys.clone(),
+                key_command: String::new(),
+            },
+            ..local.clone()
+        };
+        let hash = "89abcdef0123456789abcdef0123456789abcdef".to_owned();
+        let raw_storage = RawDbStorage::new(conn.clone(), &encrypted("k1")).await;
+        raw_storage
+            .save_raw_blobs(conn.as_ref(), vec![raw_blob(&hash)])
+            .await
+            .unwrap();
+        let blob = raw_storage
+            .get_raw_blob_by_hash(&hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(blob.data.as_deref(), Some(&b"content"[..]));
+        let stored = std::fs::read(blob.local_path.unwrap()).unwrap();
+        assert!(!stored.windows(7).any(|w| w == b"content"));
+        let raw_storage = RawDbStorage::new(conn.clone(), &encrypted("k2")).await;
+        let res = raw_storage.rotate_keys().await.unwrap();
+        assert_eq!((res.checked, res.rotated), (1, 1));
+        assert!(res.failed.is_empty());
+        assert_eq!(raw_storage.rotate_keys().await.unwrap().rotated, 0);
+        // the old master key is not needed anymore
+        let mut rotated = encrypted("k2");
+        rotated.encryption.keys.remove("k1");
+        let raw_storage = RawDbStorage::new(conn.clone(), &rotated).await;
+        let blob = raw_storage
+            .get_raw_blob_by_hash(&hash)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(blob.data.as_deref(), Some(&b"content"[..]));
+        raw_storage.release_refs(&[hash]).await.unwrap();
+
+        // content is shared until its last reference is gone
+        let hash = "0123456789abcdef0123456789abcdef01234567".to_owned();
+        raw_storage
+            .add_refs(conn.as_ref(), &[hash.clone(), hash.clone()])
+            .await
+            .unwrap();
+        raw_storage.release_refs(&[hash.clone()]).await.unwrap();
+        let blob = raw_storage.get_raw_blob_by_hash(&hash).await.unwrap();
+        assert_eq!(blob.map(|b| b.ref_count), Some(1));
+        raw_storage.release_refs(&[hash.clone()]).await.unwrap();
+        assert!(raw_storage
+            .get_raw_blob_by_hash(&hash)
+            .await
+            .unwrap()
+            .is_none());
+
+        // versions of a large file share the chunks they have in common
+        let chunked = StorageConfig {
+            big_obj_chunk_threshold: 1024,
+            ..database.clone()
+        };
+        let raw_storage = RawDbStorage::new(conn.clone(), &chunked).await;
+        let old = random_content(8 * 1024 * 1024);
+        let mut new = old.clone();
+        new[5 * 1024 * 1024] ^= 1;
+        let mut blobs = Vec::new();
+        for (sha1, data) in [
+            ("1111111111111111111111111111111111111111", &old),
+            ("2222222222222222222222222222222222222222", &new),
+        ] {
+            let mut blob = raw_blob(sha1);
+            blob.data = Some(data.clone());
+            blobs.push(blob);
+        }
+        raw_storage
+            .save_raw_blobs(conn.as_ref(), blobs)
+            .await
+            .unwrap();
+        let entries = raw_blob_chunk::Entity::find()
+            .count(conn.as_ref())
+            .await
+            .unwrap();
+        let chunks = raw_blob::Entity::find()
+            .filter(raw_blob::Column::RefCount.gt(0))
+            .count(conn.as_ref())
+            .await
+            .unwrap();
+        assert!(entries > 2 && chunks < entries);
+        let content = read_content(&raw_storage, "2222222222222222222222222222222222222222").await;
+        assert!(content == new);
+        raw_storage
+            .release_refs(&["2222222222222222222222222222222222222222".to_owned()])
+            .await
+            .unwrap();
+        assert!(raw_storage
+            .get_raw_blob_content("2222222222222222222222222222222222222222")
+            .await
+            .unwrap()
+            .is_none());
+        let content = read_content(&raw_storage, "1111111111111111111111111111111111111111").await;
+        assert!(content == old);
+    }
+}
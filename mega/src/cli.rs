@@ -1,12 +1,11 @@
 //! Cli module is responsible for parsing command line arguments and executing the appropriate.
 
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use clap::{Arg, ArgMatches, Command};
-use tracing_subscriber::fmt::writer::MakeWriterExt;
 
 use common::{
-    config::{Config, LogConfig},
+    config::Config,
     errors::{MegaError, MegaResult},
 };
 
@@ -32,19 +31,25 @@ pub fn parse(args: Option<Vec<&str>>) -> MegaResult {
     let config_path = current_dir.join("config.toml");
 
     let config = if let Some(path) = matches.get_one::<PathBuf>("config").cloned() {
-        Config::new(path.to_str().unwrap()).unwrap()
+        load_config(&path, &matches)?
     } else if config_path.exists() {
-        Config::new(config_path.to_str().unwrap()).unwrap()
+        load_config(&config_path, &matches)?
     } else {
         eprintln!("can't find config.toml under {:?}, you can manually set config.toml path with --config parameter", env::current_dir().unwrap());
         Config::default()
     };
 
-    init_log(&config.log);
+    // the daemon forks, before the threads of the log are started
+    #[cfg(unix)]
+    if let Some(("service", args)) = matches.subcommand() {
+        crate::commands::start_daemon(&config, args)?;
+    }
+
+    common::log::init(&config, "mega");
 
     ctrlc::set_handler(move || {
         tracing::info!("Received Ctrl-C signal, exiting...");
-        std::process::exit(0);
+        common::daemon::exit(0);
     }).unwrap();
 
     let (cmd, subcommand_args) = match matches.subcommand() {
@@ -60,29 +65,20 @@ pub fn parse(args: Option<Vec<&str>>) -> MegaResult {
     exec_subcommand(config, cmd, subcommand_args)
 }
 
-fn init_log(config: &LogConfig) {
-    let log_level = match config.level.as_str() {
-        "trace" => tracing::Level::TRACE,
-        "debug" => tracing::Level::DEBUG,
-        "info" => tracing::Level::INFO,
-        "warn" => tracing::Level::WARN,
-        "error" => tracing::Level::ERROR,
-        _ => tracing::Level::INFO,
-    };
-
-    let file_appender = tracing_appender::rolling::hourly(config.log_path.clone(), "mega-logs");
-
-    if config.print_std {
-        let stdout = std::io::stdout;
-        tracing_subscriber::fmt()
-            .with_writer(stdout.and(file_appender))
-            .with_max_level(log_level)
-            .init();
-    } else {
-        tracing_subscriber::fmt()
-            .with_writer(file_appender)
-            .with_max_level(log_level)
-            .init();
+fn load_config(path: &Path, matches: &ArgMatches) -> Result<Config, MegaError> {
+    match Config::new(path.to_str().unwrap()) {
+        Ok(config) => Ok(config),
+        // the config command reads the file itself to tell what is wrong with it
+        Err(err) if matches.subcommand_name() == Some("config") => {
+            eprintln!("invalid config {}: {}", path.display(), err);
+            Ok(Config::default())
+        }
+        Err(err) => Err(MegaError::with_message(&format!(
+            "invalid config {}: {}, run `mega config check {}` for details",
+            path.display(),
+            err,
+            path.display()
+        ))),
     }
 }
 
@@ -0,0 +1,348 @@
+//! This module is responsible for handling the 'admin' command.
+//! It manages users, their tenants and instance admins, clears stuck jobs and switches the
+//! maintenance mode directly in the database, so operators can do it while no server runs or its
+//! api is unusable.
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use callisto::{
+    db_enums::{JobStatus, RepoRole, RoleSubject},
+    user,
+};
+use ceres::{maintenance::mode, tenant};
+use common::{
+    config::{Config, TenancyConfig},
+    errors::{MegaError, MegaResult},
+};
+use jupiter::context::Context;
+
+const MB: f64 = 1024.0 * 1024.0;
+
+/// Recorded as the creator of role assignments granted and the maintenance mode enabled with
+/// this command.
+const OPERATOR: &str = "mega-admin";
+
+#[derive(Args, Debug)]
+struct AdminArgs {
+    #[command(subcommand)]
+    action: AdminAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum AdminAction {
+    /// Create, deactivate and reactivate users or reset their credentials
+    User {
+        #[command(subcommand)]
+        action: UserAction,
+    },
+    /// Make a user an instance admin by granting the owner role on the root directory
+    GrantAdmin { name: String },
+    /// List the largest repositories
+    Repos {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// List the maintenance and background jobs being run
+    Running,
+    /// Release a job which is stuck running
+    Unlock {
+        #[command(subcommand)]
+        target: UnlockTarget,
+    },
+    /// Reject pushes and changes on every instance while keeping reads available, e.g. during
+    /// migrations and backups
+    Maintenance {
+        #[command(subcommand)]
+        action: ModeAction,
+    },
+    /// List the tenants and their users, or move users between tenants
+    Tenant {
+        #[command(subcommand)]
+        action: TenantAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum UserAction {
+    /// Create a user which signs in without GitHub
+    Create {
+        name: String,
+        email: String,
+        /// Tenant of the user, the tenant of its email domain if not given
+        #[arg(long)]
+        tenant: Option<String>,
+    },
+    /// Refuse the sessions, tokens and ssh keys of a user until it is reactivated
+    Deactivate {
+        name: String,
+    },
+    Reactivate {
+        name: String,
+    },
+    /// Delete the access tokens and ssh keys of a user
+    ResetCredentials {
+        name: String,
+        /// Issue a new access token and print it
+        #[arg(long)]
+        token: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ModeAction {
+    /// Start rejecting writes
+    On {
+        /// Told to clients whose writes are rejected, `maintenance.mode_message` if not given
+        #[arg(long)]
+        message: Option<String>,
+    },
+    /// Allow writes again
+    Off,
+    /// Show whether the instance is in maintenance mode
+    Status,
+}
+
+#[derive(Subcommand, Debug)]
+enum TenantAction {
+    /// List the configured tenants
+    List,
+    /// List the users of a tenant, or of the instance if no tenant is given
+    Users { name: Option<String> },
+    /// Move a user to a tenant, its permissions outside of the tenant no longer apply
+    Join { user: String, tenant: String },
+    /// Move a user out of its tenant to the instance
+    Leave { user: String },
+}
+
+#[derive(Subcommand, Debug)]
+enum UnlockTarget {
+    /// Mark a running maintenance job as failed, so that its task can run again
+    Maintenance { id: i64 },
+    /// Queue a running background job again, taking it away from its worker
+    Job { id: i64 },
+}
+
+pub fn cli() -> Command {
+    AdminArgs::augment_args(
+        Command::new("admin")
+            .about("Manage users, tenants, instance admins, stuck operations and maintenance mode"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = AdminArgs::from_arg_matches(args)?;
+    let context = Context::new(config).await;
+    match args.action {
+        AdminAction::User { action } => user_action(&context, action).await?,
+        AdminAction::Tenant { action } => tenant_action(&context, action).await?,
+        AdminAction::GrantAdmin { name } => {
+            let user = find_user(&context, &name).await?;
+            context
+                .services
+                .policy_storage
+                .save_role_assignment(
+                    "/",
+                    RoleSubject::User,
+                    &user.name,
+                    RepoRole::Owner,
+                    None,
+                    OPERATOR,
+                )
+                .await?;
+            println!("{} is an instance admin", user.name);
+        }
+        AdminAction::Repos { limit } => {
+            let mut usage = context.services.quota_storage.list_usage("/").await?;
+            usage.sort_by_key(|u| std::cmp::Reverse(u.git_size + u.lfs_size));
+            for u in usage.into_iter().take(limit) {
+                println!(
+                    "{}\ttotal {}\tgit {}\tlfs {}",
+                    u.path,
+                    format_size(u.git_size + u.lfs_size),
+                    format_size(u.git_size),
+                    format_size(u.lfs_size)
+                );
+            }
+        }
+        AdminAction::Running => {
+            let jobs = context
+                .services
+                .maintenance_storage
+                .list_jobs(None, 100)
+                .await?;
+            for job in jobs.iter().filter(|j| j.status == JobStatus::Running) {
+                println!(
+                    "maintenance {}\t{}\tstarted {}",
+                    job.id, job.task, job.started_at
+                );
+            }
+            for job in context.services.job_storage.list_running_jobs().await? {
+                println!(
+                    "job {}\t{}\tattempt {}\tby {}\tuntil {}",
+                    job.id,
+                    job.job_type,
+                    job.attempts,
+                    job.locked_by.unwrap_or_default(),
+                    job.locked_until.map(|t| t.to_string()).unwrap_or_default()
+                );
+            }
+        }
+        AdminAction::Unlock { target } => {
+            let released = match target {
+                UnlockTarget::Maintenance { id } => {
+                    context
+                        .services
+                        .maintenance_storage
+                        .fail_running_job(id, "unlocked by an admin")
+                        .await?
+                }
+                UnlockTarget::Job { id } => context.services.job_storage.release_job(id).await?,
+            };
+            if !released {
+                return Err(MegaError::with_message("no such running job"));
+            }
+            println!("released");
+        }
+        AdminAction::Maintenance { action } => {
+            match action {
+                ModeAction::On { message } => mode::enable(&context, message, OPERATOR).await?,
+                ModeAction::Off => {
+                    if !mode::disable(&context, OPERATOR).await? {
+                        println!("not in maintenance mode");
+                        return Ok(());
+                    }
+                }
+                ModeAction::Status => (),
+            }
+            match mode::current(&context).await {
+                Some(mode) => println!(
+                    "in maintenance mode since {} by {}: {}",
+                    mode.enabled_at, mode.enabled_by, mode.message
+                ),
+                None => println!("not in maintenance mode, writes are allowed"),
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn user_action(context: &Context, action: UserAction) -> Result<(), MegaError> {
+    let storage = context.user_stg();
+    match action {
+        UserAction::Create {
+            name,
+            email,
+            tenant,
+        } => {
+            if storage.find_user_by_name(&name).await?.is_some() {
+                return Err(MegaError::with_message("user already exists"));
+            }
+            if storage.find_user_by_email(&email).await?.is_some() {
+                return Err(MegaError::with_message("email is used by another user"));
+            }
+            let settings = context.settings();
+            let tenant = match tenant {
+                Some(name) => Some(find_tenant(&settings.tenancy, &name)?),
+                None => tenant::of_email(&settings.tenancy, &email).map(|t| t.name.clone()),
+            };
+            let user = storage.create_user(&name, &email).await?;
+            if let Some(tenant) = &tenant {
+                storage.set_user_tenant(user.id, Some(tenant)).await?;
+            }
+            println!(
+                "created user {} ({}) in {}",
+                user.name,
+                user.id,
+                tenant.as_deref().unwrap_or("the instance")
+            );
+        }
+        UserAction::Deactivate { name } => {
+            let user = find_user(context, &name).await?;
+            storage.set_user_deactivated(user.id, true).await?;
+            println!("deactivated {}", user.name);
+        }
+        UserAction::Reactivate { name } => {
+            let user = find_user(context, &name).await?;
+            storage.set_user_deactivated(user.id, false).await?;
+            println!("reactivated {}", user.name);
+        }
+        UserAction::ResetCredentials { name, token } => {
+            let user = find_user(context, &name).await?;
+            let (tokens, keys) = storage.delete_user_credentials(user.id).await?;
+            println!("deleted {} access tokens and {} ssh keys", tokens, keys);
+            if token {
+                println!(
+                    "new access token: {}",
+                    storage.generate_token(user.id).await?
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn tenant_action(context: &Context, action: TenantAction) -> Result<(), MegaError> {
+    let storage = context.user_stg();
+    let settings = context.settings();
+    let config = &settings.tenancy;
+    match action {
+        TenantAction::List => {
+            if !config.enable {
+                println!("tenancy is disabled");
+            }
+            for t in &config.tenants {
+                let users = storage.list_tenant_users(Some(&t.name)).await?;
+                println!(
+                    "{}	{}	{} users	quota {}	email domains {}",
+                    t.name,
+                    t.path,
+                    users.len(),
+                    match t.max_size {
+                        0 => "unlimited".to_owned(),
+                        size => format!("{} MB", size),
+                    },
+                    t.email_domains.join(",")
+                );
+            }
+        }
+        TenantAction::Users { name } => {
+            for user in storage.list_tenant_users(name.as_deref()).await? {
+                println!("{}	{}	{}", user.name, user.id, user.email);
+            }
+        }
+        TenantAction::Join { user, tenant } => {
+            let tenant = find_tenant(config, &tenant)?;
+            let user = find_user(context, &user).await?;
+            storage.set_user_tenant(user.id, Some(&tenant)).await?;
+            println!("{} is a user of {}", user.name, tenant);
+        }
+        TenantAction::Leave { user } => {
+            let user = find_user(context, &user).await?;
+            storage.set_user_tenant(user.id, None).await?;
+            println!("{} is a user of the instance", user.name);
+        }
+    }
+    Ok(())
+}
+
+/// The name of the configured tenant `name`.
+fn find_tenant(config: &TenancyConfig, name: &str) -> Result<String, MegaError> {
+    tenant::find(config, name)
+        .map(|t| t.name.clone())
+        .ok_or_else(|| MegaError::with_message(&format!("tenant {} is not configured", name)))
+}
+
+async fn find_user(context: &Context, name: &str) -> Result<user::Model, MegaError> {
+    context
+        .user_stg()
+        .find_user_by_name(name)
+        .await?
+        .ok_or_else(|| MegaError::with_message(&format!("user {} not found", name)))
+}
+
+fn format_size(size: i64) -> String {
+    format!("{:.1} MB", size as f64 / MB)
+}
+
+#[cfg(test)]
+mod tests {}
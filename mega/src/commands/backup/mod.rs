@@ -0,0 +1,101 @@
+//! This module is responsible for handling the 'backup' command.
+//! It creates backups of the database and of the object files kept in local directories,
+//! checks that they can be restored and restores them while the server is stopped.
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use common::{config::Config, errors::MegaResult};
+use jupiter::backup::{self, BackupSource};
+
+#[derive(Args, Debug)]
+struct BackupArgs {
+    /// Directory the backups are kept in, `${base_dir}/backups` by default
+    #[arg(long, global = true)]
+    dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    action: BackupAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum BackupAction {
+    /// Back up the database and the object files, the server may keep running
+    Create {
+        /// Only copy the object files the latest backup does not have
+        #[arg(long)]
+        incremental: bool,
+    },
+    /// List the backups, oldest first
+    List,
+    /// Check that every file of a backup is there and its database dump can be read
+    Verify { id: String },
+    /// Restore the database and the object files of a backup, the server must be stopped
+    Restore { id: String },
+}
+
+pub fn cli() -> Command {
+    BackupArgs::augment_args(
+        Command::new("backup").about("Back up and restore the database and the stored objects"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = BackupArgs::from_arg_matches(args)?;
+    let dir = args.dir.unwrap_or_else(|| config.base_dir.join("backups"));
+    let source = BackupSource::from(&config);
+    match args.action {
+        BackupAction::Create { incremental } => {
+            let manifest = backup::create(&source, &dir, incremental).await?;
+            let copied = manifest
+                .objects
+                .iter()
+                .filter(|o| o.backup == manifest.id)
+                .count();
+            println!(
+                "created backup {} with {} objects, {} copied",
+                manifest.id,
+                manifest.objects.len(),
+                copied
+            );
+            if let Some(remote) = manifest.remote_storage {
+                eprintln!(
+                    "objects in the {:?} storage are not part of the backup",
+                    remote
+                );
+            }
+        }
+        BackupAction::List => {
+            for manifest in backup::list(&dir)? {
+                println!(
+                    "{}\t{}\t{} objects\t{}",
+                    manifest.id,
+                    manifest.created_at,
+                    manifest.objects.len(),
+                    manifest
+                        .parent
+                        .map_or("full".to_owned(), |p| format!("incremental on {}", p))
+                );
+            }
+        }
+        BackupAction::Verify { id } => {
+            let verified = backup::verify(&dir, &id).await?;
+            println!(
+                "backup {} is complete, {} objects with {} bytes",
+                id, verified.objects, verified.bytes
+            );
+        }
+        BackupAction::Restore { id } => {
+            let manifest = backup::restore(&source, &dir, &id).await?;
+            println!(
+                "restored backup {} created at {}",
+                manifest.id, manifest.created_at
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
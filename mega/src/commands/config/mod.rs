@@ -0,0 +1,62 @@
+//! This module is responsible for handling the 'config' command.
+//! It checks a config file and prints the effective configuration, with the environment
+//! variables applied and the secrets redacted.
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+};
+
+#[derive(Args, Debug)]
+struct ConfigArgs {
+    #[command(subcommand)]
+    action: ConfigAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Check a config file and print the effective configuration
+    Check {
+        path: PathBuf,
+        /// Only print the problems
+        #[arg(long, short)]
+        quiet: bool,
+    },
+}
+
+pub fn cli() -> Command {
+    ConfigArgs::augment_args(Command::new("config").about("Check the configuration"))
+}
+
+pub(crate) fn exec(_config: Config, args: &ArgMatches) -> MegaResult {
+    let args = ConfigArgs::from_arg_matches(args)?;
+    let ConfigAction::Check { path, quiet } = args.action;
+    let check = Config::check(&path.to_string_lossy()).map_err(|err| {
+        MegaError::with_message(&format!("failed to read {}: {}", path.display(), err))
+    })?;
+
+    if !quiet {
+        let effective = toml::to_string_pretty(&check.config.redacted())
+            .map_err(|err| MegaError::with_message(&err.to_string()))?;
+        println!("{}", effective);
+    }
+    for key in &check.unknown_keys {
+        eprintln!("warning: unknown key `{}` is ignored", key);
+    }
+    for error in &check.errors {
+        eprintln!("error: {}", error);
+    }
+    match check.errors.len() {
+        0 => Ok(()),
+        n => Err(MegaError::with_message(&format!(
+            "{} errors in the configuration",
+            n
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {}
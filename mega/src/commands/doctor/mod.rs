@@ -0,0 +1,385 @@
+//! This module is responsible for handling the 'doctor' command.
+//! It checks the configuration, the database and its migrations, the object storage, the
+//! message queue, the ports of the services and the Cedar policies, and prints what is wrong
+//! with a hint how to fix it.
+use std::fmt;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+use sea_orm_migration::MigratorTrait;
+
+use callisto::db_enums::MessageState;
+use common::{
+    config::{Config, RawStorageType},
+    errors::{MegaError, MegaResult},
+    model::Pagination,
+};
+use jupiter::encryption::Keyring;
+use jupiter::migration::Migrator;
+use jupiter::raw_storage;
+use jupiter::storage::{init::try_connect, mq_storage::MQStorage, policy_storage::PolicyStorage};
+use saturn::policy::PolicyStore;
+
+#[derive(Args, Debug)]
+struct DoctorArgs {
+    /// Address the services are started on
+    #[arg(long, default_value_t = String::from("127.0.0.1"))]
+    host: String,
+    #[arg(long, default_value_t = 8000)]
+    http_port: u16,
+    #[arg(long, default_value_t = 2222)]
+    ssh_port: u16,
+    /// Also check the https port
+    #[arg(long)]
+    https_port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Level::Ok => "ok",
+            Level::Warn => "warn",
+            Level::Fail => "FAIL",
+        };
+        // padded, findings are printed as a table
+        f.pad(s)
+    }
+}
+
+#[derive(Debug)]
+struct Finding {
+    check: &'static str,
+    level: Level,
+    message: String,
+}
+
+/// Findings of the checks run so far.
+#[derive(Default)]
+struct Report(Vec<Finding>);
+
+impl Report {
+    fn add(&mut self, check: &'static str, level: Level, message: impl Into<String>) {
+        self.0.push(Finding {
+            check,
+            level,
+            message: message.into(),
+        });
+    }
+
+    fn failed(&self) -> usize {
+        self.0.iter().filter(|f| f.level == Level::Fail).count()
+    }
+}
+
+pub fn cli() -> Command {
+    DoctorArgs::augment_args(
+        Command::new("doctor").about("Check the configuration and the services mega depends on"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = DoctorArgs::from_arg_matches(args)?;
+    let mut report = Report::default();
+    check_config(&config, &mut report);
+    check_dirs(&config, &mut report);
+    check_storage(&config, &mut report).await;
+    check_ports(&args, &mut report);
+    let policies = match PolicyStore::new(&config.policy) {
+        Ok(store) => Some(store),
+        Err(err) => {
+            report.add(
+                "policy",
+                Level::Fail,
+                format!("{}, fix `policy.schema_path` or `policy.policy_path`", err),
+            );
+            None
+        }
+    };
+    check_database(&config, policies, &mut report).await;
+
+    for finding in &report.0 {
+        println!(
+            "{:<5} {:<10} {}",
+            finding.level, finding.check, finding.message
+        );
+    }
+    match report.failed() {
+        0 => Ok(()),
+        n => Err(MegaError::with_message(&format!("{} checks failed", n))),
+    }
+}
+
+fn check_config(config: &Config, report: &mut Report) {
+    for error in config.validate() {
+        report.add("config", Level::Fail, error);
+    }
+    let db = &config.database;
+    if db.db_type == "sqlite" {
+        let parent = Path::new(&db.db_path).parent();
+        if parent.is_some_and(|p| !p.as_os_str().is_empty() && !p.is_dir()) {
+            report.add(
+                "config",
+                Level::Fail,
+                format!("directory of `database.db_path` {} is missing", db.db_path),
+            );
+        }
+    }
+    if config.monorepo.admin.is_empty() {
+        report.add(
+            "config",
+            Level::Warn,
+            "`monorepo.admin` is empty, nobody owns the root directories",
+        );
+    }
+    match &config.oauth {
+        None => report.add(
+            "config",
+            Level::Warn,
+            "no [oauth] section, users cannot sign in to the web ui",
+        ),
+        Some(oauth) if oauth.github_client_id.is_empty() => report.add(
+            "config",
+            Level::Warn,
+            "`oauth.github_client_id` is empty, users cannot sign in to the web ui",
+        ),
+        Some(_) => {}
+    }
+    if report.0.iter().all(|f| f.check != "config") {
+        report.add("config", Level::Ok, "settings are consistent");
+    }
+}
+
+/// Directories mega writes to must exist or be creatable, and be writable.
+fn check_dirs(config: &Config, report: &mut Report) {
+    let mut dirs = vec![
+        ("base_dir", config.base_dir.as_path()),
+        (
+            "lfs.lfs_obj_local_path",
+            config.lfs.lfs_obj_local_path.as_path(),
+        ),
+        (
+            "pack.pack_decode_cache_path",
+            config.pack.pack_decode_cache_path.as_path(),
+        ),
+    ];
+    if config.storage.raw_obj_storage_type == RawStorageType::Local {
+        dirs.push((
+            "storage.raw_obj_local_path",
+            config.storage.raw_obj_local_path.as_path(),
+        ));
+    }
+    for (name, dir) in dirs {
+        let probe = dir.join(".mega-doctor");
+        let res = std::fs::create_dir_all(dir)
+            .and_then(|_| std::fs::write(&probe, b"mega"))
+            .and_then(|_| std::fs::remove_file(&probe));
+        match res {
+            Ok(()) => report.add(
+                "dirs",
+                Level::Ok,
+                format!("{} {} is writable", name, dir.display()),
+            ),
+            Err(err) => report.add(
+                "dirs",
+                Level::Fail,
+                format!(
+                    "`{}` {} is not writable: {}, fix its permissions or point it elsewhere",
+                    name,
+                    dir.display(),
+                    err
+                ),
+            ),
+        }
+    }
+}
+
+/// Write, read and delete an object in the backend of `storage.raw_obj_storage_type`.
+async fn check_storage(config: &Config, report: &mut Report) {
+    let storage = &config.storage;
+    let backend = Keyring::from_config(&storage.encryption)
+        .and_then(|keyring| raw_storage::init(storage.raw_obj_storage_type, storage, keyring));
+    let res = match backend {
+        Ok(None) => {
+            report.add("storage", Level::Ok, "blob content is kept in the database");
+            return;
+        }
+        Ok(Some(backend)) => raw_storage::probe(backend.as_ref()).await,
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok(()) => report.add(
+            "storage",
+            Level::Ok,
+            format!(
+                "{:?} object storage is readable and writable",
+                storage.raw_obj_storage_type
+            ),
+        ),
+        Err(err) => report.add(
+            "storage",
+            Level::Fail,
+            format!(
+                "{:?} object storage failed: {}, check the `storage` settings",
+                storage.raw_obj_storage_type, err
+            ),
+        ),
+    }
+}
+
+/// The ports of the services are free, they are taken while mega is running.
+fn check_ports(args: &DoctorArgs, report: &mut Report) {
+    let mut ports = vec![("http", args.http_port), ("ssh", args.ssh_port)];
+    if let Some(port) = args.https_port {
+        ports.push(("https", port));
+    }
+    for (service, port) in ports {
+        match TcpListener::bind((args.host.as_str(), port)) {
+            Ok(_) => report.add(
+                "ports",
+                Level::Ok,
+                format!("{} port {}:{} is free", service, args.host, port),
+            ),
+            Err(err) => report.add(
+                "ports",
+                Level::Warn,
+                format!(
+                    "{} port {}:{} is taken: {}, stop what uses it unless it is mega",
+                    service, args.host, port, err
+                ),
+            ),
+        }
+    }
+}
+
+/// Connect to the database, then check its migrations, the message queue and the policies
+/// stored in it.
+async fn check_database(config: &Config, policies: Option<PolicyStore>, report: &mut Report) {
+    let conn = match try_connect(&config.database).await {
+        Ok(conn) => Arc::new(conn),
+        Err(err) => {
+            report.add(
+                "database",
+                Level::Fail,
+                format!(
+                    "{}, check `database.db_url` and that the database is up",
+                    err
+                ),
+            );
+            return;
+        }
+    };
+    report.add(
+        "database",
+        Level::Ok,
+        format!("connected to {}", config.database.db_type),
+    );
+
+    match Migrator::get_pending_migrations(conn.as_ref()).await {
+        Ok(pending) if pending.is_empty() => {
+            report.add("migrate", Level::Ok, "schema is up to date")
+        }
+        Ok(pending) => report.add(
+            "migrate",
+            Level::Fail,
+            format!(
+                "{} migrations are pending, run `mega migrate up`",
+                pending.len()
+            ),
+        ),
+        Err(err) => report.add(
+            "migrate",
+            Level::Fail,
+            format!("failed to read the migrations: {}", err),
+        ),
+    }
+
+    let mq = MQStorage::new(conn.clone()).await;
+    let count = |state| {
+        let mq = mq.clone();
+        async move {
+            mq.list_messages(
+                state,
+                Pagination {
+                    page: 1,
+                    per_page: 1,
+                },
+            )
+            .await
+            .map(|(_, total)| total)
+        }
+    };
+    match (
+        count(MessageState::Pending).await,
+        count(MessageState::Dead).await,
+    ) {
+        (Ok(pending), Ok(0)) => report.add(
+            "mq",
+            Level::Ok,
+            format!("{} messages pending, none dead-lettered", pending),
+        ),
+        (Ok(pending), Ok(dead)) => report.add(
+            "mq",
+            Level::Warn,
+            format!(
+                "{} messages pending and {} dead, requeue them with POST /api/v1/mq/dead/requeue",
+                pending, dead
+            ),
+        ),
+        (Err(err), _) | (_, Err(err)) => report.add(
+            "mq",
+            Level::Fail,
+            format!("failed to read the message queue: {}", err),
+        ),
+    }
+
+    let Some(policies) = policies else {
+        return;
+    };
+    let res = match PolicyStorage::new(conn).await.current_policies().await {
+        Ok(namespaces) => policies
+            .set_namespaces(namespaces)
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    match res {
+        Ok(()) => report.add("policy", Level::Ok, "schema and policies are valid"),
+        Err(err) => report.add(
+            "policy",
+            Level::Fail,
+            format!(
+                "{}, fix or roll back the policies of the namespace through the policy api",
+                err
+            ),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use common::config::Config;
+
+    use super::{check_config, Level, Report};
+
+    #[test]
+    fn test_check_config() {
+        let mut config = Config::default();
+        config.database.db_type = "oracle".to_owned();
+        config.monorepo.import_dir = PathBuf::from("third-part");
+        let mut report = Report::default();
+        check_config(&config, &mut report);
+        assert_eq!(report.failed(), 2);
+        assert!(report.0.iter().all(|f| f.level != Level::Ok));
+    }
+}
@@ -0,0 +1,104 @@
+//! This module is responsible for handling the 'gc' command.
+//! It runs the maintenance tasks which keep repositories fast and small over the whole
+//! instance, or over the repositories below some paths, and reports what each task did.
+use clap::{ArgMatches, Args, Command, FromArgMatches, ValueEnum};
+
+use callisto::db_enums::MaintenanceTask;
+use ceres::maintenance::gc::{self, GcOptions, Outcome};
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+};
+use jupiter::context::Context;
+
+#[derive(Args, Debug)]
+struct GcArgs {
+    /// Task to run, may be given more than once, all tasks run if none is given
+    #[arg(long = "task", value_enum)]
+    tasks: Vec<GcTask>,
+    /// Only the repositories at or below this path, may be given more than once
+    #[arg(long = "path")]
+    paths: Vec<String>,
+    /// How many repositories are worked on at the same time
+    #[arg(short, long, default_value_t = 4)]
+    jobs: usize,
+    /// Delete unreachable objects even if `maintenance.prune_unreachable_objects` is not set
+    #[arg(long)]
+    prune: bool,
+    /// Only report what would be done, nothing is changed
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum GcTask {
+    /// Prebuild the packs served for full clones and remove those of old tips
+    Repack,
+    /// Index the objects reachable from each prebuilt pack
+    Bitmap,
+    /// Compute generation numbers of commits
+    CommitGraph,
+    /// Find unreachable objects, and remove them if pruning
+    ObjectGc,
+    /// Remove LFS objects which are no longer referenced
+    LfsGc,
+}
+
+impl From<GcTask> for MaintenanceTask {
+    fn from(task: GcTask) -> Self {
+        match task {
+            GcTask::Repack => MaintenanceTask::Repack,
+            GcTask::Bitmap => MaintenanceTask::Bitmap,
+            GcTask::CommitGraph => MaintenanceTask::CommitGraph,
+            GcTask::ObjectGc => MaintenanceTask::ObjectGc,
+            GcTask::LfsGc => MaintenanceTask::LfsGc,
+        }
+    }
+}
+
+pub fn cli() -> Command {
+    GcArgs::augment_args(
+        Command::new("gc")
+            .about("Run repack, bitmap, commit-graph, object and LFS gc on repositories"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = GcArgs::from_arg_matches(args)?;
+    let options = GcOptions {
+        tasks: args.tasks.into_iter().map(MaintenanceTask::from).collect(),
+        paths: args.paths,
+        jobs: args.jobs,
+        prune: args.prune,
+        dry_run: args.dry_run,
+    };
+    let context = Context::new(config).await;
+    let reports = gc::run(&context, &options).await;
+
+    for report in &reports {
+        let outcome = match report.outcome {
+            Outcome::Succeeded => "ok",
+            Outcome::Failed => "fail",
+            Outcome::Skipped => "skip",
+        };
+        println!(
+            "{:<4} {:<12} {:>8.1}s  {}",
+            outcome,
+            report.task.to_string(),
+            report.elapsed.as_secs_f64(),
+            report.summary
+        );
+        for (path, err) in &report.failed {
+            eprintln!("     failed for {}: {}", path, err);
+        }
+    }
+    match reports
+        .iter()
+        .filter(|r| r.outcome == Outcome::Failed)
+        .count()
+    {
+        0 => Ok(()),
+        n => Err(MegaError::with_message(&format!("{} tasks failed", n))),
+    }
+}
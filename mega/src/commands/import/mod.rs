@@ -0,0 +1,81 @@
+//! This module is responsible for handling the 'import' command.
+//! It imports local bare repositories, or a directory of them, as import repositories. Running it
+//! again resumes an interrupted import, repositories already imported are skipped.
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use ceres::import::{self, Outcome};
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+    utils::repo_path,
+};
+use jupiter::context::Context;
+
+#[derive(Args, Debug)]
+struct ImportArgs {
+    /// A bare repository or a directory with bare repositories below it
+    source: PathBuf,
+    /// Directory the repositories are imported to, `monorepo.import_dir` by default
+    #[arg(long)]
+    to: Option<PathBuf>,
+    /// Batches of objects stored at the same time
+    #[arg(long, default_value_t = 4)]
+    parallel: usize,
+}
+
+pub fn cli() -> Command {
+    ImportArgs::augment_args(
+        Command::new("import").about("Import local bare git repositories as import repositories"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = ImportArgs::from_arg_matches(args)?;
+    let context = Context::new(config).await;
+    let target = args
+        .to
+        .unwrap_or_else(|| context.config.monorepo.import_dir.clone());
+    let repos = import::find_repos(&args.source)?;
+    if repos.is_empty() {
+        return Err(MegaError::with_message(&format!(
+            "no bare repository found in {}",
+            args.source.display()
+        )));
+    }
+
+    let total = repos.len();
+    let mut failed = 0;
+    for (i, repo) in repos.iter().enumerate() {
+        let path = repo_path(&import::target_path(&target, &args.source, repo));
+        println!("[{}/{}] {} -> {}", i + 1, total, repo.display(), path);
+        let name = path.clone();
+        let res = import::import_repo(&context, repo, &path, args.parallel, move |objects| {
+            println!("  {}: {} objects stored", name, objects)
+        })
+        .await;
+        match res {
+            Ok(Outcome::Imported { objects, refs }) => {
+                println!("  imported {} objects and {} refs", objects, refs)
+            }
+            Ok(Outcome::UpToDate) => println!("  up to date"),
+            Ok(Outcome::Empty) => println!("  skipped, no branches or tags"),
+            Err(err) => {
+                failed += 1;
+                println!("  failed: {}", err);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(MegaError::with_message(&format!(
+            "{} of {} repositories failed to import, run the import again to retry them",
+            failed, total
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
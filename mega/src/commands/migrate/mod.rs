@@ -0,0 +1,62 @@
+//! This module is responsible for handling the 'migrate' command.
+//! It applies, reverts and lists the versioned migrations of the database schema, services
+//! check for pending migrations on start as configured by `database.migration`.
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+use sea_orm_migration::{MigrationStatus, MigratorTrait};
+
+use common::{config::Config, errors::MegaResult};
+use jupiter::migration::Migrator;
+use jupiter::storage::init::connect;
+
+#[derive(Args, Debug)]
+struct MigrateArgs {
+    #[command(subcommand)]
+    action: MigrateAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum MigrateAction {
+    /// Apply pending migrations
+    Up {
+        /// Number of migrations to apply, all pending ones if omitted
+        #[arg(short, long)]
+        num: Option<u32>,
+    },
+    /// Revert the latest applied migrations
+    Down {
+        /// Number of migrations to revert
+        #[arg(short, long, default_value_t = 1)]
+        num: u32,
+    },
+    /// List the migrations and whether they are applied
+    Status,
+}
+
+pub fn cli() -> Command {
+    MigrateArgs::augment_args(
+        Command::new("migrate").about("Apply or revert migrations of the database schema"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = MigrateArgs::from_arg_matches(args)?;
+    let conn = connect(&config.database).await;
+    match args.action {
+        MigrateAction::Up { num } => Migrator::up(&conn, num).await?,
+        MigrateAction::Down { num } => Migrator::down(&conn, Some(num)).await?,
+        MigrateAction::Status => {
+            for migration in Migrator::get_migration_with_status(&conn).await? {
+                let status = match migration.status() {
+                    MigrationStatus::Applied => "applied",
+                    MigrationStatus::Pending => "pending",
+                };
+                println!("{:<8} {}", status, migration.name());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
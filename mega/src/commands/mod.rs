@@ -1,16 +1,69 @@
+mod admin;
+mod backup;
+mod config;
+mod doctor;
+mod gc;
+mod import;
+mod migrate;
+#[cfg(target_os = "linux")]
+mod mount;
+mod p2p;
+mod policy;
+mod quota;
+#[cfg(debug_assertions)]
+mod seed;
 mod service;
+mod stats;
+mod storage;
 
 use clap::{ArgMatches, Command};
 
 use common::{config::Config, errors::MegaResult};
 
+#[cfg(unix)]
+pub(crate) use service::start_daemon;
+
 pub fn builtin() -> Vec<Command> {
-    vec![service::cli()]
+    vec![
+        service::cli(),
+        backup::cli(),
+        migrate::cli(),
+        storage::cli(),
+        quota::cli(),
+        policy::cli(),
+        admin::cli(),
+        import::cli(),
+        doctor::cli(),
+        gc::cli(),
+        config::cli(),
+        stats::cli(),
+        p2p::cli(),
+        #[cfg(debug_assertions)]
+        seed::cli(),
+        #[cfg(target_os = "linux")]
+        mount::cli(),
+    ]
 }
 
 pub(crate) fn builtin_exec(cmd: &str) -> Option<fn(Config, &ArgMatches) -> MegaResult> {
     let f = match cmd {
         "service" => service::exec,
+        "backup" => backup::exec,
+        "migrate" => migrate::exec,
+        "storage" => storage::exec,
+        "quota" => quota::exec,
+        "policy" => policy::exec,
+        "admin" => admin::exec,
+        "import" => import::exec,
+        "doctor" => doctor::exec,
+        "gc" => gc::exec,
+        "config" => config::exec,
+        "stats" => stats::exec,
+        "p2p" => p2p::exec,
+        #[cfg(debug_assertions)]
+        "seed" => seed::exec,
+        #[cfg(target_os = "linux")]
+        "mount" => mount::exec,
         _ => return None,
     };
 
@@ -0,0 +1,433 @@
+//! Read-only FUSE filesystem over the trees of a monorepo commit.
+//!
+//! Inodes are handed out as directories are listed, a directory is listed by the mono server the
+//! first time it is looked into. File content is addressed by object id, it is downloaded on the
+//! first open and kept in the cache directory where it never goes stale.
+
+use std::ffi::{OsStr, OsString};
+use std::io::SeekFrom;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use std::vec::IntoIter;
+
+use bytes::Bytes;
+use fuse3::raw::prelude::*;
+use fuse3::{Errno, FileType, Inode, Result, Timestamp};
+use futures::stream::{iter, Iter};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::RwLock;
+
+use ceres::model::tree::{TreeEntries, TreeEntry};
+use common::errors::MegaError;
+use common::model::CommonResult;
+use mercury::internal::object::tree::TreeItemMode;
+
+/// The mounted commit never changes, the kernel may cache entries and attributes for long.
+const TTL: Duration = Duration::from_secs(3600);
+
+/// `FOPEN_DIRECT_IO`, reads bypass the page cache and are not cut at the reported size.
+const FOPEN_DIRECT_IO: u32 = 1;
+
+/// Client of the mono server API used by the mount.
+pub struct MonoClient {
+    client: reqwest::Client,
+    server: String,
+}
+
+impl MonoClient {
+    pub fn new(server: &str) -> Self {
+        MonoClient {
+            client: reqwest::Client::new(),
+            server: server.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Entries of the monorepo directory `path` at `refs`, the latest commit if empty.
+    pub async fn tree(
+        &self,
+        path: &str,
+        refs: &str,
+    ) -> std::result::Result<TreeEntries, MegaError> {
+        let res: CommonResult<TreeEntries> = self
+            .client
+            .get(format!("{}/api/v1/tree/entries", self.server))
+            .query(&[("path", path), ("refs", refs)])
+            .send()
+            .await
+            .map_err(http_error)?
+            .json()
+            .await
+            .map_err(http_error)?;
+        match res.data {
+            Some(data) if res.req_result => Ok(data),
+            _ => Err(MegaError::with_message(&format!(
+                "failed to list {}: {}",
+                path, res.err_message
+            ))),
+        }
+    }
+
+    pub async fn blob(&self, oid: &str) -> std::result::Result<Bytes, MegaError> {
+        self.client
+            .get(format!("{}/api/v1/file/blob/{}", self.server, oid))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(http_error)?
+            .bytes()
+            .await
+            .map_err(http_error)
+    }
+}
+
+fn http_error(err: reqwest::Error) -> MegaError {
+    MegaError::with_message(&err.to_string())
+}
+
+struct Node {
+    parent: Inode,
+    /// Path of the object in the monorepo
+    path: String,
+    oid: String,
+    mode: TreeItemMode,
+    size: u64,
+    /// Entries of a directory, `None` until it is listed
+    children: Option<Vec<(OsString, Inode)>>,
+}
+
+impl Node {
+    fn kind(&self) -> FileType {
+        match self.mode {
+            // submodules are shown as empty directories
+            TreeItemMode::Tree | TreeItemMode::Commit => FileType::Directory,
+            TreeItemMode::Link => FileType::Symlink,
+            TreeItemMode::Blob | TreeItemMode::BlobExecutable => FileType::RegularFile,
+        }
+    }
+}
+
+pub struct MonoFs {
+    client: MonoClient,
+    commit: String,
+    cache: PathBuf,
+    mounted_at: Timestamp,
+    /// The node of inode `n` is at index `n - 1`, the root is inode 1
+    nodes: RwLock<Vec<Node>>,
+}
+
+impl MonoFs {
+    /// Filesystem of the directory `path` listed by `root`, it serves the commit of `root`.
+    pub fn new(client: MonoClient, path: &str, root: TreeEntries, cache: PathBuf) -> Self {
+        let mut nodes = vec![Node {
+            parent: 1,
+            path: path.to_owned(),
+            oid: root.oid,
+            mode: TreeItemMode::Tree,
+            size: 0,
+            children: None,
+        }];
+        insert_children(&mut nodes, 1, root.entries);
+        MonoFs {
+            client,
+            commit: root.commit,
+            cache,
+            mounted_at: Timestamp::from(SystemTime::now()),
+            nodes: RwLock::new(nodes),
+        }
+    }
+
+    /// Entries of the directory `inode`, listed by the server on first use.
+    async fn children(&self, inode: Inode) -> Result<Vec<(OsString, Inode)>> {
+        let path = {
+            let nodes = self.nodes.read().await;
+            let node = get_node(&nodes, inode)?;
+            match (&node.children, node.mode) {
+                (Some(children), _) => return Ok(children.clone()),
+                (None, TreeItemMode::Tree) => node.path.clone(),
+                (None, TreeItemMode::Commit) => return Ok(vec![]),
+                _ => return Err(libc::ENOTDIR.into()),
+            }
+        };
+        let listing = self.client.tree(&path, &self.commit).await.map_err(|err| {
+            tracing::warn!("{}", err);
+            Errno::from(libc::EIO)
+        })?;
+        let mut nodes = self.nodes.write().await;
+        // listed by a concurrent lookup in the meantime
+        if let Some(children) = &get_node(&nodes, inode)?.children {
+            return Ok(children.clone());
+        }
+        Ok(insert_children(&mut nodes, inode, listing.entries))
+    }
+
+    fn attr(&self, req: &Request, inode: Inode, node: &Node) -> FileAttr {
+        let perm = match node.mode {
+            TreeItemMode::Tree | TreeItemMode::Commit | TreeItemMode::BlobExecutable => 0o555,
+            TreeItemMode::Link => 0o777,
+            TreeItemMode::Blob => 0o444,
+        };
+        FileAttr {
+            ino: inode,
+            size: node.size,
+            blocks: node.size.div_ceil(512),
+            atime: self.mounted_at,
+            mtime: self.mounted_at,
+            ctime: self.mounted_at,
+            kind: node.kind(),
+            perm,
+            nlink: if node.kind() == FileType::Directory {
+                2
+            } else {
+                1
+            },
+            uid: req.uid,
+            gid: req.gid,
+            rdev: 0,
+            blksize: 4096,
+        }
+    }
+
+    async fn entry(&self, req: &Request, inode: Inode) -> Result<ReplyEntry> {
+        let nodes = self.nodes.read().await;
+        Ok(ReplyEntry {
+            ttl: TTL,
+            attr: self.attr(req, inode, get_node(&nodes, inode)?),
+            generation: 0,
+        })
+    }
+
+    /// Download the content of `oid` into the cache unless it is there, returns its size.
+    async fn fetch(&self, oid: &str) -> Result<u64> {
+        let path = self.cache.join(oid);
+        if let Ok(meta) = tokio::fs::metadata(&path).await {
+            return Ok(meta.len());
+        }
+        let data = self.client.blob(oid).await.map_err(|err| {
+            tracing::warn!("failed to download {}: {}", oid, err);
+            Errno::from(libc::EIO)
+        })?;
+        // written aside first so concurrent opens never see a partial file
+        let tmp = self
+            .cache
+            .join(format!("{}.{}.tmp", oid, rand::random::<u32>()));
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(data.len() as u64)
+    }
+
+    async fn dir_entries(&self, parent: Inode) -> Result<Vec<(Inode, OsString)>> {
+        let children = self.children(parent).await?;
+        let nodes = self.nodes.read().await;
+        let mut entries = vec![
+            (parent, OsString::from(".")),
+            (get_node(&nodes, parent)?.parent, OsString::from("..")),
+        ];
+        entries.extend(children.into_iter().map(|(name, inode)| (inode, name)));
+        Ok(entries)
+    }
+}
+
+fn get_node(nodes: &[Node], inode: Inode) -> Result<&Node> {
+    nodes
+        .get((inode as usize).wrapping_sub(1))
+        .ok_or_else(Errno::new_not_exist)
+}
+
+fn insert_children(
+    nodes: &mut Vec<Node>,
+    parent: Inode,
+    entries: Vec<TreeEntry>,
+) -> Vec<(OsString, Inode)> {
+    let base = nodes[parent as usize - 1]
+        .path
+        .trim_end_matches('/')
+        .to_owned();
+    let mut children = Vec::new();
+    for entry in entries {
+        nodes.push(Node {
+            parent,
+            path: format!("{}/{}", base, entry.name),
+            oid: entry.oid,
+            mode: entry.mode,
+            size: entry.size,
+            children: None,
+        });
+        children.push((OsString::from(entry.name), nodes.len() as Inode));
+    }
+    nodes[parent as usize - 1].children = Some(children.clone());
+    children
+}
+
+impl Filesystem for MonoFs {
+    type DirEntryStream<'a>
+        = Iter<IntoIter<Result<DirectoryEntry>>>
+    where
+        Self: 'a;
+    type DirEntryPlusStream<'a>
+        = Iter<IntoIter<Result<DirectoryEntryPlus>>>
+    where
+        Self: 'a;
+
+    async fn init(&self, _req: Request) -> Result<ReplyInit> {
+        Ok(ReplyInit {
+            max_write: NonZeroU32::new(128 * 1024).unwrap(),
+        })
+    }
+
+    async fn destroy(&self, _req: Request) {}
+
+    async fn lookup(&self, req: Request, parent: Inode, name: &OsStr) -> Result<ReplyEntry> {
+        let inode = self
+            .children(parent)
+            .await?
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, inode)| inode)
+            .ok_or_else(Errno::new_not_exist)?;
+        self.entry(&req, inode).await
+    }
+
+    async fn getattr(
+        &self,
+        req: Request,
+        inode: Inode,
+        _fh: Option<u64>,
+        _flags: u32,
+    ) -> Result<ReplyAttr> {
+        let entry = self.entry(&req, inode).await?;
+        Ok(ReplyAttr {
+            ttl: entry.ttl,
+            attr: entry.attr,
+        })
+    }
+
+    async fn readlink(&self, _req: Request, inode: Inode) -> Result<ReplyData> {
+        let oid = get_node(&*self.nodes.read().await, inode)?.oid.clone();
+        self.fetch(&oid).await?;
+        let data = tokio::fs::read(self.cache.join(&oid)).await?;
+        Ok(ReplyData {
+            data: Bytes::from(data),
+        })
+    }
+
+    async fn open(&self, _req: Request, inode: Inode, flags: u32) -> Result<ReplyOpen> {
+        if flags & (libc::O_WRONLY | libc::O_RDWR) as u32 != 0 {
+            return Err(libc::EROFS.into());
+        }
+        let (oid, size) = {
+            let nodes = self.nodes.read().await;
+            let node = get_node(&nodes, inode)?;
+            if node.kind() != FileType::RegularFile {
+                return Err(libc::EISDIR.into());
+            }
+            (node.oid.clone(), node.size)
+        };
+        let actual = self.fetch(&oid).await?;
+        if actual == size {
+            return Ok(ReplyOpen { fh: 0, flags: 0 });
+        }
+        // the server does not know the size of every blob, fix it up for later stats and let
+        // this read run to the end of the file
+        self.nodes.write().await[inode as usize - 1].size = actual;
+        Ok(ReplyOpen {
+            fh: 0,
+            flags: FOPEN_DIRECT_IO,
+        })
+    }
+
+    async fn read(
+        &self,
+        _req: Request,
+        inode: Inode,
+        _fh: u64,
+        offset: u64,
+        size: u32,
+    ) -> Result<ReplyData> {
+        let oid = get_node(&*self.nodes.read().await, inode)?.oid.clone();
+        let mut file = tokio::fs::File::open(self.cache.join(&oid)).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = Vec::with_capacity(size as usize);
+        file.take(size as u64).read_to_end(&mut data).await?;
+        Ok(ReplyData {
+            data: Bytes::from(data),
+        })
+    }
+
+    async fn opendir(&self, _req: Request, _inode: Inode, _flags: u32) -> Result<ReplyOpen> {
+        Ok(ReplyOpen { fh: 0, flags: 0 })
+    }
+
+    async fn readdir(
+        &self,
+        _req: Request,
+        parent: Inode,
+        _fh: u64,
+        offset: i64,
+    ) -> Result<ReplyDirectory<Self::DirEntryStream<'_>>> {
+        let entries = self.dir_entries(parent).await?;
+        let nodes = self.nodes.read().await;
+        let entries: Vec<Result<DirectoryEntry>> = entries
+            .into_iter()
+            .enumerate()
+            .skip(offset as usize)
+            .map(|(index, (inode, name))| {
+                Ok(DirectoryEntry {
+                    inode,
+                    kind: get_node(&nodes, inode)?.kind(),
+                    name,
+                    offset: index as i64 + 1,
+                })
+            })
+            .collect();
+        Ok(ReplyDirectory {
+            entries: iter(entries),
+        })
+    }
+
+    async fn readdirplus(
+        &self,
+        req: Request,
+        parent: Inode,
+        _fh: u64,
+        offset: u64,
+        _lock_owner: u64,
+    ) -> Result<ReplyDirectoryPlus<Self::DirEntryPlusStream<'_>>> {
+        let entries = self.dir_entries(parent).await?;
+        let nodes = self.nodes.read().await;
+        let entries: Vec<Result<DirectoryEntryPlus>> = entries
+            .into_iter()
+            .enumerate()
+            .skip(offset as usize)
+            .map(|(index, (inode, name))| {
+                let node = get_node(&nodes, inode)?;
+                Ok(DirectoryEntryPlus {
+                    inode,
+                    generation: 0,
+                    kind: node.kind(),
+                    name,
+                    offset: index as i64 + 1,
+                    attr: self.attr(&req, inode, node),
+                    entry_ttl: TTL,
+                    attr_ttl: TTL,
+                })
+            })
+            .collect();
+        Ok(ReplyDirectoryPlus {
+            entries: iter(entries),
+        })
+    }
+
+    async fn statfs(&self, _req: Request, _inode: Inode) -> Result<ReplyStatFs> {
+        Ok(ReplyStatFs {
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: self.nodes.read().await.len() as u64,
+            ffree: 0,
+            bsize: 4096,
+            namelen: 255,
+            frsize: 4096,
+        })
+    }
+}
@@ -0,0 +1,76 @@
+//! This module is responsible for handling the 'mount' command.
+//! It mounts the monorepo served by a mono server as a read-only FUSE filesystem. Directories
+//! are listed and files are downloaded when they are first accessed, so huge trees can be
+//! browsed and built from without a checkout.
+//!
+//! The mount keeps serving the commit resolved when it was mounted, unmount it with
+//! `fusermount -u <mountpoint>`.
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+use fuse3::raw::Session;
+use fuse3::MountOptions;
+
+use common::{config::Config, errors::MegaResult};
+
+use self::fs::{MonoClient, MonoFs};
+
+mod fs;
+
+#[derive(Args, Debug)]
+struct MountArgs {
+    /// Directory the monorepo is mounted at
+    mountpoint: PathBuf,
+
+    /// Address of the mono server
+    #[arg(long, default_value = "http://localhost:8000")]
+    server: String,
+
+    /// Commit id or ref of the monorepo root to mount, the latest commit if omitted
+    #[arg(long, default_value = "")]
+    refs: String,
+
+    /// Directory of the monorepo mounted as the root of the filesystem
+    #[arg(long, default_value = "/")]
+    path: String,
+
+    /// Directory downloaded files are kept in, `mount-cache` below the base dir if omitted
+    #[arg(long)]
+    cache: Option<PathBuf>,
+}
+
+pub fn cli() -> Command {
+    MountArgs::augment_args(
+        Command::new("mount").about("Mount the monorepo as a read-only filesystem"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = MountArgs::from_arg_matches(args)?;
+    let cache = args
+        .cache
+        .unwrap_or_else(|| config.base_dir.join("mount-cache"));
+    std::fs::create_dir_all(&cache)?;
+
+    let client = MonoClient::new(&args.server);
+    let root = client.tree(&args.path, &args.refs).await?;
+    println!(
+        "mounting {} at commit {} on {}",
+        args.path,
+        root.commit,
+        args.mountpoint.display()
+    );
+    let fs = MonoFs::new(client, &args.path, root, cache);
+
+    let mut options = MountOptions::default();
+    options.fs_name("mega").read_only(true);
+    let handle = Session::new(options)
+        .mount_with_unprivileged(fs, &args.mountpoint)
+        .await?;
+    handle.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
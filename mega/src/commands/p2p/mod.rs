@@ -0,0 +1,147 @@
+//! This module is responsible for handling the 'p2p' command.
+//! It publishes repositories to the peers of the ztm network and subscribes to the ones of other
+//! peers, the running server provides them in the DHT and syncs the subscriptions, see
+//! `gemini::sync`. The peers are authorized as the principals they are mapped to, as anonymous
+//! users otherwise.
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+};
+use gemini::sync::{self, Subscription};
+use jupiter::context::Context;
+use mono::git_protocol;
+
+#[derive(Args, Debug)]
+struct P2pArgs {
+    #[command(subcommand)]
+    action: P2pAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum P2pAction {
+    /// Publish a repository or monorepo directory, printing its identifier
+    Publish {
+        #[arg(long)]
+        path: String,
+        /// Name of the repository in its identifier
+        #[arg(long)]
+        alias: String,
+    },
+    /// Mirror the repository of another peer and keep it in sync
+    Subscribe {
+        /// `p2p://` identifier of the repository
+        identifier: String,
+        /// Path of the mirror below `monorepo.import_dir`, its alias there by default
+        #[arg(long)]
+        path: Option<String>,
+    },
+    /// Stop syncing a repository, its mirror is kept
+    Unsubscribe { identifier: String },
+    /// List the subscribed repositories and when they were synced
+    List,
+    /// Authorize the requests of a peer as a user or service account
+    MapPeer {
+        peer_id: String,
+        /// Name of the user, or `name[bot]` of the service account
+        principal: String,
+    },
+    /// Authorize the requests of a peer as an anonymous user again
+    UnmapPeer { peer_id: String },
+    /// List the peers and the principals they are authorized as
+    Peers,
+}
+
+pub fn cli() -> Command {
+    P2pArgs::augment_args(
+        Command::new("p2p").about("Publish and subscribe to repositories over the ztm network"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = P2pArgs::from_arg_matches(args)?;
+    let context = Context::new(config).await;
+    match args.action {
+        P2pAction::Publish { path, alias } => {
+            let identifier = sync::publish(&context, &path, &alias)
+                .await
+                .map_err(|e| MegaError::with_message(&e))?;
+            println!("{}", identifier);
+        }
+        P2pAction::Subscribe { identifier, path } => {
+            let subscription = sync::subscribe(&context, &identifier, path)
+                .await
+                .map_err(|e| MegaError::with_message(&e))?;
+            println!(
+                "subscribed to {}, mirrored at {}",
+                subscription.identifier, subscription.local_path
+            );
+        }
+        P2pAction::Unsubscribe { identifier } => {
+            sync::unsubscribe(&context, None, &identifier)
+                .await
+                .map_err(|e| MegaError::with_message(&e))?;
+        }
+        P2pAction::List => {
+            for model in context.services.ztm_storage.get_all_subscriptions().await? {
+                let s = Subscription::from(model);
+                let state = match (&s.announcement, &s.last_error) {
+                    (_, Some(err)) => format!("failed: {}", err),
+                    (Some(announcement), None) => format!("{} refs", announcement.refs.len()),
+                    (None, None) => "not synced".to_owned(),
+                };
+                let synced_at = s
+                    .synced_at
+                    .and_then(chrono::DateTime::from_timestamp_millis)
+                    .map(|t| t.to_rfc3339())
+                    .unwrap_or_else(|| "-".to_owned());
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    s.identifier, s.local_path, synced_at, state
+                );
+            }
+        }
+        P2pAction::MapPeer { peer_id, principal } => {
+            if !git_protocol::is_active_principal(&context, &principal).await {
+                return Err(MegaError::with_message(&format!(
+                    "{} is not an active user or service account",
+                    principal
+                )));
+            }
+            context
+                .services
+                .ztm_storage
+                .save_peer_principal(&peer_id, &principal)
+                .await?;
+        }
+        P2pAction::UnmapPeer { peer_id } => {
+            if !context
+                .services
+                .ztm_storage
+                .delete_peer_principal(&peer_id)
+                .await?
+            {
+                return Err(MegaError::with_message(&format!(
+                    "{} is not mapped to a principal",
+                    peer_id
+                )));
+            }
+        }
+        P2pAction::Peers => {
+            for model in context
+                .services
+                .ztm_storage
+                .get_all_peer_principals()
+                .await?
+            {
+                println!("{}\t{}", model.peer_id, model.principal);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
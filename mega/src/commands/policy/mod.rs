@@ -0,0 +1,124 @@
+//! This module is responsible for handling the 'policy' command.
+//! It runs the test suites of the Cedar policies so that changes can be checked in CI
+//! before they are deployed, see `saturn::policy_test` for the format of the suites.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use common::{
+    config::{Config, PolicyConfig},
+    errors::{MegaError, MegaResult},
+};
+use saturn::{
+    policy::{is_valid_namespace, PolicyStore},
+    policy_test::TestSuite,
+};
+
+#[derive(Args, Debug)]
+struct PolicyArgs {
+    #[command(subcommand)]
+    action: PolicyAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum PolicyAction {
+    /// Run test suites against the policies, failing if any case does not get the expected decision
+    Test {
+        /// JSON files with the test cases
+        #[arg(required = true)]
+        suites: Vec<PathBuf>,
+        /// Cedar schema file, `policy.schema_path` by default
+        #[arg(long)]
+        schema: Option<String>,
+        /// Cedar policy file, `policy.policy_path` by default
+        #[arg(long)]
+        policies: Option<String>,
+        /// Policies of a namespace as `name=file`, can be repeated
+        #[arg(long = "namespace", value_parser = parse_namespace)]
+        namespaces: Vec<(String, PathBuf)>,
+    },
+}
+
+pub fn cli() -> Command {
+    PolicyArgs::augment_args(Command::new("policy").about("Check the Cedar policies"))
+}
+
+pub(crate) fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = PolicyArgs::from_arg_matches(args)?;
+    match args.action {
+        PolicyAction::Test {
+            suites,
+            schema,
+            policies,
+            namespaces,
+        } => {
+            let store = PolicyStore::new(&PolicyConfig {
+                schema_path: schema.unwrap_or(config.policy.schema_path),
+                policy_path: policies.unwrap_or(config.policy.policy_path),
+                action_schemas: config.policy.action_schemas,
+                ..Default::default()
+            })
+            .map_err(|e| MegaError::with_message(&e.to_string()))?;
+            let mut contents = BTreeMap::new();
+            for (name, path) in namespaces {
+                contents.insert(name, fs::read_to_string(path)?);
+            }
+            store
+                .set_namespaces(contents)
+                .map_err(|e| MegaError::with_message(&e.to_string()))?;
+
+            let (mut passed, mut failed) = (0, 0);
+            for path in suites {
+                let suite = TestSuite::parse(&fs::read_to_string(&path)?)
+                    .map_err(|e| MegaError::with_message(&format!("{}: {}", path.display(), e)))?;
+                for result in suite.run(store.current()) {
+                    let outcome = match &result.outcome {
+                        Ok(decision) => format!(
+                            "{} by [{}]",
+                            if decision.is_allowed() {
+                                "allow"
+                            } else {
+                                "deny"
+                            },
+                            decision.policies().join(", ")
+                        ),
+                        Err(err) => format!("error: {}", err),
+                    };
+                    if result.passed() {
+                        passed += 1;
+                        println!("ok\t{}: {}\t{}", path.display(), result.case.name, outcome);
+                    } else {
+                        failed += 1;
+                        println!(
+                            "FAIL\t{}: {}\texpected {:?}, got {}",
+                            path.display(),
+                            result.case.name,
+                            result.case.expect,
+                            outcome
+                        );
+                    }
+                }
+            }
+            println!("{} passed, {} failed", passed, failed);
+            if failed > 0 {
+                return Err(MegaError::with_message(&format!(
+                    "{} policy test cases failed",
+                    failed
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_namespace(s: &str) -> Result<(String, PathBuf), String> {
+    let (name, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=file: {}", s))?;
+    if !is_valid_namespace(name) {
+        return Err(format!("invalid namespace: {}", name));
+    }
+    Ok((name.to_owned(), PathBuf::from(path)))
+}
@@ -0,0 +1,82 @@
+//! This module is responsible for handling the 'quota' command.
+//! It reports the storage used by repositories and namespaces against the limits of `quota`.
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use ceres::quota;
+use common::{config::Config, errors::MegaResult};
+use jupiter::context::Context;
+
+const MB: f64 = 1024.0 * 1024.0;
+
+#[derive(Args, Debug)]
+struct QuotaArgs {
+    #[command(subcommand)]
+    action: QuotaAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum QuotaAction {
+    /// Show the storage used by the repositories below a directory
+    Usage {
+        #[arg(long, default_value = "/")]
+        path: String,
+    },
+    /// Show the storage used by every organization and user, largest first
+    Namespaces,
+}
+
+pub fn cli() -> Command {
+    QuotaArgs::augment_args(
+        Command::new("quota").about("Report the storage used by repositories and namespaces"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = QuotaArgs::from_arg_matches(args)?;
+    let context = Context::new(config).await;
+    match args.action {
+        QuotaAction::Usage { path } => {
+            let usage = context.services.quota_storage.list_usage(&path).await?;
+            for u in usage {
+                let limit = quota::repo_limit(&context.config.quota, &u.path);
+                println!(
+                    "{}\tgit {}\tlfs {}\tlimit {}",
+                    u.path,
+                    format_size(u.git_size as u64),
+                    format_size(u.lfs_size as u64),
+                    format_limit(limit)
+                );
+            }
+        }
+        QuotaAction::Namespaces => {
+            for n in quota::namespace_usage(&context).await? {
+                println!(
+                    "{:?} {}\tgit {}\tlfs {}\tlimit {}\t{}",
+                    n.kind,
+                    n.name,
+                    format_size(n.git_size as u64),
+                    format_size(n.lfs_size as u64),
+                    format_limit(n.limit),
+                    n.paths.join(",")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn format_size(size: u64) -> String {
+    format!("{:.1} MB", size as f64 / MB)
+}
+
+fn format_limit(limit: u64) -> String {
+    if limit == 0 {
+        "unlimited".to_owned()
+    } else {
+        format_size(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {}
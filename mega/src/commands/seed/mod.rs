@@ -0,0 +1,283 @@
+//! This module is responsible for handling the 'seed' command, only built in debug builds.
+//! It fills the database with synthetic users, repositories, issues and merge requests for
+//! development and load tests. The same seed and sizes give the same data set.
+use std::path::Path;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use callisto::db_enums::{ConvType, MergeStatus};
+use ceres::api_service::mono_api_service::MonoApiService;
+use ceres::api_service::ApiHandler;
+use ceres::model::create_file::CreateFileInfo;
+use ceres::protocol::mr::MergeRequest;
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+    utils::generate_link,
+};
+use jupiter::context::Context;
+
+const NAMES: &[&str] = &[
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi", "ivan", "judy",
+];
+
+const WORDS: &[&str] = &[
+    "cache", "index", "parser", "buffer", "client", "server", "queue", "config", "store", "token",
+    "stream", "worker", "router", "schema", "widget", "report",
+];
+
+/// Directories of a repository the files are committed to, the repository itself first.
+const DIRS: &[&str] = &["", "src", "docs", "tests"];
+
+#[derive(Args, Debug)]
+struct SeedArgs {
+    /// Users to create, existing users with the same names are reused
+    #[arg(long, default_value_t = 10)]
+    users: usize,
+    /// Repositories to create below the path
+    #[arg(long, default_value_t = 5)]
+    repos: usize,
+    /// Commits of every repository
+    #[arg(long, default_value_t = 20)]
+    commits: usize,
+    /// Issues to create
+    #[arg(long, default_value_t = 20)]
+    issues: usize,
+    /// Merge requests to create, at most one per repository stays open
+    #[arg(long, default_value_t = 10)]
+    mrs: usize,
+    /// Seed of the generated data
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Directory of the monorepo the repositories are created in, it must not exist yet
+    #[arg(long, default_value = "/seed")]
+    path: String,
+}
+
+pub fn cli() -> Command {
+    SeedArgs::augment_args(
+        Command::new("seed").about("Fill the database with synthetic data for development"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = SeedArgs::from_arg_matches(args)?;
+    let root = Path::new(&args.path);
+    let (Some(parent), Some(name)) = (root.parent(), root.file_name()) else {
+        return Err(MegaError::with_message(&format!(
+            "invalid path: {}",
+            args.path
+        )));
+    };
+    let (parent, name) = (parent.to_str().unwrap(), name.to_str().unwrap());
+    let context = Context::new(config.clone()).await;
+    context
+        .services
+        .mono_storage
+        .init_monorepo(&config.monorepo)
+        .await;
+    let service = MonoApiService {
+        context: context.clone(),
+    };
+    let mut rng = StdRng::seed_from_u64(args.seed);
+
+    // fails early if the directory exists
+    create(&service, parent, name, None).await?;
+    let users = seed_users(&context, &mut rng, args.users).await?;
+    println!("{} users", users.len());
+
+    let root = root.to_str().unwrap().trim_end_matches('/');
+    let mut histories = Vec::with_capacity(args.repos);
+    for i in 0..args.repos {
+        let repo = format!("{}-{}", WORDS.choose(&mut rng).unwrap(), i + 1);
+        let path = format!("{}/{}", root, repo);
+        create(&service, root, &repo, None).await?;
+        let history = seed_commits(&service, &context, &mut rng, &path, args.commits).await?;
+        println!("{} with {} commits", path, history.len());
+        histories.push((path, history));
+    }
+
+    for i in 0..args.issues {
+        seed_issue(&context, &mut rng, &users, i).await?;
+    }
+    println!("{} issues", args.issues);
+
+    let mut open = vec![false; histories.len()];
+    let mut mrs = 0;
+    for i in 0..args.mrs {
+        let repo = rng.gen_range(0..histories.len().max(1));
+        let Some((path, history)) = histories.get(repo) else {
+            break;
+        };
+        if history.len() < 2 {
+            continue;
+        }
+        let at = rng.gen_range(1..history.len());
+        let mut status = *[MergeStatus::Open, MergeStatus::Merged, MergeStatus::Closed]
+            .choose(&mut rng)
+            .unwrap();
+        if status == MergeStatus::Open && std::mem::replace(&mut open[repo], true) {
+            status = MergeStatus::Merged;
+        }
+        let mr = MergeRequest {
+            link: generate_link(),
+            title: format!("Update {} ({})", WORDS.choose(&mut rng).unwrap(), i + 1),
+            status,
+            merge_date: (status == MergeStatus::Merged).then(|| chrono::Utc::now().naive_utc()),
+            path: path.clone(),
+            from_hash: history[at - 1].clone(),
+            to_hash: history[at].clone(),
+            ..Default::default()
+        };
+        let link = mr.link.clone();
+        context.mr_stg().save_mr(mr.into()).await?;
+        for _ in 0..rng.gen_range(0..4) {
+            let user = users.choose(&mut rng).unwrap();
+            let comment = Some(sentence(&mut rng));
+            context
+                .mr_stg()
+                .add_mr_conversation(&link, *user, ConvType::Comment, comment)
+                .await?;
+        }
+        let end = match status {
+            MergeStatus::Open => None,
+            MergeStatus::Merged => Some(ConvType::Merged),
+            MergeStatus::Closed => Some(ConvType::Closed),
+        };
+        if let Some(conv_type) = end {
+            let user = users.choose(&mut rng).unwrap();
+            context
+                .mr_stg()
+                .add_mr_conversation(&link, *user, conv_type, None)
+                .await?;
+        }
+        mrs += 1;
+    }
+    println!("{} merge requests", mrs);
+    Ok(())
+}
+
+/// Create the users, returns their ids.
+async fn seed_users(
+    context: &Context,
+    rng: &mut StdRng,
+    count: usize,
+) -> Result<Vec<i64>, MegaError> {
+    let storage = context.user_stg();
+    let mut res = Vec::with_capacity(count.max(1));
+    for i in 0..count.max(1) {
+        let name = format!("{}-{}", NAMES.choose(rng).unwrap(), i + 1);
+        let user = match storage.find_user_by_name(&name).await? {
+            Some(user) => user,
+            None => {
+                let email = format!("{}@example.com", name);
+                storage.create_user(&name, &email).await?
+            }
+        };
+        res.push(user.id);
+    }
+    Ok(res)
+}
+
+/// Commit `count` files to the repository at `path`, returns the commits of the monorepo
+/// after each of them.
+async fn seed_commits(
+    service: &MonoApiService,
+    context: &Context,
+    rng: &mut StdRng,
+    path: &str,
+    count: usize,
+) -> Result<Vec<String>, MegaError> {
+    let mut dirs = vec![path.to_owned()];
+    let mut res = Vec::with_capacity(count);
+    for i in 0..count {
+        let dir = DIRS[rng.gen_range(0..DIRS.len())];
+        let dir_path = if dir.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{}/{}", path, dir)
+        };
+        if !dirs.contains(&dir_path) {
+            create(service, path, dir, None).await?;
+            dirs.push(dir_path.clone());
+        }
+        let file = format!("{}_{}.rs", WORDS.choose(rng).unwrap(), i + 1);
+        let lines = rng.gen_range(3..40);
+        let content = (0..lines)
+            .map(|_| format!("// {}\n", sentence(rng)))
+            .collect();
+        create(service, &dir_path, &file, Some(content)).await?;
+        let root = context.services.mono_storage.get_ref("/").await?.unwrap();
+        res.push(root.ref_commit_hash);
+    }
+    Ok(res)
+}
+
+async fn seed_issue(
+    context: &Context,
+    rng: &mut StdRng,
+    users: &[i64],
+    i: usize,
+) -> Result<(), MegaError> {
+    let storage = context.issue_stg();
+    let conn = storage.get_connection();
+    let owner = *users.choose(rng).unwrap();
+    let title = format!(
+        "{} fails to {} ({})",
+        capitalize(WORDS.choose(rng).unwrap()),
+        WORDS.choose(rng).unwrap(),
+        i + 1
+    );
+    let issue = storage.save_issue(conn, owner, &title).await?;
+    for _ in 0..rng.gen_range(0..5) {
+        let user = *users.choose(rng).unwrap();
+        storage
+            .add_issue_conversation(conn, &issue.link, user, Some(sentence(rng)))
+            .await?;
+    }
+    if rng.gen_bool(0.4) {
+        storage.close_issue(conn, &issue.link).await?;
+    }
+    Ok(())
+}
+
+/// Create the directory, or the file with `content`, `name` in `path`.
+async fn create(
+    service: &MonoApiService,
+    path: &str,
+    name: &str,
+    content: Option<String>,
+) -> Result<(), MegaError> {
+    service
+        .create_monorepo_file(CreateFileInfo {
+            is_directory: content.is_none(),
+            name: name.to_owned(),
+            path: path.to_owned(),
+            content,
+        })
+        .await
+        .map_err(|err| {
+            MegaError::with_message(&format!("failed to create {}/{}: {}", path, name, err))
+        })
+}
+
+fn sentence(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(3..10);
+    let words: Vec<&str> = (0..len).map(|_| *WORDS.choose(rng).unwrap()).collect();
+    capitalize(&words.join(" ")) + "."
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {}
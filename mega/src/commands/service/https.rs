@@ -16,11 +16,13 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
 
     tracing::info!("{server_matchers:#?}");
     let context = Context::new(config.clone()).await;
+    super::watch_config(&context);
     context
         .services
         .mono_storage
         .init_monorepo(&config.monorepo)
         .await;
+    common::daemon::expect_servers(1);
     https_server::https_server(context, server_matchers).await;
     Ok(())
 }
@@ -1,32 +1,104 @@
 //! This module is responsible for handling the 'service' command.
-//! It includes subcommands for starting different kinds of servers, such as HTTPS and SSH.
+//! It includes subcommands for starting different kinds of servers, such as HTTPS and SSH, and
+//! the relay node of the peers.
 //!
 //!
 //!
-use clap::{ArgMatches, Command};
+use std::path::PathBuf;
+
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 use common::{config::Config, errors::MegaResult};
+use jupiter::context::Context;
 
 mod http;
 mod https;
 mod multi;
+mod relay;
 mod ssh;
+#[cfg(windows)]
+mod windows;
 
 // This function generates the CLI for the 'service' command.
 // It includes subcommands for each server type.
 pub fn cli() -> Command {
-    let subcommands = vec![http::cli(), https::cli(), ssh::cli(), multi::cli()];
-    Command::new("service")
+    let subcommands = vec![
+        http::cli(),
+        https::cli(),
+        ssh::cli(),
+        multi::cli(),
+        relay::cli(),
+    ];
+    let command = Command::new("service")
         .about("Start different kinds of server: for example https or ssh")
-        .subcommands(subcommands)
+        .arg(
+            Arg::new("daemon")
+                .long("daemon")
+                .global(true)
+                .action(ArgAction::SetTrue)
+                .help("Detach from the terminal and run in the background"),
+        )
+        .arg(
+            Arg::new("pid-file")
+                .long("pid-file")
+                .global(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write the pid to this file, `${base_dir}/mega.pid` with --daemon"),
+        )
+        .arg(
+            Arg::new("drain-timeout")
+                .long("drain-timeout")
+                .global(true)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("600")
+                .help("Seconds to finish the transfers in flight after an upgrade on SIGUSR2"),
+        )
+        .subcommands(subcommands);
+    #[cfg(windows)]
+    let command = windows::cli(command);
+    command
+}
+
+/// Detach as a daemon and write the pid file as asked for, before the service starts any
+/// thread.
+#[cfg(unix)]
+pub(crate) fn start_daemon(config: &Config, args: &ArgMatches) -> MegaResult {
+    let daemon = args.get_flag("daemon");
+    let pid_file = match args.get_one::<PathBuf>("pid-file") {
+        Some(path) => Some(path.clone()),
+        None if daemon => Some(config.base_dir.join("mega.pid")),
+        None => None,
+    };
+    common::daemon::start(daemon, pid_file.as_deref())
 }
 
 // This function executes the 'service' command.
 // It determines which subcommand was used and calls the appropriate function.
+pub(crate) fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    #[cfg(windows)]
+    match args.subcommand() {
+        Some(("install", args)) => return windows::install(&config, args),
+        Some(("uninstall", args)) => return windows::uninstall(args),
+        _ => {}
+    }
+    #[cfg(windows)]
+    if let Some(name) = args.get_one::<String>("windows-service") {
+        let args = args.clone();
+        return common::winservice::run(name, move || serve(config, &args));
+    }
+    serve(config, args)
+}
+
 #[tokio::main]
-pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+async fn serve(config: Config, args: &ArgMatches) -> MegaResult {
     use taurus::init::init_mq;
     init_mq(&config).await;
+    #[cfg(unix)]
+    {
+        common::daemon::exit_on_terminate();
+        let drain_timeout = *args.get_one::<u64>("drain-timeout").unwrap();
+        common::daemon::upgrade_on_user_signal(std::time::Duration::from_secs(drain_timeout));
+    }
 
     let (cmd, subcommand_args) = match args.subcommand() {
         Some((cmd, args)) => (cmd, args),
@@ -40,9 +112,22 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
         "https" => https::exec(config, subcommand_args).await,
         "ssh" => ssh::exec(config, subcommand_args).await,
         "multi" => multi::exec(config, subcommand_args).await,
+        "relay" => relay::exec(config, subcommand_args).await,
         _ => Ok(()),
     }
 }
 
+/// Apply config reloads to the log level, and reload the config on SIGHUP, or when the
+/// Windows service control manager asks to.
+fn watch_config(context: &Context) {
+    context
+        .live
+        .on_reload(|config| common::log::set_level(&config.log.level));
+    #[cfg(unix)]
+    context.reload_on_hangup();
+    #[cfg(windows)]
+    context.reload_on_param_change();
+}
+
 #[cfg(test)]
 mod tests {}
@@ -2,14 +2,18 @@ use std::path::PathBuf;
 
 use clap::{ArgMatches, Args, Command, FromArgMatches, ValueEnum};
 
+use ceres::maintenance::{MaintenanceJob, Scheduler, MAINTENANCE_JOB};
 use common::{
     config::Config,
     errors::MegaResult,
     model::{CommonOptions, ZtmOptions},
 };
 use gateway::https_server::{self, HttpOptions, HttpsOptions};
+use gateway::routing::{ServiceInfo, SERVICES};
 use jupiter::context::Context;
+use jupiter::worker::WorkerPool;
 use mono::server::ssh_server::{self, SshCustom, SshOptions};
+use taurus::replay::{ReplayJob, REPLAY_JOB};
 
 #[derive(Debug, PartialEq, Clone, ValueEnum)]
 pub enum StartCommand {
@@ -60,7 +64,23 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
     let service_type = server_matchers.service;
 
     let context = Context::new(config.clone()).await;
+    super::watch_config(&context);
     context.services.mono_storage.init_monorepo(&config.monorepo).await;
+    if config.maintenance.enable {
+        tokio::spawn(Scheduler::new(context.clone()).start());
+    }
+    if config.jobs.enable {
+        let workers = WorkerPool::new(context.clone())
+            .register(MAINTENANCE_JOB, MaintenanceJob)
+            .register(REPLAY_JOB, ReplayJob);
+        tokio::spawn(workers.start());
+    }
+
+    // one of the http and https servers, and the ssh server
+    let web =
+        service_type.contains(&StartCommand::Http) || service_type.contains(&StartCommand::Https);
+    let ssh = service_type.contains(&StartCommand::Ssh);
+    common::daemon::expect_servers(usize::from(web) + usize::from(ssh));
 
     let context_clone = context.clone();
     let http_server = if service_type.contains(&StartCommand::Http) {
@@ -84,6 +104,15 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
     };
 
     let ssh_server = if service_type.contains(&StartCommand::Ssh) {
+        SERVICES.register(ServiceInfo {
+            name: "ssh".to_owned(),
+            protocol: "ssh".to_owned(),
+            address: format!(
+                "{}:{}",
+                server_matchers.common.host, server_matchers.ssh.ssh_port
+            ),
+            routes: Vec::new(),
+        });
         let ssh = SshOptions {
             common: server_matchers.common.clone(),
             custom: server_matchers.ssh,
@@ -0,0 +1,25 @@
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use common::{config::Config, errors::MegaResult};
+use gateway::relay_server::{self, RelayOptions};
+
+pub fn cli() -> Command {
+    RelayOptions::augment_args_for_update(
+        Command::new("relay")
+            .about("Start a relay node forwarding messages between peers behind NATs"),
+    )
+}
+
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let server_matchers = RelayOptions::from_arg_matches(args)
+        .map_err(|err| err.exit())
+        .unwrap();
+    tracing::info!("{server_matchers:#?}");
+    // the relay and its http server
+    common::daemon::expect_servers(2);
+    relay_server::relay_server(config, server_matchers).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
@@ -16,11 +16,13 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
         .unwrap();
     tracing::info!("{server_matchers:#?}");
     let context = Context::new(config.clone()).await;
+    super::watch_config(&context);
     context
         .services
         .mono_storage
         .init_monorepo(&config.monorepo)
         .await;
+    common::daemon::expect_servers(1);
     start_server(context, &server_matchers).await;
     Ok(())
 }
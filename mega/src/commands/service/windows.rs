@@ -0,0 +1,95 @@
+//! Installing `mega service` as a Windows service, which the service control manager starts with
+//! `--windows-service <name>` and the server arguments given on install.
+use std::ffi::OsString;
+
+use clap::{Arg, ArgMatches, Command};
+
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+};
+
+pub(super) fn cli(command: Command) -> Command {
+    command
+        .arg(
+            Arg::new("windows-service")
+                .long("windows-service")
+                .global(true)
+                .value_name("NAME")
+                .help("Run as the Windows service NAME, set by `mega service install`"),
+        )
+        .subcommand(
+            Command::new("install")
+                .about("Install a server as a Windows service started with Windows")
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .default_value("mega")
+                        .help("Name of the service and its event log source"),
+                )
+                .arg(
+                    Arg::new("display-name")
+                        .long("display-name")
+                        .default_value("Mega")
+                        .help("Name of the service shown in the services console"),
+                )
+                .arg(
+                    Arg::new("args")
+                        .required(true)
+                        .num_args(1..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true)
+                        .value_parser(clap::value_parser!(OsString))
+                        .help("The server to run with its arguments, like `multi http ssh`"),
+                ),
+        )
+        .subcommand(
+            Command::new("uninstall")
+                .about("Stop and remove the Windows service")
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .default_value("mega")
+                        .help("Name of the service"),
+                ),
+        )
+}
+
+/// Install the service running the server of `args` with the config file in use, whose path is
+/// made absolute since services start in the system directory.
+pub(super) fn install(config: &Config, args: &ArgMatches) -> MegaResult {
+    let name = args.get_one::<String>("name").unwrap();
+    let display_name = args.get_one::<String>("display-name").unwrap();
+    let config_path = config.path.as_ref().ok_or_else(|| {
+        MegaError::with_message("the service needs a config file, pass it with --config")
+    })?;
+    let config_path = std::path::absolute(config_path)?;
+
+    let mut service_args = vec![OsString::from("--windows-service"), OsString::from(name)];
+    service_args.extend(args.get_many::<OsString>("args").unwrap().cloned());
+    // fail now rather than when the service starts
+    super::cli()
+        .no_binary_name(true)
+        .try_get_matches_from(&service_args)
+        .map_err(|err| MegaError::with_message(&err.to_string()))?;
+
+    let mut arguments = vec![
+        OsString::from("--config"),
+        config_path.into_os_string(),
+        OsString::from("service"),
+    ];
+    arguments.extend(service_args);
+    common::winservice::install(name, display_name, arguments)?;
+    println!(
+        "installed the service {}, start it with `sc start {}`",
+        name, name
+    );
+    Ok(())
+}
+
+pub(super) fn uninstall(args: &ArgMatches) -> MegaResult {
+    let name = args.get_one::<String>("name").unwrap();
+    common::winservice::uninstall(name)?;
+    println!("removed the service {}", name);
+    Ok(())
+}
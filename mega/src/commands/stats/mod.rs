@@ -0,0 +1,85 @@
+//! This module is responsible for handling the 'stats' command.
+//! It reports the statistics of the whole instance for capacity planning.
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use ceres::stats;
+use common::{config::Config, errors::MegaResult};
+use jupiter::context::Context;
+
+const MB: f64 = 1024.0 * 1024.0;
+
+#[derive(Args, Debug)]
+struct StatsArgs {
+    /// Days of activity to report, today included
+    #[arg(long, default_value_t = stats::ACTIVE_DAYS)]
+    days: u64,
+
+    /// Print the statistics as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+pub fn cli() -> Command {
+    StatsArgs::augment_args(
+        Command::new("stats")
+            .about("Report repositories, storage, users, activity and queues of the instance"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = StatsArgs::from_arg_matches(args)?;
+    let context = Context::new(config).await;
+    let stats = stats::collect(&context, args.days).await?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats).unwrap());
+        return Ok(());
+    }
+
+    println!(
+        "repositories\tmonorepo {}\timport {}",
+        stats.monorepo_repos, stats.import_repos
+    );
+    println!(
+        "storage\tgit {}\tlfs {}",
+        format_size(stats.git_size),
+        format_size(stats.lfs_size)
+    );
+    println!(
+        "users\t{}\tdeactivated {}\tactive in {} days {}",
+        stats.users,
+        stats.deactivated_users,
+        stats::ACTIVE_DAYS,
+        stats.active_users
+    );
+    println!("open merge requests\t{}", stats.open_mrs);
+    println!("queue\t{}", format_counts(&stats.messages));
+    println!("jobs\t{}", format_counts(&stats.jobs));
+    println!();
+    println!("day\tpushes\tmrs opened\tmrs merged\tactive users");
+    for day in &stats.days {
+        println!(
+            "{}\t{}\t{}\t{}\t{}",
+            day.day, day.pushes, day.mrs_opened, day.mrs_merged, day.active_users
+        );
+    }
+    Ok(())
+}
+
+fn format_size(size: i64) -> String {
+    format!("{:.1} MB", size as f64 / MB)
+}
+
+fn format_counts(counts: &std::collections::BTreeMap<String, i64>) -> String {
+    if counts.is_empty() {
+        return "empty".to_owned();
+    }
+    counts
+        .iter()
+        .map(|(state, count)| format!("{} {}", state, count))
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+#[cfg(test)]
+mod tests {}
@@ -0,0 +1,99 @@
+//! This module is responsible for handling the 'storage' command.
+//! It moves the content of raw blobs between the database and the object storage backends
+//! after `storage.raw_obj_storage_type` or `storage.big_obj_threshold` was changed, and
+//! encrypts stored objects for the current master key after `storage.encryption` was changed.
+use std::sync::Arc;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches, Subcommand};
+
+use common::{config::Config, errors::MegaError, errors::MegaResult};
+use jupiter::lfs_storage::{self, local_storage::LocalStorage};
+use jupiter::storage::init::database_connection;
+use jupiter::storage::raw_db_storage::RawDbStorage;
+
+#[derive(Args, Debug)]
+struct StorageArgs {
+    #[command(subcommand)]
+    action: StorageAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum StorageAction {
+    /// Move blob content to where the current configuration puts it, content is read from the
+    /// database, the local directory and the configured backend
+    Migrate {
+        /// Only count the blobs which would be moved
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Encrypt blob content kept outside the database and LFS objects for the master key
+    /// `storage.encryption.key_id`, after it was changed or encryption was enabled
+    RotateKeys,
+}
+
+pub fn cli() -> Command {
+    StorageArgs::augment_args(
+        Command::new("storage").about("Manage where the content of blobs is stored"),
+    )
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let args = StorageArgs::from_arg_matches(args)?;
+    let conn = Arc::new(database_connection(&config.database).await);
+    let storage = RawDbStorage::new(conn, &config.storage).await;
+    match args.action {
+        StorageAction::Migrate { dry_run } => {
+            let res = storage.migrate_blobs(dry_run).await?;
+            let verb = if dry_run { "would move" } else { "moved" };
+            println!("checked {} blobs, {} {}", res.checked, verb, res.moved);
+            for (sha1, err) in &res.failed {
+                eprintln!("failed to move {}: {}", sha1, err);
+            }
+            if !res.failed.is_empty() {
+                return Err(MegaError::with_message(&format!(
+                    "{} blobs could not be moved",
+                    res.failed.len()
+                )));
+            }
+        }
+        StorageAction::RotateKeys => {
+            let res = storage.rotate_keys().await?;
+            println!(
+                "checked {} blobs, encrypted {} again",
+                res.checked, res.rotated
+            );
+            let mut failed = res.failed;
+            let lfs = lfs_storage::encrypted(
+                Arc::new(LocalStorage::init(config.lfs.lfs_obj_local_path.clone())),
+                storage.keyring(),
+            );
+            let (mut checked, mut rotated) = (0, 0);
+            for (oid, _) in lfs.list_objects()? {
+                checked += 1;
+                match lfs.rotate_object(&oid).await {
+                    Ok(true) => rotated += 1,
+                    Ok(false) => (),
+                    Err(err) => failed.push((oid, err.to_string())),
+                }
+            }
+            println!(
+                "checked {} lfs objects, encrypted {} again",
+                checked, rotated
+            );
+            for (id, err) in &failed {
+                eprintln!("failed to encrypt {}: {}", id, err);
+            }
+            if !failed.is_empty() {
+                return Err(MegaError::with_message(&format!(
+                    "{} objects could not be encrypted",
+                    failed.len()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {}
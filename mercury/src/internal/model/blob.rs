@@ -44,6 +44,7 @@ impl From<Blob> for raw_blob::Model {
             local_path: None,
             remote_url: None,
             created_at: chrono::Utc::now().naive_utc(),
+            ref_count: 0,
         }
     }
 }
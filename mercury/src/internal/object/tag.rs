@@ -75,6 +75,27 @@ impl Display for Tag {
 }
 
 impl Tag {
+    /// An annotated tag `tag_name` of the object `object_hash` with `message`.
+    pub fn new(
+        object_hash: SHA1,
+        object_type: ObjectType,
+        tag_name: &str,
+        tagger: Signature,
+        message: &str,
+    ) -> Tag {
+        let mut tag = Tag {
+            id: SHA1::default(),
+            object_hash,
+            object_type,
+            tag_name: tag_name.to_owned(),
+            tagger,
+            // the message is separated from the header by an empty line, parsed tags keep it
+            message: format!("\n{}\n", message.trim_end()),
+        };
+        tag.id = SHA1::from_type_and_data(ObjectType::Tag, &tag.to_data().unwrap());
+        tag
+    }
+
     // pub fn new_from_meta(meta: Meta) -> Result<Tag, GitError> {
     //     Ok(Tag::new_from_data(meta.data))
     // }
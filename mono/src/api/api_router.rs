@@ -7,34 +7,64 @@ use axum::{
     routing::{get, post},
     Json, Router,
 };
+use futures::TryStreamExt;
 use http::StatusCode;
 
 use ceres::{
     api_service::ApiHandler,
+    history,
     model::{
+        blame::BlameLine,
         create_file::CreateFileInfo,
-        query::{BlobContentQuery, CodePreviewQuery},
-        tree::{LatestCommitInfo, TreeBriefItem, TreeCommitItem},
+        query::{BlobContentQuery, CodePreviewQuery, PathHistoryQuery, TreeQuery},
+        tree::{LatestCommitInfo, TreeBriefItem, TreeCommitItem, TreeEntries},
     },
 };
-use common::{errors::ProtocolError, model::CommonResult};
+use common::{
+    errors::ProtocolError,
+    model::{CommonPage, CommonResult, PageParams},
+    utils::{repo_path, MEGA_BRANCH_NAME},
+};
+use jupiter::storage::health::{self, DbStatus};
+use saturn::ActionEnum;
 use taurus::event::api_request::{ApiRequestEvent, ApiType};
+use taurus::event::repo_created::RepoCreatedEvent;
 
+use crate::api::break_glass::break_glass_router;
 use crate::api::error::ApiError;
+use crate::api::feature::feature_router;
 use crate::api::issue::issue_router;
+use crate::api::maintenance::maintenance_router;
+use crate::api::mq::mq_router;
 use crate::api::mr::mr_router;
+use crate::api::oauth::model::LoginUser;
+use crate::api::policy::policy_router;
+use crate::api::quota::quota_router;
+use crate::api::release::release_router;
+use crate::api::repo::repo_router;
+use crate::api::role::role_router;
+use crate::api::secret_scan::secret_scan_router;
+use crate::api::service_account::service_account_router;
+use crate::api::signature::signature_router;
+use crate::api::stats::stats_router;
 use crate::api::user::user_router;
+use crate::api::util;
+use crate::api::webhook::webhook_router;
 use crate::api::MonoApiServiceState;
 
 pub fn routers() -> Router<MonoApiServiceState> {
     let router = Router::new()
         .route("/status", get(life_cycle_check))
+        .route("/health", get(health_check))
         .route("/create-file", post(create_file))
         .route("/latest-commit", get(get_latest_commit))
         .route("/tree/commit-info", get(get_tree_commit_info))
         .route("/tree/path-can-clone", get(path_can_be_cloned))
         .route("/tree", get(get_tree_info))
+        .route("/tree/entries", get(get_tree_entries))
         .route("/blob", get(get_blob_string))
+        .route("/history", post(get_path_history))
+        .route("/blame", get(get_blame))
         .route("/file/blob/{object_id}", get(get_blob_file))
         .route("/file/tree", get(get_tree_file));
     Router::new()
@@ -42,13 +72,34 @@ pub fn routers() -> Router<MonoApiServiceState> {
         .merge(mr_router::routers())
         .merge(user_router::routers())
         .merge(issue_router::routers())
+        .merge(repo_router::routers())
+        .merge(maintenance_router::routers())
+        .merge(secret_scan_router::routers())
+        .merge(release_router::routers())
+        .merge(quota_router::routers())
+        .merge(mq_router::routers())
+        .merge(signature_router::routers())
+        .merge(policy_router::routers())
+        .merge(role_router::routers())
+        .merge(break_glass_router::routers())
+        .merge(service_account_router::routers())
+        .merge(feature_router::routers())
+        .merge(stats_router::routers())
+        .merge(webhook_router::routers())
 }
 
 async fn get_blob_string(
+    user: Option<LoginUser>,
     Query(query): Query<BlobContentQuery>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::Blob, &state.0.context.config);
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&query.path),
+        &state.context,
+    )
+    .await?;
+    ApiRequestEvent::notify(ApiType::Blob, &state.0.context.config).await;
     let res = state
         .api_handler(query.path.clone().into())
         .await?
@@ -62,32 +113,114 @@ async fn get_blob_string(
     Ok(Json(res))
 }
 
+/// The commits which changed a file or directory of the monorepo, latest first.
+async fn get_path_history(
+    user: Option<LoginUser>,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<PageParams<PathHistoryQuery>>,
+) -> Result<Json<CommonResult<CommonPage<LatestCommitInfo>>>, ApiError> {
+    let path = json.additional.path;
+    util::check_user_read_access(user.as_ref(), std::path::Path::new(&path), &state.context)
+        .await?;
+    let res = state
+        .context
+        .services
+        .mono_storage
+        .get_path_history("/", MEGA_BRANCH_NAME, &path, json.pagination)
+        .await;
+    let res = match res {
+        Ok((commits, total)) => {
+            let monorepo = state.monorepo();
+            let items: Result<Vec<LatestCommitInfo>, _> = commits
+                .into_iter()
+                .map(|c| monorepo.convert_commit_to_info(c.into()))
+                .collect();
+            match items {
+                Ok(items) => CommonResult::success(Some(CommonPage { total, items })),
+                Err(err) => CommonResult::failed(&err.to_string()),
+            }
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// The lines of a file of the monorepo with the commit which last changed each of them.
+async fn get_blame(
+    user: Option<LoginUser>,
+    Query(query): Query<BlobContentQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<BlameLine>>>, ApiError> {
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&query.path),
+        &state.context,
+    )
+    .await?;
+    let res = match history::blame(&state.context, "/", MEGA_BRANCH_NAME, &query.path).await {
+        Ok(Some(lines)) => CommonResult::success(Some(lines)),
+        Ok(None) => CommonResult::failed("file not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
 async fn life_cycle_check() -> Result<impl IntoResponse, ApiError> {
     Ok(Json("http ready"))
 }
 
+/// Health of the database, unavailable while it is down or all connections are in use so load
+/// balancers send requests elsewhere.
+async fn health_check(state: State<MonoApiServiceState>) -> impl IntoResponse {
+    let health = health::check(state.context.services.mono_storage.get_connection());
+    let status = match health.status {
+        DbStatus::Ok => StatusCode::OK,
+        DbStatus::Saturated | DbStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(health))
+}
+
+/// Create a file or directory in the directory `path` of the monorepo, like the `create`
+/// command over SSH it requires permission to create repositories in it.
 async fn create_file(
+    user: LoginUser,
     state: State<MonoApiServiceState>,
     Json(json): Json<CreateFileInfo>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::CreateFile, &state.0.context.config);
+    util::check_permissions(&user, &json.path, ActionEnum::CreateRepo, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(json.path.clone()))?;
+    ApiRequestEvent::notify(ApiType::CreateFile, &state.0.context.config).await;
     let res = state
         .api_handler(json.path.clone().into())
         .await?
         .create_monorepo_file(json.clone())
         .await;
     let res = match res {
-        Ok(_) => CommonResult::success(None),
+        Ok(_) => {
+            if json.is_directory {
+                let path = PathBuf::from(&json.path).join(&json.name);
+                RepoCreatedEvent::notify(&repo_path(&path), &user.name).await;
+            }
+            CommonResult::success(None)
+        }
         Err(err) => CommonResult::failed(&err.to_string()),
     };
     Ok(Json(res))
 }
 
 async fn get_latest_commit(
+    user: Option<LoginUser>,
     Query(query): Query<CodePreviewQuery>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<LatestCommitInfo>, ApiError> {
-    ApiRequestEvent::notify(ApiType::LastestCommit, &state.0.context.config);
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&query.path),
+        &state.context,
+    )
+    .await?;
+    ApiRequestEvent::notify(ApiType::LastestCommit, &state.0.context.config).await;
     let res = state
         .api_handler(query.path.clone().into())
         .await?
@@ -97,14 +230,21 @@ async fn get_latest_commit(
 }
 
 async fn get_tree_info(
-    Query(query): Query<CodePreviewQuery>,
+    user: Option<LoginUser>,
+    Query(query): Query<TreeQuery>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<Vec<TreeBriefItem>>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::TreeInfo, &state.0.context.config);
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&query.path),
+        &state.context,
+    )
+    .await?;
+    ApiRequestEvent::notify(ApiType::TreeInfo, &state.0.context.config).await;
     let res = state
         .api_handler(query.path.clone().into())
         .await?
-        .get_tree_info(query.path.into())
+        .get_tree_info(query.path.clone().into(), &query.range())
         .await;
     let res = match res {
         Ok(data) => CommonResult::success(Some(data)),
@@ -113,15 +253,55 @@ async fn get_tree_info(
     Ok(Json(res))
 }
 
+/// Entries of a monorepo directory with their object ids, modes and sizes at the commit or
+/// root ref given by `refs`.
+///
+/// With `page` only a page of the entries sorted by name is returned, `total` tells how many
+/// there are. `prefix` keeps the entries whose name starts with it.
+async fn get_tree_entries(
+    user: Option<LoginUser>,
+    Query(query): Query<TreeQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<TreeEntries>>, ApiError> {
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&query.path),
+        &state.context,
+    )
+    .await?;
+    ApiRequestEvent::notify(ApiType::TreeInfo, &state.0.context.config).await;
+    let res = state
+        .monorepo()
+        .get_tree_entries(
+            std::path::Path::new(&query.path),
+            &query.refs,
+            &query.range(),
+        )
+        .await;
+    let res = match res {
+        Ok(Some(data)) => CommonResult::success(Some(data)),
+        Ok(None) => CommonResult::failed("directory not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
 async fn get_tree_commit_info(
-    Query(query): Query<CodePreviewQuery>,
+    user: Option<LoginUser>,
+    Query(query): Query<TreeQuery>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<Vec<TreeCommitItem>>>, ProtocolError> {
-    ApiRequestEvent::notify(ApiType::CommitInfo, &state.0.context.config);
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&query.path),
+        &state.context,
+    )
+    .await?;
+    ApiRequestEvent::notify(ApiType::CommitInfo, &state.0.context.config).await;
     let res = state
         .api_handler(query.path.clone().into())
         .await?
-        .get_tree_commit_info(query.path.into())
+        .get_tree_commit_info(query.path.clone().into(), &query.range())
         .await;
     let res = match res {
         Ok(data) => CommonResult::success(Some(data)),
@@ -130,19 +310,53 @@ async fn get_tree_commit_info(
     Ok(Json(res))
 }
 
+/// Paths of a blob checked for read access before its content is served.
+const BLOB_PATHS_CHECKED: u64 = 20;
+
+/// The content of the blob `oid`, if the user can read one of the paths it was committed at.
+/// Blobs are shared by all repositories, so a blob of a private repository can't be read by
+/// its hash.
 pub async fn get_blob_file(
+    user: Option<LoginUser>,
     state: State<MonoApiServiceState>,
     Path(oid): Path<String>,
 ) -> Result<Response, ApiError> {
-    let api_handler = state.monorepo();
-
-    let result = api_handler.get_raw_blob_by_hash(&oid).await.unwrap();
+    let paths = state
+        .context
+        .services
+        .mono_storage
+        .get_blob_paths(&oid, BLOB_PATHS_CHECKED)
+        .await?;
+    let mut readable = false;
+    for path in paths {
+        let path = std::path::Path::new(&path);
+        if util::check_user_read_access(user.as_ref(), path, &state.context)
+            .await
+            .is_ok()
+        {
+            readable = true;
+            break;
+        }
+    }
+    if !readable {
+        return Err(ProtocolError::NotFound(oid).into());
+    }
+    let result = state
+        .context
+        .services
+        .raw_db_storage
+        .get_raw_blob_content(&oid)
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
     let file_name = format!("inline; filename=\"{}\"", oid);
     match result {
-        Some(model) => Ok(Response::builder()
+        // large blobs are streamed from their storage instead of being loaded at once
+        Some(content) => Ok(Response::builder()
             .header("Content-Type", "application/octet-stream")
             .header("Content-Disposition", file_name)
-            .body(Body::from(model.data.unwrap()))
+            .body(Body::from_stream(
+                content.map_err(|err| std::io::Error::other(err.to_string())),
+            ))
             .unwrap()),
         None => Ok({
             Response::builder()
@@ -154,9 +368,16 @@ pub async fn get_blob_file(
 }
 
 pub async fn get_tree_file(
+    user: Option<LoginUser>,
     state: State<MonoApiServiceState>,
     Query(query): Query<CodePreviewQuery>,
 ) -> Result<Response, ApiError> {
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&query.path),
+        &state.context,
+    )
+    .await?;
     let res = state
         .api_handler(query.path.clone().into())
         .await?
@@ -0,0 +1,174 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::{Duration, Utc};
+
+use callisto::auth_decision;
+use common::{errors::ProtocolError, model::CommonResult, utils::generate_id};
+use saturn::break_glass::{self, BreakGlassGrant, BREAK_GLASS_NAMESPACE};
+
+use crate::api::break_glass::{BreakGlass, GrantInfo, GrantParams};
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/break-glass",
+        Router::new()
+            .route("/", get(list_grants).post(break_glass))
+            .route("/{id}/revoke", post(revoke_grant)),
+    )
+}
+
+/// Only the configured admins may break glass. This is not decided by the policies, so that
+/// admins locked out by them can still get access.
+fn check_break_glass_admin(
+    user: &LoginUser,
+    state: &State<MonoApiServiceState>,
+) -> Result<(), ApiError> {
+    if !state
+        .context
+        .config
+        .policy
+        .break_glass_admins
+        .contains(&user.name)
+    {
+        return Err(ProtocolError::Forbidden(
+            "break-glass access is only for the configured admins".to_owned(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+async fn list_grants(
+    user: LoginUser,
+    Query(params): Query<GrantParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<GrantInfo>>>, ApiError> {
+    check_break_glass_admin(&user, &state)?;
+    let res = match state
+        .context
+        .services
+        .policy_storage
+        .list_break_glass_grants(params.active)
+        .await
+    {
+        Ok(grants) => CommonResult::success(Some(grants.into_iter().map(|g| g.into()).collect())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Grant the signed in admin access to everything on a path until the grant expires.
+async fn break_glass(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<BreakGlass>,
+) -> Result<Json<CommonResult<GrantInfo>>, ApiError> {
+    check_break_glass_admin(&user, &state)?;
+    if json.reason.trim().is_empty() {
+        return Ok(Json(CommonResult::failed("a reason is required")));
+    }
+    // `Duration::minutes` panics on values out of its range
+    let duration = Duration::try_minutes(json.minutes)
+        .filter(|d| *d > Duration::zero() && *d <= BreakGlassGrant::MAX_DURATION);
+    let Some(duration) = duration else {
+        return Ok(Json(CommonResult::failed(&format!(
+            "access lasts 1 to {} minutes",
+            BreakGlassGrant::MAX_DURATION.num_minutes()
+        ))));
+    };
+    let grant = state
+        .context
+        .services
+        .policy_storage
+        .save_break_glass_grant(
+            &user.name,
+            &json.path,
+            json.reason.trim(),
+            (Utc::now() + duration).naive_utc(),
+        )
+        .await?;
+    tracing::warn!(
+        "break-glass access to {} granted to {} until {}: {}",
+        grant.path,
+        grant.user,
+        grant.expires_at,
+        grant.reason
+    );
+    record(&user, "breakGlass", &grant.path, &state);
+    if let Err(err) = apply_grants(&state).await {
+        return Ok(Json(CommonResult::failed(&err)));
+    }
+    Ok(Json(CommonResult::success(Some(grant.into()))))
+}
+
+/// End a grant before it expires.
+async fn revoke_grant(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    check_break_glass_admin(&user, &state)?;
+    let storage = &state.context.services.policy_storage;
+    let Some(grant) = storage.get_break_glass_grant(id).await? else {
+        return Ok(Json(CommonResult::failed("break-glass grant not found")));
+    };
+    if !storage.revoke_break_glass_grant(id, &user.name).await? {
+        return Ok(Json(CommonResult::failed(
+            "break-glass grant already expired or was revoked",
+        )));
+    }
+    tracing::warn!(
+        "break-glass access to {} of {} revoked by {}",
+        grant.path,
+        grant.user,
+        user.name
+    );
+    record(&user, "revokeBreakGlass", &grant.path, &state);
+    let res = match apply_grants(&state).await {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err),
+    };
+    Ok(Json(res))
+}
+
+/// Keep the change of a grant in the audit trail, with the decisions made by it.
+fn record(user: &LoginUser, action: &str, path: &str, state: &State<MonoApiServiceState>) {
+    util::save_decision(
+        auth_decision::Model {
+            id: generate_id(),
+            principal: user.name.clone(),
+            action: action.to_owned(),
+            resource: path.to_owned(),
+            allowed: true,
+            policies: String::new(),
+            errors: None,
+            break_glass: true,
+            created_at: Utc::now().naive_utc(),
+        },
+        &state.context,
+    );
+}
+
+/// Put the policies of the active grants in use, dropping those which expired.
+async fn apply_grants(state: &State<MonoApiServiceState>) -> Result<(), String> {
+    let grants = state
+        .context
+        .services
+        .policy_storage
+        .break_glass_grants()
+        .await
+        .map_err(|err| err.to_string())?;
+    let policies = (!grants.is_empty()).then(|| break_glass::policies(&grants, Utc::now()));
+    state
+        .context
+        .policies
+        .set_namespace(BREAK_GLASS_NAMESPACE, policies.as_deref())
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::break_glass_grant;
+
+pub mod break_glass_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct GrantInfo {
+    pub id: i64,
+    pub user: String,
+    pub path: String,
+    pub reason: String,
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>,
+    pub revoked_by: Option<String>,
+    pub created_at: i64,
+}
+
+impl From<break_glass_grant::Model> for GrantInfo {
+    fn from(value: break_glass_grant::Model) -> Self {
+        Self {
+            id: value.id,
+            user: value.user,
+            path: value.path,
+            reason: value.reason,
+            expires_at: value.expires_at.and_utc().timestamp(),
+            revoked_at: value.revoked_at.map(|t| t.and_utc().timestamp()),
+            revoked_by: value.revoked_by,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BreakGlass {
+    /// Directory or repository to get access to, with everything below it
+    pub path: String,
+    /// Why access is needed, kept with the grant
+    pub reason: String,
+    /// Minutes the access lasts, at most the longest duration of a grant
+    pub minutes: i64,
+}
+
+#[derive(Deserialize)]
+pub struct GrantParams {
+    /// Only the grants which neither expired nor were revoked
+    #[serde(default)]
+    pub active: bool,
+}
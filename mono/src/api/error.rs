@@ -1,12 +1,19 @@
 use axum::response::{IntoResponse, Response};
 use http::StatusCode;
 
+use common::errors::ProtocolError;
+
 #[derive(Debug)]
 pub struct ApiError(anyhow::Error);
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        tracing::error!("Application error: {:#}", self.0);
+        // protocol errors carry their own status code, e.g. not found or forbidden
+        let err = match self.0.downcast::<ProtocolError>() {
+            Ok(err) => return err.into_response(),
+            Err(err) => err,
+        };
+        tracing::error!("Application error: {:#}", err);
 
         (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
     }
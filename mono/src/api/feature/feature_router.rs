@@ -0,0 +1,130 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use ceres::feature::{self, Target};
+use common::{
+    config::{is_valid_flag_name, FeatureFlag},
+    model::CommonResult,
+};
+use saturn::ActionEnum;
+
+use crate::api::error::ApiError;
+use crate::api::feature::{FeatureInfo, FeatureParams, SaveFeature};
+use crate::api::oauth::model::LoginUser;
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/features",
+        Router::new()
+            .route("/", get(list_features))
+            .route("/{name}", get(check_feature).post(save_feature))
+            .route("/{name}/delete", post(delete_feature)),
+    )
+}
+
+/// Feature flags are managed by the admins of the root directory.
+const FORBIDDEN: &str = "managing feature flags requires admin permission";
+
+/// The flags in use, stored ones replace those of the config.
+async fn list_features(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<FeatureInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let flags = feature::list(&state.context).await?;
+    Ok(Json(CommonResult::success(Some(
+        flags.into_iter().map(|f| f.into()).collect(),
+    ))))
+}
+
+/// Whether the feature is on for the user, on the repository at `path` if given, so the UI can
+/// show it.
+async fn check_feature(
+    user: LoginUser,
+    Path(name): Path<String>,
+    Query(params): Query<FeatureParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<bool>>, ApiError> {
+    let target = Target::new(Some(&user.name), params.path.as_deref());
+    let enabled = feature::is_enabled(&state.context, &name, target).await;
+    Ok(Json(CommonResult::success(Some(enabled))))
+}
+
+/// Store the flag, replacing the flag of the same name of the config until it is deleted.
+async fn save_feature(
+    user: LoginUser,
+    Path(name): Path<String>,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<SaveFeature>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    if !is_valid_flag_name(&name) {
+        return Ok(Json(CommonResult::failed(
+            "feature flag names are at most 64 lowercase letters, digits and '_'",
+        )));
+    }
+    if json.percentage > 100 {
+        return Ok(Json(CommonResult::failed("percentage is above 100")));
+    }
+    let flag = FeatureFlag {
+        name,
+        enabled: json.enabled,
+        users: json.users,
+        paths: json.paths,
+        percentage: json.percentage,
+    };
+    state
+        .context
+        .services
+        .feature_storage
+        .save_flag(&flag, &user.name)
+        .await?;
+    tracing::info!("feature flag {:?} saved by {}", flag, user.name);
+    Ok(Json(CommonResult::success(None)))
+}
+
+/// Delete the stored flag, the flag of the config is used again.
+async fn delete_feature(
+    user: LoginUser,
+    Path(name): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state
+        .context
+        .services
+        .feature_storage
+        .delete_flag(&name)
+        .await?
+    {
+        true => {
+            tracing::info!("feature flag {} deleted by {}", name, user.name);
+            CommonResult::success(None)
+        }
+        false => CommonResult::failed("feature flag is not managed through the api"),
+    };
+    Ok(Json(res))
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+
+use ceres::feature::Source;
+use common::config::FeatureFlag;
+
+pub mod feature_router;
+
+#[derive(Serialize)]
+pub struct FeatureInfo {
+    pub name: String,
+    pub enabled: bool,
+    pub users: Vec<String>,
+    pub paths: Vec<String>,
+    pub percentage: u8,
+    /// `config` for flags of the config, `api` for flags managed through the api
+    pub source: String,
+    pub updated_by: Option<String>,
+    pub updated_at: Option<i64>,
+}
+
+impl From<(FeatureFlag, Source)> for FeatureInfo {
+    fn from((flag, source): (FeatureFlag, Source)) -> Self {
+        let (source, updated_by, updated_at) = match source {
+            Source::Config => ("config", None, None),
+            Source::Stored {
+                updated_by,
+                updated_at,
+            } => (
+                "api",
+                Some(updated_by),
+                Some(updated_at.and_utc().timestamp()),
+            ),
+        };
+        Self {
+            name: flag.name,
+            enabled: flag.enabled,
+            users: flag.users,
+            paths: flag.paths,
+            percentage: flag.percentage,
+            source: source.to_owned(),
+            updated_by,
+            updated_at,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SaveFeature {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub percentage: u8,
+}
+
+#[derive(Deserialize)]
+pub struct FeatureParams {
+    /// The repository the feature would be used on
+    pub path: Option<String>,
+}
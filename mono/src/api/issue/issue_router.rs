@@ -6,7 +6,11 @@ use axum::{
 use bytes::Bytes;
 use serde::Deserialize;
 
+use ceres::tenant;
+use common::errors::MegaError;
 use common::model::{CommonPage, CommonResult, PageParams};
+use taurus::event::issue::{IssueAction, IssueEvent};
+use taurus::outbox;
 
 use crate::api::error::ApiError;
 use crate::api::issue::{IssueDetail, IssueItem, NewIssue};
@@ -32,13 +36,16 @@ pub struct StatusParams {
     pub status: String,
 }
 
+/// The issues opened by the users of the tenant of the user, see [`ceres::tenant`].
 async fn fetch_issue_list(
+    user: Option<LoginUser>,
     state: State<MonoApiServiceState>,
     Json(json): Json<PageParams<StatusParams>>,
 ) -> Result<Json<CommonResult<CommonPage<IssueItem>>>, ApiError> {
+    let scope = tenant::user_scope(&state.context, user.as_ref().map(|u| u.name.as_str())).await?;
     let res = state
         .issue_stg()
-        .get_issue_by_status(&json.additional.status, json.pagination)
+        .get_issue_by_status(&json.additional.status, &scope, json.pagination)
         .await;
     let res = match res {
         Ok((items, total)) => CommonResult::success(Some(CommonPage {
@@ -55,11 +62,10 @@ async fn issue_detail(
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<IssueDetail>>, ApiError> {
-    let res = match state.issue_stg().get_issue(&link).await {
+    let res = match state.issue_stg().get_issue_with_conversations(&link).await {
         Ok(data) => {
-            if let Some(model) = data {
+            if let Some((model, conversations)) = data {
                 let mut detail: IssueDetail = model.into();
-                let conversations = state.mr_stg().get_mr_conversations(&link).await.unwrap();
                 detail.conversations = conversations.into_iter().map(|x| x.into()).collect();
                 CommonResult::success(Some(detail))
             } else {
@@ -76,24 +82,33 @@ async fn new_issue(
     state: State<MonoApiServiceState>,
     Json(json): Json<NewIssue>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    let stg = state.issue_stg().clone();
-    let res = stg.save_issue(user.user_id, &json.title).await.unwrap();
+    let stg = &state.issue_stg();
+    let context = &state.context;
+    // the event is published if and only if the issue is saved
     let res = stg
-        .add_issue_conversation(&res.link, user.user_id, Some(json.description))
+        .transaction(|txn| async move {
+            let issue = stg.save_issue(&*txn, user.user_id, &json.title).await?;
+            stg.add_issue_conversation(&*txn, &issue.link, user.user_id, Some(json.description))
+                .await?;
+            IssueEvent::stage(context, &*txn, &issue, IssueAction::Opened, &user.name).await
+        })
         .await;
     let res = match res {
-        Ok(_) => CommonResult::success(None),
+        Ok(_) => {
+            outbox::flush();
+            CommonResult::success(None)
+        }
         Err(err) => CommonResult::failed(&err.to_string()),
     };
     Ok(Json(res))
 }
 
 async fn close_issue(
-    _: LoginUser,
+    user: LoginUser,
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    let res = match state.issue_stg().close_issue(&link).await {
+    let res = match update_issue(&state, &link, IssueAction::Closed, &user.name).await {
         Ok(_) => CommonResult::success(None),
         Err(err) => CommonResult::failed(&err.to_string()),
     };
@@ -101,17 +116,41 @@ async fn close_issue(
 }
 
 async fn reopen_issue(
-    _: LoginUser,
+    user: LoginUser,
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<String>>, ApiError> {
-    let res = match state.issue_stg().reopen_issue(&link).await {
+    let res = match update_issue(&state, &link, IssueAction::Reopened, &user.name).await {
         Ok(_) => CommonResult::success(None),
         Err(err) => CommonResult::failed(&err.to_string()),
     };
     Ok(Json(res))
 }
 
+/// Close or reopen the issue `link`, its event is staged in the same transaction.
+async fn update_issue(
+    state: &MonoApiServiceState,
+    link: &str,
+    action: IssueAction,
+    operator: &str,
+) -> Result<(), MegaError> {
+    let stg = &state.issue_stg();
+    let context = &state.context;
+    stg.transaction(|txn| async move {
+        let issue = match action {
+            IssueAction::Closed => stg.close_issue(&*txn, link).await?,
+            _ => stg.reopen_issue(&*txn, link).await?,
+        };
+        if let Some(issue) = issue {
+            IssueEvent::stage(context, &*txn, &issue, action, operator).await?;
+        }
+        Ok(())
+    })
+    .await?;
+    outbox::flush();
+    Ok(())
+}
+
 async fn save_comment(
     user: LoginUser,
     Path(link): Path<String>,
@@ -120,9 +159,9 @@ async fn save_comment(
 ) -> Result<Json<CommonResult<String>>, ApiError> {
     let json_string =
         String::from_utf8(body.to_vec()).unwrap_or_else(|_| "Invalid UTF-8".to_string());
-    let res = match state
-        .issue_stg()
-        .add_issue_conversation(&link, user.user_id, Some(json_string))
+    let stg = state.issue_stg();
+    let res = match stg
+        .add_issue_conversation(stg.get_connection(), &link, user.user_id, Some(json_string))
         .await
     {
         Ok(_) => CommonResult::success(None),
@@ -51,7 +51,7 @@ use axum::{
     routing::{get, post, put},
     Json, Router,
 };
-use futures::TryStreamExt;
+use futures::{StreamExt, TryStreamExt};
 
 use ceres::lfs::{
     handler,
@@ -60,7 +60,7 @@ use ceres::lfs::{
         LockResponse, RequestVars, UnlockRequest, UnlockResponse, VerifiableLockRequest,
     },
 };
-use common::errors::GitLFSError;
+use common::errors::{GitLFSError, MegaError};
 
 use crate::api::MonoApiServiceState;
 
@@ -308,18 +308,14 @@ pub async fn lfs_upload_object(
         ..Default::default()
     };
 
-    // Collect bytes asynchronously from the stream into a Vec<u8>
-    let body_bytes: Vec<u8> = req
+    // Hand the body over as a stream, so large objects are never buffered as a whole.
+    let content = req
         .into_body()
         .into_data_stream()
-        .try_fold(Vec::new(), |mut acc, chunk| async move {
-            acc.extend_from_slice(&chunk);
-            Ok(acc)
-        })
-        .await
-        .unwrap();
+        .map_err(|err| MegaError::with_message(&err.to_string()))
+        .boxed();
 
-    let result = handler::lfs_upload_object(&state.context, &request_vars, &body_bytes).await;
+    let result = handler::lfs_upload_object(&state.context, &request_vars, content).await;
     match result {
         Ok(_) => Ok(Response::builder()
             .header("Content-Type", LFS_CONTENT_TYPE)
@@ -0,0 +1,333 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use callisto::db_enums::JobTrigger;
+use ceres::maintenance::{branch_cleanup, mode, subtree_split, Scheduler};
+use common::{config::Reload, model::CommonResult};
+use saturn::ActionEnum;
+use taurus::event::repo::{RepoEvent, RepoEventKind};
+
+use crate::api::error::ApiError;
+use crate::api::maintenance::{
+    CreateSplit, JobHistoryParams, JobInfo, ModeInfo, ReportInfo, ReportParams, RunTask, SetMode,
+    SplitInfo, StaleBranchInfo, StaleBranchParams, TaskInfo, TrashInfo,
+};
+use crate::api::oauth::model::LoginUser;
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+const DEFAULT_HISTORY_LIMIT: u64 = 50;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/maintenance",
+        Router::new()
+            .route("/tasks", get(list_tasks))
+            .route("/jobs", get(list_jobs))
+            .route("/jobs/{id}", get(get_job))
+            .route("/run", post(run_task))
+            .route("/reports", get(list_reports))
+            .route("/reports/{job_id}", get(get_report))
+            .route("/stale-branches", get(list_stale_branches))
+            .route("/splits", get(list_splits).post(create_split))
+            .route("/splits/{id}/delete", post(delete_split))
+            .route("/trash", get(list_trash))
+            .route("/trash/{id}/restore", post(restore_repo))
+            .route("/policies/reload", post(reload_policies))
+            .route("/config/reload", post(reload_config))
+            .route("/mode", get(get_mode).post(set_mode)),
+    )
+}
+
+/// Maintenance is instance wide, only admins of the root directory may access it.
+const FORBIDDEN: &str = "maintenance requires admin permission";
+
+async fn list_tasks(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<TaskInfo>>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let config = &state.context.config.maintenance;
+    let now = chrono::Utc::now().naive_utc();
+    let tasks = Scheduler::schedules(config)
+        .into_iter()
+        .map(|(task, schedule)| TaskInfo {
+            task,
+            schedule: Scheduler::schedule_expr(config, task).to_owned(),
+            next_run: schedule
+                .filter(|_| config.enable)
+                .and_then(|s| s.next_after(now))
+                .map(|dt| dt.and_utc().timestamp()),
+        })
+        .collect();
+    Ok(Json(CommonResult::success(Some(tasks))))
+}
+
+async fn list_jobs(
+    user: LoginUser,
+    Query(params): Query<JobHistoryParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<JobInfo>>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = state
+        .context
+        .services
+        .maintenance_storage
+        .list_jobs(params.task, params.limit.unwrap_or(DEFAULT_HISTORY_LIMIT))
+        .await;
+    let res = match res {
+        Ok(jobs) => CommonResult::success(Some(jobs.into_iter().map(|j| j.into()).collect())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn get_job(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<JobInfo>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = state.context.services.maintenance_storage.get_job(id).await;
+    let res = match res {
+        Ok(Some(job)) => CommonResult::success(Some(job.into())),
+        Ok(None) => CommonResult::failed("job not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Start a task right away, the job runs in the background and can be followed with `/jobs/{id}`.
+async fn run_task(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<RunTask>,
+) -> Result<Json<CommonResult<JobInfo>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = Scheduler::new(state.context.clone())
+        .trigger(
+            json.task,
+            JobTrigger::Manual,
+            Some(user.name.clone()),
+            json.path,
+        )
+        .await;
+    let res = match res {
+        Ok(Some(job)) => CommonResult::success(Some(job.into())),
+        Ok(None) => CommonResult::failed(&format!("{} is already running", json.task)),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Integrity reports of the verify task, latest first.
+async fn list_reports(
+    user: LoginUser,
+    Query(params): Query<ReportParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<ReportInfo>>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = state
+        .context
+        .services
+        .maintenance_storage
+        .list_reports(params.path, params.limit.unwrap_or(DEFAULT_HISTORY_LIMIT))
+        .await;
+    let res = match res {
+        Ok(reports) => CommonResult::success(Some(reports.into_iter().map(|r| r.into()).collect())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn get_report(
+    user: LoginUser,
+    Path(job_id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<ReportInfo>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = state
+        .context
+        .services
+        .maintenance_storage
+        .get_report(job_id)
+        .await;
+    let res = match res {
+        Ok(Some(report)) => CommonResult::success(Some(report.into())),
+        Ok(None) => CommonResult::failed("report not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Dry run of the branch cleanup task, lists the branches it would report or delete now.
+async fn list_stale_branches(
+    user: LoginUser,
+    Query(params): Query<StaleBranchParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<StaleBranchInfo>>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = branch_cleanup::find_stale_branches(&state.context, params.path.as_deref()).await;
+    let res = match res {
+        Ok(branches) => {
+            CommonResult::success(Some(branches.into_iter().map(|b| b.into()).collect()))
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn list_splits(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<SplitInfo>>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = state.context.services.mono_storage.list_splits().await;
+    let res = match res {
+        Ok(splits) => CommonResult::success(Some(splits.into_iter().map(|s| s.into()).collect())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Register a split, its history is written by the next run of the `subtree_split` task.
+async fn create_split(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<CreateSplit>,
+) -> Result<Json<CommonResult<SplitInfo>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res =
+        subtree_split::create_split(&state.context, &json.path, &json.repo_path, &user.name).await;
+    let res = match res {
+        Ok(split) => CommonResult::success(Some(split.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Stop updating a split, the split repository is kept as it is.
+async fn delete_split(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let res = state.context.services.mono_storage.delete_split(id).await;
+    let res = match res {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Deleted repositories which have not been purged yet.
+async fn list_trash(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<TrashInfo>>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let retention_days = state.context.config.maintenance.repo_retention_days;
+    let res = state
+        .context
+        .services
+        .git_db_storage
+        .list_deleted_git_repos()
+        .await;
+    let res = match res {
+        Ok(repos) => CommonResult::success(Some(
+            repos
+                .into_iter()
+                .map(|r| TrashInfo::new(r, retention_days))
+                .collect(),
+        )),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Take a repository out of the trash, it is accessible again as it was before its deletion.
+async fn restore_repo(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let storage = &state.context.services.git_db_storage;
+    let Some(repo) = storage.get_deleted_git_repo(id).await? else {
+        return Ok(Json(CommonResult::failed("deleted repository not found")));
+    };
+    let path = repo.repo_path.clone();
+    let res = match storage.restore_git_repo(repo).await {
+        Ok(_) => {
+            RepoEvent::notify(RepoEventKind::Restored { path }, &user.name).await;
+            CommonResult::success(None)
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Read the policy files and the policies of the namespaces again, the policies in use are
+/// kept when they are invalid.
+async fn reload_policies(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let namespaces = state
+        .context
+        .services
+        .policy_storage
+        .current_policies()
+        .await?;
+    let res = match state.context.policies.set_namespaces(namespaces) {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Read the config file again and put its reloadable settings in use, the config in use is kept
+/// when the file is invalid.
+async fn reload_config(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Reload>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    let context = state.context.clone();
+    let res = match tokio::task::spawn_blocking(move || context.reload_config()).await {
+        Ok(Ok(reload)) => CommonResult::success(Some(reload)),
+        Ok(Err(err)) => CommonResult::failed(&err.to_string()),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// The maintenance mode of the instance, readable by everyone so clients can tell why writes are
+/// rejected.
+async fn get_mode(
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<ModeInfo>>, ApiError> {
+    let mode = mode::current(&state.context).await;
+    Ok(Json(CommonResult::success(Some(mode.into()))))
+}
+
+/// Put the instance in maintenance mode, rejecting writes, or end it.
+async fn set_mode(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<SetMode>,
+) -> Result<Json<CommonResult<ModeInfo>>, ApiError> {
+    util::check_instance_admin(&user, ActionEnum::RunMaintenance, FORBIDDEN, state.clone()).await?;
+    if json.enabled {
+        mode::enable(&state.context, json.message, &user.name).await?;
+    } else {
+        mode::disable(&state.context, &user.name).await?;
+    }
+    let mode = mode::current(&state.context).await;
+    Ok(Json(CommonResult::success(Some(mode.into()))))
+}
@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::{JobStatus, JobTrigger, MaintenanceTask};
+use callisto::{git_repo, integrity_report, maintenance_job, subtree_split};
+use ceres::maintenance::branch_cleanup::StaleBranch;
+use ceres::maintenance::mode::MaintenanceMode;
+use ceres::maintenance::verify::Problem;
+
+pub mod maintenance_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct TaskInfo {
+    pub task: MaintenanceTask,
+    /// Cron expression, empty if the task only runs when triggered manually
+    pub schedule: String,
+    pub next_run: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JobInfo {
+    pub id: i64,
+    pub task: MaintenanceTask,
+    pub triggered_by: JobTrigger,
+    pub target: Option<String>,
+    pub status: JobStatus,
+    pub operator: Option<String>,
+    pub message: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+impl From<maintenance_job::Model> for JobInfo {
+    fn from(value: maintenance_job::Model) -> Self {
+        Self {
+            id: value.id,
+            task: value.task,
+            triggered_by: value.triggered_by,
+            target: value.target,
+            status: value.status,
+            operator: value.operator,
+            message: value.message,
+            started_at: value.started_at.and_utc().timestamp(),
+            finished_at: value.finished_at.map(|dt| dt.and_utc().timestamp()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JobHistoryParams {
+    pub task: Option<MaintenanceTask>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct RunTask {
+    pub task: MaintenanceTask,
+    /// Repository to verify or collect garbage in, or directory to split, the whole monorepo if
+    /// omitted
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReportInfo {
+    pub job_id: i64,
+    pub path: String,
+    pub healthy: bool,
+    pub checked_objects: i64,
+    pub problems: Vec<Problem>,
+    pub created_at: i64,
+}
+
+impl From<integrity_report::Model> for ReportInfo {
+    fn from(value: integrity_report::Model) -> Self {
+        Self {
+            job_id: value.job_id,
+            path: value.path,
+            healthy: value.healthy,
+            checked_objects: value.checked_objects,
+            problems: serde_json::from_str(&value.problems).unwrap_or_default(),
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReportParams {
+    pub path: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StaleBranchInfo {
+    pub path: String,
+    pub branch: String,
+    pub commit: String,
+    pub updated_at: i64,
+}
+
+impl From<StaleBranch> for StaleBranchInfo {
+    fn from(value: StaleBranch) -> Self {
+        Self {
+            path: value.path,
+            branch: value.branch,
+            commit: value.commit,
+            updated_at: value.updated_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct StaleBranchParams {
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SplitInfo {
+    pub id: i64,
+    /// Monorepo directory which is split
+    pub path: String,
+    /// Path of the read-only split repository
+    pub repo_path: String,
+    pub last_commit: Option<String>,
+    pub last_split: Option<String>,
+    pub created_by: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl From<subtree_split::Model> for SplitInfo {
+    fn from(value: subtree_split::Model) -> Self {
+        Self {
+            id: value.id,
+            path: value.path,
+            repo_path: value.repo_path,
+            last_commit: value.last_commit,
+            last_split: value.last_split,
+            created_by: value.created_by,
+            created_at: value.created_at.and_utc().timestamp(),
+            updated_at: value.updated_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateSplit {
+    pub path: String,
+    pub repo_path: String,
+}
+
+/// A deleted repository, which can be restored until `purge_at`.
+#[derive(Serialize, Deserialize)]
+pub struct TrashInfo {
+    pub id: i64,
+    pub path: String,
+    pub deleted_by: Option<String>,
+    pub deleted_at: i64,
+    pub purge_at: i64,
+}
+
+impl TrashInfo {
+    pub fn new(repo: git_repo::Model, retention_days: u32) -> Self {
+        let deleted_at = repo.deleted_at.unwrap_or(repo.updated_at);
+        Self {
+            id: repo.id,
+            path: repo.repo_path,
+            deleted_by: repo.deleted_by,
+            deleted_at: deleted_at.and_utc().timestamp(),
+            purge_at: (deleted_at + chrono::Duration::days(retention_days as i64))
+                .and_utc()
+                .timestamp(),
+        }
+    }
+}
+
+/// Whether the instance is in maintenance mode, and why.
+#[derive(Serialize, Deserialize)]
+pub struct ModeInfo {
+    pub enabled: bool,
+    pub message: Option<String>,
+    pub enabled_by: Option<String>,
+    pub enabled_at: Option<i64>,
+}
+
+impl From<Option<MaintenanceMode>> for ModeInfo {
+    fn from(value: Option<MaintenanceMode>) -> Self {
+        match value {
+            Some(mode) => Self {
+                enabled: true,
+                message: Some(mode.message),
+                enabled_by: Some(mode.enabled_by),
+                enabled_at: Some(mode.enabled_at.and_utc().timestamp()),
+            },
+            None => Self {
+                enabled: false,
+                message: None,
+                enabled_by: None,
+                enabled_at: None,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SetMode {
+    pub enabled: bool,
+    /// Told to clients whose writes are rejected, `maintenance.mode_message` if omitted
+    pub message: Option<String>,
+}
@@ -17,12 +17,26 @@ use jupiter::{
 };
 
 pub mod api_router;
+pub mod break_glass;
 pub mod error;
+pub mod feature;
 pub mod issue;
 pub mod lfs;
+pub mod maintenance;
+pub mod mq;
 pub mod mr;
 pub mod oauth;
+pub mod policy;
+pub mod quota;
+pub mod release;
+pub mod repo;
+pub mod role;
+pub mod secret_scan;
+pub mod service_account;
+pub mod signature;
+pub mod stats;
 pub mod user;
+pub mod webhook;
 
 #[derive(Clone)]
 pub struct MonoApiServiceState {
@@ -45,9 +59,9 @@ impl FromRef<MonoApiServiceState> for BasicClient {
     }
 }
 
-impl FromRef<MonoApiServiceState> for UserStorage {
+impl FromRef<MonoApiServiceState> for Context {
     fn from_ref(state: &MonoApiServiceState) -> Self {
-        state.context.user_stg()
+        state.context.clone()
     }
 }
 
@@ -94,54 +108,538 @@ impl MonoApiServiceState {
     }
 }
 pub mod util {
-    use std::path::PathBuf;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
 
+    use async_trait::async_trait;
     use axum::extract::State;
 
-    use cedar_policy::Context;
-    use ceres::api_service::ApiHandler;
-    use saturn::{context::CedarContext, entitystore::EntityStore, util::EntityUid, ActionEnum};
+    use callisto::db_enums::{OrgRole, TeamPermission, Visibility};
+    use callisto::{auth_decision, organization};
+    use ceres::api_service::{mono_api_service::MonoApiService, ApiHandler};
+    use ceres::entity_file::{self, ENTITY_FILE};
+    use ceres::tenant;
+    use common::config::AuthAudit;
+    use common::errors::{MegaError, ProtocolError};
+    use common::utils::generate_id;
+    use jupiter::context::Context as MegaContext;
+    use mercury::internal::object::tree::{Tree, TreeItemMode};
+    use saturn::{
+        context::{AuthDecision, CedarContext},
+        entitystore::{EntityStore, OrgEntities, RepoPermission, TeamEntities},
+        hierarchy::{DirectoryTree, TreeSource},
+        policy::PolicyBundle,
+        request::{AuthMethod, RequestInfo},
+        resolver::EntitySource,
+        role::{self, entity_uid},
+        service_account::principal_uid,
+        ActionEnum,
+    };
 
+    use crate::api::oauth::model::LoginUser;
     use crate::api::MonoApiServiceState;
+    use crate::server::middleware::PeerIdentity;
 
-    pub async fn get_entitystore(path: PathBuf, state: State<MonoApiServiceState>) -> EntityStore {
-        let mut entities: EntityStore = EntityStore::new();
-        for component in path.ancestors() {
-            if component != std::path::Path::new("/") {
-                let cedar_path = component.join(".mega_cedar.json");
-                let entity_str = state
-                    .monorepo()
-                    .get_blob_as_string(cedar_path)
-                    .await
-                    .unwrap();
-                if let Some(entity_str) = entity_str {
-                    entities.merge(serde_json::from_str(&entity_str).unwrap());
+    /// The entity files of the monorepo directories, identified by the hash of their blob.
+    struct MonoEntitySource {
+        monorepo: MonoApiService,
+    }
+
+    #[async_trait]
+    impl EntitySource for MonoEntitySource {
+        type Error = MegaError;
+
+        async fn file_id(&self, dir: &Path) -> Result<Option<String>, MegaError> {
+            if dir == Path::new("/") {
+                return Ok(None);
+            }
+            let Some(blob_id) = entity_file_id(dir, &self.monorepo.context).await? else {
+                return Ok(None);
+            };
+            // unsigned or tampered entity files are replaced by their last signed version
+            entity_file::trusted_blob(&self.monorepo.context, &dir.to_string_lossy(), &blob_id)
+                .await
+        }
+
+        async fn load(&self, id: &str) -> Result<EntityStore, MegaError> {
+            let blob = self
+                .monorepo
+                .get_raw_blob_by_hash(id)
+                .await?
+                .and_then(|model| model.data)
+                .ok_or_else(|| MegaError::with_message(&format!("missing blob {}", id)))?;
+            serde_json::from_slice(&blob).map_err(|e| MegaError::with_message(&e.to_string()))
+        }
+    }
+
+    /// The trees of the monorepo, the resource hierarchy is generated from.
+    struct MonoTreeSource<'a> {
+        context: &'a MegaContext,
+    }
+
+    #[async_trait]
+    impl TreeSource for MonoTreeSource<'_> {
+        type Error = MegaError;
+
+        async fn root(&self) -> Result<Option<String>, MegaError> {
+            let refs = self.context.services.mono_storage.get_ref("/").await?;
+            Ok(refs.map(|refs| refs.ref_tree_hash))
+        }
+
+        async fn subtrees(
+            &self,
+            ids: Vec<String>,
+        ) -> Result<HashMap<String, Vec<(String, String)>>, MegaError> {
+            let trees = self
+                .context
+                .services
+                .mono_storage
+                .get_trees_by_hashes(ids)
+                .await?;
+            Ok(trees
+                .into_iter()
+                .map(|model| {
+                    let tree = Tree::from(model);
+                    let subtrees = tree
+                        .tree_items
+                        .into_iter()
+                        .filter(|item| item.mode == TreeItemMode::Tree)
+                        .map(|item| (item.name, item.id.to_string()))
+                        .collect();
+                    (tree.id.to_string(), subtrees)
+                })
+                .collect())
+        }
+    }
+
+    /// The directories of the monorepo, rebuilt from its trees if they changed.
+    pub async fn directory_tree(context: &MegaContext) -> Result<Arc<DirectoryTree>, MegaError> {
+        context
+            .hierarchy
+            .directories(&MonoTreeSource { context })
+            .await
+    }
+
+    /// The blob of the entity file in the directory `dir`, whether it is trusted or not.
+    pub async fn entity_file_id(
+        dir: &Path,
+        context: &MegaContext,
+    ) -> Result<Option<String>, MegaError> {
+        let monorepo = MonoApiService {
+            context: context.clone(),
+        };
+        let tree = monorepo
+            .search_tree_by_path(dir)
+            .await
+            .map_err(|e| MegaError::with_message(&e.to_string()))?;
+        Ok(tree.and_then(|tree| {
+            tree.tree_items
+                .into_iter()
+                .find(|item| item.name == ENTITY_FILE)
+                .map(|item| item.id.to_string())
+        }))
+    }
+
+    pub async fn get_entitystore(
+        path: PathBuf,
+        context: &MegaContext,
+    ) -> Result<EntityStore, MegaError> {
+        let source = MonoEntitySource {
+            monorepo: MonoApiService {
+                context: context.clone(),
+            },
+        };
+        let resolved = context.entities.resolve(&path, &source).await?;
+        let mut entities = EntityStore::clone(&resolved);
+        append_org_entities(&mut entities, &path, context).await?;
+        if let Some((owned, user)) = context.user_stg().find_owner_user(&path).await? {
+            entities.add_repo_admin(&owned.path, &user.name);
+        }
+        let visibility = context.services.mono_storage.get_visibility(&path).await?;
+        entities.set_repo_visibility(
+            path.to_str().unwrap(),
+            visibility != Visibility::Public,
+            visibility == Visibility::Internal,
+        );
+        let settings = context.settings();
+        if let Some(tenant) = tenant::of_path(&settings.tenancy, path.to_str().unwrap()) {
+            entities.set_repo_tenant(path.to_str().unwrap(), &tenant.name);
+        }
+        let directories = directory_tree(context).await?;
+        entities.set_repo_directories(
+            path.to_str().unwrap(),
+            &directories.directories(path.to_str().unwrap()),
+        );
+        Ok(entities)
+    }
+
+    /// Add the organization owning `path` with its members and teams to the entity store.
+    async fn append_org_entities(
+        entities: &mut EntityStore,
+        path: &Path,
+        context: &MegaContext,
+    ) -> Result<(), MegaError> {
+        let storage = context.user_stg();
+        let Some((owned, org)) = storage.find_owner_org(path).await? else {
+            return Ok(());
+        };
+        let org_entities = load_org_entities(org, None, context).await?;
+        entities.add_org(&owned.path, &org_entities);
+        Ok(())
+    }
+
+    /// Add the organizations and teams `username` is a member of, policies may grant
+    /// permissions to them, and the tenant of the user.
+    pub async fn append_user_groups(
+        entities: &mut EntityStore,
+        username: &str,
+        context: &MegaContext,
+    ) -> Result<(), MegaError> {
+        let storage = context.user_stg();
+        let Some(user) = storage.find_user_by_name(username).await? else {
+            return Ok(());
+        };
+        if let Some(tenant) = user.tenant.filter(|_| context.settings().tenancy.enable) {
+            entities.set_user_tenant(username, &tenant);
+        }
+        for org in storage.list_user_orgs(user.id).await? {
+            let org_entities = load_org_entities(org, Some(user.id), context).await?;
+            entities.add_org_groups(&org_entities);
+        }
+        Ok(())
+    }
+
+    /// The members and teams of `org`, only the memberships of `user_id` if given.
+    async fn load_org_entities(
+        org: organization::Model,
+        user_id: Option<i64>,
+        context: &MegaContext,
+    ) -> Result<OrgEntities, MegaError> {
+        let storage = context.user_stg();
+        let is_selected = |id: i64| user_id.is_none_or(|user_id| user_id == id);
+        let members: Vec<_> = storage
+            .list_org_members(org.id)
+            .await?
+            .into_iter()
+            .filter(|m| is_selected(m.user_id))
+            .collect();
+        let teams = storage.list_teams(org.id).await?;
+        let team_members: Vec<_> = storage
+            .list_team_members(teams.iter().map(|t| t.id).collect())
+            .await?
+            .into_iter()
+            .filter(|m| is_selected(m.user_id))
+            .collect();
+
+        let mut user_ids: Vec<i64> = members.iter().map(|m| m.user_id).collect();
+        user_ids.extend(team_members.iter().map(|m| m.user_id));
+        let names: HashMap<i64, String> = storage
+            .find_users_by_ids(user_ids)
+            .await?
+            .into_iter()
+            .map(|u| (u.id, u.name))
+            .collect();
+        let team_names: HashMap<i64, String> =
+            teams.iter().map(|t| (t.id, t.name.clone())).collect();
+
+        let mut org_entities = OrgEntities {
+            name: org.name,
+            ..Default::default()
+        };
+        for member in members {
+            if let Some(name) = names.get(&member.user_id) {
+                match member.role {
+                    OrgRole::Owner => org_entities.owners.push(name.clone()),
+                    OrgRole::Member => org_entities.members.push(name.clone()),
                 }
             }
         }
-        entities
+        for team in teams {
+            org_entities.teams.push(TeamEntities {
+                parent: team.parent_id.and_then(|id| team_names.get(&id).cloned()),
+                permission: match team.permission {
+                    TeamPermission::Admin => RepoPermission::Admin,
+                    TeamPermission::Maintainer => RepoPermission::Maintainer,
+                    TeamPermission::Reader => RepoPermission::Reader,
+                },
+                members: team_members
+                    .iter()
+                    .filter(|m| m.team_id == team.id)
+                    .filter_map(|m| names.get(&m.user_id).cloned())
+                    .collect(),
+                name: team.name,
+            });
+        }
+        Ok(org_entities)
     }
 
-    pub async fn check_permissions(
+    /// The entities of `path` with the groups of `username`, to authorize the user on it.
+    async fn user_entities(
         username: &str,
         path: &str,
+        context: &MegaContext,
+    ) -> Result<EntityStore, MegaError> {
+        let mut entities = get_entitystore(path.into(), context).await?;
+        append_user_groups(&mut entities, username, context).await?;
+        Ok(entities)
+    }
+
+    pub async fn check_permissions(
+        user: &LoginUser,
+        path: &str,
+        operation: ActionEnum,
+        state: State<MonoApiServiceState>,
+    ) -> Result<(), saturn::context::Error> {
+        is_authorized(
+            &user.name,
+            path,
+            operation,
+            &user.request_info(),
+            &state.context,
+        )
+        .await
+    }
+
+    /// Check that `user` may do `operation` on the whole instance, otherwise they are
+    /// forbidden with `message`.
+    pub async fn check_instance_admin(
+        user: &LoginUser,
         operation: ActionEnum,
+        message: &str,
         state: State<MonoApiServiceState>,
+    ) -> Result<(), ProtocolError> {
+        check_permissions(user, "/", operation, state)
+            .await
+            .map_err(|_| ProtocolError::Forbidden(message.to_owned()))
+    }
+
+    /// Check whether `username` may do `operation` on `path`, the policies may also take
+    /// `request` into account.
+    #[tracing::instrument(skip(request, context))]
+    pub async fn is_authorized(
+        username: &str,
+        path: &str,
+        operation: ActionEnum,
+        request: &RequestInfo,
+        context: &MegaContext,
     ) -> Result<(), saturn::context::Error> {
-        let entities = get_entitystore(path.into(), state).await;
-        let cedar_context = CedarContext::new(entities).unwrap();
-        cedar_context.is_authorized(
-            format!(r#"User::"{}""#, username)
-                .to_owned()
-                .parse::<EntityUid>()
-                .unwrap(),
-            format!(r#"Action::"{}""#, operation)
-                .parse::<EntityUid>()
-                .unwrap(),
-            format!(r#"Repository::"{}""#, path)
-                .parse::<EntityUid>()
-                .unwrap(),
-            Context::empty(),
+        let entities = user_entities(username, path, context)
+            .await
+            .map_err(|err| saturn::context::Error::Request(err.to_string()))?;
+        let cedar_context = CedarContext::with_policies(entities, context.policies.current());
+        let decision = cedar_context.authorize(
+            principal_uid(username),
+            entity_uid("Action", &operation.to_string()),
+            entity_uid("Repository", path),
+            request.context()?,
+        )?;
+        record_decision(
+            username,
+            &operation.to_string(),
+            operation.is_sensitive(),
+            path,
+            &decision,
+            context,
+        );
+        decision.into_result()
+    }
+
+    /// Like [`is_authorized`] for an action declared by an extension, see [`saturn::policy`].
+    pub async fn is_authorized_custom(
+        username: &str,
+        path: &str,
+        action: &str,
+        request: &RequestInfo,
+        context: &MegaContext,
+    ) -> Result<(), saturn::context::Error> {
+        let policies = context.policies.current();
+        if !policies.is_custom_action(action) {
+            return Err(saturn::context::Error::Request(format!(
+                "unknown action: {}",
+                action
+            )));
+        }
+        let decision = decide(username, action, path, request, policies, context).await?;
+        record_decision(username, action, false, path, &decision, context);
+        decision.into_result()
+    }
+
+    /// The actions `username` may do on `path`, so they can be offered instead of failing.
+    #[tracing::instrument(skip(request, context))]
+    pub async fn allowed_actions(
+        username: &str,
+        path: &str,
+        request: &RequestInfo,
+        context: &MegaContext,
+    ) -> Result<Vec<ActionEnum>, saturn::context::Error> {
+        let entities = user_entities(username, path, context)
+            .await
+            .map_err(|err| saturn::context::Error::Request(err.to_string()))?;
+        let cedar_context = CedarContext::with_policies(entities, context.policies.current());
+        cedar_context.allowed_actions(
+            principal_uid(username),
+            entity_uid("Repository", path),
+            &ActionEnum::ALL,
+            request.context()?,
         )
     }
+
+    /// The policies and the entities of `path` sliced to `username`, for the frontend to decide
+    /// which actions to offer, see [`saturn::wasm`]. Role assignments are only included as far
+    /// as they apply to the user.
+    pub async fn permission_hints(
+        username: &str,
+        path: &str,
+        context: &MegaContext,
+    ) -> Result<(String, EntityStore), MegaError> {
+        let entities = user_entities(username, path, context).await?;
+        let assignments = context.services.policy_storage.role_assignments().await?;
+        let roles: Vec<_> = role::effective_roles(&assignments, &entities, username, path)
+            .into_iter()
+            .cloned()
+            .collect();
+        let policies = format!(
+            "{}\n{}",
+            context.policies.current().public_policies(),
+            role::policies(&roles)
+        );
+        let entities = entities.slice(&principal_uid(username), &entity_uid("Repository", path));
+        Ok((policies, entities))
+    }
+
+    /// Decide whether `username` may do `action` on `path` with `policies`, without enforcing
+    /// or recording the decision.
+    #[tracing::instrument(skip(request, policies, context))]
+    pub async fn decide(
+        username: &str,
+        action: &str,
+        path: &str,
+        request: &RequestInfo,
+        policies: Arc<PolicyBundle>,
+        context: &MegaContext,
+    ) -> Result<AuthDecision, saturn::context::Error> {
+        let entities = user_entities(username, path, context)
+            .await
+            .map_err(|err| saturn::context::Error::Request(err.to_string()))?;
+        CedarContext::with_policies(entities, policies).authorize(
+            principal_uid(username),
+            entity_uid("Action", action),
+            entity_uid("Repository", path),
+            request.context()?,
+        )
+    }
+
+    /// Keep the decision in the audit trail if configured to, it is written in the background.
+    fn record_decision(
+        username: &str,
+        action: &str,
+        sensitive: bool,
+        path: &str,
+        decision: &AuthDecision,
+        context: &MegaContext,
+    ) {
+        // break-glass decisions are always recorded
+        let record = decision.is_break_glass()
+            || match context.settings().policy.audit {
+                AuthAudit::Off => false,
+                AuthAudit::Denied => !decision.is_allowed() || sensitive,
+                AuthAudit::All => true,
+            };
+        if !record {
+            return;
+        }
+        let errors = decision.errors();
+        save_decision(
+            auth_decision::Model {
+                id: generate_id(),
+                principal: username.to_owned(),
+                action: action.to_owned(),
+                resource: path.to_owned(),
+                allowed: decision.is_allowed(),
+                policies: decision.policies().join(","),
+                errors: (!errors.is_empty()).then(|| errors.join("\n")),
+                break_glass: decision.is_break_glass(),
+                created_at: chrono::Utc::now().naive_utc(),
+            },
+            context,
+        );
+    }
+
+    /// Write `model` to the audit trail in the background.
+    pub fn save_decision(model: auth_decision::Model, context: &MegaContext) {
+        let storage = context.services.policy_storage.clone();
+        tokio::spawn(async move {
+            if let Err(err) = storage.save_auth_decision(model).await {
+                tracing::error!("failed to record authorization decision: {}", err);
+            }
+        });
+    }
+
+    /// Check whether `username` (`None` for anonymous requests) can read `path`.
+    ///
+    /// Public paths are readable by anyone, other paths require a signed in user and are
+    /// then checked by saturn, denied reads are reported as not found so private
+    /// repositories can't be discovered. With tenancy, public paths of tenants are not
+    /// readable by anonymous users, and saturn decides for signed in users whether the read
+    /// crosses tenants.
+    pub async fn check_read_access(
+        username: Option<&str>,
+        path: &Path,
+        request: &RequestInfo,
+        context: &MegaContext,
+    ) -> Result<(), ProtocolError> {
+        let visibility = context
+            .services
+            .mono_storage
+            .get_visibility(path)
+            .await
+            .map_err(|err| ProtocolError::Unavailable(err.to_string()))?;
+        let settings = context.settings();
+        let tenancy = &settings.tenancy;
+        let public = match username {
+            _ if visibility != Visibility::Public => false,
+            Some(_) => !tenancy.enable,
+            None => tenant::of_path(tenancy, path.to_str().unwrap()).is_none(),
+        };
+        if public {
+            return Ok(());
+        }
+        let not_found = || ProtocolError::NotFound(path.display().to_string());
+        let username = username.ok_or_else(not_found)?;
+        is_authorized(
+            username,
+            path.to_str().unwrap(),
+            ActionEnum::ViewRepo,
+            request,
+            context,
+        )
+        .await
+        .map_err(|_| not_found())
+    }
+
+    /// [`check_read_access`] for the signed in `user` of an api request.
+    pub async fn check_user_read_access(
+        user: Option<&LoginUser>,
+        path: &Path,
+        context: &MegaContext,
+    ) -> Result<(), ProtocolError> {
+        let request = user.map(|u| u.request_info()).unwrap_or_default();
+        check_read_access(user.map(|u| u.name.as_str()), path, &request, context).await
+    }
+
+    /// [`check_read_access`] for a request of a ztm `peer`, made as the principal it is mapped
+    /// to, anonymous for the requests which didn't come through an encrypted channel.
+    pub async fn check_peer_read_access(
+        peer: Option<&PeerIdentity>,
+        path: &Path,
+        context: &MegaContext,
+    ) -> Result<(), ProtocolError> {
+        let username = peer.and_then(|peer| peer.principal.as_deref());
+        let request = RequestInfo {
+            auth_method: username.map(|_| AuthMethod::PeerKey),
+            ..Default::default()
+        };
+        check_read_access(username, path, &request, context).await
+    }
 }
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::MessageState;
+use callisto::mq_storage;
+use taurus::replay::Consumer;
+
+pub mod mq_router;
+
+#[derive(Serialize)]
+pub struct MessageInfo {
+    pub id: i64,
+    pub category: Option<String>,
+    pub content: Option<String>,
+    pub state: String,
+    pub lane: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    /// Consumer processing the message while it is inflight
+    pub claimed_by: Option<String>,
+    /// When a failed or inflight message is due again, unix timestamp
+    pub visible_at: Option<i64>,
+    pub create_time: i64,
+}
+
+impl From<mq_storage::Model> for MessageInfo {
+    fn from(value: mq_storage::Model) -> Self {
+        Self {
+            id: value.id,
+            category: value.category,
+            content: value.content,
+            state: value.state.to_string(),
+            lane: value.lane.to_string(),
+            attempts: value.attempts,
+            last_error: value.last_error,
+            claimed_by: value.claimed_by,
+            visible_at: value.visible_at.map(|t| t.and_utc().timestamp()),
+            create_time: value.create_time.and_utc().timestamp(),
+        }
+    }
+}
+
+/// Events to replay to a consumer, those after the message `after` and created at `since` or
+/// later, both optional.
+#[derive(Deserialize)]
+pub struct ReplayRequest {
+    pub consumer: Consumer,
+    pub after: Option<i64>,
+    /// Unix timestamp
+    pub since: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct MessageFilter {
+    pub state: MessageState,
+}
+
+/// Messages in `state` to delete, only those of `category` if given.
+#[derive(Deserialize)]
+pub struct PurgeRequest {
+    pub state: MessageState,
+    pub category: Option<String>,
+}
+
+/// The unprocessed messages of a category in a state.
+#[derive(Serialize)]
+pub struct QueueDepth {
+    pub category: Option<String>,
+    pub state: String,
+    pub count: i64,
+    /// Seconds since the oldest of them was published
+    pub lag: i64,
+}
@@ -0,0 +1,302 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use callisto::db_enums::MessageState;
+use common::{
+    config::MqBroker,
+    model::{CommonPage, CommonResult, Pagination},
+};
+use saturn::ActionEnum;
+use taurus::event::schema::{self, EventSchema};
+use taurus::replay::{Consumer, ReplayPayload, REPLAY_JOB};
+
+use crate::api::error::ApiError;
+use crate::api::mq::{MessageFilter, MessageInfo, PurgeRequest, QueueDepth, ReplayRequest};
+use crate::api::oauth::model::LoginUser;
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/mq",
+        Router::new()
+            .route("/schemas", get(list_schemas))
+            .route("/stats", get(queue_stats))
+            .route("/messages", get(list_messages))
+            .route("/messages/{id}", get(get_message).delete(delete_message))
+            .route("/purge", post(purge))
+            .route("/dead", get(list_dead))
+            .route("/dead/requeue", post(requeue_all))
+            .route("/dead/{id}/requeue", post(requeue))
+            .route("/replay", post(replay)),
+    )
+}
+
+const FORBIDDEN: &str = "managing messages requires admin permission";
+
+/// The schemas of the typed events with all their versions, for consumers of the events.
+async fn list_schemas(_: LoginUser) -> Json<CommonResult<[&'static EventSchema; 4]>> {
+    Json(CommonResult::success(Some(schema::registry())))
+}
+
+/// The unprocessed messages by category and state, with the age of the oldest of each. Only
+/// the database broker keeps the messages.
+async fn queue_stats(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<QueueDepth>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    if state.context.config.mq.broker != MqBroker::Database {
+        return Ok(Json(CommonResult::failed(
+            "messages are only kept with the database broker",
+        )));
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let depth = state.context.services.mq_storage.queue_depth().await?;
+    let res = depth
+        .into_iter()
+        .map(|(category, msg_state, count, oldest)| QueueDepth {
+            category,
+            state: msg_state.to_string(),
+            count,
+            lag: oldest.map_or(0, |oldest| (now - oldest).num_seconds().max(0)),
+        })
+        .collect();
+    Ok(Json(CommonResult::success(Some(res))))
+}
+
+/// Messages in a state, latest first, to see what is pending or stuck.
+async fn list_messages(
+    user: LoginUser,
+    Query(filter): Query<MessageFilter>,
+    Query(page): Query<Pagination>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<CommonPage<MessageInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let (items, total) = state
+        .context
+        .services
+        .mq_storage
+        .list_messages(filter.state, page)
+        .await?;
+    Ok(Json(CommonResult::success(Some(CommonPage {
+        items: items.into_iter().map(|m| m.into()).collect(),
+        total,
+    }))))
+}
+
+async fn get_message(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<MessageInfo>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state.context.services.mq_storage.get_message(id).await? {
+        Some(msg) => CommonResult::success(Some(msg.into())),
+        None => CommonResult::failed("message not found"),
+    };
+    Ok(Json(res))
+}
+
+/// Delete a message so it is never processed, unless it is being processed.
+async fn delete_message(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = if state.context.services.mq_storage.delete_message(id).await? {
+        tracing::info!("message {} deleted by {}", id, user.name);
+        CommonResult::success(None)
+    } else {
+        CommonResult::failed("message not found or being processed")
+    };
+    Ok(Json(res))
+}
+
+/// Delete all messages in a state, of a category if given, returns their number. Messages
+/// being processed are never deleted.
+async fn purge(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<PurgeRequest>,
+) -> Result<Json<CommonResult<u64>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    if json.state == MessageState::Inflight {
+        return Ok(Json(CommonResult::failed(
+            "messages being processed can not be purged",
+        )));
+    }
+    let count = state
+        .context
+        .services
+        .mq_storage
+        .purge_messages(json.state, json.category.as_deref())
+        .await?;
+    tracing::info!(
+        "{} {} messages of {} purged by {}",
+        count,
+        json.state,
+        json.category.as_deref().unwrap_or("all categories"),
+        user.name
+    );
+    Ok(Json(CommonResult::success(Some(count))))
+}
+
+/// Messages whose processing failed too often, latest first.
+async fn list_dead(
+    user: LoginUser,
+    Query(page): Query<Pagination>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<CommonPage<MessageInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state
+        .context
+        .services
+        .mq_storage
+        .list_messages(MessageState::Dead, page)
+        .await
+    {
+        Ok((items, total)) => CommonResult::success(Some(CommonPage {
+            items: items.into_iter().map(|m| m.into()).collect(),
+            total,
+        })),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Retry a dead-lettered message with a fresh attempt count.
+async fn requeue(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state.context.services.mq_storage.requeue_message(id).await {
+        Ok(true) => CommonResult::success(None),
+        Ok(false) => CommonResult::failed("dead-lettered message not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Retry all dead-lettered messages, returns their number.
+async fn requeue_all(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<u64>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state
+        .context
+        .services
+        .mq_storage
+        .requeue_dead_messages()
+        .await
+    {
+        Ok(count) => CommonResult::success(Some(count)),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Queue a replay of processed events to a consumer, returns the id of its background job.
+/// Only the database broker keeps the processed messages.
+async fn replay(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<ReplayRequest>,
+) -> Result<Json<CommonResult<i64>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    if state.context.config.mq.broker != MqBroker::Database {
+        return Ok(Json(CommonResult::failed(
+            "events are only replayed with the database broker",
+        )));
+    }
+    if let Consumer::Webhook { id } = json.consumer {
+        if state
+            .context
+            .services
+            .webhook_storage
+            .find_webhook(id)
+            .await?
+            .is_none()
+        {
+            return Ok(Json(CommonResult::failed("webhook not found")));
+        }
+    }
+    let payload = ReplayPayload {
+        consumer: json.consumer,
+        after: json.after,
+        since: json.since,
+        operator: user.name.clone(),
+    };
+    let storage = &state.context.services.job_storage;
+    let job = storage
+        .enqueue(storage.get_connection(), REPLAY_JOB, &payload, None)
+        .await?;
+    tracing::info!(
+        "replay of events to {:?} queued by {} as job {}",
+        payload.consumer,
+        user.name,
+        job.id
+    );
+    Ok(Json(CommonResult::success(Some(job.id))))
+}
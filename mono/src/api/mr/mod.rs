@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use callisto::{mega_conversation, mega_mr};
+use callisto::{mega_conversation, mega_mr, mega_mr_mergeability};
 
 pub mod mr_router;
 
@@ -41,6 +41,8 @@ pub struct MRDetail {
     pub open_timestamp: i64,
     pub merge_timestamp: Option<i64>,
     pub conversations: Vec<MegaConversation>,
+    /// Whether an open merge request merges cleanly, `None` for merged and closed ones
+    pub mergeability: Option<MergeabilityInfo>,
 }
 
 impl From<mega_mr::Model> for MRDetail {
@@ -53,6 +55,25 @@ impl From<mega_mr::Model> for MRDetail {
             open_timestamp: value.created_at.and_utc().timestamp(),
             merge_timestamp: value.merge_date.map(|dt| dt.and_utc().timestamp()),
             conversations: vec![],
+            mergeability: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MergeabilityInfo {
+    /// `mergeable` or `conflicting`
+    pub status: String,
+    pub conflict_paths: Vec<String>,
+    pub checked_at: i64,
+}
+
+impl From<mega_mr_mergeability::Model> for MergeabilityInfo {
+    fn from(value: mega_mr_mergeability::Model) -> Self {
+        Self {
+            status: value.status.to_string(),
+            conflict_paths: serde_json::from_str(&value.conflict_paths).unwrap_or_default(),
+            checked_at: value.checked_at.and_utc().timestamp(),
         }
     }
 }
@@ -90,4 +111,4 @@ pub struct FilesChangedItem {
 pub struct FilesChangedList {
     pub files: Vec<FilesChangedItem>,
     pub content: String,
-}
\ No newline at end of file
+}
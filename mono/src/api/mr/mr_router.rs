@@ -9,10 +9,14 @@ use axum::{
 use bytes::Bytes;
 
 use callisto::db_enums::{ConvType, MergeStatus};
+use ceres::entity_file;
+use ceres::merge;
 use ceres::protocol::mr::MergeRequest;
+use ceres::tenant;
 use common::model::{CommonPage, CommonResult, PageParams};
 use saturn::ActionEnum;
 use taurus::event::api_request::{ApiRequestEvent, ApiType};
+use taurus::event::mr_updated::{MrAction, MrUpdatedEvent};
 
 use crate::api::error::ApiError;
 use crate::api::mr::{FilesChangedItem, FilesChangedList, MRDetail, MRStatusParams, MrInfoItem};
@@ -43,13 +47,14 @@ async fn reopen_mr(
     if let Some(model) = state.mr_stg().get_mr(&link).await.unwrap() {
         if model.status == MergeStatus::Closed {
             util::check_permissions(
-                &user.name,
+                &user,
                 &model.path,
                 ActionEnum::EditMergeRequest,
                 state.clone(),
             )
             .await
             .unwrap();
+            let path = model.path.clone();
             let mut mr: MergeRequest = model.into();
             mr.status = MergeStatus::Open;
             let res = match state
@@ -57,7 +62,12 @@ async fn reopen_mr(
                 .reopen_mr(mr.into(), user.user_id, &user.name)
                 .await
             {
-                Ok(_) => CommonResult::success(None),
+                Ok(_) => {
+                    merge::refresh(&state.context, &link).await;
+                    let action = MrAction::Reopened;
+                    MrUpdatedEvent::notify(&link, &path, action, Some(&user.name)).await;
+                    CommonResult::success(None)
+                }
                 Err(err) => CommonResult::failed(&err.to_string()),
             };
             return Ok(Json(res));
@@ -74,13 +84,14 @@ async fn close_mr(
     if let Some(model) = state.mr_stg().get_mr(&link).await.unwrap() {
         if model.status == MergeStatus::Open {
             util::check_permissions(
-                &user.name,
+                &user,
                 &model.path,
                 ActionEnum::EditMergeRequest,
                 state.clone(),
             )
             .await
             .unwrap();
+            let path = model.path.clone();
             let mut mr: MergeRequest = model.into();
             mr.status = MergeStatus::Closed;
             let res = match state
@@ -88,7 +99,11 @@ async fn close_mr(
                 .close_mr(mr.into(), user.user_id, &user.name)
                 .await
             {
-                Ok(_) => CommonResult::success(None),
+                Ok(_) => {
+                    let action = MrAction::Closed;
+                    MrUpdatedEvent::notify(&link, &path, action, Some(&user.name)).await;
+                    CommonResult::success(None)
+                }
                 Err(err) => CommonResult::failed(&err.to_string()),
             };
             return Ok(Json(res));
@@ -105,32 +120,77 @@ async fn merge(
     if let Some(model) = state.mr_stg().get_mr(&link).await.unwrap() {
         if model.status == MergeStatus::Open {
             let path = model.path.clone();
-            util::check_permissions(
-                &user.name,
-                &path,
-                ActionEnum::ApproveMergeRequest,
-                state.clone(),
-            )
-            .await
-            .unwrap();
-            ApiRequestEvent::notify(ApiType::MergeRequest, &state.0.context.config);
+            util::check_permissions(&user, &path, ActionEnum::ApproveMergeRequest, state.clone())
+                .await
+                .unwrap();
+            ApiRequestEvent::notify(ApiType::MergeRequest, &state.0.context.config).await;
+            let (from_hash, to_hash) = (model.from_hash.clone(), model.to_hash.clone());
             let res = state.monorepo().merge_mr(&mut model.into()).await;
             let res = match res {
-                Ok(_) => CommonResult::success(None),
+                Ok(_) => {
+                    sign_entity_files(&state, &user, &path, &from_hash, &to_hash).await;
+                    let action = MrAction::Merged;
+                    MrUpdatedEvent::notify(&link, &path, action, Some(&user.name)).await;
+                    CommonResult::success(None)
+                }
                 Err(err) => CommonResult::failed(&err.to_string()),
             };
-            ApiRequestEvent::notify(ApiType::MergeDone, &state.0.context.config);
+            ApiRequestEvent::notify(ApiType::MergeDone, &state.0.context.config).await;
             return Ok(Json(res));
         }
     }
     Ok(Json(CommonResult::failed("not found")))
 }
 
+/// Sign the entity files a merged merge request brought to the main ref in the directories the
+/// user who merged it administers, the others are not trusted until an admin merges them.
+async fn sign_entity_files(
+    state: &State<MonoApiServiceState>,
+    user: &LoginUser,
+    path: &str,
+    from_hash: &str,
+    to_hash: &str,
+) {
+    let context = &state.context;
+    if context.config.policy.entity_signing_key.is_empty() {
+        return;
+    }
+    let files = match entity_file::changed_entity_files(context, path, from_hash, to_hash).await {
+        Ok(files) => files,
+        Err(err) => {
+            tracing::error!(
+                "failed to find the entity files changed in {}: {}",
+                path,
+                err
+            );
+            return;
+        }
+    };
+    for (dir, blob_id) in files {
+        let allowed = util::check_permissions(user, &dir, ActionEnum::AddAdmin, state.clone())
+            .await
+            .is_ok();
+        if !allowed {
+            tracing::warn!(
+                "entity file of {} merged by {} is not signed, admin permission required",
+                dir,
+                user.name
+            );
+            continue;
+        }
+        if let Err(err) = entity_file::sign_file(context, &dir, &blob_id, &user.name).await {
+            tracing::error!("failed to sign the entity file of {}: {}", dir, err);
+        }
+    }
+}
+
+/// The merge requests of the tenant of the user, see [`ceres::tenant`].
 async fn fetch_mr_list(
+    user: Option<LoginUser>,
     state: State<MonoApiServiceState>,
     Json(json): Json<PageParams<MRStatusParams>>,
 ) -> Result<Json<CommonResult<CommonPage<MrInfoItem>>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::MergeList, &state.0.context.config);
+    ApiRequestEvent::notify(ApiType::MergeList, &state.0.context.config).await;
     let status = json.additional.status;
     let status = if status == "open" {
         vec![MergeStatus::Open]
@@ -139,9 +199,25 @@ async fn fetch_mr_list(
     } else {
         vec![MergeStatus::Open, MergeStatus::Closed, MergeStatus::Merged]
     };
+    let scope = tenant::user_scope(&state.context, user.as_ref().map(|u| u.name.as_str())).await?;
+    // only the merge requests of the paths the user can read are listed
+    let mut paths = vec![];
+    for path in state.mr_stg().get_mr_paths(status.clone(), &scope).await? {
+        if util::check_user_read_access(user.as_ref(), std::path::Path::new(&path), &state.context)
+            .await
+            .is_ok()
+        {
+            paths.push(path);
+        }
+    }
     let res = match state
         .mr_stg()
-        .get_mr_by_status(status, json.pagination.page, json.pagination.per_page)
+        .get_mr_by_paths(
+            status,
+            paths,
+            json.pagination.page,
+            json.pagination.per_page,
+        )
         .await
     {
         Ok((items, total)) => CommonResult::success(Some(CommonPage {
@@ -154,16 +230,33 @@ async fn fetch_mr_list(
 }
 
 async fn mr_detail(
+    user: Option<LoginUser>,
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<MRDetail>>, ApiError> {
-    ApiRequestEvent::notify(ApiType::MergeDetail, &state.0.context.config);
-    let res = match state.mr_stg().get_mr(&link).await {
+    ApiRequestEvent::notify(ApiType::MergeDetail, &state.0.context.config).await;
+    let res = match state.mr_stg().get_mr_with_conversations(&link).await {
         Ok(data) => {
-            if let Some(model) = data {
+            if let Some((model, conversations)) = data {
+                util::check_user_read_access(
+                    user.as_ref(),
+                    std::path::Path::new(&model.path),
+                    &state.context,
+                )
+                .await?;
+                let mergeability = if model.status == MergeStatus::Open {
+                    merge::current(&state.context, &model)
+                        .await
+                        .unwrap_or_else(|err| {
+                            tracing::error!("failed to check whether {} merges: {}", link, err);
+                            None
+                        })
+                } else {
+                    None
+                };
                 let mut detail: MRDetail = model.into();
-                let conversations = state.mr_stg().get_mr_conversations(&link).await.unwrap();
                 detail.conversations = conversations.into_iter().map(|x| x.into()).collect();
+                detail.mergeability = mergeability.map(|m| m.into());
                 CommonResult::success(Some(detail))
             } else {
                 CommonResult::success(None)
@@ -175,9 +268,19 @@ async fn mr_detail(
 }
 
 async fn get_mr_files_changed(
+    user: Option<LoginUser>,
     Path(link): Path<String>,
     state: State<MonoApiServiceState>,
 ) -> Result<Json<CommonResult<FilesChangedList>>, ApiError> {
+    let Some(model) = state.mr_stg().get_mr(&link).await? else {
+        return Ok(Json(CommonResult::failed("not found")));
+    };
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&model.path),
+        &state.context,
+    )
+    .await?;
     let res = state.monorepo().content_diff(&link).await;
     let res = match res {
         Ok(data) => {
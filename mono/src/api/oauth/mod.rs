@@ -1,7 +1,9 @@
-use anyhow::Context;
+use std::convert::Infallible;
+
+use anyhow::Context as _;
 use async_session::{MemoryStore, Session, SessionStore};
 use axum::{
-    extract::{FromRef, FromRequestParts, Query, State},
+    extract::{FromRef, FromRequestParts, OptionalFromRequestParts, Query, State},
     http::{header::SET_COOKIE, HeaderMap},
     response::{IntoResponse, Redirect, Response},
     routing::get,
@@ -16,12 +18,15 @@ use oauth2::{
     ClientSecret, CsrfToken, RedirectUrl, Scope, TokenResponse, TokenUrl,
 };
 
+use ceres::{stats, tenant};
 use common::config::OauthConfig;
-use jupiter::storage::user_storage::UserStorage;
+use common::errors::ProtocolError;
+use jupiter::context::Context;
 use model::{GitHubUserJson, LoginUser, OauthCallbackParams};
 
 use crate::api::error::ApiError;
 use crate::api::MonoApiServiceState;
+use crate::server::middleware::ClientIp;
 
 pub mod model;
 
@@ -77,21 +82,29 @@ async fn login_authorized(
         tracing::error!("github:user_info:err {:?}", resp.text().await.unwrap());
     }
 
-    let new_user: user::Model = github_user.into();
+    let mfa = github_user.two_factor_authentication;
+    let mut new_user: user::Model = github_user.into();
     let user = state
         .user_stg()
         .find_user_by_email(&new_user.email)
         .await
         .unwrap();
 
-    let login_user: LoginUser;
+    let mut login_user: LoginUser;
     if let Some(user) = user {
+        if user.deactivated_at.is_some() {
+            return Err(ProtocolError::Forbidden("user is deactivated".to_owned()).into());
+        }
         // Create a new session filled with user data
         login_user = user.into();
     } else {
+        // new users join the tenant of their email domain
+        new_user.tenant = tenant::of_email(&state.context.settings().tenancy, &new_user.email)
+            .map(|t| t.name.clone());
         state.user_stg().save_user(new_user.clone()).await.unwrap();
         login_user = new_user.into();
     }
+    login_user.mfa = mfa;
 
     let mut session = Session::new();
     session
@@ -193,7 +206,7 @@ impl IntoResponse for AuthRedirect {
 impl<S> FromRequestParts<S> for LoginUser
 where
     MemoryStore: FromRef<S>,
-    UserStorage: FromRef<S>,
+    Context: FromRef<S>,
     S: Send + Sync,
 {
     // If anything goes wrong or no session is found, redirect to the auth page
@@ -220,8 +233,41 @@ where
             .unwrap()
             .ok_or(AuthRedirect)?;
 
-        let user = session.get::<LoginUser>("user").ok_or(AuthRedirect)?;
+        let mut user = session.get::<LoginUser>("user").ok_or(AuthRedirect)?;
+        // sessions of users deactivated after signing in are no longer accepted
+        let context = Context::from_ref(state);
+        let active = context
+            .user_stg()
+            .is_user_active(user.user_id)
+            .await
+            .unwrap_or(false);
+        if !active {
+            return Err(AuthRedirect);
+        }
+        user.ip = parts.extensions.get::<ClientIp>().map(|ClientIp(ip)| *ip);
+        stats::record_activity(&context, &user.name).await;
 
         Ok(user)
     }
 }
+
+/// Allow handlers to take `Option<LoginUser>` for endpoints which also serve anonymous users.
+impl<S> OptionalFromRequestParts<S> for LoginUser
+where
+    MemoryStore: FromRef<S>,
+    Context: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        Ok(
+            <LoginUser as FromRequestParts<S>>::from_request_parts(parts, state)
+                .await
+                .ok(),
+        )
+    }
+}
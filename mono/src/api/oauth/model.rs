@@ -1,5 +1,8 @@
+use std::net::IpAddr;
+
 use chrono::NaiveDateTime;
 use common::utils::generate_id;
+use saturn::request::{AuthMethod, RequestInfo};
 use serde::{Deserialize, Serialize};
 
 use callisto::user;
@@ -24,6 +27,9 @@ pub struct GitHubUserJson {
     pub avatar_url: String,
     // email can be null from github
     pub email: Option<String>,
+    /// Only returned for the signed in user
+    #[serde(default)]
+    pub two_factor_authentication: bool,
 }
 
 impl From<GitHubUserJson> for user::Model {
@@ -36,6 +42,8 @@ impl From<GitHubUserJson> for user::Model {
             is_github: true,
             created_at: chrono::Utc::now().naive_utc(),
             updated_at: None,
+            deactivated_at: None,
+            tenant: None,
         }
     }
 }
@@ -48,6 +56,24 @@ pub struct LoginUser {
     pub avatar_url: String,
     pub email: String,
     pub created_at: NaiveDateTime,
+    /// Whether the user signed in with two-factor authentication enabled
+    #[serde(default)]
+    pub mfa: bool,
+    /// Address of the client making the current request, not part of the session
+    #[serde(skip)]
+    pub ip: Option<IpAddr>,
+}
+
+impl LoginUser {
+    /// What policies may take into account about the current request.
+    pub fn request_info(&self) -> RequestInfo {
+        RequestInfo {
+            ip: self.ip,
+            auth_method: Some(AuthMethod::Session),
+            mfa: self.mfa,
+            ..Default::default()
+        }
+    }
 }
 
 impl From<user::Model> for LoginUser {
@@ -58,6 +84,8 @@ impl From<user::Model> for LoginUser {
             avatar_url: value.avatar_url,
             email: value.email,
             created_at: value.created_at,
+            mfa: false,
+            ip: None,
         }
     }
 }
@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::{auth_decision, policy_version};
+use saturn::{context::AuthDecision, request::RequestInfo};
+
+pub mod policy_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct PolicyInfo {
+    pub namespace: String,
+    pub version: i32,
+    /// The policies in the Cedar syntax, `None` for the version which deleted them
+    pub content: Option<String>,
+    pub operator: String,
+    pub created_at: i64,
+}
+
+impl From<policy_version::Model> for PolicyInfo {
+    fn from(value: policy_version::Model) -> Self {
+        Self {
+            namespace: value.namespace,
+            version: value.version,
+            content: value.content,
+            operator: value.operator,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SavePolicy {
+    pub content: String,
+}
+
+#[derive(Deserialize)]
+pub struct RollbackPolicy {
+    /// The version whose policies become the current ones
+    pub version: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DecisionInfo {
+    pub id: i64,
+    pub principal: String,
+    pub action: String,
+    pub resource: String,
+    pub allowed: bool,
+    /// Ids of the policies which decided, `namespace/policyN` for those of a namespace. A
+    /// denied request without any is denied as no policy permits it.
+    pub policies: Vec<String>,
+    pub errors: Vec<String>,
+    /// Allowed by a break-glass grant, or a grant being made or revoked
+    pub break_glass: bool,
+    pub created_at: i64,
+}
+
+impl From<auth_decision::Model> for DecisionInfo {
+    fn from(value: auth_decision::Model) -> Self {
+        Self {
+            id: value.id,
+            principal: value.principal,
+            action: value.action,
+            resource: value.resource,
+            allowed: value.allowed,
+            policies: value
+                .policies
+                .split(',')
+                .filter(|id| !id.is_empty())
+                .map(|id| id.to_owned())
+                .collect(),
+            errors: value
+                .errors
+                .map(|errors| errors.lines().map(|e| e.to_owned()).collect())
+                .unwrap_or_default(),
+            break_glass: value.break_glass,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DecisionParams {
+    /// Name of the user
+    pub principal: Option<String>,
+    /// Cedar name of the action, e.g. `pushRepo`
+    pub action: Option<String>,
+    /// Path the request was made on
+    pub resource: Option<String>,
+    pub allowed: Option<bool>,
+    pub break_glass: Option<bool>,
+    /// Unix timestamps, decisions from `since` up to before `until`
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub page: Option<u64>,
+    pub per_page: Option<u64>,
+}
+
+/// A request to decide without enforcing anything, with the current policies or with those
+/// of a namespace changed.
+#[derive(Deserialize)]
+pub struct SimulateRequest {
+    /// Name of the user, `name[bot]` for a service account
+    pub principal: String,
+    /// Cedar name of the action, e.g. `pushRepo`
+    pub action: String,
+    /// Path of the repository
+    pub resource: String,
+    /// How the request is made, its time defaults to now
+    #[serde(default)]
+    pub context: RequestInfo,
+    pub proposed: Option<ProposedPolicy>,
+}
+
+#[derive(Deserialize)]
+pub struct ProposedPolicy {
+    pub namespace: String,
+    /// Replaces the policies of the namespace, `None` decides as if they were deleted
+    pub content: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SimulateResult {
+    pub allowed: bool,
+    /// Ids of the policies which decided, like those of [`DecisionInfo`]
+    pub policies: Vec<String>,
+    pub errors: Vec<String>,
+    pub break_glass: bool,
+}
+
+impl From<AuthDecision> for SimulateResult {
+    fn from(value: AuthDecision) -> Self {
+        Self {
+            allowed: value.is_allowed(),
+            policies: value.policies(),
+            errors: value.errors(),
+            break_glass: value.is_break_glass(),
+        }
+    }
+}
+
+/// Sign the current entity file of a directory, e.g. one pushed before entity files were signed.
+#[derive(Deserialize)]
+pub struct SignEntityFile {
+    /// Directory of the entity file
+    pub path: String,
+}
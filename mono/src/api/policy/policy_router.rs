@@ -0,0 +1,358 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use ceres::entity_file;
+use common::{
+    errors::ProtocolError,
+    model::{CommonPage, CommonResult, Pagination},
+    utils::repo_path,
+};
+use jupiter::storage::policy_storage::AuthDecisionFilter;
+use saturn::{policy::is_valid_namespace, ActionEnum};
+use taurus::event::policy::{PolicyEvent, PolicyEventKind};
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::policy::{
+    DecisionInfo, DecisionParams, PolicyInfo, RollbackPolicy, SavePolicy, SignEntityFile,
+    SimulateRequest, SimulateResult,
+};
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new()
+        .nest(
+            "/policies",
+            Router::new()
+                .route("/", get(list_policies))
+                .route("/{namespace}", get(get_policy).post(save_policy))
+                .route("/{namespace}/delete", post(delete_policy))
+                .route("/{namespace}/versions", get(list_versions))
+                .route("/{namespace}/rollback", post(rollback_policy)),
+        )
+        .route("/policy-simulation", post(simulate))
+        .route("/entity-files/sign", post(sign_entity_file))
+        .nest(
+            "/audit",
+            Router::new().route("/decisions", get(list_decisions)),
+        )
+}
+
+/// Policies decide what everyone may do, only admins of the root directory may manage them.
+const FORBIDDEN: &str = "managing policies requires admin permission";
+
+/// The current policies of every namespace.
+async fn list_policies(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<PolicyInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state.context.services.policy_storage.list_current().await {
+        Ok(policies) => {
+            CommonResult::success(Some(policies.into_iter().map(|p| p.into()).collect()))
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn get_policy(
+    user: LoginUser,
+    Path(namespace): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<PolicyInfo>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let latest = state
+        .context
+        .services
+        .policy_storage
+        .get_latest_version(&namespace)
+        .await?;
+    let res = match latest.filter(|p| p.content.is_some()) {
+        Some(policy) => CommonResult::success(Some(policy.into())),
+        None => CommonResult::failed("namespace has no policies"),
+    };
+    Ok(Json(res))
+}
+
+/// Versions of the policies of a namespace, latest first.
+async fn list_versions(
+    user: LoginUser,
+    Path(namespace): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<PolicyInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state
+        .context
+        .services
+        .policy_storage
+        .list_versions(&namespace)
+        .await
+    {
+        Ok(versions) => {
+            CommonResult::success(Some(versions.into_iter().map(|p| p.into()).collect()))
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Create the policies of a namespace or replace them by a new version.
+async fn save_policy(
+    user: LoginUser,
+    Path(namespace): Path<String>,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<SavePolicy>,
+) -> Result<Json<CommonResult<PolicyInfo>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    if !is_valid_namespace(&namespace) {
+        return Ok(Json(CommonResult::failed("invalid namespace")));
+    }
+    let latest = state
+        .context
+        .services
+        .policy_storage
+        .get_latest_version(&namespace)
+        .await?;
+    let kind = match latest.and_then(|p| p.content) {
+        Some(_) => PolicyEventKind::Updated,
+        None => PolicyEventKind::Created,
+    };
+    change_policy(&user, &state, &namespace, Some(json.content), kind).await
+}
+
+async fn delete_policy(
+    user: LoginUser,
+    Path(namespace): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<PolicyInfo>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let latest = state
+        .context
+        .services
+        .policy_storage
+        .get_latest_version(&namespace)
+        .await?;
+    if latest.and_then(|p| p.content).is_none() {
+        return Ok(Json(CommonResult::failed("namespace has no policies")));
+    }
+    change_policy(&user, &state, &namespace, None, PolicyEventKind::Deleted).await
+}
+
+/// Make the policies of an earlier version the current ones, as a new version.
+async fn rollback_policy(
+    user: LoginUser,
+    Path(namespace): Path<String>,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<RollbackPolicy>,
+) -> Result<Json<CommonResult<PolicyInfo>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let Some(version) = state
+        .context
+        .services
+        .policy_storage
+        .get_version(&namespace, json.version)
+        .await?
+    else {
+        return Ok(Json(CommonResult::failed("policy version not found")));
+    };
+    let kind = PolicyEventKind::RolledBack {
+        to: version.version,
+    };
+    change_policy(&user, &state, &namespace, version.content, kind).await
+}
+
+/// Validate the policies of the namespace together with all others, record them as a new
+/// version and put them in use. `None` removes the policies of the namespace.
+async fn change_policy(
+    user: &LoginUser,
+    state: &State<MonoApiServiceState>,
+    namespace: &str,
+    content: Option<String>,
+    kind: PolicyEventKind,
+) -> Result<Json<CommonResult<PolicyInfo>>, ApiError> {
+    let policies = &state.context.policies;
+    if let Err(err) = policies.check_namespace(namespace, content.as_deref()) {
+        return Ok(Json(CommonResult::failed(&err.to_string())));
+    }
+    let version = state
+        .context
+        .services
+        .policy_storage
+        .save_version(namespace, content, &user.name)
+        .await?;
+    PolicyEvent::notify(namespace, kind, version.version, &user.name).await;
+    // only fails if the policy files changed into invalid ones meanwhile
+    if let Err(err) = policies.set_namespace(namespace, version.content.as_deref()) {
+        return Ok(Json(CommonResult::failed(&format!(
+            "version {} was saved but is not in use: {}",
+            version.version, err
+        ))));
+    }
+    Ok(Json(CommonResult::success(Some(version.into()))))
+}
+
+/// Decide a hypothetical request without enforcing or recording anything, with the current
+/// policies or with the proposed policies of a namespace, to review a change before it is made.
+async fn simulate(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<SimulateRequest>,
+) -> Result<Json<CommonResult<SimulateResult>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let policies = match &json.proposed {
+        Some(proposed) => {
+            if !is_valid_namespace(&proposed.namespace) {
+                return Ok(Json(CommonResult::failed("invalid namespace")));
+            }
+            match state
+                .context
+                .policies
+                .proposed(&proposed.namespace, proposed.content.as_deref())
+            {
+                Ok(policies) => Arc::new(policies),
+                Err(err) => return Ok(Json(CommonResult::failed(&err.to_string()))),
+            }
+        }
+        None => state.context.policies.current(),
+    };
+    let res = match util::decide(
+        &json.principal,
+        &json.action,
+        &json.resource,
+        &json.context,
+        policies,
+        &state.context,
+    )
+    .await
+    {
+        Ok(decision) => CommonResult::success(Some(decision.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Sign the current entity file of a directory, only admins of the directory may vouch for it.
+/// Returns the blob of the signed file.
+async fn sign_entity_file(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<SignEntityFile>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_permissions(&user, &json.path, ActionEnum::AddAdmin, state.clone())
+        .await
+        .map_err(|_| {
+            ProtocolError::Forbidden(
+                "signing an entity file requires admin permission on its directory".to_owned(),
+            )
+        })?;
+    // signatures are for the directory as the authorization walks it, without a trailing '/'
+    let dir: PathBuf = PathBuf::from(&json.path).components().collect();
+    let Some(blob_id) = util::entity_file_id(&dir, &state.context).await? else {
+        return Ok(Json(CommonResult::failed(
+            "no entity file in the directory",
+        )));
+    };
+    let dir = repo_path(&dir);
+    let res = match entity_file::sign_file(&state.context, &dir, &blob_id, &user.name).await {
+        Ok(()) => CommonResult::success(Some(blob_id)),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Recorded authorization decisions, latest first, to find out why a request was denied.
+async fn list_decisions(
+    user: LoginUser,
+    Query(params): Query<DecisionParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<CommonPage<DecisionInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let timestamp = |secs: Option<i64>| {
+        secs.and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+            .map(|dt| dt.naive_utc())
+    };
+    let filter = AuthDecisionFilter {
+        principal: params.principal,
+        action: params.action,
+        resource: params.resource,
+        allowed: params.allowed,
+        break_glass: params.break_glass,
+        since: timestamp(params.since),
+        until: timestamp(params.until),
+    };
+    let default = Pagination::default();
+    let page = Pagination {
+        page: params.page.unwrap_or(default.page),
+        per_page: params.per_page.unwrap_or(default.per_page),
+    };
+    let res = match state
+        .context
+        .services
+        .policy_storage
+        .list_auth_decisions(&filter, page)
+        .await
+    {
+        Ok((items, total)) => CommonResult::success(Some(CommonPage {
+            items: items.into_iter().map(|d| d.into()).collect(),
+            total,
+        })),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
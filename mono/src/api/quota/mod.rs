@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::repo_usage;
+use ceres::quota::{NamespaceKind, NamespaceUsage};
+
+pub mod quota_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct UsageInfo {
+    pub path: String,
+    /// In bytes, like all sizes
+    pub git_size: i64,
+    pub lfs_size: i64,
+    /// 0 is unlimited
+    pub limit: u64,
+    pub updated_at: i64,
+}
+
+impl UsageInfo {
+    pub fn new(value: repo_usage::Model, limit: u64) -> Self {
+        Self {
+            path: value.path,
+            git_size: value.git_size,
+            lfs_size: value.lfs_size,
+            limit,
+            updated_at: value.updated_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct UsageParams {
+    /// Repositories below this directory, `/` if not given
+    pub path: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct NamespaceInfo {
+    pub kind: NamespaceKind,
+    pub name: String,
+    pub paths: Vec<String>,
+    pub git_size: i64,
+    pub lfs_size: i64,
+    pub limit: u64,
+}
+
+impl From<NamespaceUsage> for NamespaceInfo {
+    fn from(value: NamespaceUsage) -> Self {
+        Self {
+            kind: value.kind,
+            name: value.name,
+            paths: value.paths,
+            git_size: value.git_size,
+            lfs_size: value.lfs_size,
+            limit: value.limit,
+        }
+    }
+}
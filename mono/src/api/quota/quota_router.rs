@@ -0,0 +1,68 @@
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+
+use ceres::quota;
+use common::model::CommonResult;
+use saturn::ActionEnum;
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::quota::{NamespaceInfo, UsageInfo, UsageParams};
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/quota",
+        Router::new()
+            .route("/usage", get(list_usage))
+            .route("/namespaces", get(list_namespaces)),
+    )
+}
+
+/// Storage used by the repositories below a directory, with the limit of each.
+async fn list_usage(
+    user: LoginUser,
+    Query(params): Query<UsageParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<UsageInfo>>>, ApiError> {
+    let path = params.path.unwrap_or_else(|| "/".to_owned());
+    util::check_user_read_access(Some(&user), std::path::Path::new(&path), &state.context).await?;
+    let settings = state.context.settings();
+    let config = &settings.quota;
+    let res = match state.context.services.quota_storage.list_usage(&path).await {
+        Ok(usage) => CommonResult::success(Some(
+            usage
+                .into_iter()
+                .map(|u| {
+                    let limit = quota::repo_limit(config, &u.path);
+                    UsageInfo::new(u, limit)
+                })
+                .collect(),
+        )),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Storage used by every organization and user, largest first.
+async fn list_namespaces(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<NamespaceInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        "namespace usage requires admin permission",
+        state.clone(),
+    )
+    .await?;
+    let res = match quota::namespace_usage(&state.context).await {
+        Ok(usage) => CommonResult::success(Some(usage.into_iter().map(|n| n.into()).collect())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
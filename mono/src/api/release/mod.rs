@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::{release, release_asset};
+
+pub mod release_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct ReleaseInfo {
+    pub id: i64,
+    pub path: String,
+    pub tag_name: String,
+    pub commit_id: String,
+    pub name: String,
+    pub notes: String,
+    pub changelog: String,
+    pub created_by: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub assets: Vec<AssetInfo>,
+}
+
+impl ReleaseInfo {
+    pub fn new(release: release::Model, assets: Vec<release_asset::Model>) -> Self {
+        Self {
+            id: release.id,
+            path: release.path,
+            tag_name: release.tag_name,
+            commit_id: release.commit_id,
+            name: release.name,
+            notes: release.notes,
+            changelog: release.changelog,
+            created_by: release.created_by,
+            created_at: release.created_at.and_utc().timestamp(),
+            updated_at: release.updated_at.and_utc().timestamp(),
+            assets: assets.into_iter().map(|a| a.into()).collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssetInfo {
+    pub id: i64,
+    pub name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub download_count: i64,
+    pub uploaded_by: String,
+    pub created_at: i64,
+}
+
+impl From<release_asset::Model> for AssetInfo {
+    fn from(value: release_asset::Model) -> Self {
+        Self {
+            id: value.id,
+            name: value.name,
+            content_type: value.content_type,
+            size: value.size,
+            download_count: value.download_count,
+            uploaded_by: value.uploaded_by,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReleaseParams {
+    pub path: String,
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateRelease {
+    /// Path of the import repository or of the monorepo
+    pub path: String,
+    pub tag_name: String,
+    /// Defaults to the tag name
+    pub name: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AssetParams {
+    pub name: String,
+}
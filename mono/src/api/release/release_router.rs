@@ -0,0 +1,248 @@
+use axum::{
+    body::Body,
+    extract::{Path, Query, Request, State},
+    response::Response,
+    routing::{get, post},
+    Json, Router,
+};
+use futures::{future, StreamExt, TryStreamExt};
+use http::StatusCode;
+
+use common::{
+    errors::{MegaError, ProtocolError},
+    model::CommonResult,
+};
+use saturn::ActionEnum;
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::release::{AssetInfo, AssetParams, CreateRelease, ReleaseInfo, ReleaseParams};
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+const DEFAULT_RELEASE_LIMIT: u64 = 20;
+const MAX_RELEASE_LIMIT: u64 = 100;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/releases",
+        Router::new()
+            .route("/", get(list_releases).post(create_release))
+            .route("/{id}", get(get_release))
+            .route("/{id}/delete", post(delete_release))
+            .route("/{id}/assets", post(upload_asset))
+            .route("/{id}/assets/{asset_id}", get(download_asset))
+            .route("/{id}/assets/{asset_id}/delete", post(delete_asset)),
+    )
+}
+
+async fn check_manager(
+    user: &LoginUser,
+    path: &str,
+    state: &State<MonoApiServiceState>,
+) -> Result<(), ApiError> {
+    util::check_permissions(user, path, ActionEnum::ManageReleases, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(path.to_owned()))?;
+    Ok(())
+}
+
+async fn list_releases(
+    user: Option<LoginUser>,
+    Query(params): Query<ReleaseParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<ReleaseInfo>>>, ApiError> {
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&params.path),
+        &state.context,
+    )
+    .await?;
+    let storage = &state.context.services.release_storage;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_RELEASE_LIMIT)
+        .clamp(1, MAX_RELEASE_LIMIT);
+    let releases = storage.list_releases(&params.path, limit).await?;
+    let mut res = Vec::new();
+    for release in releases {
+        let assets = storage.list_assets(release.id).await?;
+        res.push(ReleaseInfo::new(release, assets));
+    }
+    Ok(Json(CommonResult::success(Some(res))))
+}
+
+/// Create a release from a tag, the changelog is generated from the commits since the
+/// previous release.
+async fn create_release(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<CreateRelease>,
+) -> Result<Json<CommonResult<ReleaseInfo>>, ApiError> {
+    check_manager(&user, &json.path, &state).await?;
+    let res = ceres::release::create_release(
+        &state.context,
+        &json.path,
+        &json.tag_name,
+        json.name.as_deref().unwrap_or_default(),
+        json.notes.as_deref().unwrap_or_default(),
+        &user.name,
+    )
+    .await;
+    let res = match res {
+        Ok(release) => CommonResult::success(Some(ReleaseInfo::new(release, vec![]))),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn get_release(
+    user: Option<LoginUser>,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<ReleaseInfo>>, ApiError> {
+    let storage = &state.context.services.release_storage;
+    let Some(release) = storage.get_release(id).await? else {
+        return Ok(Json(CommonResult::failed("release not found")));
+    };
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&release.path),
+        &state.context,
+    )
+    .await?;
+    let assets = storage.list_assets(id).await?;
+    Ok(Json(CommonResult::success(Some(ReleaseInfo::new(
+        release, assets,
+    )))))
+}
+
+async fn delete_release(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    let storage = &state.context.services.release_storage;
+    let Some(release) = storage.get_release(id).await? else {
+        return Ok(Json(CommonResult::failed("release not found")));
+    };
+    check_manager(&user, &release.path, &state).await?;
+    let res = match ceres::release::delete_release(&state.context, id).await {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Attach the request body as asset `name`, the upload is refused once it exceeds
+/// `release.max_asset_size`.
+async fn upload_asset(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    Query(params): Query<AssetParams>,
+    state: State<MonoApiServiceState>,
+    req: Request<Body>,
+) -> Result<Json<CommonResult<AssetInfo>>, ApiError> {
+    let storage = &state.context.services.release_storage;
+    let Some(release) = storage.get_release(id).await? else {
+        return Ok(Json(CommonResult::failed("release not found")));
+    };
+    check_manager(&user, &release.path, &state).await?;
+    let name = params.name.trim();
+    if name.is_empty() || name.contains(['/', '\\', '"']) || name.chars().any(char::is_control) {
+        return Ok(Json(CommonResult::failed("invalid asset name")));
+    }
+    let content_type = req
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_owned();
+
+    // The limit is checked while the body streams into storage, so oversized uploads are cut off early.
+    let max_size = 1024 * 1024 * state.context.settings().release.max_asset_size;
+    let mut received = 0;
+    let content = req
+        .into_body()
+        .into_data_stream()
+        .map_err(|err| MegaError::with_message(&err.to_string()))
+        .and_then(move |chunk| {
+            received += chunk.len();
+            let res = if max_size > 0 && received > max_size {
+                Err(MegaError::with_message("asset exceeds the size limit"))
+            } else {
+                Ok(chunk)
+            };
+            future::ready(res)
+        })
+        .boxed();
+
+    let res =
+        ceres::release::add_asset(&state.context, id, name, &content_type, content, &user.name)
+            .await;
+    let res = match res {
+        Ok(asset) => CommonResult::success(Some(asset.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn download_asset(
+    user: Option<LoginUser>,
+    Path((id, asset_id)): Path<(i64, i64)>,
+    state: State<MonoApiServiceState>,
+) -> Result<Response, ApiError> {
+    let storage = &state.context.services.release_storage;
+    let release = storage.get_release(id).await?;
+    let asset = storage
+        .get_asset(asset_id)
+        .await?
+        .filter(|a| a.release_id == id);
+    let (Some(release), Some(asset)) = (release, asset) else {
+        return Ok(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap());
+    };
+    util::check_user_read_access(
+        user.as_ref(),
+        std::path::Path::new(&release.path),
+        &state.context,
+    )
+    .await?;
+    let content = ceres::release::download_asset(&state.context, &asset)
+        .await?
+        .map_err(|err| std::io::Error::other(err.to_string()));
+    Ok(Response::builder()
+        .header("Content-Type", asset.content_type)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", asset.name),
+        )
+        .body(Body::from_stream(content))
+        .unwrap())
+}
+
+async fn delete_asset(
+    user: LoginUser,
+    Path((id, asset_id)): Path<(i64, i64)>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    let storage = &state.context.services.release_storage;
+    let Some(release) = storage.get_release(id).await? else {
+        return Ok(Json(CommonResult::failed("release not found")));
+    };
+    check_manager(&user, &release.path, &state).await?;
+    let Some(asset) = storage
+        .get_asset(asset_id)
+        .await?
+        .filter(|a| a.release_id == id)
+    else {
+        return Ok(Json(CommonResult::failed("asset not found")));
+    };
+    let res = match ceres::release::delete_asset(&state.context, asset).await {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
@@ -0,0 +1,139 @@
+use callisto::db_enums::Visibility;
+use callisto::{branch_setting, import_refs, mega_refs, mega_tag, tag_protection};
+use ceres::maintenance::branch_cleanup;
+use serde::{Deserialize, Serialize};
+
+pub mod repo_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct VisibilityInfo {
+    pub path: String,
+    pub visibility: Visibility,
+}
+
+/// Settings are inherited by subdirectories, paths without any use the defaults.
+#[derive(Serialize, Deserialize)]
+pub struct BranchSettingInfo {
+    pub path: String,
+    /// Delete the merge request ref once it has been merged
+    pub delete_on_merge: bool,
+    /// Glob patterns of branches which are never deleted as stale, e.g. `release/*`
+    pub protected_branches: Vec<String>,
+}
+
+impl From<branch_setting::Model> for BranchSettingInfo {
+    fn from(value: branch_setting::Model) -> Self {
+        Self {
+            protected_branches: branch_cleanup::protected_patterns(&value),
+            path: value.path,
+            delete_on_merge: value.delete_on_merge,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TagRuleInfo {
+    pub id: i64,
+    /// Directory the rule was set on, it applies to all repositories below
+    pub path: String,
+    pub pattern: String,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+impl From<tag_protection::Model> for TagRuleInfo {
+    fn from(value: tag_protection::Model) -> Self {
+        Self {
+            id: value.id,
+            path: value.path,
+            pattern: value.pattern,
+            created_by: value.created_by,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+/// Only users with permission to manage protected tags may create or delete tags
+/// matching `pattern`, e.g. `v*`.
+#[derive(Serialize, Deserialize)]
+pub struct CreateTagRule {
+    pub path: String,
+    pub pattern: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TagInfo {
+    pub name: String,
+    /// The tagged commit, for annotated tags the commit their tag object points to
+    pub commit: String,
+    pub created_at: i64,
+    /// Message of an annotated tag, `None` for lightweight tags
+    pub message: Option<String>,
+    pub tagger: Option<String>,
+}
+
+impl From<import_refs::Model> for TagInfo {
+    fn from(value: import_refs::Model) -> Self {
+        Self {
+            name: value.ref_name,
+            commit: value.ref_git_id,
+            created_at: value.created_at.and_utc().timestamp(),
+            message: None,
+            tagger: None,
+        }
+    }
+}
+
+impl From<(mega_refs::Model, Option<mega_tag::Model>)> for TagInfo {
+    fn from((refs, tag): (mega_refs::Model, Option<mega_tag::Model>)) -> Self {
+        let created_at = refs.created_at.and_utc().timestamp();
+        match tag {
+            Some(tag) => Self {
+                name: refs.ref_name,
+                commit: tag.object_id,
+                created_at,
+                message: Some(tag.message.trim().to_owned()),
+                tagger: Some(tag.tagger),
+            },
+            None => Self {
+                name: refs.ref_name,
+                commit: refs.ref_commit_hash,
+                created_at,
+                message: None,
+                tagger: None,
+            },
+        }
+    }
+}
+
+/// Tag `commit` of the repository at `path`, `commit` and `message` are ignored for deletion.
+///
+/// In the monorepo `commit` defaults to the latest commit of `path`, and with a `message` an
+/// annotated tag is created. Tags of import repositories are lightweight.
+#[derive(Serialize, Deserialize)]
+pub struct TagRequest {
+    pub path: String,
+    pub name: String,
+    pub commit: Option<String>,
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenameRepo {
+    pub path: String,
+    pub new_name: String,
+}
+
+/// Only import repositories can be deleted, they are kept in the trash until they are purged.
+#[derive(Serialize, Deserialize)]
+pub struct DeleteRepo {
+    pub path: String,
+}
+
+/// Exactly one of `org` and `user` names the new owner.
+#[derive(Serialize, Deserialize)]
+pub struct TransferRepo {
+    pub path: String,
+    pub org: Option<String>,
+    pub user: Option<String>,
+}
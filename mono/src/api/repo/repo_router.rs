@@ -0,0 +1,480 @@
+use std::path::PathBuf;
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use callisto::db_enums::RefType;
+use callisto::import_refs;
+use ceres::model::query::BlobContentQuery;
+use ceres::protocol::smart::is_protected_tag;
+use common::{
+    errors::ProtocolError,
+    model::CommonResult,
+    utils::{generate_id, repo_path, TAG_REF_PREFIX},
+};
+use saturn::ActionEnum;
+use taurus::event::repo::{RepoEvent, RepoEventKind};
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::repo::{
+    BranchSettingInfo, CreateTagRule, DeleteRepo, RenameRepo, TagInfo, TagRequest, TagRuleInfo,
+    TransferRepo, VisibilityInfo,
+};
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/repo",
+        Router::new()
+            .route("/visibility", get(get_visibility).post(set_visibility))
+            .route(
+                "/branch-settings",
+                get(get_branch_setting).post(set_branch_setting),
+            )
+            .route("/tag-rules", get(list_tag_rules).post(create_tag_rule))
+            .route("/tag-rules/{id}/delete", post(delete_tag_rule))
+            .route("/tags", get(list_tags).post(create_tag))
+            .route("/tags/delete", post(delete_tag))
+            .route("/rename", post(rename))
+            .route("/delete", post(delete))
+            .route("/transfer", post(transfer)),
+    )
+}
+
+async fn get_visibility(
+    user: Option<LoginUser>,
+    Query(query): Query<BlobContentQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<VisibilityInfo>>, ApiError> {
+    let path = std::path::Path::new(&query.path);
+    util::check_user_read_access(user.as_ref(), path, &state.context).await?;
+    let res = state
+        .context
+        .services
+        .mono_storage
+        .get_visibility(path)
+        .await;
+    let res = match res {
+        Ok(visibility) => CommonResult::success(Some(VisibilityInfo {
+            path: query.path,
+            visibility,
+        })),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn set_visibility(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<VisibilityInfo>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_permissions(&user, &json.path, ActionEnum::SetVisibility, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(json.path.clone()))?;
+    let res = state
+        .context
+        .services
+        .mono_storage
+        .save_visibility(&json.path, json.visibility)
+        .await;
+    let res = match res {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn get_branch_setting(
+    user: Option<LoginUser>,
+    Query(query): Query<BlobContentQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<BranchSettingInfo>>, ApiError> {
+    let path = std::path::Path::new(&query.path);
+    util::check_user_read_access(user.as_ref(), path, &state.context).await?;
+    let res = state
+        .context
+        .services
+        .mono_storage
+        .get_branch_setting(path)
+        .await;
+    let res = match res {
+        Ok(Some(setting)) => CommonResult::success(Some(setting.into())),
+        Ok(None) => CommonResult::success(Some(BranchSettingInfo {
+            path: query.path,
+            delete_on_merge: true,
+            protected_branches: Vec::new(),
+        })),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn set_branch_setting(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<BranchSettingInfo>,
+) -> Result<Json<CommonResult<BranchSettingInfo>>, ApiError> {
+    util::check_permissions(&user, &json.path, ActionEnum::ManageBranches, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(json.path.clone()))?;
+    let res = state
+        .context
+        .services
+        .mono_storage
+        .save_branch_setting(
+            &json.path,
+            json.delete_on_merge,
+            serde_json::to_string(&json.protected_branches).unwrap(),
+        )
+        .await;
+    let res = match res {
+        Ok(setting) => CommonResult::success(Some(setting.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Tag rules applying to `path`, including the ones inherited from its parents.
+async fn list_tag_rules(
+    user: Option<LoginUser>,
+    Query(query): Query<BlobContentQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<TagRuleInfo>>>, ApiError> {
+    let path = std::path::Path::new(&query.path);
+    util::check_user_read_access(user.as_ref(), path, &state.context).await?;
+    let res = state
+        .context
+        .services
+        .mono_storage
+        .get_tag_rules(path)
+        .await;
+    let res = match res {
+        Ok(rules) => CommonResult::success(Some(rules.into_iter().map(|r| r.into()).collect())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn create_tag_rule(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<CreateTagRule>,
+) -> Result<Json<CommonResult<TagRuleInfo>>, ApiError> {
+    util::check_permissions(&user, &json.path, ActionEnum::ManageTagRules, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(json.path.clone()))?;
+    if json.pattern.trim().is_empty() {
+        return Ok(Json(CommonResult::failed("pattern must not be empty")));
+    }
+    let res = state
+        .context
+        .services
+        .mono_storage
+        .save_tag_rule(&json.path, json.pattern.trim(), &user.name)
+        .await;
+    let res = match res {
+        Ok(rule) => CommonResult::success(Some(rule.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn delete_tag_rule(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Path(id): Path<i64>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    let storage = &state.context.services.mono_storage;
+    let Some(rule) = storage.get_tag_rule(id).await? else {
+        return Ok(Json(CommonResult::failed("tag rule not found")));
+    };
+    util::check_permissions(&user, &rule.path, ActionEnum::ManageTagRules, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(rule.path.clone()))?;
+    let res = match storage.delete_tag_rule(id).await {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn list_tags(
+    user: Option<LoginUser>,
+    Query(query): Query<BlobContentQuery>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<TagInfo>>>, ApiError> {
+    let path = std::path::Path::new(&query.path);
+    util::check_user_read_access(user.as_ref(), path, &state.context).await?;
+    let storage = &state.context.services.git_db_storage;
+    let Some(repo) = storage.find_git_repo_exact_match(&query.path).await? else {
+        let res = match state.monorepo().list_tags(&query.path).await {
+            Ok(tags) => CommonResult::success(Some(tags.into_iter().map(|t| t.into()).collect())),
+            Err(err) => CommonResult::failed(&err.to_string()),
+        };
+        return Ok(Json(res));
+    };
+    let tags = storage
+        .get_ref(repo.id)
+        .await?
+        .into_iter()
+        .filter(|r| r.ref_type == RefType::Tag)
+        .map(|r| r.into())
+        .collect();
+    Ok(Json(CommonResult::success(Some(tags))))
+}
+
+/// Creating or deleting a tag requires push permission, protected tags also require
+/// permission to manage them.
+async fn check_tag_permission(
+    user: &LoginUser,
+    path: &str,
+    ref_name: &str,
+    state: &State<MonoApiServiceState>,
+) -> Result<(), ApiError> {
+    util::check_permissions(user, path, ActionEnum::PushRepo, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(path.to_owned()))?;
+    let patterns: Vec<String> = state
+        .context
+        .services
+        .mono_storage
+        .get_tag_rules(std::path::Path::new(path))
+        .await?
+        .into_iter()
+        .map(|r| r.pattern)
+        .collect();
+    if is_protected_tag(&patterns, ref_name) {
+        util::check_permissions(user, path, ActionEnum::ManageProtectedTags, state.clone())
+            .await
+            .map_err(|_| ProtocolError::Forbidden(format!("{} is a protected tag", ref_name)))?;
+    }
+    Ok(())
+}
+
+/// Create a tag in an import repository, or in the monorepo if there is none at the path.
+async fn create_tag(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<TagRequest>,
+) -> Result<Json<CommonResult<TagInfo>>, ApiError> {
+    let ref_name = format!("{}{}", TAG_REF_PREFIX, json.name);
+    check_tag_permission(&user, &json.path, &ref_name, &state).await?;
+    let storage = &state.context.services.git_db_storage;
+    let Some(repo) = storage.find_git_repo_exact_match(&json.path).await? else {
+        let res = match state
+            .monorepo()
+            .create_tag(
+                &json.path,
+                &json.name,
+                json.commit,
+                json.message.as_deref(),
+                (&user.name, &user.email),
+            )
+            .await
+        {
+            Ok(tag) => CommonResult::success(Some(tag.into())),
+            Err(err) => CommonResult::failed(&err.to_string()),
+        };
+        return Ok(Json(res));
+    };
+    if json.message.is_some() {
+        return Ok(Json(CommonResult::failed(
+            "annotated tags are not supported in import repositories",
+        )));
+    }
+    let Some(commit) = json.commit else {
+        return Ok(Json(CommonResult::failed("commit is required")));
+    };
+    if storage
+        .get_commit_by_hash(repo.id, &commit)
+        .await?
+        .is_none()
+    {
+        return Ok(Json(CommonResult::failed("commit not found")));
+    }
+    let refs = storage.get_ref(repo.id).await?;
+    if refs.iter().any(|r| r.ref_name == ref_name) {
+        return Ok(Json(CommonResult::failed("tag already exists")));
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let tag = import_refs::Model {
+        id: generate_id(),
+        repo_id: repo.id,
+        ref_name,
+        ref_git_id: commit,
+        ref_type: RefType::Tag,
+        default_branch: false,
+        created_at: now,
+        updated_at: now,
+    };
+    let res = match storage
+        .save_ref(storage.get_connection(), repo.id, tag.clone())
+        .await
+    {
+        Ok(_) => CommonResult::success(Some(tag.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn delete_tag(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<TagRequest>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    let ref_name = format!("{}{}", TAG_REF_PREFIX, json.name);
+    check_tag_permission(&user, &json.path, &ref_name, &state).await?;
+    let storage = &state.context.services.git_db_storage;
+    let Some(repo) = storage.find_git_repo_exact_match(&json.path).await? else {
+        let res = match state.monorepo().delete_tag(&json.path, &json.name).await {
+            Ok(_) => CommonResult::success(None),
+            Err(err) => CommonResult::failed(&err.to_string()),
+        };
+        return Ok(Json(res));
+    };
+    let refs = storage.get_ref(repo.id).await?;
+    if !refs
+        .iter()
+        .any(|r| r.ref_name == ref_name && r.ref_type == RefType::Tag)
+    {
+        return Ok(Json(CommonResult::failed("tag not found")));
+    }
+    let res = match storage.remove_ref(repo.id, &ref_name).await {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn rename(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<RenameRepo>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_permissions(&user, &json.path, ActionEnum::RenameRepo, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(json.path.clone()))?;
+    let res = state
+        .monorepo()
+        .rename_path(&PathBuf::from(&json.path), &json.new_name)
+        .await;
+    let res = match res {
+        Ok(new_path) => {
+            let new_path = repo_path(&new_path);
+            RepoEvent::notify(
+                RepoEventKind::Renamed {
+                    from: json.path,
+                    to: new_path.clone(),
+                },
+                &user.name,
+            )
+            .await;
+            CommonResult::success(Some(new_path))
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Move an import repository to the trash, admins can restore it until it is purged by the
+/// `repo_purge` task.
+async fn delete(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<DeleteRepo>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_permissions(&user, &json.path, ActionEnum::DeleteRepo, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(json.path.clone()))?;
+    let storage = &state.context.services.git_db_storage;
+    let Some(repo) = storage.find_git_repo_exact_match(&json.path).await? else {
+        return Ok(Json(CommonResult::failed("import repository not found")));
+    };
+    if state
+        .context
+        .services
+        .mono_storage
+        .get_split_by_repo_path(&json.path)
+        .await?
+        .is_some()
+    {
+        return Ok(Json(CommonResult::failed(
+            "repository is the target of a split, delete the split first",
+        )));
+    }
+    let res = match storage.delete_git_repo(repo, &user.name).await {
+        Ok(_) => {
+            RepoEvent::notify(RepoEventKind::Deleted { path: json.path }, &user.name).await;
+            CommonResult::success(None)
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn transfer(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<TransferRepo>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_permissions(&user, &json.path, ActionEnum::TransferRepo, state.clone())
+        .await
+        .map_err(|_| ProtocolError::Forbidden(json.path.clone()))?;
+    let storage = &state.user_stg();
+    let path = PathBuf::from(&json.path);
+    let from = match storage.find_owner_org(&path).await? {
+        Some((_, org)) => Some(org.name),
+        None => storage.find_owner_user(&path).await?.map(|(_, u)| u.name),
+    };
+
+    let (to, org_id, user_id) = match (json.org, json.user) {
+        (Some(org_name), None) => {
+            let Some(org) = storage.find_org_by_name(&org_name).await? else {
+                return Ok(Json(CommonResult::failed("organization not found")));
+            };
+            (org.name, Some(org.id), None)
+        }
+        (None, Some(user_name)) => {
+            let Some(new_owner) = storage.find_user_by_name(&user_name).await? else {
+                return Ok(Json(CommonResult::failed("user not found")));
+            };
+            (new_owner.name, None, Some(new_owner.id))
+        }
+        _ => {
+            return Ok(Json(CommonResult::failed(
+                "exactly one of org and user is required",
+            )))
+        }
+    };
+    // the repository is never left without an owner or with two of them
+    let repo = json.path.as_str();
+    storage
+        .transaction(|txn| async move {
+            storage.delete_org_repo(&*txn, repo).await?;
+            storage.delete_user_repo(&*txn, repo).await?;
+            if let Some(org_id) = org_id {
+                storage.save_org_repo(&*txn, org_id, repo).await?;
+            }
+            if let Some(user_id) = user_id {
+                storage.save_user_repo(&*txn, user_id, repo).await?;
+            }
+            Ok(())
+        })
+        .await?;
+    RepoEvent::notify(
+        RepoEventKind::Transferred {
+            path: json.path,
+            from,
+            to,
+        },
+        &user.name,
+    )
+    .await;
+    Ok(Json(CommonResult::success(None)))
+}
@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::{RepoRole, RoleSubject};
+use callisto::role_assignment;
+use saturn::role::{Role, RoleAssignment};
+
+pub mod role_router;
+
+#[derive(Serialize)]
+pub struct RoleInfo {
+    pub role: Role,
+    /// Cedar names of the actions the role allows
+    pub actions: Vec<&'static str>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssignmentInfo {
+    pub id: i64,
+    pub path: String,
+    pub subject_type: RoleSubject,
+    pub subject: String,
+    pub role: RepoRole,
+    /// End of a temporary assignment
+    pub expires_at: Option<i64>,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+impl From<role_assignment::Model> for AssignmentInfo {
+    fn from(value: role_assignment::Model) -> Self {
+        Self {
+            id: value.id,
+            path: value.path,
+            subject_type: value.subject_type,
+            subject: value.subject,
+            role: value.role,
+            expires_at: value.expires_at.map(|t| t.and_utc().timestamp()),
+            created_by: value.created_by,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AssignRole {
+    pub path: String,
+    pub subject_type: RoleSubject,
+    /// Name of the user, team (`org/team`), organization or service account
+    pub subject: String,
+    pub role: RepoRole,
+    /// Days the role is assigned for, e.g. for contractors, permanently if not given
+    pub days: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct AssignmentParams {
+    /// Assignments on this path and below it, `/` if not given
+    pub path: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct EffectiveParams {
+    pub path: String,
+    /// The signed in user if not given
+    pub user: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct EffectiveRoles {
+    /// The assignments giving the user a role on the path
+    pub assignments: Vec<RoleAssignment>,
+    /// The highest of their roles
+    pub role: Option<Role>,
+    pub actions: Vec<&'static str>,
+}
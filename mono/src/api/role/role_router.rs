@@ -0,0 +1,210 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use callisto::db_enums::RoleSubject;
+use common::{errors::ProtocolError, model::CommonResult};
+use saturn::entitystore::EntityStore;
+use saturn::role::{self, Role, ROLE_NAMESPACE};
+use saturn::ActionEnum;
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::role::{
+    AssignRole, AssignmentInfo, AssignmentParams, EffectiveParams, EffectiveRoles, RoleInfo,
+};
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+/// Longest time a temporary role is assigned for.
+const MAX_DAYS: i64 = 366;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/roles",
+        Router::new()
+            .route("/", get(list_roles))
+            .route("/assignments", get(list_assignments).post(assign_role))
+            .route("/assignments/{id}/delete", post(delete_assignment))
+            .route("/effective", get(effective_roles)),
+    )
+}
+
+/// Roles are assigned by the admins of the path they apply to.
+async fn check_admin(
+    user: &LoginUser,
+    path: &str,
+    state: &State<MonoApiServiceState>,
+) -> Result<(), ApiError> {
+    util::check_permissions(user, path, ActionEnum::AddAdmin, state.clone())
+        .await
+        .map_err(|_| {
+            ProtocolError::Forbidden(format!(
+                "assigning roles on {} requires admin permission",
+                path
+            ))
+        })?;
+    Ok(())
+}
+
+/// The built-in roles with the actions each allows, lowest first.
+async fn list_roles(_: LoginUser) -> Result<Json<CommonResult<Vec<RoleInfo>>>, ApiError> {
+    let roles = Role::ALL
+        .into_iter()
+        .map(|role| RoleInfo {
+            role,
+            actions: role.actions(),
+        })
+        .collect();
+    Ok(Json(CommonResult::success(Some(roles))))
+}
+
+async fn list_assignments(
+    user: LoginUser,
+    Query(params): Query<AssignmentParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<AssignmentInfo>>>, ApiError> {
+    let path = params.path.unwrap_or_else(|| "/".to_owned());
+    check_admin(&user, &path, &state).await?;
+    let res = match state
+        .context
+        .services
+        .policy_storage
+        .list_role_assignments(&path)
+        .await
+    {
+        Ok(assignments) => {
+            CommonResult::success(Some(assignments.into_iter().map(|a| a.into()).collect()))
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Assign a role on a path to a user, team or organization, replacing the role it had there.
+/// Temporary roles stop applying once they expired and are removed by the grant expiry job.
+async fn assign_role(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<AssignRole>,
+) -> Result<Json<CommonResult<AssignmentInfo>>, ApiError> {
+    check_admin(&user, &json.path, &state).await?;
+    let storage = state.context.user_stg();
+    let exists = match json.subject_type {
+        RoleSubject::User => storage.find_user_by_name(&json.subject).await?.is_some(),
+        RoleSubject::Organization => storage.find_org_by_name(&json.subject).await?.is_some(),
+        RoleSubject::Bot => storage.find_service_account(&json.subject).await?.is_some(),
+        RoleSubject::Team => match json.subject.split_once('/') {
+            Some((org, team)) => match storage.find_org_by_name(org).await? {
+                Some(org) => storage
+                    .list_teams(org.id)
+                    .await?
+                    .iter()
+                    .any(|t| t.name == team),
+                None => false,
+            },
+            None => false,
+        },
+    };
+    if !exists {
+        return Ok(Json(CommonResult::failed(&format!(
+            "{} {} not found",
+            json.subject_type, json.subject
+        ))));
+    }
+    let expires_at = match json.days {
+        Some(days) if !(1..=MAX_DAYS).contains(&days) => {
+            return Ok(Json(CommonResult::failed(&format!(
+                "temporary roles are assigned for 1 to {} days",
+                MAX_DAYS
+            ))));
+        }
+        Some(days) => Some((chrono::Utc::now() + chrono::Duration::days(days)).naive_utc()),
+        None => None,
+    };
+    let assignment = state
+        .context
+        .services
+        .policy_storage
+        .save_role_assignment(
+            &json.path,
+            json.subject_type,
+            &json.subject,
+            json.role,
+            expires_at,
+            &user.name,
+        )
+        .await?;
+    if let Err(err) = apply_roles(&state).await {
+        return Ok(Json(CommonResult::failed(&err)));
+    }
+    Ok(Json(CommonResult::success(Some(assignment.into()))))
+}
+
+async fn delete_assignment(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    let storage = &state.context.services.policy_storage;
+    let Some(assignment) = storage.get_role_assignment(id).await? else {
+        return Ok(Json(CommonResult::failed("role assignment not found")));
+    };
+    check_admin(&user, &assignment.path, &state).await?;
+    storage.delete_role_assignment(id).await?;
+    let res = match apply_roles(&state).await {
+        Ok(_) => CommonResult::success(None),
+        Err(err) => CommonResult::failed(&err),
+    };
+    Ok(Json(res))
+}
+
+/// The roles a user has on a path, directly or through the teams and organizations they are
+/// in. Only admins of the path may look up the roles of other users.
+async fn effective_roles(
+    user: LoginUser,
+    Query(params): Query<EffectiveParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<EffectiveRoles>>, ApiError> {
+    let name = params.user.unwrap_or_else(|| user.name.clone());
+    if name != user.name {
+        check_admin(&user, &params.path, &state).await?;
+    }
+    let assignments = state
+        .context
+        .services
+        .policy_storage
+        .role_assignments()
+        .await?;
+    let mut entities = EntityStore::new();
+    util::append_user_groups(&mut entities, &name, &state.context).await?;
+    let effective: Vec<_> = role::effective_roles(&assignments, &entities, &name, &params.path)
+        .into_iter()
+        .cloned()
+        .collect();
+    let role = effective.iter().map(|a| a.role).max();
+    Ok(Json(CommonResult::success(Some(EffectiveRoles {
+        assignments: effective,
+        role,
+        actions: role.map(|r| r.actions()).unwrap_or_default(),
+    }))))
+}
+
+/// Put the policies of the current role assignments in use.
+async fn apply_roles(state: &State<MonoApiServiceState>) -> Result<(), String> {
+    let assignments = state
+        .context
+        .services
+        .policy_storage
+        .role_assignments()
+        .await
+        .map_err(|err| err.to_string())?;
+    let policies = (!assignments.is_empty()).then(|| role::policies(&assignments));
+    state
+        .context
+        .policies
+        .set_namespace(ROLE_NAMESPACE, policies.as_deref())
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::{FindingStatus, SecretAction};
+use callisto::{quarantined_push, secret_finding};
+
+pub mod secret_scan_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct FindingInfo {
+    pub id: i64,
+    pub path: String,
+    pub blob_id: String,
+    pub rule: String,
+    pub line: i32,
+    pub preview: String,
+    pub action: SecretAction,
+    pub pusher: Option<String>,
+    pub push_id: Option<i64>,
+    pub status: FindingStatus,
+    pub reviewer: Option<String>,
+    pub created_at: i64,
+    pub reviewed_at: Option<i64>,
+}
+
+impl From<secret_finding::Model> for FindingInfo {
+    fn from(value: secret_finding::Model) -> Self {
+        Self {
+            id: value.id,
+            path: value.path,
+            blob_id: value.blob_id,
+            rule: value.rule,
+            line: value.line,
+            preview: value.preview,
+            action: value.action,
+            pusher: value.pusher,
+            push_id: value.push_id,
+            status: value.status,
+            reviewer: value.reviewer,
+            created_at: value.created_at.and_utc().timestamp(),
+            reviewed_at: value.reviewed_at.map(|dt| dt.and_utc().timestamp()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FindingParams {
+    pub status: Option<FindingStatus>,
+    /// Findings of a single repository, all findings require admin permission on `/`
+    pub path: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct ReviewFinding {
+    pub status: FindingStatus,
+}
+
+/// A push whose ref updates are held back until a reviewer releases it.
+#[derive(Serialize, Deserialize)]
+pub struct HeldPushInfo {
+    pub id: i64,
+    pub path: String,
+    pub pusher: Option<String>,
+    /// `[old_id, new_id, ref_name]` of each held ref update
+    pub ref_updates: Vec<[String; 3]>,
+    pub created_at: i64,
+    pub released_at: Option<i64>,
+    pub released_by: Option<String>,
+}
+
+impl From<quarantined_push::Model> for HeldPushInfo {
+    fn from(value: quarantined_push::Model) -> Self {
+        Self {
+            id: value.id,
+            path: value.path,
+            pusher: value.pusher,
+            ref_updates: serde_json::from_str(&value.ref_updates).unwrap_or_default(),
+            created_at: value.created_at.and_utc().timestamp(),
+            released_at: value.released_at.map(|dt| dt.and_utc().timestamp()),
+            released_by: value.released_by,
+        }
+    }
+}
+
+/// The outcome of releasing a held push, per ref update.
+#[derive(Serialize, Deserialize)]
+pub struct ReleasedRef {
+    pub ref_name: String,
+    pub updated: bool,
+    pub error: Option<String>,
+}
@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use ceres::protocol::{SmartProtocol, TransportProtocol};
+use common::{errors::ProtocolError, model::CommonResult};
+use saturn::ActionEnum;
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::secret_scan::{
+    FindingInfo, FindingParams, HeldPushInfo, ReleasedRef, ReviewFinding,
+};
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+const DEFAULT_FINDING_LIMIT: u64 = 50;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/secret-scan",
+        Router::new()
+            .route("/findings", get(list_findings))
+            .route("/findings/{id}/review", post(review_finding))
+            .route("/pushes", get(list_held_pushes))
+            .route("/pushes/{id}/release", post(release_push)),
+    )
+}
+
+async fn check_reviewer(
+    user: &LoginUser,
+    path: &str,
+    state: &State<MonoApiServiceState>,
+) -> Result<(), ApiError> {
+    util::check_permissions(user, path, ActionEnum::ReviewSecrets, state.clone())
+        .await
+        .map_err(|_| {
+            ProtocolError::Forbidden(
+                "reviewing secret findings requires admin permission".to_owned(),
+            )
+        })?;
+    Ok(())
+}
+
+async fn list_findings(
+    user: LoginUser,
+    Query(params): Query<FindingParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<FindingInfo>>>, ApiError> {
+    check_reviewer(&user, params.path.as_deref().unwrap_or("/"), &state).await?;
+    let res = state
+        .context
+        .services
+        .secret_storage
+        .list_findings(
+            params.status,
+            params.path,
+            params.limit.unwrap_or(DEFAULT_FINDING_LIMIT),
+        )
+        .await;
+    let res = match res {
+        Ok(findings) => {
+            CommonResult::success(Some(findings.into_iter().map(|f| f.into()).collect()))
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Close a finding, a blob marked as false positive passes later scans.
+async fn review_finding(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<ReviewFinding>,
+) -> Result<Json<CommonResult<FindingInfo>>, ApiError> {
+    let storage = state.context.services.secret_storage.clone();
+    let finding = match storage.get_finding(id).await {
+        Ok(Some(finding)) => finding,
+        Ok(None) => return Ok(Json(CommonResult::failed("finding not found"))),
+        Err(err) => return Ok(Json(CommonResult::failed(&err.to_string()))),
+    };
+    check_reviewer(&user, &finding.path, &state).await?;
+    let res = match storage
+        .review_finding(finding, json.status, &user.name)
+        .await
+    {
+        Ok(finding) => CommonResult::success(Some(finding.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Pushes quarantined by secret scanning which were not released yet.
+async fn list_held_pushes(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<HeldPushInfo>>>, ApiError> {
+    check_reviewer(&user, "/", &state).await?;
+    let pushes = state
+        .context
+        .services
+        .secret_storage
+        .list_held_pushes()
+        .await?;
+    Ok(Json(CommonResult::success(Some(
+        pushes.into_iter().map(|p| p.into()).collect(),
+    ))))
+}
+
+/// Apply the ref updates of a quarantined push once all of its findings are reviewed.
+async fn release_push(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<ReleasedRef>>>, ApiError> {
+    let storage = state.context.services.secret_storage.clone();
+    let Some(push) = storage.get_quarantined_push(id).await? else {
+        return Ok(Json(CommonResult::failed("push not found")));
+    };
+    check_reviewer(&user, &push.path, &state).await?;
+    if push.released_at.is_some() {
+        return Ok(Json(CommonResult::failed("push is already released")));
+    }
+    if storage.count_open_findings(push.id).await? > 0 {
+        return Ok(Json(CommonResult::failed(
+            "review the findings of the push before it is released",
+        )));
+    }
+    let mut protocol = SmartProtocol::new(
+        PathBuf::from(&push.path),
+        state.context.clone(),
+        TransportProtocol::Http,
+    );
+    let commands = protocol.release_push(&push).await?;
+    storage.mark_push_released(push, &user.name).await?;
+    let res = commands
+        .into_iter()
+        .map(|c| ReleasedRef {
+            updated: !c.is_failed(),
+            error: c.is_failed().then_some(c.error_msg),
+            ref_name: c.ref_name,
+        })
+        .collect();
+    Ok(Json(CommonResult::success(Some(res))))
+}
@@ -0,0 +1,54 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::{service_account, service_account_token};
+use saturn::service_account::BOT_SUFFIX;
+
+pub mod service_account_router;
+
+#[derive(Serialize, Deserialize)]
+pub struct ServiceAccountInfo {
+    pub id: i64,
+    pub name: String,
+    /// Name to sign in with, `name[bot]`
+    pub login: String,
+    pub description: String,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+impl From<service_account::Model> for ServiceAccountInfo {
+    fn from(value: service_account::Model) -> Self {
+        Self {
+            id: value.id,
+            login: format!("{}{}", value.name, BOT_SUFFIX),
+            name: value.name,
+            description: value.description,
+            created_by: value.created_by,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub id: i64,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+impl From<service_account_token::Model> for TokenInfo {
+    fn from(value: service_account_token::Model) -> Self {
+        Self {
+            id: value.id,
+            created_by: value.created_by,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateServiceAccount {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
@@ -0,0 +1,180 @@
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use callisto::service_account;
+use common::{errors::ProtocolError, model::CommonResult};
+use saturn::{service_account::is_valid_name, ActionEnum};
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::service_account::{CreateServiceAccount, ServiceAccountInfo, TokenInfo};
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/service-accounts",
+        Router::new()
+            .route("/", get(list_accounts).post(create_account))
+            .route("/{name}/delete", post(delete_account))
+            .route("/{name}/tokens", get(list_tokens).post(generate_token))
+            .route("/{name}/tokens/{id}/delete", post(delete_token)),
+    )
+}
+
+/// Service accounts are managed by the admins of the root directory, what they may do is
+/// granted to them like to users, e.g. with roles.
+const FORBIDDEN: &str = "managing service accounts requires admin permission";
+
+async fn find_account(
+    name: &str,
+    state: &State<MonoApiServiceState>,
+) -> Result<service_account::Model, ApiError> {
+    state
+        .context
+        .user_stg()
+        .find_service_account(name)
+        .await?
+        .ok_or_else(|| ProtocolError::NotFound(format!("service account {}", name)).into())
+}
+
+async fn list_accounts(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<ServiceAccountInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state.context.user_stg().list_service_accounts().await {
+        Ok(accounts) => {
+            CommonResult::success(Some(accounts.into_iter().map(|a| a.into()).collect()))
+        }
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn create_account(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<CreateServiceAccount>,
+) -> Result<Json<CommonResult<ServiceAccountInfo>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    if !is_valid_name(&json.name) {
+        return Ok(Json(CommonResult::failed(
+            "service account names are at most 64 letters, digits, '-' and '_'",
+        )));
+    }
+    let storage = state.context.user_stg();
+    if storage.find_service_account(&json.name).await?.is_some() {
+        return Ok(Json(CommonResult::failed("service account already exists")));
+    }
+    let account = storage
+        .save_service_account(&json.name, &json.description, &user.name)
+        .await?;
+    Ok(Json(CommonResult::success(Some(account.into()))))
+}
+
+async fn delete_account(
+    user: LoginUser,
+    Path(name): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let account = find_account(&name, &state).await?;
+    state
+        .context
+        .user_stg()
+        .delete_service_account(account.id)
+        .await?;
+    Ok(Json(CommonResult::success(None)))
+}
+
+async fn list_tokens(
+    user: LoginUser,
+    Path(name): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<TokenInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let account = find_account(&name, &state).await?;
+    let tokens = state
+        .context
+        .user_stg()
+        .list_service_account_tokens(account.id)
+        .await?;
+    Ok(Json(CommonResult::success(Some(
+        tokens.into_iter().map(|t| t.into()).collect(),
+    ))))
+}
+
+/// Issue a token for the service account, it is only shown once.
+async fn generate_token(
+    user: LoginUser,
+    Path(name): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let account = find_account(&name, &state).await?;
+    let token = state
+        .context
+        .user_stg()
+        .generate_service_account_token(account.id, &user.name)
+        .await?;
+    Ok(Json(CommonResult::success(Some(token))))
+}
+
+async fn delete_token(
+    user: LoginUser,
+    Path((name, id)): Path<(String, i64)>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let account = find_account(&name, &state).await?;
+    let res = match state
+        .context
+        .user_stg()
+        .delete_service_account_token(account.id, id)
+        .await?
+    {
+        true => CommonResult::success(None),
+        false => CommonResult::failed("token not found"),
+    };
+    Ok(Json(res))
+}
@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+use callisto::db_enums::SignatureStatus;
+use callisto::object_signature;
+
+pub mod signature_router;
+
+#[derive(Serialize)]
+pub struct SignatureInfo {
+    pub object_id: String,
+    pub status: SignatureStatus,
+    /// Email of the committer or tagger
+    pub signer_email: String,
+    /// The user of that email, if there is one
+    pub user_id: Option<i64>,
+    /// The signing key which verified the signature
+    pub key_id: Option<i64>,
+    /// Why the signature is unverified
+    pub reason: Option<String>,
+    pub verified_at: i64,
+}
+
+impl From<object_signature::Model> for SignatureInfo {
+    fn from(value: object_signature::Model) -> Self {
+        Self {
+            object_id: value.object_id,
+            status: value.status,
+            signer_email: value.signer_email,
+            user_id: value.user_id,
+            key_id: value.key_id,
+            reason: value.reason,
+            verified_at: value.verified_at.and_utc().timestamp(),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Path, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use ceres::signature;
+use common::model::CommonResult;
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::signature::SignatureInfo;
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/signatures",
+        Router::new()
+            .route("/{object_id}", get(get_signature))
+            .route("/{object_id}/verify", post(verify_signature)),
+    )
+}
+
+/// Signature status of a commit or tag, verified now if it was not yet.
+async fn get_signature(
+    _: LoginUser,
+    Path(object_id): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<SignatureInfo>>, ApiError> {
+    Ok(Json(verify(&state, &object_id, false).await))
+}
+
+/// Verify the signature of a commit or tag again, e.g. after the signer changed their email.
+async fn verify_signature(
+    _: LoginUser,
+    Path(object_id): Path<String>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<SignatureInfo>>, ApiError> {
+    Ok(Json(verify(&state, &object_id, true).await))
+}
+
+async fn verify(
+    state: &MonoApiServiceState,
+    object_id: &str,
+    refresh: bool,
+) -> CommonResult<SignatureInfo> {
+    match signature::verify_object(&state.context, object_id, refresh).await {
+        Ok(Some(model)) => CommonResult::success(Some(model.into())),
+        Ok(None) => CommonResult::failed("commit or tag not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    }
+}
@@ -0,0 +1,9 @@
+use serde::Deserialize;
+
+pub mod stats_router;
+
+#[derive(Deserialize)]
+pub struct StatsParams {
+    /// Days of activity reported, today included, 30 if not given
+    pub days: Option<u64>,
+}
@@ -0,0 +1,43 @@
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Json, Router,
+};
+
+use ceres::stats::{self, InstanceStats};
+use common::model::CommonResult;
+use saturn::ActionEnum;
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::stats::StatsParams;
+use crate::api::util;
+use crate::api::MonoApiServiceState;
+
+/// The most days of activity one request reports.
+const MAX_DAYS: u64 = 366;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().route("/stats", get(instance_stats))
+}
+
+/// Statistics of the whole instance for capacity planning.
+async fn instance_stats(
+    user: LoginUser,
+    Query(params): Query<StatsParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<InstanceStats>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        "instance statistics require admin permission",
+        state.clone(),
+    )
+    .await?;
+    let days = params.days.unwrap_or(stats::ACTIVE_DAYS).clamp(1, MAX_DAYS);
+    let res = match stats::collect(&state.context, days).await {
+        Ok(stats) => CommonResult::success(Some(stats)),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
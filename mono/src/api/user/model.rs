@@ -1,5 +1,7 @@
-use callisto::{access_token, ssh_keys};
+use callisto::db_enums::SigningKeyType;
+use callisto::{access_token, signing_key, ssh_keys};
 use chrono::NaiveDateTime;
+use saturn::entitystore::EntityStore;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize)]
@@ -29,6 +31,36 @@ impl From<ssh_keys::Model> for ListSSHKey {
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct AddSigningKey {
+    pub title: String,
+    /// An armored GPG public key or an OpenSSH public key
+    pub key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ListSigningKey {
+    pub id: i64,
+    pub key_type: SigningKeyType,
+    pub title: String,
+    pub key: String,
+    pub fingerprint: String,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<signing_key::Model> for ListSigningKey {
+    fn from(value: signing_key::Model) -> Self {
+        Self {
+            id: value.id,
+            key_type: value.key_type,
+            title: value.title,
+            key: value.key,
+            fingerprint: value.fingerprint,
+            created_at: value.created_at,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ListToken {
     pub id: i64,
@@ -54,3 +86,41 @@ pub struct RepoPermissions {
     pub maintainer: Vec<String>,
     pub reader: Vec<String>,
 }
+
+#[derive(Debug, Deserialize)]
+pub struct RepoPermissionsParams {
+    pub path: String,
+    /// Another user to ask for, only admins may
+    pub user: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RepoPermissions {
+    pub path: String,
+    pub user: String,
+    /// Cedar names of the actions the user may do, like `pushRepo`
+    pub actions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeParams {
+    pub path: String,
+    /// Cedar name of an action declared by an extension, like `triggerPipeline`
+    pub action: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PermissionHintsParams {
+    pub path: String,
+}
+
+/// What the frontend needs to decide which actions to offer on a path.
+#[derive(Debug, Serialize)]
+pub struct PermissionHints {
+    pub path: String,
+    pub user: String,
+    /// Cedar policies
+    pub policies: String,
+    /// The entities of the path known to the user, in the format of `.mega_cedar.json`
+    pub entities: EntityStore,
+}
@@ -1,5 +1,3 @@
-use std::collections::HashMap;
-
 use axum::{
     extract::{Path, Query, State},
     routing::{get, post},
@@ -7,11 +5,20 @@ use axum::{
 };
 use russh_keys::{parse_public_key_base64, HashAlg};
 
+use ceres::signature;
+use common::errors::ProtocolError;
 use common::model::CommonResult;
+use saturn::request::RequestInfo;
+use saturn::ActionEnum;
 
 use crate::api::user::model::AddSSHKey;
+use crate::api::user::model::AddSigningKey;
 use crate::api::user::model::ListSSHKey;
+use crate::api::user::model::ListSigningKey;
 use crate::api::user::model::ListToken;
+use crate::api::user::model::{
+    AuthorizeParams, PermissionHints, PermissionHintsParams, RepoPermissions, RepoPermissionsParams,
+};
 use crate::api::MonoApiServiceState;
 use crate::api::{error::ApiError, oauth::model::LoginUser, util};
 
@@ -23,10 +30,15 @@ pub fn routers() -> Router<MonoApiServiceState> {
             .route("/ssh", get(list_key))
             .route("/ssh", post(add_key))
             .route("/ssh/{key_id}/delete", post(remove_key))
+            .route("/signing-keys", get(list_signing_keys))
+            .route("/signing-keys", post(add_signing_key))
+            .route("/signing-keys/{key_id}/delete", post(remove_signing_key))
             .route("/token/generate", post(generate_token))
             .route("/token/list", get(list_token))
             .route("/token/{key_id}/delete", post(remove_token))
-            .route("/repo-permissions", get(repo_permissions)),
+            .route("/repo-permissions", get(repo_permissions))
+            .route("/authorize", get(authorize))
+            .route("/permission-hints", get(permission_hints)),
     )
 }
 
@@ -96,6 +108,77 @@ async fn list_key(
     Ok(Json(res))
 }
 
+/// Register a GPG or SSH key the user signs commits and tags with.
+async fn add_signing_key(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<AddSigningKey>,
+) -> Result<Json<CommonResult<ListSigningKey>>, ApiError> {
+    let (key_type, fingerprint) = match signature::parse_key(&json.key) {
+        Ok(parsed) => parsed,
+        Err(err) => return Ok(Json(CommonResult::failed(&err.to_string()))),
+    };
+    let title = if json.title.is_empty() {
+        fingerprint.clone()
+    } else {
+        json.title
+    };
+    let res = state
+        .context
+        .services
+        .signature_storage
+        .save_signing_key(
+            user.user_id,
+            &user.email,
+            key_type,
+            &title,
+            json.key.trim(),
+            &fingerprint,
+        )
+        .await;
+    let res = match res {
+        Ok(model) => CommonResult::success(Some(model.into())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn remove_signing_key(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Path(key_id): Path<i64>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    let res = state
+        .context
+        .services
+        .signature_storage
+        .delete_signing_key(user.user_id, key_id)
+        .await;
+    let res = match res {
+        Ok(true) => CommonResult::success(None),
+        Ok(false) => CommonResult::failed("signing key not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+async fn list_signing_keys(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<ListSigningKey>>>, ApiError> {
+    let res = state
+        .context
+        .services
+        .signature_storage
+        .list_signing_keys(user.user_id)
+        .await;
+    let res = match res {
+        Ok(data) => CommonResult::success(Some(data.into_iter().map(|x| x.into()).collect())),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
 async fn generate_token(
     user: LoginUser,
     state: State<MonoApiServiceState>,
@@ -136,14 +219,74 @@ async fn list_token(
     Ok(Json(res))
 }
 
+/// The actions the signed in user, or another user for admins, may do on a path.
 async fn repo_permissions(
-    Query(query): Query<HashMap<String, String>>,
+    user: LoginUser,
+    Query(query): Query<RepoPermissionsParams>,
     state: State<MonoApiServiceState>,
-) -> Result<Json<CommonResult<String>>, ApiError> {
-    let path = std::path::PathBuf::from(query.get("path").unwrap());
-    let _ = util::get_entitystore(path, state).await;
-    // TODO
-    Ok(Json(CommonResult::success(Some(String::new()))))
+) -> Result<Json<CommonResult<RepoPermissions>>, ApiError> {
+    // what others may do is decided without knowing where they make requests from
+    let (name, request) = match query.user {
+        Some(name) if name != user.name => {
+            util::check_instance_admin(
+                &user,
+                ActionEnum::AdministerInstance,
+                "permissions of other users are only shown to admins",
+                state.clone(),
+            )
+            .await?;
+            (name, RequestInfo::default())
+        }
+        _ => (user.name.clone(), user.request_info()),
+    };
+    let actions = util::allowed_actions(&name, &query.path, &request, &state.context)
+        .await
+        .map_err(|err| ProtocolError::InvalidInput(err.to_string()))?;
+    Ok(Json(CommonResult::success(Some(RepoPermissions {
+        path: query.path,
+        user: name,
+        actions: actions.iter().map(|action| action.to_string()).collect(),
+    }))))
+}
+
+/// Policies and entities for the frontend to decide which actions to offer on a path without
+/// asking for each of them, the server still authorizes every request.
+async fn permission_hints(
+    user: LoginUser,
+    Query(query): Query<PermissionHintsParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<PermissionHints>>, ApiError> {
+    let (policies, entities) =
+        util::permission_hints(&user.name, &query.path, &state.context).await?;
+    Ok(Json(CommonResult::success(Some(PermissionHints {
+        path: query.path,
+        user: user.name,
+        policies,
+        entities,
+    }))))
+}
+
+/// Whether the signed in user may do an action of an extension on a path, for extensions
+/// authorizing the requests made to them.
+async fn authorize(
+    user: LoginUser,
+    Query(query): Query<AuthorizeParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<bool>>, ApiError> {
+    let res = match util::is_authorized_custom(
+        &user.name,
+        &query.path,
+        &query.action,
+        &user.request_info(),
+        &state.context,
+    )
+    .await
+    {
+        Ok(()) => CommonResult::success(Some(true)),
+        Err(saturn::context::Error::AuthDenied(_)) => CommonResult::success(Some(false)),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
 }
 
 #[cfg(test)]
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use callisto::db_enums::MessageState;
+use callisto::{webhook, webhook_delivery};
+
+pub mod webhook_router;
+
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    /// Key of the signatures, generated if not given
+    pub secret: Option<String>,
+    /// Schemas of the events delivered, all if empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Only events of this path and below it are delivered, the whole monorepo if not given
+    pub path: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct WebhookInfo {
+    pub id: i64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub path: String,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+impl From<webhook::Model> for WebhookInfo {
+    fn from(value: webhook::Model) -> Self {
+        Self {
+            id: value.id,
+            url: value.url,
+            events: value
+                .events
+                .split(',')
+                .filter(|e| !e.is_empty())
+                .map(|e| e.to_owned())
+                .collect(),
+            path: value.path,
+            created_by: value.created_by,
+            created_at: value.created_at.and_utc().timestamp(),
+        }
+    }
+}
+
+/// A created webhook, its secret is only returned once.
+#[derive(Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: WebhookInfo,
+    pub secret: String,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryInfo {
+    pub id: i64,
+    /// Idempotency key of the event, the id of its message
+    pub event_id: i64,
+    pub event: String,
+    pub payload: String,
+    pub state: String,
+    pub attempts: i32,
+    pub next_attempt_at: Option<i64>,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub delivered_at: Option<i64>,
+}
+
+impl From<webhook_delivery::Model> for DeliveryInfo {
+    fn from(value: webhook_delivery::Model) -> Self {
+        Self {
+            id: value.id,
+            event_id: value.event_id,
+            event: value.event,
+            payload: value.payload,
+            state: value.state.to_string(),
+            attempts: value.attempts,
+            next_attempt_at: value.next_attempt_at.map(|t| t.and_utc().timestamp()),
+            response_status: value.response_status,
+            response_body: value.response_body,
+            last_error: value.last_error,
+            created_at: value.created_at.and_utc().timestamp(),
+            delivered_at: value.delivered_at.map(|t| t.and_utc().timestamp()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryParams {
+    /// Only deliveries in this state, `dead` for the dead-lettered ones
+    pub state: Option<MessageState>,
+}
@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+
+use common::model::{CommonPage, CommonResult, Pagination};
+use saturn::ActionEnum;
+use taurus::event::schema;
+
+use crate::api::error::ApiError;
+use crate::api::oauth::model::LoginUser;
+use crate::api::util;
+use crate::api::webhook::{CreatedWebhook, DeliveryInfo, DeliveryParams, NewWebhook, WebhookInfo};
+use crate::api::MonoApiServiceState;
+
+pub fn routers() -> Router<MonoApiServiceState> {
+    Router::new().nest(
+        "/webhooks",
+        Router::new()
+            .route("/", get(list_webhooks).post(create_webhook))
+            .route("/{id}/delete", post(delete_webhook))
+            .route("/{id}/deliveries", get(list_deliveries))
+            .route("/{id}/deliveries/{delivery}/requeue", post(requeue)),
+    )
+}
+
+/// Webhooks receive the events of the whole monorepo, so they are managed by its admins.
+const FORBIDDEN: &str = "managing webhooks requires admin permission";
+
+async fn list_webhooks(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<Vec<WebhookInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let webhooks = state
+        .context
+        .services
+        .webhook_storage
+        .list_webhooks()
+        .await?;
+    Ok(Json(CommonResult::success(Some(
+        webhooks.into_iter().map(|w| w.into()).collect(),
+    ))))
+}
+
+/// Subscribe a webhook to the typed events, returns it with its secret.
+async fn create_webhook(
+    user: LoginUser,
+    state: State<MonoApiServiceState>,
+    Json(json): Json<NewWebhook>,
+) -> Result<Json<CommonResult<CreatedWebhook>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    match reqwest::Url::parse(&json.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        _ => return Ok(Json(CommonResult::failed("url is not http or https"))),
+    }
+    let schemas = schema::registry();
+    if let Some(event) = json
+        .events
+        .iter()
+        .find(|e| !schemas.iter().any(|s| s.name == e.as_str()))
+    {
+        return Ok(Json(CommonResult::failed(&format!(
+            "{} is not the schema of an event",
+            event
+        ))));
+    }
+    let path = json.path.unwrap_or_else(|| "/".to_owned());
+    if !path.starts_with('/') {
+        return Ok(Json(CommonResult::failed("path does not start with /")));
+    }
+    let secret = match json.secret {
+        Some(secret) if !secret.is_empty() => secret,
+        _ => uuid::Uuid::new_v4().simple().to_string(),
+    };
+    let webhook = state
+        .context
+        .services
+        .webhook_storage
+        .create_webhook(&json.url, &secret, &json.events, &path, &user.name)
+        .await?;
+    tracing::info!(
+        "webhook {} to {} created by {}",
+        webhook.id,
+        webhook.url,
+        user.name
+    );
+    Ok(Json(CommonResult::success(Some(CreatedWebhook {
+        webhook: webhook.into(),
+        secret,
+    }))))
+}
+
+/// Delete the webhook with its deliveries.
+async fn delete_webhook(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state
+        .context
+        .services
+        .webhook_storage
+        .delete_webhook(id)
+        .await?
+    {
+        true => {
+            tracing::info!("webhook {} deleted by {}", id, user.name);
+            CommonResult::success(None)
+        }
+        false => CommonResult::failed("webhook not found"),
+    };
+    Ok(Json(res))
+}
+
+/// Deliveries to the webhook with the last response to each, latest first.
+async fn list_deliveries(
+    user: LoginUser,
+    Path(id): Path<i64>,
+    Query(page): Query<Pagination>,
+    Query(params): Query<DeliveryParams>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<CommonPage<DeliveryInfo>>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state
+        .context
+        .services
+        .webhook_storage
+        .list_deliveries(id, params.state, page)
+        .await
+    {
+        Ok((items, total)) => CommonResult::success(Some(CommonPage {
+            items: items.into_iter().map(|d| d.into()).collect(),
+            total,
+        })),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
+
+/// Attempt a dead-lettered delivery again with a fresh attempt count.
+async fn requeue(
+    user: LoginUser,
+    Path((id, delivery)): Path<(i64, i64)>,
+    state: State<MonoApiServiceState>,
+) -> Result<Json<CommonResult<String>>, ApiError> {
+    util::check_instance_admin(
+        &user,
+        ActionEnum::AdministerInstance,
+        FORBIDDEN,
+        state.clone(),
+    )
+    .await?;
+    let res = match state
+        .context
+        .services
+        .webhook_storage
+        .requeue_delivery(id, delivery)
+        .await
+    {
+        Ok(true) => CommonResult::success(None),
+        Ok(false) => CommonResult::failed("dead-lettered delivery not found"),
+        Err(err) => CommonResult::failed(&err.to_string()),
+    };
+    Ok(Json(res))
+}
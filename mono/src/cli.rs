@@ -3,10 +3,9 @@
 use std::env;
 use std::path::PathBuf;
 use clap::{Arg, ArgMatches, Command};
-use tracing_subscriber::fmt::writer::MakeWriterExt;
 
 use common::{
-    config::{Config, LogConfig},
+    config::Config,
     errors::{MegaError, MegaResult},
 };
 
@@ -40,11 +39,11 @@ pub fn parse(args: Option<Vec<&str>>) -> MegaResult {
         Config::default()
     };
 
-    init_log(&config.log);
+    common::log::init(&config, "mono");
 
     ctrlc::set_handler(move || {
         tracing::info!("Received Ctrl-C signal, exiting...");
-        std::process::exit(0);
+        common::daemon::exit(0);
     }).unwrap();
 
     let (cmd, subcommand_args) = match matches.subcommand() {
@@ -60,32 +59,6 @@ pub fn parse(args: Option<Vec<&str>>) -> MegaResult {
     exec_subcommand(config, cmd, subcommand_args)
 }
 
-fn init_log(config: &LogConfig) {
-    let log_level = match config.level.as_str() {
-        "trace" => tracing::Level::TRACE,
-        "debug" => tracing::Level::DEBUG,
-        "info" => tracing::Level::INFO,
-        "warn" => tracing::Level::WARN,
-        "error" => tracing::Level::ERROR,
-        _ => tracing::Level::INFO,
-    };
-
-    let file_appender = tracing_appender::rolling::hourly(config.log_path.clone(), "mono-logs");
-
-    if config.print_std {
-        let stdout = std::io::stdout;
-        tracing_subscriber::fmt()
-            .with_writer(stdout.and(file_appender))
-            .with_max_level(log_level)
-            .init();
-    } else {
-        tracing_subscriber::fmt()
-            .with_writer(file_appender)
-            .with_max_level(log_level)
-            .init();
-    }
-}
-
 fn cli() -> Command {
     Command::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
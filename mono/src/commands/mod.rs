@@ -1,4 +1,6 @@
+pub mod release;
 pub mod service;
+pub mod split;
 
 use clap::{ArgMatches, Command};
 
@@ -6,12 +8,14 @@ use common::{config::Config, errors::MegaResult};
 
 
 pub fn builtin() -> Vec<Command> {
-    vec![service::cli()]
+    vec![service::cli(), release::cli(), split::cli()]
 }
 
 pub(crate) fn builtin_exec(cmd: &str) -> Option<fn(Config, &ArgMatches) -> MegaResult> {
     let f = match cmd {
         "service" => service::exec,
+        "release" => release::exec,
+        "split" => split::exec,
         _ => return None,
     };
 
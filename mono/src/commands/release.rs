@@ -0,0 +1,162 @@
+//! This module is responsible for handling the 'release' command.
+//! It lists, creates and deletes the releases of import repositories and uploads their assets,
+//! working on the database configured for the server.
+
+use std::path::PathBuf;
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+};
+use jupiter::context::Context;
+use jupiter::raw_storage::file_stream;
+
+#[derive(Args, Debug)]
+struct ListArgs {
+    /// Path of the import repository
+    #[arg(long)]
+    path: String,
+
+    #[arg(long, default_value_t = 20)]
+    limit: u64,
+}
+
+#[derive(Args, Debug)]
+struct CreateArgs {
+    /// Path of the import repository
+    #[arg(long)]
+    path: String,
+
+    /// Tag the release is created from
+    #[arg(long)]
+    tag: String,
+
+    /// Name of the release, defaults to the tag
+    #[arg(long, default_value = "")]
+    name: String,
+
+    /// File with the release notes
+    #[arg(long)]
+    notes: Option<PathBuf>,
+
+    /// User recorded as the creator of the release
+    #[arg(long, default_value = "admin")]
+    user: String,
+}
+
+#[derive(Args, Debug)]
+struct DeleteArgs {
+    #[arg(long)]
+    id: i64,
+}
+
+#[derive(Args, Debug)]
+struct UploadArgs {
+    /// Id of the release
+    #[arg(long)]
+    id: i64,
+
+    /// File attached to the release, its file name is the asset name
+    #[arg(long)]
+    file: PathBuf,
+
+    #[arg(long, default_value = "application/octet-stream")]
+    content_type: String,
+
+    /// User recorded as the uploader of the asset
+    #[arg(long, default_value = "admin")]
+    user: String,
+}
+
+pub fn cli() -> Command {
+    Command::new("release")
+        .about("Manage the releases of import repositories")
+        .subcommand(ListArgs::augment_args(
+            Command::new("list").about("List the latest releases of a repository"),
+        ))
+        .subcommand(CreateArgs::augment_args(
+            Command::new("create").about("Create a release from a tag with a generated changelog"),
+        ))
+        .subcommand(DeleteArgs::augment_args(
+            Command::new("delete").about("Delete a release and its assets"),
+        ))
+        .subcommand(UploadArgs::augment_args(
+            Command::new("upload").about("Attach a file to a release"),
+        ))
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let Some((cmd, args)) = args.subcommand() else {
+        return Ok(());
+    };
+    let context = Context::new(config).await;
+    match cmd {
+        "list" => {
+            let args = ListArgs::from_arg_matches(args)?;
+            let storage = &context.services.release_storage;
+            for release in storage.list_releases(&args.path, args.limit).await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    release.id, release.tag_name, release.name, release.created_at
+                );
+                for asset in storage.list_assets(release.id).await? {
+                    println!(
+                        "\t{}\t{}\t{} bytes\t{} downloads",
+                        asset.id, asset.name, asset.size, asset.download_count
+                    );
+                }
+            }
+        }
+        "create" => {
+            let args = CreateArgs::from_arg_matches(args)?;
+            let notes = match args.notes {
+                Some(file) => std::fs::read_to_string(file)?,
+                None => String::new(),
+            };
+            let release = ceres::release::create_release(
+                &context, &args.path, &args.tag, &args.name, &notes, &args.user,
+            )
+            .await?;
+            println!("created release {} of {}", release.id, release.tag_name);
+            println!("{}", release.changelog);
+        }
+        "delete" => {
+            let args = DeleteArgs::from_arg_matches(args)?;
+            ceres::release::delete_release(&context, args.id).await?;
+            println!("deleted release {}", args.id);
+        }
+        "upload" => {
+            let args = UploadArgs::from_arg_matches(args)?;
+            if context
+                .services
+                .release_storage
+                .get_release(args.id)
+                .await?
+                .is_none()
+            {
+                return Err(MegaError::with_message("release not found"));
+            }
+            let name = args
+                .file
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .ok_or_else(|| MegaError::with_message("invalid file name"))?;
+            let content = file_stream(tokio::fs::File::open(&args.file).await?);
+            let asset = ceres::release::add_asset(
+                &context,
+                args.id,
+                &name,
+                &args.content_type,
+                content,
+                &args.user,
+            )
+            .await?;
+            println!("uploaded asset {} as {}", name, asset.id);
+        }
+        _ => return Err(MegaError::unknown_subcommand(cmd)),
+    }
+    Ok(())
+}
@@ -17,6 +17,7 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
 
     tracing::info!("{server_matchers:#?}");
     let context = Context::new(config.clone()).await;
+    super::watch_config(&context);
     context
         .services
         .mono_storage
@@ -6,6 +6,7 @@
 use clap::{ArgMatches, Command};
 
 use common::{config::Config, errors::MegaResult};
+use jupiter::context::Context;
 
 pub mod http;
 pub mod https;
@@ -44,5 +45,14 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
     }
 }
 
+/// Apply config reloads to the log level, and reload the config on SIGHUP.
+fn watch_config(context: &Context) {
+    context
+        .live
+        .on_reload(|config| common::log::set_level(&config.log.level));
+    #[cfg(unix)]
+    context.reload_on_hangup();
+}
+
 #[cfg(test)]
 mod tests {}
@@ -1,7 +1,10 @@
 use std::path::PathBuf;
 
+use ceres::maintenance::{MaintenanceJob, Scheduler, MAINTENANCE_JOB};
 use clap::{ArgMatches, Args, Command, FromArgMatches, ValueEnum};
 use jupiter::context::Context;
+use jupiter::worker::WorkerPool;
+use taurus::replay::{ReplayJob, REPLAY_JOB};
 
 use crate::server::{
     https_server::{self, HttpOptions, HttpsOptions},
@@ -55,11 +58,21 @@ pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
     let service_type = server_matchers.service;
 
     let context = Context::new(config.clone()).await;
+    super::watch_config(&context);
     context
         .services
         .mono_storage
         .init_monorepo(&config.monorepo)
         .await;
+    if config.maintenance.enable {
+        tokio::spawn(Scheduler::new(context.clone()).start());
+    }
+    if config.jobs.enable {
+        let workers = WorkerPool::new(context.clone())
+            .register(MAINTENANCE_JOB, MaintenanceJob)
+            .register(REPLAY_JOB, ReplayJob);
+        tokio::spawn(workers.start());
+    }
     let context_clone = context.clone();
     let http_server = if service_type.contains(&StartCommand::Http) {
         let http = HttpOptions {
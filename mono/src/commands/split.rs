@@ -0,0 +1,95 @@
+//! This module is responsible for handling the 'split' command.
+//! It registers splits of monorepo directories into read-only repositories and brings them up
+//! to date, working on the database configured for the server.
+
+use clap::{ArgMatches, Args, Command, FromArgMatches};
+
+use ceres::maintenance::subtree_split;
+use common::{
+    config::Config,
+    errors::{MegaError, MegaResult},
+};
+use jupiter::context::Context;
+
+#[derive(Args, Debug)]
+struct CreateArgs {
+    /// Monorepo directory to split
+    #[arg(long)]
+    path: String,
+
+    /// Path of the split repository, below the import directory
+    #[arg(long)]
+    repo_path: String,
+
+    /// User recorded as the creator of the split
+    #[arg(long, default_value = "admin")]
+    user: String,
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// Only update the splits of directories at or below this path
+    #[arg(long)]
+    path: Option<String>,
+}
+
+pub fn cli() -> Command {
+    Command::new("split")
+        .about("Split monorepo directories into standalone read-only repositories")
+        .subcommand(Command::new("list").about("List the registered splits"))
+        .subcommand(CreateArgs::augment_args(
+            Command::new("create").about("Register the split of a directory"),
+        ))
+        .subcommand(RunArgs::augment_args(
+            Command::new("run").about("Rewrite the commits made since the last run"),
+        ))
+}
+
+#[tokio::main]
+pub(crate) async fn exec(config: Config, args: &ArgMatches) -> MegaResult {
+    let Some((cmd, args)) = args.subcommand() else {
+        return Ok(());
+    };
+    let context = Context::new(config).await;
+    let storage = &context.services.mono_storage;
+    match cmd {
+        "list" => {
+            for split in storage.list_splits().await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    split.id,
+                    split.path,
+                    split.repo_path,
+                    split.last_split.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        "create" => {
+            let args = CreateArgs::from_arg_matches(args)?;
+            let split =
+                subtree_split::create_split(&context, &args.path, &args.repo_path, &args.user)
+                    .await?;
+            println!(
+                "split {} of {} into {}",
+                split.id, split.path, split.repo_path
+            );
+        }
+        "run" => {
+            let args = RunArgs::from_arg_matches(args)?;
+            for split in storage.list_splits().await? {
+                if !args
+                    .path
+                    .as_deref()
+                    .is_none_or(|p| std::path::Path::new(&split.path).starts_with(p))
+                {
+                    continue;
+                }
+                let path = split.path.clone();
+                let count = subtree_split::run_split(&context, split).await?;
+                println!("{}: {} new commits", path, count);
+            }
+        }
+        _ => return Err(MegaError::unknown_subcommand(cmd)),
+    }
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! Commands other than git run over ssh, like `ssh git@mega info`.
+//!
+//! Similar to gitolite, users can list the repositories they have access to, create new
+//! repositories in the monorepo and query their permissions. Every decision is made by saturn,
+//! the same policies apply as in the web api.
+
+use std::collections::BTreeSet;
+use std::path::{Component, Path};
+
+use ceres::api_service::mono_api_service::MonoApiService;
+use ceres::api_service::ApiHandler;
+use ceres::model::create_file::CreateFileInfo;
+use common::utils::repo_path;
+use jupiter::context::Context;
+use saturn::request::RequestInfo;
+use saturn::ActionEnum;
+use taurus::event::repo_created::RepoCreatedEvent;
+
+use crate::api::util;
+
+const HELP: &str = "\
+available commands:
+  info [prefix]               list the repositories you can access
+  create <path>               create a repository in the monorepo
+  perm check <path> <action>  check whether you are allowed an action on a path
+  perm list <path>            list the actions you are allowed on a path
+  help                        show this message
+";
+
+/// Whether `name` is one of the commands handled here instead of a git command.
+pub fn is_command(name: &str) -> bool {
+    matches!(name, "info" | "create" | "perm" | "help")
+}
+
+/// Run the command `args` for `username`, the output is written to stdout on success and to
+/// stderr with a failing exit status otherwise.
+pub async fn run(
+    context: &Context,
+    username: Option<&str>,
+    request: &RequestInfo,
+    args: &[&str],
+) -> Result<String, String> {
+    match args {
+        ["info"] => info(context, username, request, None).await,
+        ["info", prefix] => info(context, username, request, Some(*prefix)).await,
+        ["create", path] => create(context, signed_in(username)?, request, path).await,
+        ["perm", "check", path, action] => {
+            let action: ActionEnum = action.parse()?;
+            if util::is_authorized(signed_in(username)?, path, action, request, context)
+                .await
+                .is_ok()
+            {
+                Ok("allowed\n".to_owned())
+            } else {
+                Err("denied".to_owned())
+            }
+        }
+        ["perm", "list", path] => {
+            let actions = util::allowed_actions(signed_in(username)?, path, request, context)
+                .await
+                .map_err(|err| err.to_string())?;
+            Ok(actions
+                .iter()
+                .map(|action| format!("{}\n", action))
+                .collect())
+        }
+        ["help"] => Ok(HELP.to_owned()),
+        _ => Err(format!("invalid arguments\n{}", HELP)),
+    }
+}
+
+fn signed_in(username: Option<&str>) -> Result<&str, String> {
+    username.ok_or_else(|| "this command requires a signed in user".to_owned())
+}
+
+/// List the monorepo, its cloned directories and the import repositories which `username` can
+/// read, marking the ones it can push to.
+async fn info(
+    context: &Context,
+    username: Option<&str>,
+    request: &RequestInfo,
+    prefix: Option<&str>,
+) -> Result<String, String> {
+    let mut paths = BTreeSet::from(["/".to_owned()]);
+    let refs = context
+        .services
+        .mono_storage
+        .get_default_refs()
+        .await
+        .map_err(|err| err.to_string())?;
+    paths.extend(refs.into_iter().map(|r| r.path));
+    let repos = context
+        .services
+        .git_db_storage
+        .list_git_repos()
+        .await
+        .map_err(|err| err.to_string())?;
+    paths.extend(repos.into_iter().map(|r| r.repo_path));
+
+    let mut res = format!(
+        "hello {}, this is mega {}\n\n",
+        username.unwrap_or("anonymous"),
+        env!("CARGO_PKG_VERSION")
+    );
+    for path in paths {
+        if prefix.is_some_and(|p| !Path::new(&path).starts_with(p)) {
+            continue;
+        }
+        if util::check_read_access(username, Path::new(&path), request, context)
+            .await
+            .is_err()
+        {
+            continue;
+        }
+        let writable = match username {
+            Some(username) => {
+                util::is_authorized(username, &path, ActionEnum::PushRepo, request, context)
+                    .await
+                    .is_ok()
+            }
+            None => false,
+        };
+        let mode = if writable { " R W" } else { " R  " };
+        res.push_str(&format!("{}\t{}\n", mode, path));
+    }
+    Ok(res)
+}
+
+/// Create the directory `path` in the monorepo, allowed to users who may create repositories in
+/// its parent directory.
+async fn create(
+    context: &Context,
+    username: &str,
+    request: &RequestInfo,
+    path: &str,
+) -> Result<String, String> {
+    let path = Path::new(path);
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(format!("invalid path: {}", path.display()));
+    };
+    if !path.has_root()
+        || path
+            .components()
+            .any(|c| matches!(c, Component::ParentDir | Component::CurDir))
+    {
+        return Err(format!("invalid path: {}", path.display()));
+    }
+    if path.starts_with(&context.config.monorepo.import_dir) {
+        return Err("import repositories are created by pushing to them".to_owned());
+    }
+    let parent = parent.to_str().unwrap();
+    if util::is_authorized(username, parent, ActionEnum::CreateRepo, request, context)
+        .await
+        .is_err()
+    {
+        return Err(format!(
+            "permission denied to create repositories in {}",
+            parent
+        ));
+    }
+    let service = MonoApiService {
+        context: context.clone(),
+    };
+    service
+        .create_monorepo_file(CreateFileInfo {
+            is_directory: true,
+            name: name.to_string_lossy().into_owned(),
+            path: parent.to_owned(),
+            content: None,
+        })
+        .await
+        .map_err(|err| err.to_string())?;
+    RepoCreatedEvent::notify(&repo_path(path), username).await;
+    tracing::info!(
+        "{} created repository {} over ssh",
+        username,
+        path.display()
+    );
+    Ok(format!("created {}\n", path.display()))
+}
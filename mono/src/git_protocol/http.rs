@@ -1,4 +1,5 @@
 use std::convert::Infallible;
+use std::net::IpAddr;
 
 use anyhow::Result;
 use axum::body::Body;
@@ -15,6 +16,13 @@ use tokio_stream::StreamExt;
 use ceres::protocol::{smart, ServiceType, SmartProtocol};
 use common::errors::ProtocolError;
 use common::model::InfoRefsParams;
+use saturn::request::{AuthMethod, RequestInfo};
+
+use crate::api::util;
+use crate::git_protocol::{
+    check_protected_tags, check_push_access, check_user_token, refresh_hierarchy,
+};
+use crate::server::middleware::{ClientIp, PeerIdentity};
 
 // # Discovering Reference
 // HTTP clients that support the "smart" protocol (or both the "smart" and "dumb" protocols) MUST
@@ -24,8 +32,21 @@ use common::model::InfoRefsParams;
 // The request MUST NOT contain additional query parameters.
 pub async fn git_info_refs(
     params: InfoRefsParams,
+    headers: &HeaderMap<HeaderValue>,
+    client_ip: Option<IpAddr>,
+    peer: Option<&PeerIdentity>,
     mut pack_protocol: SmartProtocol,
 ) -> Result<Response<Body>, ProtocolError> {
+    // pushes are never anonymous, git asks for credentials before it sends the pack
+    if params.service.as_deref() == Some("git-receive-pack") {
+        let username = http_auth_user(headers, &pack_protocol.context).await;
+        if username.is_none() {
+            return auth_failed();
+        }
+    }
+    if let Some(resp) = check_read_access(headers, client_ip, peer, &pack_protocol).await? {
+        return Ok(resp);
+    }
     let service_name = params.service.unwrap();
     pack_protocol.service_type = Some(service_name.parse::<ServiceType>().unwrap());
 
@@ -41,48 +62,70 @@ pub async fn git_info_refs(
     Ok(response)
 }
 
-async fn http_auth(header: &HeaderMap<HeaderValue>, context: &Context) -> bool {
+/// Returns the name of the user authenticated by the `Authorization` header, if any.
+pub async fn http_auth_user(header: &HeaderMap<HeaderValue>, context: &Context) -> Option<String> {
     for (k, v) in header {
         if k == http::header::AUTHORIZATION {
-            let decoded = general_purpose::STANDARD
-                .decode(
-                    v.to_str()
-                        .unwrap()
-                        .strip_prefix("Basic ")
-                        .unwrap()
-                        .as_bytes(),
-                )
-                .unwrap();
+            let encoded = v.to_str().ok()?.strip_prefix("Basic ")?;
+            let decoded = general_purpose::STANDARD.decode(encoded.as_bytes()).ok()?;
             let credentials = String::from_utf8(decoded).unwrap_or_default();
             let mut parts = credentials.splitn(2, ':');
             let username = parts.next().unwrap_or("");
             let token = parts.next().unwrap_or("");
             tracing::debug!("{}, {}", username, token);
-            let auth_config = context.config.authentication.clone();
-            if auth_config.enable_test_user
-                && username == auth_config.test_user_name
-                && token == auth_config.test_user_token
-            {
-                return true;
-            }
-            match context
-                .user_stg()
-                .find_user_by_name(username)
-                .await
-                .unwrap()
-            {
-                Some(user) => {
-                    return context
-                        .user_stg()
-                        .check_token(user.id, token)
-                        .await
-                        .unwrap();
-                }
-                None => return false,
-            }
+            let valid = check_user_token(context, username, token).await;
+            return valid.then(|| username.to_owned());
+        }
+    }
+    None
+}
+
+/// Enforce repository visibility for fetch and clone.
+///
+/// Anonymous requests to non-public paths are challenged for credentials,
+/// returns the challenge response in this case. Requests of a `peer` without credentials are
+/// made as the principal of the peer instead, and never challenged.
+async fn check_read_access(
+    header: &HeaderMap<HeaderValue>,
+    client_ip: Option<IpAddr>,
+    peer: Option<&PeerIdentity>,
+    pack_protocol: &SmartProtocol,
+) -> Result<Option<Response<Body>>, ProtocolError> {
+    let mut username = http_auth_user(header, &pack_protocol.context).await;
+    let mut request = request_info(&username, client_ip);
+    if username.is_none() {
+        if let Some(principal) = peer.and_then(|peer| peer.principal.clone()) {
+            username = Some(principal);
+            request.auth_method = Some(AuthMethod::PeerKey);
         }
     }
-    false
+    match util::check_read_access(
+        username.as_deref(),
+        &pack_protocol.path,
+        &request,
+        &pack_protocol.context,
+    )
+    .await
+    {
+        Ok(()) => Ok(None),
+        Err(_) if username.is_none() && peer.is_none() => auth_failed().map(Some),
+        Err(err) => Err(err),
+    }
+}
+
+/// What policies may take into account about a request of `username`, authenticated by an
+/// access token.
+fn request_info(username: &Option<String>, client_ip: Option<IpAddr>) -> RequestInfo {
+    RequestInfo {
+        ip: client_ip,
+        auth_method: username.is_some().then_some(AuthMethod::Token),
+        ..Default::default()
+    }
+}
+
+/// Address of the client as found by the network policy middleware.
+fn client_ip(req: &Request<Body>) -> Option<IpAddr> {
+    req.extensions().get::<ClientIp>().map(|ClientIp(ip)| *ip)
 }
 
 fn auth_failed() -> Result<Response<Body>, ProtocolError> {
@@ -121,6 +164,12 @@ pub async fn git_upload_pack(
     req: Request<Body>,
     mut pack_protocol: SmartProtocol,
 ) -> Result<Response<Body>, ProtocolError> {
+    let peer = req.extensions().get::<PeerIdentity>();
+    if let Some(resp) =
+        check_read_access(req.headers(), client_ip(&req), peer, &pack_protocol).await?
+    {
+        return Ok(resp);
+    }
     let upload_request: BytesMut = req
         .into_body()
         .into_data_stream()
@@ -185,11 +234,19 @@ pub async fn git_receive_pack(
     req: Request<Body>,
     mut pack_protocol: SmartProtocol,
 ) -> Result<Response<Body>, ProtocolError> {
-    if pack_protocol.context.config.authentication.enable_http_auth
-        && !http_auth(req.headers(), &pack_protocol.context).await
-    {
+    pack_protocol.username = http_auth_user(req.headers(), &pack_protocol.context).await;
+    // anonymous pushes are never allowed, the client is asked for credentials
+    if pack_protocol.username.is_none() {
         return auth_failed();
     }
+    let request = request_info(&pack_protocol.username, client_ip(&req));
+    check_push_access(
+        pack_protocol.username.as_deref(),
+        &pack_protocol.path,
+        &request,
+        &pack_protocol.context,
+    )
+    .await?;
     // Convert the request body into a data stream.
     let mut data_stream = req.into_body().into_data_stream();
     let mut report_status = Bytes::new();
@@ -202,12 +259,14 @@ pub async fn git_receive_pack(
         if let Some(pos) = search_subsequence(&chunk, b"PACK") {
             chunk_buffer.extend_from_slice(&chunk[0..pos]);
             pack_protocol.git_receive_pack_protocol(Bytes::copy_from_slice(&chunk_buffer));
+            check_protected_tags(&mut pack_protocol, &request).await;
             // Create a new stream from the remaining bytes and the rest of the data stream.
             let left_chunk_bytes = Bytes::copy_from_slice(&chunk[pos..]);
             let pack_stream = stream::once(async { Ok(left_chunk_bytes) }).chain(data_stream);
             report_status = pack_protocol
                 .git_receive_pack_stream(Box::pin(pack_stream))
                 .await?;
+            refresh_hierarchy(&pack_protocol.context).await;
             break;
         } else {
             chunk_buffer.extend_from_slice(&chunk);
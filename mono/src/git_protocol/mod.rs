@@ -1,2 +1,170 @@
+use std::path::Path;
+
+use ceres::protocol::SmartProtocol;
+use common::errors::ProtocolError;
+use jupiter::context::Context;
+use saturn::request::RequestInfo;
+use saturn::service_account;
+use saturn::ActionEnum;
+
+use crate::api::util;
+
 pub mod ssh;
-pub mod http;
\ No newline at end of file
+pub mod http;
+pub mod commands;
+
+/// Whether `token` is an access token of the user `username`, of the service account when
+/// `username` is `name[bot]`, or the credentials of the test user when it is enabled. Other
+/// credentials of users who are not deactivated are left to the auth providers of plugins.
+pub async fn check_user_token(context: &Context, username: &str, token: &str) -> bool {
+    let auth_config = &context.config.authentication;
+    if auth_config.enable_test_user
+        && username == auth_config.test_user_name
+        && token == auth_config.test_user_token
+    {
+        return true;
+    }
+    if let Some(name) = service_account::bot_name(username) {
+        let valid = match context.user_stg().find_service_account(name).await {
+            Ok(Some(account)) => {
+                context
+                    .user_stg()
+                    .check_service_account_token(account.id, token)
+                    .await
+            }
+            Ok(None) => Ok(false),
+            Err(err) => Err(err),
+        };
+        return valid.unwrap_or_else(|err| {
+            tracing::error!("failed to check the token of {}: {}", username, err);
+            false
+        });
+    }
+    let user = match context.user_stg().find_user_by_name(username).await {
+        Ok(user) => user,
+        Err(err) => {
+            tracing::error!("failed to check the token of {}: {}", username, err);
+            return false;
+        }
+    };
+    if let Some(user) = user {
+        if user.deactivated_at.is_some() {
+            return false;
+        }
+        match context.user_stg().check_token(user.id, token).await {
+            Ok(true) => return true,
+            Ok(false) => {}
+            Err(err) => {
+                tracing::error!("failed to check the token of {}: {}", username, err);
+                return false;
+            }
+        }
+    }
+    // credentials of an auth provider plugin, like the password of a directory
+    context.plugins.authenticate(username, token).await
+}
+
+/// Whether `principal` is a user who is not deactivated, or the `name[bot]` of a service account.
+/// Principals which can't be looked up are not active.
+pub async fn is_active_principal(context: &Context, principal: &str) -> bool {
+    let active = match service_account::bot_name(principal) {
+        Some(name) => context
+            .user_stg()
+            .find_service_account(name)
+            .await
+            .map(|account| account.is_some()),
+        None => context
+            .user_stg()
+            .find_user_by_name(principal)
+            .await
+            .map(|user| user.is_some_and(|user| user.deactivated_at.is_none())),
+    };
+    active.unwrap_or_else(|err| {
+        tracing::error!("failed to look up {}: {}", principal, err);
+        false
+    })
+}
+
+/// The principal the ztm peer `peer_id` is authorized as, if it is mapped to one which is still
+/// active.
+pub async fn peer_principal(context: &Context, peer_id: &str) -> Option<String> {
+    let mapping = match context
+        .services
+        .ztm_storage
+        .get_peer_principal(peer_id)
+        .await
+    {
+        Ok(mapping) => mapping?,
+        Err(err) => {
+            tracing::error!(
+                "failed to look up the principal of peer {}: {}",
+                peer_id,
+                err
+            );
+            return None;
+        }
+    };
+    if is_active_principal(context, &mapping.principal).await {
+        Some(mapping.principal)
+    } else {
+        tracing::warn!(
+            "peer {} is mapped to {}, which is not active",
+            peer_id,
+            mapping.principal
+        );
+        None
+    }
+}
+
+/// Refuse a push to `path` unless `username` may push to it, anonymous pushes never may.
+pub async fn check_push_access(
+    username: Option<&str>,
+    path: &Path,
+    request: &RequestInfo,
+    context: &Context,
+) -> Result<(), ProtocolError> {
+    let forbidden =
+        || ProtocolError::Forbidden(format!("no permission to push to {}", path.display()));
+    let username = username.ok_or_else(forbidden)?;
+    util::is_authorized(
+        username,
+        path.to_str().unwrap(),
+        ActionEnum::PushRepo,
+        request,
+        context,
+    )
+    .await
+    .map_err(|_| forbidden())
+}
+
+/// Refuse pushed tags matching a tag protection rule unless the pusher may manage
+/// protected tags, anonymous pushes never may.
+pub async fn check_protected_tags(pack_protocol: &mut SmartProtocol, request: &RequestInfo) {
+    let tags = pack_protocol.protected_tags().await;
+    if tags.is_empty() {
+        return;
+    }
+    let allowed = match &pack_protocol.username {
+        Some(username) => util::is_authorized(
+            username,
+            pack_protocol.path.to_str().unwrap(),
+            ActionEnum::ManageProtectedTags,
+            request,
+            &pack_protocol.context,
+        )
+        .await
+        .is_ok(),
+        None => false,
+    };
+    if !allowed {
+        pack_protocol.deny_refs(&tags, "protected tag, permission to manage tags required");
+    }
+}
+
+/// Build the resource hierarchy of a push which changed the tree of the monorepo, like one of an
+/// import repository, rather than on the first request authorized with it.
+pub async fn refresh_hierarchy(context: &Context) {
+    if let Err(err) = util::directory_tree(context).await {
+        tracing::error!("failed to build the directories of the monorepo: {}", err);
+    }
+}
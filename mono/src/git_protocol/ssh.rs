@@ -1,25 +1,37 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
 use chrono::{DateTime, Duration, Utc};
 use futures::{stream, StreamExt};
-use russh::server::{self, Auth, Msg, Session};
+use russh::server::{self, Auth, Msg, Response, Session};
 use russh::{Channel, ChannelId, MethodSet};
 use russh_keys::{self, HashAlg, PublicKey};
 use tokio::io::AsyncReadExt;
+use tracing::Instrument;
 
 use ceres::lfs::lfs_structs::Link;
 use ceres::protocol::smart::{self};
 use ceres::protocol::ServiceType;
 use ceres::protocol::{SmartProtocol, TransportProtocol};
+use common::config::SshConfig;
+use common::errors::ProtocolError;
+use common::network::{AccessMode, AuthLimiter, NetworkPolicy};
 use jupiter::context::Context;
+use saturn::request::{AuthMethod, RequestInfo};
 use tokio::sync::Mutex;
 
+use crate::api::util;
 use crate::git_protocol::http::search_subsequence;
+use crate::git_protocol::{
+    check_protected_tags, check_push_access, check_user_token, commands, refresh_hierarchy,
+};
 
 type ClientMap = HashMap<(usize, ChannelId), Channel<Msg>>;
 #[allow(dead_code)]
@@ -30,12 +42,23 @@ pub struct SshServer {
     pub context: Context,
     pub smart_protocol: Option<SmartProtocol>,
     pub data_combined: BytesMut,
+    pub network_policy: Arc<NetworkPolicy>,
+    pub auth_limiter: Arc<AuthLimiter>,
+    pub remote_addr: Option<SocketAddr>,
+    /// Name of the user the client authenticated as
+    pub username: Option<String>,
+    /// How the client authenticated
+    pub auth_method: Option<AuthMethod>,
+    /// Id of the connection in the log, the lines logged for its git commands carry it
+    pub request_id: String,
 }
 
 impl server::Server for SshServer {
     type Handler = Self;
-    fn new_client(&mut self, _: Option<std::net::SocketAddr>) -> Self {
-        let s = self.clone();
+    fn new_client(&mut self, addr: Option<SocketAddr>) -> Self {
+        let mut s = self.clone();
+        s.remote_addr = addr;
+        s.request_id = common::log::request_id(None);
         self.id += 1;
         s
     }
@@ -75,20 +98,239 @@ impl server::Handler for SshServer {
         data: &[u8],
         session: &mut Session,
     ) -> Result<(), Self::Error> {
+        let span = self.span();
+        self.exec(channel, data, session).instrument(span).await
+    }
+
+    async fn authentication_banner(&mut self) -> Result<Option<String>, Self::Error> {
+        let banner = &self.context.config.ssh.banner;
+        Ok((!banner.is_empty()).then(|| format!("{}\n", banner.trim_end())))
+    }
+
+    async fn auth_publickey(
+        &mut self,
+        user: &str,
+        public_key: &PublicKey,
+    ) -> Result<Auth, Self::Error> {
+        let fingerprint = public_key.fingerprint(HashAlg::Sha256).to_string();
+        tracing::debug!("auth_publickey: {} / {}", user, fingerprint);
+        if let Some(auth) = self.check_client(user, "publickey") {
+            return Ok(auth);
+        }
+        let res = self
+            .context
+            .user_stg()
+            .search_ssh_key_finger(&fingerprint)
+            .await
+            .unwrap();
+        let owner = match res.first() {
+            Some(key) => self
+                .context
+                .user_stg()
+                .find_users_by_ids(vec![key.user_id])
+                .await
+                .unwrap()
+                .pop()
+                .filter(|owner| owner.deactivated_at.is_none()),
+            None => None,
+        };
+        if let Some(owner) = owner {
+            self.username = Some(owner.name);
+            self.auth_method = Some(AuthMethod::SshKey);
+            Ok(self.audit_auth(user, "publickey", true))
+        } else {
+            Ok(self.audit_auth(user, "publickey", false))
+        }
+    }
+
+    async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
+        if let Some(auth) = self.check_client(user, "password") {
+            return Ok(auth);
+        }
+        let valid = check_user_token(&self.context, user, password).await;
+        if valid {
+            self.username = Some(user.to_owned());
+            self.auth_method = Some(AuthMethod::Token);
+        }
+        Ok(self.audit_auth(user, "password", valid))
+    }
+
+    /// Asks for an access token of the user, the same credentials as password authentication.
+    async fn auth_keyboard_interactive(
+        &mut self,
+        user: &str,
+        _submethods: &str,
+        response: Option<Response<'async_trait>>,
+    ) -> Result<Auth, Self::Error> {
+        if let Some(auth) = self.check_client(user, "keyboard-interactive") {
+            return Ok(auth);
+        }
+        let Some(mut response) = response else {
+            return Ok(Auth::Partial {
+                name: Cow::Borrowed("Mega"),
+                instructions: Cow::Borrowed("Authenticate with an access token of your account"),
+                prompts: Cow::Owned(vec![(Cow::Borrowed("Access token: "), false)]),
+            });
+        };
+        let token = response
+            .next()
+            .map(|answer| String::from_utf8_lossy(&answer).into_owned())
+            .unwrap_or_default();
+        let valid = check_user_token(&self.context, user, &token).await;
+        if valid {
+            self.username = Some(user.to_owned());
+            self.auth_method = Some(AuthMethod::Token);
+        }
+        Ok(self.audit_auth(user, "keyboard-interactive", valid))
+    }
+
+    async fn data(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        // the command was refused
+        let Some(smart_protocol) = self.smart_protocol.as_mut() else {
+            return Ok(());
+        };
+        tracing::info!(
+            "receiving data length:{}",
+            // String::from_utf8_lossy(data),
+            data.len()
+        );
+        let service_type = smart_protocol.service_type.unwrap();
+        match service_type {
+            ServiceType::UploadPack => {
+                let span = self.span();
+                self.handle_upload_pack(channel, data, session)
+                    .instrument(span)
+                    .await;
+            }
+            ServiceType::ReceivePack => {
+                self.data_combined.extend_from_slice(data);
+            }
+        };
+        session.channel_success(channel)?;
+        Ok(())
+    }
+
+    async fn channel_eof(
+        &mut self,
+        channel: ChannelId,
+        session: &mut Session,
+    ) -> Result<(), Self::Error> {
+        if let Some(smart_protocol) = self.smart_protocol.as_mut() {
+            if smart_protocol.service_type.unwrap() == ServiceType::ReceivePack {
+                let span = self.span();
+                self.handle_receive_pack(channel, session)
+                    .instrument(span)
+                    .await;
+            };
+        }
+
+        {
+            let mut clients = self.clients.lock().await;
+            clients.remove(&(self.id, channel));
+        }
+        session.exit_status_request(channel, 0000)?;
+        session.close(channel)?;
+        Ok(())
+    }
+}
+
+/// The authentication methods enabled by `ssh.auth_methods`, unknown names are ignored.
+pub fn auth_methods(config: &SshConfig) -> MethodSet {
+    let mut methods = MethodSet::empty();
+    for method in &config.auth_methods {
+        match method.as_str() {
+            "publickey" => methods |= MethodSet::PUBLICKEY,
+            "password" => methods |= MethodSet::PASSWORD,
+            "keyboard-interactive" => methods |= MethodSet::KEYBOARD_INTERACTIVE,
+            other => tracing::warn!("unknown ssh authentication method: {}", other),
+        }
+    }
+    methods
+}
+
+impl SshServer {
+    /// The span the git commands of the connection run in.
+    fn span(&self) -> tracing::Span {
+        common::log::request_span("ssh", &self.request_id)
+    }
+
+    async fn exec(
+        &mut self,
+        channel: ChannelId,
+        data: &[u8],
+        session: &mut Session,
+    ) -> Result<(), anyhow::Error> {
         let data = String::from_utf8_lossy(data).trim().to_owned();
         tracing::info!("exec_request, channel:{:?}, command: {}", channel, data);
         // command exmaple:
         // Push: git-receive-pack '/path/to/repo.git'
         // Pull: git-upload-pack '/path/to/repo.git'
         // LFS HTTP Authenticate: git-lfs-authenticate '/path/to/repo.git' download/upload
+        let args: Vec<_> = data.split_whitespace().collect();
+        if args.first().is_some_and(|name| commands::is_command(name)) {
+            return self.run_command(channel, &args, session).await;
+        }
         let command: Vec<_> = data.split(' ').collect();
         let path = command[1];
-        let path = path.replace(".git", "").replace('\'', "");
+        let mut path = path.replace(".git", "").replace('\'', "");
+        // follow the redirect of a renamed repository during its grace period
+        if let Ok(Some(new_path)) = self
+            .context
+            .services
+            .mono_storage
+            .find_redirect(Path::new(&path))
+            .await
+        {
+            let note = format!(
+                "remote: repository moved to {}, please update your remote url\n",
+                new_path
+            );
+            session.extended_data(channel, 1, note.into_bytes().into())?;
+            path = new_path;
+        }
+        let mode = match (command[0], command.get(2)) {
+            ("git-receive-pack", _) | ("git-lfs-authenticate", Some(&"upload")) => {
+                AccessMode::Write
+            }
+            _ => AccessMode::Read,
+        };
+        let mut check = self.check_network_policy(Some(Path::new(&path)), mode);
+        // the refs are advertised to pushes too
+        if check.is_ok() {
+            check = util::check_read_access(
+                self.username.as_deref(),
+                Path::new(&path),
+                &self.request_info(),
+                &self.context,
+            )
+            .await;
+        }
+        if check.is_ok() && mode == AccessMode::Write {
+            check = check_push_access(
+                self.username.as_deref(),
+                Path::new(&path),
+                &self.request_info(),
+                &self.context,
+            )
+            .await;
+        }
+        if let Err(err) = check {
+            session.extended_data(channel, 1, format!("{}\n", err).into_bytes().into())?;
+            session.exit_status_request(channel, 1)?;
+            session.close(channel)?;
+            return Ok(());
+        }
         let mut smart_protocol = SmartProtocol::new(
             PathBuf::from(&path),
             self.context.clone(),
             TransportProtocol::Ssh,
         );
+        smart_protocol.username = self.username.clone();
         match command[0] {
             "git-upload-pack" | "git-receive-pack" => {
                 smart_protocol.service_type = Some(ServiceType::from_str(command[0]).unwrap());
@@ -125,77 +367,136 @@ impl server::Handler for SshServer {
         Ok(())
     }
 
-    async fn auth_publickey(
-        &mut self,
-        user: &str,
-        public_key: &PublicKey,
-    ) -> Result<Auth, Self::Error> {
-        let fingerprint = public_key.fingerprint(HashAlg::Sha256).to_string();
+    /// Reject clients from banned addresses or refused by the network policy before their
+    /// credentials are looked at, as well as methods which are not enabled.
+    fn check_client(&self, user: &str, method: &str) -> Option<Auth> {
+        let remote = self.remote_addr.map(|addr| addr.ip());
+        let config = &self.context.config.ssh;
+        if !config.auth_methods.iter().any(|m| m == method) {
+            tracing::info!(
+                target: "ssh_auth",
+                remote = ?remote,
+                user,
+                method,
+                result = "disabled",
+                "ssh authentication method not enabled"
+            );
+            return Some(Auth::Reject {
+                proceed_with_methods: Some(auth_methods(config)),
+            });
+        }
+        let banned = remote.is_some_and(|ip| self.auth_limiter.is_banned(ip, Instant::now()));
+        if !banned && self.check_network_policy(None, AccessMode::Read).is_ok() {
+            return None;
+        }
+        tracing::warn!(
+            target: "ssh_auth",
+            remote = ?remote,
+            user,
+            method,
+            result = if banned { "banned" } else { "network_policy" },
+            "ssh authentication refused"
+        );
+        Some(Auth::Reject {
+            proceed_with_methods: None,
+        })
+    }
 
-        tracing::info!(
-            "auth_publickey: {} / {}",
+    /// Log the outcome of an authentication attempt for intrusion detection, failures count
+    /// towards banning the client address.
+    fn audit_auth(&self, user: &str, method: &str, accepted: bool) -> Auth {
+        let remote = self.remote_addr.map(|addr| addr.ip());
+        if accepted {
+            if let Some(ip) = remote {
+                self.auth_limiter.record_success(ip);
+            }
+            tracing::info!(
+                target: "ssh_auth",
+                remote = ?remote,
+                user,
+                method,
+                account = self.username.as_deref(),
+                result = "accepted",
+                "ssh authentication succeeded"
+            );
+            return Auth::Accept;
+        }
+        let banned = remote.is_some_and(|ip| self.auth_limiter.record_failure(ip, Instant::now()));
+        tracing::warn!(
+            target: "ssh_auth",
+            remote = ?remote,
             user,
-            fingerprint
+            method,
+            result = "rejected",
+            banned,
+            "ssh authentication failed"
         );
-        let res = self.context.user_stg().search_ssh_key_finger(&fingerprint).await.unwrap();
-        if !res.is_empty() {
-            tracing::info!("Client public key verified successfully!");
-            Ok(Auth::Accept)
-        } else {
-            tracing::warn!("Client public key verification failed!");
-            Ok(Auth::Reject {
-                proceed_with_methods: Some(MethodSet::PUBLICKEY),
-            })
+        Auth::Reject {
+            proceed_with_methods: (!banned).then(|| auth_methods(&self.context.config.ssh)),
         }
     }
 
-    async fn data(
-        &mut self,
+    /// Run one of the non-git [`commands`], `create` also has to pass the network policy for
+    /// writes.
+    async fn run_command(
+        &self,
         channel: ChannelId,
-        data: &[u8],
+        args: &[&str],
         session: &mut Session,
-    ) -> Result<(), Self::Error> {
-        let smart_protocol = self.smart_protocol.as_mut().unwrap();
-        tracing::info!(
-            "receiving data length:{}",
-            // String::from_utf8_lossy(data),
-            data.len()
-        );
-        let service_type = smart_protocol.service_type.unwrap();
-        match service_type {
-            ServiceType::UploadPack => {
-                self.handle_upload_pack(channel, data, session).await;
+    ) -> Result<(), anyhow::Error> {
+        let res = if args[0] == "create" {
+            self.check_network_policy(args.get(1).map(Path::new), AccessMode::Write)
+                .map_err(|err| err.to_string())
+        } else {
+            Ok(())
+        };
+        let res = match res {
+            Ok(()) => {
+                let request = self.request_info();
+                commands::run(&self.context, self.username.as_deref(), &request, args).await
             }
-            ServiceType::ReceivePack => {
-                self.data_combined.extend_from_slice(data);
+            Err(err) => Err(err),
+        };
+        let status = match res {
+            Ok(output) => {
+                session.data(channel, output.into_bytes().into())?;
+                0
+            }
+            Err(err) => {
+                session.extended_data(channel, 1, format!("{}\n", err).into_bytes().into())?;
+                1
             }
         };
-        session.channel_success(channel)?;
+        session.exit_status_request(channel, status)?;
+        session.close(channel)?;
         Ok(())
     }
 
-    async fn channel_eof(
-        &mut self,
-        channel: ChannelId,
-        session: &mut Session,
-    ) -> Result<(), Self::Error> {
-        if let Some(smart_protocol) = self.smart_protocol.as_mut() {
-            if smart_protocol.service_type.unwrap() == ServiceType::ReceivePack {
-                self.handle_receive_pack(channel, session).await;
-            };
+    /// What policies may take into account about the requests of the client.
+    fn request_info(&self) -> RequestInfo {
+        RequestInfo {
+            ip: self.remote_addr.map(|addr| addr.ip()),
+            auth_method: self.auth_method,
+            ..Default::default()
         }
+    }
 
-        {
-            let mut clients = self.clients.lock().await;
-            clients.remove(&(self.id, channel));
+    fn check_network_policy(
+        &self,
+        path: Option<&Path>,
+        mode: AccessMode,
+    ) -> Result<(), ProtocolError> {
+        if !self.network_policy.enable {
+            return Ok(());
+        }
+        match self.remote_addr {
+            Some(addr) => self.network_policy.check(addr.ip(), path, mode),
+            None => Err(ProtocolError::Forbidden(
+                "Unable to determine client address".to_owned(),
+            )),
         }
-        session.exit_status_request(channel, 0000)?;
-        session.close(channel)?;
-        Ok(())
     }
-}
 
-impl SshServer {
     async fn handle_upload_pack(&mut self, channel: ChannelId, data: &[u8], session: &mut Session) {
         let smart_protocol = self.smart_protocol.as_mut().unwrap();
 
@@ -224,6 +525,7 @@ impl SshServer {
     }
 
     async fn handle_receive_pack(&mut self, channel: ChannelId, session: &mut Session) {
+        let request = self.request_info();
         let smart_protocol = self.smart_protocol.as_mut().unwrap();
         let data = self.data_combined.split().freeze();
         let mut data_stream = Box::pin(stream::once(async move { Ok(data) }));
@@ -234,6 +536,7 @@ impl SshServer {
 
             if let Some(pos) = search_subsequence(&chunk, b"PACK") {
                 smart_protocol.git_receive_pack_protocol(Bytes::copy_from_slice(&chunk[..pos]));
+                check_protected_tags(smart_protocol, &request).await;
                 let remaining_bytes = Bytes::copy_from_slice(&chunk[pos..]);
                 let remaining_stream =
                     stream::once(async { Ok(remaining_bytes) }).chain(data_stream);
@@ -241,6 +544,7 @@ impl SshServer {
                     .git_receive_pack_stream(Box::pin(remaining_stream))
                     .await
                     .unwrap();
+                refresh_hierarchy(&smart_protocol.context).await;
                 break;
             }
         }
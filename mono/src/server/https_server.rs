@@ -1,16 +1,20 @@
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use anyhow::Result;
 use async_session::MemoryStore;
 use axum::body::Body;
 use axum::extract::{Query, State};
-use axum::http::{self, Request, Uri};
+use axum::http::{self, HeaderMap, Request, Uri};
+use axum::middleware;
 use axum::response::Response;
 use axum::routing::get;
+use axum::Extension;
 use axum::Router;
 use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
 use clap::Args;
 use lazy_static::lazy_static;
 use regex::Regex;
@@ -22,12 +26,16 @@ use tower_http::trace::TraceLayer;
 use ceres::protocol::{ServiceType, SmartProtocol, TransportProtocol};
 use common::errors::ProtocolError;
 use common::model::{CommonOptions, InfoRefsParams};
+use common::network::NetworkPolicy;
 use jupiter::context::Context;
 
 use crate::api::api_router::{self};
 use crate::api::lfs::lfs_router;
 use crate::api::oauth::{self, oauth_client};
 use crate::api::MonoApiServiceState;
+use crate::server::middleware::{
+    maintenance_mode, network_policy, path_redirect, request_id, ClientIp, PeerIdentity,
+};
 
 #[derive(Args, Clone, Debug)]
 pub struct HttpOptions {
@@ -80,8 +88,19 @@ pub async fn start_https(context: Context, options: HttpsOptions) {
     let config = RustlsConfig::from_pem_file(https_cert_path.to_owned(), https_key_path.to_owned())
         .await
         .unwrap();
-    axum_server::bind_rustls(addr, config)
-        .serve(app.into_make_service())
+    let listener = common::daemon::listen("https", addr).unwrap();
+    // stop accepting when a new process takes over, the connections in flight are served
+    let handle = Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            common::daemon::draining().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+    axum_server::from_tcp_rustls(listener, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
@@ -97,10 +116,15 @@ pub async fn start_http(context: Context, options: HttpOptions) {
     let server_url = format!("{}:{}", host, http_port);
 
     let addr = SocketAddr::from_str(&server_url).unwrap();
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    let listener = common::daemon::listen("http", addr).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(common::daemon::draining())
+    .await
+    .unwrap();
 }
 
 /// This is the main entry for the mono server.
@@ -116,14 +140,86 @@ pub async fn start_http(context: Context, options: HttpOptions) {
 ///   - POST       `/objects/batch`
 /// 2. The API router nested in the `/api/v1`:
 ///   - GET        `/api/v1/status`
+///   - GET        `/api/v1/health`
 ///   - POST       `/api/v1/create-file`
 ///   - GET        `/api/v1/latest-commit`
 ///   - GET        `/api/v1/tree/commit-info`
 ///   - GET        `/api/v1/tree`
+///   - GET        `/api/v1/tree/entries`
 ///   - GET        `/api/v1/blob`
+///   - POST       `/api/v1/history`
+///   - GET        `/api/v1/blame`
 ///   - GET        `/api/v1/file/blob/:object_id`
 ///   - GET        `/api/v1/file/tree`
 ///   - GET        `/api/v1/path-can-clone`
+///   - GET or POST `/api/v1/repo/visibility`
+///   - GET or POST `/api/v1/repo/branch-settings`
+///   - GET or POST `/api/v1/repo/tag-rules`
+///   - POST       `/api/v1/repo/tag-rules/{id}/delete`
+///   - GET or POST `/api/v1/repo/tags`
+///   - POST       `/api/v1/repo/tags/delete`
+///   - POST       `/api/v1/repo/rename`
+///   - POST       `/api/v1/repo/transfer`
+///   - GET        `/api/v1/maintenance/tasks`
+///   - GET        `/api/v1/maintenance/jobs`
+///   - GET        `/api/v1/maintenance/jobs/{id}`
+///   - POST       `/api/v1/maintenance/run`
+///   - GET        `/api/v1/maintenance/reports`
+///   - GET        `/api/v1/maintenance/reports/{job_id}`
+///   - GET        `/api/v1/maintenance/stale-branches`
+///   - GET or POST `/api/v1/maintenance/splits`
+///   - POST       `/api/v1/maintenance/splits/{id}/delete`
+///   - POST       `/api/v1/maintenance/policies/reload`
+///   - POST       `/api/v1/maintenance/config/reload`
+///   - GET or POST `/api/v1/maintenance/mode`
+///   - GET        `/api/v1/secret-scan/findings`
+///   - POST       `/api/v1/secret-scan/findings/{id}/review`
+///   - GET or POST `/api/v1/releases/`
+///   - GET        `/api/v1/releases/{id}`
+///   - POST       `/api/v1/releases/{id}/delete`
+///   - POST       `/api/v1/releases/{id}/assets`
+///   - GET        `/api/v1/releases/{id}/assets/{asset_id}`
+///   - POST       `/api/v1/releases/{id}/assets/{asset_id}/delete`
+///   - GET        `/api/v1/quota/usage`
+///   - GET        `/api/v1/quota/namespaces`
+///   - GET        `/api/v1/mq/schemas`
+///   - GET        `/api/v1/mq/stats`
+///   - GET        `/api/v1/mq/messages`
+///   - GET        `/api/v1/mq/messages/{id}`
+///   - DELETE     `/api/v1/mq/messages/{id}`
+///   - POST       `/api/v1/mq/purge`
+///   - GET        `/api/v1/mq/dead`
+///   - POST       `/api/v1/mq/dead/requeue`
+///   - POST       `/api/v1/mq/dead/{id}/requeue`
+///   - POST       `/api/v1/mq/replay`
+///   - GET        `/api/v1/signatures/{object_id}`
+///   - POST       `/api/v1/signatures/{object_id}/verify`
+///   - GET        `/api/v1/policies/`
+///   - GET or POST `/api/v1/policies/{namespace}`
+///   - POST       `/api/v1/policies/{namespace}/delete`
+///   - GET        `/api/v1/policies/{namespace}/versions`
+///   - POST       `/api/v1/policies/{namespace}/rollback`
+///   - POST       `/api/v1/policy-simulation`
+///   - POST       `/api/v1/entity-files/sign`
+///   - GET        `/api/v1/audit/decisions`
+///   - GET        `/api/v1/roles/`
+///   - GET or POST `/api/v1/roles/assignments`
+///   - POST       `/api/v1/roles/assignments/{id}/delete`
+///   - GET        `/api/v1/roles/effective`
+///   - GET or POST `/api/v1/break-glass/`
+///   - POST       `/api/v1/break-glass/{id}/revoke`
+///   - GET or POST `/api/v1/service-accounts/`
+///   - POST       `/api/v1/service-accounts/{name}/delete`
+///   - GET or POST `/api/v1/service-accounts/{name}/tokens`
+///   - POST       `/api/v1/service-accounts/{name}/tokens/{id}/delete`
+///   - GET        `/api/v1/features/`
+///   - GET or POST `/api/v1/features/{name}`
+///   - POST       `/api/v1/features/{name}/delete`
+///   - GET        `/api/v1/stats`
+///   - GET or POST `/api/v1/webhooks/`
+///   - POST       `/api/v1/webhooks/{id}/delete`
+///   - GET        `/api/v1/webhooks/{id}/deliveries`
+///   - POST       `/api/v1/webhooks/{id}/deliveries/{delivery}/requeue`
 /// 3. The OAuth router nested in the `/auth`:
 ///   - GET        `/auth/github`
 ///   - GET        `/auth/authorized`
@@ -147,8 +243,14 @@ pub async fn app(context: Context, host: String, port: u16, common: CommonOption
         store: Some(MemoryStore::new()),
     };
 
+    let policy = Arc::new(
+        NetworkPolicy::new(&context.config.network_policy).expect("Invalid network policy"),
+    );
+
     // add RequestDecompressionLayer for handle gzip encode
     // add TraceLayer for log record
+    // add request_id to run the request and its log records in the span of its id
+    // add maintenance_mode to reject writes while the instance is in maintenance
     // add CorsLayer to add cors header
     Router::new()
         .merge(lfs_router::routers().with_state(api_state.clone()))
@@ -165,21 +267,34 @@ pub async fn app(context: Context, host: String, port: u16, common: CommonOption
                 http::header::CONTENT_TYPE,
             ])),
         )
+        .layer(middleware::from_fn_with_state(
+            context.clone(),
+            maintenance_mode,
+        ))
+        .layer(middleware::from_fn_with_state(
+            context.clone(),
+            path_redirect,
+        ))
+        .layer(middleware::from_fn_with_state(policy, network_policy))
         .layer(TraceLayer::new_for_http())
+        .layer(middleware::from_fn(request_id))
         .layer(RequestDecompressionLayer::new())
         .with_state(state)
 }
 
 lazy_static! {
     /// The following regular expressions are used to match the Git server protocol.
-    static ref INFO_REFS_REGEX: Regex = Regex::new(r"/info/refs$").unwrap();
-    static ref REGEX_GIT_UPLOAD_PACK: Regex = Regex::new(r"/git-upload-pack$").unwrap();
-    static ref REGEX_GIT_RECEIVE_PACK: Regex = Regex::new(r"/git-receive-pack$").unwrap();
+    pub static ref INFO_REFS_REGEX: Regex = Regex::new(r"/info/refs$").unwrap();
+    pub static ref REGEX_GIT_UPLOAD_PACK: Regex = Regex::new(r"/git-upload-pack$").unwrap();
+    pub static ref REGEX_GIT_RECEIVE_PACK: Regex = Regex::new(r"/git-receive-pack$").unwrap();
 }
 
 pub async fn get_method_router(
     state: State<AppState>,
     Query(params): Query<InfoRefsParams>,
+    headers: HeaderMap,
+    client_ip: Option<Extension<ClientIp>>,
+    peer: Option<Extension<PeerIdentity>>,
     uri: Uri,
 ) -> Result<Response<Body>, ProtocolError> {
     if INFO_REFS_REGEX.is_match(uri.path()) {
@@ -188,7 +303,10 @@ pub async fn get_method_router(
             state.context.clone(),
             TransportProtocol::Http,
         );
-        crate::git_protocol::http::git_info_refs(params, pack_protocol).await
+        let client_ip = client_ip.map(|Extension(ClientIp(ip))| ip);
+        let peer = peer.as_ref().map(|Extension(peer)| peer);
+        crate::git_protocol::http::git_info_refs(params, &headers, client_ip, peer, pack_protocol)
+            .await
     } else {
         Err(ProtocolError::NotFound(
             "Operation not supported".to_owned(),
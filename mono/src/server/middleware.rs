@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Query, State};
+use axum::http::{header, HeaderValue, Method, Request, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Instrument;
+
+use ceres::maintenance::mode;
+use common::errors::ProtocolError;
+use common::log::{self, REQUEST_ID_HEADER};
+use common::network::{AccessMode, NetworkPolicy};
+use jupiter::context::Context;
+
+use crate::server::https_server::{
+    remove_git_suffix, INFO_REFS_REGEX, REGEX_GIT_RECEIVE_PACK, REGEX_GIT_UPLOAD_PACK,
+};
+
+/// Address of the client, added to the extensions of every request by [`network_policy`].
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// The ztm peer which sent a request through its encrypted channel, added to the extensions by
+/// the gateway. Peers send no credentials, they are authorized as the principal they are mapped
+/// to, if any, and as anonymous users otherwise.
+#[derive(Clone, Debug)]
+pub struct PeerIdentity {
+    pub peer_id: String,
+    pub principal: Option<String>,
+}
+
+/// Reject requests whose client address is not permitted by the configured network policy.
+///
+/// The server must be started with `into_make_service_with_connect_info::<SocketAddr>`
+/// so that the peer address is available, otherwise only `X-Forwarded-For` can be used.
+pub async fn network_policy(
+    State(policy): State<Arc<NetworkPolicy>>,
+    mut req: Request<Body>,
+    next: Next,
+) -> Result<Response, ProtocolError> {
+    let ip = client_ip(&req, policy.trust_forwarded_for);
+    if let Some(ip) = ip {
+        req.extensions_mut().insert(ClientIp(ip));
+    }
+    if !policy.enable {
+        return Ok(next.run(req).await);
+    }
+    let ip = ip
+        .ok_or_else(|| ProtocolError::Forbidden("Unable to determine client address".to_owned()))?;
+    let (path, mode) = classify_request(&req);
+    policy.check(ip, path.as_deref(), mode)?;
+    Ok(next.run(req).await)
+}
+
+/// Run the request in a span with its request id, so every line logged for it carries the id.
+///
+/// The id sent in `X-Request-Id` is used if there is one, it is passed on to the handlers in the
+/// same header and returned to the client. A trace the client sent in `traceparent` is continued.
+pub async fn request_id(mut req: Request<Body>, next: Next) -> Response {
+    let received = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok());
+    let id = log::request_id(received);
+    let value = HeaderValue::from_str(&id).unwrap();
+    req.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+    let span = log::request_span("http", &id);
+    log::continue_trace(&span, req.headers());
+    let mut res = next.run(req).instrument(span).await;
+    res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    res
+}
+
+/// Reject requests which change something while the instance is in maintenance mode, with the
+/// message of the mode.
+pub async fn maintenance_mode(
+    State(context): State<Context>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, ProtocolError> {
+    if is_write(req.method(), req.uri().path()) {
+        if let Some(mode) = mode::current(&context).await {
+            return Err(ProtocolError::Unavailable(mode.message));
+        }
+    }
+    Ok(next.run(req).await)
+}
+
+/// Whether a request may change something and is rejected in maintenance mode.
+///
+/// Pushes are left to the protocol, which rejects them with a message git shows. The
+/// maintenance api stays available to end the mode, and so does signing in. Api paths may
+/// come without their `/api/v1` prefix, as behind the gateway.
+fn is_write(method: &Method, path: &str) -> bool {
+    // posts which only read
+    const READS: [&str; 7] = [
+        "/git-upload-pack",
+        "/git-receive-pack",
+        "/objects/batch",
+        "/verify",
+        "/list",
+        "/history",
+        "/policy-simulation",
+    ];
+    if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return false;
+    }
+    let path = path.strip_prefix("/api/v1").unwrap_or(path);
+    !(path.starts_with("/maintenance/")
+        || path.starts_with("/auth/")
+        || READS.iter().any(|r| path.ends_with(r)))
+}
+
+/// Redirect requests for a renamed repository to its new path while the redirect is alive.
+///
+/// Reads are answered with `301`, other methods with `308` so that the body is sent again.
+pub async fn path_redirect(
+    State(context): State<Context>,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    let (path, _) = classify_request(&req);
+    if let Some(path) = path {
+        let new_path = context
+            .services
+            .mono_storage
+            .find_redirect(&path)
+            .await
+            .unwrap_or_default();
+        if let Some(new_path) = new_path {
+            let status = match *req.method() {
+                Method::GET | Method::HEAD => StatusCode::MOVED_PERMANENTLY,
+                _ => StatusCode::PERMANENT_REDIRECT,
+            };
+            let location = redirect_location(req.uri(), path.to_str().unwrap(), &new_path);
+            tracing::info!("redirect {} to {}", req.uri(), location);
+            return Response::builder()
+                .status(status)
+                .header(header::LOCATION, location)
+                .body(Body::empty())
+                .unwrap();
+        }
+    }
+    next.run(req).await
+}
+
+/// Replace the repository path either in the url path (git protocol) or in the `path` query parameter (api).
+fn redirect_location(uri: &Uri, old_path: &str, new_path: &str) -> String {
+    if let Some(rest) = uri.path().strip_prefix(old_path) {
+        return match uri.query() {
+            Some(query) => format!("{}{}?{}", new_path, rest, query),
+            None => format!("{}{}", new_path, rest),
+        };
+    }
+    let query = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some(("path", _)) => format!("path={}", new_path),
+            _ => pair.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{}?{}", uri.path(), query)
+}
+
+/// Address of the client of `req`. Behind a reverse proxy it is the last address of
+/// `X-Forwarded-For`, the one the proxy added, the addresses before it are sent by the client.
+pub fn client_ip(req: &Request<Body>, trust_forwarded_for: bool) -> Option<IpAddr> {
+    if trust_forwarded_for {
+        let forwarded = req
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|v| v.trim().parse().ok());
+        if forwarded.is_some() {
+            return forwarded;
+        }
+    }
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Work out which repository path a request targets and whether it reads or writes.
+///
+/// Git smart protocol requests carry the repository in the url, api requests
+/// usually carry it in the `path` query parameter. The body is not read, api requests
+/// sending the path in it target no repository, only the instance-wide network rules
+/// apply to them.
+fn classify_request(req: &Request<Body>) -> (Option<PathBuf>, AccessMode) {
+    let uri = req.uri().clone();
+    let uri_path = uri.path();
+    let query = Query::<HashMap<String, String>>::try_from_uri(&uri)
+        .map(|Query(q)| q)
+        .unwrap_or_default();
+
+    if REGEX_GIT_RECEIVE_PACK.is_match(uri_path) {
+        return (
+            Some(remove_git_suffix(uri, "/git-receive-pack")),
+            AccessMode::Write,
+        );
+    }
+    if REGEX_GIT_UPLOAD_PACK.is_match(uri_path) {
+        return (
+            Some(remove_git_suffix(uri, "/git-upload-pack")),
+            AccessMode::Read,
+        );
+    }
+    if INFO_REFS_REGEX.is_match(uri_path) {
+        let mode = match query.get("service").map(String::as_str) {
+            Some("git-receive-pack") => AccessMode::Write,
+            _ => AccessMode::Read,
+        };
+        return (Some(remove_git_suffix(uri, "/info/refs")), mode);
+    }
+
+    let path = query.get("path").map(PathBuf::from);
+    let mode = match *req.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => AccessMode::Read,
+        // lfs batch only negotiates transfers, actual uploads are PUT requests
+        Method::POST if uri_path.ends_with("/objects/batch") => AccessMode::Read,
+        _ => AccessMode::Write,
+    };
+    (path, mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use common::config::{NetworkPolicyConfig, NetworkRule, RepoNetworkRule};
+    use common::network::{AccessMode, NetworkPolicy};
+
+    use super::{classify_request, client_ip, is_write, redirect_location};
+
+    #[test]
+    fn test_client_ip() {
+        // the client sends a forged address, the proxy appends the one it saw
+        let req = Request::get("/")
+            .header("x-forwarded-for", "10.0.0.1, 203.0.113.7")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(client_ip(&req, true), Some("203.0.113.7".parse().unwrap()));
+        // the header is ignored unless the proxy is trusted
+        assert_eq!(client_ip(&req, false), None);
+    }
+
+    #[test]
+    fn test_classify_request() {
+        let req = Request::get("/project/mega.git/info/refs?service=git-receive-pack")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            classify_request(&req),
+            (Some(PathBuf::from("/project/mega")), AccessMode::Write)
+        );
+
+        let req = Request::post("/project/mega.git/git-upload-pack")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            classify_request(&req),
+            (Some(PathBuf::from("/project/mega")), AccessMode::Read)
+        );
+
+        let req = Request::post("/api/v1/create-file")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(classify_request(&req), (None, AccessMode::Write));
+
+        let req = Request::get("/api/v1/tree?path=/project")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            classify_request(&req),
+            (Some(PathBuf::from("/project")), AccessMode::Read)
+        );
+    }
+
+    #[test]
+    fn test_repo_rules_cover_path_requests_only() {
+        let config = NetworkPolicyConfig {
+            enable: true,
+            repos: vec![RepoNetworkRule {
+                path: "/project/secret".to_owned(),
+                write: NetworkRule {
+                    allow: vec!["10.1.0.0/16".to_owned()],
+                    deny: vec![],
+                },
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let policy = NetworkPolicy::new(&config).unwrap();
+        let ip = "8.8.8.8".parse().unwrap();
+        let check = |req: Request<Body>| {
+            let (path, mode) = classify_request(&req);
+            policy.check(ip, path.as_deref(), mode)
+        };
+
+        let push = Request::post("/project/secret.git/git-receive-pack")
+            .body(Body::empty())
+            .unwrap();
+        assert!(check(push).is_err());
+        let query = Request::post("/api/v1/branch-setting?path=/project/secret")
+            .body(Body::empty())
+            .unwrap();
+        assert!(check(query).is_err());
+        // the path in the body is not looked at
+        let body = Request::post("/api/v1/create-file")
+            .body(Body::from(r#"{"path":"/project/secret","name":"a"}"#))
+            .unwrap();
+        assert!(check(body).is_ok());
+    }
+
+    #[test]
+    fn test_is_write() {
+        assert!(is_write(&Method::POST, "/api/v1/mr/42/merge"));
+        assert!(is_write(&Method::POST, "/api/v1/issue/comment/7"));
+        assert!(is_write(&Method::PUT, "/objects/abc"));
+        assert!(!is_write(&Method::GET, "/api/v1/tree"));
+        assert!(!is_write(&Method::POST, "/api/v1/history"));
+        assert!(!is_write(&Method::POST, "/api/v1/mr/list"));
+        assert!(!is_write(
+            &Method::POST,
+            "/project/mega.git/git-upload-pack"
+        ));
+        assert!(!is_write(&Method::POST, "/objects/batch"));
+        assert!(!is_write(&Method::POST, "/api/v1/maintenance/mode"));
+        assert!(!is_write(&Method::POST, "/maintenance/mode"));
+    }
+
+    #[test]
+    fn test_redirect_location() {
+        let uri = "/project/old.git/info/refs?service=git-upload-pack"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            redirect_location(&uri, "/project/old", "/project/new"),
+            "/project/new.git/info/refs?service=git-upload-pack"
+        );
+
+        let uri = "/api/v1/tree?path=/project/old/src&refs=main"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            redirect_location(&uri, "/project/old/src", "/project/new/src"),
+            "/api/v1/tree?path=/project/new/src&refs=main"
+        );
+    }
+}
@@ -1,2 +1,3 @@
 pub mod https_server;
+pub mod middleware;
 pub mod ssh_server;
\ No newline at end of file
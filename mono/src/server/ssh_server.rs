@@ -11,11 +11,12 @@ use russh::{server::Server, Preferred};
 use russh_keys::{ssh_key::rand_core::OsRng, PrivateKey};
 
 use common::model::CommonOptions;
+use common::network::{AuthLimiter, NetworkPolicy};
 use jupiter::context::Context;
 use tokio::sync::Mutex;
 use vault::vault::{read_secret, write_secret};
 
-use crate::git_protocol::ssh::SshServer;
+use crate::git_protocol::ssh::{auth_methods, SshServer};
 
 #[derive(Args, Clone, Debug)]
 pub struct SshOptions {
@@ -29,7 +30,7 @@ pub struct SshOptions {
 #[derive(Args, Clone, Debug)]
 pub struct SshCustom {
     #[arg(long, default_value_t = 2222)]
-    ssh_port: u16,
+    pub ssh_port: u16,
 }
 
 /// start a ssh server
@@ -44,6 +45,7 @@ pub async fn start_server(context: Context, command: &SshOptions) {
             ..Preferred::default()
         },
         auth_rejection_time_initial: Some(std::time::Duration::from_secs(0)),
+        methods: auth_methods(&context.config.ssh),
         ..Default::default()
     };
 
@@ -53,16 +55,42 @@ pub async fn start_server(context: Context, command: &SshOptions) {
         common: CommonOptions { host, .. },
         custom: SshCustom { ssh_port },
     } = command;
+    let network_policy = Arc::new(
+        NetworkPolicy::new(&context.config.network_policy).expect("Invalid network policy"),
+    );
+    let auth_limiter = Arc::new(AuthLimiter::new(&context.config.ssh));
     let mut ssh_server = SshServer {
         clients: Arc::new(Mutex::new(HashMap::new())),
         id: 0,
         context,
         smart_protocol: None,
         data_combined: BytesMut::new(),
+        network_policy,
+        auth_limiter,
+        remote_addr: None,
+        username: None,
+        auth_method: None,
+        request_id: String::new(),
     };
     let server_url = format!("{}:{}", host, ssh_port);
     let addr = SocketAddr::from_str(&server_url).unwrap();
-    ssh_server.run_on_address(ru_config, addr).await.unwrap();
+    let clients = ssh_server.clients.clone();
+    let listener = common::daemon::listen("ssh", addr).unwrap();
+    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+    let draining = tokio::select! {
+        res = ssh_server.run_on_socket(ru_config, &listener) => {
+            res.unwrap();
+            false
+        }
+        _ = common::daemon::draining() => true,
+    };
+    if draining {
+        // stop accepting when a new process takes over, and wait for the transfers in flight
+        drop(listener);
+        while !clients.lock().await.is_empty() {
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        }
+    }
 }
 
 pub fn load_key() -> PrivateKey {
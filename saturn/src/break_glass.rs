@@ -0,0 +1,52 @@
+//! Break-glass access, temporary access an instance admin grants themselves in an emergency.
+//!
+//! Grants are turned into permit policies kept apart from the other policies as the namespace
+//! [`BREAK_GLASS_NAMESPACE`]. They are only evaluated when the other policies deny a request,
+//! so a grant also overrides `forbid` policies, and decisions made by them are marked as
+//! break-glass decisions. Every grant permits its user everything on its path and below it up
+//! to its expiry, which is checked against `context.time` so an expired grant no longer
+//! applies even before it is removed from the policies.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::role::{directory_uid, entity_uid};
+
+/// Namespace of the policies generated from the grants, it can't be managed through the API
+/// as it is not a valid namespace name there.
+pub const BREAK_GLASS_NAMESPACE: &str = "@break_glass";
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BreakGlassGrant {
+    pub user: String,
+    /// Directory or repository the grant applies to, with everything below it
+    pub path: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl BreakGlassGrant {
+    /// Longest time a grant may last.
+    pub const MAX_DURATION: Duration = Duration::hours(4);
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        now < self.expires_at
+    }
+
+    fn policy(&self) -> String {
+        format!(
+            "permit (\n    principal == {},\n    action,\n    resource in {}\n)\nwhen {{ context has time && context.time < {} }};\n",
+            entity_uid("User", &self.user),
+            directory_uid(&self.path),
+            self.expires_at.timestamp()
+        )
+    }
+}
+
+/// The Cedar policies of the grants in `grants` which are active at `now`.
+pub fn policies(grants: &[BreakGlassGrant], now: DateTime<Utc>) -> String {
+    grants
+        .iter()
+        .filter(|g| g.is_active(now))
+        .map(|g| g.policy())
+        .collect()
+}
@@ -1,17 +1,22 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
 use cedar_policy::{
-    Authorizer, CedarSchemaError, Context, Decision, Diagnostics, ParseErrors, PolicySet,
-    PolicySetError, Request, Schema, SchemaError, ValidationMode, Validator,
+    Authorizer, CedarSchemaError, Context, Decision, Diagnostics, Entities, ParseErrors, PolicyId,
+    PolicySetError, Request, Response, SchemaError,
 };
-use itertools::Itertools;
 use thiserror::Error;
 
-use crate::{entitystore::EntityStore, util::EntityUid};
+use crate::{entitystore::EntityStore, policy::PolicyBundle, util::EntityUid, ActionEnum};
+
+/// Id of the decisions denying requests which cross tenants, see
+/// [`EntityStore::crosses_tenants`].
+pub const TENANT_ISOLATION: &str = "tenantIsolation";
 
 pub struct CedarContext {
     pub entities: EntityStore,
     authorizer: Authorizer,
-    policies: PolicySet,
-    schema: Schema,
+    policies: Arc<PolicyBundle>,
 }
 
 #[allow(dead_code)]
@@ -43,33 +48,19 @@ pub enum Error {
 }
 
 impl CedarContext {
-    pub fn new(
-        entities: EntityStore,
-    ) -> Result<Self, ContextError> {
-        let schema_content = include_str!("../mega.cedarschema");
-        let policy_content = include_str!("../mega_policies.cedar");
-        let (schema, _) = Schema::from_cedarschema_str(schema_content).unwrap();
-        let policies = policy_content.parse()?;
-        let validator = Validator::new(schema.clone());
-        let output = validator.validate(&policies, ValidationMode::default());
-
-        if output.validation_passed() {
-            tracing::info!("All policy validation passed!");
-            let authorizer = Authorizer::new();
-            let c = Self {
-                entities,
-                authorizer,
-                policies,
-                schema,
-            };
-
-            Ok(c)
-        } else {
-            let error_string = output
-                .validation_errors()
-                .map(|err| format!("{err}"))
-                .join("\n");
-            Err(ContextError::Validation(error_string))
+    /// Context with the built-in schema and policies.
+    pub fn new(entities: EntityStore) -> Result<Self, ContextError> {
+        let policies = PolicyBundle::builtin()?;
+        tracing::info!("All policy validation passed!");
+        Ok(Self::with_policies(entities, Arc::new(policies)))
+    }
+
+    /// Context with `policies`, usually the current ones of a [`PolicyStore`](crate::policy::PolicyStore).
+    pub fn with_policies(entities: EntityStore, policies: Arc<PolicyBundle>) -> Self {
+        Self {
+            entities,
+            authorizer: Authorizer::new(),
+            policies,
         }
     }
 
@@ -80,13 +71,81 @@ impl CedarContext {
         resource: impl AsRef<EntityUid>,
         context: Context,
     ) -> Result<(), Error> {
-        let es = self.entities.as_entities(&self.schema);
+        self.authorize(principal, action, resource, context)?
+            .into_result()
+    }
+
+    /// Like [`CedarContext::is_authorized`], also telling which policies decided.
+    #[tracing::instrument(skip_all)]
+    pub fn authorize(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+        context: Context,
+    ) -> Result<AuthDecision, Error> {
+        let es = self.entities.as_entities(Some(&self.policies.schema));
+        self.decide(&es, principal, action, resource, context)
+    }
+
+    /// The ones of `actions` which `principal` may do on `resource`.
+    #[tracing::instrument(skip_all)]
+    pub fn allowed_actions(
+        &self,
+        principal: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+        actions: &[ActionEnum],
+        context: Context,
+    ) -> Result<Vec<ActionEnum>, Error> {
+        let es = self.entities.as_entities(Some(&self.policies.schema));
+        let mut allowed = Vec::new();
+        for action in actions {
+            let euid: EntityUid = format!(r#"Action::"{}""#, action).parse().unwrap();
+            let decision = self.decide(
+                &es,
+                principal.as_ref(),
+                euid,
+                resource.as_ref(),
+                context.clone(),
+            )?;
+            if decision.is_allowed() {
+                allowed.push(*action);
+            }
+        }
+        Ok(allowed)
+    }
+
+    fn decide(
+        &self,
+        es: &Entities,
+        principal: impl AsRef<EntityUid>,
+        action: impl AsRef<EntityUid>,
+        resource: impl AsRef<EntityUid>,
+        context: Context,
+    ) -> Result<AuthDecision, Error> {
+        // tenants are isolated whatever the policies and break-glass grants allow
+        if self
+            .entities
+            .crosses_tenants(principal.as_ref(), resource.as_ref())
+        {
+            tracing::info!(
+                "tenant isolation denied: principal: {}, action: {}, resource: {}",
+                principal.as_ref(),
+                action.as_ref(),
+                resource.as_ref()
+            );
+            let reason = HashSet::from([PolicyId::new(TENANT_ISOLATION)]);
+            return Ok(AuthDecision {
+                response: Response::new(Decision::Deny, reason, Vec::new()),
+                break_glass: false,
+            });
+        }
         let q = Request::new(
             principal.as_ref().clone().into(),
             action.as_ref().clone().into(),
             resource.as_ref().clone().into(),
             context,
-            Some(&self.schema),
+            Some(&self.policies.schema),
         )
         .map_err(|e| Error::Request(e.to_string()))?;
         tracing::info!(
@@ -95,11 +154,76 @@ impl CedarContext {
             action.as_ref(),
             resource.as_ref()
         );
-        let response = self.authorizer.is_authorized(&q, &self.policies, &es);
+        let response = self
+            .authorizer
+            .is_authorized(&q, &self.policies.policies, es);
         tracing::info!("Auth response: {:?}", response);
-        match response.decision() {
+        if response.decision() == Decision::Deny
+            && self.policies.break_glass.policies().next().is_some()
+        {
+            let response = self
+                .authorizer
+                .is_authorized(&q, &self.policies.break_glass, es);
+            if response.decision() == Decision::Allow {
+                tracing::warn!(
+                    "break-glass access: principal: {}, action: {}, resource: {}",
+                    principal.as_ref(),
+                    action.as_ref(),
+                    resource.as_ref()
+                );
+                return Ok(AuthDecision {
+                    response,
+                    break_glass: true,
+                });
+            }
+        }
+        Ok(AuthDecision {
+            response,
+            break_glass: false,
+        })
+    }
+}
+
+/// The decision on a request with the diagnostics explaining it.
+#[derive(Debug, Clone)]
+pub struct AuthDecision {
+    response: Response,
+    break_glass: bool,
+}
+
+impl AuthDecision {
+    pub fn is_allowed(&self) -> bool {
+        self.response.decision() == Decision::Allow
+    }
+
+    /// Whether the request was denied by the other policies and allowed by a break-glass
+    /// grant, see [`crate::break_glass`].
+    pub fn is_break_glass(&self) -> bool {
+        self.break_glass
+    }
+
+    /// Ids of the policies which decided, empty for requests denied as nothing permits them.
+    pub fn policies(&self) -> Vec<String> {
+        self.response
+            .diagnostics()
+            .reason()
+            .map(|id| id.to_string())
+            .collect()
+    }
+
+    /// Errors evaluating policies, those policies were ignored.
+    pub fn errors(&self) -> Vec<String> {
+        self.response
+            .diagnostics()
+            .errors()
+            .map(|err| err.to_string())
+            .collect()
+    }
+
+    pub fn into_result(self) -> Result<(), Error> {
+        match self.response.decision() {
             Decision::Allow => Ok(()),
-            Decision::Deny => Err(Error::AuthDenied(response.diagnostics().clone())),
+            Decision::Deny => Err(Error::AuthDenied(self.response.diagnostics().clone())),
         }
     }
 }
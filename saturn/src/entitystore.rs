@@ -1,21 +1,32 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
 use cedar_policy::{Entities, Schema};
 use serde::{Deserialize, Serialize};
-use serde_json::{ json, to_string_pretty};
+use serde_json::{json, to_string_pretty};
 
 use crate::{
-    objects::{Issue, MergeRequest, Repo, User, UserGroup},
+    objects::{Directory, Issue, MergeRequest, Organization, Repo, Team, User, UserGroup},
+    role::{directory_uid, entity_uid},
     util::EntityUid,
 };
 
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 pub struct EntityStore {
     users: HashMap<EntityUid, User>,
     repos: HashMap<EntityUid, Repo>,
     merge_requests: HashMap<EntityUid, MergeRequest>,
     issues: HashMap<EntityUid, Issue>,
     user_groups: HashMap<EntityUid, UserGroup>,
+    #[serde(default)]
+    organizations: HashMap<EntityUid, Organization>,
+    #[serde(default)]
+    teams: HashMap<EntityUid, Team>,
+    #[serde(default)]
+    directories: HashMap<EntityUid, Directory>,
+    /// Tenants of users and repositories, see [`EntityStore::crosses_tenants`]
+    #[serde(default)]
+    tenants: HashMap<EntityUid, String>,
 }
 
 impl EntityStore {
@@ -26,22 +37,32 @@ impl EntityStore {
             merge_requests: HashMap::new(),
             issues: HashMap::new(),
             user_groups: HashMap::new(),
+            organizations: HashMap::new(),
+            teams: HashMap::new(),
+            directories: HashMap::new(),
+            tenants: HashMap::new(),
         }
     }
 
-    #[allow(dead_code)]
-    pub fn as_entities(&self, schema: &Schema) -> Entities {
+    /// The entities for Cedar, validated against `schema` if given.
+    pub fn as_entities(&self, schema: Option<&Schema>) -> Entities {
         let users = self.users.values().map(|user| user.clone().into());
         let repos = self.repos.values().map(|repo| repo.clone().into());
         let merge_requests = self.merge_requests.values().map(|user| user.clone().into());
         let issues = self.issues.values().map(|repo| repo.clone().into());
         let user_groups = self.user_groups.values().map(|group| group.clone().into());
+        let organizations = self.organizations.values().map(|org| org.clone().into());
+        let teams = self.teams.values().map(|team| team.clone().into());
+        let directories = self.directories.values().map(|dir| dir.clone().into());
         let all = users
             .chain(repos)
             .chain(user_groups)
+            .chain(organizations)
+            .chain(teams)
+            .chain(directories)
             .chain(merge_requests)
             .chain(issues);
-        Entities::from_entities(all, Some(schema)).unwrap()
+        Entities::from_entities(all, schema).unwrap()
     }
 
     pub fn merge(&mut self, other: EntityStore) {
@@ -50,7 +71,256 @@ impl EntityStore {
         self.merge_requests.extend(other.merge_requests);
         self.issues.extend(other.issues);
         self.user_groups.extend(other.user_groups);
+        self.organizations.extend(other.organizations);
+        self.teams.extend(other.teams);
+        self.directories.extend(other.directories);
+        self.tenants.extend(other.tenants);
     }
+
+    /// Add the members and teams of the organization which owns `repo`.
+    ///
+    /// Every team is in the admin/maintainer/reader group of the repository according to
+    /// its permission, so members of a child team inherit the permissions of all parents.
+    /// Members of the organization can read the repository, its owners are admins.
+    pub fn add_org(&mut self, repo: &str, org: &OrgEntities) {
+        let (admins, maintainers, readers) = self.repo_groups(&repo_uid(repo));
+        let role_group = |permission: &RepoPermission| match permission {
+            RepoPermission::Admin => admins.clone(),
+            RepoPermission::Maintainer => maintainers.clone(),
+            RepoPermission::Reader => readers.clone(),
+        };
+
+        self.add_org_groups(org);
+        self.organizations
+            .get_mut(&org_uid(&org.name))
+            .unwrap()
+            .add_parent(readers.clone());
+        for owner in &org.owners {
+            self.add_user_parent(owner, admins.clone());
+        }
+        for team in &org.teams {
+            self.teams
+                .get_mut(&team_uid(&org.name, &team.name))
+                .unwrap()
+                .add_parent(role_group(&team.permission));
+        }
+    }
+
+    /// Add the organization with its teams and their members, without any permission on a
+    /// repository, so policies can grant permissions to `principal in Organization::"org"`
+    /// or `principal in Team::"org/team"`.
+    ///
+    /// A team is in its organization and in its parent team, users are in the organization
+    /// and in the teams they are members of.
+    pub fn add_org_groups(&mut self, org: &OrgEntities) {
+        let org_uid = org_uid(&org.name);
+        self.organizations
+            .entry(org_uid.clone())
+            .or_insert_with(|| Organization::new(org_uid.clone()));
+        for user in org.owners.iter().chain(&org.members) {
+            self.add_user_parent(user, org_uid.clone());
+        }
+        for team in &org.teams {
+            let euid = team_uid(&org.name, &team.name);
+            let entry = self
+                .teams
+                .entry(euid.clone())
+                .or_insert_with(|| Team::new(euid.clone()));
+            entry.add_parent(org_uid.clone());
+            if let Some(parent) = &team.parent {
+                entry.add_parent(team_uid(&org.name, parent));
+            }
+            for member in &team.members {
+                self.add_user_parent(member, euid.clone());
+            }
+        }
+    }
+
+    /// Make `user` an admin of `repo`, used for repositories owned by a single user.
+    pub fn add_repo_admin(&mut self, repo: &str, user: &str) {
+        let (admins, _, _) = self.repo_groups(&repo_uid(repo));
+        self.add_user_parent(user, admins);
+    }
+
+    /// Set the visibility attributes of `repo`, the repository entity is created
+    /// with the default groups if no entity file defines it.
+    pub fn set_repo_visibility(&mut self, repo: &str, is_private: bool, is_internal: bool) {
+        let euid = repo_uid(repo);
+        let (admins, maintainers, readers) = self.repo_groups(&euid);
+        self.repos
+            .entry(euid.clone())
+            .or_insert_with(|| Repo::new(euid, admins, maintainers, readers))
+            .set_visibility(is_private, is_internal);
+    }
+
+    /// Put `repo` in the directories of its path, role assignments on a directory apply to
+    /// the repositories below it.
+    pub fn add_repo_directories(&mut self, repo: &str) {
+        let mut directories: Vec<String> = Path::new(repo)
+            .ancestors()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .collect();
+        directories.reverse();
+        self.set_repo_directories(repo, &directories);
+    }
+
+    /// Put `repo` in the last of `directories`, each of them in the one before, see
+    /// [`crate::hierarchy`]. Directories and parents of the repository declared by entity files
+    /// are replaced.
+    pub fn set_repo_directories(&mut self, repo: &str, directories: &[String]) {
+        self.directories.clear();
+        let mut parent: Option<EntityUid> = None;
+        for dir in directories {
+            let euid = directory_uid(dir);
+            self.directories.insert(
+                euid.clone(),
+                Directory::new(euid.clone(), parent.iter().cloned().collect()),
+            );
+            parent = Some(euid);
+        }
+        let euid = repo_uid(repo);
+        let (admins, maintainers, readers) = self.repo_groups(&euid);
+        self.repos
+            .entry(euid.clone())
+            .or_insert_with(|| Repo::new(euid, admins, maintainers, readers))
+            .set_parents(parent.into_iter().collect());
+    }
+
+    /// Put the user `user` in `tenant`, users not put in any tenant belong to the instance.
+    pub fn set_user_tenant(&mut self, user: &str, tenant: &str) {
+        let euid: EntityUid = format!(r#"User::"{}""#, user).parse().unwrap();
+        self.tenants.insert(euid, tenant.to_owned());
+    }
+
+    /// Put the repository `repo` in `tenant`.
+    pub fn set_repo_tenant(&mut self, repo: &str, tenant: &str) {
+        self.tenants.insert(repo_uid(repo), tenant.to_owned());
+    }
+
+    /// Whether a request of `principal` on `resource` crosses tenants: users of a tenant only
+    /// reach the repositories of their tenant, and users of the instance those of no tenant.
+    pub fn crosses_tenants(&self, principal: &EntityUid, resource: &EntityUid) -> bool {
+        self.tenants.get(principal) != self.tenants.get(resource)
+    }
+
+    /// Only the entities deciding the requests of `principal` on `resource`: the principal
+    /// with the groups, organizations and teams it is in, and the resource with its directories.
+    /// Nothing is left about anyone else, so they can be handed to the principal.
+    pub fn slice(&self, principal: &EntityUid, resource: &EntityUid) -> EntityStore {
+        let mut keep = self.ancestors(principal);
+        keep.insert(principal.clone());
+        keep.extend(self.ancestors(resource));
+        keep.insert(resource.clone());
+        EntityStore {
+            users: pick(&self.users, &keep),
+            repos: pick(&self.repos, &keep),
+            merge_requests: HashMap::new(),
+            issues: HashMap::new(),
+            user_groups: pick(&self.user_groups, &keep),
+            organizations: pick(&self.organizations, &keep),
+            teams: pick(&self.teams, &keep),
+            directories: pick(&self.directories, &keep),
+            tenants: pick(&self.tenants, &keep),
+        }
+    }
+
+    /// The groups, organizations and teams `euid` is in, or the directories for repositories,
+    /// directly or through others.
+    pub fn ancestors(&self, euid: &EntityUid) -> HashSet<EntityUid> {
+        let mut ancestors = HashSet::new();
+        let mut pending = vec![euid.clone()];
+        while let Some(euid) = pending.pop() {
+            let parents = self
+                .users
+                .get(&euid)
+                .map(|e| e.parents())
+                .or_else(|| self.user_groups.get(&euid).map(|e| e.parents()))
+                .or_else(|| self.organizations.get(&euid).map(|e| e.parents()))
+                .or_else(|| self.teams.get(&euid).map(|e| e.parents()))
+                .or_else(|| self.repos.get(&euid).map(|e| e.parents()))
+                .or_else(|| self.directories.get(&euid).map(|e| e.parents()));
+            for parent in parents.into_iter().flatten() {
+                if ancestors.insert(parent.clone()) {
+                    pending.push(parent.clone());
+                }
+            }
+        }
+        ancestors
+    }
+
+    fn repo_groups(&self, repo: &EntityUid) -> (EntityUid, EntityUid, EntityUid) {
+        match self.repos.get(repo) {
+            Some(repo) => {
+                let (a, m, r) = repo.groups();
+                (a.clone(), m.clone(), r.clone())
+            }
+            None => (
+                group_uid("admin"),
+                group_uid("matainer"),
+                group_uid("reader"),
+            ),
+        }
+    }
+
+    fn add_user_parent(&mut self, user: &str, parent: EntityUid) {
+        let euid: EntityUid = format!(r#"User::"{}""#, user).parse().unwrap();
+        self.users
+            .entry(euid.clone())
+            .or_insert_with(|| User::new(euid))
+            .add_parent(parent);
+    }
+}
+
+fn pick<T: Clone>(
+    entities: &HashMap<EntityUid, T>,
+    keep: &HashSet<EntityUid>,
+) -> HashMap<EntityUid, T> {
+    entities
+        .iter()
+        .filter(|(euid, _)| keep.contains(euid))
+        .map(|(euid, entity)| (euid.clone(), entity.clone()))
+        .collect()
+}
+
+fn repo_uid(repo: &str) -> EntityUid {
+    entity_uid("Repository", repo)
+}
+
+fn group_uid(name: &str) -> EntityUid {
+    format!(r#"UserGroup::"{}""#, name).parse().unwrap()
+}
+
+fn org_uid(name: &str) -> EntityUid {
+    format!(r#"Organization::"{}""#, name).parse().unwrap()
+}
+
+fn team_uid(org: &str, team: &str) -> EntityUid {
+    format!(r#"Team::"{}/{}""#, org, team).parse().unwrap()
+}
+
+/// Permission a team grants on the repositories owned by its organization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoPermission {
+    Admin,
+    Maintainer,
+    Reader,
+}
+
+/// Organization membership as stored in the database, users are referenced by name.
+#[derive(Debug, Clone, Default)]
+pub struct OrgEntities {
+    pub name: String,
+    pub owners: Vec<String>,
+    pub members: Vec<String>,
+    pub teams: Vec<TeamEntities>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TeamEntities {
+    pub name: String,
+    pub parent: Option<String>,
+    pub permission: RepoPermission,
+    pub members: Vec<String>,
 }
 
 pub fn generate_entity(user: &str, repo: &str) -> Result<String, Box<dyn std::error::Error>> {
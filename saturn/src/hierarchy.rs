@@ -0,0 +1,245 @@
+//! The resource hierarchy, generated from the trees of the monorepo.
+//!
+//! Every directory of the monorepo is a `Directory` entity in its parent directory, and a
+//! repository is in the directory of its own path, so role assignments and grants on a directory
+//! apply to everything below it. The directories are read from the trees of the monorepo instead
+//! of being declared in entity files, directories and repository parents declared there are
+//! replaced, so the hierarchy always follows the actual layout. Import repositories are attached
+//! to the tree when they are pushed, a path which is not part of the tree yet, like a repository
+//! being created, is a directory of its own in the deepest directory it is below.
+//!
+//! The directories are rebuilt when the root tree of the monorepo changed, the trees which did
+//! not change since the last build are not read again.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+/// Where the trees of the monorepo are read from.
+#[async_trait]
+pub trait TreeSource: Send + Sync {
+    type Error: Send;
+
+    /// Id of the root tree of the monorepo, `None` if it is not initialized.
+    async fn root(&self) -> Result<Option<String>, Self::Error>;
+
+    /// The `(name, tree id)` of the subdirectories of the trees `ids`, by tree id.
+    async fn subtrees(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<HashMap<String, Vec<(String, String)>>, Self::Error>;
+}
+
+/// The directories of the monorepo at one root tree.
+#[derive(Debug, Default)]
+pub struct DirectoryTree {
+    root: Option<String>,
+    /// Tree id of every directory by its path
+    dirs: BTreeMap<String, String>,
+}
+
+impl DirectoryTree {
+    /// The directories `path` is in, from `/` down to `path` itself.
+    ///
+    /// All the ancestors of the path are directories while the tree is not known.
+    pub fn directories(&self, path: &str) -> Vec<String> {
+        let mut ancestors: Vec<String> = Path::new(path)
+            .ancestors()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .collect();
+        ancestors.reverse();
+        if self.root.is_none() {
+            return ancestors;
+        }
+        let mut res: Vec<String> = ancestors
+            .into_iter()
+            .take_while(|dir| self.dirs.contains_key(dir))
+            .collect();
+        if res.last().map(String::as_str) != Some(path) {
+            res.push(path.to_owned());
+        }
+        res
+    }
+
+    /// The directories below `path` with their tree ids.
+    fn below<'a>(&'a self, path: &str) -> impl Iterator<Item = (&'a String, &'a String)> {
+        let prefix = match path {
+            "/" => "/".to_owned(),
+            _ => format!("{}/", path),
+        };
+        self.dirs
+            .range::<String, _>((Bound::Excluded(prefix.clone()), Bound::Unbounded))
+            .take_while(move |(dir, _)| dir.starts_with(&prefix))
+    }
+}
+
+#[derive(Default)]
+pub struct Hierarchy {
+    current: Mutex<Arc<DirectoryTree>>,
+}
+
+impl Hierarchy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The directories of the monorepo, rebuilt if its root tree changed.
+    pub async fn directories<S: TreeSource>(
+        &self,
+        source: &S,
+    ) -> Result<Arc<DirectoryTree>, S::Error> {
+        let root = source.root().await?;
+        let previous = self.current.lock().unwrap().clone();
+        if previous.root == root {
+            return Ok(previous);
+        }
+        let tree = Arc::new(match root {
+            Some(root) => build(&previous, root, source).await?,
+            None => DirectoryTree::default(),
+        });
+        *self.current.lock().unwrap() = tree.clone();
+        Ok(tree)
+    }
+}
+
+/// Read the directories of the tree `root`, taking those of the trees which did not change from
+/// `previous`.
+async fn build<S: TreeSource>(
+    previous: &DirectoryTree,
+    root: String,
+    source: &S,
+) -> Result<DirectoryTree, S::Error> {
+    let mut dirs = BTreeMap::new();
+    let mut level = vec![("/".to_owned(), root.clone())];
+    while !level.is_empty() {
+        let mut unread = Vec::new();
+        for (path, id) in level {
+            if previous.dirs.get(&path) == Some(&id) {
+                dirs.extend(previous.below(&path).map(|(d, i)| (d.clone(), i.clone())));
+            } else {
+                unread.push((path.clone(), id.clone()));
+            }
+            dirs.insert(path, id);
+        }
+        if unread.is_empty() {
+            break;
+        }
+        let mut ids: Vec<String> = unread.iter().map(|(_, id)| id.clone()).collect();
+        ids.sort();
+        ids.dedup();
+        let subtrees = source.subtrees(ids).await?;
+        level = Vec::new();
+        for (path, id) in unread {
+            for (name, sub) in subtrees.get(&id).into_iter().flatten() {
+                let dir = match path.as_str() {
+                    "/" => format!("/{}", name),
+                    _ => format!("{}/{}", path, name),
+                };
+                level.push((dir, sub.clone()));
+            }
+        }
+    }
+    Ok(DirectoryTree {
+        root: Some(root),
+        dirs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::{Hierarchy, TreeSource};
+
+    /// Subdirectories by tree id, with the trees read.
+    #[derive(Default)]
+    struct MemorySource {
+        root: Option<String>,
+        trees: HashMap<String, Vec<(String, String)>>,
+        read: Mutex<Vec<String>>,
+    }
+
+    impl MemorySource {
+        fn add(&mut self, id: &str, subtrees: &[(&str, &str)]) {
+            self.trees.insert(
+                id.to_owned(),
+                subtrees
+                    .iter()
+                    .map(|(name, id)| (name.to_string(), id.to_string()))
+                    .collect(),
+            );
+        }
+    }
+
+    #[async_trait]
+    impl TreeSource for MemorySource {
+        type Error = String;
+
+        async fn root(&self) -> Result<Option<String>, String> {
+            Ok(self.root.clone())
+        }
+
+        async fn subtrees(
+            &self,
+            ids: Vec<String>,
+        ) -> Result<HashMap<String, Vec<(String, String)>>, String> {
+            self.read.lock().unwrap().extend(ids.iter().cloned());
+            Ok(ids
+                .into_iter()
+                .filter_map(|id| Some((id.clone(), self.trees.get(&id)?.clone())))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_directories() {
+        let hierarchy = Hierarchy::new();
+        let mut source = MemorySource::default();
+        // every ancestor is a directory while there is no tree
+        let tree = hierarchy.directories(&source).await.unwrap();
+        assert_eq!(
+            tree.directories("/project/mega"),
+            ["/", "/project", "/project/mega"]
+        );
+
+        source.root = Some("r1".to_owned());
+        source.add("r1", &[("project", "p1"), ("doc", "d1")]);
+        source.add("p1", &[("mega", "m1")]);
+        source.add("m1", &[("src", "s1")]);
+        source.add("d1", &[]);
+        source.add("s1", &[]);
+        let tree = hierarchy.directories(&source).await.unwrap();
+        assert_eq!(
+            tree.directories("/project/mega/src"),
+            ["/", "/project", "/project/mega", "/project/mega/src"]
+        );
+        // a path not in the tree is in the deepest directory it is below
+        assert_eq!(
+            tree.directories("/project/new/repo"),
+            ["/", "/project", "/project/new/repo"]
+        );
+        assert_eq!(tree.directories("/"), ["/"]);
+
+        // only the trees which changed are read again
+        source.read.lock().unwrap().clear();
+        source.root = Some("r2".to_owned());
+        source.add("r2", &[("project", "p2"), ("doc", "d1")]);
+        source.add("p2", &[("mega", "m1"), ("libra", "l1")]);
+        source.add("l1", &[]);
+        let tree = hierarchy.directories(&source).await.unwrap();
+        assert_eq!(
+            tree.directories("/project/libra"),
+            ["/", "/project", "/project/libra"]
+        );
+        assert_eq!(tree.directories("/project/mega/src").len(), 4);
+        let mut read = source.read.lock().unwrap().clone();
+        read.sort();
+        assert_eq!(read, ["l1", "p2", "r2"]);
+    }
+}
@@ -1,17 +1,28 @@
 use std::fmt::{self, Display};
+use std::str::FromStr;
 
+pub mod break_glass;
 pub mod context;
 pub mod entitystore;
+pub mod hierarchy;
 mod objects;
+pub mod policy;
+pub mod policy_test;
+pub mod request;
+pub mod resolver;
+pub mod role;
+pub mod service_account;
 pub mod util;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ActionEnum {
     // ** Anyone
-    // ViewRepo,
-    // PullRepo,
+    ViewRepo,
+    PullRepo,
     // ForkRepo,
-    // PushRepo,
+    PushRepo,
     // OpenIssue,
     // ** Maintainer
     CreateMergeRequest,
@@ -22,6 +33,17 @@ pub enum ActionEnum {
     // ** Admin
     AddMaintainer,
     AddAdmin,
+    SetVisibility,
+    RenameRepo,
+    TransferRepo,
+    RunMaintenance,
+    AdministerInstance,
+    ReviewSecrets,
+    ManageBranches,
+    ManageTagRules,
+    ManageProtectedTags,
+    ManageReleases,
+    CreateRepo,
     DeleteRepo,
     DeleteIssue,
     DeleteMergeRequest,
@@ -30,6 +52,9 @@ pub enum ActionEnum {
 impl Display for ActionEnum {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
+            ActionEnum::ViewRepo => "viewRepo",
+            ActionEnum::PullRepo => "pullRepo",
+            ActionEnum::PushRepo => "pushRepo",
             ActionEnum::CreateMergeRequest => "createMergeRequest",
             ActionEnum::EditIssue => "editIssue",
             ActionEnum::EditMergeRequest => "editMergeRequest",
@@ -37,6 +62,17 @@ impl Display for ActionEnum {
             ActionEnum::ApproveMergeRequest => "approveMergeRequest",
             ActionEnum::AddMaintainer => "addMaintainer",
             ActionEnum::AddAdmin => "addAdmin",
+            ActionEnum::SetVisibility => "setVisibility",
+            ActionEnum::RenameRepo => "renameRepo",
+            ActionEnum::TransferRepo => "transferRepo",
+            ActionEnum::RunMaintenance => "runMaintenance",
+            ActionEnum::AdministerInstance => "administerInstance",
+            ActionEnum::ReviewSecrets => "reviewSecrets",
+            ActionEnum::ManageBranches => "manageBranches",
+            ActionEnum::ManageTagRules => "manageTagRules",
+            ActionEnum::ManageProtectedTags => "manageProtectedTags",
+            ActionEnum::ManageReleases => "manageReleases",
+            ActionEnum::CreateRepo => "createRepo",
             ActionEnum::DeleteRepo => "deleteRepo",
             ActionEnum::DeleteIssue => "deleteIssue",
             ActionEnum::DeleteMergeRequest => "deleteMergeRequest",
@@ -45,6 +81,63 @@ impl Display for ActionEnum {
     }
 }
 
+impl ActionEnum {
+    pub const ALL: [ActionEnum; 24] = [
+        ActionEnum::ViewRepo,
+        ActionEnum::PullRepo,
+        ActionEnum::PushRepo,
+        ActionEnum::CreateMergeRequest,
+        ActionEnum::EditIssue,
+        ActionEnum::EditMergeRequest,
+        ActionEnum::AssignIssue,
+        ActionEnum::ApproveMergeRequest,
+        ActionEnum::AddMaintainer,
+        ActionEnum::AddAdmin,
+        ActionEnum::SetVisibility,
+        ActionEnum::RenameRepo,
+        ActionEnum::TransferRepo,
+        ActionEnum::RunMaintenance,
+        ActionEnum::AdministerInstance,
+        ActionEnum::ReviewSecrets,
+        ActionEnum::ManageBranches,
+        ActionEnum::ManageTagRules,
+        ActionEnum::ManageProtectedTags,
+        ActionEnum::ManageReleases,
+        ActionEnum::CreateRepo,
+        ActionEnum::DeleteRepo,
+        ActionEnum::DeleteIssue,
+        ActionEnum::DeleteMergeRequest,
+    ];
+
+    /// Actions only admins should be able to do, they are audited even when allowed.
+    pub fn is_sensitive(&self) -> bool {
+        matches!(
+            self,
+            ActionEnum::AddMaintainer
+                | ActionEnum::AddAdmin
+                | ActionEnum::SetVisibility
+                | ActionEnum::RenameRepo
+                | ActionEnum::TransferRepo
+                | ActionEnum::RunMaintenance
+                | ActionEnum::AdministerInstance
+                | ActionEnum::ReviewSecrets
+                | ActionEnum::DeleteRepo
+        )
+    }
+}
+
+impl FromStr for ActionEnum {
+    type Err = String;
+
+    /// Parse the cedar name of an action, e.g. `pushRepo`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ActionEnum::ALL
+            .into_iter()
+            .find(|action| action.to_string() == s)
+            .ok_or_else(|| format!("unknown action: {}", s))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use std::{fs, sync::Once};
@@ -52,9 +145,15 @@ mod test {
     use cedar_policy::{Authorizer, Context, Entities, PolicySet, Request};
 
     use crate::{
-        context::{CedarContext, Error},
-        entitystore::EntityStore,
+        break_glass::{self, BreakGlassGrant, BREAK_GLASS_NAMESPACE},
+        context::{CedarContext, Error, TENANT_ISOLATION},
+        entitystore::{generate_entity, EntityStore, OrgEntities, RepoPermission, TeamEntities},
+        policy::PolicyStore,
+        request::{AuthMethod, RequestInfo},
+        role::{self, Role, RoleAssignment, Subject, ROLE_NAMESPACE},
+        service_account::principal_uid,
         util::EntityUid,
+        ActionEnum,
     };
 
     static INIT: Once = Once::new();
@@ -224,4 +323,546 @@ mod test {
             )
             .is_err_and(|e| matches!(e, Error::AuthDenied(_))));
     }
+
+    #[test]
+    fn test_org_team_policy() {
+        let entity_str = generate_entity("root", "/org").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        let org = OrgEntities {
+            name: "mega".to_owned(),
+            owners: vec!["alice".to_owned()],
+            members: vec!["bob".to_owned()],
+            teams: vec![
+                TeamEntities {
+                    name: "core".to_owned(),
+                    parent: None,
+                    permission: RepoPermission::Maintainer,
+                    members: vec![],
+                },
+                TeamEntities {
+                    name: "storage".to_owned(),
+                    parent: Some("core".to_owned()),
+                    permission: RepoPermission::Reader,
+                    members: vec!["carol".to_owned()],
+                },
+            ],
+        };
+        entities.add_org("/org", &org);
+        let app_context = load_context(entities);
+        let resource: EntityUid = r#"Repository::"/org""#.parse().unwrap();
+        let check = |user: &str, action: &str| {
+            app_context.is_authorized(
+                format!(r#"User::"{}""#, user).parse::<EntityUid>().unwrap(),
+                format!(r#"Action::"{}""#, action)
+                    .parse::<EntityUid>()
+                    .unwrap(),
+                &resource,
+                Context::empty(),
+            )
+        };
+
+        // org owner is admin of the repo
+        assert!(check("alice", "deleteRepo").is_ok());
+        // org member can read but not maintain
+        assert!(check("bob", "viewRepo").is_ok());
+        assert!(check("bob", "approveMergeRequest").is_err());
+        // nested team inherits maintainer permission of its parent
+        assert!(check("carol", "approveMergeRequest").is_ok());
+        assert!(check("carol", "deleteRepo").is_err());
+        // outsider can't view private repo
+        assert!(check("dave", "viewRepo").is_err());
+    }
+
+    #[test]
+    fn test_sliced_entities() {
+        let entity_str = generate_entity("root", "/org").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        entities.add_org(
+            "/org",
+            &OrgEntities {
+                name: "mega".to_owned(),
+                owners: vec!["alice".to_owned()],
+                members: vec!["bob".to_owned()],
+                teams: vec![TeamEntities {
+                    name: "core".to_owned(),
+                    parent: None,
+                    permission: RepoPermission::Maintainer,
+                    members: vec!["carol".to_owned()],
+                }],
+            },
+        );
+        let carol = principal_uid("carol");
+        let resource: EntityUid = r#"Repository::"/org""#.parse().unwrap();
+        let sliced = entities.slice(&carol, &resource);
+        let json = serde_json::to_string(&sliced).unwrap();
+        assert!(json.contains("carol") && json.contains("/org"));
+        assert!(!json.contains("alice") && !json.contains("bob") && !json.contains("root"));
+
+        // the sliced entities still decide the requests of carol
+        let app_context = load_context(sliced);
+        let check = |action: &str| {
+            app_context.is_authorized(
+                &carol,
+                format!(r#"Action::"{}""#, action)
+                    .parse::<EntityUid>()
+                    .unwrap(),
+                &resource,
+                Context::empty(),
+            )
+        };
+        assert!(check("approveMergeRequest").is_ok());
+        assert!(check("deleteRepo").is_err());
+    }
+
+    #[test]
+    fn test_team_granted_policy() {
+        let entity_str = generate_entity("root", "/project").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        entities.set_repo_visibility("/project", true, false);
+        entities.add_org_groups(&OrgEntities {
+            name: "mega".to_owned(),
+            owners: vec![],
+            members: vec!["alice".to_owned(), "bob".to_owned()],
+            teams: vec![
+                TeamEntities {
+                    name: "core".to_owned(),
+                    parent: None,
+                    permission: RepoPermission::Reader,
+                    members: vec![],
+                },
+                TeamEntities {
+                    name: "storage".to_owned(),
+                    parent: Some("core".to_owned()),
+                    permission: RepoPermission::Reader,
+                    members: vec!["alice".to_owned()],
+                },
+            ],
+        });
+        let policies = PolicyStore::builtin();
+        policies
+            .set_namespace(
+                "teams",
+                Some(
+                    r#"permit (
+                        principal in Team::"mega/core",
+                        action == Action::"pushRepo",
+                        resource == Repository::"/project"
+                    );
+                    permit (
+                        principal in Organization::"mega",
+                        action == Action::"viewRepo",
+                        resource == Repository::"/project"
+                    );"#,
+                ),
+            )
+            .unwrap();
+        let app_context = CedarContext::with_policies(entities, policies.current());
+        let resource: EntityUid = r#"Repository::"/project""#.parse().unwrap();
+        let check = |user: &str, action: &str| {
+            app_context.is_authorized(
+                format!(r#"User::"{}""#, user).parse::<EntityUid>().unwrap(),
+                format!(r#"Action::"{}""#, action)
+                    .parse::<EntityUid>()
+                    .unwrap(),
+                &resource,
+                Context::empty(),
+            )
+        };
+
+        // member of a child team is granted what its parent team is granted
+        assert!(check("alice", "pushRepo").is_ok());
+        assert!(check("alice", "deleteRepo").is_err());
+        // organization members are granted what the organization is granted
+        assert!(check("bob", "viewRepo").is_ok());
+        assert!(check("bob", "pushRepo").is_err());
+        assert!(check("dave", "viewRepo").is_err());
+    }
+
+    #[test]
+    fn test_role_policy() {
+        let entity_str = generate_entity("root", "/project/mega").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        entities.set_repo_visibility("/project/mega", true, false);
+        entities.add_repo_directories("/project/mega");
+        entities.add_org_groups(&OrgEntities {
+            name: "mega".to_owned(),
+            owners: vec![],
+            members: vec!["alice".to_owned()],
+            teams: vec![
+                TeamEntities {
+                    name: "core".to_owned(),
+                    parent: None,
+                    permission: RepoPermission::Reader,
+                    members: vec![],
+                },
+                TeamEntities {
+                    name: "storage".to_owned(),
+                    parent: Some("core".to_owned()),
+                    permission: RepoPermission::Reader,
+                    members: vec!["alice".to_owned()],
+                },
+            ],
+        });
+        let assignments = vec![
+            RoleAssignment {
+                path: "/project".to_owned(),
+                subject: Subject::Team("mega/core".to_owned()),
+                role: Role::Developer,
+                expires_at: None,
+            },
+            RoleAssignment {
+                path: "/project/mega".to_owned(),
+                subject: Subject::User("bob".to_owned()),
+                role: Role::Guest,
+                expires_at: None,
+            },
+            RoleAssignment {
+                path: "/other".to_owned(),
+                subject: Subject::User("alice".to_owned()),
+                role: Role::Owner,
+                expires_at: None,
+            },
+            RoleAssignment {
+                path: "/project".to_owned(),
+                subject: Subject::User("dave".to_owned()),
+                role: Role::Developer,
+                expires_at: Some(chrono::Utc::now() + chrono::Duration::days(30)),
+            },
+            RoleAssignment {
+                path: "/project".to_owned(),
+                subject: Subject::User("erin".to_owned()),
+                role: Role::Developer,
+                expires_at: Some(chrono::Utc::now() - chrono::Duration::days(1)),
+            },
+            RoleAssignment {
+                path: "/project".to_owned(),
+                subject: Subject::User("frank".to_owned()),
+                role: Role::Reporter,
+                expires_at: None,
+            },
+        ];
+        let policies = PolicyStore::builtin();
+        policies
+            .set_namespace(ROLE_NAMESPACE, Some(&role::policies(&assignments)))
+            .unwrap();
+        let effective = role::effective_roles(&assignments, &entities, "alice", "/project/mega");
+        assert_eq!(effective, vec![&assignments[0]]);
+
+        let app_context = CedarContext::with_policies(entities, policies.current());
+        let resource: EntityUid = r#"Repository::"/project/mega""#.parse().unwrap();
+        let check = |user: &str, action: &str| {
+            app_context.is_authorized(
+                format!(r#"User::"{}""#, user).parse::<EntityUid>().unwrap(),
+                format!(r#"Action::"{}""#, action)
+                    .parse::<EntityUid>()
+                    .unwrap(),
+                &resource,
+                RequestInfo::default().context().unwrap(),
+            )
+        };
+
+        // roles on a directory apply to the repositories below it
+        assert!(check("alice", "pushRepo").is_ok());
+        assert!(check("alice", "approveMergeRequest").is_err());
+        assert!(check("bob", "pullRepo").is_ok());
+        assert!(check("bob", "pushRepo").is_err());
+        // reporters work on issues, their pushes are refused
+        assert!(check("frank", "editIssue").is_ok());
+        assert!(check("frank", "pushRepo").is_err());
+        assert!(check("carol", "pullRepo").is_err());
+        // temporary roles apply until they expire
+        assert!(check("dave", "pushRepo").is_ok());
+        assert!(check("erin", "pushRepo").is_err());
+        assert!(
+            role::effective_roles(&assignments, &EntityStore::new(), "erin", "/project").is_empty()
+        );
+    }
+
+    #[test]
+    fn test_request_context_policy() {
+        let entity_str = generate_entity("root", "/project").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        // public, anyone may push
+        entities.set_repo_visibility("/project", false, false);
+        let policies = PolicyStore::builtin();
+        policies
+            .set_namespace(
+                "context",
+                Some(
+                    r#"forbid (principal, action == Action::"deleteRepo", resource)
+                    unless { context has mfa && context.mfa };
+                    forbid (principal, action == Action::"pushRepo", resource)
+                    unless { context has ip && context.ip.isInRange(ip("10.0.0.0/8")) };
+                    forbid (principal, action == Action::"renameRepo", resource)
+                    when { context has weekday && context.weekday > 5 };"#,
+                ),
+            )
+            .unwrap();
+        let app_context = CedarContext::with_policies(entities, policies.current());
+        let resource: EntityUid = r#"Repository::"/project""#.parse().unwrap();
+        let check = |user: &str, action: &str, request: &RequestInfo| {
+            app_context.is_authorized(
+                format!(r#"User::"{}""#, user).parse::<EntityUid>().unwrap(),
+                format!(r#"Action::"{}""#, action)
+                    .parse::<EntityUid>()
+                    .unwrap(),
+                &resource,
+                request.context().unwrap(),
+            )
+        };
+        // a Monday
+        let monday = "2026-10-12T10:00:00Z".parse().unwrap();
+        let office = RequestInfo {
+            ip: Some("10.1.2.3".parse().unwrap()),
+            auth_method: Some(AuthMethod::Session),
+            mfa: true,
+            time: monday,
+        };
+        let home = RequestInfo {
+            ip: Some("192.168.1.10".parse().unwrap()),
+            mfa: false,
+            time: monday + chrono::Duration::days(5),
+            ..office.clone()
+        };
+
+        assert!(check("root", "deleteRepo", &office).is_ok());
+        assert!(check("root", "deleteRepo", &home).is_err());
+        assert!(check("anyone", "pushRepo", &office).is_ok());
+        assert!(check("anyone", "pushRepo", &home).is_err());
+        assert!(check("root", "renameRepo", &office).is_ok());
+        assert!(check("root", "renameRepo", &home).is_err());
+        // requests without context are denied what the policies require context for
+        assert!(app_context
+            .is_authorized(
+                r#"User::"root""#.parse::<EntityUid>().unwrap(),
+                r#"Action::"deleteRepo""#.parse::<EntityUid>().unwrap(),
+                &resource,
+                Context::empty(),
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_service_account_policy() {
+        let entity_str = generate_entity("alice", "/project/mega").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        entities.set_repo_visibility("/project/mega", false, false);
+        entities.add_repo_directories("/project/mega");
+        let assignments = vec![RoleAssignment {
+            path: "/project".to_owned(),
+            subject: Subject::Bot("release".to_owned()),
+            role: Role::Owner,
+            expires_at: None,
+        }];
+        let policies = PolicyStore::builtin();
+        policies
+            .set_namespace(ROLE_NAMESPACE, Some(&role::policies(&assignments)))
+            .unwrap();
+        let app_context = CedarContext::with_policies(entities, policies.current());
+        let resource: EntityUid = r#"Repository::"/project/mega""#.parse().unwrap();
+        let check = |username: &str, action: &str| {
+            app_context.is_authorized(
+                principal_uid(username),
+                format!(r#"Action::"{}""#, action)
+                    .parse::<EntityUid>()
+                    .unwrap(),
+                &resource,
+                Context::empty(),
+            )
+        };
+
+        // users may contribute to public repositories, service accounts only read them
+        assert!(check("bob", "pushRepo").is_ok());
+        assert!(check("ci[bot]", "pullRepo").is_ok());
+        assert!(check("ci[bot]", "pushRepo").is_err());
+        // what is granted to a service account is allowed, except administering
+        assert!(check("release[bot]", "pushRepo").is_ok());
+        assert!(check("release[bot]", "manageReleases").is_ok());
+        assert!(check("release[bot]", "deleteRepo").is_err());
+        assert!(check("alice", "deleteRepo").is_ok());
+    }
+
+    #[test]
+    fn test_break_glass_policy() {
+        let entity_str = generate_entity("alice", "/project/mega").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        entities.add_repo_directories("/project/mega");
+        let policies = PolicyStore::builtin();
+        policies
+            .set_namespace(
+                "context",
+                Some(
+                    r#"forbid (principal, action == Action::"deleteRepo", resource)
+                    unless { context has mfa && context.mfa };"#,
+                ),
+            )
+            .unwrap();
+        let now = chrono::Utc::now();
+        let grants = vec![
+            BreakGlassGrant {
+                user: "alice".to_owned(),
+                path: "/project".to_owned(),
+                expires_at: now + chrono::Duration::hours(1),
+            },
+            BreakGlassGrant {
+                user: "bob".to_owned(),
+                path: "/project".to_owned(),
+                expires_at: now - chrono::Duration::minutes(1),
+            },
+        ];
+        // expired grants are left out
+        let content = break_glass::policies(&grants, now);
+        assert_eq!(content.matches("permit").count(), 1);
+        policies
+            .set_namespace(BREAK_GLASS_NAMESPACE, Some(&content))
+            .unwrap();
+
+        let app_context = CedarContext::with_policies(entities, policies.current());
+        let resource: EntityUid = r#"Repository::"/project/mega""#.parse().unwrap();
+        let decide = |user: &str, action: &str, request: &RequestInfo| {
+            app_context
+                .authorize(
+                    format!(r#"User::"{}""#, user).parse::<EntityUid>().unwrap(),
+                    format!(r#"Action::"{}""#, action)
+                        .parse::<EntityUid>()
+                        .unwrap(),
+                    &resource,
+                    request.context().unwrap(),
+                )
+                .unwrap()
+        };
+
+        // the grant overrides the forbid policy
+        let decision = decide("alice", "deleteRepo", &RequestInfo::default());
+        assert!(decision.is_allowed() && decision.is_break_glass());
+        assert!(decision.policies()[0].starts_with(BREAK_GLASS_NAMESPACE));
+        // requests the other policies allow are not break-glass decisions
+        let decision = decide("alice", "pullRepo", &RequestInfo::default());
+        assert!(decision.is_allowed() && !decision.is_break_glass());
+        assert!(!decide("bob", "pullRepo", &RequestInfo::default()).is_allowed());
+        // the grant is checked against the time of the request
+        let later = RequestInfo {
+            time: now + chrono::Duration::hours(2),
+            ..Default::default()
+        };
+        assert!(!decide("alice", "deleteRepo", &later).is_allowed());
+    }
+
+    #[test]
+    fn test_tenant_isolation() {
+        let entity_str = generate_entity("alice", "/acme/mega").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        entities.set_repo_tenant("/acme/mega", "acme");
+        entities.set_user_tenant("bob", "globex");
+        let policies = PolicyStore::builtin();
+        let grants = vec![BreakGlassGrant {
+            user: "bob".to_owned(),
+            path: "/acme".to_owned(),
+            expires_at: chrono::Utc::now() + chrono::Duration::hours(1),
+        }];
+        policies
+            .set_namespace(
+                BREAK_GLASS_NAMESPACE,
+                Some(&break_glass::policies(&grants, chrono::Utc::now())),
+            )
+            .unwrap();
+        let resource: EntityUid = r#"Repository::"/acme/mega""#.parse().unwrap();
+        let decide = |entities: &EntityStore, user: &str, action: &str| {
+            CedarContext::with_policies(entities.clone(), policies.current())
+                .authorize(
+                    principal_uid(user),
+                    role::entity_uid("Action", action),
+                    &resource,
+                    Context::empty(),
+                )
+                .unwrap()
+        };
+
+        // the admin of the repository is a user of the instance
+        let decision = decide(&entities, "alice", "deleteRepo");
+        assert!(!decision.is_allowed());
+        assert_eq!(decision.policies(), [TENANT_ISOLATION]);
+        entities.set_user_tenant("alice", "acme");
+        assert!(decide(&entities, "alice", "deleteRepo").is_allowed());
+        // public repositories and break-glass grants do not cross tenants
+        assert!(!decide(&entities, "bob", "viewRepo").is_allowed());
+        assert!(!decide(&entities, "bob", "deleteRepo").is_break_glass());
+
+        // the tenants are handed to the frontend with the entities
+        let sliced = entities.slice(&principal_uid("alice"), &resource);
+        assert!(!sliced.crosses_tenants(&principal_uid("alice"), &resource));
+    }
+
+    #[test]
+    fn test_allowed_actions() {
+        let entity_str = generate_entity("alice", "/project").unwrap();
+        let entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        let app_context = load_context(entities);
+        let resource: EntityUid = r#"Repository::"/project""#.parse().unwrap();
+        let allowed = |user: &str| {
+            app_context
+                .allowed_actions(
+                    format!(r#"User::"{}""#, user).parse::<EntityUid>().unwrap(),
+                    &resource,
+                    &ActionEnum::ALL,
+                    Context::empty(),
+                )
+                .unwrap()
+        };
+
+        let admin = allowed("alice");
+        assert!(admin.contains(&ActionEnum::ViewRepo));
+        assert!(admin.contains(&ActionEnum::DeleteRepo));
+        // the repository is private
+        assert!(allowed("bob").is_empty());
+    }
+
+    #[test]
+    fn test_repo_visibility_policy() {
+        let entity_str = generate_entity("root", "/internal").unwrap();
+        let mut entities: EntityStore = serde_json::from_str(&entity_str).unwrap();
+        entities.set_repo_visibility("/internal", true, true);
+        let app_context = load_context(entities);
+        let resource: EntityUid = r#"Repository::"/internal""#.parse().unwrap();
+        let user: EntityUid = r#"User::"someone""#.parse().unwrap();
+
+        // any signed in user can read internal repo
+        assert!(app_context
+            .is_authorized(
+                &user,
+                r#"Action::"pullRepo""#.parse::<EntityUid>().unwrap(),
+                &resource,
+                Context::empty()
+            )
+            .is_ok());
+        // but can't push to it
+        assert!(app_context
+            .is_authorized(
+                &user,
+                r#"Action::"pushRepo""#.parse::<EntityUid>().unwrap(),
+                &resource,
+                Context::empty()
+            )
+            .is_err_and(|e| matches!(e, Error::AuthDenied(_))));
+
+        // paths may contain quotes and backslashes
+        let mut entities = EntityStore::new();
+        entities.set_repo_visibility(r#"/a"b\c"#, true, false);
+        let resource = role::entity_uid("Repository", r#"/a"b\c"#);
+        assert!(load_context(entities)
+            .is_authorized(
+                &user,
+                r#"Action::"pullRepo""#.parse::<EntityUid>().unwrap(),
+                &resource,
+                Context::empty()
+            )
+            .is_err_and(|e| matches!(e, Error::AuthDenied(_))));
+    }
+    #[test]
+    fn test_action_from_str() {
+        for action in ActionEnum::ALL {
+            assert_eq!(action.to_string().parse::<ActionEnum>(), Ok(action));
+        }
+        assert!("forkRepo".parse::<ActionEnum>().is_err());
+    }
 }
@@ -11,6 +11,23 @@ pub struct User {
     parents: HashSet<EntityUid>,
 }
 
+impl User {
+    pub(crate) fn new(euid: EntityUid) -> Self {
+        Self {
+            euid,
+            parents: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn add_parent(&mut self, parent: EntityUid) {
+        self.parents.insert(parent);
+    }
+
+    pub(crate) fn parents(&self) -> &HashSet<EntityUid> {
+        &self.parents
+    }
+}
+
 impl From<User> for Entity {
     fn from(value: User) -> Entity {
         Entity::new_no_attrs(
@@ -26,6 +43,16 @@ pub struct UserGroup {
     parents: HashSet<EntityUid>,
 }
 
+impl UserGroup {
+    pub(crate) fn new(euid: EntityUid, parents: HashSet<EntityUid>) -> Self {
+        Self { euid, parents }
+    }
+
+    pub(crate) fn parents(&self) -> &HashSet<EntityUid> {
+        &self.parents
+    }
+}
+
 impl From<UserGroup> for Entity {
     fn from(value: UserGroup) -> Entity {
         Entity::new_no_attrs(
@@ -35,16 +62,146 @@ impl From<UserGroup> for Entity {
     }
 }
 
+/// An organization, its members and teams are in it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Organization {
+    euid: EntityUid,
+    parents: HashSet<EntityUid>,
+}
+
+impl Organization {
+    pub(crate) fn new(euid: EntityUid) -> Self {
+        Self {
+            euid,
+            parents: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn add_parent(&mut self, parent: EntityUid) {
+        self.parents.insert(parent);
+    }
+
+    pub(crate) fn parents(&self) -> &HashSet<EntityUid> {
+        &self.parents
+    }
+}
+
+impl From<Organization> for Entity {
+    fn from(value: Organization) -> Entity {
+        Entity::new_no_attrs(
+            value.euid.into(),
+            value.parents.into_iter().map(|euid| euid.into()).collect(),
+        )
+    }
+}
+
+/// A team of an organization, in the organization and in its parent team.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Team {
+    euid: EntityUid,
+    parents: HashSet<EntityUid>,
+}
+
+impl Team {
+    pub(crate) fn new(euid: EntityUid) -> Self {
+        Self {
+            euid,
+            parents: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn add_parent(&mut self, parent: EntityUid) {
+        self.parents.insert(parent);
+    }
+
+    pub(crate) fn parents(&self) -> &HashSet<EntityUid> {
+        &self.parents
+    }
+}
+
+impl From<Team> for Entity {
+    fn from(value: Team) -> Entity {
+        Entity::new_no_attrs(
+            value.euid.into(),
+            value.parents.into_iter().map(|euid| euid.into()).collect(),
+        )
+    }
+}
+
+/// A directory of the monorepo, in its parent directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Directory {
+    euid: EntityUid,
+    parents: HashSet<EntityUid>,
+}
+
+impl Directory {
+    pub(crate) fn new(euid: EntityUid, parents: HashSet<EntityUid>) -> Self {
+        Self { euid, parents }
+    }
+
+    pub(crate) fn parents(&self) -> &HashSet<EntityUid> {
+        &self.parents
+    }
+}
+
+impl From<Directory> for Entity {
+    fn from(value: Directory) -> Entity {
+        Entity::new_no_attrs(
+            value.euid.into(),
+            value.parents.into_iter().map(|euid| euid.into()).collect(),
+        )
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Repo {
     euid: EntityUid,
     is_private: bool,
+    #[serde(default)]
+    is_internal: bool,
     admins: EntityUid,
     maintainers: EntityUid,
     readers: EntityUid,
     parents: HashSet<EntityUid>,
 }
 
+impl Repo {
+    pub(crate) fn new(
+        euid: EntityUid,
+        admins: EntityUid,
+        maintainers: EntityUid,
+        readers: EntityUid,
+    ) -> Self {
+        Self {
+            euid,
+            is_private: false,
+            is_internal: false,
+            admins,
+            maintainers,
+            readers,
+            parents: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn groups(&self) -> (&EntityUid, &EntityUid, &EntityUid) {
+        (&self.admins, &self.maintainers, &self.readers)
+    }
+
+    pub(crate) fn set_visibility(&mut self, is_private: bool, is_internal: bool) {
+        self.is_private = is_private;
+        self.is_internal = is_internal;
+    }
+
+    pub(crate) fn set_parents(&mut self, parents: HashSet<EntityUid>) {
+        self.parents = parents;
+    }
+
+    pub(crate) fn parents(&self) -> &HashSet<EntityUid> {
+        &self.parents
+    }
+}
+
 impl From<Repo> for Entity {
     fn from(value: Repo) -> Self {
         let attrs = [
@@ -52,6 +209,10 @@ impl From<Repo> for Entity {
                 "is_private",
                 RestrictedExpression::new_bool(value.is_private),
             ),
+            (
+                "is_internal",
+                RestrictedExpression::new_bool(value.is_internal),
+            ),
             (
                 "admins",
                 format!("{}", value.admins.as_ref()).parse().unwrap(),
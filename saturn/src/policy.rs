@@ -0,0 +1,499 @@
+//! Cedar schema and policies of the server, which can be replaced while it runs.
+//!
+//! The policies in use are held by a [`PolicyStore`]. They are those of the configured files
+//! together with the policies of the namespaces managed through the API. Reloading reads the
+//! files, parses them and validates the policies against the schema, only then the new ones
+//! replace the old ones in a single swap. Requests being authorized keep the policies they
+//! started with, invalid files are reported and the previous policies stay in use.
+//!
+//! Extensions declare the actions they authorize, like `triggerPipeline`, in schema fragments
+//! which are merged into the schema. They may only declare actions, which can refer to the
+//! entity types and the `RequestContext` of the schema:
+//!
+//! ```cedarschema
+//! action "triggerPipeline" appliesTo {
+//!     principal: [User, Bot],
+//!     resource: [Repository],
+//!     context: RequestContext,
+//! };
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+#[cfg(feature = "server")]
+use std::fs;
+#[cfg(feature = "server")]
+use std::path::PathBuf;
+#[cfg(feature = "server")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "server")]
+use std::thread::{self, JoinHandle};
+#[cfg(feature = "server")]
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "server")]
+use arc_swap::ArcSwap;
+use cedar_policy::{PolicyId, PolicySet, Schema, SchemaFragment, ValidationMode, Validator};
+use itertools::Itertools;
+
+#[cfg(feature = "server")]
+use common::config::PolicyConfig;
+
+use crate::break_glass::BREAK_GLASS_NAMESPACE;
+use crate::context::ContextError;
+
+const SCHEMA: &str = include_str!("../mega.cedarschema");
+const POLICIES: &str = include_str!("../mega_policies.cedar");
+
+/// Namespaces are named by at most 64 ASCII letters, digits, `-` and `_`.
+pub fn is_valid_namespace(namespace: &str) -> bool {
+    !namespace.is_empty()
+        && namespace.len() <= 64
+        && namespace
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// A schema with policies validated against it.
+pub struct PolicyBundle {
+    pub(crate) policies: PolicySet,
+    /// Policies of the break-glass grants, evaluated when `policies` deny a request
+    pub(crate) break_glass: PolicySet,
+    pub(crate) schema: Schema,
+    /// Actions declared by the schema fragments of extensions
+    custom_actions: BTreeSet<String>,
+}
+
+impl PolicyBundle {
+    /// Parse `schema` and `policies` in the cedar syntax, the policies must pass validation.
+    pub fn parse(schema: &str, policies: &str) -> Result<Self, ContextError> {
+        Self::parse_with_namespaces(schema, policies, &BTreeMap::new())
+    }
+
+    /// Like [`PolicyBundle::parse`], adding the policies of every namespace in `namespaces`.
+    /// Their ids are prefixed with the namespace, validation errors name the namespace.
+    pub fn parse_with_namespaces(
+        schema: &str,
+        policies: &str,
+        namespaces: &BTreeMap<String, String>,
+    ) -> Result<Self, ContextError> {
+        Self::parse_with_extensions(schema, &[], policies, namespaces)
+    }
+
+    /// Like [`PolicyBundle::parse_with_namespaces`], merging the schema fragments of
+    /// `extensions` into `schema`. They may only declare actions, the custom actions of the
+    /// bundle.
+    pub fn parse_with_extensions(
+        schema: &str,
+        extensions: &[String],
+        policies: &str,
+        namespaces: &BTreeMap<String, String>,
+    ) -> Result<Self, ContextError> {
+        let (schema, custom_actions) = parse_schema(schema, extensions)?;
+        let mut policies: PolicySet = policies.parse()?;
+        let mut break_glass = PolicySet::new();
+        for (namespace, content) in namespaces {
+            let set: PolicySet = content.parse()?;
+            if set.templates().next().is_some() {
+                return Err(ContextError::Validation(format!(
+                    "{namespace}: policy templates are not supported"
+                )));
+            }
+            let target = if namespace == BREAK_GLASS_NAMESPACE {
+                &mut break_glass
+            } else {
+                &mut policies
+            };
+            for policy in set.policies() {
+                let id = PolicyId::new(format!("{namespace}/{}", policy.id()));
+                target.add(policy.new_id(id))?;
+            }
+        }
+        let validator = Validator::new(schema.clone());
+        for set in [&policies, &break_glass] {
+            let output = validator.validate(set, ValidationMode::default());
+            if !output.validation_passed() {
+                let error_string = output
+                    .validation_errors()
+                    .map(|err| format!("{err}"))
+                    .join("\n");
+                return Err(ContextError::Validation(error_string));
+            }
+        }
+        Ok(PolicyBundle {
+            policies,
+            break_glass,
+            schema,
+            custom_actions,
+        })
+    }
+
+    /// The schema and policies built into the server.
+    pub fn builtin() -> Result<Self, ContextError> {
+        Self::parse(SCHEMA, POLICIES)
+    }
+
+    /// Whether `action` was declared by an extension, see [`crate::policy`].
+    pub fn is_custom_action(&self, action: &str) -> bool {
+        self.custom_actions.contains(action)
+    }
+
+    /// Cedar names of the actions declared by extensions.
+    pub fn custom_actions(&self) -> impl Iterator<Item = &str> {
+        self.custom_actions.iter().map(|a| a.as_str())
+    }
+
+    /// The policies in the Cedar syntax for [`crate::wasm`], without those of the namespaces
+    /// managed by the server, like role assignments and break-glass grants, which name users.
+    pub fn public_policies(&self) -> String {
+        self.policies
+            .policies()
+            .filter(|p| !p.id().to_string().starts_with('@'))
+            .map(|p| p.to_string())
+            .join("\n")
+    }
+}
+
+/// Merge the schema fragments of `extensions` into `schema`, returning the merged schema with
+/// the names of the actions the fragments declared.
+fn parse_schema(
+    schema: &str,
+    extensions: &[String],
+) -> Result<(Schema, BTreeSet<String>), ContextError> {
+    let (base, _) = Schema::from_cedarschema_str(schema)?;
+    if extensions.is_empty() {
+        return Ok((base, BTreeSet::new()));
+    }
+    let mut fragments = vec![SchemaFragment::from_cedarschema_str(schema)?.0];
+    for extension in extensions {
+        fragments.push(SchemaFragment::from_cedarschema_str(extension)?.0);
+    }
+    let merged = Schema::from_schema_fragments(fragments)?;
+    if merged.entity_types().count() != base.entity_types().count() {
+        return Err(ContextError::Validation(
+            "action schemas may only declare actions".to_owned(),
+        ));
+    }
+    let builtin: HashSet<_> = base.actions().collect();
+    let custom_actions = merged
+        .actions()
+        .filter(|action| !builtin.contains(action))
+        .map(|action| action.id().unescaped().to_owned())
+        .collect();
+    Ok((merged, custom_actions))
+}
+
+/// The policies in use and where they are read from.
+#[cfg(feature = "server")]
+pub struct PolicyStore {
+    current: ArcSwap<PolicyBundle>,
+    sources: Mutex<Sources>,
+}
+
+/// What the current policies were built from, held while they are replaced.
+#[cfg(feature = "server")]
+#[derive(Default)]
+struct Sources {
+    files: Files,
+    /// Modification times of the files
+    modified: Vec<Option<SystemTime>>,
+    /// Policies of the namespaces managed through the API
+    namespaces: BTreeMap<String, String>,
+}
+
+/// Files the schema and policies are read from, the built-in ones if they are not set.
+#[cfg(feature = "server")]
+#[derive(Default)]
+struct Files {
+    schema_path: Option<PathBuf>,
+    policy_path: Option<PathBuf>,
+    action_paths: Vec<PathBuf>,
+}
+
+#[cfg(feature = "server")]
+impl PolicyStore {
+    /// Only the built-in schema and policies, reloading keeps them.
+    pub fn builtin() -> Self {
+        PolicyStore {
+            current: ArcSwap::from_pointee(
+                PolicyBundle::builtin().expect("built-in policies are invalid"),
+            ),
+            sources: Mutex::new(Sources::default()),
+        }
+    }
+
+    /// Load the files of `config`, the built-in schema or policies for those not configured.
+    pub fn new(config: &PolicyConfig) -> Result<Self, ContextError> {
+        let store = PolicyStore::builtin();
+        store.set_files(config)?;
+        Ok(store)
+    }
+
+    /// Read the schema and policies from the files of `config` from now on, if the policies
+    /// they result in are valid.
+    pub fn set_files(&self, config: &PolicyConfig) -> Result<(), ContextError> {
+        let path = |p: &str| (!p.is_empty()).then(|| PathBuf::from(p));
+        let files = Files {
+            schema_path: path(&config.schema_path),
+            policy_path: path(&config.policy_path),
+            action_paths: config.action_schemas.iter().map(PathBuf::from).collect(),
+        };
+        let mut sources = self.sources.lock().unwrap();
+        let modified = files.modified();
+        let bundle = files.read(&sources.namespaces)?;
+        self.current.store(Arc::new(bundle));
+        sources.files = files;
+        sources.modified = modified;
+        Ok(())
+    }
+
+    /// The policies to authorize a request with.
+    pub fn current(&self) -> Arc<PolicyBundle> {
+        self.current.load_full()
+    }
+
+    /// Read the files again and use their policies if they are valid.
+    pub fn reload(&self) -> Result<(), ContextError> {
+        let mut sources = self.sources.lock().unwrap();
+        let namespaces = sources.namespaces.clone();
+        self.replace(&mut sources, namespaces)?;
+        tracing::info!("policies reloaded");
+        Ok(())
+    }
+
+    /// Check that replacing the policies of `namespace` by `content` leaves valid policies,
+    /// `None` checks removing them.
+    pub fn check_namespace(
+        &self,
+        namespace: &str,
+        content: Option<&str>,
+    ) -> Result<(), ContextError> {
+        self.proposed(namespace, content)?;
+        Ok(())
+    }
+
+    /// The policies replacing those of `namespace` by `content` would result in, without
+    /// putting them in use, `None` removes them.
+    pub fn proposed(
+        &self,
+        namespace: &str,
+        content: Option<&str>,
+    ) -> Result<PolicyBundle, ContextError> {
+        let sources = self.sources.lock().unwrap();
+        let namespaces = Self::with_namespace(&sources.namespaces, namespace, content);
+        sources.files.read(&namespaces)
+    }
+
+    /// Replace the policies of `namespace` by `content` if the result is valid, `None` removes
+    /// them.
+    pub fn set_namespace(
+        &self,
+        namespace: &str,
+        content: Option<&str>,
+    ) -> Result<(), ContextError> {
+        let mut sources = self.sources.lock().unwrap();
+        let namespaces = Self::with_namespace(&sources.namespaces, namespace, content);
+        self.replace(&mut sources, namespaces)
+    }
+
+    /// Replace the policies of all namespaces, reading the files again.
+    pub fn set_namespaces(&self, namespaces: BTreeMap<String, String>) -> Result<(), ContextError> {
+        let mut sources = self.sources.lock().unwrap();
+        self.replace(&mut sources, namespaces)
+    }
+
+    /// Reload the policies whenever their files changed, checked every `interval`.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let store = self.clone();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let modified = store.sources.lock().unwrap().files.modified();
+            if store.sources.lock().unwrap().modified == modified {
+                continue;
+            }
+            if let Err(err) = store.reload() {
+                tracing::error!("keeping the previous policies, reload failed: {}", err);
+                // reported once, until the files change again
+                store.sources.lock().unwrap().modified = modified;
+            }
+        })
+    }
+
+    fn replace(
+        &self,
+        sources: &mut Sources,
+        namespaces: BTreeMap<String, String>,
+    ) -> Result<(), ContextError> {
+        let modified = sources.files.modified();
+        let bundle = sources.files.read(&namespaces)?;
+        self.current.store(Arc::new(bundle));
+        sources.modified = modified;
+        sources.namespaces = namespaces;
+        Ok(())
+    }
+
+    fn with_namespace(
+        namespaces: &BTreeMap<String, String>,
+        namespace: &str,
+        content: Option<&str>,
+    ) -> BTreeMap<String, String> {
+        let mut namespaces = namespaces.clone();
+        match content {
+            Some(content) => namespaces.insert(namespace.to_owned(), content.to_owned()),
+            None => namespaces.remove(namespace),
+        };
+        namespaces
+    }
+}
+
+#[cfg(feature = "server")]
+impl Files {
+    fn read(&self, namespaces: &BTreeMap<String, String>) -> Result<PolicyBundle, ContextError> {
+        let read = |path: &Option<PathBuf>, builtin: &str| match path {
+            Some(path) => fs::read_to_string(path),
+            None => Ok(builtin.to_owned()),
+        };
+        let extensions = self
+            .action_paths
+            .iter()
+            .map(fs::read_to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        PolicyBundle::parse_with_extensions(
+            &read(&self.schema_path, SCHEMA)?,
+            &extensions,
+            &read(&self.policy_path, POLICIES)?,
+            namespaces,
+        )
+    }
+
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [&self.schema_path, &self.policy_path]
+            .into_iter()
+            .flatten()
+            .chain(&self.action_paths)
+            .map(|path| fs::metadata(path).ok().and_then(|m| m.modified().ok()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use common::config::PolicyConfig;
+
+    use super::{is_valid_namespace, PolicyBundle, PolicyStore, POLICIES, SCHEMA};
+
+    #[test]
+    fn test_reload_keeps_valid_policies() {
+        let dir = std::env::temp_dir().join(format!("saturn-policy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let policy_path = dir.join("policies.cedar");
+        fs::write(&policy_path, POLICIES).unwrap();
+        let store = PolicyStore::new(&PolicyConfig {
+            policy_path: policy_path.to_string_lossy().into_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        let count = store.current().policies.policies().count();
+        assert!(count > 0);
+
+        // an action the schema does not know about fails validation
+        let unknown = "permit(principal, action == Action::\"unknownAction\", resource);";
+        fs::write(&policy_path, format!("{POLICIES}\n{unknown}")).unwrap();
+        assert!(store.reload().is_err());
+        assert_eq!(store.current().policies.policies().count(), count);
+
+        let first = POLICIES.split_inclusive(';').next().unwrap();
+        fs::write(&policy_path, first).unwrap();
+        store.reload().unwrap();
+        assert_eq!(store.current().policies.policies().count(), 1);
+
+        // other files are only used if their policies are valid
+        let invalid = PolicyConfig {
+            policy_path: dir.join("missing.cedar").to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+        assert!(store.set_files(&invalid).is_err());
+        assert_eq!(store.current().policies.policies().count(), 1);
+        store.set_files(&PolicyConfig::default()).unwrap();
+        assert_eq!(store.current().policies.policies().count(), count);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_namespace_policies() {
+        let store = PolicyStore::builtin();
+        let count = store.current().policies.policies().count();
+
+        let content = "permit(principal, action == Action::\"viewRepo\", resource);";
+        store.set_namespace("team-a", Some(content)).unwrap();
+        let current = store.current();
+        assert_eq!(current.policies.policies().count(), count + 1);
+        assert!(current
+            .policies
+            .policies()
+            .any(|p| p.id().to_string() == "team-a/policy0"));
+
+        let unknown = "permit(principal, action == Action::\"unknownAction\", resource);";
+        assert!(store.check_namespace("team-b", Some(unknown)).is_err());
+        assert!(store.set_namespace("team-a", Some(unknown)).is_err());
+        assert_eq!(store.current().policies.policies().count(), count + 1);
+
+        // proposed policies are not put in use
+        let proposed = store.proposed("team-a", None).unwrap();
+        assert_eq!(proposed.policies.policies().count(), count);
+        assert_eq!(store.current().policies.policies().count(), count + 1);
+
+        // reloading the files keeps the policies of the namespaces
+        store.reload().unwrap();
+        assert_eq!(store.current().policies.policies().count(), count + 1);
+        store.set_namespace("team-a", None).unwrap();
+        assert_eq!(store.current().policies.policies().count(), count);
+    }
+
+    #[test]
+    fn test_custom_actions() {
+        let extension = r#"
+            action "triggerPipeline" appliesTo {
+                principal: [User, Bot],
+                resource: [Repository],
+                context: RequestContext,
+            };
+        "#;
+        let policies = format!(
+            "{POLICIES}\npermit(principal, action == Action::\"triggerPipeline\", resource);"
+        );
+        let bundle = PolicyBundle::parse_with_extensions(
+            SCHEMA,
+            &[extension.to_owned()],
+            &policies,
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        assert!(bundle.is_custom_action("triggerPipeline"));
+        assert!(!bundle.is_custom_action("pushRepo"));
+        assert_eq!(bundle.custom_actions().count(), 1);
+        // without the extension the action is unknown
+        assert!(PolicyBundle::parse(SCHEMA, &policies).is_err());
+        // extensions can't add entity types or redeclare actions
+        for extension in ["entity Pipeline;", r#"action "pushRepo";"#] {
+            assert!(PolicyBundle::parse_with_extensions(
+                SCHEMA,
+                &[extension.to_owned()],
+                POLICIES,
+                &BTreeMap::new()
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_is_valid_namespace() {
+        assert!(is_valid_namespace("release_team-2"));
+        assert!(!is_valid_namespace(""));
+        assert!(!is_valid_namespace("a/b"));
+        assert!(!is_valid_namespace(&"a".repeat(65)));
+    }
+}
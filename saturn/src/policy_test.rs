@@ -0,0 +1,136 @@
+//! Declarative test cases for the policies, run by `mega policy test` so policy changes can be
+//! checked before they are deployed.
+//!
+//! A test suite is a JSON file with the entities in the format of `.mega.json` and the cases:
+//!
+//! ```json
+//! {
+//!   "entities": { "users": {}, "repos": {}, "merge_requests": {}, "issues": {}, "user_groups": {} },
+//!   "cases": [
+//!     {
+//!       "name": "deleting requires a second factor",
+//!       "principal": "alice",
+//!       "action": "deleteRepo",
+//!       "resource": "/project",
+//!       "context": { "ip": "10.1.2.3", "mfa": false },
+//!       "expect": "deny"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! The principal is the name of a user or `name[bot]` for a service account, the resource the
+//! path of a repository and the context is a [`RequestInfo`], its time defaults to the time the
+//! case is run.
+
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::{
+    context::{AuthDecision, CedarContext, Error},
+    entitystore::EntityStore,
+    policy::PolicyBundle,
+    request::RequestInfo,
+    role::entity_uid,
+    service_account::principal_uid,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct TestSuite {
+    #[serde(default)]
+    pub entities: EntityStore,
+    pub cases: Vec<TestCase>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    /// Name of the user, or `name[bot]` for a service account
+    pub principal: String,
+    /// Cedar name of the action, like `pushRepo`
+    pub action: String,
+    /// Path of the repository
+    pub resource: String,
+    #[serde(default)]
+    pub context: RequestInfo,
+    pub expect: Expect,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expect {
+    Allow,
+    Deny,
+}
+
+pub struct CaseResult<'a> {
+    pub case: &'a TestCase,
+    /// The decision, an error if the request is not valid for the schema
+    pub outcome: Result<AuthDecision, Error>,
+}
+
+impl CaseResult<'_> {
+    pub fn passed(&self) -> bool {
+        let expected = self.case.expect == Expect::Allow;
+        self.outcome
+            .as_ref()
+            .is_ok_and(|decision| decision.is_allowed() == expected)
+    }
+}
+
+impl TestSuite {
+    pub fn parse(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Decide every case with `policies`.
+    pub fn run(&self, policies: Arc<PolicyBundle>) -> Vec<CaseResult<'_>> {
+        let context = CedarContext::with_policies(self.entities.clone(), policies);
+        self.cases
+            .iter()
+            .map(|case| CaseResult {
+                case,
+                outcome: case.context.context().and_then(|request| {
+                    context.authorize(
+                        principal_uid(&case.principal),
+                        entity_uid("Action", &case.action),
+                        entity_uid("Repository", &case.resource),
+                        request,
+                    )
+                }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TestSuite;
+    use crate::policy::PolicyStore;
+
+    #[test]
+    fn test_run_suite() {
+        let entities = crate::entitystore::generate_entity("alice", "/project").unwrap();
+        let suite = TestSuite::parse(&format!(
+            r#"{{
+                "entities": {entities},
+                "cases": [
+                    {{ "name": "admin deletes", "principal": "alice", "action": "deleteRepo",
+                       "resource": "/project", "expect": "allow" }},
+                    {{ "name": "outsider views", "principal": "bob", "action": "viewRepo",
+                       "resource": "/project", "context": {{ "mfa": true }}, "expect": "allow" }},
+                    {{ "name": "unknown action", "principal": "bob", "action": "unknownAction",
+                       "resource": "/project", "expect": "deny" }}
+                ]
+            }}"#
+        ))
+        .unwrap();
+        let results = suite.run(PolicyStore::builtin().current());
+        let passed: Vec<bool> = results.iter().map(|r| r.passed()).collect();
+        assert_eq!(passed, vec![true, false, false]);
+        let decision = results[0].outcome.as_ref().unwrap();
+        assert!(!decision.policies().is_empty());
+        assert!(results[2].outcome.is_err());
+    }
+}
@@ -0,0 +1,101 @@
+//! What is known about a request besides who makes it, passed to the policies as the Cedar
+//! context so they can depend on where and how a request is made, e.g.
+//!
+//! ```cedar
+//! forbid (principal, action == Action::"deleteRepo", resource)
+//! unless { context has mfa && context.mfa };
+//! ```
+//!
+//! Every attribute is optional in the schema, policies check them with `has` first.
+
+use std::fmt::{self, Display};
+use std::net::IpAddr;
+
+use cedar_policy::{Context, RestrictedExpression};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Deserialize;
+
+use crate::context::Error;
+
+/// How the user making a request signed in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// Session of the web UI
+    Session,
+    /// Access token, over http or as ssh password
+    Token,
+    SshKey,
+    /// Identity key of a ztm peer, for the requests of other mega instances over p2p
+    PeerKey,
+}
+
+impl Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AuthMethod::Session => "session",
+            AuthMethod::Token => "token",
+            AuthMethod::SshKey => "ssh_key",
+            AuthMethod::PeerKey => "peer_key",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RequestInfo {
+    /// Address of the client, `context.ip`
+    pub ip: Option<IpAddr>,
+    /// `context.auth_method`, the name of the method like `ssh_key`
+    pub auth_method: Option<AuthMethod>,
+    /// Whether the user signed in with a second factor, `context.mfa`
+    pub mfa: bool,
+    /// `context.time` in seconds since the epoch, with `context.hour` (0 to 23) and
+    /// `context.weekday` (1 for Monday to 7) in UTC for time windows
+    pub time: DateTime<Utc>,
+}
+
+impl Default for RequestInfo {
+    fn default() -> Self {
+        RequestInfo {
+            ip: None,
+            auth_method: None,
+            mfa: false,
+            time: Utc::now(),
+        }
+    }
+}
+
+impl RequestInfo {
+    pub fn context(&self) -> Result<Context, Error> {
+        let mut pairs = vec![
+            ("mfa".to_owned(), RestrictedExpression::new_bool(self.mfa)),
+            (
+                "time".to_owned(),
+                RestrictedExpression::new_long(self.time.timestamp()),
+            ),
+            (
+                "hour".to_owned(),
+                RestrictedExpression::new_long(self.time.hour().into()),
+            ),
+            (
+                "weekday".to_owned(),
+                RestrictedExpression::new_long(self.time.weekday().number_from_monday().into()),
+            ),
+        ];
+        if let Some(ip) = self.ip {
+            pairs.push((
+                "ip".to_owned(),
+                RestrictedExpression::new_ip(ip.to_string()),
+            ));
+        }
+        if let Some(method) = self.auth_method {
+            pairs.push((
+                "auth_method".to_owned(),
+                RestrictedExpression::new_string(method.to_string()),
+            ));
+        }
+        Context::from_pairs(pairs).map_err(|e| Error::Request(e.to_string()))
+    }
+}
@@ -0,0 +1,184 @@
+//! Entities of a monorepo path, merged from the entity files of the path and its ancestors.
+//!
+//! Every directory may have an entity file. The entities of a path are those of the root
+//! directory, overridden by those of each directory on the way down to the path: an entity
+//! defined by a child directory replaces the one of the same id defined by its parents. The
+//! merged view of every directory is cached, it is rebuilt when the file of the directory or
+//! of one of its ancestors changed.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::entitystore::EntityStore;
+
+/// Where the entity files of the directories are read from.
+#[async_trait]
+pub trait EntitySource: Send + Sync {
+    type Error: Send;
+
+    /// Id of the entity file of `dir`, which changes whenever its content does, `None` if the
+    /// directory has none.
+    async fn file_id(&self, dir: &Path) -> Result<Option<String>, Self::Error>;
+
+    /// Entities of the file with `id`.
+    async fn load(&self, id: &str) -> Result<EntityStore, Self::Error>;
+}
+
+#[derive(Default)]
+pub struct EntityResolver {
+    cache: Mutex<HashMap<PathBuf, CachedView>>,
+}
+
+struct CachedView {
+    file_id: Option<String>,
+    /// View of the parent directory this one was merged onto, `None` for the root
+    parent: Option<Arc<EntityStore>>,
+    entities: Arc<EntityStore>,
+}
+
+impl EntityResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The entities of `path`, reading the files which changed since they were cached.
+    pub async fn resolve<S: EntitySource>(
+        &self,
+        path: &Path,
+        source: &S,
+    ) -> Result<Arc<EntityStore>, S::Error> {
+        let mut dirs: Vec<&Path> = path.ancestors().collect();
+        dirs.reverse();
+        let mut view: Option<Arc<EntityStore>> = None;
+        for dir in dirs {
+            let file_id = source.file_id(dir).await?;
+            let cached = self
+                .cache
+                .lock()
+                .unwrap()
+                .get(dir)
+                .filter(|c| c.file_id == file_id && same_view(&c.parent, &view))
+                .map(|c| c.entities.clone());
+            let entities = match cached {
+                Some(entities) => entities,
+                None => {
+                    let mut entities = view.as_deref().cloned().unwrap_or_default();
+                    if let Some(id) = &file_id {
+                        entities.merge(source.load(id).await?);
+                    }
+                    let entities = Arc::new(entities);
+                    self.cache.lock().unwrap().insert(
+                        dir.to_path_buf(),
+                        CachedView {
+                            file_id,
+                            parent: view.clone(),
+                            entities: entities.clone(),
+                        },
+                    );
+                    entities
+                }
+            };
+            view = Some(entities);
+        }
+        Ok(view.unwrap_or_default())
+    }
+
+    /// Forget the cached views, files are read again when resolving.
+    pub fn clear(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+}
+
+fn same_view(a: &Option<Arc<EntityStore>>, b: &Option<Arc<EntityStore>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+
+    use async_trait::async_trait;
+
+    use super::{EntityResolver, EntitySource};
+    use crate::entitystore::{generate_entity, EntityStore};
+    use crate::util::EntityUid;
+
+    /// Entity files by directory, with the number of files loaded.
+    #[derive(Default)]
+    struct MemorySource {
+        files: HashMap<PathBuf, String>,
+        loaded: Mutex<usize>,
+    }
+
+    #[async_trait]
+    impl EntitySource for MemorySource {
+        type Error = String;
+
+        async fn file_id(&self, dir: &Path) -> Result<Option<String>, String> {
+            Ok(self
+                .files
+                .get(dir)
+                .map(|content| format!("{}:{}", dir.display(), content.len())))
+        }
+
+        async fn load(&self, id: &str) -> Result<EntityStore, String> {
+            *self.loaded.lock().unwrap() += 1;
+            let (dir, _) = id.rsplit_once(':').unwrap();
+            serde_json::from_str(&self.files[Path::new(dir)]).map_err(|e| e.to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_child_overrides() {
+        let mut entities: EntityStore =
+            serde_json::from_str(&generate_entity("alice", "/project").unwrap()).unwrap();
+        entities.set_repo_visibility("/project", false, false);
+        let mut source = MemorySource::default();
+        source.files.insert(
+            PathBuf::from("/"),
+            serde_json::to_string(&entities).unwrap(),
+        );
+        // the child makes the repository private
+        entities.set_repo_visibility("/project", true, false);
+        source.files.insert(
+            PathBuf::from("/project"),
+            serde_json::to_string(&entities).unwrap(),
+        );
+
+        let resolver = EntityResolver::new();
+        let resolved = resolver
+            .resolve(Path::new("/project/mega"), &source)
+            .await
+            .unwrap();
+        let repo: EntityUid = r#"Repository::"/project""#.parse().unwrap();
+        let json = serde_json::to_value(&*resolved).unwrap();
+        assert_eq!(json["repos"][repo.to_string()]["is_private"], true);
+        assert_eq!(*source.loaded.lock().unwrap(), 2);
+
+        // cached views are used until a file changes
+        let root = resolver.resolve(Path::new("/"), &source).await.unwrap();
+        let json = serde_json::to_value(&*root).unwrap();
+        assert_eq!(json["repos"][repo.to_string()]["is_private"], false);
+        resolver
+            .resolve(Path::new("/project/mega"), &source)
+            .await
+            .unwrap();
+        assert_eq!(*source.loaded.lock().unwrap(), 2);
+
+        source.files.get_mut(Path::new("/")).unwrap().push_str("\n");
+        resolver
+            .resolve(Path::new("/project/mega"), &source)
+            .await
+            .unwrap();
+        assert_eq!(*source.loaded.lock().unwrap(), 4);
+    }
+}
@@ -0,0 +1,257 @@
+//! Roles assigned to users, teams or organizations on a directory.
+//!
+//! A role stands for a curated set of actions, every role can do what the roles below it can
+//! do. Assignments are turned into Cedar policies which are added to the other policies as
+//! the namespace [`ROLE_NAMESPACE`]. An assignment applies to the directory and everything
+//! below it, repositories are in the directories of their path for that. Temporary
+//! assignments only apply to requests made before they expire.
+
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entitystore::EntityStore;
+use crate::service_account::principal_uid;
+use crate::util::EntityUid;
+
+/// Namespace of the policies generated from the role assignments, it can't be managed through
+/// the API as it is not a valid namespace name there.
+pub const ROLE_NAMESPACE: &str = "@roles";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Read and fork
+    Guest,
+    /// Also open and triage issues
+    Reporter,
+    /// Also push and open merge requests
+    Developer,
+    /// Also approve merge requests and manage branches, tags and releases
+    Maintainer,
+    /// Everything, including deleting, renaming and transferring the repositories
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::Guest,
+        Role::Reporter,
+        Role::Developer,
+        Role::Maintainer,
+        Role::Owner,
+    ];
+
+    /// Cedar names of the actions the role allows, including those of the roles below it.
+    pub fn actions(&self) -> Vec<&'static str> {
+        Role::ALL
+            .iter()
+            .take_while(|role| *role <= self)
+            .flat_map(|role| role.own_actions().iter().copied())
+            .collect()
+    }
+
+    fn own_actions(&self) -> &'static [&'static str] {
+        match self {
+            Role::Guest => &["viewRepo", "pullRepo", "forkRepo"],
+            Role::Reporter => &["openIssue", "editIssue", "assignIssue"],
+            Role::Developer => &[
+                "pushRepo",
+                "createMergeRequest",
+                "editMergeRequest",
+                "manageReleases",
+            ],
+            Role::Maintainer => &[
+                "approveMergeRequest",
+                "manageBranches",
+                "manageProtectedTags",
+                "manageTagRules",
+                "createRepo",
+                "addMaintainer",
+                "deleteIssue",
+                "deleteMergeRequest",
+            ],
+            Role::Owner => &[
+                "addAdmin",
+                "setVisibility",
+                "renameRepo",
+                "transferRepo",
+                "deleteRepo",
+                "reviewSecrets",
+                "runMaintenance",
+                "administerInstance",
+            ],
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Role::Guest => "guest",
+            Role::Reporter => "reporter",
+            Role::Developer => "developer",
+            Role::Maintainer => "maintainer",
+            Role::Owner => "owner",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.to_string() == s)
+            .ok_or_else(|| format!("unknown role: {}", s))
+    }
+}
+
+/// Who a role is assigned to, teams are named `org/team` and service accounts without
+/// [`BOT_SUFFIX`](crate::service_account::BOT_SUFFIX).
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "name", rename_all = "snake_case")]
+pub enum Subject {
+    User(String),
+    Team(String),
+    Organization(String),
+    Bot(String),
+}
+
+impl Subject {
+    pub fn euid(&self) -> EntityUid {
+        let (type_name, name) = match self {
+            Subject::User(name) => ("User", name),
+            Subject::Team(name) => ("Team", name),
+            Subject::Organization(name) => ("Organization", name),
+            Subject::Bot(name) => ("Bot", name),
+        };
+        entity_uid(type_name, name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleAssignment {
+    /// Directory or repository the role applies to, with everything below it
+    pub path: String,
+    pub subject: Subject,
+    pub role: Role,
+    /// End of a temporary assignment, checked against `context.time`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl RoleAssignment {
+    /// Whether the assignment applies to `path`.
+    pub fn covers(&self, path: &str) -> bool {
+        Path::new(path).starts_with(&self.path)
+    }
+
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| now < expires_at)
+    }
+
+    fn policy(&self) -> String {
+        let principal = match self.subject {
+            Subject::User(_) | Subject::Bot(_) => format!("principal == {}", self.subject.euid()),
+            Subject::Team(_) | Subject::Organization(_) => {
+                format!("principal in {}", self.subject.euid())
+            }
+        };
+        let actions = self
+            .role
+            .actions()
+            .iter()
+            .map(|action| format!(r#"Action::"{}""#, action))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let condition = match self.expires_at {
+            Some(expires_at) => format!(
+                "\nwhen {{ context has time && context.time < {} }}",
+                expires_at.timestamp()
+            ),
+            None => String::new(),
+        };
+        format!(
+            "permit (\n    {},\n    action in [{}],\n    resource in {}\n){};\n",
+            principal,
+            actions,
+            directory_uid(&self.path),
+            condition
+        )
+    }
+}
+
+/// The Cedar policies granting the roles of `assignments`.
+pub fn policies(assignments: &[RoleAssignment]) -> String {
+    assignments.iter().map(|a| a.policy()).collect()
+}
+
+/// The active assignments which give `user` a role on `path`, directly or through the teams
+/// and organizations `entities` have the user in. `user` may be a service account.
+pub fn effective_roles<'a>(
+    assignments: &'a [RoleAssignment],
+    entities: &EntityStore,
+    user: &str,
+    path: &str,
+) -> Vec<&'a RoleAssignment> {
+    let now = Utc::now();
+    let user = principal_uid(user);
+    let mut principals: HashSet<EntityUid> = entities.ancestors(&user);
+    principals.insert(user);
+    assignments
+        .iter()
+        .filter(|a| a.covers(path) && a.is_active(now) && principals.contains(&a.subject.euid()))
+        .collect()
+}
+
+/// `Directory::"<path>"`, repositories are in the directories of their path and the path itself.
+pub(crate) fn directory_uid(path: &str) -> EntityUid {
+    entity_uid("Directory", path)
+}
+
+/// `<type_name>::"<id>"`, the id may contain any character.
+pub fn entity_uid(type_name: &str, id: &str) -> EntityUid {
+    cedar_policy::EntityUid::from_type_name_and_id(
+        type_name.parse().unwrap(),
+        cedar_policy::EntityId::new(id),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Role, RoleAssignment, Subject};
+
+    #[test]
+    fn test_role_actions() {
+        let guest = Role::Guest.actions();
+        let owner = Role::Owner.actions();
+        assert!(guest.contains(&"pullRepo") && !guest.contains(&"pushRepo"));
+        assert!(Role::Developer.actions().contains(&"pushRepo"));
+        assert!(guest.iter().all(|a| owner.contains(a)));
+        for role in Role::ALL {
+            assert_eq!(role.to_string().parse::<Role>(), Ok(role));
+        }
+    }
+
+    #[test]
+    fn test_assignment_covers() {
+        let assignment = RoleAssignment {
+            path: "/project".to_owned(),
+            subject: Subject::User("alice".to_owned()),
+            role: Role::Guest,
+            expires_at: None,
+        };
+        assert!(assignment.covers("/project"));
+        assert!(assignment.covers("/project/mega"));
+        assert!(!assignment.covers("/projects"));
+        assert!(!assignment.covers("/"));
+    }
+}
@@ -0,0 +1,51 @@
+//! Service accounts, the principals of CI systems and automation.
+//!
+//! A service account `ci` signs in as `ci[bot]` with an access token of its own and is the
+//! principal `Bot::"ci"`, so policies can tell it from users with `principal is Bot`. The
+//! built-in policies only let service accounts read public and internal repositories, anything
+//! else has to be granted to them, e.g. by a role, and they are never allowed to administer
+//! repositories.
+
+use crate::role::entity_uid;
+use crate::util::EntityUid;
+
+/// Appended to the name of a service account to sign in with it, user names can't end with it.
+pub const BOT_SUFFIX: &str = "[bot]";
+
+/// Service accounts are named by at most 64 ASCII letters, digits, `-` and `_`.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// The name of the service account `username` signs in as, `None` for users.
+pub fn bot_name(username: &str) -> Option<&str> {
+    username
+        .strip_suffix(BOT_SUFFIX)
+        .filter(|name| is_valid_name(name))
+}
+
+/// The principal of requests made by `username`, a user or a service account.
+pub fn principal_uid(username: &str) -> EntityUid {
+    match bot_name(username) {
+        Some(name) => entity_uid("Bot", name),
+        None => entity_uid("User", username),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bot_name, principal_uid};
+
+    #[test]
+    fn test_principal_uid() {
+        assert_eq!(bot_name("ci[bot]"), Some("ci"));
+        assert_eq!(bot_name("[bot]"), None);
+        assert_eq!(bot_name("a b[bot]"), None);
+        assert_eq!(principal_uid("ci[bot]").to_string(), r#"Bot::"ci""#);
+        assert_eq!(principal_uid("alice").to_string(), r#"User::"alice""#);
+    }
+}
@@ -0,0 +1,74 @@
+//! Permission hints for web frontends, built with
+//! `wasm-pack build saturn --no-default-features --features wasm`.
+//!
+//! The frontend fetches the policies and the entities of a path, sliced to the signed in user,
+//! from `GET /api/v1/user/permission-hints` and decides locally which buttons and menus to
+//! show without asking the server for every action:
+//!
+//! ```js
+//! const hints = new PermissionHints(res.policies, JSON.stringify(res.entities));
+//! const actions = hints.allowedActions(res.user, res.path, ["pushRepo", "deleteRepo"], "{}");
+//! ```
+//!
+//! The hints leave out the schema and the policies naming other users, they are only a
+//! convenience: every request is still authorized by the server.
+
+use cedar_policy::{Authorizer, Decision, Entities, PolicySet, Request};
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    entitystore::EntityStore, request::RequestInfo, role::entity_uid,
+    service_account::principal_uid,
+};
+
+#[wasm_bindgen]
+pub struct PermissionHints {
+    policies: PolicySet,
+    entities: Entities,
+    authorizer: Authorizer,
+}
+
+#[wasm_bindgen]
+impl PermissionHints {
+    /// `policies` in the Cedar syntax and `entities` in the format of `.mega_cedar.json`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(policies: &str, entities: &str) -> Result<PermissionHints, JsError> {
+        let policies: PolicySet = policies.parse()?;
+        let entities: EntityStore = serde_json::from_str(entities)?;
+        Ok(PermissionHints {
+            policies,
+            entities: entities.as_entities(None),
+            authorizer: Authorizer::new(),
+        })
+    }
+
+    /// The ones of `actions` which `user` may likely do on the repository at `path`, `context`
+    /// is a JSON [`RequestInfo`] like `{"mfa": true}`.
+    #[wasm_bindgen(js_name = allowedActions)]
+    pub fn allowed_actions(
+        &self,
+        user: &str,
+        path: &str,
+        actions: Vec<String>,
+        context: &str,
+    ) -> Result<Vec<String>, JsError> {
+        let context = serde_json::from_str::<RequestInfo>(context)?.context()?;
+        let mut allowed = Vec::new();
+        for action in actions {
+            let request = Request::new(
+                principal_uid(user).into(),
+                entity_uid("Action", &action).into(),
+                entity_uid("Repository", path).into(),
+                context.clone(),
+                None,
+            )?;
+            let response = self
+                .authorizer
+                .is_authorized(&request, &self.policies, &self.entities);
+            if response.decision() == Decision::Allow {
+                allowed.push(action);
+            }
+        }
+        Ok(allowed)
+    }
+}
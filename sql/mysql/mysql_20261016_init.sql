@@ -0,0 +1,507 @@
+CREATE TABLE IF NOT EXISTS `mega_commit` (
+  `id` BIGINT PRIMARY KEY,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `tree` VARCHAR(40) NOT NULL,
+  `parents_id` JSON NOT NULL,  -- for compatibility with sqlite, DO NOT use Array Type
+  `author` TEXT,
+  `committer` TEXT,
+  `content` TEXT,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_mc_git_id UNIQUE (commit_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_mc_git_id` ON `mega_commit` (`commit_id`);
+CREATE TABLE IF NOT EXISTS `mega_tree` (
+  `id` BIGINT PRIMARY KEY,
+  `tree_id` VARCHAR(40) NOT NULL,
+  `sub_trees` LONGBLOB NOT NULL,
+  `size` INT NOT NULL,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_mt_git_id` ON `mega_tree` (`tree_id`);
+CREATE TABLE IF NOT EXISTS `mega_blob` (
+  `id` BIGINT PRIMARY KEY,
+  `blob_id` VARCHAR(40) NOT NULL,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `name` TEXT NOT NULL,
+  `size` INT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_mb_git_id` ON `mega_blob` (`blob_id`);
+CREATE TABLE IF NOT EXISTS `mega_tag` (
+  `id` BIGINT PRIMARY KEY,
+  `tag_id` VARCHAR(40) NOT NULL,
+  `object_id` VARCHAR(40) NOT NULL,
+  `object_type` VARCHAR(20) NOT NULL,
+  `tag_name` TEXT NOT NULL,
+  `tagger` TEXT NOT NULL,
+  `message` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_mtag_tag_id UNIQUE (tag_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE TABLE IF NOT EXISTS `mega_mr` (
+  `id` BIGINT PRIMARY KEY,
+  `link` VARCHAR(40) NOT NULL,
+  `title` TEXT NOT NULL,
+  `merge_date` DATETIME(6),
+  `status` VARCHAR(20) NOT NULL,
+  `path` VARCHAR(512) NOT NULL,
+  `from_hash` VARCHAR(40) NOT NULL,
+  `to_hash` VARCHAR(40) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_mr_path` ON `mega_mr` (`path`);
+
+CREATE TABLE IF NOT EXISTS `mega_conversation` (
+  `id` BIGINT PRIMARY KEY,
+  `link` VARCHAR(20) NOT NULL,
+  `user_id` BIGINT NOT NULL,
+  `conv_type`  VARCHAR(20) NOT NULL,
+  `comment` TEXT,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_conversation` ON `mega_conversation` (`link`);
+
+
+CREATE TABLE IF NOT EXISTS `mega_issue` (
+  `id` BIGINT PRIMARY KEY,
+  `link`  VARCHAR(20) NOT NULL,
+  `title` VARCHAR(255) NOT NULL,
+  `owner` BIGINT NOT NULL,
+  `status` VARCHAR(20) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  `closed_at` DATETIME(6) DEFAULT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_issue` ON `mega_issue` (`link`);
+
+CREATE TABLE IF NOT EXISTS `mega_refs` (
+  `id` BIGINT PRIMARY KEY,
+  `path` VARCHAR(512) NOT NULL,
+  `ref_name` VARCHAR(255) NOT NULL,
+  `ref_commit_hash` VARCHAR(40) NOT NULL,
+  `ref_tree_hash` VARCHAR(40) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_mref_path UNIQUE (path, ref_name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE TABLE IF NOT EXISTS `import_refs` (
+  `id` BIGINT PRIMARY KEY,
+  `repo_id` BIGINT NOT NULL,
+  `ref_name` VARCHAR(255) NOT NULL,
+  `ref_git_id` VARCHAR(40) NOT NULL,
+  `ref_type` VARCHAR(20) NOT NULL,
+  `default_branch` BOOLEAN NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_ref_path_name UNIQUE (repo_id, ref_name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_refs_repo_id` ON `import_refs` (`repo_id`);
+CREATE TABLE IF NOT EXISTS `git_repo` (
+  `id` BIGINT PRIMARY KEY,
+  `repo_path` VARCHAR(512) NOT NULL,
+  `repo_name` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_ir_path UNIQUE (repo_path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_ir_repo_path` ON `git_repo` (`repo_path`);
+CREATE TABLE IF NOT EXISTS `git_commit` (
+  `id` BIGINT PRIMARY KEY,
+  `repo_id` BIGINT NOT NULL,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `tree` VARCHAR(40) NOT NULL,
+  `parents_id` JSON NOT NULL,
+  `author` TEXT,
+  `committer` TEXT,
+  `content` TEXT,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_c_git_repo_id UNIQUE (repo_id, commit_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_ic_git_id` ON `git_commit` (`commit_id`);
+CREATE INDEX `idx_ic_repo_id` ON `git_commit` (`repo_id`);
+CREATE TABLE IF NOT EXISTS `git_tree` (
+  `id` BIGINT PRIMARY KEY,
+  `repo_id` BIGINT NOT NULL,
+  `tree_id` VARCHAR(40) NOT NULL,
+  `sub_trees` LONGBLOB NOT NULL,
+  `size` INT NOT NULL,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_t_git_repo UNIQUE (repo_id, tree_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_t_git_id` ON `git_tree` (`tree_id`);
+CREATE INDEX `idx_t_repo_id` ON `git_tree` (`repo_id`);
+CREATE TABLE IF NOT EXISTS `git_blob` (
+  `id` BIGINT PRIMARY KEY,
+  `repo_id` BIGINT NOT NULL,
+  `blob_id` VARCHAR(40) NOT NULL,
+  `name` VARCHAR(128),
+  `size` INT NOT NULL,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_b_git_repo UNIQUE (repo_id, blob_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_b_git_id` ON `git_blob` (`blob_id`);
+CREATE TABLE IF NOT EXISTS `git_tag` (
+  `id` BIGINT PRIMARY KEY,
+  `repo_id` BIGINT NOT NULL,
+  `tag_id` VARCHAR(40) NOT NULL,
+  `object_id` VARCHAR(40) NOT NULL,
+  `object_type` VARCHAR(20) NOT NULL,
+  `tag_name` TEXT NOT NULL,
+  `tagger` TEXT NOT NULL,
+  `message` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_gtag_tag_id UNIQUE (tag_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE TABLE IF NOT EXISTS `raw_blob` (
+  `id` BIGINT PRIMARY KEY,
+  `sha1` VARCHAR(40) NOT NULL,
+  `content` TEXT,
+  `file_type` VARCHAR(20),
+  `storage_type` VARCHAR(20) NOT NULL,
+  `data` LONGBLOB,
+  `local_path` TEXT,
+  `remote_url` TEXT,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_rb_sha1 UNIQUE (sha1)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_rb_sha1` ON `raw_blob` (`sha1`);
+CREATE TABLE IF NOT EXISTS `git_pr` (
+  `id` BIGINT PRIMARY KEY,
+  `number` BIGINT NOT NULL,
+  `title` VARCHAR(255) NOT NULL,
+  `state` VARCHAR(255) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  `closed_at` DATETIME(6) DEFAULT NULL,
+  `merged_at` DATETIME(6) DEFAULT NULL,
+  `merge_commit_sha` VARCHAR(200) DEFAULT NULL,
+  `repo_id` BIGINT NOT NULL,
+  `sender_name` VARCHAR(255) NOT NULL,
+  `sender_id` BIGINT NOT NULL,
+  `user_name` VARCHAR(255) NOT NULL,
+  `user_id` BIGINT NOT NULL,
+  `commits_url` VARCHAR(255) NOT NULL,
+  `patch_url` VARCHAR(255) NOT NULL,
+  `head_label` VARCHAR(255) NOT NULL,
+  `head_ref` VARCHAR(255) NOT NULL,
+  `base_label` VARCHAR(255) NOT NULL,
+  `base_ref` VARCHAR(255) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE TABLE IF NOT EXISTS `git_issue` (
+  `id` BIGINT PRIMARY KEY,
+  `number` BIGINT NOT NULL,
+  `title` VARCHAR(255) NOT NULL,
+  `sender_name` VARCHAR(255) NOT NULL,
+  `sender_id` BIGINT NOT NULL,
+  `state` VARCHAR(255) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  `closed_at` DATETIME(6) DEFAULT NULL,
+  `repo_id` BIGINT NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE TABLE IF NOT EXISTS `lfs_locks` (
+  `id` VARCHAR(40) PRIMARY KEY,
+  `data` TEXT NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE TABLE IF NOT EXISTS `lfs_objects` (
+  `oid` VARCHAR(64) PRIMARY KEY,
+  `size` BIGINT NOT NULL,
+  `exist` BOOLEAN NOT NULL,
+  `splited` BOOLEAN NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE TABLE IF NOT EXISTS `lfs_split_relations` (
+    `ori_oid` VARCHAR(64) NOT NULL,
+    `sub_oid` VARCHAR(64) NOT NULL,
+    `offset` BIGINT NOT NULL,
+    `size` BIGINT NOT NULL,
+    PRIMARY KEY (`ori_oid`, `sub_oid`, `offset`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `ztm_node` (
+  `peer_id` VARCHAR(64) PRIMARY KEY,
+  `hub` VARCHAR(64),
+  `agent_name` VARCHAR(64),
+  `service_name` VARCHAR(64),
+  `type` VARCHAR(64),
+  `online` BOOLEAN NOT NULL,
+  `last_online_time` BIGINT NOT NULL,
+  `service_port` INT NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `ztm_repo_info` (
+  `identifier` VARCHAR(128) PRIMARY KEY,
+  `name` VARCHAR(64),
+  `origin` VARCHAR(64),
+  `update_time` BIGINT NOT NULL,
+  `commit` VARCHAR(64)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `ztm_lfs_info` (
+  `id` BIGINT PRIMARY KEY,
+  `file_hash` VARCHAR(256),
+  `hash_type` VARCHAR(64),
+  `file_size` BIGINT NOT NULL,
+  `creation_time` BIGINT NOT NULL,
+  `peer_id` VARCHAR(64),
+  `origin` VARCHAR(256)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `ztm_nostr_event` (
+  `id` VARCHAR(128) PRIMARY KEY,
+  `pubkey` VARCHAR(128),
+  `created_at` BIGINT NOT NULL,
+  `kind` INT,
+  `tags` TEXT,
+  `content` TEXT,
+  `sig` VARCHAR(256)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `ztm_nostr_req` (
+  `id` VARCHAR(128) PRIMARY KEY,
+  `subscription_id` VARCHAR(128),
+  `filters` TEXT
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `mq_storage` (
+  `id` BIGINT PRIMARY KEY,
+  `category` VARCHAR(64),
+  `create_time` DATETIME(6) NOT NULL,
+  `content` TEXT
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `ztm_path_mapping` (
+  `id` BIGINT PRIMARY KEY,
+  `alias` VARCHAR(255) NOT NULL,
+  `repo_path` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_alias UNIQUE (alias)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `user` (
+  `id` BIGINT PRIMARY KEY,
+  `name` TEXT NOT NULL,
+  `email` VARCHAR(255) NOT NULL,
+  `avatar_url` TEXT NOT NULL,
+  `is_github` BOOLEAN NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6),
+  CONSTRAINT uniq_email UNIQUE (email)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `ssh_keys` (
+  `id` BIGINT PRIMARY KEY,
+  `user_id` BIGINT NOT NULL,
+  `title` TEXT NOT NULL,
+  `ssh_key` TEXT NOT NULL,
+  `finger` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_user_id` ON `ssh_keys` (`user_id`);
+CREATE INDEX `idx_ssh_key_finger` ON `ssh_keys` (`finger`(8));
+
+
+CREATE TABLE IF NOT EXISTS `access_token` (
+  `id` BIGINT PRIMARY KEY,
+  `user_id` BIGINT NOT NULL,
+  `token` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_token_user_id` ON `access_token` (`user_id`);
+CREATE INDEX `idx_token` ON `access_token` (`token`(8));
+
+CREATE TABLE IF NOT EXISTS `organization` (
+  `id` BIGINT PRIMARY KEY,
+  `name` VARCHAR(255) NOT NULL,
+  `description` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_org_name UNIQUE (name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `org_member` (
+  `id` BIGINT PRIMARY KEY,
+  `org_id` BIGINT NOT NULL,
+  `user_id` BIGINT NOT NULL,
+  `role` VARCHAR(20) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_org_member UNIQUE (org_id, user_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_org_member_user_id` ON `org_member` (`user_id`);
+
+CREATE TABLE IF NOT EXISTS `team` (
+  `id` BIGINT PRIMARY KEY,
+  `org_id` BIGINT NOT NULL,
+  `parent_id` BIGINT,
+  `name` VARCHAR(255) NOT NULL,
+  `permission` VARCHAR(20) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_team_name UNIQUE (org_id, name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `team_member` (
+  `id` BIGINT PRIMARY KEY,
+  `team_id` BIGINT NOT NULL,
+  `user_id` BIGINT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_team_member UNIQUE (team_id, user_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_team_member_user_id` ON `team_member` (`user_id`);
+
+CREATE TABLE IF NOT EXISTS `org_repo` (
+  `id` BIGINT PRIMARY KEY,
+  `org_id` BIGINT NOT NULL,
+  `path` VARCHAR(512) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_org_repo_path UNIQUE (path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_org_repo_org_id` ON `org_repo` (`org_id`);
+
+CREATE TABLE IF NOT EXISTS `repo_visibility` (
+  `id` BIGINT PRIMARY KEY,
+  `path` VARCHAR(512) NOT NULL,
+  `visibility` VARCHAR(20) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_visibility_path UNIQUE (path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `user_repo` (
+  `id` BIGINT PRIMARY KEY,
+  `user_id` BIGINT NOT NULL,
+  `path` VARCHAR(512) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_user_repo_path UNIQUE (path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_user_repo_user_id` ON `user_repo` (`user_id`);
+
+CREATE TABLE IF NOT EXISTS `repo_redirect` (
+  `id` BIGINT PRIMARY KEY,
+  `old_path` VARCHAR(512) NOT NULL,
+  `new_path` TEXT NOT NULL,
+  `expires_at` DATETIME(6) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_redirect_old_path UNIQUE (old_path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `maintenance_job` (
+  `id` BIGINT PRIMARY KEY,
+  `task` VARCHAR(20) NOT NULL,
+  `triggered_by` VARCHAR(20) NOT NULL,
+  `target` TEXT,
+  `status` VARCHAR(20) NOT NULL,
+  `operator` VARCHAR(255),
+  `message` TEXT,
+  `started_at` DATETIME(6) NOT NULL,
+  `finished_at` DATETIME(6)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_maintenance_job_task` ON `maintenance_job` (`task`);
+
+CREATE TABLE IF NOT EXISTS `commit_graph` (
+  `commit_id` VARCHAR(40) PRIMARY KEY,
+  `generation` BIGINT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `integrity_report` (
+  `id` BIGINT PRIMARY KEY,
+  `job_id` BIGINT NOT NULL,
+  `path` VARCHAR(512) NOT NULL,
+  `healthy` BOOLEAN NOT NULL,
+  `checked_objects` BIGINT NOT NULL,
+  `problems` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_integrity_report_job UNIQUE (job_id)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_integrity_report_path` ON `integrity_report` (`path`);
+
+CREATE TABLE IF NOT EXISTS `secret_finding` (
+  `id` BIGINT PRIMARY KEY,
+  `path` TEXT NOT NULL,
+  `blob_id` VARCHAR(40) NOT NULL,
+  `rule` VARCHAR(255) NOT NULL,
+  `line` INTEGER NOT NULL,
+  `preview` TEXT NOT NULL,
+  `action` VARCHAR(20) NOT NULL,
+  `pusher` VARCHAR(255),
+  `status` VARCHAR(20) NOT NULL,
+  `reviewer` VARCHAR(255),
+  `created_at` DATETIME(6) NOT NULL,
+  `reviewed_at` DATETIME(6)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+CREATE INDEX `idx_secret_finding_blob` ON `secret_finding` (`blob_id`);
+
+CREATE TABLE IF NOT EXISTS `branch_setting` (
+  `id` BIGINT PRIMARY KEY,
+  `path` VARCHAR(512) NOT NULL,
+  `delete_on_merge` BOOLEAN NOT NULL,
+  `protected_branches` TEXT NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_branch_setting_path UNIQUE (path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `tag_protection` (
+  `id` BIGINT PRIMARY KEY,
+  `path` VARCHAR(512) NOT NULL,
+  `pattern` VARCHAR(255) NOT NULL,
+  `created_by` VARCHAR(255) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_tag_protection_path_pattern UNIQUE (path, pattern)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `release` (
+  `id` BIGINT PRIMARY KEY,
+  `path` VARCHAR(512) NOT NULL,
+  `tag_name` VARCHAR(255) NOT NULL,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `name` VARCHAR(255) NOT NULL,
+  `notes` TEXT NOT NULL,
+  `changelog` TEXT NOT NULL,
+  `created_by` VARCHAR(255) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_release_path_tag UNIQUE (path, tag_name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `release_asset` (
+  `id` BIGINT PRIMARY KEY,
+  `release_id` BIGINT NOT NULL,
+  `name` VARCHAR(255) NOT NULL,
+  `content_type` VARCHAR(255) NOT NULL,
+  `size` BIGINT NOT NULL,
+  `oid` VARCHAR(64) NOT NULL,
+  `download_count` BIGINT NOT NULL,
+  `uploaded_by` VARCHAR(255) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_release_asset_name UNIQUE (release_id, name)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `subtree_split` (
+  `id` BIGINT PRIMARY KEY,
+  `path` VARCHAR(512) NOT NULL,
+  `repo_path` VARCHAR(512) NOT NULL,
+  `last_commit` VARCHAR(40),
+  `last_split` VARCHAR(40),
+  `created_by` VARCHAR(255) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_subtree_split_path UNIQUE (path),
+  CONSTRAINT uniq_subtree_split_repo_path UNIQUE (repo_path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
+
+CREATE TABLE IF NOT EXISTS `virtual_repo` (
+  `id` BIGINT PRIMARY KEY,
+  `path` VARCHAR(512) NOT NULL,
+  `root_commit` VARCHAR(40) NOT NULL,
+  `commit_id` VARCHAR(40) NOT NULL,
+  `created_at` DATETIME(6) NOT NULL,
+  `updated_at` DATETIME(6) NOT NULL,
+  CONSTRAINT uniq_virtual_repo_path UNIQUE (path)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_bin;
@@ -315,6 +315,197 @@ CREATE TABLE IF NOT EXISTS "access_token" (
 CREATE INDEX "idx_token_user_id" ON "access_token" ("user_id");
 CREATE INDEX "idx_token" ON "access_token" ((left(token, 8)));
 
+CREATE TABLE IF NOT EXISTS "organization" (
+  "id" BIGINT PRIMARY KEY,
+  "name" TEXT NOT NULL,
+  "description" TEXT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_org_name UNIQUE (name)
+);
+
+CREATE TABLE IF NOT EXISTS "org_member" (
+  "id" BIGINT PRIMARY KEY,
+  "org_id" BIGINT NOT NULL,
+  "user_id" BIGINT NOT NULL,
+  "role" VARCHAR(20) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_org_member UNIQUE (org_id, user_id)
+);
+CREATE INDEX "idx_org_member_user_id" ON "org_member" ("user_id");
+
+CREATE TABLE IF NOT EXISTS "team" (
+  "id" BIGINT PRIMARY KEY,
+  "org_id" BIGINT NOT NULL,
+  "parent_id" BIGINT,
+  "name" TEXT NOT NULL,
+  "permission" VARCHAR(20) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_team_name UNIQUE (org_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS "team_member" (
+  "id" BIGINT PRIMARY KEY,
+  "team_id" BIGINT NOT NULL,
+  "user_id" BIGINT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_team_member UNIQUE (team_id, user_id)
+);
+CREATE INDEX "idx_team_member_user_id" ON "team_member" ("user_id");
+
+CREATE TABLE IF NOT EXISTS "org_repo" (
+  "id" BIGINT PRIMARY KEY,
+  "org_id" BIGINT NOT NULL,
+  "path" TEXT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_org_repo_path UNIQUE (path)
+);
+CREATE INDEX "idx_org_repo_org_id" ON "org_repo" ("org_id");
+
+CREATE TABLE IF NOT EXISTS "repo_visibility" (
+  "id" BIGINT PRIMARY KEY,
+  "path" TEXT NOT NULL,
+  "visibility" VARCHAR(20) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_visibility_path UNIQUE (path)
+);
+
+CREATE TABLE IF NOT EXISTS "user_repo" (
+  "id" BIGINT PRIMARY KEY,
+  "user_id" BIGINT NOT NULL,
+  "path" TEXT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_user_repo_path UNIQUE (path)
+);
+CREATE INDEX "idx_user_repo_user_id" ON "user_repo" ("user_id");
+
+CREATE TABLE IF NOT EXISTS "repo_redirect" (
+  "id" BIGINT PRIMARY KEY,
+  "old_path" TEXT NOT NULL,
+  "new_path" TEXT NOT NULL,
+  "expires_at" TIMESTAMP NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_redirect_old_path UNIQUE (old_path)
+);
+
+CREATE TABLE IF NOT EXISTS "maintenance_job" (
+  "id" BIGINT PRIMARY KEY,
+  "task" VARCHAR(20) NOT NULL,
+  "triggered_by" VARCHAR(20) NOT NULL,
+  "target" TEXT,
+  "status" VARCHAR(20) NOT NULL,
+  "operator" VARCHAR(255),
+  "message" TEXT,
+  "started_at" TIMESTAMP NOT NULL,
+  "finished_at" TIMESTAMP
+);
+CREATE INDEX "idx_maintenance_job_task" ON "maintenance_job" ("task");
+
+CREATE TABLE IF NOT EXISTS "commit_graph" (
+  "commit_id" VARCHAR(40) PRIMARY KEY,
+  "generation" BIGINT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "integrity_report" (
+  "id" BIGINT PRIMARY KEY,
+  "job_id" BIGINT NOT NULL,
+  "path" TEXT NOT NULL,
+  "healthy" BOOLEAN NOT NULL,
+  "checked_objects" BIGINT NOT NULL,
+  "problems" TEXT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_integrity_report_job UNIQUE (job_id)
+);
+CREATE INDEX "idx_integrity_report_path" ON "integrity_report" ("path");
+
+CREATE TABLE IF NOT EXISTS "secret_finding" (
+  "id" BIGINT PRIMARY KEY,
+  "path" TEXT NOT NULL,
+  "blob_id" VARCHAR(40) NOT NULL,
+  "rule" VARCHAR(255) NOT NULL,
+  "line" INTEGER NOT NULL,
+  "preview" TEXT NOT NULL,
+  "action" VARCHAR(20) NOT NULL,
+  "pusher" VARCHAR(255),
+  "status" VARCHAR(20) NOT NULL,
+  "reviewer" VARCHAR(255),
+  "created_at" TIMESTAMP NOT NULL,
+  "reviewed_at" TIMESTAMP
+);
+CREATE INDEX "idx_secret_finding_blob" ON "secret_finding" ("blob_id");
+
+CREATE TABLE IF NOT EXISTS "branch_setting" (
+  "id" BIGINT PRIMARY KEY,
+  "path" TEXT NOT NULL,
+  "delete_on_merge" BOOLEAN NOT NULL,
+  "protected_branches" TEXT NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_branch_setting_path UNIQUE (path)
+);
+
+CREATE TABLE IF NOT EXISTS "tag_protection" (
+  "id" BIGINT PRIMARY KEY,
+  "path" TEXT NOT NULL,
+  "pattern" VARCHAR(255) NOT NULL,
+  "created_by" VARCHAR(255) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_tag_protection_path_pattern UNIQUE (path, pattern)
+);
+
+CREATE TABLE IF NOT EXISTS "release" (
+  "id" BIGINT PRIMARY KEY,
+  "path" TEXT NOT NULL,
+  "tag_name" VARCHAR(255) NOT NULL,
+  "commit_id" VARCHAR(40) NOT NULL,
+  "name" VARCHAR(255) NOT NULL,
+  "notes" TEXT NOT NULL,
+  "changelog" TEXT NOT NULL,
+  "created_by" VARCHAR(255) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_release_path_tag UNIQUE (path, tag_name)
+);
+
+CREATE TABLE IF NOT EXISTS "release_asset" (
+  "id" BIGINT PRIMARY KEY,
+  "release_id" BIGINT NOT NULL,
+  "name" VARCHAR(255) NOT NULL,
+  "content_type" VARCHAR(255) NOT NULL,
+  "size" BIGINT NOT NULL,
+  "oid" VARCHAR(64) NOT NULL,
+  "download_count" BIGINT NOT NULL,
+  "uploaded_by" VARCHAR(255) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_release_asset_name UNIQUE (release_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS "subtree_split" (
+  "id" BIGINT PRIMARY KEY,
+  "path" TEXT NOT NULL,
+  "repo_path" TEXT NOT NULL,
+  "last_commit" VARCHAR(40),
+  "last_split" VARCHAR(40),
+  "created_by" VARCHAR(255) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_subtree_split_path UNIQUE (path),
+  CONSTRAINT uniq_subtree_split_repo_path UNIQUE (repo_path)
+);
+
+CREATE TABLE IF NOT EXISTS "virtual_repo" (
+  "id" BIGINT PRIMARY KEY,
+  "path" TEXT NOT NULL,
+  "root_commit" VARCHAR(40) NOT NULL,
+  "commit_id" VARCHAR(40) NOT NULL,
+  "created_at" TIMESTAMP NOT NULL,
+  "updated_at" TIMESTAMP NOT NULL,
+  CONSTRAINT uniq_virtual_repo_path UNIQUE (path)
+);
+
 
 CREATE TABLE IF NOT EXISTS "builds" (
   "build_id" uuid NOT NULL PRIMARY KEY,
@@ -0,0 +1,127 @@
+use std::future::Future;
+use std::time::Duration;
+
+use common::errors::MegaError;
+use tokio::sync::{mpsc, oneshot};
+
+type Waiter = oneshot::Sender<Result<(), MegaError>>;
+
+// Collects the items published at about the same time and hands them to a flush in one batch,
+// so publishing many messages at once, like the push events of a bulk import, takes a round
+// trip to the broker per batch instead of one per message. The first item of a batch waits up
+// to `linger` for others, and a batch is flushed early once it has `max` items. While a batch is
+// flushed the next one fills up.
+pub(crate) struct Batcher<T> {
+    sender: mpsc::UnboundedSender<(T, Waiter)>,
+}
+
+impl<T: Send + 'static> Batcher<T> {
+    // Start the task flushing the batches, `flush` returns the result of each of their items.
+    pub(crate) fn start<F, Fut>(max: usize, linger: Duration, flush: F) -> Self
+    where
+        F: Fn(Vec<T>) -> Fut + Send + 'static,
+        Fut: Future<Output = Vec<Result<(), MegaError>>> + Send,
+    {
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(T, Waiter)>();
+        tokio::spawn(async move {
+            while let Some(first) = receiver.recv().await {
+                let mut batch = vec![first];
+                let deadline = tokio::time::Instant::now() + linger;
+                while batch.len() < max {
+                    match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                        Ok(Some(item)) => batch.push(item),
+                        _ => break,
+                    }
+                }
+                let (items, waiters): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+                let results = flush(items).await;
+                for (waiter, res) in waiters.into_iter().zip(results) {
+                    let _ = waiter.send(res);
+                }
+            }
+        });
+        Batcher { sender }
+    }
+
+    // Add the item to the next batch, returns once the batch was flushed.
+    pub(crate) async fn add(&self, item: T) -> Result<(), MegaError> {
+        let (waiter, res) = oneshot::channel();
+        self.sender
+            .send((item, waiter))
+            .map_err(|_| MegaError::with_message("the batches are no longer flushed"))?;
+        res.await
+            .map_err(|_| MegaError::with_message("the batch was dropped"))?
+    }
+}
+
+// The result of a flush of `count` items which succeeds or fails as a whole, for each item.
+pub(crate) fn each(count: usize, res: Result<(), MegaError>) -> Vec<Result<(), MegaError>> {
+    match res {
+        Ok(()) => (0..count).map(|_| Ok(())).collect(),
+        Err(e) => {
+            let message = e.to_string();
+            (0..count)
+                .map(|_| Err(MegaError::with_message(&message)))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use common::errors::MegaError;
+
+    use super::{each, Batcher};
+
+    #[tokio::test]
+    async fn test_batcher() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let recorded = batches.clone();
+        let batcher = Arc::new(Batcher::start(
+            3,
+            Duration::from_millis(50),
+            move |items: Vec<i32>| {
+                let recorded = recorded.clone();
+                async move {
+                    let results = items
+                        .iter()
+                        .map(|&item| match item {
+                            4 => Err(MegaError::with_message("broken")),
+                            _ => Ok(()),
+                        })
+                        .collect();
+                    recorded.lock().unwrap().push(items);
+                    results
+                }
+            },
+        ));
+        let handles: Vec<_> = (1..=5)
+            .map(|item| {
+                let batcher = batcher.clone();
+                tokio::spawn(async move { batcher.add(item).await })
+            })
+            .collect();
+        let mut failed = Vec::new();
+        for (item, handle) in (1..=5).zip(handles) {
+            if handle.await.unwrap().is_err() {
+                failed.push(item);
+            }
+        }
+        assert_eq!(failed, [4]);
+        let batches = batches.lock().unwrap();
+        assert!(batches.iter().all(|batch| batch.len() <= 3));
+        assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), 5);
+        assert!(batches.len() < 5);
+    }
+
+    #[test]
+    fn test_each() {
+        assert!(each(2, Ok(())).iter().all(Result::is_ok));
+        let res = each(2, Err(MegaError::with_message("broken")));
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(Result::is_err));
+    }
+}
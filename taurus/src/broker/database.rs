@@ -0,0 +1,275 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use callisto::db_enums::{MessageLane, MessageState};
+use callisto::mq_storage::Model;
+use common::errors::MegaError;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use jupiter::context::Context;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::broker::batch::{self, Batcher};
+use crate::broker::Broker;
+use crate::event::Message;
+
+// How often stored messages which are due are claimed, unless the last claim was full.
+const POLL_INTERVAL: u64 = 2;
+// Messages claimed at once.
+const BATCH: u64 = 100;
+
+// Messages are stored in `mq_storage` before they are delivered and acknowledged once
+// processed, the instances sharing the database share them like a consumer group. Each instance
+// is a consumer which processes up to `mq.concurrency` messages of each lane at once, so bulk
+// events never take the room of user-facing ones: a message published while its lane has room
+// is claimed and processed by it right away, any other one is claimed by the next instance with
+// room. A claim is extended while the message is processed and only its
+// consumer records the outcome, so a message whose instance stopped is delivered again by
+// another one once its claim ran out, and never processed by two of them at the same time.
+pub struct DatabaseBroker {
+    sender: Sender<(Message, Option<OwnedSemaphorePermit>)>,
+    receiver: Receiver<(Message, Option<OwnedSemaphorePermit>)>,
+    context: Context,
+    // the consumer this instance claims messages as
+    consumer: String,
+    // one permit per message processed at the same time, by lane
+    lanes: Arc<Lanes>,
+    // stores the published messages in batches
+    batcher: Batcher<Model>,
+}
+
+struct Lanes {
+    interactive: Arc<Semaphore>,
+    background: Arc<Semaphore>,
+}
+
+impl Lanes {
+    fn permits(&self, lane: MessageLane) -> &Arc<Semaphore> {
+        match lane {
+            MessageLane::Interactive => &self.interactive,
+            MessageLane::Background => &self.background,
+        }
+    }
+}
+
+impl DatabaseBroker {
+    pub fn new(context: Context) -> Self {
+        let (sender, receiver) = unbounded::<(Message, Option<OwnedSemaphorePermit>)>();
+        let concurrency = &context.config.mq.concurrency;
+        let lanes = Arc::new(Lanes {
+            interactive: Arc::new(Semaphore::new(concurrency.interactive.max(1))),
+            background: Arc::new(Semaphore::new(concurrency.background.max(1))),
+        });
+        let config = &context.config.mq.batch;
+        let st = context.services.mq_storage.clone();
+        let batcher = Batcher::start(
+            config.max_messages.max(1),
+            Duration::from_millis(config.linger_ms),
+            move |models: Vec<Model>| {
+                let st = st.clone();
+                async move { batch::each(models.len(), st.insert_messages(models).await) }
+            },
+        );
+        DatabaseBroker {
+            sender,
+            receiver,
+            context,
+            consumer: format!("consumer-{}", Uuid::new_v4()),
+            lanes,
+            batcher,
+        }
+    }
+}
+
+#[async_trait]
+impl Broker for DatabaseBroker {
+    fn start(&self) {
+        tracing::info!("message consumer {} started", self.consumer);
+        let receiver = self.receiver.clone();
+        let context = self.context.clone();
+        let consumer = self.consumer.clone();
+        tokio::spawn(async move {
+            loop {
+                match receiver.recv() {
+                    Ok((msg, permit)) => {
+                        let span =
+                            tracing::info_span!(parent: &msg.span, "mq_process", id = msg.id);
+                        let context = context.clone();
+                        let consumer = consumer.clone();
+                        // The message was stored as claimed by its first attempt.
+                        tokio::spawn(
+                            async move {
+                                process(&context, &consumer, msg, 1).await;
+                                drop(permit);
+                            }
+                            .instrument(span),
+                        );
+                    }
+                    Err(e) => {
+                        // Should not error here.
+                        panic!("Event Loop Panic: {e}");
+                    }
+                }
+            }
+        });
+
+        let context = self.context.clone();
+        let consumer = self.consumer.clone();
+        let lanes = self.lanes.clone();
+        tokio::spawn(async move {
+            loop {
+                // the interactive lane goes first
+                let mut more = false;
+                for lane in [MessageLane::Interactive, MessageLane::Background] {
+                    more |= claim_messages(&context, &consumer, lane, lanes.permits(lane)).await;
+                }
+                if !more {
+                    tokio::time::sleep(Duration::from_secs(POLL_INTERVAL)).await;
+                }
+            }
+        });
+    }
+
+    // Store the message with the others of its batch, then deliver it to this instance if its
+    // lane has room. It stays hidden from other consumers while it is processed, and is
+    // delivered again once `mq.visibility_timeout` seconds passed without its claim being
+    // extended. A message published while its lane is busy is left to any instance with room.
+    // A message which could not be stored is still delivered.
+    async fn publish(&self, msg: Message) {
+        let id = msg.id;
+        let mut model: Model = msg.clone().into();
+        let permit = self
+            .lanes
+            .permits(msg.evt.lane())
+            .clone()
+            .try_acquire_owned()
+            .ok();
+        if permit.is_some() {
+            let timeout = self.context.config.mq.visibility_timeout as i64;
+            model.state = MessageState::Inflight;
+            model.attempts = 1;
+            model.visible_at = Some(model.create_time + chrono::Duration::seconds(timeout));
+            model.claimed_by = Some(self.consumer.clone());
+        }
+        match self.batcher.add(model).await {
+            Ok(()) if permit.is_none() => return,
+            Ok(()) => {}
+            Err(e) => tracing::error!(
+                "Failed to store message {}, it is only delivered once: {}",
+                id,
+                e
+            ),
+        }
+        let _ = self.sender.send((msg, permit));
+    }
+}
+
+// Process the `attempts`-th attempt of the message claimed by `consumer`, extending its claim
+// until it is settled.
+async fn process(context: &Context, consumer: &str, msg: Message, attempts: i32) {
+    let id = msg.id;
+    let st = &context.services.mq_storage;
+    let timeout = context.config.mq.visibility_timeout;
+    let run = msg.process(context);
+    tokio::pin!(run);
+    // the claim is extended long before it is over
+    let mut heartbeat = tokio::time::interval(Duration::from_secs((timeout / 3).max(1)));
+    heartbeat.tick().await;
+    let res = loop {
+        tokio::select! {
+            res = &mut run => break res,
+            _ = heartbeat.tick() => match st.extend_claim(id, consumer, timeout).await {
+                Ok(true) => (),
+                Ok(false) => {
+                    tracing::warn!("Message {} was taken over by another consumer", id)
+                }
+                Err(e) => tracing::error!("Failed to extend the claim of message {}: {}", id, e),
+            }
+        }
+    };
+    settle(context, consumer, id, attempts, res).await;
+}
+
+// Acknowledge the message `id` after its `attempts`-th attempt succeeded, or record the failure
+// for a redelivery, a message which failed `mq.max_attempts` times is dead-lettered until it is
+// requeued. Nothing is recorded if another consumer took the message over.
+async fn settle(
+    context: &Context,
+    consumer: &str,
+    id: i64,
+    attempts: i32,
+    res: Result<(), MegaError>,
+) {
+    let st = &context.services.mq_storage;
+    let res = match res {
+        Ok(()) => st.complete_message(id, consumer).await,
+        Err(e) => match st
+            .fail_message(id, consumer, attempts, &e.to_string(), &context.config.mq)
+            .await
+        {
+            Ok(MessageState::Dead) => {
+                tracing::error!(
+                    "Message {} dead-lettered after {} attempts: {}",
+                    id,
+                    attempts,
+                    e
+                );
+                Ok(())
+            }
+            Ok(_) => {
+                tracing::warn!("Processing message {} failed: {}", id, e);
+                Ok(())
+            }
+            Err(err) => Err(err),
+        },
+    };
+    if let Err(e) = res {
+        tracing::error!("Failed to record the outcome of message {}: {}", id, e);
+    }
+}
+
+// Claim the stored messages of `lane` which are due as many as this instance has room for and
+// process them: pending ones left by busy instances, failed ones once their retry delay is over,
+// and ones whose claim ran out. Returns `false` if there are no more due ones or no room for
+// them.
+async fn claim_messages(
+    context: &Context,
+    consumer: &str,
+    lane: MessageLane,
+    permits: &Arc<Semaphore>,
+) -> bool {
+    let limit = (permits.available_permits() as u64).min(BATCH);
+    if limit == 0 {
+        return false;
+    }
+    let st = &context.services.mq_storage;
+    let claimed = match st
+        .claim_messages(consumer, lane, limit, context.config.mq.visibility_timeout)
+        .await
+    {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            tracing::error!("Failed to claim messages of the {} lane: {}", lane, e);
+            return false;
+        }
+    };
+    let full = claimed.len() as u64 == limit;
+    for model in claimed {
+        // messages published meanwhile may have taken the room, the semaphore is never closed
+        let permit = permits.clone().acquire_owned().await.unwrap();
+        let (id, attempts) = (model.id, model.attempts);
+        let msg: Message = model.into();
+        let context = context.clone();
+        let consumer = consumer.to_owned();
+        tokio::spawn(
+            async move {
+                process(&context, &consumer, msg, attempts).await;
+                drop(permit);
+            }
+            .instrument(tracing::info_span!("mq_process", id)),
+        );
+    }
+    full
+}
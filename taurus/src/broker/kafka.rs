@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use callisto::db_enums::{MessageLane, MessageState};
+use callisto::mq_storage::Model;
+use common::config::MqConfig;
+use common::errors::MegaError;
+use jupiter::context::Context;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+use rdkafka::message::{Header, Headers, Message as _, OwnedHeaders, OwnedMessage};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Offset, TopicPartitionList};
+use tracing::Instrument;
+
+use crate::broker::{retry_delay, topic, Broker, DEAD_TOPIC, TOPICS};
+use crate::event::Message;
+
+// Seconds a message may wait to be sent before publishing it fails.
+const SEND_TIMEOUT: u64 = 30;
+
+// Every type of event has a topic of its own, the message id is the key and the event is the
+// JSON payload, with its category in the `category` header. The producer sends the messages
+// published within `mq.batch.linger_ms` in batches compressed with zstd, which the consumers
+// decompress. The instances consume the topics
+// in one consumer group, and commit the offset of a message once it is processed, so a message
+// is delivered again if its instance stopped before. A failed message is retried in place, which
+// holds up its partition, and published to the dead topic after `mq.max_attempts` attempts.
+pub struct KafkaBroker {
+    producer: FutureProducer,
+    consumer: std::sync::Arc<StreamConsumer>,
+    prefix: String,
+    context: Context,
+}
+
+impl KafkaBroker {
+    pub fn new(context: Context) -> Result<Self, MegaError> {
+        let kafka = &context.config.mq.kafka;
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", &kafka.brokers);
+        for (key, value) in &kafka.properties {
+            config.set(key, value);
+        }
+        let mut producer_config = config.clone();
+        producer_config.set("message.timeout.ms", (SEND_TIMEOUT * 1000).to_string());
+        // librdkafka batches and compresses the messages, unless the properties say otherwise
+        let batch = &context.config.mq.batch;
+        let compression = if batch.compress { "zstd" } else { "none" };
+        for (key, value) in [
+            ("linger.ms", batch.linger_ms.to_string()),
+            ("batch.num.messages", batch.max_messages.max(1).to_string()),
+            ("compression.type", compression.to_owned()),
+        ] {
+            if !kafka.properties.contains_key(key) {
+                producer_config.set(key, value);
+            }
+        }
+        let producer: FutureProducer = producer_config.create().map_err(kafka_error)?;
+        let consumer: StreamConsumer = config
+            .set("group.id", &kafka.group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .map_err(kafka_error)?;
+        let topics: Vec<String> = TOPICS
+            .iter()
+            .map(|topic| format!("{}{}", kafka.topic_prefix, topic))
+            .collect();
+        let topics: Vec<&str> = topics.iter().map(String::as_str).collect();
+        consumer.subscribe(&topics).map_err(kafka_error)?;
+        Ok(KafkaBroker {
+            producer,
+            consumer: std::sync::Arc::new(consumer),
+            prefix: kafka.topic_prefix.clone(),
+            context,
+        })
+    }
+
+    async fn send(
+        &self,
+        topic: &str,
+        key: &str,
+        payload: &str,
+        headers: OwnedHeaders,
+    ) -> Result<(), MegaError> {
+        let topic = format!("{}{}", self.prefix, topic);
+        let record = FutureRecord::to(&topic)
+            .key(key)
+            .payload(payload)
+            .headers(headers);
+        self.producer
+            .send(record, Duration::from_secs(SEND_TIMEOUT))
+            .await
+            .map(|_| ())
+            .map_err(|(err, _)| kafka_error(err))
+    }
+
+    // Process the message until it succeeds or failed `mq.max_attempts` times, then publish it
+    // to the dead topic.
+    async fn consume(&self, msg: &OwnedMessage) {
+        let Some(model) = decode(msg) else {
+            tracing::error!(
+                "Skipping undecodable message at {}:{}",
+                msg.topic(),
+                msg.offset()
+            );
+            return;
+        };
+        let id = model.id;
+        let config: &MqConfig = &self.context.config.mq;
+        let mut attempts = 0;
+        let error = loop {
+            attempts += 1;
+            let message: Message = model.clone().into();
+            let span = tracing::info_span!("mq_process", id);
+            let res = message.process(&self.context).instrument(span).await;
+            match res {
+                Ok(()) => return,
+                Err(e) if attempts >= config.max_attempts.max(1) => break e,
+                Err(e) => {
+                    tracing::warn!("Processing message {} failed: {}", id, e);
+                    let delay = retry_delay(attempts, config);
+                    tokio::time::sleep(Duration::from_secs(delay)).await;
+                }
+            }
+        };
+        tracing::error!(
+            "Message {} dead-lettered after {} attempts: {}",
+            id,
+            attempts,
+            error
+        );
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: "category",
+                value: model.category.as_deref(),
+            })
+            .insert(Header {
+                key: "error",
+                value: Some(&error.to_string()),
+            });
+        let payload = model.content.unwrap_or_default();
+        if let Err(e) = self
+            .send(DEAD_TOPIC, &id.to_string(), &payload, headers)
+            .await
+        {
+            tracing::error!("Failed to dead-letter message {}: {}", id, e);
+        }
+    }
+}
+
+#[async_trait]
+impl Broker for KafkaBroker {
+    fn start(&self) {
+        let broker = KafkaBroker {
+            producer: self.producer.clone(),
+            consumer: self.consumer.clone(),
+            prefix: self.prefix.clone(),
+            context: self.context.clone(),
+        };
+        tokio::spawn(async move {
+            loop {
+                let msg = match broker.consumer.recv().await {
+                    Ok(msg) => msg.detach(),
+                    Err(e) => {
+                        tracing::error!("Failed to receive a message from kafka: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                broker.consume(&msg).await;
+                let mut offsets = TopicPartitionList::new();
+                let next = Offset::Offset(msg.offset() + 1);
+                let res = offsets
+                    .add_partition_offset(msg.topic(), msg.partition(), next)
+                    .and_then(|()| broker.consumer.commit(&offsets, CommitMode::Async));
+                if let Err(e) = res {
+                    tracing::error!(
+                        "Failed to commit the offset of {}:{}: {}",
+                        msg.topic(),
+                        msg.offset(),
+                        e
+                    );
+                }
+            }
+        });
+    }
+
+    // Publish the message to the topic of its event, a message which could not be published
+    // is processed here once.
+    async fn publish(&self, msg: Message) {
+        let id = msg.id;
+        let topic = topic(&msg.evt);
+        let model: Model = msg.clone().into();
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "category",
+            value: model.category.as_deref(),
+        });
+        let payload = model.content.unwrap_or_default();
+        if let Err(e) = self.send(topic, &id.to_string(), &payload, headers).await {
+            tracing::error!(
+                "Failed to publish message {}, it is only processed here once: {}",
+                id,
+                e
+            );
+            let span = tracing::info_span!(parent: &msg.span, "mq_process", id);
+            let context = self.context.clone();
+            tokio::spawn(
+                async move {
+                    if let Err(e) = msg.process(&context).await {
+                        tracing::warn!("Processing message {} failed: {}", id, e);
+                    }
+                }
+                .instrument(span),
+            );
+        }
+    }
+}
+
+// The message as it is stored in the database, so it is decoded like stored ones.
+fn decode(msg: &OwnedMessage) -> Option<Model> {
+    let id = std::str::from_utf8(msg.key()?).ok()?.parse().ok()?;
+    let category = msg.headers().and_then(|headers| {
+        headers
+            .iter()
+            .find(|header| header.key == "category")
+            .and_then(|header| header.value)
+            .and_then(|value| String::from_utf8(value.to_vec()).ok())
+    });
+    let content = msg
+        .payload()
+        .and_then(|payload| String::from_utf8(payload.to_vec()).ok());
+    let create_time = msg
+        .timestamp()
+        .to_millis()
+        .and_then(chrono::DateTime::from_timestamp_millis)
+        .unwrap_or_else(chrono::Utc::now)
+        .naive_utc();
+    Some(Model {
+        id,
+        category,
+        create_time,
+        content,
+        state: MessageState::Pending,
+        attempts: 0,
+        visible_at: None,
+        last_error: None,
+        claimed_by: None,
+        // lanes are the ones of the database broker
+        lane: MessageLane::Interactive,
+    })
+}
+
+fn kafka_error(err: impl std::fmt::Display) -> MegaError {
+    MegaError::with_message(&format!("kafka: {}", err))
+}
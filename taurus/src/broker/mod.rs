@@ -0,0 +1,88 @@
+use async_trait::async_trait;
+use common::config::MqBroker;
+use jupiter::context::Context;
+
+#[cfg(any(feature = "kafka", feature = "nats"))]
+use crate::event::EventType;
+use crate::event::Message;
+
+mod batch;
+pub mod database;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "nats")]
+pub mod nats;
+
+// Topics of the types of events in the brokers with topics, after their prefix.
+#[cfg(any(feature = "kafka", feature = "nats"))]
+const TOPICS: [&str; 8] = [
+    "api_request",
+    "github_webhook",
+    "repo",
+    "policy",
+    "push",
+    "mr_updated",
+    "issue",
+    "repo_created",
+];
+// Topic of the messages which failed `mq.max_attempts` times.
+#[cfg(any(feature = "kafka", feature = "nats"))]
+const DEAD_TOPIC: &str = "dead";
+
+// Publishes messages and delivers them to `EventType::process`, selected by `mq.broker`.
+// Messages are delivered at least once, a broker retries a message whose processing failed
+// until `mq.max_attempts` is reached and dead-letters it.
+#[async_trait]
+pub trait Broker: Send + Sync {
+    // Start delivering messages, once.
+    fn start(&self);
+
+    // Publish the message, it is durable once this returns unless publishing failed, which
+    // is logged.
+    async fn publish(&self, msg: Message);
+}
+
+// The broker configured by `mq.broker`.
+pub(crate) async fn from_config(ctx: &Context) -> Box<dyn Broker> {
+    match ctx.config.mq.broker {
+        MqBroker::Database => Box::new(database::DatabaseBroker::new(ctx.clone())),
+        #[cfg(feature = "kafka")]
+        MqBroker::Kafka => {
+            Box::new(kafka::KafkaBroker::new(ctx.clone()).expect("Invalid kafka configuration"))
+        }
+        #[cfg(feature = "nats")]
+        MqBroker::Nats => Box::new(
+            nats::NatsBroker::new(ctx.clone())
+                .await
+                .expect("Invalid nats configuration"),
+        ),
+        #[allow(unreachable_patterns)]
+        broker => panic!(
+            "mq.broker is {:?}, but mega is built without its feature",
+            broker
+        ),
+    }
+}
+
+// Topic of the type of `evt`, one of `TOPICS`.
+#[cfg(any(feature = "kafka", feature = "nats"))]
+fn topic(evt: &EventType) -> &'static str {
+    match evt {
+        EventType::ApiRequest(_) => TOPICS[0],
+        EventType::GithubWebhook(_) => TOPICS[1],
+        EventType::Repo(_) => TOPICS[2],
+        EventType::Policy(_) => TOPICS[3],
+        EventType::Push(_) => TOPICS[4],
+        EventType::MrUpdated(_) => TOPICS[5],
+        EventType::Issue(_) => TOPICS[6],
+        EventType::RepoCreated(_) => TOPICS[7],
+        EventType::ErrorEvent => DEAD_TOPIC,
+    }
+}
+
+// Seconds before the next attempt after the `attempts`-th one failed, doubled with every attempt.
+#[cfg(any(feature = "kafka", feature = "nats"))]
+fn retry_delay(attempts: u32, config: &common::config::MqConfig) -> u64 {
+    let exp = attempts.clamp(1, 16) - 1;
+    config.retry_delay.saturating_mul(1 << exp)
+}
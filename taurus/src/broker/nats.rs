@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use async_nats::jetstream::consumer::{pull, AckPolicy, PullConsumer};
+use async_nats::jetstream::{self, AckKind};
+use async_nats::HeaderMap;
+use async_trait::async_trait;
+use callisto::db_enums::{MessageLane, MessageState};
+use callisto::mq_storage::Model;
+use common::errors::MegaError;
+use futures::StreamExt;
+use jupiter::context::Context;
+use serde::{Deserialize, Serialize};
+use tracing::Instrument;
+
+use crate::broker::batch::{self, Batcher};
+use crate::broker::{retry_delay, topic, Broker, DEAD_TOPIC, TOPICS};
+use crate::event::Message;
+
+// Header with the id of a message, JetStream drops a message published twice with the same id.
+const ID_HEADER: &str = "Nats-Msg-Id";
+// Header with the number of messages in a batch, whose payload is the JSON array of them.
+const BATCH_HEADER: &str = "Mega-Batch";
+// Header of a payload compressed with zstd.
+const ENCODING_HEADER: &str = "Content-Encoding";
+
+// Every type of event is published to a subject of its own in one stream, the subjects of the
+// stream are `{prefix}>`. The event is the JSON payload, with its category in the `category`
+// header. The messages of a subject published within `mq.batch.linger_ms` are published as one
+// batch, compressed with zstd, and split up again by the consumers. The instances share one
+// durable consumer, a message is acknowledged once it is processed and redelivered if it was
+// not within `mq.visibility_timeout` seconds. A failed message is redelivered after the retry
+// delay, with the other messages of its batch, and published to the dead subject after
+// `mq.max_attempts` attempts.
+pub struct NatsBroker {
+    publisher: Publisher,
+    consumer: PullConsumer,
+    batcher: std::sync::Arc<Batcher<(&'static str, Model)>>,
+    context: Context,
+}
+
+#[derive(Clone)]
+struct Publisher {
+    jetstream: jetstream::Context,
+    prefix: String,
+    compress: bool,
+}
+
+// A message of a batch.
+#[derive(Serialize, Deserialize)]
+struct Batched {
+    id: i64,
+    category: Option<String>,
+    content: Option<String>,
+    // unix timestamp in milliseconds
+    create_time: i64,
+}
+
+impl NatsBroker {
+    pub async fn new(context: Context) -> Result<Self, MegaError> {
+        let nats = &context.config.mq.nats;
+        let options = if nats.credentials_file.is_empty() {
+            async_nats::ConnectOptions::new()
+        } else {
+            async_nats::ConnectOptions::with_credentials_file(&nats.credentials_file)
+                .await
+                .map_err(nats_error)?
+        };
+        let client = options.connect(&nats.url).await.map_err(nats_error)?;
+        let jetstream = jetstream::new(client);
+        let stream = jetstream
+            .get_or_create_stream(jetstream::stream::Config {
+                name: nats.stream.clone(),
+                subjects: vec![format!("{}>", nats.subject_prefix)],
+                ..Default::default()
+            })
+            .await
+            .map_err(nats_error)?;
+        let consumer = stream
+            .get_or_create_consumer(
+                &nats.consumer,
+                pull::Config {
+                    durable_name: Some(nats.consumer.clone()),
+                    ack_policy: AckPolicy::Explicit,
+                    ack_wait: Duration::from_secs(context.config.mq.visibility_timeout),
+                    // the dead subject is in the stream too
+                    filter_subjects: TOPICS
+                        .iter()
+                        .map(|topic| format!("{}{}", nats.subject_prefix, topic))
+                        .collect(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .map_err(nats_error)?;
+        let batch = &context.config.mq.batch;
+        let publisher = Publisher {
+            jetstream,
+            prefix: nats.subject_prefix.clone(),
+            compress: batch.compress,
+        };
+        let flusher = publisher.clone();
+        let batcher = Batcher::start(
+            batch.max_messages.max(1),
+            Duration::from_millis(batch.linger_ms),
+            move |items: Vec<(&'static str, Model)>| {
+                let flusher = flusher.clone();
+                async move { flusher.flush(items).await }
+            },
+        );
+        Ok(NatsBroker {
+            publisher,
+            consumer,
+            batcher: std::sync::Arc::new(batcher),
+            context,
+        })
+    }
+
+    // Process the messages, one or a batch, and acknowledge them, ask for a redelivery after the
+    // retry delay, or dead-letter the failed ones once they failed `mq.max_attempts` times.
+    async fn consume(&self, msg: jetstream::Message) {
+        let attempts = msg.info().map_or(1, |info| info.delivered.max(1) as u32);
+        let Some(models) = decode(&msg) else {
+            tracing::error!("Skipping undecodable message on {}", msg.subject);
+            if let Err(e) = msg.ack_with(AckKind::Term).await {
+                tracing::error!("Failed to drop an undecodable message: {}", e);
+            }
+            return;
+        };
+        let config = &self.context.config.mq;
+        let mut failed = Vec::new();
+        for model in models {
+            let id = model.id;
+            let message: Message = model.clone().into();
+            let span = tracing::info_span!("mq_process", id);
+            if let Err(e) = message.process(&self.context).instrument(span).await {
+                failed.push((model, e));
+            }
+        }
+        let ack = if failed.is_empty() {
+            AckKind::Ack
+        } else if attempts >= config.max_attempts.max(1) {
+            for (model, e) in failed {
+                self.dead_letter(model, e, attempts).await;
+            }
+            AckKind::Term
+        } else {
+            for (model, e) in &failed {
+                tracing::warn!("Processing message {} failed: {}", model.id, e);
+            }
+            let delay = retry_delay(attempts, config);
+            AckKind::Nak(Some(Duration::from_secs(delay)))
+        };
+        if let Err(e) = msg.ack_with(ack).await {
+            tracing::error!("Failed to acknowledge message on {}: {}", msg.subject, e);
+        }
+    }
+
+    async fn dead_letter(&self, model: Model, error: MegaError, attempts: u32) {
+        let id = model.id;
+        tracing::error!(
+            "Message {} dead-lettered after {} attempts: {}",
+            id,
+            attempts,
+            error
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(ID_HEADER, id.to_string().as_str());
+        if let Some(category) = &model.category {
+            headers.insert("category", category.as_str());
+        }
+        headers.insert("error", error.to_string().as_str());
+        let payload = model.content.unwrap_or_default().into_bytes();
+        if let Err(e) = self.publisher.send(DEAD_TOPIC, headers, payload).await {
+            tracing::error!("Failed to dead-letter message {}: {}", id, e);
+        }
+    }
+}
+
+impl Publisher {
+    async fn send(
+        &self,
+        topic: &str,
+        headers: HeaderMap,
+        payload: Vec<u8>,
+    ) -> Result<(), MegaError> {
+        let subject = format!("{}{}", self.prefix, topic);
+        self.jetstream
+            .publish_with_headers(subject, headers, payload.into())
+            .await
+            .map_err(nats_error)?
+            .await
+            .map_err(nats_error)?;
+        Ok(())
+    }
+
+    // Publish the messages of a batch to the subjects of their events, one message or batch
+    // per subject, returns the result of each.
+    async fn flush(&self, items: Vec<(&'static str, Model)>) -> Vec<Result<(), MegaError>> {
+        let mut results: Vec<Result<(), MegaError>> = items.iter().map(|_| Ok(())).collect();
+        let mut subjects: BTreeMap<&str, Vec<(usize, Model)>> = BTreeMap::new();
+        for (index, (topic, model)) in items.into_iter().enumerate() {
+            subjects.entry(topic).or_default().push((index, model));
+        }
+        for (topic, models) in subjects {
+            let (indexes, models): (Vec<_>, Vec<_>) = models.into_iter().unzip();
+            let res = match self.encode(models) {
+                Ok((headers, payload)) => self.send(topic, headers, payload).await,
+                Err(e) => Err(e),
+            };
+            let results_of_subject = batch::each(indexes.len(), res);
+            for (index, res) in indexes.into_iter().zip(results_of_subject) {
+                results[index] = res;
+            }
+        }
+        results
+    }
+
+    // The headers and payload of one message, or of a batch of several.
+    fn encode(&self, mut models: Vec<Model>) -> Result<(HeaderMap, Vec<u8>), MegaError> {
+        let mut headers = HeaderMap::new();
+        if models.len() == 1 {
+            let model = models.remove(0);
+            headers.insert(ID_HEADER, model.id.to_string().as_str());
+            if let Some(category) = &model.category {
+                headers.insert("category", category.as_str());
+            }
+            return Ok((headers, model.content.unwrap_or_default().into_bytes()));
+        }
+        let (first, last) = (models[0].id, models[models.len() - 1].id);
+        headers.insert(ID_HEADER, format!("batch-{}-{}", first, last).as_str());
+        headers.insert(BATCH_HEADER, models.len().to_string().as_str());
+        let batch: Vec<Batched> = models
+            .into_iter()
+            .map(|model| Batched {
+                id: model.id,
+                category: model.category,
+                content: model.content,
+                create_time: model.create_time.and_utc().timestamp_millis(),
+            })
+            .collect();
+        let payload = serde_json::to_vec(&batch).map_err(nats_error)?;
+        if !self.compress {
+            return Ok((headers, payload));
+        }
+        headers.insert(ENCODING_HEADER, "zstd");
+        let payload = zstd::encode_all(payload.as_slice(), 0).map_err(nats_error)?;
+        Ok((headers, payload))
+    }
+}
+
+#[async_trait]
+impl Broker for NatsBroker {
+    fn start(&self) {
+        let broker = NatsBroker {
+            publisher: self.publisher.clone(),
+            consumer: self.consumer.clone(),
+            batcher: self.batcher.clone(),
+            context: self.context.clone(),
+        };
+        tokio::spawn(async move {
+            loop {
+                let mut messages = match broker.consumer.messages().await {
+                    Ok(messages) => messages,
+                    Err(e) => {
+                        tracing::error!("Failed to consume messages from nats: {}", e);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                while let Some(msg) = messages.next().await {
+                    match msg {
+                        Ok(msg) => broker.consume(msg).await,
+                        Err(e) => tracing::error!("Failed to receive a message from nats: {}", e),
+                    }
+                }
+            }
+        });
+    }
+
+    // Publish the message to the subject of its event with the others of its batch, a message
+    // which could not be published is processed here once.
+    async fn publish(&self, msg: Message) {
+        let id = msg.id;
+        let topic = topic(&msg.evt);
+        let model: Model = msg.clone().into();
+        if let Err(e) = self.batcher.add((topic, model)).await {
+            tracing::error!(
+                "Failed to publish message {}, it is only processed here once: {}",
+                id,
+                e
+            );
+            let span = tracing::info_span!(parent: &msg.span, "mq_process", id);
+            let context = self.context.clone();
+            tokio::spawn(
+                async move {
+                    if let Err(e) = msg.process(&context).await {
+                        tracing::warn!("Processing message {} failed: {}", id, e);
+                    }
+                }
+                .instrument(span),
+            );
+        }
+    }
+}
+
+// The messages as they are stored in the database, so they are decoded like stored ones.
+fn decode(msg: &jetstream::Message) -> Option<Vec<Model>> {
+    let headers = msg.headers.as_ref()?;
+    let create_time = msg
+        .info()
+        .ok()
+        .and_then(|info| chrono::DateTime::from_timestamp(info.published.unix_timestamp(), 0))
+        .unwrap_or_else(chrono::Utc::now)
+        .naive_utc();
+    if headers.get(BATCH_HEADER).is_none() {
+        let id = headers.get(ID_HEADER)?.as_str().parse().ok()?;
+        let category = headers
+            .get("category")
+            .map(|value| value.as_str().to_owned());
+        let content = String::from_utf8(msg.payload.to_vec()).ok();
+        return Some(vec![model(id, category, content, create_time)]);
+    }
+    let payload = match headers.get(ENCODING_HEADER).map(|value| value.as_str()) {
+        Some("zstd") => zstd::decode_all(&msg.payload[..]).ok()?,
+        Some(_) => return None,
+        None => msg.payload.to_vec(),
+    };
+    let batch: Vec<Batched> = serde_json::from_slice(&payload).ok()?;
+    Some(
+        batch
+            .into_iter()
+            .map(|batched| {
+                let create_time = chrono::DateTime::from_timestamp_millis(batched.create_time)
+                    .map_or(create_time, |time| time.naive_utc());
+                model(batched.id, batched.category, batched.content, create_time)
+            })
+            .collect(),
+    )
+}
+
+fn model(
+    id: i64,
+    category: Option<String>,
+    content: Option<String>,
+    create_time: chrono::NaiveDateTime,
+) -> Model {
+    Model {
+        id,
+        category,
+        create_time,
+        content,
+        state: MessageState::Pending,
+        attempts: 0,
+        visible_at: None,
+        last_error: None,
+        claimed_by: None,
+        // lanes are the ones of the database broker
+        lane: MessageLane::Interactive,
+    }
+}
+
+fn nats_error(err: impl std::fmt::Display) -> MegaError {
+    MegaError::with_message(&format!("nats: {}", err))
+}
@@ -1,4 +1,5 @@
 use common::config::Config;
+use common::errors::MegaError;
 use serde::{Deserialize, Serialize};
 use async_trait::async_trait;
 
@@ -44,18 +45,20 @@ impl std::fmt::Display for ApiRequestEvent {
 
 #[async_trait]
 impl EventBase for ApiRequestEvent {
-    async fn process(&self) {
+    async fn process(&self) -> Result<(), MegaError> {
         tracing::info!("Handling Api Request event: [{}]", &self);
+        Ok(())
     }
 }
 
 impl ApiRequestEvent {
-    // Create and enqueue this event.
-    pub fn notify(api: ApiType, config: &Config) {
+    // Create and enqueue this event, it is stored before this returns.
+    pub async fn notify(api: ApiType, config: &Config) {
         get_mq().send(EventType::ApiRequest(ApiRequestEvent {
             api,
             config: config.clone(),
-        }));
+        }))
+        .await;
     }
 }
 
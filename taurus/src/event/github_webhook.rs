@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use common::errors::MegaError;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use crate::event::{EventBase, EventType};
@@ -35,19 +36,21 @@ impl std::fmt::Display for GithubWebhookEvent {
 
 #[async_trait]
 impl EventBase for GithubWebhookEvent {
-    async fn process(&self) {
+    async fn process(&self) -> Result<(), MegaError> {
         tracing::info!("Processing: [{}]", &self);
         tracing::info!("Payload: {:#?}", &self.payload);
+        Ok(())
     }
 }
 
 impl GithubWebhookEvent {
-    // Create and enqueue this event.
-    pub fn notify(_type: WebhookType, payload: Value) {
+    // Create and enqueue this event, it is stored before this returns.
+    pub async fn notify(_type: WebhookType, payload: Value) {
         get_mq().send(EventType::GithubWebhook(GithubWebhookEvent {
             _type,
             payload,
-        }));
+        }))
+        .await;
     }
 }
 
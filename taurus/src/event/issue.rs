@@ -0,0 +1,96 @@
+use async_trait::async_trait;
+use callisto::mega_issue;
+use common::errors::MegaError;
+use jupiter::context::Context;
+use sea_orm::ConnectionTrait;
+use serde::{Deserialize, Serialize};
+
+use crate::event::schema::{Envelope, EventSchema, Field, SchemaVersion, TypedEvent};
+use crate::{event::EventBase, event::EventType, outbox};
+
+/// # Issue Event
+///
+/// Emitted when an issue is opened, closed or reopened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssueEvent {
+    pub link: String,
+    pub title: String,
+    pub action: IssueAction,
+    /// Name of the user who performed the change
+    pub operator: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueAction {
+    Opened,
+    Closed,
+    Reopened,
+}
+
+impl TypedEvent for IssueEvent {
+    const SCHEMA: &'static EventSchema = &EventSchema {
+        name: "issue",
+        versions: &[SchemaVersion {
+            version: 1,
+            fields: &[
+                Field::required("link"),
+                Field::required("title"),
+                Field::required("action"),
+                Field::required("operator"),
+            ],
+        }],
+    };
+}
+
+impl std::fmt::Display for IssueEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Issue Event: {} {:?} by {}",
+            self.link, self.action, self.operator
+        )
+    }
+}
+
+#[async_trait]
+impl EventBase for IssueEvent {
+    async fn process(&self) -> Result<(), MegaError> {
+        tracing::info!("Handling Issue event: [{}]", &self);
+        Ok(())
+    }
+}
+
+impl IssueEvent {
+    // Stage this event in the transaction `conn` which changes the issue, see `outbox::stage`.
+    pub async fn stage(
+        context: &Context,
+        conn: &impl ConnectionTrait,
+        issue: &mega_issue::Model,
+        action: IssueAction,
+        operator: &str,
+    ) -> Result<(), MegaError> {
+        let evt = EventType::Issue(IssueEvent {
+            link: issue.link.clone(),
+            title: issue.title.clone(),
+            action,
+            operator: operator.to_owned(),
+        });
+        outbox::stage(context, conn, evt).await
+    }
+}
+
+// For storing the data into database.
+impl From<IssueEvent> for serde_json::Value {
+    fn from(value: IssueEvent) -> Self {
+        Envelope::wrap(&value)
+    }
+}
+
+impl TryFrom<serde_json::Value> for IssueEvent {
+    type Error = crate::event::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        Envelope::unwrap(value)
+    }
+}
@@ -3,20 +3,45 @@ use std::fmt::Display;
 use api_request::ApiRequestEvent;
 
 use async_trait::async_trait;
+use callisto::db_enums::{MessageLane, MessageState};
 use chrono::{DateTime, Utc};
+use common::errors::MegaError;
+use jupiter::context::Context;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use thiserror::Error;
 use github_webhook::GithubWebhookEvent;
+use issue::IssueEvent;
+use mr_updated::MrUpdatedEvent;
+use policy::PolicyEvent;
+use push::PushEvent;
+use repo::RepoEvent;
+use repo_created::RepoCreatedEvent;
+
+use crate::metrics;
+use crate::webhook;
 
 pub mod api_request;
 pub mod github_webhook;
+pub mod issue;
+pub mod mr_updated;
+pub mod policy;
+pub mod push;
+pub mod repo;
+pub mod repo_created;
+pub mod schema;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum EventType {
     ApiRequest(ApiRequestEvent),
     GithubWebhook(GithubWebhookEvent),
+    Repo(RepoEvent),
+    Policy(PolicyEvent),
+    Push(PushEvent),
+    MrUpdated(MrUpdatedEvent),
+    Issue(IssueEvent),
+    RepoCreated(RepoCreatedEvent),
 
     // Reserved
     ErrorEvent,
@@ -27,12 +52,16 @@ pub struct Message {
     pub(crate) id: i64,
     pub(crate) create_time: DateTime<Utc>,
     pub(crate) evt: EventType,
+    // Span the message was published in, it is processed in a span below it.
+    pub(crate) span: tracing::Span,
 }
 
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("Error converting from database")]
     MismatchedData(#[from] serde_json::error::Error),
+    #[error("Event of the schema {0} is not the expected one")]
+    UnexpectedSchema(String),
 }
 
 #[async_trait]
@@ -40,7 +69,8 @@ pub trait EventBase:
     Send + Sync + std::fmt::Display + Into<serde_json::Value> + TryFrom<serde_json::Value>
 {
     // defines the callback function for this event.
-    async fn process(&self);
+    // A message whose event fails is retried until `mq.max_attempts` is reached.
+    async fn process(&self) -> Result<(), MegaError>;
 }
 
 impl Display for EventType {
@@ -50,7 +80,7 @@ impl Display for EventType {
 }
 
 impl EventType {
-    pub(crate) async fn process(&self) {
+    pub(crate) async fn process(&self) -> Result<(), MegaError> {
         match self {
             // I can't easily add a trait bound for the enum members,
             // so you have to manually add a process logic for your event here.
@@ -58,15 +88,86 @@ impl EventType {
             // EventType::SomeOtherEvent(xxx) => xxx.process().await,
 
             EventType::GithubWebhook(evt) => evt.process().await,
+            EventType::Repo(evt) => evt.process().await,
+            EventType::Policy(evt) => evt.process().await,
+            EventType::Push(evt) => evt.process().await,
+            EventType::MrUpdated(evt) => evt.process().await,
+            EventType::Issue(evt) => evt.process().await,
+            EventType::RepoCreated(evt) => evt.process().await,
 
             // This won't happen unless failed to load events from database.
             // And that's because of a event conversion error.
             // You should recheck yout conversion code logic.
-            EventType::ErrorEvent => panic!("Got error event"),
+            EventType::ErrorEvent => Err(MegaError::with_message("event can not be decoded")),
+        }
+    }
+}
+
+impl EventType {
+    // The category messages of the event are stored and counted by.
+    pub(crate) fn category(&self) -> &'static str {
+        match self {
+            EventType::ApiRequest(_) => "ApiRequestEvent",
+            EventType::GithubWebhook(_) => "GithubWebhookEvent",
+            EventType::Repo(_) => "RepoEvent",
+            EventType::Policy(_) => "PolicyEvent",
+            EventType::Push(_) => "PushEvent",
+            EventType::MrUpdated(_) => "MrUpdatedEvent",
+            EventType::Issue(_) => "IssueEvent",
+            EventType::RepoCreated(_) => "RepoCreatedEvent",
+
+            #[allow(unreachable_patterns)]
+            _ => "Unknown",
+        }
+    }
+
+    // The lane messages of the event are processed in: bulk events which nobody waits for are
+    // kept from holding up the others.
+    pub(crate) fn lane(&self) -> MessageLane {
+        match self {
+            EventType::ApiRequest(_) | EventType::GithubWebhook(_) => MessageLane::Background,
+            _ => MessageLane::Interactive,
+        }
+    }
+
+    // Store deliveries of typed events to the webhooks subscribed to them, or only to the webhook
+    // `only`. `id` is the id of the message, a webhook gets one delivery of it.
+    pub(crate) async fn deliver(
+        &self,
+        context: &Context,
+        id: i64,
+        only: Option<i64>,
+    ) -> Result<(), MegaError> {
+        match self {
+            EventType::Push(evt) => {
+                webhook::dispatch(context, evt, Some(evt.path.as_str()), id, only).await
+            }
+            EventType::MrUpdated(evt) => {
+                webhook::dispatch(context, evt, Some(evt.path.as_str()), id, only).await
+            }
+            EventType::Issue(evt) => webhook::dispatch(context, evt, None, id, only).await,
+            EventType::RepoCreated(evt) => {
+                webhook::dispatch(context, evt, Some(evt.path.as_str()), id, only).await
+            }
+            _ => Ok(()),
         }
     }
 }
 
+impl Message {
+    // Run the callback of the event, then deliver it to the webhooks. The id of the message is
+    // the idempotency key of the event, it stays the same when the message is delivered again or
+    // replayed.
+    pub(crate) async fn process(&self, context: &Context) -> Result<(), MegaError> {
+        let res = match self.evt.process().await {
+            Ok(()) => self.evt.deliver(context, self.id, None).await,
+            Err(e) => Err(e),
+        };
+        metrics::record(self.evt.category(), res.is_ok());
+        res
+    }
+}
+
 impl Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -81,15 +182,18 @@ impl From<Message> for callisto::mq_storage::Model {
     fn from(val: Message) -> Self {
         use callisto::mq_storage::Model;
 
-        let category = match val.evt {
-            EventType::ApiRequest(_) => Some(String::from("ApiRequestEvent")),
-
-            #[allow(unreachable_patterns)]
-            _ => Some(String::from("Unknown")),
-        };
+        let category = Some(val.evt.category().to_owned());
+        let lane = val.evt.lane();
 
         let content: Value = match val.evt {
             EventType::ApiRequest(evt) => evt.into(),
+            EventType::GithubWebhook(evt) => evt.into(),
+            EventType::Repo(evt) => evt.into(),
+            EventType::Policy(evt) => evt.into(),
+            EventType::Push(evt) => evt.into(),
+            EventType::MrUpdated(evt) => evt.into(),
+            EventType::Issue(evt) => evt.into(),
+            EventType::RepoCreated(evt) => evt.into(),
 
             #[allow(unreachable_patterns)]
             _ => Value::Null,
@@ -100,6 +204,12 @@ impl From<Message> for callisto::mq_storage::Model {
             category,
             create_time: val.create_time.naive_utc(),
             content: Some(content.to_string()),
+            state: MessageState::Pending,
+            attempts: 0,
+            visible_at: None,
+            last_error: None,
+            claimed_by: None,
+            lane,
         }
     }
 }
@@ -108,19 +218,30 @@ impl From<callisto::mq_storage::Model> for Message {
     fn from(value: callisto::mq_storage::Model) -> Self {
         let id = value.id;
         let create_time = value.create_time.and_utc();
-        let evt = match value.category.unwrap().as_str() {
-            "ApiRequestEvent" => {
-                if let Some(s) = value.content {
-                    let evt = serde_json::from_str(&s).unwrap();
-                    EventType::ApiRequest(evt)
-                } else {
-                    EventType::ErrorEvent
-                }
-            },
+        // messages which can not be decoded fail and end up dead-lettered
+        let content = value.content.unwrap_or_default();
+        let evt = match value.category.as_deref().unwrap_or_default() {
+            "ApiRequestEvent" => serde_json::from_str(&content)
+                .map_or(EventType::ErrorEvent, EventType::ApiRequest),
+            "GithubWebhookEvent" => serde_json::from_str(&content)
+                .map_or(EventType::ErrorEvent, EventType::GithubWebhook),
+            "RepoEvent" => serde_json::from_str(&content)
+                .map_or(EventType::ErrorEvent, EventType::Repo),
+            "PolicyEvent" => serde_json::from_str(&content)
+                .map_or(EventType::ErrorEvent, EventType::Policy),
+            // typed events are decoded from their envelope, whatever its version
+            "PushEvent" => typed(&content).map_or(EventType::ErrorEvent, EventType::Push),
+            "MrUpdatedEvent" => typed(&content).map_or(EventType::ErrorEvent, EventType::MrUpdated),
+            "IssueEvent" => typed(&content).map_or(EventType::ErrorEvent, EventType::Issue),
+            "RepoCreatedEvent" => typed(&content).map_or(EventType::ErrorEvent, EventType::RepoCreated),
 
             _ => EventType::ErrorEvent
         };
 
-        Self { id, create_time, evt }
+        Self { id, create_time, evt, span: tracing::Span::none() }
     }
 }
+
+fn typed<T: schema::TypedEvent>(content: &str) -> Result<T, Error> {
+    schema::Envelope::unwrap(serde_json::from_str(content)?)
+}
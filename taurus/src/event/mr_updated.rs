@@ -0,0 +1,91 @@
+use async_trait::async_trait;
+use common::errors::MegaError;
+use serde::{Deserialize, Serialize};
+
+use crate::event::schema::{Envelope, EventSchema, Field, SchemaVersion, TypedEvent};
+use crate::{event::EventBase, event::EventType, queue::try_get_mq};
+
+/// # Merge Request Updated Event
+///
+/// Emitted when a merge request is opened, closed, reopened or merged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MrUpdatedEvent {
+    pub link: String,
+    /// Directory of the monorepo the merge request changes
+    pub path: String,
+    pub action: MrAction,
+    /// Name of the user who performed the change, if known
+    pub operator: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MrAction {
+    Opened,
+    Closed,
+    Reopened,
+    Merged,
+}
+
+impl TypedEvent for MrUpdatedEvent {
+    const SCHEMA: &'static EventSchema = &EventSchema {
+        name: "mr_updated",
+        versions: &[SchemaVersion {
+            version: 1,
+            fields: &[
+                Field::required("link"),
+                Field::required("path"),
+                Field::required("action"),
+                Field::optional("operator"),
+            ],
+        }],
+    };
+}
+
+impl std::fmt::Display for MrUpdatedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Merge Request Updated Event: {} {:?} by {:?}",
+            self.link, self.action, self.operator
+        )
+    }
+}
+
+#[async_trait]
+impl EventBase for MrUpdatedEvent {
+    async fn process(&self) -> Result<(), MegaError> {
+        tracing::info!("Handling Merge Request Updated event: [{}]", &self);
+        Ok(())
+    }
+}
+
+impl MrUpdatedEvent {
+    // Create and enqueue this event, it is dropped if the queue is not running.
+    pub async fn notify(link: &str, path: &str, action: MrAction, operator: Option<&str>) {
+        if let Some(mq) = try_get_mq() {
+            mq.send(EventType::MrUpdated(MrUpdatedEvent {
+                link: link.to_owned(),
+                path: path.to_owned(),
+                action,
+                operator: operator.map(str::to_owned),
+            }))
+            .await;
+        }
+    }
+}
+
+// For storing the data into database.
+impl From<MrUpdatedEvent> for serde_json::Value {
+    fn from(value: MrUpdatedEvent) -> Self {
+        Envelope::wrap(&value)
+    }
+}
+
+impl TryFrom<serde_json::Value> for MrUpdatedEvent {
+    type Error = crate::event::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        Envelope::unwrap(value)
+    }
+}
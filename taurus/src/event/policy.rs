@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use common::errors::MegaError;
+use serde::{Deserialize, Serialize};
+
+use crate::{event::EventBase, event::EventType, queue::get_mq};
+
+/// # Policy Event
+///
+/// Emitted for every change of the Cedar policies of a namespace, so that the changes to who
+/// may do what can be audited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyEvent {
+    pub namespace: String,
+    pub kind: PolicyEventKind,
+    /// Version the change created
+    pub version: i32,
+    /// Name of the user who performed the change
+    pub operator: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PolicyEventKind {
+    Created,
+    Updated,
+    Deleted,
+    /// The policies of an earlier version were made the current ones again
+    RolledBack {
+        to: i32,
+    },
+}
+
+impl std::fmt::Display for PolicyEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Policy Event: {:?} {} version {} by {}",
+            self.kind, self.namespace, self.version, self.operator
+        )
+    }
+}
+
+#[async_trait]
+impl EventBase for PolicyEvent {
+    async fn process(&self) -> Result<(), MegaError> {
+        tracing::info!("Handling Policy event: [{}]", &self);
+        Ok(())
+    }
+}
+
+impl PolicyEvent {
+    // Create and enqueue this event, it is stored before this returns.
+    pub async fn notify(namespace: &str, kind: PolicyEventKind, version: i32, operator: &str) {
+        get_mq()
+            .send(EventType::Policy(PolicyEvent {
+                namespace: namespace.to_owned(),
+                kind,
+                version,
+                operator: operator.to_owned(),
+            }))
+            .await;
+    }
+}
+
+// For storing the data into database.
+impl From<PolicyEvent> for serde_json::Value {
+    fn from(value: PolicyEvent) -> Self {
+        serde_json::to_value(value).unwrap()
+    }
+}
+
+impl TryFrom<serde_json::Value> for PolicyEvent {
+    type Error = crate::event::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        let res: PolicyEvent = serde_json::from_value(value)?;
+        Ok(res)
+    }
+}
@@ -0,0 +1,85 @@
+use async_trait::async_trait;
+use common::errors::MegaError;
+use serde::{Deserialize, Serialize};
+
+use crate::event::schema::{Envelope, EventSchema, Field, SchemaVersion, TypedEvent};
+use crate::{event::EventBase, event::EventType, queue::try_get_mq};
+
+/// # Push Event
+///
+/// Emitted once the refs of a push are stored, with the refs which were updated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushEvent {
+    /// Repository or directory of the monorepo pushed to
+    pub path: String,
+    /// Name of the user who pushed, none for anonymous pushes
+    pub username: Option<String>,
+    pub refs: Vec<PushedRef>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushedRef {
+    pub name: String,
+    /// All zeros for a created ref
+    pub old_id: String,
+    /// All zeros for a deleted ref
+    pub new_id: String,
+}
+
+impl TypedEvent for PushEvent {
+    const SCHEMA: &'static EventSchema = &EventSchema {
+        name: "push",
+        versions: &[SchemaVersion {
+            version: 1,
+            fields: &[
+                Field::required("path"),
+                Field::optional("username"),
+                Field::required("refs"),
+            ],
+        }],
+    };
+}
+
+impl std::fmt::Display for PushEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Push Event: {} refs of {} by {:?}",
+            self.refs.len(),
+            self.path,
+            self.username
+        )
+    }
+}
+
+#[async_trait]
+impl EventBase for PushEvent {
+    async fn process(&self) -> Result<(), MegaError> {
+        tracing::info!("Handling Push event: [{}]", &self);
+        Ok(())
+    }
+}
+
+impl PushEvent {
+    // Create and enqueue this event, it is dropped if the queue is not running.
+    pub async fn notify(self) {
+        if let Some(mq) = try_get_mq() {
+            mq.send(EventType::Push(self)).await;
+        }
+    }
+}
+
+// For storing the data into database.
+impl From<PushEvent> for serde_json::Value {
+    fn from(value: PushEvent) -> Self {
+        Envelope::wrap(&value)
+    }
+}
+
+impl TryFrom<serde_json::Value> for PushEvent {
+    type Error = crate::event::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        Envelope::unwrap(value)
+    }
+}
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use common::errors::MegaError;
+use serde::{Deserialize, Serialize};
+
+use crate::{event::EventBase, event::EventType, queue::get_mq};
+
+/// # Repo Event
+///
+/// Emitted when a repository or directory changes its path or owner, or a repository is
+/// deleted or restored, so that mirrors and webhook consumers can follow the change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoEvent {
+    pub kind: RepoEventKind,
+    /// Name of the user who performed the change
+    pub operator: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RepoEventKind {
+    Renamed {
+        from: String,
+        to: String,
+    },
+    Transferred {
+        path: String,
+        from: Option<String>,
+        to: String,
+    },
+    /// Moved to the trash, it is purged once the retention period is over
+    Deleted {
+        path: String,
+    },
+    Restored {
+        path: String,
+    },
+}
+
+impl std::fmt::Display for RepoEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Repo Event: {:?} by {}", self.kind, self.operator)
+    }
+}
+
+#[async_trait]
+impl EventBase for RepoEvent {
+    async fn process(&self) -> Result<(), MegaError> {
+        tracing::info!("Handling Repo event: [{}]", &self);
+        Ok(())
+    }
+}
+
+impl RepoEvent {
+    // Create and enqueue this event, it is stored before this returns.
+    pub async fn notify(kind: RepoEventKind, operator: &str) {
+        get_mq()
+            .send(EventType::Repo(RepoEvent {
+                kind,
+                operator: operator.to_owned(),
+            }))
+            .await;
+    }
+}
+
+// For storing the data into database.
+impl From<RepoEvent> for serde_json::Value {
+    fn from(value: RepoEvent) -> Self {
+        serde_json::to_value(value).unwrap()
+    }
+}
+
+impl TryFrom<serde_json::Value> for RepoEvent {
+    type Error = crate::event::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        let res: RepoEvent = serde_json::from_value(value)?;
+        Ok(res)
+    }
+}
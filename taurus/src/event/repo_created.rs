@@ -0,0 +1,68 @@
+use async_trait::async_trait;
+use common::errors::MegaError;
+use serde::{Deserialize, Serialize};
+
+use crate::event::schema::{Envelope, EventSchema, Field, SchemaVersion, TypedEvent};
+use crate::{event::EventBase, event::EventType, queue::try_get_mq};
+
+/// # Repo Created Event
+///
+/// Emitted when a directory of the monorepo is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepoCreatedEvent {
+    pub path: String,
+    /// Name of the user who created it
+    pub operator: String,
+}
+
+impl TypedEvent for RepoCreatedEvent {
+    const SCHEMA: &'static EventSchema = &EventSchema {
+        name: "repo_created",
+        versions: &[SchemaVersion {
+            version: 1,
+            fields: &[Field::required("path"), Field::required("operator")],
+        }],
+    };
+}
+
+impl std::fmt::Display for RepoCreatedEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Repo Created Event: {} by {}", self.path, self.operator)
+    }
+}
+
+#[async_trait]
+impl EventBase for RepoCreatedEvent {
+    async fn process(&self) -> Result<(), MegaError> {
+        tracing::info!("Handling Repo Created event: [{}]", &self);
+        Ok(())
+    }
+}
+
+impl RepoCreatedEvent {
+    // Create and enqueue this event, it is dropped if the queue is not running.
+    pub async fn notify(path: &str, operator: &str) {
+        if let Some(mq) = try_get_mq() {
+            mq.send(EventType::RepoCreated(RepoCreatedEvent {
+                path: path.to_owned(),
+                operator: operator.to_owned(),
+            }))
+            .await;
+        }
+    }
+}
+
+// For storing the data into database.
+impl From<RepoCreatedEvent> for serde_json::Value {
+    fn from(value: RepoCreatedEvent) -> Self {
+        Envelope::wrap(&value)
+    }
+}
+
+impl TryFrom<serde_json::Value> for RepoCreatedEvent {
+    type Error = crate::event::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        Envelope::unwrap(value)
+    }
+}
@@ -0,0 +1,260 @@
+//! Versioned schemas of the typed events, which consumers outside mega rely on.
+//!
+//! A typed event is published in an [`Envelope`] naming its schema and the version it was
+//! produced with. The fields of every version are kept in the [`registry`], and a new version
+//! may only add optional fields, which [`EventSchema::check`] enforces. So a consumer of any
+//! version decodes the events of all others: fields it does not know are ignored and fields it
+//! misses are optional.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::event::issue::IssueEvent;
+use crate::event::mr_updated::MrUpdatedEvent;
+use crate::event::push::PushEvent;
+use crate::event::repo_created::RepoCreatedEvent;
+
+/// A field of an event, an optional field may be missing or null.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Field {
+    pub name: &'static str,
+    pub required: bool,
+}
+
+impl Field {
+    pub const fn required(name: &'static str) -> Self {
+        Field {
+            name,
+            required: true,
+        }
+    }
+
+    pub const fn optional(name: &'static str) -> Self {
+        Field {
+            name,
+            required: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchemaVersion {
+    pub version: u32,
+    pub fields: &'static [Field],
+}
+
+/// A schema with all its versions, oldest first.
+#[derive(Debug, Serialize)]
+pub struct EventSchema {
+    pub name: &'static str,
+    pub versions: &'static [SchemaVersion],
+}
+
+impl EventSchema {
+    /// The version events are produced with.
+    pub fn latest(&self) -> &SchemaVersion {
+        self.versions.last().expect("a schema has a version")
+    }
+
+    /// Check that the versions are numbered from 1 and each is compatible with the one before.
+    pub fn check(&self) -> Result<(), String> {
+        for (i, version) in self.versions.iter().enumerate() {
+            if version.version as usize != i + 1 {
+                return Err(format!(
+                    "{} version {} should be {}",
+                    self.name,
+                    version.version,
+                    i + 1
+                ));
+            }
+        }
+        for pair in self.versions.windows(2) {
+            check_compatible(&pair[0], &pair[1])
+                .map_err(|err| format!("{} version {}: {}", self.name, pair[1].version, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether the events of `new` can be decoded by consumers of `old` and the other way round.
+pub fn check_compatible(old: &SchemaVersion, new: &SchemaVersion) -> Result<(), String> {
+    for field in old.fields {
+        match new.fields.iter().find(|f| f.name == field.name) {
+            None => return Err(format!("field {} is removed", field.name)),
+            Some(f) if f.required != field.required => {
+                return Err(format!("field {} changed if it is required", field.name))
+            }
+            Some(_) => {}
+        }
+    }
+    for field in new.fields {
+        if field.required && !old.fields.iter().any(|f| f.name == field.name) {
+            return Err(format!("added field {} is required", field.name));
+        }
+    }
+    Ok(())
+}
+
+/// An event with a schema in the [`registry`], its fields are the ones of the latest version.
+pub trait TypedEvent: Serialize + DeserializeOwned {
+    const SCHEMA: &'static EventSchema;
+}
+
+/// How typed events are published.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    pub schema: String,
+    /// Version the event was produced with
+    pub version: u32,
+    pub data: Value,
+}
+
+impl Envelope {
+    pub fn wrap<T: TypedEvent>(event: &T) -> Value {
+        let envelope = Envelope {
+            schema: T::SCHEMA.name.to_owned(),
+            version: T::SCHEMA.latest().version,
+            data: serde_json::to_value(event).unwrap(),
+        };
+        serde_json::to_value(envelope).unwrap()
+    }
+
+    /// The event in `value`, produced with any version of its schema.
+    pub fn unwrap<T: TypedEvent>(value: Value) -> Result<T, crate::event::Error> {
+        let envelope: Envelope = serde_json::from_value(value)?;
+        if envelope.schema != T::SCHEMA.name {
+            return Err(crate::event::Error::UnexpectedSchema(envelope.schema));
+        }
+        Ok(serde_json::from_value(envelope.data)?)
+    }
+}
+
+/// The schemas of all typed events.
+pub fn registry() -> [&'static EventSchema; 4] {
+    [
+        PushEvent::SCHEMA,
+        MrUpdatedEvent::SCHEMA,
+        IssueEvent::SCHEMA,
+        RepoCreatedEvent::SCHEMA,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{check_compatible, registry, Envelope, Field, SchemaVersion, TypedEvent};
+    use crate::event::issue::{IssueAction, IssueEvent};
+    use crate::event::mr_updated::{MrAction, MrUpdatedEvent};
+    use crate::event::push::{PushEvent, PushedRef};
+    use crate::event::repo_created::RepoCreatedEvent;
+
+    #[test]
+    fn test_registry_compatible() {
+        for schema in registry() {
+            schema.check().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_check_compatible() {
+        let v1 = SchemaVersion {
+            version: 1,
+            fields: &[Field::required("path"), Field::optional("user")],
+        };
+        let added = SchemaVersion {
+            version: 2,
+            fields: &[
+                Field::required("path"),
+                Field::optional("user"),
+                Field::optional("size"),
+            ],
+        };
+        assert!(check_compatible(&v1, &added).is_ok());
+        let removed = SchemaVersion {
+            version: 2,
+            fields: &[Field::optional("user")],
+        };
+        assert!(check_compatible(&v1, &removed).is_err());
+        let stricter = SchemaVersion {
+            version: 2,
+            fields: &[Field::required("path"), Field::required("user")],
+        };
+        assert!(check_compatible(&v1, &stricter).is_err());
+        let looser = SchemaVersion {
+            version: 2,
+            fields: &[Field::optional("path"), Field::optional("user")],
+        };
+        assert!(check_compatible(&v1, &looser).is_err());
+        let required = SchemaVersion {
+            version: 2,
+            fields: &[
+                Field::required("path"),
+                Field::optional("user"),
+                Field::required("size"),
+            ],
+        };
+        assert!(check_compatible(&v1, &required).is_err());
+    }
+
+    /// The fields of the event are the ones of the latest version, and it survives an envelope.
+    fn assert_matches_schema<T: TypedEvent + std::fmt::Debug>(event: T) {
+        let value = Envelope::wrap(&event);
+        let Value::Object(data) = &value["data"] else {
+            panic!("{} is not an object", T::SCHEMA.name);
+        };
+        let fields = T::SCHEMA.latest().fields;
+        for key in data.keys() {
+            assert!(
+                fields.iter().any(|f| f.name == key),
+                "{} is not in the schema {}",
+                key,
+                T::SCHEMA.name
+            );
+        }
+        for field in fields.iter().filter(|f| f.required) {
+            assert!(data.contains_key(field.name), "{} is missing", field.name);
+        }
+        let decoded: T = Envelope::unwrap(value).unwrap();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", event));
+    }
+
+    #[test]
+    fn test_events_match_schemas() {
+        assert_matches_schema(PushEvent {
+            path: "/project".to_owned(),
+            username: Some("alice".to_owned()),
+            refs: vec![PushedRef {
+                name: "refs/heads/main".to_owned(),
+                old_id: "0".repeat(40),
+                new_id: "1".repeat(40),
+            }],
+        });
+        assert_matches_schema(MrUpdatedEvent {
+            link: "ABCD".to_owned(),
+            path: "/project".to_owned(),
+            action: MrAction::Merged,
+            operator: Some("alice".to_owned()),
+        });
+        assert_matches_schema(IssueEvent {
+            link: "EFGH".to_owned(),
+            title: "Broken".to_owned(),
+            action: IssueAction::Closed,
+            operator: "alice".to_owned(),
+        });
+        assert_matches_schema(RepoCreatedEvent {
+            path: "/project".to_owned(),
+            operator: "alice".to_owned(),
+        });
+    }
+
+    #[test]
+    fn test_unwrap_other_schema() {
+        let value = Envelope::wrap(&RepoCreatedEvent {
+            path: "/project".to_owned(),
+            operator: "alice".to_owned(),
+        });
+        assert!(Envelope::unwrap::<PushEvent>(value).is_err());
+    }
+}
@@ -1,16 +1,18 @@
 use common::config::Config;
 use jupiter::context::Context;
+use crate::outbox;
 use crate::queue::{MessageQueue, MQ};
+use crate::schedule;
+use crate::webhook;
 
 pub async fn init_mq(config: &Config) {
     let ctx = Context::new(config.clone()).await;
-    let seq = match ctx.services.mq_storage.get_latest_message().await {
-        Some(model) => model.id + 1,
-        None => 1,
-    };
 
-    let mq = MessageQueue::new(seq, ctx);
+    let mq = MessageQueue::new(ctx.clone()).await;
     mq.start();
+    webhook::start(ctx.clone());
 
     MQ.set(mq).unwrap();
+    outbox::start(ctx.clone());
+    schedule::start(ctx);
 }
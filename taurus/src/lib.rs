@@ -1,4 +1,9 @@
 pub mod init;
+pub mod broker;
 pub mod event;
+pub mod metrics;
+pub mod outbox;
 pub mod queue;
-pub mod cache;
+pub mod replay;
+pub mod schedule;
+pub mod webhook;
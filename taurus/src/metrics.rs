@@ -0,0 +1,115 @@
+//! Metrics of the message queue, served by the gateway at `/metrics` in the prometheus text
+//! format next to the storage metrics.
+//!
+//! Every instance counts the messages it processed by category and outcome, so the failure rate
+//! of a category is the rate of its failed ones over the rate of all. With the database broker
+//! the depth of the queue is read from the database on every scrape: the messages in each state
+//! by category, the age of the oldest one, which for pending messages is the lag of the
+//! consumers, and the events staged in the outbox which were not published yet.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use chrono::{NaiveDateTime, Utc};
+use common::config::MqBroker;
+use jupiter::context::Context;
+
+/// Messages processed by category, those which succeeded and those which failed
+static PROCESSED: Mutex<BTreeMap<&'static str, (u64, u64)>> = Mutex::new(BTreeMap::new());
+
+// Count an attempt of processing a message of `category`.
+pub(crate) fn record(category: &'static str, ok: bool) {
+    let mut processed = PROCESSED.lock().unwrap();
+    let (succeeded, failed) = processed.entry(category).or_default();
+    if ok {
+        *succeeded += 1;
+    } else {
+        *failed += 1;
+    }
+}
+
+/// All message queue metrics in the prometheus text format.
+pub async fn render(context: &Context) -> String {
+    let mut res = String::new();
+    {
+        let processed = PROCESSED.lock().unwrap();
+        res.push_str("# HELP mega_mq_processed_total Messages processed by this instance.\n");
+        res.push_str("# TYPE mega_mq_processed_total counter\n");
+        for (category, (succeeded, failed)) in processed.iter() {
+            for (result, count) in [("ok", succeeded), ("failed", failed)] {
+                let _ = writeln!(
+                    res,
+                    "mega_mq_processed_total{{category=\"{}\",result=\"{}\"}} {}",
+                    category, result, count
+                );
+            }
+        }
+    }
+    if context.config.mq.broker != MqBroker::Database {
+        return res;
+    }
+
+    let now = Utc::now().naive_utc();
+    match context.services.mq_storage.queue_depth().await {
+        Ok(depth) => {
+            res.push_str("# HELP mega_mq_messages Unprocessed messages in the queue.\n");
+            res.push_str("# TYPE mega_mq_messages gauge\n");
+            for (category, state, count, _) in &depth {
+                let _ = writeln!(
+                    res,
+                    "mega_mq_messages{{category=\"{}\",state=\"{}\"}} {}",
+                    category.as_deref().unwrap_or_default(),
+                    state,
+                    count
+                );
+            }
+            res.push_str(
+                "# HELP mega_mq_lag_seconds Age of the oldest unprocessed message in the queue.\n",
+            );
+            res.push_str("# TYPE mega_mq_lag_seconds gauge\n");
+            for (category, state, _, oldest) in &depth {
+                let _ = writeln!(
+                    res,
+                    "mega_mq_lag_seconds{{category=\"{}\",state=\"{}\"}} {}",
+                    category.as_deref().unwrap_or_default(),
+                    state,
+                    age(*oldest, now)
+                );
+            }
+        }
+        Err(e) => tracing::error!("Failed to read the depth of the message queue: {}", e),
+    }
+    match context.services.outbox_storage.backlog().await {
+        Ok((count, oldest)) => {
+            res.push_str("# HELP mega_mq_outbox_events Events staged and not published yet.\n");
+            res.push_str("# TYPE mega_mq_outbox_events gauge\n");
+            let _ = writeln!(res, "mega_mq_outbox_events {}", count);
+            res.push_str(
+                "# HELP mega_mq_outbox_lag_seconds Age of the oldest event not published yet.\n",
+            );
+            res.push_str("# TYPE mega_mq_outbox_lag_seconds gauge\n");
+            let _ = writeln!(res, "mega_mq_outbox_lag_seconds {}", age(oldest, now));
+        }
+        Err(e) => tracing::error!("Failed to read the backlog of the outbox: {}", e),
+    }
+    res
+}
+
+// Seconds since `oldest`, 0 if there is none.
+fn age(oldest: Option<NaiveDateTime>, now: NaiveDateTime) -> i64 {
+    oldest.map_or(0, |oldest| (now - oldest).num_seconds().max(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{record, PROCESSED};
+
+    #[test]
+    fn test_record() {
+        record("TestEvent", true);
+        record("TestEvent", false);
+        record("TestEvent", true);
+        assert_eq!(PROCESSED.lock().unwrap()["TestEvent"], (2, 1));
+    }
+}
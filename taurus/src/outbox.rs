@@ -0,0 +1,143 @@
+//! Transactional outbox of events about changes of the database.
+//!
+//! An event published after the transaction of its change committed is lost if the instance
+//! stops in between, and one published before is out there even if the change is rolled back.
+//! So such events are [staged](stage) in the `event_outbox` table through the transaction of
+//! the change instead, and the relays of all instances publish the staged events of committed
+//! transactions to the queue and mark them sent.
+//!
+//! An event is published at least once: a relay which stopped after publishing an event but
+//! before marking it sent leaves it to be published again by another one. It keeps the id of its
+//! message then, the idempotency key consumers drop repeated events by.
+
+use std::time::{Duration, Instant};
+
+use callisto::db_enums::{MessageLane, MessageState};
+use callisto::{event_outbox, mq_storage};
+use chrono::Utc;
+use common::errors::MegaError;
+use common::utils::generate_id;
+use jupiter::context::Context;
+use sea_orm::ConnectionTrait;
+use tokio::sync::Notify;
+
+use crate::event::{EventType, Message};
+use crate::queue::get_mq;
+
+// Seconds between looking for staged events when none was flushed.
+const POLL_INTERVAL: u64 = 2;
+// Events published at once.
+const BATCH: u64 = 100;
+// Seconds a claimed event stays hidden from other relays, it is published again once they are
+// over if it was not marked sent by then.
+const CLAIM_TIMEOUT: u64 = 60;
+// Days sent events are kept before they are deleted.
+const RETENTION_DAYS: i64 = 7;
+// Seconds between deleting old sent events.
+const PURGE_INTERVAL: u64 = 3600;
+
+// Wakes the relay of this instance when events are flushed.
+static WAKE: Notify = Notify::const_new();
+
+/// Write `evt` to the outbox through `conn`, the transaction of the change the event is about.
+/// It is published once the transaction commits, call [`flush`] then to publish it right away.
+pub async fn stage(
+    context: &Context,
+    conn: &impl ConnectionTrait,
+    evt: EventType,
+) -> Result<(), MegaError> {
+    let (category, content) = encode(evt);
+    context
+        .services
+        .outbox_storage
+        .add_event(conn, generate_id(), &category, &content)
+        .await
+}
+
+/// Publish the events staged by committed transactions now instead of at the next poll.
+pub fn flush() {
+    WAKE.notify_one();
+}
+
+// Start the relay of this instance, once the queue is initialized.
+pub(crate) fn start(context: Context) {
+    tokio::spawn(async move {
+        let mut purged = Instant::now();
+        loop {
+            if relay(&context).await < BATCH as usize {
+                let interval = Duration::from_secs(POLL_INTERVAL);
+                let _ = tokio::time::timeout(interval, WAKE.notified()).await;
+            }
+            if purged.elapsed() >= Duration::from_secs(PURGE_INTERVAL) {
+                purged = Instant::now();
+                purge(&context).await;
+            }
+        }
+    });
+}
+
+// Publish the staged events in the order they were staged, returns their number.
+async fn relay(context: &Context) -> usize {
+    let st = &context.services.outbox_storage;
+    let claimed = match st.claim_events(BATCH, CLAIM_TIMEOUT).await {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            tracing::error!("Failed to claim staged events: {}", e);
+            return 0;
+        }
+    };
+    let count = claimed.len();
+    for event in claimed {
+        let id = event.id;
+        get_mq().publish(message(event)).await;
+        if let Err(e) = st.mark_sent(id).await {
+            tracing::error!("Failed to mark staged event {} sent: {}", id, e);
+        }
+    }
+    count
+}
+
+// The category and content of `evt` as they are stored.
+pub(crate) fn encode(evt: EventType) -> (String, String) {
+    let model: mq_storage::Model = Message {
+        id: 0,
+        create_time: Utc::now(),
+        evt,
+        span: tracing::Span::none(),
+    }
+    .into();
+    (
+        model.category.unwrap_or_default(),
+        model.content.unwrap_or_default(),
+    )
+}
+
+// The message the staged event is published as, decoded like a stored message.
+fn message(event: event_outbox::Model) -> Message {
+    let id = event.id;
+    let mut msg: Message = mq_storage::Model {
+        id,
+        category: Some(event.category),
+        create_time: event.created_at,
+        content: Some(event.content),
+        state: MessageState::Pending,
+        attempts: 0,
+        visible_at: None,
+        last_error: None,
+        claimed_by: None,
+        // it is published in the lane of its event
+        lane: MessageLane::Interactive,
+    }
+    .into();
+    msg.span = tracing::info_span!("mq_publish", id);
+    msg
+}
+
+async fn purge(context: &Context) {
+    let before = Utc::now().naive_utc() - chrono::Duration::days(RETENTION_DAYS);
+    match context.services.outbox_storage.purge_sent(before).await {
+        Ok(0) => {}
+        Ok(count) => tracing::info!("Deleted {} sent events from the outbox", count),
+        Err(e) => tracing::error!("Failed to delete sent events from the outbox: {}", e),
+    }
+}
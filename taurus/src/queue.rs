@@ -1,13 +1,11 @@
 use std::fmt::Debug;
-use std::sync::atomic::AtomicI64;
-use std::sync::{Arc, OnceLock};
+use std::sync::OnceLock;
 
 use chrono::Utc;
-use crossbeam_channel::{unbounded, Sender};
-use crossbeam_channel::Receiver;
+use common::utils::generate_id;
 use jupiter::context::Context;
 
-use crate::cache::get_mcache;
+use crate::broker::{self, Broker};
 use crate::event::{Message, EventType};
 
 // Lazy initialized static MessageQueue instance.
@@ -16,67 +14,51 @@ pub fn get_mq() -> &'static MessageQueue {
     MQ.get().unwrap()
 }
 
+// The queue if it was initialized, commands other than the service have none.
+pub(crate) fn try_get_mq() -> Option<&'static MessageQueue> {
+    MQ.get()
+}
+
 pub struct MessageQueue {
-    sender: Sender<Message>,
-    receiver: Receiver<Message>,
-    // sem: Arc<Semaphore>,
-    cur_id: Arc<AtomicI64>,
+    broker: Box<dyn Broker>,
     pub(crate) context: Context,
 }
 
-unsafe impl Send for MessageQueue{}
-unsafe impl Sync for MessageQueue{}
-
 impl Debug for MessageQueue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // Just ignore context field.
-        f.debug_struct("MessageQueue").field("sender", &self.sender).field("receiver", &self.receiver).finish()
+        f.debug_struct("MessageQueue").field("broker", &self.context.config.mq.broker).finish()
     }
 }
 
 impl MessageQueue {
     // Should be singleton.
-    pub(crate) fn new(seq: i64, ctx: Context) -> Self {
-        let (s, r) = unbounded::<Message>();
-
+    pub(crate) async fn new(ctx: Context) -> Self {
         MessageQueue {
-            sender: s.to_owned(),
-            receiver: r.to_owned(),
-            // sem: Arc::new(Semaphore::new(n_workers)),
-            cur_id: Arc::new(AtomicI64::new(seq)),
+            broker: broker::from_config(&ctx).await,
             context: ctx,
         }
     }
 
     pub(crate) fn start(&self) {
-        let receiver = self.receiver.clone();
-        // let sem = self.sem.clone();
-
-        tokio::spawn(async move {
-            let mc = get_mcache();
-            loop {
-                match receiver.recv() {
-                    Ok(msg) => {
-                        let stored = msg.clone();
-                        mc.add(stored).await;
-                        tokio::spawn(async move {
-                            msg.evt.process().await;
-                        });
-                    },
-                    Err(e) => {
-                        // Should not error here.
-                        panic!("Event Loop Panic: {e}");
-                    }
-                }
-            }
-        });
+        self.broker.start();
     }
 
-    pub(crate) fn send(&self, evt: EventType) {
-        let _ = self.sender.send(Message {
-            id: self.cur_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+    pub(crate) async fn send(&self, evt: EventType) {
+        let id = generate_id();
+        // Below the span of the request which publishes the message.
+        let span = tracing::info_span!("mq_publish", id);
+        self.publish(Message {
+            id,
             create_time: Utc::now(),
-            evt
-        });
+            evt,
+            span,
+        })
+        .await;
+    }
+
+    // Publish a message whose id was given before, like the one of a staged event.
+    pub(crate) async fn publish(&self, msg: Message) {
+        self.broker.publish(msg).await;
     }
 }
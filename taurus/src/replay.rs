@@ -0,0 +1,111 @@
+//! Replay of processed events to a consumer, e.g. to rebuild what a handler derives from them
+//! or to re-fire the events a webhook missed.
+//!
+//! A replay is queued as a background job of the type [`REPLAY_JOB`] and goes through the
+//! messages the database broker stored after an offset, the id of a message, or a time. Every
+//! event keeps the id of its message as its idempotency key, so a webhook gets one delivery of
+//! an event however often it is replayed. A replay to the handlers runs the callbacks of the
+//! events again, like a redelivery of their messages does.
+
+use async_trait::async_trait;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+
+use common::errors::MegaError;
+use jupiter::context::Context;
+use jupiter::worker::JobHandler;
+
+use crate::event::Message;
+
+/// Background job type of replays, run by [`ReplayJob`].
+pub const REPLAY_JOB: &str = "event_replay";
+
+// Messages read at once.
+const BATCH: u64 = 100;
+
+/// Who the events are replayed to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Consumer {
+    /// The callbacks of the events, then the webhooks subscribed to them
+    Handlers,
+    /// The webhooks subscribed to the events
+    Webhooks,
+    /// Only the webhook `id`, if it is subscribed to the events
+    Webhook { id: i64 },
+}
+
+/// Payload of a queued replay.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayPayload {
+    pub consumer: Consumer,
+    /// Id of the message after which the replay starts, from the first one if not given
+    pub after: Option<i64>,
+    /// Unix timestamp of the first event replayed
+    pub since: Option<i64>,
+    /// User who queued the replay
+    pub operator: String,
+}
+
+/// Replays the processed events of a queued replay in the order they were published.
+pub struct ReplayJob;
+
+#[async_trait]
+impl JobHandler for ReplayJob {
+    async fn run(&self, context: &Context, payload: &str) -> Result<(), MegaError> {
+        let payload: ReplayPayload = serde_json::from_str(payload)
+            .map_err(|err| MegaError::with_message(&err.to_string()))?;
+        let since = match payload.since {
+            Some(since) => Some(
+                DateTime::from_timestamp(since, 0)
+                    .ok_or_else(|| MegaError::with_message("since is out of range"))?
+                    .naive_utc(),
+            ),
+            None => None,
+        };
+        tracing::info!(
+            "replaying events to {:?} queued by {}",
+            payload.consumer,
+            payload.operator
+        );
+        let st = &context.services.mq_storage;
+        let mut after = payload.after.unwrap_or(0);
+        let mut count = 0;
+        loop {
+            let batch = st.list_history(after, since, BATCH).await?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            after = last.id;
+            for model in batch {
+                let msg: Message = model.into();
+                match payload.consumer {
+                    Consumer::Handlers => msg.process(context).await?,
+                    Consumer::Webhooks => msg.evt.deliver(context, msg.id, None).await?,
+                    Consumer::Webhook { id } => msg.evt.deliver(context, msg.id, Some(id)).await?,
+                }
+                count += 1;
+            }
+        }
+        tracing::info!(
+            "replayed {} events to {:?}, the last one is {}",
+            count,
+            payload.consumer,
+            after
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Consumer;
+
+    #[test]
+    fn test_consumer_format() {
+        let consumer: Consumer = serde_json::from_str(r#"{"type":"webhook","id":1}"#).unwrap();
+        assert_eq!(consumer, Consumer::Webhook { id: 1 });
+        let consumer: Consumer = serde_json::from_str(r#"{"type":"handlers"}"#).unwrap();
+        assert_eq!(consumer, Consumer::Handlers);
+    }
+}
@@ -0,0 +1,206 @@
+//! Messages published at a later time, e.g. a reminder on a stale merge request in 14 days or
+//! the retry of some work after a backoff.
+//!
+//! A [scheduled](schedule) message is stored in the `scheduled_message` table until it is due,
+//! so it survives restarts. The scheduler of every instance claims the messages due within the
+//! next [`HORIZON`] seconds and keeps them in a timer wheel of one second ticks, which releases
+//! each at its `deliver_at`. Releasing a message removes it from the table and stages it in the
+//! [outbox](crate::outbox) in one transaction, so it is published once, and never after it was
+//! [canceled](cancel).
+
+use std::time::Duration;
+
+use callisto::scheduled_message;
+use chrono::{DateTime, Utc};
+use common::errors::MegaError;
+use common::utils::generate_id;
+use jupiter::context::Context;
+use tokio::sync::Notify;
+
+use crate::event::EventType;
+use crate::outbox;
+
+/// Seconds ahead messages are claimed and loaded into the timer wheel.
+pub const HORIZON: u64 = 30;
+// Seconds between loading due messages when none was scheduled.
+const LOAD_INTERVAL: u64 = 10;
+// Messages claimed at once.
+const BATCH: u64 = 500;
+// Seconds a claimed message is held after it is due, it is claimed by another instance once
+// they are over unless it was released by then.
+const CLAIM_MARGIN: u64 = 60;
+// Slots of the timer wheel, one per tick.
+const SLOTS: u64 = 64;
+
+// Wakes the scheduler of this instance when a message is scheduled.
+static WAKE: Notify = Notify::const_new();
+
+/// Publish `evt` at `deliver_at`, or right away if it is past. A message scheduled with the
+/// `cancel_key` of an earlier one replaces it. Returns the id of the message.
+pub async fn schedule(
+    context: &Context,
+    evt: EventType,
+    deliver_at: DateTime<Utc>,
+    cancel_key: Option<&str>,
+) -> Result<i64, MegaError> {
+    let (category, content) = outbox::encode(evt);
+    let msg = scheduled_message::Model {
+        id: generate_id(),
+        cancel_key: cancel_key.map(str::to_owned),
+        category,
+        content,
+        deliver_at: deliver_at.naive_utc(),
+        claimed_until: None,
+        created_at: Utc::now().naive_utc(),
+    };
+    let id = msg.id;
+    context.services.schedule_storage.schedule(msg).await?;
+    if deliver_at <= Utc::now() + chrono::Duration::seconds(HORIZON as i64) {
+        WAKE.notify_one();
+    }
+    Ok(id)
+}
+
+/// Cancel the message scheduled with `cancel_key`, returns `false` if there is none which was
+/// not released yet.
+pub async fn cancel(context: &Context, cancel_key: &str) -> Result<bool, MegaError> {
+    context.services.schedule_storage.cancel(cancel_key).await
+}
+
+// Start the scheduler of this instance.
+pub(crate) fn start(context: Context) {
+    tokio::spawn(async move {
+        let mut wheel = TimerWheel::new(Utc::now().timestamp() as u64);
+        let mut load = true;
+        let mut loaded = 0;
+        loop {
+            let now = Utc::now().timestamp() as u64;
+            if load || now >= loaded + LOAD_INTERVAL {
+                for msg in claim(&context, now).await {
+                    wheel.insert(msg.deliver_at.and_utc().timestamp() as u64, msg);
+                }
+                loaded = now;
+            }
+            for msg in wheel.advance(now) {
+                release(&context, msg).await;
+            }
+            let tick = Duration::from_secs(1);
+            load = tokio::time::timeout(tick, WAKE.notified()).await.is_ok();
+        }
+    });
+}
+
+// Claim the messages due within the horizon.
+async fn claim(context: &Context, now: u64) -> Vec<scheduled_message::Model> {
+    let before = DateTime::from_timestamp((now + HORIZON) as i64, 0)
+        .unwrap_or_default()
+        .naive_utc();
+    let st = &context.services.schedule_storage;
+    match st.claim_due(before, CLAIM_MARGIN, BATCH).await {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            tracing::error!("Failed to claim scheduled messages: {}", e);
+            vec![]
+        }
+    }
+}
+
+// Stage the message in the outbox unless it was canceled.
+async fn release(context: &Context, msg: scheduled_message::Model) {
+    let st = &context.services.schedule_storage;
+    let id = msg.id;
+    let res = st
+        .transaction(|txn| async move {
+            if !st.take(&*txn, msg.id).await? {
+                return Ok(false);
+            }
+            context
+                .services
+                .outbox_storage
+                .add_event(&*txn, msg.id, &msg.category, &msg.content)
+                .await?;
+            Ok(true)
+        })
+        .await;
+    match res {
+        Ok(true) => outbox::flush(),
+        Ok(false) => tracing::debug!("Scheduled message {} was canceled", id),
+        Err(e) => tracing::error!("Failed to release scheduled message {}: {}", id, e),
+    }
+}
+
+// Hashed timer wheel with a slot per tick: an entry is kept in the slot of its due tick modulo
+// the number of slots, and released the first time the wheel passes that slot at or after its
+// due tick.
+struct TimerWheel<T> {
+    slots: Vec<Vec<(u64, T)>>,
+    // the first tick which did not pass yet
+    next: u64,
+}
+
+impl<T> TimerWheel<T> {
+    fn new(now: u64) -> Self {
+        TimerWheel {
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            next: now,
+        }
+    }
+
+    // Add an entry due at the tick `due`, one which is past is due at the next tick.
+    fn insert(&mut self, due: u64, item: T) {
+        let due = due.max(self.next);
+        self.slots[(due % SLOTS) as usize].push((due, item));
+    }
+
+    // Pass the ticks up to `now`, returns the entries due by then.
+    fn advance(&mut self, now: u64) -> Vec<T> {
+        let mut due = Vec::new();
+        if now < self.next {
+            return due;
+        }
+        let ticks = (now - self.next + 1).min(SLOTS);
+        for tick in self.next..self.next + ticks {
+            let slot = &mut self.slots[(tick % SLOTS) as usize];
+            let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(slot)
+                .into_iter()
+                .partition(|(d, _)| *d <= now);
+            *slot = waiting;
+            due.extend(ready.into_iter().map(|(_, item)| item));
+        }
+        self.next = now + 1;
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TimerWheel, SLOTS};
+
+    #[test]
+    fn test_timer_wheel() {
+        let mut wheel = TimerWheel::new(100);
+        wheel.insert(103, "a");
+        wheel.insert(101, "b");
+        wheel.insert(103 + SLOTS, "later");
+        wheel.insert(50, "past");
+        assert_eq!(wheel.advance(100), ["past"]);
+        assert!(wheel.advance(100).is_empty());
+        assert_eq!(wheel.advance(102), ["b"]);
+        assert_eq!(wheel.advance(103), ["a"]);
+        // a lap of the wheel releases the entries of its slots which are due
+        assert!(wheel.advance(103 + SLOTS - 1).is_empty());
+        assert_eq!(wheel.advance(103 + SLOTS), ["later"]);
+    }
+
+    #[test]
+    fn test_timer_wheel_skipped_ticks() {
+        let mut wheel = TimerWheel::new(0);
+        wheel.insert(5, 1);
+        wheel.insert(SLOTS + 10, 2);
+        wheel.insert(3 * SLOTS, 3);
+        let mut due = wheel.advance(2 * SLOTS);
+        due.sort();
+        assert_eq!(due, [1, 2]);
+        assert_eq!(wheel.advance(3 * SLOTS), [3]);
+    }
+}
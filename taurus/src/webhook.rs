@@ -0,0 +1,262 @@
+//! Delivery of the typed events to the webhooks subscribed to them.
+//!
+//! Processing a typed event stores a delivery for every webhook subscribed to its schema and
+//! path. The workers of all instances claim due deliveries from the database and post the
+//! envelope of the event to the webhook with the headers:
+//!
+//! - `X-Mega-Event`: schema of the event
+//! - `X-Mega-Delivery`: id of the delivery, the same for all its attempts
+//! - `X-Mega-Event-Id`: idempotency key of the event, the id of its message. A webhook gets one
+//!   delivery of an event however often it is replayed, a receiver subscribed through several
+//!   webhooks drops repeated events by this key
+//! - `X-Mega-Signature-256`: `sha256=` followed by the hex HMAC-SHA256 of the body keyed with
+//!   the secret of the webhook
+//!
+//! A delivery answered with a 2xx status is done. Others are retried with a delay doubling with
+//! every attempt, and dead-lettered after `webhook.max_attempts` attempts until they are
+//! requeued.
+
+use std::time::Duration;
+
+use callisto::db_enums::MessageState;
+use callisto::{webhook, webhook_delivery};
+use common::errors::MegaError;
+use common::utils::generate_id;
+use jupiter::context::Context;
+use jupiter::storage::webhook_storage::DeliveryResponse;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use ring::hmac;
+use tokio::sync::Notify;
+
+use crate::event::schema::{Envelope, TypedEvent};
+
+// Seconds between looking for due deliveries when none was stored.
+const POLL_INTERVAL: u64 = 5;
+// Deliveries attempted at once.
+const BATCH: u64 = 50;
+// Seconds a claimed delivery stays hidden after the timeout of its request.
+const CLAIM_MARGIN: u64 = 30;
+// Bytes of a response body which are recorded.
+const MAX_RESPONSE_BODY: usize = 4096;
+
+// Wakes the worker of this instance when deliveries are stored.
+static WAKE: Notify = Notify::const_new();
+
+// Start the worker of this instance, unless `webhook.enable` is off.
+pub(crate) fn start(context: Context) {
+    let config = &context.config.webhook;
+    if !config.enable {
+        return;
+    }
+    let client = Client::builder()
+        .timeout(Duration::from_secs(config.timeout))
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Failed to build the webhook client");
+    tokio::spawn(async move {
+        loop {
+            if deliver_due(&context, &client).await < BATCH as usize {
+                let interval = Duration::from_secs(POLL_INTERVAL);
+                let _ = tokio::time::timeout(interval, WAKE.notified()).await;
+            }
+        }
+    });
+}
+
+// Store a delivery of `event` for every webhook subscribed to it, or only for the webhook `only`.
+// `path` is where it happened, `event_id` the id of its message.
+pub(crate) async fn dispatch<T: TypedEvent>(
+    context: &Context,
+    event: &T,
+    path: Option<&str>,
+    event_id: i64,
+    only: Option<i64>,
+) -> Result<(), MegaError> {
+    let st = &context.services.webhook_storage;
+    let name = T::SCHEMA.name;
+    let mut webhooks = st.subscribed_webhooks(name, path).await?;
+    if let Some(only) = only {
+        webhooks.retain(|webhook| webhook.id == only);
+    }
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+    let payload = Envelope::wrap(event).to_string();
+    let now = chrono::Utc::now().naive_utc();
+    let deliveries = webhooks
+        .iter()
+        .map(|webhook| webhook_delivery::Model {
+            id: generate_id(),
+            webhook_id: webhook.id,
+            event_id,
+            event: name.to_owned(),
+            payload: payload.clone(),
+            state: MessageState::Pending,
+            attempts: 0,
+            next_attempt_at: None,
+            response_status: None,
+            response_body: None,
+            last_error: None,
+            created_at: now,
+            delivered_at: None,
+        })
+        .collect();
+    if st.save_deliveries(deliveries).await? > 0 {
+        WAKE.notify_one();
+    }
+    Ok(())
+}
+
+// Attempt the due deliveries, returns their number.
+async fn deliver_due(context: &Context, client: &Client) -> usize {
+    let config = &context.config.webhook;
+    let st = &context.services.webhook_storage;
+    let claimed = match st
+        .claim_deliveries(BATCH, config.timeout + CLAIM_MARGIN)
+        .await
+    {
+        Ok(claimed) => claimed,
+        Err(e) => {
+            tracing::error!("Failed to claim webhook deliveries: {}", e);
+            return 0;
+        }
+    };
+    let count = claimed.len();
+    let tasks: Vec<_> = claimed
+        .into_iter()
+        .map(|delivery| {
+            let (context, client) = (context.clone(), client.clone());
+            tokio::spawn(async move { attempt(&context, &client, delivery).await })
+        })
+        .collect();
+    for task in tasks {
+        let _ = task.await;
+    }
+    count
+}
+
+// Post the delivery to its webhook and record the outcome.
+async fn attempt(context: &Context, client: &Client, delivery: webhook_delivery::Model) {
+    let st = &context.services.webhook_storage;
+    let webhook = match st.find_webhook(delivery.webhook_id).await {
+        Ok(Some(webhook)) => webhook,
+        // deleted with its deliveries after they were claimed
+        Ok(None) => return,
+        Err(e) => {
+            tracing::error!(
+                "Failed to find the webhook of delivery {}: {}",
+                delivery.id,
+                e
+            );
+            return;
+        }
+    };
+    let (response, res) = post(client, &webhook, &delivery).await;
+    let res = match res {
+        Ok(()) => st.complete_delivery(delivery.id, response).await,
+        Err(error) => match st
+            .fail_delivery(
+                delivery.id,
+                delivery.attempts,
+                response,
+                &error,
+                &context.config.webhook,
+            )
+            .await
+        {
+            Ok(MessageState::Dead) => {
+                tracing::error!(
+                    "Delivery {} to {} dead-lettered after {} attempts: {}",
+                    delivery.id,
+                    webhook.url,
+                    delivery.attempts,
+                    error
+                );
+                Ok(())
+            }
+            Ok(_) => {
+                tracing::warn!(
+                    "Delivery {} to {} failed: {}",
+                    delivery.id,
+                    webhook.url,
+                    error
+                );
+                Ok(())
+            }
+            Err(err) => Err(err),
+        },
+    };
+    if let Err(e) = res {
+        tracing::error!(
+            "Failed to record the outcome of delivery {}: {}",
+            delivery.id,
+            e
+        );
+    }
+}
+
+// Post the payload of the delivery, returns the response and why the attempt failed.
+async fn post(
+    client: &Client,
+    webhook: &webhook::Model,
+    delivery: &webhook_delivery::Model,
+) -> (DeliveryResponse, Result<(), String>) {
+    let res = client
+        .post(&webhook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("X-Mega-Event", &delivery.event)
+        .header("X-Mega-Delivery", delivery.id.to_string())
+        .header("X-Mega-Event-Id", delivery.event_id.to_string())
+        .header(
+            "X-Mega-Signature-256",
+            sign(&webhook.secret, delivery.payload.as_bytes()),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+    let mut res = match res {
+        Ok(res) => res,
+        Err(e) => return (DeliveryResponse::default(), Err(e.to_string())),
+    };
+    let status = res.status();
+    let mut body = Vec::new();
+    while body.len() < MAX_RESPONSE_BODY {
+        match res.chunk().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            _ => break,
+        }
+    }
+    body.truncate(MAX_RESPONSE_BODY);
+    let response = DeliveryResponse {
+        status: Some(status.as_u16() as i32),
+        body: Some(String::from_utf8_lossy(&body).into_owned()),
+    };
+    if status.is_success() {
+        (response, Ok(()))
+    } else {
+        (
+            response,
+            Err(format!("the webhook responded with {}", status)),
+        )
+    }
+}
+
+/// The `X-Mega-Signature-256` header of a delivery with `body` to a webhook with `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    format!("sha256={}", hex::encode(hmac::sign(&key, body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sign;
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("key", b"The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+}